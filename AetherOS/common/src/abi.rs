@@ -0,0 +1,962 @@
+// common/src/abi.rs
+
+//! The syscall ABI shared by the kernel and V-Nodes.
+//!
+//! This is the only place syscall numbers, return codes and argument layouts
+//! are defined. The kernel re-exports this module from `kernel/syscall.rs`, so
+//! both sides of the boundary are compiled from the same table.
+//!
+//! Every syscall takes exactly three argument registers. Arguments a syscall
+//! does not use are reserved and must be zero; the kernel rejects anything else
+//! with `E_INVALID_ARG` so that a caller passing a stale register is caught
+//! instead of having the value silently ignored.
+
+#![allow(dead_code)]
+
+/// Version of this ABI. Bumped whenever a syscall is added or an argument's
+/// meaning changes; never decreases.
+pub const ABI_VERSION: u64 = 23;
+
+/// Oldest kernel ABI the V-Node client library can run against. Servers log
+/// with a severity in `SYS_LOG`'s third argument, which a kernel before
+/// version 20 rejects as reserved, and read their callers with
+/// `SYS_IPC_CREDS` (version 16).
+pub const MIN_KERNEL_ABI_VERSION: u64 = 20;
+
+const _: () = assert!(MIN_KERNEL_ABI_VERSION <= ABI_VERSION);
+
+// Error codes
+pub const E_UNKNOWN_SYSCALL: u64 = 0xFFFFFFFFFFFFFFFF;
+pub const E_ACC_DENIED: u64 = 0xFFFFFFFFFFFFFFFE;
+pub const E_BUSY: u64 = 0xFFFFFFFFFFFFFFFD;
+pub const E_UNAUTHENTICATED: u64 = 0xFFFFFFFFFFFFFFFC; // No identity is bound to the task
+pub const E_INVALID_ARG: u64 = 0xFFFFFFFFFFFFFFFB; // A reserved argument or flag bit was non-zero
+pub const E_PEER_GONE: u64 = 0xFFFFFFFFFFFFFFFA; // The server of an IPC call exited before replying
+pub const E_SYSCALL_FILTERED: u64 = 0xFFFFFFFFFFFFFFF9; // The task's syscall filter doesn't allow this syscall
+pub const E_ERROR: u64 = 1;
+pub const SUCCESS: u64 = 0;
+
+// Syscall numbers
+pub const SYS_LOG: u64 = 0;
+pub const SYS_IPC_SEND: u64 = 1;
+pub const SYS_IPC_RECV: u64 = 2;
+pub const SYS_BLOCK_ON_CHAN: u64 = 3;
+pub const SYS_TIME: u64 = 4;
+pub const SYS_IRQ_REGISTER: u64 = 5;
+pub const SYS_NET_RX_POLL: u64 = 6;
+pub const SYS_NET_ALLOC_BUF: u64 = 7;
+pub const SYS_NET_FREE_BUF: u64 = 8;
+pub const SYS_NET_TX: u64 = 9;
+pub const SYS_IRQ_ACK: u64 = 10;
+pub const SYS_GET_DMA_BUF_PTR: u64 = 11;
+pub const SYS_SET_DMA_BUF_LEN: u64 = 12;
+pub const SYS_IPC_RECV_NONBLOCKING: u64 = 13;
+pub const SYS_FB_ACQUIRE: u64 = 14;
+pub const SYS_TASK_STATS: u64 = 15;
+pub const SYS_IPC_LAST_SENDER: u64 = 16;
+pub const SYS_GET_IDENTITY: u64 = 17;
+pub const SYS_SET_IDENTITY: u64 = 18;
+pub const SYS_ABI_VERSION: u64 = 19;
+pub const SYS_MAP_FILE: u64 = 20;
+pub const SYS_UNMAP: u64 = 21;
+pub const SYS_SHARE_PAGES: u64 = 22;
+pub const SYS_UNSHARE_PAGES: u64 = 23;
+pub const SYS_INPUT_READ: u64 = 24;
+pub const SYS_CLOCK_GETTIME: u64 = 25;
+pub const SYS_DEBUG_READ_MEM: u64 = 26;
+pub const SYS_DEBUG_WRITE_MEM: u64 = 27;
+pub const SYS_DEBUG_GET_REGS: u64 = 28;
+pub const SYS_DEBUG_SUSPEND: u64 = 29;
+pub const SYS_DEBUG_RESUME: u64 = 30;
+pub const SYS_DEBUG_GET_BACKTRACE: u64 = 31;
+pub const SYS_TASK_LIST: u64 = 32;
+pub const SYS_KLOG_READ: u64 = 33;
+pub const SYS_BOOT_STATUS: u64 = 34;
+pub const SYS_INPUT_CONFIG: u64 = 35;
+pub const SYS_GET_STARTUP_INFO: u64 = 36;
+pub const SYS_RANDOM: u64 = 37;
+pub const SYS_IPC_CALL: u64 = 38;
+pub const SYS_IPC_REPLY: u64 = 39;
+pub const SYS_IPC_REPLY_TOKEN: u64 = 40;
+pub const SYS_FILTER_RESTRICT: u64 = 41;
+pub const SYS_AUDIO_OPEN: u64 = 42;
+pub const SYS_AUDIO_QUEUE: u64 = 43;
+pub const SYS_IPC_CREDS: u64 = 44;
+pub const SYS_SYSTEM_POWER: u64 = 45;
+pub const SYS_FAULT_STORMS: u64 = 46;
+pub const SYS_LOG_FORWARD: u64 = 47;
+pub const SYS_BOOT_ARGS: u64 = 48;
+pub const SYS_DEBUG_DUMP: u64 = 49;
+pub const SYS_CORE_DUMP_TAKE: u64 = 50;
+pub const SYS_SHARE_PAGES_ON_DEMAND: u64 = 51;
+pub const SYS_PAGE_REQUESTS: u64 = 52;
+pub const SYS_PAGE_SUPPLY: u64 = 53;
+
+/// Number of defined syscalls; valid numbers are `0..SYSCALL_COUNT`.
+pub const SYSCALL_COUNT: usize = 54;
+
+/// A set of syscalls, one bit per syscall number: a task's syscall filter,
+/// and the argument of `SYS_FILTER_RESTRICT`.
+pub type SyscallSet = u64;
+
+/// Every defined syscall.
+pub const ALL_SYSCALLS: SyscallSet = (1 << SYSCALL_COUNT) - 1;
+
+// A filter is a fixed bitset; growing past 64 syscalls needs a wider one.
+const _: () = assert!(SYSCALL_COUNT <= 64);
+
+/// Whether `set` holds syscall `number`. Numbers past the last syscall are never held.
+pub const fn syscall_set_contains(set: SyscallSet, number: u64) -> bool {
+    number < SYSCALL_COUNT as u64 && set & (1 << number) != 0
+}
+
+/// The set of the syscalls named in `names`, e.g. `["SYS_LOG", "SYS_TIME"]`.
+/// Returns the first name that isn't a syscall if there is one.
+pub fn syscall_set_from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<SyscallSet, &'a str> {
+    let mut set = 0;
+    for name in names {
+        let spec = SYSCALLS.iter().find(|spec| spec.name == name).ok_or(name)?;
+        set |= 1 << spec.number;
+    }
+    Ok(set)
+}
+
+// Severities for SYS_LOG (arg3, since ABI version 20). Higher is more severe.
+pub const LOG_SEVERITY_DEFAULT: u64 = 0; // What callers that predate severities pass; the kernel treats it as Info
+pub const LOG_SEVERITY_DEBUG: u64 = 1;
+pub const LOG_SEVERITY_INFO: u64 = 2;
+pub const LOG_SEVERITY_WARN: u64 = 3;
+pub const LOG_SEVERITY_ERROR: u64 = 4;
+
+// Clocks for SYS_TIME (arg1)
+pub const TIME_TICKS: u64 = 0; // Timer ticks since boot
+pub const TIME_NANOS: u64 = 1; // Nanoseconds since boot (since ABI version 17)
+pub const TIME_SECS_NANOS: u64 = 2; // `TIME_NANOS` split into seconds (high 32 bits) and nanoseconds (low 32 bits)
+pub const TIME_FLAGS: u64 = 3; // `TIME_FLAG_*` bits describing `TIME_NANOS`
+
+/// `TIME_NANOS` comes from the calibrated invariant TSC. Without it the clock
+/// advances in whole ticks (10 ms).
+pub const TIME_FLAG_HIGH_RES: u64 = 1 << 0;
+
+/// Splits a `TIME_SECS_NANOS` result into seconds and nanoseconds.
+pub const fn split_secs_nanos(value: u64) -> (u64, u32) {
+    (value >> 32, value as u32)
+}
+
+// Flags for SYS_IRQ_REGISTER (arg3)
+pub const IRQ_REGISTER_FORCE: u64 = 1 << 0; // Take over an IRQ registered by another live task
+pub const IRQ_REGISTER_FLAGS: u64 = IRQ_REGISTER_FORCE;
+
+/// Length of the message the kernel sends on an IRQ's channel: the IRQ number,
+/// then the `TIME_NANOS` time at which the interrupt was taken (little-endian
+/// u64). Kernels older than ABI version 17 send the tick instead.
+pub const IRQ_MSG_LEN: usize = 9;
+
+/// Splits an IRQ notification into the IRQ number and its capture time. Input
+/// drivers pass it on as the event's capture time. Messages from kernels older
+/// than ABI version 3 carry only the IRQ number and report time 0.
+pub fn parse_irq_message(msg: &[u8]) -> Option<(u8, u64)> {
+    let irq = *msg.first()?;
+    let captured_at = match msg.get(1..IRQ_MSG_LEN) {
+        Some(tick) => u64::from_le_bytes(tick.try_into().ok()?),
+        None => 0,
+    };
+    Some((irq, captured_at))
+}
+
+/// Length of one record written by `SYS_INPUT_READ`.
+pub const INPUT_EVENT_LEN: usize = 16;
+
+/// Record kind of a `MouseReport`.
+pub const INPUT_KIND_MOUSE: u8 = 1;
+/// Record kind of a `KeyReport`.
+pub const INPUT_KIND_KEY: u8 = 2;
+
+/// Mouse button bits in `MouseReport::buttons`, in PS/2 packet order.
+pub const MOUSE_BUTTON_LEFT: u8 = 1 << 0;
+pub const MOUSE_BUTTON_RIGHT: u8 = 1 << 1;
+pub const MOUSE_BUTTON_MIDDLE: u8 = 1 << 2;
+
+/// One decoded mouse packet: relative movement and the buttons held afterwards.
+/// `dy` grows downwards, like screen coordinates; `wheel` is positive for
+/// scrolling down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseReport {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: u8,
+    /// `TIME_NANOS` time at which the interrupt that completed the packet was taken.
+    pub captured_at: u64,
+}
+
+impl MouseReport {
+    /// Layout: kind, buttons, dx (LE i16), dy (LE i16), wheel, reserved, captured_at (LE u64).
+    pub fn to_bytes(&self) -> [u8; INPUT_EVENT_LEN] {
+        let mut out = [0u8; INPUT_EVENT_LEN];
+        out[0] = INPUT_KIND_MOUSE;
+        out[1] = self.buttons;
+        out[2..4].copy_from_slice(&self.dx.to_le_bytes());
+        out[4..6].copy_from_slice(&self.dy.to_le_bytes());
+        out[6] = self.wheel as u8;
+        out[8..16].copy_from_slice(&self.captured_at.to_le_bytes());
+        out
+    }
+
+    /// Decodes one record from `SYS_INPUT_READ`. Returns `None` for other kinds.
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        if record.len() < INPUT_EVENT_LEN || record[0] != INPUT_KIND_MOUSE {
+            return None;
+        }
+        Some(Self {
+            buttons: record[1],
+            dx: i16::from_le_bytes([record[2], record[3]]),
+            dy: i16::from_le_bytes([record[4], record[5]]),
+            wheel: record[6] as i8,
+            captured_at: u64::from_le_bytes(record[8..16].try_into().ok()?),
+        })
+    }
+}
+
+// Flags in byte 1 of a key record.
+const KEY_FLAG_PRESSED: u8 = 1 << 0;
+const KEY_FLAG_REPEAT: u8 = 1 << 1;
+
+/// One key press or release. `keycode` is a `common::keys` keycode and
+/// `modifiers` the `MOD_*` bits held after the event. `text` is what the key
+/// types in the configured layout; releases never type anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyReport {
+    pub keycode: u16,
+    pub pressed: bool,
+    /// Generated by the kernel's key repeat while the key is held.
+    pub repeat: bool,
+    pub modifiers: u8,
+    pub text: Option<char>,
+    /// `TIME_NANOS` time at which the interrupt that completed the scancode
+    /// was taken. Repeats carry the time they were generated at.
+    pub captured_at: u64,
+}
+
+impl KeyReport {
+    /// Layout: kind, flags, keycode (LE u16), modifiers, text (LE 24-bit
+    /// codepoint, 0 for none), captured_at (LE u64).
+    pub fn to_bytes(&self) -> [u8; INPUT_EVENT_LEN] {
+        let mut out = [0u8; INPUT_EVENT_LEN];
+        out[0] = INPUT_KIND_KEY;
+        if self.pressed {
+            out[1] |= KEY_FLAG_PRESSED;
+        }
+        if self.repeat {
+            out[1] |= KEY_FLAG_REPEAT;
+        }
+        out[2..4].copy_from_slice(&self.keycode.to_le_bytes());
+        out[4] = self.modifiers;
+        out[5..8].copy_from_slice(&(self.text.map_or(0, u32::from)).to_le_bytes()[..3]);
+        out[8..16].copy_from_slice(&self.captured_at.to_le_bytes());
+        out
+    }
+
+    /// Decodes one record from `SYS_INPUT_READ`. Returns `None` for other kinds.
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        if record.len() < INPUT_EVENT_LEN || record[0] != INPUT_KIND_KEY {
+            return None;
+        }
+        let text = u32::from_le_bytes([record[5], record[6], record[7], 0]);
+        Some(Self {
+            pressed: record[1] & KEY_FLAG_PRESSED != 0,
+            repeat: record[1] & KEY_FLAG_REPEAT != 0,
+            keycode: u16::from_le_bytes([record[2], record[3]]),
+            modifiers: record[4],
+            text: if text == 0 { None } else { char::from_u32(text) },
+            captured_at: u64::from_le_bytes(record[8..16].try_into().ok()?),
+        })
+    }
+}
+
+/// Longest delay before a held key starts repeating that `SYS_INPUT_CONFIG` accepts.
+pub const KEY_REPEAT_MAX_DELAY_MS: u64 = 10_000;
+/// Highest repeat rate `SYS_INPUT_CONFIG` accepts. Repeats are generated on
+/// timer ticks, so rates above the tick rate (100 Hz) can't be met anyway.
+pub const KEY_REPEAT_MAX_RATE_HZ: u64 = 100;
+
+/// Size of a V-Node's args page, and so the longest `StartupInfo` encoding
+/// `SYS_GET_STARTUP_INFO` can return.
+pub const STARTUP_INFO_MAX_LEN: usize = 4096;
+
+/// Most bytes one `SYS_RANDOM` call fills.
+pub const RANDOM_MAX_LEN: usize = 256;
+
+/// Length of the record written by `SYS_CLOCK_GETTIME`.
+pub const CLOCK_TIME_LEN: usize = 16;
+
+/// Wall-clock time: UTC seconds since the Unix epoch plus the fraction of the
+/// current second. The fraction comes from the kernel's `TIME_NANOS` clock,
+/// so it has tick (10 ms) resolution unless `TIME_FLAG_HIGH_RES` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct WallClock {
+    pub secs: u64,
+    pub nanos: u32,
+}
+
+impl WallClock {
+    /// Layout: secs (LE u64), nanos (LE u32), reserved (4 bytes).
+    pub fn to_bytes(&self) -> [u8; CLOCK_TIME_LEN] {
+        let mut out = [0u8; CLOCK_TIME_LEN];
+        out[0..8].copy_from_slice(&self.secs.to_le_bytes());
+        out[8..12].copy_from_slice(&self.nanos.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        Some(Self {
+            secs: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            nanos: u32::from_le_bytes(record.get(8..12)?.try_into().ok()?),
+        })
+    }
+}
+
+/// Length of the record written by `SYS_AUDIO_OPEN`.
+pub const AUDIO_RING_LEN: usize = 24;
+
+/// The PCM output ring `SYS_AUDIO_OPEN` hands out: a DMA buffer of `periods`
+/// periods of `period_bytes` each, played in order and around. Samples are
+/// signed 16-bit little-endian, interleaved by channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AudioRing {
+    /// DMA buffer handle, for `SYS_GET_DMA_BUF_PTR`.
+    pub handle: u64,
+    pub period_bytes: u32,
+    pub periods: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+impl AudioRing {
+    /// Layout: handle (LE u64), period_bytes (LE u32), periods (LE u32),
+    /// sample_rate (LE u32), channels (LE u16), bits_per_sample (LE u16).
+    pub fn to_bytes(&self) -> [u8; AUDIO_RING_LEN] {
+        let mut out = [0u8; AUDIO_RING_LEN];
+        out[0..8].copy_from_slice(&self.handle.to_le_bytes());
+        out[8..12].copy_from_slice(&self.period_bytes.to_le_bytes());
+        out[12..16].copy_from_slice(&self.periods.to_le_bytes());
+        out[16..20].copy_from_slice(&self.sample_rate.to_le_bytes());
+        out[20..22].copy_from_slice(&self.channels.to_le_bytes());
+        out[22..24].copy_from_slice(&self.bits_per_sample.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        Some(Self {
+            handle: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            period_bytes: u32::from_le_bytes(record.get(8..12)?.try_into().ok()?),
+            periods: u32::from_le_bytes(record.get(12..16)?.try_into().ok()?),
+            sample_rate: u32::from_le_bytes(record.get(16..20)?.try_into().ok()?),
+            channels: u16::from_le_bytes(record.get(20..22)?.try_into().ok()?),
+            bits_per_sample: u16::from_le_bytes(record.get(22..24)?.try_into().ok()?),
+        })
+    }
+
+    /// Bytes of one frame: a sample for every channel.
+    pub fn frame_bytes(&self) -> usize {
+        self.channels as usize * (self.bits_per_sample as usize / 8)
+    }
+}
+
+/// Bytes of a task name carried in `TaskStats`; longer names are cut.
+pub const TASK_NAME_LEN: usize = 32;
+
+/// Size of `TaskStats` before ABI version 7 (`id`, `log_messages` and
+/// `log_suppressed`). `SYS_TASK_STATS` still accepts a buffer this small and
+/// fills only that prefix.
+pub const TASK_STATS_V1_LEN: usize = 24;
+
+/// Values of `TaskStats::state`.
+pub const TASK_STATE_RUNNING: u32 = 0;
+pub const TASK_STATE_READY: u32 = 1;
+pub const TASK_STATE_BLOCKED: u32 = 2;
+pub const TASK_STATE_EXITED: u32 = 3;
+
+/// `TaskStats::last_cpu` of a task that hasn't run yet.
+pub const TASK_CPU_NONE: u32 = u32::MAX;
+
+/// Bits of `TaskStats::flags`.
+pub const TASK_FLAG_SUSPENDED: u32 = 1 << 0;
+/// The task runs under a syscall filter (since ABI version 14).
+pub const TASK_FLAG_FILTERED: u32 = 1 << 1;
+/// The task's service asked for no core dumps (since ABI version 22).
+pub const TASK_FLAG_NO_CORE_DUMP: u32 = 1 << 2;
+
+/// Per-task counters and scheduling state, as written by `SYS_TASK_STATS`.
+/// Fields are only ever appended; a caller passes the size it knows about.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TaskStats {
+    pub id: u64,
+    pub log_messages: u64,
+    pub log_suppressed: u64,
+    // Since ABI version 7:
+    /// One of the `TASK_STATE_*` values.
+    pub state: u32,
+    /// `TASK_FLAG_*` bits.
+    pub flags: u32,
+    /// The CPU the task last ran on, or `TASK_CPU_NONE` if it hasn't run yet.
+    pub last_cpu: u32,
+    pub reserved: u32,
+    /// CPUs the task may run on, one bit per CPU id.
+    pub affinity: u64,
+    /// The task's name, NUL-padded.
+    pub name: [u8; TASK_NAME_LEN],
+    // Since ABI version 14:
+    /// Syscalls its filter turned away.
+    pub filter_violations: u64,
+    // Since ABI version 19:
+    /// Page faults the kernel resolved, e.g. by paging in a mapped file.
+    pub page_faults_resolved: u64,
+    /// Page faults it couldn't resolve. Like the three below, one kills the
+    /// task, and the kernel logs the task's counters as it does.
+    pub page_faults_fatal: u64,
+    pub general_protection_faults: u64,
+    pub invalid_opcodes: u64,
+    pub alignment_checks: u64,
+}
+
+impl TaskStats {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            // Cut inside a character; keep the part that decodes.
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Length of a record `SYS_FAULT_STORMS` writes.
+pub const FAULT_STORM_LEN: usize = 48;
+
+/// A task that took faults faster than the threshold set with `SYS_FAULT_STORMS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaultStorm {
+    pub task: u64,
+    /// Faults it took within the second that crossed the threshold.
+    pub faults: u32,
+    /// The threshold at the time, in faults per second.
+    pub threshold: u32,
+    /// The task's name, NUL-padded; longer names are cut.
+    pub name: [u8; TASK_NAME_LEN],
+}
+
+impl FaultStorm {
+    /// Layout: task (LE u64), faults (LE u32), threshold (LE u32), name (32 bytes).
+    pub fn to_bytes(&self) -> [u8; FAULT_STORM_LEN] {
+        let mut out = [0u8; FAULT_STORM_LEN];
+        out[0..8].copy_from_slice(&self.task.to_le_bytes());
+        out[8..12].copy_from_slice(&self.faults.to_le_bytes());
+        out[12..16].copy_from_slice(&self.threshold.to_le_bytes());
+        out[16..48].copy_from_slice(&self.name);
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        Some(Self {
+            task: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            faults: u32::from_le_bytes(record.get(8..12)?.try_into().ok()?),
+            threshold: u32::from_le_bytes(record.get(12..16)?.try_into().ok()?),
+            name: record.get(16..48)?.try_into().ok()?,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Length of the header of a record the kernel forwards to the channel set with
+/// `SYS_LOG_FORWARD`. The message follows it, up to the end of the IPC message.
+pub const LOG_RECORD_HEADER_LEN: usize = 56;
+
+/// A `SYS_LOG` message, as forwarded to the log daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogRecord {
+    pub task: u64,
+    /// Timer ticks since boot when the message was logged.
+    pub ticks: u64,
+    /// One of the `LOG_SEVERITY_*` values other than `LOG_SEVERITY_DEFAULT`.
+    pub severity: u32,
+    /// Records dropped since the previous one because the channel was full.
+    pub dropped: u32,
+    /// The task's name, NUL-padded; longer names are cut.
+    pub name: [u8; TASK_NAME_LEN],
+}
+
+impl LogRecord {
+    /// Layout: task (LE u64), ticks (LE u64), severity (LE u32), dropped (LE
+    /// u32), name (32 bytes).
+    pub fn to_bytes(&self) -> [u8; LOG_RECORD_HEADER_LEN] {
+        let mut out = [0u8; LOG_RECORD_HEADER_LEN];
+        out[0..8].copy_from_slice(&self.task.to_le_bytes());
+        out[8..16].copy_from_slice(&self.ticks.to_le_bytes());
+        out[16..20].copy_from_slice(&self.severity.to_le_bytes());
+        out[20..24].copy_from_slice(&self.dropped.to_le_bytes());
+        out[24..56].copy_from_slice(&self.name);
+        out
+    }
+
+    /// Splits a forwarded message into its header and the message bytes.
+    pub fn from_bytes(record: &[u8]) -> Option<(Self, &[u8])> {
+        let header = Self {
+            task: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            ticks: u64::from_le_bytes(record.get(8..16)?.try_into().ok()?),
+            severity: u32::from_le_bytes(record.get(16..20)?.try_into().ok()?),
+            dropped: u32::from_le_bytes(record.get(20..24)?.try_into().ok()?),
+            name: record.get(24..56)?.try_into().ok()?,
+        };
+        Some((header, &record[LOG_RECORD_HEADER_LEN..]))
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Length of the record `SYS_IPC_CREDS` writes.
+pub const IPC_CREDS_LEN: usize = 80;
+
+/// Bits of the flags word in an `IpcCreds` record.
+pub const IPC_CREDS_FLAG_AID: u32 = 1 << 0; // The sender had an identity bound
+
+/// Who sent an IPC message, as the kernel saw it when the message was queued.
+/// Nothing in the payload can change it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IpcCreds {
+    /// Task ID of the sender; 0 for the kernel.
+    pub sender: u64,
+    /// The sender's task name, NUL-padded; longer names are cut.
+    pub name: [u8; TASK_NAME_LEN],
+    /// The Aid bound to the sender, if it had one.
+    pub aid: Option<[u8; 32]>,
+}
+
+impl IpcCreds {
+    /// Layout: sender (LE u64), flags (LE u32), reserved (4 bytes), name
+    /// (32 bytes), Aid (32 bytes, zero without `IPC_CREDS_FLAG_AID`).
+    pub fn to_bytes(&self) -> [u8; IPC_CREDS_LEN] {
+        let mut out = [0u8; IPC_CREDS_LEN];
+        out[0..8].copy_from_slice(&self.sender.to_le_bytes());
+        let flags = if self.aid.is_some() { IPC_CREDS_FLAG_AID } else { 0 };
+        out[8..12].copy_from_slice(&flags.to_le_bytes());
+        out[16..48].copy_from_slice(&self.name);
+        if let Some(aid) = self.aid {
+            out[48..80].copy_from_slice(&aid);
+        }
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        let flags = u32::from_le_bytes(record.get(8..12)?.try_into().ok()?);
+        let aid: [u8; 32] = record.get(48..80)?.try_into().ok()?;
+        Some(Self {
+            sender: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            name: record.get(16..48)?.try_into().ok()?,
+            aid: if flags & IPC_CREDS_FLAG_AID != 0 { Some(aid) } else { None },
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Length of a record `SYS_PAGE_REQUESTS` writes.
+pub const PAGE_REQUEST_LEN: usize = 16;
+
+/// A page of an on-demand backing that a mapping touched before the storage
+/// service filled it. The service fills it and answers with `SYS_PAGE_SUPPLY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageRequest {
+    pub backing: u64,
+    /// Page-aligned offset into the backing.
+    pub offset: u64,
+}
+
+impl PageRequest {
+    /// Layout: backing (LE u64), offset (LE u64).
+    pub fn to_bytes(&self) -> [u8; PAGE_REQUEST_LEN] {
+        let mut out = [0u8; PAGE_REQUEST_LEN];
+        out[0..8].copy_from_slice(&self.backing.to_le_bytes());
+        out[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        Some(Self {
+            backing: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            offset: u64::from_le_bytes(record.get(8..16)?.try_into().ok()?),
+        })
+    }
+}
+
+// Flags for SYS_KLOG_READ (arg3)
+pub const KLOG_LAST_BOOT: u64 = 1 << 0; // Read the log recovered from the previous boot instead of this one
+pub const KLOG_READ_FLAGS: u64 = KLOG_LAST_BOOT;
+
+/// Length of the record that starts the output of `SYS_KLOG_READ` with
+/// `KLOG_LAST_BOOT`, ahead of the log text.
+pub const LAST_BOOT_INFO_LEN: usize = 16;
+
+/// Values of `LastBootInfo::reason`: what wrote the dump.
+pub const LAST_BOOT_CHECKPOINT: u32 = 0;
+pub const LAST_BOOT_PANIC: u32 = 1;
+pub const LAST_BOOT_SHUTDOWN: u32 = 2; // Written by SYS_SYSTEM_POWER just before powering off or resetting
+
+/// Where the previous boot's log came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LastBootInfo {
+    /// Sequence number of the boot that wrote the dump.
+    pub seq: u64,
+    /// One of the `LAST_BOOT_*` values.
+    pub reason: u32,
+}
+
+impl LastBootInfo {
+    /// Layout: seq (LE u64), reason (LE u32), reserved (4 bytes).
+    pub fn to_bytes(&self) -> [u8; LAST_BOOT_INFO_LEN] {
+        let mut out = [0u8; LAST_BOOT_INFO_LEN];
+        out[0..8].copy_from_slice(&self.seq.to_le_bytes());
+        out[8..12].copy_from_slice(&self.reason.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(record: &[u8]) -> Option<Self> {
+        Some(Self {
+            seq: u64::from_le_bytes(record.get(0..8)?.try_into().ok()?),
+            reason: u32::from_le_bytes(record.get(8..12)?.try_into().ok()?),
+        })
+    }
+}
+
+// Flags for SYS_BOOT_STATUS (arg3)
+pub const BOOT_STATUS_FAILED: u64 = 1 << 0; // Draw the line in red
+pub const BOOT_STATUS_CLEAR: u64 = 1 << 1; // Remove the line; the text is ignored
+pub const BOOT_STATUS_FLAGS: u64 = BOOT_STATUS_FAILED | BOOT_STATUS_CLEAR;
+
+/// Longest status text `SYS_BOOT_STATUS` reads; the rest is cut.
+pub const BOOT_STATUS_MAX_LEN: u64 = 256;
+
+// Actions for SYS_SYSTEM_POWER (arg1)
+pub const POWER_SHUTDOWN: u64 = 0; // Power the machine off
+pub const POWER_REBOOT: u64 = 1; // Reset the machine
+
+/// Most bytes one `SYS_DEBUG_READ_MEM` or `SYS_DEBUG_WRITE_MEM` call copies.
+pub const DEBUG_MEM_MAX: u64 = 64 * 1024;
+
+/// Most return addresses `SYS_DEBUG_GET_BACKTRACE` reports.
+pub const DEBUG_MAX_FRAMES: usize = 32;
+
+/// Argument block of `SYS_DEBUG_READ_MEM` and `SYS_DEBUG_WRITE_MEM`, which
+/// need more than three registers. The kernel fills in `copied`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DebugMemRequest {
+    /// Address in the target task.
+    pub addr: u64,
+    /// Address of the caller's buffer.
+    pub buf: u64,
+    /// Bytes to copy, at most `DEBUG_MEM_MAX`.
+    pub len: u64,
+    /// Set by the kernel: bytes copied before the range ran into memory the
+    /// target can't access. Equals `len` on `SUCCESS`.
+    pub copied: u64,
+}
+
+/// Argument block of `SYS_IPC_CALL`: the request, the buffer for the reply,
+/// and the call's reply token. The kernel fills in `token` and `reply_len`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IpcCall {
+    /// Address and length of the request in the caller.
+    pub req: u64,
+    pub req_len: u64,
+    /// Address and capacity of the caller's reply buffer.
+    pub resp: u64,
+    pub resp_cap: u64,
+    /// 0 to start a call. When the call has to wait, the kernel sets it and
+    /// returns `E_BUSY`; calling again with it unchanged collects the reply.
+    pub token: u64,
+    /// Set by the kernel: length of the reply. On `E_ERROR` after a reply
+    /// that didn't fit, the length the buffer would have needed.
+    pub reply_len: u64,
+}
+
+/// A task's saved registers, as returned by `SYS_DEBUG_GET_REGS`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RegisterFrame {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rflags: u64,
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub cs: u64,
+    pub ss: u64,
+}
+
+/// What a syscall argument register carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// Reserved; must be zero.
+    Unused,
+    /// A plain number (IRQ line, interface index, allocation size).
+    Value,
+    ChannelId,
+    TaskId,
+    /// Address in the caller's memory. May be 0 where the syscall documents it.
+    Pointer,
+    /// Byte length of the buffer passed in the preceding argument.
+    Length,
+    /// A DMA buffer handle returned by `SYS_NET_ALLOC_BUF`.
+    DmaHandle,
+    /// A file backing handle returned by `SYS_SHARE_PAGES`.
+    BackingHandle,
+    /// A reply token returned by `SYS_IPC_REPLY_TOKEN`.
+    ReplyToken,
+    /// A bit set. Bits outside the mask are reserved and must be zero.
+    Flags(u64),
+}
+
+impl ArgKind {
+    /// Whether `value` is acceptable for an argument of this kind.
+    pub const fn accepts(self, value: u64) -> bool {
+        match self {
+            ArgKind::Unused => value == 0,
+            ArgKind::Flags(mask) => value & !mask == 0,
+            _ => true,
+        }
+    }
+}
+
+/// Layout of one syscall.
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallSpec {
+    pub number: u64,
+    pub name: &'static str,
+    pub args: [ArgKind; 3],
+}
+
+impl SyscallSpec {
+    /// Number of argument registers the syscall reads.
+    pub const fn arg_count(&self) -> usize {
+        let mut count = 0;
+        let mut i = 0;
+        while i < self.args.len() {
+            if !matches!(self.args[i], ArgKind::Unused) {
+                count = i + 1;
+            }
+            i += 1;
+        }
+        count
+    }
+
+    /// Checks reserved arguments and flag bits. Returns the index of the first
+    /// offending argument, if any.
+    pub fn check_args(&self, args: [u64; 3]) -> Result<(), usize> {
+        for (i, (kind, value)) in self.args.iter().zip(args.iter()).enumerate() {
+            if !kind.accepts(*value) {
+                return Err(i);
+            }
+        }
+        Ok(())
+    }
+}
+
+const fn spec(number: u64, name: &'static str, args: [ArgKind; 3]) -> SyscallSpec {
+    SyscallSpec { number, name, args }
+}
+
+use ArgKind::*;
+
+const TABLE: [SyscallSpec; SYSCALL_COUNT] = [
+    spec(SYS_LOG, "SYS_LOG", [Pointer, Length, Value]),
+    spec(SYS_IPC_SEND, "SYS_IPC_SEND", [ChannelId, Pointer, Length]),
+    spec(SYS_IPC_RECV, "SYS_IPC_RECV", [ChannelId, Pointer, Length]),
+    spec(SYS_BLOCK_ON_CHAN, "SYS_BLOCK_ON_CHAN", [ChannelId, Unused, Unused]),
+    spec(SYS_TIME, "SYS_TIME", [Value, Unused, Unused]),
+    spec(SYS_IRQ_REGISTER, "SYS_IRQ_REGISTER", [Value, ChannelId, Flags(IRQ_REGISTER_FLAGS)]),
+    spec(SYS_NET_RX_POLL, "SYS_NET_RX_POLL", [Value, DmaHandle, Length]),
+    spec(SYS_NET_ALLOC_BUF, "SYS_NET_ALLOC_BUF", [Value, Unused, Unused]),
+    spec(SYS_NET_FREE_BUF, "SYS_NET_FREE_BUF", [DmaHandle, Unused, Unused]),
+    spec(SYS_NET_TX, "SYS_NET_TX", [Value, DmaHandle, Length]),
+    spec(SYS_IRQ_ACK, "SYS_IRQ_ACK", [Value, Unused, Unused]),
+    spec(SYS_GET_DMA_BUF_PTR, "SYS_GET_DMA_BUF_PTR", [DmaHandle, Unused, Unused]),
+    spec(SYS_SET_DMA_BUF_LEN, "SYS_SET_DMA_BUF_LEN", [DmaHandle, Length, Unused]),
+    spec(SYS_IPC_RECV_NONBLOCKING, "SYS_IPC_RECV_NONBLOCKING", [ChannelId, Pointer, Length]),
+    spec(SYS_FB_ACQUIRE, "SYS_FB_ACQUIRE", [Unused, Unused, Unused]),
+    spec(SYS_TASK_STATS, "SYS_TASK_STATS", [TaskId, Pointer, Length]),
+    spec(SYS_IPC_LAST_SENDER, "SYS_IPC_LAST_SENDER", [Unused, Unused, Unused]),
+    spec(SYS_GET_IDENTITY, "SYS_GET_IDENTITY", [TaskId, Pointer, Length]),
+    spec(SYS_SET_IDENTITY, "SYS_SET_IDENTITY", [TaskId, Pointer, Unused]),
+    spec(SYS_ABI_VERSION, "SYS_ABI_VERSION", [Unused, Unused, Unused]),
+    spec(SYS_MAP_FILE, "SYS_MAP_FILE", [BackingHandle, Value, Length]),
+    spec(SYS_UNMAP, "SYS_UNMAP", [Pointer, Length, Unused]),
+    spec(SYS_SHARE_PAGES, "SYS_SHARE_PAGES", [Pointer, Length, TaskId]),
+    spec(SYS_UNSHARE_PAGES, "SYS_UNSHARE_PAGES", [BackingHandle, Unused, Unused]),
+    spec(SYS_INPUT_READ, "SYS_INPUT_READ", [Pointer, Length, Unused]),
+    spec(SYS_CLOCK_GETTIME, "SYS_CLOCK_GETTIME", [Pointer, Length, Unused]),
+    spec(SYS_DEBUG_READ_MEM, "SYS_DEBUG_READ_MEM", [TaskId, Pointer, Length]),
+    spec(SYS_DEBUG_WRITE_MEM, "SYS_DEBUG_WRITE_MEM", [TaskId, Pointer, Length]),
+    spec(SYS_DEBUG_GET_REGS, "SYS_DEBUG_GET_REGS", [TaskId, Pointer, Length]),
+    spec(SYS_DEBUG_SUSPEND, "SYS_DEBUG_SUSPEND", [TaskId, Unused, Unused]),
+    spec(SYS_DEBUG_RESUME, "SYS_DEBUG_RESUME", [TaskId, Unused, Unused]),
+    spec(SYS_DEBUG_GET_BACKTRACE, "SYS_DEBUG_GET_BACKTRACE", [TaskId, Pointer, Length]),
+    spec(SYS_TASK_LIST, "SYS_TASK_LIST", [Pointer, Length, Unused]),
+    spec(SYS_KLOG_READ, "SYS_KLOG_READ", [Pointer, Length, Flags(KLOG_READ_FLAGS)]),
+    spec(SYS_BOOT_STATUS, "SYS_BOOT_STATUS", [Pointer, Length, Flags(BOOT_STATUS_FLAGS)]),
+    spec(SYS_INPUT_CONFIG, "SYS_INPUT_CONFIG", [Value, Value, Value]),
+    spec(SYS_GET_STARTUP_INFO, "SYS_GET_STARTUP_INFO", [Pointer, Length, Unused]),
+    spec(SYS_RANDOM, "SYS_RANDOM", [Pointer, Length, Unused]),
+    spec(SYS_IPC_CALL, "SYS_IPC_CALL", [ChannelId, Pointer, Length]),
+    spec(SYS_IPC_REPLY, "SYS_IPC_REPLY", [ReplyToken, Pointer, Length]),
+    spec(SYS_IPC_REPLY_TOKEN, "SYS_IPC_REPLY_TOKEN", [Unused, Unused, Unused]),
+    spec(SYS_FILTER_RESTRICT, "SYS_FILTER_RESTRICT", [Flags(ALL_SYSCALLS), Unused, Unused]),
+    spec(SYS_AUDIO_OPEN, "SYS_AUDIO_OPEN", [ChannelId, Pointer, Length]),
+    spec(SYS_AUDIO_QUEUE, "SYS_AUDIO_QUEUE", [Value, Length, Unused]),
+    spec(SYS_IPC_CREDS, "SYS_IPC_CREDS", [Pointer, Length, Unused]),
+    spec(SYS_SYSTEM_POWER, "SYS_SYSTEM_POWER", [Value, Unused, Unused]),
+    spec(SYS_FAULT_STORMS, "SYS_FAULT_STORMS", [Value, Pointer, Length]),
+    spec(SYS_LOG_FORWARD, "SYS_LOG_FORWARD", [ChannelId, Unused, Unused]),
+    spec(SYS_BOOT_ARGS, "SYS_BOOT_ARGS", [Pointer, Length, Unused]),
+    spec(SYS_DEBUG_DUMP, "SYS_DEBUG_DUMP", [TaskId, Unused, Unused]),
+    spec(SYS_CORE_DUMP_TAKE, "SYS_CORE_DUMP_TAKE", [Value, Pointer, Length]),
+    spec(SYS_SHARE_PAGES_ON_DEMAND, "SYS_SHARE_PAGES_ON_DEMAND", [Pointer, Length, TaskId]),
+    spec(SYS_PAGE_REQUESTS, "SYS_PAGE_REQUESTS", [Pointer, Length, Unused]),
+    spec(SYS_PAGE_SUPPLY, "SYS_PAGE_SUPPLY", [BackingHandle, Value, Unused]),
+];
+
+// Each entry must sit at the index of its own syscall number, so `lookup` can
+// index the table directly and a renumbered constant fails the build.
+const _: () = {
+    let mut i = 0;
+    while i < TABLE.len() {
+        assert!(TABLE[i].number == i as u64);
+        i += 1;
+    }
+};
+
+/// The syscall table, indexed by syscall number.
+pub static SYSCALLS: [SyscallSpec; SYSCALL_COUNT] = TABLE;
+
+/// Looks up the layout of syscall `number`.
+pub fn lookup(number: u64) -> Option<&'static SyscallSpec> {
+    SYSCALLS.get(number as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeSet;
+
+    /// The kernel's dispatcher. `common/src/syscalls.rs` is a copy of it.
+    const KERNEL_SYSCALL_RS: &str = include_str!("../../kernel/syscall.rs");
+
+    /// Names of the syscalls with an arm in the dispatcher's `match n`.
+    fn dispatched(source: &str) -> BTreeSet<&str> {
+        source
+            .lines()
+            .filter(|line| line.starts_with("        SYS_"))
+            .filter_map(|line| line.split("=>").next())
+            .flat_map(|pattern| pattern.split('|'))
+            .map(str::trim)
+            .collect()
+    }
+
+    #[test]
+    fn kernel_dispatches_exactly_the_table() {
+        let table: BTreeSet<&str> = SYSCALLS.iter().map(|spec| spec.name).collect();
+        assert_eq!(table.len(), SYSCALL_COUNT);
+        assert_eq!(dispatched(KERNEL_SYSCALL_RS), table);
+    }
+
+    #[test]
+    fn common_copy_of_the_dispatcher_matches_the_kernel() {
+        assert!(include_str!("syscalls.rs") == KERNEL_SYSCALL_RS, "common/src/syscalls.rs differs from kernel/syscall.rs");
+    }
+
+    #[test]
+    fn table_is_indexed_by_number() {
+        for (i, spec) in SYSCALLS.iter().enumerate() {
+            assert_eq!(lookup(i as u64).map(|found| found.name), Some(spec.name));
+        }
+        assert!(lookup(SYSCALL_COUNT as u64).is_none());
+        assert!(lookup(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn version_probe_takes_no_arguments() {
+        let spec = lookup(SYS_ABI_VERSION).unwrap();
+        assert_eq!(spec.name, "SYS_ABI_VERSION");
+        assert_eq!(spec.arg_count(), 0);
+        assert_eq!(spec.check_args([0, 0, 0]), Ok(()));
+        assert!(MIN_KERNEL_ABI_VERSION <= ABI_VERSION);
+    }
+
+    #[test]
+    fn nonzero_unused_arguments_are_rejected() {
+        assert_eq!(lookup(SYS_ABI_VERSION).unwrap().check_args([1, 0, 0]), Err(0));
+        // The syscall2/syscall3 mix-up: a stale third register.
+        let time = lookup(SYS_TIME).unwrap();
+        assert_eq!(time.check_args([TIME_NANOS, 0, 0]), Ok(()));
+        assert_eq!(time.check_args([TIME_NANOS, 0, 0xdead]), Err(2));
+        assert_eq!(time.check_args([TIME_NANOS, 7, 0]), Err(1));
+        let block = lookup(SYS_BLOCK_ON_CHAN).unwrap();
+        assert_eq!(block.arg_count(), 1);
+        assert_eq!(block.check_args([5, 0, 1]), Err(2));
+    }
+
+    #[test]
+    fn flag_arguments_accept_only_their_mask() {
+        let restrict = lookup(SYS_FILTER_RESTRICT).unwrap();
+        assert_eq!(restrict.check_args([ALL_SYSCALLS, 0, 0]), Ok(()));
+        assert_eq!(restrict.check_args([1 << SYSCALL_COUNT, 0, 0]), Err(0));
+        let klog = lookup(SYS_KLOG_READ).unwrap();
+        assert_eq!(klog.arg_count(), 3);
+        assert_eq!(klog.check_args([0x1000, 64, KLOG_READ_FLAGS]), Ok(()));
+        assert_eq!(klog.check_args([0x1000, 64, !KLOG_READ_FLAGS]), Err(2));
+    }
+
+    #[test]
+    fn syscall_sets_by_name() {
+        let set = syscall_set_from_names(["SYS_LOG", "SYS_TIME"]).unwrap();
+        assert!(syscall_set_contains(set, SYS_LOG));
+        assert!(syscall_set_contains(set, SYS_TIME));
+        assert!(!syscall_set_contains(set, SYS_IPC_SEND));
+        assert!(!syscall_set_contains(ALL_SYSCALLS, SYSCALL_COUNT as u64));
+        assert_eq!(syscall_set_from_names(["SYS_LOG", "SYS_NOPE"]), Err("SYS_NOPE"));
+    }
+}
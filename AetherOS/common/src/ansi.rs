@@ -0,0 +1,554 @@
+// common/src/ansi.rs
+
+//! ANSI escape sequences: a parser that applies terminal output to a grid of
+//! character cells, and helpers for programs that want to emit color.
+//!
+//! `Parser` is a state machine fed one `char` at a time, so a sequence split
+//! across two writes still parses. It understands the subset shell programs
+//! use:
+//!
+//! *   CR, LF, backspace and tab. LF also returns to column 0, the translation
+//!     a tty would otherwise do, since V-Nodes write bare `\n`.
+//! *   BEL, counted on the grid for the terminal to collect with `take_bells`
+//!     and play as the audio mixer's bell tone (`audio_ipc::BELL_TONE_HZ`).
+//! *   CSI SGR (`m`): reset, bold, underline, reverse, the 8 standard and 8
+//!     bright colors for foreground (30–37, 90–97) and background (40–47,
+//!     100–107), and the defaults (39, 49).
+//! *   CSI cursor movement: CUU/CUD/CUF/CUB (`A`–`D`), CHA (`G`) and CUP
+//!     (`H`, `f`), clamped to the grid.
+//! *   CSI erase: EL (`K`) and ED (`J`), modes 0, 1 and 2.
+//!
+//! Anything else starting with ESC is consumed up to its final byte and
+//! dropped. A sequence interrupted by another ESC is abandoned; one broken by
+//! a byte that can't be part of it is abandoned and the byte ignored. Either
+//! way, the text after it prints normally. Extended colors (`38;5;n`,
+//! `38;2;r;g;b`) are skipped whole, so their parameters aren't read as SGR codes.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::text;
+
+pub const ESC: char = '\x1b';
+
+/// SGR sequences for programs that write color.
+pub const RESET: &str = "\x1b[0m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const BLUE: &str = "\x1b[34m";
+
+/// Parameters kept per sequence; further ones are dropped.
+const MAX_PARAMS: usize = 16;
+/// Larger parameters are clamped, so long digit runs can't overflow.
+const MAX_PARAM: u16 = 9999;
+const TAB_WIDTH: usize = 8;
+
+/// `text` between `color` (one of the constants above) and a reset.
+pub fn paint(text: &str, color: &str) -> String {
+    let mut painted = String::with_capacity(color.len() + text.len() + RESET.len());
+    painted.push_str(color);
+    painted.push_str(text);
+    painted.push_str(RESET);
+    painted
+}
+
+/// Removes escape sequences, leaving the text a plain client would show.
+pub fn strip(text: &str) -> String {
+    let mut grid = Grid::new(0, 0);
+    let mut parser = Parser::new();
+    let mut plain = String::with_capacity(text.len());
+    for c in text.chars() {
+        if parser.is_ground() && c != ESC {
+            plain.push(c);
+        } else {
+            parser.advance(&mut grid, c);
+        }
+    }
+    plain
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    /// The renderer's default foreground or background.
+    #[default]
+    Default,
+    /// 0–7 are black, red, green, yellow, blue, magenta, cyan and white;
+    /// 8–15 are their bright variants.
+    Palette(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attrs {
+    pub bold: bool,
+    pub underline: bool,
+    /// Swap foreground and background when drawing.
+    pub reverse: bool,
+}
+
+/// Colors and attributes, for a cell or for the text about to be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attrs,
+}
+
+/// Stands in the cell after a wide character, which the renderer skips.
+pub const WIDE_TAIL: char = '\0';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell { ch: ' ', style: Style { fg: Color::Default, bg: Color::Default, attrs: Attrs { bold: false, underline: false, reverse: false } } };
+}
+
+/// The screen: `height` rows of `width` cells, a cursor, and the style new
+/// text is written in. Text reaching the bottom scrolls the grid up.
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>, // Row by row
+    row: usize,
+    col: usize, // May equal `width`: the next character wraps first
+    pub style: Style,
+    bells: u32, // BELs since the last `take_bells`
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, cells: alloc::vec![Cell::BLANK; width * height], row: 0, col: 0, style: Style::default(), bells: 0 }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Cell {
+        self.cells[row * self.width + col]
+    }
+
+    pub fn row(&self, row: usize) -> &[Cell] {
+        &self.cells[row * self.width..(row + 1) * self.width]
+    }
+
+    /// The text of a row, wide-character tails left out and trailing blanks trimmed.
+    pub fn row_text(&self, row: usize) -> String {
+        let text: String = self.row(row).iter().map(|cell| cell.ch).filter(|&c| c != WIDE_TAIL).collect();
+        String::from(text.trim_end_matches(' '))
+    }
+
+    /// BELs written since the last call. A terminal rings once however
+    /// many arrived in one write.
+    pub fn take_bells(&mut self) -> u32 {
+        core::mem::take(&mut self.bells)
+    }
+
+    /// Cursor row and column, both from 0.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col.min(self.width.saturating_sub(1)))
+    }
+
+    /// Moves the cursor, clamped to the grid.
+    pub fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.height.saturating_sub(1));
+        self.col = col.min(self.width.saturating_sub(1));
+    }
+
+    /// Writes a character at the cursor and advances it, wrapping at the end
+    /// of the row. Zero-width characters are dropped.
+    pub fn put(&mut self, c: char) {
+        let width = text::char_width(c);
+        if width == 0 || self.width < width || self.height == 0 {
+            return;
+        }
+        if self.col + width > self.width {
+            self.col = 0;
+            self.line_feed();
+        }
+        let index = self.row * self.width + self.col;
+        self.cells[index] = Cell { ch: c, style: self.style };
+        if width == 2 {
+            self.cells[index + 1] = Cell { ch: WIDE_TAIL, style: self.style };
+        }
+        self.col += width;
+    }
+
+    pub fn carriage_return(&mut self) {
+        self.col = 0;
+    }
+
+    /// Moves down a row, scrolling at the bottom.
+    pub fn line_feed(&mut self) {
+        if self.row + 1 < self.height {
+            self.row += 1;
+        } else if self.height > 0 {
+            self.cells.drain(..self.width);
+            let blank = self.blank();
+            self.cells.extend(core::iter::repeat(blank).take(self.width));
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.col = self.col.min(self.width.saturating_sub(1)).saturating_sub(1);
+    }
+
+    pub fn tab(&mut self) {
+        let next = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+        self.col = next.min(self.width.saturating_sub(1));
+    }
+
+    /// EL: 0 clears from the cursor to the end of the row, 1 from the start
+    /// of the row through the cursor, 2 the whole row.
+    pub fn erase_in_line(&mut self, mode: u16) {
+        if self.cells.is_empty() {
+            return;
+        }
+        let (row, col) = self.cursor();
+        let start = row * self.width;
+        match mode {
+            0 => self.clear(start + col, start + self.width),
+            1 => self.clear(start, start + col + 1),
+            2 => self.clear(start, start + self.width),
+            _ => {},
+        }
+    }
+
+    /// ED: 0 clears from the cursor to the end of the screen, 1 from the
+    /// start of the screen through the cursor, 2 (and 3) all of it. The
+    /// cursor doesn't move.
+    pub fn erase_in_display(&mut self, mode: u16) {
+        if self.cells.is_empty() {
+            return;
+        }
+        let (row, col) = self.cursor();
+        let at = row * self.width + col;
+        match mode {
+            0 => self.clear(at, self.cells.len()),
+            1 => self.clear(0, at + 1),
+            2 | 3 => self.clear(0, self.cells.len()),
+            _ => {},
+        }
+    }
+
+    /// Erased cells keep the current background, as on other terminals.
+    fn blank(&self) -> Cell {
+        Cell { ch: ' ', style: Style { bg: self.style.bg, ..Style::default() } }
+    }
+
+    fn clear(&mut self, from: usize, to: usize) {
+        let blank = self.blank();
+        self.cells[from..to].fill(blank);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After ESC.
+    Escape,
+    /// After ESC `[`. `ignore` is set for private (`?`…) and intermediate
+    /// forms, which are parsed to their end but not carried out.
+    Csi { params: Vec<u16>, current: Option<u16>, ignore: bool },
+}
+
+pub struct Parser {
+    state: State,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self { state: State::Ground }
+    }
+
+    /// Whether the parser is between sequences.
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground
+    }
+
+    pub fn feed(&mut self, grid: &mut Grid, text: &str) {
+        for c in text.chars() {
+            self.advance(grid, c);
+        }
+    }
+
+    pub fn advance(&mut self, grid: &mut Grid, c: char) {
+        match core::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => match c {
+                ESC => self.state = State::Escape,
+                c if c.is_control() => execute(grid, c),
+                c => grid.put(c),
+            },
+            State::Escape => match c {
+                '[' => self.state = State::Csi { params: Vec::new(), current: None, ignore: false },
+                ESC => self.state = State::Escape,
+                'c' => {
+                    // RIS: full reset.
+                    *grid = Grid::new(grid.width, grid.height);
+                },
+                _ => {}, // Other escapes are two characters long; drop them.
+            },
+            State::Csi { mut params, mut current, mut ignore } => match c {
+                '0'..='9' => {
+                    let digit = c as u16 - '0' as u16;
+                    current = Some(current.unwrap_or(0).saturating_mul(10).saturating_add(digit).min(MAX_PARAM));
+                    self.state = State::Csi { params, current, ignore };
+                },
+                ';' => {
+                    if params.len() < MAX_PARAMS {
+                        params.push(current.unwrap_or(0));
+                    }
+                    self.state = State::Csi { params, current: None, ignore };
+                },
+                ':' | '<'..='?' => {
+                    // Private markers and colon sub-parameters aren't supported.
+                    ignore = true;
+                    self.state = State::Csi { params, current, ignore };
+                },
+                ' '..='/' => {
+                    ignore = true;
+                    self.state = State::Csi { params, current, ignore };
+                },
+                '@'..='~' => {
+                    if (current.is_some() || !params.is_empty()) && params.len() < MAX_PARAMS {
+                        params.push(current.unwrap_or(0));
+                    }
+                    if !ignore {
+                        dispatch(grid, c, &params);
+                    }
+                },
+                ESC => self.state = State::Escape,
+                c if c.is_control() => {
+                    // Controls inside a sequence take effect without ending it.
+                    execute(grid, c);
+                    self.state = State::Csi { params, current, ignore };
+                },
+                _ => {}, // Not part of any sequence: abandon it.
+            },
+        }
+    }
+}
+
+fn execute(grid: &mut Grid, c: char) {
+    match c {
+        '\r' => grid.carriage_return(),
+        '\n' | '\x0b' | '\x0c' => {
+            grid.carriage_return();
+            grid.line_feed();
+        },
+        '\x08' => grid.backspace(),
+        '\t' => grid.tab(),
+        '\x07' => grid.bells = grid.bells.saturating_add(1),
+        _ => {}, // The rest do nothing here.
+    }
+}
+
+fn dispatch(grid: &mut Grid, final_char: char, params: &[u16]) {
+    // A missing or 0 count means 1; positions are 1-based.
+    let count = |index: usize| params.get(index).copied().filter(|&n| n > 0).unwrap_or(1) as usize;
+    let (row, col) = grid.cursor();
+    match final_char {
+        'A' => grid.move_to(row.saturating_sub(count(0)), col),
+        'B' => grid.move_to(row + count(0), col),
+        'C' => grid.move_to(row, col + count(0)),
+        'D' => grid.move_to(row, col.saturating_sub(count(0))),
+        'G' => grid.move_to(row, count(0) - 1),
+        'H' | 'f' => grid.move_to(count(0) - 1, count(1) - 1),
+        'J' => grid.erase_in_display(params.first().copied().unwrap_or(0)),
+        'K' => grid.erase_in_line(params.first().copied().unwrap_or(0)),
+        'm' => select_graphic_rendition(&mut grid.style, params),
+        _ => {},
+    }
+}
+
+fn select_graphic_rendition(style: &mut Style, params: &[u16]) {
+    if params.is_empty() {
+        *style = Style::default();
+        return;
+    }
+    let mut params = params.iter().copied();
+    while let Some(code) = params.next() {
+        match code {
+            0 => *style = Style::default(),
+            1 => style.attrs.bold = true,
+            4 => style.attrs.underline = true,
+            7 => style.attrs.reverse = true,
+            22 => style.attrs.bold = false,
+            24 => style.attrs.underline = false,
+            27 => style.attrs.reverse = false,
+            30..=37 => style.fg = Color::Palette((code - 30) as u8),
+            39 => style.fg = Color::Default,
+            40..=47 => style.bg = Color::Palette((code - 40) as u8),
+            49 => style.bg = Color::Default,
+            90..=97 => style.fg = Color::Palette((code - 90 + 8) as u8),
+            100..=107 => style.bg = Color::Palette((code - 100 + 8) as u8),
+            38 | 48 => {
+                // 256-color and RGB forms aren't shown; skip their arguments.
+                match params.next() {
+                    Some(5) => { params.next(); },
+                    Some(2) => { params.nth(2); },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_after(text: &str) -> Grid {
+        let mut grid = Grid::new(10, 3);
+        Parser::new().feed(&mut grid, text);
+        grid
+    }
+
+    #[test]
+    fn sgr_sets_and_resets_colors_and_attributes() {
+        let grid = grid_after("\x1b[1;4;31;42mA\x1b[22;39mB\x1b[94;107mC\x1b[0mD\x1b[7mE\x1b[mF");
+        let style = |col| grid.cell(0, col).style;
+        assert_eq!(style(0), Style { fg: Color::Palette(1), bg: Color::Palette(2), attrs: Attrs { bold: true, underline: true, reverse: false } });
+        assert_eq!(style(1), Style { fg: Color::Default, bg: Color::Palette(2), attrs: Attrs { bold: false, underline: true, reverse: false } });
+        assert_eq!(style(2).fg, Color::Palette(12));
+        assert_eq!(style(2).bg, Color::Palette(15));
+        assert_eq!(style(3), Style::default());
+        assert!(style(4).attrs.reverse);
+        assert_eq!(style(5), Style::default());
+        assert_eq!(grid.row_text(0), "ABCDEF");
+    }
+
+    #[test]
+    fn extended_colors_are_skipped_whole() {
+        // Without skipping, the 5 and the 1 would turn on blink and bold.
+        let grid = grid_after("\x1b[38;5;1;4mA\x1b[0;48;2;1;1;1mB");
+        assert_eq!(grid.cell(0, 0).style.fg, Color::Default);
+        assert_eq!(grid.cell(0, 0).style.attrs, Attrs { bold: false, underline: true, reverse: false });
+        assert_eq!(grid.cell(0, 1).style, Style::default());
+    }
+
+    #[test]
+    fn cursor_movement_is_one_based_and_clamped() {
+        let mut grid = grid_after("\x1b[2;5H");
+        assert_eq!(grid.cursor(), (1, 4));
+        Parser::new().feed(&mut grid, "\x1b[A\x1b[3C");
+        assert_eq!(grid.cursor(), (0, 7));
+        Parser::new().feed(&mut grid, "\x1b[99B\x1b[99D");
+        assert_eq!(grid.cursor(), (2, 0));
+        Parser::new().feed(&mut grid, "\x1b[0G\x1b[H");
+        assert_eq!(grid.cursor(), (0, 0));
+        Parser::new().feed(&mut grid, "\x1b[50;50f");
+        assert_eq!(grid.cursor(), (2, 9));
+    }
+
+    #[test]
+    fn erase_in_line_and_display() {
+        let mut grid = grid_after("abcdefghij\nklmnopqrst\nuvwxyz");
+        Parser::new().feed(&mut grid, "\x1b[2;4H\x1b[K");
+        assert_eq!(grid.row_text(1), "klm");
+        Parser::new().feed(&mut grid, "\x1b[1;3H\x1b[1K");
+        assert_eq!(grid.row_text(0), "   defghij");
+        Parser::new().feed(&mut grid, "\x1b[3;2H\x1b[J");
+        assert_eq!(grid.row_text(2), "u");
+        Parser::new().feed(&mut grid, "\x1b[2J");
+        assert!((0..3).all(|row| grid.row_text(row).is_empty()));
+        assert_eq!(grid.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn text_wraps_and_scrolls() {
+        let grid = grid_after("0123456789ab\nc\nd");
+        assert_eq!(grid.row_text(0), "ab");
+        assert_eq!(grid.row_text(1), "c");
+        assert_eq!(grid.row_text(2), "d");
+    }
+
+    #[test]
+    fn controls_work_inside_and_outside_sequences() {
+        let mut grid = grid_after("ab\x08c\tx\r\x07y");
+        assert_eq!(grid.row_text(0), "yc      x");
+        assert_eq!(grid.take_bells(), 1);
+        assert_eq!(grid.take_bells(), 0);
+        // A BEL in the middle of a sequence rings without ending it.
+        Parser::new().feed(&mut grid, "\x1b[3\x071m");
+        assert_eq!(grid.take_bells(), 1);
+        assert_eq!(grid.style.fg, Color::Palette(1));
+    }
+
+    #[test]
+    fn sequences_split_across_writes_still_parse() {
+        let mut grid = Grid::new(10, 1);
+        let mut parser = Parser::new();
+        for part in ["x\x1b", "[3", "2", "my"] {
+            parser.feed(&mut grid, part);
+            assert_eq!(parser.is_ground(), part == "my");
+        }
+        assert_eq!(grid.row_text(0), "xy");
+        assert_eq!(grid.cell(0, 1).style.fg, Color::Palette(2));
+    }
+
+    #[test]
+    fn truncated_and_broken_sequences_are_abandoned() {
+        // Interrupted by another ESC: the new sequence applies.
+        let grid = grid_after("\x1b[31\x1b[32mA");
+        assert_eq!(grid.cell(0, 0).style.fg, Color::Palette(2));
+        // Broken by a printable byte that can't be in a CSI: it is dropped.
+        let grid = grid_after("\x1b[31\u{e9}A");
+        assert_eq!(grid.row_text(0), "A");
+        assert_eq!(grid.cell(0, 0).style, Style::default());
+        // Private and intermediate forms run to their end and do nothing.
+        let grid = grid_after("\x1b[?25lA\x1b[1 qB");
+        assert_eq!(grid.row_text(0), "AB");
+        // Other escapes are dropped with the character after ESC.
+        let grid = grid_after("\x1b7A\x1b8B");
+        assert_eq!(grid.row_text(0), "AB");
+        // A sequence cut off at the end leaves the parser waiting.
+        let mut parser = Parser::new();
+        let mut grid = Grid::new(10, 1);
+        parser.feed(&mut grid, "A\x1b[1");
+        assert!(!parser.is_ground());
+        assert_eq!(grid.row_text(0), "A");
+    }
+
+    #[test]
+    fn huge_parameters_are_clamped() {
+        let grid = grid_after("\x1b[99999999999999999999;2H");
+        assert_eq!(grid.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn reset_clears_the_grid() {
+        let grid = grid_after("\x1b[31mabc\x1bc");
+        assert_eq!(grid.row_text(0), "");
+        assert_eq!(grid.style, Style::default());
+        assert_eq!(grid.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn strip_leaves_only_the_text() {
+        assert_eq!(strip(&paint("ok", GREEN)), "ok");
+        assert_eq!(strip("\x1b[1;31merror:\x1b[0m bad \x1b[2Kthing"), "error: bad thing");
+        assert_eq!(strip("tab\there\nnext"), "tab\there\nnext");
+        assert_eq!(strip("cut \x1b[3"), "cut ");
+        assert_eq!(strip("wide \u{4e16}\u{754c}"), "wide \u{4e16}\u{754c}");
+        assert_eq!(strip(""), "");
+    }
+}
@@ -0,0 +1,268 @@
+// common/src/ax.rs
+
+//! `.ax` package archives: a manifest and the chunks it lists, in one file.
+//!
+//! This is what `axpkg pack` writes and the registry stores under
+//! `/var/aether/registry/packages`. Layout, all integers little-endian:
+//!
+//! ```text
+//! magic     8 bytes  "AETHERAX"
+//! version   u32      1
+//! manifest  u32 length, then PackageManifest::to_canonical_bytes()
+//! chunks    u32 count, then count x { u32 length, bytes }, in chunk_cids order
+//! ```
+//!
+//! `unpack` checks everything but the signature: the manifest's own
+//! consistency and every chunk against its CID. Whether the publisher is
+//! trusted, and the signature valid, is for the caller to decide with
+//! `common::trust`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::cid::compute_cid;
+use crate::manifest::{ManifestError, PackageManifest, CHUNK_SIZE};
+
+pub const MAGIC: &[u8; 8] = b"AETHERAX";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AxError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    TrailingBytes,
+    Manifest(ManifestError),
+    /// The archive doesn't hold one chunk per CID in the manifest.
+    ChunkCount { expected: usize, actual: usize },
+    /// Chunk `index` doesn't hash to its CID.
+    ChunkMismatch { index: usize },
+    /// A file's chunks don't add up to its size.
+    FileSize(String),
+}
+
+impl fmt::Display for AxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not an .ax archive"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported .ax version {}", version),
+            Self::Truncated => f.write_str("archive is truncated"),
+            Self::TrailingBytes => f.write_str("archive has trailing bytes"),
+            Self::Manifest(e) => write!(f, "{}", e),
+            Self::ChunkCount { expected, actual } => write!(f, "expected {} chunks, found {}", expected, actual),
+            Self::ChunkMismatch { index } => write!(f, "chunk {} does not match its CID", index),
+            Self::FileSize(path) => write!(f, "chunks of '{}' don't match its size", path),
+        }
+    }
+}
+
+impl From<ManifestError> for AxError {
+    fn from(e: ManifestError) -> Self {
+        AxError::Manifest(e)
+    }
+}
+
+/// A verified package, with its files reassembled.
+pub struct Package {
+    pub manifest: PackageManifest,
+    pub chunks: Vec<Vec<u8>>,
+    /// `(path, contents)`, in manifest order.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+pub fn pack(manifest: &PackageManifest, chunks: &[Vec<u8>]) -> Vec<u8> {
+    let encoded = manifest.to_canonical_bytes();
+    let mut out = Vec::with_capacity(encoded.len() + chunks.iter().map(|chunk| chunk.len() + 4).sum::<usize>() + 20);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    out.extend_from_slice(&encoded);
+    out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    for chunk in chunks {
+        out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// Packs a package whose files arrive as one run of bytes, such as the
+/// concatenated chunks the swarm engine fetches. The run is split back into
+/// chunks along the manifest's file sizes; the chunks are checked by `unpack`.
+pub fn pack_contents(manifest: &PackageManifest, contents: &[u8]) -> Result<Vec<u8>, AxError> {
+    let total: u64 = manifest.files.iter().map(|file| file.size).sum();
+    if total != contents.len() as u64 {
+        return Err(AxError::Truncated);
+    }
+    let mut chunks = Vec::with_capacity(manifest.chunk_cids.len());
+    let mut rest = contents;
+    for file in &manifest.files {
+        let (data, tail) = rest.split_at(file.size as usize);
+        rest = tail;
+        if data.is_empty() {
+            chunks.push(Vec::new());
+        } else {
+            chunks.extend(data.chunks(CHUNK_SIZE).map(|chunk| chunk.to_vec()));
+        }
+    }
+    Ok(pack(manifest, &chunks))
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], AxError> {
+    let end = pos.checked_add(len).filter(|end| *end <= bytes.len()).ok_or(AxError::Truncated)?;
+    let slice = &bytes[*pos..end];
+    *pos = end;
+    Ok(slice)
+}
+
+fn take_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, AxError> {
+    let mut raw = [0u8; 4];
+    raw.copy_from_slice(take(bytes, pos, 4)?);
+    Ok(u32::from_le_bytes(raw))
+}
+
+/// Parses and verifies an archive, except for the signature.
+pub fn unpack(bytes: &[u8]) -> Result<Package, AxError> {
+    let mut pos = 0;
+    if take(bytes, &mut pos, MAGIC.len()).map_err(|_| AxError::BadMagic)? != MAGIC {
+        return Err(AxError::BadMagic);
+    }
+    let version = take_u32(bytes, &mut pos)?;
+    if version != VERSION {
+        return Err(AxError::UnsupportedVersion(version));
+    }
+    let manifest_len = take_u32(bytes, &mut pos)? as usize;
+    let manifest = PackageManifest::from_canonical_bytes(take(bytes, &mut pos, manifest_len)?)?;
+    manifest.validate()?;
+
+    let count = take_u32(bytes, &mut pos)? as usize;
+    if count != manifest.chunk_cids.len() {
+        return Err(AxError::ChunkCount { expected: manifest.chunk_cids.len(), actual: count });
+    }
+    let mut chunks = Vec::with_capacity(count);
+    for (index, cid) in manifest.chunk_cids.iter().enumerate() {
+        let len = take_u32(bytes, &mut pos)? as usize;
+        let chunk = take(bytes, &mut pos, len)?;
+        if compute_cid(chunk) != *cid {
+            return Err(AxError::ChunkMismatch { index });
+        }
+        chunks.push(chunk.to_vec());
+    }
+    if pos != bytes.len() {
+        return Err(AxError::TrailingBytes);
+    }
+
+    let mut files = Vec::with_capacity(manifest.files.len());
+    let mut next = 0;
+    for file in &manifest.files {
+        let parts = &chunks[next..next + file.chunk_count()];
+        next += file.chunk_count();
+        let contents: Vec<u8> = parts.concat();
+        if contents.len() as u64 != file.size {
+            return Err(AxError::FileSize(file.path.clone()));
+        }
+        files.push((file.path.clone(), contents));
+    }
+    Ok(Package { manifest, chunks, files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::capability::Grant;
+    use crate::manifest::ManifestBuilder;
+    use crate::semver::SemVer;
+    use crate::trust::Aid;
+
+    /// A package with a file over two chunks long, an empty one and a small
+    /// one, signed with made-up bytes; `unpack` doesn't check the signature.
+    fn package(big: usize) -> (PackageManifest, Vec<Vec<u8>>, Vec<(String, Vec<u8>)>) {
+        let files = vec![
+            (String::from("bin/demo.vnode"), (0..big).map(|i| (i % 251) as u8).collect::<Vec<u8>>()),
+            (String::from("etc/demo/empty"), Vec::new()),
+            (String::from("etc/demo/config.toml"), b"greeting = \"hello\"\n".to_vec()),
+        ];
+        let mut builder = ManifestBuilder::new("demo", SemVer::parse("1.2.0-rc.1").unwrap())
+            .description("A demo package")
+            .tag("demo")
+            .publisher(Aid([7; 32]))
+            .grant(Grant::parse("StorageAccess:/data/demo").unwrap())
+            .syscall("SYS_NET_TX");
+        for (path, contents) in &files {
+            builder = builder.file(path, contents);
+        }
+        let (mut manifest, chunks) = builder.build().unwrap();
+        manifest.signature = vec![0x5A; 64];
+        let mut sorted = files;
+        sorted.sort();
+        (manifest, chunks, sorted)
+    }
+
+    #[test]
+    fn a_packed_archive_unpacks_to_the_same_package() {
+        let (manifest, chunks, files) = package(2 * CHUNK_SIZE + 5);
+        assert_eq!(chunks.len(), 5, "three chunks for the big file, one each for the others");
+        let archive = pack(&manifest, &chunks);
+        let package = unpack(&archive).unwrap();
+        assert_eq!(package.files, files);
+        assert_eq!(package.chunks, chunks);
+        assert_eq!(package.manifest.to_canonical_bytes(), manifest.to_canonical_bytes());
+        assert_eq!(package.manifest.root_cid, manifest.root_cid);
+        assert_eq!(package.manifest.signature, manifest.signature);
+        assert_eq!(package.manifest.version.to_string(), "1.2.0-rc.1");
+        // Packing what came out gives the same bytes again.
+        assert_eq!(pack(&package.manifest, &package.chunks), archive);
+    }
+
+    #[test]
+    fn packing_the_concatenated_contents_gives_the_same_archive() {
+        let (manifest, chunks, _) = package(CHUNK_SIZE + 1);
+        let contents = chunks.concat();
+        assert_eq!(pack_contents(&manifest, &contents).unwrap(), pack(&manifest, &chunks));
+        assert_eq!(pack_contents(&manifest, &contents[1..]), Err(AxError::Truncated));
+    }
+
+    #[test]
+    fn damaged_archives_are_refused() {
+        let (manifest, chunks, _) = package(300);
+        let archive = pack(&manifest, &chunks);
+        let manifest_len = u32::from_le_bytes(archive[12..16].try_into().unwrap()) as usize;
+        let chunks_at = 16 + manifest_len;
+
+        let mut bad = archive.clone();
+        bad[0] = b'X';
+        assert!(matches!(unpack(&bad), Err(AxError::BadMagic)));
+        assert!(matches!(unpack(&archive[..4]), Err(AxError::BadMagic)));
+
+        let mut bad = archive.clone();
+        bad[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(unpack(&bad), Err(AxError::UnsupportedVersion(2))));
+
+        for len in [12, chunks_at - 1, chunks_at + 3, archive.len() - 1] {
+            assert!(unpack(&archive[..len]).is_err(), "truncated to {} bytes", len);
+        }
+        assert!(matches!(unpack(&archive[..archive.len() - 1]), Err(AxError::Truncated)));
+
+        let mut bad = archive.clone();
+        bad.push(0);
+        assert!(matches!(unpack(&bad), Err(AxError::TrailingBytes)));
+
+        // A flipped byte in the first chunk, the big file's.
+        let mut bad = archive.clone();
+        bad[chunks_at + 4 + 4] ^= 1;
+        assert_eq!(unpack(&bad).err(), Some(AxError::ChunkMismatch { index: 0 }));
+
+        let mut bad = archive.clone();
+        bad[chunks_at..chunks_at + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(unpack(&bad), Err(AxError::ChunkCount { expected: 3, actual: 2 })));
+
+        // Chunks that hash right but belong to another manifest.
+        let (other, _, _) = package(301);
+        assert_eq!(unpack(&pack(&other, &chunks)).err(), Some(AxError::ChunkMismatch { index: 0 }));
+    }
+}
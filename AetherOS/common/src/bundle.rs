@@ -0,0 +1,172 @@
+// common/src/bundle.rs
+
+//! Offline bundles: several packages and every chunk they reference, in one
+//! file, for machines without swarm access.
+//!
+//! The registry writes them with `RegistryRequest::Export` and installs them
+//! with `ImportBundle`; `axpkg bundle` and `axpkg inspect` do the same on the
+//! host. Layout, all integers little-endian:
+//!
+//! ```text
+//! magic     8 bytes  "AETHERBN"
+//! version   u32      1
+//! index     u32 count, then count x { u32 length, PackageManifest::to_canonical_bytes() }
+//! chunks    u32 count, then count x { 32-byte CID, u32 length, bytes }
+//! checksum  CID of everything above
+//! ```
+//!
+//! A chunk shared by several packages is stored once. `parse` checks the
+//! checksum, every chunk against its CID and that each package has all of its
+//! chunks, so a partial bundle fails before anything is installed. Signatures
+//! are left to the caller, as with `.ax` archives.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::ax::{self, AxError, Package};
+use crate::cid::compute_cid;
+use crate::manifest::{ManifestError, PackageManifest};
+
+pub const MAGIC: &[u8; 8] = b"AETHERBN";
+pub const VERSION: u32 = 1;
+
+const CID_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    TrailingBytes,
+    /// The bytes don't match the checksum at the end.
+    Checksum,
+    /// Manifest `index` in the index doesn't parse or validate.
+    Manifest { index: usize, error: ManifestError },
+    /// Entry `index` of the chunk table doesn't hash to its CID.
+    ChunkMismatch { index: usize },
+    /// `package` references a chunk the bundle doesn't contain; `index` is
+    /// its position in the package's `chunk_cids`.
+    MissingChunk { package: String, index: usize },
+    /// The package's chunks don't add up to its files.
+    Package { package: String, error: AxError },
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not a package bundle"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported bundle version {}", version),
+            Self::Truncated => f.write_str("bundle is truncated"),
+            Self::TrailingBytes => f.write_str("bundle has trailing bytes"),
+            Self::Checksum => f.write_str("bundle checksum does not match"),
+            Self::Manifest { index, error } => write!(f, "manifest {}: {}", index, error),
+            Self::ChunkMismatch { index } => write!(f, "chunk {} does not match its CID", index),
+            Self::MissingChunk { package, index } => write!(f, "'{}' is missing chunk {}", package, index),
+            Self::Package { package, error } => write!(f, "'{}': {}", package, error),
+        }
+    }
+}
+
+/// Writes a bundle of `packages`, each with its chunks in `chunk_cids` order.
+pub fn build(packages: &[(PackageManifest, Vec<Vec<u8>>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(packages.len() as u32).to_le_bytes());
+    for (manifest, _) in packages {
+        let encoded = manifest.to_canonical_bytes();
+        out.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        out.extend_from_slice(&encoded);
+    }
+
+    let mut chunks: BTreeMap<&[u8], &[u8]> = BTreeMap::new();
+    for (manifest, data) in packages {
+        for (cid, chunk) in manifest.chunk_cids.iter().zip(data) {
+            chunks.insert(&cid.as_bytes()[..], chunk);
+        }
+    }
+    out.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    for (cid, chunk) in chunks {
+        out.extend_from_slice(cid);
+        out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    let checksum = compute_cid(&out);
+    out.extend_from_slice(checksum.as_bytes());
+    out
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], BundleError> {
+    let end = pos.checked_add(len).filter(|end| *end <= bytes.len()).ok_or(BundleError::Truncated)?;
+    let slice = &bytes[*pos..end];
+    *pos = end;
+    Ok(slice)
+}
+
+fn take_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, BundleError> {
+    let mut raw = [0u8; 4];
+    raw.copy_from_slice(take(bytes, pos, 4)?);
+    Ok(u32::from_le_bytes(raw))
+}
+
+/// Parses and verifies a bundle, except for the signatures. Packages come
+/// back in index order, as verified `.ax` packages.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Package>, BundleError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(BundleError::BadMagic);
+    }
+    let body_len = bytes.len().checked_sub(CID_LEN).ok_or(BundleError::Truncated)?;
+    let (body, checksum) = bytes.split_at(body_len);
+    let mut pos = MAGIC.len();
+    let version = take_u32(body, &mut pos)?;
+    if version != VERSION {
+        return Err(BundleError::UnsupportedVersion(version));
+    }
+    if compute_cid(body).as_bytes()[..] != *checksum {
+        return Err(BundleError::Checksum);
+    }
+
+    let count = take_u32(body, &mut pos)? as usize;
+    let mut manifests = Vec::new();
+    for index in 0..count {
+        let len = take_u32(body, &mut pos)? as usize;
+        let manifest = PackageManifest::from_canonical_bytes(take(body, &mut pos, len)?)
+            .and_then(|manifest| manifest.validate().map(|()| manifest))
+            .map_err(|error| BundleError::Manifest { index, error })?;
+        manifests.push(manifest);
+    }
+
+    let count = take_u32(body, &mut pos)? as usize;
+    let mut chunks: BTreeMap<&[u8], &[u8]> = BTreeMap::new(); // By CID bytes
+    for index in 0..count {
+        let cid = take(body, &mut pos, CID_LEN)?;
+        let len = take_u32(body, &mut pos)? as usize;
+        let chunk = take(body, &mut pos, len)?;
+        if compute_cid(chunk).as_bytes()[..] != *cid {
+            return Err(BundleError::ChunkMismatch { index });
+        }
+        chunks.insert(cid, chunk);
+    }
+    if pos != body.len() {
+        return Err(BundleError::TrailingBytes);
+    }
+
+    manifests.into_iter().map(|manifest| {
+        let mut data = Vec::with_capacity(manifest.chunk_cids.len());
+        for (index, cid) in manifest.chunk_cids.iter().enumerate() {
+            match chunks.get(&cid.as_bytes()[..]) {
+                Some(chunk) => data.push(chunk.to_vec()),
+                None => return Err(BundleError::MissingChunk { package: manifest.name.clone(), index }),
+            }
+        }
+        // Through the archive format, so a bundled package gets exactly the checks an .ax file does.
+        ax::unpack(&ax::pack(&manifest, &data)).map_err(|error| BundleError::Package { package: manifest.name.clone(), error })
+    }).collect()
+}
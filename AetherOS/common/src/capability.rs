@@ -0,0 +1,221 @@
+// common/src/capability.rs
+
+//! Capabilities a package asks for, as its manifest declares them and as the
+//! registry records them once the user has approved them.
+//!
+//! `Capability` mirrors the kernel's `caps::Capability`, which stays out of
+//! the wire formats; the kernel converts from this one. A `Grant` is a
+//! capability with an optional scope, e.g. `StorageAccess` limited to
+//! `/data/app-x`, written `StorageAccess:/data/app-x` in manifests, package
+//! metadata and init's service configuration. A scope only ever narrows: an
+//! unscoped grant covers every scoped grant of the same capability, and a
+//! scoped one covers the scopes below its own path.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::abi::syscall_set_from_names;
+
+pub const MAX_SCOPE_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Capability {
+    LogWrite,
+    LogRead,
+    TimeRead,
+    NetworkAccess,
+    StorageAccess,
+    IrqRegister(u8),
+    DmaAlloc,
+    DmaAccess,
+    IrqAck(u8),
+    IpcManage,
+    FramebufferAccess,
+    BootStatus,
+    IdentityAdmin,
+    Debug,
+    AudioOutput,
+    PowerControl,
+    TaskMonitor,
+}
+
+impl Capability {
+    const PLAIN: [Capability; 15] = [
+        Self::LogWrite, Self::LogRead, Self::TimeRead, Self::NetworkAccess, Self::StorageAccess, Self::DmaAlloc, Self::DmaAccess,
+        Self::IpcManage, Self::FramebufferAccess, Self::BootStatus, Self::IdentityAdmin, Self::Debug, Self::AudioOutput, Self::PowerControl,
+        Self::TaskMonitor,
+    ];
+
+    /// Parses the `Display` form: the variant name, with the IRQ in
+    /// parentheses for `IrqRegister(11)` and `IrqAck(11)`.
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(plain) = Self::PLAIN.iter().find(|cap| format!("{}", cap) == text) {
+            return Some(*plain);
+        }
+        let (name, irq) = text.strip_suffix(')')?.split_once('(')?;
+        let irq = irq.parse().ok()?;
+        match name {
+            "IrqRegister" => Some(Self::IrqRegister(irq)),
+            "IrqAck" => Some(Self::IrqAck(irq)),
+            _ => None,
+        }
+    }
+
+    /// Whether the capability can be limited to a path.
+    pub fn is_scopable(&self) -> bool {
+        matches!(self, Self::StorageAccess)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IrqRegister(irq) => write!(f, "IrqRegister({})", irq),
+            Self::IrqAck(irq) => write!(f, "IrqAck({})", irq),
+            other => fmt::Debug::fmt(other, f),
+        }
+    }
+}
+
+/// An absolute path without empty, `.` or `..` components.
+pub fn is_valid_scope(scope: &str) -> bool {
+    scope.len() <= MAX_SCOPE_LEN
+        && scope.starts_with('/')
+        && scope.len() > 1
+        && !scope.contains('\0')
+        && scope[1..].split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// Whether `inner` is `outer` or below it.
+fn scope_within(inner: &str, outer: &str) -> bool {
+    inner == outer || inner.strip_prefix(outer).is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Grant {
+    pub capability: Capability,
+    /// The path the grant is limited to; `None` is all of it.
+    pub scope: Option<String>,
+}
+
+impl Grant {
+    pub fn new(capability: Capability) -> Self {
+        Self { capability, scope: None }
+    }
+
+    pub fn scoped(capability: Capability, scope: &str) -> Self {
+        Self { capability, scope: Some(scope.to_string()) }
+    }
+
+    /// Parses `spec` output, e.g. `NetworkAccess` or `StorageAccess:/data/app-x`.
+    /// Only scopable capabilities take a scope, and it must be a valid one.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.split_once(':') {
+            None => Capability::parse(text).map(Self::new),
+            Some((name, scope)) => {
+                let capability = Capability::parse(name).filter(Capability::is_scopable)?;
+                is_valid_scope(scope).then(|| Self::scoped(capability, scope))
+            },
+        }
+    }
+
+    /// The machine-readable form, as `parse` reads it.
+    pub fn spec(&self) -> String {
+        match &self.scope {
+            Some(scope) => format!("{}:{}", self.capability, scope),
+            None => self.capability.to_string(),
+        }
+    }
+
+    /// Whether holding `self` already gives everything `other` asks for.
+    pub fn covers(&self, other: &Grant) -> bool {
+        self.capability == other.capability
+            && match (&self.scope, &other.scope) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(outer), Some(inner)) => scope_within(inner, outer),
+            }
+    }
+
+    /// The part of `self` that `limit` also allows, if any: the narrower of the two.
+    pub fn narrowed_to(&self, limit: &Grant) -> Option<Grant> {
+        if limit.covers(self) {
+            Some(self.clone())
+        } else if self.covers(limit) {
+            Some(limit.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// How the grant is shown to the user, e.g. "StorageAccess limited to /data/app-x".
+impl fmt::Display for Grant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.scope {
+            Some(scope) => write!(f, "{} limited to {}", self.capability, scope),
+            None => write!(f, "{}", self.capability),
+        }
+    }
+}
+
+/// What a package asks for: capabilities and the syscalls its service may make.
+/// Both lists are kept sorted and free of duplicates by `normalize`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeclaredCapabilities {
+    pub grants: Vec<Grant>,
+    /// Syscall names for the service's filter, on top of init's `BASE_SYSCALLS`.
+    pub syscalls: Vec<String>,
+}
+
+impl DeclaredCapabilities {
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty() && self.syscalls.is_empty()
+    }
+
+    pub fn normalize(&mut self) {
+        self.grants.sort();
+        self.grants.dedup();
+        self.syscalls.sort();
+        self.syscalls.dedup();
+    }
+
+    /// The first problem: a scope on a capability that takes none, a bad
+    /// scope, or a syscall name that isn't one.
+    pub fn check(&self) -> Result<(), String> {
+        for grant in &self.grants {
+            if let Some(scope) = &grant.scope {
+                if !grant.capability.is_scopable() || !is_valid_scope(scope) {
+                    return Err(format!("invalid grant '{}'", grant.spec()));
+                }
+            }
+        }
+        syscall_set_from_names(self.syscalls.iter().map(String::as_str))
+            .map(|_| ())
+            .map_err(|name| format!("unknown syscall '{}'", name))
+    }
+
+    /// Whether everything asked for here is already covered by `other`.
+    pub fn is_within(&self, other: &DeclaredCapabilities) -> bool {
+        self.grants.iter().all(|grant| other.grants.iter().any(|held| held.covers(grant)))
+            && self.syscalls.iter().all(|name| other.syscalls.contains(name))
+    }
+
+    /// What `self` holds that `limit` also allows, grant by grant, never more than either.
+    pub fn narrowed_to(&self, limit: &DeclaredCapabilities) -> DeclaredCapabilities {
+        let mut narrowed = DeclaredCapabilities {
+            grants: self.grants.iter()
+                .flat_map(|grant| limit.grants.iter().filter_map(|allowed| grant.narrowed_to(allowed)))
+                .collect(),
+            syscalls: self.syscalls.iter().filter(|name| limit.syscalls.contains(name)).cloned().collect(),
+        };
+        narrowed.normalize();
+        narrowed
+    }
+}
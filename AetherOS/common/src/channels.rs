@@ -0,0 +1,67 @@
+// common/src/channels.rs
+
+//! Well-known IPC channel IDs.
+//!
+//! Channels below `FIRST_DYNAMIC_CHANNEL` are fixed: each belongs to one
+//! service, or is where a service receives one kind of message (events from
+//! the bus, readiness notices, ...). Init hands the same IDs out in the
+//! startup info, and a service uses the constant here when it was started
+//! without one. Channels from `FIRST_DYNAMIC_CHANNEL` up to `MAX_CHANNELS`
+//! are handed out at run time by the kernel's mailbox allocator and by init,
+//! so nothing may hard-code one of them.
+//!
+//! A new well-known channel takes the next free ID below
+//! `FIRST_DYNAMIC_CHANNEL` and goes in this table.
+
+pub const REGISTRY: u32 = 1;
+pub const NET_BRIDGE: u32 = 2;
+/// The network stack, `svc://aethernet-service`.
+pub const NET_STACK: u32 = 3;
+pub const SOCKET_API: u32 = 4;
+pub const DNS_RESOLVER: u32 = 5;
+pub const INIT_SERVICE: u32 = 6;
+pub const VFS: u32 = 7;
+pub const SHELL: u32 = 8;
+pub const FILE_MANAGER: u32 = 9;
+pub const MAIL_SERVICE: u32 = 10;
+pub const MODEL_RUNTIME: u32 = 11;
+pub const DISPLAY_COMPOSITOR: u32 = 12;
+pub const EVENT_BUS: u32 = 13;
+pub const SETTINGS: u32 = 14;
+pub const SESSION: u32 = 15;
+pub const SYSMON: u32 = 16;
+/// Policy changes and interface events the bus delivers to socket-api.
+pub const SOCKET_API_EVENTS: u32 = 17;
+/// Limit changes the bus delivers to the registry.
+pub const REGISTRY_EVENTS: u32 = 18;
+/// Interface events the bus delivers to the DNS resolver.
+pub const DNS_RESOLVER_EVENTS: u32 = 19;
+pub const NOTIFICATIONS: u32 = 20;
+/// Clicks on notifications, from the compositor.
+pub const NOTIFICATIONS_CLICKS: u32 = 21;
+/// Events the bus delivers to notifications.
+pub const NOTIFICATIONS_EVENTS: u32 = 22;
+pub const AUDIO_MIXER: u32 = 23;
+/// The sound card's period completions.
+pub const AUDIO_MIXER_IRQ: u32 = 24;
+/// Events the bus delivers to the audio mixer.
+pub const AUDIO_MIXER_EVENTS: u32 = 25;
+/// Events the bus delivers to the console shell.
+pub const SHELL_EVENTS: u32 = 26;
+/// Events the bus delivers to init.
+pub const INIT_EVENTS: u32 = 27;
+/// The network stack's notices of readable sockets, to socket-api.
+pub const SOCKET_API_READY: u32 = 28;
+/// socket-api's `DataAvailable` notices, to the DNS resolver.
+pub const DNS_RESOLVER_READY: u32 = 29;
+/// The kernel's copies of `SYS_LOG` messages, to logd.
+pub const LOGD_RECORDS: u32 = 30;
+/// The VFS's change notices to the console shell's `logs --follow`.
+pub const SHELL_VFS_WATCH: u32 = 31;
+
+/// First channel ID handed out at run time.
+pub const FIRST_DYNAMIC_CHANNEL: u32 = 32;
+/// Number of channels the kernel has; IDs run from 0 to one less.
+pub const MAX_CHANNELS: u32 = 64;
+
+const _: () = assert!(SHELL_VFS_WATCH < FIRST_DYNAMIC_CHANNEL && FIRST_DYNAMIC_CHANNEL < MAX_CHANNELS);
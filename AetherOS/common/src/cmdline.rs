@@ -0,0 +1,259 @@
+// common/src/cmdline.rs
+
+//! The kernel command line: options that change one boot without a rebuild.
+//!
+//! A command line is a list of words separated by spaces. A word is either
+//! `key=value` or a bare flag:
+//!
+//! ```text
+//! loglevel=debug fbcon=off driver.off=ac97 driver.off=ps2_mouse init.target=minimal memtest
+//! ```
+//!
+//! *   Quotes group a value with spaces in it: `motd="hello world"`. Either
+//!     `"` or `'` works, the other kind is literal inside, and the quotes
+//!     themselves are dropped, so `a="b"c` is `a=bc`. An unterminated quote
+//!     is an error.
+//! *   `key=` sets `key` to the empty string, which is not the same as the
+//!     flag `key`.
+//! *   A key given more than once keeps every value, in order. `value` gives
+//!     the last one, `values` all of them.
+//! *   Keys nobody knows are kept. The kernel applies the options it knows
+//!     and hands the whole line to services, which pick out theirs, so a
+//!     service's option needs no kernel change. Services prefix their keys
+//!     with their name, e.g. `init.target`.
+//!
+//! The same parser runs in the kernel, in V-Nodes (`read` wraps
+//! `SYS_BOOT_ARGS`) and on the host, where `axpkg initrd --cmdline` checks a
+//! line before writing it to `BOOT_CMDLINE_PATH` in the image.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::abi::SYS_BOOT_ARGS;
+use crate::syscall::syscall3;
+use crate::text;
+
+/// Where `axpkg initrd --cmdline` puts the line in the boot image.
+pub const BOOT_CMDLINE_PATH: &str = "/boot/cmdline";
+/// Longest command line the kernel accepts.
+pub const MAX_CMDLINE_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CmdlineError {
+    TooLong(usize),
+    /// A quote opened at this byte offset is never closed.
+    UnterminatedQuote(usize),
+    /// A word at this byte offset starts with `=`.
+    EmptyKey(usize),
+}
+
+impl fmt::Display for CmdlineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong(len) => write!(f, "command line is {} bytes, the limit is {}", len, MAX_CMDLINE_BYTES),
+            Self::UnterminatedQuote(at) => write!(f, "quote at offset {} is never closed", at),
+            Self::EmptyKey(at) => write!(f, "option at offset {} has no name before '='", at),
+        }
+    }
+}
+
+/// One word of the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootOption {
+    Flag(String),
+    Value { key: String, value: String },
+}
+
+impl BootOption {
+    pub fn key(&self) -> &str {
+        match self {
+            BootOption::Flag(key) => key,
+            BootOption::Value { key, .. } => key,
+        }
+    }
+}
+
+/// A parsed command line, in the order it was written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootArgs {
+    options: Vec<BootOption>,
+}
+
+/// Splits `line` into words, dropping quotes. Each word comes with the byte
+/// offset it starts at, for errors.
+fn words(line: &str) -> Result<Vec<(usize, String, Option<usize>)>, CmdlineError> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let Some(&(start, _)) = chars.peek() else { break };
+        let mut word = String::new();
+        let mut equals = None; // Where the first unquoted '=' ended up in `word`
+        let mut quote: Option<(char, usize)> = None;
+        while let Some(&(at, c)) = chars.peek() {
+            match quote {
+                Some((open, _)) if c == open => quote = None,
+                Some(_) => word.push(c),
+                None if c.is_whitespace() => break,
+                None if c == '"' || c == '\'' => quote = Some((c, at)),
+                None => {
+                    if c == '=' && equals.is_none() {
+                        equals = Some(word.len());
+                    }
+                    word.push(c);
+                },
+            }
+            chars.next();
+        }
+        if let Some((_, at)) = quote {
+            return Err(CmdlineError::UnterminatedQuote(at));
+        }
+        words.push((start, word, equals));
+    }
+    Ok(words)
+}
+
+impl BootArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(line: &str) -> Result<Self, CmdlineError> {
+        if line.len() > MAX_CMDLINE_BYTES {
+            return Err(CmdlineError::TooLong(line.len()));
+        }
+        let mut options = Vec::new();
+        for (start, word, equals) in words(line)? {
+            let option = match equals {
+                Some(0) => return Err(CmdlineError::EmptyKey(start)),
+                Some(at) => BootOption::Value { key: String::from(&word[..at]), value: String::from(&word[at + 1..]) },
+                None => BootOption::Flag(word),
+            };
+            options.push(option);
+        }
+        Ok(Self { options })
+    }
+
+    pub fn options(&self) -> &[BootOption] {
+        &self.options
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    /// Whether `name` was given as a bare flag.
+    pub fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|option| matches!(option, BootOption::Flag(flag) if flag == name))
+    }
+
+    /// Every value given for `key`, in order.
+    pub fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options.iter().filter_map(move |option| match option {
+            BootOption::Value { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The last value given for `key`, which is the one that counts when an
+    /// option can only have one.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.options.iter().rev().find_map(|option| match option {
+            BootOption::Value { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Why the command line couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// The kernel says the call is filtered or unknown.
+    Unavailable(u64),
+    /// The kernel's line doesn't parse. The kernel checked it at boot, so
+    /// this means the two disagree about the syntax.
+    Invalid(CmdlineError),
+}
+
+/// This boot's command line, from `SYS_BOOT_ARGS`. Empty when the boot had
+/// none.
+pub fn read() -> Result<BootArgs, ReadError> {
+    let mut buf = alloc::vec![0u8; MAX_CMDLINE_BYTES];
+    let res = unsafe { syscall3(SYS_BOOT_ARGS, buf.as_mut_ptr() as u64, buf.len() as u64, 0) };
+    if res as usize > buf.len() {
+        return Err(ReadError::Unavailable(res));
+    }
+    buf.truncate(res as usize);
+    BootArgs::parse(&text::from_utf8_lossy(&buf)).map_err(ReadError::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> BootArgs {
+        BootArgs::parse(line).unwrap()
+    }
+
+    #[test]
+    fn empty_value_is_not_a_flag() {
+        let args = parse("key=");
+        assert_eq!(args.value("key"), Some(""));
+        assert!(!args.flag("key"));
+        assert_eq!(BootArgs::parse("a=1 =x"), Err(CmdlineError::EmptyKey(4)));
+    }
+
+    #[test]
+    fn bare_words_are_flags() {
+        let args = parse("memtest loglevel=debug");
+        assert!(args.flag("memtest"));
+        assert!(!args.flag("loglevel"));
+        assert_eq!(args.value("memtest"), None);
+    }
+
+    #[test]
+    fn quotes_group_and_are_dropped() {
+        assert_eq!(parse("msg=\"a b  c\"").value("msg"), Some("a b  c"));
+        assert_eq!(parse("msg='say \"hi\"'").value("msg"), Some("say \"hi\""));
+        assert_eq!(parse("a=\"b\"c").value("a"), Some("bc"));
+        assert_eq!(parse("msg=\"x=y\"").options(), &[BootOption::Value { key: "msg".into(), value: "x=y".into() }]);
+        assert_eq!(BootArgs::parse("a=\"b"), Err(CmdlineError::UnterminatedQuote(2)));
+    }
+
+    #[test]
+    fn repeated_keys_keep_every_value() {
+        let args = parse("x=1 x=2 x=");
+        assert_eq!(args.values("x").collect::<Vec<_>>(), ["1", "2", ""]);
+        assert_eq!(args.value("x"), Some(""));
+        assert_eq!(args.values("y").count(), 0);
+    }
+
+    #[test]
+    fn any_whitespace_separates_words() {
+        let args = parse(" a=1\tb=2\n\n  c ");
+        assert_eq!(args.options().len(), 3);
+        assert_eq!(args.value("b"), Some("2"));
+        assert!(args.flag("c"));
+        assert!(parse("").is_empty());
+        assert!(parse(" \t\n ").is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_kept() {
+        let args = parse("init.target=minimal vendor.thing=1");
+        let keys: Vec<&str> = args.options().iter().map(BootOption::key).collect();
+        assert_eq!(keys, ["init.target", "vendor.thing"]);
+    }
+
+    #[test]
+    fn long_lines_are_rejected() {
+        let line = "a".repeat(MAX_CMDLINE_BYTES + 1);
+        assert_eq!(BootArgs::parse(&line), Err(CmdlineError::TooLong(MAX_CMDLINE_BYTES + 1)));
+        assert!(BootArgs::parse(&"a".repeat(MAX_CMDLINE_BYTES)).is_ok());
+    }
+}
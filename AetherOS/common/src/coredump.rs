@@ -0,0 +1,322 @@
+// common/src/coredump.rs
+
+//! The core dump format. The kernel stages a dump when it kills a task for a
+//! fault it can't go on from, or when a debugger asks with `SYS_DEBUG_DUMP`;
+//! init takes it with `SYS_CORE_DUMP_TAKE` and writes it to `/data/crash`,
+//! and `tools/coredump-inspect` reads it on the host.
+//!
+//! Layout, all integers little-endian:
+//!
+//! ```text
+//! header     CORE_HEADER_LEN (88) bytes, see `CoreHeader::to_bytes`
+//! registers  REGISTERS_LEN (160) bytes: the 20 `RegisterFrame` fields in declaration order
+//! regions    `region_count` records, each a REGION_HEADER_LEN (32) byte
+//!            `RegionHeader` followed by `stored` bytes of the region's
+//!            contents from its start
+//! ```
+//!
+//! Only writable regions have contents; code and read-only data are in the
+//! binary, and file mappings are in the file. A region that was cut to fit
+//! the size cap has `stored` below `memsz`, and the header has
+//! `CORE_FLAG_TRUNCATED`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::abi::{RegisterFrame, TASK_NAME_LEN};
+
+/// First eight bytes of every dump.
+pub const CORE_MAGIC: [u8; 8] = *b"AECORE01";
+/// Bumped whenever the layout changes.
+pub const CORE_VERSION: u32 = 1;
+
+pub const CORE_HEADER_LEN: usize = 88;
+pub const REGISTERS_LEN: usize = 20 * 8;
+pub const REGION_HEADER_LEN: usize = 32;
+
+/// Where `/data/crash` is; init writes `<service>-<task>-<tick>.core` there.
+pub const CRASH_DIR: &str = "/data/crash";
+
+/// Values of `CoreHeader::reason`.
+pub const CORE_REASON_PAGE_FAULT: u32 = 1;
+pub const CORE_REASON_GENERAL_PROTECTION: u32 = 2;
+pub const CORE_REASON_INVALID_OPCODE: u32 = 3;
+pub const CORE_REASON_ALIGNMENT_CHECK: u32 = 4;
+/// Taken with `SYS_DEBUG_DUMP`; the task goes on running.
+pub const CORE_REASON_REQUESTED: u32 = 5;
+
+/// Bits of `CoreHeader::flags`.
+/// At least one region was cut to fit the size cap.
+pub const CORE_FLAG_TRUNCATED: u32 = 1 << 0;
+/// The task's service asked for no dumps: there are no registers and no
+/// regions, only the header saying it crashed.
+pub const CORE_FLAG_WITHHELD: u32 = 1 << 1;
+/// Only `rip`, `rsp`, `rflags`, `cs` and `ss` are from the fault. The
+/// exception handlers don't get the general registers, so the others are
+/// the ones saved when the task was last switched out.
+pub const CORE_FLAG_PARTIAL_REGISTERS: u32 = 1 << 2;
+
+/// Bits of `RegionHeader::flags`.
+pub const REGION_READ: u32 = 1 << 0;
+pub const REGION_WRITE: u32 = 1 << 1;
+pub const REGION_EXECUTE: u32 = 1 << 2;
+/// A read-only file mapping (`SYS_MAP_FILE`) rather than a segment of the image.
+pub const REGION_FILE: u32 = 1 << 3;
+
+/// What a reason code means, for logs and the inspector.
+pub fn reason_name(reason: u32) -> &'static str {
+    match reason {
+        CORE_REASON_PAGE_FAULT => "page fault",
+        CORE_REASON_GENERAL_PROTECTION => "general protection fault",
+        CORE_REASON_INVALID_OPCODE => "invalid opcode",
+        CORE_REASON_ALIGNMENT_CHECK => "alignment check",
+        CORE_REASON_REQUESTED => "requested",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CoreHeader {
+    /// One of the `CORE_REASON_*` values.
+    pub reason: u32,
+    pub task: u64,
+    /// The address a page fault was for (CR2); 0 for the other reasons.
+    pub fault_addr: u64,
+    /// The exception's error code, if it has one.
+    pub error_code: u64,
+    /// Timer tick the dump was taken at.
+    pub tick: u64,
+    pub region_count: u32,
+    /// `CORE_FLAG_*` bits.
+    pub flags: u32,
+    /// The task's name, NUL-padded; longer names are cut.
+    pub name: [u8; TASK_NAME_LEN],
+}
+
+impl CoreHeader {
+    /// Layout: magic (8 bytes), version (LE u32), reason (LE u32), task,
+    /// fault_addr, error_code, tick (LE u64 each), region_count, flags (LE
+    /// u32 each), name (32 bytes).
+    pub fn to_bytes(&self) -> [u8; CORE_HEADER_LEN] {
+        let mut out = [0u8; CORE_HEADER_LEN];
+        out[0..8].copy_from_slice(&CORE_MAGIC);
+        out[8..12].copy_from_slice(&CORE_VERSION.to_le_bytes());
+        out[12..16].copy_from_slice(&self.reason.to_le_bytes());
+        out[16..24].copy_from_slice(&self.task.to_le_bytes());
+        out[24..32].copy_from_slice(&self.fault_addr.to_le_bytes());
+        out[32..40].copy_from_slice(&self.error_code.to_le_bytes());
+        out[40..48].copy_from_slice(&self.tick.to_le_bytes());
+        out[48..52].copy_from_slice(&self.region_count.to_le_bytes());
+        out[52..56].copy_from_slice(&self.flags.to_le_bytes());
+        out[56..88].copy_from_slice(&self.name);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CoreError> {
+        if bytes.len() < CORE_HEADER_LEN {
+            return Err(CoreError::Short);
+        }
+        if bytes[0..8] != CORE_MAGIC {
+            return Err(CoreError::NotACore);
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != CORE_VERSION {
+            return Err(CoreError::Version(version));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Ok(Self {
+            reason: u32_at(12),
+            task: u64_at(16),
+            fault_addr: u64_at(24),
+            error_code: u64_at(32),
+            tick: u64_at(40),
+            region_count: u32_at(48),
+            flags: u32_at(52),
+            name: bytes[56..88].try_into().unwrap(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(TASK_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+fn register_fields(regs: &RegisterFrame) -> [u64; 20] {
+    [
+        regs.rip, regs.rsp, regs.rbp, regs.rflags, regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi,
+        regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.cs, regs.ss,
+    ]
+}
+
+/// Layout: the 20 fields of `RegisterFrame` as LE u64s, `rip` first, `ss` last.
+pub fn registers_to_bytes(regs: &RegisterFrame) -> [u8; REGISTERS_LEN] {
+    let mut out = [0u8; REGISTERS_LEN];
+    for (i, value) in register_fields(regs).iter().enumerate() {
+        out[i * 8..i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+pub fn registers_from_bytes(bytes: &[u8]) -> Option<RegisterFrame> {
+    let bytes = bytes.get(..REGISTERS_LEN)?;
+    let field = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+    Some(RegisterFrame {
+        rip: field(0), rsp: field(1), rbp: field(2), rflags: field(3),
+        rax: field(4), rbx: field(5), rcx: field(6), rdx: field(7), rsi: field(8), rdi: field(9),
+        r8: field(10), r9: field(11), r10: field(12), r11: field(13), r12: field(14), r13: field(15), r14: field(16), r15: field(17),
+        cs: field(18), ss: field(19),
+    })
+}
+
+/// The record in front of each region's contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RegionHeader {
+    pub vaddr: u64,
+    pub memsz: u64,
+    /// `REGION_*` bits.
+    pub flags: u32,
+    /// Bytes of contents that follow, from `vaddr` on; at most `memsz`.
+    pub stored: u64,
+}
+
+impl RegionHeader {
+    /// Layout: vaddr, memsz (LE u64 each), flags (LE u32), 4 reserved zero
+    /// bytes, stored (LE u64).
+    pub fn to_bytes(&self) -> [u8; REGION_HEADER_LEN] {
+        let mut out = [0u8; REGION_HEADER_LEN];
+        out[0..8].copy_from_slice(&self.vaddr.to_le_bytes());
+        out[8..16].copy_from_slice(&self.memsz.to_le_bytes());
+        out[16..20].copy_from_slice(&self.flags.to_le_bytes());
+        out[24..32].copy_from_slice(&self.stored.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            vaddr: u64::from_le_bytes(bytes.get(0..8)?.try_into().ok()?),
+            memsz: u64::from_le_bytes(bytes.get(8..16)?.try_into().ok()?),
+            flags: u32::from_le_bytes(bytes.get(16..20)?.try_into().ok()?),
+            stored: u64::from_le_bytes(bytes.get(24..32)?.try_into().ok()?),
+        })
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.memsz
+    }
+}
+
+/// How much of each region's contents fits in `budget` bytes. Regions are
+/// kept whole while they fit; otherwise the largest are cut first, down to
+/// a common length, so one big heap doesn't crowd out every small region.
+/// Returns the length to store for each of `sizes`, in the same order.
+pub fn fit_regions(sizes: &[u64], budget: u64) -> Vec<u64> {
+    let total = sizes.iter().fold(0u64, |sum, size| sum.saturating_add(*size));
+    if total <= budget {
+        return sizes.to_vec();
+    }
+    let mut sorted = sizes.to_vec();
+    sorted.sort_unstable();
+    let mut left = budget;
+    let mut level = 0;
+    for (i, size) in sorted.iter().enumerate() {
+        let remaining = (sorted.len() - i) as u64;
+        // Every region from here on is at least `size`; if they can't all
+        // have that much, they share what is left equally.
+        if size.saturating_mul(remaining) > left {
+            level = left / remaining;
+            break;
+        }
+        left -= size;
+    }
+    sizes.iter().map(|size| (*size).min(level)).collect()
+}
+
+/// The full length of a dump with regions that store `stored` bytes each.
+pub fn dump_len(stored: &[u64]) -> u64 {
+    let records = stored.iter().fold(0u64, |sum, stored| sum.saturating_add(REGION_HEADER_LEN as u64).saturating_add(*stored));
+    (CORE_HEADER_LEN + REGISTERS_LEN) as u64 + records
+}
+
+/// Why a dump couldn't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreError {
+    /// Shorter than its header, or than its records say.
+    Short,
+    /// Doesn't start with `CORE_MAGIC`.
+    NotACore,
+    /// Written in a layout this code doesn't know.
+    Version(u32),
+    /// A region stores more than its size.
+    BadRegion(usize),
+}
+
+/// One region of a parsed dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreRegion {
+    pub header: RegionHeader,
+    pub contents: Vec<u8>,
+}
+
+/// A whole dump, as read back by `CoreDump::parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreDump {
+    pub header: CoreHeader,
+    pub registers: RegisterFrame,
+    pub regions: Vec<CoreRegion>,
+}
+
+impl CoreDump {
+    /// Writes the dump out in the layout above.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let stored: Vec<u64> = self.regions.iter().map(|region| region.contents.len() as u64).collect();
+        let mut out = Vec::with_capacity(dump_len(&stored) as usize);
+        let header = CoreHeader { region_count: self.regions.len() as u32, ..self.header };
+        out.extend_from_slice(&header.to_bytes());
+        out.extend_from_slice(&registers_to_bytes(&self.registers));
+        for region in &self.regions {
+            let record = RegionHeader { stored: region.contents.len() as u64, ..region.header };
+            out.extend_from_slice(&record.to_bytes());
+            out.extend_from_slice(&region.contents);
+        }
+        out
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, CoreError> {
+        let header = CoreHeader::from_bytes(bytes)?;
+        let registers = registers_from_bytes(&bytes[CORE_HEADER_LEN..]).ok_or(CoreError::Short)?;
+        let mut at = CORE_HEADER_LEN + REGISTERS_LEN;
+        let mut regions = Vec::new();
+        for i in 0..header.region_count as usize {
+            let record = RegionHeader::from_bytes(bytes.get(at..).ok_or(CoreError::Short)?).ok_or(CoreError::Short)?;
+            if record.stored > record.memsz {
+                return Err(CoreError::BadRegion(i));
+            }
+            at += REGION_HEADER_LEN;
+            let end = at.checked_add(record.stored as usize).ok_or(CoreError::Short)?;
+            let contents = bytes.get(at..end).ok_or(CoreError::Short)?.to_vec();
+            regions.push(CoreRegion { header: record, contents });
+            at = end;
+        }
+        Ok(Self { header, registers, regions })
+    }
+
+    /// The region holding `addr`.
+    pub fn region_at(&self, addr: u64) -> Option<&CoreRegion> {
+        self.regions.iter().find(|region| region.header.contains(addr))
+    }
+
+    /// The stored bytes from `addr` to the end of what was stored of its
+    /// region. `None` if no stored contents cover `addr`.
+    pub fn bytes_at(&self, addr: u64) -> Option<&[u8]> {
+        let region = self.region_at(addr)?;
+        region.contents.get((addr - region.header.vaddr) as usize..).filter(|rest| !rest.is_empty())
+    }
+}
@@ -0,0 +1,81 @@
+// common/src/debug.rs
+
+//! Wrappers for the `SYS_DEBUG_*` syscalls. All of them need `CAP_DEBUG` and
+//! fail with `E_ACC_DENIED` without it.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::abi::{DebugMemRequest, RegisterFrame, DEBUG_MAX_FRAMES, E_ERROR, SUCCESS};
+use crate::abi::{SYS_DEBUG_DUMP, SYS_DEBUG_GET_BACKTRACE, SYS_DEBUG_GET_REGS, SYS_DEBUG_READ_MEM, SYS_DEBUG_RESUME, SYS_DEBUG_SUSPEND, SYS_DEBUG_WRITE_MEM};
+use crate::syscall::syscall3;
+
+/// Result of a memory access: the bytes that made it, and whether the range
+/// ran into memory the target can't access before the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemAccess<T> {
+    pub value: T,
+    pub truncated: bool,
+}
+
+/// Parks `task` so it isn't scheduled until `resume`.
+pub fn suspend(task: u64) -> Result<(), u64> {
+    status(unsafe { syscall3(SYS_DEBUG_SUSPEND, task, 0, 0) })
+}
+
+pub fn resume(task: u64) -> Result<(), u64> {
+    status(unsafe { syscall3(SYS_DEBUG_RESUME, task, 0, 0) })
+}
+
+/// Has the kernel queue a core dump of `task`, which has to be stopped, for
+/// init to write to `/data/crash`. The task goes on running once resumed.
+pub fn dump(task: u64) -> Result<(), u64> {
+    status(unsafe { syscall3(SYS_DEBUG_DUMP, task, 0, 0) })
+}
+
+fn status(res: u64) -> Result<(), u64> {
+    if res == SUCCESS { Ok(()) } else { Err(res) }
+}
+
+/// Registers of a task that isn't running, e.g. one suspended with `suspend`.
+pub fn registers(task: u64) -> Result<RegisterFrame, u64> {
+    let mut regs = RegisterFrame::default();
+    let size = core::mem::size_of::<RegisterFrame>() as u64;
+    let res = unsafe { syscall3(SYS_DEBUG_GET_REGS, task, &mut regs as *mut RegisterFrame as u64, size) };
+    if res == size { Ok(regs) } else { Err(res) }
+}
+
+fn mem_call(number: u64, task: u64, addr: u64, buf: u64, len: usize) -> Result<(usize, bool), u64> {
+    let mut request = DebugMemRequest { addr, buf, len: len as u64, copied: 0 };
+    let res = unsafe { syscall3(number, task, &mut request as *mut DebugMemRequest as u64, core::mem::size_of::<DebugMemRequest>() as u64) };
+    match res {
+        SUCCESS => Ok((request.copied as usize, false)),
+        E_ERROR => Ok((request.copied as usize, true)),
+        code => Err(code),
+    }
+}
+
+/// Reads `len` bytes of `task`'s memory at `addr`.
+pub fn read_memory(task: u64, addr: u64, len: usize) -> Result<MemAccess<Vec<u8>>, u64> {
+    let mut buf = alloc::vec![0u8; len];
+    let (copied, truncated) = mem_call(SYS_DEBUG_READ_MEM, task, addr, buf.as_mut_ptr() as u64, len)?;
+    buf.truncate(copied);
+    Ok(MemAccess { value: buf, truncated })
+}
+
+/// Writes `data` to `task`'s memory at `addr`. Returns how many bytes were written.
+pub fn write_memory(task: u64, addr: u64, data: &[u8]) -> Result<MemAccess<usize>, u64> {
+    let (copied, truncated) = mem_call(SYS_DEBUG_WRITE_MEM, task, addr, data.as_ptr() as u64, data.len())?;
+    Ok(MemAccess { value: copied, truncated })
+}
+
+/// The saved instruction pointer followed by the return addresses found by
+/// walking frame pointers.
+pub fn backtrace(task: u64) -> Result<Vec<u64>, u64> {
+    let mut frames = [0u64; DEBUG_MAX_FRAMES];
+    let res = unsafe { syscall3(SYS_DEBUG_GET_BACKTRACE, task, frames.as_mut_ptr() as u64, core::mem::size_of_val(&frames) as u64) };
+    if res as usize <= DEBUG_MAX_FRAMES { Ok(frames[..res as usize].to_vec()) } else { Err(res) }
+}
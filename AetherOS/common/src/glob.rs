@@ -0,0 +1,216 @@
+// common/src/glob.rs
+
+//! Shell-style wildcards for file names, shared by the shell's argument
+//! expansion and the file manager's content search.
+//!
+//! `*` stands for any run of characters, `?` for any one character and
+//! `[...]` for one character from a class: single characters and ranges such
+//! as `a-z`, negated with a leading `!` or `^`. A `]` right after the opening
+//! bracket (or the negation) is part of the class. A `[` without a closing
+//! `]` is an ordinary character. A backslash makes the character after it
+//! ordinary, so `\*` matches a `*`; a trailing backslash stands for itself.
+//! Patterns match a single name; `/` is not special here, and `**` is the
+//! same as `*`.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+enum Token {
+    Literal(char),
+    AnyOne,
+    AnyRun,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::AnyOne => true,
+            Token::AnyRun => false, // Handled by the matcher
+            Token::Class { negated, ranges } => ranges.iter().any(|(low, high)| *low <= c && c <= *high) != *negated,
+        }
+    }
+}
+
+/// Whether `text` contains a wildcard that `glob_match` would treat as one.
+pub fn has_wildcards(text: &str) -> bool {
+    parse(text).iter().any(|token| !matches!(token, Token::Literal(_)))
+}
+
+/// Whether `name` matches `pattern`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = parse(pattern);
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star = None; // Pattern index after the last `*`, and where in `name` it took over
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(Token::AnyRun) => {
+                p += 1;
+                star = Some((p, n));
+            },
+            Some(token) if token.matches(name[n]) => {
+                p += 1;
+                n += 1;
+            },
+            // Let the last `*` swallow one more character and try again.
+            _ => match star {
+                Some((after_star, taken)) => {
+                    p = after_star;
+                    n = taken + 1;
+                    star = Some((after_star, n));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|token| matches!(token, Token::AnyRun))
+}
+
+fn parse(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => tokens.push(Token::AnyRun),
+            '?' => tokens.push(Token::AnyOne),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                tokens.push(Token::Literal(chars[i]));
+            },
+            '[' => match parse_class(&chars[i + 1..]) {
+                Some((class, used)) => {
+                    tokens.push(class);
+                    i += used;
+                },
+                None => tokens.push(Token::Literal('[')),
+            },
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses a class from just after its `[`. Returns it and how many
+/// characters it took, including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut ranges = Vec::new();
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && i > first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).map_or(false, |high| *high != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "notes.txt"));
+        assert!(glob_match("*.txt", "notes.txt"));
+        assert!(glob_match("*.txt", ".txt"));
+        assert!(!glob_match("*.txt", "notes.txt.bak"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(!glob_match("a*b*c", "aXXbYYb"));
+        assert!(glob_match("*a*a*a", "aaaa"));
+        // `/` is an ordinary character to the matcher.
+        assert!(glob_match("*", "a/b"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("?", "a"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("?", "ab"));
+        assert!(glob_match("file?.log", "file1.log"));
+        assert!(!glob_match("file?.log", "file.log"));
+        assert!(glob_match("??*", "ab"));
+        assert!(!glob_match("??*", "a"));
+        // One character, not one byte.
+        assert!(glob_match("?.md", "é.md"));
+    }
+
+    #[test]
+    fn classes_match_one_character_from_the_set() {
+        assert!(glob_match("[abc]", "b"));
+        assert!(!glob_match("[abc]", "d"));
+        assert!(glob_match("log[0-9].txt", "log7.txt"));
+        assert!(!glob_match("log[0-9].txt", "logx.txt"));
+        assert!(glob_match("[a-cx-z]", "y"));
+        assert!(!glob_match("[a-cx-z]", "m"));
+        assert!(glob_match("[!abc]", "d"));
+        assert!(!glob_match("[!abc]", "a"));
+        assert!(glob_match("[^0-9]", "x"));
+        assert!(!glob_match("[^0-9]", "5"));
+        // A `]` first in the class, or first after the negation, belongs to it.
+        assert!(glob_match("[]a]", "]"));
+        assert!(glob_match("[!]]", "a"));
+        assert!(!glob_match("[!]]", "]"));
+        // A `-` at the end is a character, not a range.
+        assert!(glob_match("[a-]", "-"));
+        // A `[` that is never closed is an ordinary character.
+        assert!(glob_match("[abc", "[abc"));
+        assert!(!glob_match("[abc", "a"));
+        // A class never matches the empty string.
+        assert!(!glob_match("x[ab]", "x"));
+    }
+
+    #[test]
+    fn double_star_is_the_same_as_star() {
+        assert!(glob_match("**", "anything"));
+        assert!(glob_match("**.rs", "main.rs"));
+        assert!(glob_match("a**z", "az"));
+        assert!(glob_match("a**z", "a/b/z"));
+        assert!(!glob_match("a**z", "a/b/y"));
+        for name in ["", "x", "src.rs", "a.b.c"] {
+            assert_eq!(glob_match("**.rs", name), glob_match("*.rs", name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn backslash_makes_the_next_character_ordinary() {
+        assert!(glob_match("\\*.txt", "*.txt"));
+        assert!(!glob_match("\\*.txt", "notes.txt"));
+        assert!(glob_match("what\\?", "what?"));
+        assert!(!glob_match("what\\?", "whats"));
+        assert!(glob_match("\\[abc]", "[abc]"));
+        assert!(!glob_match("\\[abc]", "a"));
+        assert!(glob_match("a\\\\b", "a\\b"));
+        assert!(glob_match("end\\", "end\\"));
+        assert!(!has_wildcards("\\*"));
+        assert!(has_wildcards("\\**"));
+    }
+
+    #[test]
+    fn has_wildcards_agrees_with_the_parser() {
+        assert!(!has_wildcards(""));
+        assert!(!has_wildcards("plain-name.txt"));
+        assert!(has_wildcards("*.txt"));
+        assert!(has_wildcards("file?"));
+        assert!(has_wildcards("[ab]"));
+        assert!(!has_wildcards("[ab"));
+        assert!(!has_wildcards("]"));
+    }
+}
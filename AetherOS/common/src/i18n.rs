@@ -0,0 +1,681 @@
+// common/src/i18n.rs
+
+//! Message catalogs for user-facing text.
+//!
+//! A string shown to users is written as `tr!(key, default, args...)`. The
+//! default is the English template, kept at the call site so the code reads
+//! as before and works without any catalog. With a locale set, the template
+//! comes from that language's catalog instead:
+//!
+//! ```ignore
+//! let text = tr!("shell.command_not_found", "Command '{0}' not found.", command);
+//! ```
+//!
+//! Templates number their arguments: `{0}` is the first, and an argument may
+//! be used any number of times or not at all. `{{` and `}}` stand for braces.
+//!
+//! Text that depends on a count is written as `tr_plural!(key, count, one,
+//! other, args...)`, with the English singular and plural forms. A catalog
+//! gives one template per plural category of its language, under
+//! `key.one`, `key.few`, `key.many` and `key.other` (see `plural_category`);
+//! a category it leaves out uses `key.other`.
+//!
+//! Catalogs are written as text, one `key = template` per line, with `#`
+//! comments and `\n` for a line break inside a template. `axpkg initrd
+//! --locale` compiles `<lang>.msg` into `/locale/<lang>/messages.bin`, all
+//! integers little-endian:
+//!
+//! ```text
+//! magic    8 bytes  "AXMSGCAT"
+//! version  u32      1
+//! count    u32
+//! entries  count x { key_len u32, key, template_len u32, template }
+//! ```
+//!
+//! Keys are unique and sorted, so compiling the same catalog twice gives the
+//! same file.
+//!
+//! A missing catalog, a key the catalog doesn't have and a catalog template
+//! that uses an argument the call doesn't pass all fall back to the English
+//! default. None of them is an error for the caller; each is logged once per
+//! key.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Display, Write};
+
+use crate::ipc::event_ipc::Event;
+use crate::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
+use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use crate::ipc::vnode::VNodeChannel;
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS};
+
+pub const MAGIC: &[u8; 8] = b"AXMSGCAT";
+pub const VERSION: u32 = 1;
+/// Where `axpkg initrd` puts the compiled catalogs, one directory per language.
+pub const LOCALE_DIR: &str = "/locale";
+pub const CATALOG_FILE: &str = "messages.bin";
+/// The setting that holds the active language, e.g. "de". "en" uses the defaults.
+pub const LOCALE_KEY: &str = "locale.language";
+/// The language of the defaults at the call sites.
+pub const DEFAULT_LOCALE: &str = "en";
+/// Largest catalog `set_locale` reads.
+pub const MAX_CATALOG_BYTES: u32 = 256 * 1024;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{` or `}` that is neither a placeholder nor doubled.
+    Malformed,
+    /// `{n}` with fewer than `n + 1` arguments.
+    MissingArgument(usize),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("unmatched brace"),
+            Self::MissingArgument(index) => write!(f, "uses {{{}}} but has no such argument", index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CatalogError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    NotUtf8,
+    /// A source line that isn't `key = template`, a comment or blank.
+    MissingEquals { line: usize },
+    InvalidKey { line: usize, key: String },
+    DuplicateKey(String),
+    InvalidTemplate { key: String, error: TemplateError },
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not a message catalog"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported catalog version {}", version),
+            Self::Truncated => f.write_str("catalog is truncated"),
+            Self::NotUtf8 => f.write_str("catalog is not valid UTF-8"),
+            Self::MissingEquals { line } => write!(f, "line {}: expected 'key = template'", line),
+            Self::InvalidKey { line, key } => write!(f, "line {}: invalid key '{}'", line, key),
+            Self::DuplicateKey(key) => write!(f, "duplicate key '{}'", key),
+            Self::InvalidTemplate { key, error } => write!(f, "template of '{}': {}", key, error),
+        }
+    }
+}
+
+/// Keys are dotted lowercase names such as "shell.usage.cp".
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_'))
+}
+
+/// Walks `template`, copying text and calling `arg` for each placeholder's
+/// index. `arg` appends the argument and returns false if there is none, in
+/// which case the placeholder is kept as written.
+fn fill(template: &str, mut arg: impl FnMut(usize, &mut String) -> bool) -> (String, Option<TemplateError>) {
+    let mut out = String::with_capacity(template.len());
+    let mut error = None;
+    let mut rest = template;
+    while let Some(pos) = rest.find(|c| c == '{' || c == '}') {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let digits = tail.strip_prefix('{')
+            .and_then(|inner| inner.find('}').map(|end| &inner[..end]))
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()));
+        let Some(digits) = digits else {
+            error.get_or_insert(TemplateError::Malformed);
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+            continue;
+        };
+        let len = digits.len() + 2;
+        let index = digits.parse::<usize>().unwrap_or(usize::MAX);
+        if !arg(index, &mut out) {
+            error.get_or_insert(TemplateError::MissingArgument(index));
+            out.push_str(&tail[..len]);
+        }
+        rest = &tail[len..];
+    }
+    out.push_str(rest);
+    (out, error)
+}
+
+/// Fills in `template`. Placeholders that can't be filled are left as
+/// written and the first problem is returned along with the text.
+pub fn render(template: &str, args: &[&dyn Display]) -> (String, Option<TemplateError>) {
+    fill(template, |index, out| match args.get(index) {
+        Some(arg) => {
+            let _ = write!(out, "{}", arg);
+            true
+        },
+        None => false,
+    })
+}
+
+/// Checks a template's syntax. Returns how many arguments it needs: one
+/// more than the highest placeholder, 0 if it has none.
+pub fn arity(template: &str) -> Result<usize, TemplateError> {
+    let mut needed = 0;
+    match fill(template, |index, _| {
+        needed = needed.max(index.saturating_add(1));
+        true
+    }).1 {
+        Some(error) => Err(error),
+        None => Ok(needed),
+    }
+}
+
+/// The templates of one language, by key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    messages: BTreeMap<String, String>,
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CatalogError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(CatalogError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, CatalogError> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn str(&mut self) -> Result<&'a str, CatalogError> {
+        let len = self.u32()? as usize;
+        core::str::from_utf8(self.take(len)?).map_err(|_| CatalogError::NotUtf8)
+    }
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the text form. Every template is checked, so a catalog that
+    /// parses never has a malformed one.
+    pub fn parse(source: &str) -> Result<Self, CatalogError> {
+        let mut catalog = Self::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, template) = line.split_once('=').ok_or(CatalogError::MissingEquals { line: i + 1 })?;
+            let key = key.trim();
+            if !is_valid_key(key) {
+                return Err(CatalogError::InvalidKey { line: i + 1, key: key.to_string() });
+            }
+            catalog.insert(key, &unescape(template.trim()))?;
+        }
+        Ok(catalog)
+    }
+
+    /// Adds a template. Fails if the key is taken or the template is malformed.
+    pub fn insert(&mut self, key: &str, template: &str) -> Result<(), CatalogError> {
+        if self.messages.contains_key(key) {
+            return Err(CatalogError::DuplicateKey(key.to_string()));
+        }
+        arity(template).map_err(|error| CatalogError::InvalidTemplate { key: key.to_string(), error })?;
+        self.messages.insert(key.to_string(), template.to_string());
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.messages.keys().map(String::as_str)
+    }
+
+    /// The compiled form `set_locale` loads.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.messages.len() as u32).to_le_bytes());
+        for (key, template) in &self.messages {
+            for text in [key, template] {
+                bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(text.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CatalogError> {
+        let mut cursor = Cursor { bytes, pos: 0 };
+        if cursor.take(MAGIC.len()).map_err(|_| CatalogError::BadMagic)? != MAGIC {
+            return Err(CatalogError::BadMagic);
+        }
+        let version = cursor.u32()?;
+        if version != VERSION {
+            return Err(CatalogError::UnsupportedVersion(version));
+        }
+        let count = cursor.u32()?;
+        let mut catalog = Self::new();
+        for line in 1..=count as usize {
+            let key = cursor.str()?;
+            if !is_valid_key(key) {
+                return Err(CatalogError::InvalidKey { line, key: key.to_string() });
+            }
+            let template = cursor.str()?;
+            catalog.insert(key, template)?;
+        }
+        Ok(catalog)
+    }
+}
+
+/// Undoes the escapes of the text form: `\n` and `\\`. Any other backslash is kept.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            },
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// The active language and what has been logged about it.
+struct State {
+    lang: Option<String>, // None while the defaults are used
+    catalog: Catalog,
+    warned: BTreeSet<&'static str>, // Keys a fallback was logged for
+}
+
+struct Active(UnsafeCell<State>);
+
+// SAFETY: a V-Node is one task with one thread, and nothing calls into this
+// module from an interrupt handler, so the state is never accessed concurrently.
+unsafe impl Sync for Active {}
+
+static ACTIVE: Active = Active(UnsafeCell::new(State { lang: None, catalog: Catalog { messages: BTreeMap::new() }, warned: BTreeSet::new() }));
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    // SAFETY: see `Active`; `f` can't reach `ACTIVE` again, so the borrow is unique.
+    f(unsafe { &mut *ACTIVE.0.get() })
+}
+
+/// Makes `catalog` the active one, for `lang`.
+pub fn install(lang: &str, catalog: Catalog) {
+    with_state(|state| {
+        state.lang = Some(lang.to_string());
+        state.catalog = catalog;
+        state.warned.clear();
+    });
+}
+
+/// Goes back to the English defaults.
+pub fn reset() {
+    with_state(|state| {
+        state.lang = None;
+        state.catalog = Catalog::new();
+        state.warned.clear();
+    });
+}
+
+/// The active language, "en" while the defaults are used.
+pub fn locale() -> String {
+    with_state(|state| state.lang.clone().unwrap_or_else(|| DEFAULT_LOCALE.to_string()))
+}
+
+/// The languages to try for `lang`, most specific first: "de-AT" is also
+/// served by "de".
+pub fn fallback_chain(lang: &str) -> Vec<&str> {
+    let mut chain = Vec::new();
+    if !lang.is_empty() {
+        chain.push(lang);
+    }
+    if let Some((base, _)) = lang.split_once(|c| c == '-' || c == '_') {
+        if !base.is_empty() {
+            chain.push(base);
+        }
+    }
+    chain
+}
+
+fn read_catalog(vfs_chan: &mut VNodeChannel, lang: &str) -> Result<Catalog, String> {
+    if lang.is_empty() || lang.contains('/') || lang == "." || lang == ".." {
+        return Err(format!("invalid language '{}'", lang));
+    }
+    let path = format!("{}/{}/{}", LOCALE_DIR, lang, CATALOG_FILE);
+    let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.clone(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as u32,
+        Ok(VfsResponse::Error { message, .. }) => return Err(format!("{}: {}", path, message)),
+        _ => return Err(format!("{}: unexpected response from VFS", path)),
+    };
+    let data = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_CATALOG_BYTES, offset: 0 });
+    let _ = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+    match data {
+        Ok(VfsResponse::Data(bytes)) => Catalog::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e)),
+        Ok(VfsResponse::Error { message, .. }) => Err(format!("{}: {}", path, message)),
+        _ => Err(format!("{}: unexpected response from VFS", path)),
+    }
+}
+
+/// Loads the catalog of `lang` through the VFS and makes it active, trying
+/// the languages of `fallback_chain` in turn. "en" goes back to the
+/// defaults. If no catalog loads, the defaults are used and the error says why.
+pub fn set_locale(vfs_chan: &mut VNodeChannel, lang: &str) -> Result<(), String> {
+    if lang == DEFAULT_LOCALE || lang.is_empty() {
+        reset();
+        return Ok(());
+    }
+    let mut errors = Vec::new();
+    for candidate in fallback_chain(lang) {
+        match read_catalog(vfs_chan, candidate) {
+            Ok(catalog) => {
+                log(&format!("i18n: Using the '{}' catalog ({} messages) for '{}'.", candidate, catalog.len(), lang));
+                install(candidate, catalog);
+                return Ok(());
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+    reset();
+    Err(format!("no catalog for '{}' ({}); using English", lang, errors.join("; ")))
+}
+
+/// Reads `LOCALE_KEY` from the settings service and loads its catalog.
+/// Logs and keeps English if either fails.
+pub fn load_from_settings(settings_chan: &mut VNodeChannel, vfs_chan: &mut VNodeChannel) {
+    let lang = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: LOCALE_KEY.to_string() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Str(lang), .. }) => lang,
+        _ => return,
+    };
+    if let Err(e) = set_locale(vfs_chan, &lang) {
+        log(&format!("i18n: {}.", e));
+    }
+}
+
+/// The event bus topic prefix to subscribe to for `locale_changed`.
+pub fn locale_topic() -> String {
+    format!("settings.{}", LOCALE_KEY)
+}
+
+/// The new language, if `event` says the locale setting changed.
+pub fn locale_changed(event: &Event) -> Option<String> {
+    if event.topic != locale_topic() {
+        return None;
+    }
+    match postcard::from_bytes::<SettingChanged>(&event.payload) {
+        Ok(SettingChanged { value: SettingValue::Str(lang), .. }) => Some(lang),
+        _ => None,
+    }
+}
+
+/// The plural forms a language distinguishes. Which one a count takes is
+/// decided by `plural_category`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluralCategory {
+    One,
+    Few,
+    Many,
+    Other,
+}
+
+impl PluralCategory {
+    /// The suffix of the catalog key holding this form, e.g. "one" in `init.stop.done.one`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::One => "one",
+            Self::Few => "few",
+            Self::Many => "many",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// The plural category of `count` in `lang`, by the rules of its base
+/// language. Languages without a rule here count like English: `One` for 1,
+/// `Other` for everything else.
+pub fn plural_category(lang: &str, count: u64) -> PluralCategory {
+    let base = fallback_chain(lang).last().copied().unwrap_or(DEFAULT_LOCALE);
+    let (last, last_two) = (count % 10, count % 100);
+    match base {
+        "ja" | "ko" | "zh" => PluralCategory::Other,
+        "fr" if count <= 1 => PluralCategory::One,
+        "fr" => PluralCategory::Other,
+        "ru" | "uk" if last == 1 && last_two != 11 => PluralCategory::One,
+        "pl" if count == 1 => PluralCategory::One,
+        "ru" | "uk" | "pl" if (2..=4).contains(&last) && !(12..=14).contains(&last_two) => PluralCategory::Few,
+        "ru" | "uk" | "pl" => PluralCategory::Many,
+        _ if count == 1 => PluralCategory::One,
+        _ => PluralCategory::Other,
+    }
+}
+
+/// Renders `template` (the catalog's, if it has one) or else `default`, and
+/// logs the first problem with `key` for the active language.
+fn resolve(state: &mut State, key: &'static str, template: Option<String>, default: &'static str, args: &[&dyn Display]) -> String {
+    let problem = match (state.lang.as_deref(), template) {
+        (Some(lang), Some(template)) => match render(&template, args) {
+            (text, None) => return text,
+            (_, Some(error)) => Some(format!("its '{}' template {}", lang, error)),
+        },
+        (Some(lang), None) => Some(format!("it is missing from the '{}' catalog", lang)),
+        (None, _) => None,
+    };
+    let (text, error) = render(default, args);
+    let problem = problem.map(|problem| format!("'{}' is shown in English: {}", key, problem))
+        .or_else(|| error.map(|error| format!("the default template of '{}' {}", key, error)));
+    if let Some(problem) = problem {
+        if state.warned.insert(key) {
+            log(&format!("i18n: {}.", problem));
+        }
+    }
+    text
+}
+
+/// What `tr!` expands to: the template of `key` in the active catalog, or
+/// `default`, with `args` filled in.
+pub fn translate(key: &'static str, default: &'static str, args: &[&dyn Display]) -> String {
+    with_state(|state| {
+        let template = state.catalog.get(key).map(String::from);
+        resolve(state, key, template, default, args)
+    })
+}
+
+/// What `tr_plural!` expands to: the active catalog's form of `key` for
+/// `count`, or its `other` form, or else `one` or `other` by the English rule.
+pub fn translate_plural(key: &'static str, count: u64, one: &'static str, other: &'static str, args: &[&dyn Display]) -> String {
+    with_state(|state| {
+        let template = state.lang.as_deref().and_then(|lang| {
+            let form = plural_category(lang, count).name();
+            state.catalog.get(&format!("{}.{}", key, form)).or_else(|| state.catalog.get(&format!("{}.other", key))).map(String::from)
+        });
+        resolve(state, key, template, if count == 1 { one } else { other }, args)
+    })
+}
+
+/// Translates a user-facing string: `tr!(key, default, args...)`. See the
+/// module documentation.
+#[macro_export]
+macro_rules! tr {
+    ($key:literal, $default:literal $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::translate($key, $default, &[$(&$arg as &dyn ::core::fmt::Display),*])
+    };
+}
+
+/// Translates a user-facing string that depends on a count:
+/// `tr_plural!(key, count, one, other, args...)`. See the module documentation.
+#[macro_export]
+macro_rules! tr_plural {
+    ($key:literal, $count:expr, $one:literal, $other:literal $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::translate_plural($key, $count as u64, $one, $other, &[$(&$arg as &dyn ::core::fmt::Display),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const GERMAN: &str = "\
+# Test catalog
+shell.command_not_found = Befehl '{0}' nicht gefunden.
+shell.swap = {1} vor {0}
+shell.too_many = {0} und {2}
+init.stop.done.one = {0} Instanz beendet.
+init.stop.done.other = {0} Instanzen beendet.
+shell.files.other = {0} Dateien
+";
+
+    #[test]
+    fn plural_rules_follow_the_language() {
+        use PluralCategory::*;
+        for lang in ["en", "de", "de-AT", "sv", ""] {
+            assert_eq!(plural_category(lang, 0), Other, "{}", lang);
+            assert_eq!(plural_category(lang, 1), One, "{}", lang);
+            assert_eq!(plural_category(lang, 2), Other, "{}", lang);
+            assert_eq!(plural_category(lang, 21), Other, "{}", lang);
+        }
+        assert_eq!(plural_category("fr", 0), One);
+        assert_eq!(plural_category("fr_CA", 1), One);
+        assert_eq!(plural_category("fr", 2), Other);
+        assert_eq!(plural_category("ja", 1), Other);
+
+        let russian: Vec<_> = [1, 2, 4, 5, 11, 12, 14, 21, 22, 25, 101, 111, 112].iter().map(|n| plural_category("ru", *n)).collect();
+        assert_eq!(russian, vec![One, Few, Few, Many, Many, Many, Many, One, Few, Many, One, Many, Many]);
+        let polish: Vec<_> = [1, 2, 5, 12, 21, 22, 25, 102].iter().map(|n| plural_category("pl", *n)).collect();
+        assert_eq!(polish, vec![One, Few, Many, Many, Many, Few, Many, Few]);
+        assert_eq!(PluralCategory::Few.name(), "few");
+    }
+
+    #[test]
+    fn fallback_chain_tries_the_base_language() {
+        assert_eq!(fallback_chain("de-AT"), vec!["de-AT", "de"]);
+        assert_eq!(fallback_chain("pt_BR"), vec!["pt_BR", "pt"]);
+        assert_eq!(fallback_chain("de"), vec!["de"]);
+        assert_eq!(fallback_chain("-x"), vec!["-x"]);
+        assert!(fallback_chain("").is_empty());
+    }
+
+    #[test]
+    fn catalogs_parse_and_round_trip() {
+        let catalog = Catalog::parse("# comment\n\n  a.b  =  x {0}\\ny\nc = \\\\n\n").unwrap();
+        assert_eq!(catalog.get("a.b"), Some("x {0}\ny"));
+        assert_eq!(catalog.get("c"), Some("\\n"));
+        assert_eq!(Catalog::from_bytes(&catalog.to_bytes()), Ok(catalog.clone()));
+        assert_eq!(Catalog::parse(GERMAN).unwrap().to_bytes(), Catalog::parse(GERMAN).unwrap().to_bytes());
+
+        assert_eq!(Catalog::parse("ok = 1\nnothing here"), Err(CatalogError::MissingEquals { line: 2 }));
+        assert_eq!(Catalog::parse("Bad.Key = x"), Err(CatalogError::InvalidKey { line: 1, key: "Bad.Key".into() }));
+        assert_eq!(Catalog::parse("a = 1\na = 2"), Err(CatalogError::DuplicateKey("a".into())));
+        assert_eq!(
+            Catalog::parse("a = {0"),
+            Err(CatalogError::InvalidTemplate { key: "a".into(), error: TemplateError::Malformed })
+        );
+        assert_eq!(Catalog::from_bytes(b"AXMSGCA"), Err(CatalogError::BadMagic));
+        let bytes = catalog.to_bytes();
+        assert_eq!(Catalog::from_bytes(&bytes[..bytes.len() - 1]), Err(CatalogError::Truncated));
+    }
+
+    #[test]
+    fn placeholders_are_filled_by_index() {
+        assert_eq!(render("{1} before {0}", &[&"a", &"b"]), ("b before a".into(), None));
+        assert_eq!(render("{{0}} is {0}", &[&7]), ("{0} is 7".into(), None));
+        assert_eq!(render("{0} and {2}", &[&1, &2]), ("1 and {2}".into(), Some(TemplateError::MissingArgument(2))));
+        assert_eq!(arity("{0} {3} {{9}}"), Ok(4));
+        assert_eq!(arity("no args"), Ok(0));
+        assert_eq!(arity("oops }"), Err(TemplateError::Malformed));
+    }
+
+    // The active catalog is global, so every step that installs one is in
+    // this one test rather than racing other tests.
+    #[test]
+    fn lookups_fall_back_to_the_english_defaults() {
+        reset();
+        assert_eq!(locale(), "en");
+        assert_eq!(tr!("shell.command_not_found", "Command '{0}' not found.", "x"), "Command 'x' not found.");
+        assert_eq!(tr_plural!("init.stop.done", 1, "Stopped {0} instance.", "Stopped {0} instances.", 1), "Stopped 1 instance.");
+        assert_eq!(tr_plural!("init.stop.done", 0, "Stopped {0} instance.", "Stopped {0} instances.", 0), "Stopped 0 instances.");
+
+        install("de", Catalog::parse(GERMAN).unwrap());
+        assert_eq!(locale(), "de");
+        assert_eq!(tr!("shell.command_not_found", "Command '{0}' not found.", "x"), "Befehl 'x' nicht gefunden.");
+        assert_eq!(tr!("shell.swap", "{0} then {1}", "a", "b"), "b vor a");
+        // A missing key, and a template using an argument the call lacks, are
+        // shown in English and logged once per key.
+        assert_eq!(tr!("shell.missing", "Missing {0}", 1), "Missing 1");
+        assert_eq!(tr!("shell.missing", "Missing {0}", 2), "Missing 2");
+        assert_eq!(tr!("shell.too_many", "{0} and {1}", 1, 2), "1 and 2");
+        with_state(|state| assert_eq!(state.warned.iter().copied().collect::<Vec<_>>(), vec!["shell.missing", "shell.too_many"]));
+
+        assert_eq!(tr_plural!("init.stop.done", 1, "Stopped {0} instance.", "Stopped {0} instances.", 1), "1 Instanz beendet.");
+        assert_eq!(tr_plural!("init.stop.done", 3, "Stopped {0} instance.", "Stopped {0} instances.", 3), "3 Instanzen beendet.");
+        // Without a `.one` form the catalog's `.other` is used for every count.
+        assert_eq!(tr_plural!("shell.files", 1, "{0} file", "{0} files", 1), "1 Dateien");
+        // Without any form, the English one for the count.
+        assert_eq!(tr_plural!("shell.dirs", 1, "{0} directory", "{0} directories", 1), "1 directory");
+        assert_eq!(tr_plural!("shell.dirs", 2, "{0} directory", "{0} directories", 2), "2 directories");
+
+        // The plural rule is the catalog's language.
+        install("ru", Catalog::parse("n.few = {0} few\nn.many = {0} many\nn.one = {0} one").unwrap());
+        let forms: Vec<_> = [1, 3, 5, 21].iter().map(|n| tr_plural!("n", *n, "{0} x", "{0} xs", n)).collect();
+        assert_eq!(forms, vec!["1 one", "3 few", "5 many", "21 one"]);
+        with_state(|state| assert!(state.warned.is_empty()));
+
+        reset();
+        assert_eq!(locale(), "en");
+        assert_eq!(tr!("shell.command_not_found", "Command '{0}' not found.", "x"), "Command 'x' not found.");
+    }
+}
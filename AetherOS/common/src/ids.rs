@@ -0,0 +1,239 @@
+// common/src/ids.rs
+
+//! Typed IDs and time units.
+//!
+//! Channel IDs, task IDs, socket handles, DMA handles and window IDs are all
+//! small integers, and ticks and milliseconds are both `u64`, so passing one
+//! where another was expected used to compile. Each is a newtype here,
+//! `#[repr(transparent)]` so ABI structs keep their layout and
+//! `#[serde(transparent)]` so IPC messages encode exactly as before (the
+//! fixtures in `ipc::compat` hold them to that).
+//!
+//! The integer goes in with `from_raw` and comes out with `raw`. That is for
+//! the raw-syscall boundary, where every argument is a `u64`, and for array
+//! indexes and log lines; everywhere else an ID stays typed. No ID converts
+//! into another. Ticks and milliseconds convert only through
+//! `TICKS_PER_SECOND`, with the rounding spelled out.
+//!
+//! ```
+//! use aetheros_common::ids::{ChannelId, Millis, TaskId, Ticks};
+//! fn wake(_task: TaskId) {}
+//! wake(TaskId::from_raw(ChannelId::from_raw(3).raw() as u64));
+//! let deadline = Ticks::from_raw(5) + Millis::from_raw(50).to_ticks_ceil();
+//! assert_eq!(deadline, Ticks::from_raw(10));
+//! ```
+//!
+//! Without the conversions, neither compiles:
+//!
+//! ```compile_fail,E0308
+//! use aetheros_common::ids::{ChannelId, TaskId};
+//! fn wake(_task: TaskId) {}
+//! wake(ChannelId::from_raw(3));
+//! ```
+//!
+//! ```compile_fail,E0308
+//! use aetheros_common::ids::{Millis, Ticks};
+//! let deadline = Ticks::from_raw(5) + Millis::from_raw(50);
+//! ```
+
+#![allow(dead_code)]
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+
+use serde::{Deserialize, Serialize};
+
+/// Nominal timer interrupt frequency: one tick is 10 ms.
+pub const TICKS_PER_SECOND: u64 = 100;
+pub const MILLIS_PER_SECOND: u64 = 1000;
+
+macro_rules! id_type {
+    ($(#[$doc:meta])* $name:ident($raw:ty)) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+        #[repr(transparent)]
+        #[serde(transparent)]
+        pub struct $name($raw);
+
+        impl $name {
+            pub const fn from_raw(raw: $raw) -> Self {
+                Self(raw)
+            }
+
+            pub const fn raw(self) -> $raw {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+id_type! {
+    /// A kernel mailbox (IPC channel).
+    ChannelId(u32)
+}
+
+id_type! {
+    /// A task. Task 0 is the kernel itself, which sends IRQ notifications
+    /// and startup info.
+    TaskId(u64)
+}
+
+id_type! {
+    /// A socket of the socket API, per client task.
+    SocketHandle(u32)
+}
+
+id_type! {
+    /// A kernel DMA buffer, from `SYS_NET_ALLOC_BUF`.
+    DmaHandle(u64)
+}
+
+id_type! {
+    /// A compositor window.
+    WindowId(u32)
+}
+
+impl ChannelId {
+    /// A channel ID passed as a syscall argument. `None` if it doesn't fit,
+    /// rather than truncating it to some other channel.
+    pub fn from_arg(arg: u64) -> Option<Self> {
+        u32::try_from(arg).ok().map(Self)
+    }
+}
+
+impl TaskId {
+    pub const KERNEL: TaskId = TaskId(0);
+}
+
+/// Timer ticks: a point since boot, or a span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[repr(transparent)]
+#[serde(transparent)]
+pub struct Ticks(u64);
+
+/// Milliseconds: a point since boot, or a span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[repr(transparent)]
+#[serde(transparent)]
+pub struct Millis(u64);
+
+impl Ticks {
+    pub const ZERO: Ticks = Ticks(0);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub const fn raw(self) -> u64 {
+        self.0
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs.saturating_mul(TICKS_PER_SECOND))
+    }
+
+    /// The time these ticks take. Exact: a tick is a whole number of milliseconds.
+    pub const fn to_millis(self) -> Millis {
+        Millis(self.0.saturating_mul(MILLIS_PER_SECOND / TICKS_PER_SECOND))
+    }
+
+    /// Whole seconds, rounded down.
+    pub const fn to_secs(self) -> u64 {
+        self.0 / TICKS_PER_SECOND
+    }
+
+    pub const fn saturating_sub(self, earlier: Ticks) -> Ticks {
+        Ticks(self.0.saturating_sub(earlier.0))
+    }
+
+    pub const fn saturating_add(self, span: Ticks) -> Ticks {
+        Ticks(self.0.saturating_add(span.0))
+    }
+}
+
+impl Millis {
+    pub const ZERO: Millis = Millis(0);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub const fn raw(self) -> u64 {
+        self.0
+    }
+
+    /// The ticks that cover this long, rounded up, so a timeout never ends early.
+    pub const fn to_ticks_ceil(self) -> Ticks {
+        let per_tick = MILLIS_PER_SECOND / TICKS_PER_SECOND;
+        Ticks(self.0 / per_tick + (self.0 % per_tick != 0) as u64)
+    }
+
+    /// The tick this point in time falls in, rounded down.
+    pub const fn to_ticks_floor(self) -> Ticks {
+        Ticks(self.0 / (MILLIS_PER_SECOND / TICKS_PER_SECOND))
+    }
+
+    pub const fn saturating_sub(self, earlier: Millis) -> Millis {
+        Millis(self.0.saturating_sub(earlier.0))
+    }
+}
+
+macro_rules! unit_arithmetic {
+    ($name:ident) => {
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, rhs: $name) -> $name {
+                $name(self.0 + rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: $name) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, rhs: $name) -> $name {
+                $name(self.0 - rhs.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+unit_arithmetic!(Ticks);
+unit_arithmetic!(Millis);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_arguments_never_wrap() {
+        assert_eq!(ChannelId::from_arg(u32::MAX as u64), Some(ChannelId::from_raw(u32::MAX)));
+        assert_eq!(ChannelId::from_arg(1 << 32), None);
+    }
+
+    #[test]
+    fn millis_round_to_ticks_as_asked() {
+        assert_eq!(Millis::from_raw(1).to_ticks_ceil(), Ticks::from_raw(1));
+        assert_eq!(Millis::from_raw(10).to_ticks_ceil(), Ticks::from_raw(1));
+        assert_eq!(Millis::from_raw(11).to_ticks_ceil(), Ticks::from_raw(2));
+        assert_eq!(Millis::from_raw(19).to_ticks_floor(), Ticks::from_raw(1));
+        assert_eq!(Ticks::from_raw(3).to_millis(), Millis::from_raw(30));
+        assert_eq!(Ticks::from_secs(2), Ticks::from_raw(200));
+    }
+}
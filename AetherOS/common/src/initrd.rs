@@ -0,0 +1,171 @@
+// common/src/initrd.rs
+
+//! The initrd image: the files the kernel boots with, V-Node binaries under
+//! `/initrd/` and configuration under `/etc/`.
+//!
+//! The image is handed to the kernel as the bootloader's ramdisk and parsed in
+//! place by `aetherfs::init`. Host tools build it with `build`. Layout, all
+//! integers little-endian:
+//!
+//! ```text
+//! magic    8 bytes  "AXINITRD"
+//! version  u32      1
+//! count    u32
+//! entries  count x { path_len u32, path, offset u64, len u64 }
+//! data     file contents; offset is from the start of the image
+//! ```
+//!
+//! Paths are absolute, unique, and sorted, so building the same files twice
+//! gives the same image.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const MAGIC: &[u8; 8] = b"AXINITRD";
+pub const VERSION: u32 = 1;
+/// Where the V-Node loader looks for `<name>.bin`.
+pub const VNODE_DIR: &str = "/initrd";
+pub const ETC_DIR: &str = "/etc";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitrdError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    InvalidPath(String),
+    DuplicatePath(String),
+    /// An entry's data lies outside the image.
+    OutOfBounds(String),
+}
+
+impl fmt::Display for InitrdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not an initrd image"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported initrd version {}", version),
+            Self::Truncated => f.write_str("image is truncated"),
+            Self::InvalidPath(path) => write!(f, "invalid path '{}'", path),
+            Self::DuplicatePath(path) => write!(f, "duplicate path '{}'", path),
+            Self::OutOfBounds(path) => write!(f, "data of '{}' lies outside the image", path),
+        }
+    }
+}
+
+/// An absolute `/`-separated path without empty, `.` or `..` components.
+pub fn is_valid_path(path: &str) -> bool {
+    match path.strip_prefix('/') {
+        Some(rest) => !rest.is_empty() && !path.contains('\0') && rest.split('/').all(|part| !part.is_empty() && part != "." && part != ".."),
+        None => false,
+    }
+}
+
+/// A parsed image. File contents borrow from the image bytes.
+pub struct Initrd<'a> {
+    files: Vec<(&'a str, &'a [u8])>, // Sorted by path
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], InitrdError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(InitrdError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, InitrdError> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn u64(&mut self) -> Result<u64, InitrdError> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+}
+
+impl<'a> Initrd<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Self, InitrdError> {
+        let mut cursor = Cursor { bytes: image, pos: 0 };
+        if cursor.take(MAGIC.len()).map_err(|_| InitrdError::BadMagic)? != MAGIC {
+            return Err(InitrdError::BadMagic);
+        }
+        let version = cursor.u32()?;
+        if version != VERSION {
+            return Err(InitrdError::UnsupportedVersion(version));
+        }
+        let count = cursor.u32()? as usize;
+        let mut files: Vec<(&'a str, &'a [u8])> = Vec::new();
+        for _ in 0..count {
+            let path_len = cursor.u32()? as usize;
+            let path = core::str::from_utf8(cursor.take(path_len)?).map_err(|_| InitrdError::InvalidPath(String::from("<not UTF-8>")))?;
+            if !is_valid_path(path) {
+                return Err(InitrdError::InvalidPath(String::from(path)));
+            }
+            let offset = cursor.u64()?;
+            let len = cursor.u64()?;
+            let data = offset.checked_add(len)
+                .filter(|end| *end <= image.len() as u64)
+                .map(|end| &image[offset as usize..end as usize])
+                .ok_or_else(|| InitrdError::OutOfBounds(String::from(path)))?;
+            files.push((path, data));
+        }
+        files.sort_by(|a, b| a.0.cmp(b.0));
+        if let Some(pair) = files.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(InitrdError::DuplicatePath(String::from(pair[0].0)));
+        }
+        Ok(Self { files })
+    }
+
+    pub fn get(&self, path: &str) -> Option<&'a [u8]> {
+        self.files.binary_search_by(|(p, _)| (*p).cmp(path)).ok().map(|i| self.files[i].1)
+    }
+
+    /// Every file, sorted by path.
+    pub fn files(&self) -> &[(&'a str, &'a [u8])] {
+        &self.files
+    }
+}
+
+/// Builds an image from `(path, contents)` pairs.
+pub fn build(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, InitrdError> {
+    let mut sorted: Vec<&(String, Vec<u8>)> = files.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    for pair in sorted.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(InitrdError::DuplicatePath(pair[0].0.clone()));
+        }
+    }
+    if let Some((path, _)) = sorted.iter().find(|(path, _)| !is_valid_path(path)) {
+        return Err(InitrdError::InvalidPath(path.clone()));
+    }
+
+    let table_len: usize = sorted.iter().map(|(path, _)| 4 + path.len() + 16).sum();
+    let mut offset = (MAGIC.len() + 8 + table_len) as u64;
+    let mut image = Vec::new();
+    image.extend_from_slice(MAGIC);
+    image.extend_from_slice(&VERSION.to_le_bytes());
+    image.extend_from_slice(&(sorted.len() as u32).to_le_bytes());
+    for (path, data) in &sorted {
+        image.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        image.extend_from_slice(path.as_bytes());
+        image.extend_from_slice(&offset.to_le_bytes());
+        image.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        offset += data.len() as u64;
+    }
+    for (_, data) in &sorted {
+        image.extend_from_slice(data);
+    }
+    Ok(image)
+}
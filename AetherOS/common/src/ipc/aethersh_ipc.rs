@@ -0,0 +1,171 @@
+// common/src/ipc/aethersh_ipc.rs
+
+#![no_std]
+
+//! The aethersh remote shell protocol, spoken over TCP between the shell's
+//! `aethersh` built-in and `vnode/aethersh-server`.
+//!
+//! Every message is a frame: its length as a little-endian `u32`, then a
+//! postcard-encoded `ClientFrame` or `ServerFrame`. The server speaks first:
+//!
+//! 1.  `Challenge { version, nonce }`. The nonce comes from the session
+//!     service, on behalf of the shell instance the server started for this
+//!     connection.
+//! 2.  The client signs the nonce with its identity key and answers
+//!     `Auth { aid, proof }`. The server checks the Aid against
+//!     `AUTHORIZED_PATH` and the signature, then has the shell instance log
+//!     in with the same proof, which binds the Aid to it. It answers
+//!     `Authenticated` or `Refused`, and closes after `Refused`.
+//! 3.  Each `Line` runs in the shell instance and is answered with `Output`,
+//!     or with `Prompt` when the command needs a decision; the client then
+//!     sends `Answer`. `Bye` ends the session, as does `Closed` from the server.
+//!
+//! ## Transport
+//!
+//! There is no TLS layer yet, so frames travel in plaintext, and both ends
+//! refuse to hold a session with a peer outside 127.0.0.0/8
+//! (`is_loopback`). That is the only plaintext case there will be: once TLS
+//! is available, sessions to other addresses run over it.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::ipc::session_ipc::{self, AidBytes};
+
+/// Protocol version in `Challenge`. A client that speaks another one hangs up.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Port the server listens on unless `aethersh.port` says otherwise.
+pub const DEFAULT_PORT: u16 = 2222;
+/// Aids allowed to log in, one hex Aid per line. Anything after it on the
+/// line is a comment, as are lines starting with `#`.
+pub const AUTHORIZED_PATH: &str = "/etc/aethersh/authorized";
+/// Where the client finds the key it authenticates with, under the home
+/// directory of the identity the shell is logged in as.
+pub const IDENTITY_FILE: &str = ".aether/identity";
+/// Largest frame either end accepts. A command's output is cut to fit.
+pub const MAX_FRAME_LEN: usize = 256 * 1024;
+/// Time a client has from connecting to sending `Auth`.
+pub const AUTH_TIMEOUT_SECS: u64 = 30;
+/// Time a client waits for the answer to a `Line`. Longer than the longest
+/// built-in, `tcpdump -t 300`.
+pub const REPLY_TIMEOUT_SECS: u64 = 330;
+
+pub const PORT_KEY: &str = "aethersh.port";
+pub const IDLE_TIMEOUT_KEY: &str = "aethersh.idle_timeout_secs";
+pub const MAX_SESSIONS_KEY: &str = "aethersh.max_sessions";
+
+/// Client to server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientFrame {
+    /// `proof` is the signature over the challenge's nonce.
+    Auth { aid: AidBytes, proof: Vec<u8> },
+    /// A command line, run as if typed into the shell.
+    Line { line: String },
+    /// The reply to a `Prompt`.
+    Answer { text: String },
+    Bye,
+}
+
+/// Server to client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerFrame {
+    Challenge { version: u32, nonce: AidBytes },
+    /// The session runs as the Aid that authenticated, starting in `cwd`.
+    Authenticated { cwd: String },
+    /// Authentication failed, or the server can't take the connection. The
+    /// server closes the connection after sending it.
+    Refused { reason: String },
+    Output { stdout: String, stderr: String, exit_code: i32 },
+    /// The command waits for an `Answer`.
+    Prompt { message: String },
+    /// The server ended the session, e.g. after it sat idle too long.
+    Closed { reason: String },
+}
+
+/// Why bytes from the peer aren't a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The length prefix is over `MAX_FRAME_LEN`.
+    TooLong(usize),
+    /// The frame's body doesn't decode.
+    Malformed,
+}
+
+/// `frame` with its length prefix, ready to send.
+pub fn encode<T: Serialize>(frame: &T) -> Vec<u8> {
+    let body = postcard::to_allocvec(frame).unwrap_or_default();
+    let mut out = Vec::with_capacity(4 + body.len());
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Collects bytes from a TCP stream and splits them into frames.
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The next whole frame, or `None` until more bytes arrive. After an
+    /// error the stream can't be resynchronized; the caller hangs up.
+    pub fn next<T: DeserializeOwned>(&mut self) -> Result<Option<T>, FrameError> {
+        let Some(prefix) = self.buf.get(..4) else {
+            return Ok(None);
+        };
+        let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(FrameError::TooLong(len));
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame = postcard::from_bytes(&self.buf[4..4 + len]).map_err(|_| FrameError::Malformed);
+        self.buf.drain(..4 + len);
+        frame.map(Some)
+    }
+}
+
+/// The client's default identity file for `aid`: `/home/<aid hex>/.aether/identity`.
+pub fn identity_path(aid: &AidBytes) -> String {
+    let mut path = session_ipc::home_dir(aid);
+    path.push('/');
+    path.push_str(IDENTITY_FILE);
+    path
+}
+
+/// Whether `addr` is in 127.0.0.0/8, the only place sessions run in plaintext.
+pub fn is_loopback(addr: [u8; 4]) -> bool {
+    addr[0] == 127
+}
+
+/// The Aids in an authorized file. Lines that don't start with a valid Aid
+/// are skipped and returned by number, so they can be reported.
+pub fn parse_authorized(text: &str) -> (Vec<AidBytes>, Vec<usize>) {
+    let mut aids = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().next().and_then(session_ipc::aid_from_hex) {
+            Some(aid) => aids.push(aid),
+            None => rejected.push(index + 1),
+        }
+    }
+    (aids, rejected)
+}
@@ -0,0 +1,85 @@
+// common/src/ipc/audio_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a stream. Not reused while the audio mixer runs.
+pub type StreamId = u64;
+
+/// Represents requests from client V-Nodes to the audio mixer V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AudioRequest {
+    /// Play signed 16-bit little-endian PCM, interleaved if `channels` is 2,
+    /// at `volume` percent. With `more` set the stream stays open for
+    /// `AppendPcm`; otherwise it ends when `data` has played.
+    PlayPcm {
+        sample_rate: u32,
+        channels: u8,
+        volume: u8,
+        data: Vec<u8>,
+        more: bool,
+    },
+    /// More PCM for a stream opened with `more`. Clear `more` on the last part.
+    AppendPcm { id: StreamId, data: Vec<u8>, more: bool },
+    /// Play a tone at `volume` percent.
+    PlayTone { freq_hz: u32, duration_ms: u32, volume: u8 },
+    /// End a stream now, whatever is left of it.
+    Stop { id: StreamId },
+    Stats,
+}
+
+/// Represents responses from the audio mixer V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AudioResponse {
+    /// Answers `PlayPcm` and `PlayTone`.
+    Playing { id: StreamId },
+    Success,
+    Stats(AudioStats),
+    Error(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioStats {
+    /// False if the system has no sound card; nothing can be played then.
+    pub device: bool,
+    /// Rate everything is mixed to.
+    pub sample_rate: u32,
+    pub active_streams: u32,
+    pub master_volume: u8,
+    /// Periods handed to the sound card since the mixer started.
+    pub periods_played: u64,
+    /// Times the sound card ran out of queued audio while something was playing.
+    pub underruns: u64,
+    /// Times an open stream ran out of data and played silence until more came.
+    pub starved: u64,
+}
+
+/// Sample rates `PlayPcm` accepts.
+pub const MIN_SAMPLE_RATE: u32 = 4_000;
+pub const MAX_SAMPLE_RATE: u32 = 192_000;
+
+/// Tone frequencies and length `PlayTone` accepts.
+pub const MIN_TONE_HZ: u32 = 20;
+pub const MAX_TONE_HZ: u32 = 20_000;
+pub const MAX_TONE_MS: u32 = 10_000;
+
+/// Most PCM bytes per `PlayPcm` or `AppendPcm`, so the request fits in one message.
+pub const MAX_PCM_CHUNK: usize = crate::ipc::vnode::MAX_MESSAGE_SIZE - 64;
+
+/// The terminal bell (BEL, `\x07`): a short, quiet beep.
+pub const BELL_TONE_HZ: u32 = 880;
+pub const BELL_TONE_MS: u32 = 120;
+pub const BELL_TONE_VOLUME: u8 = 40;
+
+/// Played for `Critical` notifications.
+pub const ALERT_TONE_HZ: u32 = 1_200;
+pub const ALERT_TONE_MS: u32 = 300;
+pub const ALERT_TONE_VOLUME: u8 = 70;
+
+/// Setting that scales everything the mixer plays, in percent.
+pub const MASTER_VOLUME_KEY: &str = "audio.master_volume";
@@ -0,0 +1,56 @@
+// common/src/ipc/block_ipc.rs
+
+//! How a block device failed a request. The disk driver reports one per
+//! request and AetherFS passes it up to the VFS unchanged, so the VFS can
+//! tell a bad sector, which may read on a second try, from a device that is
+//! gone, which won't.
+
+#![no_std]
+
+use serde::{Deserialize, Serialize};
+
+/// Status bytes a virtio-blk device writes at the end of each request.
+pub const VIRTIO_BLK_S_OK: u8 = 0;
+pub const VIRTIO_BLK_S_IOERR: u8 = 1;
+pub const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The device couldn't read or write the sectors (`VIRTIO_BLK_S_IOERR`).
+    Media,
+    /// The request didn't complete within the driver's timeout.
+    Timeout,
+    /// The device went away: unplugged, reset, or answering with nonsense.
+    DeviceGone,
+    /// The device doesn't do this kind of request, e.g. a discard (`VIRTIO_BLK_S_UNSUPP`).
+    Unsupported,
+}
+
+impl BlockError {
+    /// The outcome of a virtio-blk request, from the status byte the device
+    /// wrote. A status the specification doesn't define means the device is
+    /// broken.
+    pub fn from_virtio_status(status: u8) -> Result<(), BlockError> {
+        match status {
+            VIRTIO_BLK_S_OK => Ok(()),
+            VIRTIO_BLK_S_IOERR => Err(BlockError::Media),
+            VIRTIO_BLK_S_UNSUPP => Err(BlockError::Unsupported),
+            _ => Err(BlockError::DeviceGone),
+        }
+    }
+
+    /// Whether the same request may succeed if sent again. A marginal sector
+    /// sometimes reads on a later try, and a timeout may have been a stall.
+    pub fn is_transient(self) -> bool {
+        matches!(self, BlockError::Media | BlockError::Timeout)
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            BlockError::Media => "media error",
+            BlockError::Timeout => "device timeout",
+            BlockError::DeviceGone => "device gone",
+            BlockError::Unsupported => "not supported by the device",
+        }
+    }
+}
@@ -0,0 +1,535 @@
+// common/src/ipc/compat.rs
+
+//! Golden encodings of the IPC protocols.
+//!
+//! Postcard encodes an enum variant as its position and a struct as its
+//! fields in order, with no names. Reordering variants, inserting one in the
+//! middle, or adding, removing or reordering a field changes the bytes, and
+//! V-Nodes built before the change then misread every message after it
+//! without any error. The envelope's version only catches that at runtime.
+//!
+//! Each fixture below is a sample message with the bytes it encoded to when
+//! it was added. The tests at the end of this file check that every sample
+//! still encodes to exactly those bytes and that the bytes still decode to
+//! the sample, so an incompatible edit fails `cargo test` instead of a
+//! running system; `axpkg check-protocols` runs the same check. Adding a variant means adding a line here; an intentional break
+//! means bumping `ENVELOPE_VERSION` and regenerating the bytes (see
+//! `docs/system/ipc-compat.md`).
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::ids::{SocketHandle, Ticks, WindowId};
+use crate::ipc::aethersh_ipc::{ClientFrame, ServerFrame};
+use crate::ipc::block_ipc::BlockError;
+use crate::ipc::dns_ipc::{DnsRequest, DnsResponse};
+use crate::ipc::envelope::Envelope;
+use crate::ipc::lifecycle_ipc::{LifecycleRequest, LifecycleResponse};
+use crate::ipc::init_ipc::{BootProgress, BootReport, BootState, CrashReport, GroupCrashPolicy, GroupInfo, GroupMemberInfo, GroupState, InitRequest, InitResponse, InstanceInfo, MemberRestart, ServiceBootTime, ServiceTarget};
+use crate::ipc::mail_ipc::{MailRequest, MailResponse, SearchHit};
+use crate::ipc::metrics_ipc::{MetricSample, MetricValue, MetricsRequest, MetricsResponse};
+use crate::ipc::model_runtime_ipc::{InferRequest, InferResponse, InputConstraint};
+use crate::ipc::net_ipc::{CaptureDirection, CaptureFilter, CaptureStats, CloseReason, ConnectState, ConnectionHistory, ConnectionRecord, FlowProtocol, InterfaceInfo, NeighborEntry, NeighborState, NetStackRequest, NetStackResponse, SocketQuota, StateChange, TcpConnState};
+use crate::ipc::socket_ipc::{AttemptError, ConnectAttempt, ListenerInfo, NetRule, PolicyAction, SendMode, SendStats, ServicePolicy, SocketOption, SocketRequest, SocketResponse};
+use crate::ipc::ui_protocol::{CompositorStats, CursorShape, DisplayInfo, DisplayMode, DragData, HotkeyAction, HotkeyInfo, KeyEventType, MouseEventType, NotificationButton, OutputInfo, UiEvent, UiRequest, UiResponse, WallpaperFit, WindowInfo, WindowLatency};
+use crate::ipc::vfs_ipc::{AllocateMode, NameError, StorageHealth, VfsMetadata, VfsRequest, VfsResponse, VfsUsage};
+use crate::ui::latency::{InputTiming, PipelineLatency};
+
+/// One sample message and the bytes it must encode to.
+pub struct Fixture {
+    /// The sample's constructor, e.g. `VfsRequest::Open`.
+    pub name: String,
+    pub golden: &'static [u8],
+    /// What the sample encodes to today.
+    pub encoded: Vec<u8>,
+    /// Why the golden bytes don't decode to the sample today, if they don't.
+    pub decode_error: Option<String>,
+}
+
+impl Fixture {
+    /// Checks `sample` against `golden` both ways. Decoded values are
+    /// compared through `Debug`, so protocol types needn't implement `PartialEq`.
+    pub fn new<T: Serialize + DeserializeOwned + Debug>(expr: &'static str, sample: T, golden: &'static [u8]) -> Self {
+        let name = expr.split(|c: char| c == ' ' || c == '(' || c == '{').next().unwrap_or(expr);
+        let encoded = postcard::to_allocvec(&sample).unwrap_or_default();
+        let decode_error = match postcard::from_bytes::<T>(golden) {
+            Ok(decoded) if format!("{:?}", decoded) == format!("{:?}", sample) => None,
+            Ok(decoded) => Some(format!("decodes as {:?}", decoded)),
+            Err(e) => Some(format!("doesn't decode: {}", e)),
+        };
+        Self { name: String::from(name), golden, encoded, decode_error }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.encoded == self.golden && self.decode_error.is_none()
+    }
+
+    /// Today's encoding in the form the fixture lines use, for regenerating.
+    pub fn regenerated(&self) -> String {
+        let bytes: Vec<String> = self.encoded.iter().map(|byte| format!("{}", byte)).collect();
+        format!("[{}]", bytes.join(", "))
+    }
+}
+
+/// `fixture!(<sample> => [<bytes>])`: one sample and the bytes it must encode to.
+macro_rules! fixture {
+    ($sample:expr => [$($byte:expr),* $(,)?]) => {
+        Fixture::new(stringify!($sample), $sample, &[$($byte),*])
+    };
+}
+
+fn metadata() -> VfsMetadata {
+    VfsMetadata { is_dir: false, size: 4096, created: 1_700_000_000, modified: 1_700_000_060, permissions: 0o644, allocated_size: 4096 }
+}
+
+fn samples() -> Vec<MetricSample> {
+    vec![MetricSample {
+        name: "vfs_cache_hits_total".into(),
+        help: "Cache hits.".into(),
+        labels: vec![("service".into(), "vfs".into())],
+        value: MetricValue::Histogram { bounds: vec![1, 10], buckets: vec![3, 2, 1], count: 6, sum: 25 },
+    }, MetricSample {
+        name: "vfs_open_files".into(),
+        help: "Open files.".into(),
+        labels: vec![("service".into(), "vfs".into())],
+        value: MetricValue::Gauge(-2),
+    }]
+}
+
+fn timing() -> InputTiming {
+    InputTiming { captured_at: 100, dispatched_at: 101, received_at: 103, committed_at: 110 }
+}
+
+fn window() -> WindowInfo {
+    WindowInfo { id: WindowId::from_raw(1), title: "Terminal".into(), x: 20, y: 30, width: 640, height: 400, title_bar_height: 20, output_id: 0 }
+}
+
+fn output() -> OutputInfo {
+    OutputInfo { id: 1, x: 1024, y: 0, width: 800, height: 600, offscreen: true }
+}
+
+fn display_info() -> DisplayInfo {
+    DisplayInfo {
+        width: 1024,
+        height: 768,
+        modes: vec![DisplayMode { width: 1024, height: 768 }],
+        mode_switching: false,
+        background_color: "#202030".into(),
+        wallpaper: "".into(),
+        fit: WallpaperFit::Fill,
+        scale_percent: 150,
+    }
+}
+
+/// Every fixture, grouped by protocol.
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        // VfsRequest
+        fixture!(VfsRequest::Open { path: "/home/a.txt".into(), flags: 0x41 } => [0, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116, 65]),
+        fixture!(VfsRequest::Read { fd: 3, len: 512, offset: 1024 } => [1, 3, 128, 4, 128, 8]),
+        fixture!(VfsRequest::Write { fd: 3, data: vec![1, 2, 3], offset: 0 } => [2, 3, 3, 1, 2, 3, 0]),
+        fixture!(VfsRequest::List { path: "/home".into() } => [3, 5, 47, 104, 111, 109, 101]),
+        fixture!(VfsRequest::Stat { path: "/home/a.txt".into() } => [4, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116]),
+        fixture!(VfsRequest::Close { fd: 3 } => [5, 3]),
+        fixture!(VfsRequest::Delete { path: "/tmp/x".into() } => [6, 6, 47, 116, 109, 112, 47, 120]),
+        fixture!(VfsRequest::CreateDirectory { path: "/tmp/d".into() } => [7, 6, 47, 116, 109, 112, 47, 100]),
+        fixture!(VfsRequest::Move { source: "/tmp/x".into(), destination: "/tmp/y".into() } => [8, 6, 47, 116, 109, 112, 47, 120, 6, 47, 116, 109, 112, 47, 121]),
+        fixture!(VfsRequest::Fsync { fd: 3 } => [9, 3]),
+        fixture!(VfsRequest::SyncAll => [10]),
+        fixture!(VfsRequest::Metrics(MetricsRequest::Scrape) => [11, 0]),
+        fixture!(VfsRequest::Pin { fd: 3 } => [12, 3]),
+        fixture!(VfsRequest::Unpin { backing: 77 } => [13, 77]),
+        fixture!(VfsRequest::GetUsage { owner: Some([7; 32]) } => [14, 1, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7]),
+        fixture!(VfsRequest::TxBegin => [15]),
+        fixture!(VfsRequest::TxCommit { id: 9 } => [16, 9]),
+        fixture!(VfsRequest::TxAbort { id: 9 } => [17, 9]),
+        fixture!(VfsRequest::InTx { id: 9, request: VfsRequest::Close { fd: 4 }.into() } => [18, 9, 5, 4]),
+        fixture!(VfsRequest::ReadStream { fd: 3, offset: 0, len: u64::MAX } => [19, 3, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1]),
+        fixture!(VfsRequest::StreamCredit { stream_id: 5, chunks: 8 } => [20, 5, 8]),
+        fixture!(VfsRequest::WriteStream { fd: 3, offset: 100 } => [21, 3, 100]),
+        fixture!(VfsRequest::StreamData { stream_id: 5, seq: 2, data: vec![9, 8], eof: true } => [22, 5, 2, 2, 9, 8, 1]),
+        fixture!(VfsRequest::SetXattr { path: "/home/a.txt".into(), name: "user.mime_type".into(), value: b"text/plain".to_vec() } => [23, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116, 14, 117, 115, 101, 114, 46, 109, 105, 109, 101, 95, 116, 121, 112, 101, 10, 116, 101, 120, 116, 47, 112, 108, 97, 105, 110]),
+        fixture!(VfsRequest::GetXattr { path: "/home/a.txt".into(), name: "user.mime_type".into() } => [24, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116, 14, 117, 115, 101, 114, 46, 109, 105, 109, 101, 95, 116, 121, 112, 101]),
+        fixture!(VfsRequest::ListXattrs { path: "/home/a.txt".into() } => [25, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116]),
+        fixture!(VfsRequest::RemoveXattr { path: "/home/a.txt".into(), name: "user.mime_type".into() } => [26, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116, 14, 117, 115, 101, 114, 46, 109, 105, 109, 101, 95, 116, 121, 112, 101]),
+        fixture!(VfsRequest::StatWithXattrs { path: "/home/a.txt".into(), names: vec!["system.package".into()] } => [27, 11, 47, 104, 111, 109, 101, 47, 97, 46, 116, 120, 116, 1, 14, 115, 121, 115, 116, 101, 109, 46, 112, 97, 99, 107, 97, 103, 101]),
+        fixture!(VfsRequest::ListWithXattrs { path: "/home".into(), names: vec!["user.mime_type".into()] } => [28, 5, 47, 104, 111, 109, 101, 1, 14, 117, 115, 101, 114, 46, 109, 105, 109, 101, 95, 116, 121, 112, 101]),
+        fixture!(VfsRequest::Lock { fd: 3, exclusive: true, wait: false } => [29, 3, 1, 0]),
+        fixture!(VfsRequest::Unlock { fd: 3 } => [30, 3]),
+        fixture!(VfsRequest::Watch { path: "/tmp/x".into(), reply_chan: 20 } => [31, 6, 47, 116, 109, 112, 47, 120, 20]),
+        fixture!(VfsRequest::Unwatch { path: "/tmp/x".into(), reply_chan: 20 } => [32, 6, 47, 116, 109, 112, 47, 120, 20]),
+        fixture!(VfsRequest::Allocate { fd: 3, offset: 4096, len: 8192, mode: AllocateMode::Reserve } => [33, 3, 128, 32, 128, 64, 1]),
+        fixture!(VfsRequest::SeekData { fd: 3, offset: 100 } => [34, 3, 100]),
+        fixture!(VfsRequest::SeekHole { fd: 3, offset: 100 } => [35, 3, 100]),
+        fixture!(VfsRequest::StorageHealth => [36]),
+        // VfsResponse
+        fixture!(VfsResponse::Success(3) => [0, 6]),
+        fixture!(VfsResponse::Data(vec![104, 105]) => [1, 2, 104, 105]),
+        fixture!(VfsResponse::Metadata(metadata()) => [2, 0, 128, 32, 128, 226, 207, 170, 6, 188, 226, 207, 170, 6, 164, 3, 128, 32]),
+        fixture!(VfsResponse::DirectoryEntries(BTreeMap::from([("a.txt".into(), metadata())])) => [3, 1, 5, 97, 46, 116, 120, 116, 0, 128, 32, 128, 226, 207, 170, 6, 188, 226, 207, 170, 6, 164, 3, 128, 32]),
+        fixture!(VfsResponse::Error { code: -2, message: "Not found".into() } => [4, 3, 9, 78, 111, 116, 32, 102, 111, 117, 110, 100]),
+        fixture!(VfsResponse::DeleteSuccess => [5]),
+        fixture!(VfsResponse::CreateDirectorySuccess => [6]),
+        fixture!(VfsResponse::MoveSuccess => [7]),
+        fixture!(VfsResponse::Metrics(MetricsResponse::Metrics(samples())) => [8, 0, 2, 20, 118, 102, 115, 95, 99, 97, 99, 104, 101, 95, 104, 105, 116, 115, 95, 116, 111, 116, 97, 108, 11, 67, 97, 99, 104, 101, 32, 104, 105, 116, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 2, 2, 1, 10, 3, 3, 2, 1, 6, 25, 14, 118, 102, 115, 95, 111, 112, 101, 110, 95, 102, 105, 108, 101, 115, 11, 79, 112, 101, 110, 32, 102, 105, 108, 101, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 1, 3]),
+        fixture!(VfsResponse::Unauthenticated => [9]),
+        fixture!(VfsResponse::Pinned { backing: 77, size: 4096 } => [10, 77, 128, 32]),
+        fixture!(VfsResponse::InvalidName { path: "/a/../b".into(), reason: NameError::RelativeComponent } => [11, 7, 47, 97, 47, 46, 46, 47, 98, 2]),
+        fixture!(VfsResponse::QuotaExceeded { owner: [7; 32], used: 1000, limit: 1024 } => [12, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 232, 7, 128, 8]),
+        fixture!(VfsResponse::Usage(VfsUsage { owner: [7; 32], used_bytes: 1000, limit_bytes: 1024, file_count: 3 }) => [13, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 232, 7, 128, 8, 3]),
+        fixture!(VfsResponse::TxBegun { id: 9 } => [14, 9]),
+        fixture!(VfsResponse::StreamStarted { stream_id: 5 } => [15, 5]),
+        fixture!(VfsResponse::StreamChunk { stream_id: 5, seq: 0, data: vec![1, 2], eof: false } => [16, 5, 0, 2, 1, 2, 0]),
+        fixture!(VfsResponse::StreamAck { stream_id: 5, seq: 2, written: 300 } => [17, 5, 2, 172, 2]),
+        fixture!(VfsResponse::StreamFinished { stream_id: 5, written: 300 } => [18, 5, 172, 2]),
+        fixture!(VfsResponse::StreamError { stream_id: 5, code: -28, message: "No space".into() } => [19, 5, 55, 8, 78, 111, 32, 115, 112, 97, 99, 101]),
+        fixture!(VfsResponse::XattrNames(vec!["system.package".into(), "user.mime_type".into()]) => [20, 2, 14, 115, 121, 115, 116, 101, 109, 46, 112, 97, 99, 107, 97, 103, 101, 14, 117, 115, 101, 114, 46, 109, 105, 109, 101, 95, 116, 121, 112, 101]),
+        fixture!(VfsResponse::MetadataWithXattrs { metadata: metadata(), xattrs: BTreeMap::from([("system.package".into(), b"editor".to_vec())]) } => [21, 0, 128, 32, 128, 226, 207, 170, 6, 188, 226, 207, 170, 6, 164, 3, 128, 32, 1, 14, 115, 121, 115, 116, 101, 109, 46, 112, 97, 99, 107, 97, 103, 101, 6, 101, 100, 105, 116, 111, 114]),
+        fixture!(VfsResponse::DirectoryEntriesWithXattrs(BTreeMap::from([("a.txt".into(), (metadata(), BTreeMap::new()))])) => [22, 1, 5, 97, 46, 116, 120, 116, 0, 128, 32, 128, 226, 207, 170, 6, 188, 226, 207, 170, 6, 164, 3, 128, 32, 0]),
+        fixture!(VfsResponse::WouldBlock => [23]),
+        fixture!(VfsResponse::Deadlock => [24]),
+        fixture!(VfsResponse::Changed { path: "/tmp/x".into(), size: 300 } => [25, 6, 47, 116, 109, 112, 47, 120, 172, 2]),
+        fixture!(VfsResponse::Offset(8192) => [26, 128, 64]),
+        fixture!(VfsResponse::MediaError { path: "/tmp/x".into(), offset: 8192, len: 4096, cause: BlockError::Media } => [27, 6, 47, 116, 109, 112, 47, 120, 128, 64, 128, 32, 0]),
+        fixture!(VfsResponse::StorageHealth(StorageHealth { reads: 1000, writes: 300, retried: 2, unrecoverable: 1, last_error: 1700000000, read_only: true }) => [28, 232, 7, 172, 2, 2, 1, 128, 226, 207, 170, 6, 1]),
+        // SocketRequest
+        fixture!(SocketRequest::Socket { domain: 2, ty: 1, protocol: 0 } => [0, 4, 2, 0]),
+        fixture!(SocketRequest::Bind { fd: SocketHandle::from_raw(1), addr: [0, 0, 0, 0], port: 8080 } => [1, 1, 0, 0, 0, 0, 144, 63]),
+        fixture!(SocketRequest::Listen { fd: SocketHandle::from_raw(1), backlog: 16 } => [2, 1, 32]),
+        fixture!(SocketRequest::Accept { fd: SocketHandle::from_raw(1) } => [3, 1]),
+        fixture!(SocketRequest::Connect { fd: SocketHandle::from_raw(1), addr: [10, 0, 2, 2], port: 80 } => [4, 1, 10, 0, 2, 2, 80]),
+        fixture!(SocketRequest::ConnectHost { fd: SocketHandle::from_raw(1), hostname: "example.com".into(), port: 443, attempt_timeout_ms: 0 } => [5, 1, 11, 101, 120, 97, 109, 112, 108, 101, 46, 99, 111, 109, 187, 3, 0]),
+        fixture!(SocketRequest::GetPeerName { fd: SocketHandle::from_raw(1) } => [6, 1]),
+        fixture!(SocketRequest::Send { fd: SocketHandle::from_raw(1), data: vec![71, 69, 84] } => [7, 1, 3, 71, 69, 84]),
+        fixture!(SocketRequest::SendTo { fd: SocketHandle::from_raw(2), addr: [224, 0, 0, 251], port: 5353, data: vec![0, 1] } => [8, 2, 224, 0, 0, 251, 233, 41, 2, 0, 1]),
+        fixture!(SocketRequest::Recv { fd: SocketHandle::from_raw(1), len: 1500 } => [9, 1, 220, 11]),
+        fixture!(SocketRequest::JoinMulticast { fd: SocketHandle::from_raw(2), group: [224, 0, 0, 251] } => [10, 2, 224, 0, 0, 251]),
+        fixture!(SocketRequest::LeaveMulticast { fd: SocketHandle::from_raw(2), group: [224, 0, 0, 251] } => [11, 2, 224, 0, 0, 251]),
+        fixture!(SocketRequest::Close { fd: SocketHandle::from_raw(1) } => [12, 1]),
+        fixture!(SocketRequest::GetSocketInfo { fd: SocketHandle::from_raw(1) } => [13, 1]),
+        fixture!(SocketRequest::GetPolicy => [14]),
+        fixture!(SocketRequest::SetSockOpt { fd: SocketHandle::from_raw(3), option: SocketOption::NoDelay(true) } => [15, 3, 0, 1]),
+        fixture!(SocketRequest::Flush { fd: SocketHandle::from_raw(3) } => [16, 3]),
+        fixture!(SocketRequest::RecvAsync { fd: SocketHandle::from_raw(2), reply_chan: 29 } => [17, 2, 29]),
+        fixture!(SocketRequest::RecvFrom { fd: SocketHandle::from_raw(2), len: 512 } => [18, 2, 128, 4]),
+        // SocketResponse
+        fixture!(SocketResponse::Success(4) => [0, 8]),
+        fixture!(SocketResponse::Data(vec![79, 75]) => [1, 2, 79, 75]),
+        fixture!(SocketResponse::Error(111, "Connection refused".into()) => [2, 222, 1, 18, 67, 111, 110, 110, 101, 99, 116, 105, 111, 110, 32, 114, 101, 102, 117, 115, 101, 100]),
+        fixture!(SocketResponse::Accepted { new_fd: SocketHandle::from_raw(5), remote_addr: [10, 0, 2, 15], remote_port: 49152 } => [3, 5, 10, 0, 2, 15, 128, 128, 3]),
+        fixture!(SocketResponse::PolicyDenied { rule: "mail-service rule 2 (deny 10.0.0.0/8)".into() } => [4, 37, 109, 97, 105, 108, 45, 115, 101, 114, 118, 105, 99, 101, 32, 114, 117, 108, 101, 32, 50, 32, 40, 100, 101, 110, 121, 32, 49, 48, 46, 48, 46, 48, 46, 48, 47, 56, 41]),
+        fixture!(SocketResponse::Policy(vec![ServicePolicy {
+            service: "mail-service".into(),
+            default: PolicyAction::Allow,
+            rules: vec![NetRule { action: PolicyAction::Deny, network: [10, 0, 0, 0], prefix_len: 8, first_port: 0, last_port: 65535 }],
+        }]) => [5, 1, 12, 109, 97, 105, 108, 45, 115, 101, 114, 118, 105, 99, 101, 0, 1, 1, 10, 0, 0, 0, 8, 0, 255, 255, 3]),
+        fixture!(SocketResponse::SocketInfo { ty: 1, local_port: 8080, listener: Some(ListenerInfo { backlog: 16, available: 15, pending: 1 }) } => [6, 2, 144, 63, 1, 16, 15, 1]),
+        fixture!(SocketResponse::ConnectedHost { addr: [93, 184, 216, 34], port: 443 } => [7, 93, 184, 216, 34, 187, 3]),
+        fixture!(SocketResponse::ResolveFailed { hostname: "nowhere.invalid".into(), reason: "not found".into() } => [8, 15, 110, 111, 119, 104, 101, 114, 101, 46, 105, 110, 118, 97, 108, 105, 100, 9, 110, 111, 116, 32, 102, 111, 117, 110, 100]),
+        fixture!(SocketResponse::ConnectFailed { attempts: vec![
+            ConnectAttempt { addr: [10, 0, 0, 1], error: AttemptError::Refused },
+            ConnectAttempt { addr: [10, 0, 0, 2], error: AttemptError::TimedOut },
+            ConnectAttempt { addr: [10, 0, 0, 3], error: AttemptError::PolicyDenied { rule: "deny 10.0.0.3/32".into() } },
+            ConnectAttempt { addr: [10, 0, 0, 4], error: AttemptError::NetworkDown },
+            ConnectAttempt { addr: [10, 0, 0, 5], error: AttemptError::Other(113, "No route".into()) },
+        ] } => [9, 5, 10, 0, 0, 1, 0, 10, 0, 0, 2, 1, 10, 0, 0, 3, 2, 16, 100, 101, 110, 121, 32, 49, 48, 46, 48, 46, 48, 46, 51, 47, 51, 50, 10, 0, 0, 4, 3, 10, 0, 0, 5, 4, 226, 1, 8, 78, 111, 32, 114, 111, 117, 116, 101]),
+        fixture!(SocketResponse::PeerName { addr: [10, 0, 2, 2], port: 80 } => [10, 10, 0, 2, 2, 80]),
+        fixture!(SocketResponse::NetworkDown => [11]),
+        fixture!(SocketResponse::StreamInfo { local_port: 49152, send: SendStats { mode: SendMode::Coalesce, queued: 0, segments: 4, bytes: 1200 } } => [12, 128, 128, 3, 0, 0, 4, 176, 9]),
+        fixture!(SocketResponse::DataAvailable { fd: SocketHandle::from_raw(2) } => [13, 2]),
+        fixture!(SocketResponse::DataFrom { data: vec![0xAB], addr: [10, 0, 2, 3], port: 53 } => [14, 1, 171, 10, 0, 2, 3, 53]),
+        // NetStackRequest
+        fixture!(NetStackRequest::OpenSocket(0, 8080) => [0, 0, 144, 63]),
+        fixture!(NetStackRequest::Send(1, vec![1, 2]) => [1, 1, 2, 1, 2]),
+        fixture!(NetStackRequest::SendTo(2, [10, 0, 2, 3], 53, vec![0xAB]) => [2, 2, 10, 0, 2, 3, 53, 1, 171]),
+        fixture!(NetStackRequest::Recv(1) => [3, 1]),
+        fixture!(NetStackRequest::CloseSocket(1) => [4, 1]),
+        fixture!(NetStackRequest::Listen { port: 8080, backlog: 16 } => [5, 144, 63, 16]),
+        fixture!(NetStackRequest::Accept(1) => [6, 1]),
+        fixture!(NetStackRequest::GetSocketInfo(1) => [7, 1]),
+        fixture!(NetStackRequest::Connect { handle: 1, remote_ip: [10, 0, 2, 2], remote_port: 80 } => [8, 1, 10, 0, 2, 2, 80]),
+        fixture!(NetStackRequest::ConnectStatus(1) => [9, 1]),
+        fixture!(NetStackRequest::Metrics(MetricsRequest::Scrape) => [10, 0]),
+        fixture!(NetStackRequest::GetNeighbors => [11]),
+        fixture!(NetStackRequest::AddStaticNeighbor { ip: [10, 0, 2, 2], mac: [0x52, 0x54, 0, 0x12, 0x34, 0x56] } => [12, 10, 0, 2, 2, 82, 84, 0, 18, 52, 86]),
+        fixture!(NetStackRequest::RemoveNeighbor { ip: [10, 0, 2, 2] } => [13, 10, 0, 2, 2]),
+        fixture!(NetStackRequest::FlushNeighbors { force: true } => [14, 1]),
+        fixture!(NetStackRequest::JoinMulticastGroup { handle: 2, group: [224, 0, 0, 251] } => [15, 2, 224, 0, 0, 251]),
+        fixture!(NetStackRequest::LeaveMulticastGroup { handle: 2, group: [224, 0, 0, 251] } => [16, 2, 224, 0, 0, 251]),
+        fixture!(NetStackRequest::GetInterface => [17]),
+        fixture!(NetStackRequest::SetInterfaceState { up: false } => [18, 0]),
+        fixture!(NetStackRequest::CaptureStart {
+            filter: Some(CaptureFilter { direction: Some(CaptureDirection::Rx), ethertype: Some(0x0800), ip_protocol: Some(17), port: Some(53) }),
+            snaplen: 256,
+            max_bytes: 65536,
+        } => [19, 1, 1, 0, 1, 128, 16, 1, 17, 1, 53, 128, 2, 128, 128, 4]),
+        fixture!(NetStackRequest::CaptureStop => [20]),
+        fixture!(NetStackRequest::CaptureDump { vfs_path: "/c.pcap".into() } => [21, 7, 47, 99, 46, 112, 99, 97, 112]),
+        fixture!(NetStackRequest::GetConnectionHistory { max: 20 } => [22, 20]),
+        fixture!(NetStackRequest::SetSockOpt { handle: 1, option: SocketOption::Cork(true) } => [23, 1, 1, 1]),
+        fixture!(NetStackRequest::Flush(1) => [24, 1]),
+        fixture!(NetStackRequest::WatchRecv { handle: 1, reply_chan: 28 } => [25, 1, 28]),
+        fixture!(NetStackRequest::RecvFrom(2) => [26, 2]),
+        fixture!(NetStackRequest::ForTask { task: 1001, request: Box::new(NetStackRequest::Recv(1)) } => [27, 233, 7, 3, 1]),
+        // NetStackResponse
+        fixture!(NetStackResponse::SocketOpened(1) => [0, 1]),
+        fixture!(NetStackResponse::Data(vec![1, 2]) => [1, 2, 1, 2]),
+        fixture!(NetStackResponse::Error(114) => [2, 114]),
+        fixture!(NetStackResponse::Success => [3]),
+        fixture!(NetStackResponse::QuotaExceeded(SocketQuota::PerTask, 64) => [4, 0, 64]),
+        fixture!(NetStackResponse::Metrics(MetricsResponse::Metrics(samples())) => [5, 0, 2, 20, 118, 102, 115, 95, 99, 97, 99, 104, 101, 95, 104, 105, 116, 115, 95, 116, 111, 116, 97, 108, 11, 67, 97, 99, 104, 101, 32, 104, 105, 116, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 2, 2, 1, 10, 3, 3, 2, 1, 6, 25, 14, 118, 102, 115, 95, 111, 112, 101, 110, 95, 102, 105, 108, 101, 115, 11, 79, 112, 101, 110, 32, 102, 105, 108, 101, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 1, 3]),
+        fixture!(NetStackResponse::Neighbors(vec![NeighborEntry { ip: [10, 0, 2, 2], mac: [0x52, 0x54, 0, 0x12, 0x34, 0x56], state: NeighborState::Static, age_ms: 1500 }]) => [6, 1, 10, 0, 2, 2, 82, 84, 0, 18, 52, 86, 1, 220, 11]),
+        fixture!(NetStackResponse::Interface(InterfaceInfo { mac: [0x52, 0x54, 0, 0x12, 0x34, 0x57], ip: [10, 0, 2, 15], prefix_len: 24, up: true }) => [7, 82, 84, 0, 18, 52, 87, 10, 0, 2, 15, 24, 1]),
+        fixture!(NetStackResponse::Accepted { handle: 3, remote_ip: [10, 0, 2, 2], remote_port: 49152 } => [8, 3, 10, 0, 2, 2, 128, 128, 3]),
+        fixture!(NetStackResponse::SocketInfo { local_port: 8080, listener: None } => [9, 144, 63, 0]),
+        fixture!(NetStackResponse::Connection(ConnectState::Established) => [10, 1]),
+        fixture!(NetStackResponse::InterfaceDown => [11]),
+        fixture!(NetStackResponse::Capture(CaptureStats { running: false, packets: 3, bytes: 250, matched: 5, dropped: 2 }) => [12, 0, 3, 250, 1, 5, 2]),
+        fixture!(NetStackResponse::ConnectionHistory(ConnectionHistory {
+            enabled: true,
+            open: vec![],
+            closed: vec![ConnectionRecord {
+                protocol: FlowProtocol::Tcp,
+                local_port: 49152,
+                remote_ip: [10, 0, 2, 2],
+                remote_port: 80,
+                opened_tick: 100,
+                closed_tick: Some(250),
+                reason: Some(CloseReason::Fin),
+                packets_in: 5,
+                packets_out: 4,
+                bytes_in: 300,
+                bytes_out: 20,
+                transitions: vec![StateChange { state: TcpConnState::SynSent, tick: 100 }, StateChange { state: TcpConnState::Established, tick: 101 }],
+                transitions_dropped: 0,
+            }],
+            evicted: 0,
+            untracked: 0,
+        }) => [13, 1, 0, 1, 0, 128, 128, 3, 10, 0, 2, 2, 80, 100, 1, 250, 1, 1, 0, 5, 4, 172, 2, 20, 2, 0, 100, 2, 101, 0, 0, 0]),
+        fixture!(NetStackResponse::StreamInfo { local_port: 49152, send: SendStats { mode: SendMode::Cork, queued: 100, segments: 2, bytes: 1072 } } => [14, 128, 128, 3, 2, 100, 2, 176, 8]),
+        fixture!(NetStackResponse::Flushed { queued: 0 } => [15, 0]),
+        fixture!(NetStackResponse::DataReady { handle: 1 } => [16, 1]),
+        fixture!(NetStackResponse::DataFrom { data: vec![0xAB], addr: [10, 0, 2, 3], port: 53 } => [17, 1, 171, 10, 0, 2, 3, 53]),
+        // DnsRequest and DnsResponse
+        fixture!(DnsRequest::ResolveHostname { hostname: "example.com".into() } => [0, 11, 101, 120, 97, 109, 112, 108, 101, 46, 99, 111, 109]),
+        fixture!(DnsRequest::ResolveAll { hostname: "example.com".into() } => [1, 11, 101, 120, 97, 109, 112, 108, 101, 46, 99, 111, 109]),
+        fixture!(DnsRequest::FlushCache => [2]),
+        fixture!(DnsResponse::ResolvedHostname { hostname: "example.com".into(), ip_address: [93, 184, 216, 34] } => [0, 11, 101, 120, 97, 109, 112, 108, 101, 46, 99, 111, 109, 93, 184, 216, 34]),
+        fixture!(DnsResponse::ResolvedAddresses { hostname: "example.com".into(), addresses: vec![[93, 184, 216, 34], [10, 0, 0, 1]] } => [1, 11, 101, 120, 97, 109, 112, 108, 101, 46, 99, 111, 109, 2, 93, 184, 216, 34, 10, 0, 0, 1]),
+        fixture!(DnsResponse::NotFound { query: "nowhere.invalid".into() } => [2, 15, 110, 111, 119, 104, 101, 114, 101, 46, 105, 110, 118, 97, 108, 105, 100]),
+        fixture!(DnsResponse::Error { message: "timed out".into() } => [3, 9, 116, 105, 109, 101, 100, 32, 111, 117, 116]),
+        fixture!(DnsResponse::CacheFlushed { entries: 3 } => [4, 3]),
+        // InitRequest
+        fixture!(InitRequest::ServiceStart { service_name: "vfs".into(), instance_label: Some("vfs-2".into()) } => [0, 3, 118, 102, 115, 1, 5, 118, 102, 115, 45, 50]),
+        fixture!(InitRequest::ServiceStatus { target: ServiceTarget::Instance(1000) } => [1, 0, 232, 7]),
+        fixture!(InitRequest::ServiceRestart { target: ServiceTarget::AllInstances("vfs".into()) } => [2, 1, 3, 118, 102, 115]),
+        fixture!(InitRequest::ServiceStop { target: ServiceTarget::Instance(1000) } => [3, 0, 232, 7]),
+        fixture!(InitRequest::ListServices => [4]),
+        fixture!(InitRequest::BootReport => [5]),
+        fixture!(InitRequest::GroupStart { name: "web".into() } => [6, 3, 119, 101, 98]),
+        fixture!(InitRequest::GroupStop { name: "web".into() } => [7, 3, 119, 101, 98]),
+        fixture!(InitRequest::GroupStatus { name: "web".into() } => [8, 3, 119, 101, 98]),
+        fixture!(InitRequest::ListGroups => [9]),
+        fixture!(InitRequest::ListServicesWithGroups => [10]),
+        fixture!(InitRequest::SystemShutdown { reboot: true, force: false } => [11, 1, 0]),
+        fixture!(InitRequest::Crashes { service_name: Some("vfs".into()) } => [12, 1, 3, 118, 102, 115]),
+        // InitResponse
+        fixture!(InitResponse::Success("Stopped".into()) => [0, 7, 83, 116, 111, 112, 112, 101, 100]),
+        fixture!(InitResponse::InstanceStarted { service_name: "vfs".into(), instance_id: 1001, channel: 40 } => [1, 3, 118, 102, 115, 233, 7, 40]),
+        fixture!(InitResponse::Instances(vec![InstanceInfo { instance_id: 1001, service_name: "vfs".into(), label: None, channel: 40 }]) => [2, 1, 233, 7, 3, 118, 102, 115, 0, 40]),
+        fixture!(InitResponse::ServiceList(vec!["event-bus".into(), "vfs".into()]) => [3, 2, 9, 101, 118, 101, 110, 116, 45, 98, 117, 115, 3, 118, 102, 115]),
+        fixture!(InitResponse::BootReport(BootReport {
+            timeline: vec![
+                BootProgress { service: "vfs".into(), index: 1, total: 2, state: BootState::Starting, tick: 10 },
+                BootProgress { service: "vfs".into(), index: 1, total: 2, state: BootState::Started, tick: 12 },
+                BootProgress { service: "shell".into(), index: 2, total: 2, state: BootState::Failed("dependency vfs failed".into()), tick: 13 },
+            ],
+            total: 2,
+            finished: 2,
+            total_ticks: Ticks::from_raw(3),
+            slowest: vec![ServiceBootTime { service: "vfs".into(), ticks: Ticks::from_raw(2) }],
+            failed: vec![("shell".into(), "dependency vfs failed".into())],
+        }) => [4, 3, 3, 118, 102, 115, 1, 2, 0, 10, 3, 118, 102, 115, 1, 2, 1, 12, 5, 115, 104, 101, 108, 108, 2, 2, 2, 21, 100, 101, 112, 101, 110, 100, 101, 110, 99, 121, 32, 118, 102, 115, 32, 102, 97, 105, 108, 101, 100, 13, 2, 2, 3, 1, 3, 118, 102, 115, 2, 1, 5, 115, 104, 101, 108, 108, 21, 100, 101, 112, 101, 110, 100, 101, 110, 99, 121, 32, 118, 102, 115, 32, 102, 97, 105, 108, 101, 100]),
+        fixture!(InitResponse::Error("Service 'x' not found in configuration.".into()) => [5, 39, 83, 101, 114, 118, 105, 99, 101, 32, 39, 120, 39, 32, 110, 111, 116, 32, 102, 111, 117, 110, 100, 32, 105, 110, 32, 99, 111, 110, 102, 105, 103, 117, 114, 97, 116, 105, 111, 110, 46]),
+        fixture!(InitResponse::GroupStatus(GroupInfo {
+            name: "web".into(),
+            state: GroupState::Degraded,
+            crash_policy: GroupCrashPolicy::RestartGroup,
+            members: vec![
+                GroupMemberInfo { service_name: "webview".into(), restart: MemberRestart::OnCrash, instance_id: Some(1001) },
+                GroupMemberInfo { service_name: "net".into(), restart: MemberRestart::Never, instance_id: None },
+            ],
+        }) => [6, 3, 119, 101, 98, 1, 1, 2, 7, 119, 101, 98, 118, 105, 101, 119, 0, 1, 233, 7, 3, 110, 101, 116, 1, 0]),
+        fixture!(InitResponse::Groups(vec![GroupInfo { name: "web".into(), state: GroupState::Stopped, crash_policy: GroupCrashPolicy::StopGroup, members: vec![] }]) => [7, 1, 3, 119, 101, 98, 2, 2, 0]),
+        fixture!(InitResponse::ServiceGroupList(vec![("net".into(), Some("web".into())), ("vfs".into(), None)]) => [8, 2, 3, 110, 101, 116, 1, 3, 119, 101, 98, 3, 118, 102, 115, 0]),
+        fixture!(InitResponse::Crashes(vec![CrashReport {
+            task_id: 1001,
+            task_name: "vfs".into(),
+            service_name: Some("vfs".into()),
+            reason: "page fault".into(),
+            rip: 4096,
+            fault_addr: 0,
+            tick: 500,
+            core_dump: None,
+            truncated: false,
+        }]) => [9, 1, 233, 7, 3, 118, 102, 115, 1, 3, 118, 102, 115, 10, 112, 97, 103, 101, 32, 102, 97, 117, 108, 116, 128, 32, 0, 244, 3, 0, 0]),
+        // UiRequest
+        fixture!(UiRequest::CreateWindow { title: "Terminal".into(), width: 640, height: 400 } => [0, 8, 84, 101, 114, 109, 105, 110, 97, 108, 128, 5, 144, 3]),
+        fixture!(UiRequest::DrawToSurface { window_id: WindowId::from_raw(1), x: 0, y: 0, width: 1, height: 1, pixels: vec![255, 0, 0, 255], input: Some(timing()) } => [1, 1, 0, 0, 1, 1, 4, 255, 0, 0, 255, 1, 100, 101, 103, 110]),
+        fixture!(UiRequest::MouseEvent { window_id: WindowId::from_raw(1), x: 50, y: 60, button: 0, event_type: MouseEventType::MouseDown, captured_at: 100 } => [2, 1, 50, 60, 0, 0, 100]),
+        fixture!(UiRequest::KeyEvent { window_id: WindowId::from_raw(1), keycode: 0x1B, event_type: KeyEventType::KeyUp, captured_at: 100, text: None } => [3, 1, 27, 1, 100, 0]),
+        fixture!(UiRequest::ResizeWindow { window_id: WindowId::from_raw(1), width: 800, height: 600 } => [4, 1, 160, 6, 216, 4]),
+        fixture!(UiRequest::CloseWindow { window_id: WindowId::from_raw(1) } => [5, 1]),
+        fixture!(UiRequest::GetWindows => [6]),
+        fixture!(UiRequest::ListOutputs => [7]),
+        fixture!(UiRequest::MoveWindowToOutput { window_id: WindowId::from_raw(1), output_id: 1 } => [8, 1, 1]),
+        fixture!(UiRequest::AddVirtualOutput { width: 800, height: 600 } => [9, 160, 6, 216, 4]),
+        fixture!(UiRequest::RemoveOutput { output_id: 1 } => [10, 1]),
+        fixture!(UiRequest::CaptureScreen { output_id: 0 } => [11, 0]),
+        fixture!(UiRequest::GetStats => [12]),
+        fixture!(UiRequest::Metrics(MetricsRequest::Scrape) => [13, 0]),
+        fixture!(UiRequest::ShowNotification {
+            id: 3,
+            summary: "New mail".into(),
+            body: "A message arrived in Inbox.".into(),
+            critical: false,
+            actions: vec![NotificationButton { key: "open".into(), label: "Open".into() }],
+            events_chan: 21,
+        } => [14, 3, 8, 78, 101, 119, 32, 109, 97, 105, 108, 27, 65, 32, 109, 101, 115, 115, 97, 103, 101, 32, 97, 114, 114, 105, 118, 101, 100, 32, 105, 110, 32, 73, 110, 98, 111, 120, 46, 0, 1, 4, 111, 112, 101, 110, 4, 79, 112, 101, 110, 21]),
+        fixture!(UiRequest::HideNotification { id: 3 } => [15, 3]),
+        fixture!(UiRequest::SetCursor { window_id: WindowId::from_raw(1), shape: CursorShape::TextBeam } => [16, 1, 1]),
+        fixture!(UiRequest::StartDrag { window_id: WindowId::from_raw(1), mime: "text/plain".into(), data: DragData::Inline(vec![104, 105]) } => [17, 1, 10, 116, 101, 120, 116, 47, 112, 108, 97, 105, 110, 0, 2, 104, 105]),
+        fixture!(UiRequest::AcceptDrag { window_id: WindowId::from_raw(2), accept: true } => [18, 2, 1]),
+        fixture!(UiRequest::SetBackground { color_or_image_path: "#102030".into(), fit: WallpaperFit::Fill } => [19, 7, 35, 49, 48, 50, 48, 51, 48, 2]),
+        fixture!(UiRequest::GetDisplayInfo => [20]),
+        fixture!(UiRequest::SetDisplayMode { width: 800, height: 600 } => [21, 160, 6, 216, 4]),
+        fixture!(UiRequest::RegisterHotkey { modifiers: 4, keycode: 0x17, tag: 1, events_chan: 30 } => [22, 4, 23, 1, 30]),
+        fixture!(UiRequest::UnregisterHotkey { modifiers: 4, keycode: 0x17 } => [23, 4, 23]),
+        fixture!(UiRequest::ListHotkeys => [24]),
+        fixture!(UiRequest::CreateWindowWithEvents { title: "Terminal".into(), width: 640, height: 400, events_chan: 40 } => [25, 8, 84, 101, 114, 109, 105, 110, 97, 108, 128, 5, 144, 3, 40]),
+        // UiResponse
+        fixture!(UiResponse::Success { window_id: Some(WindowId::from_raw(1)) } => [0, 1, 1]),
+        fixture!(UiResponse::Windows(vec![window()]) => [1, 1, 1, 8, 84, 101, 114, 109, 105, 110, 97, 108, 20, 30, 128, 5, 144, 3, 20, 0]),
+        fixture!(UiResponse::Outputs(vec![output()]) => [2, 1, 1, 128, 8, 0, 160, 6, 216, 4, 1]),
+        fixture!(UiResponse::Output(output()) => [3, 1, 128, 8, 0, 160, 6, 216, 4, 1]),
+        fixture!(UiResponse::Screen { output_id: 0, width: 1, height: 1, pixels: vec![0, 0, 0, 255] } => [4, 0, 1, 1, 4, 0, 0, 0, 255]),
+        fixture!(UiResponse::Stats(CompositorStats {
+            latency: PipelineLatency::default(),
+            windows: vec![WindowLatency { window_id: WindowId::from_raw(1), title: "Terminal".into(), latency: PipelineLatency::default() }],
+        }) => [5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 8, 84, 101, 114, 109, 105, 110, 97, 108, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+        fixture!(UiResponse::Metrics(MetricsResponse::Metrics(samples())) => [6, 0, 2, 20, 118, 102, 115, 95, 99, 97, 99, 104, 101, 95, 104, 105, 116, 115, 95, 116, 111, 116, 97, 108, 11, 67, 97, 99, 104, 101, 32, 104, 105, 116, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 2, 2, 1, 10, 3, 3, 2, 1, 6, 25, 14, 118, 102, 115, 95, 111, 112, 101, 110, 95, 102, 105, 108, 101, 115, 11, 79, 112, 101, 110, 32, 102, 105, 108, 101, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 1, 3]),
+        fixture!(UiResponse::Error { message: "Window 9 not found.".into() } => [7, 19, 87, 105, 110, 100, 111, 119, 32, 57, 32, 110, 111, 116, 32, 102, 111, 117, 110, 100, 46]),
+        fixture!(UiResponse::DisplayInfo(display_info()) => [8, 128, 8, 128, 6, 1, 128, 8, 128, 6, 0, 7, 35, 50, 48, 50, 48, 51, 48, 0, 2, 150, 1]),
+        fixture!(UiResponse::NotSupported { message: "Mode switching is not supported.".into() } => [9, 32, 77, 111, 100, 101, 32, 115, 119, 105, 116, 99, 104, 105, 110, 103, 32, 105, 115, 32, 110, 111, 116, 32, 115, 117, 112, 112, 111, 114, 116, 101, 100, 46]),
+        fixture!(UiResponse::Hotkeys(vec![
+            HotkeyInfo { modifiers: 4, keycode: 0x2B, owner: "display-compositor".into(), action: HotkeyAction::SwitchWindow },
+            HotkeyInfo { modifiers: 4, keycode: 0x17, owner: "shell".into(), action: HotkeyAction::Client { tag: 1 } },
+        ]) => [10, 2, 4, 43, 18, 100, 105, 115, 112, 108, 97, 121, 45, 99, 111, 109, 112, 111, 115, 105, 116, 111, 114, 0, 4, 23, 5, 115, 104, 101, 108, 108, 2, 1]),
+        // UiEvent
+        fixture!(UiEvent::Mouse { window_id: WindowId::from_raw(1), x: 5, y: 6, button: 1, event_type: MouseEventType::Scroll, timing: timing() } => [0, 1, 5, 6, 1, 3, 100, 101, 103, 110]),
+        fixture!(UiEvent::Key { window_id: WindowId::from_raw(1), keycode: 8, event_type: KeyEventType::KeyDown, timing: timing(), text: Some("é".into()) } => [1, 1, 8, 0, 100, 101, 103, 110, 1, 2, 195, 169]),
+        fixture!(UiEvent::CloseRequested { window_id: WindowId::from_raw(1) } => [2, 1]),
+        fixture!(UiEvent::Resized { window_id: WindowId::from_raw(1), width: 800, height: 600 } => [3, 1, 160, 6, 216, 4]),
+        fixture!(UiEvent::NotificationClicked { id: 3, action: Some("open".into()) } => [4, 3, 1, 4, 111, 112, 101, 110]),
+        fixture!(UiEvent::DragOver { window_id: WindowId::from_raw(2), x: 5, y: 6, mime: "text/plain".into() } => [5, 2, 5, 6, 10, 116, 101, 120, 116, 47, 112, 108, 97, 105, 110]),
+        fixture!(UiEvent::DragLeave { window_id: WindowId::from_raw(2) } => [6, 2]),
+        fixture!(UiEvent::Drop { window_id: WindowId::from_raw(2), x: 5, y: 6, mime: "text/plain".into(), data: DragData::Token(7) } => [7, 2, 5, 6, 10, 116, 101, 120, 116, 47, 112, 108, 97, 105, 110, 1, 7]),
+        fixture!(UiEvent::DragEnded { window_id: WindowId::from_raw(1), dropped: false } => [8, 1, 0]),
+        fixture!(UiEvent::HotkeyPressed { tag: 1 } => [9, 1]),
+        // MailRequest and MailResponse
+        fixture!(MailRequest::SendMail { recipient: "bob@local".into(), subject: "Hi".into(), body: "Lunch?".into() } => [0, 9, 98, 111, 98, 64, 108, 111, 99, 97, 108, 2, 72, 105, 6, 76, 117, 110, 99, 104, 63]),
+        fixture!(MailRequest::ListMailboxes => [1]),
+        fixture!(MailRequest::ReadMessage { mailbox: "Inbox".into(), message_id: 4 } => [2, 5, 73, 110, 98, 111, 120, 4]),
+        fixture!(MailResponse::Success("Sent".into()) => [0, 4, 83, 101, 110, 116]),
+        fixture!(MailResponse::Mailboxes(vec!["Inbox".into(), "Sent".into()]) => [1, 2, 5, 73, 110, 98, 111, 120, 4, 83, 101, 110, 116]),
+        fixture!(MailResponse::Message("Subject: Hi".into()) => [2, 11, 83, 117, 98, 106, 101, 99, 116, 58, 32, 72, 105]),
+        fixture!(MailResponse::Error("Mailbox not found".into()) => [3, 17, 77, 97, 105, 108, 98, 111, 120, 32, 110, 111, 116, 32, 102, 111, 117, 110, 100]),
+        fixture!(MailResponse::Unauthenticated => [4]),
+        fixture!(MailResponse::UnknownRecipient("carol".into()) => [5, 5, 99, 97, 114, 111, 108]),
+        fixture!(MailRequest::Search { query: "from:bob".into(), offset: 0, limit: 20 } => [3, 8, 102, 114, 111, 109, 58, 98, 111, 98, 0, 20]),
+        fixture!(MailRequest::RebuildIndex { mailbox: Some("Inbox".into()) } => [4, 1, 5, 73, 110, 98, 111, 120]),
+        fixture!(MailRequest::DeleteMessage { mailbox: "Inbox".into(), message_id: 4 } => [5, 5, 73, 110, 98, 111, 120, 4]),
+        fixture!(MailResponse::SearchResults {
+            total: 1,
+            hits: vec![SearchHit { mailbox: "Inbox".into(), message_id: 4, date: 1_792_207_587, from: "bob@local".into(), subject: "Hi".into(), snippet: "Lunch?".into() }],
+        } => [6, 1, 1, 5, 73, 110, 98, 111, 120, 4, 227, 213, 203, 214, 6, 9, 98, 111, 98, 64, 108, 111, 99, 97, 108, 2, 72, 105, 6, 76, 117, 110, 99, 104, 63]),
+        fixture!(MailResponse::BadQuery { position: 10, message: "bad date".into() } => [7, 10, 8, 98, 97, 100, 32, 100, 97, 116, 101]),
+        // InferRequest and InferResponse
+        fixture!(InferRequest::ImageClassification { model_id: "mobilenet".into(), image_data: vec![137, 80, 78, 71] } => [0, 9, 109, 111, 98, 105, 108, 101, 110, 101, 116, 4, 137, 80, 78, 71]),
+        fixture!(InferRequest::TextGeneration { model_id: "tiny-lm".into(), prompt: "Hello".into(), max_tokens: 32 } => [1, 7, 116, 105, 110, 121, 45, 108, 109, 5, 72, 101, 108, 108, 111, 32]),
+        fixture!(InferRequest::Batch { requests: vec![InferRequest::TextGeneration { model_id: "tiny-lm".into(), prompt: "Hi".into(), max_tokens: 4 }] } => [2, 1, 1, 7, 116, 105, 110, 121, 45, 108, 109, 2, 72, 105, 4]),
+        fixture!(InferRequest::Metrics(MetricsRequest::Scrape) => [3, 0]),
+        fixture!(InferResponse::ImageClassificationResult { class_labels: vec!["cat".into(), "dog".into()], probabilities: vec![0.75, 0.25] } => [0, 2, 3, 99, 97, 116, 3, 100, 111, 103, 2, 0, 0, 64, 63, 0, 0, 128, 62]),
+        fixture!(InferResponse::TextGenerationResult { generated_text: "Hello there".into() } => [1, 11, 72, 101, 108, 108, 111, 32, 116, 104, 101, 114, 101]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::MaxImageBytes { limit: 1024, actual: 2048 } } => [2, 0, 128, 8, 128, 16]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::MaxPromptChars { limit: 100, actual: 120 } } => [2, 1, 100, 120]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::MaxTokens { limit: 256, requested: 0 } } => [2, 2, 128, 2, 0]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::UnsupportedInput } => [2, 3]),
+        fixture!(InferResponse::Error { message: "Model not loaded".into() } => [3, 16, 77, 111, 100, 101, 108, 32, 110, 111, 116, 32, 108, 111, 97, 100, 101, 100]),
+        fixture!(InferResponse::BatchResult { results: vec![InferResponse::TextGenerationResult { generated_text: "ok".into() }, InferResponse::InvalidInput { constraint: InputConstraint::UnsupportedInput }] } => [4, 2, 1, 2, 111, 107, 2, 3]),
+        fixture!(InferResponse::Busy { in_flight: 4, limit: 4 } => [5, 4, 4]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::BatchSize { limit: 32, actual: 40 } } => [2, 4, 32, 40]),
+        fixture!(InferResponse::InvalidInput { constraint: InputConstraint::MixedBatch } => [2, 5]),
+        fixture!(InferResponse::Metrics(MetricsResponse::Metrics(samples())) => [6, 0, 2, 20, 118, 102, 115, 95, 99, 97, 99, 104, 101, 95, 104, 105, 116, 115, 95, 116, 111, 116, 97, 108, 11, 67, 97, 99, 104, 101, 32, 104, 105, 116, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 2, 2, 1, 10, 3, 3, 2, 1, 6, 25, 14, 118, 102, 115, 95, 111, 112, 101, 110, 95, 102, 105, 108, 101, 115, 11, 79, 112, 101, 110, 32, 102, 105, 108, 101, 115, 46, 1, 7, 115, 101, 114, 118, 105, 99, 101, 3, 118, 102, 115, 1, 3]),
+        // LifecycleRequest and LifecycleResponse
+        fixture!(LifecycleRequest::Shutdown { reboot: false } => [0, 0]),
+        fixture!(LifecycleResponse::Stopped => [0]),
+        fixture!(LifecycleRequest::Heartbeat => [1]),
+        fixture!(LifecycleResponse::Alive => [1]),
+        // aethersh frames, over TCP rather than IPC
+        fixture!(ClientFrame::Auth { aid: [7; 32], proof: vec![1, 2] } => [0, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 2, 1, 2]),
+        fixture!(ClientFrame::Line { line: "ls".into() } => [1, 2, 108, 115]),
+        fixture!(ClientFrame::Answer { text: "y".into() } => [2, 1, 121]),
+        fixture!(ClientFrame::Bye => [3]),
+        fixture!(ServerFrame::Challenge { version: 1, nonce: [7; 32] } => [0, 1, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7]),
+        fixture!(ServerFrame::Authenticated { cwd: "/".into() } => [1, 1, 47]),
+        fixture!(ServerFrame::Refused { reason: "no".into() } => [2, 2, 110, 111]),
+        fixture!(ServerFrame::Output { stdout: "ok\n".into(), stderr: "".into(), exit_code: 1 } => [3, 3, 111, 107, 10, 0, 2]),
+        fixture!(ServerFrame::Prompt { message: "?".into() } => [4, 1, 63]),
+        fixture!(ServerFrame::Closed { reason: "idle".into() } => [5, 4, 105, 100, 108, 101]),
+        // Envelope
+        fixture!(Envelope::request(7, Some(Ticks::from_raw(500)), VfsRequest::Stat { path: "/home".into() }) => [3, 7, 1, 244, 3, 0, 1, 4, 5, 47, 104, 111, 109, 101]),
+        fixture!(Envelope::reply(7, VfsResponse::Success(0)) => [3, 7, 0, 0, 1, 0, 0]),
+        fixture!(Envelope::<VfsRequest>::cancel(7) => [3, 7, 0, 1, 0]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One line per fixture that fails, with the bytes to compare.
+    fn describe(fixture: &Fixture) -> String {
+        match &fixture.decode_error {
+            Some(error) => format!("{}: golden bytes {}", fixture.name, error),
+            None => format!("{}: encodes to {} instead of {:?}", fixture.name, fixture.regenerated(), fixture.golden),
+        }
+    }
+
+    #[test]
+    fn every_fixture_matches_its_golden_bytes() {
+        let fixtures = fixtures();
+        assert!(!fixtures.is_empty());
+        let failed: Vec<String> = fixtures.iter().filter(|fixture| !fixture.passed()).map(describe).collect();
+        assert!(failed.is_empty(), "{} of {} fixtures changed:\n{}", failed.len(), fixtures.len(), failed.join("\n"));
+    }
+
+    #[test]
+    fn a_changed_encoding_fails_both_ways() {
+        let moved = Fixture::new("LifecycleRequest::Heartbeat", LifecycleRequest::Heartbeat, &[0]);
+        assert!(!moved.passed());
+        assert_eq!(moved.regenerated(), "[1]");
+        assert!(moved.decode_error.is_some());
+        let truncated = Fixture::new("ClientFrame::Line", ClientFrame::Line { line: "ls".into() }, &[1, 2, 108]);
+        assert!(!truncated.passed());
+        assert!(truncated.decode_error.is_some());
+    }
+}
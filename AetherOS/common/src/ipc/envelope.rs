@@ -0,0 +1,216 @@
+// common/src/ipc/envelope.rs
+
+#![no_std]
+
+//! Request envelopes with deadlines and cancellation, for request chains such
+//! as shell -> file-manager -> VFS.
+//!
+//! A client wraps each request in an `Envelope` with a fresh `RequestId` and,
+//! optionally, the tick by which it needs the answer. If it gives up, because
+//! the user cancelled or the deadline passed, it sends a cancel envelope with
+//! the same id and stops waiting. The service checks for that between the
+//! steps of a long operation (`Inbox::check`), stops, cancels whatever it asked
+//! of its own downstream services, rolls back what it can, and answers with its
+//! protocol's `Cancelled` response.
+//!
+//! ```ignore
+//! let id = envelope::next_request_id();
+//! match envelope::call(&mut chan, id, Some(time::ticks() + Ticks::from_secs(30)), &request, || user_pressed_ctrl_c()) {
+//!     Ok(response) => ...,
+//!     Err(RequestError::Cancelled) | Err(RequestError::DeadlineExceeded) => ...,
+//!     Err(RequestError::Ipc) => ...,
+//! }
+//! ```
+
+extern crate alloc;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::abi::IpcCreds;
+use crate::ids::{TaskId, Ticks};
+use crate::ipc::server::ServerChannel;
+use crate::ipc::vnode::{self, VNodeChannel};
+use crate::ipc::IpcSend;
+use crate::time;
+
+/// Bumped when the envelope's fields change, or when a protocol carried in
+/// envelopes changes incompatibly (see `compat`); a service drops envelopes
+/// of another version.
+pub const ENVELOPE_VERSION: u8 = 3;
+
+/// Identifies a request and its reply, and a cancel for it. Unique per client task.
+pub type RequestId = u64;
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_request_id() -> RequestId {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub version: u8,
+    pub request_id: RequestId,
+    /// Tick by which the client needs the answer; the service may give up after it.
+    pub deadline_ticks: Option<Ticks>,
+    /// Set on the message that cancels `request_id`; `body` is `None` then.
+    pub cancel: bool,
+    /// The request, or in a reply, the response.
+    pub body: Option<T>,
+}
+
+impl<T> Envelope<T> {
+    pub fn request(request_id: RequestId, deadline_ticks: Option<Ticks>, body: T) -> Self {
+        Self { version: ENVELOPE_VERSION, request_id, deadline_ticks, cancel: false, body: Some(body) }
+    }
+
+    pub fn reply(request_id: RequestId, body: T) -> Self {
+        Self { version: ENVELOPE_VERSION, request_id, deadline_ticks: None, cancel: false, body: Some(body) }
+    }
+
+    pub fn cancel(request_id: RequestId) -> Self {
+        Self { version: ENVELOPE_VERSION, request_id, deadline_ticks: None, cancel: true, body: None }
+    }
+
+    pub fn expired(&self, now: Ticks) -> bool {
+        self.deadline_ticks.map_or(false, |deadline| now >= deadline)
+    }
+}
+
+/// Why `call` returned without a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    /// The caller gave up; the service was told to stop.
+    Cancelled,
+    /// The deadline passed; the service was told to stop.
+    DeadlineExceeded,
+    /// The request couldn't be sent or the reply couldn't be read.
+    Ipc,
+}
+
+/// Sends `request` and waits for its reply, polling `give_up` while waiting.
+/// If `give_up` returns true or `deadline_ticks` passes, a cancel goes to the
+/// service and any late reply is ignored by the next call.
+pub fn call<Req: Serialize, Resp: DeserializeOwned>(
+    chan: &mut VNodeChannel,
+    request_id: RequestId,
+    deadline_ticks: Option<Ticks>,
+    request: &Req,
+    give_up: impl FnMut() -> bool,
+) -> Result<Resp, RequestError> {
+    vnode::count_round_trip();
+    chan.send(&Envelope::request(request_id, deadline_ticks, request)).map_err(|_| RequestError::Ipc)?;
+    next_reply::<Req, Resp>(chan, request_id, deadline_ticks, give_up)
+}
+
+/// Waits for another reply to `request_id`, for services that answer one
+/// request with several replies, like the File Manager's content search.
+/// Gives up like `call`.
+pub fn next_reply<Req: Serialize, Resp: DeserializeOwned>(
+    chan: &mut VNodeChannel,
+    request_id: RequestId,
+    deadline_ticks: Option<Ticks>,
+    mut give_up: impl FnMut() -> bool,
+) -> Result<Resp, RequestError> {
+    loop {
+        if let Some(data) = chan.recv_non_blocking().map_err(|_| RequestError::Ipc)? {
+            match postcard::from_bytes::<Envelope<Resp>>(&data) {
+                Ok(Envelope { request_id: id, body: Some(response), .. }) if id == request_id => return Ok(response),
+                // A late reply to an earlier, abandoned request.
+                Ok(_) => continue,
+                Err(_) => return Err(RequestError::Ipc),
+            }
+        }
+        let error = if give_up() {
+            RequestError::Cancelled
+        } else if deadline_ticks.map_or(false, |deadline| time::ticks() >= deadline) {
+            RequestError::DeadlineExceeded
+        } else {
+            continue;
+        };
+        let _ = chan.send(&Envelope::<Req>::cancel(request_id));
+        return Err(error);
+    }
+}
+
+/// A service's view of its client channel while it works on a request: it
+/// notices cancels and sets everything else aside for later.
+pub struct Inbox {
+    waiting: VecDeque<(Option<IpcCreds>, Vec<u8>)>, // Sender credentials and message
+    cancelled: BTreeSet<RequestId>,
+    creds: Option<IpcCreds>,
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Self { waiting: VecDeque::new(), cancelled: BTreeSet::new(), creds: None }
+    }
+
+    /// The credentials of the envelope `next` returned last. A request that
+    /// was set aside keeps its own, so use this rather than
+    /// `session_ipc::last_creds`.
+    pub fn creds(&self) -> Option<IpcCreds> {
+        self.creds
+    }
+
+    /// The task that sent the envelope `next` returned last.
+    pub fn sender(&self) -> Option<TaskId> {
+        self.creds.map(|creds| TaskId::from_raw(creds.sender))
+    }
+
+    /// The next request: one set aside earlier, or a new one. A request is
+    /// always sent before its cancel, so a cancel read here, or remembered
+    /// once nothing is set aside, is for a request that already finished and
+    /// is dropped.
+    pub fn next<T: DeserializeOwned>(&mut self, chan: &mut dyn ServerChannel) -> Option<Envelope<T>> {
+        loop {
+            let (creds, data) = match self.waiting.pop_front() {
+                Some(waiting) => waiting,
+                None => {
+                    self.cancelled.clear();
+                    let message = chan.next_message().ok()??;
+                    (message.creds, message.data)
+                },
+            };
+            match postcard::from_bytes::<Envelope<T>>(&data) {
+                Ok(envelope) if envelope.version == ENVELOPE_VERSION && !envelope.cancel => {
+                    // Cancelled before it was even started.
+                    if self.cancelled.remove(&envelope.request_id) {
+                        continue;
+                    }
+                    self.creds = creds;
+                    return Some(envelope);
+                },
+                _ => continue,
+            }
+        }
+    }
+
+    /// True if the request should stop: it was cancelled or its deadline has
+    /// passed. Call it between the steps of a long operation.
+    pub fn check(&mut self, chan: &mut dyn ServerChannel, request_id: RequestId, deadline_ticks: Option<Ticks>) -> bool {
+        while let Ok(Some(message)) = chan.next_message() {
+            match postcard::from_bytes::<Header>(&message.data) {
+                Ok(header) if header.cancel => {
+                    self.cancelled.insert(header.request_id);
+                },
+                _ => self.waiting.push_back((message.creds, message.data)),
+            }
+        }
+        self.cancelled.remove(&request_id) || deadline_ticks.map_or(false, |deadline| time::ticks() >= deadline)
+    }
+}
+
+/// The fields of an `Envelope` ahead of the body, read without knowing its type.
+#[derive(Deserialize)]
+struct Header {
+    #[allow(dead_code)]
+    version: u8,
+    request_id: RequestId,
+    #[allow(dead_code)]
+    deadline_ticks: Option<Ticks>,
+    cancel: bool,
+}
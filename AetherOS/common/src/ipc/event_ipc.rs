@@ -0,0 +1,95 @@
+// common/src/ipc/event_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+/// Represents requests from V-Nodes to the event-bus V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EventBusRequest {
+    /// Subscribe to every topic starting with `topic_prefix` (e.g., "settings.").
+    /// Matching events are delivered as `Event` messages on `reply_chan`.
+    Subscribe { topic_prefix: String, reply_chan: u32 },
+    /// Remove a subscription previously made with the same prefix and channel.
+    Unsubscribe { topic_prefix: String, reply_chan: u32 },
+    /// Publish an event to all matching subscribers.
+    Publish { topic: String, payload: Vec<u8> },
+    /// Keep the events published on topics starting with `topic_prefix`, up
+    /// to `max_events` of them and, unless 0, for `max_age_secs`. With
+    /// `persistent` they are kept in the VFS across restarts of the bus.
+    /// Declaring a prefix again changes its retention. A prefix can't
+    /// overlap another durable one.
+    DeclareDurable { topic_prefix: String, max_events: u32, max_age_secs: u64, persistent: bool },
+    /// Like `Subscribe`, on a prefix within a durable topic: the retained
+    /// events from `from` on are delivered first, in order, then live ones,
+    /// as `DurableDelivery` messages. Subscribing again with the same prefix
+    /// and channel starts over from `from`.
+    SubscribeDurable { topic_prefix: String, reply_chan: u32, from: ReplayFrom },
+}
+
+/// Represents responses from the event-bus V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EventBusResponse {
+    /// The request was accepted. For `Publish`, carries the number of subscribers notified.
+    Success(u32),
+    /// Indicates an error occurred.
+    Error(String),
+    /// Answers `SubscribeDurable`: `replay` retained events match and come
+    /// first. `head` is the durable topic's newest event at the time.
+    Subscribed { head: Cursor, replay: u32 },
+    /// Answers `SubscribeDurable` with `ReplayFrom::After` when events after
+    /// the cursor were trimmed, or the cursor is from an earlier life of the
+    /// log. Nothing was subscribed. `oldest` is where the retained events
+    /// start; subscribing after it replays all of them.
+    CursorExpired { oldest: Cursor },
+}
+
+/// An event delivered to a subscriber's channel.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    pub topic: String,
+    pub payload: Vec<u8>, // Topic-specific, usually a postcard-encoded struct
+}
+
+/// Where a durable subscription starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayFrom {
+    /// Every retained event.
+    Beginning,
+    /// The retained events published at or after this time, in epoch seconds.
+    Since(u64),
+    /// The events after one the subscriber already has, from a cursor it saved.
+    After(Cursor),
+    /// Live events only, like `Subscribe`.
+    Now,
+}
+
+/// A position in a durable topic's log: the event with sequence number
+/// `seq`, 0 before the first. Subscribers save the last one they handled and
+/// resume `After` it. `epoch` changes when the log starts over, e.g. a
+/// non-persistent one when the bus restarts, so an old cursor can't point
+/// at different events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// The durable topic's prefix.
+    pub topic: String,
+    pub epoch: u64,
+    pub seq: u64,
+}
+
+/// What a durable subscription delivers on its `reply_chan`, instead of `Event`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DurableDelivery {
+    /// A retained or live event. `cursor` is its position.
+    Event { cursor: Cursor, topic: String, payload: Vec<u8> },
+    /// The subscriber fell behind and `missed` events of the log were
+    /// trimmed before it got them. Delivery goes on after `resume`.
+    Gap { missed: u64, resume: Cursor },
+    /// Every retained event has been delivered; the ones that follow are
+    /// live. `cursor` is the log's newest event, also when it didn't match.
+    Live { cursor: Cursor },
+}
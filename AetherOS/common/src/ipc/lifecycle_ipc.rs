@@ -0,0 +1,122 @@
+// common/src/ipc/lifecycle_ipc.rs
+
+#![no_std]
+
+//! Orderly shutdown of services.
+//!
+//! Init gives every instance it starts a lifecycle channel, under
+//! `LIFECYCLE_CHANNEL` in its startup info. When the system shuts down, init
+//! sends each service that takes part a `Shutdown` in an envelope whose
+//! deadline is the service's stop timeout. The service finishes or abandons
+//! what it is doing, writes out whatever it only holds in memory, answers
+//! `Stopped` and handles nothing after that. Init stops a service that
+//! doesn't answer in time anyway, so a service must not count on the answer
+//! being waited for.
+//!
+//! A supervisor can also send `Heartbeat` to see that a service's run loop
+//! still turns; `Lifecycle` answers it with `Alive` without involving the
+//! service.
+//!
+//! A service checks for the request once per pass of its run loop:
+//!
+//! ```ignore
+//! let mut lifecycle = Lifecycle::new(channels.get(LIFECYCLE_CHANNEL).copied());
+//! loop {
+//!     ...
+//!     if let Some(request) = lifecycle.shutdown_requested() {
+//!         self.flush();
+//!         lifecycle.stopped(request);
+//!     }
+//! }
+//! ```
+//!
+//! Services built on `server::serve_loop` get all of this from the loop.
+
+extern crate alloc;
+use alloc::boxed::Box;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ids::Ticks;
+use crate::ipc::envelope::{Envelope, Inbox, RequestId};
+use crate::ipc::server::ServerChannel;
+use crate::ipc::vnode::VNodeChannel;
+
+/// Key of the lifecycle channel in `StartupInfo::assigned_channels`.
+pub const LIFECYCLE_CHANNEL: &str = "lifecycle";
+
+/// What init asks of a service, in an envelope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LifecycleRequest {
+    /// Stop for a system shutdown or reboot, by the envelope's deadline.
+    Shutdown { reboot: bool },
+    /// Answer `Alive` if the run loop is still turning.
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LifecycleResponse {
+    /// Everything is written out; the service handles no more requests.
+    Stopped,
+    /// Answers `Heartbeat`.
+    Alive,
+}
+
+/// A shutdown request a service has received and not yet answered.
+#[derive(Debug, Clone, Copy)]
+pub struct ShutdownRequest {
+    pub request_id: RequestId,
+    /// Tick by which init wants the answer.
+    pub deadline_ticks: Option<Ticks>,
+    pub reboot: bool,
+}
+
+/// A service's end of its lifecycle channel.
+pub struct Lifecycle {
+    chan: Option<Box<dyn ServerChannel>>,
+    inbox: Inbox,
+}
+
+impl Lifecycle {
+    /// `channel` is the lifecycle channel from the startup info. A service
+    /// started without one never gets a request.
+    pub fn new(channel: Option<u32>) -> Self {
+        Self { chan: channel.map(|id| Box::new(VNodeChannel::new(id)) as Box<dyn ServerChannel>), inbox: Inbox::new() }
+    }
+
+    /// A lifecycle channel that isn't a kernel channel, from the host harness.
+    pub fn on(chan: Box<dyn ServerChannel>) -> Self {
+        Self { chan: Some(chan), inbox: Inbox::new() }
+    }
+
+    /// The shutdown request waiting on the channel, if there is one. Never
+    /// blocks. Heartbeats waiting ahead of it are answered on the way.
+    pub fn shutdown_requested(&mut self) -> Option<ShutdownRequest> {
+        let chan = self.chan.as_mut()?;
+        loop {
+            let envelope = self.inbox.next::<LifecycleRequest>(chan.as_mut())?;
+            match envelope.body? {
+                LifecycleRequest::Shutdown { reboot } => {
+                    return Some(ShutdownRequest { request_id: envelope.request_id, deadline_ticks: envelope.deadline_ticks, reboot });
+                },
+                LifecycleRequest::Heartbeat => answer(chan.as_mut(), envelope.request_id, LifecycleResponse::Alive),
+            }
+        }
+    }
+
+    /// Answers `request` and waits for the task to be stopped.
+    pub fn stopped(&mut self, request: ShutdownRequest) -> ! {
+        let chan = self.chan.as_mut().expect("the request came from this channel");
+        answer(chan.as_mut(), request.request_id, LifecycleResponse::Stopped);
+        loop {
+            // Anything more on the channel is too late to matter.
+            let _ = chan.wait_message();
+        }
+    }
+}
+
+fn answer(chan: &mut dyn ServerChannel, request_id: RequestId, response: LifecycleResponse) {
+    if let Ok(bytes) = postcard::to_allocvec(&Envelope::reply(request_id, response)) {
+        let _ = chan.answer(None, &bytes);
+    }
+}
@@ -0,0 +1,76 @@
+// common/src/ipc/log_ipc.rs
+
+#![no_std]
+
+//! Where logd keeps per-service logs, shared by logd and whatever reads them.
+//!
+//! logd receives every `SYS_LOG` message through `SYS_LOG_FORWARD` and appends
+//! it to `/data/log/<service>.log`, one `[secs.ms] LEVEL message` line each.
+//! A file that would grow past `log.max_file_kb` is rotated first: `.log`
+//! becomes `.log.1`, `.log.1` becomes `.log.2` and so on, and the oldest past
+//! `log.keep_files` is deleted.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::abi::{LOG_SEVERITY_DEBUG, LOG_SEVERITY_DEFAULT, LOG_SEVERITY_ERROR, LOG_SEVERITY_INFO, LOG_SEVERITY_WARN};
+
+/// Directory holding the log files.
+pub const LOG_DIR: &str = "/data/log";
+
+/// Lowest severity written, unless overridden for the service.
+pub const FLOOR_KEY: &str = "log.floor";
+/// Per-service floors, as `<service>=<level>,...`.
+pub const FLOOR_OVERRIDES_KEY: &str = "log.floor_overrides";
+/// Size at which a log file is rotated, in KiB.
+pub const MAX_FILE_KB_KEY: &str = "log.max_file_kb";
+/// Rotated files kept per service.
+pub const KEEP_FILES_KEY: &str = "log.keep_files";
+
+/// The file name part for `service`: its task name with anything but
+/// letters, digits, `.`, `_` and `-` replaced by `_`. A task without a name
+/// logs as `task-<id>`.
+pub fn sanitize_service_name(name: &str, task: u64) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.chars().all(|c| c == '.') {
+        format!("task-{}", task)
+    } else {
+        sanitized
+    }
+}
+
+/// The live log file of `service`, which must already be sanitized.
+pub fn log_file_path(service: &str) -> String {
+    format!("{}/{}.log", LOG_DIR, service)
+}
+
+/// The `n`th rotated file of `service`; 1 is the most recent.
+pub fn rotated_path(service: &str, n: u32) -> String {
+    format!("{}/{}.log.{}", LOG_DIR, service, n)
+}
+
+/// `debug`, `info`, `warn` or `error`, as in the settings and log lines.
+pub fn parse_severity(name: &str) -> Option<u64> {
+    match name {
+        "debug" => Some(LOG_SEVERITY_DEBUG),
+        "info" => Some(LOG_SEVERITY_INFO),
+        "warn" => Some(LOG_SEVERITY_WARN),
+        "error" => Some(LOG_SEVERITY_ERROR),
+        _ => None,
+    }
+}
+
+/// The level column of a log line. Messages logged without a severity are Info.
+pub fn severity_label(severity: u64) -> &'static str {
+    match severity {
+        LOG_SEVERITY_DEBUG => "DEBUG",
+        LOG_SEVERITY_DEFAULT | LOG_SEVERITY_INFO => "INFO",
+        LOG_SEVERITY_WARN => "WARN",
+        _ => "ERROR",
+    }
+}
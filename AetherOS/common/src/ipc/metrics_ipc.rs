@@ -0,0 +1,42 @@
+// common/src/ipc/metrics_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+/// Sent to a service wrapped in its own request enum (e.g. `NetStackRequest::Metrics`).
+/// Services answer it with `common::metrics::Registry::handle`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetricsRequest {
+    /// Every registered metric with its current value.
+    Scrape,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetricsResponse {
+    Metrics(Vec<MetricSample>),
+}
+
+/// One metric as reported by a scrape. The first label is always `service`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricSample {
+    pub name: String,
+    pub help: String,
+    pub labels: Vec<(String, String)>,
+    pub value: MetricValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetricValue {
+    /// Only ever increases.
+    Counter(u64),
+    /// Goes up and down, e.g. open sockets.
+    Gauge(i64),
+    /// `buckets[i]` counts observations <= `bounds[i]` (not cumulative); the
+    /// last bucket counts those above every bound.
+    Histogram { bounds: Vec<u64>, buckets: Vec<u64>, count: u64, sum: u64 },
+}
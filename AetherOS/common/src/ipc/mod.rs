@@ -0,0 +1,60 @@
+// common/src/ipc/mod.rs
+
+//! The IPC protocols between V-Nodes, and the channel they travel over.
+//!
+//! Most protocols live next to this file; the ones under `AetherOS/src/ipc`
+//! are declared from there.
+
+pub mod vnode; // VNodeChannel, the kernel channel every protocol below travels over
+pub mod envelope; // Request IDs, deadlines and cancellation around any request
+pub mod server; // The shared service loop
+pub mod lifecycle_ipc;
+pub mod compat; // Golden postcard fixtures for every protocol
+
+pub mod aethersh_ipc;
+pub mod audio_ipc;
+pub mod block_ipc;
+pub mod event_ipc;
+pub mod log_ipc;
+pub mod metrics_ipc;
+pub mod notification_ipc;
+pub mod registry_ipc;
+pub mod session_ipc;
+pub mod settings_ipc;
+pub mod sysmon_ipc;
+pub mod ui_protocol;
+pub mod vfs_ipc;
+pub mod vfs_lock;
+pub mod vfs_stream;
+pub mod vfs_tx;
+
+#[path = "../../../src/ipc/dns_ipc.rs"]
+pub mod dns_ipc;
+#[path = "../../../src/ipc/file_manager_ipc.rs"]
+pub mod file_manager_ipc;
+#[path = "../../../src/ipc/init_ipc.rs"]
+pub mod init_ipc;
+#[path = "../../../src/ipc/mail_ipc.rs"]
+pub mod mail_ipc;
+#[path = "../../../src/ipc/model_runtime_ipc.rs"]
+pub mod model_runtime_ipc;
+#[path = "../../../src/ipc/net_ipc.rs"]
+pub mod net_ipc;
+#[path = "../../../src/ipc/shell_ipc.rs"]
+pub mod shell_ipc;
+#[path = "../../../src/ipc/socket_client.rs"]
+pub mod socket_client;
+#[path = "../../../src/ipc/socket_ipc.rs"]
+pub mod socket_ipc;
+
+/// Sending a message on a channel, serialized with postcard.
+pub trait IpcSend {
+    fn send_raw(&mut self, bytes: &[u8]) -> Result<(), ()>;
+    fn send<T: serde::Serialize>(&mut self, msg: &T) -> Result<(), ()>;
+}
+
+/// Receiving a message from a channel without blocking; `None` when nothing
+/// is waiting or the message doesn't decode.
+pub trait IpcRecv {
+    fn recv<T: serde::de::DeserializeOwned>(&mut self) -> Option<T>;
+}
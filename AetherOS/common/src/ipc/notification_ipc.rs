@@ -0,0 +1,88 @@
+// common/src/ipc/notification_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a notification. Not reused while the notifications service runs.
+pub type NotificationId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Urgency {
+    Low,
+    Normal,
+    /// Shown even with do-not-disturb on, and stays until clicked or dismissed.
+    Critical,
+}
+
+/// A button on a notification. Clicking it sends `NotificationEvent::ActionInvoked`
+/// with `key` to the notification's `reply_chan`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationAction {
+    pub key: String,
+    pub label: String,
+}
+
+/// Represents requests from client V-Nodes to the notifications V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NotificationRequest {
+    /// Show a notification. It goes away after `timeout_ms`, or
+    /// `DEFAULT_TIMEOUT_MS` if 0, unless it is `Critical`. Events for it are
+    /// sent on `reply_chan`, if given.
+    Notify {
+        summary: String,
+        body: String,
+        urgency: Urgency,
+        timeout_ms: u32,
+        actions: Vec<NotificationAction>,
+        reply_chan: Option<u32>,
+    },
+    /// Take a notification off the screen. It stays in the history.
+    Dismiss { id: NotificationId },
+    /// The latest `limit` notifications, newest first, whether they were shown or not.
+    ListRecent { limit: u32 },
+}
+
+/// Represents responses from the notifications V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NotificationResponse {
+    /// Answers `Notify`. `shown` is false if do-not-disturb held it back.
+    Posted { id: NotificationId, shown: bool },
+    Success,
+    /// Answers `ListRecent`.
+    Recent(Vec<NotificationRecord>),
+    Error(String),
+}
+
+/// Sent to a notification's `reply_chan`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NotificationEvent {
+    /// The user clicked the action button with `key`.
+    ActionInvoked { id: NotificationId, action: String },
+}
+
+/// A notification as kept in the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationRecord {
+    pub id: NotificationId,
+    /// The event-bus topic it was made from, e.g. "mail.received", or
+    /// "task <id>" for one an app posted.
+    pub source: String,
+    pub summary: String,
+    pub body: String,
+    pub urgency: Urgency,
+    /// Unix timestamp, or 0 if the clock was unavailable.
+    pub posted_at: u64,
+    /// False if do-not-disturb kept it off the screen.
+    pub shown: bool,
+}
+
+/// How long a notification stays up when `Notify` gives a `timeout_ms` of 0.
+pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
+
+/// Setting that holds back every notification except `Critical` ones.
+pub const DO_NOT_DISTURB_KEY: &str = "notifications.do_not_disturb";
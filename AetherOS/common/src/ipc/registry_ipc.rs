@@ -0,0 +1,263 @@
+// common/src/ipc/registry_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capability::DeclaredCapabilities;
+use crate::cid::Cid;
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ipc::session_ipc::AidBytes;
+
+/// What the user decided about a package from an untrusted publisher.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum InstallDecision {
+    Proceed,
+    Cancel,
+}
+
+/// Represents requests from client V-Nodes (e.g., the shell) to the Registry V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RegistryRequest {
+    /// Fetch and install a package by name.
+    Install { package_name: String },
+    /// Answer a `ConfirmationRequired` response. Only the task that sent the
+    /// `Install` may answer. With `remember`, a `Proceed` also adds the
+    /// publisher to the trusted publishers list.
+    ConfirmInstall { ticket: u64, decision: InstallDecision, remember: bool },
+    /// Swarm traffic: limits, rates and per-peer counters.
+    SwarmStats,
+    /// Find packages by name, tag or description: in the local catalog and,
+    /// unless `local_only`, in the catalogs of the nearest peers.
+    Search { query: String, limit: u32, local_only: bool },
+    /// Write the named installed packages, with all their chunks, to one
+    /// bundle file (see `common::bundle`).
+    Export { names: Vec<String>, dest_path: String },
+    /// Verify a bundle and, unless `verify_only`, install every package in it
+    /// without using the network. Packages from untrusted publishers go
+    /// through `ConfirmationRequired` like a normal install.
+    ImportBundle { path: String, verify_only: bool },
+    Metrics(MetricsRequest),
+    /// Check the package store for files no install accounts for.
+    Verify,
+    /// Answer a `ReviewRequired` response like `ConfirmInstall`, leaving out
+    /// the grants at the positions in `denied` (into its `grants`). The
+    /// package is installed with the rest.
+    ConfirmGrants { ticket: u64, decision: InstallDecision, remember: bool, denied: Vec<u32> },
+    /// What this node knows about how retrievable a catalog package is.
+    /// Asked before `Install`, to warn about a package nobody seems to seed.
+    PackageHealth { package_name: String },
+}
+
+/// Represents responses from the Registry V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RegistryResponse {
+    /// The package was installed.
+    Installed { package_name: String },
+    /// The package is validly signed by a publisher that isn't trusted yet. The
+    /// downloaded data is held until the ticket is answered or expires.
+    ConfirmationRequired { ticket: u64, publisher_aid: AidBytes, package_name: String, fingerprint: String },
+    /// The install was cancelled and the downloaded data discarded.
+    Cancelled { package_name: String },
+    /// The ticket is unknown, expired, or was issued to another task.
+    InvalidTicket { ticket: u64 },
+    SwarmStats(SwarmStats),
+    /// Best match first. Peers that didn't answer before the deadline are
+    /// counted in `peers_asked` but not in `peers_answered`.
+    SearchResults { results: Vec<SearchResult>, peers_asked: u32, peers_answered: u32 },
+    Exported { path: String, packages: u32, chunks: u32, bytes: u64 },
+    /// The bundle checked out. One result per package, in bundle order.
+    Imported { results: Vec<ImportResult> },
+    /// The bundle failed verification; nothing was installed.
+    InvalidBundle { path: String, problem: BundleProblem },
+    Metrics(MetricsResponse),
+    /// Indicates an error occurred.
+    Error(String),
+    /// Answers `Verify`. `orphans` are the paths of files in the package store
+    /// that no install stamped with their package name.
+    Verified { packages: u32, orphans: Vec<String> },
+    /// The package asks for capabilities the user hasn't approved for it:
+    /// on its first install, or because it asks for more than last time.
+    /// `grants` are shown as they read ("StorageAccess limited to
+    /// /data/app-x") and `syscalls` by name. Answer with `ConfirmGrants`, or
+    /// `ConfirmInstall` to approve all of them. `publisher_trusted` says
+    /// whether the publisher question is settled already.
+    ReviewRequired { ticket: u64, publisher_aid: AidBytes, package_name: String, fingerprint: String, publisher_trusted: bool, grants: Vec<String>, syscalls: Vec<String> },
+    /// Answers `PackageHealth`.
+    PackageHealth { package_name: String, health: PackageHealth },
+}
+
+/// Chunk traffic with one peer since the registry started. Rates are bytes
+/// per second averaged over the last minute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerTraffic {
+    pub addr: [u8; 4],
+    pub port: u16,
+    pub bytes_served: u64,
+    pub chunks_served: u64,
+    pub bytes_fetched: u64,
+    pub chunks_fetched: u64,
+    pub serve_rate: u64,
+    pub fetch_rate: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwarmStats {
+    /// Limits in bytes per second; 0 is unlimited.
+    pub upload_limit: u64,
+    pub download_limit: u64,
+    /// Aggregate rates over the last minute, in bytes per second.
+    pub upload_rate: u64,
+    pub download_rate: u64,
+    /// Chunk requests waiting for upload capacity.
+    pub queued: u32,
+    pub deferred_total: u64,
+    /// Requests answered `Busy` because the peer was over its share or the queue was full.
+    pub rejected_total: u64,
+    pub peers: Vec<PeerTraffic>,
+    /// Known peers not found dead, by how they were found: saved by an
+    /// earlier run, from `swarm.bootstrap_peers`, or pinged us since startup.
+    pub restored_peers: u32,
+    pub bootstrap_peers: u32,
+    pub discovered_peers: u32,
+    /// Peers that didn't answer their ping, left out of the next save.
+    pub dead_peers: u32,
+}
+
+/// A package found by `Search`, once however many nodes reported it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub root_cid: Cid,
+    /// The best score any node gave it for the query.
+    pub score: u32,
+    /// Whether it is in this node's catalog.
+    pub local: bool,
+    /// The peers that reported it, which can serve its chunks.
+    pub peers: Vec<([u8; 4], u16)>,
+    /// Results are ranked by `score` plus a boost for packages that are
+    /// actually retrievable (see `docs/system/registry.md`).
+    pub health: PackageHealth,
+}
+
+/// How retrievable a package looks from this node. Numbers gossiped by
+/// peers are hints: capped, and forgotten after a few hours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageHealth {
+    /// Approximate number of nodes serving its chunks, this one included.
+    pub seeders: u32,
+    /// Seconds since anyone last fetched it successfully, as far as we heard.
+    pub last_fetched_secs_ago: Option<u64>,
+    /// Chunks of it this node served to peers since it started.
+    pub chunks_served: u64,
+    /// Fetches of it from this node that failed since it started.
+    pub fetch_failures: u32,
+}
+
+/// Why `ImportBundle` rejected a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BundleProblem {
+    /// Not a bundle, or a version or structure this registry can't read.
+    Format(String),
+    /// The overall checksum doesn't match: the file was damaged or altered.
+    Checksum,
+    /// Entry `chunk` of the chunk table doesn't hash to its CID.
+    CorruptChunk { chunk: u32 },
+    /// `package` references a chunk the bundle doesn't contain.
+    MissingChunk { package: String, chunk: u32 },
+    /// The signature on `package` doesn't verify against its publisher.
+    BadSignature { package: String },
+}
+
+/// What became of one package of an imported bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportOutcome {
+    /// `verify_only`: the package checked out and was not installed.
+    Verified,
+    Installed,
+    /// Answer with `ConfirmInstall`, as for `Install`.
+    ConfirmationRequired { ticket: u64, publisher_aid: AidBytes, fingerprint: String },
+    /// Verified, but storing it failed.
+    Failed(String),
+    /// Answer with `ConfirmGrants`, as for `Install`.
+    ReviewRequired { ticket: u64, publisher_aid: AidBytes, fingerprint: String, publisher_trusted: bool, grants: Vec<String>, syscalls: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportResult {
+    pub package_name: String,
+    pub version: String,
+    pub outcome: ImportOutcome,
+}
+
+/// Short, human-comparable form of a publisher Aid: the first 8 bytes in hex,
+/// grouped in pairs (e.g., "cd12:34ab:..."). Shown in confirmation prompts.
+pub fn fingerprint(aid: &AidBytes) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(19);
+    for (i, byte) in aid[..8].iter().enumerate() {
+        if i > 0 && i % 2 == 0 {
+            out.push(':');
+        }
+        out.push(DIGITS[(byte >> 4) as usize] as char);
+        out.push(DIGITS[(byte & 0xF) as usize] as char);
+    }
+    out
+}
+
+/// Event bus topic the registry publishes a `PackageInstalled` on after
+/// storing a package.
+pub const PACKAGE_INSTALLED_TOPIC: &str = "package.installed";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageInstalled {
+    pub package_name: String,
+}
+
+/// What the registry installed and what the user approved of each package's
+/// declared capabilities, postcard-encoded. Written by the registry in the
+/// same transaction as the package; read by init to start package services.
+pub const INSTALLED_INDEX_PATH: &str = "/var/aether/registry/installed";
+
+const INSTALLED_INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub version: String,
+    /// What the installed version's manifest declares.
+    pub requested: DeclaredCapabilities,
+    /// The part of `requested` the user approved; never more.
+    pub approved: DeclaredCapabilities,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledIndex {
+    version: u32,
+    pub packages: BTreeMap<String, InstalledPackage>,
+}
+
+impl InstalledIndex {
+    pub fn new() -> Self {
+        Self { version: INSTALLED_INDEX_VERSION, packages: BTreeMap::new() }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let index: Self = postcard::from_bytes(bytes).map_err(|_| String::from("the installed-packages index is corrupt"))?;
+        if index.version != INSTALLED_INDEX_VERSION {
+            return Err(format!("installed-packages index format version {} is not {}", index.version, INSTALLED_INDEX_VERSION));
+        }
+        Ok(index)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        postcard::to_allocvec(self).map_err(|_| String::from("failed to encode the installed-packages index"))
+    }
+}
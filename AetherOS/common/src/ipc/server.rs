@@ -0,0 +1,471 @@
+// common/src/ipc/server.rs
+
+#![no_std]
+
+//! The run loop of a request/response service.
+//!
+//! A service implements `Service`: its request and response types, and a
+//! `handle` that hands each request to a handler, typically a match calling
+//! one method per variant. `serve_loop` does the rest, the same way for
+//! every service:
+//!
+//! *   reads the next message, decodes it, and takes who sent it from the
+//!     credentials the kernel stamped on it rather than from the payload;
+//! *   logs the request with its trace ID: the envelope's request ID, or for
+//!     plain requests a number the loop counts up;
+//! *   answers every request. One that doesn't decode is answered with
+//!     `ServerError::Malformed`, one the service has no handler for with
+//!     `Unimplemented`, one whose deadline passed before it was started with
+//!     `Expired`, and one whose handler panics with `Panicked`;
+//! *   answers metrics scrapes from the service's registry, and shutdowns
+//!     and heartbeats on the lifecycle channel (see `lifecycle_ipc`);
+//! *   calls the service's `tick` once per pass, for its timers and sweeps.
+//!
+//! A handler holds a request back only by saying so, with `Outcome::Later`,
+//! and then answers it through `Service::ready`.
+//!
+//! The loop only needs a `ServerChannel`: `VNodeChannel` on a real boot, a
+//! message queue in the host harness, which calls `Server::poll` itself.
+//!
+//! ## Panics
+//!
+//! There is no unwinding in a V-Node, so a panicking handler can't be caught
+//! and the service stops. What the loop can do is keep the request from going
+//! unanswered: before each handler it leaves the `Panicked` answer where the
+//! panic handler finds it, and the panic handler sends it:
+//!
+//! ```ignore
+//! #[panic_handler]
+//! pub extern "C" fn panic(info: &PanicInfo) -> ! {
+//!     server::answer_in_flight();
+//!     log(...);
+//!     loop {}
+//! }
+//! ```
+//!
+//! The host harness, which can catch a panic, calls `Server::recover` instead.
+
+extern crate alloc;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::abi::{IpcCreds, LOG_SEVERITY_DEFAULT, LOG_SEVERITY_WARN, SYS_IPC_REPLY};
+use crate::ids::Ticks;
+use crate::ipc::envelope::{Envelope, Inbox};
+use crate::ipc::lifecycle_ipc::{Lifecycle, ShutdownRequest};
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ipc::session_ipc::{self, AidBytes};
+use crate::ipc::vnode::{ReplyToken, VNodeChannel};
+use crate::metrics::Registry;
+use crate::syscall::{syscall3, SYS_IPC_SEND, SYS_LOG};
+use crate::time;
+
+/// A message as a server receives it.
+pub struct Message {
+    pub data: Vec<u8>,
+    /// Present if the message came with `SYS_IPC_CALL`; the answer goes back with it.
+    pub token: Option<ReplyToken>,
+    /// Stamped by the kernel. `None` only from a stand-in that has none to give.
+    pub creds: Option<IpcCreds>,
+}
+
+/// The channel a server takes requests from and answers on.
+pub trait ServerChannel {
+    /// The next message, if one is waiting. Never blocks.
+    fn next_message(&mut self) -> Result<Option<Message>, ()>;
+
+    /// Waits for the next message.
+    fn wait_message(&mut self) -> Result<Message, ()>;
+
+    /// Answers the call `token` came with, or without one, sends `bytes` on the channel.
+    fn answer(&mut self, token: Option<ReplyToken>, bytes: &[u8]) -> Result<(), ()>;
+
+    /// The kernel channel behind this one, which `answer_in_flight` sends
+    /// on. A stand-in has none.
+    fn kernel_channel(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl ServerChannel for VNodeChannel {
+    fn next_message(&mut self) -> Result<Option<Message>, ()> {
+        Ok(self.recv_call_non_blocking()?.map(|(data, token)| Message { data, token, creds: session_ipc::last_creds() }))
+    }
+
+    fn wait_message(&mut self) -> Result<Message, ()> {
+        let (data, token) = self.recv_call()?;
+        Ok(Message { data, token, creds: session_ipc::last_creds() })
+    }
+
+    fn answer(&mut self, token: Option<ReplyToken>, bytes: &[u8]) -> Result<(), ()> {
+        self.reply_raw(token, bytes)
+    }
+
+    fn kernel_channel(&self) -> Option<u32> {
+        Some(self.id)
+    }
+}
+
+/// How requests and answers travel on a service's channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// The message is the request and the answer is the response, sent back
+    /// with the call's reply token.
+    Plain,
+    /// The request comes in an `Envelope` and is answered in one with the same
+    /// request ID. Only these can have a deadline or be cancelled.
+    Enveloped,
+}
+
+/// Why the loop answered a request for the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerError {
+    /// The message didn't decode as a request.
+    Malformed,
+    /// The service has no handler for the request.
+    Unimplemented,
+    /// The request's deadline passed before the service got to it.
+    Expired,
+    /// The handler panicked, and the service has stopped.
+    Panicked,
+}
+
+impl ServerError {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ServerError::Malformed => "malformed request",
+            ServerError::Unimplemented => "not implemented by this service",
+            ServerError::Expired => "deadline passed before the request was started",
+            ServerError::Panicked => "the service failed while handling the request",
+        }
+    }
+}
+
+/// What a handler did with a request.
+pub enum Outcome<R> {
+    /// Answer with this.
+    Reply(R),
+    /// Nothing to answer now. The service answers it later through
+    /// `Service::ready`, or the protocol doesn't answer this message at all,
+    /// like a stream's credit.
+    Later,
+    /// The service doesn't handle this request; the loop answers `ServerError::Unimplemented`.
+    Unimplemented,
+}
+
+/// The request being handled, for its handler.
+pub struct Call<'a> {
+    /// Identifies the request in log lines: its envelope's request ID, or
+    /// the loop's count of plain requests.
+    pub trace: u64,
+    pub creds: Option<IpcCreds>,
+    pub token: Option<ReplyToken>,
+    pub deadline_ticks: Option<Ticks>,
+    framing: Framing,
+    chan: &'a mut dyn ServerChannel,
+    inbox: &'a mut Inbox,
+}
+
+impl<'a> Call<'a> {
+    /// The identity the sender is bound to.
+    pub fn caller(&self) -> Option<AidBytes> {
+        self.creds.and_then(|creds| creds.aid)
+    }
+
+    /// The task that sent the request.
+    pub fn sender(&self) -> Option<u64> {
+        self.creds.map(|creds| creds.sender)
+    }
+
+    /// True if the request should stop: it was cancelled or its deadline has
+    /// passed. Call it between the steps of a long operation. Requests that
+    /// come plain never stop.
+    pub fn stop(&mut self) -> bool {
+        match self.framing {
+            Framing::Enveloped => self.inbox.check(self.chan, self.trace, self.deadline_ticks),
+            Framing::Plain => false,
+        }
+    }
+
+    /// Sends one of several answers ahead of the last, which the handler
+    /// returns, like a batch of search results. Only an enveloped request can
+    /// be answered more than once.
+    pub fn send_partial<R: Serialize>(&mut self, response: R) -> Result<(), ()> {
+        if self.framing != Framing::Enveloped {
+            return Err(());
+        }
+        let bytes = postcard::to_allocvec(&Envelope::reply(self.trace, response)).map_err(|_| ())?;
+        self.chan.answer(None, &bytes)
+    }
+}
+
+pub trait Service {
+    type Request: DeserializeOwned + Debug;
+    type Response: Serialize;
+
+    /// Names the service in log lines, e.g. "VFS".
+    const NAME: &'static str;
+    const FRAMING: Framing = Framing::Plain;
+    /// Severity each request is logged at.
+    const TRACE_SEVERITY: u64 = LOG_SEVERITY_DEFAULT;
+
+    fn handle(&mut self, call: &mut Call, request: Self::Request) -> Outcome<Self::Response>;
+
+    /// The protocol's answer for a request the loop failed on the service's behalf.
+    fn error(error: ServerError) -> Self::Response;
+
+    /// The scrape `request` carries, if it is one, and the response variant
+    /// the metrics go back in.
+    fn scrape(_request: &Self::Request) -> Option<(&MetricsRequest, fn(MetricsResponse) -> Self::Response)> {
+        None
+    }
+
+    /// What scrapes are answered from. A service without metrics answers
+    /// them `Unimplemented`.
+    fn metrics(&self) -> Option<&Registry> {
+        None
+    }
+
+    /// Answers to requests held back with `Outcome::Later` that are now due,
+    /// with the reply token each came with. Only plain requests are held back.
+    fn ready(&mut self) -> Vec<(Option<ReplyToken>, Self::Response)> {
+        Vec::new()
+    }
+
+    /// Once per pass of the loop, after the request if there was one, so
+    /// work that has to follow an answer goes here.
+    fn tick(&mut self, _now: Ticks) {}
+
+    /// Init asked the service to stop: write out what is only held in memory.
+    /// The loop answers `Stopped` after.
+    fn shutdown(&mut self, _reboot: bool) {}
+}
+
+/// What a pass of `Server::poll` did.
+#[derive(Debug, Clone, Copy)]
+pub enum Poll {
+    /// No request was waiting.
+    Idle,
+    Handled,
+    /// Init asked the service to stop. `serve_loop` calls `Service::shutdown`
+    /// and answers it.
+    Shutdown(ShutdownRequest),
+}
+
+/// The `Panicked` answer for the request in flight, for `answer_in_flight`.
+/// A V-Node has one thread, so these are only ever touched by the loop and
+/// then by the panic handler that interrupts it.
+static IN_FLIGHT: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT_CHANNEL: AtomicU32 = AtomicU32::new(0);
+static IN_FLIGHT_HAS_TOKEN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT_TOKEN: AtomicU64 = AtomicU64::new(0);
+static IN_FLIGHT_ANSWER: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static IN_FLIGHT_ANSWER_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends the `Panicked` answer for the request whose handler was running,
+/// if there was one. Call it from the panic handler.
+pub fn answer_in_flight() {
+    if !IN_FLIGHT.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let ptr = IN_FLIGHT_ANSWER.load(Ordering::SeqCst) as u64;
+    let len = IN_FLIGHT_ANSWER_LEN.load(Ordering::SeqCst) as u64;
+    unsafe {
+        if IN_FLIGHT_HAS_TOKEN.load(Ordering::SeqCst) {
+            syscall3(SYS_IPC_REPLY, IN_FLIGHT_TOKEN.load(Ordering::SeqCst), ptr, len);
+        } else {
+            syscall3(SYS_IPC_SEND, IN_FLIGHT_CHANNEL.load(Ordering::SeqCst) as u64, ptr, len);
+        }
+    }
+}
+
+pub struct Server {
+    lifecycle: Lifecycle,
+    inbox: Inbox, // Enveloped requests and cancels set aside while a handler runs
+    next_trace: u64,
+    in_flight: Option<(Option<ReplyToken>, Vec<u8>)>, // Token and `Panicked` answer of the request being handled
+}
+
+impl Server {
+    pub fn new(lifecycle: Lifecycle) -> Self {
+        Self { lifecycle, inbox: Inbox::new(), next_trace: 1, in_flight: None }
+    }
+
+    /// Handles the next request, if one is waiting, runs the service's
+    /// `tick`, sends the answers it has ready, and checks the lifecycle
+    /// channel. Never blocks.
+    pub fn poll<S: Service>(&mut self, chan: &mut dyn ServerChannel, service: &mut S, now: Ticks) -> Poll {
+        let handled = match S::FRAMING {
+            Framing::Plain => self.next_plain(chan, service, now),
+            Framing::Enveloped => self.next_enveloped(chan, service, now),
+        };
+        service.tick(now);
+        for (token, response) in service.ready() {
+            self.send::<S>(chan, token, 0, &response);
+        }
+        if let Some(request) = self.lifecycle.shutdown_requested() {
+            return Poll::Shutdown(request);
+        }
+        if handled { Poll::Handled } else { Poll::Idle }
+    }
+
+    /// Sends the `Panicked` answer for the request whose handler panicked.
+    /// For the host harness, after it caught the panic; on a real boot the
+    /// panic handler does this with `answer_in_flight`.
+    pub fn recover(&mut self, chan: &mut dyn ServerChannel) {
+        self.disarm();
+        if let Some((token, bytes)) = self.in_flight.take() {
+            if chan.answer(token, &bytes).is_err() {
+                log_at(LOG_SEVERITY_WARN, "Server: Failed to answer a request whose handler panicked.");
+            }
+        }
+    }
+
+    fn next_plain<S: Service>(&mut self, chan: &mut dyn ServerChannel, service: &mut S, now: Ticks) -> bool {
+        let Ok(Some(message)) = chan.next_message() else {
+            return false;
+        };
+        let trace = self.next_trace;
+        self.next_trace += 1;
+        match postcard::from_bytes::<S::Request>(&message.data) {
+            Ok(request) => self.dispatch(chan, service, trace, message.creds, message.token, None, request, now),
+            Err(_) => {
+                log_at(LOG_SEVERITY_WARN, &format!("{}: [{}] Failed to decode a request from {}.", S::NAME, trace, sender_name(message.creds)));
+                self.send::<S>(chan, message.token, trace, &S::error(ServerError::Malformed));
+            },
+        }
+        true
+    }
+
+    /// An envelope that doesn't decode can't be answered, having no request
+    /// ID to answer; the inbox drops it.
+    fn next_enveloped<S: Service>(&mut self, chan: &mut dyn ServerChannel, service: &mut S, now: Ticks) -> bool {
+        let Some(envelope) = self.inbox.next::<S::Request>(chan) else {
+            return false;
+        };
+        let Some(request) = envelope.body else {
+            return false;
+        };
+        let creds = self.inbox.creds();
+        self.dispatch(chan, service, envelope.request_id, creds, None, envelope.deadline_ticks, request, now);
+        true
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch<S: Service>(
+        &mut self,
+        chan: &mut dyn ServerChannel,
+        service: &mut S,
+        trace: u64,
+        creds: Option<IpcCreds>,
+        token: Option<ReplyToken>,
+        deadline_ticks: Option<Ticks>,
+        request: S::Request,
+        now: Ticks,
+    ) {
+        log_at(S::TRACE_SEVERITY, &format!("{}: [{}] {:?} from {}.", S::NAME, trace, request, sender_name(creds)));
+
+        if let Some((scrape, wrap)) = S::scrape(&request) {
+            let response = match service.metrics() {
+                Some(registry) => wrap(registry.handle(scrape)),
+                None => S::error(ServerError::Unimplemented),
+            };
+            self.send::<S>(chan, token, trace, &response);
+            return;
+        }
+        // Nobody is waiting for the answer any more.
+        if deadline_ticks.map_or(false, |deadline| now >= deadline) {
+            self.send::<S>(chan, token, trace, &S::error(ServerError::Expired));
+            return;
+        }
+
+        self.arm::<S>(chan, token, trace);
+        let mut call = Call { trace, creds, token, deadline_ticks, framing: S::FRAMING, chan: &mut *chan, inbox: &mut self.inbox };
+        let outcome = service.handle(&mut call, request);
+        self.disarm();
+        self.in_flight = None;
+
+        let response = match outcome {
+            Outcome::Reply(response) => response,
+            Outcome::Later => return,
+            Outcome::Unimplemented => {
+                log_at(LOG_SEVERITY_WARN, &format!("{}: [{}] No handler for the request.", S::NAME, trace));
+                S::error(ServerError::Unimplemented)
+            },
+        };
+        self.send::<S>(chan, token, trace, &response);
+    }
+
+    /// Answers a request: `trace` is its request ID if it came in an envelope.
+    fn send<S: Service>(&mut self, chan: &mut dyn ServerChannel, token: Option<ReplyToken>, trace: u64, response: &S::Response) {
+        let sent = match encode::<S>(trace, response) {
+            Some(bytes) => chan.answer(token, &bytes),
+            None => Err(()),
+        };
+        if sent.is_err() {
+            log_at(LOG_SEVERITY_WARN, &format!("{}: [{}] Failed to send the response.", S::NAME, trace));
+        }
+    }
+
+    /// Keeps the `Panicked` answer for the request about to be handled, for
+    /// `recover` and, on a kernel channel, `answer_in_flight`.
+    fn arm<S: Service>(&mut self, chan: &mut dyn ServerChannel, token: Option<ReplyToken>, trace: u64) {
+        let Some(bytes) = encode::<S>(trace, &S::error(ServerError::Panicked)) else {
+            return;
+        };
+        let (_, answer) = self.in_flight.insert((token, bytes));
+        if let Some(channel) = chan.kernel_channel() {
+            IN_FLIGHT_CHANNEL.store(channel, Ordering::SeqCst);
+            IN_FLIGHT_HAS_TOKEN.store(token.is_some(), Ordering::SeqCst);
+            IN_FLIGHT_TOKEN.store(token.unwrap_or(0), Ordering::SeqCst);
+            IN_FLIGHT_ANSWER.store(answer.as_mut_ptr(), Ordering::SeqCst);
+            IN_FLIGHT_ANSWER_LEN.store(answer.len(), Ordering::SeqCst);
+            IN_FLIGHT.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn disarm(&mut self) {
+        IN_FLIGHT.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Serves `service` on `chan` for good. `lifecycle` is the service's
+/// lifecycle channel, for shutdowns and heartbeats.
+pub fn serve_loop<S: Service>(chan: &mut impl ServerChannel, lifecycle: Lifecycle, service: &mut S) -> ! {
+    let mut server = Server::new(lifecycle);
+    log_at(LOG_SEVERITY_DEFAULT, &format!("{}: Entering main event loop.", S::NAME));
+    let mut now = time::ticks();
+    loop {
+        if let Poll::Shutdown(request) = server.poll(chan, service, now) {
+            log_at(LOG_SEVERITY_DEFAULT, &format!("{}: Stopping for shutdown.", S::NAME));
+            service.shutdown(request.reboot);
+            server.lifecycle.stopped(request);
+        }
+        // Yield to other V-Nodes to prevent busy-waiting
+        now = time::ticks(); // This will cause a context switch
+    }
+}
+
+fn encode<S: Service>(trace: u64, response: &S::Response) -> Option<Vec<u8>> {
+    match S::FRAMING {
+        Framing::Plain => postcard::to_allocvec(response).ok(),
+        Framing::Enveloped => postcard::to_allocvec(&Envelope::reply(trace, response)).ok(),
+    }
+}
+
+fn sender_name(creds: Option<IpcCreds>) -> alloc::string::String {
+    match creds {
+        Some(creds) => format!("{} (task {})", creds.name(), creds.sender),
+        None => "an unknown sender".into(),
+    }
+}
+
+fn log_at(severity: u64, msg: &str) {
+    unsafe {
+        syscall3(SYS_LOG, msg.as_ptr() as u64, msg.len() as u64, severity);
+    }
+}
@@ -0,0 +1,133 @@
+// common/src/ipc/session_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+use crate::abi::{IpcCreds, IPC_CREDS_LEN, SYS_IPC_CREDS, E_UNKNOWN_SYSCALL};
+use crate::syscall::{syscall3, SYS_IPC_LAST_SENDER, SYS_GET_IDENTITY, SYS_TASK_STATS, E_ERROR};
+
+/// Raw bytes of an Aid, as bound to a task by the kernel.
+pub type AidBytes = [u8; 32];
+
+/// Reserved identity that init binds to trusted system services (e.g., mail-service)
+/// so they can act on files in any user's home directory.
+pub const SYSTEM_AID: AidBytes = [0; 32];
+
+/// Represents requests from client V-Nodes to the session V-Node.
+/// `Challenge` and `Login` always apply to the task that sent them.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SessionRequest {
+    /// Ask for a fresh nonce to sign for `Login`.
+    Challenge,
+    /// Bind `aid` to the calling task. `proof` is a signature over the last
+    /// nonce issued to this task, made with the Aid's key.
+    Login { aid: AidBytes, proof: Vec<u8> },
+    /// Clear the calling task's identity.
+    Logout,
+    /// Look up the identity bound to a task.
+    GetIdentity { task_id: u64 },
+    /// Look up the identity registered under a local name in `/etc/identities`.
+    Resolve { name: String },
+}
+
+/// Represents responses from the session V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SessionResponse {
+    /// A nonce to sign for `Login`. Valid for one attempt.
+    Nonce(AidBytes),
+    /// The identity bound to a task.
+    Identity { task_id: u64, aid: AidBytes },
+    /// The identity registered under `name`.
+    Resolved { name: String, aid: AidBytes },
+    /// No identity is registered under the name.
+    UnknownName(String),
+    /// The task has no identity bound.
+    Unauthenticated,
+    /// Indicates successful operation.
+    Success,
+    /// Indicates an error occurred.
+    Error(String),
+}
+
+/// Returns the task ID stamped on the last IPC message the current task received.
+/// Services call this right after receiving a request to learn who sent it.
+pub fn last_sender() -> Option<u64> {
+    let sender = unsafe { syscall3(SYS_IPC_LAST_SENDER, 0, 0, 0) };
+    if sender == E_ERROR { None } else { Some(sender) }
+}
+
+/// Returns who sent the last IPC message the current task received: its task
+/// ID, name and identity as the kernel recorded them when the message was
+/// sent. Nothing in the payload can change them, and
+/// neither can a sender that logs in or out afterwards.
+///
+/// Kernels older than ABI version 16 don't record credentials; on those the
+/// identity is looked up now, and the name is left empty.
+pub fn last_creds() -> Option<IpcCreds> {
+    let mut record = [0u8; IPC_CREDS_LEN];
+    match unsafe { syscall3(SYS_IPC_CREDS, record.as_mut_ptr() as u64, record.len() as u64, 0) } {
+        E_UNKNOWN_SYSCALL => {
+            let sender = last_sender()?;
+            Some(IpcCreds { sender, aid: identity_of(sender), ..IpcCreds::default() })
+        }
+        len if len == IPC_CREDS_LEN as u64 => IpcCreds::from_bytes(&record),
+        _ => None,
+    }
+}
+
+/// Returns the identity the kernel has bound to `task_id`, or `None` if the task is unauthenticated.
+pub fn identity_of(task_id: u64) -> Option<AidBytes> {
+    let mut aid: AidBytes = [0; 32];
+    let res = unsafe { syscall3(SYS_GET_IDENTITY, task_id, aid.as_mut_ptr() as u64, aid.len() as u64) };
+    if res == aid.len() as u64 { Some(aid) } else { None }
+}
+
+/// Whether `task_id` still exists. The scheduler drops a task once it exits,
+/// after which `SYS_TASK_STATS` fails for it.
+pub fn task_alive(task_id: u64) -> bool {
+    let mut stats = [0u64; 3]; // `TaskStats`: id, log_messages, log_suppressed
+    let res = unsafe { syscall3(SYS_TASK_STATS, task_id, stats.as_mut_ptr() as u64, core::mem::size_of_val(&stats) as u64) };
+    res != E_ERROR
+}
+
+/// Returns the identity of the sender of the last received message.
+pub fn caller_identity() -> Option<AidBytes> {
+    last_creds().and_then(|creds| creds.aid)
+}
+
+/// Lowercase hex form of an Aid, as used in home directory names.
+pub fn aid_to_hex(aid: &AidBytes) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(64);
+    for byte in aid {
+        out.push(DIGITS[(byte >> 4) as usize] as char);
+        out.push(DIGITS[(byte & 0xF) as usize] as char);
+    }
+    out
+}
+
+/// Parses the form produced by `aid_to_hex` (either case accepted).
+pub fn aid_from_hex(hex: &str) -> Option<AidBytes> {
+    let bytes = hex.as_bytes();
+    if bytes.len() != 64 {
+        return None;
+    }
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    let mut aid: AidBytes = [0; 32];
+    for (i, pair) in bytes.chunks(2).enumerate() {
+        aid[i] = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(aid)
+}
+
+/// Home directory of an identity: `/home/<aid hex>`.
+pub fn home_dir(aid: &AidBytes) -> String {
+    let mut path = String::from("/home/");
+    path.push_str(&aid_to_hex(aid));
+    path
+}
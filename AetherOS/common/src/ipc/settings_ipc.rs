@@ -0,0 +1,66 @@
+// common/src/ipc/settings_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+/// A typed setting value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SettingValue {
+    Bool(bool),
+    Int(i64),
+    /// Used for string and enum settings. Also accepted by `Set` for any type,
+    /// in which case the settings service parses it according to the schema.
+    Str(String),
+}
+
+/// A setting as reported by `List`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingEntry {
+    pub key: String,
+    pub value: SettingValue,
+    /// Schema type, e.g. "bool", "int(1..=4)", "enum(ipv4|ipv6|any)". Empty for unknown keys.
+    pub kind: String,
+    pub default: Option<SettingValue>,
+    pub description: String,
+    /// True if the key was found in the settings file but is not in the schema.
+    /// Such keys are preserved on disk but cannot be changed.
+    pub unknown: bool,
+}
+
+/// Payload of the "settings.<key>" event published after every successful change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingChanged {
+    pub key: String,
+    pub value: SettingValue,
+}
+
+/// Represents requests from client V-Nodes to the settings V-Node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SettingsRequest {
+    /// Get the current value of a setting.
+    Get { key: String },
+    /// Set a setting. The value is validated against the schema.
+    Set { key: String, value: SettingValue },
+    /// List all settings, including unknown keys preserved from the settings file.
+    List,
+    /// Reset a setting to its schema default.
+    ResetToDefault { key: String },
+}
+
+/// Represents responses from the settings V-Node to client V-Nodes.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SettingsResponse {
+    /// The current value of the requested setting.
+    Value { key: String, value: SettingValue },
+    /// All settings.
+    Settings(Vec<SettingEntry>),
+    /// The setting was changed and persisted.
+    Success,
+    /// Indicates an error occurred (unknown key, wrong type, out of range, I/O failure).
+    Error(String),
+}
@@ -0,0 +1,34 @@
+// common/src/ipc/sysmon_ipc.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ipc::metrics_ipc::MetricSample;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SysmonRequest {
+    /// Scrape every monitored service and return the combined samples.
+    Scrape,
+    /// As `Scrape`, rendered in the Prometheus text format for the host-side
+    /// serial/TCP diagnostics path.
+    ScrapeText,
+    /// init's boot report (`InitRequest::BootReport`) as text, so CI can
+    /// poll for the end of the boot over the same diagnostics path.
+    BootReport,
+    /// The shell's slowest recent commands (`ShellRequest::GetTimings`) as
+    /// text, slowest first, at most `limit`.
+    SlowCommands { limit: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SysmonResponse {
+    /// Samples of all services that answered, sysmon's own last. Services that
+    /// didn't answer are counted in `sysmon_scrape_failures_total`.
+    Metrics(Vec<MetricSample>),
+    Text(String),
+}
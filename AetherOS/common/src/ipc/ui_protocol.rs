@@ -8,10 +8,16 @@ use alloc::vec::Vec;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ids::WindowId;
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ui::latency::{InputTiming, PipelineLatency};
+
 /// Represents requests from client V-Nodes to the UI Compositor or other UI services.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum UiRequest {
-    /// Request to create a new window surface.
+    /// Request to create a new window surface. Its `UiEvent`s go to the
+    /// compositor's own channel; a client that reads its events elsewhere uses
+    /// `CreateWindowWithEvents`.
     CreateWindow {
         title: String,
         width: u32,
@@ -19,33 +25,158 @@ pub enum UiRequest {
     },
     /// Request to draw pixels to a specific window surface.
     DrawToSurface {
-        window_id: u32,
+        window_id: WindowId,
         x: u32,
         y: u32,
         width: u32,
         height: u32,
         pixels: Vec<u8>, // RGBA pixel data
+        /// Timing of the input event this frame responds to, from `AppLatency::on_commit`.
+        input: Option<InputTiming>,
     },
-    /// Request to handle a mouse event.
+    /// Raw mouse input for the compositor (from the input bridge). `x`/`y` are global
+    /// desktop coordinates (see `OutputInfo`); the compositor hit-tests them and forwards `UiEvent::Mouse` to the owner.
+    /// `captured_at` is the kernel time (`TIME_NANOS`) stamped on the input IRQ.
     MouseEvent {
-        window_id: u32,
+        window_id: WindowId,
         x: u32,
         y: u32,
         button: u8,
         event_type: MouseEventType,
+        captured_at: u64,
     },
-    /// Request to handle a keyboard event.
+    /// Raw keyboard input for the compositor, forwarded as `UiEvent::Key` to the window's owner.
     KeyEvent {
-        window_id: u32,
+        window_id: WindowId,
         keycode: u16,
         event_type: KeyEventType,
+        captured_at: u64,
+        /// What the key typed, from the display owner's `keymap::Composer`:
+        /// `None` for releases, dead keys and keys inside a compose
+        /// sequence, and up to two characters when a dead key doesn't combine.
+        text: Option<String>,
+    },
+    /// Resize a window's client area. The size is clamped to the compositor's
+    /// minimum and to the window's output; the owner learns the size it got from the
+    /// `UiEvent::Resized` that follows the response.
+    ResizeWindow {
+        window_id: WindowId,
+        width: u32,
+        height: u32,
     },
     /// Request to close a window.
     CloseWindow {
-        window_id: u32,
+        window_id: WindowId,
     },
     /// Request to get information about active windows.
     GetWindows,
+    /// List the outputs (displays) and where they sit in the global coordinate space.
+    ListOutputs,
+    /// Move a window onto another output, keeping its position relative to the
+    /// output's origin as far as it fits.
+    MoveWindowToOutput {
+        window_id: WindowId,
+        output_id: u32,
+    },
+    /// Add an output backed by an offscreen buffer, to the right of the others.
+    /// The size is clamped to 320x200..4096x4096.
+    AddVirtualOutput {
+        width: u32,
+        height: u32,
+    },
+    /// Remove an output. Its windows move to the output with the lowest ID.
+    /// The last output can't be removed.
+    RemoveOutput {
+        output_id: u32,
+    },
+    /// Read back what an output currently shows, without the cursor.
+    CaptureScreen {
+        output_id: u32,
+    },
+    /// Request input latency statistics, per window.
+    GetStats,
+    /// Scrape the compositor's metrics (aggregate latency histograms, window and input counts).
+    Metrics(MetricsRequest),
+    /// Show a notification bubble above all windows, in the top right corner of
+    /// the primary output. Showing an `id` that is already up replaces it in place.
+    /// Clicks on it are reported to `events_chan` as `NotificationClicked`.
+    ShowNotification {
+        id: u64,
+        summary: String,
+        body: String,
+        critical: bool, // Drawn with a red border
+        actions: Vec<NotificationButton>,
+        events_chan: u32,
+    },
+    /// Take a notification bubble down. Hiding one that isn't up succeeds.
+    HideNotification {
+        id: u64,
+    },
+    /// Show `shape` while the pointer is over the window's client area. The
+    /// title bar keeps the compositor's own shapes. New windows start with `Arrow`.
+    SetCursor {
+        window_id: WindowId,
+        shape: CursorShape,
+    },
+    /// Start dragging `data` out of the window. Only valid while the left
+    /// button that was pressed in the window's client area is still held.
+    /// Inline data is limited to `MAX_INLINE_DRAG_BYTES`; larger data goes by token.
+    /// The source learns how the drag ended from `UiEvent::DragEnded`.
+    StartDrag {
+        window_id: WindowId,
+        mime: String,
+        data: DragData,
+    },
+    /// Answer a `UiEvent::DragOver`: whether the window takes the drop. Holds
+    /// until the window answers again or the drag leaves it.
+    AcceptDrag {
+        window_id: WindowId,
+        accept: bool,
+    },
+    /// Set the desktop background: a color as `#RRGGBB`, which replaces any
+    /// wallpaper, or the VFS path of an image, decoded and laid out over the
+    /// color per `fit`. Lasts until the compositor restarts; the
+    /// `compositor.*` settings are what it starts with.
+    SetBackground {
+        color_or_image_path: String,
+        fit: WallpaperFit,
+    },
+    /// Answered with `DisplayInfo`.
+    GetDisplayInfo,
+    /// Switch the primary output to `width x height`, one of the modes
+    /// `DisplayInfo` lists. Answered with the new `DisplayInfo`, or with
+    /// `NotSupported` if the framebuffer can't be reprogrammed.
+    SetDisplayMode {
+        width: u32,
+        height: u32,
+    },
+    /// Claim a global shortcut: `keycode` pressed with exactly `modifiers`
+    /// (`keys::MOD_*`; Caps Lock doesn't count) held. The focused window no
+    /// longer sees the combination; `UiEvent::HotkeyPressed` with `tag` goes to
+    /// `events_chan` instead. Only the system UI may register (see
+    /// `Nexus/UI/docs/ui/compositor.md`), and a combination that is already
+    /// taken is refused with an `Error` naming its owner. Held until
+    /// `UnregisterHotkey` or until the registering task exits.
+    RegisterHotkey {
+        modifiers: u8,
+        keycode: u16,
+        tag: u32,
+        events_chan: u32,
+    },
+    /// Release a combination the caller registered.
+    UnregisterHotkey {
+        modifiers: u8,
+        keycode: u16,
+    },
+    /// Answered with `Hotkeys`.
+    ListHotkeys,
+    /// Like `CreateWindow`, with the window's `UiEvent`s sent to `events_chan`.
+    CreateWindowWithEvents {
+        title: String,
+        width: u32,
+        height: u32,
+        events_chan: u32,
+    },
 }
 
 /// Represents responses from the UI Compositor or other UI services to client V-Nodes.
@@ -53,17 +184,118 @@ pub enum UiRequest {
 pub enum UiResponse {
     /// Indicates a successful operation, optionally with a window ID.
     Success {
-        window_id: Option<u32>,
+        window_id: Option<WindowId>,
     },
     /// Returns a list of active windows and their properties.
     Windows(Vec<WindowInfo>),
+    /// Answers `ListOutputs`, ordered by ID.
+    Outputs(Vec<OutputInfo>),
+    /// Answers `AddVirtualOutput` with the output created.
+    Output(OutputInfo),
+    /// Answers `CaptureScreen`: `width * height` RGBA pixels, row by row.
+    Screen {
+        output_id: u32,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    /// Input latency statistics for all windows.
+    Stats(CompositorStats),
+    /// Answers `Metrics`.
+    Metrics(MetricsResponse),
     /// Indicates an error occurred during a UI operation.
     Error {
         message: String,
     },
+    /// Answers `GetDisplayInfo` and `SetDisplayMode`.
+    DisplayInfo(DisplayInfo),
+    /// The request is valid but the display can't carry it out, e.g. a mode
+    /// switch on a framebuffer the bootloader fixed. Clients grey out the
+    /// control rather than report an error.
+    NotSupported {
+        message: String,
+    },
+    /// Answers `ListHotkeys`: the compositor's built-ins, then the
+    /// registered combinations in the order they were claimed.
+    Hotkeys(Vec<HotkeyInfo>),
 }
 
+/// Notifications sent by the compositor to the V-Node that owns a window.
 #[derive(Debug, Serialize, Deserialize)]
+pub enum UiEvent {
+    /// Pointer input inside the window's client area. `x`/`y` are client-relative.
+    Mouse {
+        window_id: WindowId,
+        x: u32,
+        y: u32,
+        button: u8,
+        event_type: MouseEventType,
+        timing: InputTiming,
+    },
+    /// Keyboard input for the focused window. `text` is the `KeyEvent`'s,
+    /// unchanged; a text field should insert it rather than map `keycode`.
+    Key {
+        window_id: WindowId,
+        keycode: u16,
+        event_type: KeyEventType,
+        timing: InputTiming,
+        text: Option<String>,
+    },
+    /// The user clicked the window's close button. The owner should answer with
+    /// `CloseWindow` (or ignore it); the compositor force-closes the window if the
+    /// owner doesn't respond within its close timeout.
+    CloseRequested {
+        window_id: WindowId,
+    },
+    /// The client area changed size, whether the client asked for it or not.
+    /// Draws larger than the new size are rejected from now on, so the owner
+    /// should lay out again and redraw the whole area.
+    Resized {
+        window_id: WindowId,
+        width: u32,
+        height: u32,
+    },
+    /// The user clicked a notification bubble, on the button `action` if any.
+    /// The compositor has already taken the bubble down.
+    NotificationClicked {
+        id: u64,
+        action: Option<String>,
+    },
+    /// A drag is over the window's client area, at client-relative `x`/`y`.
+    /// Sent when it enters and on every move. Answer with `AcceptDrag`; a
+    /// window that doesn't answer doesn't take the drop.
+    DragOver {
+        window_id: WindowId,
+        x: u32,
+        y: u32,
+        mime: String,
+    },
+    /// The drag left the window, or was cancelled while over it.
+    DragLeave {
+        window_id: WindowId,
+    },
+    /// The button was released over the window, which had accepted the drag.
+    Drop {
+        window_id: WindowId,
+        x: u32,
+        y: u32,
+        mime: String,
+        data: DragData,
+    },
+    /// Sent to the source window when its drag ends: `dropped` if a window
+    /// took the drop, false if it was released elsewhere or cancelled.
+    DragEnded {
+        window_id: WindowId,
+        dropped: bool,
+    },
+    /// A combination the client registered was pressed. Sent to its
+    /// `events_chan` on the key-down; key repeats send it again.
+    HotkeyPressed {
+        tag: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MouseEventType {
     MouseDown,
     MouseUp,
@@ -71,7 +303,44 @@ pub enum MouseEventType {
     Scroll,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// `button` of `MouseDown` and `MouseUp` events. `MouseMove` events carry 0.
+pub const BUTTON_LEFT: u8 = 0;
+pub const BUTTON_RIGHT: u8 = 1;
+pub const BUTTON_MIDDLE: u8 = 2;
+
+// `button` of `Scroll` events: one event per wheel notch.
+pub const SCROLL_UP: u8 = 0;
+pub const SCROLL_DOWN: u8 = 1;
+
+/// Pointer sprites the compositor has built in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CursorShape {
+    Arrow,
+    /// Over text that can be selected or typed into.
+    TextBeam,
+    /// Over something clickable, e.g. a link.
+    Hand,
+    /// Over an edge that resizes left and right.
+    ResizeHorizontal,
+    /// Over an edge that resizes up and down.
+    ResizeVertical,
+}
+
+/// Largest payload `StartDrag` carries inline.
+pub const MAX_INLINE_DRAG_BYTES: usize = 2048;
+
+/// What a drag carries. The compositor passes it from source to target untouched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DragData {
+    Inline(Vec<u8>),
+    /// A handle for data too large to inline, which the target redeems
+    /// through the shared clipboard. Conceptual: there is no clipboard
+    /// service yet, so for now the source and target agree between them
+    /// what a token names.
+    Token(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum KeyEventType {
     KeyDown,
     KeyUp,
@@ -79,10 +348,121 @@ pub enum KeyEventType {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WindowInfo {
-    pub id: u32,
+    pub id: WindowId,
     pub title: String,
     pub x: u32,
     pub y: u32,
+    pub width: u32, // Client area width
+    pub height: u32, // Client area height
+    /// Height of the compositor-drawn title bar above the client area. The client area
+    /// starts at (x, y + title_bar_height); DrawToSurface coordinates are relative to it.
+    pub title_bar_height: u32,
+    /// The output the window belongs to. A window may extend onto neighbouring outputs.
+    pub output_id: u32,
+}
+
+/// A global shortcut and what it does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotkeyInfo {
+    pub modifiers: u8,
+    pub keycode: u16,
+    /// Task name of the client that registered it, or "display-compositor".
+    pub owner: String,
+    pub action: HotkeyAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HotkeyAction {
+    /// Built in: cycle focus through the windows, top to bottom, while the
+    /// modifiers stay held. Bound by `compositor.hotkey.switch_window`.
+    SwitchWindow,
+    /// Built in: save the primary output to `SCREENSHOT_DIR` and publish
+    /// `SCREENSHOT_SAVED_TOPIC`. Bound by `compositor.hotkey.screenshot`.
+    Screenshot,
+    /// Registered by a client, which gets `HotkeyPressed { tag }`.
+    Client { tag: u32 },
+}
+
+/// Where the screenshot hotkey saves, as `screenshot-<epoch secs>.ppm`.
+pub const SCREENSHOT_DIR: &str = "/data/screenshots";
+
+/// Published on the event bus by the compositor after the screenshot hotkey
+/// saved a file. The payload is a `ScreenshotSaved`.
+pub const SCREENSHOT_SAVED_TOPIC: &str = "screenshot.saved";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenshotSaved {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A button on a notification bubble. `key` is reported back when it is clicked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationButton {
+    pub key: String,
+    pub label: String,
+}
+
+/// A display, as a rectangle in the global coordinate space that window
+/// positions and pointer input use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputInfo {
+    pub id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// True for outputs backed by an offscreen buffer rather than a scanout.
+    pub offscreen: bool,
+}
+
+/// Input latency aggregated by the compositor, over all windows and per window.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompositorStats {
+    pub latency: PipelineLatency,
+    pub windows: Vec<WindowLatency>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowLatency {
+    pub window_id: WindowId,
+    pub title: String,
+    pub latency: PipelineLatency,
+}
+
+/// How a wallpaper is laid out on an output of a different size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WallpaperFit {
+    /// At its own size, in the middle, cropped if larger; the background color shows around it.
+    Center,
+    /// Scaled to fit inside the output, keeping its aspect ratio.
+    Fit,
+    /// Scaled to cover the output, keeping its aspect ratio; the overhang is cropped.
+    Fill,
+    /// Scaled to the output's size, whatever its aspect ratio.
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The primary output's mode and the desktop's appearance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayInfo {
     pub width: u32,
     pub height: u32,
+    /// Modes `SetDisplayMode` accepts; only the current one when `mode_switching` is false.
+    pub modes: Vec<DisplayMode>,
+    pub mode_switching: bool,
+    /// `#RRGGBB`, under the wallpaper if there is one.
+    pub background_color: String,
+    /// VFS path of the wallpaper shown, empty for none.
+    pub wallpaper: String,
+    pub fit: WallpaperFit,
+    /// The UI scale in percent: 100, 150 or 200.
+    pub scale_percent: u16,
 }
@@ -3,15 +3,37 @@
 #![no_std]
 
 extern crate alloc;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::string::String;
 use alloc::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::block_ipc::BlockError;
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ipc::session_ipc::AidBytes;
+
 // Placeholder for File Descriptor type
 pub type Fd = u32;
 
+/// Identifies a transaction started with `VfsRequest::TxBegin`.
+pub type TxId = u64;
+
+/// Identifies a stream started with `ReadStream` or `WriteStream`.
+pub type StreamId = u64;
+
+/// Most data carried by one `StreamChunk` or `StreamData`, so the whole
+/// message fits in one IPC message with room for its other fields.
+pub const STREAM_CHUNK_SIZE: usize = crate::ipc::vnode::MAX_MESSAGE_SIZE - 64;
+
+/// Chunks a stream may have in flight: a read stream starts with this much
+/// credit, and a writer may send this many chunks ahead of the VFS's acks.
+pub const STREAM_WINDOW: u32 = 8;
+
+/// `len` for `ReadStream` to read to the end of the file.
+pub const TO_EOF: u64 = u64::MAX;
+
 // Placeholder for VFS metadata structure
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VfsMetadata {
@@ -20,9 +42,162 @@ pub struct VfsMetadata {
     pub created: u64, // Unix timestamp
     pub modified: u64,
     pub permissions: u32, // e.g., 0o755
+    /// Bytes with storage behind them. Less than `size` for a sparse file,
+    /// whose holes read as zeros without taking space.
+    pub allocated_size: u64,
     // Add more fields as needed
 }
 
+/// What `VfsRequest::Allocate` does to its range.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AllocateMode {
+    /// Make the range read as zeros, freeing the blocks wholly inside it.
+    ZeroRange,
+    /// Back the range with storage, charged to the owner's quota now, so
+    /// later writes to it can't fail for lack of space. Contents are unchanged.
+    Reserve,
+}
+
+/// Storage accounted to one owner, reported by `VfsRequest::GetUsage`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct VfsUsage {
+    pub owner: AidBytes,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+    pub file_count: u64,
+}
+
+/// The storage's health record, reported by `VfsRequest::StorageHealth`.
+/// The counts are kept across boots.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StorageHealth {
+    /// Reads and writes sent to the device, retries not counted.
+    pub reads: u64,
+    pub writes: u64,
+    /// Operations that failed at first and were tried again.
+    pub retried: u64,
+    /// Operations that still failed after their last retry.
+    pub unrecoverable: u64,
+    /// When a device operation last failed, retried or not, in UTC epoch
+    /// seconds. 0 if none ever has.
+    pub last_error: u64,
+    /// A write failed this boot, and the VFS has refused changes since.
+    pub read_only: bool,
+}
+
+/// Event bus topic the VFS publishes a `StorageFault` on when the storage
+/// fails an operation for good for the first time.
+pub const STORAGE_ERROR_TOPIC: &str = "storage.error";
+/// Event bus topic the VFS publishes a `StorageFault` on when a failed
+/// write makes it refuse further changes.
+pub const STORAGE_DEGRADED_TOPIC: &str = "storage.degraded";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageFault {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+    pub write: bool,
+    pub cause: BlockError,
+}
+
+/// Longest single path component, in bytes.
+pub const MAX_NAME_BYTES: usize = 255;
+
+/// Why a path or file name was rejected.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum NameError {
+    /// The path does not start with '/'.
+    NotAbsolute,
+    /// A component between two slashes is empty.
+    EmptyComponent,
+    /// A component is "." or "..".
+    RelativeComponent,
+    /// A component is longer than `MAX_NAME_BYTES`.
+    TooLong,
+    /// A component contains NUL or another control character.
+    ControlCharacter,
+    /// A name from a backend is not valid UTF-8.
+    InvalidUtf8,
+}
+
+fn validate_name(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        Err(NameError::EmptyComponent)
+    } else if name == "." || name == ".." {
+        Err(NameError::RelativeComponent)
+    } else if name.len() > MAX_NAME_BYTES {
+        Err(NameError::TooLong)
+    } else if name.chars().any(char::is_control) {
+        Err(NameError::ControlCharacter)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that `path` is absolute and every component is a valid name.
+/// A single trailing slash is allowed.
+pub fn validate_path(path: &str) -> Result<(), NameError> {
+    let rest = path.strip_prefix('/').ok_or(NameError::NotAbsolute)?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    if rest.is_empty() {
+        return Ok(()); // The root directory
+    }
+    rest.split('/').try_for_each(validate_name)
+}
+
+/// Decodes a directory entry name reported by a backend as raw bytes. Names
+/// that are not valid UTF-8 are rejected rather than lossily converted, so two
+/// distinct on-disk names can never collapse into one entry.
+pub fn name_from_bytes(bytes: &[u8]) -> Result<&str, NameError> {
+    let name = core::str::from_utf8(bytes).map_err(|_| NameError::InvalidUtf8)?;
+    validate_name(name)?;
+    Ok(name)
+}
+
+/// Extended attributes of one file, by name.
+pub type Xattrs = BTreeMap<String, Vec<u8>>;
+
+/// Longest extended attribute name, namespace prefix included, in bytes.
+pub const MAX_XATTR_NAME_BYTES: usize = 255;
+/// Largest value of a single extended attribute.
+pub const MAX_XATTR_VALUE_BYTES: usize = 1024;
+/// Most bytes of names and values one file may carry, all attributes together.
+pub const MAX_XATTR_BYTES_PER_FILE: usize = 4096;
+
+/// The package that installed a file, stamped by the registry.
+pub const XATTR_PACKAGE: &str = "system.package";
+/// The content ID of a file's data, hex-encoded.
+pub const XATTR_CID: &str = "system.cid";
+/// The MIME type of a file's data, e.g. "text/html".
+pub const XATTR_MIME_TYPE: &str = "user.mime_type";
+
+/// Who may change an extended attribute, by the prefix of its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XattrNamespace {
+    /// `user.*`: anyone who may write the file. Copies keep them.
+    User,
+    /// `system.*`: only the system identity. Copies drop them, moves keep them.
+    System,
+}
+
+/// The namespace of an extended attribute name, or `None` if the name has
+/// no known namespace, nothing after it, a control character, or is longer
+/// than `MAX_XATTR_NAME_BYTES`.
+pub fn xattr_namespace(name: &str) -> Option<XattrNamespace> {
+    if name.len() > MAX_XATTR_NAME_BYTES || name.chars().any(char::is_control) {
+        return None;
+    }
+    let (namespace, rest) = if let Some(rest) = name.strip_prefix("user.") {
+        (XattrNamespace::User, rest)
+    } else if let Some(rest) = name.strip_prefix("system.") {
+        (XattrNamespace::System, rest)
+    } else {
+        return None;
+    };
+    if rest.is_empty() { None } else { Some(namespace) }
+}
+
 /// Represents requests from client V-Nodes to the VFS V-Node.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum VfsRequest {
@@ -44,6 +219,76 @@ pub enum VfsRequest {
     CreateDirectory { path: String },
     /// Move/rename a file or directory.
     Move { source: String, destination: String },
+    /// Flush all buffered writes of an open file to the backend before replying.
+    Fsync { fd: Fd },
+    /// Flush all buffered writes of every file to the backend before replying.
+    SyncAll,
+    /// Scrape the VFS metrics, including the write-back cache counters.
+    Metrics(MetricsRequest),
+    /// Pin the file in memory for `SYS_MAP_FILE` by the calling task.
+    Pin { fd: Fd },
+    /// Release a pin obtained with `Pin`.
+    Unpin { backing: u64 },
+    /// Get the storage used by `owner` (the caller's own identity if `None`).
+    /// Only the system identity may ask about someone else.
+    GetUsage { owner: Option<AidBytes> },
+    /// Start a transaction. Changes made in it become visible to others only on `TxCommit`.
+    TxBegin,
+    /// Apply every change made in the transaction at once, or none of them.
+    TxCommit { id: TxId },
+    /// Discard the transaction's changes.
+    TxAbort { id: TxId },
+    /// Run `request` inside the transaction. Supported: Open, Read, Write, Close,
+    /// Stat, Delete, CreateDirectory, Move, SetXattr and RemoveXattr; fds
+    /// opened this way stay in the transaction and can be used directly afterwards.
+    InTx { id: TxId, request: Box<VfsRequest> },
+    /// Start pushing up to `len` bytes from `offset` as `StreamChunk`s, as
+    /// fast as the stream's credit allows. Answered with `StreamStarted`.
+    ReadStream { fd: Fd, offset: u64, len: u64 },
+    /// Allow a read stream `chunks` more chunks. Not answered.
+    StreamCredit { stream_id: StreamId, chunks: u32 },
+    /// Start accepting `StreamData` to write from `offset`. Answered with `StreamStarted`.
+    WriteStream { fd: Fd, offset: u64 },
+    /// The next piece of a write stream, numbered from 0. Not answered one by
+    /// one: the VFS sends `StreamAck` as it goes and `StreamFinished` after `eof`.
+    StreamData { stream_id: StreamId, seq: u64, data: Vec<u8>, eof: bool },
+    /// Set extended attribute `name` of `path`, replacing any value it had.
+    /// `system.*` names may only be set by the system identity.
+    SetXattr { path: String, name: String, value: Vec<u8> },
+    /// Get one extended attribute. Answered with `Data`.
+    GetXattr { path: String, name: String },
+    /// Get the names of every extended attribute of `path`. Answered with `XattrNames`.
+    ListXattrs { path: String },
+    /// Remove one extended attribute. Same permissions as `SetXattr`.
+    RemoveXattr { path: String, name: String },
+    /// `Stat`, with the attributes in `names` the file has. Answered with `MetadataWithXattrs`.
+    StatWithXattrs { path: String, names: Vec<String> },
+    /// `List`, with the attributes in `names` each entry has. Answered with
+    /// `DirectoryEntriesWithXattrs`.
+    ListWithXattrs { path: String, names: Vec<String> },
+    /// Take an advisory lock on the file behind `fd`: shared, or exclusive if
+    /// `exclusive` is set. With `wait`, a conflicting lock parks the caller
+    /// until it is granted, in the order the waits arrived; without it the
+    /// answer is `WouldBlock`. Locking the same fd again changes the mode.
+    Lock { fd: Fd, exclusive: bool, wait: bool },
+    /// Release the lock held through `fd`. Closing the fd does the same.
+    Unlock { fd: Fd },
+    /// Push a `Changed` to `reply_chan` after every change to `path` or,
+    /// for a directory, anything below it, until `Unwatch` or the caller exits.
+    Watch { path: String, reply_chan: u32 },
+    /// End a watch set up with `Watch`.
+    Unwatch { path: String, reply_chan: u32 },
+    /// Zero or reserve `len` bytes from `offset` (see `AllocateMode`). A range
+    /// past the end of the file extends it, the new part reading as zeros.
+    Allocate { fd: Fd, offset: u64, len: u64, mode: AllocateMode },
+    /// The first offset at or after `offset` that holds data. Answered with
+    /// `Offset`; at or past the end of the file, with error 6 (ENXIO).
+    SeekData { fd: Fd, offset: u64 },
+    /// The first offset at or after `offset` that is in a hole. The end of the
+    /// file counts as one. Answered like `SeekData`.
+    SeekHole { fd: Fd, offset: u64 },
+    /// The storage's health record. Answered with `StorageHealth`.
+    StorageHealth,
 }
 
 /// Represents responses from the VFS V-Node to client V-Nodes.
@@ -65,4 +310,66 @@ pub enum VfsResponse {
     CreateDirectorySuccess,
     /// Indicates successful move/rename.
     MoveSuccess,
+    /// Answers `Metrics`.
+    Metrics(MetricsResponse),
+    /// The request touches a home directory but the calling task has no identity bound.
+    Unauthenticated,
+    /// The file is pinned; map it with `SYS_MAP_FILE(backing, 0, size)`.
+    Pinned { backing: u64, size: u64 },
+    /// The path is malformed or contains an invalid name.
+    InvalidName { path: String, reason: NameError },
+    /// The write or move would take the owner over its quota. Nothing was changed.
+    QuotaExceeded { owner: AidBytes, used: u64, limit: u64 },
+    /// Returns storage usage.
+    Usage(VfsUsage),
+    /// The transaction was started.
+    TxBegun { id: TxId },
+    /// Answers `ReadStream` and `WriteStream`.
+    StreamStarted { stream_id: StreamId },
+    /// The next piece of a read stream. `eof` is set on the last one, which may be empty.
+    StreamChunk { stream_id: StreamId, seq: u64, data: Vec<u8>, eof: bool },
+    /// A write stream's chunks up to and including `seq` are written,
+    /// `written` bytes in total.
+    StreamAck { stream_id: StreamId, seq: u64, written: u64 },
+    /// A write stream is complete.
+    StreamFinished { stream_id: StreamId, written: u64 },
+    /// A stream stopped at its first error; nothing more is sent or accepted for it.
+    StreamError { stream_id: StreamId, code: i32, message: String },
+    /// Answers `ListXattrs`.
+    XattrNames(Vec<String>),
+    /// Answers `StatWithXattrs`.
+    MetadataWithXattrs { metadata: VfsMetadata, xattrs: Xattrs },
+    /// Answers `ListWithXattrs`.
+    DirectoryEntriesWithXattrs(BTreeMap<String, (VfsMetadata, Xattrs)>),
+    /// A `Lock` without `wait` conflicts with a lock someone holds or waits for.
+    WouldBlock,
+    /// Waiting for the `Lock` would never end: the caller itself holds a lock
+    /// on the file that is in the way, or asked to upgrade a shared lock
+    /// others hold too.
+    Deadlock,
+    /// Pushed to a watch's channel: `path` was changed and is now `size`
+    /// bytes; 0 if it was deleted or moved away.
+    Changed { path: String, size: u64 },
+    /// Answers `SeekData` and `SeekHole`.
+    Offset(u64),
+    /// The device couldn't read `len` bytes at `offset` of `path`, even
+    /// after retries, and nothing was read. The range is as narrow as the
+    /// VFS could make it; the rest of the file may still read.
+    MediaError { path: String, offset: u64, len: u64, cause: BlockError },
+    /// Answers `StorageHealth`.
+    StorageHealth(StorageHealth),
+}
+
+impl VfsResponse {
+    /// The stream a pushed message belongs to. Those arrive unasked,
+    /// in between the replies to requests.
+    pub fn stream_id(&self) -> Option<StreamId> {
+        match self {
+            VfsResponse::StreamChunk { stream_id, .. }
+            | VfsResponse::StreamAck { stream_id, .. }
+            | VfsResponse::StreamFinished { stream_id, .. }
+            | VfsResponse::StreamError { stream_id, .. } => Some(*stream_id),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,63 @@
+// common/src/ipc/vfs_lock.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use crate::ipc::vfs_tx::describe;
+use crate::ipc::vnode::VNodeChannel;
+
+/// Runs `f` while holding an advisory lock on `path`, exclusive or shared,
+/// waiting for it if someone else holds it. The lock is released when `f`
+/// returns, error or not.
+///
+/// The locks only keep out writers that take them too: everything that
+/// rewrites a file shared between tasks should go through here, typically
+/// with a `VfsTx` inside `f`.
+pub fn with_lock<R>(chan: &mut VNodeChannel, path: &str, exclusive: bool, f: impl FnOnce(&mut VNodeChannel) -> Result<R, String>) -> Result<R, String> {
+    with_locks(chan, &[path], exclusive, f)
+}
+
+/// `with_lock` for several files. They are locked in path order, so two
+/// tasks locking overlapping sets can't each end up waiting for the other.
+pub fn with_locks<R>(chan: &mut VNodeChannel, paths: &[&str], exclusive: bool, f: impl FnOnce(&mut VNodeChannel) -> Result<R, String>) -> Result<R, String> {
+    let mut paths: Vec<&str> = paths.to_vec();
+    paths.sort_unstable();
+    paths.dedup();
+
+    let mut fds: Vec<Fd> = Vec::with_capacity(paths.len());
+    let mut locked = Ok(());
+    for path in paths {
+        match lock(chan, path, exclusive) {
+            Ok(fd) => fds.push(fd),
+            Err(e) => {
+                locked = Err(e);
+                break;
+            },
+        }
+    }
+    let result = locked.and_then(|()| f(chan));
+    // Closing an fd releases its lock.
+    for fd in fds.into_iter().rev() {
+        let _ = chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+    }
+    result
+}
+
+/// Opens `path`, creating it if needed, and waits for a lock on it.
+fn lock(chan: &mut VNodeChannel, path: &str, exclusive: bool) -> Result<Fd, String> {
+    let fd = match chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as Fd,
+        other => return Err(describe(other)),
+    };
+    match chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Lock { fd, exclusive, wait: true }) {
+        Ok(VfsResponse::Success(_)) => Ok(fd),
+        other => {
+            let _ = chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+            Err(alloc::format!("Cannot lock {}: {}", path, describe(other)))
+        },
+    }
+}
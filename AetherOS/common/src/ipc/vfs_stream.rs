@@ -0,0 +1,213 @@
+// common/src/ipc/vfs_stream.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ipc::vfs_ipc::{Fd, StreamId, VfsRequest, VfsResponse, STREAM_CHUNK_SIZE, STREAM_WINDOW};
+use crate::ipc::vnode::VNodeChannel;
+use crate::ipc::IpcSend;
+
+/// Streamed reads and writes over a VFS channel.
+///
+/// The VFS pushes stream messages in between its replies, so everything that
+/// talks to the VFS on this channel while a stream is open has to go through
+/// `request` here: it sets aside stream messages that arrive while it waits,
+/// for the stream they belong to. Several streams can be open at once.
+///
+/// ```ignore
+/// let mut streams = VfsStreams::new(&mut vfs_chan);
+/// let reader = streams.open_read(src_fd, 0, TO_EOF)?;
+/// let writer = streams.open_write(dest_fd, 0)?;
+/// while let Some(chunk) = streams.read(reader)? {
+///     streams.write(writer, chunk)?;
+/// }
+/// let copied = streams.finish(writer)?;
+/// ```
+pub struct VfsStreams<'a> {
+    chan: &'a mut VNodeChannel,
+    inbox: BTreeMap<StreamId, VecDeque<VfsResponse>>,
+    readers: BTreeMap<StreamId, Reader>,
+    writers: BTreeMap<StreamId, Writer>,
+}
+
+struct Reader {
+    consumed: u32, // Chunks taken since credit was last returned
+    done: bool,
+}
+
+struct Writer {
+    next_seq: u64,
+    acked: Option<u64>, // Highest acked chunk
+}
+
+impl<'a> VfsStreams<'a> {
+    pub fn new(chan: &'a mut VNodeChannel) -> Self {
+        Self { chan, inbox: BTreeMap::new(), readers: BTreeMap::new(), writers: BTreeMap::new() }
+    }
+
+    /// Sends a request and waits for its reply, keeping stream messages that arrive first.
+    pub fn request(&mut self, request: &VfsRequest) -> Result<VfsResponse, String> {
+        self.chan.send(request).map_err(|_| "Failed to send to VFS".to_string())?;
+        loop {
+            let message = self.recv()?;
+            match message.stream_id() {
+                Some(id) => self.inbox.entry(id).or_default().push_back(message),
+                None => return Ok(message),
+            }
+        }
+    }
+
+    /// Starts reading `len` bytes (or `TO_EOF`) of `fd` from `offset`.
+    pub fn open_read(&mut self, fd: Fd, offset: u64, len: u64) -> Result<StreamId, String> {
+        let id = self.start(&VfsRequest::ReadStream { fd, offset, len })?;
+        self.readers.insert(id, Reader { consumed: 0, done: false });
+        Ok(id)
+    }
+
+    /// The next chunk of a read stream, or `None` after the last one.
+    pub fn read(&mut self, id: StreamId) -> Result<Option<Vec<u8>>, String> {
+        match self.readers.get(&id) {
+            Some(reader) if reader.done => return Ok(None),
+            Some(_) => {},
+            None => return Err(format!("No read stream {}", id)),
+        }
+        let (data, eof) = match self.next_for(id)? {
+            VfsResponse::StreamChunk { data, eof, .. } => (data, eof),
+            other => {
+                self.readers.remove(&id);
+                return Err(describe(other));
+            },
+        };
+        let reader = self.readers.get_mut(&id).expect("checked above");
+        if eof {
+            reader.done = true;
+        } else {
+            // Credit goes back in batches, so the VFS never runs dry and we send few messages.
+            reader.consumed += 1;
+            if reader.consumed >= STREAM_WINDOW / 2 {
+                let chunks = core::mem::take(&mut reader.consumed);
+                self.chan.send(&VfsRequest::StreamCredit { stream_id: id, chunks }).map_err(|_| "Failed to send to VFS".to_string())?;
+            }
+        }
+        Ok(if data.is_empty() && eof { None } else { Some(data) })
+    }
+
+    /// Reads the rest of a stream, failing if it is longer than `max_len`.
+    pub fn read_to_end(&mut self, id: StreamId, max_len: usize) -> Result<Vec<u8>, String> {
+        let mut contents = Vec::new();
+        while let Some(chunk) = self.read(id)? {
+            if contents.len() + chunk.len() > max_len {
+                self.readers.remove(&id);
+                return Err(format!("File is larger than {} bytes", max_len));
+            }
+            contents.extend_from_slice(&chunk);
+        }
+        self.readers.remove(&id);
+        Ok(contents)
+    }
+
+    /// Starts writing to `fd` from `offset`.
+    pub fn open_write(&mut self, fd: Fd, offset: u64) -> Result<StreamId, String> {
+        let id = self.start(&VfsRequest::WriteStream { fd, offset })?;
+        self.writers.insert(id, Writer { next_seq: 0, acked: None });
+        Ok(id)
+    }
+
+    /// Queues `data` on a write stream, in pieces of at most `STREAM_CHUNK_SIZE`.
+    /// Only waits when a window's worth of chunks hasn't been acked yet.
+    pub fn write(&mut self, id: StreamId, data: Vec<u8>) -> Result<(), String> {
+        if data.len() <= STREAM_CHUNK_SIZE {
+            return self.send_data(id, data, false);
+        }
+        for piece in data.chunks(STREAM_CHUNK_SIZE) {
+            self.send_data(id, piece.to_vec(), false)?;
+        }
+        Ok(())
+    }
+
+    /// Ends a write stream and waits until everything is written. Returns the bytes written.
+    pub fn finish(&mut self, id: StreamId) -> Result<u64, String> {
+        self.send_data(id, Vec::new(), true)?;
+        let result = loop {
+            match self.next_for(id)? {
+                VfsResponse::StreamAck { .. } => continue,
+                VfsResponse::StreamFinished { written, .. } => break Ok(written),
+                other => break Err(describe(other)),
+            }
+        };
+        self.writers.remove(&id);
+        result
+    }
+
+    fn send_data(&mut self, id: StreamId, data: Vec<u8>, eof: bool) -> Result<(), String> {
+        let seq = match self.writers.get(&id) {
+            Some(writer) => writer.next_seq,
+            None => return Err(format!("No write stream {}", id)),
+        };
+        // Wait for room in the window. An error the VFS already sent surfaces here.
+        while seq >= self.writers[&id].acked.map_or(0, |acked| acked + 1) + STREAM_WINDOW as u64 {
+            match self.next_for(id)? {
+                VfsResponse::StreamAck { seq: acked, .. } => self.writers.get_mut(&id).expect("checked above").acked = Some(acked),
+                other => {
+                    self.writers.remove(&id);
+                    return Err(describe(other));
+                },
+            }
+        }
+        if let Some(error) = self.take_error(id) {
+            self.writers.remove(&id);
+            return Err(error);
+        }
+        self.chan.send(&VfsRequest::StreamData { stream_id: id, seq, data, eof }).map_err(|_| "Failed to send to VFS".to_string())?;
+        self.writers.get_mut(&id).expect("checked above").next_seq += 1;
+        Ok(())
+    }
+
+    /// Removes a queued `StreamError` for `id`, if one has arrived.
+    fn take_error(&mut self, id: StreamId) -> Option<String> {
+        let queue = self.inbox.get_mut(&id)?;
+        let index = queue.iter().position(|message| matches!(message, VfsResponse::StreamError { .. }))?;
+        queue.remove(index).map(describe)
+    }
+
+    fn start(&mut self, request: &VfsRequest) -> Result<StreamId, String> {
+        match self.request(request)? {
+            VfsResponse::StreamStarted { stream_id } => Ok(stream_id),
+            other => Err(describe(other)),
+        }
+    }
+
+    /// The next message for stream `id`: one set aside earlier, or the next to arrive for it.
+    fn next_for(&mut self, id: StreamId) -> Result<VfsResponse, String> {
+        if let Some(message) = self.inbox.get_mut(&id).and_then(VecDeque::pop_front) {
+            return Ok(message);
+        }
+        loop {
+            let message = self.recv()?;
+            match message.stream_id() {
+                Some(other) if other == id => return Ok(message),
+                Some(other) => self.inbox.entry(other).or_default().push_back(message),
+                None => return Err(format!("Unexpected reply from VFS while streaming: {:?}", message)),
+            }
+        }
+    }
+
+    fn recv(&mut self) -> Result<VfsResponse, String> {
+        let data = self.chan.recv_blocking().map_err(|_| "No response from VFS".to_string())?;
+        postcard::from_bytes(&data).map_err(|_| "Malformed response from VFS".to_string())
+    }
+}
+
+fn describe(response: VfsResponse) -> String {
+    match response {
+        VfsResponse::StreamError { message, .. } | VfsResponse::Error { message, .. } => message,
+        VfsResponse::QuotaExceeded { used, limit, .. } => format!("Storage quota exceeded ({} of {} bytes used)", used, limit),
+        VfsResponse::Unauthenticated => "Not authenticated".to_string(),
+        other => format!("Unexpected response from VFS: {:?}", other),
+    }
+}
@@ -0,0 +1,123 @@
+// common/src/ipc/vfs_tx.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ipc::vfs_ipc::{TxId, VfsRequest, VfsResponse};
+use crate::ipc::vnode::VNodeChannel;
+
+/// A VFS transaction, for services that need several files to change together.
+///
+/// Nothing done through it is visible to other clients until `commit` succeeds.
+/// Dropping it without committing aborts it, so an early `?` return never
+/// leaves half of the changes behind.
+pub struct VfsTx<'a> {
+    chan: &'a mut VNodeChannel,
+    id: TxId,
+    finished: bool,
+}
+
+impl<'a> VfsTx<'a> {
+    pub fn begin(chan: &'a mut VNodeChannel) -> Result<Self, String> {
+        match chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::TxBegin) {
+            Ok(VfsResponse::TxBegun { id }) => Ok(Self { chan, id, finished: false }),
+            other => Err(describe(other)),
+        }
+    }
+
+    pub fn id(&self) -> TxId {
+        self.id
+    }
+
+    fn request(&mut self, request: VfsRequest) -> Result<VfsResponse, String> {
+        self.chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::InTx { id: self.id, request: Box::new(request) })
+            .map_err(|_| "No response from VFS".to_string())
+    }
+
+    /// Replaces the contents of `path` with `data`.
+    pub fn write_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), String> {
+        let fd = match self.request(VfsRequest::Open { path: path.to_string(), flags: 1 /* O_WRONLY | O_CREAT | O_TRUNC */ })? {
+            VfsResponse::Success(fd) => fd as u32,
+            other => return Err(describe(Ok(other))),
+        };
+        let written = self.request(VfsRequest::Write { fd, data, offset: 0 });
+        let _ = self.request(VfsRequest::Close { fd });
+        match written? {
+            VfsResponse::Success(_) => Ok(()),
+            other => Err(describe(Ok(other))),
+        }
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), String> {
+        match self.request(VfsRequest::Delete { path: path.to_string() })? {
+            VfsResponse::DeleteSuccess => Ok(()),
+            other => Err(describe(Ok(other))),
+        }
+    }
+
+    pub fn create_directory(&mut self, path: &str) -> Result<(), String> {
+        match self.request(VfsRequest::CreateDirectory { path: path.to_string() })? {
+            VfsResponse::CreateDirectorySuccess => Ok(()),
+            other => Err(describe(Ok(other))),
+        }
+    }
+
+    pub fn rename(&mut self, source: &str, destination: &str) -> Result<(), String> {
+        match self.request(VfsRequest::Move { source: source.to_string(), destination: destination.to_string() })? {
+            VfsResponse::MoveSuccess => Ok(()),
+            other => Err(describe(Ok(other))),
+        }
+    }
+
+    /// Sets an extended attribute of `path`. Its limits are checked on commit.
+    pub fn set_xattr(&mut self, path: &str, name: &str, value: Vec<u8>) -> Result<(), String> {
+        match self.request(VfsRequest::SetXattr { path: path.to_string(), name: name.to_string(), value })? {
+            VfsResponse::Success(_) => Ok(()),
+            other => Err(describe(Ok(other))),
+        }
+    }
+
+    /// Makes every change visible at once. On error nothing was changed.
+    pub fn commit(mut self) -> Result<(), String> {
+        self.finished = true;
+        match self.chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::TxCommit { id: self.id }) {
+            Ok(VfsResponse::Success(_)) => Ok(()),
+            other => Err(describe(other)),
+        }
+    }
+
+    pub fn abort(mut self) {
+        self.send_abort();
+    }
+
+    fn send_abort(&mut self) {
+        self.finished = true;
+        let _ = self.chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::TxAbort { id: self.id });
+    }
+}
+
+impl Drop for VfsTx<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.send_abort();
+        }
+    }
+}
+
+pub(crate) fn describe(response: Result<VfsResponse, ()>) -> String {
+    match response {
+        Ok(VfsResponse::Error { message, .. }) => message,
+        Ok(VfsResponse::QuotaExceeded { used, limit, .. }) => format!("Storage quota exceeded ({} of {} bytes used)", used, limit),
+        Ok(VfsResponse::Unauthenticated) => "Not authenticated".to_string(),
+        Ok(VfsResponse::InvalidName { path, reason }) => format!("Invalid path {}: {:?}", path, reason),
+        Ok(VfsResponse::WouldBlock) => "The file is locked".to_string(),
+        Ok(VfsResponse::Deadlock) => "Waiting for the lock would deadlock".to_string(),
+        Ok(_) => "Unexpected response from VFS".to_string(),
+        Err(_) => "No response from VFS".to_string(),
+    }
+}
@@ -4,18 +4,80 @@
 
 extern crate alloc;
 
+use alloc::format;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::abi::{SYS_ABI_VERSION, SYS_LOG, E_UNKNOWN_SYSCALL, MIN_KERNEL_ABI_VERSION};
+use crate::abi::{IpcCall, IpcCreds, SYS_IPC_CALL, SYS_IPC_REPLY, SYS_IPC_REPLY_TOKEN, E_BUSY};
 use crate::ipc::{IpcSend, IpcRecv};
+use crate::ipc::session_ipc;
 use crate::syscall::{syscall3, SYS_IPC_SEND, SYS_IPC_RECV, SYS_IPC_RECV_NONBLOCKING, SUCCESS, E_ERROR};
 
+static ABI_CHECKED: AtomicBool = AtomicBool::new(false);
+/// Set once the kernel turns out to predate `SYS_IPC_CALL`; `send_and_recv`
+/// then sends and receives separately.
+static IPC_CALL_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+static ROUND_TRIPS: AtomicU64 = AtomicU64::new(0);
+
+/// Request/reply round trips this task has started, through `send_and_recv`
+/// or `envelope::call`. Take it before and after some work to count what
+/// that work cost in IPC.
+pub fn round_trips() -> u64 {
+    ROUND_TRIPS.load(Ordering::Relaxed)
+}
+
+/// Counts one round trip; called once per request, whether or not it gets an answer.
+pub(crate) fn count_round_trip() {
+    ROUND_TRIPS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Names a call received with `recv_call`, for answering it with `reply`.
+pub type ReplyToken = u64;
+
+/// The reply token of the message just received; `None` if it wasn't a
+/// call, or the kernel predates calls.
+fn last_reply_token() -> Option<ReplyToken> {
+    match unsafe { syscall3(SYS_IPC_REPLY_TOKEN, 0, 0, 0) } {
+        0 | E_UNKNOWN_SYSCALL => None,
+        token => Some(token),
+    }
+}
+
+/// Returns the kernel's syscall ABI version. Kernels that predate
+/// `SYS_ABI_VERSION` report version 0.
+pub fn kernel_abi_version() -> u64 {
+    match unsafe { syscall3(SYS_ABI_VERSION, 0, 0, 0) } {
+        E_UNKNOWN_SYSCALL => 0,
+        version => version,
+    }
+}
+
+/// Refuses to run on a kernel older than `min_version`. V-Nodes that rely on
+/// newer syscalls can call this from `_start` with their own minimum; every
+/// V-Node gets the library's minimum checked when it opens its first channel.
+pub fn require_kernel_abi(min_version: u64) {
+    let version = kernel_abi_version();
+    if version < min_version {
+        let msg = format!("Kernel syscall ABI {} is older than the required {}; refusing to run.", version, min_version);
+        unsafe { syscall3(SYS_LOG, msg.as_ptr() as u64, msg.len() as u64, 0); }
+        panic!("kernel ABI too old");
+    }
+}
+
+/// Largest message a channel receives.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
 pub struct VNodeChannel {
     pub id: u32,
-    buffer: [u8; 4096],
+    buffer: [u8; MAX_MESSAGE_SIZE],
 }
 
 impl VNodeChannel {
     pub fn new(id: u32) -> Self {
-        Self { id, buffer: [0; 4096] }
+        if !ABI_CHECKED.swap(true, Ordering::Relaxed) {
+            require_kernel_abi(MIN_KERNEL_ABI_VERSION);
+        }
+        Self { id, buffer: [0; MAX_MESSAGE_SIZE] }
     }
 
     pub fn recv_blocking(&mut self) -> Result<Vec<u8>, ()> {
@@ -70,10 +132,98 @@ impl VNodeChannel {
         }
     }
 
+    /// Receives the next message like `recv_blocking`, along with its reply
+    /// token if it is a call. A server that waits here lets a caller's
+    /// `SYS_IPC_CALL` switch straight to it. Answer with `reply`.
+    pub fn recv_call(&mut self) -> Result<(Vec<u8>, Option<ReplyToken>), ()> {
+        let data = self.recv_blocking()?;
+        Ok((data, last_reply_token()))
+    }
+
+    /// `recv_call` for run loops that poll.
+    pub fn recv_call_non_blocking(&mut self) -> Result<Option<(Vec<u8>, Option<ReplyToken>)>, ()> {
+        match self.recv_non_blocking()? {
+            Some(data) => Ok(Some((data, last_reply_token()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Receives the next message like `recv_blocking`, along with the
+    /// credentials the kernel stamped on it (see `session_ipc::last_creds`).
+    /// Services that check who is asking should use these rather than
+    /// anything the payload claims.
+    pub fn recv_with_creds(&mut self) -> Result<(Vec<u8>, IpcCreds), ()> {
+        let data = self.recv_blocking()?;
+        Ok((data, session_ipc::last_creds().ok_or(())?))
+    }
+
+    /// `recv_with_creds` for run loops that poll.
+    pub fn recv_with_creds_non_blocking(&mut self) -> Result<Option<(Vec<u8>, IpcCreds)>, ()> {
+        match self.recv_non_blocking()? {
+            Some(data) => Ok(Some((data, session_ipc::last_creds().ok_or(())?))),
+            None => Ok(None),
+        }
+    }
+
+    /// `recv_call_non_blocking` with the message's credentials.
+    pub fn recv_call_with_creds_non_blocking(&mut self) -> Result<Option<(Vec<u8>, Option<ReplyToken>, IpcCreds)>, ()> {
+        match self.recv_non_blocking()? {
+            Some(data) => Ok(Some((data, last_reply_token(), session_ipc::last_creds().ok_or(())?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Answers a message from `recv_call`: with `SYS_IPC_REPLY`, which switches
+    /// straight back to a waiting caller, if it was a call, and with a plain
+    /// send otherwise.
+    pub fn reply<T: serde::Serialize>(&mut self, token: Option<ReplyToken>, msg: &T) -> Result<(), ()> {
+        let serialized = postcard::to_allocvec(msg).map_err(|_| ())?;
+        self.reply_raw(token, &serialized)
+    }
+
+    /// `reply` with a message that is already serialized.
+    pub fn reply_raw(&mut self, token: Option<ReplyToken>, bytes: &[u8]) -> Result<(), ()> {
+        let token = match token {
+            Some(token) => token,
+            None => return self.send_raw(bytes),
+        };
+        let res = unsafe { syscall3(SYS_IPC_REPLY, token, bytes.as_ptr() as u64, bytes.len() as u64) };
+        if res == SUCCESS { Ok(()) } else { Err(()) }
+    }
+
+    /// Sends `request` and waits for the reply in one `SYS_IPC_CALL`. Returns
+    /// the kernel's error code on failure: `E_UNKNOWN_SYSCALL` from a kernel
+    /// without calls, `E_PEER_GONE` if the server exited before replying.
+    pub fn call_raw(&mut self, request: &[u8]) -> Result<Vec<u8>, u64> {
+        let mut call = IpcCall {
+            req: request.as_ptr() as u64,
+            req_len: request.len() as u64,
+            resp: self.buffer.as_mut_ptr() as u64,
+            resp_cap: self.buffer.len() as u64,
+            ..IpcCall::default()
+        };
+        loop {
+            let res = unsafe { syscall3(SYS_IPC_CALL, self.id as u64, &mut call as *mut IpcCall as u64, core::mem::size_of::<IpcCall>() as u64) };
+            match res {
+                SUCCESS => return Ok(self.buffer[..call.reply_len as usize].to_vec()),
+                E_BUSY => {}, // We were blocked until the reply; `call.token` now names the call
+                code => return Err(code),
+            }
+        }
+    }
+
     pub fn send_and_recv<Req: serde::Serialize, Resp: serde::de::DeserializeOwned>(
         &mut self, request: &Req
     ) -> Result<Resp, ()> {
         let serialized_request = postcard::to_allocvec(request).map_err(|_| ())?;
+        count_round_trip();
+        if !IPC_CALL_UNSUPPORTED.load(Ordering::Relaxed) {
+            match self.call_raw(&serialized_request) {
+                Ok(data) => return postcard::from_bytes(&data).map_err(|_| ()),
+                Err(E_UNKNOWN_SYSCALL) => IPC_CALL_UNSUPPORTED.store(true, Ordering::Relaxed),
+                Err(_) => return Err(()),
+            }
+        }
         self.send_raw(&serialized_request)?;
         
         // After sending, immediately try to receive the response.
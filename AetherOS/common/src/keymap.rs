@@ -0,0 +1,816 @@
+// common/src/keymap.rs
+
+//! Keyboard layouts as data, with dead keys and Compose.
+//!
+//! The kernel's built-in `keys::Layout` maps a key and its modifiers to one
+//! character, which is all a stateless translation can do. The display owner
+//! types with a `Keymap` and a `Composer` instead:
+//!
+//! *   A dead key types nothing by itself. It holds its accent until the
+//!     next key, and then types the accented character (´ then e is é), or
+//!     the accent followed by the character if the two don't combine
+//!     (´ then q is ´q). Space, or the same dead key again, types the accent
+//!     alone.
+//! *   The Compose key starts a sequence: the next characters typed are
+//!     looked up in the compose table (Compose, o, / is ø; Compose, -, -, .
+//!     is –). A sequence that can't complete any more is discarded, with the
+//!     key that broke it.
+//!
+//! Keymaps are text files, one per layout, that `axpkg initrd --keymaps`
+//! checks and ships as `/keymaps/<name>.keymap`. A layout is selected by
+//! name with the `keyboard.layout` setting, so adding one needs a file and
+//! no code. A line is a comment (`#`), blank, or one of:
+//!
+//! ```text
+//! altgr                      Right Alt is AltGr, for the third column
+//! key <KEY> <plain> [<shift> [<altgr>]]
+//! compose <sequence> <result>
+//! ```
+//!
+//! `<KEY>` is the name of a `KEY_*` constant of `common::keys` without the
+//! prefix, e.g. `Q` or `LEFT_BRACKET`, and only the keys that type
+//! something differently per layout can be mapped. A symbol is a single
+//! character, `U+XXXX` for one that is awkward to write (space, `#`), `none`,
+//! or `dead:<accent>` for a dead key (`grave`, `acute`, `circumflex`,
+//! `tilde`, `diaeresis`, `ring`, `cedilla`, `caron`). A key whose plain
+//! symbol is a lower case letter and whose shifted one is the same letter
+//! in upper case follows Caps Lock. `compose` lines add to the built-in
+//! table of common Latin sequences or replace one of its results; a
+//! sequence is its characters written together, up to `MAX_COMPOSE_LEN`.
+//!
+//! Enter, Tab, Space, Backspace, Escape and the keypad type the same on
+//! every layout and can't be mapped. Ctrl with a letter gives the matching
+//! control character, as with `Layout`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Bound;
+
+use crate::abi::KeyReport;
+use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use crate::ipc::vnode::VNodeChannel;
+use crate::keys::{self, MOD_ALT, MOD_ALTGR, MOD_CAPS_LOCK, MOD_CTRL, MOD_META, MOD_SHIFT};
+
+/// Where `axpkg initrd` puts the keymap files.
+pub const KEYMAP_DIR: &str = "/keymaps";
+pub const KEYMAP_EXTENSION: &str = "keymap";
+/// The setting that names the layout, e.g. "de".
+pub const LAYOUT_KEY: &str = "keyboard.layout";
+/// The setting that names the Compose key (see `compose_key_from_name`).
+pub const COMPOSE_KEY_SETTING: &str = "keyboard.compose_key";
+/// Longest compose sequence, not counting the Compose key.
+pub const MAX_COMPOSE_LEN: usize = 4;
+/// Largest keymap file `load` reads.
+pub const MAX_KEYMAP_BYTES: u32 = 64 * 1024;
+
+/// An accent a dead key holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Accent {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+    Ring,
+    Cedilla,
+    Caron,
+}
+
+impl Accent {
+    pub const ALL: [Accent; 8] = [Accent::Grave, Accent::Acute, Accent::Circumflex, Accent::Tilde, Accent::Diaeresis, Accent::Ring, Accent::Cedilla, Accent::Caron];
+
+    /// Name after `dead:` in a keymap file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Accent::Grave => "grave",
+            Accent::Acute => "acute",
+            Accent::Circumflex => "circumflex",
+            Accent::Tilde => "tilde",
+            Accent::Diaeresis => "diaeresis",
+            Accent::Ring => "ring",
+            Accent::Cedilla => "cedilla",
+            Accent::Caron => "caron",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|accent| accent.name() == name)
+    }
+
+    /// What the accent types on its own.
+    pub fn spacing(self) -> char {
+        match self {
+            Accent::Grave => '`',
+            Accent::Acute => '´',
+            Accent::Circumflex => '^',
+            Accent::Tilde => '~',
+            Accent::Diaeresis => '¨',
+            Accent::Ring => '°',
+            Accent::Cedilla => '¸',
+            Accent::Caron => 'ˇ',
+        }
+    }
+
+    /// The character that stands for the accent in compose sequences, so
+    /// Compose, ', e is é. A dead key pressed during a sequence adds this.
+    pub fn compose_char(self) -> char {
+        match self {
+            Accent::Grave => '`',
+            Accent::Acute => '\'',
+            Accent::Circumflex => '^',
+            Accent::Tilde => '~',
+            Accent::Diaeresis => '"',
+            Accent::Ring => 'o',
+            Accent::Cedilla => ',',
+            Accent::Caron => 'c',
+        }
+    }
+
+    /// `base` with the accent, if Unicode has it as one character.
+    pub fn combine(self, base: char) -> Option<char> {
+        let (_, pairs) = COMBINED.iter().find(|(accent, _)| *accent == self)?;
+        let mut chars = pairs.chars();
+        while let (Some(plain), Some(accented)) = (chars.next(), chars.next()) {
+            if plain == base {
+                return Some(accented);
+            }
+        }
+        None
+    }
+}
+
+/// Each accent's letters, as pairs of the plain letter and the accented one.
+const COMBINED: &[(Accent, &str)] = &[
+    (Accent::Grave, "aàAÀeèEÈiìIÌoòOÒuùUÙ"),
+    (Accent::Acute, "aáAÁeéEÉiíIÍoóOÓuúUÚyýYÝcćCĆnńNŃsśSŚzźZŹ"),
+    (Accent::Circumflex, "aâAÂeêEÊiîIÎoôOÔuûUÛ"),
+    (Accent::Tilde, "aãAÃnñNÑoõOÕ"),
+    (Accent::Diaeresis, "aäAÄeëEËiïIÏoöOÖuüUÜyÿ"),
+    (Accent::Ring, "aåAÅuůUŮ"),
+    (Accent::Cedilla, "cçCÇsşSŞ"),
+    (Accent::Caron, "cčCČsšSŠzžZŽrřRŘeěEĚnňNŇ"),
+];
+
+/// Compose sequences beyond an accent and a letter, which are derived from
+/// `COMBINED` through `Accent::compose_char`.
+const LATIN_COMPOSE: &[(&str, char)] = &[
+    ("ss", 'ß'),
+    ("ae", 'æ'),
+    ("AE", 'Æ'),
+    ("oe", 'œ'),
+    ("OE", 'Œ'),
+    ("o/", 'ø'),
+    ("O/", 'Ø'),
+    ("oo", '°'),
+    ("co", '©'),
+    ("ro", '®'),
+    ("TM", '™'),
+    ("=e", '€'),
+    ("L-", '£'),
+    ("Y=", '¥'),
+    ("<<", '«'),
+    (">>", '»'),
+    ("!!", '¡'),
+    ("??", '¿'),
+    ("12", '½'),
+    ("14", '¼'),
+    ("34", '¾'),
+    ("xx", '×'),
+    (":-", '÷'),
+    ("+-", '±'),
+    ("--.", '–'),
+    ("---", '—'),
+];
+
+/// What a key types at one level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    Char(char),
+    Dead(Accent),
+}
+
+/// Where a compose sequence stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComposeMatch {
+    Complete(char),
+    /// Some longer sequence starts with it.
+    Prefix,
+    Invalid,
+}
+
+/// Compose sequences and their results. No sequence is a prefix of another,
+/// so a sequence is complete as soon as it matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComposeTable {
+    sequences: BTreeMap<String, char>,
+}
+
+impl ComposeTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The common Latin set: every accent with its letters, then `LATIN_COMPOSE`.
+    pub fn latin() -> Self {
+        let mut table = Self::new();
+        for (accent, pairs) in COMBINED {
+            let mut chars = pairs.chars();
+            while let (Some(plain), Some(accented)) = (chars.next(), chars.next()) {
+                let sequence: String = [accent.compose_char(), plain].into_iter().collect();
+                table.sequences.insert(sequence, accented);
+            }
+        }
+        for (sequence, result) in LATIN_COMPOSE {
+            table.sequences.insert(sequence.to_string(), *result);
+        }
+        table
+    }
+
+    /// Adds `sequence`, or replaces its result. Fails if it is empty, too
+    /// long, or a prefix of another sequence or the other way round.
+    pub fn insert(&mut self, sequence: &str, result: char) -> Result<(), String> {
+        let len = sequence.chars().count();
+        if len == 0 || len > MAX_COMPOSE_LEN {
+            return Err(format!("sequence '{}' must be 1 to {} characters", sequence, MAX_COMPOSE_LEN));
+        }
+        if !self.sequences.contains_key(sequence) {
+            let clash = self.sequences.keys()
+                .find(|other| other.starts_with(sequence) || sequence.starts_with(other.as_str()));
+            if let Some(other) = clash {
+                return Err(format!("sequence '{}' clashes with '{}'", sequence, other));
+            }
+        }
+        self.sequences.insert(sequence.to_string(), result);
+        Ok(())
+    }
+
+    pub fn lookup(&self, sequence: &str) -> ComposeMatch {
+        if let Some(result) = self.sequences.get(sequence) {
+            return ComposeMatch::Complete(*result);
+        }
+        // Sequences starting with `sequence` sort right after it.
+        match self.sequences.range::<str, _>((Bound::Excluded(sequence), Bound::Unbounded)).next() {
+            Some((next, _)) if next.starts_with(sequence) => ComposeMatch::Prefix,
+            _ => ComposeMatch::Invalid,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+}
+
+/// Keys a keymap file can map, by the name it uses for them.
+const KEY_NAMES: &[(&str, u16)] = &[
+    ("A", keys::KEY_A), ("B", keys::KEY_B), ("C", keys::KEY_C), ("D", keys::KEY_D),
+    ("E", keys::KEY_E), ("F", keys::KEY_F), ("G", keys::KEY_G), ("H", keys::KEY_H),
+    ("I", keys::KEY_I), ("J", keys::KEY_J), ("K", keys::KEY_K), ("L", keys::KEY_L),
+    ("M", keys::KEY_M), ("N", keys::KEY_N), ("O", keys::KEY_O), ("P", keys::KEY_P),
+    ("Q", keys::KEY_Q), ("R", keys::KEY_R), ("S", keys::KEY_S), ("T", keys::KEY_T),
+    ("U", keys::KEY_U), ("V", keys::KEY_V), ("W", keys::KEY_W), ("X", keys::KEY_X),
+    ("Y", keys::KEY_Y), ("Z", keys::KEY_Z),
+    ("1", keys::KEY_1), ("2", keys::KEY_2), ("3", keys::KEY_3), ("4", keys::KEY_4),
+    ("5", keys::KEY_5), ("6", keys::KEY_6), ("7", keys::KEY_7), ("8", keys::KEY_8),
+    ("9", keys::KEY_9), ("0", keys::KEY_0),
+    ("MINUS", keys::KEY_MINUS),
+    ("EQUAL", keys::KEY_EQUAL),
+    ("LEFT_BRACKET", keys::KEY_LEFT_BRACKET),
+    ("RIGHT_BRACKET", keys::KEY_RIGHT_BRACKET),
+    ("BACKSLASH", keys::KEY_BACKSLASH),
+    ("NON_US_HASH", keys::KEY_NON_US_HASH),
+    ("SEMICOLON", keys::KEY_SEMICOLON),
+    ("APOSTROPHE", keys::KEY_APOSTROPHE),
+    ("GRAVE", keys::KEY_GRAVE),
+    ("COMMA", keys::KEY_COMMA),
+    ("DOT", keys::KEY_DOT),
+    ("SLASH", keys::KEY_SLASH),
+    ("NON_US_BACKSLASH", keys::KEY_NON_US_BACKSLASH),
+];
+
+/// Values of the `keyboard.compose_key` setting and the keys they name.
+const COMPOSE_KEYS: &[(&str, Option<u16>)] = &[
+    ("menu", Some(keys::KEY_MENU)),
+    ("none", None),
+    ("right_alt", Some(keys::KEY_RIGHT_ALT)),
+    ("right_ctrl", Some(keys::KEY_RIGHT_CTRL)),
+    ("right_meta", Some(keys::KEY_RIGHT_META)),
+];
+
+/// The Compose key a `keyboard.compose_key` value names: `Some(None)` for
+/// "none", `None` for a value that isn't one.
+pub fn compose_key_from_name(name: &str) -> Option<Option<u16>> {
+    COMPOSE_KEYS.iter().find(|(known, _)| *known == name).map(|(_, keycode)| *keycode)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    UnknownDirective { line: usize, word: String },
+    UnknownKey { line: usize, name: String },
+    DuplicateKey { line: usize, name: String },
+    /// A `key` line with no symbol or more than three.
+    SymbolCount { line: usize },
+    BadSymbol { line: usize, token: String },
+    /// A `compose` line that isn't a sequence and one character.
+    BadCompose { line: usize, reason: String },
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownDirective { line, word } => write!(f, "line {}: unknown directive '{}'", line, word),
+            Self::UnknownKey { line, name } => write!(f, "line {}: no mappable key '{}'", line, name),
+            Self::DuplicateKey { line, name } => write!(f, "line {}: key '{}' is mapped twice", line, name),
+            Self::SymbolCount { line } => write!(f, "line {}: expected 1 to 3 symbols", line),
+            Self::BadSymbol { line, token } => write!(f, "line {}: invalid symbol '{}'", line, token),
+            Self::BadCompose { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+/// `U+XXXX` or a single character.
+fn parse_char(token: &str) -> Option<char> {
+    if let Some(hex) = token.strip_prefix("U+") {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    let mut chars = token.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn parse_symbol(token: &str) -> Option<Option<Symbol>> {
+    if token == "none" {
+        return Some(None);
+    }
+    if let Some(accent) = token.strip_prefix("dead:") {
+        return Accent::from_name(accent).map(|accent| Some(Symbol::Dead(accent)));
+    }
+    parse_char(token).map(|c| Some(Symbol::Char(c)))
+}
+
+/// One layout: what each key types at each level, and the compose table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    name: String,
+    /// Right Alt selects the third level instead of acting as Alt.
+    pub altgr: bool,
+    keys: BTreeMap<u16, [Option<Symbol>; 3]>, // Plain, Shift, AltGr
+    compose: ComposeTable,
+}
+
+impl Keymap {
+    /// A keymap that maps nothing, for `name`. Only the fixed keys type.
+    pub fn empty(name: &str) -> Self {
+        Self { name: name.to_string(), altgr: false, keys: BTreeMap::new(), compose: ComposeTable::latin() }
+    }
+
+    /// Parses the text form of the layout called `name`.
+    pub fn parse(name: &str, source: &str) -> Result<Self, KeymapError> {
+        let mut keymap = Self::empty(name);
+        for (i, line) in source.lines().enumerate() {
+            let line_no = i + 1;
+            let mut words = line.split_whitespace();
+            let Some(directive) = words.next() else { continue };
+            if directive.starts_with('#') {
+                continue;
+            }
+            let args: Vec<&str> = words.collect();
+            match directive {
+                "altgr" if args.is_empty() => keymap.altgr = true,
+                "key" => {
+                    let Some((key_name, symbols)) = args.split_first() else {
+                        return Err(KeymapError::SymbolCount { line: line_no });
+                    };
+                    let keycode = KEY_NAMES.iter().find(|(known, _)| known == key_name).map(|(_, keycode)| *keycode)
+                        .ok_or_else(|| KeymapError::UnknownKey { line: line_no, name: key_name.to_string() })?;
+                    if symbols.is_empty() || symbols.len() > 3 {
+                        return Err(KeymapError::SymbolCount { line: line_no });
+                    }
+                    let mut levels = [None; 3];
+                    for (level, token) in symbols.iter().enumerate() {
+                        levels[level] = parse_symbol(token).ok_or_else(|| KeymapError::BadSymbol { line: line_no, token: token.to_string() })?;
+                    }
+                    if keymap.keys.insert(keycode, levels).is_some() {
+                        return Err(KeymapError::DuplicateKey { line: line_no, name: key_name.to_string() });
+                    }
+                },
+                "compose" => {
+                    let [sequence, result] = args[..] else {
+                        return Err(KeymapError::BadCompose { line: line_no, reason: String::from("expected 'compose <sequence> <result>'") });
+                    };
+                    let result = parse_char(result).ok_or_else(|| KeymapError::BadSymbol { line: line_no, token: result.to_string() })?;
+                    keymap.compose.insert(sequence, result).map_err(|reason| KeymapError::BadCompose { line: line_no, reason })?;
+                },
+                _ => return Err(KeymapError::UnknownDirective { line: line_no, word: directive.to_string() }),
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn compose(&self) -> &ComposeTable {
+        &self.compose
+    }
+
+    /// What `keycode` types with `modifiers` held, as the display owner
+    /// computed them (Right Alt is `MOD_ALTGR` only if the keymap says so).
+    pub fn translate(&self, keycode: u16, modifiers: u8) -> Option<Symbol> {
+        let levels = self.keys.get(&keycode);
+        if modifiers & MOD_ALTGR != 0 {
+            return levels?[2];
+        }
+        let plain = levels.and_then(|levels| levels[0]);
+        if modifiers & MOD_CTRL != 0 {
+            return match plain {
+                Some(Symbol::Char(c)) if c.is_ascii_lowercase() => Some(Symbol::Char((c as u8 - b'a' + 1) as char)),
+                _ => None,
+            };
+        }
+        if let Some(c) = keys::fixed_char(keycode) {
+            return Some(Symbol::Char(c));
+        }
+        let levels = levels?;
+        let mut shift = modifiers & MOD_SHIFT != 0;
+        // Caps Lock inverts Shift for letters only.
+        if modifiers & MOD_CAPS_LOCK != 0 && self.is_letter(levels) {
+            shift = !shift;
+        }
+        if shift { levels[1] } else { levels[0] }
+    }
+
+    fn is_letter(&self, levels: &[Option<Symbol>; 3]) -> bool {
+        match (levels[0], levels[1]) {
+            (Some(Symbol::Char(lower)), Some(Symbol::Char(upper))) => lower.is_lowercase() && lower.to_uppercase().eq(core::iter::once(upper)),
+            _ => false,
+        }
+    }
+}
+
+/// A dead key or Compose sequence waiting for more keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pending {
+    Nothing,
+    Dead(Accent),
+    Compose(String),
+}
+
+/// Turns the key events of `SYS_INPUT_READ` into text with a `Keymap`,
+/// keeping the state dead keys and Compose need between keys.
+#[derive(Debug, Clone)]
+pub struct Composer {
+    keymap: Keymap,
+    compose_key: Option<u16>,
+    pending: Pending,
+    left_alt: bool,
+    right_alt: bool,
+    held: Option<(u16, Option<String>)>, // Last key pressed and what it typed, for its repeats
+}
+
+impl Composer {
+    pub fn new(keymap: Keymap, compose_key: Option<u16>) -> Self {
+        Self { keymap, compose_key, pending: Pending::Nothing, left_alt: false, right_alt: false, held: None }
+    }
+
+    /// Switches layouts. A pending dead key or sequence is dropped, since it
+    /// was typed on the old layout.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+        self.reset();
+    }
+
+    pub fn set_compose_key(&mut self, compose_key: Option<u16>) {
+        self.compose_key = compose_key;
+        self.reset();
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Whether a dead key or Compose sequence is waiting for more keys.
+    pub fn is_pending(&self) -> bool {
+        self.pending != Pending::Nothing
+    }
+
+    /// Drops whatever is pending, e.g. when focus moves to another window.
+    pub fn reset(&mut self) {
+        self.pending = Pending::Nothing;
+        self.held = None;
+    }
+
+    /// The report's modifiers, with Alt and AltGr worked out for this keymap
+    /// rather than the kernel's layout.
+    fn modifiers(&self, report: &KeyReport) -> u8 {
+        let mut modifiers = report.modifiers & !(MOD_ALT | MOD_ALTGR);
+        let right_alt = self.right_alt && self.compose_key != Some(keys::KEY_RIGHT_ALT);
+        if self.left_alt || (right_alt && !self.keymap.altgr) {
+            modifiers |= MOD_ALT;
+        }
+        if right_alt && self.keymap.altgr {
+            modifiers |= MOD_ALTGR;
+        }
+        modifiers
+    }
+
+    /// Feeds one key event. Returns the text it types, if any: nothing for
+    /// releases, modifiers, dead keys and keys that extend a sequence, and up
+    /// to two characters when a dead key doesn't combine.
+    pub fn feed(&mut self, report: &KeyReport) -> Option<String> {
+        match report.keycode {
+            keys::KEY_LEFT_ALT => self.left_alt = report.pressed,
+            keys::KEY_RIGHT_ALT => self.right_alt = report.pressed,
+            _ => {},
+        }
+        if !report.pressed {
+            return None;
+        }
+        // A repeat types what its press did: an accented letter repeats as
+        // such, and a dead key or a key swallowed by a sequence adds nothing.
+        if report.repeat {
+            return match &self.held {
+                Some((keycode, text)) if *keycode == report.keycode => text.clone(),
+                _ => None,
+            };
+        }
+        let text = self.press(report);
+        self.held = Some((report.keycode, text.clone()));
+        text
+    }
+
+    fn press(&mut self, report: &KeyReport) -> Option<String> {
+        let keycode = report.keycode;
+        if Some(keycode) == self.compose_key {
+            // Compose again starts over.
+            self.pending = Pending::Compose(String::new());
+            return None;
+        }
+        if keys::is_modifier(keycode) || keys::is_lock(keycode) {
+            return None;
+        }
+        let modifiers = self.modifiers(report);
+        let pending = core::mem::replace(&mut self.pending, Pending::Nothing);
+        if pending != Pending::Nothing {
+            // Escape and Backspace cancel; a shortcut drops the accent or
+            // sequence and goes through as usual.
+            if matches!(keycode, keys::KEY_ESCAPE | keys::KEY_BACKSPACE) {
+                return None;
+            }
+            if modifiers & (MOD_CTRL | MOD_ALT | MOD_META) != 0 {
+                return self.type_symbol(self.keymap.translate(keycode, modifiers));
+            }
+        }
+        let symbol = self.keymap.translate(keycode, modifiers);
+        match pending {
+            Pending::Nothing => self.type_symbol(symbol),
+            Pending::Dead(accent) => match symbol {
+                // Keys that type nothing, like the arrows, drop the accent.
+                None => None,
+                Some(Symbol::Dead(next)) if next == accent => Some(accent.spacing().to_string()),
+                Some(Symbol::Dead(next)) => {
+                    self.pending = Pending::Dead(next);
+                    Some(accent.spacing().to_string())
+                },
+                Some(Symbol::Char(' ')) => Some(accent.spacing().to_string()),
+                Some(Symbol::Char(c)) => Some(match accent.combine(c) {
+                    Some(combined) => combined.to_string(),
+                    None => [accent.spacing(), c].into_iter().collect(),
+                }),
+            },
+            Pending::Compose(mut sequence) => {
+                let c = match symbol {
+                    Some(Symbol::Char(c)) => c,
+                    Some(Symbol::Dead(accent)) => accent.compose_char(),
+                    None => return None,
+                };
+                sequence.push(c);
+                match self.keymap.compose.lookup(&sequence) {
+                    ComposeMatch::Complete(result) => Some(result.to_string()),
+                    ComposeMatch::Prefix => {
+                        self.pending = Pending::Compose(sequence);
+                        None
+                    },
+                    ComposeMatch::Invalid => None,
+                }
+            },
+        }
+    }
+
+    fn type_symbol(&mut self, symbol: Option<Symbol>) -> Option<String> {
+        match symbol? {
+            Symbol::Char(c) => Some(c.to_string()),
+            Symbol::Dead(accent) => {
+                self.pending = Pending::Dead(accent);
+                None
+            },
+        }
+    }
+}
+
+/// Reads and parses `/keymaps/<name>.keymap` through the VFS.
+pub fn load(vfs_chan: &mut VNodeChannel, name: &str) -> Result<Keymap, String> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(format!("invalid layout '{}'", name));
+    }
+    let path = format!("{}/{}.{}", KEYMAP_DIR, name, KEYMAP_EXTENSION);
+    let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.clone(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as u32,
+        Ok(VfsResponse::Error { message, .. }) => return Err(format!("{}: {}", path, message)),
+        _ => return Err(format!("{}: unexpected response from VFS", path)),
+    };
+    let data = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_KEYMAP_BYTES, offset: 0 });
+    let _ = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+    match data {
+        Ok(VfsResponse::Data(bytes)) => {
+            let source = core::str::from_utf8(&bytes).map_err(|_| format!("{}: not valid UTF-8", path))?;
+            Keymap::parse(name, source).map_err(|e| format!("{}: {}", path, e))
+        },
+        Ok(VfsResponse::Error { message, .. }) => Err(format!("{}: {}", path, message)),
+        _ => Err(format!("{}: unexpected response from VFS", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::*;
+
+    const DE: &str = include_str!("../../keymaps/de.keymap");
+    const US: &str = include_str!("../../keymaps/us.keymap");
+
+    fn de() -> Composer {
+        Composer::new(Keymap::parse("de", DE).unwrap(), Some(KEY_MENU))
+    }
+
+    fn us() -> Composer {
+        Composer::new(Keymap::parse("us", US).unwrap(), Some(KEY_MENU))
+    }
+
+    fn report(keycode: u16, pressed: bool, repeat: bool, modifiers: u8) -> KeyReport {
+        KeyReport { keycode, pressed, repeat, modifiers, text: None, captured_at: 0 }
+    }
+
+    /// Presses and releases `keycode`, returning what the press typed.
+    fn tap(composer: &mut Composer, keycode: u16, modifiers: u8) -> Option<String> {
+        let text = composer.feed(&report(keycode, true, false, modifiers));
+        assert_eq!(composer.feed(&report(keycode, false, false, modifiers)), None);
+        text
+    }
+
+    /// Taps each key without modifiers and returns what each one typed.
+    fn taps(composer: &mut Composer, keycodes: &[u16]) -> Vec<Option<String>> {
+        keycodes.iter().map(|keycode| tap(composer, *keycode, 0)).collect()
+    }
+
+    fn typed(text: &str) -> Option<String> {
+        Some(String::from(text))
+    }
+
+    #[test]
+    fn shipped_keymaps_parse() {
+        let de = Keymap::parse("de", DE).unwrap();
+        assert!(de.altgr);
+        assert_eq!(de.translate(KEY_Y, 0), Some(Symbol::Char('z')));
+        assert_eq!(de.translate(KEY_EQUAL, 0), Some(Symbol::Dead(Accent::Acute)));
+        assert_eq!(de.translate(KEY_E, MOD_ALTGR), Some(Symbol::Char('\u{20AC}')));
+        let us = Keymap::parse("us", US).unwrap();
+        assert!(!us.altgr);
+        assert_eq!(us.translate(KEY_3, MOD_SHIFT), Some(Symbol::Char('#')));
+    }
+
+    #[test]
+    fn letters_follow_caps_lock_and_ctrl_gives_controls() {
+        let de = Keymap::parse("de", DE).unwrap();
+        assert_eq!(de.translate(KEY_A, MOD_CAPS_LOCK), Some(Symbol::Char('A')));
+        assert_eq!(de.translate(KEY_A, MOD_CAPS_LOCK | MOD_SHIFT), Some(Symbol::Char('a')));
+        assert_eq!(de.translate(KEY_SEMICOLON, MOD_CAPS_LOCK), Some(Symbol::Char('\u{D6}')));
+        assert_eq!(de.translate(KEY_1, MOD_CAPS_LOCK), Some(Symbol::Char('1')));
+        assert_eq!(de.translate(KEY_C, MOD_CTRL), Some(Symbol::Char('\u{3}')));
+        assert_eq!(de.translate(KEY_SPACE, MOD_SHIFT), Some(Symbol::Char(' ')));
+    }
+
+    #[test]
+    fn multi_step_compose() {
+        let mut composer = us();
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_O, KEY_SLASH]), [None, None, typed("\u{F8}")]);
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_MINUS, KEY_MINUS, KEY_DOT]), [None, None, None, typed("\u{2013}")]);
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_MINUS, KEY_MINUS, KEY_MINUS]), [None, None, None, typed("\u{2014}")]);
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_1, KEY_2]), [None, None, typed("\u{BD}")]);
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_APOSTROPHE, KEY_E]), [None, None, typed("\u{E9}")]);
+        assert!(!composer.is_pending());
+    }
+
+    #[test]
+    fn invalid_sequences_are_dropped_with_the_key_that_broke_them() {
+        let mut composer = us();
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_Q, KEY_Q]), [None, None, typed("q")]);
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_MINUS, KEY_X, KEY_X]), [None, None, None, typed("x")]);
+        // Compose again starts over.
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_MINUS, KEY_MENU, KEY_O, KEY_SLASH]), [None, None, None, None, typed("\u{F8}")]);
+    }
+
+    #[test]
+    fn escape_backspace_and_shortcuts_cancel() {
+        let mut composer = de();
+        assert_eq!(taps(&mut composer, &[KEY_MENU, KEY_O, KEY_ESCAPE, KEY_SLASH]), [None, None, None, typed("-")]);
+        assert_eq!(taps(&mut composer, &[KEY_EQUAL, KEY_BACKSPACE, KEY_E]), [None, None, typed("e")]);
+        assert_eq!(tap(&mut composer, KEY_EQUAL, 0), None);
+        assert_eq!(tap(&mut composer, KEY_C, MOD_CTRL), typed("\u{3}"));
+        assert!(!composer.is_pending());
+        assert_eq!(tap(&mut composer, KEY_E, 0), typed("e"));
+    }
+
+    #[test]
+    fn dead_keys_with_shift() {
+        let mut composer = de();
+        assert_eq!(tap(&mut composer, KEY_EQUAL, 0), None);
+        assert_eq!(tap(&mut composer, KEY_E, MOD_SHIFT), typed("\u{C9}"));
+        assert_eq!(tap(&mut composer, KEY_EQUAL, MOD_SHIFT), None);
+        assert_eq!(tap(&mut composer, KEY_A, 0), typed("\u{E0}"));
+        assert_eq!(tap(&mut composer, KEY_EQUAL, 0), None);
+        assert_eq!(tap(&mut composer, KEY_Q, MOD_SHIFT), typed("\u{B4}Q"));
+        assert_eq!(taps(&mut composer, &[KEY_EQUAL, KEY_EQUAL]), [None, typed("\u{B4}")]);
+        assert_eq!(taps(&mut composer, &[KEY_EQUAL, KEY_SPACE]), [None, typed("\u{B4}")]);
+        // A different dead key types the first accent and waits with its own.
+        assert_eq!(taps(&mut composer, &[KEY_EQUAL, KEY_GRAVE]), [None, typed("\u{B4}")]);
+        assert!(composer.is_pending());
+        assert_eq!(tap(&mut composer, KEY_O, 0), typed("\u{F4}"));
+    }
+
+    #[test]
+    fn repeats_type_what_the_press_did() {
+        let mut composer = de();
+        let held = |composer: &mut Composer, keycode| composer.feed(&report(keycode, true, true, 0));
+        assert_eq!(composer.feed(&report(KEY_EQUAL, true, false, 0)), None);
+        assert_eq!(held(&mut composer, KEY_EQUAL), None);
+        assert_eq!(held(&mut composer, KEY_EQUAL), None);
+        composer.feed(&report(KEY_EQUAL, false, false, 0));
+        assert_eq!(composer.feed(&report(KEY_E, true, false, 0)), typed("\u{E9}"));
+        assert_eq!(held(&mut composer, KEY_E), typed("\u{E9}"));
+        assert_eq!(held(&mut composer, KEY_E), typed("\u{E9}"));
+        composer.feed(&report(KEY_E, false, false, 0));
+        // Repeats inside a sequence don't extend it.
+        assert_eq!(composer.feed(&report(KEY_MENU, true, false, 0)), None);
+        composer.feed(&report(KEY_MENU, false, false, 0));
+        assert_eq!(composer.feed(&report(KEY_SLASH, true, false, 0)), None);
+        assert_eq!(held(&mut composer, KEY_SLASH), None);
+        composer.feed(&report(KEY_SLASH, false, false, 0));
+        assert_eq!(tap(&mut composer, KEY_SLASH, 0), None);
+        assert_eq!(tap(&mut composer, KEY_DOT, 0), typed("\u{2013}"));
+    }
+
+    #[test]
+    fn switching_layouts_drops_pending_state() {
+        let mut composer = de();
+        assert_eq!(tap(&mut composer, KEY_EQUAL, 0), None);
+        composer.set_keymap(Keymap::parse("us", US).unwrap());
+        assert!(!composer.is_pending());
+        assert_eq!(tap(&mut composer, KEY_Y, 0), typed("y"));
+
+        let mut composer = de();
+        assert_eq!(composer.feed(&report(KEY_RIGHT_ALT, true, false, MOD_ALT)), None);
+        assert_eq!(tap(&mut composer, KEY_Q, MOD_ALT), typed("@"));
+        composer.set_keymap(Keymap::parse("us", US).unwrap());
+        assert_eq!(tap(&mut composer, KEY_Q, MOD_ALT), typed("q"));
+    }
+
+    #[test]
+    fn parse_errors_carry_the_line() {
+        let parse = |source| Keymap::parse("test", source).unwrap_err();
+        assert_eq!(parse("key FOO a"), KeymapError::UnknownKey { line: 1, name: String::from("FOO") });
+        assert_eq!(parse("key A a\nkey A b"), KeymapError::DuplicateKey { line: 2, name: String::from("A") });
+        assert_eq!(parse("\nkey A a A b c"), KeymapError::SymbolCount { line: 2 });
+        assert_eq!(parse("key A"), KeymapError::SymbolCount { line: 1 });
+        assert_eq!(parse("key A dead:breve"), KeymapError::BadSymbol { line: 1, token: String::from("dead:breve") });
+        assert_eq!(parse("shift A"), KeymapError::UnknownDirective { line: 1, word: String::from("shift") });
+        assert!(matches!(parse("compose -- x"), KeymapError::BadCompose { line: 1, .. }));
+        assert!(matches!(parse("compose --.. x"), KeymapError::BadCompose { line: 1, .. }));
+        assert!(matches!(parse("compose abcde x"), KeymapError::BadCompose { line: 1, .. }));
+        // Replacing a built-in result is fine.
+        let keymap = Keymap::parse("test", "# comment\n\ncompose ss U+1E9E").unwrap();
+        assert_eq!(keymap.compose().lookup("ss"), ComposeMatch::Complete('\u{1E9E}'));
+        assert_eq!(keymap.compose().lookup("-"), ComposeMatch::Prefix);
+        assert_eq!(keymap.compose().lookup("q"), ComposeMatch::Invalid);
+    }
+
+    #[test]
+    fn compose_key_names() {
+        assert_eq!(compose_key_from_name("menu"), Some(Some(KEY_MENU)));
+        assert_eq!(compose_key_from_name("none"), Some(None));
+        assert_eq!(compose_key_from_name("caps"), None);
+    }
+}
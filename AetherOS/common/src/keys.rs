@@ -0,0 +1,392 @@
+// common/src/keys.rs
+
+//! Keycodes, modifier bits, key combinations and keyboard layouts.
+//!
+//! Keycodes are USB HID usage IDs (page 7), whatever the keyboard actually
+//! speaks: the kernel's PS/2 driver translates scancodes into them, and
+//! `UiRequest::KeyEvent` carries them on. A keycode names a physical key by
+//! its position on a US keyboard; which character it types depends on the
+//! layout.
+//!
+//! `Layout` is the kernel's built-in translation, which fills in
+//! `KeyReport::text`. The display owner types with a `keymap::Keymap` loaded
+//! from the initrd instead, which adds dead keys and Compose (see
+//! `common::keymap`).
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+pub const KEY_A: u16 = 0x04;
+pub const KEY_B: u16 = 0x05;
+pub const KEY_C: u16 = 0x06;
+pub const KEY_D: u16 = 0x07;
+pub const KEY_E: u16 = 0x08;
+pub const KEY_F: u16 = 0x09;
+pub const KEY_G: u16 = 0x0A;
+pub const KEY_H: u16 = 0x0B;
+pub const KEY_I: u16 = 0x0C;
+pub const KEY_J: u16 = 0x0D;
+pub const KEY_K: u16 = 0x0E;
+pub const KEY_L: u16 = 0x0F;
+pub const KEY_M: u16 = 0x10;
+pub const KEY_N: u16 = 0x11;
+pub const KEY_O: u16 = 0x12;
+pub const KEY_P: u16 = 0x13;
+pub const KEY_Q: u16 = 0x14;
+pub const KEY_R: u16 = 0x15;
+pub const KEY_S: u16 = 0x16;
+pub const KEY_T: u16 = 0x17;
+pub const KEY_U: u16 = 0x18;
+pub const KEY_V: u16 = 0x19;
+pub const KEY_W: u16 = 0x1A;
+pub const KEY_X: u16 = 0x1B;
+pub const KEY_Y: u16 = 0x1C;
+pub const KEY_Z: u16 = 0x1D;
+pub const KEY_1: u16 = 0x1E;
+pub const KEY_2: u16 = 0x1F;
+pub const KEY_3: u16 = 0x20;
+pub const KEY_4: u16 = 0x21;
+pub const KEY_5: u16 = 0x22;
+pub const KEY_6: u16 = 0x23;
+pub const KEY_7: u16 = 0x24;
+pub const KEY_8: u16 = 0x25;
+pub const KEY_9: u16 = 0x26;
+pub const KEY_0: u16 = 0x27;
+pub const KEY_ENTER: u16 = 0x28;
+pub const KEY_ESCAPE: u16 = 0x29;
+pub const KEY_BACKSPACE: u16 = 0x2A;
+pub const KEY_TAB: u16 = 0x2B;
+pub const KEY_SPACE: u16 = 0x2C;
+pub const KEY_MINUS: u16 = 0x2D;
+pub const KEY_EQUAL: u16 = 0x2E;
+pub const KEY_LEFT_BRACKET: u16 = 0x2F;
+pub const KEY_RIGHT_BRACKET: u16 = 0x30;
+pub const KEY_BACKSLASH: u16 = 0x31;
+pub const KEY_NON_US_HASH: u16 = 0x32; // Next to Enter on ISO keyboards
+pub const KEY_SEMICOLON: u16 = 0x33;
+pub const KEY_APOSTROPHE: u16 = 0x34;
+pub const KEY_GRAVE: u16 = 0x35;
+pub const KEY_COMMA: u16 = 0x36;
+pub const KEY_DOT: u16 = 0x37;
+pub const KEY_SLASH: u16 = 0x38;
+pub const KEY_CAPS_LOCK: u16 = 0x39;
+pub const KEY_F1: u16 = 0x3A;
+pub const KEY_F2: u16 = 0x3B;
+pub const KEY_F3: u16 = 0x3C;
+pub const KEY_F4: u16 = 0x3D;
+pub const KEY_F5: u16 = 0x3E;
+pub const KEY_F6: u16 = 0x3F;
+pub const KEY_F7: u16 = 0x40;
+pub const KEY_F8: u16 = 0x41;
+pub const KEY_F9: u16 = 0x42;
+pub const KEY_F10: u16 = 0x43;
+pub const KEY_F11: u16 = 0x44;
+pub const KEY_F12: u16 = 0x45;
+pub const KEY_PRINT_SCREEN: u16 = 0x46;
+pub const KEY_SCROLL_LOCK: u16 = 0x47;
+pub const KEY_PAUSE: u16 = 0x48;
+pub const KEY_INSERT: u16 = 0x49;
+pub const KEY_HOME: u16 = 0x4A;
+pub const KEY_PAGE_UP: u16 = 0x4B;
+pub const KEY_DELETE: u16 = 0x4C;
+pub const KEY_END: u16 = 0x4D;
+pub const KEY_PAGE_DOWN: u16 = 0x4E;
+pub const KEY_RIGHT: u16 = 0x4F;
+pub const KEY_LEFT: u16 = 0x50;
+pub const KEY_DOWN: u16 = 0x51;
+pub const KEY_UP: u16 = 0x52;
+pub const KEY_NUM_LOCK: u16 = 0x53;
+pub const KEY_KP_SLASH: u16 = 0x54;
+pub const KEY_KP_ASTERISK: u16 = 0x55;
+pub const KEY_KP_MINUS: u16 = 0x56;
+pub const KEY_KP_PLUS: u16 = 0x57;
+pub const KEY_KP_ENTER: u16 = 0x58;
+pub const KEY_KP_1: u16 = 0x59;
+pub const KEY_KP_2: u16 = 0x5A;
+pub const KEY_KP_3: u16 = 0x5B;
+pub const KEY_KP_4: u16 = 0x5C;
+pub const KEY_KP_5: u16 = 0x5D;
+pub const KEY_KP_6: u16 = 0x5E;
+pub const KEY_KP_7: u16 = 0x5F;
+pub const KEY_KP_8: u16 = 0x60;
+pub const KEY_KP_9: u16 = 0x61;
+pub const KEY_KP_0: u16 = 0x62;
+pub const KEY_KP_DOT: u16 = 0x63;
+pub const KEY_NON_US_BACKSLASH: u16 = 0x64; // Next to left Shift on ISO keyboards
+pub const KEY_MENU: u16 = 0x65;
+pub const KEY_LEFT_CTRL: u16 = 0xE0;
+pub const KEY_LEFT_SHIFT: u16 = 0xE1;
+pub const KEY_LEFT_ALT: u16 = 0xE2;
+pub const KEY_LEFT_META: u16 = 0xE3;
+pub const KEY_RIGHT_CTRL: u16 = 0xE4;
+pub const KEY_RIGHT_SHIFT: u16 = 0xE5;
+pub const KEY_RIGHT_ALT: u16 = 0xE6;
+pub const KEY_RIGHT_META: u16 = 0xE7;
+
+/// Every keycode is below this, so a key set fits in a 256-bit map.
+pub const KEYCODE_LIMIT: u16 = 0x100;
+
+// Modifier bits, as held after the event.
+pub const MOD_SHIFT: u8 = 1 << 0;
+pub const MOD_CTRL: u8 = 1 << 1;
+pub const MOD_ALT: u8 = 1 << 2;
+pub const MOD_ALTGR: u8 = 1 << 3; // Right Alt on layouts that use it for a third level
+pub const MOD_META: u8 = 1 << 4;
+pub const MOD_CAPS_LOCK: u8 = 1 << 5; // Toggled, not held
+
+/// Shift, Ctrl, Alt and Meta on either side.
+pub fn is_modifier(keycode: u16) -> bool {
+    (KEY_LEFT_CTRL..=KEY_RIGHT_META).contains(&keycode)
+}
+
+/// The `MOD_*` bit a modifier key holds, for tracking modifiers from key
+/// events alone. Right Alt counts as Alt here, whatever the layout; 0 for
+/// other keys.
+pub fn modifier_bit(keycode: u16) -> u8 {
+    match keycode {
+        KEY_LEFT_SHIFT | KEY_RIGHT_SHIFT => MOD_SHIFT,
+        KEY_LEFT_CTRL | KEY_RIGHT_CTRL => MOD_CTRL,
+        KEY_LEFT_ALT | KEY_RIGHT_ALT => MOD_ALT,
+        KEY_LEFT_META | KEY_RIGHT_META => MOD_META,
+        _ => 0,
+    }
+}
+
+/// Lock keys toggle state instead of typing, so they don't repeat.
+pub fn is_lock(keycode: u16) -> bool {
+    matches!(keycode, KEY_CAPS_LOCK | KEY_NUM_LOCK | KEY_SCROLL_LOCK)
+}
+
+/// What the keys that type the same on every layout type, Shift or not.
+/// Ctrl with any of them types nothing.
+pub fn fixed_char(keycode: u16) -> Option<char> {
+    let c = match keycode {
+        KEY_ENTER | KEY_KP_ENTER => '\n',
+        KEY_ESCAPE => '\u{1b}',
+        KEY_BACKSPACE => '\u{8}',
+        KEY_TAB => '\t',
+        KEY_SPACE => ' ',
+        // The keypad types as if Num Lock were always on.
+        KEY_KP_SLASH => '/',
+        KEY_KP_ASTERISK => '*',
+        KEY_KP_MINUS => '-',
+        KEY_KP_PLUS => '+',
+        KEY_KP_DOT => '.',
+        KEY_KP_1..=KEY_KP_9 => char::from_digit((keycode - KEY_KP_1 + 1) as u32, 10)?,
+        KEY_KP_0 => '0',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// How keycodes turn into characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Us,
+    /// German QWERTZ. Dead keys type their accent directly; the `de` keymap
+    /// file makes them dead.
+    De,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 2] = [Layout::Us, Layout::De];
+
+    /// Number passed to `SYS_INPUT_CONFIG`.
+    pub fn id(self) -> u64 {
+        match self {
+            Layout::Us => 0,
+            Layout::De => 1,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| layout.id() == id)
+    }
+
+    /// Name used by the `keyboard.layout` setting.
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::De => "de",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    /// Right Alt is AltGr rather than a second Alt.
+    pub fn has_altgr(self) -> bool {
+        self == Layout::De
+    }
+
+    /// The character `keycode` types with `modifiers` held, if any. Ctrl with
+    /// a letter gives the matching control character (Ctrl+C is U+0003); Ctrl
+    /// with anything else types nothing.
+    pub fn translate(self, keycode: u16, modifiers: u8) -> Option<char> {
+        if modifiers & MOD_ALTGR != 0 {
+            return self.altgr(keycode);
+        }
+        let shift = modifiers & MOD_SHIFT != 0;
+        if let Some(letter) = self.letter(keycode) {
+            if modifiers & MOD_CTRL != 0 {
+                return letter.is_ascii_lowercase().then(|| (letter as u8 - b'a' + 1) as char);
+            }
+            // Caps Lock inverts Shift for letters only.
+            let upper = shift != (modifiers & MOD_CAPS_LOCK != 0);
+            return Some(if upper { letter.to_uppercase().next().unwrap_or(letter) } else { letter });
+        }
+        if modifiers & MOD_CTRL != 0 {
+            return None;
+        }
+        if let Some(c) = fixed_char(keycode) {
+            return Some(c);
+        }
+        let (plain, shifted) = self.symbol(keycode)?;
+        Some(if shift { shifted } else { plain })
+    }
+
+    /// Keys that Caps Lock affects, in lower case.
+    fn letter(self, keycode: u16) -> Option<char> {
+        match (self, keycode) {
+            (Layout::De, KEY_Y) => Some('z'),
+            (Layout::De, KEY_Z) => Some('y'),
+            (Layout::De, KEY_SEMICOLON) => Some('ö'),
+            (Layout::De, KEY_APOSTROPHE) => Some('ä'),
+            (Layout::De, KEY_LEFT_BRACKET) => Some('ü'),
+            (_, KEY_A..=KEY_Z) => Some((b'a' + (keycode - KEY_A) as u8) as char),
+            _ => None,
+        }
+    }
+
+    /// Unshifted and shifted character of the digit row and punctuation keys.
+    fn symbol(self, keycode: u16) -> Option<(char, char)> {
+        let pair = match self {
+            Layout::Us => match keycode {
+                KEY_1 => ('1', '!'),
+                KEY_2 => ('2', '@'),
+                KEY_3 => ('3', '#'),
+                KEY_4 => ('4', '$'),
+                KEY_5 => ('5', '%'),
+                KEY_6 => ('6', '^'),
+                KEY_7 => ('7', '&'),
+                KEY_8 => ('8', '*'),
+                KEY_9 => ('9', '('),
+                KEY_0 => ('0', ')'),
+                KEY_MINUS => ('-', '_'),
+                KEY_EQUAL => ('=', '+'),
+                KEY_LEFT_BRACKET => ('[', '{'),
+                KEY_RIGHT_BRACKET => (']', '}'),
+                KEY_BACKSLASH | KEY_NON_US_HASH | KEY_NON_US_BACKSLASH => ('\\', '|'),
+                KEY_SEMICOLON => (';', ':'),
+                KEY_APOSTROPHE => ('\'', '"'),
+                KEY_GRAVE => ('`', '~'),
+                KEY_COMMA => (',', '<'),
+                KEY_DOT => ('.', '>'),
+                KEY_SLASH => ('/', '?'),
+                _ => return None,
+            },
+            Layout::De => match keycode {
+                KEY_1 => ('1', '!'),
+                KEY_2 => ('2', '"'),
+                KEY_3 => ('3', '§'),
+                KEY_4 => ('4', '$'),
+                KEY_5 => ('5', '%'),
+                KEY_6 => ('6', '&'),
+                KEY_7 => ('7', '/'),
+                KEY_8 => ('8', '('),
+                KEY_9 => ('9', ')'),
+                KEY_0 => ('0', '='),
+                KEY_MINUS => ('ß', '?'),
+                KEY_EQUAL => ('´', '`'),
+                KEY_RIGHT_BRACKET => ('+', '*'),
+                KEY_BACKSLASH | KEY_NON_US_HASH => ('#', '\''),
+                KEY_GRAVE => ('^', '°'),
+                KEY_COMMA => (',', ';'),
+                KEY_DOT => ('.', ':'),
+                KEY_SLASH => ('-', '_'),
+                KEY_NON_US_BACKSLASH => ('<', '>'),
+                _ => return None,
+            },
+        };
+        Some(pair)
+    }
+
+    fn altgr(self, keycode: u16) -> Option<char> {
+        match (self, keycode) {
+            (Layout::De, KEY_Q) => Some('@'),
+            (Layout::De, KEY_E) => Some('€'),
+            (Layout::De, KEY_M) => Some('µ'),
+            (Layout::De, KEY_2) => Some('²'),
+            (Layout::De, KEY_3) => Some('³'),
+            (Layout::De, KEY_7) => Some('{'),
+            (Layout::De, KEY_8) => Some('['),
+            (Layout::De, KEY_9) => Some(']'),
+            (Layout::De, KEY_0) => Some('}'),
+            (Layout::De, KEY_MINUS) => Some('\\'),
+            (Layout::De, KEY_RIGHT_BRACKET) => Some('~'),
+            (Layout::De, KEY_NON_US_BACKSLASH) => Some('|'),
+            _ => None,
+        }
+    }
+}
+
+/// Modifiers a key combination can name, in the order `combo_name` writes them.
+pub const COMBO_MODIFIERS: [(u8, &str); 4] = [(MOD_CTRL, "Ctrl"), (MOD_ALT, "Alt"), (MOD_SHIFT, "Shift"), (MOD_META, "Meta")];
+
+/// Keys a key combination can name other than letters, digits and F1 to F12.
+const COMBO_KEYS: &[(&str, u16)] = &[
+    ("Enter", KEY_ENTER), ("Escape", KEY_ESCAPE), ("Backspace", KEY_BACKSPACE), ("Tab", KEY_TAB), ("Space", KEY_SPACE),
+    ("PrintScreen", KEY_PRINT_SCREEN), ("Pause", KEY_PAUSE), ("Insert", KEY_INSERT), ("Delete", KEY_DELETE),
+    ("Home", KEY_HOME), ("End", KEY_END), ("PageUp", KEY_PAGE_UP), ("PageDown", KEY_PAGE_DOWN),
+    ("Left", KEY_LEFT), ("Right", KEY_RIGHT), ("Up", KEY_UP), ("Down", KEY_DOWN), ("Menu", KEY_MENU),
+];
+
+/// The name of a key in a combination, e.g. "T", "5", "F4" or "PrintScreen".
+pub fn combo_key_name(keycode: u16) -> Option<String> {
+    match keycode {
+        KEY_A..=KEY_Z => Some(((b'A' + (keycode - KEY_A) as u8) as char).to_string()),
+        KEY_1..=KEY_9 => Some(((b'1' + (keycode - KEY_1) as u8) as char).to_string()),
+        KEY_0 => Some("0".to_string()),
+        KEY_F1..=KEY_F12 => Some(format!("F{}", keycode - KEY_F1 + 1)),
+        _ => COMBO_KEYS.iter().find(|(_, known)| *known == keycode).map(|(name, _)| name.to_string()),
+    }
+}
+
+/// Writes a key combination the way `parse_combo` reads it, e.g. "Ctrl+Alt+T".
+/// Keys without a name are written as their keycode in hex.
+pub fn combo_name(modifiers: u8, keycode: u16) -> String {
+    let mut name = String::new();
+    for (bit, modifier) in COMBO_MODIFIERS {
+        if modifiers & bit != 0 {
+            name.push_str(modifier);
+            name.push('+');
+        }
+    }
+    match combo_key_name(keycode) {
+        Some(key) => name.push_str(&key),
+        None => name.push_str(&format!("0x{:02X}", keycode)),
+    }
+    name
+}
+
+/// Parses a key combination such as "Alt+Tab" or "ctrl+shift+s": modifiers
+/// and then one key, joined by `+`, in any case. Returns the `MOD_*` bits and
+/// the keycode.
+pub fn parse_combo(text: &str) -> Option<(u8, u16)> {
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    let key = parts.pop()?;
+    let mut modifiers = 0;
+    for part in parts {
+        let (bit, _) = COMBO_MODIFIERS.iter().find(|(_, name)| name.eq_ignore_ascii_case(part))?;
+        modifiers |= bit;
+    }
+    let keycode = (0..KEYCODE_LIMIT).find(|&keycode| combo_key_name(keycode).map_or(false, |name| name.eq_ignore_ascii_case(key)))?;
+    Some((modifiers, keycode))
+}
@@ -0,0 +1,84 @@
+// common/src/klog.rs
+
+//! Reading the kernel log: `SYS_KLOG_READ`. Also receiving every `SYS_LOG`
+//! message as it is logged: `SYS_LOG_FORWARD`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::abi::{LastBootInfo, E_ACC_DENIED, E_BUSY, E_ERROR, KLOG_LAST_BOOT, LAST_BOOT_INFO_LEN, SUCCESS, SYS_KLOG_READ, SYS_LOG_FORWARD};
+use crate::syscall::syscall3;
+use crate::text;
+
+/// The log the previous boot left behind.
+#[derive(Debug, Clone)]
+pub struct LastBoot {
+    pub info: LastBootInfo,
+    pub text: String,
+}
+
+/// Why the log couldn't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KlogError {
+    /// The caller lacks `CAP_LOG_READ`.
+    Denied,
+    /// The kernel has no log from the previous boot.
+    NoLastBoot,
+}
+
+/// Why the kernel won't forward `SYS_LOG` messages to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardError {
+    /// The caller lacks `CAP_LOG_READ`, or the channel isn't one.
+    Denied,
+    /// Another task already receives them.
+    Busy,
+}
+
+/// Calls `SYS_KLOG_READ` with a buffer that grows until everything fits.
+fn read_raw(flags: u64) -> Result<Vec<u8>, KlogError> {
+    let mut buf = alloc::vec![0u8; 16 * 1024];
+    loop {
+        let res = unsafe { syscall3(SYS_KLOG_READ, buf.as_mut_ptr() as u64, buf.len() as u64, flags) };
+        match res {
+            E_ACC_DENIED => return Err(KlogError::Denied),
+            // A real last-boot result is never this short, so E_ERROR can't be mistaken for a length.
+            E_ERROR if flags & KLOG_LAST_BOOT != 0 => return Err(KlogError::NoLastBoot),
+            total if total as usize <= buf.len() => {
+                buf.truncate(total as usize);
+                return Ok(buf);
+            },
+            // The log is longer than the buffer; retry with room for what is printed meanwhile.
+            total => buf.resize(total as usize + 1024, 0),
+        }
+    }
+}
+
+/// This boot's kernel log, as much as the ring still holds. The oldest line
+/// may be cut.
+pub fn read() -> Result<String, KlogError> {
+    read_raw(0).map(|bytes| text::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The log recovered from the previous boot, if it left one.
+pub fn last_boot() -> Result<LastBoot, KlogError> {
+    let bytes = read_raw(KLOG_LAST_BOOT)?;
+    let info = LastBootInfo::from_bytes(&bytes).ok_or(KlogError::NoLastBoot)?;
+    let text = text::from_utf8_lossy(bytes.get(LAST_BOOT_INFO_LEN..).unwrap_or_default()).into_owned();
+    Ok(LastBoot { info, text })
+}
+
+/// Has the kernel queue a `LogRecord` on `channel` for every `SYS_LOG`
+/// message from now on, other than the caller's own. Only one task at a time
+/// receives them; calling again moves them to another channel.
+pub fn forward_to(channel: u32) -> Result<(), ForwardError> {
+    match unsafe { syscall3(SYS_LOG_FORWARD, channel as u64, 0, 0) } {
+        SUCCESS => Ok(()),
+        E_BUSY => Err(ForwardError::Busy),
+        _ => Err(ForwardError::Denied),
+    }
+}
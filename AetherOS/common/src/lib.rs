@@ -1,13 +1,39 @@
-#![no_std]
+// Host tools (tools/axpkg) build this crate with `std` and without `vnode`;
+// so do the unit tests.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 extern crate alloc;
 
 pub mod cid;
 pub mod manifest;
+pub mod capability;
+pub mod ax;
+pub mod bundle;
+pub mod initrd;
+pub mod semver;
 pub mod trust;
 pub mod arp_dht;
 pub mod swarm_engine;
 pub mod ipc;
+pub mod abi;
+pub mod text;
+pub mod glob;
+pub mod url;
+pub mod keys;
+pub mod keymap;
+pub mod ansi;
+pub mod metrics;
+pub mod time;
+pub mod ids;
+pub mod i18n;
+pub mod cmdline;
+pub mod debug;
+pub mod coredump;
+pub mod tasks;
+pub mod klog;
+pub mod startup;
+pub mod channels;
+pub mod random;
 pub mod syscall;
 
 // Temporarily include kernel and vnode modules for cross-crate access during development
@@ -15,20 +41,16 @@ pub mod syscall;
 pub mod kernel;
 pub mod vnode;
 
-pub mod socket_ipc;
-pub mod dns_ipc;
-pub mod init_ipc;
-pub mod vfs_ipc;
-pub mod shell_ipc;
-pub mod file_manager_ipc;
-pub mod mail_ipc;
-pub mod model_runtime_ipc;
+// The protocols are declared in `ipc`; these paths are kept for older users.
+pub use ipc::{socket_ipc, socket_client, dns_ipc, init_ipc, vfs_ipc, shell_ipc, file_manager_ipc, mail_ipc, model_runtime_ipc};
 
 // Explicitly declare and re-export nexus_net_transport module
+#[cfg(feature = "vnode")]
 pub mod nexus_net_transport;
+#[cfg(feature = "vnode")]
 pub use nexus_net_transport::*;
 
-pub mod ui_protocol;
+pub use ipc::ui_protocol;
 pub use ui_protocol::*;
 
 pub mod ui;
@@ -0,0 +1,437 @@
+// common/src/manifest.rs
+
+//! Package manifests: what a package contains, who published it, and the
+//! content addresses of its chunks.
+//!
+//! Manifests are made with `ManifestBuilder`, which splits each file into
+//! `CHUNK_SIZE` chunks and addresses them with `common::cid`. The root CID is
+//! the CID of the manifest's canonical encoding (everything but the root and
+//! the signature), and is what the publisher signs. Files and tags are
+//! sorted before encoding, so the same package always yields the same root
+//! regardless of the order it was described in.
+//!
+//! A package that runs as a service declares the capabilities and syscalls
+//! it needs (see `common::capability`). The declaration is covered by the
+//! root like everything else, but encoded as an optional section at the very
+//! end, after the signature in the canonical bytes. A manifest that declares
+//! nothing encodes exactly as manifests did before the section existed, so
+//! their roots and signatures stay valid.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::capability::{DeclaredCapabilities, Grant};
+use crate::cid::{compute_cid, Cid};
+use crate::trust::Aid;
+
+pub use crate::semver::{PreRelease, SemVer, VersionReq};
+
+/// Files are split into chunks of this size; the last chunk of a file may be shorter.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+pub const MAX_NAME_LEN: usize = 64;
+pub const MAX_TAG_LEN: usize = 32;
+pub const MAX_DESCRIPTION_LEN: usize = 1024;
+pub const MAX_PATH_LEN: usize = 255;
+const CID_LEN: usize = 32;
+
+/// A file in the package, stored as `chunk_count()` consecutive chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+impl FileEntry {
+    /// Chunks the file occupies. An empty file still has one (empty) chunk,
+    /// so every path has content to fetch.
+    pub fn chunk_count(&self) -> usize {
+        (self.size as usize).div_ceil(CHUNK_SIZE).max(1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: SemVer,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub publisher: Aid,
+    /// Sorted by path.
+    pub files: Vec<FileEntry>,
+    /// The chunks of every file, in `files` order.
+    pub chunk_cids: Vec<Cid>,
+    pub root_cid: Cid,
+    /// The publisher's signature over `root_cid`.
+    pub signature: Vec<u8>,
+    /// What the package's service asks to be granted; empty for packages
+    /// that don't run as one.
+    pub capabilities: DeclaredCapabilities,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestError {
+    InvalidName(String),
+    InvalidVersion(String),
+    InvalidTag(String),
+    DescriptionTooLong(usize),
+    NoFiles,
+    InvalidPath(String),
+    DuplicatePath(String),
+    /// `files` isn't sorted by path, so the encoding wouldn't be canonical.
+    UnsortedFiles,
+    /// `chunk_cids` doesn't have one entry per chunk of `files`.
+    ChunkCountMismatch { expected: usize, actual: usize },
+    /// `root_cid` isn't the CID of the manifest's contents.
+    RootMismatch,
+    /// The encoded manifest is truncated, has trailing bytes or bad UTF-8.
+    Malformed(&'static str),
+    /// A declared grant or syscall that can't be granted as written.
+    InvalidCapabilities(String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "invalid package name '{}'", name),
+            Self::InvalidVersion(reason) => write!(f, "invalid version: {}", reason),
+            Self::InvalidTag(tag) => write!(f, "invalid tag '{}'", tag),
+            Self::DescriptionTooLong(len) => write!(f, "description is {} bytes (max {})", len, MAX_DESCRIPTION_LEN),
+            Self::NoFiles => f.write_str("package has no files"),
+            Self::InvalidPath(path) => write!(f, "invalid file path '{}'", path),
+            Self::DuplicatePath(path) => write!(f, "duplicate file path '{}'", path),
+            Self::UnsortedFiles => f.write_str("files are not sorted by path"),
+            Self::ChunkCountMismatch { expected, actual } => write!(f, "expected {} chunk CIDs, found {}", expected, actual),
+            Self::RootMismatch => f.write_str("root CID does not match the manifest contents"),
+            Self::Malformed(what) => write!(f, "malformed manifest: {}", what),
+            Self::InvalidCapabilities(reason) => write!(f, "declared capabilities: {}", reason),
+        }
+    }
+}
+
+/// Lowercase ASCII letters, digits and `-`; starts with a letter, doesn't end with `-`.
+pub fn is_valid_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    !bytes.is_empty()
+        && bytes.len() <= MAX_NAME_LEN
+        && bytes[0].is_ascii_lowercase()
+        && bytes[bytes.len() - 1] != b'-'
+        && bytes.iter().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || *b == b'-')
+}
+
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.len() <= MAX_TAG_LEN && tag.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// A relative `/`-separated path with no empty, `.` or `..` components, so it
+/// can't escape the directory the package is installed into.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.len() <= MAX_PATH_LEN
+        && !path.contains(['\\', '\0'])
+        && path.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+impl PackageManifest {
+    /// Checks everything that can be checked without the chunk contents or
+    /// the publisher's key: syntax of the name, version, tags and paths, and
+    /// that the files, chunk list and root CID agree with each other.
+    pub fn validate(&self) -> Result<(), ManifestError> {
+        if !is_valid_name(&self.name) {
+            return Err(ManifestError::InvalidName(self.name.clone()));
+        }
+        if !self.version.build.is_empty() {
+            // Build metadata doesn't take part in ordering, so two packages
+            // differing only in it would be indistinguishable to the resolver.
+            return Err(ManifestError::InvalidVersion(alloc::format!("'{}': build metadata not allowed", self.version)));
+        }
+        if let Some(tag) = self.tags.iter().find(|tag| !is_valid_tag(tag)) {
+            return Err(ManifestError::InvalidTag(tag.clone()));
+        }
+        if self.description.len() > MAX_DESCRIPTION_LEN {
+            return Err(ManifestError::DescriptionTooLong(self.description.len()));
+        }
+        if self.files.is_empty() {
+            return Err(ManifestError::NoFiles);
+        }
+        if let Some(file) = self.files.iter().find(|file| !is_valid_path(&file.path)) {
+            return Err(ManifestError::InvalidPath(file.path.clone()));
+        }
+        for pair in self.files.windows(2) {
+            if pair[0].path == pair[1].path {
+                return Err(ManifestError::DuplicatePath(pair[1].path.clone()));
+            }
+            if pair[0].path > pair[1].path {
+                return Err(ManifestError::UnsortedFiles);
+            }
+        }
+        self.capabilities.check().map_err(ManifestError::InvalidCapabilities)?;
+        let expected = self.files.iter().map(FileEntry::chunk_count).sum();
+        if self.chunk_cids.len() != expected {
+            return Err(ManifestError::ChunkCountMismatch { expected, actual: self.chunk_cids.len() });
+        }
+        if compute_cid(&self.signed_bytes()) != self.root_cid {
+            return Err(ManifestError::RootMismatch);
+        }
+        Ok(())
+    }
+
+    /// The chunk CIDs of the file at `path`.
+    pub fn chunks_of(&self, path: &str) -> Option<&[Cid]> {
+        let mut start = 0;
+        for file in &self.files {
+            let end = start + file.chunk_count();
+            if file.path == path {
+                return self.chunk_cids.get(start..end);
+            }
+            start = end;
+        }
+        None
+    }
+
+    /// The canonical encoding of everything the root CID covers.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_str(&mut out, &self.name);
+        put_str(&mut out, &alloc::format!("{}", self.version));
+        put_str(&mut out, &self.description);
+        let mut tags: Vec<&String> = self.tags.iter().collect();
+        tags.sort();
+        put_u32(&mut out, tags.len() as u32);
+        for tag in tags {
+            put_str(&mut out, tag);
+        }
+        out.extend_from_slice(&self.publisher.0);
+        put_u32(&mut out, self.files.len() as u32);
+        for file in &self.files {
+            put_str(&mut out, &file.path);
+            out.extend_from_slice(&file.size.to_le_bytes());
+        }
+        put_u32(&mut out, self.chunk_cids.len() as u32);
+        for cid in &self.chunk_cids {
+            out.extend_from_slice(cid.as_bytes());
+        }
+        out.extend_from_slice(&self.capabilities_bytes());
+        out
+    }
+
+    /// The declared capabilities section: grants as specs, then syscall
+    /// names, each sorted. Empty if nothing is declared.
+    fn capabilities_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.capabilities.is_empty() {
+            return out;
+        }
+        let mut grants: Vec<String> = self.capabilities.grants.iter().map(Grant::spec).collect();
+        grants.sort();
+        put_u32(&mut out, grants.len() as u32);
+        for grant in &grants {
+            put_str(&mut out, grant);
+        }
+        let mut syscalls: Vec<&String> = self.capabilities.syscalls.iter().collect();
+        syscalls.sort();
+        put_u32(&mut out, syscalls.len() as u32);
+        for name in syscalls {
+            put_str(&mut out, name);
+        }
+        out
+    }
+
+    /// The full encoding, for storing and sending manifests: `signed_bytes`
+    /// up to the declared capabilities, the root CID, the length-prefixed
+    /// signature, and then the capabilities section if there is one.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let capabilities = self.capabilities_bytes();
+        let mut out = self.signed_bytes();
+        out.truncate(out.len() - capabilities.len());
+        out.extend_from_slice(self.root_cid.as_bytes());
+        put_u32(&mut out, self.signature.len() as u32);
+        out.extend_from_slice(&self.signature);
+        out.extend_from_slice(&capabilities);
+        out
+    }
+
+    /// Decodes `to_canonical_bytes` output. This only checks the encoding;
+    /// call `validate` before trusting the contents.
+    pub fn from_canonical_bytes(bytes: &[u8]) -> Result<Self, ManifestError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let name = reader.string()?;
+        let version_text = reader.string()?;
+        let version = SemVer::parse(&version_text).map_err(ManifestError::InvalidVersion)?;
+        let description = reader.string()?;
+        let tag_count = reader.u32()? as usize;
+        let mut tags = Vec::new();
+        for _ in 0..tag_count {
+            tags.push(reader.string()?);
+        }
+        let mut publisher = [0u8; 32];
+        publisher.copy_from_slice(reader.take(32)?);
+        let file_count = reader.u32()? as usize;
+        let mut files = Vec::new();
+        for _ in 0..file_count {
+            let path = reader.string()?;
+            let size = reader.u64()?;
+            files.push(FileEntry { path, size });
+        }
+        let chunk_count = reader.u32()? as usize;
+        let mut chunk_cids = Vec::new();
+        for _ in 0..chunk_count {
+            chunk_cids.push(reader.cid()?);
+        }
+        let root_cid = reader.cid()?;
+        let signature_len = reader.u32()? as usize;
+        let signature = reader.take(signature_len)?.to_vec();
+        let mut capabilities = DeclaredCapabilities::default();
+        if reader.pos != bytes.len() {
+            let grant_count = reader.u32()? as usize;
+            for _ in 0..grant_count {
+                let spec = reader.string()?;
+                capabilities.grants.push(Grant::parse(&spec).ok_or(ManifestError::InvalidCapabilities(alloc::format!("invalid grant '{}'", spec)))?);
+            }
+            let syscall_count = reader.u32()? as usize;
+            for _ in 0..syscall_count {
+                capabilities.syscalls.push(reader.string()?);
+            }
+            // A section with duplicates then encodes differently and fails `validate`.
+            capabilities.normalize();
+        }
+        if reader.pos != bytes.len() {
+            return Err(ManifestError::Malformed("trailing bytes"));
+        }
+        Ok(Self { name, version, description, tags, publisher: Aid(publisher), files, chunk_cids, root_cid, signature, capabilities })
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ManifestError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(ManifestError::Malformed("truncated"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, ManifestError> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn u64(&mut self) -> Result<u64, ManifestError> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+
+    fn string(&mut self) -> Result<String, ManifestError> {
+        let len = self.u32()? as usize;
+        let raw = self.take(len)?;
+        core::str::from_utf8(raw).map(String::from).map_err(|_| ManifestError::Malformed("invalid UTF-8"))
+    }
+
+    fn cid(&mut self) -> Result<Cid, ManifestError> {
+        Cid::from_bytes(self.take(CID_LEN)?).ok_or(ManifestError::Malformed("invalid CID"))
+    }
+}
+
+/// Builds and chunks a package. The result is unsigned; the publisher signs
+/// `root_cid` and stores the signature in `signature`.
+pub struct ManifestBuilder {
+    name: String,
+    version: SemVer,
+    description: String,
+    tags: Vec<String>,
+    publisher: Aid,
+    files: Vec<(String, Vec<u8>)>,
+    capabilities: DeclaredCapabilities,
+}
+
+impl ManifestBuilder {
+    pub fn new(name: &str, version: SemVer) -> Self {
+        Self { name: String::from(name), version, description: String::new(), tags: Vec::new(), publisher: Aid([0u8; 32]), files: Vec::new(), capabilities: DeclaredCapabilities::default() }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = String::from(description);
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(String::from(tag));
+        self
+    }
+
+    pub fn publisher(mut self, publisher: Aid) -> Self {
+        self.publisher = publisher;
+        self
+    }
+
+    pub fn file(mut self, path: &str, content: &[u8]) -> Self {
+        self.files.push((String::from(path), content.to_vec()));
+        self
+    }
+
+    /// Declares a capability the package's service needs.
+    pub fn grant(mut self, grant: Grant) -> Self {
+        self.capabilities.grants.push(grant);
+        self
+    }
+
+    /// Declares a syscall the package's service needs, by name (`SYS_NET_TX`).
+    pub fn syscall(mut self, name: &str) -> Self {
+        self.capabilities.syscalls.push(String::from(name));
+        self
+    }
+
+    /// Validates the package and returns its manifest together with the
+    /// chunks, in the same order as `chunk_cids`.
+    pub fn build(mut self) -> Result<(PackageManifest, Vec<Vec<u8>>), ManifestError> {
+        self.files.sort_by(|a, b| a.0.cmp(&b.0));
+        self.tags.sort();
+        self.tags.dedup();
+        self.capabilities.normalize();
+        let mut files = Vec::with_capacity(self.files.len());
+        let mut chunks = Vec::new();
+        for (path, content) in &self.files {
+            files.push(FileEntry { path: path.clone(), size: content.len() as u64 });
+            if content.is_empty() {
+                chunks.push(Vec::new());
+            } else {
+                chunks.extend(content.chunks(CHUNK_SIZE).map(|chunk| chunk.to_vec()));
+            }
+        }
+        let chunk_cids: Vec<Cid> = chunks.iter().map(|chunk| compute_cid(chunk)).collect();
+        let mut manifest = PackageManifest {
+            name: self.name,
+            version: self.version,
+            description: self.description,
+            tags: self.tags,
+            publisher: self.publisher,
+            files,
+            chunk_cids,
+            root_cid: compute_cid(&[]),
+            signature: Vec::new(),
+            capabilities: self.capabilities,
+        };
+        manifest.root_cid = compute_cid(&manifest.signed_bytes());
+        manifest.validate()?;
+        Ok((manifest, chunks))
+    }
+}
@@ -0,0 +1,386 @@
+// common/src/metrics.rs
+
+//! Named counters, gauges and histograms that services register once and
+//! report through the standard `MetricsRequest::Scrape`.
+//!
+//! Registering returns a handle. The handle shares an atomic cell with the
+//! registry, so updating it takes `&self`, never allocates and never needs the
+//! registry. That lets a component hold its own handles instead of threading
+//! the registry through every call. The registry reads the cells when scraped.
+//!
+//! Durations are recorded in nanoseconds from `time::monotonic_nanos`, under
+//! names ending in `_nanoseconds`. `DURATION_BOUNDS` suits most of them.
+//!
+//! ```ignore
+//! let mut metrics = Registry::new("net-stack");
+//! let opened = metrics.counter("net_sockets_opened_total", "Sockets opened since startup.");
+//! opened.inc();
+//! // In the request handler:
+//! NetStackRequest::Metrics(request) => NetStackResponse::Metrics(metrics.handle(&request)),
+//! ```
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use crate::ipc::metrics_ipc::{MetricSample, MetricValue, MetricsRequest, MetricsResponse};
+use crate::time;
+
+/// Histogram bounds for durations in nanoseconds: 10 µs to 1 s, roughly
+/// doubling. Without a high-resolution clock everything under a tick (10 ms)
+/// lands in the first bucket.
+pub const DURATION_BOUNDS: [u64; 14] = [
+    10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000, 10_000_000, 25_000_000, 50_000_000, 250_000_000, 1_000_000_000,
+];
+
+#[derive(Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+struct HistogramCells {
+    bounds: &'static [u64],
+    buckets: Vec<AtomicU64>, // One per bound, plus the overflow bucket
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+/// Fixed-bucket histogram. Bounds are inclusive upper limits in ascending order.
+#[derive(Clone)]
+pub struct Histogram(Arc<HistogramCells>);
+
+impl Histogram {
+    pub fn observe(&self, value: u64) {
+        let cells = &self.0;
+        let index = cells.bounds.iter().position(|bound| value <= *bound).unwrap_or(cells.bounds.len());
+        cells.buckets[index].fetch_add(1, Ordering::Relaxed);
+        cells.count.fetch_add(1, Ordering::Relaxed);
+        cells.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Records the nanoseconds since `start`, a `time::monotonic_nanos` value.
+    pub fn observe_since(&self, start: u64) {
+        self.observe(time::monotonic_nanos().saturating_sub(start));
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.count.load(Ordering::Relaxed)
+    }
+}
+
+enum Cell {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+struct Entry {
+    name: &'static str,
+    help: &'static str,
+    labels: Vec<(&'static str, String)>,
+    cell: Cell,
+}
+
+/// The metrics of one service.
+pub struct Registry {
+    service: &'static str,
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// `service` is attached to every metric as the `service` label.
+    pub fn new(service: &'static str) -> Self {
+        Self { service, entries: Vec::new() }
+    }
+
+    pub fn counter(&mut self, name: &'static str, help: &'static str) -> Counter {
+        self.counter_with(name, help, &[])
+    }
+
+    /// A counter with extra labels. Register one per label combination, e.g.
+    /// `trigger="timed"` and `trigger="budget"` under the same name.
+    pub fn counter_with(&mut self, name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Counter {
+        let counter = Counter(Arc::new(AtomicU64::new(0)));
+        self.push(name, help, labels, Cell::Counter(counter.clone()));
+        counter
+    }
+
+    pub fn gauge(&mut self, name: &'static str, help: &'static str) -> Gauge {
+        self.gauge_with(name, help, &[])
+    }
+
+    pub fn gauge_with(&mut self, name: &'static str, help: &'static str, labels: &[(&'static str, &str)]) -> Gauge {
+        let gauge = Gauge(Arc::new(AtomicI64::new(0)));
+        self.push(name, help, labels, Cell::Gauge(gauge.clone()));
+        gauge
+    }
+
+    pub fn histogram(&mut self, name: &'static str, help: &'static str, bounds: &'static [u64]) -> Histogram {
+        self.histogram_with(name, help, bounds, &[])
+    }
+
+    pub fn histogram_with(&mut self, name: &'static str, help: &'static str, bounds: &'static [u64], labels: &[(&'static str, &str)]) -> Histogram {
+        let cells = HistogramCells {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        };
+        let histogram = Histogram(Arc::new(cells));
+        self.push(name, help, labels, Cell::Histogram(histogram.clone()));
+        histogram
+    }
+
+    fn push(&mut self, name: &'static str, help: &'static str, labels: &[(&'static str, &str)], cell: Cell) {
+        let labels = labels.iter().map(|(key, value)| (*key, value.to_string())).collect();
+        self.entries.push(Entry { name, help, labels, cell });
+    }
+
+    /// Current values of all metrics, in registration order.
+    pub fn scrape(&self) -> Vec<MetricSample> {
+        self.entries.iter().map(|entry| {
+            let mut labels = Vec::with_capacity(entry.labels.len() + 1);
+            labels.push(("service".to_string(), self.service.to_string()));
+            labels.extend(entry.labels.iter().map(|(key, value)| (key.to_string(), value.clone())));
+            let value = match &entry.cell {
+                Cell::Counter(counter) => MetricValue::Counter(counter.get()),
+                Cell::Gauge(gauge) => MetricValue::Gauge(gauge.get()),
+                Cell::Histogram(Histogram(cells)) => MetricValue::Histogram {
+                    bounds: cells.bounds.to_vec(),
+                    buckets: cells.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+                    count: cells.count.load(Ordering::Relaxed),
+                    sum: cells.sum.load(Ordering::Relaxed),
+                },
+            };
+            MetricSample { name: entry.name.to_string(), help: entry.help.to_string(), labels, value }
+        }).collect()
+    }
+
+    /// Answers a `MetricsRequest`. Services call this from the arm that
+    /// matches their `Metrics` request variant.
+    pub fn handle(&self, request: &MetricsRequest) -> MetricsResponse {
+        match request {
+            MetricsRequest::Scrape => MetricsResponse::Metrics(self.scrape()),
+        }
+    }
+}
+
+/// Escapes a label value: backslash, double quote and newline.
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes help text: backslash and newline.
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn write_labels(out: &mut String, labels: &[(String, String)], extra: Option<(&str, &str)>) {
+    let mut pairs = labels.iter().map(|(key, value)| (key.as_str(), value.as_str())).chain(extra).peekable();
+    if pairs.peek().is_none() {
+        return;
+    }
+    out.push('{');
+    for (i, (key, value)) in pairs.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}=\"{}\"", key, escape_label_value(value));
+    }
+    out.push('}');
+}
+
+/// Renders samples in the Prometheus text exposition format. `# HELP` and
+/// `# TYPE` are written before the first sample of each name. Histogram
+/// buckets are cumulative, as the format requires.
+pub fn render_text(samples: &[MetricSample]) -> String {
+    let mut out = String::new();
+    let mut described = BTreeSet::new();
+    for sample in samples {
+        if described.insert(sample.name.as_str()) {
+            let kind = match sample.value {
+                MetricValue::Counter(_) => "counter",
+                MetricValue::Gauge(_) => "gauge",
+                MetricValue::Histogram { .. } => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", sample.name, escape_help(&sample.help));
+            let _ = writeln!(out, "# TYPE {} {}", sample.name, kind);
+        }
+        match &sample.value {
+            MetricValue::Counter(value) => {
+                out.push_str(&sample.name);
+                write_labels(&mut out, &sample.labels, None);
+                let _ = writeln!(out, " {}", value);
+            },
+            MetricValue::Gauge(value) => {
+                out.push_str(&sample.name);
+                write_labels(&mut out, &sample.labels, None);
+                let _ = writeln!(out, " {}", value);
+            },
+            MetricValue::Histogram { bounds, buckets, count, sum } => {
+                let mut cumulative = 0;
+                for (i, bucket) in buckets.iter().enumerate() {
+                    cumulative += bucket;
+                    let le = bounds.get(i).map_or_else(|| "+Inf".to_string(), |bound| format!("{}", bound));
+                    let _ = write!(out, "{}_bucket", sample.name);
+                    write_labels(&mut out, &sample.labels, Some(("le", &le)));
+                    let _ = writeln!(out, " {}", cumulative);
+                }
+                let _ = write!(out, "{}_sum", sample.name);
+                write_labels(&mut out, &sample.labels, None);
+                let _ = writeln!(out, " {}", sum);
+                let _ = write!(out, "{}_count", sample.name);
+                write_labels(&mut out, &sample.labels, None);
+                let _ = writeln!(out, " {}", count);
+            },
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn increments_from_many_threads_all_count() {
+        let mut metrics = Registry::new("test");
+        let requests = metrics.counter("requests_total", "Requests.");
+        let live = metrics.gauge("live", "Live things.");
+        let latency = metrics.histogram("latency_nanoseconds", "Latency.", &DURATION_BOUNDS);
+        let threads: Vec<_> = (0..8u64).map(|t| {
+            let (requests, live, latency) = (requests.clone(), live.clone(), latency.clone());
+            std::thread::spawn(move || {
+                for i in 0..10_000 {
+                    requests.inc();
+                    live.inc();
+                    latency.observe(t * 10_000 + i);
+                    live.dec();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(requests.get(), 80_000);
+        assert_eq!(live.get(), 0);
+        assert_eq!(latency.count(), 80_000);
+        match &metrics.scrape()[2].value {
+            MetricValue::Histogram { buckets, sum, .. } => {
+                assert_eq!(buckets.iter().sum::<u64>(), 80_000);
+                assert_eq!(*sum, (0..80_000).sum::<u64>());
+            },
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn scrapes_attach_the_service_label_first() {
+        let mut metrics = Registry::new("vfs");
+        metrics.counter_with("vfs_flushes_total", "Flushes.", &[("trigger", "timed")]).add(3);
+        metrics.gauge("vfs_dirty_pages", "Dirty pages.").set(-2);
+        let samples = match metrics.handle(&MetricsRequest::Scrape) {
+            MetricsResponse::Metrics(samples) => samples,
+            response => panic!("{:?}", response),
+        };
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].labels, vec![("service".to_string(), "vfs".to_string()), ("trigger".to_string(), "timed".to_string())]);
+        assert_eq!(samples[0].value, MetricValue::Counter(3));
+        assert_eq!(samples[1].labels, vec![("service".to_string(), "vfs".to_string())]);
+        assert_eq!(samples[1].value, MetricValue::Gauge(-2));
+    }
+
+    #[test]
+    fn text_has_one_header_per_name_and_cumulative_buckets() {
+        static BOUNDS: [u64; 2] = [10, 100];
+        let mut metrics = Registry::new("net-stack");
+        metrics.counter_with("net_flushes_total", "Flushes.", &[("trigger", "timed")]).add(4);
+        metrics.counter_with("net_flushes_total", "Flushes.", &[("trigger", "budget")]).inc();
+        let latency = metrics.histogram("net_latency_nanoseconds", "Line one\nline \\two.", &BOUNDS);
+        for value in [5, 10, 50, 1_000] {
+            latency.observe(value);
+        }
+        assert_eq!(render_text(&metrics.scrape()), "\
+# HELP net_flushes_total Flushes.
+# TYPE net_flushes_total counter
+net_flushes_total{service=\"net-stack\",trigger=\"timed\"} 4
+net_flushes_total{service=\"net-stack\",trigger=\"budget\"} 1
+# HELP net_latency_nanoseconds Line one\\nline \\\\two.
+# TYPE net_latency_nanoseconds histogram
+net_latency_nanoseconds_bucket{service=\"net-stack\",le=\"10\"} 2
+net_latency_nanoseconds_bucket{service=\"net-stack\",le=\"100\"} 3
+net_latency_nanoseconds_bucket{service=\"net-stack\",le=\"+Inf\"} 4
+net_latency_nanoseconds_sum{service=\"net-stack\"} 1065
+net_latency_nanoseconds_count{service=\"net-stack\"} 4
+");
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label_value("plain"), "plain");
+        assert_eq!(escape_label_value("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+        let samples = vec![MetricSample {
+            name: "paths".to_string(),
+            help: String::new(),
+            labels: vec![("path".to_string(), "C:\\\"x\"\n".to_string())],
+            value: MetricValue::Gauge(1),
+        }];
+        assert!(render_text(&samples).ends_with("paths{path=\"C:\\\\\\\"x\\\"\\n\"} 1\n"));
+    }
+}
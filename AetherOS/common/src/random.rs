@@ -0,0 +1,15 @@
+// common/src/random.rs
+
+//! Random bytes from the kernel (`SYS_RANDOM`), for keys and nonces.
+
+use crate::abi::{RANDOM_MAX_LEN, SYS_RANDOM};
+use crate::syscall::syscall3;
+
+/// Fills `out` with random bytes. False if the kernel has no random number
+/// generator; `out` is then partly or wholly unchanged and must not be used.
+pub fn fill(out: &mut [u8]) -> bool {
+    out.chunks_mut(RANDOM_MAX_LEN).all(|chunk| {
+        let res = unsafe { syscall3(SYS_RANDOM, chunk.as_mut_ptr() as u64, chunk.len() as u64, 0) };
+        res == chunk.len() as u64
+    })
+}
@@ -0,0 +1,500 @@
+// common/src/semver.rs
+
+//! Semantic versions (semver.org 2.0.0) and version requirements, for package
+//! manifests and the registry's dependency resolution.
+//!
+//! Requirements are comma-separated comparators that must all match:
+//!
+//! *   `^1.2.3` allows changes that don't modify the left-most non-zero part:
+//!     `>=1.2.3, <2.0.0`; `^0.2.3` is `>=0.2.3, <0.3.0`; `^0.0.3` is `=0.0.3`.
+//! *   `~1.2.3` allows patch changes: `>=1.2.3, <1.3.0`. `~1` is `>=1.0.0, <2.0.0`.
+//! *   `=1.2.3` and a bare `1.2.3` match exactly that version.
+//! *   `>`, `>=`, `<`, `<=` form ranges, e.g. `>=1.4, <2`.
+//! *   `*` matches any release.
+//!
+//! Parts left out of a `^`, `~` or range version count as 0. As in Cargo, a
+//! pre-release version only matches a requirement that names a pre-release
+//! of the same major.minor.patch, so `^1.2.0` doesn't pull in `1.3.0-beta`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+/// One dot-separated part of a pre-release tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreRelease {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Ord for PreRelease {
+    /// Numeric identifiers compare as numbers and sort before alphanumeric ones.
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreRelease::Numeric(a), PreRelease::Numeric(b)) => a.cmp(b),
+            (PreRelease::Numeric(_), PreRelease::Alpha(_)) => Ordering::Less,
+            (PreRelease::Alpha(_), PreRelease::Numeric(_)) => Ordering::Greater,
+            (PreRelease::Alpha(a), PreRelease::Alpha(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for PreRelease {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PreRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreRelease::Numeric(n) => write!(f, "{}", n),
+            PreRelease::Alpha(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A semantic version. Build metadata is kept for display but, as the spec
+/// requires, ignored by comparisons, including equality.
+#[derive(Debug, Clone)]
+pub struct SemVer {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<PreRelease>,
+    pub build: String,
+}
+
+impl SemVer {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self { major, minor, patch, pre: Vec::new(), build: String::new() }
+    }
+
+    /// Parses `major.minor.patch[-pre][+build]`. Every part must be present and
+    /// numeric parts may not have leading zeros.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let partial = parse_partial(text)?;
+        match (partial.minor, partial.patch) {
+            (Some(minor), Some(patch)) => Ok(Self { major: partial.major, minor, patch, pre: partial.pre, build: partial.build }),
+            _ => Err(format!("'{}': expected major.minor.patch", text)),
+        }
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    fn same_release(&self, other: &SemVer) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+}
+
+impl Ord for SemVer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch)).then_with(|| {
+            // A pre-release sorts before the release itself: 1.0.0-rc.1 < 1.0.0.
+            match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // Identifier by identifier; a shorter list that is a prefix sorts first.
+                (false, false) => self.pre.cmp(&other.pre),
+            }
+        })
+    }
+}
+
+impl PartialOrd for SemVer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SemVer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SemVer {}
+
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (i, id) in self.pre.iter().enumerate() {
+            f.write_str(if i == 0 { "-" } else { "." })?;
+            write!(f, "{}", id)?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build)?;
+        }
+        Ok(())
+    }
+}
+
+/// A version that may leave out minor and patch, as written in requirements.
+#[derive(Debug, Clone)]
+struct Partial {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<PreRelease>,
+    build: String,
+}
+
+impl Partial {
+    fn floor(&self) -> SemVer {
+        SemVer { major: self.major, minor: self.minor.unwrap_or(0), patch: self.patch.unwrap_or(0), pre: self.pre.clone(), build: String::new() }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn parse_number(text: &str, whole: &str) -> Result<u64, String> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("'{}': '{}' is not a number", whole, text));
+    }
+    if text.len() > 1 && text.starts_with('0') {
+        return Err(format!("'{}': '{}' has a leading zero", whole, text));
+    }
+    text.parse().map_err(|_| format!("'{}': '{}' is too large", whole, text))
+}
+
+fn parse_partial(text: &str) -> Result<Partial, String> {
+    let (rest, build) = match text.split_once('+') {
+        Some((rest, build)) => {
+            if !build.split('.').all(is_identifier) {
+                return Err(format!("'{}': invalid build metadata", text));
+            }
+            (rest, build.to_string())
+        },
+        None => (text, String::new()),
+    };
+    let (core, pre) = match rest.split_once('-') {
+        Some((core, pre)) => {
+            let ids = pre.split('.').map(|id| {
+                if !is_identifier(id) {
+                    Err(format!("'{}': invalid pre-release identifier '{}'", text, id))
+                } else if id.bytes().all(|b| b.is_ascii_digit()) {
+                    parse_number(id, text).map(PreRelease::Numeric)
+                } else {
+                    Ok(PreRelease::Alpha(id.to_string()))
+                }
+            }).collect::<Result<Vec<_>, _>>()?;
+            (core, ids)
+        },
+        None => (rest, Vec::new()),
+    };
+    let mut parts = core.split('.');
+    let major = parse_number(parts.next().unwrap_or(""), text)?;
+    let minor = parts.next().map(|p| parse_number(p, text)).transpose()?;
+    let patch = parts.next().map(|p| parse_number(p, text)).transpose()?;
+    if parts.next().is_some() {
+        return Err(format!("'{}': too many version parts", text));
+    }
+    if !pre.is_empty() && patch.is_none() {
+        return Err(format!("'{}': a pre-release needs a full major.minor.patch", text));
+    }
+    Ok(Partial { major, minor, patch, pre, build })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Caret,
+    Tilde,
+    Any,
+}
+
+#[derive(Debug, Clone)]
+struct Comparator {
+    op: Op,
+    version: Partial,
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text == "*" {
+            return Ok(Self { op: Op::Any, version: Partial { major: 0, minor: None, patch: None, pre: Vec::new(), build: String::new() } });
+        }
+        let (op, version) = [(">=", Op::GreaterEq), ("<=", Op::LessEq), (">", Op::Greater), ("<", Op::Less), ("=", Op::Exact), ("^", Op::Caret), ("~", Op::Tilde)]
+            .iter()
+            .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (*op, rest.trim_start())))
+            .unwrap_or((Op::Exact, text));
+        let version = parse_partial(version)?;
+        if op == Op::Exact && (version.minor.is_none() || version.patch.is_none()) {
+            return Err(format!("'{}': an exact requirement needs major.minor.patch", text));
+        }
+        Ok(Self { op, version })
+    }
+
+    /// Exclusive upper bound of a caret or tilde requirement.
+    fn upper_bound(&self) -> SemVer {
+        let v = &self.version;
+        let (major, minor, patch) = match self.op {
+            Op::Caret => match (v.major, v.minor, v.patch) {
+                (0, Some(0), Some(patch)) => (0, 0, patch + 1),
+                (0, Some(minor), _) => (0, minor + 1, 0),
+                (major, _, _) => (major + 1, 0, 0),
+            },
+            _ => match v.minor {
+                Some(minor) => (v.major, minor + 1, 0),
+                None => (v.major + 1, 0, 0),
+            },
+        };
+        // `-0` is the lowest possible pre-release, so no pre-release of the bound slips under it.
+        SemVer { major, minor, patch, pre: alloc::vec![PreRelease::Numeric(0)], build: String::new() }
+    }
+
+    fn matches(&self, version: &SemVer) -> bool {
+        let floor = self.version.floor();
+        match self.op {
+            Op::Any => true,
+            Op::Exact => *version == floor,
+            Op::Greater => *version > floor,
+            Op::GreaterEq => *version >= floor,
+            Op::Less => *version < floor,
+            Op::LessEq => *version <= floor,
+            Op::Caret | Op::Tilde => *version >= floor && *version < self.upper_bound(),
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            Op::Any => return f.write_str("*"),
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Caret => "^",
+            Op::Tilde => "~",
+        };
+        let v = &self.version;
+        write!(f, "{}{}", op, v.major)?;
+        if let Some(minor) = v.minor {
+            write!(f, ".{}", minor)?;
+        }
+        if let Some(patch) = v.patch {
+            write!(f, ".{}", patch)?;
+        }
+        for (i, id) in v.pre.iter().enumerate() {
+            f.write_str(if i == 0 { "-" } else { "." })?;
+            write!(f, "{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// A version requirement such as `^1.2` or `>=1.4, <2`.
+#[derive(Debug, Clone)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let comparators = text.split(',').map(Comparator::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { comparators })
+    }
+
+    /// Whether `version` satisfies every comparator.
+    pub fn matches(&self, version: &SemVer) -> bool {
+        if !self.comparators.iter().all(|c| c.matches(version)) {
+            return false;
+        }
+        if !version.is_prerelease() {
+            return true;
+        }
+        // Pre-releases are opt-in per release.
+        self.comparators.iter().any(|c| !c.version.pre.is_empty() && c.version.floor().same_release(version))
+    }
+
+    /// The highest of `candidates` that matches, as the resolver picks it.
+    pub fn best_match<'a>(&self, candidates: impl IntoIterator<Item = &'a SemVer>) -> Option<&'a SemVer> {
+        candidates.into_iter().filter(|v| self.matches(v)).max()
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, comparator) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", comparator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn v(text: &str) -> SemVer {
+        SemVer::parse(text).unwrap()
+    }
+
+    fn req(text: &str) -> VersionReq {
+        VersionReq::parse(text).unwrap()
+    }
+
+    #[test]
+    fn precedence_follows_the_spec_example() {
+        // semver.org 2.0.0, item 11.
+        let ordered = [
+            "1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2",
+            "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0", "1.0.1", "1.1.0", "2.0.0", "10.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn build_metadata_is_ignored_by_comparisons_but_kept_for_display() {
+        assert_eq!(v("1.0.0+build.1"), v("1.0.0+other"));
+        assert_eq!(v("1.0.0-rc.1+exp.sha.5114f85").cmp(&v("1.0.0-rc.1")), Ordering::Equal);
+        assert_eq!(v("1.0.0-rc.1+exp.sha.5114f85").to_string(), "1.0.0-rc.1+exp.sha.5114f85");
+    }
+
+    #[test]
+    fn malformed_versions_are_refused() {
+        for text in ["1", "1.2", "1.2.3.4", "01.2.3", "1.02.3", "1.2.3-", "1.2.3-01", "1.2.3-a..b", "1.2.3+", "1.2.3+a_b", "a.b.c", "", "1.2.99999999999999999999"] {
+            assert!(SemVer::parse(text).is_err(), "{:?} parsed", text);
+        }
+        assert_eq!(v("1.2.3-0a").pre, vec![PreRelease::Alpha("0a".to_string())]);
+    }
+
+    #[test]
+    fn caret_and_tilde_stop_at_the_next_breaking_release() {
+        let cases = [
+            ("^1.2.3", "1.2.3", "1.9.9", "2.0.0", "1.2.2"),
+            ("^0.2.3", "0.2.3", "0.2.9", "0.3.0", "0.2.2"),
+            ("^0.0.3", "0.0.3", "0.0.3", "0.0.4", "0.0.2"),
+            ("~1.2.3", "1.2.3", "1.2.9", "1.3.0", "1.2.2"),
+            ("~1", "1.0.0", "1.9.9", "2.0.0", "0.9.9"),
+            (">=1.4, <2", "1.4.0", "1.99.0", "2.0.0", "1.3.9"),
+        ];
+        for (requirement, low, high, above, below) in cases {
+            let r = req(requirement);
+            assert!(r.matches(&v(low)) && r.matches(&v(high)), "{} matches {} and {}", requirement, low, high);
+            assert!(!r.matches(&v(above)) && !r.matches(&v(below)), "{} refuses {} and {}", requirement, above, below);
+        }
+        assert!(req("*").matches(&v("0.0.1")));
+        assert!(VersionReq::parse("=1.2").is_err());
+    }
+
+    #[test]
+    fn pre_releases_only_match_a_requirement_that_names_their_release() {
+        assert!(!req("^1.2.0").matches(&v("1.3.0-beta")));
+        assert!(!req("^1.2.0").matches(&v("2.0.0-alpha")), "the upper bound's pre-releases stay out");
+        assert!(req("^1.3.0-alpha").matches(&v("1.3.0-beta")));
+        assert!(!req("^1.3.0-alpha").matches(&v("1.3.1-beta")));
+        assert!(req("^1.3.0-alpha").matches(&v("1.4.0")));
+        assert!(!req("*").matches(&v("1.0.0-rc.1")));
+    }
+
+    #[test]
+    fn best_match_picks_the_highest_release_that_matches() {
+        let candidates = [v("1.2.0"), v("1.4.1"), v("1.5.0-rc.1"), v("2.0.0")];
+        assert_eq!(req("^1.2").best_match(&candidates), Some(&candidates[1]));
+        assert_eq!(req(">=2.1").best_match(&candidates), None);
+        assert_eq!(req(">=1.0, <1.3").to_string(), ">=1.0, <1.3");
+    }
+
+    /// xorshift64, so the properties below see the same versions every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        /// A version with small parts, so equal and neighbouring ones come up.
+        fn version(&mut self) -> SemVer {
+            let mut version = SemVer::new(self.below(3), self.below(3), self.below(3));
+            for _ in 0..self.below(3) {
+                version.pre.push(match self.below(3) {
+                    0 => PreRelease::Alpha(["alpha", "beta", "rc"][self.below(3) as usize].to_string()),
+                    _ => PreRelease::Numeric(self.below(12)),
+                });
+            }
+            if self.below(4) == 0 {
+                version.build = "build.7".to_string();
+            }
+            version
+        }
+    }
+
+    #[test]
+    fn ordering_is_a_total_order_that_survives_display_and_parse() {
+        let mut rng = Rng(0x5EED_5EED_5EED_5EED);
+        let versions: Vec<SemVer> = (0..200).map(|_| rng.version()).collect();
+        for a in &versions {
+            assert_eq!(&v(&a.to_string()), a);
+            assert_eq!(v(&a.to_string()).to_string(), a.to_string());
+            for b in &versions {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse(), "{} vs {}", a, b);
+                assert_eq!(a == b, a.cmp(b) == Ordering::Equal);
+            }
+        }
+        let mut sorted = versions.clone();
+        sorted.sort();
+        for window in sorted.windows(3) {
+            assert!(window[0] <= window[1] && window[1] <= window[2]);
+            assert!(window[0] <= window[2], "{} <= {} <= {}", window[0], window[1], window[2]);
+        }
+        for pair in sorted.windows(2) {
+            if pair[0].same_release(&pair[1]) && pair[1].pre.is_empty() {
+                assert!(pair[0].pre.is_empty() || pair[0] < pair[1], "a pre-release sorts before its release");
+            }
+        }
+    }
+
+    #[test]
+    fn caret_and_tilde_agree_with_the_ranges_they_stand_for() {
+        let mut rng = Rng(0xC0FF_EE00_0DDB_A11C);
+        for _ in 0..200 {
+            let base = SemVer::new(rng.below(3), rng.below(3), rng.below(3));
+            let (major, minor, patch) = (base.major, base.minor, base.patch);
+            let caret_upper = match (major, minor) {
+                (0, 0) => format!("0.0.{}", patch + 1),
+                (0, _) => format!("0.{}.0", minor + 1),
+                _ => format!("{}.0.0", major + 1),
+            };
+            let caret = req(&format!("^{}", base));
+            let caret_range = req(&format!(">={}, <{}", base, caret_upper));
+            let tilde = req(&format!("~{}", base));
+            let tilde_range = req(&format!(">={}, <{}.{}.0", base, major, minor + 1));
+            for _ in 0..20 {
+                let candidate = rng.version();
+                assert_eq!(caret.matches(&candidate), caret_range.matches(&candidate), "^{} vs {}", base, candidate);
+                assert_eq!(tilde.matches(&candidate), tilde_range.matches(&candidate), "~{} vs {}", base, candidate);
+                if candidate.is_prerelease() {
+                    assert!(!caret.matches(&candidate), "^{} takes no pre-release, not even {}", base, candidate);
+                }
+            }
+        }
+    }
+}
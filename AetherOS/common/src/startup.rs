@@ -0,0 +1,83 @@
+// common/src/startup.rs
+
+//! What a V-Node is told when it starts: `SYS_GET_STARTUP_INFO`.
+//!
+//! Init decides which channels an instance talks to and passes them, with the
+//! instance's label and an optional configuration blob, to the V-Node loader.
+//! The loader writes them into the new task's args page before its first
+//! instruction runs. A V-Node reads them once at `_start`:
+//!
+//! ```ignore
+//! let channels = startup::channels();
+//! let settings_chan = channels.get("settings").copied().unwrap_or(14);
+//! ```
+//!
+//! The fallback is the service's old well-known channel, so a V-Node started
+//! without startup info (from the initrd by the kernel, or by an older init)
+//! still finds its peers.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::abi::{E_ERROR, STARTUP_INFO_MAX_LEN, SYS_GET_STARTUP_INFO};
+use crate::syscall::syscall3;
+
+/// Key of the instance's own channel in `assigned_channels`. The loader adds
+/// it, since only the kernel knows which channel it allocated.
+pub const SELF_CHANNEL: &str = "self";
+
+/// Startup parameters of a V-Node instance, postcard-encoded in its args page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartupInfo {
+    /// The instance's label, e.g. "mail-service" or "user:alice".
+    pub instance_id: String,
+    /// Channel IDs by the name of the service on the other end.
+    pub assigned_channels: BTreeMap<String, u32>,
+    /// Service-specific configuration; empty if there is none.
+    pub config_blob: Vec<u8>,
+}
+
+impl StartupInfo {
+    /// The encoding the loader writes. `None` if it wouldn't fit in the args
+    /// page (`STARTUP_INFO_MAX_LEN`).
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        postcard::to_allocvec(self).ok().filter(|bytes| bytes.len() <= STARTUP_INFO_MAX_LEN)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        postcard::from_bytes(bytes).ok()
+    }
+}
+
+/// Calls `SYS_GET_STARTUP_INFO`, retrying with a bigger buffer if the first
+/// one was too small. `None` if the task was started without startup info.
+pub fn read() -> Option<StartupInfo> {
+    let mut buf = alloc::vec![0u8; 512];
+    loop {
+        let res = unsafe { syscall3(SYS_GET_STARTUP_INFO, buf.as_mut_ptr() as u64, buf.len() as u64, 0) };
+        match res {
+            // An encoding is at least three bytes long, so E_ERROR can't be mistaken for a length.
+            E_ERROR => return None,
+            total if total as usize <= buf.len() => return StartupInfo::from_bytes(&buf[..total as usize]),
+            // Nothing was copied; the kernel said how much room it needs.
+            total if total as usize <= STARTUP_INFO_MAX_LEN => buf.resize(total as usize, 0),
+            _ => return None,
+        }
+    }
+}
+
+/// The channels init assigned to this instance; empty without startup info.
+pub fn channels() -> BTreeMap<String, u32> {
+    read().map(|info| info.assigned_channels).unwrap_or_default()
+}
+
+/// The configuration blob init passed to this instance; empty without one.
+pub fn config() -> Vec<u8> {
+    read().map(|info| info.config_blob).unwrap_or_default()
+}
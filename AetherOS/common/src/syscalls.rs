@@ -4,62 +4,114 @@
 
 extern crate alloc;
 use alloc::vec::Vec;
-use core::str;
 
-use crate::{kprintln, task, ipc, caps, timer};
+use common::ids::{DmaHandle, TaskId};
+use common::text;
+
+use crate::{kprintln, task, ipc, caps, timer, klog, logfwd, pstore, power, bootinfo};
+use crate::error::KernelError;
+use crate::config::MAX_LOG_MESSAGE_BYTES;
 use crate::arch::x86_64::{irq, dma}; // Use refactored arch modules
+use crate::drivers::{ac97, framebuffer, input, ps2_keyboard, rng, rtc};
+use crate::memory::file_map;
+use crate::task::ratelimit::LogDecision;
+use crate::task::tcb::{Identity, TaskStats};
+use crate::task::debug;
+use crate::task::faults;
+use crate::task::coredump;
+#[cfg(feature = "det-sched")]
+use crate::task::detsched::{self, Event, IrqPoint};
+
+// Syscall numbers, return codes and argument layouts live in the shared ABI
+// module so V-Nodes are built against exactly the same table.
+pub use common::abi::*;
 
-// Error codes
-pub const E_ACC_DENIED: u64 = 0xFFFFFFFFFFFFFFFE;
-pub const E_UNKNOWN_SYSCALL: u64 = 0xFFFFFFFFFFFFFFFF;
-pub const E_ERROR: u64 = 1;
-pub const SUCCESS: u64 = 0;
-
-// Syscall numbers
-pub const SYS_LOG: u64 = 0;
-pub const SYS_IPC_SEND: u64 = 1;
-pub const SYS_IPC_RECV: u64 = 2;
-pub const SYS_BLOCK_ON_CHAN: u64 = 3;
-pub const SYS_TIME: u64 = 4;
-pub const SYS_IRQ_REGISTER: u64 = 5;
-pub const SYS_NET_RX_POLL: u64 = 6;
-pub const SYS_NET_ALLOC_BUF: u64 = 7;
-pub const SYS_NET_FREE_BUF: u64 = 8;
-pub const SYS_NET_TX: u64 = 9;
-pub const SYS_IRQ_ACK: u64 = 10;
-pub const SYS_GET_DMA_BUF_PTR: u64 = 11;
-pub const SYS_SET_DMA_BUF_LEN: u64 = 12;
-pub const SYS_IPC_RECV_NONBLOCKING: u64 = 13;
+// Arguments arrive as bare u64s. They become typed IDs right here, as the
+// match arms read them, and results go back out with `raw()`; nothing past
+// this file handles an ID as an integer. A channel ID that doesn't fit in 32
+// bits is E_INVALID_ARG: `a1 as u32` used to wrap it onto another channel.
 
 #[no_mangle]
 pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
+    #[cfg(feature = "det-sched")]
+    {
+        let task_id = task::scheduler::current_task_id();
+        detsched::record(Event::SyscallEnter { task: task_id, number: n });
+        detsched::irq_point(IrqPoint::SyscallEntry);
+        let result = dispatch(n, a1, a2, a3);
+        detsched::record(Event::SyscallExit { task: task_id, number: n, result });
+        detsched::irq_point(IrqPoint::SyscallExit);
+        result
+    }
+    #[cfg(not(feature = "det-sched"))]
+    dispatch(n, a1, a2, a3)
+}
+
+fn dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
     let current_task = task::get_current_task();
 
+    // The syscall filter comes first: a filtered call has no effect at all,
+    // not even an argument check. SYS_FILTER_RESTRICT can only narrow the
+    // filter, so it is always allowed.
+    if n != SYS_FILTER_RESTRICT && !current_task.may_call(n) {
+        if task::record_filter_violation(current_task.id) == 1 {
+            kprintln!("[kernel] syscall: Task {} ({}) made syscall {} outside its filter; further violations are only counted.", current_task.id, current_task.name, n);
+        }
+        return E_SYSCALL_FILTERED;
+    }
+
+    // Reserved arguments must be zero. Unknown numbers fall through to the match below.
+    if let Some(spec) = lookup(n) {
+        if let Err(index) = spec.check_args([a1, a2, a3]) {
+            kprintln!("[kernel] syscall: {} from task {} has reserved bits set in arg{}.", spec.name, current_task.id, index + 1);
+            return E_INVALID_ARG;
+        }
+    }
+
     match n {
         SYS_LOG => {
+            // a1/a2: the message, a3: one of the LOG_SEVERITY_* values.
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::LogWrite) {
                 return E_ACC_DENIED;
             }
+            let severity = match a3 {
+                LOG_SEVERITY_DEFAULT => LOG_SEVERITY_INFO,
+                LOG_SEVERITY_DEBUG..=LOG_SEVERITY_ERROR => a3,
+                _ => return E_INVALID_ARG,
+            };
+            // Per-task token bucket: dropped messages are counted and summarized, not silently lost.
+            if task::check_log_rate(current_task.id) == LogDecision::Suppress {
+                return SUCCESS;
+            }
             let ptr = a1 as *const u8;
             let len = a2 as usize;
             // SAFETY: Caller provides pointer/len pair from V-Node's memory space.
             // The kernel must ensure this is a valid and safe access.
             // For now, we trust the V-Node to provide valid memory.
-            let msg = unsafe { core::slice::from_raw_parts(ptr, len) };
-            if let Ok(s) = str::from_utf8(msg) {
+            let msg = unsafe { core::slice::from_raw_parts(ptr, len.min(MAX_LOG_MESSAGE_BYTES * 4)) };
+            // Invalid sequences (typically a message cut mid-codepoint by the caller) are
+            // shown as U+FFFD rather than dropping the whole line.
+            let s = text::from_utf8_lossy(msg);
+            let s = text::ellipsize_bytes(&s, MAX_LOG_MESSAGE_BYTES);
+            // Below the `loglevel=` floor the message only goes to logd.
+            if severity >= bootinfo::log_floor() {
                 kprintln!("[V-Node Log {}] {}", current_task.id, s);
-                SUCCESS
-            } else {
-                kprintln!("[kernel] SYS_LOG: Invalid UTF-8 sequence from task {}.", current_task.id);
-                E_ERROR
             }
+            logfwd::forward(current_task.id, &current_task.name, severity as u32, s.as_bytes());
+            SUCCESS
         }
         SYS_IPC_SEND => {
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IpcManage) {
                 return E_ACC_DENIED;
             }
-            let channel_id = a1 as ipc::ChannelId;
+            let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                return E_INVALID_ARG;
+            };
             let buf = unsafe { core::slice::from_raw_parts(a2 as *const u8, a3 as usize) };
+            // A server that took a call with a plain receive answers it with a plain send.
+            if let Some(token) = task::reply_token(current_task.id).filter(|token| ipc::call::answers(*token, current_task.id, channel_id)) {
+                return if ipc::call::reply(token, current_task.id, buf).is_ok() { SUCCESS } else { E_ERROR };
+            }
             if ipc::kernel_send(channel_id, current_task.id, buf).is_ok() {
                 SUCCESS
             }
@@ -71,18 +123,28 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IpcManage) {
                 return E_ACC_DENIED;
             }
-            let channel_id = a1 as ipc::ChannelId;
+            let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                return E_INVALID_ARG;
+            };
+            // The first receiver on a channel owns it; it is closed when that task dies.
+            ipc::kernel_claim(channel_id, current_task.id);
             let out_ptr = a2 as *mut u8;
             let out_cap = a3 as usize;
 
             let message = if n == SYS_IPC_RECV {
-                // For blocking receive, if no message, block the task
-                if !ipc::kernel_peek(channel_id) {
-                    task::block_current_on_channel(channel_id);
-                    // Scheduler will pick another task. When unblocked, this syscall will be re-entered.
-                    return SUCCESS; // Indicate that task is blocked, no data returned yet
+                // For blocking receive, if no message, block the task. The check and
+                // the block are one step, so a message sent in between still wakes it.
+                match ipc::kernel_recv_or_block(channel_id, current_task.id) {
+                    Some(message) => Some(message),
+                    None => {
+                        // An IRQ taken here, after blocking and before switching away, must wake the task.
+                        #[cfg(feature = "det-sched")]
+                        detsched::irq_point(IrqPoint::Preempt);
+                        task::schedule();
+                        // Scheduler will pick another task. When unblocked, this syscall will be re-entered.
+                        return SUCCESS; // Indicate that task is blocked, no data returned yet
+                    }
                 }
-                ipc::kernel_recv(channel_id)
             } else { // Non-blocking
                 ipc::kernel_recv(channel_id)
             };
@@ -94,9 +156,18 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
                     unsafe {
                         core::ptr::copy_nonoverlapping(data.data.as_ptr(), out_ptr, data.data.len());
                     }
+                    task::record_last_creds(current_task.id, data.creds);
+                    task::record_reply_token(current_task.id, data.reply_token);
+                    if let Some(token) = data.reply_token {
+                        ipc::call::accept(token, current_task.id);
+                    }
                     data.data.len() as u64
                 } else {
                     kprintln!("[kernel] SYS_IPC_RECV: Message too large for V-Node's buffer (task {}).", current_task.id);
+                    // The message is gone, so a caller waiting for its answer would wait forever.
+                    if let Some(token) = data.reply_token {
+                        ipc::call::drop_call(token);
+                    }
                     E_ERROR // Message too large for provided buffer
                 }
             } else {
@@ -106,24 +177,45 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
         SYS_BLOCK_ON_CHAN => {
             // This syscall is now mostly internal to SYS_IPC_RECV for blocking.
             // If explicitly called, it blocks the current task on a given channel ID.
-            task::block_current_on_channel(a1 as u32);
+            let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                return E_INVALID_ARG;
+            };
+            task::block_current_on_channel(channel_id);
             SUCCESS
         }
         SYS_TIME => {
+            // a1: which clock (TIME_*). Unknown clocks are E_INVALID_ARG.
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::TimeRead) {
                 return E_ACC_DENIED;
             }
-            timer::get_current_ticks()
+            match a1 {
+                TIME_TICKS => timer::get_current_ticks().raw(),
+                TIME_NANOS => timer::nanos(),
+                TIME_SECS_NANOS => {
+                    let nanos = timer::nanos();
+                    (nanos / timer::NANOS_PER_SECOND) << 32 | nanos % timer::NANOS_PER_SECOND
+                }
+                TIME_FLAGS => match timer::clock_source() {
+                    timer::ClockSource::Tsc { .. } => TIME_FLAG_HIGH_RES,
+                    timer::ClockSource::Ticks => 0,
+                },
+                _ => E_INVALID_ARG,
+            }
         }
         SYS_IRQ_REGISTER => {
             let irq_num = a1 as u8;
-            let channel_id = a2 as u32;
+            let Some(channel_id) = ipc::ChannelId::from_arg(a2) else {
+                return E_INVALID_ARG;
+            };
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IrqRegister(irq_num) || *cap == caps::Capability::NetworkAccess) {
                 // NetworkAccess is a broad capability that implies IRQ registration for network devices.
                 return E_ACC_DENIED;
             }
-            irq::register_irq_handler(irq_num, channel_id);
-            SUCCESS
+            let force = a3 & IRQ_REGISTER_FORCE != 0;
+            match irq::register_irq_handler(irq_num, channel_id, current_task.id, force) {
+                Ok(()) => SUCCESS,
+                Err(e) => e.to_syscall_code(),
+            }
         }
         SYS_NET_RX_POLL => {
             // This syscall is highly dependent on specific hardware/driver.
@@ -163,7 +255,7 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
             let packet_len = simulated_packet.len();
 
             let _iface_id = a1; // Not used in current simulation
-            let dma_handle = a2;
+            let dma_handle = DmaHandle::from_raw(a2);
             let out_cap = a3 as usize;
 
             if packet_len <= out_cap {
@@ -191,8 +283,8 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
                 return E_ACC_DENIED;
             }
             let size = a1 as usize;
-            if let Some(handle) = dma::alloc_dma_buffer(size) {
-                handle
+            if let Some(handle) = dma::alloc_dma_buffer(size, current_task.id) {
+                handle.raw()
             }
             else {
                 E_ERROR
@@ -202,7 +294,7 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::DmaAlloc || *cap == caps::Capability::NetworkAccess) {
                 return E_ACC_DENIED;
             }
-            dma::free_dma_buffer(a1);
+            dma::free_dma_buffer(DmaHandle::from_raw(a1));
             SUCCESS
         }
         SYS_NET_TX => {
@@ -225,7 +317,7 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::DmaAccess || *cap == caps::Capability::NetworkAccess) {
                  return E_ACC_DENIED;
             }
-            if let Some(ptr) = dma::get_dma_buffer_ptr(a1) {
+            if let Some(ptr) = dma::get_dma_buffer_ptr(DmaHandle::from_raw(a1)) {
                 ptr as u64
             }
             else {
@@ -236,13 +328,566 @@ pub extern "C" fn syscall_dispatch(n: u64, a1: u64, a2: u64, a3: u64) -> u64 {
             if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::DmaAccess || *cap == caps::Capability::NetworkAccess) {
                  return E_ACC_DENIED;
             }
-            if dma::set_dma_buffer_len(a1, a2 as usize).is_ok() {
+            if dma::set_dma_buffer_len(DmaHandle::from_raw(a1), a2 as usize).is_ok() {
                 SUCCESS
             }
             else {
                 E_ERROR
             }
         }
+        SYS_FB_ACQUIRE => {
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::FramebufferAccess) {
+                return E_ACC_DENIED;
+            }
+            // Hands the display over to the caller; the kernel console stops drawing
+            // until a panic reclaims it. Returns the framebuffer base address.
+            if let Some((base, info)) = framebuffer::acquire(current_task.id) {
+                input::clear(); // Input queued for the previous owner is not the new one's
+                kprintln!("[kernel] SYS_FB_ACQUIRE: Framebuffer ({}x{}) handed to task {}.", info.width, info.height, current_task.id);
+                base
+            } else {
+                kprintln!("[kernel] SYS_FB_ACQUIRE: No framebuffer available (task {}).", current_task.id);
+                E_ERROR
+            }
+        }
+        SYS_TASK_STATS => {
+            // a1: task ID, a2: output buffer, a3: output buffer capacity.
+            // Callers built against an older ABI pass a smaller `TaskStats`; they get its prefix.
+            let out_cap = a3 as usize;
+            if out_cap < TASK_STATS_V1_LEN {
+                return E_ERROR;
+            }
+            if let Some(stats) = task::task_stats(TaskId::from_raw(a1)) {
+                let len = out_cap.min(core::mem::size_of::<TaskStats>());
+                // SAFETY: `a2` points to a writable buffer of at least `out_cap` bytes in the caller.
+                unsafe { core::ptr::copy_nonoverlapping(&stats as *const TaskStats as *const u8, a2 as *mut u8, len); }
+                len as u64
+            } else {
+                E_ERROR
+            }
+        }
+        SYS_IPC_LAST_SENDER => {
+            // Returns the task ID stamped on the last message the caller received.
+            match task::last_sender(current_task.id) {
+                Some(sender) => sender.raw(),
+                None => E_ERROR,
+            }
+        }
+        SYS_GET_IDENTITY => {
+            // a1: task ID, a2: output buffer for the 32-byte Aid, a3: output buffer capacity.
+            let size = core::mem::size_of::<Identity>();
+            if (a3 as usize) < size {
+                return E_ERROR;
+            }
+            match task::task_identity(TaskId::from_raw(a1)) {
+                Some(identity) => {
+                    // SAFETY: `a2` points to a writable buffer of at least `a3` bytes in the caller.
+                    unsafe { core::ptr::copy_nonoverlapping(identity.as_ptr(), a2 as *mut u8, size); }
+                    size as u64
+                }
+                None => E_UNAUTHENTICATED,
+            }
+        }
+        SYS_SET_IDENTITY => {
+            // a1: task ID, a2: pointer to a 32-byte Aid, or 0 to clear the binding.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IdentityAdmin) {
+                return E_ACC_DENIED;
+            }
+            let identity = if a2 == 0 {
+                None
+            } else {
+                let mut aid: Identity = [0; 32];
+                // SAFETY: `a2` points to 32 readable bytes in the caller.
+                unsafe { core::ptr::copy_nonoverlapping(a2 as *const u8, aid.as_mut_ptr(), aid.len()); }
+                Some(aid)
+            };
+            if task::set_task_identity(TaskId::from_raw(a1), identity) {
+                kprintln!("[kernel] syscall: Task {} {} identity of task {}.", current_task.id, if a2 == 0 { "cleared" } else { "bound" }, a1);
+                SUCCESS
+            } else {
+                E_ERROR
+            }
+        }
+        SYS_ABI_VERSION => ABI_VERSION,
+        SYS_SHARE_PAGES => {
+            // a1: page-aligned base of the pages to pin, a2: length, a3: task allowed to map them.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::StorageAccess) {
+                return E_ACC_DENIED;
+            }
+            match file_map::share_pages(current_task.id, TaskId::from_raw(a3), a1 as usize, a2 as usize) {
+                Ok(handle) => handle,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_UNSHARE_PAGES => {
+            // Returns E_BUSY while the backing is still mapped; the caller must keep the memory alive.
+            match file_map::unshare_pages(current_task.id, a1) {
+                Ok(()) => SUCCESS,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_SHARE_PAGES_ON_DEMAND => {
+            // As SYS_SHARE_PAGES, but the caller fills each page when a mapping first touches it:
+            // it collects the pages with SYS_PAGE_REQUESTS and reports them with SYS_PAGE_SUPPLY.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::StorageAccess) {
+                return E_ACC_DENIED;
+            }
+            match file_map::share_pages_on_demand(current_task.id, TaskId::from_raw(a3), a1 as usize, a2 as usize) {
+                Ok(handle) => handle,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_PAGE_REQUESTS => {
+            // a1: output buffer of PageRequest records, a2: its size in bytes. Returns the number
+            // of records written; pages that didn't fit are reported by the next call.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::StorageAccess) {
+                return E_ACC_DENIED;
+            }
+            let requests = file_map::page_requests(current_task.id, a2 as usize / PAGE_REQUEST_LEN);
+            for (i, (backing, offset)) in requests.iter().enumerate() {
+                let record = PageRequest { backing: *backing, offset: *offset as u64 };
+                // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                let out = unsafe { core::slice::from_raw_parts_mut((a1 as *mut u8).add(i * PAGE_REQUEST_LEN), PAGE_REQUEST_LEN) };
+                out.copy_from_slice(&record.to_bytes());
+            }
+            requests.len() as u64
+        }
+        SYS_PAGE_SUPPLY => {
+            // a1: on-demand backing, a2: offset of the page the caller has filled. Wakes the
+            // tasks waiting for it; returns how many there were.
+            match file_map::supply_page(current_task.id, a1, a2 as usize) {
+                Ok(woken) => woken as u64,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_MAP_FILE => {
+            // a1: backing handle, a2: page-aligned offset, a3: length. Returns the mapping's address.
+            match file_map::map_file(current_task.id, a1, a2 as usize, a3 as usize) {
+                Ok(addr) => addr as u64,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_UNMAP => {
+            // a1: address returned by SYS_MAP_FILE, a2: the length it was mapped with.
+            match file_map::unmap(current_task.id, a1 as usize, a2 as usize) {
+                Ok(()) => SUCCESS,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_INPUT_READ => {
+            // a1: output buffer, a2: its capacity. Returns the number of INPUT_EVENT_LEN-byte
+            // records written; 0 if nothing is queued. Input goes to whoever owns the display.
+            if framebuffer::owner() != current_task.id {
+                return E_ACC_DENIED;
+            }
+            // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+            let out = unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, a2 as usize) };
+            input::read(out) as u64
+        }
+        SYS_INPUT_CONFIG => {
+            // a1: key repeat delay in ms, a2: repeat rate in Hz (0 turns repeat off),
+            // a3: keyboard layout id. The display owner applies the user's settings.
+            if framebuffer::owner() != current_task.id {
+                return E_ACC_DENIED;
+            }
+            if ps2_keyboard::configure(a1, a2, a3) { SUCCESS } else { E_INVALID_ARG }
+        }
+        SYS_CLOCK_GETTIME => {
+            // a1: output buffer of at least CLOCK_TIME_LEN bytes, a2: its capacity.
+            // Returns CLOCK_TIME_LEN, or E_ERROR if there is no wall clock.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::TimeRead) {
+                return E_ACC_DENIED;
+            }
+            if (a2 as usize) < CLOCK_TIME_LEN {
+                return E_ERROR;
+            }
+            match rtc::now() {
+                Some(clock) => {
+                    // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                    let out = unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, CLOCK_TIME_LEN) };
+                    out.copy_from_slice(&clock.to_bytes());
+                    CLOCK_TIME_LEN as u64
+                }
+                None => E_ERROR,
+            }
+        }
+        SYS_DEBUG_READ_MEM | SYS_DEBUG_WRITE_MEM => {
+            // a1: target task, a2: DebugMemRequest (the kernel sets `copied`), a3: its size.
+            // SUCCESS if the whole range was copied; E_ERROR if it stopped early at memory
+            // the target can't access, with `copied` saying how far it got.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::Debug) {
+                return E_ACC_DENIED;
+            }
+            if (a3 as usize) < core::mem::size_of::<DebugMemRequest>() {
+                return E_INVALID_ARG;
+            }
+            // SAFETY: `a2` points to a DebugMemRequest in the caller.
+            let mut request = unsafe { core::ptr::read_unaligned(a2 as *const DebugMemRequest) };
+            if request.len > DEBUG_MEM_MAX {
+                return E_INVALID_ARG;
+            }
+            let result = if n == SYS_DEBUG_READ_MEM {
+                // SAFETY: `request.buf` points to a writable buffer of `request.len` bytes in the caller.
+                let out = unsafe { core::slice::from_raw_parts_mut(request.buf as *mut u8, request.len as usize) };
+                debug::read_memory(current_task.id, TaskId::from_raw(a1), request.addr, out)
+            } else {
+                // SAFETY: `request.buf` points to `request.len` readable bytes in the caller.
+                let data = unsafe { core::slice::from_raw_parts(request.buf as *const u8, request.len as usize) };
+                debug::write_memory(current_task.id, TaskId::from_raw(a1), request.addr, data)
+            };
+            match result {
+                Ok(copied) => {
+                    request.copied = copied as u64;
+                    // SAFETY: as above; the request block is writable.
+                    unsafe { core::ptr::write_unaligned(a2 as *mut DebugMemRequest, request); }
+                    if request.copied == request.len { SUCCESS } else { E_ERROR }
+                }
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_DEBUG_GET_REGS => {
+            // a1: target task, a2: output buffer, a3: its capacity. Returns the frame's size.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::Debug) {
+                return E_ACC_DENIED;
+            }
+            let size = core::mem::size_of::<RegisterFrame>();
+            if (a3 as usize) < size {
+                return E_ERROR;
+            }
+            match debug::registers(current_task.id, TaskId::from_raw(a1)) {
+                Ok(regs) => {
+                    // SAFETY: `a2` points to a writable buffer of at least `a3` bytes in the caller.
+                    unsafe { core::ptr::write_unaligned(a2 as *mut RegisterFrame, regs); }
+                    size as u64
+                }
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_DEBUG_SUSPEND | SYS_DEBUG_RESUME => {
+            // a1: target task.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::Debug) {
+                return E_ACC_DENIED;
+            }
+            let result = if n == SYS_DEBUG_SUSPEND {
+                debug::suspend(current_task.id, TaskId::from_raw(a1))
+            } else {
+                debug::resume(current_task.id, TaskId::from_raw(a1))
+            };
+            match result {
+                Ok(()) => SUCCESS,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_DEBUG_GET_BACKTRACE => {
+            // a1: target task, a2: output buffer of u64 addresses, a3: its size in bytes.
+            // Returns the number of addresses written, at most DEBUG_MAX_FRAMES.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::Debug) {
+                return E_ACC_DENIED;
+            }
+            let mut frames = [0u64; DEBUG_MAX_FRAMES];
+            let capacity = (a3 as usize / 8).min(DEBUG_MAX_FRAMES);
+            match debug::backtrace(current_task.id, TaskId::from_raw(a1), &mut frames[..capacity]) {
+                Ok(count) => {
+                    for (i, frame) in frames[..count].iter().enumerate() {
+                        // SAFETY: `a2` points to a writable buffer of at least `a3` bytes in the caller.
+                        unsafe { core::ptr::write_unaligned((a2 as *mut u64).add(i), *frame); }
+                    }
+                    count as u64
+                }
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_TASK_LIST => {
+            // a1: output buffer of u64 task IDs, a2: its size in bytes.
+            // Returns the number of tasks, which may exceed what fit.
+            let capacity = a2 as usize / 8;
+            let ids = task::task_ids();
+            for (i, id) in ids.iter().take(capacity).enumerate() {
+                // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                unsafe { core::ptr::write_unaligned((a1 as *mut u64).add(i), id.raw()); }
+            }
+            ids.len() as u64
+        }
+        SYS_KLOG_READ => {
+            // a1: output buffer, a2: its size in bytes, a3: KLOG_* flags.
+            // Copies the newest bytes that fit and returns the full length, which
+            // may exceed what fit. With KLOG_LAST_BOOT the output starts with a
+            // LastBootInfo record, and E_ERROR means the previous boot left no log.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::LogRead) {
+                return E_ACC_DENIED;
+            }
+            let out: &mut [u8] = if a2 == 0 {
+                &mut []
+            } else {
+                // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, a2 as usize) }
+            };
+            if a3 & KLOG_LAST_BOOT != 0 {
+                pstore::read_last_boot(out).map_or(E_ERROR, |len| len as u64)
+            } else {
+                klog::read(out) as u64
+            }
+        }
+        SYS_BOOT_STATUS => {
+            // a1: UTF-8 text, a2: its length, a3: BOOT_STATUS_* flags.
+            // Draws the line pinned to the bottom of the kernel console. Once a
+            // display V-Node owns the framebuffer this does nothing.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::BootStatus) {
+                return E_ACC_DENIED;
+            }
+            if a3 & BOOT_STATUS_CLEAR != 0 {
+                framebuffer::clear_status();
+                return SUCCESS;
+            }
+            // SAFETY: Caller provides pointer/len pair from its own memory space.
+            let bytes = unsafe { core::slice::from_raw_parts(a1 as *const u8, a2.min(BOOT_STATUS_MAX_LEN) as usize) };
+            framebuffer::set_status(&text::from_utf8_lossy(bytes), a3 & BOOT_STATUS_FAILED != 0);
+            SUCCESS
+        }
+        SYS_GET_STARTUP_INFO => {
+            // a1: output buffer, a2: its size in bytes.
+            // Copies the caller's startup info if it fits and returns its length
+            // either way, so a caller whose buffer was too small can retry.
+            // E_ERROR means the task was started without any.
+            let out: &mut [u8] = if a2 == 0 {
+                &mut []
+            } else {
+                // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, a2 as usize) }
+            };
+            task::read_startup_info(current_task.id, out).map_or(E_ERROR, |len| len as u64)
+        }
+        SYS_RANDOM => {
+            // a1: output buffer, a2: bytes to fill, at most RANDOM_MAX_LEN.
+            // Returns a2, or E_ERROR if the CPU has no random number generator.
+            if a2 as usize > RANDOM_MAX_LEN {
+                return E_INVALID_ARG;
+            }
+            // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+            let out = unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, a2 as usize) };
+            if rng::fill(out) { a2 } else { E_ERROR }
+        }
+        SYS_IPC_CALL => {
+            // a1: channel, a2: IpcCall (the kernel sets `token` and `reply_len`), a3: its size.
+            // SUCCESS with the reply in the caller's buffer. E_BUSY while the call waits:
+            // the caller was blocked and collects the reply by calling again with `token`.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IpcManage) {
+                return E_ACC_DENIED;
+            }
+            if (a3 as usize) < core::mem::size_of::<IpcCall>() {
+                return E_INVALID_ARG;
+            }
+            // SAFETY: `a2` points to an IpcCall in the caller.
+            let mut call = unsafe { core::ptr::read_unaligned(a2 as *const IpcCall) };
+            if call.token == 0 {
+                let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                    return E_INVALID_ARG;
+                };
+                // SAFETY: `call.req` points to `call.req_len` readable bytes in the caller.
+                let request = unsafe { core::slice::from_raw_parts(call.req as *const u8, call.req_len as usize) };
+                match ipc::call::start(channel_id, current_task.id, request, call.resp_cap as usize) {
+                    Ok(token) => {
+                        call.token = token;
+                        // SAFETY: as above; the block is writable.
+                        unsafe { core::ptr::write_unaligned(a2 as *mut IpcCall, call); }
+                        // The caller resumes here once the reply is in, and collects it on its next call.
+                        return E_BUSY;
+                    }
+                    Err(_) => return E_ERROR,
+                }
+            }
+            let result = match ipc::call::collect(call.token, current_task.id) {
+                ipc::call::Outcome::Replied(reply) => {
+                    call.reply_len = reply.len() as u64;
+                    // Checked at SYS_IPC_REPLY too, but against the capacity the call started with.
+                    if call.reply_len > call.resp_cap {
+                        E_ERROR
+                    } else {
+                        // SAFETY: `call.resp` points to a writable buffer of `call.resp_cap` bytes in the caller.
+                        unsafe { core::ptr::copy_nonoverlapping(reply.as_ptr(), call.resp as *mut u8, reply.len()); }
+                        SUCCESS
+                    }
+                }
+                ipc::call::Outcome::ReplyTooLarge(len) => {
+                    call.reply_len = len as u64;
+                    E_ERROR
+                }
+                ipc::call::Outcome::Dropped => E_ERROR,
+                ipc::call::Outcome::ServerGone => E_PEER_GONE,
+                ipc::call::Outcome::Waiting => {
+                    task::schedule();
+                    return E_BUSY;
+                }
+                ipc::call::Outcome::Unknown => return E_INVALID_ARG,
+            };
+            // SAFETY: as above; the block is writable.
+            unsafe { core::ptr::write_unaligned(a2 as *mut IpcCall, call); }
+            result
+        }
+        SYS_IPC_REPLY => {
+            // a1: reply token from SYS_IPC_REPLY_TOKEN, a2/a3: the reply.
+            // E_INVALID_ARG unless the caller received that call and hasn't answered it.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::IpcManage) {
+                return E_ACC_DENIED;
+            }
+            // SAFETY: `a2` points to `a3` readable bytes in the caller.
+            let reply = unsafe { core::slice::from_raw_parts(a2 as *const u8, a3 as usize) };
+            match ipc::call::reply(a1, current_task.id, reply) {
+                Ok(()) => SUCCESS,
+                Err(ipc::call::ReplyError::TooLarge) => E_ERROR,
+                Err(ipc::call::ReplyError::Unknown) => E_INVALID_ARG,
+            }
+        }
+        SYS_IPC_REPLY_TOKEN => {
+            // The reply token of the last message the caller received; 0 if it wasn't a call.
+            task::reply_token(current_task.id).unwrap_or(0)
+        }
+        SYS_FILTER_RESTRICT => {
+            // a1: the syscalls to keep. The filter becomes its intersection with a1.
+            task::restrict_syscall_filter(current_task.id, a1);
+            SUCCESS
+        }
+        SYS_AUDIO_OPEN => {
+            // a1: channel for completion notifications, a2: AudioRing buffer, a3: its size.
+            // E_ERROR without a sound card, E_BUSY while another task owns the output.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::AudioOutput) {
+                return E_ACC_DENIED;
+            }
+            if (a3 as usize) < AUDIO_RING_LEN {
+                return E_INVALID_ARG;
+            }
+            if !ac97::available() {
+                return E_ERROR;
+            }
+            let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                return E_INVALID_ARG;
+            };
+            match ac97::open(current_task.id, channel_id) {
+                Ok(ring) => {
+                    // SAFETY: `a2` points to a writable buffer of at least AUDIO_RING_LEN bytes in the caller.
+                    let out = unsafe { core::slice::from_raw_parts_mut(a2 as *mut u8, AUDIO_RING_LEN) };
+                    out.copy_from_slice(&ring.to_bytes());
+                    SUCCESS
+                }
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_AUDIO_QUEUE => {
+            // a1: ring period, a2: bytes of it to play. Returns the periods played since open;
+            // E_BUSY if every period is still queued. Errors are all high codes, never a count.
+            match ac97::queue(current_task.id, a1 as usize, a2 as usize) {
+                Ok(completed) => completed,
+                Err(KernelError::Busy) => E_BUSY,
+                Err(KernelError::PermissionDenied) => E_ACC_DENIED,
+                Err(_) => E_INVALID_ARG,
+            }
+        }
+        SYS_IPC_CREDS => {
+            // a1: output buffer for an IpcCreds record, a2: its capacity. Returns IPC_CREDS_LEN;
+            // E_INVALID_ARG if the buffer is too small, E_ERROR if nothing was received yet.
+            if (a2 as usize) < IPC_CREDS_LEN {
+                return E_INVALID_ARG;
+            }
+            match task::last_creds(current_task.id) {
+                Some(creds) => {
+                    // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                    let out = unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, IPC_CREDS_LEN) };
+                    out.copy_from_slice(&creds.to_bytes());
+                    IPC_CREDS_LEN as u64
+                }
+                None => E_ERROR,
+            }
+        }
+        SYS_SYSTEM_POWER => {
+            // a1: POWER_SHUTDOWN or POWER_REBOOT. Doesn't return on success; the caller
+            // should have stopped the services and synced the VFS first.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::PowerControl) {
+                return E_ACC_DENIED;
+            }
+            let action = match a1 {
+                POWER_SHUTDOWN => power::Action::Shutdown,
+                POWER_REBOOT => power::Action::Reboot,
+                _ => return E_INVALID_ARG,
+            };
+            kprintln!("[kernel] syscall: Task {} ({}) requested {:?}.", current_task.id, current_task.name, action);
+            power::power(action)
+        }
+        SYS_FAULT_STORMS => {
+            // a1: storm threshold in faults per second (0 turns the alarm off), a2: output
+            // buffer of FaultStorm records, a3: its size in bytes. Returns the number of
+            // records written; storms that didn't fit stay queued for the next call.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::TaskMonitor) {
+                return E_ACC_DENIED;
+            }
+            let Ok(threshold) = u32::try_from(a1) else {
+                return E_INVALID_ARG;
+            };
+            faults::set_storm_threshold(threshold);
+            let storms = faults::take_storms(a3 as usize / FAULT_STORM_LEN);
+            for (i, storm) in storms.iter().enumerate() {
+                // SAFETY: `a2` points to a writable buffer of at least `a3` bytes in the caller.
+                let out = unsafe { core::slice::from_raw_parts_mut((a2 as *mut u8).add(i * FAULT_STORM_LEN), FAULT_STORM_LEN) };
+                out.copy_from_slice(&storm.to_bytes());
+            }
+            storms.len() as u64
+        }
+        SYS_LOG_FORWARD => {
+            // a1: channel that receives a LogRecord for every SYS_LOG message from now on.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::LogRead) {
+                return E_ACC_DENIED;
+            }
+            let Some(channel_id) = ipc::ChannelId::from_arg(a1) else {
+                return E_INVALID_ARG;
+            };
+            match logfwd::register(channel_id, current_task.id) {
+                Ok(()) => SUCCESS,
+                Err(daemon) => {
+                    kprintln!("[kernel] syscall: Task {} asked for SYS_LOG messages, but task {} gets them.", current_task.id, daemon);
+                    E_BUSY
+                }
+            }
+        }
+        SYS_BOOT_ARGS => {
+            // a1: output buffer, a2: its size in bytes.
+            // Copies the kernel command line and returns its full length, 0 if
+            // the boot had none. Anyone may read it; the syscall filter can say otherwise.
+            let out: &mut [u8] = if a2 == 0 {
+                &mut []
+            } else {
+                // SAFETY: `a1` points to a writable buffer of at least `a2` bytes in the caller.
+                unsafe { core::slice::from_raw_parts_mut(a1 as *mut u8, a2 as usize) }
+            };
+            bootinfo::copy_line(out) as u64
+        }
+        SYS_DEBUG_DUMP => {
+            // a1: target task. Queues a core dump of it for init; the task isn't killed.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::Debug) {
+                return E_ACC_DENIED;
+            }
+            match debug::dump(current_task.id, TaskId::from_raw(a1)) {
+                Ok(()) => SUCCESS,
+                Err(e) => e.to_syscall_code(),
+            }
+        }
+        SYS_CORE_DUMP_TAKE => {
+            // a1: size cap of a dump in KiB (0 keeps no contents), a2: output buffer, a3: its
+            // size in bytes. Returns the full length of the oldest queued dump, 0 if none;
+            // the dump is copied and dropped from the queue only if it fits.
+            if !current_task.capabilities.iter().any(|cap| *cap == caps::Capability::TaskMonitor) {
+                return E_ACC_DENIED;
+            }
+            let Ok(cap_kb) = u32::try_from(a1) else {
+                return E_INVALID_ARG;
+            };
+            coredump::set_size_cap_kb(cap_kb);
+            let out: &mut [u8] = if a3 == 0 {
+                &mut []
+            } else {
+                // SAFETY: `a2` points to a writable buffer of at least `a3` bytes in the caller.
+                unsafe { core::slice::from_raw_parts_mut(a2 as *mut u8, a3 as usize) }
+            };
+            coredump::take(out) as u64
+        }
         _ => {
             kprintln!("[kernel] syscall: Unknown syscall number {} from task {}.", n, current_task.id);
             E_UNKNOWN_SYSCALL
@@ -0,0 +1,79 @@
+// common/src/tasks.rs
+
+//! Listing tasks and reading their stats: `SYS_TASK_LIST` and `SYS_TASK_STATS`.
+//! Also narrowing the caller's own syscall filter: `SYS_FILTER_RESTRICT`, and
+//! taking the fault storms the kernel saw: `SYS_FAULT_STORMS`, and the core
+//! dumps it queued: `SYS_CORE_DUMP_TAKE`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::abi::{FaultStorm, SyscallSet, TaskStats, E_SYSCALL_FILTERED, FAULT_STORM_LEN, SUCCESS, SYS_CORE_DUMP_TAKE, SYS_FAULT_STORMS, SYS_FILTER_RESTRICT, SYS_TASK_LIST, SYS_TASK_STATS};
+use crate::syscall::syscall3;
+
+/// IDs of all tasks, in ascending order. Tasks may start or exit right after.
+pub fn list() -> Vec<u64> {
+    let mut ids = alloc::vec![0u64; 64];
+    loop {
+        let res = unsafe { syscall3(SYS_TASK_LIST, ids.as_mut_ptr() as u64, (ids.len() * 8) as u64, 0) };
+        let total = res as usize;
+        if total <= ids.len() {
+            ids.truncate(total);
+            return ids;
+        }
+        // More tasks than fit; retry with room for them and a few that start meanwhile.
+        ids.resize(total + 16, 0);
+    }
+}
+
+/// Stats of a task, or `None` if it doesn't exist (anymore).
+pub fn stats(task_id: u64) -> Option<TaskStats> {
+    let mut stats = TaskStats::default();
+    let size = core::mem::size_of::<TaskStats>() as u64;
+    let res = unsafe { syscall3(SYS_TASK_STATS, task_id, &mut stats as *mut TaskStats as u64, size) };
+    if res == size { Some(stats) } else { None }
+}
+
+/// Drops every syscall not in `keep` from the caller's filter, for good. A
+/// task without a filter gets one. Typically called once initialization is
+/// done, before handling untrusted input.
+pub fn restrict_syscalls(keep: SyscallSet) -> bool {
+    unsafe { syscall3(SYS_FILTER_RESTRICT, keep, 0, 0) == SUCCESS }
+}
+
+/// Storms taken per `SYS_FAULT_STORMS` call; more wait for the next one.
+const FAULT_STORMS_PER_CALL: usize = 16;
+
+/// Sets the kernel's fault storm threshold, in faults per second (0 turns
+/// the alarm off), and takes the storms tasks raised since the last call.
+/// `None` if the kernel refused, e.g. because the caller lacks `CAP_TASK_MONITOR`.
+pub fn take_fault_storms(threshold: u32) -> Option<Vec<FaultStorm>> {
+    let mut buf = [0u8; FAULT_STORMS_PER_CALL * FAULT_STORM_LEN];
+    let res = unsafe { syscall3(SYS_FAULT_STORMS, threshold as u64, buf.as_mut_ptr() as u64, buf.len() as u64) };
+    if res as usize > FAULT_STORMS_PER_CALL {
+        return None; // An error code
+    }
+    Some(buf.chunks(FAULT_STORM_LEN).take(res as usize).filter_map(FaultStorm::from_bytes).collect())
+}
+
+/// Sets the kernel's cap on a core dump, in KiB (0 keeps only the header,
+/// registers and region list), and takes the oldest dump it queued, in the
+/// `common::coredump` format. `None` if there is none, or if the kernel
+/// refused, e.g. because the caller lacks `CAP_TASK_MONITOR`.
+pub fn take_core_dump(cap_kb: u32) -> Option<Vec<u8>> {
+    let len = unsafe { syscall3(SYS_CORE_DUMP_TAKE, cap_kb as u64, 0, 0) };
+    // The error codes are the top of the range, far past any dump.
+    if len == 0 || len >= E_SYSCALL_FILTERED {
+        return None;
+    }
+    let mut buf = alloc::vec![0u8; len as usize];
+    let res = unsafe { syscall3(SYS_CORE_DUMP_TAKE, cap_kb as u64, buf.as_mut_ptr() as u64, buf.len() as u64) };
+    // Another taker can't have got in between: only init holds the capability.
+    if res != len {
+        return None;
+    }
+    Some(buf)
+}
@@ -0,0 +1,242 @@
+// common/src/text.rs
+
+//! UTF-8 aware string helpers.
+//!
+//! Everything here works on codepoints: truncation never splits a multi-byte
+//! sequence, but a grapheme made of several codepoints (e.g., a letter plus a
+//! combining accent) may still be cut between its parts. Display widths follow
+//! a simplified East Asian Width table: wide and fullwidth characters take two
+//! terminal cells, combining marks and controls take none.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+/// Substituted for invalid byte sequences and for characters a font can't draw.
+pub const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
+/// Decodes `bytes` as UTF-8, replacing each invalid sequence with U+FFFD.
+/// Borrows when the input is already valid.
+pub fn from_utf8_lossy(bytes: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(bytes)
+}
+
+/// Longest prefix of `s` that is at most `max_bytes` long and ends on a char boundary.
+pub fn truncate_to_bytes(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Prefix of `s` holding at most `max_chars` codepoints.
+pub fn truncate_to_chars(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
+
+/// Number of terminal cells `c` occupies: 0, 1 or 2.
+pub fn char_width(c: char) -> usize {
+    let cp = c as u32;
+    if cp == 0 || c.is_control() || is_zero_width(cp) {
+        0
+    } else if is_wide(cp) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Number of terminal cells `s` occupies.
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// Longest prefix of `s` that fits in `max_cells` terminal cells. A wide
+/// character that would straddle the limit is left out entirely.
+pub fn truncate_to_width(s: &str, max_cells: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in s.char_indices() {
+        let width = char_width(c);
+        if used + width > max_cells {
+            return &s[..i];
+        }
+        used += width;
+    }
+    s
+}
+
+/// Shortens `s` to at most `max_bytes`, appending "..." when anything was cut.
+/// The result (including the marker) never exceeds `max_bytes` bytes.
+pub fn ellipsize_bytes(s: &str, max_bytes: usize) -> Cow<'_, str> {
+    const MARKER: &str = "...";
+    if s.len() <= max_bytes {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::from(truncate_to_bytes(s, max_bytes.saturating_sub(MARKER.len())));
+    out.push_str(truncate_to_bytes(MARKER, max_bytes));
+    Cow::Owned(out)
+}
+
+fn is_zero_width(cp: u32) -> bool {
+    matches!(cp,
+        0x0300..=0x036F   // Combining diacritical marks
+        | 0x0483..=0x0489 // Cyrillic combining marks
+        | 0x0591..=0x05BD // Hebrew points
+        | 0x200B..=0x200F // Zero-width space, joiners, direction marks
+        | 0x20D0..=0x20FF // Combining marks for symbols
+        | 0xFE00..=0xFE0F // Variation selectors
+        | 0xFE20..=0xFE2F // Combining half marks
+    )
+}
+
+fn is_wide(cp: u32) -> bool {
+    matches!(cp,
+        0x1100..=0x115F     // Hangul Jamo initial consonants
+        | 0x2E80..=0x303E   // CJK radicals, punctuation
+        | 0x3041..=0x33FF   // Hiragana, Katakana, CJK compatibility
+        | 0x3400..=0x4DBF   // CJK extension A
+        | 0x4E00..=0x9FFF   // CJK unified ideographs
+        | 0xA000..=0xA4CF   // Yi
+        | 0xAC00..=0xD7A3   // Hangul syllables
+        | 0xF900..=0xFAFF   // CJK compatibility ideographs
+        | 0xFE30..=0xFE4F   // CJK compatibility forms
+        | 0xFF00..=0xFF60   // Fullwidth forms
+        | 0xFFE0..=0xFFE6   // Fullwidth signs
+        | 0x1F300..=0x1F64F // Pictographs, emoticons
+        | 0x1F900..=0x1F9FF // Supplemental symbols and pictographs
+        | 0x20000..=0x2FFFD // CJK extensions B and later
+        | 0x30000..=0x3FFFD
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    // "aé中😀": one, two, three and four bytes; widths 1, 1, 2, 2.
+    const MIXED: &str = "a\u{E9}\u{4E2D}\u{1F600}";
+
+    /// Splits `s` into rows of at most `cells` columns, the way the terminal
+    /// wraps a line that runs past the right edge.
+    fn rows(mut s: &str, cells: usize) -> Vec<&str> {
+        let mut rows = Vec::new();
+        while !s.is_empty() {
+            let row = truncate_to_width(s, cells);
+            rows.push(row);
+            s = &s[row.len()..];
+        }
+        rows
+    }
+
+    #[test]
+    fn widths_of_each_class() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('\u{E9}'), 1);
+        assert_eq!(char_width('\u{4E2D}'), 2);
+        assert_eq!(char_width('\u{AC00}'), 2);
+        assert_eq!(char_width('\u{FF21}'), 2);
+        assert_eq!(char_width('\u{1F600}'), 2);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('\u{200B}'), 0);
+        assert_eq!(char_width('\0'), 0);
+        assert_eq!(char_width('\n'), 0);
+        assert_eq!(char_width('\u{1B}'), 0);
+    }
+
+    #[test]
+    fn display_width_of_mixed_strings() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("hello"), 5);
+        assert_eq!(display_width(MIXED), 6);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\u{65E5}\u{672C}\u{8A9E} ok"), 9);
+    }
+
+    #[test]
+    fn truncate_to_bytes_backs_off_to_a_char_boundary() {
+        // Boundaries of MIXED fall at 0, 1, 3, 6 and 10.
+        let expected = [0, 1, 1, 3, 3, 3, 6, 6, 6, 6, 10, 10];
+        for (max, &end) in expected.iter().enumerate() {
+            assert_eq!(truncate_to_bytes(MIXED, max), &MIXED[..end], "max_bytes {}", max);
+        }
+    }
+
+    #[test]
+    fn truncate_to_chars_counts_codepoints() {
+        assert_eq!(truncate_to_chars(MIXED, 0), "");
+        assert_eq!(truncate_to_chars(MIXED, 2), "a\u{E9}");
+        assert_eq!(truncate_to_chars(MIXED, 3), "a\u{E9}\u{4E2D}");
+        assert_eq!(truncate_to_chars(MIXED, 4), MIXED);
+        assert_eq!(truncate_to_chars(MIXED, 100), MIXED);
+        // A combining accent is its own codepoint, so it can be cut off.
+        assert_eq!(truncate_to_chars("e\u{301}", 1), "e");
+    }
+
+    #[test]
+    fn truncate_to_width_leaves_out_a_straddling_wide_char() {
+        assert_eq!(truncate_to_width(MIXED, 0), "");
+        assert_eq!(truncate_to_width(MIXED, 2), "a\u{E9}");
+        assert_eq!(truncate_to_width(MIXED, 3), "a\u{E9}");
+        assert_eq!(truncate_to_width(MIXED, 4), "a\u{E9}\u{4E2D}");
+        assert_eq!(truncate_to_width(MIXED, 5), "a\u{E9}\u{4E2D}");
+        assert_eq!(truncate_to_width(MIXED, 6), MIXED);
+        // Zero-width characters ride along with the one before them.
+        assert_eq!(truncate_to_width("e\u{301}x", 1), "e\u{301}");
+    }
+
+    #[test]
+    fn wrapping_never_splits_a_char_or_overflows_a_row() {
+        let line = "ab\u{4E2D}\u{6587}c\u{1F600}de";
+        assert_eq!(rows(line, 3), ["ab", "\u{4E2D}", "\u{6587}c", "\u{1F600}d", "e"]);
+        assert_eq!(rows(line, 4), ["ab\u{4E2D}", "\u{6587}c", "\u{1F600}de"]);
+        for cells in 2..12 {
+            let rows = rows(line, cells);
+            assert!(rows.iter().all(|row| display_width(row) <= cells));
+            assert_eq!(rows.concat(), line);
+        }
+    }
+
+    #[test]
+    fn ellipsize_keeps_within_the_byte_limit() {
+        assert_eq!(ellipsize_bytes("short", 5), "short");
+        assert!(matches!(ellipsize_bytes("short", 5), Cow::Borrowed(_)));
+        assert_eq!(ellipsize_bytes("abcdefgh", 6), "abc...");
+        // "中" is three bytes: with 5 bytes there's only room for the marker.
+        assert_eq!(ellipsize_bytes("\u{4E2D}\u{6587}\u{5B57}", 5), "...");
+        assert_eq!(ellipsize_bytes("\u{4E2D}\u{6587}\u{5B57}", 6), "\u{4E2D}...");
+        assert_eq!(ellipsize_bytes("abcdef", 2), "..");
+        assert_eq!(ellipsize_bytes("abcdef", 0), "");
+        for max in 0..MIXED.len() {
+            assert!(ellipsize_bytes(MIXED, max).len() <= max, "max_bytes {}", max);
+        }
+    }
+
+    #[test]
+    fn lossy_decoding_replaces_only_the_bad_bytes() {
+        assert!(matches!(from_utf8_lossy(MIXED.as_bytes()), Cow::Borrowed(s) if s == MIXED));
+        assert_eq!(from_utf8_lossy(b"ok\xFFok"), "ok\u{FFFD}ok");
+        // A multi-byte sequence cut at every point decodes to the whole chars
+        // before the cut and one replacement for the partial one.
+        let bytes = MIXED.as_bytes();
+        for end in 0..=bytes.len() {
+            let decoded = from_utf8_lossy(&bytes[..end]);
+            let whole = truncate_to_bytes(MIXED, end);
+            if whole.len() == end {
+                assert_eq!(decoded, whole);
+            } else {
+                assert_eq!(decoded, alloc::format!("{}{}", whole, REPLACEMENT_CHAR));
+            }
+        }
+    }
+}
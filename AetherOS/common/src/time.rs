@@ -0,0 +1,385 @@
+// common/src/time.rs
+
+//! Calendar time: conversions between Unix-epoch seconds and a broken-down
+//! UTC `DateTime`, and the two text forms the system uses. RFC 2822 is for
+//! mail `Date` headers, ISO 8601 for logs and listings.
+//!
+//! Time is stored and exchanged as UTC epoch seconds. The `time.utc_offset_minutes`
+//! setting is applied only when formatting for display.
+//!
+//! Durations are measured with `monotonic_nanos` instead, which doesn't depend
+//! on the wall clock.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::abi::{WallClock, CLOCK_TIME_LEN, E_ACC_DENIED, E_INVALID_ARG, SYS_CLOCK_GETTIME, SYS_TIME, TIME_FLAGS, TIME_FLAG_HIGH_RES, TIME_NANOS, TIME_TICKS};
+use crate::ids::Ticks;
+use crate::syscall::syscall3;
+
+pub const NANOS_PER_TICK: u64 = 10_000_000;
+pub const SECS_PER_MINUTE: u64 = 60;
+pub const SECS_PER_DAY: u64 = 86_400;
+
+/// Largest UTC offset accepted for display, in minutes (UTC+14:00 and UTC-14:00).
+pub const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// The current wall-clock time from the kernel (`SYS_CLOCK_GETTIME`), or
+/// `None` if the kernel has no clock or the caller lacks `CAP_TIME_READ`.
+pub fn now() -> Option<WallClock> {
+    let mut buf = [0u8; CLOCK_TIME_LEN];
+    let res = unsafe { syscall3(SYS_CLOCK_GETTIME, buf.as_mut_ptr() as u64, buf.len() as u64, 0) };
+    if res != CLOCK_TIME_LEN as u64 {
+        return None;
+    }
+    WallClock::from_bytes(&buf)
+}
+
+/// Nanoseconds since boot (`SYS_TIME(TIME_NANOS)`), for measuring durations.
+/// Kernels older than ABI version 17 reject the clock; there this is the tick
+/// count converted. 0 if the caller lacks `CAP_TIME_READ`.
+pub fn monotonic_nanos() -> u64 {
+    match unsafe { syscall3(SYS_TIME, TIME_NANOS, 0, 0) } {
+        E_ACC_DENIED => 0,
+        E_INVALID_ARG => unsafe { syscall3(SYS_TIME, TIME_TICKS, 0, 0) }.saturating_mul(NANOS_PER_TICK),
+        nanos => nanos,
+    }
+}
+
+/// Timer ticks since boot (`SYS_TIME(TIME_TICKS)`), for timeouts and
+/// deadlines that are counted in ticks, like an envelope's.
+pub fn ticks() -> Ticks {
+    Ticks::from_raw(unsafe { syscall3(SYS_TIME, TIME_TICKS, 0, 0) })
+}
+
+/// Whether `monotonic_nanos` is finer than a tick, i.e. the kernel calibrated
+/// an invariant TSC.
+pub fn high_res() -> bool {
+    let flags = unsafe { syscall3(SYS_TIME, TIME_FLAGS, 0, 0) };
+    flags != E_ACC_DENIED && flags != E_INVALID_ARG && flags & TIME_FLAG_HIGH_RES != 0
+}
+
+/// Current epoch seconds, or 0 if the clock is unavailable. For timestamps
+/// where "unknown" is acceptable, like file metadata.
+pub fn now_secs() -> u64 {
+    now().map_or(0, |clock| clock.secs)
+}
+
+pub fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: u32, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// A UTC calendar date and time of day, to the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8, // 1-12
+    pub day: u8,   // 1-31
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_epoch(secs: u64) -> Self {
+        let days = secs / SECS_PER_DAY;
+        let rem = secs % SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// Epoch seconds, or `None` for an invalid date or one before 1970.
+    pub fn to_epoch(&self) -> Option<u64> {
+        if !self.is_valid() || self.year < 1970 {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        Some(days * SECS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Day of the week, 0 = Monday.
+    pub fn weekday(&self) -> u8 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        ((days + 3) % 7) as u8 // 1970-01-01 was a Thursday
+    }
+}
+
+/// Status register B flags that say how the RTC reports the time.
+pub const RTC_STATUS_B_24_HOUR: u8 = 0x02;
+pub const RTC_STATUS_B_BINARY: u8 = 0x04;
+/// Set in the hour register for PM in 12-hour mode.
+pub const RTC_HOUR_PM: u8 = 0x80;
+
+/// The CMOS RTC's time registers as read, before decoding. `century` is 0 on
+/// machines without a century register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+    pub century: u8,
+}
+
+impl RtcRegisters {
+    /// Converts the registers to a UTC date, honouring the BCD and 12-hour
+    /// flags of status register B.
+    pub fn decode(&self, status_b: u8) -> DateTime {
+        let binary = status_b & RTC_STATUS_B_BINARY != 0;
+        let convert = |value: u8| if binary { value } else { bcd_to_binary(value) };
+        let pm = self.hour & RTC_HOUR_PM != 0;
+        let mut hour = convert(self.hour & !RTC_HOUR_PM);
+        if status_b & RTC_STATUS_B_24_HOUR == 0 {
+            // 12 AM is hour 0, 12 PM is hour 12.
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+        let century = match convert(self.century) {
+            century @ 19..=21 => century as u32,
+            _ => 20, // No century register; assume 2000-2099
+        };
+        DateTime {
+            year: century * 100 + convert(self.year) as u32,
+            month: convert(self.month),
+            day: convert(self.day),
+            hour,
+            minute: convert(self.minute),
+            second: convert(self.second),
+        }
+    }
+}
+
+pub fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Days since 1970-01-01 of a date in or after 1970.
+fn days_from_civil(year: u32, month: u8, day: u8) -> u64 {
+    // Counts from 0000-03-01 so the leap day is the last day of the year.
+    let y = (if month <= 2 { year - 1 } else { year }) as u64;
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let m = month as u64;
+    let day_of_year = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: u64) -> (u32, u8, u8) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = (year_of_era + era * 400) as u32 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Clamps an offset to `MAX_UTC_OFFSET_MINUTES` and applies it to `secs`.
+fn local(secs: u64, offset_minutes: i32) -> (DateTime, i32) {
+    let offset = offset_minutes.clamp(-MAX_UTC_OFFSET_MINUTES, MAX_UTC_OFFSET_MINUTES);
+    let shifted = (secs as i64 + offset as i64 * SECS_PER_MINUTE as i64).max(0) as u64;
+    (DateTime::from_epoch(shifted), offset)
+}
+
+/// RFC 2822 date, e.g. `Sat, 17 Oct 2026 05:26:27 +0200`.
+pub fn format_rfc2822(secs: u64, offset_minutes: i32) -> String {
+    let (dt, offset) = local(secs, offset_minutes);
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} {}{:02}{:02}",
+        WEEKDAYS[dt.weekday() as usize], dt.day, MONTHS[dt.month as usize - 1], dt.year,
+        dt.hour, dt.minute, dt.second, sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+/// Epoch seconds of an RFC 2822 date, e.g. `Sat, 17 Oct 2026 05:26:27 +0200`.
+/// The weekday and the seconds may be left out, and the weekday isn't checked.
+/// A missing zone is UTC, as are `UT`, `GMT` and `Z`. `None` if it doesn't
+/// parse or is before 1970 in UTC.
+pub fn parse_rfc2822(s: &str) -> Option<u64> {
+    let s = s.split_once(',').map_or(s, |(_, rest)| rest);
+    let mut parts = s.split_whitespace();
+    let day = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month_name))? as u8 + 1;
+    let year = parts.next()?.parse().ok()?;
+    let mut clock = parts.next()?.split(':');
+    let hour = clock.next()?.parse().ok()?;
+    let minute = clock.next()?.parse().ok()?;
+    let second = match clock.next() {
+        Some(second) => second.parse().ok()?,
+        None => 0,
+    };
+    let offset_minutes: i64 = match parts.next().unwrap_or("Z") {
+        "UT" | "GMT" | "Z" => 0,
+        zone => {
+            let (sign, digits) = match zone.as_bytes().first()? {
+                b'+' => (1, &zone[1..]),
+                b'-' => (-1, &zone[1..]),
+                _ => return None,
+            };
+            if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let hhmm: i64 = digits.parse().ok()?;
+            sign * (hhmm / 100 * 60 + hhmm % 100)
+        }
+    };
+    let local = DateTime { year, month, day, hour, minute, second }.to_epoch()?;
+    u64::try_from(local as i64 - offset_minutes * SECS_PER_MINUTE as i64).ok()
+}
+
+/// ISO 8601 date and time, e.g. `2026-10-17T05:26:27+02:00`, or with `Z` for UTC.
+pub fn format_iso8601(secs: u64, offset_minutes: i32) -> String {
+    let (dt, offset) = local(secs, offset_minutes);
+    let zone = if offset == 0 {
+        String::from("Z")
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        format!("{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+    };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}", dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second, zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    /// 2026-10-17 05:26:27 as a BCD RTC in 24-hour mode reports it.
+    const BCD: RtcRegisters = RtcRegisters { second: 0x27, minute: 0x26, hour: 0x05, day: 0x17, month: 0x10, year: 0x26, century: 0x20 };
+
+    #[test]
+    fn bcd_registers_decode_to_the_date() {
+        assert_eq!(bcd_to_binary(0x59), 59);
+        assert_eq!(bcd_to_binary(0x00), 0);
+        assert_eq!(BCD.decode(RTC_STATUS_B_24_HOUR), date(2026, 10, 17, 5, 26, 27));
+        let binary = RtcRegisters { second: 27, minute: 26, hour: 5, day: 17, month: 10, year: 26, century: 20 };
+        assert_eq!(binary.decode(RTC_STATUS_B_24_HOUR | RTC_STATUS_B_BINARY), date(2026, 10, 17, 5, 26, 27));
+    }
+
+    #[test]
+    fn twelve_hour_mode_puts_midnight_at_zero_and_noon_at_twelve() {
+        let at = |hour: u8| RtcRegisters { hour, ..BCD }.decode(0).hour;
+        assert_eq!(at(0x12), 0);
+        assert_eq!(at(0x01), 1);
+        assert_eq!(at(RTC_HOUR_PM | 0x12), 12);
+        assert_eq!(at(RTC_HOUR_PM | 0x11), 23);
+        let binary_pm = RtcRegisters { hour: RTC_HOUR_PM | 5, ..BCD }.decode(RTC_STATUS_B_BINARY);
+        assert_eq!(binary_pm.hour, 17);
+    }
+
+    #[test]
+    fn the_century_register_is_used_when_it_is_plausible() {
+        assert_eq!(RtcRegisters { year: 0x99, century: 0x19, ..BCD }.decode(RTC_STATUS_B_24_HOUR).year, 1999);
+        assert_eq!(RtcRegisters { year: 0x00, century: 0x21, ..BCD }.decode(RTC_STATUS_B_24_HOUR).year, 2100);
+        for century in [0x00, 0x45, 0xFF] {
+            assert_eq!(RtcRegisters { century, ..BCD }.decode(RTC_STATUS_B_24_HOUR).year, 2026, "century register {:#x}", century);
+        }
+    }
+
+    #[test]
+    fn leap_years_follow_the_gregorian_rules() {
+        assert!(is_leap_year(2024));
+        assert!(is_leap_year(2000));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+        assert!(!is_leap_year(2026));
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2026, 13), 0);
+        assert!(date(2000, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(2100, 2, 29, 0, 0, 0).is_valid());
+        assert_eq!(date(2100, 2, 29, 0, 0, 0).to_epoch(), None);
+    }
+
+    #[test]
+    fn epoch_seconds_round_trip_across_leap_days_and_centuries() {
+        let cases = [
+            (date(1970, 1, 1, 0, 0, 0), 0),
+            (date(2000, 2, 29, 12, 0, 0), 951_825_600),
+            (date(2000, 3, 1, 0, 0, 0), 951_868_800),
+            (date(1999, 12, 31, 23, 59, 59), 946_684_799),
+            (date(2038, 1, 19, 3, 14, 8), 2_147_483_648),
+            (date(2100, 3, 1, 0, 0, 0), 4_107_542_400),
+        ];
+        for (dt, secs) in cases {
+            assert_eq!(dt.to_epoch(), Some(secs), "{:?}", dt);
+            assert_eq!(DateTime::from_epoch(secs), dt);
+        }
+        // 2100 isn't a leap year: the day after 28 February is 1 March.
+        assert_eq!(DateTime::from_epoch(4_107_542_400 - SECS_PER_DAY), date(2100, 2, 28, 0, 0, 0));
+        // Every day from 1970 to 2401 comes back as itself.
+        for days in (0..157_000u64).step_by(7) {
+            let dt = DateTime::from_epoch(days * SECS_PER_DAY);
+            assert!(dt.is_valid());
+            assert_eq!(dt.to_epoch(), Some(days * SECS_PER_DAY));
+        }
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).to_epoch(), None);
+    }
+
+    #[test]
+    fn weekdays_count_from_monday() {
+        assert_eq!(date(1970, 1, 1, 0, 0, 0).weekday(), 3);
+        assert_eq!(date(2000, 1, 1, 0, 0, 0).weekday(), 5);
+        assert_eq!(date(2026, 10, 17, 0, 0, 0).weekday(), 5);
+        assert_eq!(date(2100, 1, 1, 0, 0, 0).weekday(), 4);
+    }
+
+    #[test]
+    fn text_forms_apply_the_offset_and_parse_back() {
+        let secs = date(2026, 10, 17, 3, 26, 27).to_epoch().unwrap();
+        assert_eq!(format_rfc2822(secs, 120), "Sat, 17 Oct 2026 05:26:27 +0200");
+        assert_eq!(format_iso8601(secs, 0), "2026-10-17T03:26:27Z");
+        assert_eq!(format_iso8601(secs, -330), "2026-10-16T21:56:27-05:30");
+        assert_eq!(format_iso8601(secs, 24 * 60), format_iso8601(secs, MAX_UTC_OFFSET_MINUTES));
+        assert_eq!(parse_rfc2822("Sat, 17 Oct 2026 05:26:27 +0200"), Some(secs));
+        assert_eq!(parse_rfc2822("17 oct 2026 03:26 GMT"), Some(secs - 27));
+        assert_eq!(parse_rfc2822("31 Dec 1999 23:59:59"), Some(946_684_799));
+        assert_eq!(parse_rfc2822("29 Feb 2100 00:00:00"), None);
+        assert_eq!(parse_rfc2822("17 Oct 2026 05:26:27 CEST"), None);
+    }
+}
@@ -0,0 +1,425 @@
+// common/src/trust.rs
+
+//! Identities, signatures and key rotation.
+//!
+//! An Aid is an ed25519 public key, so anyone holding one can check what it
+//! signed. A node's DHT `NodeId` is derived from its Aid (`Aid::node_id`).
+//!
+//! `LocalIdentity` is this node's own keypair, kept in a VFS file and loaded
+//! at startup with `load_or_create`. `TrustStore` checks signatures on behalf
+//! of an Aid and follows the key rotations it has accepted: once the owner of
+//! an Aid rotates to a new key, content signed with either key verifies as
+//! theirs, and trust placed in the old Aid carries over.
+//!
+//! ## Identity file
+//!
+//! | Offset | Length | Field |
+//! |---|---|---|
+//! | 0 | 4 | `IDENTITY_MAGIC` (`AXID`) |
+//! | 4 | 1 | `IDENTITY_FILE_VERSION` |
+//! | 5 | 1 | 0: plain, 1: passphrase-encrypted |
+//! | 6 | 16 | KDF salt (zero when plain) |
+//! | 22 | 4 | KDF iterations, little-endian (0 when plain) |
+//! | 26 | 32 | The Aid |
+//! | 58 | 32 | The secret key seed, XORed with the key stream when encrypted |
+//! | 90 | 32 | Check value over everything before it |
+//!
+//! The passphrase key is SHA-256 iterated `KDF_ITERATIONS` times over the salt
+//! and passphrase. It is a stopgap until a memory-hard KDF is available: it
+//! only keeps a copied file from being read outright. Service identities use
+//! the plain form and rely on the VFS's access control instead.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use crate::ipc::vfs_tx::VfsTx;
+use crate::ipc::vnode::VNodeChannel;
+use crate::random;
+
+/// Length of an ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Most rotations followed from one Aid; a longer chain is refused.
+pub const MAX_ROTATIONS: usize = 16;
+
+pub const IDENTITY_MAGIC: [u8; 4] = *b"AXID";
+pub const IDENTITY_FILE_VERSION: u8 = 1;
+pub const IDENTITY_FILE_LEN: usize = 122;
+/// Iterations for newly encrypted identity files.
+pub const KDF_ITERATIONS: u32 = 100_000;
+/// Files asking for more are refused, so a crafted file can't stall the caller.
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+const MODE_PLAIN: u8 = 0;
+const MODE_PASSPHRASE: u8 = 1;
+const SALT_LEN: usize = 16;
+
+// Domain separation: every hash and signed message starts with its own tag,
+// so a value made for one purpose is never valid for another.
+const NODE_ID_DOMAIN: &[u8] = b"aether-node-id-v1";
+const ROTATION_DOMAIN: &[u8] = b"aether-key-rotation-v1";
+const KDF_DOMAIN: &[u8] = b"aether-identity-kdf-v1";
+const STREAM_DOMAIN: &[u8] = b"aether-identity-stream-v1";
+const CHECK_DOMAIN: &[u8] = b"aether-identity-check-v1";
+
+/// VFS error code for a missing file.
+const ENOENT: i32 = 2;
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// An AetherOS identity: the 32-byte ed25519 public key of its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Aid(pub [u8; 32]);
+
+impl Aid {
+    pub fn from_public_key(key: &VerifyingKey) -> Self {
+        Aid(key.to_bytes())
+    }
+
+    /// `None` if the bytes aren't a valid ed25519 point.
+    pub fn public_key(&self) -> Option<VerifyingKey> {
+        VerifyingKey::from_bytes(&self.0).ok()
+    }
+
+    /// The DHT node ID of a node with this identity: SHA-256 over
+    /// `aether-node-id-v1` followed by the Aid.
+    pub fn node_id(&self) -> [u8; 32] {
+        sha256(&[NODE_ID_DOMAIN, &self.0])
+    }
+}
+
+/// Checks that `signature` over `message` was made with `aid`'s key. Rotations aren't followed.
+pub fn verify(aid: &Aid, message: &[u8], signature: &[u8]) -> bool {
+    match (aid.public_key(), Signature::from_slice(signature)) {
+        (Some(key), Ok(signature)) => key.verify_strict(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+/// The owner of `old` moving to the key `new`, signed with both keys: the old
+/// one shows the owner agreed, the new one that they hold it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationStatement {
+    pub old: Aid,
+    pub new: Aid,
+    pub old_signature: Vec<u8>,
+    pub new_signature: Vec<u8>,
+}
+
+impl RotationStatement {
+    /// What both keys sign: `aether-key-rotation-v1`, the old Aid, the new Aid.
+    pub fn signed_bytes(old: &Aid, new: &Aid) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ROTATION_DOMAIN.len() + 64);
+        bytes.extend_from_slice(ROTATION_DOMAIN);
+        bytes.extend_from_slice(&old.0);
+        bytes.extend_from_slice(&new.0);
+        bytes
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let signed = Self::signed_bytes(&self.old, &self.new);
+        self.old != self.new && verify(&self.old, &signed, &self.old_signature) && verify(&self.new, &signed, &self.new_signature)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).unwrap_or_default()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        postcard::from_bytes(bytes).ok()
+    }
+}
+
+/// Why `TrustStore::accept_rotation` refused a statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustError {
+    /// One of the signatures doesn't verify.
+    BadSignature,
+    /// The old key was already rotated to a different one. Only the first
+    /// rotation counts, so whoever steals an old key can't redirect its Aid.
+    Conflict { current: Aid },
+    /// The new key already leads back to the old one.
+    Cycle,
+    /// The chain would be longer than `MAX_ROTATIONS`.
+    TooManyRotations,
+}
+
+impl fmt::Display for TrustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadSignature => f.write_str("rotation statement has a bad signature"),
+            Self::Conflict { .. } => f.write_str("key was already rotated to a different key"),
+            Self::Cycle => f.write_str("rotation would form a cycle"),
+            Self::TooManyRotations => write!(f, "more than {} rotations", MAX_ROTATIONS),
+        }
+    }
+}
+
+/// Checks signatures, following the key rotations it has accepted.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    successors: BTreeMap<Aid, Aid>, // Old key -> the key it was rotated to
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `aid` and every key it was rotated to since, oldest first.
+    pub fn keys(&self, aid: &Aid) -> Vec<Aid> {
+        let mut keys = alloc::vec![*aid];
+        while let Some(next) = self.successors.get(keys.last().unwrap()) {
+            if keys.len() > MAX_ROTATIONS || keys.contains(next) {
+                break;
+            }
+            keys.push(*next);
+        }
+        keys
+    }
+
+    /// The key `aid` was last rotated to, or `aid` itself. Reputation and
+    /// trust decisions should be keyed by the Aid a chain starts from, and
+    /// signatures checked with `verify_signature`, which accepts any key in it.
+    pub fn current(&self, aid: &Aid) -> Aid {
+        *self.keys(aid).last().unwrap()
+    }
+
+    /// True if `signature` over `message` was made by `aid` or a key it was
+    /// rotated to. Keys earlier in a chain stay valid, so content signed
+    /// before a rotation still verifies.
+    pub fn verify_signature(&self, aid: &Aid, message: &[u8], signature: &[u8]) -> bool {
+        self.keys(aid).iter().any(|key| verify(key, message, signature))
+    }
+
+    /// Records a rotation after checking both of its signatures. Accepting
+    /// the same statement twice is fine.
+    pub fn accept_rotation(&mut self, statement: &RotationStatement) -> Result<(), TrustError> {
+        if !statement.is_valid() {
+            return Err(TrustError::BadSignature);
+        }
+        match self.successors.get(&statement.old) {
+            Some(current) if *current == statement.new => return Ok(()),
+            Some(current) => return Err(TrustError::Conflict { current: *current }),
+            None => {},
+        }
+        let onward = self.keys(&statement.new);
+        if onward.contains(&statement.old) {
+            return Err(TrustError::Cycle);
+        }
+        // Count the keys leading up to `old` as well as the ones after `new`.
+        let before = self.successors.iter().filter(|(_, next)| self.keys(next).contains(&statement.old)).count();
+        if before + onward.len() > MAX_ROTATIONS {
+            return Err(TrustError::TooManyRotations);
+        }
+        self.successors.insert(statement.old, statement.new);
+        Ok(())
+    }
+}
+
+/// Why a `LocalIdentity` couldn't be created, loaded or saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityError {
+    /// `SYS_RANDOM` failed; no key can be generated.
+    NoRandomness,
+    /// The identity file doesn't exist.
+    NotFound,
+    /// The file is encrypted and no passphrase, or the wrong one, was given.
+    WrongPassphrase,
+    /// The file is damaged or not an identity file.
+    Corrupt(&'static str),
+    /// The VFS refused the read or write.
+    Storage(String),
+}
+
+impl fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRandomness => f.write_str("no random number generator (SYS_RANDOM failed)"),
+            Self::NotFound => f.write_str("identity file not found"),
+            Self::WrongPassphrase => f.write_str("wrong passphrase"),
+            Self::Corrupt(what) => write!(f, "corrupt identity file: {}", what),
+            Self::Storage(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Stretches `passphrase` into a 32-byte key.
+fn derive_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = sha256(&[KDF_DOMAIN, salt, passphrase]);
+    for _ in 1..iterations {
+        key = sha256(&[&key, salt, passphrase]);
+    }
+    key
+}
+
+/// This node's keypair.
+pub struct LocalIdentity {
+    key: SigningKey,
+    aid: Aid,
+}
+
+impl LocalIdentity {
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let key = SigningKey::from_bytes(seed);
+        let aid = Aid::from_public_key(&key.verifying_key());
+        Self { key, aid }
+    }
+
+    /// A new keypair from `SYS_RANDOM`.
+    pub fn generate() -> Result<Self, IdentityError> {
+        let mut seed = [0u8; 32];
+        if !random::fill(&mut seed) {
+            return Err(IdentityError::NoRandomness);
+        }
+        Ok(Self::from_seed(&seed))
+    }
+
+    pub fn aid(&self) -> Aid {
+        self.aid
+    }
+
+    /// Signs `message`; the result is `SIGNATURE_LEN` bytes. Used for package
+    /// signatures over the root CID and for login proofs over the session nonce.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key.sign(message).to_bytes().to_vec()
+    }
+
+    /// A new keypair, and the statement that moves this identity to it. Save
+    /// the new identity before publishing the statement.
+    pub fn rotate(&self) -> Result<(LocalIdentity, RotationStatement), IdentityError> {
+        let next = Self::generate()?;
+        let signed = RotationStatement::signed_bytes(&self.aid, &next.aid);
+        let statement = RotationStatement {
+            old: self.aid,
+            new: next.aid,
+            old_signature: self.sign(&signed),
+            new_signature: next.sign(&signed),
+        };
+        Ok((next, statement))
+    }
+
+    /// The identity file's contents; see the module documentation. With a
+    /// passphrase the seed is encrypted under a key derived from it.
+    pub fn to_file_bytes(&self, passphrase: Option<&[u8]>) -> Result<Vec<u8>, IdentityError> {
+        let mut salt = [0u8; SALT_LEN];
+        let (mode, iterations) = match passphrase {
+            Some(_) => {
+                if !random::fill(&mut salt) {
+                    return Err(IdentityError::NoRandomness);
+                }
+                (MODE_PASSPHRASE, KDF_ITERATIONS)
+            },
+            None => (MODE_PLAIN, 0),
+        };
+        let key = passphrase.map_or([0u8; 32], |passphrase| derive_key(passphrase, &salt, iterations));
+        let stream = sha256(&[STREAM_DOMAIN, &key]);
+
+        let mut out = Vec::with_capacity(IDENTITY_FILE_LEN);
+        out.extend_from_slice(&IDENTITY_MAGIC);
+        out.push(IDENTITY_FILE_VERSION);
+        out.push(mode);
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&iterations.to_le_bytes());
+        out.extend_from_slice(&self.aid.0);
+        let seed = self.key.to_bytes();
+        out.extend(seed.iter().zip(stream.iter()).map(|(s, k)| if mode == MODE_PLAIN { *s } else { s ^ k }));
+        let check = sha256(&[CHECK_DOMAIN, &key, &out]);
+        out.extend_from_slice(&check);
+        Ok(out)
+    }
+
+    /// Reads an identity file. An encrypted one needs its passphrase.
+    pub fn from_file_bytes(bytes: &[u8], passphrase: Option<&[u8]>) -> Result<Self, IdentityError> {
+        if bytes.len() != IDENTITY_FILE_LEN || bytes[..4] != IDENTITY_MAGIC {
+            return Err(IdentityError::Corrupt("not an identity file"));
+        }
+        if bytes[4] != IDENTITY_FILE_VERSION {
+            return Err(IdentityError::Corrupt("unknown version"));
+        }
+        let salt = &bytes[6..22];
+        let iterations = u32::from_le_bytes([bytes[22], bytes[23], bytes[24], bytes[25]]);
+        let key = match (bytes[5], passphrase) {
+            (MODE_PLAIN, _) => [0u8; 32],
+            (MODE_PASSPHRASE, None) => return Err(IdentityError::WrongPassphrase),
+            (MODE_PASSPHRASE, Some(_)) if iterations == 0 || iterations > MAX_KDF_ITERATIONS => {
+                return Err(IdentityError::Corrupt("bad KDF iterations"));
+            },
+            (MODE_PASSPHRASE, Some(passphrase)) => derive_key(passphrase, salt, iterations),
+            _ => return Err(IdentityError::Corrupt("unknown mode")),
+        };
+        if sha256(&[CHECK_DOMAIN, &key, &bytes[..90]])[..] != bytes[90..] {
+            return Err(if bytes[5] == MODE_PASSPHRASE { IdentityError::WrongPassphrase } else { IdentityError::Corrupt("check value mismatch") });
+        }
+
+        let stream = sha256(&[STREAM_DOMAIN, &key]);
+        let mut seed = [0u8; 32];
+        for (i, byte) in bytes[58..90].iter().enumerate() {
+            seed[i] = if bytes[5] == MODE_PLAIN { *byte } else { byte ^ stream[i] };
+        }
+        let identity = Self::from_seed(&seed);
+        if identity.aid.0[..] != bytes[26..58] {
+            return Err(IdentityError::Corrupt("key doesn't match the Aid"));
+        }
+        Ok(identity)
+    }
+
+    /// Loads the identity stored at `path` through the VFS.
+    pub fn load(vfs_chan: &mut VNodeChannel, path: &str, passphrase: Option<&[u8]>) -> Result<Self, IdentityError> {
+        let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as u32,
+            Ok(VfsResponse::Error { code: ENOENT, .. }) => return Err(IdentityError::NotFound),
+            Ok(VfsResponse::Error { message, .. }) => return Err(IdentityError::Storage(message)),
+            _ => return Err(IdentityError::Storage("Unexpected response from VFS".to_string())),
+        };
+        let read = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: IDENTITY_FILE_LEN as u32 + 1, offset: 0 });
+        let _ = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        match read {
+            Ok(VfsResponse::Data(data)) => Self::from_file_bytes(&data, passphrase),
+            Ok(VfsResponse::Error { message, .. }) => Err(IdentityError::Storage(message)),
+            _ => Err(IdentityError::Storage("Unexpected response from VFS".to_string())),
+        }
+    }
+
+    /// Writes the identity to `path` in one VFS transaction, so a crash
+    /// never leaves half a key behind.
+    pub fn save(&self, vfs_chan: &mut VNodeChannel, path: &str, passphrase: Option<&[u8]>) -> Result<(), IdentityError> {
+        let bytes = self.to_file_bytes(passphrase)?;
+        VfsTx::begin(vfs_chan)
+            .and_then(|mut tx| {
+                tx.write_file(path, bytes)?;
+                tx.commit()
+            })
+            .map_err(IdentityError::Storage)
+    }
+
+    /// Loads the identity at `path`, or generates one and stores it there if
+    /// the file doesn't exist yet. Pass `None` for service identities.
+    pub fn load_or_create(vfs_chan: &mut VNodeChannel, path: &str, passphrase: Option<&[u8]>) -> Result<Self, IdentityError> {
+        match Self::load(vfs_chan, path, passphrase) {
+            Err(IdentityError::NotFound) => {
+                let identity = Self::generate()?;
+                identity.save(vfs_chan, path, passphrase)?;
+                Ok(identity)
+            },
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// common/src/ui/image.rs
+
+//! Decoding images into RGBA pixel buffers for the WebView.
+//!
+//! Each format is an `ImageDecoder`; `decode` asks every decoder in `DECODERS`
+//! whether it recognises the data and uses the first that does. Only
+//! uncompressed formats are supported so far: binary PPM (`P6`) and BMP with
+//! 24 or 32 bits per pixel and no compression. A PNG decoder only needs to be
+//! added to the list.
+//!
+//! `encode_ppm` goes the other way, for screenshots.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Largest image, in pixels, any decoder accepts (4096 x 4096). The check comes
+/// before the pixel buffer is allocated, so a crafted header can't exhaust memory.
+pub const MAX_IMAGE_PIXELS: usize = 4096 * 4096;
+
+/// A decoded image: `width * height` pixels, row by row from the top, 4 bytes
+/// (R, G, B, A) each, the layout `UiRequest::DrawToSurface` takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    /// No decoder recognises the data.
+    UnknownFormat,
+    /// A format feature the decoder doesn't implement, e.g. compressed BMP.
+    Unsupported(&'static str),
+    Malformed(&'static str),
+    TooLarge { width: u32, height: u32 },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => f.write_str("unknown image format"),
+            Self::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Self::Malformed(what) => write!(f, "malformed image: {}", what),
+            Self::TooLarge { width, height } => write!(f, "image is too large ({}x{})", width, height),
+        }
+    }
+}
+
+pub trait ImageDecoder {
+    /// Short name for logs, e.g. "bmp".
+    fn name(&self) -> &'static str;
+    /// True if `data` looks like this format. Only the first few bytes are checked.
+    fn recognizes(&self, data: &[u8]) -> bool;
+    fn decode(&self, data: &[u8]) -> Result<Image, ImageError>;
+}
+
+/// Every supported format, tried in order.
+pub static DECODERS: &[&(dyn ImageDecoder + Sync)] = &[&PpmDecoder, &BmpDecoder];
+
+/// Decodes `data` with the first decoder that recognises it.
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+    DECODERS.iter().find(|decoder| decoder.recognizes(data)).ok_or(ImageError::UnknownFormat)?.decode(data)
+}
+
+/// Checks the dimensions and returns the pixel buffer's length.
+fn buffer_len(width: u32, height: u32) -> Result<usize, ImageError> {
+    let pixels = (width as usize).checked_mul(height as usize).filter(|pixels| *pixels <= MAX_IMAGE_PIXELS);
+    pixels.map(|pixels| pixels * 4).ok_or(ImageError::TooLarge { width, height })
+}
+
+/// Binary portable pixmap (`P6`) with a maximum value of at most 255.
+pub struct PpmDecoder;
+
+impl PpmDecoder {
+    /// The next whitespace-separated header number, skipping `#` comments.
+    fn header_value(data: &[u8], pos: &mut usize) -> Result<u32, ImageError> {
+        loop {
+            match data.get(*pos) {
+                Some(b'#') => {
+                    while !matches!(data.get(*pos), Some(b'\n') | None) {
+                        *pos += 1;
+                    }
+                },
+                Some(byte) if byte.is_ascii_whitespace() => *pos += 1,
+                Some(_) => break,
+                None => return Err(ImageError::Malformed("truncated PPM header")),
+            }
+        }
+        let start = *pos;
+        while data.get(*pos).map_or(false, |byte| byte.is_ascii_digit()) {
+            *pos += 1;
+        }
+        core::str::from_utf8(&data[start..*pos]).ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(ImageError::Malformed("bad PPM header value"))
+    }
+}
+
+impl ImageDecoder for PpmDecoder {
+    fn name(&self) -> &'static str {
+        "ppm"
+    }
+
+    fn recognizes(&self, data: &[u8]) -> bool {
+        data.starts_with(b"P6")
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Image, ImageError> {
+        let mut pos = 2;
+        let width = Self::header_value(data, &mut pos)?;
+        let height = Self::header_value(data, &mut pos)?;
+        let max_value = Self::header_value(data, &mut pos)?;
+        if max_value == 0 || max_value > 255 {
+            return Err(ImageError::Unsupported("PPM with 16-bit samples"));
+        }
+        pos += 1; // The single whitespace byte before the raster.
+        let len = buffer_len(width, height)?;
+        let raster = data.get(pos..pos + len / 4 * 3).ok_or(ImageError::Malformed("truncated PPM raster"))?;
+
+        let mut pixels = Vec::with_capacity(len);
+        for rgb in raster.chunks_exact(3) {
+            for sample in rgb {
+                pixels.push((*sample as u32 * 255 / max_value) as u8);
+            }
+            pixels.push(0xFF);
+        }
+        Ok(Image { width, height, pixels })
+    }
+}
+
+/// Encodes `width * height` RGBA pixels as a binary PPM, dropping alpha.
+/// `PpmDecoder` reads it back.
+pub fn encode_ppm(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let header = alloc::format!("P6\n{} {}\n255\n", width, height);
+    let mut data = Vec::with_capacity(header.len() + rgba.len() / 4 * 3);
+    data.extend_from_slice(header.as_bytes());
+    for pixel in rgba.chunks_exact(4).take((width * height) as usize) {
+        data.extend_from_slice(&pixel[..3]);
+    }
+    data
+}
+
+/// Windows bitmap: `BITMAPINFOHEADER` or later, 24 or 32 bits per pixel,
+/// uncompressed (`BI_RGB`). Rows may be stored bottom-up or top-down.
+pub struct BmpDecoder;
+
+const BMP_FILE_HEADER_LEN: usize = 14;
+const BI_RGB: u32 = 0;
+
+fn le_u16(data: &[u8], at: usize) -> Result<u16, ImageError> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or(ImageError::Malformed("truncated BMP header"))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Result<u32, ImageError> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or(ImageError::Malformed("truncated BMP header"))
+}
+
+impl ImageDecoder for BmpDecoder {
+    fn name(&self) -> &'static str {
+        "bmp"
+    }
+
+    fn recognizes(&self, data: &[u8]) -> bool {
+        data.starts_with(b"BM")
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Image, ImageError> {
+        let raster_offset = le_u32(data, 10)? as usize;
+        let info = BMP_FILE_HEADER_LEN;
+        if le_u32(data, info)? < 40 {
+            return Err(ImageError::Unsupported("BMP core header"));
+        }
+        let width = le_u32(data, info + 4)? as i32;
+        let height = le_u32(data, info + 8)? as i32;
+        let bits = le_u16(data, info + 14)?;
+        if le_u32(data, info + 16)? != BI_RGB {
+            return Err(ImageError::Unsupported("compressed BMP"));
+        }
+        let bytes_per_pixel = match bits {
+            24 => 3,
+            32 => 4,
+            _ => return Err(ImageError::Unsupported("BMP with a palette or 16-bit pixels")),
+        };
+        if width <= 0 || height == 0 || height == i32::MIN {
+            return Err(ImageError::Malformed("bad BMP dimensions"));
+        }
+        // A negative height means the rows are stored top-down.
+        let top_down = height < 0;
+        let (width, height) = (width as u32, height.unsigned_abs());
+        let len = buffer_len(width, height)?;
+
+        let stride = (width as usize * bytes_per_pixel + 3) & !3; // Rows are padded to 4 bytes.
+        let raster = data.get(raster_offset..).filter(|raster| raster.len() >= stride * height as usize)
+            .ok_or(ImageError::Malformed("truncated BMP raster"))?;
+        let mut pixels = Vec::with_capacity(len);
+        for y in 0..height as usize {
+            let row = if top_down { y } else { height as usize - 1 - y };
+            let row = &raster[row * stride..row * stride + width as usize * bytes_per_pixel];
+            for bgr in row.chunks_exact(bytes_per_pixel) {
+                // The fourth byte of 32-bit BI_RGB pixels is unused, not alpha.
+                pixels.extend_from_slice(&[bgr[2], bgr[1], bgr[0], 0xFF]);
+            }
+        }
+        Ok(Image { width, height, pixels })
+    }
+}
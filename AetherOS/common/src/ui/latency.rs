@@ -0,0 +1,233 @@
+// common/src/ui/latency.rs
+
+//! Input latency measurement for the UI pipeline.
+//!
+//! An input event is stamped with the time at which its interrupt was
+//! captured, and every later hop adds its own stamp to `InputTiming`:
+//!
+//! 1. capture -> dispatch: the compositor forwards the event to the window owner
+//! 2. dispatch -> receipt: the owner picks it up
+//! 3. receipt -> commit: the owner submits the frame it drew in response
+//! 4. commit -> composite: the compositor puts that frame on screen
+//!
+//! All stamps are nanoseconds since boot from the kernel's clock
+//! (`time::monotonic_nanos`), so there is no skew between clocks of different
+//! V-Nodes. Deltas go into fixed-bucket histograms; recording a sample never
+//! allocates.
+
+use serde::{Deserialize, Serialize};
+
+/// Inclusive upper bounds of the histogram buckets, in nanoseconds (50 µs to
+/// 1 s). A final overflow bucket takes everything above the last bound.
+pub const BUCKET_BOUNDS: [u64; 12] = [
+    50_000, 100_000, 250_000, 500_000, 1_000_000, 2_000_000,
+    4_000_000, 8_000_000, 16_000_000, 33_000_000, 100_000_000, 1_000_000_000,
+];
+pub const BUCKET_COUNT: usize = BUCKET_BOUNDS.len() + 1;
+
+/// Stages of the pipeline, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stage {
+    CaptureToDispatch,
+    DispatchToReceipt,
+    ReceiptToCommit,
+    CommitToComposite,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::CaptureToDispatch, Stage::DispatchToReceipt, Stage::ReceiptToCommit, Stage::CommitToComposite];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::CaptureToDispatch => "capture->dispatch",
+            Stage::DispatchToReceipt => "dispatch->receipt",
+            Stage::ReceiptToCommit => "receipt->commit",
+            Stage::CommitToComposite => "commit->composite",
+        }
+    }
+}
+
+/// Nanosecond stamps collected along the pipeline. A stamp of 0 means the hop
+/// hasn't happened (or the source didn't record it).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputTiming {
+    pub captured_at: u64,
+    pub dispatched_at: u64,
+    pub received_at: u64,
+    pub committed_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub buckets: [u32; BUCKET_COUNT],
+    pub count: u32,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, nanos: u64) {
+        let index = BUCKET_BOUNDS.iter().position(|bound| nanos <= *bound).unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[index] = self.buckets[index].saturating_add(1);
+        self.count = self.count.saturating_add(1);
+    }
+
+    /// Upper bound, in nanoseconds, of the bucket holding the `pct`-th percentile.
+    /// `None` without samples; `Some(u64::MAX)` if it falls in the overflow bucket.
+    pub fn percentile(&self, pct: u32) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        // Rank of the sample at the percentile, 1-based and rounded up.
+        let rank = ((self.count as u64 * pct.min(100) as u64 + 99) / 100).max(1);
+        let mut seen = 0u64;
+        for (index, n) in self.buckets.iter().enumerate() {
+            seen += *n as u64;
+            if seen >= rank {
+                return Some(BUCKET_BOUNDS.get(index).copied().unwrap_or(u64::MAX));
+            }
+        }
+        Some(u64::MAX)
+    }
+}
+
+/// One histogram per stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipelineLatency {
+    pub stages: [LatencyHistogram; 4],
+}
+
+impl PipelineLatency {
+    pub fn record(&mut self, stage: Stage, nanos: u64) {
+        self.stages[stage as usize].record(nanos);
+    }
+
+    pub fn stage(&self, stage: Stage) -> &LatencyHistogram {
+        &self.stages[stage as usize]
+    }
+
+    /// Records every stage whose start and end stamps are both present, ending
+    /// with `composited_at` for the last stage (0 to skip it).
+    pub fn record_timing(&mut self, timing: &InputTiming, composited_at: u64) {
+        for (stage, nanos) in stage_deltas(timing, composited_at) {
+            self.record(stage, nanos);
+        }
+    }
+}
+
+/// The stages `timing` has both stamps for, with their duration in nanoseconds.
+/// `composited_at` ends the last stage (0 if it hasn't happened).
+pub fn stage_deltas(timing: &InputTiming, composited_at: u64) -> impl Iterator<Item = (Stage, u64)> {
+    let stamps = [timing.captured_at, timing.dispatched_at, timing.received_at, timing.committed_at, composited_at];
+    (0..Stage::ALL.len()).filter_map(move |i| {
+        let (start, end) = (stamps[i], stamps[i + 1]);
+        (start != 0 && end != 0).then(|| (Stage::ALL[i], end.saturating_sub(start)))
+    })
+}
+
+/// Client-side half of the measurement, kept by a UI app.
+///
+/// Call `on_event` when an input event arrives and `on_commit` when submitting
+/// the frame drawn in response; attach the returned timing to the
+/// `DrawToSurface` request so the compositor can measure the last stage.
+#[derive(Debug, Default)]
+pub struct AppLatency {
+    pending: Option<InputTiming>,
+    stats: PipelineLatency,
+}
+
+impl AppLatency {
+    pub fn on_event(&mut self, timing: &InputTiming, now: u64) {
+        let mut timing = *timing;
+        timing.received_at = now;
+        if timing.dispatched_at != 0 {
+            self.stats.record(Stage::DispatchToReceipt, now.saturating_sub(timing.dispatched_at));
+        }
+        // Only the newest event is tracked; a frame answers all events since the last one.
+        self.pending = Some(timing);
+    }
+
+    pub fn on_commit(&mut self, now: u64) -> Option<InputTiming> {
+        let mut timing = self.pending.take()?;
+        timing.committed_at = now;
+        self.stats.record(Stage::ReceiptToCommit, now.saturating_sub(timing.received_at));
+        Some(timing)
+    }
+
+    /// The app's own view of the stages it observes (dispatch->receipt, receipt->commit).
+    pub fn stats(&self) -> &PipelineLatency {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn histogram(samples: &[u64]) -> LatencyHistogram {
+        let mut histogram = LatencyHistogram::default();
+        for nanos in samples {
+            histogram.record(*nanos);
+        }
+        histogram
+    }
+
+    #[test]
+    fn empty_histogram_has_no_percentiles() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(0), None);
+        assert_eq!(histogram.percentile(50), None);
+        assert_eq!(histogram.percentile(100), None);
+    }
+
+    #[test]
+    fn single_sample_is_every_percentile() {
+        let histogram = histogram(&[3 * MS]);
+        assert_eq!(histogram.count, 1);
+        for pct in [0, 1, 50, 99, 100, 250] {
+            assert_eq!(histogram.percentile(pct), Some(4 * MS), "p{}", pct);
+        }
+    }
+
+    #[test]
+    fn samples_on_a_bound_go_in_that_bucket() {
+        let histogram = histogram(&[50_000, 50_001, 1_000_000_000, 1_000_000_001]);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[BUCKET_BOUNDS.len() - 1], 1);
+        assert_eq!(histogram.buckets[BUCKET_BOUNDS.len()], 1);
+        assert_eq!(histogram.percentile(100), Some(u64::MAX));
+    }
+
+    #[test]
+    fn percentile_rank_rounds_up() {
+        // 10 samples: 9 fast, 1 slow. p90 is the 9th sample, p91 the 10th.
+        let mut samples = [MS; 10];
+        samples[9] = 20 * MS;
+        let histogram = histogram(&samples);
+        assert_eq!(histogram.percentile(0), Some(MS));
+        assert_eq!(histogram.percentile(90), Some(MS));
+        assert_eq!(histogram.percentile(91), Some(33 * MS));
+        assert_eq!(histogram.percentile(100), Some(33 * MS));
+    }
+
+    #[test]
+    fn deltas_skip_stages_missing_a_stamp() {
+        let timing = InputTiming { captured_at: 100, dispatched_at: 150, received_at: 0, committed_at: 400 };
+        let deltas: alloc::vec::Vec<_> = stage_deltas(&timing, 900).collect();
+        assert_eq!(deltas, [(Stage::CaptureToDispatch, 50), (Stage::CommitToComposite, 500)]);
+        assert_eq!(stage_deltas(&timing, 0).count(), 1);
+    }
+
+    #[test]
+    fn app_records_receipt_and_commit_stages() {
+        let mut app = AppLatency::default();
+        assert_eq!(app.on_commit(10), None);
+        app.on_event(&InputTiming { captured_at: 100, dispatched_at: 200, ..InputTiming::default() }, 300);
+        let timing = app.on_commit(1_000).unwrap();
+        assert_eq!(timing, InputTiming { captured_at: 100, dispatched_at: 200, received_at: 300, committed_at: 1_000 });
+        assert_eq!(app.stats().stage(Stage::DispatchToReceipt).count, 1);
+        assert_eq!(app.stats().stage(Stage::ReceiptToCommit).count, 1);
+        assert_eq!(app.on_commit(2_000), None);
+    }
+}
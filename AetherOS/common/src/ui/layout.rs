@@ -8,6 +8,8 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 
 use crate::syscall::{syscall3, SYS_LOG, SUCCESS};
+use crate::ui::font::GLYPH_WIDTH;
+use crate::ui::scale::UiScale;
 use crate::ui::html_parser::DomNode;
 
 // Temporary log function for V-Nodes
@@ -34,15 +36,51 @@ pub struct LayoutBox {
     pub content_height: u32,
     pub children: Vec<LayoutBox>,
     pub debug_name: String, // For debugging purposes
+    /// Set on the box of an `<img>`, which the renderer fills with the image.
+    pub image: Option<ImageSlot>,
 }
 
-pub struct LayoutEngine;
+/// What goes into an `<img>` box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSlot {
+    /// The decoded image, keyed by its `src` as written in the document.
+    Loaded { src: String },
+    /// The image failed to load or decode; the renderer draws a placeholder.
+    Placeholder { src: String },
+}
+
+/// Size of the placeholder box of an image that didn't load and has no
+/// `width` and `height` attributes.
+pub const PLACEHOLDER_SIZE: u32 = 32;
+
+/// Height of a line of text at 1x: a glyph cell and 4px of leading.
+pub const LINE_HEIGHT: u32 = 20;
+
+/// Lays out in logical pixels and returns boxes in physical ones, at `scale`.
+/// Image sizes are pixel data and stay as they are; the sizes the engine
+/// makes up (text lines, placeholders, `width`/`height` attributes) are scaled.
+pub struct LayoutEngine {
+    scale: UiScale,
+}
 
 impl LayoutEngine {
-    pub fn new() -> Self { LayoutEngine { } }
+    pub fn new() -> Self { LayoutEngine { scale: UiScale::X1 } }
+
+    pub fn with_scale(scale: UiScale) -> Self { LayoutEngine { scale } }
+
+    /// Applies from the next layout; callers lay out again to follow a `ui.scale` change.
+    pub fn set_scale(&mut self, scale: UiScale) { self.scale = scale; }
+
+    pub fn scale(&self) -> UiScale { self.scale }
 
     // Very basic conceptual layout calculation
-    pub fn layout(&self, dom: &DomNode, _computed_styles: &BTreeMap<String, String>, viewport_width: u32, viewport_height: u32) -> LayoutBox {
+    pub fn layout(&self, dom: &DomNode, computed_styles: &BTreeMap<String, String>, viewport_width: u32, viewport_height: u32) -> LayoutBox {
+        self.layout_with_images(dom, computed_styles, &BTreeMap::new(), viewport_width, viewport_height)
+    }
+
+    /// Like `layout`, with the intrinsic size of every image that loaded, by
+    /// `src` as written. An `<img>` missing from `images` gets a placeholder box.
+    pub fn layout_with_images(&self, dom: &DomNode, _computed_styles: &BTreeMap<String, String>, images: &BTreeMap<String, (u32, u32)>, viewport_width: u32, viewport_height: u32) -> LayoutBox {
         log("LayoutEngine: Performing layout (stub).");
 
         let root_box = LayoutBox {
@@ -54,15 +92,44 @@ impl LayoutEngine {
             content_height: viewport_height,
             children: Vec::new(),
             debug_name: String::from("root"),
+            image: None,
         };
 
         match dom {
+            DomNode::Element { tag_name, attributes, .. } if tag_name == "img" => {
+                let attribute = |name: &str| attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+                let src = String::from(attribute("src").unwrap_or(""));
+                let (width, height, image) = match images.get(&src) {
+                    // Scaled down to the viewport width, keeping the aspect ratio.
+                    Some(&(width, height)) if width > viewport_width => {
+                        (viewport_width, (height as u64 * viewport_width as u64 / width as u64) as u32, ImageSlot::Loaded { src })
+                    },
+                    Some(&(width, height)) => (width, height, ImageSlot::Loaded { src }),
+                    None => {
+                        let dimension = |name: &str| attribute(name).and_then(|value| value.trim_end_matches("px").parse::<u32>().ok());
+                        let width = self.scale.px(dimension("width").unwrap_or(PLACEHOLDER_SIZE)).min(viewport_width);
+                        let height = self.scale.px(dimension("height").unwrap_or(PLACEHOLDER_SIZE));
+                        (width, height, ImageSlot::Placeholder { src })
+                    },
+                };
+                LayoutBox {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                    content_width: width,
+                    content_height: height,
+                    children: Vec::new(),
+                    debug_name: String::from("img"),
+                    image: Some(image),
+                }
+            },
             DomNode::Element { tag_name, children, .. } => {
                 let mut children_layouts = Vec::new();
                 let mut current_y = 0;
                 for child in children {
                     // Simple stacking layout
-                    let child_layout = self.layout(child, _computed_styles, viewport_width, viewport_height);
+                    let child_layout = self.layout_with_images(child, _computed_styles, images, viewport_width, viewport_height);
                     children_layouts.push(LayoutBox { 
                         x: 0, y: current_y, 
                         width: child_layout.width, 
@@ -70,7 +137,8 @@ impl LayoutEngine {
                         content_width: child_layout.content_width, 
                         content_height: child_layout.content_height, 
                         children: child_layout.children, 
-                        debug_name: alloc::format!("{}-child", tag_name) 
+                        debug_name: alloc::format!("{}-child", tag_name),
+                        image: child_layout.image,
                     });
                     current_y += child_layout.height;
                 }
@@ -83,13 +151,14 @@ impl LayoutEngine {
                     content_height: current_y, // Sum of children height for conceptual content height
                     children: children_layouts,
                     debug_name: tag_name.clone(),
+                    image: None,
                 }
             },
             DomNode::Text(text) => {
-                // Simple text layout: assume a fixed line height and character width
-                let char_width = 8; // Pixels per character
-                let line_height = 20; // Pixels per line
-                let width = (text.len() * char_width).min(viewport_width as usize) as u32;
+                // Simple text layout: assume a fixed line height and character width.
+                // Width is counted in font cells, not bytes; wide CJK characters take two.
+                let line_height = self.scale.px(LINE_HEIGHT);
+                let width = self.scale.px((crate::text::display_width(text) * GLYPH_WIDTH) as u32).min(viewport_width);
                 let height = line_height;
                 LayoutBox {
                     x: 0,
@@ -100,6 +169,7 @@ impl LayoutEngine {
                     content_height: height,
                     children: Vec::new(),
                     debug_name: String::from("text"),
+                    image: None,
                 }
             },
         }
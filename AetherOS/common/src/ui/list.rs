@@ -0,0 +1,629 @@
+// common/src/ui/list.rs
+
+//! Virtualized lists for collections too large to lay out whole.
+//!
+//! A `VirtualList` doesn't own a widget per item. It asks a `ListSource` how
+//! many items there are and binds item data into row widgets on demand, and
+//! only for the rows that intersect the viewport plus `overscan` rows on
+//! either side. A row that scrolls out is unbound and kept for the next row
+//! that scrolls in, so the number of row instances depends on the viewport
+//! height, not on the number of items.
+//!
+//! Rows may differ in height. A row's height is measured when it is first
+//! bound and cached; rows never bound count with the estimated height.
+//! Offsets come from a Fenwick tree over the heights, so finding the row at
+//! a scroll offset, or the offset of a row, is O(log n) for any list length.
+//! When a measurement differs from the estimate above the viewport, the
+//! scroll offset moves by the difference so the rows on screen stay put.
+//!
+//! Every change reports damage in the list's own coordinates, clipped to its
+//! viewport: scrolling damages the viewport, a selection change or a changed
+//! row only the rows concerned.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+/// Rows bound beyond each edge of the viewport, so a small scroll doesn't
+/// have to bind before it can paint.
+pub const DEFAULT_OVERSCAN: usize = 3;
+
+/// Passes `update` makes at most when measurements keep changing which rows
+/// are visible. Each pass replaces estimates with measurements, so it settles
+/// quickly; the limit only guards against a source whose heights never settle.
+const MAX_LAYOUT_PASSES: usize = 4;
+
+/// Where the list's items come from. `RowWidgets` is whatever a row is made
+/// of (labels, an icon, ...); the list creates instances with `Default` and
+/// reuses them for other items.
+pub trait ListSource {
+    type RowWidgets: Default;
+
+    fn len(&self) -> usize;
+
+    /// Shows item `index` in `row`, replacing whatever item it showed before.
+    fn bind_row(&self, index: usize, row: &mut Self::RowWidgets);
+
+    /// The height of `row` as bound, laid out `width` pixels wide. At least 1.
+    fn measure_row(&self, row: &Self::RowWidgets, width: u32) -> u32;
+}
+
+/// An area of the list, relative to its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ListRect {
+    pub fn union(&self, other: &ListRect) -> ListRect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        ListRect { x, y, width: right - x, height: bottom - y }
+    }
+}
+
+/// Where `scroll_to_index` puts the row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollAlign {
+    /// At the top of the viewport.
+    Start,
+    Center,
+    /// At the bottom of the viewport.
+    End,
+    /// As little scrolling as it takes to show it whole; none if it is.
+    Nearest,
+}
+
+/// A bound row. `y` is its top relative to the viewport's, negative for a row
+/// partly or wholly above it.
+pub struct LiveRow<R> {
+    pub index: usize,
+    pub y: i64,
+    pub height: u32,
+    pub widgets: R,
+}
+
+/// Row heights with prefix sums, for offsets in O(log n).
+struct Heights {
+    heights: Vec<u32>,
+    tree: Vec<u64>, // Fenwick tree, 1-based
+}
+
+impl Heights {
+    fn new(len: usize, height: u32) -> Self {
+        let heights = alloc::vec![height; len];
+        let mut tree = alloc::vec![0u64; len + 1];
+        // Linear construction: each node passes its sum on to its parent.
+        for i in 1..=len {
+            tree[i] += height as u64;
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= len {
+                tree[parent] += tree[i];
+            }
+        }
+        Self { heights, tree }
+    }
+
+    fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    fn set(&mut self, index: usize, height: u32) {
+        let old = core::mem::replace(&mut self.heights[index], height);
+        let mut i = index + 1;
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i] - old as u64 + height as u64;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The offset of row `index`: the heights of the rows before it.
+    fn offset_of(&self, index: usize) -> u64 {
+        let mut sum = 0;
+        let mut i = index.min(self.len());
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+
+    fn total(&self) -> u64 {
+        self.offset_of(self.len())
+    }
+
+    /// The row covering `offset`; the last row for offsets past the end.
+    fn index_at(&self, offset: u64) -> usize {
+        let mut index = 0;
+        let mut remaining = offset;
+        let mut step = self.tree.len().checked_next_power_of_two().unwrap_or(0) >> 1;
+        while step > 0 {
+            let next = index + step;
+            if next < self.tree.len() && self.tree[next] <= remaining {
+                index = next;
+                remaining -= self.tree[next];
+            }
+            step >>= 1;
+        }
+        index.min(self.len().saturating_sub(1))
+    }
+}
+
+pub struct VirtualList<S: ListSource> {
+    width: u32,
+    viewport_height: u32,
+    scroll: u64,
+    estimate: u32,
+    overscan: usize,
+    heights: Heights,
+    measured: Vec<bool>,
+    live: Vec<LiveRow<S::RowWidgets>>, // Sorted by index
+    spare: Vec<S::RowWidgets>, // Unbound instances, reused before new ones are made
+    instances: usize, // Row instances ever made
+    selected: Option<usize>,
+    damage: Option<ListRect>,
+}
+
+impl<S: ListSource> VirtualList<S> {
+    /// A list of `source`'s items in a `width` by `viewport_height` area.
+    /// Rows count as `estimated_row_height` until they are measured.
+    pub fn new(source: &S, width: u32, viewport_height: u32, estimated_row_height: u32) -> Self {
+        let estimate = estimated_row_height.max(1);
+        let len = source.len();
+        Self {
+            width,
+            viewport_height,
+            scroll: 0,
+            estimate,
+            overscan: DEFAULT_OVERSCAN,
+            heights: Heights::new(len, estimate),
+            measured: alloc::vec![false; len],
+            live: Vec::new(),
+            spare: Vec::new(),
+            instances: 0,
+            selected: None,
+            damage: Some(ListRect { x: 0, y: 0, width, height: viewport_height }),
+        }
+    }
+
+    pub fn set_overscan(&mut self, rows: usize) {
+        self.overscan = rows;
+    }
+
+    /// Resizes the list. A new width lays text out differently, so every
+    /// measurement is dropped and the live rows are measured again.
+    pub fn set_viewport(&mut self, width: u32, viewport_height: u32) {
+        if width != self.width {
+            self.forget_heights(self.heights.len());
+            let rows = core::mem::take(&mut self.live);
+            self.spare.extend(rows.into_iter().map(|row| row.widgets));
+        }
+        self.width = width;
+        self.viewport_height = viewport_height;
+        self.damage_all();
+    }
+
+    /// The items were replaced, added or removed: everything is bound and
+    /// measured again. The selection is dropped if its item is gone.
+    pub fn data_changed(&mut self, source: &S) {
+        let len = source.len();
+        self.forget_heights(len);
+        self.selected = self.selected.filter(|index| *index < len);
+        let rows = core::mem::take(&mut self.live);
+        self.spare.extend(rows.into_iter().map(|row| row.widgets));
+        self.damage_all();
+    }
+
+    /// Item `index` changed in place: its row is bound and measured again.
+    pub fn row_changed(&mut self, index: usize) {
+        if let Some(position) = self.live.iter().position(|row| row.index == index) {
+            let row = self.live.remove(position);
+            self.damage_row(row.y, row.height);
+            self.spare.push(row.widgets);
+        }
+        if index < self.measured.len() {
+            self.measured[index] = false;
+        }
+    }
+
+    fn forget_heights(&mut self, len: usize) {
+        self.heights = Heights::new(len, self.estimate);
+        self.measured = alloc::vec![false; len];
+    }
+
+    pub fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    pub fn content_height(&self) -> u64 {
+        self.heights.total()
+    }
+
+    pub fn scroll_offset(&self) -> u64 {
+        self.scroll
+    }
+
+    fn max_scroll(&self) -> u64 {
+        self.heights.total().saturating_sub(self.viewport_height as u64)
+    }
+
+    /// Scrolls to `offset` pixels from the top, clamped to the content.
+    pub fn scroll_to(&mut self, offset: u64) {
+        let offset = offset.min(self.max_scroll());
+        if offset != self.scroll {
+            self.scroll = offset;
+            self.damage_all();
+        }
+    }
+
+    /// Scrolls by `delta` pixels, positive towards the end.
+    pub fn scroll_by(&mut self, delta: i64) {
+        let offset = if delta < 0 { self.scroll.saturating_sub(delta.unsigned_abs()) } else { self.scroll.saturating_add(delta as u64) };
+        self.scroll_to(offset);
+    }
+
+    /// Scrolls so row `index` is where `align` says. An unmeasured row is
+    /// placed by its estimate and corrected by the next `update`.
+    pub fn scroll_to_index(&mut self, index: usize, align: ScrollAlign) {
+        if index >= self.len() {
+            return;
+        }
+        let top = self.heights.offset_of(index);
+        let height = self.heights.heights[index] as u64;
+        let viewport = self.viewport_height as u64;
+        let offset = match align {
+            ScrollAlign::Start => top,
+            ScrollAlign::Center => (top + height / 2).saturating_sub(viewport / 2),
+            ScrollAlign::End => (top + height).saturating_sub(viewport),
+            ScrollAlign::Nearest if top < self.scroll => top,
+            // A row taller than the viewport shows its top.
+            ScrollAlign::Nearest if top + height > self.scroll + viewport && height > viewport => top,
+            ScrollAlign::Nearest if top + height > self.scroll + viewport => top + height - viewport,
+            ScrollAlign::Nearest => self.scroll,
+        };
+        self.scroll_to(offset);
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Selects row `index`, or nothing, and damages the rows that changed.
+    pub fn select(&mut self, index: Option<usize>) {
+        let index = index.filter(|index| *index < self.len());
+        if index == self.selected {
+            return;
+        }
+        for changed in [self.selected, index].into_iter().flatten() {
+            if let Some((y, height)) = self.live.iter().find(|row| row.index == changed).map(|row| (row.y, row.height)) {
+                self.damage_row(y, height);
+            }
+        }
+        self.selected = index;
+    }
+
+    /// Moves the selection by `delta` rows, clamped to the list, and scrolls
+    /// it into view. Without a selection, selects the first or last row.
+    pub fn move_selection(&mut self, delta: i64) {
+        let len = self.len();
+        if len == 0 {
+            return;
+        }
+        let target = match self.selected {
+            Some(index) if delta < 0 => index.saturating_sub(delta.unsigned_abs() as usize),
+            Some(index) => index.saturating_add(delta as usize).min(len - 1),
+            None if delta < 0 => len - 1,
+            None => 0,
+        };
+        self.select(Some(target));
+        self.scroll_to_index(target, ScrollAlign::Nearest);
+    }
+
+    /// The row at `y` pixels below the viewport's top, e.g. for a click.
+    pub fn row_at(&self, y: u32) -> Option<usize> {
+        if self.len() == 0 || y >= self.viewport_height {
+            return None;
+        }
+        let offset = self.scroll + y as u64;
+        (offset < self.heights.total()).then(|| self.heights.index_at(offset))
+    }
+
+    /// Binds, measures and positions the rows around the viewport. Call it
+    /// after scrolling, resizing or changing data, before painting.
+    pub fn update(&mut self, source: &S) {
+        for _ in 0..MAX_LAYOUT_PASSES {
+            if !self.layout_pass(source) {
+                break;
+            }
+        }
+        let scroll = self.scroll;
+        for row in &mut self.live {
+            row.y = self.heights.offset_of(row.index) as i64 - scroll as i64;
+            row.height = self.heights.heights[row.index];
+        }
+    }
+
+    /// Binds the rows for the current scroll offset. Returns whether a
+    /// measurement changed a height, which may change the rows needed.
+    fn layout_pass(&mut self, source: &S) -> bool {
+        let len = self.len();
+        if len == 0 {
+            let rows = core::mem::take(&mut self.live);
+            self.spare.extend(rows.into_iter().map(|row| row.widgets));
+            self.scroll = 0;
+            return false;
+        }
+        self.scroll = self.scroll.min(self.max_scroll());
+        let first = self.heights.index_at(self.scroll);
+        let last = self.heights.index_at(self.scroll + self.viewport_height.saturating_sub(1) as u64);
+        let start = first.saturating_sub(self.overscan);
+        let end = (last + 1 + self.overscan).min(len);
+
+        // Unbind what left the range first, so the newcomers can reuse it.
+        let (keep, gone): (Vec<_>, Vec<_>) = core::mem::take(&mut self.live).into_iter().partition(|row| (start..end).contains(&row.index));
+        self.spare.extend(gone.into_iter().map(|row| row.widgets));
+        self.live = keep;
+
+        // The first visible row is the anchor: it stays where it is on screen.
+        let anchor_within = self.scroll - self.heights.offset_of(first);
+        let mut changed = false;
+        for index in start..end {
+            let position = match self.live.binary_search_by_key(&index, |row| row.index) {
+                Ok(_) => continue,
+                Err(position) => position,
+            };
+            let mut widgets = match self.spare.pop() {
+                Some(widgets) => widgets,
+                None => {
+                    self.instances += 1;
+                    S::RowWidgets::default()
+                },
+            };
+            source.bind_row(index, &mut widgets);
+            let height = source.measure_row(&widgets, self.width).max(1);
+            if !self.measured[index] || self.heights.heights[index] != height {
+                changed |= self.heights.heights[index] != height;
+                self.heights.set(index, height);
+                self.measured[index] = true;
+            }
+            self.live.insert(position, LiveRow { index, y: 0, height, widgets });
+        }
+        if changed {
+            self.scroll = (self.heights.offset_of(first) + anchor_within).min(self.max_scroll());
+            self.damage_all();
+        }
+        changed
+    }
+
+    pub fn rows(&self) -> &[LiveRow<S::RowWidgets>] {
+        &self.live
+    }
+
+    /// Row instances that exist, bound or spare.
+    pub fn instance_count(&self) -> usize {
+        self.instances
+    }
+
+    /// What needs repainting since the last call, if anything.
+    pub fn take_damage(&mut self) -> Option<ListRect> {
+        self.damage.take()
+    }
+
+    fn damage_all(&mut self) {
+        self.add_damage(ListRect { x: 0, y: 0, width: self.width, height: self.viewport_height });
+    }
+
+    fn damage_row(&mut self, y: i64, height: u32) {
+        let top = y.max(0);
+        let bottom = (y + height as i64).min(self.viewport_height as i64);
+        if bottom > top {
+            self.add_damage(ListRect { x: 0, y: top as u32, width: self.width, height: (bottom - top) as u32 });
+        }
+    }
+
+    fn add_damage(&mut self, rect: ListRect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.damage = Some(match self.damage {
+            Some(damage) => damage.union(&rect),
+            None => rect,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 400;
+    const VIEWPORT: u32 = 600;
+    const ESTIMATE: u32 = 20;
+
+    /// `len` rows that all measure `height`.
+    struct Rows {
+        len: usize,
+        height: u32,
+    }
+
+    #[derive(Default)]
+    struct Row {
+        item: Option<usize>,
+    }
+
+    impl ListSource for Rows {
+        type RowWidgets = Row;
+
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn bind_row(&self, index: usize, row: &mut Row) {
+            row.item = Some(index);
+        }
+
+        fn measure_row(&self, _row: &Row, _width: u32) -> u32 {
+            self.height
+        }
+    }
+
+    fn list(source: &Rows) -> VirtualList<Rows> {
+        let mut list = VirtualList::new(source, WIDTH, VIEWPORT, ESTIMATE);
+        list.update(source);
+        list.take_damage();
+        list
+    }
+
+    fn y_of(list: &VirtualList<Rows>, index: usize) -> Option<i64> {
+        list.rows().iter().find(|row| row.index == index).map(|row| row.y)
+    }
+
+    fn rows_show_their_items(list: &VirtualList<Rows>) -> bool {
+        list.rows().iter().all(|row| row.widgets.item == Some(row.index))
+    }
+
+    #[test]
+    fn instances_depend_on_the_viewport_not_the_length() {
+        // 30 rows fill the viewport, 31 when it is between two, plus overscan on both sides.
+        let bound = (VIEWPORT / ESTIMATE) as usize + 1 + 2 * DEFAULT_OVERSCAN;
+        for len in [100, 10_000, 1_000_000] {
+            let source = Rows { len, height: ESTIMATE };
+            for step in [1, 7, VIEWPORT as i64] {
+                let mut list = list(&source);
+                for _ in 0..200 {
+                    list.scroll_by(step);
+                    list.update(&source);
+                    assert!(list.instance_count() <= bound, "{} rows by {}: {} instances", len, step, list.instance_count());
+                }
+                list.scroll_to(u64::MAX);
+                list.update(&source);
+                assert!(list.instance_count() <= bound);
+                assert!(rows_show_their_items(&list));
+            }
+        }
+    }
+
+    #[test]
+    fn jumping_away_and_back_rebinds_without_new_instances() {
+        let source = Rows { len: 10_000, height: ESTIMATE };
+        let mut list = list(&source);
+        list.scroll_to_index(9_000, ScrollAlign::Start);
+        list.update(&source);
+        assert_eq!(y_of(&list, 9_000), Some(0));
+        assert!(rows_show_their_items(&list));
+        let instances = list.instance_count();
+
+        list.scroll_to(0);
+        list.update(&source);
+        assert_eq!(list.rows().first().map(|row| row.index), Some(0));
+        assert!(rows_show_their_items(&list));
+        assert_eq!(list.instance_count(), instances);
+    }
+
+    #[test]
+    fn measured_heights_replace_the_estimate() {
+        let source = Rows { len: 10_000, height: 2 * ESTIMATE };
+        let list = list(&source);
+        // The first pass bound 30 estimated rows plus overscan; only the ones
+        // still needed at their measured height are kept, but all stay measured.
+        let measured = (VIEWPORT / ESTIMATE) as u64 + DEFAULT_OVERSCAN as u64;
+        assert_eq!(list.content_height(), measured * 2 * ESTIMATE as u64 + (10_000 - measured) * ESTIMATE as u64);
+        assert!(list.rows().iter().all(|row| row.height == 2 * ESTIMATE));
+        assert_eq!(y_of(&list, 1), Some(2 * ESTIMATE as i64));
+        assert_eq!(list.row_at(2 * ESTIMATE + 1), Some(1));
+    }
+
+    #[test]
+    fn corrections_above_the_viewport_keep_the_rows_in_place() {
+        let source = Rows { len: 10_000, height: 2 * ESTIMATE };
+        let mut list = list(&source);
+        // Placed by the estimate; the overscan rows above it measure taller.
+        list.scroll_to_index(500, ScrollAlign::Start);
+        list.update(&source);
+        assert_eq!(y_of(&list, 500), Some(0));
+
+        // Row 499 comes into view and row 496 into the overscan, 20 pixels
+        // taller than estimated: the offset moves with it.
+        list.scroll_by(-(2 * ESTIMATE as i64));
+        list.update(&source);
+        assert_eq!(y_of(&list, 499), Some(0));
+        assert_eq!(y_of(&list, 500), Some(2 * ESTIMATE as i64));
+        assert!(list.take_damage().is_some());
+    }
+
+    #[test]
+    fn scroll_to_index_aligns_the_row() {
+        let source = Rows { len: 10_000, height: ESTIMATE };
+        let mut list = list(&source);
+        let row = ESTIMATE as u64;
+        list.scroll_to_index(100, ScrollAlign::Start);
+        assert_eq!(list.scroll_offset(), 100 * row);
+        list.scroll_to_index(100, ScrollAlign::Center);
+        assert_eq!(list.scroll_offset(), 100 * row + row / 2 - VIEWPORT as u64 / 2);
+        list.scroll_to_index(100, ScrollAlign::End);
+        assert_eq!(list.scroll_offset(), 101 * row - VIEWPORT as u64);
+
+        // Nearest leaves a visible row alone and otherwise scrolls to the closer edge.
+        list.scroll_to_index(95, ScrollAlign::Nearest);
+        assert_eq!(list.scroll_offset(), 101 * row - VIEWPORT as u64);
+        list.scroll_to_index(50, ScrollAlign::Nearest);
+        assert_eq!(list.scroll_offset(), 50 * row);
+        list.scroll_to_index(200, ScrollAlign::Nearest);
+        assert_eq!(list.scroll_offset(), 201 * row - VIEWPORT as u64);
+
+        // Clamped to the content; past the end nothing moves.
+        list.scroll_to_index(9_999, ScrollAlign::Start);
+        assert_eq!(list.scroll_offset(), 10_000 * row - VIEWPORT as u64);
+        list.scroll_to_index(10_000, ScrollAlign::Start);
+        assert_eq!(list.scroll_offset(), 10_000 * row - VIEWPORT as u64);
+        list.update(&source);
+        assert_eq!(list.rows().last().map(|row| (row.index, row.y)), Some((9_999, VIEWPORT as i64 - ESTIMATE as i64)));
+    }
+
+    #[test]
+    fn damage_stays_within_the_viewport() {
+        let source = Rows { len: 10_000, height: ESTIMATE };
+        let mut list = list(&source);
+        let viewport = ListRect { x: 0, y: 0, width: WIDTH, height: VIEWPORT };
+        list.scroll_by(10);
+        list.update(&source);
+        assert_eq!(list.take_damage(), Some(viewport));
+        assert_eq!(list.take_damage(), None);
+
+        // Rows 1 and 3, at 10 and 50 pixels.
+        list.select(Some(1));
+        assert_eq!(list.take_damage(), Some(ListRect { x: 0, y: 10, width: WIDTH, height: ESTIMATE }));
+        list.select(Some(3));
+        assert_eq!(list.take_damage(), Some(ListRect { x: 0, y: 10, width: WIDTH, height: 3 * ESTIMATE }));
+        // Row 30 is cut by the bottom edge.
+        list.select(Some(30));
+        assert_eq!(list.take_damage(), Some(ListRect { x: 0, y: 50, width: WIDTH, height: VIEWPORT - 50 }));
+
+        list.move_selection(1);
+        list.update(&source);
+        assert_eq!(list.take_damage(), Some(viewport));
+        assert_eq!(y_of(&list, 31), Some(VIEWPORT as i64 - ESTIMATE as i64));
+    }
+
+    #[test]
+    fn a_shorter_source_drops_the_selection_and_clamps_the_offset() {
+        let source = Rows { len: 10_000, height: ESTIMATE };
+        let mut list = list(&source);
+        list.select(Some(5_000));
+        list.scroll_to_index(5_000, ScrollAlign::Center);
+        list.update(&source);
+
+        let shorter = Rows { len: 100, height: ESTIMATE };
+        list.data_changed(&shorter);
+        list.update(&shorter);
+        assert_eq!(list.selected(), None);
+        assert_eq!(list.scroll_offset(), 100 * ESTIMATE as u64 - VIEWPORT as u64);
+        assert!(rows_show_their_items(&list));
+    }
+}
@@ -0,0 +1,13 @@
+// common/src/ui/mod.rs
+
+//! What the UI V-Nodes share: parsing, layout, text, images, scaling, lists
+//! and input latency.
+
+pub mod html_parser;
+pub mod css_engine;
+pub mod layout;
+pub mod font;
+pub mod image;
+pub mod scale; // Display scale factors and logical-to-device pixels
+pub mod list; // Virtualized lists with measured row heights
+pub mod latency; // Input-to-present latency histograms
@@ -0,0 +1,63 @@
+// common/src/ui/scale.rs
+
+//! The global UI scale: 1x, 1.5x or 2x, from the `ui.scale` setting.
+//!
+//! Everything is laid out in logical pixels and multiplied out at the end,
+//! in integer math over half steps, so 1.5x is `* 3 / 2`. Odd sizes round
+//! up at 1.5x, so a 1px line never disappears. Glyphs are scaled by
+//! nearest-neighbour replication: at 2x every font pixel becomes a 2x2
+//! block, at 1.5x every other row and column is doubled.
+
+#![allow(dead_code)]
+
+/// The setting every scaled UI follows.
+pub const SCALE_SETTING: &str = "ui.scale";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UiScale {
+    #[default]
+    X1,
+    X1_5,
+    X2,
+}
+
+impl UiScale {
+    pub const ALL: [UiScale; 3] = [UiScale::X1, UiScale::X1_5, UiScale::X2];
+
+    /// The value of `ui.scale` for this scale.
+    pub fn name(self) -> &'static str {
+        match self {
+            UiScale::X1 => "1",
+            UiScale::X1_5 => "1.5",
+            UiScale::X2 => "2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scale| scale.name() == name)
+    }
+
+    pub fn percent(self) -> u16 {
+        self.halves() as u16 * 50
+    }
+
+    /// The scale factor in half steps: 2, 3 or 4.
+    fn halves(self) -> u32 {
+        match self {
+            UiScale::X1 => 2,
+            UiScale::X1_5 => 3,
+            UiScale::X2 => 4,
+        }
+    }
+
+    /// Physical pixels for `logical` pixels, rounded up.
+    pub fn px(self, logical: u32) -> u32 {
+        (logical * self.halves()).div_ceil(2)
+    }
+
+    /// The logical pixel a physical offset falls in, for hit-testing and
+    /// for sampling a bitmap drawn at this scale.
+    pub fn to_logical(self, physical: u32) -> u32 {
+        physical * 2 / self.halves()
+    }
+}
@@ -0,0 +1,450 @@
+// common/src/url.rs
+
+//! URL parsing and resolution of relative references, after RFC 3986.
+//!
+//! Enough for the WebView: `scheme://host[:port]/path?query#fragment`, and
+//! joining `./`, `../`, absolute-path and scheme-relative (`//host/path`)
+//! references against a document URL. Hosts are lowercased. In hosts and
+//! paths, escapes of unreserved characters are decoded and the rest get
+//! uppercase hex (RFC 3986, 6.2.2), so equivalent URLs compare equal and
+//! `%2E%2E` is a `..` segment. `percent_decode` gives a component's bytes.
+//! IPv6 hosts are kept in brackets. There is no IDNA.
+//!
+//! `domain_matches` is the host comparison of RFC 6265, 5.1.3, for cookies.
+//! There is no public suffix list: `co.uk` counts as an ordinary domain.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlError {
+    /// No `scheme:` prefix, and so nothing to resolve against.
+    MissingScheme,
+    InvalidScheme,
+    InvalidPort,
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingScheme => f.write_str("relative URL without a base"),
+            Self::InvalidScheme => f.write_str("invalid scheme"),
+            Self::InvalidPort => f.write_str("invalid port"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    /// Lowercase, without the colon.
+    pub scheme: String,
+    /// Lowercase; empty for URLs without an authority, like `mailto:`.
+    pub host: String,
+    /// Only if written; see `port_or_default`.
+    pub port: Option<u16>,
+    /// Starts with `/` whenever there is a host.
+    pub path: String,
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+fn valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+/// Splits `rest` into (path, query, fragment).
+fn split_path(rest: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query), fragment),
+        None => (rest, None, fragment),
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+/// The bytes `text` stands for, with every `%XX` escape decoded. A `%` not
+/// followed by two hex digits is kept as it is.
+pub fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], bytes.get(i + 1).copied().and_then(hex_value), bytes.get(i + 2).copied().and_then(hex_value)) {
+            (b'%', Some(high), Some(low)) => Some(high << 4 | low),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            },
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    out
+}
+
+/// Decodes escapes of unreserved characters and uppercases the hex of the
+/// others (RFC 3986, 6.2.2.1 and 6.2.2.2).
+fn normalize_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        let escape = rest.as_bytes().get(start + 1..start + 3);
+        match escape.map(|hex| (hex_value(hex[0]), hex_value(hex[1]))) {
+            Some((Some(high), Some(low))) => {
+                let byte = high << 4 | low;
+                if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                    out.push(byte as char);
+                } else {
+                    out.push_str(&alloc::format!("%{:02X}", byte));
+                }
+                rest = &rest[start + 3..];
+            },
+            _ => {
+                out.push('%');
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Splits an authority without userinfo into host and port. An IPv6 host
+/// keeps its brackets, and its colons aren't taken for a port.
+fn split_host_port(authority: &str) -> Result<(&str, Option<u16>), UrlError> {
+    let (host, port) = match authority.find(']') {
+        Some(end) if authority.starts_with('[') => {
+            let (host, after) = authority.split_at(end + 1);
+            match after {
+                "" | ":" => (host, None),
+                _ => (host, Some(after.strip_prefix(':').ok_or(UrlError::InvalidPort)?)),
+            }
+        },
+        _ => match authority.rsplit_once(':') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = port.map(|port| port.parse::<u16>().map_err(|_| UrlError::InvalidPort)).transpose()?;
+    Ok((host, port))
+}
+
+/// Resolves `.` and `..` segments (RFC 3986, 5.2.4). `..` above the root stays at the root.
+fn remove_dot_segments(path: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." | ".." => {
+                if *segment == ".." && out.len() > 1 {
+                    out.pop();
+                }
+                // "a/b/.." names the directory, so keep its trailing slash.
+                if last {
+                    out.push("");
+                }
+            },
+            segment => out.push(segment),
+        }
+    }
+    let joined = out.join("/");
+    if path.starts_with('/') && !joined.starts_with('/') {
+        let mut rooted = String::from("/");
+        rooted.push_str(&joined);
+        rooted
+    } else {
+        joined
+    }
+}
+
+impl Url {
+    /// Parses an absolute URL.
+    pub fn parse(input: &str) -> Result<Url, UrlError> {
+        let input = input.trim();
+        let (scheme, rest) = input.split_once(':').ok_or(UrlError::MissingScheme)?;
+        if !valid_scheme(scheme) {
+            return Err(if scheme.contains('/') { UrlError::MissingScheme } else { UrlError::InvalidScheme });
+        }
+        let scheme = scheme.to_ascii_lowercase();
+
+        let (host, port, rest) = match rest.strip_prefix("//") {
+            Some(after) => {
+                let end = after.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(after.len());
+                let (authority, rest) = after.split_at(end);
+                // Userinfo isn't used for anything; drop it rather than mistake it for the host.
+                let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+                let (host, port) = split_host_port(authority)?;
+                (normalize_escapes(host).to_ascii_lowercase(), port, rest)
+            },
+            None => (String::new(), None, rest),
+        };
+
+        let (path, query, fragment) = split_path(rest);
+        let path = if !host.is_empty() && path.is_empty() { String::from("/") } else { remove_dot_segments(&normalize_escapes(path)) };
+        Ok(Url {
+            scheme,
+            host,
+            port,
+            path,
+            query: query.map(String::from),
+            fragment: fragment.map(String::from),
+        })
+    }
+
+    /// Resolves `reference`, as found in an `href` or `src`, against this URL.
+    pub fn join(&self, reference: &str) -> Result<Url, UrlError> {
+        let reference = reference.trim();
+        if let Some((scheme, _)) = reference.split_once(':') {
+            if valid_scheme(scheme) {
+                return Url::parse(reference);
+            }
+        }
+        if reference.starts_with("//") {
+            return Url::parse(&alloc::format!("{}:{}", self.scheme, reference));
+        }
+
+        let (path, query, fragment) = split_path(reference);
+        let mut url = self.clone();
+        url.fragment = fragment.map(String::from);
+        if path.is_empty() {
+            // "", "?q" and "#f" keep the path; only "#f" keeps the query too.
+            if query.is_some() {
+                url.query = query.map(String::from);
+            }
+            return Ok(url);
+        }
+        url.query = query.map(String::from);
+        let path = normalize_escapes(path);
+        url.path = if path.starts_with('/') {
+            remove_dot_segments(&path)
+        } else {
+            let dir = match self.path.rfind('/') {
+                Some(end) => &self.path[..=end],
+                None if self.host.is_empty() => "",
+                None => "/",
+            };
+            remove_dot_segments(&alloc::format!("{}{}", dir, path))
+        };
+        Ok(url)
+    }
+
+    /// The explicit port, or the scheme's usual one.
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port.or(match self.scheme.as_str() {
+            "http" => Some(80),
+            "https" => Some(443),
+            _ => None,
+        })
+    }
+
+    /// True if both URLs have the same scheme, host and port.
+    pub fn same_origin(&self, other: &Url) -> bool {
+        self.scheme == other.scheme && self.host == other.host && self.port_or_default() == other.port_or_default()
+    }
+
+    /// `scheme://host[:port]`, with the port only if written.
+    pub fn origin(&self) -> String {
+        match self.port {
+            Some(port) => alloc::format!("{}://{}:{}", self.scheme, self.host, port),
+            None => alloc::format!("{}://{}", self.scheme, self.host),
+        }
+    }
+
+    /// This URL without its fragment, as used for fetching and caching.
+    pub fn without_fragment(&self) -> String {
+        let mut url = self.clone();
+        url.fragment = None;
+        url.to_string()
+    }
+}
+
+/// True if `host` is an IPv4 address or a bracketed IPv6 address rather than a name.
+pub fn is_ip_address(host: &str) -> bool {
+    if host.starts_with('[') && host.ends_with(']') {
+        return true;
+    }
+    let parts: Vec<&str> = host.split('.').collect();
+    parts.len() == 4 && parts.iter().all(|part| !part.is_empty() && part.len() <= 3 && part.parse::<u8>().is_ok())
+}
+
+/// True if `host` is `domain` or a subdomain of it (RFC 6265, 5.1.3). Both
+/// are lowercase, without a leading dot. An IP address only matches itself.
+pub fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    !is_ip_address(host)
+        && !domain.is_empty()
+        && host.len() > domain.len()
+        && host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scheme)?;
+        if !self.host.is_empty() {
+            write!(f, "//{}", self.host)?;
+            if let Some(port) = self.port {
+                write!(f, ":{}", port)?;
+            }
+        }
+        f.write_str(&self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).unwrap()
+    }
+
+    fn joined(base: &str, reference: &str) -> String {
+        url(base).join(reference).unwrap().to_string()
+    }
+
+    #[test]
+    fn absolute_urls_split_into_their_parts() {
+        let parsed = url(" HTTP://User:pw@Example.COM:8080/a/b.html?x=1&y#top ");
+        assert_eq!(parsed.scheme, "http");
+        assert_eq!(parsed.host, "example.com");
+        assert_eq!(parsed.port, Some(8080));
+        assert_eq!(parsed.path, "/a/b.html");
+        assert_eq!(parsed.query.as_deref(), Some("x=1&y"));
+        assert_eq!(parsed.fragment.as_deref(), Some("top"));
+        assert_eq!(parsed.to_string(), "http://example.com:8080/a/b.html?x=1&y#top");
+        assert_eq!(parsed.without_fragment(), "http://example.com:8080/a/b.html?x=1&y");
+
+        assert_eq!(url("https://example.com").path, "/");
+        assert_eq!(url("http://example.com:/").port, None);
+        let mail = url("mailto:someone@example.com");
+        assert_eq!((mail.host.as_str(), mail.path.as_str()), ("", "someone@example.com"));
+    }
+
+    #[test]
+    fn bad_urls_are_refused() {
+        assert_eq!(Url::parse("example.com/index.html"), Err(UrlError::MissingScheme));
+        assert_eq!(Url::parse("/a:b"), Err(UrlError::MissingScheme));
+        assert_eq!(Url::parse("1http://example.com"), Err(UrlError::InvalidScheme));
+        assert_eq!(Url::parse("http://example.com:99999/"), Err(UrlError::InvalidPort));
+        assert_eq!(Url::parse("http://example.com:8o/"), Err(UrlError::InvalidPort));
+    }
+
+    #[test]
+    fn ipv6_hosts_keep_their_brackets_and_colons() {
+        let plain = url("http://[::1]/index.html");
+        assert_eq!((plain.host.as_str(), plain.port), ("[::1]", None));
+        let with_port = url("http://[FE80::1%25eth0]:8080/");
+        assert_eq!((with_port.host.as_str(), with_port.port), ("[fe80::1%25eth0]", Some(8080)));
+        assert_eq!(with_port.origin(), "http://[fe80::1%25eth0]:8080");
+        assert_eq!(Url::parse("http://[::1]x/"), Err(UrlError::InvalidPort));
+        assert!(is_ip_address(&plain.host));
+        assert!(is_ip_address("10.0.2.15"));
+        assert!(!is_ip_address("10.0.2"));
+        assert!(!is_ip_address("10.0.2.256"));
+        assert!(!is_ip_address("example.com"));
+    }
+
+    #[test]
+    fn escapes_are_normalized_and_decoded() {
+        assert_eq!(url("http://example.com/%7euser/a%2db%2fc%zz%").path, "/~user/a-b%2Fc%zz%");
+        assert_eq!(url("http://example.com/a%2fb"), url("http://example.com/a%2Fb"));
+        assert_eq!(url("http://example.com/a/%2E%2e/b").path, "/b");
+        assert_eq!(url("http://ex%61mple.com/").host, "example.com");
+        assert_eq!(url("http://example.com/?q=%7e").query.as_deref(), Some("q=%7e"), "queries are left as written");
+
+        assert_eq!(percent_decode("a%20b%2Fc"), b"a b/c");
+        assert_eq!(percent_decode("%E2%82%AC"), "\u{20AC}".as_bytes());
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%g0%4"), b"%g0%4");
+    }
+
+    #[test]
+    fn relative_references_resolve_as_in_rfc_3986() {
+        // RFC 3986, 5.4.1 and 5.4.2, for the parts that apply to hierarchical URLs.
+        let base = "http://a/b/c/d;p?q";
+        let cases = [
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("https://other/x", "https://other/x"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(joined(base, reference), expected, "{:?}", reference);
+        }
+        assert_eq!(joined("http://[::1]:8080/a/b", "../c"), "http://[::1]:8080/c");
+    }
+
+    #[test]
+    fn origins_compare_scheme_host_and_effective_port() {
+        let page = url("http://example.com/index.html");
+        assert!(page.same_origin(&url("http://EXAMPLE.com:80/style.css")));
+        assert!(!page.same_origin(&url("https://example.com/")));
+        assert!(!page.same_origin(&url("http://example.com:8080/")));
+        assert!(!page.same_origin(&url("http://www.example.com/")));
+        assert_eq!(url("https://example.com").port_or_default(), Some(443));
+        assert_eq!(url("gopher://example.com").port_or_default(), None);
+    }
+
+    #[test]
+    fn domains_match_themselves_and_their_subdomains() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("www.example.com", "example.com"));
+        assert!(!domain_matches("badexample.com", "example.com"));
+        assert!(!domain_matches("example.com", "www.example.com"));
+        assert!(!domain_matches("10.0.2.15", "0.2.15"));
+        assert!(domain_matches("10.0.2.15", "10.0.2.15"));
+        assert!(!domain_matches("example.com", ""));
+    }
+}
@@ -1,10 +1,49 @@
 //! Central kernel configuration constants.
 
 /// Maximum number of IPC channels exposed by the kernel syscall ABI.
-pub const IPC_CHANNEL_COUNT: u32 = 32;
+pub const IPC_CHANNEL_COUNT: u32 = common::channels::MAX_CHANNELS;
 
 /// AetherOS page size in bytes.
 pub const PAGE_SIZE: usize = 4096;
 
 /// Reserved conceptual kernel memory size in bytes (256 MiB).
 pub const KERNEL_MEMORY_SIZE: usize = 256 * 1024 * 1024;
+
+/// Default sustained SYS_LOG rate per task, in messages per second.
+pub const DEFAULT_LOG_RATE_PER_SEC: u32 = 50;
+
+/// Default SYS_LOG burst size per task, in messages.
+pub const DEFAULT_LOG_BURST: u32 = 100;
+
+/// Minimum interval between "suppressed N messages" summaries for one task, in seconds.
+pub const LOG_SUPPRESSION_REPORT_INTERVAL_SECS: u64 = 1;
+
+/// Longest SYS_LOG message printed, in bytes. Longer messages are cut on a
+/// character boundary and marked with "...".
+pub const MAX_LOG_MESSAGE_BYTES: usize = 512;
+
+/// Most forwarded SYS_LOG records queued on the log daemon's channel. Past
+/// this the kernel drops them, and counts them, rather than queue more.
+pub const LOG_FORWARD_QUEUE_MAX: usize = 256;
+
+/// Most CPUs the kernel manages. Per-CPU state is sized for this many; CPU
+/// ids are `0..MAX_CPUS` and must fit the 64-bit affinity mask.
+pub const MAX_CPUS: usize = 16;
+
+/// Size of the in-memory kernel log ring, in bytes.
+pub const KLOG_RING_BYTES: usize = 64 * 1024;
+
+/// Physical address of the region the kernel log is dumped to for the next
+/// boot. It must be page-aligned RAM that firmware and the bootloader leave
+/// alone across a warm reboot; 112 MiB lies inside QEMU's default 128 MiB.
+pub const PSTORE_PHYS_START: u64 = 0x0700_0000;
+
+/// Size of that region, in bytes. The dump holds this much of the log, minus its header.
+pub const PSTORE_SIZE: usize = 16 * 1024;
+
+/// Interval between checkpoints of the log to the persistent region, in seconds.
+pub const PSTORE_CHECKPOINT_SECS: u64 = 5;
+
+/// Most bad frames the boot memory test remembers and keeps out of the frame
+/// allocator. A machine with more than this needs new RAM, not a workaround.
+pub const MEMTEST_MAX_BAD_FRAMES: usize = 256;
@@ -48,6 +48,9 @@ pub enum KernelError {
 
     /// Device or resource busy.
     Busy,
+
+    /// No identity is bound to the task.
+    Unauthenticated,
 }
 
 impl KernelError {
@@ -59,8 +62,10 @@ impl KernelError {
     /// ```
     pub fn to_syscall_code(self) -> u64 {
         match self {
-            Self::PermissionDenied => crate::kernel::syscall::E_ACC_DENIED,
-            _ => crate::kernel::syscall::E_ERROR,
+            Self::PermissionDenied => crate::syscall::E_ACC_DENIED,
+            Self::Busy => crate::syscall::E_BUSY,
+            Self::Unauthenticated => crate::syscall::E_UNAUTHENTICATED,
+            _ => crate::syscall::E_ERROR,
         }
     }
 }
@@ -78,6 +83,7 @@ impl core::fmt::Display for KernelError {
             Self::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
             Self::WouldBlock => write!(f, "Operation would block"),
             Self::Busy => write!(f, "Device or resource busy"),
+            Self::Unauthenticated => write!(f, "No identity bound to task"),
         }
     }
 }
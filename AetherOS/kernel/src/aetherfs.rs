@@ -5,25 +5,45 @@
 extern crate alloc;
 use alloc::vec::Vec;
 use alloc::string::{String, ToString};
+use common::initrd::Initrd;
+use spin::Mutex;
 use crate::kprintln;
 
-/// Initializes the AetherFS (conceptual).
-/// In a real system, this would involve setting up disk drivers, superblocks, etc.
-pub fn init() {
-    kprintln!("[kernel] aetherfs: Initializing (conceptual)...");
-    // TODO: Implement actual AetherFS initialization logic.
+/// The boot image, parsed in place. Files are served straight from the
+/// bootloader's ramdisk, which stays mapped for the kernel's lifetime.
+static INITRD: Mutex<Option<Initrd<'static>>> = Mutex::new(None);
+
+/// Initializes AetherFS from the initrd image the bootloader loaded as its
+/// ramdisk (see `common::initrd`, built by `axpkg initrd`). Without one, or if
+/// it doesn't parse, every read fails and no V-Node can be loaded.
+pub fn init(ramdisk: Option<&'static [u8]>) {
+    kprintln!("[kernel] aetherfs: Initializing...");
+    let image = match ramdisk {
+        Some(image) => image,
+        None => {
+            kprintln!("[kernel] aetherfs: No initrd was loaded; boot with an image built by `axpkg initrd`.");
+            return;
+        }
+    };
+    match Initrd::parse(image) {
+        Ok(initrd) => {
+            kprintln!("[kernel] aetherfs: Initrd has {} files ({} bytes).", initrd.files().len(), image.len());
+            for (path, data) in initrd.files() {
+                kprintln!("[kernel] aetherfs:   {} ({} bytes)", path, data.len());
+            }
+            *INITRD.lock() = Some(initrd);
+        }
+        Err(e) => kprintln!("[kernel] aetherfs: Ignoring the initrd: {}.", e),
+    }
     kprintln!("[kernel] aetherfs: Initialized.");
 }
 
-/// Simulates reading a file from AetherFS.
-/// Returns a dummy `Vec<u8>` or an error.
+/// Reads a file from the initrd.
 pub fn read_file(path: &str) -> Result<Vec<u8>, String> {
-    kprintln!("[kernel] aetherfs: Reading conceptual file: {}.", path);
-    // Simulate file content based on path
-    match path {
-        "/initrd/vnode_main.bin" => Ok(b"dummy_vnode_binary_content".to_vec()),
-        "/initrd/manifest.json" => Ok(b"{\"name\":\"dummy\"}".to_vec()),
-        _ => Err(format!("Conceptual file not found: {}", path)),
+    kprintln!("[kernel] aetherfs: Reading file: {}.", path);
+    match INITRD.lock().as_ref() {
+        Some(initrd) => initrd.get(path).map(|data| data.to_vec()).ok_or_else(|| format!("File not found: {}", path)),
+        None => Err("No initrd loaded".to_string()),
     }
 }
 
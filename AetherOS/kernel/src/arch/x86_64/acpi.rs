@@ -0,0 +1,120 @@
+// kernel/src/arch/x86_64/acpi.rs
+
+//! Just enough ACPI to count CPUs: RSDP -> RSDT/XSDT -> MADT. Only built with
+//! the `smp` feature.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const SDT_HEADER_LEN: usize = 36;
+
+// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LAPIC_ADDRESS_OVERRIDE: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+// Local APIC flags: usable now, or can be enabled later.
+const LAPIC_ENABLED: u32 = 1 << 0;
+const LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// What the MADT says about the CPUs.
+pub struct Madt {
+    /// Physical address of the local APIC registers.
+    pub lapic_base: u64,
+    /// Local APIC IDs of the usable CPUs, in table order.
+    pub apic_ids: Vec<u32>,
+}
+
+/// Physical memory, through the bootloader's complete mapping of it.
+struct Phys {
+    offset: u64,
+}
+
+impl Phys {
+    /// SAFETY: the caller makes sure `addr..addr + len` is physical memory
+    /// holding ACPI data, which the bootloader leaves mapped and unmodified.
+    unsafe fn bytes(&self, addr: u64, len: usize) -> &'static [u8] {
+        core::slice::from_raw_parts((self.offset + addr) as *const u8, len)
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// A whole system description table, checked against its length and checksum.
+unsafe fn table(phys: &Phys, addr: u64) -> Result<&'static [u8], &'static str> {
+    let header = phys.bytes(addr, SDT_HEADER_LEN);
+    let len = u32_at(header, 4) as usize;
+    if len < SDT_HEADER_LEN {
+        return Err("table shorter than its header");
+    }
+    let table = phys.bytes(addr, len);
+    if !checksum_ok(table) {
+        return Err("bad table checksum");
+    }
+    Ok(table)
+}
+
+/// Finds and parses the MADT, starting from the RSDP at physical `rsdp_addr`.
+pub fn parse_madt(rsdp_addr: u64, physical_memory_offset: u64) -> Result<Madt, &'static str> {
+    let phys = Phys { offset: physical_memory_offset };
+    // SAFETY: the bootloader found the RSDP there; everything else is reached
+    // through pointers in checksummed ACPI tables.
+    unsafe {
+        let rsdp = phys.bytes(rsdp_addr, 36);
+        if &rsdp[0..8] != RSDP_SIGNATURE || !checksum_ok(&rsdp[0..20]) {
+            return Err("bad RSDP");
+        }
+        // ACPI 2.0+ has the XSDT with 64-bit pointers; 1.0 only the RSDT.
+        let (root, entry_len) = if rsdp[15] >= 2 && u64_at(rsdp, 24) != 0 {
+            (table(&phys, u64_at(rsdp, 24))?, 8)
+        } else {
+            (table(&phys, u32_at(rsdp, 16) as u64)?, 4)
+        };
+
+        let madt = root[SDT_HEADER_LEN..].chunks_exact(entry_len)
+            .map(|entry| if entry_len == 8 { u64_at(entry, 0) } else { u32_at(entry, 0) as u64 })
+            .find(|addr| phys.bytes(*addr, 4) == MADT_SIGNATURE)
+            .ok_or("no MADT")?;
+        let madt = table(&phys, madt)?;
+        parse_madt_entries(madt)
+    }
+}
+
+fn parse_madt_entries(madt: &[u8]) -> Result<Madt, &'static str> {
+    let mut lapic_base = u32_at(madt, SDT_HEADER_LEN) as u64;
+    let mut apic_ids = Vec::new();
+    let mut at = SDT_HEADER_LEN + 8; // Local APIC address and flags
+    while at + 2 <= madt.len() {
+        let (kind, len) = (madt[at], madt[at + 1] as usize);
+        if len < 2 || at + len > madt.len() {
+            return Err("malformed MADT entry");
+        }
+        let entry = &madt[at..at + len];
+        let usable = |flags: u32| flags & (LAPIC_ENABLED | LAPIC_ONLINE_CAPABLE) != 0;
+        match kind {
+            MADT_LOCAL_APIC if len >= 8 && usable(u32_at(entry, 4)) => apic_ids.push(entry[3] as u32),
+            MADT_LOCAL_X2APIC if len >= 16 && usable(u32_at(entry, 8)) => apic_ids.push(u32_at(entry, 4)),
+            MADT_LAPIC_ADDRESS_OVERRIDE if len >= 12 => lapic_base = u64_at(entry, 4),
+            _ => {}
+        }
+        at += len;
+    }
+    if apic_ids.is_empty() {
+        return Err("MADT lists no CPUs");
+    }
+    Ok(Madt { lapic_base, apic_ids })
+}
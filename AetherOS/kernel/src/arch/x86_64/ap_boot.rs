@@ -0,0 +1,66 @@
+// kernel/src/arch/x86_64/ap_boot.rs
+
+//! Starting the application processors. Only built with the `smp` feature.
+//!
+//! This is scaffolding: the CPUs are counted and the steps are laid out, but
+//! the real-mode trampoline isn't written yet, so no AP is started and the
+//! kernel keeps running on CPU 0. The sequence, once it is:
+//!
+//! 1. Copy the trampoline to `TRAMPOLINE_PAGE` (below 1 MiB, page aligned).
+//!    It switches from real mode through protected mode to long mode, loads
+//!    the kernel's page tables and a stack from `ApBootArgs`, and calls
+//!    `ap_main` with the CPU id.
+//! 2. Per AP: send INIT, wait 10 ms, send STARTUP twice with the trampoline's
+//!    page number, 200 us apart.
+//! 3. Wait up to 100 ms for the AP to come online (`task::cpu::is_online`).
+
+#![allow(dead_code)]
+
+use crate::kprintln;
+use crate::task::cpu::CpuId;
+use crate::task::scheduler;
+use crate::task::tcb::TaskControlBlock;
+
+/// Physical page the trampoline runs from. The STARTUP IPI vector is its page number.
+pub const TRAMPOLINE_PAGE: u64 = 0x8000;
+pub const STARTUP_VECTOR: u8 = (TRAMPOLINE_PAGE >> 12) as u8;
+
+// Interrupt command register values for the start-up sequence.
+const ICR_INIT: u32 = 0x0000_4500; // INIT, level assert
+const ICR_STARTUP: u32 = 0x0000_4600; // STARTUP; OR in the vector
+
+/// What the trampoline needs to enter the kernel, stored at the end of its page.
+#[repr(C)]
+pub struct ApBootArgs {
+    pub cr3: u64,
+    pub stack_top: u64,
+    pub entry: u64,
+    pub cpu: u64,
+}
+
+/// Starts CPUs `1..count`. Returns how many came online.
+pub fn start_aps(count: usize, _physical_memory_offset: u64) -> usize {
+    if count <= 1 {
+        return 0;
+    }
+    // TODO: Write the trampoline, then for each AP: copy it to TRAMPOLINE_PAGE,
+    // fill in ApBootArgs, send_icr(apic_id, ICR_INIT), wait, and
+    // send_icr(apic_id, ICR_STARTUP | STARTUP_VECTOR) twice.
+    kprintln!("[kernel] smp: AP start-up isn't implemented yet; {} CPUs stay offline.", count - 1);
+    0
+}
+
+/// Where an AP enters the kernel from the trampoline. It sets up its own
+/// descriptor tables, joins the scheduler with its idle task and idles until
+/// work is queued or stolen.
+pub extern "C" fn ap_main(cpu: CpuId) -> ! {
+    super::gdt::init();
+    super::idt::init();
+    // Task IDs below 1000 are the kernel's; an AP's idle task has its CPU id.
+    let idle = TaskControlBlock::new(cpu as u64, alloc::format!("idle/{}", cpu), alloc::vec::Vec::new());
+    scheduler::init_cpu(cpu, idle);
+    loop {
+        scheduler::schedule();
+        x86_64::instructions::hlt();
+    }
+}
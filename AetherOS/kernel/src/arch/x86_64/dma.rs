@@ -4,45 +4,275 @@
 
 extern crate alloc;
 use alloc::collections::BTreeMap;
+#[cfg(feature = "heap-debug")]
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
+use crate::error::KernelError;
 use crate::kprintln;
+use common::ids::{DmaHandle, TaskId};
+#[cfg(feature = "heap-debug")]
+use crate::{heap::FREE_POISON, kerrorln, task::scheduler};
 
 /// A simple DMA buffer manager for simulation.
 /// In a real system, this would manage physically contiguous memory pages
 /// and provide their physical addresses to devices.
 /// For V-Nodes, these buffers are mapped into their virtual address space.
 
+mod domain; // Devices see memory through a domain per driver; every buffer is mapped in one
+pub use domain::{DeviceAddr, DmaDirection, DmaDomain, DmaSegment, Translation};
+
+/// Address bits of a device that reaches all of memory.
+pub const ADDRESS_BITS_ALL: u32 = 64;
+
+/// The domain `alloc_dma_buffer` puts a task's buffers in, created on first use.
+const BUFFER_DOMAIN: &str = "buffers";
+
+/// Static counter for generating unique DMA domain IDs.
+static NEXT_DOMAIN: AtomicU64 = AtomicU64::new(1);
+
+/// Every live domain by ID. Locked after `DMA_BUFFERS`.
+static DOMAINS: Mutex<BTreeMap<u64, DmaDomain>> = Mutex::new(BTreeMap::new());
+
 /// Static counter for generating unique DMA buffer handles.
 static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
 
+/// Bytes behind each buffer that a device or driver writing past its end
+/// would change. `heap-debug` builds check them when the buffer is freed.
+#[cfg(feature = "heap-debug")]
+const GUARD_SIZE: usize = 16;
+#[cfg(not(feature = "heap-debug"))]
+const GUARD_SIZE: usize = 0;
+#[cfg(feature = "heap-debug")]
+const GUARD_BYTE: u8 = 0xD6;
+
+/// Freed buffers kept back, poisoned, before their memory is released.
+#[cfg(feature = "heap-debug")]
+const QUARANTINE_LEN: usize = 8;
+
+/// A DMA buffer together with the task that allocated it.
+struct DmaBuffer {
+    /// ID of the task that owns this buffer; it is freed when that task is torn down.
+    owner: TaskId,
+    /// The domain it is mapped in, and its address there.
+    domain: u64,
+    device: DeviceAddr,
+    /// The `Vec<u8>` acts as the memory backing for the DMA buffer.
+    data: Vec<u8>,
+    /// Return address of the code that allocated it, for corruption reports.
+    #[cfg(feature = "heap-debug")]
+    site: usize,
+}
+
+impl DmaBuffer {
+    /// The buffer's usable size, without the guard.
+    fn capacity(&self) -> usize {
+        self.data.capacity() - GUARD_SIZE
+    }
+}
+
 /// Stores the allocated DMA buffers, mapped by their unique handles.
-/// The `Vec<u8>` acts as the memory backing for the DMA buffer.
-static DMA_BUFFERS: Mutex<BTreeMap<u64, Vec<u8>>> = Mutex::new(BTreeMap::new());
+static DMA_BUFFERS: Mutex<BTreeMap<DmaHandle, DmaBuffer>> = Mutex::new(BTreeMap::new());
+
+/// Freed buffers by handle, oldest first (`heap-debug` only). Locked after `DMA_BUFFERS`.
+#[cfg(feature = "heap-debug")]
+static QUARANTINE: Mutex<VecDeque<(DmaHandle, DmaBuffer)>> = Mutex::new(VecDeque::new());
+
+#[cfg(feature = "heap-debug")]
+fn report(problem: &str, handle: DmaHandle, buf: &DmaBuffer) -> ! {
+    kerrorln!(
+        "[kernel] dma: {} on handle {}: {} bytes allocated at {:#x} by task {}; detected in task {}.",
+        problem, handle, buf.capacity(), buf.site, buf.owner, scheduler::current_task_id()
+    );
+    panic!("DMA corruption: {} on handle {}", problem, handle);
+}
+
+/// Checks a freed buffer's guard, poisons it and quarantines it. The buffer
+/// pushed out of the quarantine must still hold nothing but poison.
+#[cfg(feature = "heap-debug")]
+fn retire(handle: DmaHandle, mut buf: DmaBuffer) {
+    let capacity = buf.capacity();
+    // SAFETY: The guard lies within the allocation and was written by `alloc_dma_buffer`.
+    let guard = unsafe { core::slice::from_raw_parts(buf.data.as_ptr().add(capacity), GUARD_SIZE) };
+    if guard.iter().any(|&b| b != GUARD_BYTE) {
+        report("write past the end (overflow)", handle, &buf);
+    }
+    // SAFETY: `capacity` bytes are allocated.
+    unsafe { core::ptr::write_bytes(buf.data.as_mut_ptr(), FREE_POISON, capacity); }
+    let mut quarantine = QUARANTINE.lock();
+    quarantine.push_back((handle, buf));
+    if quarantine.len() > QUARANTINE_LEN {
+        let (old_handle, old) = quarantine.pop_front().unwrap();
+        // SAFETY: Poisoned in full when it was retired.
+        let data = unsafe { core::slice::from_raw_parts(old.data.as_ptr(), old.capacity()) };
+        if let Some(offset) = data.iter().position(|&b| b != FREE_POISON) {
+            kerrorln!("[kernel] dma: freed buffer {} changed at offset {}.", old_handle, offset);
+            report("use after free", old_handle, &old);
+        }
+    }
+}
+
+#[cfg(not(feature = "heap-debug"))]
+fn retire(_handle: DmaHandle, _buf: DmaBuffer) {}
+
+/// Creates a DMA domain for a driver working for `owner`, whose device
+/// addresses `address_bits` bits. Returns its ID.
+pub fn create_domain(owner: TaskId, name: &'static str, address_bits: u32) -> u64 {
+    let id = NEXT_DOMAIN.fetch_add(1, Ordering::SeqCst);
+    DOMAINS.lock().insert(id, DmaDomain::new(owner, name, address_bits));
+    kprintln!("[kernel] dma: Created domain {} '{}' ({}-bit) for task {}.", id, name, address_bits, owner);
+    id
+}
+
+/// Frees the buffers in `domain` and tears it down. Returns the number of
+/// other mappings still in it, which are leaks.
+pub fn destroy_domain(domain: u64) -> usize {
+    let mut buffers = DMA_BUFFERS.lock();
+    let mut domains = DOMAINS.lock();
+    let owned: Vec<DmaHandle> = buffers.iter().filter(|(_, buf)| buf.domain == domain).map(|(&handle, _)| handle).collect();
+    for handle in owned {
+        let buf = buffers.remove(&handle).unwrap();
+        unmap_buffer(&mut domains, &buf);
+        retire(handle, buf);
+    }
+    domains.remove(&domain).map_or(0, DmaDomain::teardown)
+}
+
+/// Tears down every domain of `task_id`, after its buffers were released.
+/// Returns the number of mappings leaked in them.
+pub fn release_task_domains(task_id: TaskId) -> usize {
+    let owned: Vec<u64> = DOMAINS.lock().iter().filter(|(_, domain)| domain.owner() == task_id).map(|(&id, _)| id).collect();
+    owned.into_iter().map(destroy_domain).sum()
+}
+
+/// Returns the number of mappings, buffers included, in the domains of `task_id`.
+pub fn count_task_mappings(task_id: TaskId) -> usize {
+    DOMAINS.lock().values().filter(|domain| domain.owner() == task_id).map(DmaDomain::mapping_count).sum()
+}
+
+fn with_domain<R>(domain: u64, f: impl FnOnce(&mut DmaDomain) -> Result<R, KernelError>) -> Result<R, KernelError> {
+    DOMAINS.lock().get_mut(&domain).map_or(Err(KernelError::InvalidArgument("no such DMA domain")), f)
+}
+
+/// Maps `segments` in `domain`; see `DmaDomain::map`.
+pub fn map(domain: u64, segments: &[DmaSegment], direction: DmaDirection) -> Result<DeviceAddr, KernelError> {
+    with_domain(domain, |d| d.map(segments, direction))
+}
+
+/// Unmaps `device` in `domain`; see `DmaDomain::unmap`.
+pub fn unmap(domain: u64, device: DeviceAddr) -> Result<(), KernelError> {
+    with_domain(domain, |d| d.unmap(device))
+}
+
+/// Syncs the mapping at `device` in `domain` for the device; see `DmaDomain::sync_for_device`.
+pub fn sync_for_device(domain: u64, device: DeviceAddr) -> Result<(), KernelError> {
+    with_domain(domain, |d| d.sync_for_device(device))
+}
 
-/// Allocates a new DMA-capable buffer of the specified `size`.
-/// Returns a unique handle to the buffer, or `None` if allocation fails.
+/// Syncs the mapping at `device` in `domain` for the CPU; see `DmaDomain::sync_for_cpu`.
+pub fn sync_for_cpu(domain: u64, device: DeviceAddr) -> Result<(), KernelError> {
+    with_domain(domain, |d| d.sync_for_cpu(device))
+}
+
+/// Unmaps a buffer being freed. A buffer whose domain is gone has no mapping left.
+fn unmap_buffer(domains: &mut BTreeMap<u64, DmaDomain>, buf: &DmaBuffer) {
+    if let Some(domain) = domains.get_mut(&buf.domain) {
+        let _ = domain.unmap(buf.device);
+    }
+}
+
+/// The ID of `owner`'s buffer domain, created if it has none.
+fn buffer_domain(owner: TaskId) -> u64 {
+    let existing = DOMAINS.lock().iter().find(|(_, domain)| domain.owner() == owner && domain.name() == BUFFER_DOMAIN).map(|(&id, _)| id);
+    existing.unwrap_or_else(|| create_domain(owner, BUFFER_DOMAIN, ADDRESS_BITS_ALL))
+}
+
+/// Allocates a new DMA-capable buffer of the specified `size` on behalf of `owner`,
+/// in its buffer domain. Returns a unique handle to the buffer, or `None` if allocation fails.
 ///
 /// In a real system, this would involve allocating physically contiguous memory.
-pub fn alloc_dma_buffer(size: usize) -> Option<u64> {
-    let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+pub fn alloc_dma_buffer(size: usize, owner: TaskId) -> Option<DmaHandle> {
+    alloc_dma_buffer_in(buffer_domain(owner), size)
+}
+
+/// Allocates a DMA buffer of `size` bytes mapped in `domain`, owned by the
+/// domain's owner. Returns its handle, or `None` if `size` is 0, the domain
+/// doesn't exist or the buffer can't be mapped.
+pub fn alloc_dma_buffer_in(domain: u64, size: usize) -> Option<DmaHandle> {
+    if size == 0 {
+        return None;
+    }
+    let handle = DmaHandle::from_raw(NEXT_HANDLE.fetch_add(1, Ordering::SeqCst));
     let mut buffers = DMA_BUFFERS.lock();
+    let mut domains = DOMAINS.lock();
+    let target = domains.get_mut(&domain)?;
+    let owner = target.owner();
 
     // Allocate a Vec with the given capacity. This simulates a contiguous memory block.
-    let buffer = Vec::with_capacity(size);
-    buffers.insert(handle, buffer);
+    let data = Vec::with_capacity(size + GUARD_SIZE);
+    #[cfg(not(feature = "heap-debug"))]
+    let mut buf = DmaBuffer { owner, domain, device: 0, data };
+    #[cfg(feature = "heap-debug")]
+    let mut buf = {
+        let mut buf = DmaBuffer { owner, domain, device: 0, data, site: crate::heap::return_address!(0) };
+        // SAFETY: The guard lies within the allocation.
+        unsafe { core::ptr::write_bytes(buf.data.as_mut_ptr().add(buf.capacity()), GUARD_BYTE, GUARD_SIZE); }
+        buf
+    };
+    let segment = DmaSegment { addr: buf.data.as_mut_ptr() as usize, len: buf.capacity() };
+    buf.device = match target.map(&[segment], DmaDirection::Bidirectional) {
+        Ok(device) => device,
+        Err(e) => {
+            kprintln!("[kernel] dma: Could not map a {}-byte buffer in domain {}: {:?}.", size, domain, e);
+            return None;
+        }
+    };
+    buffers.insert(handle, buf);
 
-    kprintln!("[kernel] dma: Allocated buffer with handle {} and size {}.", handle, size);
+    kprintln!("[kernel] dma: Allocated buffer with handle {} and size {} for task {}.", handle, size, owner);
     Some(handle)
 }
 
+/// Returns the ID of the task owning the buffer with the given `handle`.
+pub fn get_dma_buffer_owner(handle: DmaHandle) -> Option<TaskId> {
+    DMA_BUFFERS.lock().get(&handle).map(|buf| buf.owner)
+}
+
+/// Frees every DMA buffer owned by `task_id`. Returns the number of buffers freed.
+pub fn release_task_buffers(task_id: TaskId) -> usize {
+    let mut buffers = DMA_BUFFERS.lock();
+    let mut domains = DOMAINS.lock();
+    let owned: Vec<DmaHandle> = buffers.iter().filter(|(_, buf)| buf.owner == task_id).map(|(&handle, _)| handle).collect();
+    for &handle in &owned {
+        let buf = buffers.remove(&handle).unwrap();
+        unmap_buffer(&mut domains, &buf);
+        retire(handle, buf);
+    }
+    if !owned.is_empty() {
+        kprintln!("[kernel] dma: Released {} buffers owned by task {}.", owned.len(), task_id);
+    }
+    owned.len()
+}
+
+/// Returns the number of DMA buffers currently owned by `task_id`.
+pub fn count_task_buffers(task_id: TaskId) -> usize {
+    DMA_BUFFERS.lock().values().filter(|buf| buf.owner == task_id).count()
+}
+
 /// Frees the DMA buffer associated with the given `handle`.
-pub fn free_dma_buffer(handle: u64) {
+pub fn free_dma_buffer(handle: DmaHandle) {
     let mut buffers = DMA_BUFFERS.lock();
-    if buffers.remove(&handle).is_some() {
+    if let Some(buf) = buffers.remove(&handle) {
+        unmap_buffer(&mut DOMAINS.lock(), &buf);
+        retire(handle, buf);
         kprintln!("[kernel] dma: Freed buffer with handle {}.", handle);
     } else {
+        // Handles are never reused, so a quarantined one was freed before.
+        #[cfg(feature = "heap-debug")]
+        if let Some((_, buf)) = QUARANTINE.lock().iter().find(|(freed, _)| *freed == handle) {
+            report("double free", handle, buf);
+        }
         kprintln!("[kernel] dma: Attempted to free non-existent buffer with handle {}.", handle);
     }
 }
@@ -50,26 +280,46 @@ pub fn free_dma_buffer(handle: u64) {
 /// Returns a mutable raw pointer to the start of the DMA buffer.
 /// This pointer would typically be a virtual address for the V-Node,
 /// but for the kernel, it's the direct address of the `Vec`'s data.
-pub fn get_dma_buffer_ptr(handle: u64) -> Option<*mut u8> {
+/// It is the CPU's side; the device is given `get_dma_buffer_device_addr`.
+pub fn get_dma_buffer_ptr(handle: DmaHandle) -> Option<*mut u8> {
     let mut buffers = DMA_BUFFERS.lock();
-    buffers.get_mut(&handle).map(|buf| buf.as_mut_ptr())
+    buffers.get_mut(&handle).map(|buf| buf.data.as_mut_ptr())
+}
+
+/// Returns the address of the DMA buffer in its domain, for the device.
+pub fn get_dma_buffer_device_addr(handle: DmaHandle) -> Option<DeviceAddr> {
+    DMA_BUFFERS.lock().get(&handle).map(|buf| buf.device)
+}
+
+/// Syncs the DMA buffer for its device, after the CPU wrote to it.
+pub fn sync_dma_buffer_for_device(handle: DmaHandle) -> Result<(), KernelError> {
+    let buffers = DMA_BUFFERS.lock();
+    let buf = buffers.get(&handle).ok_or(KernelError::InvalidArgument("DMA handle not found"))?;
+    with_domain(buf.domain, |d| d.sync_for_device(buf.device))
+}
+
+/// Syncs the DMA buffer for the CPU, after its device wrote to it.
+pub fn sync_dma_buffer_for_cpu(handle: DmaHandle) -> Result<(), KernelError> {
+    let buffers = DMA_BUFFERS.lock();
+    let buf = buffers.get(&handle).ok_or(KernelError::InvalidArgument("DMA handle not found"))?;
+    with_domain(buf.domain, |d| d.sync_for_cpu(buf.device))
 }
 
 /// Returns the current capacity (allocated size) of the DMA buffer.
-pub fn get_dma_buffer_capacity(handle: u64) -> Option<usize> {
+pub fn get_dma_buffer_capacity(handle: DmaHandle) -> Option<usize> {
     let buffers = DMA_BUFFERS.lock();
-    buffers.get(&handle).map(|buf| buf.capacity())
+    buffers.get(&handle).map(DmaBuffer::capacity)
 }
 
 /// Sets the effective length of the data within the DMA buffer.
 /// This is used to indicate how much of the buffer is currently valid data.
-pub fn set_dma_buffer_len(handle: u64, len: usize) -> Result<(), &'static str> {
+pub fn set_dma_buffer_len(handle: DmaHandle, len: usize) -> Result<(), &'static str> {
     let mut buffers = DMA_BUFFERS.lock();
     if let Some(buf) = buffers.get_mut(&handle) {
         if len <= buf.capacity() {
             // SAFETY: We checked `len <= capacity`, so this is safe.
             // This is crucial for `Vec` to function correctly as a buffer.
-            unsafe { buf.set_len(len); }
+            unsafe { buf.data.set_len(len); }
             kprintln!("[kernel] dma: Set length for handle {} to {}.", handle, len);
             Ok(())
         } else {
@@ -83,7 +333,7 @@ pub fn set_dma_buffer_len(handle: u64, len: usize) -> Result<(), &'static str> {
 }
 
 /// Returns the current length (used size) of the DMA buffer.
-pub fn get_dma_buffer_len(handle: u64) -> Option<usize> {
+pub fn get_dma_buffer_len(handle: DmaHandle) -> Option<usize> {
     let buffers = DMA_BUFFERS.lock();
-    buffers.get(&handle).map(|buf| buf.len())
+    buffers.get(&handle).map(|buf| buf.data.len())
 }
@@ -0,0 +1,257 @@
+// kernel/src/arch/x86_64/dma/domain.rs
+
+//! Per-device DMA address spaces.
+//!
+//! A `DmaDomain` is the memory one driver's device can reach. `map` hands it
+//! kernel memory and returns the address the device is to use; `unmap` takes
+//! it back. A device address is the kernel address for now
+//! (`Translation::Identity`). An IOMMU or a virtio IOTLB would be another
+//! `Translation`, and callers wouldn't change.
+//!
+//! Memory the device can't use in place is bounced. That is memory in more
+//! than one piece, or beyond the device's address limit. The device gets a
+//! contiguous copy instead, and the copies are explicit: `sync_for_device`
+//! copies the caller's memory to the device's before a transfer, and
+//! `sync_for_cpu` copies it back after one, each only in the direction the
+//! mapping was made for. `map` and `unmap` sync too, so a mapping made for a
+//! single transfer needs neither call. On a mapping that isn't bounced both
+//! do nothing.
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::error::KernelError;
+use crate::kprintln;
+use common::ids::TaskId;
+
+/// An address as the device sees it.
+pub type DeviceAddr = u64;
+
+/// Which way data moves in the transfers a mapping is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads: transmit buffers, audio periods, descriptor lists.
+    ToDevice,
+    /// The device writes: receive buffers.
+    FromDevice,
+    /// Either, as for buffers a driver uses both ways.
+    Bidirectional,
+}
+
+impl DmaDirection {
+    fn to_device(self) -> bool {
+        self != DmaDirection::FromDevice
+    }
+
+    fn from_device(self) -> bool {
+        self != DmaDirection::ToDevice
+    }
+}
+
+/// A piece of kernel memory handed to `DmaDomain::map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaSegment {
+    pub addr: usize,
+    pub len: usize,
+}
+
+impl DmaSegment {
+    /// The memory of `data`. The caller keeps it alive until it is unmapped.
+    pub fn of<T>(data: &mut [T]) -> Self {
+        DmaSegment { addr: data.as_mut_ptr() as usize, len: core::mem::size_of_val(data) }
+    }
+}
+
+/// How a domain turns kernel addresses into device addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+    /// The device sees memory at its kernel address. DMA is simulated until
+    /// buffers come from physical frames, so this is the only translation.
+    Identity,
+}
+
+impl Translation {
+    /// The device address for `len` bytes at kernel address `addr`. An IOMMU
+    /// would get an entry here.
+    fn map(self, addr: usize, _len: usize) -> DeviceAddr {
+        match self {
+            Translation::Identity => addr as DeviceAddr,
+        }
+    }
+
+    /// Removes what `map` set up. An IOMMU would drop the entry and flush its IOTLB.
+    fn unmap(self, _device: DeviceAddr, _len: usize) {}
+}
+
+/// Whether `segment` is one physically contiguous run. Paging has no
+/// translation to look at yet, so a single span of kernel memory counts as
+/// contiguous; with real frames this compares the frame of each page.
+fn physically_contiguous(_segment: DmaSegment) -> bool {
+    true
+}
+
+struct Mapping {
+    segments: Vec<DmaSegment>,
+    direction: DmaDirection,
+    /// The copy the device uses when it can't use `segments` in place.
+    bounce: Option<Vec<u8>>,
+}
+
+impl Mapping {
+    fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len).sum()
+    }
+
+    /// Copies the caller's memory into the bounce buffer, if there is one.
+    fn copy_to_bounce(&mut self) {
+        let Some(bounce) = self.bounce.as_mut() else {
+            return;
+        };
+        let mut at = 0;
+        for segment in &self.segments {
+            // SAFETY: The mapper keeps each segment valid until `unmap`, and the
+            // bounce buffer is as long as all of them.
+            unsafe { core::ptr::copy_nonoverlapping(segment.addr as *const u8, bounce.as_mut_ptr().add(at), segment.len); }
+            at += segment.len;
+        }
+    }
+
+    /// Copies the bounce buffer, if there is one, back to the caller's memory.
+    fn copy_from_bounce(&self) {
+        let Some(bounce) = self.bounce.as_ref() else {
+            return;
+        };
+        let mut at = 0;
+        for segment in &self.segments {
+            // SAFETY: As for `copy_to_bounce`.
+            unsafe { core::ptr::copy_nonoverlapping(bounce.as_ptr().add(at), segment.addr as *mut u8, segment.len); }
+            at += segment.len;
+        }
+    }
+}
+
+/// What one driver's device can reach, and everything mapped for it.
+pub struct DmaDomain {
+    /// The task the driver works for; the domain goes when that task is torn down.
+    owner: TaskId,
+    name: &'static str,
+    /// Device addresses must be below `1 << address_bits`.
+    address_bits: u32,
+    translation: Translation,
+    mappings: BTreeMap<DeviceAddr, Mapping>,
+}
+
+impl DmaDomain {
+    /// A domain for a device that addresses `address_bits` bits.
+    pub fn new(owner: TaskId, name: &'static str, address_bits: u32) -> Self {
+        DmaDomain { owner, name, address_bits, translation: Translation::Identity, mappings: BTreeMap::new() }
+    }
+
+    pub fn owner(&self) -> TaskId {
+        self.owner
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether the device can reach `len` bytes at `device`.
+    fn reaches(&self, device: DeviceAddr, len: usize) -> bool {
+        match device.checked_add(len as u64) {
+            Some(end) => self.address_bits >= 64 || end <= 1 << self.address_bits,
+            None => false,
+        }
+    }
+
+    /// Maps `segments`, in order, as one run of device memory for transfers in
+    /// `direction`, and returns its device address. A single contiguous
+    /// segment the device can reach is mapped in place; anything else is
+    /// bounced. `OutOfMemory` means the device can't reach a bounce buffer either.
+    pub fn map(&mut self, segments: &[DmaSegment], direction: DmaDirection) -> Result<DeviceAddr, KernelError> {
+        if segments.is_empty() || segments.iter().any(|segment| segment.len == 0) {
+            return Err(KernelError::InvalidArgument("empty DMA segment"));
+        }
+        let mut mapping = Mapping { segments: segments.to_vec(), direction, bounce: None };
+        let len = mapping.len();
+
+        if let [segment] = segments {
+            let device = self.translation.map(segment.addr, len);
+            if physically_contiguous(*segment) && self.reaches(device, len) {
+                if self.mappings.contains_key(&device) {
+                    // The identity translation gives memory one device address.
+                    return Err(KernelError::Busy);
+                }
+                self.mappings.insert(device, mapping);
+                return Ok(device);
+            }
+            self.translation.unmap(device, len);
+        }
+
+        let mut bounce = alloc::vec![0u8; len];
+        let device = self.translation.map(bounce.as_mut_ptr() as usize, len);
+        if !self.reaches(device, len) {
+            self.translation.unmap(device, len);
+            kprintln!("[kernel] dma: Domain '{}' of task {} can't reach a {}-byte bounce buffer.", self.name, self.owner, len);
+            return Err(KernelError::OutOfMemory);
+        }
+        mapping.bounce = Some(bounce);
+        if direction.to_device() {
+            mapping.copy_to_bounce();
+        }
+        self.mappings.insert(device, mapping);
+        Ok(device)
+    }
+
+    /// Unmaps what `map` returned `device` for, syncing it for the CPU first.
+    pub fn unmap(&mut self, device: DeviceAddr) -> Result<(), KernelError> {
+        let mapping = self.mappings.remove(&device).ok_or(KernelError::InvalidArgument("DMA address not mapped"))?;
+        if mapping.direction.from_device() {
+            mapping.copy_from_bounce();
+        }
+        self.translation.unmap(device, mapping.len());
+        Ok(())
+    }
+
+    /// Makes what the CPU wrote to the mapping at `device` visible to the
+    /// device. Call it after the last write and before starting the transfer.
+    pub fn sync_for_device(&mut self, device: DeviceAddr) -> Result<(), KernelError> {
+        let mapping = self.mappings.get_mut(&device).ok_or(KernelError::InvalidArgument("DMA address not mapped"))?;
+        if mapping.direction.to_device() {
+            mapping.copy_to_bounce();
+        }
+        Ok(())
+    }
+
+    /// Makes what the device wrote to the mapping at `device` visible to the
+    /// CPU. Call it after the transfer and before the first read.
+    pub fn sync_for_cpu(&mut self, device: DeviceAddr) -> Result<(), KernelError> {
+        let mapping = self.mappings.get(&device).ok_or(KernelError::InvalidArgument("DMA address not mapped"))?;
+        if mapping.direction.from_device() {
+            mapping.copy_from_bounce();
+        }
+        Ok(())
+    }
+
+    /// Whether the mapping at `device` is bounced, or `None` if nothing is mapped there.
+    pub fn is_bounced(&self, device: DeviceAddr) -> Option<bool> {
+        self.mappings.get(&device).map(|mapping| mapping.bounce.is_some())
+    }
+
+    pub fn mapping_count(&self) -> usize {
+        self.mappings.len()
+    }
+
+    /// Unmaps everything still mapped, without syncing: the driver is gone.
+    /// Each mapping is a leak and is logged. Returns how many there were.
+    pub fn teardown(mut self) -> usize {
+        let leaked = core::mem::take(&mut self.mappings);
+        for (device, mapping) in &leaked {
+            kprintln!(
+                "[kernel] dma: Domain '{}' of task {} leaked a {}-byte mapping at {:#x}{}.",
+                self.name, self.owner, mapping.len(), device, if mapping.bounce.is_some() { " (bounced)" } else { "" }
+            );
+            self.translation.unmap(*device, mapping.len());
+        }
+        leaked.len()
+    }
+}
@@ -2,8 +2,19 @@
 
 #![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
 
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-use crate::kprintln;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use crate::{kerrorln, kprintln};
+use crate::drivers::{ac97, ps2_keyboard, ps2_mouse};
+use crate::memory::file_map::{self, FaultResolution};
+use crate::task;
+use crate::task::coredump::{self, FaultContext};
+use crate::task::faults::{self, FaultKind};
+use crate::task::tcb::TaskId;
+use common::coredump::{CORE_REASON_ALIGNMENT_CHECK, CORE_REASON_GENERAL_PROTECTION, CORE_REASON_INVALID_OPCODE, CORE_REASON_PAGE_FAULT};
+use super::irq;
 
 /// Static mutable Interrupt Descriptor Table.
 /// It will be initialized once during boot.
@@ -20,6 +31,18 @@ pub fn init() {
         // Set handlers for some common exceptions
         IDT.breakpoint_handler.set_handler_fn(breakpoint_handler);
         IDT.double_fault_handler.set_handler_fn(double_fault_handler);
+        IDT.page_fault.set_handler_fn(page_fault_handler);
+        IDT.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        IDT.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        IDT.alignment_check.set_handler_fn(alignment_check_handler);
+        IDT.non_maskable_interrupt.set_handler_fn(nmi_handler);
+        IDT.machine_check.set_handler_fn(machine_check_handler);
+
+        // Hardware interrupts decoded in the kernel itself
+        IDT[(irq::IRQ_VECTOR_BASE + ps2_keyboard::PS2_KEYBOARD_IRQ) as usize].set_handler_fn(ps2_keyboard_handler);
+        IDT[(irq::IRQ_VECTOR_BASE + ps2_mouse::PS2_MOUSE_IRQ) as usize].set_handler_fn(ps2_mouse_handler);
+        #[cfg(feature = "smp")]
+        IDT[super::smp::RESCHEDULE_VECTOR as usize].set_handler_fn(reschedule_handler);
 
         // Load the IDT into the CPU
         IDT.load();
@@ -43,4 +66,188 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
     loop {}
 }
 
+/// Handler for IRQ 1. Like the mouse, the keyboard is decoded in the kernel.
+extern "x86-interrupt" fn ps2_keyboard_handler(_stack_frame: InterruptStackFrame) {
+    ps2_keyboard::handle_interrupt();
+    irq::acknowledge_irq(ps2_keyboard::PS2_KEYBOARD_IRQ);
+}
+
+/// Handler for IRQ 12. The mouse is decoded in the kernel, so unlike other
+/// IRQs nothing is forwarded to a V-Node channel.
+extern "x86-interrupt" fn ps2_mouse_handler(_stack_frame: InterruptStackFrame) {
+    ps2_mouse::handle_interrupt();
+    irq::acknowledge_irq(ps2_mouse::PS2_MOUSE_IRQ);
+}
+
+/// Routes IRQ `irq` to the AC'97 handler. The line comes from PCI
+/// configuration, so it isn't known when `init` runs.
+pub fn set_ac97_handler(irq: u8) {
+    // SAFETY: Setting one gate of the loaded IDT; the CPU reads it when the vector is raised.
+    unsafe {
+        IDT[(irq::IRQ_VECTOR_BASE + irq) as usize].set_handler_fn(ac97_handler);
+    }
+}
+
+/// Handler for the AC'97 controller's IRQ. The kernel acknowledges the
+/// controller and counts played periods, then forwards the IRQ to the
+/// audio output's owner.
+extern "x86-interrupt" fn ac97_handler(_stack_frame: InterruptStackFrame) {
+    ac97::handle_interrupt();
+}
+
+/// Handler for the reschedule IPI another CPU sends when it queues a task
+/// for this one while it idles.
+#[cfg(feature = "smp")]
+extern "x86-interrupt" fn reschedule_handler(_stack_frame: InterruptStackFrame) {
+    super::smp::end_of_interrupt();
+    task::schedule();
+}
+
+/// Handler for the page fault exception.
+/// Faults inside a file mapping are resolved here: reads page the data in,
+/// or wait for the storage service to fill it, and writes are diagnosed and
+/// kill the task. Anything else kills the task too.
+/// Both kinds are counted against the task (see `task::faults`).
+extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    let addr = Cr2::read().as_u64() as usize;
+    let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+    let task_id = task::get_current_task().id;
+
+    match file_map::handle_fault(task_id, addr, write) {
+        FaultResolution::PagedIn => {
+            faults::record(task_id, FaultKind::PageResolved);
+            return;
+        }
+        FaultResolution::Waiting => {
+            // Blocked until the storage service fills the page; the access is retried then.
+            faults::record(task_id, FaultKind::PageResolved);
+            task::schedule();
+            return;
+        }
+        FaultResolution::OwnerGone => {
+            kprintln!("[kernel] EXCEPTION: PAGE FAULT: task {} touched {:#x}, a page its storage service exited before filling.", task_id, addr);
+        }
+        FaultResolution::WriteToReadOnly => {
+            kprintln!("[kernel] EXCEPTION: PAGE FAULT: task {} wrote to read-only mapping at {:#x}.", task_id, addr);
+        }
+        FaultResolution::NotMapped => {
+            kprintln!("[kernel] EXCEPTION: PAGE FAULT at {:#x} (task {})\nError Code: {:?}\n{:#?}", addr, task_id, error_code, stack_frame);
+        }
+    }
+    kill_faulting_task(task_id, FaultKind::PageFatal, CORE_REASON_PAGE_FAULT, fault_context(&stack_frame, addr as u64, error_code.bits()));
+}
+
+/// Handler for the general protection fault exception. The error code is
+/// the segment selector involved, or 0.
+extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    let task_id = task::get_current_task().id;
+    kprintln!("[kernel] EXCEPTION: GENERAL PROTECTION FAULT at {:#x} (task {})\nError Code: {:#x}\n{:#?}", stack_frame.instruction_pointer.as_u64(), task_id, error_code, stack_frame);
+    kill_faulting_task(task_id, FaultKind::GeneralProtection, CORE_REASON_GENERAL_PROTECTION, fault_context(&stack_frame, 0, error_code));
+}
 
+/// Handler for the invalid opcode exception.
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let task_id = task::get_current_task().id;
+    kprintln!("[kernel] EXCEPTION: INVALID OPCODE at {:#x} (task {})\n{:#?}", stack_frame.instruction_pointer.as_u64(), task_id, stack_frame);
+    kill_faulting_task(task_id, FaultKind::InvalidOpcode, CORE_REASON_INVALID_OPCODE, fault_context(&stack_frame, 0, 0));
+}
+
+/// Handler for the alignment check exception. Only raised in user mode with
+/// CR0.AM and RFLAGS.AC set.
+extern "x86-interrupt" fn alignment_check_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    let task_id = task::get_current_task().id;
+    kprintln!("[kernel] EXCEPTION: ALIGNMENT CHECK at {:#x} (task {})\n{:#?}", stack_frame.instruction_pointer.as_u64(), task_id, stack_frame);
+    kill_faulting_task(task_id, FaultKind::AlignmentCheck, CORE_REASON_ALIGNMENT_CHECK, fault_context(&stack_frame, 0, error_code));
+}
+
+/// The part of the fault's state a core dump takes from the exception frame.
+fn fault_context(stack_frame: &InterruptStackFrame, addr: u64, error_code: u64) -> FaultContext {
+    FaultContext {
+        rip: stack_frame.instruction_pointer.as_u64(),
+        rsp: stack_frame.stack_pointer.as_u64(),
+        rflags: stack_frame.cpu_flags,
+        cs: stack_frame.code_segment,
+        ss: stack_frame.stack_segment,
+        addr,
+        error_code,
+    }
+}
+
+/// Counts a fault the task can't go on from, logs its fault counters, dumps
+/// its core and kills it. `reason` is the dump's `CORE_REASON_*`.
+fn kill_faulting_task(task_id: TaskId, kind: FaultKind, reason: u32, context: FaultContext) -> ! {
+    faults::record(task_id, kind);
+    if let Some(stats) = task::task_stats(task_id) {
+        kprintln!(
+            "[kernel] faults: Killing task {} ({}): page faults resolved={} fatal={}, general protection={}, invalid opcode={}, alignment check={}.",
+            task_id, stats.name(), stats.page_faults_resolved, stats.page_faults_fatal, stats.general_protection_faults, stats.invalid_opcodes, stats.alignment_checks
+        );
+    }
+    // Before the kill, which releases the image the dump copies from.
+    coredump::capture_fault(task_id, reason, context);
+    task::kill_task(task_id);
+    task::schedule();
+    loop {}
+}
+
+/// NMI status and control port. Bit 7 reports a PCI SERR# or memory parity
+/// error, bit 6 an I/O channel check.
+const NMI_STATUS_PORT: u16 = 0x61;
+const NMI_STATUS_SERR: u8 = 1 << 7;
+const NMI_STATUS_IOCHK: u8 = 1 << 6;
+
+/// Handler for the non-maskable interrupt. Counted and logged with the
+/// reason the chipset gives; nothing else is done about it. The log line
+/// takes the console locks, so an NMI landing while this CPU holds one
+/// hangs it, as a panic on the same CPU would.
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    let count = faults::count_nmi();
+    // SAFETY: Reading the NMI status port has no side effects.
+    let status: u8 = unsafe { Port::new(NMI_STATUS_PORT).read() };
+    let reason = match (status & NMI_STATUS_SERR != 0, status & NMI_STATUS_IOCHK != 0) {
+        (true, true) => "SERR# and I/O channel check",
+        (true, false) => "SERR# or memory parity error",
+        (false, true) => "I/O channel check",
+        (false, false) => "no chipset reason",
+    };
+    kerrorln!("[kernel] NMI #{} at {:#x}: port 0x61={:#04x} ({}).", count, stack_frame.instruction_pointer.as_u64(), status, reason);
+}
+
+/// Machine check MSRs: global capabilities and status, and bank `i`'s status
+/// at `IA32_MC0_STATUS + 4 * i`, with its address and misc registers after it.
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17A;
+const IA32_MC0_STATUS: u32 = 0x401;
+const MCG_CAP_COUNT_MASK: u64 = 0xFF;
+const MCI_STATUS_VAL: u64 = 1 << 63;
+const MCI_STATUS_UC: u64 = 1 << 61;
+const MCI_STATUS_MISCV: u64 = 1 << 59;
+const MCI_STATUS_ADDRV: u64 = 1 << 58;
+
+/// Handler for the machine check exception. There is no recovery: the
+/// global status and every bank with a valid error are logged, then the
+/// kernel panics, which leaves the log in pstore for the next boot.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let count = faults::count_machine_check();
+    // The interrupted code may hold the console locks; take them as the panic handler would.
+    crate::console::panic_takeover();
+    // SAFETY: The CPU raised a machine check, so it has the machine check MSRs.
+    let (cap, status) = unsafe { (Msr::new(IA32_MCG_CAP).read(), Msr::new(IA32_MCG_STATUS).read()) };
+    kerrorln!("[kernel] MACHINE CHECK #{} at {:#x}: MCG_STATUS={:#x} MCG_CAP={:#x}", count, stack_frame.instruction_pointer.as_u64(), status, cap);
+    for bank in 0..(cap & MCG_CAP_COUNT_MASK) as u32 {
+        let base = IA32_MC0_STATUS + 4 * bank;
+        // SAFETY: Banks below the MCG_CAP count exist.
+        let bank_status = unsafe { Msr::new(base).read() };
+        if bank_status & MCI_STATUS_VAL == 0 {
+            continue;
+        }
+        // SAFETY: ADDR and MISC are read only when the bank says they are valid.
+        let addr = if bank_status & MCI_STATUS_ADDRV != 0 { unsafe { Msr::new(base + 1).read() } } else { 0 };
+        let misc = if bank_status & MCI_STATUS_MISCV != 0 { unsafe { Msr::new(base + 2).read() } } else { 0 };
+        kerrorln!(
+            "[kernel] MACHINE CHECK bank {}: status={:#018x}{} addr={:#x} misc={:#x}",
+            bank, bank_status, if bank_status & MCI_STATUS_UC != 0 { " (uncorrected)" } else { "" }, addr, misc
+        );
+    }
+    panic!("machine check exception (MCG_STATUS={:#x})", status);
+}
@@ -4,20 +4,61 @@
 
 use spin::Mutex;
 use alloc::collections::BTreeMap;
-use crate::{kprintln, ipc};
+use crate::{kprintln, ipc, task, timer};
+use crate::error::KernelError;
+use common::abi::IRQ_MSG_LEN;
+use common::ids::TaskId;
+
+/// Interrupt vector of IRQ 0. The legacy PICs are remapped here so IRQs don't
+/// collide with the CPU exception vectors (0-31).
+pub const IRQ_VECTOR_BASE: u8 = 32;
+
+/// An IRQ routing entry: the channel to notify and the task that registered it.
+#[derive(Debug, Clone, Copy)]
+struct IrqRegistration {
+    channel_id: ipc::ChannelId,
+    owner: TaskId,
+}
 
 /// Maps an IRQ number to an IPC channel ID, which the kernel will use
 /// to notify the owning V-Node about an interrupt.
-static IRQ_TO_CHANNEL_MAP: Mutex<BTreeMap<u8, ipc::ChannelId>> = Mutex::new(BTreeMap::new());
+static IRQ_TO_CHANNEL_MAP: Mutex<BTreeMap<u8, IrqRegistration>> = Mutex::new(BTreeMap::new());
 
 /// Register an interrupt handler.
 /// In this microkernel model, "registering a handler" means mapping an IRQ
 /// to an IPC channel. When an interrupt occurs, the kernel will send an
 /// IPC message to the specified channel.
-pub fn register_irq_handler(irq_number: u8, channel_id: ipc::ChannelId) {
+///
+/// Fails with `KernelError::Busy` if the IRQ is already registered by another
+/// live task, unless `force` is set.
+pub fn register_irq_handler(irq_number: u8, channel_id: ipc::ChannelId, owner: TaskId, force: bool) -> Result<(), KernelError> {
+    let mut map = IRQ_TO_CHANNEL_MAP.lock();
+    if let Some(existing) = map.get(&irq_number) {
+        if existing.owner != owner && task::task_exists(existing.owner) && !force {
+            kprintln!("[kernel] irq: IRQ {} already registered by task {}, refusing request from task {}.", irq_number, existing.owner, owner);
+            return Err(KernelError::Busy);
+        }
+    }
+    map.insert(irq_number, IrqRegistration { channel_id, owner });
+    kprintln!("[kernel] irq: Registered IRQ {} to IPC channel {} for task {}.", irq_number, channel_id, owner);
+    Ok(())
+}
+
+/// Removes every IRQ registration owned by `task_id`. Returns the number removed.
+pub fn release_task_irqs(task_id: TaskId) -> usize {
     let mut map = IRQ_TO_CHANNEL_MAP.lock();
-    map.insert(irq_number, channel_id);
-    kprintln!("[kernel] irq: Registered IRQ {} to IPC channel {}.", irq_number, channel_id);
+    let before = map.len();
+    map.retain(|_, reg| reg.owner != task_id);
+    let removed = before - map.len();
+    if removed > 0 {
+        kprintln!("[kernel] irq: Unregistered {} IRQs owned by task {}.", removed, task_id);
+    }
+    removed
+}
+
+/// Returns the number of IRQ registrations currently owned by `task_id`.
+pub fn count_task_irqs(task_id: TaskId) -> usize {
+    IRQ_TO_CHANNEL_MAP.lock().values().filter(|reg| reg.owner == task_id).count()
 }
 
 /// Acknowledges a specific IRQ.
@@ -31,18 +72,29 @@ pub fn acknowledge_irq(irq_number: u8) {
 /// This function is called by the actual hardware interrupt handler.
 /// It dispatches an IPC message to the registered V-Node.
 pub fn handle_irq(irq_number: u8) {
+    // Stamp before anything else, so lock contention below doesn't count as driver latency.
+    let captured_at = timer::nanos();
     let channel_id = {
         let map = IRQ_TO_CHANNEL_MAP.lock();
-        map.get(&irq_number).cloned()
+        map.get(&irq_number).map(|reg| reg.channel_id)
     };
 
     if let Some(id) = channel_id {
         kprintln!("[kernel] irq: IRQ {} received, sending IPC to channel {}.", irq_number, id);
-        // Send a dummy message to the V-Node indicating the IRQ occurred.
+        // Notify the V-Node with the IRQ number and capture time (see `common::abi::parse_irq_message`).
         // The V-Node can then poll its device.
-        let irq_msg_data = alloc::vec![irq_number]; // Simple payload: just the IRQ number
-        // For now, we assume kernel itself is sender (task_id 0)
-        let _ = ipc::kernel_send(id, 0, &irq_msg_data);
+        let mut irq_msg_data = [0u8; IRQ_MSG_LEN];
+        irq_msg_data[0] = irq_number;
+        irq_msg_data[1..].copy_from_slice(&captured_at.to_le_bytes());
+        // Deterministic scheduling decides when the notification arrives.
+        #[cfg(feature = "det-sched")]
+        let deferred = task::detsched::defer_irq(id, &irq_msg_data);
+        #[cfg(not(feature = "det-sched"))]
+        let deferred = false;
+        // The kernel itself is the sender.
+        if !deferred {
+            let _ = ipc::kernel_send(id, TaskId::KERNEL, &irq_msg_data);
+        }
     } else {
         kprintln!("[kernel] irq: Unhandled IRQ {}.", irq_number);
     }
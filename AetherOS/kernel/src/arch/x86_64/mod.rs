@@ -6,6 +6,11 @@ pub mod idt;
 pub mod paging;
 pub mod dma;
 pub mod irq;
+pub mod smp; // CPU ids and reschedule IPIs; AP bring-up with the `smp` feature
+#[cfg(feature = "smp")]
+pub mod acpi;
+#[cfg(feature = "smp")]
+pub mod ap_boot;
 
 pub fn init() {
     gdt::init();
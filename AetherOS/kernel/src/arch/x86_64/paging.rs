@@ -4,6 +4,11 @@
 
 use crate::kprintln;
 
+// Page table entry flags (x86_64 PTE bits)
+pub const PAGE_PRESENT: u64 = 1 << 0;
+pub const PAGE_WRITABLE: u64 = 1 << 1;
+pub const PAGE_USER: u64 = 1 << 2;
+
 /// Initializes the paging system.
 /// This includes setting up the initial page tables for the kernel's address space
 /// (e.g., identity mapping for lower memory, higher-half mapping for kernel code/data).
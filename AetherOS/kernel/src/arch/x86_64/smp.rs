@@ -0,0 +1,137 @@
+// kernel/src/arch/x86_64/smp.rs
+
+//! Multi-processor support: which CPU we are on, and kicking another CPU into
+//! its scheduler.
+//!
+//! Without the `smp` feature there is exactly one CPU: `current_cpu` is 0 and
+//! reschedule IPIs are never needed, so the default build runs as it always
+//! has. With it, `init` reads the MADT to learn the CPUs' local APIC IDs and
+//! prepares the application processors (see `ap_boot`).
+
+#![allow(dead_code)]
+
+use crate::task::cpu::{self, CpuId};
+
+/// IDT vector of the reschedule IPI.
+pub const RESCHEDULE_VECTOR: u8 = 0xF0;
+
+#[cfg(not(feature = "smp"))]
+pub fn current_cpu() -> CpuId {
+    0
+}
+
+#[cfg(feature = "smp")]
+pub use self::multi::{current_cpu, end_of_interrupt, init, send_icr};
+
+/// Asks `cpu` to run its scheduler, because a task was queued for it while it
+/// idles. Does nothing for this CPU or one that isn't online, which while
+/// single-core is every other CPU.
+pub fn send_reschedule_ipi(cpu: CpuId) {
+    if cpu == cpu::current_cpu() || !cpu::is_online(cpu) {
+        return;
+    }
+    #[cfg(feature = "smp")]
+    multi::send_ipi(cpu, RESCHEDULE_VECTOR);
+}
+
+#[cfg(feature = "smp")]
+mod multi {
+    use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+    use crate::config::MAX_CPUS;
+    use crate::kprintln;
+    use crate::task::cpu::CpuId;
+    use super::super::{acpi, ap_boot};
+
+    /// Local APIC ID of each CPU id; CPU 0 is the bootstrap processor.
+    static APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+    /// CPUs found in the MADT (at most `MAX_CPUS`). Before `init` only the BSP is known.
+    static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+    /// Virtual address of the local APIC registers; 0 until `init` maps them.
+    static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+    const LAPIC_EOI: u64 = 0xB0;
+    const LAPIC_ICR_LOW: u64 = 0x300;
+    const LAPIC_ICR_HIGH: u64 = 0x310;
+
+    /// The initial local APIC ID of the executing CPU, from CPUID leaf 1.
+    fn local_apic_id() -> u32 {
+        // SAFETY: CPUID is available on every x86_64 CPU.
+        unsafe { core::arch::x86_64::__cpuid(1).ebx >> 24 }
+    }
+
+    pub fn current_cpu() -> CpuId {
+        let apic_id = local_apic_id();
+        let count = CPU_COUNT.load(Ordering::Acquire);
+        // Before `init` the table is empty and only the BSP runs.
+        (0..count).find(|cpu| APIC_IDS[*cpu].load(Ordering::Relaxed) == apic_id).unwrap_or(0)
+    }
+
+    pub fn apic_id_of(cpu: CpuId) -> u32 {
+        APIC_IDS[cpu].load(Ordering::Relaxed)
+    }
+
+    fn lapic_write(register: u64, value: u32) {
+        let base = LAPIC_BASE.load(Ordering::Acquire);
+        if base == 0 {
+            return;
+        }
+        // SAFETY: `init` set the base to the mapping of the local APIC's MMIO page.
+        unsafe { core::ptr::write_volatile((base + register) as *mut u32, value) };
+    }
+
+    /// Sends a fixed-delivery interrupt to one CPU.
+    pub fn send_ipi(cpu: CpuId, vector: u8) {
+        send_icr(apic_id_of(cpu), vector as u32);
+    }
+
+    /// Writes the interrupt command register: destination first, since writing
+    /// the low half sends.
+    pub fn send_icr(apic_id: u32, command: u32) {
+        lapic_write(LAPIC_ICR_HIGH, apic_id << 24);
+        lapic_write(LAPIC_ICR_LOW, command);
+    }
+
+    /// Signals the end of a local APIC interrupt such as an IPI.
+    pub fn end_of_interrupt() {
+        lapic_write(LAPIC_EOI, 0);
+    }
+
+    /// Finds the CPUs through the ACPI MADT. `rsdp` and `physical_memory_offset`
+    /// come from the bootloader; the latter requires it to map all physical
+    /// memory. Without either, the kernel stays on the BSP.
+    pub fn init(rsdp: Option<u64>, physical_memory_offset: Option<u64>) {
+        let (rsdp, offset) = match (rsdp, physical_memory_offset) {
+            (Some(rsdp), Some(offset)) => (rsdp, offset),
+            _ => {
+                kprintln!("[kernel] smp: No RSDP or physical memory mapping from the bootloader; running on one CPU.");
+                return;
+            }
+        };
+        let madt = match acpi::parse_madt(rsdp, offset) {
+            Ok(madt) => madt,
+            Err(e) => {
+                kprintln!("[kernel] smp: Can't read the MADT ({}); running on one CPU.", e);
+                return;
+            }
+        };
+        LAPIC_BASE.store(offset + madt.lapic_base, Ordering::Release);
+
+        // The BSP is CPU 0; the others follow in MADT order.
+        let bsp = local_apic_id();
+        let mut count = 1;
+        APIC_IDS[0].store(bsp, Ordering::Relaxed);
+        for apic_id in madt.apic_ids.iter().copied().filter(|id| *id != bsp) {
+            if count == MAX_CPUS {
+                kprintln!("[kernel] smp: More than {} CPUs; ignoring the rest.", MAX_CPUS);
+                break;
+            }
+            APIC_IDS[count].store(apic_id, Ordering::Relaxed);
+            count += 1;
+        }
+        CPU_COUNT.store(count, Ordering::Release);
+        kprintln!("[kernel] smp: {} CPUs found (BSP APIC ID {}).", count, bsp);
+
+        ap_boot::start_aps(count, offset);
+    }
+}
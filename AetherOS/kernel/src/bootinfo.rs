@@ -0,0 +1,135 @@
+// kernel/src/bootinfo.rs
+
+//! The boot's command line (see `common::cmdline`) and the options the
+//! kernel takes from it.
+//!
+//! The bootloader's `BootInfo` has no command-line field, so the line travels
+//! in the initrd, as `BOOT_CMDLINE_PATH`, which `axpkg initrd --cmdline`
+//! writes. Changing it means rebuilding the image, not the kernel. It is read
+//! as soon as the heap is up, before any driver or V-Node starts:
+//!
+//! *   `loglevel=debug|info|warn|error`: `SYS_LOG` messages below this
+//!     severity aren't printed on the console. They are still forwarded to
+//!     logd, which also takes the level as its floor for this boot. Default
+//!     `debug`, which prints everything.
+//! *   `fbcon=off`: the kernel stops drawing its console on the framebuffer;
+//!     serial output is unchanged.
+//! *   `driver.off=<name>`, once per driver: skips an optional driver, one of
+//!     `DRIVERS`.
+//! *   `memtest` or `memtest=<MiB>`: tests memory before the drivers start
+//!     (see `memory::memtest`).
+//!
+//! Everything else, known or not, is kept for `SYS_BOOT_ARGS`, so services
+//! read their own options (`init.target=`) without the kernel knowing them.
+//! A line that doesn't parse is ignored as a whole, with a log line that says
+//! why, and the boot goes on with the defaults.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use common::abi::{LOG_SEVERITY_DEBUG, LOG_SEVERITY_ERROR, LOG_SEVERITY_INFO, LOG_SEVERITY_WARN};
+use common::cmdline::{BootArgs, BOOT_CMDLINE_PATH};
+use common::initrd::Initrd;
+use crate::{kerrorln, kprintln};
+
+/// Drivers `driver.off=` can skip. The rest are needed to boot at all.
+pub const DRIVERS: [&str; 3] = ["ac97", "ps2_keyboard", "ps2_mouse"];
+
+/// The line as read, for `SYS_BOOT_ARGS`, and its parsed form.
+static CMDLINE: Mutex<Option<(String, BootArgs)>> = Mutex::new(None);
+
+/// Lowest `SYS_LOG` severity printed on the console.
+static LOG_FLOOR: AtomicU64 = AtomicU64::new(LOG_SEVERITY_DEBUG);
+
+/// The `loglevel=` names and their `LOG_SEVERITY_*` values.
+fn severity_from_name(name: &str) -> Option<u64> {
+    match name {
+        "debug" => Some(LOG_SEVERITY_DEBUG),
+        "info" => Some(LOG_SEVERITY_INFO),
+        "warn" => Some(LOG_SEVERITY_WARN),
+        "error" => Some(LOG_SEVERITY_ERROR),
+        _ => None,
+    }
+}
+
+/// Reads and parses the command line from the initrd. Needs the heap.
+pub fn init(ramdisk: Option<&'static [u8]>) {
+    let Some(line) = ramdisk
+        .and_then(|image| Initrd::parse(image).ok())
+        .and_then(|initrd| initrd.get(BOOT_CMDLINE_PATH))
+    else {
+        kprintln!("[kernel] bootinfo: No command line.");
+        return;
+    };
+    let line = match core::str::from_utf8(line) {
+        Ok(line) => line.trim_end(),
+        Err(_) => {
+            kerrorln!("[kernel] bootinfo: Ignoring the command line: not UTF-8.");
+            return;
+        }
+    };
+    let args = match BootArgs::parse(line) {
+        Ok(args) => args,
+        Err(e) => {
+            kerrorln!("[kernel] bootinfo: Ignoring the command line: {}.", e);
+            return;
+        }
+    };
+    kprintln!("[kernel] bootinfo: Command line: {}", line);
+
+    if let Some(level) = args.value("loglevel") {
+        match severity_from_name(level) {
+            Some(floor) => LOG_FLOOR.store(floor, Ordering::Relaxed),
+            None => kerrorln!("[kernel] bootinfo: Unknown loglevel '{}'; printing every message.", level),
+        }
+    }
+    for driver in args.values("driver.off") {
+        if !DRIVERS.contains(&driver) {
+            kerrorln!("[kernel] bootinfo: driver.off={} names no optional driver ({}).", driver, DRIVERS.join(", "));
+        }
+    }
+    *CMDLINE.lock() = Some((String::from(line), args));
+}
+
+fn with_args<R>(f: impl FnOnce(&BootArgs) -> R) -> Option<R> {
+    CMDLINE.lock().as_ref().map(|(_, args)| f(args))
+}
+
+/// Lowest `SYS_LOG` severity to print, from `loglevel=`.
+pub fn log_floor() -> u64 {
+    LOG_FLOOR.load(Ordering::Relaxed)
+}
+
+/// Whether the kernel draws its console on the framebuffer.
+pub fn fbcon() -> bool {
+    with_args(|args| args.value("fbcon") != Some("off")).unwrap_or(true)
+}
+
+/// Whether `driver` should be started.
+pub fn driver_enabled(driver: &str) -> bool {
+    with_args(|args| !args.values("driver.off").any(|off| off == driver)).unwrap_or(true)
+}
+
+/// `Some(None)` to test all memory, `Some(Some(mib))` to test that much,
+/// `None` for no test. A size that isn't a number tests everything.
+pub fn memtest() -> Option<Option<u64>> {
+    with_args(|args| {
+        if let Some(mib) = args.value("memtest") {
+            return Some(mib.parse().ok());
+        }
+        args.flag("memtest").then_some(None)
+    }).flatten()
+}
+
+/// Copies the command line into `out` and returns its full length.
+pub fn copy_line(out: &mut [u8]) -> usize {
+    match CMDLINE.lock().as_ref() {
+        Some((line, _)) => {
+            let n = line.len().min(out.len());
+            out[..n].copy_from_slice(&line.as_bytes()[..n]);
+            line.len()
+        }
+        None => 0,
+    }
+}
@@ -10,6 +10,9 @@ use crate::kprintln;
 pub enum Capability {
     /// Allows writing messages to the kernel log.
     LogWrite,
+    /// Allows reading the kernel log, this boot's and the previous one's (`SYS_KLOG_READ`),
+    /// and receiving every later `SYS_LOG` message (`SYS_LOG_FORWARD`).
+    LogRead,
     /// Allows reading the kernel's monotonic timer.
     TimeRead,
     /// Allows basic network operations (e.g., registering IRQs, allocating DMA for networking).
@@ -27,6 +30,26 @@ pub enum Capability {
     IrqAck(u8),
     /// Allows a V-Node to create and manage IPC channels.
     IpcManage,
+    /// Allows a V-Node to take ownership of the boot framebuffer (display compositor).
+    FramebufferAccess,
+    /// Allows drawing the boot status line on the kernel console (`SYS_BOOT_STATUS`).
+    BootStatus,
+    /// Allows binding identities to tasks (init-service and the session service).
+    IdentityAdmin,
+    /// Allows reading and writing another task's memory and registers and
+    /// suspending it (`SYS_DEBUG_*`). Reserved for a debugger V-Node and the
+    /// shell in debug builds.
+    Debug,
+    /// Allows owning the sound card's PCM output (`SYS_AUDIO_*`). Granted to
+    /// the audio mixer; everything else plays through it.
+    AudioOutput,
+    /// Allows powering off and resetting the machine (`SYS_SYSTEM_POWER`).
+    /// Granted to init-service, which stops the services first.
+    PowerControl,
+    /// Allows setting the fault storm threshold and taking the storms tasks
+    /// raised (`SYS_FAULT_STORMS`), and taking core dumps (`SYS_CORE_DUMP_TAKE`).
+    /// Granted to init-service, which publishes them.
+    TaskMonitor,
     // Add more capabilities as the system grows
 }
 
@@ -39,6 +62,8 @@ impl Capability {
         // associated with the task/V-Node making the syscall.
         match self {
             Capability::LogWrite => true, // Logging is generally permitted for V-Nodes for debugging
+            Capability::LogRead => true, // The log holds nothing V-Nodes couldn't print themselves
+            Capability::BootStatus => true, // Only drawn while the kernel console is up
             Capability::TimeRead => true, // Reading time is generally permitted
             Capability::NetworkAccess => true, // Temporarily granted for network V-Nodes development
             Capability::IrqRegister(_) => true, // Temporarily granted for driver V-Nodes
@@ -46,6 +71,12 @@ impl Capability {
             Capability::DmaAccess => true, // Temporarily granted for driver V-Nodes
             Capability::IrqAck(_) => true, // Temporarily granted for driver V-Nodes
             Capability::IpcManage => true, // Temporarily granted for general IPC usage
+            Capability::FramebufferAccess => false, // Only the display compositor is granted this
+            Capability::IdentityAdmin => false, // Only init-service and the session service are granted this
+            Capability::Debug => false, // Never implied; it bypasses every isolation boundary
+            Capability::AudioOutput => false, // Only the audio mixer is granted this
+            Capability::PowerControl => false, // Only init-service is granted this
+            Capability::TaskMonitor => false, // Only init-service is granted this
             Capability::StorageAccess => false, // Deny by default until VFS is fully robust
             // _ => {
             //     kprintln!("[kernel] caps: Capability {:?} not explicitly granted.", self);
@@ -54,3 +85,32 @@ impl Capability {
         }
     }
 }
+
+/// Package manifests and the registry's installed-packages index carry
+/// capabilities as `common::capability::Capability`; init hands the approved
+/// ones to the loader through this. A scope narrows a grant for the services
+/// that check it and doesn't reach the kernel.
+impl From<common::capability::Capability> for Capability {
+    fn from(capability: common::capability::Capability) -> Self {
+        use common::capability::Capability as Declared;
+        match capability {
+            Declared::LogWrite => Capability::LogWrite,
+            Declared::LogRead => Capability::LogRead,
+            Declared::TimeRead => Capability::TimeRead,
+            Declared::NetworkAccess => Capability::NetworkAccess,
+            Declared::StorageAccess => Capability::StorageAccess,
+            Declared::IrqRegister(irq) => Capability::IrqRegister(irq),
+            Declared::DmaAlloc => Capability::DmaAlloc,
+            Declared::DmaAccess => Capability::DmaAccess,
+            Declared::IrqAck(irq) => Capability::IrqAck(irq),
+            Declared::IpcManage => Capability::IpcManage,
+            Declared::FramebufferAccess => Capability::FramebufferAccess,
+            Declared::BootStatus => Capability::BootStatus,
+            Declared::IdentityAdmin => Capability::IdentityAdmin,
+            Declared::Debug => Capability::Debug,
+            Declared::AudioOutput => Capability::AudioOutput,
+            Declared::PowerControl => Capability::PowerControl,
+            Declared::TaskMonitor => Capability::TaskMonitor,
+        }
+    }
+}
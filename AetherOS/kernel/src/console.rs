@@ -5,9 +5,9 @@
 use core::fmt::{self, Write};
 use spin::Mutex;
 
-// We will re-route console output to the serial driver for now.
-// The Uart struct and its methods are no longer directly used for output here,
-// but kept as a placeholder if a direct framebuffer/VGA console is added later.
+// Console output is routed to the serial driver and mirrored to the framebuffer
+// text console (see drivers::framebuffer) while the kernel still owns the display.
+// The Uart struct is kept as a placeholder for direct UART access.
 struct Uart {
     __private: (),
 }
@@ -34,36 +34,59 @@ impl fmt::Write for Uart {
 // Global static for the UART console (still needed for fmt::Write impl, but mostly dummy)
 static CONSOLE: Mutex<Uart> = Mutex::new(Uart::new());
 
-// Public interface for the kernel console, which now just calls through to serial
+// Public interface for the kernel console
 pub fn print_str(s: &str) {
-    crate::drivers::serial::_print(format_args!("{}", s));
+    _print(format_args!("{}", s));
 }
 
 pub fn print_u64(n: u64) {
-    crate::drivers::serial::_print(format_args!("{}", n));
+    _print(format_args!("{}", n));
 }
 
 pub fn print_hex(n: u64) {
-    crate::drivers::serial::_print(format_args!("{:x}", n));
+    _print(format_args!("{:x}", n));
 }
 
 // Macro for kernel printing, similar to `println!`
 #[macro_export]
-macro_rules! kprint! {
-    ($($arg:tt)*) => ($crate::drivers::serial::_print(format_args!($($arg)*)));
+macro_rules! kprint {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
 }
 
 #[macro_export]
-macro_rules! kprintln! {
+macro_rules! kprintln {
     () => ($crate::kprint!("\n"));
     ($fmt:expr) => ($crate::kprint!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::kprint!(concat!($fmt, "\n"), $($arg)*));
 }
 
+/// Like `kprintln!`, but rendered in red on the framebuffer console.
+#[macro_export]
+macro_rules! kerrorln {
+    ($fmt:expr) => ($crate::console::_print_error(format_args!(concat!($fmt, "\n"))));
+    ($fmt:expr, $($arg:tt)*) => ($crate::console::_print_error(format_args!(concat!($fmt, "\n"), $($arg)*)));
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    // This function is now just a passthrough to the serial driver's _print
+    crate::klog::_print(args);
     crate::drivers::serial::_print(args);
+    crate::drivers::framebuffer::_print(crate::drivers::framebuffer::Severity::Info, args);
+}
+
+#[doc(hidden)]
+pub fn _print_error(args: fmt::Arguments) {
+    crate::klog::_print(args);
+    crate::drivers::serial::_print(args);
+    crate::drivers::framebuffer::_print(crate::drivers::framebuffer::Severity::Error, args);
+}
+
+/// Prepares every console backend for the panic handler.
+/// Locks held by the interrupted code are stolen so the panic message is always printed.
+pub fn panic_takeover() {
+    crate::klog::panic_takeover();
+    crate::drivers::serial::panic_takeover();
+    crate::drivers::framebuffer::panic_takeover();
 }
 
 // Dummy console init function (original from lib.rs, moved here for clarity of previous step)
@@ -0,0 +1,351 @@
+// kernel/src/drivers/ac97.rs
+
+#![allow(dead_code)]
+
+//! AC'97 PCM output (Intel ICH, QEMU's `-device AC97`), behind
+//! `SYS_AUDIO_OPEN` and `SYS_AUDIO_QUEUE`.
+//!
+//! One task at a time owns the output, in practice the audio mixer. Opening
+//! gives it a ring of `RING_PERIODS` periods in a DMA buffer it owns, at the
+//! codec's fixed 48 kHz, 16-bit stereo. The task fills a period and queues
+//! it; the controller plays queued periods in order from the 32-entry buffer
+//! descriptor list, which the ring walks around. Each finished period raises
+//! the interrupt, and the kernel forwards it on the owner's channel like any
+//! other IRQ notification. Queueing returns how many periods have finished
+//! since the ring was opened, so the owner knows how much room there is.
+//!
+//! When the controller plays the last queued period it stops. That is an
+//! underrun if the owner still had something to play; the next queued period
+//! starts it again. Conversion and mixing are the owner's job.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use common::abi::AudioRing;
+use common::ids::{DmaHandle, TaskId};
+use crate::arch::x86_64::{dma, idt, irq};
+use crate::arch::x86_64::dma::{DeviceAddr, DmaDirection, DmaSegment};
+use crate::error::KernelError;
+use crate::{ipc, kprintln, task};
+use super::pci::{self, COMMAND_BUS_MASTER, COMMAND_IO_SPACE};
+
+const VENDOR_INTEL: u16 = 0x8086;
+const DEVICE_ICH_AC97: u16 = 0x2415;
+
+pub const SAMPLE_RATE: u32 = 48_000; // The codec's rate without variable-rate audio
+pub const CHANNELS: u16 = 2;
+pub const BITS_PER_SAMPLE: u16 = 16;
+const FRAME_BYTES: usize = CHANNELS as usize * BITS_PER_SAMPLE as usize / 8;
+
+/// Periods in the ring, and the bytes in each: 1024 frames, about 21 ms.
+pub const RING_PERIODS: usize = 4;
+pub const PERIOD_BYTES: usize = 1024 * FRAME_BYTES;
+
+// Native audio mixer registers (BAR 0), 16 bits wide.
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+const VOLUME_0DB: u16 = 0x0000; // Master: no attenuation, unmuted
+const PCM_GAIN_0DB: u16 = 0x0808;
+
+// Native audio bus master registers (BAR 1). PCM out is the box at 0x10.
+const PO_BDBAR: u16 = 0x10; // u32: buffer descriptor list address
+const PO_CIV: u16 = 0x14; // u8: descriptor being played
+const PO_LVI: u16 = 0x15; // u8: last valid descriptor
+const PO_SR: u16 = 0x16; // u16: status
+const PO_CR: u16 = 0x1B; // u8: control
+const GLOB_CNT: u16 = 0x2C; // u32
+
+const CR_RUN: u8 = 1 << 0;
+const CR_RESET: u8 = 1 << 1;
+const CR_LAST_VALID_IE: u8 = 1 << 2;
+const CR_FIFO_ERROR_IE: u8 = 1 << 3;
+const CR_COMPLETION_IE: u8 = 1 << 4;
+
+const SR_HALTED: u16 = 1 << 0;
+const SR_LAST_VALID: u16 = 1 << 2;
+const SR_COMPLETION: u16 = 1 << 3;
+const SR_FIFO_ERROR: u16 = 1 << 4;
+const SR_CLEAR: u16 = SR_LAST_VALID | SR_COMPLETION | SR_FIFO_ERROR; // Write 1 to clear
+
+const GLOB_CNT_COLD_RESET: u32 = 1 << 1; // Set: the codec is out of reset
+
+const BDL_LEN: usize = 32;
+const BD_COMPLETION_INTERRUPT: u16 = 1 << 15;
+
+/// Address bits of the controller's DMA domain. The controller takes 32-bit
+/// addresses, but the simulated buffers sit at kernel addresses above 4 GiB
+/// (see `bus_address`), which a 32-bit domain couldn't map or bounce. It
+/// gets 32 once domains translate to physical frames.
+const ADDRESS_BITS: u32 = dma::ADDRESS_BITS_ALL;
+
+/// Polls of the control register before a box reset is given up.
+const RESET_POLL_LIMIT: u32 = 100_000;
+
+/// One entry of the buffer descriptor list, as the controller reads it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct BufferDescriptor {
+    addr: u32,
+    samples: u16, // 16-bit samples, not frames
+    flags: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Controller {
+    nam: u16,
+    nabm: u16,
+    irq: u8,
+}
+
+impl Controller {
+    fn read_u8(&self, reg: u16) -> u8 {
+        // SAFETY: `reg` is a bus master register of the controller `init` found.
+        unsafe { Port::<u8>::new(self.nabm + reg).read() }
+    }
+
+    fn write_u8(&self, reg: u16, value: u8) {
+        // SAFETY: As for `read_u8`.
+        unsafe { Port::<u8>::new(self.nabm + reg).write(value) }
+    }
+
+    fn read_u16(&self, reg: u16) -> u16 {
+        // SAFETY: As for `read_u8`.
+        unsafe { Port::<u16>::new(self.nabm + reg).read() }
+    }
+
+    fn write_u16(&self, reg: u16, value: u16) {
+        // SAFETY: As for `read_u8`.
+        unsafe { Port::<u16>::new(self.nabm + reg).write(value) }
+    }
+
+    fn write_u32(&self, reg: u16, value: u32) {
+        // SAFETY: As for `read_u8`.
+        unsafe { Port::<u32>::new(self.nabm + reg).write(value) }
+    }
+
+    fn write_mixer(&self, reg: u16, value: u16) {
+        // SAFETY: `reg` is a codec register of the controller `init` found.
+        unsafe { Port::<u16>::new(self.nam + reg).write(value) }
+    }
+
+    /// Stops PCM out and resets its registers. Returns false if the reset didn't finish.
+    fn reset_output(&self) -> bool {
+        self.write_u8(PO_CR, 0);
+        self.write_u8(PO_CR, CR_RESET);
+        (0..RESET_POLL_LIMIT).any(|_| self.read_u8(PO_CR) & CR_RESET == 0)
+    }
+}
+
+/// The ring handed to the current owner.
+struct Stream {
+    owner: TaskId,
+    domain: u64, // The controller's DMA domain, holding the ring and the list
+    handle: DmaHandle, // DMA buffer holding the periods
+    bdl: Box<[BufferDescriptor; BDL_LEN]>,
+    bdl_addr: DeviceAddr,
+    queued: u64, // Periods queued since open
+    completed: u64, // Periods played since open
+    last_civ: u8,
+    running: bool,
+}
+
+// Both are also locked by the interrupt handler, so only taken with interrupts off.
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+static STREAM: Mutex<Option<Stream>> = Mutex::new(None);
+
+/// The register value for device address `addr`. DMA buffers are simulated
+/// (see `arch::x86_64::dma`); until they come from physical frames, the
+/// device address is the kernel address and is cut to 32 bits here.
+fn bus_address(addr: DeviceAddr) -> u32 {
+    addr as u32
+}
+
+/// Unmaps a stream's descriptor list and destroys its domain with the ring.
+fn close_ring(domain: u64, bdl_addr: DeviceAddr) {
+    let _ = dma::unmap(domain, bdl_addr);
+    dma::destroy_domain(domain);
+}
+
+/// Looks for the controller, takes the codec out of reset at full volume
+/// and routes its interrupt. Returns false if there is no AC'97 device; the
+/// system then runs without sound.
+pub fn init() -> bool {
+    let Some(function) = pci::find(VENDOR_INTEL, DEVICE_ICH_AC97) else {
+        kprintln!("[kernel] ac97: No AC'97 controller; audio output disabled.");
+        return false;
+    };
+    let (Some(nam), Some(nabm)) = (function.io_bar(0), function.io_bar(1)) else {
+        kprintln!("[kernel] ac97: Controller at slot {} has no I/O BARs; audio output disabled.", function.device);
+        return false;
+    };
+    function.enable(COMMAND_IO_SPACE | COMMAND_BUS_MASTER);
+    let controller = Controller { nam, nabm, irq: function.interrupt_line() };
+
+    controller.write_u32(GLOB_CNT, GLOB_CNT_COLD_RESET);
+    controller.write_mixer(NAM_RESET, 0); // Any write resets the codec's registers
+    controller.write_mixer(NAM_MASTER_VOLUME, VOLUME_0DB);
+    controller.write_mixer(NAM_PCM_OUT_VOLUME, PCM_GAIN_0DB);
+    if !controller.reset_output() {
+        kprintln!("[kernel] ac97: PCM out didn't come out of reset; audio output disabled.");
+        return false;
+    }
+
+    idt::set_ac97_handler(controller.irq);
+    interrupts::without_interrupts(|| *CONTROLLER.lock() = Some(controller));
+    kprintln!("[kernel] ac97: Initialized (I/O {:#x}/{:#x}, IRQ {}, {} Hz).", nam, nabm, controller.irq, SAMPLE_RATE);
+    true
+}
+
+pub fn available() -> bool {
+    interrupts::without_interrupts(|| CONTROLLER.lock().is_some())
+}
+
+/// Hands the output to `owner`, with completions sent on `channel_id`.
+/// Opening again as the owner starts over with a fresh ring. Fails with
+/// `Busy` while another live task has it.
+pub fn open(owner: TaskId, channel_id: ipc::ChannelId) -> Result<AudioRing, KernelError> {
+    interrupts::without_interrupts(|| open_stream(owner, channel_id))
+}
+
+fn open_stream(owner: TaskId, channel_id: ipc::ChannelId) -> Result<AudioRing, KernelError> {
+    let controller = CONTROLLER.lock().ok_or(KernelError::InvalidArgument("no audio device"))?;
+    let mut stream = STREAM.lock();
+    if let Some(current) = stream.as_ref() {
+        if current.owner != owner && task::task_exists(current.owner) {
+            return Err(KernelError::Busy);
+        }
+    }
+    if let Some(old) = stream.take() {
+        if old.owner == owner {
+            close_ring(old.domain, old.bdl_addr); // A dead owner's ring went with its other domains
+        }
+    }
+    if !controller.reset_output() {
+        return Err(KernelError::Busy);
+    }
+
+    let ring_bytes = RING_PERIODS * PERIOD_BYTES;
+    let domain = dma::create_domain(owner, "ac97", ADDRESS_BITS);
+    let Some(handle) = dma::alloc_dma_buffer_in(domain, ring_bytes) else {
+        dma::destroy_domain(domain);
+        return Err(KernelError::OutOfMemory);
+    };
+    let ptr = dma::get_dma_buffer_ptr(handle).ok_or(KernelError::OutOfMemory)?;
+    // SAFETY: The buffer was just allocated with `ring_bytes` of capacity.
+    unsafe { core::ptr::write_bytes(ptr, 0, ring_bytes); }
+    if dma::set_dma_buffer_len(handle, ring_bytes).is_err() {
+        dma::destroy_domain(domain);
+        return Err(KernelError::OutOfMemory);
+    }
+    let mut bdl = Box::new([BufferDescriptor::default(); BDL_LEN]);
+    let bdl_addr = match dma::map(domain, &[DmaSegment::of(&mut bdl[..])], DmaDirection::ToDevice) {
+        Ok(addr) => addr,
+        Err(e) => {
+            dma::destroy_domain(domain);
+            return Err(e);
+        }
+    };
+    if let Err(e) = irq::register_irq_handler(controller.irq, channel_id, owner, false) {
+        close_ring(domain, bdl_addr);
+        return Err(e);
+    }
+
+    controller.write_u32(PO_BDBAR, bus_address(bdl_addr));
+    controller.write_u8(PO_CR, CR_COMPLETION_IE | CR_LAST_VALID_IE | CR_FIFO_ERROR_IE);
+    *stream = Some(Stream { owner, domain, handle, bdl, bdl_addr, queued: 0, completed: 0, last_civ: 0, running: false });
+    kprintln!("[kernel] ac97: Output opened by task {} (ring {}, {} x {} bytes).", owner, handle, RING_PERIODS, PERIOD_BYTES);
+
+    Ok(AudioRing {
+        handle: handle.raw(),
+        period_bytes: PERIOD_BYTES as u32,
+        periods: RING_PERIODS as u32,
+        sample_rate: SAMPLE_RATE,
+        channels: CHANNELS,
+        bits_per_sample: BITS_PER_SAMPLE,
+    })
+}
+
+/// Queues the first `len` bytes of ring period `period` to play after the
+/// ones already queued. Returns the number of periods played since open.
+pub fn queue(owner: TaskId, period: usize, len: usize) -> Result<u64, KernelError> {
+    interrupts::without_interrupts(|| queue_period(owner, period, len))
+}
+
+fn queue_period(owner: TaskId, period: usize, len: usize) -> Result<u64, KernelError> {
+    let controller = CONTROLLER.lock().ok_or(KernelError::InvalidArgument("no audio device"))?;
+    let mut guard = STREAM.lock();
+    let stream = match guard.as_mut() {
+        Some(stream) if stream.owner == owner => stream,
+        _ => return Err(KernelError::PermissionDenied),
+    };
+    if period >= RING_PERIODS || len == 0 || len > PERIOD_BYTES || len % FRAME_BYTES != 0 {
+        return Err(KernelError::InvalidArgument("period or length out of range"));
+    }
+    if stream.queued - stream.completed >= RING_PERIODS as u64 {
+        return Err(KernelError::Busy);
+    }
+    let base = dma::get_dma_buffer_device_addr(stream.handle).ok_or(KernelError::OutOfMemory)?;
+    dma::sync_dma_buffer_for_device(stream.handle)?;
+
+    let index = (stream.queued % BDL_LEN as u64) as usize;
+    stream.bdl[index] = BufferDescriptor {
+        addr: bus_address(base + (period * PERIOD_BYTES) as u64),
+        samples: (len / 2) as u16,
+        flags: BD_COMPLETION_INTERRUPT,
+    };
+    dma::sync_for_device(stream.domain, stream.bdl_addr)?;
+    stream.queued += 1;
+    controller.write_u8(PO_LVI, index as u8);
+    if !stream.running || controller.read_u16(PO_SR) & SR_HALTED != 0 {
+        let control = controller.read_u8(PO_CR);
+        controller.write_u8(PO_CR, control | CR_RUN);
+        stream.running = true;
+    }
+    Ok(stream.completed)
+}
+
+/// Called from the controller's interrupt handler. Counts the periods
+/// played, acknowledges the controller and notifies the owner.
+pub fn handle_interrupt() {
+    let Some(controller) = *CONTROLLER.lock() else {
+        return;
+    };
+    let status = controller.read_u16(PO_SR);
+    controller.write_u16(PO_SR, status & SR_CLEAR);
+    if let Some(stream) = STREAM.lock().as_mut() {
+        let civ = controller.read_u8(PO_CIV) % BDL_LEN as u8;
+        let advanced = (civ as u64 + BDL_LEN as u64 - stream.last_civ as u64) % BDL_LEN as u64;
+        stream.last_civ = civ;
+        stream.completed = (stream.completed + advanced).min(stream.queued);
+        if status & SR_HALTED != 0 {
+            // Stopped after the last queued period: everything has played.
+            stream.completed = stream.queued;
+            stream.running = false;
+        }
+    }
+    irq::handle_irq(controller.irq);
+}
+
+/// Stops the output if `task_id` owns it and destroys its ring's domain. Its
+/// IRQ registration goes with its other resources.
+pub fn release_task(task_id: TaskId) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut stream = STREAM.lock();
+        if !matches!(stream.as_ref(), Some(current) if current.owner == task_id) {
+            return false;
+        }
+        if let Some(controller) = *CONTROLLER.lock() {
+            controller.reset_output();
+        }
+        if let Some(old) = stream.take() {
+            close_ring(old.domain, old.bdl_addr);
+        }
+        kprintln!("[kernel] ac97: Output released by task {}.", task_id);
+        true
+    })
+}
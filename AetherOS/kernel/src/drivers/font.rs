@@ -0,0 +1,6 @@
+// kernel/src/drivers/font.rs
+
+//! The console font lives in `common::ui::font` so the display compositor
+//! renders title bars with the same glyphs.
+
+pub use common::ui::font::*;
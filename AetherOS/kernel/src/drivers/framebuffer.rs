@@ -0,0 +1,363 @@
+// kernel/src/drivers/framebuffer.rs
+
+#![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
+
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+
+use common::ids::TaskId;
+use common::text;
+
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// An RGB color used by the text console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0x00, g: 0x00, b: 0x00 };
+    pub const LIGHT_GRAY: Color = Color { r: 0xC0, g: 0xC0, b: 0xC0 };
+    pub const YELLOW: Color = Color { r: 0xFF, g: 0xD7, b: 0x00 };
+    pub const RED: Color = Color { r: 0xFF, g: 0x40, b: 0x40 };
+    pub const WHITE: Color = Color { r: 0xFF, g: 0xFF, b: 0xFF };
+    pub const STATUS_BLUE: Color = Color { r: 0x20, g: 0x40, b: 0x80 };
+    pub const STATUS_RED: Color = Color { r: 0xA0, g: 0x10, b: 0x10 };
+}
+
+/// Severity of console output, mapped to a foreground color on the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> Color {
+        match self {
+            Severity::Info => Color::LIGHT_GRAY,
+            Severity::Warning => Color::YELLOW,
+            Severity::Error => Color::RED,
+        }
+    }
+}
+
+/// A character-grid text console drawn directly into the bootloader framebuffer.
+pub struct FbConsole {
+    buffer: &'static mut [u8],
+    info: FrameBufferInfo,
+    col: usize,
+    row: usize,
+    fg: Color,
+    bg: Color,
+    status_row: bool, // The bottom row holds the boot status line and doesn't scroll
+}
+
+impl FbConsole {
+    fn new(buffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        let mut console = FbConsole {
+            buffer,
+            info,
+            col: 0,
+            row: 0,
+            fg: Severity::Info.color(),
+            bg: Color::BLACK,
+            status_row: false,
+        };
+        console.clear();
+        console
+    }
+
+    fn cols(&self) -> usize {
+        self.info.width / GLYPH_WIDTH
+    }
+
+    fn rows(&self) -> usize {
+        self.info.height / GLYPH_HEIGHT
+    }
+
+    /// Rows available to scrolling text.
+    fn text_rows(&self) -> usize {
+        self.rows().saturating_sub(self.status_row as usize)
+    }
+
+    /// Bytes occupied by one row of character cells.
+    fn text_row_bytes(&self) -> usize {
+        self.info.stride * self.info.bytes_per_pixel * GLYPH_HEIGHT
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0);
+        self.col = 0;
+        self.row = 0;
+        self.status_row = false;
+    }
+
+    pub fn set_severity(&mut self, severity: Severity) {
+        self.fg = severity.color();
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let bpp = self.info.bytes_per_pixel;
+        let offset = (y * self.info.stride + x) * bpp;
+        if offset + bpp > self.buffer.len() {
+            return;
+        }
+        let pixel = &mut self.buffer[offset..offset + bpp];
+        match self.info.pixel_format {
+            PixelFormat::Rgb => {
+                pixel[0] = color.r;
+                pixel[1] = color.g;
+                pixel[2] = color.b;
+            }
+            PixelFormat::Bgr => {
+                pixel[0] = color.b;
+                pixel[1] = color.g;
+                pixel[2] = color.r;
+            }
+            PixelFormat::U8 => {
+                // Grayscale: approximate luminance.
+                pixel[0] = ((color.r as u16 * 3 + color.g as u16 * 6 + color.b as u16) / 10) as u8;
+            }
+            // Unknown layouts are left untouched rather than drawing garbage.
+            _ => {}
+        }
+    }
+
+    fn draw_glyph(&mut self, c: char) {
+        let x0 = self.col * GLYPH_WIDTH;
+        let y0 = self.row * GLYPH_HEIGHT;
+        for y in 0..GLYPH_HEIGHT {
+            let bits = font::glyph_row_char(c, y);
+            for x in 0..GLYPH_WIDTH {
+                let color = if bits & (1 << x) != 0 { self.fg } else { self.bg };
+                self.write_pixel(x0 + x, y0 + y, color);
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.text_rows() {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves every text row up by one and blanks the last row.
+    fn scroll(&mut self) {
+        let row_bytes = self.text_row_bytes();
+        let used = row_bytes * self.text_rows();
+        if used > self.buffer.len() || row_bytes == 0 {
+            self.clear();
+            return;
+        }
+        self.buffer.copy_within(row_bytes..used, 0);
+        self.buffer[used - row_bytes..used].fill(0);
+    }
+
+    fn write_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.col = 0,
+            c => {
+                // Wide (CJK) characters take two cells and never straddle a line break;
+                // combining marks and other zero-width characters are not drawn.
+                let width = text::char_width(c);
+                if width == 0 {
+                    return;
+                }
+                if self.col + width > self.cols() {
+                    self.new_line();
+                }
+                self.draw_glyph(c);
+                self.col += width;
+            }
+        }
+    }
+}
+
+impl FbConsole {
+    /// Draws `text` on the bottom row, white on blue, or on red if `failed`,
+    /// and keeps the row out of scrolling until `clear_status`.
+    fn draw_status(&mut self, text: &str, failed: bool) {
+        let rows = self.rows();
+        if rows < 2 {
+            return;
+        }
+        if !self.status_row && self.row == rows - 1 {
+            // The cursor is on the row about to be taken; move the text up first.
+            self.scroll();
+            self.row = rows - 2;
+        }
+        self.status_row = true;
+        let saved = (self.col, self.row, self.fg, self.bg);
+        self.fg = Color::WHITE;
+        self.bg = if failed { Color::STATUS_RED } else { Color::STATUS_BLUE };
+        self.row = rows - 1;
+        self.col = 0;
+        let cols = self.cols();
+        for c in text.chars().filter(|c| !c.is_control()) {
+            let width = text::char_width(c);
+            if width == 0 {
+                continue;
+            }
+            if self.col + width > cols {
+                break;
+            }
+            self.draw_glyph(c);
+            self.col += width;
+        }
+        while self.col < cols {
+            self.draw_glyph(' ');
+            self.col += 1;
+        }
+        (self.col, self.row, self.fg, self.bg) = saved;
+    }
+
+    /// Blanks the status line and gives the row back to scrolling text.
+    fn clear_status(&mut self) {
+        if !self.status_row {
+            return;
+        }
+        let row_bytes = self.text_row_bytes();
+        let start = row_bytes * (self.rows() - 1);
+        if let Some(row) = self.buffer.get_mut(start..start + row_bytes) {
+            row.fill(0);
+        }
+        self.status_row = false;
+    }
+}
+
+impl fmt::Write for FbConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+        Ok(())
+    }
+}
+
+/// The kernel framebuffer console, if the bootloader provided a framebuffer.
+static FB_CONSOLE: Mutex<Option<FbConsole>> = Mutex::new(None);
+
+/// Whether the kernel may draw to the framebuffer. Cleared once a display
+/// V-Node acquires the framebuffer via `SYS_FB_ACQUIRE`.
+static KERNEL_DRAWING: AtomicBool = AtomicBool::new(true);
+
+/// Task ID of the V-Node that owns the framebuffer (0 = kernel console).
+static FB_OWNER: AtomicU64 = AtomicU64::new(0);
+
+/// Initializes the framebuffer console from the bootloader-provided framebuffer.
+pub fn init(framebuffer: Option<&'static mut FrameBuffer>) {
+    match framebuffer {
+        Some(fb) => {
+            let info = fb.info();
+            let buffer = fb.buffer_mut();
+            *FB_CONSOLE.lock() = Some(FbConsole::new(buffer, info));
+            kprintln!("[kernel] framebuffer: Text console initialized ({}x{}, {} bytes/pixel).", info.width, info.height, info.bytes_per_pixel);
+        }
+        None => {
+            kprintln!("[kernel] framebuffer: No framebuffer provided by bootloader, console is serial-only.");
+        }
+    }
+}
+
+/// Stops the kernel console on the framebuffer (`fbcon=off`) and blanks it.
+/// A display V-Node can still acquire the framebuffer, and a panic still
+/// takes it back.
+pub fn disable_console() {
+    KERNEL_DRAWING.store(false, Ordering::SeqCst);
+    if let Some(console) = FB_CONSOLE.lock().as_mut() {
+        console.clear();
+    }
+    kprintln!("[kernel] framebuffer: Console disabled on the command line; output is serial-only.");
+}
+
+/// Hands the framebuffer over to a display V-Node and stops kernel drawing.
+/// Returns the framebuffer base address and its info, or None if there is no framebuffer.
+pub fn acquire(task_id: TaskId) -> Option<(u64, FrameBufferInfo)> {
+    let console = FB_CONSOLE.lock();
+    let fb = console.as_ref()?;
+    KERNEL_DRAWING.store(false, Ordering::SeqCst);
+    FB_OWNER.store(task_id.raw(), Ordering::SeqCst);
+    Some((fb.buffer.as_ptr() as u64, fb.info))
+}
+
+/// Returns the task ID of the current framebuffer owner (the kernel while it draws the console).
+pub fn owner() -> TaskId {
+    TaskId::from_raw(FB_OWNER.load(Ordering::SeqCst))
+}
+
+/// Writes formatted output to the framebuffer console, if the kernel still owns it.
+#[doc(hidden)]
+pub fn _print(severity: Severity, args: fmt::Arguments) {
+    if !KERNEL_DRAWING.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(console) = FB_CONSOLE.lock().as_mut() {
+        console.set_severity(severity);
+        let _ = console.write_fmt(args);
+        console.set_severity(Severity::Info);
+    }
+}
+
+/// Shows the boot status line at the bottom of the console, if the kernel
+/// still owns the framebuffer. Returns whether it was drawn.
+pub fn set_status(text: &str, failed: bool) -> bool {
+    if !KERNEL_DRAWING.load(Ordering::Relaxed) {
+        return false;
+    }
+    match FB_CONSOLE.lock().as_mut() {
+        Some(console) => {
+            console.draw_status(text, failed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes the boot status line.
+pub fn clear_status() {
+    if !KERNEL_DRAWING.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(console) = FB_CONSOLE.lock().as_mut() {
+        console.clear_status();
+    }
+}
+
+/// Takes the framebuffer back from the display V-Node for the kernel's last
+/// lines before the machine powers off. Nothing runs after that to draw over them.
+pub fn reclaim() {
+    KERNEL_DRAWING.store(true, Ordering::SeqCst);
+    FB_OWNER.store(0, Ordering::SeqCst);
+    if let Some(console) = FB_CONSOLE.lock().as_mut() {
+        console.clear();
+    }
+}
+
+/// Reclaims the framebuffer for the panic handler.
+///
+/// Re-enables kernel drawing even if a display V-Node owns the framebuffer, and
+/// steals the console lock if it is held: the holder will never run again once
+/// we are panicking, so waiting on it would hide the panic message.
+pub fn panic_takeover() {
+    KERNEL_DRAWING.store(true, Ordering::SeqCst);
+    FB_OWNER.store(0, Ordering::SeqCst);
+    if FB_CONSOLE.is_locked() {
+        // SAFETY: We are on the panic path and never return to the interrupted holder.
+        unsafe { FB_CONSOLE.force_unlock(); }
+    }
+    if let Some(console) = FB_CONSOLE.lock().as_mut() {
+        // Start the panic report on a clean line so it is not interleaved with compositor output.
+        console.clear();
+    }
+}
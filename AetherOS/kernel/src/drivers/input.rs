@@ -0,0 +1,100 @@
+// kernel/src/drivers/input.rs
+
+#![allow(dead_code)]
+
+//! Queue of decoded input events, filled from interrupt handlers and drained
+//! by the display owner through `SYS_INPUT_READ`.
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use common::abi::{KeyReport, MouseReport, INPUT_EVENT_LEN};
+
+/// Events held until the display owner reads them.
+const QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+enum InputEvent {
+    Mouse(MouseReport),
+    Key(KeyReport),
+}
+
+impl InputEvent {
+    fn to_bytes(&self) -> [u8; INPUT_EVENT_LEN] {
+        match self {
+            InputEvent::Mouse(report) => report.to_bytes(),
+            InputEvent::Key(report) => report.to_bytes(),
+        }
+    }
+}
+
+static QUEUE: Mutex<VecDeque<InputEvent>> = Mutex::new(VecDeque::new());
+
+/// Events that had to be dropped because the queue was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Queues a mouse report. Called from the mouse interrupt handler.
+///
+/// When the queue is full, a report with the same buttons as the newest one is
+/// merged into it, so a stalled reader loses motion resolution but never a
+/// press or release. Only if the buttons changed is the oldest report dropped.
+pub fn push_mouse(report: MouseReport) {
+    let mut queue = QUEUE.lock();
+    if queue.len() >= QUEUE_CAPACITY {
+        if let Some(InputEvent::Mouse(last)) = queue.back_mut().filter(|last| matches!(last, InputEvent::Mouse(last) if last.buttons == report.buttons)) {
+            last.dx = last.dx.saturating_add(report.dx);
+            last.dy = last.dy.saturating_add(report.dy);
+            last.wheel = last.wheel.saturating_add(report.wheel);
+            last.captured_at = report.captured_at;
+            return;
+        }
+        queue.pop_front();
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    queue.push_back(InputEvent::Mouse(report));
+}
+
+/// Queues a key event. Called from the keyboard and timer interrupt handlers.
+///
+/// When the queue is full, a repeat is dropped rather than queued; anything
+/// else evicts the oldest event.
+pub fn push_key(report: KeyReport) {
+    let mut queue = QUEUE.lock();
+    if queue.len() >= QUEUE_CAPACITY {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        if report.repeat {
+            return;
+        }
+        queue.pop_front();
+    }
+    queue.push_back(InputEvent::Key(report));
+}
+
+/// Moves as many queued events as fit into `out`, `INPUT_EVENT_LEN` bytes
+/// each. Returns the number of events written.
+pub fn read(out: &mut [u8]) -> usize {
+    // The interrupt handler takes the same lock; keep it from firing while we hold it.
+    interrupts::without_interrupts(|| {
+        let mut queue = QUEUE.lock();
+        let mut count = 0;
+        for record in out.chunks_exact_mut(INPUT_EVENT_LEN) {
+            match queue.pop_front() {
+                Some(event) => record.copy_from_slice(&event.to_bytes()),
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    })
+}
+
+/// Discards everything queued, e.g. when the display changes hands.
+pub fn clear() {
+    interrupts::without_interrupts(|| QUEUE.lock().clear());
+}
+
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
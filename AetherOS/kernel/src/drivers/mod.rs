@@ -1,6 +1,16 @@
 // kernel/src/drivers/mod.rs
 
 pub mod serial; // New: Serial driver module
+pub mod font; // Bitmap font for the framebuffer console
+pub mod framebuffer; // Framebuffer text console
+pub mod input; // Input event queue read through SYS_INPUT_READ
+pub mod ps2_controller; // 8042 port access shared by the keyboard and mouse
+pub mod ps2_keyboard; // PS/2 keyboard on IRQ 1
+pub mod ps2_mouse; // PS/2 mouse on IRQ 12
+pub mod rtc; // CMOS real-time clock, the source of wall-clock time
+pub mod rng; // RDRAND, the source of SYS_RANDOM
+pub mod pci; // Configuration space on bus 0
+pub mod ac97; // AC'97 PCM output behind SYS_AUDIO_*
 
 // Add other driver modules here as they are implemented.
 
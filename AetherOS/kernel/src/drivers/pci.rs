@@ -0,0 +1,98 @@
+// kernel/src/drivers/pci.rs
+
+#![allow(dead_code)]
+
+//! PCI configuration space through the legacy I/O ports (mechanism #1).
+//!
+//! Enough to find a device by vendor and device ID on bus 0, read its BARs
+//! and interrupt line, and turn on I/O decoding and bus mastering. Devices
+//! behind bridges aren't found; QEMU's machines put everything on bus 0.
+
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const REG_VENDOR_DEVICE: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_BAR0: u8 = 0x10;
+const REG_INTERRUPT_LINE: u8 = 0x3C;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// BAR bit 0: the BAR decodes I/O ports rather than memory.
+const BAR_IO: u32 = 1 << 0;
+
+const NO_DEVICE: u16 = 0xFFFF;
+const DEVICES_PER_BUS: u8 = 32;
+
+/// A function on bus 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciFunction {
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciFunction {
+    fn address(&self, offset: u8) -> u32 {
+        1 << 31 | (self.device as u32) << 11 | (self.function as u32) << 8 | (offset & 0xFC) as u32
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        // SAFETY: Configuration space accesses only touch the selected function's registers.
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        // SAFETY: As for `read_u32`; callers only write registers they mean to change.
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    /// The I/O port base of BAR `index`, or `None` if it decodes memory.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.read_u32(REG_BAR0 + index * 4);
+        if bar & BAR_IO == 0 {
+            return None;
+        }
+        Some((bar & !0x3) as u16)
+    }
+
+    /// The legacy interrupt line firmware routed the function to.
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u32(REG_INTERRUPT_LINE) as u8
+    }
+
+    /// Sets `bits` in the command register.
+    pub fn enable(&self, bits: u16) {
+        let value = self.read_u32(REG_COMMAND);
+        // The upper half is the status register; writing its bits back would clear them.
+        self.write_u32(REG_COMMAND, (value & 0xFFFF) | bits as u32);
+    }
+}
+
+/// The first function on bus 0 with this vendor and device ID.
+pub fn find(vendor: u16, device: u16) -> Option<PciFunction> {
+    for slot in 0..DEVICES_PER_BUS {
+        for function in 0..8 {
+            let candidate = PciFunction { device: slot, function };
+            let id = candidate.read_u32(REG_VENDOR_DEVICE);
+            if id as u16 == NO_DEVICE {
+                if function == 0 {
+                    break; // Empty slot
+                }
+                continue;
+            }
+            if id as u16 == vendor && (id >> 16) as u16 == device {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
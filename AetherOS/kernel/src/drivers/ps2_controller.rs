@@ -0,0 +1,97 @@
+// kernel/src/drivers/ps2_controller.rs
+
+#![allow(dead_code)]
+
+//! Port access to the 8042 PS/2 controller, shared by the keyboard (first
+//! port, IRQ 1) and the mouse (auxiliary port, IRQ 12). Both devices answer
+//! through the same data port; the status register tells whose byte it is.
+
+use x86_64::instructions::port::Port;
+
+pub const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64; // Read: status register
+const COMMAND_PORT: u16 = 0x64; // Write: controller command
+
+pub const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+pub const STATUS_INPUT_FULL: u8 = 1 << 1;
+pub const STATUS_AUX_DATA: u8 = 1 << 5; // The byte in the output buffer came from the mouse
+
+pub const CMD_READ_CONFIG: u8 = 0x20;
+pub const CMD_WRITE_CONFIG: u8 = 0x60;
+pub const CMD_ENABLE_AUX: u8 = 0xA8;
+pub const CMD_ENABLE_FIRST: u8 = 0xAE;
+pub const CMD_WRITE_AUX: u8 = 0xD4; // The next data byte goes to the mouse
+
+pub const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+pub const CONFIG_AUX_IRQ: u8 = 1 << 1;
+pub const CONFIG_FIRST_CLOCK_DISABLED: u8 = 1 << 4;
+pub const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+pub const CONFIG_TRANSLATION: u8 = 1 << 6; // Keyboard set 2 arrives translated to set 1
+
+/// Acknowledgement devices send for every command byte.
+pub const ACK: u8 = 0xFA;
+/// The device wants the last byte again.
+pub const RESEND: u8 = 0xFE;
+
+/// Status polls before a controller handshake is given up.
+const POLL_LIMIT: u32 = 100_000;
+
+pub fn status() -> u8 {
+    // SAFETY: Reading the 8042 status register has no side effects.
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn wait_writable() -> bool {
+    (0..POLL_LIMIT).any(|_| status() & STATUS_INPUT_FULL == 0)
+}
+
+fn wait_readable() -> bool {
+    (0..POLL_LIMIT).any(|_| status() & STATUS_OUTPUT_FULL != 0)
+}
+
+pub fn write_command(command: u8) -> bool {
+    if !wait_writable() {
+        return false;
+    }
+    // SAFETY: The controller accepts a command once its input buffer is empty.
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    true
+}
+
+pub fn write_data(data: u8) -> bool {
+    if !wait_writable() {
+        return false;
+    }
+    // SAFETY: As above, for the data port.
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+    true
+}
+
+/// Waits for a byte and reads it. For handshakes during init only; at run
+/// time the interrupt handlers read the data port.
+pub fn read_data() -> Option<u8> {
+    if !wait_readable() {
+        return None;
+    }
+    // SAFETY: The output buffer is full, so the read consumes exactly that byte.
+    Some(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+/// Reads the byte waiting in the output buffer, from an interrupt handler.
+/// Reading it acknowledges the byte to the controller.
+pub fn read_pending() -> u8 {
+    // SAFETY: Callers check STATUS_OUTPUT_FULL first; the read consumes that byte.
+    unsafe { Port::<u8>::new(DATA_PORT).read() }
+}
+
+/// Reads the controller configuration byte.
+pub fn read_config() -> Option<u8> {
+    if !write_command(CMD_READ_CONFIG) {
+        return None;
+    }
+    read_data()
+}
+
+pub fn write_config(config: u8) -> bool {
+    write_command(CMD_WRITE_CONFIG) && write_data(config)
+}
@@ -0,0 +1,669 @@
+// kernel/src/drivers/ps2_keyboard.rs
+
+#![allow(dead_code)]
+
+//! PS/2 keyboard on the first port of the 8042 controller (IRQ 1).
+//!
+//! The interrupt handler reads one byte per interrupt. A `ScancodeDecoder`
+//! turns the bytes into key presses and releases in the `common::keys`
+//! keycode space, and `KeyboardState` tracks modifiers, translates through
+//! the configured layout and generates key repeat. Events are queued in
+//! `drivers::input` next to the mouse's.
+//!
+//! The keyboard repeats a held key by itself, by sending its make code again.
+//! Those repeats are dropped: the kernel generates its own from the timer, so
+//! the delay and rate follow the user's settings instead of the keyboard's.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use common::abi::{KeyReport, KEY_REPEAT_MAX_DELAY_MS, KEY_REPEAT_MAX_RATE_HZ};
+use common::keys::{self, Layout, KEYCODE_LIMIT, MOD_ALT, MOD_ALTGR, MOD_CAPS_LOCK, MOD_CTRL, MOD_META, MOD_SHIFT};
+use crate::{kprintln, timer};
+use crate::timer::{Millis, Ticks};
+use super::input;
+use super::ps2_controller::{self as controller, ACK, CMD_ENABLE_FIRST, CONFIG_FIRST_CLOCK_DISABLED, CONFIG_FIRST_IRQ, CONFIG_TRANSLATION, RESEND, STATUS_AUX_DATA, STATUS_OUTPUT_FULL};
+
+/// IRQ line of the PS/2 keyboard port.
+pub const PS2_KEYBOARD_IRQ: u8 = 1;
+
+const KEYBOARD_SET_LEDS: u8 = 0xED;
+
+// LED bits of the KEYBOARD_SET_LEDS value.
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// An LED update the keyboard hasn't acknowledged after this many ticks is
+/// abandoned and retried with the next key.
+const LED_ACK_TIMEOUT_TICKS: Ticks = Ticks::from_raw(10);
+
+/// A prefix byte followed by nothing for this many ticks is dropped. The bytes
+/// of one scancode arrive well within a millisecond.
+const PREFIX_GAP_TICKS: Ticks = Ticks::from_raw(2);
+
+const PREFIX_EXTENDED: u8 = 0xE0;
+const PREFIX_PAUSE: u8 = 0xE1; // Pause sends a fixed sequence with no break code
+const SET2_PREFIX_RELEASE: u8 = 0xF0;
+const SET1_RELEASE_BIT: u8 = 0x80;
+
+// Bytes that are replies or errors, never key data.
+const REPLY_ERROR: u8 = 0x00;
+const REPLY_OVERRUN: u8 = 0xFF;
+const REPLY_ECHO: u8 = 0xEE;
+const REPLY_SELF_TEST_PASSED: u8 = 0xAA; // Set 2 only: in set 1 this is a Left Shift release
+const REPLY_SELF_TEST_FAILED: u8 = 0xFC;
+
+/// Bytes after 0xE1 in the Pause sequence: `1D 45 E1 9D C5` in set 1,
+/// `14 77 E1 F0 14 F0 77` in set 2.
+const SET1_PAUSE_TAIL: u8 = 5;
+const SET2_PAUSE_TAIL: u8 = 7;
+
+// Defaults until the display owner applies the user's settings.
+const DEFAULT_REPEAT_DELAY_MS: u64 = 500;
+const DEFAULT_REPEAT_RATE_HZ: u64 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// What the controller delivers with translation on, the usual case.
+    One,
+    Two,
+}
+
+/// A key going down or up, before modifiers and layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyTransition {
+    pub keycode: u16,
+    pub pressed: bool,
+}
+
+/// Turns the scancode byte stream into key transitions.
+pub struct ScancodeDecoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool, // Set 2 only; set 1 marks releases in the code itself
+    pause_tail: u8, // Bytes of the Pause sequence still to come
+    last_byte_at: Ticks,
+    resyncs: u64,
+}
+
+impl ScancodeDecoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self { set, extended: false, release: false, pause_tail: 0, last_byte_at: Ticks::ZERO, resyncs: 0 }
+    }
+
+    fn reset(&mut self) {
+        self.extended = false;
+        self.release = false;
+        self.pause_tail = 0;
+        self.resyncs += 1;
+    }
+
+    fn mid_sequence(&self) -> bool {
+        self.extended || self.release || self.pause_tail > 0
+    }
+
+    /// Adds one byte received at tick `now`. Returns the transition once a
+    /// scancode is complete.
+    ///
+    /// Error bytes and a sequence that went quiet reset the decoder, so a lost
+    /// prefix can't turn the next key into a different one.
+    pub fn push(&mut self, byte: u8, now: Ticks) -> Option<KeyTransition> {
+        if self.mid_sequence() && now.saturating_sub(self.last_byte_at) > PREFIX_GAP_TICKS {
+            self.reset();
+        }
+        self.last_byte_at = now;
+
+        if self.pause_tail > 0 {
+            self.pause_tail -= 1;
+            return (self.pause_tail == 0).then_some(KeyTransition { keycode: keys::KEY_PAUSE, pressed: true });
+        }
+        match byte {
+            REPLY_ERROR | REPLY_OVERRUN => {
+                self.reset();
+                return None;
+            },
+            ACK | RESEND | REPLY_ECHO => return None, // Answers to commands, e.g. a late LED ack
+            REPLY_SELF_TEST_PASSED | REPLY_SELF_TEST_FAILED if self.set == ScancodeSet::Two && !self.release => {
+                // The keyboard was replugged or reset itself.
+                self.reset();
+                return None;
+            },
+            PREFIX_EXTENDED => {
+                self.extended = true;
+                return None;
+            },
+            PREFIX_PAUSE => {
+                self.pause_tail = match self.set {
+                    ScancodeSet::One => SET1_PAUSE_TAIL,
+                    ScancodeSet::Two => SET2_PAUSE_TAIL,
+                };
+                return None;
+            },
+            SET2_PREFIX_RELEASE if self.set == ScancodeSet::Two => {
+                self.release = true;
+                return None;
+            },
+            _ => {},
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let (code, pressed) = match self.set {
+            ScancodeSet::One => (byte & !SET1_RELEASE_BIT, byte & SET1_RELEASE_BIT == 0),
+            ScancodeSet::Two => (byte, !core::mem::take(&mut self.release)),
+        };
+        let keycode = match self.set {
+            ScancodeSet::One => set1_keycode(code, extended),
+            ScancodeSet::Two => set2_keycode(code, extended),
+        }?;
+        Some(KeyTransition { keycode, pressed })
+    }
+
+    /// Partial scancodes dropped so far to get back in sync.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+}
+
+fn set1_keycode(code: u8, extended: bool) -> Option<u16> {
+    use common::keys::*;
+    let keycode = if extended {
+        match code {
+            0x1C => KEY_KP_ENTER,
+            0x1D => KEY_RIGHT_CTRL,
+            0x35 => KEY_KP_SLASH,
+            0x37 => KEY_PRINT_SCREEN,
+            0x38 => KEY_RIGHT_ALT,
+            0x47 => KEY_HOME,
+            0x48 => KEY_UP,
+            0x49 => KEY_PAGE_UP,
+            0x4B => KEY_LEFT,
+            0x4D => KEY_RIGHT,
+            0x4F => KEY_END,
+            0x50 => KEY_DOWN,
+            0x51 => KEY_PAGE_DOWN,
+            0x52 => KEY_INSERT,
+            0x53 => KEY_DELETE,
+            0x5B => KEY_LEFT_META,
+            0x5C => KEY_RIGHT_META,
+            0x5D => KEY_MENU,
+            // Includes E0 2A / E0 36, the fake shifts around Print Screen and the
+            // navigation keys, which would otherwise undo a real Shift.
+            _ => return None,
+        }
+    } else {
+        match code {
+            0x01 => KEY_ESCAPE,
+            0x02..=0x0A => KEY_1 + (code - 0x02) as u16,
+            0x0B => KEY_0,
+            0x0C => KEY_MINUS,
+            0x0D => KEY_EQUAL,
+            0x0E => KEY_BACKSPACE,
+            0x0F => KEY_TAB,
+            0x10 => KEY_Q,
+            0x11 => KEY_W,
+            0x12 => KEY_E,
+            0x13 => KEY_R,
+            0x14 => KEY_T,
+            0x15 => KEY_Y,
+            0x16 => KEY_U,
+            0x17 => KEY_I,
+            0x18 => KEY_O,
+            0x19 => KEY_P,
+            0x1A => KEY_LEFT_BRACKET,
+            0x1B => KEY_RIGHT_BRACKET,
+            0x1C => KEY_ENTER,
+            0x1D => KEY_LEFT_CTRL,
+            0x1E => KEY_A,
+            0x1F => KEY_S,
+            0x20 => KEY_D,
+            0x21 => KEY_F,
+            0x22 => KEY_G,
+            0x23 => KEY_H,
+            0x24 => KEY_J,
+            0x25 => KEY_K,
+            0x26 => KEY_L,
+            0x27 => KEY_SEMICOLON,
+            0x28 => KEY_APOSTROPHE,
+            0x29 => KEY_GRAVE,
+            0x2A => KEY_LEFT_SHIFT,
+            0x2B => KEY_BACKSLASH,
+            0x2C => KEY_Z,
+            0x2D => KEY_X,
+            0x2E => KEY_C,
+            0x2F => KEY_V,
+            0x30 => KEY_B,
+            0x31 => KEY_N,
+            0x32 => KEY_M,
+            0x33 => KEY_COMMA,
+            0x34 => KEY_DOT,
+            0x35 => KEY_SLASH,
+            0x36 => KEY_RIGHT_SHIFT,
+            0x37 => KEY_KP_ASTERISK,
+            0x38 => KEY_LEFT_ALT,
+            0x39 => KEY_SPACE,
+            0x3A => KEY_CAPS_LOCK,
+            0x3B..=0x44 => KEY_F1 + (code - 0x3B) as u16,
+            0x45 => KEY_NUM_LOCK,
+            0x46 => KEY_SCROLL_LOCK,
+            0x47 => KEY_KP_7,
+            0x48 => KEY_KP_8,
+            0x49 => KEY_KP_9,
+            0x4A => KEY_KP_MINUS,
+            0x4B => KEY_KP_4,
+            0x4C => KEY_KP_5,
+            0x4D => KEY_KP_6,
+            0x4E => KEY_KP_PLUS,
+            0x4F => KEY_KP_1,
+            0x50 => KEY_KP_2,
+            0x51 => KEY_KP_3,
+            0x52 => KEY_KP_0,
+            0x53 => KEY_KP_DOT,
+            0x56 => KEY_NON_US_BACKSLASH,
+            0x57 => KEY_F11,
+            0x58 => KEY_F12,
+            _ => return None,
+        }
+    };
+    Some(keycode)
+}
+
+fn set2_keycode(code: u8, extended: bool) -> Option<u16> {
+    use common::keys::*;
+    let keycode = if extended {
+        match code {
+            0x5A => KEY_KP_ENTER,
+            0x14 => KEY_RIGHT_CTRL,
+            0x4A => KEY_KP_SLASH,
+            0x7C => KEY_PRINT_SCREEN,
+            0x11 => KEY_RIGHT_ALT,
+            0x6C => KEY_HOME,
+            0x75 => KEY_UP,
+            0x7D => KEY_PAGE_UP,
+            0x6B => KEY_LEFT,
+            0x74 => KEY_RIGHT,
+            0x69 => KEY_END,
+            0x72 => KEY_DOWN,
+            0x7A => KEY_PAGE_DOWN,
+            0x70 => KEY_INSERT,
+            0x71 => KEY_DELETE,
+            0x1F => KEY_LEFT_META,
+            0x27 => KEY_RIGHT_META,
+            0x2F => KEY_MENU,
+            // Includes E0 12 / E0 59, the set 2 fake shifts.
+            _ => return None,
+        }
+    } else {
+        match code {
+            0x76 => KEY_ESCAPE,
+            0x16 => KEY_1,
+            0x1E => KEY_2,
+            0x26 => KEY_3,
+            0x25 => KEY_4,
+            0x2E => KEY_5,
+            0x36 => KEY_6,
+            0x3D => KEY_7,
+            0x3E => KEY_8,
+            0x46 => KEY_9,
+            0x45 => KEY_0,
+            0x4E => KEY_MINUS,
+            0x55 => KEY_EQUAL,
+            0x66 => KEY_BACKSPACE,
+            0x0D => KEY_TAB,
+            0x15 => KEY_Q,
+            0x1D => KEY_W,
+            0x24 => KEY_E,
+            0x2D => KEY_R,
+            0x2C => KEY_T,
+            0x35 => KEY_Y,
+            0x3C => KEY_U,
+            0x43 => KEY_I,
+            0x44 => KEY_O,
+            0x4D => KEY_P,
+            0x54 => KEY_LEFT_BRACKET,
+            0x5B => KEY_RIGHT_BRACKET,
+            0x5A => KEY_ENTER,
+            0x14 => KEY_LEFT_CTRL,
+            0x1C => KEY_A,
+            0x1B => KEY_S,
+            0x23 => KEY_D,
+            0x2B => KEY_F,
+            0x34 => KEY_G,
+            0x33 => KEY_H,
+            0x3B => KEY_J,
+            0x42 => KEY_K,
+            0x4B => KEY_L,
+            0x4C => KEY_SEMICOLON,
+            0x52 => KEY_APOSTROPHE,
+            0x0E => KEY_GRAVE,
+            0x12 => KEY_LEFT_SHIFT,
+            0x5D => KEY_BACKSLASH,
+            0x1A => KEY_Z,
+            0x22 => KEY_X,
+            0x21 => KEY_C,
+            0x2A => KEY_V,
+            0x32 => KEY_B,
+            0x31 => KEY_N,
+            0x3A => KEY_M,
+            0x41 => KEY_COMMA,
+            0x49 => KEY_DOT,
+            0x4A => KEY_SLASH,
+            0x59 => KEY_RIGHT_SHIFT,
+            0x7C => KEY_KP_ASTERISK,
+            0x11 => KEY_LEFT_ALT,
+            0x29 => KEY_SPACE,
+            0x58 => KEY_CAPS_LOCK,
+            0x05 => KEY_F1,
+            0x06 => KEY_F2,
+            0x04 => KEY_F3,
+            0x0C => KEY_F4,
+            0x03 => KEY_F5,
+            0x0B => KEY_F6,
+            0x83 => KEY_F7,
+            0x0A => KEY_F8,
+            0x01 => KEY_F9,
+            0x09 => KEY_F10,
+            0x78 => KEY_F11,
+            0x07 => KEY_F12,
+            0x77 => KEY_NUM_LOCK,
+            0x7E => KEY_SCROLL_LOCK,
+            0x6C => KEY_KP_7,
+            0x75 => KEY_KP_8,
+            0x7D => KEY_KP_9,
+            0x7B => KEY_KP_MINUS,
+            0x6B => KEY_KP_4,
+            0x73 => KEY_KP_5,
+            0x74 => KEY_KP_6,
+            0x79 => KEY_KP_PLUS,
+            0x69 => KEY_KP_1,
+            0x72 => KEY_KP_2,
+            0x7A => KEY_KP_3,
+            0x70 => KEY_KP_0,
+            0x71 => KEY_KP_DOT,
+            0x61 => KEY_NON_US_BACKSLASH,
+            _ => return None,
+        }
+    };
+    Some(keycode)
+}
+
+/// Delay and rate of generated key repeats, in timer ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepeatTiming {
+    pub delay_ticks: Ticks,
+    pub interval_ticks: Ticks, // 0: no repeat
+}
+
+impl RepeatTiming {
+    /// Converts the settings' units. A rate of 0 turns repeat off.
+    pub fn from_settings(delay_ms: u64, rate_hz: u64) -> Self {
+        Self {
+            delay_ticks: Millis::from_raw(delay_ms).to_ticks_ceil().max(Ticks::from_raw(1)),
+            interval_ticks: if rate_hz == 0 { Ticks::ZERO } else { Ticks::from_raw((timer::TICKS_PER_SECOND / rate_hz).max(1)) },
+        }
+    }
+}
+
+/// Modifier, lock and repeat state on top of the decoder.
+pub struct KeyboardState {
+    down: [u64; (KEYCODE_LIMIT / 64) as usize],
+    caps_lock: bool,
+    layout: Layout,
+    timing: RepeatTiming,
+    repeating: Option<KeyReport>, // The press being repeated
+    next_repeat_at: Ticks,
+}
+
+impl KeyboardState {
+    pub fn new(layout: Layout, timing: RepeatTiming) -> Self {
+        Self { down: [0; (KEYCODE_LIMIT / 64) as usize], caps_lock: false, layout, timing, repeating: None, next_repeat_at: Ticks::ZERO }
+    }
+
+    pub fn configure(&mut self, layout: Layout, timing: RepeatTiming) {
+        self.layout = layout;
+        self.timing = timing;
+        self.repeating = None;
+    }
+
+    fn is_down(&self, keycode: u16) -> bool {
+        self.down[(keycode / 64) as usize] & (1 << (keycode % 64)) != 0
+    }
+
+    fn set_down(&mut self, keycode: u16, down: bool) {
+        let word = &mut self.down[(keycode / 64) as usize];
+        if down {
+            *word |= 1 << (keycode % 64);
+        } else {
+            *word &= !(1 << (keycode % 64));
+        }
+    }
+
+    pub fn modifiers(&self) -> u8 {
+        let mut modifiers = 0;
+        if self.is_down(keys::KEY_LEFT_SHIFT) || self.is_down(keys::KEY_RIGHT_SHIFT) {
+            modifiers |= MOD_SHIFT;
+        }
+        if self.is_down(keys::KEY_LEFT_CTRL) || self.is_down(keys::KEY_RIGHT_CTRL) {
+            modifiers |= MOD_CTRL;
+        }
+        if self.is_down(keys::KEY_LEFT_ALT) || (self.is_down(keys::KEY_RIGHT_ALT) && !self.layout.has_altgr()) {
+            modifiers |= MOD_ALT;
+        }
+        if self.is_down(keys::KEY_RIGHT_ALT) && self.layout.has_altgr() {
+            modifiers |= MOD_ALTGR;
+        }
+        if self.is_down(keys::KEY_LEFT_META) || self.is_down(keys::KEY_RIGHT_META) {
+            modifiers |= MOD_META;
+        }
+        if self.caps_lock {
+            modifiers |= MOD_CAPS_LOCK;
+        }
+        modifiers
+    }
+
+    /// LED bits to show. Num Lock stays lit because the keypad always types digits.
+    pub fn leds(&self) -> u8 {
+        LED_NUM_LOCK | if self.caps_lock { LED_CAPS_LOCK } else { 0 }
+    }
+
+    /// Applies a transition decoded at tick `now` and stamped `captured_at`
+    /// (`timer::nanos`). Returns the event to queue,
+    /// or `None` for the keyboard's own repeats and for releases of keys that
+    /// weren't down (e.g. pressed before a resync).
+    pub fn handle(&mut self, transition: KeyTransition, now: Ticks, captured_at: u64) -> Option<KeyReport> {
+        let KeyTransition { keycode, pressed } = transition;
+        if keycode >= KEYCODE_LIMIT || self.is_down(keycode) == pressed {
+            return None;
+        }
+        // Pause has no break code, so it is never marked as down.
+        if keycode != keys::KEY_PAUSE {
+            self.set_down(keycode, pressed);
+        }
+        if pressed && keycode == keys::KEY_CAPS_LOCK {
+            self.caps_lock = !self.caps_lock;
+        }
+
+        let modifiers = self.modifiers();
+        let report = KeyReport {
+            keycode,
+            pressed,
+            repeat: false,
+            modifiers,
+            text: if pressed { self.layout.translate(keycode, modifiers) } else { None },
+            captured_at,
+        };
+
+        let repeats = !keys::is_modifier(keycode) && !keys::is_lock(keycode) && keycode != keys::KEY_PAUSE;
+        if pressed && repeats && self.timing.interval_ticks > Ticks::ZERO {
+            self.repeating = Some(report);
+            self.next_repeat_at = now + self.timing.delay_ticks;
+        } else if !pressed && self.repeating.is_some_and(|held| held.keycode == keycode) {
+            self.repeating = None;
+        }
+        Some(report)
+    }
+
+    /// Called every timer tick. Returns a repeat of the held key, stamped
+    /// `captured_at`, when one is due.
+    pub fn tick(&mut self, now: Ticks, captured_at: u64) -> Option<KeyReport> {
+        let held = self.repeating?;
+        if now < self.next_repeat_at {
+            return None;
+        }
+        // A reader that stalled gets one repeat, not a burst of them.
+        self.next_repeat_at = now + self.timing.interval_ticks;
+        Some(KeyReport { repeat: true, captured_at, ..held })
+    }
+}
+
+/// Where an LED update stands. The keyboard acknowledges the command byte and
+/// then the value byte; those acks arrive on IRQ 1 like key data, so the
+/// interrupt handler steps through this instead of polling for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedUpdate {
+    Idle { shown: Option<u8> }, // None: unknown, e.g. after a timeout
+    AwaitCommandAck { leds: u8, since: Ticks },
+    AwaitValueAck { leds: u8, since: Ticks },
+}
+
+struct Keyboard {
+    decoder: ScancodeDecoder,
+    state: KeyboardState,
+    led: LedUpdate,
+}
+
+impl Keyboard {
+    /// Consumes the byte if it answers the LED update in flight.
+    fn led_reply(&mut self, byte: u8, now: Ticks) -> bool {
+        match (self.led, byte) {
+            (LedUpdate::AwaitCommandAck { leds, .. }, ACK) => {
+                controller::write_data(leds);
+                self.led = LedUpdate::AwaitValueAck { leds, since: now };
+            },
+            (LedUpdate::AwaitCommandAck { .. }, RESEND) => {
+                controller::write_data(KEYBOARD_SET_LEDS);
+            },
+            (LedUpdate::AwaitValueAck { leds, .. }, ACK) => {
+                self.led = LedUpdate::Idle { shown: Some(leds) };
+            },
+            (LedUpdate::AwaitValueAck { leds, .. }, RESEND) => {
+                controller::write_data(leds);
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Starts an LED update if the LEDs are out of date and none is in flight.
+    fn sync_leds(&mut self, now: Ticks) {
+        let leds = self.state.leds();
+        if let LedUpdate::Idle { shown } = self.led {
+            if shown != Some(leds) && controller::write_data(KEYBOARD_SET_LEDS) {
+                self.led = LedUpdate::AwaitCommandAck { leds, since: now };
+            }
+        }
+    }
+
+    fn expire_led_update(&mut self, now: Ticks) {
+        let since = match self.led {
+            LedUpdate::AwaitCommandAck { since, .. } | LedUpdate::AwaitValueAck { since, .. } => since,
+            LedUpdate::Idle { .. } => return,
+        };
+        if now.saturating_sub(since) > LED_ACK_TIMEOUT_TICKS {
+            self.led = LedUpdate::Idle { shown: None };
+        }
+    }
+}
+
+/// Driver state, present once `init` found a keyboard port.
+static KEYBOARD: Mutex<Option<Keyboard>> = Mutex::new(None);
+
+/// Enables the keyboard port and its interrupt. Whether the controller
+/// translates to set 1 decides which set the decoder expects. Returns false if
+/// the controller didn't answer.
+pub fn init() -> bool {
+    let config = match controller::read_config() {
+        Some(config) => config,
+        None => {
+            kprintln!("[kernel] ps2_keyboard: Could not read controller configuration, keyboard disabled.");
+            return false;
+        }
+    };
+    let set = if config & CONFIG_TRANSLATION != 0 { ScancodeSet::One } else { ScancodeSet::Two };
+    let config = (config | CONFIG_FIRST_IRQ) & !CONFIG_FIRST_CLOCK_DISABLED;
+    if !controller::write_command(CMD_ENABLE_FIRST) || !controller::write_config(config) {
+        kprintln!("[kernel] ps2_keyboard: Controller not responding, keyboard disabled.");
+        return false;
+    }
+
+    let timing = RepeatTiming::from_settings(DEFAULT_REPEAT_DELAY_MS, DEFAULT_REPEAT_RATE_HZ);
+    *KEYBOARD.lock() = Some(Keyboard {
+        decoder: ScancodeDecoder::new(set),
+        state: KeyboardState::new(Layout::default(), timing),
+        led: LedUpdate::Idle { shown: None },
+    });
+    kprintln!("[kernel] ps2_keyboard: Initialized (scancode set {}).", if set == ScancodeSet::One { 1 } else { 2 });
+    true
+}
+
+/// Called from the IRQ 1 handler. Reads the pending byte and queues an event
+/// once it completes a scancode.
+pub fn handle_interrupt() {
+    // Stamped first, like every other input IRQ (see `irq::handle_irq`).
+    let now = timer::get_current_ticks();
+    let captured_at = timer::nanos();
+    let status = controller::status();
+    if status & STATUS_OUTPUT_FULL == 0 || status & STATUS_AUX_DATA != 0 {
+        return; // Nothing pending, or a mouse byte the IRQ 12 handler will read
+    }
+    let byte = controller::read_pending();
+    let mut guard = KEYBOARD.lock();
+    let keyboard = match guard.as_mut() {
+        Some(keyboard) => keyboard,
+        None => return,
+    };
+    if keyboard.led_reply(byte, now) {
+        return;
+    }
+    let report = keyboard.decoder.push(byte, now).and_then(|transition| keyboard.state.handle(transition, now, captured_at));
+    if let Some(report) = report {
+        input::push_key(report);
+    }
+    keyboard.sync_leds(now);
+}
+
+/// Called from the timer interrupt: generates key repeats and gives up on LED
+/// updates the keyboard never acknowledged.
+pub fn on_tick(now: Ticks) {
+    if let Some(keyboard) = KEYBOARD.lock().as_mut() {
+        keyboard.expire_led_update(now);
+        if let Some(report) = keyboard.state.tick(now, timer::nanos()) {
+            input::push_key(report);
+        }
+    }
+}
+
+/// Applies the user's repeat delay and rate and layout (`SYS_INPUT_CONFIG`).
+/// Returns false for values out of range.
+pub fn configure(delay_ms: u64, rate_hz: u64, layout: u64) -> bool {
+    let layout = match Layout::from_id(layout) {
+        Some(layout) => layout,
+        None => return false,
+    };
+    if delay_ms > KEY_REPEAT_MAX_DELAY_MS || rate_hz > KEY_REPEAT_MAX_RATE_HZ {
+        return false;
+    }
+    let timing = RepeatTiming::from_settings(delay_ms, rate_hz);
+    // The interrupt handlers take the same lock; keep them from firing while we hold it.
+    interrupts::without_interrupts(|| {
+        if let Some(keyboard) = KEYBOARD.lock().as_mut() {
+            keyboard.state.configure(layout, timing);
+        }
+    });
+    true
+}
+
+/// Partial scancodes dropped so far while resynchronizing, for `sysmon`.
+pub fn resyncs() -> u64 {
+    interrupts::without_interrupts(|| KEYBOARD.lock().as_ref().map_or(0, |keyboard| keyboard.decoder.resyncs()))
+}
@@ -0,0 +1,180 @@
+// kernel/src/drivers/ps2_mouse.rs
+
+#![allow(dead_code)]
+
+//! PS/2 mouse on the auxiliary port of the 8042 controller (IRQ 12).
+//!
+//! The interrupt handler reads one byte per interrupt and feeds it to a
+//! `PacketDecoder`. Completed packets are queued in `drivers::input` for the
+//! display owner to read with `SYS_INPUT_READ`.
+//!
+//! A standard mouse sends 3-byte packets. If the IntelliMouse knock sequence
+//! (sample rates 200, 100, 80) makes it report device ID 3, it has a scroll
+//! wheel and sends a fourth byte with the wheel movement.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use common::abi::MouseReport;
+use crate::{kprintln, timer};
+use crate::timer::Ticks;
+use super::input;
+use super::ps2_controller::{self as controller, CMD_ENABLE_AUX, CMD_WRITE_AUX, CONFIG_AUX_CLOCK_DISABLED, CONFIG_AUX_IRQ, STATUS_AUX_DATA, STATUS_OUTPUT_FULL};
+
+/// IRQ line of the PS/2 auxiliary port.
+pub const PS2_MOUSE_IRQ: u8 = 12;
+
+const MOUSE_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xF3;
+const MOUSE_GET_ID: u8 = 0xF2;
+const MOUSE_ID_WHEEL: u8 = 3;
+
+/// A byte arriving more than this many ticks after the previous one starts a
+/// new packet. Bytes of one packet arrive well within a millisecond.
+const PACKET_GAP_TICKS: Ticks = Ticks::from_raw(2);
+
+// Flags in the first byte of every packet.
+const FLAG_ALWAYS_ONE: u8 = 1 << 3;
+const FLAG_X_SIGN: u8 = 1 << 4;
+const FLAG_Y_SIGN: u8 = 1 << 5;
+const FLAG_X_OVERFLOW: u8 = 1 << 6;
+const FLAG_Y_OVERFLOW: u8 = 1 << 7;
+const BUTTON_MASK: u8 = 0x07;
+
+/// Reassembles mouse packets from the byte stream.
+pub struct PacketDecoder {
+    bytes: [u8; 4],
+    len: usize,
+    packet_len: usize, // 3, or 4 with a scroll wheel
+    last_byte_at: Ticks,
+    resyncs: u64,
+}
+
+impl PacketDecoder {
+    pub const fn new(has_wheel: bool) -> Self {
+        Self { bytes: [0; 4], len: 0, packet_len: if has_wheel { 4 } else { 3 }, last_byte_at: Ticks::ZERO, resyncs: 0 }
+    }
+
+    /// Adds one byte received at tick `now` and stamped `captured_at`
+    /// (`timer::nanos`). Returns the report once a packet is complete.
+    ///
+    /// A byte that can't start a packet (bit 3 clear) is dropped, and so is a
+    /// partial packet that went quiet. Without this, one lost byte would shift
+    /// every later packet and turn movement into random clicks.
+    pub fn push(&mut self, byte: u8, now: Ticks, captured_at: u64) -> Option<MouseReport> {
+        if self.len > 0 && now.saturating_sub(self.last_byte_at) > PACKET_GAP_TICKS {
+            self.len = 0;
+            self.resyncs += 1;
+        }
+        self.last_byte_at = now;
+        if self.len == 0 && byte & FLAG_ALWAYS_ONE == 0 {
+            self.resyncs += 1;
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_len {
+            return None;
+        }
+        self.len = 0;
+        Some(self.decode(captured_at))
+    }
+
+    fn decode(&self, captured_at: u64) -> MouseReport {
+        let flags = self.bytes[0];
+        // 9-bit two's complement: the sign bit lives in the flags byte. An
+        // overflowed axis carries garbage, so it reports no movement.
+        let axis = |value: u8, sign: u8, overflow: u8| -> i16 {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 256
+            } else {
+                value as i16
+            }
+        };
+        let dx = axis(self.bytes[1], FLAG_X_SIGN, FLAG_X_OVERFLOW);
+        let dy = axis(self.bytes[2], FLAG_Y_SIGN, FLAG_Y_OVERFLOW);
+        let wheel = if self.packet_len == 4 { self.bytes[3] as i8 } else { 0 };
+        // PS/2 y grows upwards; screen y grows downwards.
+        MouseReport { dx, dy: -dy, wheel, buttons: flags & BUTTON_MASK, captured_at }
+    }
+
+    /// Packets abandoned to get back in sync with the mouse.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+}
+
+/// Decoder state, present once `init` found a mouse.
+static DECODER: Mutex<Option<PacketDecoder>> = Mutex::new(None);
+
+/// Sends a byte to the mouse and waits for its acknowledgement.
+fn mouse_command(byte: u8) -> bool {
+    controller::write_command(CMD_WRITE_AUX) && controller::write_data(byte) && controller::read_data() == Some(controller::ACK)
+}
+
+fn set_sample_rate(rate: u8) -> bool {
+    mouse_command(MOUSE_SET_SAMPLE_RATE) && mouse_command(rate)
+}
+
+/// Enables the auxiliary port and its interrupt, resets the mouse to defaults,
+/// probes for a scroll wheel and turns on data reporting. Returns false if no
+/// mouse answered; the system then simply runs without one.
+pub fn init() -> bool {
+    if !controller::write_command(CMD_ENABLE_AUX) {
+        kprintln!("[kernel] ps2_mouse: Controller not responding, mouse disabled.");
+        return false;
+    }
+    let config = match controller::read_config() {
+        Some(config) => config,
+        None => {
+            kprintln!("[kernel] ps2_mouse: Could not read controller configuration, mouse disabled.");
+            return false;
+        }
+    };
+    let config = (config | CONFIG_AUX_IRQ) & !CONFIG_AUX_CLOCK_DISABLED;
+    if !controller::write_config(config) || !mouse_command(MOUSE_SET_DEFAULTS) {
+        kprintln!("[kernel] ps2_mouse: No mouse on the auxiliary port.");
+        return false;
+    }
+
+    let has_wheel = set_sample_rate(200) && set_sample_rate(100) && set_sample_rate(80)
+        && mouse_command(MOUSE_GET_ID)
+        && controller::read_data() == Some(MOUSE_ID_WHEEL);
+    if !mouse_command(MOUSE_ENABLE_REPORTING) {
+        kprintln!("[kernel] ps2_mouse: Mouse refused to enable reporting.");
+        return false;
+    }
+
+    // The IRQ 12 handler takes the same lock; keep it from firing while we hold it.
+    interrupts::without_interrupts(|| *DECODER.lock() = Some(PacketDecoder::new(has_wheel)));
+    kprintln!("[kernel] ps2_mouse: Initialized ({}-byte packets{}).", if has_wheel { 4 } else { 3 }, if has_wheel { ", scroll wheel" } else { "" });
+    true
+}
+
+/// Called from the IRQ 12 handler. Reads the pending byte and queues a report
+/// once it completes a packet.
+pub fn handle_interrupt() {
+    // Stamped first, like every other input IRQ (see `irq::handle_irq`).
+    let now = timer::get_current_ticks();
+    let captured_at = timer::nanos();
+    let status = controller::status();
+    if status & STATUS_OUTPUT_FULL == 0 || status & STATUS_AUX_DATA == 0 {
+        return; // Nothing pending, or a keyboard byte the IRQ 1 handler will read
+    }
+    let byte = controller::read_pending();
+    let report = match DECODER.lock().as_mut() {
+        Some(decoder) => decoder.push(byte, now, captured_at),
+        None => None,
+    };
+    if let Some(report) = report {
+        input::push_mouse(report);
+    }
+}
+
+/// Packets dropped so far while resynchronizing, for `sysmon`.
+pub fn resyncs() -> u64 {
+    interrupts::without_interrupts(|| DECODER.lock().as_ref().map_or(0, PacketDecoder::resyncs))
+}
@@ -0,0 +1,63 @@
+// kernel/src/drivers/rng.rs
+
+#![allow(dead_code)]
+
+//! The CPU's random number generator, behind `SYS_RANDOM`.
+//!
+//! RDRAND is the only source so far. Without it `fill` fails rather than hand
+//! out something predictable: its callers generate keys and login nonces.
+//! QEMU's default CPU model lacks it; run with `-cpu max` or `-cpu host`.
+
+use core::arch::x86_64::{__cpuid, _rdrand64_step};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::kprintln;
+
+const CPUID_FEATURES: u32 = 1;
+const ECX_RDRAND: u32 = 1 << 30;
+/// RDRAND can fail transiently when the DRNG is drained; Intel suggests 10 tries.
+const RDRAND_RETRIES: u32 = 10;
+
+static AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    // SAFETY: CPUID leaf 1 exists on every x86_64 CPU.
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    let available = features.ecx & ECX_RDRAND != 0;
+    AVAILABLE.store(available, Ordering::SeqCst);
+    if available {
+        kprintln!("[kernel] rng: Using RDRAND.");
+    } else {
+        kprintln!("[kernel] rng: The CPU has no RDRAND; SYS_RANDOM will fail.");
+    }
+}
+
+pub fn available() -> bool {
+    AVAILABLE.load(Ordering::SeqCst)
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn next() -> Option<u64> {
+    let mut value = 0;
+    for _ in 0..RDRAND_RETRIES {
+        if _rdrand64_step(&mut value) == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Fills `out` with random bytes. False if there is no generator or it kept failing.
+pub fn fill(out: &mut [u8]) -> bool {
+    if !available() {
+        return false;
+    }
+    for chunk in out.chunks_mut(8) {
+        // SAFETY: `init` checked that the CPU supports RDRAND.
+        match unsafe { next() } {
+            Some(value) => chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]),
+            None => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,125 @@
+// kernel/src/drivers/rtc.rs
+
+#![allow(dead_code)]
+
+//! CMOS real-time clock.
+//!
+//! The RTC is read once at boot. After that the wall clock is the boot time
+//! plus the `timer::nanos` since, which is monotonic and avoids port I/O on
+//! every `SYS_CLOCK_GETTIME`. The RTC holds UTC; timezones are a display
+//! concern of user space.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use common::abi::WallClock;
+use common::time::{DateTime, RtcRegisters};
+use crate::{kprintln, timer};
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+const NMI_DISABLE: u8 = 0x80; // Set in every index write so an NMI can't interrupt the read
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_CENTURY: u8 = 0x32; // Per the ACPI FADT on most machines; 0 where absent
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+
+/// Reads before giving up on two consecutive reads agreeing.
+const READ_ATTEMPTS: u32 = 10;
+/// Status polls before an update that seems stuck is ignored.
+const POLL_LIMIT: u32 = 100_000;
+
+/// Epoch seconds read at boot and the `timer::nanos` they were read at.
+struct BootClock {
+    epoch_secs: u64,
+    nanos: u64,
+}
+
+static CLOCK: Mutex<Option<BootClock>> = Mutex::new(None);
+
+fn read_register(reg: u8) -> u8 {
+    let mut index = Port::<u8>::new(INDEX_PORT);
+    let mut data = Port::<u8>::new(DATA_PORT);
+    // SAFETY: ports 0x70/0x71 are the CMOS index and data registers.
+    unsafe {
+        index.write(NMI_DISABLE | reg);
+        data.read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+fn read_raw() -> RtcRegisters {
+    for _ in 0..POLL_LIMIT {
+        if !update_in_progress() {
+            break;
+        }
+    }
+    RtcRegisters {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: read_register(REG_CENTURY),
+    }
+}
+
+/// Reads the RTC until two consecutive reads agree, so a read can't straddle
+/// an update (e.g. 23:59:59 becoming 00:00:00 halfway through).
+fn read_stable() -> Option<DateTime> {
+    let mut previous = read_raw();
+    for _ in 0..READ_ATTEMPTS {
+        let current = read_raw();
+        if current == previous {
+            return Some(current.decode(read_register(REG_STATUS_B)));
+        }
+        previous = current;
+    }
+    None
+}
+
+/// Reads the RTC and starts the wall clock. Returns false if the RTC reads
+/// back garbage; `now` then returns `None`.
+pub fn init() -> bool {
+    let date = match read_stable() {
+        Some(date) => date,
+        None => {
+            kprintln!("[kernel] rtc: Readings never settled; no wall clock.");
+            return false;
+        }
+    };
+    let epoch_secs = match date.to_epoch() {
+        Some(secs) => secs,
+        None => {
+            kprintln!("[kernel] rtc: Invalid date {:?}; no wall clock.", date);
+            return false;
+        }
+    };
+    *CLOCK.lock() = Some(BootClock { epoch_secs, nanos: timer::nanos() });
+    kprintln!("[kernel] rtc: {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC.",
+        date.year, date.month, date.day, date.hour, date.minute, date.second);
+    true
+}
+
+/// The current wall-clock time, or `None` if `init` found no usable RTC.
+pub fn now() -> Option<WallClock> {
+    let clock = CLOCK.lock();
+    let clock = clock.as_ref()?;
+    let elapsed = timer::nanos().saturating_sub(clock.nanos);
+    Some(WallClock {
+        secs: clock.epoch_secs + elapsed / timer::NANOS_PER_SECOND,
+        nanos: (elapsed % timer::NANOS_PER_SECOND) as u32,
+    })
+}
@@ -38,4 +38,10 @@ pub fn _print(args: fmt::Arguments) {
     let _ = SERIAL1.lock().write_fmt(args);
 }
 
-
+/// Steals the serial port lock on the panic path.
+pub fn panic_takeover() {
+    if SERIAL1.is_locked() {
+        // SAFETY: The panicking CPU never returns to the interrupted lock holder.
+        unsafe { SERIAL1.force_unlock(); }
+    }
+}
@@ -2,22 +2,356 @@
 
 #![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
 
+//! ELF64 loader for V-Node binaries.
+//!
+//! Two kinds of binaries are accepted:
+//!
+//! * `ET_EXEC`: linked at a fixed virtual address and loaded there as is.
+//! * `ET_DYN` (PIE): linked at 0 and loaded at a base chosen by the caller.
+//!   The loader applies the `R_X86_64_RELATIVE` relocations of the dynamic
+//!   segment (`*(base + offset) = base + addend`) and shifts the entry point
+//!   and every segment by the base.
+//!
+//! There is no dynamic linker. A PIE must be self-contained: any relocation
+//! that needs a symbol (GOT/PLT entries, `R_X86_64_64` against a symbol),
+//! a `DT_NEEDED` library or a `PT_INTERP` request is rejected with an error
+//! naming the problem.
+
 extern crate alloc;
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::string::{String, ToString};
 use crate::kprintln;
 use crate::aetherfs; // To interact with aetherfs for loading binaries
 
-/// Placeholder for an ELF header structure.
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_X86_64: u16 = 62;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const DYN_ENTRY_SIZE: usize = 16;
+const RELA_ENTRY_SIZE: usize = 24;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_PLTRELSZ: u64 = 2;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const DT_RELSZ: u64 = 18;
+const DT_JMPREL: u64 = 23;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_RELATIVE: u32 = 8;
+
+/// Page size segments are aligned to.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Whether the binary must be loaded at its link address or can be relocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfType {
+    /// `ET_EXEC`: fixed addresses.
+    Executable,
+    /// `ET_DYN`: position-independent.
+    PositionIndependent,
+}
+
+/// The fields of the ELF header the loader uses.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ElfHeader {
+    pub elf_type: ElfType,
     pub entry_point: u64,
     pub program_headers_offset: u64,
+    pub program_header_size: u16,
     pub num_program_headers: u16,
-    // Add more fields as needed
 }
 
-/// A conceptual ELF loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+/// A loaded segment, at its final address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub memsz: u64,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// A binary laid out in memory, ready to be mapped into a task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    pub elf_type: ElfType,
+    /// Added to every link-time address; 0 for `ET_EXEC`.
+    pub load_base: u64,
+    /// Final address of the entry point.
+    pub entry_point: u64,
+    pub segments: Vec<Segment>,
+    /// Final address of `memory[0]`, page-aligned.
+    pub image_start: u64,
+    /// Segment contents with relocations applied. Gaps between segments and
+    /// the part of each segment past its file size are zero.
+    pub memory: Vec<u8>,
+    /// `R_X86_64_RELATIVE` relocations applied.
+    pub relocations: usize,
+}
+
+/// Page-aligned size of the address range the binary's `PT_LOAD` segments
+/// span, so a caller can reserve room for it before picking a base.
+pub fn image_size(elf_data: &[u8]) -> Result<u64, String> {
+    let header = parse_elf_header(elf_data)?;
+    let program_headers = parse_program_headers(elf_data, &header)?;
+    let (start, end) = load_range(&program_headers)?;
+    Ok(end - start)
+}
+
+/// Lays out and relocates `elf_data`. `base` is the load base for a PIE and
+/// is ignored for `ET_EXEC`; it must be page-aligned.
+pub fn load_image(elf_data: &[u8], base: u64) -> Result<LoadedImage, String> {
+    let header = parse_elf_header(elf_data)?;
+    let program_headers = parse_program_headers(elf_data, &header)?;
+    if program_headers.iter().any(|ph| ph.p_type == PT_INTERP) {
+        return Err("Binary requests a program interpreter (PT_INTERP); only self-contained executables are supported.".to_string());
+    }
+
+    let load_base = match header.elf_type {
+        ElfType::Executable => 0,
+        ElfType::PositionIndependent => {
+            if base % PAGE_SIZE != 0 {
+                return Err(format!("Load base {:#x} is not page-aligned.", base));
+            }
+            base
+        }
+    };
+    let (start, end) = load_range(&program_headers)?;
+    let image_start = load_base.checked_add(start).ok_or_else(|| format!("Load base {:#x} overflows the address space.", load_base))?;
+    image_start.checked_add(end - start).ok_or_else(|| format!("Image at {:#x} overflows the address space.", image_start))?;
+
+    let mut memory = vec![0u8; (end - start) as usize];
+    let mut segments = Vec::new();
+    for ph in program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        let file_end = ph.offset.checked_add(ph.filesz).filter(|end| *end <= elf_data.len() as u64)
+            .ok_or_else(|| format!("Segment at {:#x} extends past the end of the file.", ph.vaddr))?;
+        let dest = (ph.vaddr - start) as usize;
+        memory[dest..dest + ph.filesz as usize].copy_from_slice(&elf_data[ph.offset as usize..file_end as usize]);
+        segments.push(Segment {
+            vaddr: load_base + ph.vaddr,
+            memsz: ph.memsz,
+            readable: ph.flags & PF_R != 0,
+            writable: ph.flags & PF_W != 0,
+            executable: ph.flags & PF_X != 0,
+        });
+    }
+
+    let relocations = match program_headers.iter().find(|ph| ph.p_type == PT_DYNAMIC) {
+        Some(dynamic) => apply_relocations(&mut memory, start, load_base, dynamic)?,
+        None => 0, // Statically linked with nothing to relocate
+    };
+
+    Ok(LoadedImage {
+        elf_type: header.elf_type,
+        load_base,
+        entry_point: load_base + header.entry_point,
+        segments,
+        image_start,
+        memory,
+        relocations,
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    data.get(offset..offset + 8).map(|b| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(b);
+        u64::from_le_bytes(bytes)
+    })
+}
+
+/// Validates the identification bytes and machine, and reads the header fields.
+fn parse_elf_header(elf_data: &[u8]) -> Result<ElfHeader, String> {
+    if elf_data.len() < ELF_HEADER_SIZE {
+        return Err("ELF file too small to contain header.".to_string());
+    }
+    if elf_data[0..4] != ELF_MAGIC {
+        return Err("Not an ELF file (bad magic).".to_string());
+    }
+    if elf_data[4] != ELFCLASS64 || elf_data[5] != ELFDATA2LSB {
+        return Err("Only little-endian ELF64 binaries are supported.".to_string());
+    }
+    let machine = read_u16(elf_data, 18).unwrap_or(0);
+    if machine != EM_X86_64 {
+        return Err(format!("Unsupported machine type {} (expected x86_64).", machine));
+    }
+    let elf_type = match read_u16(elf_data, 16).unwrap_or(0) {
+        ET_EXEC => ElfType::Executable,
+        ET_DYN => ElfType::PositionIndependent,
+        other => return Err(format!("Unsupported ELF type {} (expected ET_EXEC or ET_DYN).", other)),
+    };
+    let header = ElfHeader {
+        elf_type,
+        entry_point: read_u64(elf_data, 24).unwrap_or(0),
+        program_headers_offset: read_u64(elf_data, 32).unwrap_or(0),
+        program_header_size: read_u16(elf_data, 54).unwrap_or(0),
+        num_program_headers: read_u16(elf_data, 56).unwrap_or(0),
+    };
+    if (header.program_header_size as usize) < PROGRAM_HEADER_SIZE {
+        return Err(format!("Program header size {} is too small.", header.program_header_size));
+    }
+    Ok(header)
+}
+
+fn parse_program_headers(elf_data: &[u8], header: &ElfHeader) -> Result<Vec<ProgramHeader>, String> {
+    (0..header.num_program_headers as usize).map(|i| {
+        let offset = (header.program_headers_offset as usize).checked_add(i * header.program_header_size as usize)
+            .filter(|offset| offset + PROGRAM_HEADER_SIZE <= elf_data.len())
+            .ok_or_else(|| format!("Program header {} extends past the end of the file.", i))?;
+        let ph = ProgramHeader {
+            p_type: read_u32(elf_data, offset).unwrap_or(0),
+            flags: read_u32(elf_data, offset + 4).unwrap_or(0),
+            offset: read_u64(elf_data, offset + 8).unwrap_or(0),
+            vaddr: read_u64(elf_data, offset + 16).unwrap_or(0),
+            filesz: read_u64(elf_data, offset + 32).unwrap_or(0),
+            memsz: read_u64(elf_data, offset + 40).unwrap_or(0),
+        };
+        if ph.p_type == PT_LOAD && ph.filesz > ph.memsz {
+            return Err(format!("Segment at {:#x} has a file size larger than its memory size.", ph.vaddr));
+        }
+        Ok(ph)
+    }).collect()
+}
+
+/// The page-aligned link-time range covered by the `PT_LOAD` segments.
+fn load_range(program_headers: &[ProgramHeader]) -> Result<(u64, u64), String> {
+    let mut range: Option<(u64, u64)> = None;
+    for ph in program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        let end = ph.vaddr.checked_add(ph.memsz).ok_or_else(|| format!("Segment at {:#x} overflows the address space.", ph.vaddr))?;
+        range = Some(match range {
+            Some((start, stop)) => (start.min(ph.vaddr), stop.max(end)),
+            None => (ph.vaddr, end),
+        });
+    }
+    let (start, end) = range.ok_or_else(|| "Binary has no loadable segments.".to_string())?;
+    let start = start & !(PAGE_SIZE - 1);
+    let end = end.checked_add(PAGE_SIZE - 1).ok_or_else(|| "Image overflows the address space.".to_string())? & !(PAGE_SIZE - 1);
+    Ok((start, end))
+}
+
+/// Name of a relocation type for error messages.
+fn relocation_name(r_type: u32) -> &'static str {
+    match r_type {
+        1 => "R_X86_64_64",
+        2 => "R_X86_64_PC32",
+        5 => "R_X86_64_COPY",
+        6 => "R_X86_64_GLOB_DAT",
+        7 => "R_X86_64_JUMP_SLOT",
+        9 => "R_X86_64_GOTPCREL",
+        16 => "R_X86_64_DTPMOD64",
+        18 => "R_X86_64_TPOFF64",
+        37 => "R_X86_64_IRELATIVE",
+        _ => "unknown",
+    }
+}
+
+/// Reads the dynamic segment and applies its `RELA` table to `memory`, which
+/// holds the image starting at link-time address `start`. Returns the number
+/// of relocations applied.
+fn apply_relocations(memory: &mut [u8], start: u64, load_base: u64, dynamic: &ProgramHeader) -> Result<usize, String> {
+    // The dynamic segment is read from the laid-out image, where it lives at its vaddr.
+    let read_image_u64 = |memory: &[u8], vaddr: u64| -> Option<u64> {
+        vaddr.checked_sub(start).and_then(|offset| read_u64(memory, offset as usize))
+    };
+
+    let mut rela = None;
+    let mut rela_size = 0;
+    let mut rela_entry = RELA_ENTRY_SIZE as u64;
+    let mut index = 0;
+    loop {
+        let entry = dynamic.vaddr + (index * DYN_ENTRY_SIZE) as u64;
+        if (index + 1) * DYN_ENTRY_SIZE > dynamic.memsz as usize {
+            return Err("Dynamic segment is not terminated by DT_NULL.".to_string());
+        }
+        let tag = read_image_u64(memory, entry).ok_or_else(|| "Dynamic segment lies outside the loaded image.".to_string())?;
+        let value = read_image_u64(memory, entry + 8).ok_or_else(|| "Dynamic segment lies outside the loaded image.".to_string())?;
+        match tag {
+            DT_NULL => break,
+            DT_NEEDED => return Err("Binary depends on a shared library (DT_NEEDED); only self-contained executables are supported.".to_string()),
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry = value,
+            DT_REL | DT_RELSZ => return Err("REL relocations are not supported on x86_64; expected RELA.".to_string()),
+            DT_JMPREL | DT_PLTRELSZ if value != 0 => return Err("Binary has PLT relocations (DT_JMPREL), which need symbol resolution.".to_string()),
+            _ => {}
+        }
+        index += 1;
+    }
+
+    let rela = match rela {
+        Some(rela) if rela_size > 0 => rela,
+        _ => return Ok(0),
+    };
+    if rela_entry != RELA_ENTRY_SIZE as u64 {
+        return Err(format!("Unexpected RELA entry size {}.", rela_entry));
+    }
+
+    let mut applied = 0;
+    for i in 0..rela_size / RELA_ENTRY_SIZE as u64 {
+        let entry = rela + i * RELA_ENTRY_SIZE as u64;
+        let truncated = || format!("Relocation {} lies outside the loaded image.", i);
+        let r_offset = read_image_u64(memory, entry).ok_or_else(truncated)?;
+        let r_info = read_image_u64(memory, entry + 8).ok_or_else(truncated)?;
+        let r_addend = read_image_u64(memory, entry + 16).ok_or_else(truncated)?;
+        let (r_type, symbol) = ((r_info & 0xFFFF_FFFF) as u32, r_info >> 32);
+        match r_type {
+            R_X86_64_NONE => continue,
+            R_X86_64_RELATIVE if symbol == 0 => {}
+            R_X86_64_RELATIVE => return Err(format!("Relocation {} is R_X86_64_RELATIVE but references symbol {}.", i, symbol)),
+            other if symbol != 0 => return Err(format!(
+                "Relocation {} ({}, type {}) at {:#x} references undefined symbol {}; only R_X86_64_RELATIVE is supported.",
+                i, relocation_name(other), other, r_offset, symbol)),
+            other => return Err(format!(
+                "Unsupported relocation {} ({}, type {}) at {:#x}; only R_X86_64_RELATIVE is supported.",
+                i, relocation_name(other), other, r_offset)),
+        }
+        let target = r_offset.checked_sub(start).map(|offset| offset as usize)
+            .filter(|offset| offset + 8 <= memory.len())
+            .ok_or_else(|| format!("Relocation {} targets {:#x}, outside the loaded image.", i, r_offset))?;
+        let value = load_base.wrapping_add(r_addend);
+        memory[target..target + 8].copy_from_slice(&value.to_le_bytes());
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// The ELF loader.
 pub struct ElfLoader {
     _private: (),
 }
@@ -25,54 +359,33 @@ pub struct ElfLoader {
 impl ElfLoader {
     /// Initializes the ELF loader.
     pub fn init() {
-        kprintln!("[kernel] elf: Initializing ElfLoader (conceptual)...");
-        // TODO: Any setup required for ELF parsing, e.g., memory regions for loading.
+        kprintln!("[kernel] elf: Initializing ElfLoader...");
         kprintln!("[kernel] elf: ElfLoader initialized.");
     }
 
-    /// Conceptually loads an ELF binary from the given path.
-    /// It would read the file from AetherFS, parse its header, and load segments.
-    pub fn load_elf(path: &str) -> Result<ElfHeader, String> {
-        kprintln!("[kernel] elf: Conceptually loading ELF from: {}.", path);
-
-        // Simulate reading the ELF binary from AetherFS.
-        let elf_data = match aetherfs::read_file(path) {
-            Ok(data) => data,
-            Err(e) => return Err(format!("Failed to read ELF file '{}': {}", path, e)),
-        };
-
-        if elf_data.len() < core::mem::size_of::<ElfHeader>() { // Simplified check
-            return Err("ELF file too small to contain header.".to_string());
-        }
-
-        // Simulate parsing the ELF header.
-        let header = Self::parse_elf_header(&elf_data)?;
-        kprintln!("[kernel] elf: Parsed ELF header: {:?}.", header);
+    /// Reads an ELF binary from AetherFS and lays it out at `base` (see `load_image`).
+    pub fn load_elf(path: &str, base: u64) -> Result<LoadedImage, String> {
+        kprintln!("[kernel] elf: Loading ELF from: {}.", path);
+        let elf_data = Self::read(path)?;
+        let image = load_image(&elf_data, base)?;
+        kprintln!("[kernel] elf: Loaded {:?} image: {} segments, {} relocations, entry {:#x}.",
+            image.elf_type, image.segments.len(), image.relocations, image.entry_point);
 
         // TODO: In a real loader:
-        // 1. Map program segments into virtual memory.
+        // 1. Map `image.memory` into the task's address space with each segment's permissions.
         // 2. Set up initial stack and arguments.
-        // 3. Create a new task (V-Node) for the loaded ELF.
 
-        Ok(header)
+        Ok(image)
     }
 
-    /// Conceptually parses an ELF header from a byte slice.
-    fn parse_elf_header(elf_data: &[u8]) -> Result<ElfHeader, String> {
-        kprintln!("[kernel] elf: Parsing conceptual ELF header...");
-        // This is a highly simplified stub. A real parser would validate magic numbers,
-        // architecture, and properly deserialize the header fields.
-
-        // For simulation, assume a valid 64-bit ELF executable.
-        // Dummy values.
-        let entry_point = 0x1000000; // Example entry point
-        let program_headers_offset = 0x40;
-        let num_program_headers = 2;
+    /// Reads the binary at `path` and returns its header and image size, so the
+    /// caller can pick a load base before `load_elf`.
+    pub fn inspect(path: &str) -> Result<(ElfHeader, u64), String> {
+        let elf_data = Self::read(path)?;
+        Ok((parse_elf_header(&elf_data)?, image_size(&elf_data)?))
+    }
 
-        Ok(ElfHeader {
-            entry_point,
-            program_headers_offset,
-            num_program_headers,
-        })
+    fn read(path: &str) -> Result<Vec<u8>, String> {
+        aetherfs::read_file(path).map_err(|e| format!("Failed to read ELF file '{}': {}", path, e))
     }
 }
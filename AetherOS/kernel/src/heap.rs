@@ -8,11 +8,39 @@ use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB, Mapper, FrameAl
 use crate::kprintln;
 use crate::memory::page_allocator::PageAllocator;
 
+#[cfg(feature = "heap-debug")]
+mod debug;
+#[cfg(feature = "heap-debug")]
+pub use debug::{SiteStats, FREE_POISON};
+#[cfg(feature = "heap-debug")]
+pub(crate) use debug::return_address;
+
 /// A dummy global allocator that panics on allocation.
 /// This will be replaced by our `LockedHeap` once memory mapping is ready.
+#[cfg(not(feature = "heap-debug"))]
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+/// With `heap-debug`, the same heap behind canaries, poisoning and site tags (see `heap::debug`).
+#[cfg(feature = "heap-debug")]
+#[global_allocator]
+static ALLOCATOR: debug::DebugHeap = debug::DebugHeap::empty();
+
+/// Allocation sites listed by `stats()` in `heap-debug` builds.
+#[cfg(feature = "heap-debug")]
+pub const TOP_SITES: usize = 8;
+
+/// Heap usage, as reported by `stats()`.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize, // Including `heap-debug` headers, canaries and quarantined blocks
+    pub free: usize,
+    /// The sites holding the most live bytes, largest first.
+    #[cfg(feature = "heap-debug")]
+    pub top_sites: [SiteStats; TOP_SITES],
+}
+
 /// Initializes the heap allocator.
 ///
 /// This function is unsafe because the caller must guarantee that the given
@@ -23,4 +51,16 @@ pub unsafe fn init(heap_start: VirtAddr, heap_size: usize) {
     kprintln!("[kernel] heap: Initialized heap at {:#x} with size {} bytes.", heap_start.as_u64(), heap_size);
 }
 
-
+pub fn stats() -> HeapStats {
+    let (size, used, free) = {
+        let heap = ALLOCATOR.lock();
+        (heap.size(), heap.used(), heap.free())
+    };
+    HeapStats {
+        size,
+        used,
+        free,
+        #[cfg(feature = "heap-debug")]
+        top_sites: ALLOCATOR.top_sites(),
+    }
+}
@@ -0,0 +1,245 @@
+// kernel/src/heap/debug.rs
+
+//! The `heap-debug` allocator, which wraps the kernel heap to catch memory
+//! corruption where it happens instead of wherever it surfaces later.
+//!
+//! Every allocation is laid out as `[padding][Header][data][tail canary]`:
+//!
+//! *   The header ends in a magic value right before the data, so a write in
+//!     front of the data (underflow) breaks it. `dealloc` checks it, and the
+//!     tail canary behind the data (overflow), before anything else.
+//! *   A freed block's header is marked freed and its data filled with
+//!     `FREE_POISON`. The block then waits in a quarantine before it goes back
+//!     to the heap. A second free finds the freed mark; a write through a
+//!     stale pointer changes the poison, which is checked when the block
+//!     leaves the quarantine to be reused.
+//! *   The header records the allocation site and owning task. Reports name
+//!     both before panicking, and live bytes are summed per site for
+//!     `heap::stats()`.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use linked_list_allocator::{Heap, LockedHeap};
+use spin::{Mutex, MutexGuard};
+
+use crate::kerrorln;
+use crate::task::scheduler;
+use crate::task::tcb::TaskId;
+
+/// Written over freed data.
+pub const FREE_POISON: u8 = 0xDF;
+const LIVE_MAGIC: u64 = 0xA11C_A7ED_B10C_CAFE;
+const FREED_MAGIC: u64 = 0xF8EE_DB10_C0DE_DEAD;
+const TAIL_CANARY: u64 = 0x5AFE_5AFE_5AFE_5AFE;
+
+const TAIL_SIZE: usize = core::mem::size_of::<u64>();
+/// Freed blocks held back from reuse, oldest first out.
+const QUARANTINE_LEN: usize = 32;
+/// Allocation sites tracked individually; the rest are summed under site 0.
+const MAX_SITES: usize = 64;
+/// Frames between `alloc` and the code that asked for memory: the
+/// `__rust_alloc` shim and `alloc::alloc::alloc`.
+const SITE_DEPTH: usize = 2;
+
+/// The return address `$depth` frames above the function this expands in
+/// (0 is its own caller), or 0 if the frame chain ends first. Follows saved
+/// frame pointers, so the kernel has to be built with
+/// `-C force-frame-pointers=yes` for the answer to mean anything. Frames
+/// that don't lie above the previous one on the stack end the walk instead
+/// of being dereferenced.
+macro_rules! return_address {
+    ($depth:expr) => {{
+        let mut frame: usize;
+        // SAFETY: Reads a register.
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack, preserves_flags)); }
+        let mut address = 0usize;
+        for _ in 0..=$depth {
+            if frame == 0 || frame % 8 != 0 {
+                address = 0;
+                break;
+            }
+            // SAFETY: `frame` is the current frame pointer or a saved one that
+            // lies a short way above it on the same stack.
+            let (next, ret) = unsafe { (*(frame as *const usize), *((frame + 8) as *const usize)) };
+            address = ret;
+            frame = if next > frame && next - frame < 64 * 1024 { next } else { 0 };
+        }
+        address
+    }};
+}
+pub(crate) use return_address;
+
+#[repr(C)]
+struct Header {
+    size: usize,
+    site: usize,
+    owner: TaskId,
+    magic: u64, // Last, so it sits right in front of the data
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+/// Live bytes and allocations attributed to one allocation site.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SiteStats {
+    /// Return address of the allocating code; resolve it with `addr2line`.
+    pub site: usize,
+    pub live_bytes: usize,
+    pub live_allocations: usize,
+}
+
+struct State {
+    quarantine: [Option<(usize, Layout)>; QUARANTINE_LEN], // Data address and caller's layout
+    next: usize, // Quarantine slot to fill (and evict) next
+    sites: [SiteStats; MAX_SITES],
+}
+
+impl State {
+    fn site_mut(&mut self, site: usize) -> &mut SiteStats {
+        let index = self.sites.iter().position(|entry| entry.site == site && entry.live_allocations > 0)
+            .or_else(|| self.sites.iter().position(|entry| entry.live_allocations == 0))
+            .unwrap_or(0);
+        let entry = &mut self.sites[index];
+        if entry.live_allocations == 0 {
+            *entry = SiteStats { site, ..SiteStats::default() };
+        } else if entry.site != site {
+            entry.site = 0; // Table full: slot 0 becomes "elsewhere"
+        }
+        entry
+    }
+
+    fn release_site(&mut self, site: usize, size: usize) {
+        let index = self.sites.iter().position(|entry| entry.site == site && entry.live_allocations > 0)
+            .or_else(|| self.sites.iter().position(|entry| entry.site == 0 && entry.live_allocations > 0));
+        if let Some(index) = index {
+            let entry = &mut self.sites[index];
+            entry.live_bytes = entry.live_bytes.saturating_sub(size);
+            entry.live_allocations -= 1;
+        }
+    }
+}
+
+pub struct DebugHeap {
+    heap: LockedHeap,
+    state: Mutex<State>,
+}
+
+/// The block actually taken from the heap for `layout`, and the offset of the data in it.
+fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(core::mem::align_of::<Header>());
+    let prefix = HEADER_SIZE.next_multiple_of(align);
+    let size = prefix.checked_add(layout.size())?.checked_add(TAIL_SIZE)?;
+    Some((Layout::from_size_align(size, align).ok()?, prefix))
+}
+
+/// Prints what was found about a block, then panics.
+fn report(problem: &str, data: usize, header: &Header) -> ! {
+    kerrorln!(
+        "[kernel] heap: {} at {:#x}: {} bytes allocated at {:#x} by task {}; detected in task {}.",
+        problem, data, header.size, header.site, header.owner, scheduler::current_task_id()
+    );
+    panic!("heap corruption: {} at {:#x}", problem, data);
+}
+
+impl DebugHeap {
+    pub const fn empty() -> Self {
+        Self {
+            heap: LockedHeap::empty(),
+            state: Mutex::new(State {
+                quarantine: [None; QUARANTINE_LEN],
+                next: 0,
+                sites: [SiteStats { site: 0, live_bytes: 0, live_allocations: 0 }; MAX_SITES],
+            }),
+        }
+    }
+
+    /// The underlying heap, as `LockedHeap::lock` gives it.
+    pub fn lock(&self) -> MutexGuard<'_, Heap> {
+        self.heap.lock()
+    }
+
+    /// The sites with the most live bytes, largest first. Unused entries are zero.
+    pub fn top_sites<const N: usize>(&self) -> [SiteStats; N] {
+        let mut sites = self.state.lock().sites;
+        sites.sort_unstable_by(|a, b| b.live_bytes.cmp(&a.live_bytes));
+        let mut top = [SiteStats::default(); N];
+        for (slot, site) in top.iter_mut().zip(sites.iter().filter(|site| site.live_allocations > 0)) {
+            *slot = *site;
+        }
+        top
+    }
+
+    /// Checks that a quarantined block is still poisoned, then returns it to the heap.
+    unsafe fn release(&self, data: usize, layout: Layout) {
+        let header = &*((data - HEADER_SIZE) as *const Header);
+        let bytes = core::slice::from_raw_parts(data as *const u8, layout.size());
+        if header.magic != FREED_MAGIC {
+            report("freed block's header modified (use after free)", data, header);
+        }
+        if let Some(offset) = bytes.iter().position(|&b| b != FREE_POISON) {
+            kerrorln!("[kernel] heap: freed data at {:#x} changed at offset {}.", data, offset);
+            report("use after free", data, header);
+        }
+        let (outer, prefix) = outer_layout(layout).expect("layout was accepted by alloc");
+        self.heap.dealloc((data - prefix) as *mut u8, outer);
+    }
+
+    /// Empties the quarantine, checking every block, so its memory can be reused.
+    unsafe fn drain_quarantine(&self) {
+        let blocks = core::mem::replace(&mut self.state.lock().quarantine, [None; QUARANTINE_LEN]);
+        for (data, layout) in blocks.into_iter().flatten() {
+            self.release(data, layout);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for DebugHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let site = return_address!(SITE_DEPTH);
+        let Some((outer, prefix)) = outer_layout(layout) else { return ptr::null_mut() };
+        let mut block = self.heap.alloc(outer);
+        if block.is_null() {
+            // Memory held in quarantine is the last reserve.
+            self.drain_quarantine();
+            block = self.heap.alloc(outer);
+            if block.is_null() {
+                return block;
+            }
+        }
+        let data = block.add(prefix);
+        let owner = scheduler::current_task_id();
+        (data.sub(HEADER_SIZE) as *mut Header).write(Header { size: layout.size(), site, owner, magic: LIVE_MAGIC });
+        (data.add(layout.size()) as *mut u64).write_unaligned(TAIL_CANARY);
+        let mut state = self.state.lock();
+        let entry = state.site_mut(site);
+        entry.live_bytes += layout.size();
+        entry.live_allocations += 1;
+        data
+    }
+
+    unsafe fn dealloc(&self, data: *mut u8, layout: Layout) {
+        let header = &mut *(data.sub(HEADER_SIZE) as *mut Header);
+        match header.magic {
+            LIVE_MAGIC => {},
+            FREED_MAGIC => report("double free", data as usize, header),
+            _ => report("header overwritten (underflow) or not a heap pointer", data as usize, header),
+        }
+        if (data.add(layout.size()) as *const u64).read_unaligned() != TAIL_CANARY {
+            report("write past the end (overflow)", data as usize, header);
+        }
+        header.magic = FREED_MAGIC;
+        ptr::write_bytes(data, FREE_POISON, layout.size());
+
+        let evicted = {
+            let mut state = self.state.lock();
+            state.release_site(header.site, header.size);
+            let slot = state.next;
+            state.next = (slot + 1) % QUARANTINE_LEN;
+            state.quarantine[slot].replace((data as usize, layout))
+        };
+        if let Some((old, old_layout)) = evicted {
+            self.release(old, old_layout);
+        }
+    }
+}
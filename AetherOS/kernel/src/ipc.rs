@@ -7,9 +7,10 @@ extern crate alloc; // Still needed by mailbox, but declared in lib.rs
 use crate::{kprintln}; // kprintln still needed for init func
 
 pub mod mailbox; // Declare the new mailbox module
+pub mod call; // SYS_IPC_CALL / SYS_IPC_REPLY and their reply tokens
 
 // Re-export public items from the mailbox module to maintain the ipc facade
-pub use mailbox::{ChannelId, Message, send as kernel_send, recv as kernel_recv, peek as kernel_peek};
+pub use mailbox::{ChannelId, Message, send as kernel_send, recv as kernel_recv, recv_or_block as kernel_recv_or_block, block_unless_ready as kernel_block_unless_ready, peek as kernel_peek, claim as kernel_claim, allocate as kernel_allocate};
 
 /// Initializes the IPC module.
 pub fn init() {
@@ -0,0 +1,256 @@
+// kernel/src/ipc/call.rs
+
+//! Synchronous IPC calls: `SYS_IPC_CALL` and `SYS_IPC_REPLY`.
+//!
+//! A call queues its request like `SYS_IPC_SEND`, tagged with a fresh reply
+//! token, and blocks the caller until the reply. If the server is blocked
+//! receiving on the channel, the CPU is handed to it directly instead of
+//! going through the run queue, and its reply hands the CPU straight back.
+//! Otherwise both sides take the normal path: the server is woken and
+//! queued, and the caller is woken and queued by the reply.
+//!
+//! A token is bound to the task that received the call and is dropped once
+//! the caller has collected the outcome, so no other task can answer it and
+//! no answer can be given twice. A server that exits with calls it took, or
+//! with calls still queued on its mailboxes, fails them with `E_PEER_GONE`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::drivers::rng;
+use crate::ipc::mailbox::{self, ChannelId};
+use crate::task::tcb::TaskId;
+use crate::task::scheduler;
+use crate::{kprintln, timer};
+
+/// Identifies one call from `SYS_IPC_CALL` until the caller has its reply.
+pub type ReplyToken = u64;
+
+enum CallState {
+    /// Queued or being handled.
+    Waiting,
+    Replied(Vec<u8>),
+    /// The reply was this long and didn't fit the caller's buffer.
+    ReplyTooLarge(usize),
+    /// The server's receive buffer was too small for the request.
+    Dropped,
+    /// The server exited before replying.
+    ServerGone,
+}
+
+struct PendingCall {
+    caller: TaskId,
+    channel: ChannelId,
+    /// The task that received the call; None while it is queued.
+    server: Option<TaskId>,
+    resp_cap: usize,
+    state: CallState,
+}
+
+/// How a call ended, as seen by its caller.
+pub enum Outcome {
+    Replied(Vec<u8>),
+    ReplyTooLarge(usize),
+    Dropped,
+    ServerGone,
+    /// No reply yet. The caller has been marked Blocked and must schedule away.
+    Waiting,
+    /// Not a call of this caller, or already collected.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyError {
+    /// Not a call this task received and still owes an answer.
+    Unknown,
+    /// The reply is larger than the caller's buffer. The caller is told so.
+    TooLarge,
+}
+
+// Lock order: CALLS before the scheduler's TASKS. Never held together with
+// MAILBOXES; the mailbox only carries tokens.
+static CALLS: Mutex<BTreeMap<ReplyToken, PendingCall>> = Mutex::new(BTreeMap::new());
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+/// A token no pending call uses: a serial number in the low half, and random
+/// bits in the high half so tokens can't be guessed from the ones a task has
+/// seen. The top bit is clear, so a token never looks like an error code.
+fn fresh_token(calls: &BTreeMap<ReplyToken, PendingCall>) -> ReplyToken {
+    loop {
+        let serial = NEXT_SERIAL.fetch_add(1, Ordering::Relaxed) & 0xFFFF_FFFF;
+        let mut salt = [0u8; 4];
+        let salt = if rng::fill(&mut salt) { u32::from_le_bytes(salt) as u64 } else { timer::get_current_ticks().raw() };
+        let token = ((salt & 0x7FFF_FFFF) << 32) | serial;
+        if serial != 0 && !calls.contains_key(&token) {
+            return token;
+        }
+    }
+}
+
+/// Marks the caller Blocked unless the call already has its outcome. Under
+/// the CALLS lock, so a reply on another CPU can't slip in between.
+fn block_while_waiting(calls: &BTreeMap<ReplyToken, PendingCall>, token: ReplyToken) -> bool {
+    match calls.get(&token) {
+        Some(call) if matches!(call.state, CallState::Waiting) => scheduler::mark_blocked(call.caller),
+        _ => false,
+    }
+}
+
+/// Sends `request` on `channel` as a call from `caller` and blocks the caller
+/// until the reply. Switches straight to the server if it is waiting on the
+/// channel; otherwise wakes it and schedules normally. Returns the call's token.
+pub fn start(channel: ChannelId, caller: TaskId, request: &[u8], resp_cap: usize) -> Result<ReplyToken, &'static str> {
+    let token = {
+        let mut calls = CALLS.lock();
+        let token = fresh_token(&calls);
+        calls.insert(token, PendingCall { caller, channel, server: None, resp_cap, state: CallState::Waiting });
+        token
+    };
+    let waiting_server = match mailbox::send_call(channel, caller, request, token) {
+        Ok(server) => server,
+        Err(e) => {
+            CALLS.lock().remove(&token);
+            return Err(e);
+        }
+    };
+    let blocked = block_while_waiting(&CALLS.lock(), token);
+    match waiting_server {
+        Some(server) if blocked && scheduler::handoff(server) => {}
+        Some(server) => {
+            scheduler::unblock_task(server);
+            if blocked {
+                scheduler::schedule();
+            }
+        }
+        None if blocked => scheduler::schedule(),
+        None => {}
+    }
+    Ok(token)
+}
+
+/// Binds a call to the task that just received it; only that task may reply.
+pub fn accept(token: ReplyToken, server: TaskId) {
+    if let Some(call) = CALLS.lock().get_mut(&token) {
+        if call.server.is_none() && matches!(call.state, CallState::Waiting) {
+            call.server = Some(server);
+        }
+    }
+}
+
+/// Whether `server` owes an answer to the call `token` on `channel`. A plain
+/// `SYS_IPC_SEND` there is then taken as the reply, so servers that don't use
+/// `SYS_IPC_REPLY` keep working with callers that use `SYS_IPC_CALL`.
+pub fn answers(token: ReplyToken, server: TaskId, channel: ChannelId) -> bool {
+    CALLS.lock().get(&token).map_or(false, |call| {
+        call.server == Some(server) && call.channel == channel && matches!(call.state, CallState::Waiting)
+    })
+}
+
+/// Completes the call `token` with `reply` from `server` and switches
+/// straight back to the caller if it is blocked waiting; otherwise it is
+/// woken normally.
+pub fn reply(token: ReplyToken, server: TaskId, reply: &[u8]) -> Result<(), ReplyError> {
+    let (caller, result) = {
+        let mut calls = CALLS.lock();
+        let call = match calls.get_mut(&token) {
+            Some(call) if call.server == Some(server) && matches!(call.state, CallState::Waiting) => call,
+            _ => return Err(ReplyError::Unknown),
+        };
+        let result = if reply.len() <= call.resp_cap {
+            call.state = CallState::Replied(reply.to_vec());
+            Ok(())
+        } else {
+            call.state = CallState::ReplyTooLarge(reply.len());
+            Err(ReplyError::TooLarge)
+        };
+        (call.caller, result)
+    };
+    if !scheduler::handoff(caller) {
+        scheduler::unblock_task(caller);
+    }
+    result
+}
+
+/// The outcome of the call `token` for `caller`, which then forgets it. With
+/// no outcome yet, the caller is marked Blocked until there is one.
+pub fn collect(token: ReplyToken, caller: TaskId) -> Outcome {
+    let mut calls = CALLS.lock();
+    match calls.get(&token) {
+        Some(call) if call.caller == caller => {}
+        _ => return Outcome::Unknown,
+    }
+    if block_while_waiting(&calls, token) {
+        return Outcome::Waiting;
+    }
+    match calls.remove(&token).map(|call| call.state) {
+        Some(CallState::Replied(data)) => Outcome::Replied(data),
+        Some(CallState::ReplyTooLarge(len)) => Outcome::ReplyTooLarge(len),
+        Some(CallState::Dropped) => Outcome::Dropped,
+        Some(CallState::ServerGone) => Outcome::ServerGone,
+        // Still waiting, but the caller doesn't exist to be blocked.
+        Some(CallState::Waiting) | None => Outcome::Unknown,
+    }
+}
+
+/// Fails calls that will never be answered and wakes their callers:
+/// `ServerGone` for calls dropped with a closed mailbox, `Dropped` for a
+/// request its receiver had no room for.
+fn fail(tokens: &[ReplyToken], gone: bool) -> usize {
+    let mut callers = Vec::new();
+    {
+        let mut calls = CALLS.lock();
+        for token in tokens {
+            if let Some(call) = calls.get_mut(token) {
+                if matches!(call.state, CallState::Waiting) {
+                    call.state = if gone { CallState::ServerGone } else { CallState::Dropped };
+                    callers.push(call.caller);
+                }
+            }
+        }
+    }
+    for caller in &callers {
+        scheduler::unblock_task(*caller);
+    }
+    callers.len()
+}
+
+/// Fails calls whose request was queued on a mailbox that was closed.
+pub fn abandon(tokens: &[ReplyToken]) -> usize {
+    fail(tokens, true)
+}
+
+/// Fails a call whose request didn't fit its receiver's buffer.
+pub fn drop_call(token: ReplyToken) {
+    fail(&[token], false);
+}
+
+/// Forgets the calls `task_id` made and fails those it took and didn't
+/// answer. Returns how many calls were affected.
+pub fn release_task(task_id: TaskId) -> usize {
+    let (forgotten, taken) = {
+        let mut calls = CALLS.lock();
+        let before = calls.len();
+        calls.retain(|_, call| call.caller != task_id);
+        let forgotten = before - calls.len();
+        let taken: Vec<ReplyToken> = calls.iter()
+            .filter(|(_, call)| call.server == Some(task_id))
+            .map(|(token, _)| *token)
+            .collect();
+        (forgotten, taken)
+    };
+    let failed = fail(&taken, true);
+    if failed > 0 {
+        kprintln!("[kernel] ipc: Task {} exited holding {} unanswered calls; failed them.", task_id, failed);
+    }
+    forgotten + failed
+}
+
+/// Number of calls `task_id` is waiting on or hasn't collected.
+pub fn pending_calls_of(task_id: TaskId) -> usize {
+    CALLS.lock().values().filter(|call| call.caller == task_id).count()
+}
@@ -7,43 +7,86 @@ use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use spin::Mutex;
 use crate::{kprintln, task};
+use crate::ipc::call;
+use common::abi::IpcCreds;
+use common::channels;
+use common::ids::TaskId;
+#[cfg(feature = "det-sched")]
+use crate::task::detsched::{self, Event};
 
 /// A unique identifier for an IPC channel.
-pub type ChannelId = u32;
+pub use common::ids::ChannelId;
 
 /// A message sent over an IPC channel.
 pub struct Message {
-    pub sender_task_id: u64, // The ID of the task that sent this message
+    pub sender_task_id: TaskId, // The ID of the task that sent this message
+    /// The sender's name and identity when the message was queued, for SYS_IPC_CREDS.
+    pub creds: IpcCreds,
     pub data: Vec<u8>,
+    /// Set on the request of an IPC call; the receiver answers it with this token.
+    pub reply_token: Option<u64>,
 }
 
 /// Represents a kernel-managed IPC channel or mailbox.
 pub struct Mailbox {
     queue: VecDeque<Message>,
+    /// The task receiving on this mailbox, if one has claimed it.
+    owner: Option<TaskId>,
+    /// The task blocked until a message arrives here.
+    waiter: Option<TaskId>,
 }
 
 impl Mailbox {
     pub fn new() -> Self {
-        Mailbox { queue: VecDeque::new() }
+        Mailbox { queue: VecDeque::new(), owner: None, waiter: None }
     }
 }
 
-/// Global array of IPC channels. Max 32 channels for simplicity.
+/// Global array of IPC channels. Max 64 channels for simplicity.
 /// In a real system, this would be a dynamic structure like a BTreeMap.
-const MAX_CHANNELS: usize = 32;
+const MAX_CHANNELS: usize = channels::MAX_CHANNELS as usize;
+/// Channels below this ID are reserved for well-known services (see `common::channels`).
+/// Channels at or above it are handed out by `allocate`.
+pub const FIRST_DYNAMIC_CHANNEL: ChannelId = ChannelId::from_raw(channels::FIRST_DYNAMIC_CHANNEL);
+/// The slot of `channel_id` in `MAILBOXES`, if it has one.
+fn slot(channel_id: ChannelId) -> Option<usize> {
+    let index = channel_id.raw() as usize;
+    (index < MAX_CHANNELS).then_some(index)
+}
+
+// Lock order: MAILBOXES before the scheduler's TASKS. A sender wakes the
+// waiter, and a receiver blocks itself, while holding MAILBOXES.
 static MAILBOXES: Mutex<[Option<Mailbox>; MAX_CHANNELS]> = Mutex::new([None; MAX_CHANNELS]);
 
 /// Sends a message over the specified IPC channel (mailbox).
 ///
 /// Returns `Ok(())` on success, `Err` with an error message on failure.
-pub fn send(channel_id: ChannelId, sender_task_id: u64, data: &[u8]) -> Result<(), &'static str> {
-    if channel_id as usize >= MAX_CHANNELS {
+pub fn send(channel_id: ChannelId, sender_task_id: TaskId, data: &[u8]) -> Result<(), &'static str> {
+    // Taken before MAILBOXES is locked: the scheduler's lock comes after it.
+    let creds = task::credentials_of(sender_task_id);
+    // If a task is blocked on this mailbox, unblock it.
+    if let Some(waiter) = deliver(channel_id, Message { sender_task_id, creds, data: data.to_vec(), reply_token: None })? {
+        task::unblock_task_on_channel(waiter);
+    }
+    Ok(())
+}
+
+/// Queues the request of an IPC call (see `ipc::call`). A task blocked on the
+/// mailbox is returned instead of woken, so the caller can switch to it directly.
+pub fn send_call(channel_id: ChannelId, sender_task_id: TaskId, data: &[u8], reply_token: u64) -> Result<Option<TaskId>, &'static str> {
+    let creds = task::credentials_of(sender_task_id);
+    deliver(channel_id, Message { sender_task_id, creds, data: data.to_vec(), reply_token: Some(reply_token) })
+}
+
+/// Queues `message` and takes the mailbox's waiter, if any.
+fn deliver(channel_id: ChannelId, message: Message) -> Result<Option<TaskId>, &'static str> {
+    let Some(index) = slot(channel_id) else {
         kprintln!("[kernel] mailbox: Send failed, channel ID {} out of bounds.", channel_id);
         return Err("Channel ID out of bounds");
-    }
+    };
 
     let mut mailboxes = MAILBOXES.lock();
-    let mailbox_entry = &mut mailboxes[channel_id as usize];
+    let mailbox_entry = &mut mailboxes[index];
 
     // Ensure the mailbox exists, create if not (dynamic mailbox creation)
     if mailbox_entry.is_none() {
@@ -52,11 +95,12 @@ pub fn send(channel_id: ChannelId, sender_task_id: u64, data: &[u8]) -> Result<(
     }
 
     if let Some(mailbox) = mailbox_entry.as_mut() {
-        mailbox.queue.push_back(Message { sender_task_id, data: data.to_vec() });
+        let sender_task_id = message.sender_task_id;
+        mailbox.queue.push_back(message);
         kprintln!("[kernel] mailbox: Message sent to mailbox {} by task {}.", channel_id, sender_task_id);
-        // If a task is blocked on this mailbox, unblock it.
-        task::unblock_task_on_channel(channel_id);
-        Ok(())
+        #[cfg(feature = "det-sched")]
+        detsched::record(Event::IpcEnqueue { channel: channel_id, sender: sender_task_id });
+        Ok(mailbox.waiter.take())
     } else {
         // This case should ideally not be reached if mailbox is created above
         kprintln!("[kernel] mailbox: Send failed, mailbox {} not found after creation attempt.", channel_id);
@@ -68,34 +112,138 @@ pub fn send(channel_id: ChannelId, sender_task_id: u64, data: &[u8]) -> Result<(
 ///
 /// Returns `Some(Message)` if a message is available, `None` otherwise.
 pub fn recv(channel_id: ChannelId) -> Option<Message> {
-    if channel_id as usize >= MAX_CHANNELS {
+    let Some(index) = slot(channel_id) else {
         kprintln!("[kernel] mailbox: Recv failed, channel ID {} out of bounds.", channel_id);
         return None;
-    }
+    };
 
     let mut mailboxes = MAILBOXES.lock();
-    if let Some(mailbox) = mailboxes[channel_id as usize].as_mut() {
-        let msg = mailbox.queue.pop_front();
-        if msg.is_some() {
-            kprintln!("[kernel] mailbox: Message received from mailbox {}.", channel_id);
-        }
-        msg
+    if let Some(mailbox) = mailboxes[index].as_mut() {
+        take_message(channel_id, mailbox)
     } else {
         kprintln!("[kernel] mailbox: Recv failed, mailbox {} not found.", channel_id);
         None
     }
 }
 
+fn take_message(channel_id: ChannelId, mailbox: &mut Mailbox) -> Option<Message> {
+    let msg = mailbox.queue.pop_front();
+    if msg.is_some() {
+        kprintln!("[kernel] mailbox: Message received from mailbox {}.", channel_id);
+        #[cfg(feature = "det-sched")]
+        detsched::record(Event::IpcDequeue { channel: channel_id, receiver: task::scheduler::current_task_id() });
+    }
+    msg
+}
+
+/// Takes the next message, or if there is none, makes `task_id` the
+/// mailbox's waiter and marks it Blocked. Both happen under the mailbox lock,
+/// so a message sent right after the check finds the waiter and wakes it; with
+/// a separate `peek` and block that wakeup could be lost. On `None` the caller
+/// schedules away.
+pub fn recv_or_block(channel_id: ChannelId, task_id: TaskId) -> Option<Message> {
+    let Some(index) = slot(channel_id) else {
+        kprintln!("[kernel] mailbox: Recv failed, channel ID {} out of bounds.", channel_id);
+        return None;
+    };
+    let mut mailboxes = MAILBOXES.lock();
+    let mailbox = mailboxes[index].get_or_insert_with(Mailbox::new);
+    let msg = take_message(channel_id, mailbox);
+    if msg.is_none() {
+        mailbox.waiter = Some(task_id);
+        task::scheduler::mark_blocked(task_id);
+    }
+    msg
+}
+
+/// Marks `task_id` Blocked as the mailbox's waiter unless a message is
+/// already queued, in one step like `recv_or_block`. Returns true if it blocked.
+pub fn block_unless_ready(channel_id: ChannelId, task_id: TaskId) -> bool {
+    let Some(index) = slot(channel_id) else {
+        return false;
+    };
+    let mut mailboxes = MAILBOXES.lock();
+    let mailbox = mailboxes[index].get_or_insert_with(Mailbox::new);
+    if !mailbox.queue.is_empty() {
+        return false;
+    }
+    mailbox.waiter = Some(task_id);
+    task::scheduler::mark_blocked(task_id)
+}
+
 /// Checks if a mailbox has messages without removing them.
 pub fn peek(channel_id: ChannelId) -> bool {
-    if channel_id as usize >= MAX_CHANNELS {
+    let Some(index) = slot(channel_id) else {
         return false;
-    }
+    };
     let mailboxes = MAILBOXES.lock();
-    if let Some(mailbox) = mailboxes[channel_id as usize].as_ref() {
+    if let Some(mailbox) = mailboxes[index].as_ref() {
         !mailbox.queue.is_empty()
     } else {
         false
     }
 }
 
+/// Number of messages queued on a mailbox; 0 if it doesn't exist.
+pub fn queued(channel_id: ChannelId) -> usize {
+    let Some(index) = slot(channel_id) else {
+        return 0;
+    };
+    MAILBOXES.lock()[index].as_ref().map_or(0, |mailbox| mailbox.queue.len())
+}
+
+/// Allocates a fresh mailbox owned by `owner` from the dynamic range.
+/// Returns `None` if all channels are in use.
+pub fn allocate(owner: TaskId) -> Option<ChannelId> {
+    let mut mailboxes = MAILBOXES.lock();
+    for channel_id in FIRST_DYNAMIC_CHANNEL.raw() as usize..MAX_CHANNELS {
+        if mailboxes[channel_id].is_none() {
+            mailboxes[channel_id] = Some(Mailbox { queue: VecDeque::new(), owner: Some(owner), waiter: None });
+            kprintln!("[kernel] mailbox: Allocated mailbox {} for task {}.", channel_id, owner);
+            return Some(ChannelId::from_raw(channel_id as u32));
+        }
+    }
+    kprintln!("[kernel] mailbox: No free mailbox for task {}.", owner);
+    None
+}
+
+/// Records `task_id` as the owner (receiver) of a mailbox, creating it if needed.
+/// The first task to receive on a channel owns it until it is torn down.
+pub fn claim(channel_id: ChannelId, task_id: TaskId) {
+    let Some(index) = slot(channel_id) else {
+        return;
+    };
+    let mut mailboxes = MAILBOXES.lock();
+    let mailbox = mailboxes[index].get_or_insert_with(Mailbox::new);
+    if mailbox.owner.is_none() {
+        mailbox.owner = Some(task_id);
+        kprintln!("[kernel] mailbox: Mailbox {} claimed by task {}.", channel_id, task_id);
+    }
+}
+
+/// Closes every mailbox owned by `task_id`, dropping any queued messages.
+/// Calls among them fail with `E_PEER_GONE`. Returns the number of mailboxes closed.
+pub fn close_task_mailboxes(task_id: TaskId) -> usize {
+    let mut orphaned_calls = Vec::new();
+    let mut closed = 0;
+    {
+        let mut mailboxes = MAILBOXES.lock();
+        for (channel_id, entry) in mailboxes.iter_mut().enumerate() {
+            if entry.as_ref().map_or(false, |mb| mb.owner == Some(task_id)) {
+                if let Some(mailbox) = entry.take() {
+                    orphaned_calls.extend(mailbox.queue.iter().filter_map(|msg| msg.reply_token));
+                }
+                closed += 1;
+                kprintln!("[kernel] mailbox: Closed mailbox {} owned by task {}.", channel_id, task_id);
+            }
+        }
+    }
+    // After releasing MAILBOXES; failing a call wakes its caller.
+    call::abandon(&orphaned_calls);
+    closed
+}
+
+/// Returns the number of mailboxes currently owned by `task_id`.
+pub fn count_task_mailboxes(task_id: TaskId) -> usize {
+    MAILBOXES.lock().iter().filter(|entry| entry.as_ref().map_or(false, |mb| mb.owner == Some(task_id))).count()
+}
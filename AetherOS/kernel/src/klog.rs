@@ -0,0 +1,89 @@
+// kernel/src/klog.rs
+
+//! The kernel log ring: everything printed through the console, kept in
+//! memory for `SYS_KLOG_READ` and for the persistent dump (see `pstore`).
+
+use core::fmt;
+use spin::Mutex;
+
+use crate::config::KLOG_RING_BYTES;
+
+pub struct Ring {
+    buf: [u8; KLOG_RING_BYTES],
+    written: u64, // Bytes written since boot; the next one goes to `written % KLOG_RING_BYTES`
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self { buf: [0; KLOG_RING_BYTES], written: 0 }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        // Only the newest KLOG_RING_BYTES of an oversized write survive anyway.
+        let skip = bytes.len().saturating_sub(KLOG_RING_BYTES);
+        self.written += skip as u64;
+        let mut rest = &bytes[skip..];
+        while !rest.is_empty() {
+            let at = (self.written % KLOG_RING_BYTES as u64) as usize;
+            let n = rest.len().min(KLOG_RING_BYTES - at);
+            self.buf[at..at + n].copy_from_slice(&rest[..n]);
+            self.written += n as u64;
+            rest = &rest[n..];
+        }
+    }
+
+    /// Bytes written since boot, including those already overwritten.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Bytes the ring holds.
+    pub fn len(&self) -> usize {
+        self.written.min(KLOG_RING_BYTES as u64) as usize
+    }
+
+    /// Copies the newest bytes that fit into `out`, oldest first. Returns how many were copied.
+    pub fn copy_tail(&self, out: &mut [u8]) -> usize {
+        let len = self.len().min(out.len());
+        let start = ((self.written - len as u64) % KLOG_RING_BYTES as u64) as usize;
+        let first = len.min(KLOG_RING_BYTES - start);
+        out[..first].copy_from_slice(&self.buf[start..start + first]);
+        out[first..len].copy_from_slice(&self.buf[..len - first]);
+        len
+    }
+}
+
+impl fmt::Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring::new());
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut *RING.lock(), args);
+}
+
+/// Runs `f` with the ring locked.
+pub fn with_ring<R>(f: impl FnOnce(&Ring) -> R) -> R {
+    f(&RING.lock())
+}
+
+/// Copies the newest bytes of the log that fit into `out`. Returns the length
+/// of the whole log, which may be more than was copied.
+pub fn read(out: &mut [u8]) -> usize {
+    let ring = RING.lock();
+    ring.copy_tail(out);
+    ring.len()
+}
+
+/// Steals the ring lock on the panic path, so the panic is logged and dumped.
+pub fn panic_takeover() {
+    if RING.is_locked() {
+        // SAFETY: The panicking CPU never returns to the interrupted lock holder.
+        unsafe { RING.force_unlock(); }
+    }
+}
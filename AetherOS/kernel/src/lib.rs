@@ -6,16 +6,24 @@
 
 extern crate alloc;
 
-use bootloader_api::info::MemoryRegions;
+use bootloader_api::info::{FrameBuffer, MemoryRegions};
 use x86_64::VirtAddr;
 
 #[macro_use]
 pub mod console; // Our new console module
+pub mod klog;    // Kernel log ring behind the console
+pub mod pstore;  // Log dump that survives a warm reboot
+pub mod logfwd;  // SYS_LOG messages forwarded to the log daemon
+pub mod power;   // Power-off and reset for SYS_SYSTEM_POWER
 pub mod timer;   // Our new timer module
 pub mod caps;    // Our new capabilities module
 pub mod task;    // Our new task management module
 pub mod ipc;     // Our new IPC module
 pub mod syscall; // Syscall dispatcher
+pub mod error;   // Kernel error types
+pub mod config;  // Kernel configuration constants
+pub mod bootinfo; // Kernel command line
+pub mod sysmon;  // Kernel statistics report
 
 // Architecture-specific modules
 pub mod arch;
@@ -35,12 +43,14 @@ pub const HEAP_START: u64 = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
 /// The main initialization function for the AetherOS kernel.
-pub fn init(memory_regions: &'static MemoryRegions) {
+pub fn init(memory_regions: &'static MemoryRegions, physical_memory_offset: Option<u64>, framebuffer: Option<&'static mut FrameBuffer>, ramdisk: Option<&'static [u8]>) {
     // Initialize architecture-specific components first
     arch::init();
     drivers::serial::init(); // Initialize serial driver first for early logging
+    drivers::framebuffer::init(framebuffer); // Mirror early logging to the screen
     console::init(); // Initialize console (now depends on serial driver)
     memory::init(memory_regions); // Initialize memory management with bootloader info
+    pstore::init(memory_regions, physical_memory_offset); // Recover the previous boot's log before this one's overwrites it
 
     // Initialize kernel heap
     // SAFETY: The caller (bootloader) must ensure that HEAP_START and HEAP_SIZE
@@ -48,9 +58,33 @@ pub fn init(memory_regions: &'static MemoryRegions) {
     // For this stub, we assume this is handled conceptually.
     unsafe { heap::init(VirtAddr::new(HEAP_START), HEAP_SIZE); }
 
+    bootinfo::init(ramdisk); // Before anything the command line can change
+    if !bootinfo::fbcon() {
+        drivers::framebuffer::disable_console();
+    }
+    if let Some(limit_mib) = bootinfo::memtest() {
+        memory::memtest::run(memory_regions, physical_memory_offset, limit_mib);
+    }
+
     timer::init(); // Initialize timer
+    drivers::rtc::init(); // Wall clock; needs the timer for elapsed time
+    drivers::rng::init();
+    // Each optional driver can be skipped with driver.off=<name> (see bootinfo).
+    let start = |name: &str, init: fn() -> bool| {
+        if bootinfo::driver_enabled(name) {
+            init();
+        } else {
+            kprintln!("[kernel] {}: Disabled on the command line.", name);
+        }
+    };
+    start("ps2_keyboard", drivers::ps2_keyboard::init); // Before the mouse, which shares the controller
+    start("ps2_mouse", drivers::ps2_mouse::init); // Optional; the system runs without a mouse
+    start("ac97", drivers::ac97::init); // Optional; the system runs without sound
     task::init(); // Initialize task management
     ipc::init();  // Initialize IPC module
+    #[cfg(feature = "det-sched")]
+    task::scenarios::run_all(); // Before any V-Node exists, so the scenarios have the scheduler to themselves
+    aetherfs::init(ramdisk); // Boot image; the ELF loader reads V-Nodes from it
     elf::init(); // Initialize ELF loader
 
     kprintln!("[kernel] AetherOS kernel initialized.");
@@ -0,0 +1,100 @@
+// kernel/src/logfwd.rs
+
+#![allow(dead_code)]
+
+//! Forwarding of `SYS_LOG` messages to the log daemon.
+//!
+//! A task with `CAP_LOG_READ` names a channel with `SYS_LOG_FORWARD`, and
+//! from then on every `SYS_LOG` message is also queued there as a
+//! `LogRecord`, after it is printed. Queueing never waits for the daemon:
+//! once `LOG_FORWARD_QUEUE_MAX` records are waiting, further ones are dropped
+//! and the count goes out with the next record that fits. The daemon's own
+//! messages are printed but not forwarded, so its logging can't feed itself.
+//!
+//! Lock order: FORWARDER before MAILBOXES and the scheduler's TASKS.
+
+extern crate alloc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use common::abi::{LogRecord, LOG_RECORD_HEADER_LEN, TASK_NAME_LEN};
+
+use crate::config::LOG_FORWARD_QUEUE_MAX;
+use crate::ipc::{self, ChannelId};
+use crate::task::{self, tcb::TaskId};
+use crate::{kprintln, timer};
+
+struct Forwarder {
+    channel: ChannelId,
+    /// The task that set it up. Its messages aren't forwarded, and its exit ends forwarding.
+    owner: TaskId,
+    /// Records dropped since the last one queued.
+    dropped: u32,
+}
+
+static FORWARDER: Mutex<Option<Forwarder>> = Mutex::new(None);
+/// Records dropped since boot, for sysmon.
+static DROPPED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Makes `owner` the log daemon, forwarding to `channel`. Fails with the
+/// current daemon if another live task is one; the daemon itself may move
+/// to another channel.
+pub fn register(channel: ChannelId, owner: TaskId) -> Result<(), TaskId> {
+    let mut forwarder = FORWARDER.lock();
+    if let Some(current) = forwarder.as_ref() {
+        if current.owner != owner && task::task_exists(current.owner) {
+            return Err(current.owner);
+        }
+    }
+    *forwarder = Some(Forwarder { channel, owner, dropped: 0 });
+    kprintln!("[kernel] logfwd: Task {} receives SYS_LOG messages on channel {}.", owner, channel);
+    Ok(())
+}
+
+/// Forwards a message `sender` logged, unless nobody listens or `sender` is
+/// the daemon. `message` is already cut to the longest message printed.
+pub fn forward(sender: TaskId, name: &str, severity: u32, message: &[u8]) {
+    let mut guard = FORWARDER.lock();
+    let Some(forwarder) = guard.as_mut() else {
+        return;
+    };
+    if forwarder.owner == sender {
+        return;
+    }
+    if ipc::mailbox::queued(forwarder.channel) >= LOG_FORWARD_QUEUE_MAX {
+        forwarder.dropped = forwarder.dropped.saturating_add(1);
+        DROPPED_TOTAL.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let mut record = LogRecord {
+        task: sender.raw(),
+        ticks: timer::get_current_ticks().raw(),
+        severity,
+        dropped: forwarder.dropped,
+        name: [0u8; TASK_NAME_LEN],
+    };
+    let len = name.len().min(TASK_NAME_LEN);
+    record.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    let mut data = Vec::with_capacity(LOG_RECORD_HEADER_LEN + message.len());
+    data.extend_from_slice(&record.to_bytes());
+    data.extend_from_slice(message);
+    if ipc::kernel_send(forwarder.channel, TaskId::KERNEL, &data).is_ok() {
+        forwarder.dropped = 0;
+    }
+}
+
+/// Ends forwarding if `task_id` is the daemon. Returns whether it was.
+pub fn release_task(task_id: TaskId) -> bool {
+    let mut forwarder = FORWARDER.lock();
+    if forwarder.as_ref().map_or(false, |current| current.owner == task_id) {
+        *forwarder = None;
+        return true;
+    }
+    false
+}
+
+/// Records dropped since boot because the daemon fell behind.
+pub fn dropped() -> u64 {
+    DROPPED_TOTAL.load(Ordering::Relaxed)
+}
@@ -11,8 +11,13 @@ use bootloader_api::BootInfo; // Import BootInfo from the bootloader_api crate
 #[no_mangle] // Don't mangle the name of this function, so the bootloader can find it
 pub extern "C" fn _start(boot_info: &'static mut BootInfo) -> ! {
     // Initialize all core kernel modules.
-    // We pass the boot_info.memory_regions to the kernel's init function.
-    crate::init(&boot_info.memory_regions);
+    // We pass the boot_info.memory_regions, the physical memory mapping, framebuffer and initrd to the kernel's init function.
+    // SAFETY: The bootloader maps the ramdisk for the kernel and never reclaims it.
+    let ramdisk = boot_info.ramdisk_addr.into_option()
+        .map(|addr| unsafe { core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize) });
+    crate::init(&boot_info.memory_regions, boot_info.physical_memory_offset.into_option(), boot_info.framebuffer.as_mut(), ramdisk);
+    #[cfg(feature = "smp")]
+    crate::arch::x86_64::smp::init(boot_info.rsdp_addr.into_option(), boot_info.physical_memory_offset.into_option());
 
     crate::kprintln!("[kernel] Welcome to AetherOS!");
 
@@ -20,6 +25,8 @@ pub extern "C" fn _start(boot_info: &'static mut BootInfo) -> ! {
     // In a real OS, this would be the idle loop, scheduling tasks.
     loop {
         crate::task::schedule(); // Give control to the scheduler
+        crate::task::print_suppressed_logs(); // SYS_LOG flood summaries the timer took
+        crate::pstore::checkpoint(); // Keep the persistent copy of the log recent
         x86_64::instructions::hlt(); // Halt the CPU until the next interrupt
     }
 }
@@ -27,8 +34,12 @@ pub extern "C" fn _start(boot_info: &'static mut BootInfo) -> ! {
 /// This function is called on panic.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    crate::kprintln!("[kernel] !!! KERNEL PANIC !!!");
-    crate::kprintln!("[kernel] Error: {}", info);
+    // Take the screen back from the compositor and steal any console locks.
+    crate::console::panic_takeover();
+    crate::kerrorln!("[kernel] !!! KERNEL PANIC !!!");
+    crate::kerrorln!("[kernel] Error: {}", info);
+    // Leave the log, panic included, for the next boot.
+    crate::pstore::panic_dump();
     // In a production system, this would involve a stack trace, dumping registers,
     // or rebooting. For now, we simply halt the system.
     loop {
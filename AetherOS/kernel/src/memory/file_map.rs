@@ -0,0 +1,335 @@
+// kernel/src/memory/file_map.rs
+
+#![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
+
+//! Read-only file mappings.
+//!
+//! A storage service (VFS) pins the pages holding a file's contents with
+//! `SYS_SHARE_PAGES`, naming the one task allowed to map them. That task maps
+//! a range with `SYS_MAP_FILE` and releases it with `SYS_UNMAP`. Mapped pages
+//! are never writable; they are faulted in on first touch.
+//!
+//! Each mapping holds a pin on its backing. The storage service cannot drop a
+//! backing (and so must not free the memory behind it) while pins remain.
+//!
+//! A backing shared with `SYS_SHARE_PAGES_ON_DEMAND` starts out empty: the
+//! storage service fills a page only once a mapping touches it. The faulting
+//! task blocks, the service collects the page with `SYS_PAGE_REQUESTS`, reads
+//! it from its device and reports it filled with `SYS_PAGE_SUPPLY`, which
+//! wakes the task to retry the access. If the service exits first, the tasks
+//! waiting on it are killed.
+
+extern crate alloc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::arch::x86_64::paging;
+use crate::error::{KernelError, Result};
+use crate::kprintln;
+use crate::memory::task_memory;
+use crate::task::scheduler;
+use crate::task::tcb::TaskId;
+
+pub const PAGE_SIZE: usize = 4096;
+
+// Starts above SUCCESS and E_ERROR so a handle is never mistaken for a status code.
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(0x100);
+
+/// Pages pinned by a storage service for one grantee.
+struct Backing {
+    /// Storage task that shared the pages; only it may unshare them.
+    owner: TaskId,
+    /// Only this task may map the backing.
+    grantee: TaskId,
+    base: usize,
+    len: usize,
+    /// Number of live mappings of this backing.
+    pins: usize,
+    /// Pages are filled by the owner when first touched.
+    on_demand: bool,
+    /// Offsets of the on-demand pages the owner has filled.
+    supplied: BTreeSet<usize>,
+    /// Offsets of the missing pages already reported by `page_requests`.
+    requested: BTreeSet<usize>,
+    /// Tasks blocked on each missing page, by page offset.
+    waiting: BTreeMap<usize, Vec<TaskId>>,
+    /// The owner exited; its missing pages will never be filled.
+    owner_gone: bool,
+}
+
+/// A range of a backing mapped into a task.
+struct Mapping {
+    backing: u64,
+    len: usize,
+    /// Page-aligned addresses of pages faulted in so far.
+    resident: BTreeSet<usize>,
+}
+
+static BACKINGS: Mutex<BTreeMap<u64, Backing>> = Mutex::new(BTreeMap::new());
+/// Keyed by (task ID, mapping start address).
+static MAPPINGS: Mutex<BTreeMap<(TaskId, usize), Mapping>> = Mutex::new(BTreeMap::new());
+
+/// Pins `len` bytes at `base` in `owner`'s memory so `grantee` can map them.
+/// `base` must be page-aligned and the whole range readable memory of `owner`.
+/// Returns the backing handle.
+pub fn share_pages(owner: TaskId, grantee: TaskId, base: usize, len: usize) -> Result<u64> {
+    share(owner, grantee, base, len, false)
+}
+
+/// Like `share_pages`, but the pages are left for `owner` to fill when a
+/// mapping first touches them (see `page_requests` and `supply_page`).
+pub fn share_pages_on_demand(owner: TaskId, grantee: TaskId, base: usize, len: usize) -> Result<u64> {
+    share(owner, grantee, base, len, true)
+}
+
+fn share(owner: TaskId, grantee: TaskId, base: usize, len: usize, on_demand: bool) -> Result<u64> {
+    if base % PAGE_SIZE != 0 || len == 0 {
+        return Err(KernelError::InvalidArgument("backing must be page-aligned and non-empty"));
+    }
+    // Mappings cover whole pages, so the owner must own the last one entirely.
+    let end = base
+        .checked_add(len)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE))
+        .ok_or(KernelError::InvalidArgument("backing wraps the address space"))?;
+    if !task_memory::covers(owner, base as u64, (end - base) as u64) {
+        return Err(KernelError::PermissionDenied);
+    }
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+    BACKINGS.lock().insert(handle, Backing {
+        owner,
+        grantee,
+        base,
+        len,
+        pins: 0,
+        on_demand,
+        supplied: BTreeSet::new(),
+        requested: BTreeSet::new(),
+        waiting: BTreeMap::new(),
+        owner_gone: false,
+    });
+    kprintln!(
+        "[kernel] file_map: Task {} shared {} bytes at {:#x} with task {} (backing {}{}).",
+        owner, len, base, grantee, handle, if on_demand { ", on demand" } else { "" }
+    );
+    Ok(handle)
+}
+
+/// Missing pages that mappings of `owner`'s on-demand backings are blocked
+/// on, as (backing, page offset), at most `max` of them. Each page is
+/// reported once.
+pub fn page_requests(owner: TaskId, max: usize) -> Vec<(u64, usize)> {
+    let mut backings = BACKINGS.lock();
+    let mut found = Vec::new();
+    for (handle, backing) in backings.iter_mut().filter(|(_, backing)| backing.owner == owner && backing.on_demand) {
+        let new: Vec<usize> = backing.waiting.keys().filter(|offset| !backing.requested.contains(offset)).copied().collect();
+        for offset in new {
+            if found.len() == max {
+                return found;
+            }
+            backing.requested.insert(offset);
+            found.push((*handle, offset));
+        }
+    }
+    found
+}
+
+/// Records that `owner` filled the page at `offset` of an on-demand backing,
+/// and wakes the tasks blocked on it. Returns how many were woken.
+pub fn supply_page(owner: TaskId, handle: u64, offset: usize) -> Result<usize> {
+    let woken = {
+        let mut backings = BACKINGS.lock();
+        let backing = backings.get_mut(&handle).ok_or(KernelError::InvalidArgument("unknown backing handle"))?;
+        if backing.owner != owner {
+            return Err(KernelError::PermissionDenied);
+        }
+        if !backing.on_demand || offset % PAGE_SIZE != 0 || offset >= backing.len {
+            return Err(KernelError::InvalidArgument("not a page of an on-demand backing"));
+        }
+        backing.supplied.insert(offset);
+        backing.requested.remove(&offset);
+        backing.waiting.remove(&offset).unwrap_or_default()
+    };
+    for task in &woken {
+        scheduler::unblock_task(*task);
+    }
+    Ok(woken.len())
+}
+
+/// Drops a backing. Fails with `Busy` while it is still mapped; the owner must
+/// keep the memory alive and retry later.
+pub fn unshare_pages(owner: TaskId, handle: u64) -> Result<()> {
+    let mut backings = BACKINGS.lock();
+    match backings.get(&handle) {
+        None => Err(KernelError::InvalidArgument("unknown backing handle")),
+        Some(backing) if backing.owner != owner => Err(KernelError::PermissionDenied),
+        Some(backing) if backing.pins > 0 => Err(KernelError::Busy),
+        Some(_) => {
+            backings.remove(&handle);
+            kprintln!("[kernel] file_map: Backing {} released by task {}.", handle, owner);
+            Ok(())
+        }
+    }
+}
+
+/// Maps `len` bytes of a backing, starting at the page-aligned `offset`, into
+/// `task`. No page is mapped until it is touched. Returns the mapping's address.
+pub fn map_file(task: TaskId, handle: u64, offset: usize, len: usize) -> Result<usize> {
+    if offset % PAGE_SIZE != 0 || len == 0 {
+        return Err(KernelError::InvalidArgument("offset must be page-aligned and len non-zero"));
+    }
+    let mut backings = BACKINGS.lock();
+    let backing = backings.get_mut(&handle).ok_or(KernelError::InvalidArgument("unknown backing handle"))?;
+    if backing.grantee != task {
+        return Err(KernelError::PermissionDenied);
+    }
+    if offset.checked_add(len).map_or(true, |end| end > backing.len) {
+        return Err(KernelError::InvalidArgument("range exceeds backing"));
+    }
+
+    // With a single address space the mapping sits at the backing's own address.
+    // Per-task address spaces will pick a free range in the caller instead.
+    let addr = backing.base + offset;
+    let mut mappings = MAPPINGS.lock();
+    if mappings.contains_key(&(task, addr)) {
+        return Err(KernelError::Busy);
+    }
+    backing.pins += 1;
+    mappings.insert((task, addr), Mapping { backing: handle, len, resident: BTreeSet::new() });
+    kprintln!("[kernel] file_map: Task {} mapped {} bytes of backing {} at {:#x} (pins: {}).", task, len, handle, addr, backing.pins);
+    Ok(addr)
+}
+
+/// Removes the mapping that starts at `addr` with length `len` and drops its pin.
+pub fn unmap(task: TaskId, addr: usize, len: usize) -> Result<()> {
+    let mapping = {
+        let mut mappings = MAPPINGS.lock();
+        match mappings.get(&(task, addr)) {
+            Some(mapping) if mapping.len == len => mappings.remove(&(task, addr)).unwrap(),
+            Some(_) => return Err(KernelError::InvalidArgument("length does not match the mapping")),
+            None => return Err(KernelError::InvalidArgument("no mapping at address")),
+        }
+    };
+    release(task, addr, mapping);
+    Ok(())
+}
+
+fn release(task: TaskId, addr: usize, mapping: Mapping) {
+    for page in &mapping.resident {
+        paging::unmap_page(*page);
+    }
+    let mut backings = BACKINGS.lock();
+    if let Some(backing) = backings.get_mut(&mapping.backing) {
+        backing.pins -= 1;
+        // Nobody is left to unshare the backing of an exited owner.
+        if backing.owner_gone && backing.pins == 0 {
+            backings.remove(&mapping.backing);
+        }
+    }
+    kprintln!("[kernel] file_map: Task {} unmapped {:#x} (backing {}).", task, addr, mapping.backing);
+}
+
+/// Drops every mapping held by `task` and every unpinned backing it shared.
+/// Tasks waiting on pages of its backings are woken to be killed. Returns the
+/// number of mappings released.
+pub fn release_task_mappings(task: TaskId) -> usize {
+    let owned: Vec<(usize, Mapping)> = {
+        let mut mappings = MAPPINGS.lock();
+        let keys: Vec<(TaskId, usize)> = mappings.keys().filter(|(t, _)| *t == task).copied().collect();
+        keys.into_iter().filter_map(|key| mappings.remove(&key).map(|m| (key.1, m))).collect()
+    };
+    let count = owned.len();
+    for (addr, mapping) in owned {
+        release(task, addr, mapping);
+    }
+    let orphaned: Vec<TaskId> = {
+        let mut backings = BACKINGS.lock();
+        backings.retain(|_, backing| backing.owner != task || backing.pins > 0);
+        let mut orphaned = Vec::new();
+        for backing in backings.values_mut() {
+            for waiters in backing.waiting.values_mut() {
+                waiters.retain(|waiter| *waiter != task);
+            }
+            backing.waiting.retain(|_, waiters| !waiters.is_empty());
+            if backing.owner == task {
+                backing.owner_gone = true;
+                orphaned.extend(core::mem::take(&mut backing.waiting).into_values().flatten());
+            }
+        }
+        orphaned
+    };
+    for waiter in orphaned {
+        scheduler::unblock_task(waiter);
+    }
+    count
+}
+
+/// Returns the number of file mappings held by `task`.
+pub fn count_task_mappings(task: TaskId) -> usize {
+    MAPPINGS.lock().keys().filter(|(t, _)| *t == task).count()
+}
+
+/// The file mappings of `task` as start address and length, in address order.
+pub fn task_mappings(task: TaskId) -> Vec<(usize, usize)> {
+    MAPPINGS.lock().iter().filter(|((t, _), _)| *t == task).map(|((_, addr), mapping)| (*addr, mapping.len)).collect()
+}
+
+/// Returns the number of live mappings of a backing.
+pub fn pin_count(handle: u64) -> Option<usize> {
+    BACKINGS.lock().get(&handle).map(|backing| backing.pins)
+}
+
+/// Outcome of a page fault that hit a file mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultResolution {
+    /// The page was mapped read-only; the faulting instruction can be retried.
+    PagedIn,
+    /// The page isn't filled yet. The task was marked blocked and retries the
+    /// access once the owner supplies the page.
+    Waiting,
+    /// The page isn't filled and the owner has exited.
+    OwnerGone,
+    /// The task wrote to a read-only file mapping.
+    WriteToReadOnly,
+    /// The address is not inside any file mapping of the task.
+    NotMapped,
+}
+
+/// Resolves a page fault of `task` at `addr`.
+pub fn handle_fault(task: TaskId, addr: usize, write: bool) -> FaultResolution {
+    // Same order as `map_file`: backings, then mappings.
+    let mut backings = BACKINGS.lock();
+    let mut mappings = MAPPINGS.lock();
+    let found = mappings
+        .range_mut((task, 0)..=(task, addr))
+        .next_back()
+        .filter(|((_, start), mapping)| addr < start + mapping.len);
+    let (_, mapping) = match found {
+        Some(entry) => entry,
+        None => return FaultResolution::NotMapped,
+    };
+    if write {
+        return FaultResolution::WriteToReadOnly;
+    }
+    let page = addr & !(PAGE_SIZE - 1);
+    if let Some(backing) = backings.get_mut(&mapping.backing).filter(|backing| backing.on_demand) {
+        let offset = page - backing.base;
+        if !backing.supplied.contains(&offset) {
+            if backing.owner_gone {
+                return FaultResolution::OwnerGone;
+            }
+            let waiters = backing.waiting.entry(offset).or_default();
+            if !waiters.contains(&task) {
+                waiters.push(task);
+            }
+            // Under the backings lock, so `supply_page` can't wake the task before it is blocked.
+            scheduler::mark_blocked(task);
+            return FaultResolution::Waiting;
+        }
+    }
+    // Single address space: virtual and physical addresses of the backing coincide.
+    paging::map_page(page, page, paging::PAGE_PRESENT | paging::PAGE_USER);
+    mapping.resident.insert(page);
+    FaultResolution::PagedIn
+}
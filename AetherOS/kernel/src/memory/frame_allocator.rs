@@ -41,8 +41,11 @@ impl BootInfoFrameAllocator {
         // Transform to an iterator of frame start addresses
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096).map(PhysAddr::new));
 
-        // Create PhysFrame for each address
-        frame_addresses.map(|addr| PhysFrame::containing_address(addr))
+        // Create PhysFrame for each address, leaving out the persistent log region
+        // and frames the boot memory test found bad
+        frame_addresses
+            .filter(|addr| !crate::pstore::reserves(*addr) && !super::memtest::is_bad(*addr))
+            .map(|addr| PhysFrame::containing_address(addr))
     }
 }
 
@@ -0,0 +1,109 @@
+// kernel/src/memory/memtest.rs
+
+//! Boot-time memory test, run when the command line has `memtest` (see
+//! `bootinfo`).
+//!
+//! Every usable frame is written with fixed patterns and with its own
+//! addresses, and read back. The test works through the bootloader's
+//! physical memory mapping in 512-byte chunks, saving each chunk on the stack
+//! first and restoring it afterwards, with interrupts off, so memory that is
+//! already in use keeps its contents. Frames that fail are logged and kept
+//! out of the frame allocator for the rest of the boot.
+//!
+//! `memtest=<MiB>` stops after that much memory; a bare `memtest` tests all
+//! of it, which takes a while on a large machine.
+
+use alloc::vec::Vec;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::PhysAddr;
+
+use crate::config::MEMTEST_MAX_BAD_FRAMES;
+use crate::{kprintln, kerrorln, pstore};
+
+const FRAME_SIZE: u64 = 4096;
+const CHUNK_WORDS: usize = 64; // 512 bytes
+const PATTERNS: [u64; 4] = [0, u64::MAX, 0x5555_5555_5555_5555, 0xAAAA_AAAA_AAAA_AAAA];
+
+/// Physical addresses of the frames that failed, in the order found.
+static BAD_FRAMES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+/// Whether the frame at `addr` failed the test.
+pub fn is_bad(addr: PhysAddr) -> bool {
+    let frame = addr.as_u64() & !(FRAME_SIZE - 1);
+    BAD_FRAMES.lock().contains(&frame)
+}
+
+/// Tests one chunk in place. Returns false if any word read back wrong.
+///
+/// # Safety
+/// `chunk` must point to `CHUNK_WORDS` mapped words that nothing else
+/// touches while interrupts are off.
+unsafe fn test_chunk(chunk: *mut u64, phys: u64) -> bool {
+    let mut saved = [0u64; CHUNK_WORDS];
+    for (i, word) in saved.iter_mut().enumerate() {
+        *word = chunk.add(i).read_volatile();
+    }
+    let mut ok = true;
+    for pattern in PATTERNS {
+        for i in 0..CHUNK_WORDS {
+            chunk.add(i).write_volatile(pattern);
+        }
+        ok &= (0..CHUNK_WORDS).all(|i| chunk.add(i).read_volatile() == pattern);
+    }
+    // Each word holds its own address, which catches address lines that are stuck or shorted.
+    for i in 0..CHUNK_WORDS {
+        chunk.add(i).write_volatile(phys + (i * 8) as u64);
+    }
+    ok &= (0..CHUNK_WORDS).all(|i| chunk.add(i).read_volatile() == phys + (i * 8) as u64);
+    for (i, word) in saved.iter().enumerate() {
+        chunk.add(i).write_volatile(*word);
+    }
+    ok
+}
+
+/// Tests usable memory, up to `limit_mib` MiB if given.
+pub fn run(memory_regions: &MemoryRegions, physical_memory_offset: Option<u64>, limit_mib: Option<u64>) {
+    let Some(offset) = physical_memory_offset else {
+        kprintln!("[kernel] memtest: Physical memory isn't mapped; skipping the memory test.");
+        return;
+    };
+    let limit = limit_mib.map(|mib| mib.saturating_mul(1024 * 1024));
+    kprintln!("[kernel] memtest: Testing {} of usable memory...", match limit_mib {
+        Some(mib) => alloc::format!("up to {} MiB", mib),
+        None => alloc::string::String::from("all"),
+    });
+    let frames = memory_regions.iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable && region.end > region.start)
+        .flat_map(|region| (region.start..region.end).step_by(FRAME_SIZE as usize))
+        .filter(|addr| !pstore::reserves(PhysAddr::new(*addr)));
+
+    let mut tested = 0u64;
+    let mut bad = Vec::new();
+    for frame in frames {
+        if limit.is_some_and(|limit| tested >= limit) {
+            break;
+        }
+        let good = interrupts::without_interrupts(|| {
+            (0..FRAME_SIZE).step_by(CHUNK_WORDS * 8).all(|at| {
+                // SAFETY: the bootloader maps all physical memory at `offset`, and
+                // with interrupts off nothing else runs on this CPU while the chunk
+                // holds test patterns.
+                unsafe { test_chunk((offset + frame + at) as *mut u64, frame + at) }
+            })
+        });
+        if !good {
+            kerrorln!("[kernel] memtest: Frame {:#x} failed.", frame);
+            if bad.len() < MEMTEST_MAX_BAD_FRAMES {
+                bad.push(frame);
+            }
+        }
+        tested += FRAME_SIZE;
+    }
+    match bad.len() {
+        0 => kprintln!("[kernel] memtest: {} MiB tested, no errors.", tested / (1024 * 1024)),
+        n => kerrorln!("[kernel] memtest: {} MiB tested, {} bad frames; they won't be allocated.", tested / (1024 * 1024), n),
+    }
+    *BAD_FRAMES.lock() = bad;
+}
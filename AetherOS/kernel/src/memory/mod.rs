@@ -1,5 +1,8 @@
 pub mod frame_allocator;
 pub mod page_allocator;
+pub mod file_map;
+pub mod task_memory;
+pub mod memtest;
 
 use crate::kprintln;
 use bootloader_api::info::MemoryRegions;
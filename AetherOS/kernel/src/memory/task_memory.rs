@@ -0,0 +1,140 @@
+// kernel/src/memory/task_memory.rs
+
+#![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
+
+//! The memory of each loaded V-Node, as laid out by the ELF loader.
+//!
+//! Until tasks get their own page tables, a task's segments live in the
+//! `LoadedImage` the V-Node loader produced. Accesses from outside the task
+//! (the `SYS_DEBUG_*` syscalls) go through this module, which translates task
+//! addresses through the segment list. A copy stops at the first byte that is
+//! outside every segment, or in a segment without the needed permission, the
+//! way a user-pointer check would refuse it; it never reads past the image.
+
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::elf::{LoadedImage, Segment};
+use crate::task::tcb::TaskId;
+
+/// How a range is about to be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+static IMAGES: Mutex<BTreeMap<TaskId, LoadedImage>> = Mutex::new(BTreeMap::new());
+
+/// Records `image` as the memory of `task`.
+pub fn install(task: TaskId, image: LoadedImage) {
+    IMAGES.lock().insert(task, image);
+}
+
+/// Drops the memory of `task`. Returns false if it had none.
+pub fn release(task: TaskId) -> bool {
+    IMAGES.lock().remove(&task).is_some()
+}
+
+/// The segments of `task`, in load order. `None` if it has no image.
+pub fn segments(task: TaskId) -> Option<Vec<Segment>> {
+    IMAGES.lock().get(&task).map(|image| image.segments.clone())
+}
+
+/// The segment of `task` containing `addr`.
+pub fn segment_at(task: TaskId, addr: u64) -> Option<Segment> {
+    let images = IMAGES.lock();
+    images.get(&task)?.segments.iter().find(|segment| contains(segment, addr)).copied()
+}
+
+/// Whether `len` bytes from `addr` all lie in readable segments of `task`.
+/// False if the task has no image or the range wraps.
+pub fn covers(task: TaskId, addr: u64, len: u64) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let images = IMAGES.lock();
+    let Some(image) = images.get(&task) else {
+        return false;
+    };
+    // Segments may be adjacent, so walk from one to the next.
+    let mut at = addr;
+    while at < end {
+        match image.segments.iter().find(|segment| segment.readable && contains(segment, at)) {
+            Some(segment) => at = segment.vaddr + segment.memsz,
+            None => return false,
+        }
+    }
+    true
+}
+
+fn contains(segment: &Segment, addr: u64) -> bool {
+    addr >= segment.vaddr && addr - segment.vaddr < segment.memsz
+}
+
+/// Offset into `image.memory` and the number of bytes up to the end of the
+/// segment holding `addr`, if that segment permits `access`.
+fn translate(image: &LoadedImage, addr: u64, access: Access) -> Option<(usize, usize)> {
+    let segment = image.segments.iter().find(|segment| contains(segment, addr))?;
+    let permitted = match access {
+        Access::Read => segment.readable,
+        Access::Write => segment.writable,
+    };
+    if !permitted {
+        return None;
+    }
+    let offset = (addr - image.image_start) as usize;
+    let run = ((segment.vaddr + segment.memsz - addr) as usize).min(image.memory.len().checked_sub(offset)?);
+    Some((offset, run))
+}
+
+/// Copies between `task`'s memory at `addr` and `buf`, segment by segment.
+/// Returns the bytes copied, or `None` if the task has no image.
+fn copy(task: TaskId, addr: u64, len: usize, access: Access, mut f: impl FnMut(&mut [u8], usize)) -> Option<usize> {
+    let mut images = IMAGES.lock();
+    let image = images.get_mut(&task)?;
+    let mut done = 0;
+    while done < len {
+        let at = match addr.checked_add(done as u64) {
+            Some(at) => at,
+            None => break,
+        };
+        let (offset, run) = match translate(image, at, access) {
+            Some(found) => found,
+            None => break,
+        };
+        let run = run.min(len - done);
+        if run == 0 {
+            break;
+        }
+        f(&mut image.memory[offset..offset + run], done);
+        done += run;
+    }
+    Some(done)
+}
+
+/// Reads `out.len()` bytes at `addr`. Returns how many were readable.
+pub fn read(task: TaskId, addr: u64, out: &mut [u8]) -> Option<usize> {
+    copy(task, addr, out.len(), Access::Read, |memory, done| {
+        out[done..done + memory.len()].copy_from_slice(memory);
+    })
+}
+
+/// Writes `data` at `addr`. Returns how many bytes landed in writable segments.
+pub fn write(task: TaskId, addr: u64, data: &[u8]) -> Option<usize> {
+    copy(task, addr, data.len(), Access::Write, |memory, done| {
+        let len = memory.len();
+        memory.copy_from_slice(&data[done..done + len]);
+    })
+}
+
+/// Reads a little-endian `u64` at `addr`, if all eight bytes are readable.
+pub fn read_u64(task: TaskId, addr: u64) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    match read(task, addr, &mut bytes)? {
+        8 => Some(u64::from_le_bytes(bytes)),
+        _ => None,
+    }
+}
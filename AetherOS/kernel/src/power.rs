@@ -0,0 +1,77 @@
+// kernel/src/power.rs
+
+//! Powering the machine off and resetting it, for `SYS_SYSTEM_POWER`.
+//!
+//! Init stops the services and syncs the VFS before it makes the call, so
+//! what is left here is to keep the log and pull the plug. The log goes to
+//! pstore as `LAST_BOOT_SHUTDOWN`, which tells the next boot this one ended
+//! on purpose.
+//!
+//! There is no ACPI interpreter to find the real PM1a control port and S5
+//! sleep type, so power-off writes the S5 command to the ports of the
+//! machines we run on: QEMU's PIIX4 (0x604) and Bochs and older QEMU
+//! (0xB004). A reset pulses the CPU reset line through the 8042 keyboard
+//! controller. If the machine is still running after that, the CPU halts with
+//! interrupts off and the console says it is safe to power off.
+
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::instructions::port::Port;
+
+use crate::drivers::{framebuffer, ps2_controller};
+use crate::{kprintln, pstore};
+
+/// PM1a control ports, and the value that enters S5 (SLP_TYPa 0, SLP_EN) on each.
+const ACPI_POWER_OFF: [(u16, u16); 2] = [(0x604, 0x2000), (0xB004, 0x2000)];
+
+/// 8042 command that pulses the CPU reset line.
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+/// Spins given to each power-off or reset method before the next is tried.
+const SETTLE_SPINS: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Shutdown,
+    Reboot,
+}
+
+fn settle() {
+    for _ in 0..SETTLE_SPINS {
+        core::hint::spin_loop();
+    }
+}
+
+/// Dumps the log and powers the machine off or resets it. Never returns:
+/// if neither works, the CPU is halted for good.
+pub fn power(action: Action) -> ! {
+    // Nothing else runs from here on, so nothing can log after the dump.
+    interrupts::disable();
+    match action {
+        Action::Shutdown => kprintln!("[kernel] power: Powering off."),
+        Action::Reboot => kprintln!("[kernel] power: Restarting."),
+    }
+    pstore::shutdown_dump();
+
+    match action {
+        Action::Shutdown => {
+            for (port, value) in ACPI_POWER_OFF {
+                // SAFETY: On a machine without a PM1a control block at `port` the write goes nowhere.
+                unsafe { Port::<u16>::new(port).write(value) };
+                settle();
+            }
+            kprintln!("[kernel] power: No ACPI power-off port answered.");
+        },
+        Action::Reboot => {
+            if ps2_controller::write_command(KBC_PULSE_RESET) {
+                settle();
+            }
+            kprintln!("[kernel] power: The keyboard controller didn't reset the machine.");
+        },
+    }
+
+    framebuffer::reclaim();
+    kprintln!("[kernel] power: It is now safe to turn off the computer.");
+    loop {
+        hlt(); // With interrupts off, this only ends at an NMI
+    }
+}
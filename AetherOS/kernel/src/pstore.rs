@@ -0,0 +1,244 @@
+// kernel/src/pstore.rs
+
+//! Persistent log dump: the tail of the kernel log ring, kept in a fixed
+//! physical region that survives a warm reboot.
+//!
+//! The panic handler, a periodic checkpoint and an orderly shutdown write the
+//! newest `DUMP_CAPACITY` bytes of the ring there, behind a header with a magic, the
+//! boot's sequence number and a CRC-32. At the next boot `init` checks the
+//! region, sets a valid dump aside for `SYS_KLOG_READ` with `KLOG_LAST_BOOT`
+//! and clears it; the VFS saves it to /data/crash once it is up.
+//!
+//! Memory that wasn't preserved (a cold boot, or QEMU, which starts every
+//! boot with zeroed RAM) fails the magic check, and that just means there is
+//! no dump. The region is only used if the memory map reports it as usable
+//! RAM and the bootloader maps physical memory. The frame allocator never
+//! hands it out either way.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use x86_64::PhysAddr;
+
+use common::abi::{LastBootInfo, LAST_BOOT_CHECKPOINT, LAST_BOOT_INFO_LEN, LAST_BOOT_PANIC, LAST_BOOT_SHUTDOWN};
+
+use crate::config::{PSTORE_CHECKPOINT_SECS, PSTORE_PHYS_START, PSTORE_SIZE};
+use crate::{klog, kprintln, timer};
+use crate::timer::Ticks;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"AEPSTOR1");
+
+/// Start of the dump region, little-endian.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    magic: u64,
+    seq: u64,
+    len: u32, // Bytes of log text after the header
+    reason: u32, // One of the LAST_BOOT_* values
+    checksum: u32, // CRC-32 of seq, len, reason and the text
+    reserved: u32,
+}
+
+const HEADER_LEN: usize = core::mem::size_of::<Header>();
+
+/// Bytes of log a dump holds.
+pub const DUMP_CAPACITY: usize = PSTORE_SIZE - HEADER_LEN;
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[0..8].copy_from_slice(&self.magic.to_le_bytes());
+        out[8..16].copy_from_slice(&self.seq.to_le_bytes());
+        out[16..20].copy_from_slice(&self.len.to_le_bytes());
+        out[20..24].copy_from_slice(&self.reason.to_le_bytes());
+        out[24..28].copy_from_slice(&self.checksum.to_le_bytes());
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Self { magic: u64_at(0), seq: u64_at(8), len: u32_at(16), reason: u32_at(20), checksum: u32_at(24), reserved: 0 }
+    }
+
+    fn checksum(seq: u64, len: u32, reason: u32, text: &[u8]) -> u32 {
+        let mut crc = crc32_update(0xFFFF_FFFF, &seq.to_le_bytes());
+        crc = crc32_update(crc, &len.to_le_bytes());
+        crc = crc32_update(crc, &reason.to_le_bytes());
+        !crc32_update(crc, text)
+    }
+}
+
+/// CRC-32 (IEEE), bit by bit: a dump is checked once per boot, so a table isn't worth its size.
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Why a region holds no usable dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpError {
+    /// No dump was written, or the memory wasn't preserved.
+    Empty,
+    /// A dump was written but doesn't check out, e.g. it was cut short by a reset.
+    Corrupt,
+}
+
+/// Writes a dump of the newest log bytes into `region`, filled in by `fill`,
+/// which returns how many bytes it wrote. The magic goes in last, so a dump cut
+/// short by a reset is not taken for a valid one.
+pub fn write_dump(region: &mut [u8], seq: u64, reason: u32, fill: impl FnOnce(&mut [u8]) -> usize) {
+    region[..8].fill(0);
+    let (header, text) = region.split_at_mut(HEADER_LEN);
+    let len = fill(&mut text[..DUMP_CAPACITY]).min(DUMP_CAPACITY) as u32;
+    let checksum = Header::checksum(seq, len, reason, &text[..len as usize]);
+    let bytes = Header { magic: MAGIC, seq, len, reason, checksum, reserved: 0 }.to_bytes();
+    header[8..].copy_from_slice(&bytes[8..]);
+    header[..8].copy_from_slice(&bytes[..8]);
+}
+
+/// Checks the dump in `region`. Returns its sequence number, reason and text.
+pub fn read_dump(region: &[u8]) -> Result<(u64, u32, &[u8]), DumpError> {
+    let header = Header::from_bytes(&region[..HEADER_LEN]);
+    if header.magic != MAGIC {
+        return Err(DumpError::Empty);
+    }
+    if header.len as usize > DUMP_CAPACITY || !matches!(header.reason, LAST_BOOT_CHECKPOINT | LAST_BOOT_PANIC | LAST_BOOT_SHUTDOWN) {
+        return Err(DumpError::Corrupt);
+    }
+    let text = &region[HEADER_LEN..HEADER_LEN + header.len as usize];
+    if Header::checksum(header.seq, header.len, header.reason, text) != header.checksum {
+        return Err(DumpError::Corrupt);
+    }
+    Ok((header.seq, header.reason, text))
+}
+
+/// Invalidates the dump in `region`.
+pub fn clear(region: &mut [u8]) {
+    region[..HEADER_LEN].fill(0);
+}
+
+/// Whether the frame at `addr` belongs to the dump region.
+pub fn reserves(addr: PhysAddr) -> bool {
+    (PSTORE_PHYS_START..PSTORE_PHYS_START + PSTORE_SIZE as u64).contains(&addr.as_u64())
+}
+
+/// Virtual address of the region, or 0 while there is none.
+static REGION: AtomicU64 = AtomicU64::new(0);
+/// Sequence number of this boot.
+static SEQ: AtomicU64 = AtomicU64::new(1);
+/// Tick of the next checkpoint.
+static NEXT_CHECKPOINT: AtomicU64 = AtomicU64::new(0);
+/// `klog` bytes written as of the last checkpoint; nothing is rewritten while it is unchanged.
+static CHECKPOINTED: AtomicU64 = AtomicU64::new(0);
+
+struct LastBoot {
+    info: Option<LastBootInfo>,
+    len: usize,
+    text: [u8; DUMP_CAPACITY],
+}
+
+/// The previous boot's dump, moved out of the region at boot.
+static LAST_BOOT: Mutex<LastBoot> = Mutex::new(LastBoot { info: None, len: 0, text: [0; DUMP_CAPACITY] });
+
+fn region() -> Option<&'static mut [u8]> {
+    match REGION.load(Ordering::Acquire) {
+        0 => None,
+        // SAFETY: `init` checked that the region is RAM mapped at this address,
+        // and the frame allocator never hands it out.
+        addr => Some(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PSTORE_SIZE) }),
+    }
+}
+
+/// Recovers the previous boot's dump, if there is one, and clears the region
+/// for this boot. Leaves the dump disabled if the region isn't usable.
+pub fn init(memory_regions: &MemoryRegions, physical_memory_offset: Option<u64>) {
+    let Some(offset) = physical_memory_offset else {
+        kprintln!("[kernel] pstore: Physical memory isn't mapped; the log won't survive a reboot.");
+        return;
+    };
+    let end = PSTORE_PHYS_START + PSTORE_SIZE as u64;
+    let usable = memory_regions.iter()
+        .any(|r| r.kind == MemoryRegionKind::Usable && r.start <= PSTORE_PHYS_START && end <= r.end);
+    if !usable {
+        kprintln!("[kernel] pstore: {:#x}..{:#x} isn't usable RAM; the log won't survive a reboot.", PSTORE_PHYS_START, end);
+        return;
+    }
+    REGION.store(offset + PSTORE_PHYS_START, Ordering::Release);
+    let region = region().expect("just set");
+
+    match read_dump(region) {
+        Ok((seq, reason, text)) => {
+            let mut last = LAST_BOOT.lock();
+            last.text[..text.len()].copy_from_slice(text);
+            last.len = text.len();
+            last.info = Some(LastBootInfo { seq, reason });
+            SEQ.store(seq + 1, Ordering::Relaxed);
+            let how = match reason {
+                LAST_BOOT_PANIC => "panicked",
+                LAST_BOOT_SHUTDOWN => "shut down cleanly",
+                _ => "was checkpointed",
+            };
+            kprintln!("[kernel] pstore: Recovered {} bytes of log from boot {}, which {}.", text.len(), seq, how);
+        },
+        Err(DumpError::Corrupt) => kprintln!("[kernel] pstore: Discarding a corrupt log dump."),
+        Err(DumpError::Empty) => {},
+    }
+    clear(region);
+    NEXT_CHECKPOINT.store((timer::get_current_ticks() + Ticks::from_secs(PSTORE_CHECKPOINT_SECS)).raw(), Ordering::Relaxed);
+}
+
+fn dump(reason: u32) {
+    if let Some(region) = region() {
+        let seq = SEQ.load(Ordering::Relaxed);
+        klog::with_ring(|ring| {
+            write_dump(region, seq, reason, |text| ring.copy_tail(text));
+            CHECKPOINTED.store(ring.written(), Ordering::Relaxed);
+        });
+    }
+}
+
+/// Writes a checkpoint if one is due and the log has grown since the last.
+/// Called from the idle loop.
+pub fn checkpoint() {
+    let now = timer::get_current_ticks();
+    if now < Ticks::from_raw(NEXT_CHECKPOINT.load(Ordering::Relaxed)) {
+        return;
+    }
+    NEXT_CHECKPOINT.store((now + Ticks::from_secs(PSTORE_CHECKPOINT_SECS)).raw(), Ordering::Relaxed);
+    if klog::with_ring(|ring| ring.written()) != CHECKPOINTED.load(Ordering::Relaxed) {
+        dump(LAST_BOOT_CHECKPOINT);
+    }
+}
+
+/// Dumps the log from the panic handler, after the panic has been printed.
+/// `klog::panic_takeover` must have run.
+pub fn panic_dump() {
+    dump(LAST_BOOT_PANIC);
+}
+
+/// Dumps the log for an orderly shutdown or reboot, after the last line has
+/// been printed, with interrupts off.
+pub fn shutdown_dump() {
+    dump(LAST_BOOT_SHUTDOWN);
+}
+
+/// Copies the previous boot's `LastBootInfo` record and as much of its log as
+/// fits into `out`. Returns the length of both together, or `None` if the
+/// previous boot left no dump.
+pub fn read_last_boot(out: &mut [u8]) -> Option<usize> {
+    let last = LAST_BOOT.lock();
+    let info = last.info?;
+    if out.len() >= LAST_BOOT_INFO_LEN {
+        out[..LAST_BOOT_INFO_LEN].copy_from_slice(&info.to_bytes());
+        let n = last.len.min(out.len() - LAST_BOOT_INFO_LEN);
+        out[LAST_BOOT_INFO_LEN..LAST_BOOT_INFO_LEN + n].copy_from_slice(&last.text[..n]);
+    }
+    Some(LAST_BOOT_INFO_LEN + last.len)
+}
@@ -0,0 +1,37 @@
+// kernel/src/sysmon.rs
+
+#![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
+
+use crate::kprintln;
+use crate::drivers::{input, ps2_keyboard, ps2_mouse};
+use crate::task::{cpu, faults, scheduler};
+use crate::{heap, logfwd, timer};
+
+/// Prints a snapshot of kernel statistics to the console.
+/// Intended for debugging. V-Node metrics are collected separately by the
+/// `sysmon` V-Node, which scrapes each service's `Metrics` endpoint; per-owner
+/// usage (`VfsRequest::GetUsage`) and the ARP table (`NetStackRequest::GetNeighbors`)
+/// are queried from those services directly.
+pub fn report() {
+    kprintln!("[kernel] sysmon: ---- report at tick {} ----", timer::get_current_ticks());
+    scheduler::for_each_task(|task| {
+        let stats = task.stats();
+        kprintln!(
+            "[kernel] sysmon: task {} '{}' state={:?} cpu={} log_messages={} log_suppressed={} faults={}",
+            task.id, task.name, task.state, task.last_cpu.map_or(-1, |cpu| cpu as i64), stats.log_messages, stats.log_suppressed, task.faults.total()
+        );
+    });
+    kprintln!("[kernel] sysmon: cpus online={:#x}", cpu::online_mask());
+    kprintln!(
+        "[kernel] sysmon: exceptions nmis={} machine_checks={} fault_storm_threshold={} storms_dropped={}",
+        faults::nmis(), faults::machine_checks(), faults::storm_threshold(), faults::dropped_storms()
+    );
+    kprintln!("[kernel] sysmon: log forwarded_dropped={}", logfwd::dropped());
+    kprintln!("[kernel] sysmon: input keyboard_resyncs={} mouse_resyncs={} events_dropped={}", ps2_keyboard::resyncs(), ps2_mouse::resyncs(), input::dropped());
+    let heap = heap::stats();
+    kprintln!("[kernel] sysmon: heap size={} used={} free={}", heap.size, heap.used, heap.free);
+    #[cfg(feature = "heap-debug")]
+    for site in heap.top_sites.iter().filter(|site| site.live_allocations > 0) {
+        kprintln!("[kernel] sysmon: heap site={:#x} live_bytes={} live_allocations={}", site.site, site.live_bytes, site.live_allocations);
+    }
+}
@@ -6,9 +6,20 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 use alloc::string::String;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use common::abi::{IpcCreds, SyscallSet};
+
 use crate::caps::Capability;
-use crate::task::tcb::{TaskControlBlock, TaskState};
+use crate::task::tcb::{Identity, TaskControlBlock, TaskId, TaskLaunch, TaskState, TaskStats};
 use crate::task::scheduler;
+use crate::task::ratelimit::LogDecision;
+use crate::config::LOG_SUPPRESSION_REPORT_INTERVAL_SECS;
+use crate::timer::{self, Ticks};
+use crate::arch::x86_64::{dma, irq};
+use crate::drivers::ac97;
+use crate::memory::{file_map, task_memory};
+use crate::{ipc, kprintln, logfwd};
 
 // Re-export TaskState and Capability for convenience if needed by external modules
 pub use crate::task::tcb::TaskState;
@@ -20,27 +31,242 @@ pub fn init() {
 }
 
 /// Creates a new task and adds it to the scheduler.
-pub fn create_task(id: u64, name: &str, capabilities: Vec<Capability>) {
+pub fn create_task(id: TaskId, name: &str, capabilities: Vec<Capability>) {
     let tcb = TaskControlBlock::new(id, String::from(name), capabilities);
     scheduler::add_task(tcb);
 }
 
+/// Returns true if the task is still alive (known to the scheduler).
+pub fn task_exists(task_id: TaskId) -> bool {
+    scheduler::task_exists(task_id)
+}
+
+/// Frees every kernel resource attributed to `task_id`: the audio output,
+/// DMA buffers and domains, IRQ
+/// registrations, the mailboxes it receives on, its IPC calls, its file
+/// mappings and its image.
+pub fn release_task_resources(task_id: TaskId) {
+    ac97::release_task(task_id); // Stop the controller before its ring is freed
+    let buffers = dma::release_task_buffers(task_id);
+    let leaked = dma::release_task_domains(task_id); // Each leak was logged
+    let irqs = irq::release_task_irqs(task_id);
+    let mailboxes = ipc::mailbox::close_task_mailboxes(task_id);
+    let calls = ipc::call::release_task(task_id);
+    let mappings = file_map::release_task_mappings(task_id);
+    task_memory::release(task_id);
+    if logfwd::release_task(task_id) {
+        kprintln!("[kernel] task: Task {} was the log daemon; SYS_LOG messages are no longer forwarded.", task_id);
+    }
+    kprintln!(
+        "[kernel] task: Released resources of task {} ({} DMA buffers, {} leaked DMA mappings, {} IRQs, {} mailboxes, {} IPC calls, {} file mappings).",
+        task_id, buffers, leaked, irqs, mailboxes, calls, mappings
+    );
+
+    debug_assert_eq!(dma::count_task_buffers(task_id), 0, "DMA buffers still attributed to dead task");
+    debug_assert_eq!(dma::count_task_mappings(task_id), 0, "DMA mappings still attributed to dead task");
+    debug_assert_eq!(irq::count_task_irqs(task_id), 0, "IRQs still attributed to dead task");
+    debug_assert_eq!(ipc::mailbox::count_task_mailboxes(task_id), 0, "Mailboxes still attributed to dead task");
+    debug_assert_eq!(ipc::call::pending_calls_of(task_id), 0, "IPC calls still attributed to dead task");
+    debug_assert_eq!(file_map::count_task_mappings(task_id), 0, "File mappings still attributed to dead task");
+}
+
+/// Applies the task's SYS_LOG token bucket. Dropped messages are counted
+/// for the summary `collect_suppressed_logs` takes.
+pub fn check_log_rate(task_id: TaskId) -> LogDecision {
+    let now = timer::get_current_ticks();
+    let decision = scheduler::with_task_mut(task_id, |tcb| tcb.log_limiter.check(now, tcb.limits.log_rate_per_sec, tcb.limits.log_burst));
+    // Unknown tasks (early boot) are not limited.
+    decision.unwrap_or(LogDecision::Allow)
+}
+
+/// Summaries waiting to be printed; further tasks stay pending in their limiters.
+const MAX_SUMMARIES_PER_REPORT: usize = 8;
+
+/// Summaries the timer took from the task table, for `print_suppressed_logs`.
+struct DueSummaries {
+    entries: [(TaskId, u64); MAX_SUMMARIES_PER_REPORT],
+    count: usize,
+}
+
+static DUE_SUMMARIES: Mutex<DueSummaries> = Mutex::new(DueSummaries { entries: [(TaskId::KERNEL, 0); MAX_SUMMARIES_PER_REPORT], count: 0 });
+
+/// Takes the summary of every task that dropped messages and whose report
+/// interval is over, so the last summary of a flood is due even when the
+/// task logs nothing afterwards. Called from the timer interrupt: it prints
+/// nothing, since the console locks are taken with interrupts enabled, and
+/// skips a turn if the task table or the due list is busy. Returns the number
+/// of summaries taken.
+pub fn collect_suppressed_logs(now: Ticks) -> usize {
+    let Some(mut due) = DUE_SUMMARIES.try_lock() else {
+        return 0;
+    };
+    let interval = Ticks::from_secs(LOG_SUPPRESSION_REPORT_INTERVAL_SECS);
+    let first = due.count;
+    scheduler::try_for_each_task_mut(|tcb| {
+        if due.count < MAX_SUMMARIES_PER_REPORT {
+            if let Some(suppressed) = tcb.log_limiter.take_summary(now, interval) {
+                let at = due.count;
+                due.entries[at] = (tcb.id, suppressed);
+                due.count += 1;
+            }
+        }
+    });
+    due.count - first
+}
+
+/// Prints one line per summary the timer took. Called from task context, by
+/// the idle loop. Returns the number of lines printed.
+pub fn print_suppressed_logs() -> usize {
+    // The timer takes the list too, so it must not interrupt us holding it.
+    let (entries, count) = interrupts::without_interrupts(|| {
+        let mut due = DUE_SUMMARIES.lock();
+        let count = core::mem::take(&mut due.count);
+        (due.entries, count)
+    });
+    for (task_id, suppressed) in &entries[..count] {
+        kprintln!("[kernel] log: task {}: suppressed {} messages.", task_id, suppressed);
+    }
+    count
+}
+
+/// Sets the instruction pointer the task's first context switch restores.
+pub fn set_entry_point(task_id: TaskId, entry_point: u64) -> bool {
+    scheduler::with_task_mut(task_id, |tcb| tcb.regs.rip = entry_point).is_some()
+}
+
+/// Gives a loaded task its entry point, stack and args page (see `TaskControlBlock::launch`).
+pub fn set_launch(task_id: TaskId, launch: TaskLaunch) -> bool {
+    scheduler::with_task_mut(task_id, |tcb| tcb.launch(launch)).is_some()
+}
+
+/// Narrows a task's syscall filter to `allowed` (see `TaskControlBlock::restrict_filter`).
+/// Returns false if the task doesn't exist.
+pub fn restrict_syscall_filter(task_id: TaskId, allowed: SyscallSet) -> bool {
+    scheduler::with_task_mut(task_id, |tcb| tcb.restrict_filter(allowed)).is_some()
+}
+
+/// Keeps the task's memory and registers out of its core dumps.
+/// Returns false if the task doesn't exist.
+pub fn set_no_core_dump(task_id: TaskId) -> bool {
+    scheduler::with_task_mut(task_id, |tcb| tcb.no_core_dump = true).is_some()
+}
+
+/// Counts a syscall the task's filter turned away. Returns the task's count so far.
+pub fn record_filter_violation(task_id: TaskId) -> u64 {
+    scheduler::with_task_mut(task_id, |tcb| {
+        tcb.filter_violations += 1;
+        tcb.filter_violations
+    }).unwrap_or(0)
+}
+
+/// Copies the task's startup info into `out` if it fits, and returns its
+/// length either way. `None` if the task has none.
+pub fn read_startup_info(task_id: TaskId, out: &mut [u8]) -> Option<usize> {
+    scheduler::with_task_mut(task_id, |tcb| {
+        if tcb.args.is_empty() {
+            return None;
+        }
+        if let Some(dest) = out.get_mut(..tcb.args.len()) {
+            dest.copy_from_slice(&tcb.args);
+        }
+        Some(tcb.args.len())
+    }).flatten()
+}
+
+/// IDs of all tasks, in ascending order.
+pub fn task_ids() -> Vec<TaskId> {
+    let mut ids = Vec::new();
+    scheduler::for_each_task(|task| ids.push(task.id));
+    ids
+}
+
+/// Returns the statistics of a task, if it exists.
+pub fn task_stats(task_id: TaskId) -> Option<TaskStats> {
+    scheduler::with_task_mut(task_id, |tcb| tcb.stats())
+}
+
+/// Restricts the CPUs a task may run on. Returns false if the task doesn't
+/// exist or the mask names no online CPU.
+pub fn set_affinity(task_id: TaskId, affinity: crate::task::cpu::CpuMask) -> bool {
+    scheduler::set_affinity(task_id, affinity)
+}
+
+/// Returns the identity bound to a task. `None` if the task doesn't exist or is unauthenticated.
+pub fn task_identity(task_id: TaskId) -> Option<Identity> {
+    scheduler::with_task_mut(task_id, |tcb| tcb.identity).flatten()
+}
+
+/// Binds (or with `None`, clears) the identity of a task. Returns false if the task doesn't exist.
+pub fn set_task_identity(task_id: TaskId, identity: Option<Identity>) -> bool {
+    scheduler::with_task_mut(task_id, |tcb| tcb.identity = identity).is_some()
+}
+
+/// The credentials a message sent by `task_id` carries now (see
+/// `TaskControlBlock::creds`). Task 0 is the kernel, which sends IRQ
+/// notifications and startup info; it never takes the scheduler's lock, so
+/// it is safe from interrupt context.
+pub fn credentials_of(task_id: TaskId) -> IpcCreds {
+    let mut creds = IpcCreds { sender: task_id.raw(), ..IpcCreds::default() };
+    if task_id == TaskId::KERNEL {
+        creds.name[..6].copy_from_slice(b"kernel");
+        return creds;
+    }
+    scheduler::with_task_mut(task_id, |tcb| tcb.creds()).unwrap_or(creds)
+}
+
+/// Records the credentials of the message a task just received.
+pub fn record_last_creds(task_id: TaskId, creds: IpcCreds) {
+    scheduler::with_task_mut(task_id, |tcb| tcb.last_creds = Some(creds));
+}
+
+/// Records the reply token of the message a task just received; `None` if it
+/// wasn't an IPC call.
+pub fn record_reply_token(task_id: TaskId, reply_token: Option<u64>) {
+    scheduler::with_task_mut(task_id, |tcb| tcb.reply_token = reply_token);
+}
+
+/// Returns the reply token of the last message a task received, if it was a call.
+pub fn reply_token(task_id: TaskId) -> Option<u64> {
+    scheduler::with_task_mut(task_id, |tcb| tcb.reply_token).flatten()
+}
+
+/// Returns the credentials of the last message a task received.
+pub fn last_creds(task_id: TaskId) -> Option<IpcCreds> {
+    scheduler::with_task_mut(task_id, |tcb| tcb.last_creds).flatten()
+}
+
+/// Returns the sender of the last message a task received.
+pub fn last_sender(task_id: TaskId) -> Option<TaskId> {
+    last_creds(task_id).map(|creds| TaskId::from_raw(creds.sender))
+}
+
+/// Tears down a task: removes it from the scheduler and releases its resources.
+pub fn kill_task(task_id: TaskId) {
+    // Don't lose the tail of a flood: report anything still pending.
+    let pending = scheduler::with_task_mut(task_id, |tcb| tcb.log_limiter.take_summary(Ticks::from_raw(u64::MAX), Ticks::ZERO)).flatten();
+    if let Some(count) = pending {
+        kprintln!("[kernel] log: task {}: suppressed {} messages.", task_id, count);
+    }
+    scheduler::remove_task(task_id);
+    release_task_resources(task_id);
+}
+
 /// Returns a clone of the currently executing task's TaskControlBlock.
 pub fn get_current_task() -> TaskControlBlock {
     scheduler::get_current_task_tcb()
 }
 
-/// Blocks the current task on an IPC channel.
-pub fn block_current_on_channel(channel_id: u32) {
-    // In a real IPC implementation, the channel ID would be associated with the task
-    // and used by `ipc::kernel_send` to unblock.
-    // For now, this just marks the task as blocked and triggers a schedule.
-    scheduler::block_current_task();
-    // The IPC module will directly unblock by calling `scheduler::unblock_task`.
+/// Blocks the current task on an IPC channel until a message arrives there.
+/// Returns at once if one is already queued.
+pub fn block_current_on_channel(channel_id: ipc::ChannelId) {
+    // The mailbox records the task as its waiter, and `ipc::kernel_send` unblocks it.
+    if ipc::kernel_block_unless_ready(channel_id, scheduler::current_task_id()) {
+        scheduler::schedule();
+    }
 }
 
-/// Unblocks a task that was waiting on a specific IPC channel.
-pub fn unblock_task_on_channel(task_id: u64) {
+/// Unblocks a task that was waiting on an IPC channel.
+pub fn unblock_task_on_channel(task_id: TaskId) {
     scheduler::unblock_task(task_id);
 }
 
@@ -0,0 +1,195 @@
+// kernel/src/task/coredump.rs
+
+#![allow(dead_code)]
+
+//! Core dumps (the format is in `common::coredump`).
+//!
+//! When an exception handler kills a task, and when a debugger asks with
+//! `SYS_DEBUG_DUMP`, the task's registers, its regions and the contents of
+//! its writable ones are copied into a dump that is queued here until init
+//! takes it with `SYS_CORE_DUMP_TAKE` and writes it to `/data/crash`. The
+//! copy is made right away: a killed task's image is released with it.
+//!
+//! The size cap, which init sets with every take, bounds the whole dump.
+//! Contents that don't fit are cut, the largest regions first (see
+//! `common::coredump::fit_regions`). A cap of 0 still queues the header,
+//! the registers and the region list. A task loaded with the no-dump flag
+//! gets a header and nothing else, so its keys never leave it.
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+
+use common::abi::{RegisterFrame, TASK_NAME_LEN};
+use common::coredump::{self, CoreHeader, RegionHeader, CORE_FLAG_PARTIAL_REGISTERS, CORE_FLAG_TRUNCATED, CORE_FLAG_WITHHELD, CORE_HEADER_LEN, REGION_EXECUTE, REGION_FILE, REGION_HEADER_LEN, REGION_READ, REGION_WRITE, REGISTERS_LEN};
+
+use crate::kprintln;
+use crate::memory::{file_map, task_memory};
+use crate::task::scheduler;
+use crate::task::tcb::TaskId;
+use crate::timer;
+
+/// Dumps kept until init takes them. Past this the newest are dropped, and counted.
+const MAX_PENDING_DUMPS: usize = 4;
+
+/// The cap until init first sets one, in KiB. Matches the default of the
+/// `tasks.core_dump_max_kb` setting.
+pub const DEFAULT_SIZE_CAP_KB: u32 = 1024;
+
+/// What the exception handler knows about the fault that the TCB doesn't.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultContext {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+    pub cs: u64,
+    pub ss: u64,
+    /// CR2 for a page fault, 0 otherwise.
+    pub addr: u64,
+    pub error_code: u64,
+}
+
+/// A dump waiting for init, with the contents already copied out.
+struct StagedDump {
+    header: CoreHeader,
+    registers: RegisterFrame,
+    regions: Vec<(RegionHeader, Vec<u8>)>,
+}
+
+impl StagedDump {
+    fn len(&self) -> usize {
+        let stored: Vec<u64> = self.regions.iter().map(|(region, _)| region.stored).collect();
+        coredump::dump_len(&stored) as usize
+    }
+
+    /// Writes the dump to `out`, which is at least `len()` bytes.
+    fn write(&self, out: &mut [u8]) {
+        out[..CORE_HEADER_LEN].copy_from_slice(&self.header.to_bytes());
+        let mut at = CORE_HEADER_LEN;
+        out[at..at + REGISTERS_LEN].copy_from_slice(&coredump::registers_to_bytes(&self.registers));
+        at += REGISTERS_LEN;
+        for (region, contents) in &self.regions {
+            out[at..at + REGION_HEADER_LEN].copy_from_slice(&region.to_bytes());
+            at += REGION_HEADER_LEN;
+            out[at..at + contents.len()].copy_from_slice(contents);
+            at += contents.len();
+        }
+    }
+}
+
+static SIZE_CAP_KB: AtomicU32 = AtomicU32::new(DEFAULT_SIZE_CAP_KB);
+static PENDING: Mutex<VecDeque<StagedDump>> = Mutex::new(VecDeque::new());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the cap on a dump's size, in KiB. 0 keeps no contents.
+pub fn set_size_cap_kb(kb: u32) {
+    SIZE_CAP_KB.store(kb, Ordering::Relaxed);
+}
+
+pub fn size_cap_kb() -> u32 {
+    SIZE_CAP_KB.load(Ordering::Relaxed)
+}
+
+/// Dumps a task the exception handler is about to kill. `reason` is one of
+/// the `CORE_REASON_*` values. Only the registers in `context` are from the
+/// fault; the general registers are the ones saved at the last switch.
+pub fn capture_fault(task_id: TaskId, reason: u32, context: FaultContext) {
+    let Some(mut registers) = scheduler::with_task_mut(task_id, |tcb| tcb.regs) else {
+        return;
+    };
+    registers.rip = context.rip;
+    registers.rsp = context.rsp;
+    registers.rflags = context.rflags;
+    registers.cs = context.cs;
+    registers.ss = context.ss;
+    stage(task_id, reason, context.addr, context.error_code, registers, CORE_FLAG_PARTIAL_REGISTERS);
+}
+
+/// Dumps a task that isn't running, for `SYS_DEBUG_DUMP`; its saved
+/// registers are complete. The caller has checked the target.
+pub fn capture_requested(task_id: TaskId, registers: RegisterFrame) {
+    stage(task_id, coredump::CORE_REASON_REQUESTED, 0, 0, registers, 0);
+}
+
+fn stage(task_id: TaskId, reason: u32, fault_addr: u64, error_code: u64, registers: RegisterFrame, flags: u32) {
+    let Some((name_str, withheld)) = scheduler::with_task_mut(task_id, |tcb| (tcb.name.clone(), tcb.no_core_dump)) else {
+        return;
+    };
+    let mut name = [0u8; TASK_NAME_LEN];
+    let len = name_str.len().min(TASK_NAME_LEN);
+    name[..len].copy_from_slice(&name_str.as_bytes()[..len]);
+    let mut header = CoreHeader { reason, task: task_id.raw(), fault_addr, error_code, tick: timer::get_current_ticks().raw(), region_count: 0, flags, name };
+
+    let dump = if withheld {
+        header.flags = CORE_FLAG_WITHHELD;
+        StagedDump { header, registers: RegisterFrame::default(), regions: Vec::new() }
+    } else {
+        let regions = copy_regions(task_id, &mut header);
+        header.region_count = regions.len() as u32;
+        StagedDump { header, registers, regions }
+    };
+    kprintln!(
+        "[kernel] coredump: Task {} ({}): {}, {} regions, {} bytes{}.",
+        task_id, name_str, coredump::reason_name(reason), dump.regions.len(), dump.len(),
+        if withheld { ", withheld" } else if dump.header.flags & CORE_FLAG_TRUNCATED != 0 { ", truncated" } else { "" }
+    );
+    let mut pending = PENDING.lock();
+    if pending.len() < MAX_PENDING_DUMPS {
+        pending.push_back(dump);
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The task's segments, then its file mappings, with the part of each
+/// writable segment that fits the cap copied out.
+fn copy_regions(task_id: TaskId, header: &mut CoreHeader) -> Vec<(RegionHeader, Vec<u8>)> {
+    let segments = task_memory::segments(task_id).unwrap_or_default();
+    let mappings = file_map::task_mappings(task_id);
+    let mut regions: Vec<RegionHeader> = segments.iter().map(|segment| RegionHeader {
+        vaddr: segment.vaddr,
+        memsz: segment.memsz,
+        flags: (if segment.readable { REGION_READ } else { 0 }) | (if segment.writable { REGION_WRITE } else { 0 }) | (if segment.executable { REGION_EXECUTE } else { 0 }),
+        stored: 0,
+    }).collect();
+    regions.extend(mappings.iter().map(|(addr, len)| RegionHeader { vaddr: *addr as u64, memsz: *len as u64, flags: REGION_READ | REGION_FILE, stored: 0 }));
+
+    let wanted: Vec<u64> = regions.iter().map(|region| if region.flags & REGION_WRITE != 0 { region.memsz } else { 0 }).collect();
+    let overhead = coredump::dump_len(&alloc::vec![0; regions.len()]);
+    let budget = (size_cap_kb() as u64 * 1024).saturating_sub(overhead);
+    let fitted = coredump::fit_regions(&wanted, budget);
+
+    regions.into_iter().zip(wanted.iter().zip(fitted.iter())).map(|(mut region, (wanted, fitted))| {
+        let mut contents = alloc::vec![0u8; *fitted as usize];
+        let copied = task_memory::read(task_id, region.vaddr, &mut contents).unwrap_or(0);
+        contents.truncate(copied);
+        region.stored = copied as u64;
+        if region.stored < *wanted {
+            header.flags |= CORE_FLAG_TRUNCATED;
+        }
+        (region, contents)
+    }).collect()
+}
+
+/// Copies the oldest queued dump to `out` and drops it, if it fits. Returns
+/// its length either way, so a caller with too small a buffer can come back
+/// with a bigger one; 0 if none is queued.
+pub fn take(out: &mut [u8]) -> usize {
+    let mut pending = PENDING.lock();
+    let Some(dump) = pending.front() else {
+        return 0;
+    };
+    let len = dump.len();
+    if out.len() >= len {
+        dump.write(&mut out[..len]);
+        pending.pop_front();
+    }
+    len
+}
+
+/// Dumps dropped because init didn't take them in time.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
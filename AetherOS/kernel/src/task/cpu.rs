@@ -0,0 +1,78 @@
+// kernel/src/task/cpu.rs
+
+//! CPU ids, affinity masks and per-CPU state.
+//!
+//! Only CPU 0 is online unless the `smp` feature brings up more (see
+//! `arch::x86_64::smp`). Code that needs "the current task" or "this CPU's
+//! queue" goes through here, so it doesn't change when more CPUs come online.
+
+#![allow(dead_code)]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::x86_64::smp;
+use crate::config::MAX_CPUS;
+
+/// Index of a CPU, `0..MAX_CPUS`. CPU 0 is the bootstrap processor.
+pub type CpuId = usize;
+
+/// A set of CPUs, one bit per `CpuId`.
+pub type CpuMask = u64;
+
+/// Every CPU, including ones that come online later.
+pub const ALL_CPUS: CpuMask = !0;
+
+const _: () = assert!(MAX_CPUS <= CpuMask::BITS as usize);
+
+pub const fn mask_of(cpu: CpuId) -> CpuMask {
+    1 << cpu
+}
+
+/// CPUs that run the scheduler. The bootstrap processor is online from the start.
+static ONLINE: AtomicU64 = AtomicU64::new(mask_of(0));
+
+pub fn online_mask() -> CpuMask {
+    ONLINE.load(Ordering::Acquire)
+}
+
+pub fn is_online(cpu: CpuId) -> bool {
+    online_mask() & mask_of(cpu) != 0
+}
+
+pub fn online_count() -> usize {
+    online_mask().count_ones() as usize
+}
+
+/// Marks a CPU online. Called by the CPU itself once it can schedule.
+pub fn set_online(cpu: CpuId) {
+    ONLINE.fetch_or(mask_of(cpu), Ordering::AcqRel);
+}
+
+/// The CPU this code is running on.
+pub fn current_cpu() -> CpuId {
+    smp::current_cpu()
+}
+
+/// One `T` per CPU. `get` returns the slot of the CPU it runs on, so a
+/// per-CPU variable is declared once instead of as a global that silently
+/// assumes one CPU. Slots are shared, so `T` provides its own interior
+/// mutability (atomics, locks).
+pub struct CpuLocal<T> {
+    slots: [T; MAX_CPUS],
+}
+
+impl<T> CpuLocal<T> {
+    pub const fn new(slots: [T; MAX_CPUS]) -> Self {
+        Self { slots }
+    }
+
+    /// This CPU's slot.
+    pub fn get(&self) -> &T {
+        &self.slots[current_cpu()]
+    }
+
+    /// Another CPU's slot.
+    pub fn on(&self, cpu: CpuId) -> &T {
+        &self.slots[cpu]
+    }
+}
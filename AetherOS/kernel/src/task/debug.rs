@@ -0,0 +1,139 @@
+// kernel/src/task/debug.rs
+
+#![allow(dead_code)]
+
+//! Inspecting and controlling another task: the operations behind the
+//! `SYS_DEBUG_*` syscalls. The caller must hold `Capability::Debug`; that is
+//! checked by the dispatcher. Nothing here may target the kernel task or the
+//! caller itself (a task that suspended itself could never resume).
+
+use common::abi::{RegisterFrame, DEBUG_MEM_MAX};
+
+use crate::memory::task_memory;
+use crate::syscall::{E_ACC_DENIED, E_BUSY, E_INVALID_ARG};
+use crate::task::{coredump, scheduler};
+use crate::task::tcb::{TaskId, TaskState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugError {
+    /// The target is the kernel task.
+    Protected,
+    /// The target is the calling task.
+    SelfTarget,
+    NoSuchTask,
+    /// The target is running, so it has no saved registers.
+    Running,
+    /// The target has no loaded image, so there is no memory to access.
+    NoImage,
+    /// More than `DEBUG_MEM_MAX` bytes were requested.
+    TooLarge,
+    /// The target's service asked for no core dumps.
+    NoDump,
+}
+
+impl DebugError {
+    pub fn to_syscall_code(self) -> u64 {
+        match self {
+            Self::Protected | Self::NoDump => E_ACC_DENIED,
+            Self::Running => E_BUSY,
+            Self::SelfTarget | Self::NoSuchTask | Self::NoImage | Self::TooLarge => E_INVALID_ARG,
+        }
+    }
+}
+
+type Result<T> = core::result::Result<T, DebugError>;
+
+fn check_target(caller: TaskId, target: TaskId) -> Result<()> {
+    if target == TaskId::KERNEL {
+        return Err(DebugError::Protected);
+    }
+    if target == caller {
+        return Err(DebugError::SelfTarget);
+    }
+    if !scheduler::task_exists(target) {
+        return Err(DebugError::NoSuchTask);
+    }
+    Ok(())
+}
+
+pub fn suspend(caller: TaskId, target: TaskId) -> Result<()> {
+    check_target(caller, target)?;
+    scheduler::suspend_task(target).then_some(()).ok_or(DebugError::NoSuchTask)
+}
+
+pub fn resume(caller: TaskId, target: TaskId) -> Result<()> {
+    check_target(caller, target)?;
+    scheduler::resume_task(target).then_some(()).ok_or(DebugError::NoSuchTask)
+}
+
+/// The registers saved when `target` was last switched out.
+pub fn registers(caller: TaskId, target: TaskId) -> Result<RegisterFrame> {
+    check_target(caller, target)?;
+    match scheduler::with_task_mut(target, |tcb| (tcb.state, tcb.regs)) {
+        Some((TaskState::Running, _)) => Err(DebugError::Running),
+        Some((_, regs)) => Ok(regs),
+        None => Err(DebugError::NoSuchTask),
+    }
+}
+
+/// Queues a core dump of `target` for init to write out, as if it had
+/// crashed, but leaves it running. It has to be stopped, like for
+/// `registers`, so the dump is of one moment.
+pub fn dump(caller: TaskId, target: TaskId) -> Result<()> {
+    let regs = registers(caller, target)?;
+    if scheduler::with_task_mut(target, |tcb| tcb.no_core_dump).unwrap_or(false) {
+        return Err(DebugError::NoDump);
+    }
+    coredump::capture_requested(target, regs);
+    Ok(())
+}
+
+/// Reads `target`'s memory at `addr` into `out`. Returns the bytes read,
+/// fewer than requested if the range runs into memory the task can't read.
+pub fn read_memory(caller: TaskId, target: TaskId, addr: u64, out: &mut [u8]) -> Result<usize> {
+    check_target(caller, target)?;
+    if out.len() as u64 > DEBUG_MEM_MAX {
+        return Err(DebugError::TooLarge);
+    }
+    task_memory::read(target, addr, out).ok_or(DebugError::NoImage)
+}
+
+/// Writes `data` to `target`'s memory at `addr`. Like the task itself, the
+/// debugger can only write to writable segments.
+pub fn write_memory(caller: TaskId, target: TaskId, addr: u64, data: &[u8]) -> Result<usize> {
+    check_target(caller, target)?;
+    if data.len() as u64 > DEBUG_MEM_MAX {
+        return Err(DebugError::TooLarge);
+    }
+    task_memory::write(target, addr, data).ok_or(DebugError::NoImage)
+}
+
+/// Best-effort frame-pointer walk. `out[0]` is the saved instruction pointer,
+/// the rest are return addresses. Stops at a null, misaligned or unreadable
+/// frame pointer, or one that doesn't move up the stack, so code built without
+/// frame pointers yields a short trace rather than garbage.
+pub fn backtrace(caller: TaskId, target: TaskId, out: &mut [u64]) -> Result<usize> {
+    let regs = registers(caller, target)?;
+    if out.is_empty() {
+        return Ok(0);
+    }
+    out[0] = regs.rip;
+    let mut count = 1;
+    let mut frame = regs.rbp;
+    while count < out.len() && frame != 0 && frame % 8 == 0 {
+        let (saved_frame, return_addr) = match (task_memory::read_u64(target, frame), task_memory::read_u64(target, frame.wrapping_add(8))) {
+            (Some(saved_frame), Some(return_addr)) => (saved_frame, return_addr),
+            _ => break,
+        };
+        if return_addr == 0 {
+            break;
+        }
+        out[count] = return_addr;
+        count += 1;
+        if saved_frame <= frame {
+            break;
+        }
+        frame = saved_frame;
+    }
+    Ok(count)
+}
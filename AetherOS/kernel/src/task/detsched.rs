@@ -0,0 +1,309 @@
+// kernel/src/task/detsched.rs
+
+//! Deterministic scheduling, for reproducing bugs that only show up under one
+//! interleaving.
+//!
+//! With the `det-sched` feature, the kernel's choice points ask this module
+//! instead of following timing:
+//!
+//! *   which queued task a CPU runs next (`pick`, from `scheduler::schedule`);
+//! *   when an IRQ notification is delivered. `irq::handle_irq` hands it to
+//!     `defer_irq`, and at every `irq_point` (syscall entry and exit, and the
+//!     preemption point inside a blocking receive) each pending notification
+//!     is either delivered or kept for a later point.
+//!
+//! A run is seeded, with every decision drawn from SplitMix64, or replays a
+//! list of decisions recorded earlier. Either way the decisions are recorded,
+//! so a seed that fails becomes a fixed list that reproduces the failure even
+//! after the code around it changes the number of choices. Syscall entry and
+//! exit, IPC enqueue and dequeue, picks, handoffs and IRQ deliveries go into a
+//! trace.
+//!
+//! Outside a run every hook does nothing. `sweep` runs a `Scenario` across
+//! seeds and checks its invariant after each; see `task::scenarios`.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::{ipc, kprintln};
+use crate::ipc::ChannelId;
+use crate::task::tcb::TaskId;
+
+/// Trace events kept per run; older ones are dropped.
+pub const MAX_TRACE_EVENTS: usize = 1024;
+
+/// One choice the kernel made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Ran the task at this index of the CPU's run queue.
+    Pick(usize),
+    /// Delivered a pending IRQ notification (true) or kept it for later.
+    Deliver(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqPoint {
+    SyscallEntry,
+    /// Inside a blocking receive, after the task was marked Blocked and
+    /// before the switch away from it.
+    Preempt,
+    SyscallExit,
+    /// The end of a run, where whatever is still pending is delivered.
+    Flush,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    SyscallEnter { task: TaskId, number: u64 },
+    SyscallExit { task: TaskId, number: u64, result: u64 },
+    IpcEnqueue { channel: ChannelId, sender: TaskId },
+    IpcDequeue { channel: ChannelId, receiver: TaskId },
+    /// The scheduler chose `task` out of `of` queued on `cpu`.
+    Picked { cpu: usize, task: TaskId, of: usize },
+    /// An IPC call or reply switched `cpu` from `from` to `to` directly.
+    Handoff { cpu: usize, from: TaskId, to: TaskId },
+    IrqDelivered { channel: ChannelId, at: IrqPoint },
+    /// A replay asked for a decision of one kind and found the other, or none:
+    /// the code no longer makes the choices it made when recorded.
+    Diverged { decision: usize },
+}
+
+/// What a run decided and saw.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    /// `None` for a replay.
+    pub seed: Option<u64>,
+    pub decisions: Vec<Decision>,
+    pub trace: VecDeque<Event>,
+    pub diverged: bool,
+}
+
+enum Source {
+    Seeded { state: u64 },
+    Replay { decisions: Vec<Decision>, next: usize },
+}
+
+struct Run {
+    source: Source,
+    recording: Recording,
+    pending_irqs: VecDeque<(ChannelId, Vec<u8>)>,
+}
+
+impl Run {
+    /// SplitMix64: small, fast and good enough to spread choices evenly.
+    fn next_random(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn decide(&mut self, fresh: impl FnOnce(u64) -> Decision, same_kind: impl Fn(&Decision) -> bool, fallback: Decision) -> Decision {
+        let decision = match &mut self.source {
+            Source::Seeded { state } => Some(fresh(Self::next_random(state))),
+            Source::Replay { decisions, next } => match decisions.get(*next) {
+                Some(decision) if same_kind(decision) => {
+                    *next += 1;
+                    Some(*decision)
+                },
+                _ => None,
+            },
+        };
+        let decision = decision.unwrap_or_else(|| {
+            self.recording.diverged = true;
+            self.record(Event::Diverged { decision: self.recording.decisions.len() });
+            fallback
+        });
+        self.recording.decisions.push(decision);
+        decision
+    }
+
+    fn record(&mut self, event: Event) {
+        if self.recording.trace.len() == MAX_TRACE_EVENTS {
+            self.recording.trace.pop_front();
+        }
+        self.recording.trace.push_back(event);
+    }
+}
+
+// A leaf lock: nothing else is locked while it is held. Hooks run with
+// TASKS, RUN_QUEUES or MAILBOXES held, and `irq_point` releases it before
+// delivering, since delivering takes MAILBOXES.
+static RUN: Mutex<Option<Run>> = Mutex::new(None);
+/// Mirrors `RUN.is_some()`, so the hooks outside a run cost one load.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn start(source: Source, seed: Option<u64>) {
+    *RUN.lock() = Some(Run {
+        source,
+        recording: Recording { seed, ..Recording::default() },
+        pending_irqs: VecDeque::new(),
+    });
+    ACTIVE.store(true, Ordering::SeqCst);
+}
+
+/// Starts a run whose decisions come from `seed`.
+pub fn start_seeded(seed: u64) {
+    kprintln!("[kernel] detsched: Seed {:#018x}.", seed);
+    start(Source::Seeded { state: seed }, Some(seed));
+}
+
+/// Starts a run that makes `decisions` in order.
+pub fn start_replay(decisions: Vec<Decision>) {
+    kprintln!("[kernel] detsched: Replaying {} decisions.", decisions.len());
+    start(Source::Replay { decisions, next: 0 }, None);
+}
+
+/// Delivers what is still pending and ends the run.
+pub fn finish() -> Recording {
+    irq_point(IrqPoint::Flush);
+    ACTIVE.store(false, Ordering::SeqCst);
+    RUN.lock().take().map(|run| run.recording).unwrap_or_default()
+}
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+pub fn record(event: Event) {
+    if !active() {
+        return;
+    }
+    if let Some(run) = RUN.lock().as_mut() {
+        run.record(event);
+    }
+}
+
+/// Which of `of` queued tasks to run next, as an index into the queue.
+pub fn pick(of: usize) -> usize {
+    let mut run = RUN.lock();
+    let run = match run.as_mut() {
+        Some(run) if of > 0 => run,
+        _ => return 0,
+    };
+    match run.decide(|random| Decision::Pick((random % of as u64) as usize), |d| matches!(d, Decision::Pick(i) if *i < of), Decision::Pick(0)) {
+        Decision::Pick(index) => index,
+        Decision::Deliver(_) => 0,
+    }
+}
+
+/// Holds an IRQ notification for the next `irq_point`. Returns false outside
+/// a run, where the caller sends it right away.
+pub fn defer_irq(channel: ChannelId, data: &[u8]) -> bool {
+    if !active() {
+        return false;
+    }
+    match RUN.lock().as_mut() {
+        Some(run) => {
+            run.pending_irqs.push_back((channel, data.to_vec()));
+            true
+        },
+        None => false,
+    }
+}
+
+/// A point where a pending IRQ notification may arrive. Each one is
+/// delivered or kept, as decided; at `Flush` all of them are delivered.
+pub fn irq_point(at: IrqPoint) {
+    if !active() {
+        return;
+    }
+    let due: Vec<(ChannelId, Vec<u8>)> = {
+        let mut run = RUN.lock();
+        let run = match run.as_mut() {
+            Some(run) => run,
+            None => return,
+        };
+        let mut due = Vec::new();
+        let mut kept = VecDeque::new();
+        while let Some(irq) = run.pending_irqs.pop_front() {
+            let deliver = at == IrqPoint::Flush
+                || run.decide(|random| Decision::Deliver(random & 1 == 1), |d| matches!(d, Decision::Deliver(_)), Decision::Deliver(true)) == Decision::Deliver(true);
+            if deliver {
+                run.record(Event::IrqDelivered { channel: irq.0, at });
+                due.push(irq);
+            } else {
+                kept.push_back(irq);
+            }
+        }
+        run.pending_irqs = kept;
+        due
+    };
+    for (channel, data) in due {
+        let _ = ipc::kernel_send(channel, TaskId::KERNEL, &data);
+    }
+}
+
+/// A multi-task situation to explore across seeds.
+pub trait Scenario {
+    fn name(&self) -> &'static str;
+    /// Creates the scenario's tasks and channels and lets them run. Called
+    /// inside a run, so its scheduling and IRQ delivery are decided there.
+    fn run(&mut self);
+    /// Checked after the run, once pending IRQs are delivered.
+    fn invariant(&self) -> Result<(), String>;
+    /// Removes everything `run` created, whether the invariant held or not.
+    fn teardown(&mut self);
+}
+
+/// A seed for which a scenario's invariant failed.
+pub struct Failure {
+    pub seed: u64,
+    pub message: String,
+    pub recording: Recording,
+}
+
+fn run_once(scenario: &mut dyn Scenario) -> (Result<(), String>, Recording) {
+    scenario.run();
+    let recording = finish();
+    let result = scenario.invariant();
+    scenario.teardown();
+    (result, recording)
+}
+
+/// Runs `scenario` once per seed and checks its invariant after each.
+/// Stops at the first failure. Returns the number of seeds that passed.
+pub fn sweep(scenario: &mut dyn Scenario, seeds: Range<u64>) -> Result<u64, Failure> {
+    let count = seeds.end.saturating_sub(seeds.start);
+    for seed in seeds {
+        start_seeded(seed);
+        let (result, recording) = run_once(scenario);
+        if let Err(message) = result {
+            return Err(Failure { seed, message, recording });
+        }
+    }
+    Ok(count)
+}
+
+/// Runs `scenario` with recorded decisions, e.g. those of a `Failure`, and
+/// checks its invariant.
+pub fn replay(scenario: &mut dyn Scenario, decisions: Vec<Decision>) -> (Result<(), String>, Recording) {
+    start_replay(decisions);
+    run_once(scenario)
+}
+
+/// The decisions in a compact form for the log: `P<index>` for picks, `D`
+/// and `K` for delivering and keeping an IRQ notification.
+pub fn format_decisions(decisions: &[Decision]) -> String {
+    let mut out = String::new();
+    for decision in decisions {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        match decision {
+            Decision::Pick(index) => out.push_str(&format!("P{}", index)),
+            Decision::Deliver(true) => out.push('D'),
+            Decision::Deliver(false) => out.push('K'),
+        }
+    }
+    out
+}
@@ -0,0 +1,167 @@
+// kernel/src/task/faults.rs
+
+#![allow(dead_code)]
+
+//! CPU exception accounting.
+//!
+//! The exception handlers count every fault against the task that took it,
+//! in its TCB, and `SYS_TASK_STATS` reports the counts. A task that takes
+//! more faults within a second than the threshold init sets with
+//! `SYS_FAULT_STORMS` raises a fault storm: a record naming it is queued
+//! until init takes it and publishes it as `task.faultstorm`. A task raises
+//! one storm, however long it lasts; once a whole second stays at or under
+//! the threshold, the next one raises another.
+//!
+//! NMIs and machine checks belong to the machine rather than a task and are
+//! counted system-wide, in atomics: their handlers can interrupt anything,
+//! including code holding the task table lock.
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+
+use common::abi::{FaultStorm, TASK_NAME_LEN};
+
+use crate::kprintln;
+use crate::task::scheduler;
+use crate::task::tcb::TaskId;
+use crate::timer::{self, Ticks};
+
+/// Storms kept until init takes them. Past this the newest are dropped, and counted.
+const MAX_PENDING_STORMS: usize = 32;
+
+/// What a task's exception handler did about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// A page fault the kernel resolved, e.g. by paging in a mapped file.
+    PageResolved,
+    /// A page fault the kernel couldn't resolve.
+    PageFatal,
+    GeneralProtection,
+    InvalidOpcode,
+    AlignmentCheck,
+}
+
+/// A task's faults, kept in its TCB.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultCounters {
+    pub page_resolved: u64,
+    pub page_fatal: u64,
+    pub general_protection: u64,
+    pub invalid_opcode: u64,
+    pub alignment_check: u64,
+    /// Tick at which the current one-second window started.
+    window_start_tick: Ticks,
+    /// Faults of every kind within it.
+    window_faults: u32,
+    /// A storm was raised and hasn't calmed down since.
+    storming: bool,
+}
+
+impl FaultCounters {
+    pub fn total(&self) -> u64 {
+        self.page_resolved + self.page_fatal + self.general_protection + self.invalid_opcode + self.alignment_check
+    }
+
+    /// Counts a fault at tick `now`. Returns the faults in the current window
+    /// if this one raises a storm at `threshold` faults per second (0 is off).
+    fn count(&mut self, kind: FaultKind, now: Ticks, threshold: u32) -> Option<u32> {
+        match kind {
+            FaultKind::PageResolved => self.page_resolved += 1,
+            FaultKind::PageFatal => self.page_fatal += 1,
+            FaultKind::GeneralProtection => self.general_protection += 1,
+            FaultKind::InvalidOpcode => self.invalid_opcode += 1,
+            FaultKind::AlignmentCheck => self.alignment_check += 1,
+        }
+        let elapsed = now.saturating_sub(self.window_start_tick);
+        if elapsed >= Ticks::from_secs(1) {
+            // A second without faults in between, or a window at or under the rate, ends the storm.
+            if threshold == 0 || elapsed >= Ticks::from_secs(2) || self.window_faults <= threshold {
+                self.storming = false;
+            }
+            self.window_start_tick = now;
+            self.window_faults = 0;
+        }
+        self.window_faults = self.window_faults.saturating_add(1);
+        if threshold == 0 || self.storming || self.window_faults <= threshold {
+            return None;
+        }
+        self.storming = true;
+        Some(self.window_faults)
+    }
+}
+
+/// Faults per second above which a task raises a storm; 0 turns the alarm off.
+static STORM_THRESHOLD: AtomicU32 = AtomicU32::new(0);
+static PENDING_STORMS: Mutex<VecDeque<FaultStorm>> = Mutex::new(VecDeque::new());
+static DROPPED_STORMS: AtomicU64 = AtomicU64::new(0);
+
+static NMIS: AtomicU64 = AtomicU64::new(0);
+static MACHINE_CHECKS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a fault of `kind` against `task_id`, and queues a storm if it
+/// raises one. What the exception handlers call; a fault outside any known
+/// task isn't counted.
+pub fn record(task_id: TaskId, kind: FaultKind) {
+    let threshold = STORM_THRESHOLD.load(Ordering::Relaxed);
+    let now = timer::get_current_ticks();
+    let storm = scheduler::with_task_mut(task_id, |task| {
+        let faults = task.faults.count(kind, now, threshold)?;
+        let mut name = [0u8; TASK_NAME_LEN];
+        let len = task.name.len().min(TASK_NAME_LEN);
+        name[..len].copy_from_slice(&task.name.as_bytes()[..len]);
+        Some(FaultStorm { task: task_id.raw(), faults, threshold, name })
+    }).flatten();
+    let Some(storm) = storm else {
+        return;
+    };
+    kprintln!("[kernel] faults: Task {} ({}) took {} faults within a second, over the threshold of {}.", task_id, storm.name(), storm.faults, threshold);
+    let mut pending = PENDING_STORMS.lock();
+    if pending.len() < MAX_PENDING_STORMS {
+        pending.push_back(storm);
+    } else {
+        DROPPED_STORMS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Sets the storm threshold in faults per second; 0 turns the alarm off.
+pub fn set_storm_threshold(per_sec: u32) {
+    STORM_THRESHOLD.store(per_sec, Ordering::Relaxed);
+}
+
+pub fn storm_threshold() -> u32 {
+    STORM_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Takes up to `max` queued storms, oldest first. The rest stay queued.
+pub fn take_storms(max: usize) -> Vec<FaultStorm> {
+    let mut pending = PENDING_STORMS.lock();
+    let count = max.min(pending.len());
+    pending.drain(..count).collect()
+}
+
+/// Storms dropped because init didn't take them in time.
+pub fn dropped_storms() -> u64 {
+    DROPPED_STORMS.load(Ordering::Relaxed)
+}
+
+/// Counts an NMI and returns how many there have been.
+pub fn count_nmi() -> u64 {
+    NMIS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub fn nmis() -> u64 {
+    NMIS.load(Ordering::Relaxed)
+}
+
+/// Counts a machine check and returns how many there have been. The handler
+/// panics right after, so it is more than one only if another CPU took one too.
+pub fn count_machine_check() -> u64 {
+    MACHINE_CHECKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+pub fn machine_checks() -> u64 {
+    MACHINE_CHECKS.load(Ordering::Relaxed)
+}
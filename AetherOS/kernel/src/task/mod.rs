@@ -1,5 +1,15 @@
 pub mod scheduler;
 pub mod tcb; // New: Task Control Block module
+pub mod ratelimit; // Per-task SYS_LOG rate limiting
+pub mod faults; // Per-task fault counters, fault storms, NMI and machine check counts
+pub mod debug; // SYS_DEBUG_* operations on other tasks
+pub mod coredump; // Core dumps of killed and debugged tasks, queued for init
+pub mod cpu; // CPU ids, affinity masks and per-CPU state
+pub mod runqueue; // Per-CPU run queues and work stealing
+#[cfg(feature = "det-sched")]
+pub mod detsched; // Seeded and replayed scheduling decisions
+#[cfg(feature = "det-sched")]
+pub mod scenarios; // Multi-task scenarios swept over seeds at boot
 
 // Other task-related modules would be declared here.
 
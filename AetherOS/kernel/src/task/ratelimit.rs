@@ -0,0 +1,88 @@
+// kernel/src/task/ratelimit.rs
+
+#![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
+
+use crate::timer::{Ticks, TICKS_PER_SECOND};
+
+/// Tokens are tracked in thousandths so slow refill rates don't lose fractions.
+const MILLI: u64 = 1000;
+
+/// Outcome of a rate-limited log attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogDecision {
+    /// The message may be written.
+    Allow,
+    /// The message must be dropped.
+    Suppress,
+}
+
+/// Per-task token bucket for `SYS_LOG`.
+///
+/// Lives inline in the TCB: no allocation, just counters updated under the
+/// scheduler's task table lock.
+#[derive(Debug, Clone, Copy)]
+pub struct LogRateLimiter {
+    /// Current token count, in thousandths of a message.
+    tokens_milli: u64,
+    /// Tick at which tokens were last refilled.
+    last_refill_tick: Ticks,
+    /// Tick at which the last suppression summary was emitted.
+    last_report_tick: Ticks,
+    /// Messages dropped since the last summary line.
+    pub suppressed_since_report: u64,
+    /// Messages dropped over the task's lifetime.
+    pub suppressed_total: u64,
+    /// Messages written over the task's lifetime.
+    pub allowed_total: u64,
+}
+
+impl LogRateLimiter {
+    /// Creates a limiter with a full bucket.
+    pub const fn new(burst: u32) -> Self {
+        Self {
+            tokens_milli: burst as u64 * MILLI,
+            last_refill_tick: Ticks::ZERO,
+            last_report_tick: Ticks::ZERO,
+            suppressed_since_report: 0,
+            suppressed_total: 0,
+            allowed_total: 0,
+        }
+    }
+
+    fn refill(&mut self, now: Ticks, rate_per_sec: u32, burst: u32) {
+        let elapsed = now.saturating_sub(self.last_refill_tick).raw();
+        if elapsed == 0 {
+            return;
+        }
+        let added = elapsed.saturating_mul(rate_per_sec as u64 * MILLI) / TICKS_PER_SECOND;
+        self.tokens_milli = self.tokens_milli.saturating_add(added).min(burst as u64 * MILLI);
+        self.last_refill_tick = now;
+    }
+
+    /// Consumes one token if available.
+    pub fn check(&mut self, now: Ticks, rate_per_sec: u32, burst: u32) -> LogDecision {
+        self.refill(now, rate_per_sec, burst);
+        if self.tokens_milli >= MILLI {
+            self.tokens_milli -= MILLI;
+            self.allowed_total += 1;
+            LogDecision::Allow
+        } else {
+            self.suppressed_since_report += 1;
+            self.suppressed_total += 1;
+            LogDecision::Suppress
+        }
+    }
+
+    /// Returns the number of messages to report as suppressed if a summary is
+    /// due (at most once per `interval`), resetting the pending count. The
+    /// timer asks for every task (`task::collect_suppressed_logs`).
+    pub fn take_summary(&mut self, now: Ticks, interval: Ticks) -> Option<u64> {
+        if self.suppressed_since_report == 0 || now.saturating_sub(self.last_report_tick) < interval {
+            return None;
+        }
+        let count = self.suppressed_since_report;
+        self.suppressed_since_report = 0;
+        self.last_report_tick = now;
+        Some(count)
+    }
+}
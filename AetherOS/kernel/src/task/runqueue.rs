@@ -0,0 +1,94 @@
+// kernel/src/task/runqueue.rs
+
+//! Per-CPU run queues: where a ready task is placed, and which one a CPU runs
+//! next.
+//!
+//! This is plain bookkeeping with no locks or CPU detection of its own, so the
+//! scheduler passes in the CPU and the online mask. That keeps the placement
+//! and stealing rules usable with any number of simulated CPUs.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+
+use crate::config::MAX_CPUS;
+use crate::task::cpu::{mask_of, CpuId, CpuMask};
+use crate::task::tcb::TaskId;
+
+/// A ready task, with the affinity it had when it was queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Queued {
+    pub task_id: TaskId,
+    pub affinity: CpuMask,
+}
+
+pub struct RunQueues {
+    queues: [VecDeque<Queued>; MAX_CPUS],
+}
+
+impl RunQueues {
+    pub const fn new() -> Self {
+        Self { queues: [const { VecDeque::new() }; MAX_CPUS] }
+    }
+
+    pub fn len(&self, cpu: CpuId) -> usize {
+        self.queues[cpu].len()
+    }
+
+    /// The CPU a task should be queued on: its previous CPU if it may still
+    /// run there (its cache may still be warm), otherwise the allowed online
+    /// CPU with the shortest queue. `None` if no CPU it may run on is online.
+    pub fn place(&self, affinity: CpuMask, last_cpu: Option<CpuId>, online: CpuMask) -> Option<CpuId> {
+        let allowed = affinity & online;
+        if let Some(cpu) = last_cpu.filter(|cpu| allowed & mask_of(*cpu) != 0) {
+            return Some(cpu);
+        }
+        (0..MAX_CPUS).filter(|cpu| allowed & mask_of(*cpu) != 0).min_by_key(|cpu| self.queues[*cpu].len())
+    }
+
+    pub fn push(&mut self, cpu: CpuId, task_id: TaskId, affinity: CpuMask) {
+        self.queues[cpu].push_back(Queued { task_id, affinity });
+    }
+
+    /// Takes the next task for `cpu`: the front of its own queue, or if that
+    /// is empty, one stolen from another online CPU.
+    pub fn pop(&mut self, cpu: CpuId, online: CpuMask) -> Option<Queued> {
+        self.queues[cpu].pop_front().or_else(|| self.steal(cpu, online))
+    }
+
+    /// Takes the task at `index` in `cpu`'s own queue. Deterministic scheduling
+    /// uses it to run queued tasks in an order of its choosing.
+    pub fn take(&mut self, cpu: CpuId, index: usize) -> Option<Queued> {
+        self.queues[cpu].remove(index)
+    }
+
+    /// Takes a task from the online CPU with the longest queue that holds one
+    /// allowed on `cpu`. The victim loses the task at the back of its queue,
+    /// the one it would have run last.
+    pub fn steal(&mut self, cpu: CpuId, online: CpuMask) -> Option<Queued> {
+        let mut victims: alloc::vec::Vec<CpuId> = (0..MAX_CPUS)
+            .filter(|victim| *victim != cpu && online & mask_of(*victim) != 0 && !self.queues[*victim].is_empty())
+            .collect();
+        victims.sort_by_key(|victim| core::cmp::Reverse(self.queues[*victim].len()));
+        for victim in victims {
+            let queue = &mut self.queues[victim];
+            if let Some(index) = queue.iter().rposition(|queued| queued.affinity & mask_of(cpu) != 0) {
+                return queue.remove(index);
+            }
+        }
+        None
+    }
+
+    /// Removes a task from whichever queue holds it.
+    pub fn remove(&mut self, task_id: TaskId) {
+        for queue in self.queues.iter_mut() {
+            queue.retain(|queued| queued.task_id != task_id);
+        }
+    }
+
+    /// The CPU whose queue holds a task.
+    pub fn cpu_of(&self, task_id: TaskId) -> Option<CpuId> {
+        self.queues.iter().position(|queue| queue.iter().any(|queued| queued.task_id == task_id))
+    }
+}
@@ -0,0 +1,1729 @@
+// kernel/src/task/scenarios.rs
+
+//! Scenarios swept over seeds at boot with the `det-sched` feature.
+//!
+//! Each one drives real tasks, mailboxes and syscalls through the scheduler
+//! and checks an invariant afterwards. A failing seed is logged with its
+//! decisions and the tail of its trace, and replayed once to confirm that the
+//! recording reproduces it.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::arch::x86_64::dma::{self, DmaDirection, DmaSegment};
+use crate::arch::x86_64::irq;
+use crate::drivers::ps2_keyboard::{ScancodeDecoder, ScancodeSet};
+use crate::config::LOG_SUPPRESSION_REPORT_INTERVAL_SECS;
+use crate::caps::Capability;
+use crate::drivers::rng;
+use crate::elf::{ElfType, LoadedImage, Segment};
+use crate::error::KernelError;
+use crate::memory::file_map::{self, FaultResolution};
+use crate::memory::task_memory;
+use crate::ipc::{self, ChannelId};
+use crate::kprintln;
+use crate::syscall::{syscall_dispatch, IpcCall, SYS_IPC_CALL, SYS_IPC_RECV, SYS_IPC_RECV_NONBLOCKING, SYS_IPC_REPLY, SYS_IPC_REPLY_TOKEN, SYS_IPC_SEND};
+use crate::syscall::{SYS_FILTER_RESTRICT, SYS_TIME, ALL_SYSCALLS, TASK_FLAG_FILTERED};
+use crate::syscall::{split_secs_nanos, TIME_NANOS, TIME_SECS_NANOS};
+use crate::syscall::{E_ACC_DENIED, E_BUSY, E_PEER_GONE, E_SYSCALL_FILTERED, SUCCESS};
+use crate::syscall::{SYS_LOG, SYS_NET_TX, SYS_SHARE_PAGES, LOG_SEVERITY_INFO};
+use crate::syscall::{IpcCreds, IPC_CREDS_LEN, SYS_IPC_CREDS};
+use crate::syscall::{FaultStorm, FAULT_STORM_LEN, SYS_FAULT_STORMS};
+use crate::syscall::{RegisterFrame, SYS_CORE_DUMP_TAKE, SYS_DEBUG_DUMP};
+use crate::syscall::{ABI_VERSION, MIN_KERNEL_ABI_VERSION, SYSCALL_COUNT, SYS_ABI_VERSION, SYS_BLOCK_ON_CHAN, E_INVALID_ARG, E_UNKNOWN_SYSCALL};
+use crate::task::detsched::{self, Scenario};
+use crate::task::coredump::{self, FaultContext};
+use crate::task::faults::{self, FaultKind};
+use crate::task::ratelimit::LogRateLimiter;
+use crate::task::scheduler;
+use crate::task::tcb::{TaskId, TaskState};
+use crate::timer::{self, ClockSource, Millis, Ticks};
+use crate::vnode_loader::{self, SpawnedVNode};
+
+use common::capability::{self as declared, DeclaredCapabilities, Grant};
+use common::channels::FIRST_DYNAMIC_CHANNEL;
+use common::coredump::{CoreDump, CORE_FLAG_PARTIAL_REGISTERS, CORE_FLAG_TRUNCATED, CORE_FLAG_WITHHELD, CORE_REASON_PAGE_FAULT, CORE_REASON_REQUESTED, REGION_EXECUTE, REGION_READ, REGION_WRITE};
+
+/// Seeds tried per scenario at boot.
+pub const SEEDS_PER_SCENARIO: u64 = 64;
+/// Trace events logged for a failure.
+const FAILURE_TRACE_EVENTS: usize = 32;
+
+/// Scenario tasks get IDs from here, clear of V-Nodes (1000 and up) and idle tasks.
+const FIRST_TASK_ID: u64 = 900;
+
+/// The `n`th scenario task.
+const fn scenario_task(n: u64) -> TaskId {
+    TaskId::from_raw(FIRST_TASK_ID + n)
+}
+
+/// Runs `task_id` next, by scheduling until it is current. Bounded, so a task
+/// that is blocked or gone doesn't hang the sweep.
+fn run_as(task_id: TaskId) -> bool {
+    for _ in 0..8 {
+        if scheduler::current_task_id() == task_id {
+            return true;
+        }
+        scheduler::schedule();
+    }
+    scheduler::current_task_id() == task_id
+}
+
+fn state_of(task_id: TaskId) -> Option<TaskState> {
+    scheduler::with_task_mut(task_id, |task| task.state)
+}
+
+fn remove(task_id: TaskId) {
+    crate::task::kill_task(task_id);
+}
+
+/// A receiver blocks on a channel an IRQ is routed to, and the IRQ
+/// notification arrives at any point around its receive. Invariant: no lost
+/// wakeup, i.e. the receiver is never left Blocked with a message waiting.
+///
+/// This found the check-then-block race in `SYS_IPC_RECV`: it checked for a
+/// message and blocked in two steps, and an IRQ delivered in between queued a
+/// message nobody would wake the receiver for.
+pub struct IrqWakeup {
+    channel: Option<ChannelId>,
+}
+
+impl IrqWakeup {
+    const RECEIVER: TaskId = scenario_task(0);
+    /// Secondary ATA; nothing registers it before V-Nodes start.
+    const IRQ: u8 = 15;
+
+    pub fn new() -> Self {
+        Self { channel: None }
+    }
+}
+
+impl Scenario for IrqWakeup {
+    fn name(&self) -> &'static str {
+        "irq-wakeup"
+    }
+
+    fn run(&mut self) {
+        crate::task::create_task(Self::RECEIVER, "detsched-receiver", alloc::vec![Capability::IpcManage]);
+        let channel = match ipc::kernel_allocate(Self::RECEIVER) {
+            Some(channel) => channel,
+            None => return,
+        };
+        self.channel = Some(channel);
+        let _ = irq::register_irq_handler(Self::IRQ, channel, Self::RECEIVER, true);
+
+        // The notification is pending from here on; detsched decides where it lands.
+        irq::handle_irq(Self::IRQ);
+        if run_as(Self::RECEIVER) {
+            let mut buf = [0u8; 64];
+            syscall_dispatch(SYS_IPC_RECV, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+        }
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        let channel = self.channel.ok_or_else(|| "no channel could be allocated".to_string())?;
+        if state_of(Self::RECEIVER) == Some(TaskState::Blocked) && ipc::kernel_peek(channel) {
+            return Err(format!("lost wakeup: task {} is blocked with a message queued on channel {}", Self::RECEIVER, channel));
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::RECEIVER);
+        self.channel = None;
+        scheduler::schedule(); // Off the removed task
+    }
+}
+
+/// Two senders each send `MESSAGES` messages to one receiver, which drains
+/// its channel without blocking, all in the order the scheduler picks.
+/// Invariant: every message arrives exactly once, each sender's in order.
+pub struct MessagesOnce {
+    channel: Option<ChannelId>,
+    received: Vec<(u8, u8)>, // (sender index, sequence number)
+}
+
+impl MessagesOnce {
+    const RECEIVER: TaskId = scenario_task(1);
+    const SENDERS: [TaskId; 2] = [scenario_task(2), scenario_task(3)];
+    const MESSAGES: u8 = 4;
+    const MAX_STEPS: usize = 64;
+
+    pub fn new() -> Self {
+        Self { channel: None, received: Vec::new() }
+    }
+
+    fn receive_all(&mut self, channel: ChannelId) {
+        let mut buf = [0u8; 2];
+        while syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64) == 2 {
+            self.received.push((buf[0], buf[1]));
+        }
+    }
+}
+
+impl Scenario for MessagesOnce {
+    fn name(&self) -> &'static str {
+        "messages-once"
+    }
+
+    fn run(&mut self) {
+        self.received.clear();
+        for (i, task_id) in Self::SENDERS.iter().enumerate() {
+            crate::task::create_task(*task_id, if i == 0 { "detsched-sender-0" } else { "detsched-sender-1" }, alloc::vec![Capability::IpcManage]);
+        }
+        crate::task::create_task(Self::RECEIVER, "detsched-drain", alloc::vec![Capability::IpcManage]);
+        let channel = match ipc::kernel_allocate(Self::RECEIVER) {
+            Some(channel) => channel,
+            None => return,
+        };
+        self.channel = Some(channel);
+
+        // Whoever the scheduler picks takes its next step.
+        let mut sent = [0u8; 2];
+        for _ in 0..Self::MAX_STEPS {
+            scheduler::schedule();
+            let current = scheduler::current_task_id();
+            if let Some(i) = Self::SENDERS.iter().position(|id| *id == current) {
+                if sent[i] < Self::MESSAGES {
+                    let message = [i as u8, sent[i]];
+                    syscall_dispatch(SYS_IPC_SEND, channel.raw() as u64, message.as_ptr() as u64, message.len() as u64);
+                    sent[i] += 1;
+                }
+            } else if current == Self::RECEIVER {
+                self.receive_all(channel);
+            }
+        }
+        // Senders that never got their turn count as sending nothing; drain the rest.
+        if run_as(Self::RECEIVER) {
+            self.receive_all(channel);
+        }
+        for (i, count) in sent.iter().enumerate() {
+            self.received.retain(|(sender, seq)| *sender as usize != i || seq < count);
+        }
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        let channel = self.channel.ok_or_else(|| "no channel could be allocated".to_string())?;
+        if ipc::kernel_peek(channel) {
+            return Err(format!("channel {} still holds messages after draining", channel));
+        }
+        for sender in 0..Self::SENDERS.len() as u8 {
+            let sequence: Vec<u8> = self.received.iter().filter(|(s, _)| *s == sender).map(|(_, seq)| *seq).collect();
+            if sequence.iter().enumerate().any(|(expected, seq)| *seq as usize != expected) {
+                return Err(format!("sender {} delivered {:?}, expected each message once, in order", sender, sequence));
+            }
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::RECEIVER);
+        for task_id in Self::SENDERS {
+            remove(task_id);
+        }
+        self.channel = None;
+        scheduler::schedule();
+    }
+}
+
+/// Issues or collects the call in `call` as the current task. Returns the
+/// syscall's result.
+fn ipc_call(channel: ChannelId, call: &mut IpcCall) -> u64 {
+    syscall_dispatch(SYS_IPC_CALL, channel.raw() as u64, call as *mut IpcCall as u64, core::mem::size_of::<IpcCall>() as u64)
+}
+
+/// A call to be made with `ipc_call`: `request`, and `reply` for the answer.
+fn new_call(request: &[u8], reply: &mut [u8]) -> IpcCall {
+    IpcCall {
+        req: request.as_ptr() as u64,
+        req_len: request.len() as u64,
+        resp: reply.as_mut_ptr() as u64,
+        resp_cap: reply.len() as u64,
+        ..IpcCall::default()
+    }
+}
+
+/// A client calls a server that doesn't wait in a blocking receive and
+/// answers with a plain `SYS_IPC_SEND`, like V-Nodes that predate calls. The
+/// call can't hand off and takes the normal path both ways.
+/// Invariant: the client gets the reply, and nothing is left in the mailbox
+/// or the call table.
+pub struct CallFallback {
+    channel: Option<ChannelId>,
+    result: Option<u64>,
+    reply: [u8; 2],
+}
+
+impl CallFallback {
+    const SERVER: TaskId = scenario_task(4);
+    const CLIENT: TaskId = scenario_task(5);
+    const REQUEST: [u8; 2] = [0xCA, 0x11];
+    const REPLY: [u8; 2] = [0x0C, 0xA1];
+    const MAX_STEPS: usize = 32;
+
+    pub fn new() -> Self {
+        Self { channel: None, result: None, reply: [0; 2] }
+    }
+
+    /// One turn of the server: answer a request if one is waiting.
+    fn serve(channel: ChannelId) {
+        let mut buf = [0u8; 2];
+        if syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64) == 2 {
+            syscall_dispatch(SYS_IPC_SEND, channel.raw() as u64, Self::REPLY.as_ptr() as u64, Self::REPLY.len() as u64);
+        }
+    }
+}
+
+impl Scenario for CallFallback {
+    fn name(&self) -> &'static str {
+        "call-fallback"
+    }
+
+    fn run(&mut self) {
+        self.result = None;
+        self.reply = [0; 2];
+        crate::task::create_task(Self::SERVER, "detsched-poller", alloc::vec![Capability::IpcManage]);
+        crate::task::create_task(Self::CLIENT, "detsched-caller", alloc::vec![Capability::IpcManage]);
+        let channel = match ipc::kernel_allocate(Self::SERVER) {
+            Some(channel) => channel,
+            None => return,
+        };
+        self.channel = Some(channel);
+
+        let mut call = new_call(&Self::REQUEST, &mut self.reply);
+        for _ in 0..Self::MAX_STEPS {
+            scheduler::schedule();
+            match scheduler::current_task_id() {
+                Self::SERVER => Self::serve(channel),
+                Self::CLIENT if self.result.is_none() => {
+                    let result = ipc_call(channel, &mut call);
+                    if result != E_BUSY {
+                        self.result = Some(result);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        let channel = self.channel.ok_or_else(|| "no channel could be allocated".to_string())?;
+        match self.result {
+            Some(SUCCESS) if self.reply == Self::REPLY => {},
+            Some(SUCCESS) => return Err(format!("the call returned {:?}, expected {:?}", self.reply, Self::REPLY)),
+            Some(code) => return Err(format!("the call failed with {:#x}", code)),
+            None => return Err(format!("the call never completed; the client is {:?}", state_of(Self::CLIENT))),
+        }
+        if ipc::kernel_peek(channel) {
+            return Err(format!("channel {} still holds a message; the reply went to the mailbox", channel));
+        }
+        if ipc::call::pending_calls_of(Self::CLIENT) != 0 {
+            return Err("the collected call is still in the call table".to_string());
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::CLIENT);
+        remove(Self::SERVER);
+        self.channel = None;
+        scheduler::schedule();
+    }
+}
+
+/// A server waiting in a blocking receive is handed a call and exits before
+/// answering, either after taking the request or with it still queued.
+/// Invariant: the client is woken and its call fails with `E_PEER_GONE`.
+pub struct CallServerCrash {
+    channel: Option<ChannelId>,
+    result: Option<u64>,
+}
+
+impl CallServerCrash {
+    const SERVER: TaskId = scenario_task(6);
+    const CLIENT: TaskId = scenario_task(7);
+
+    pub fn new() -> Self {
+        Self { channel: None, result: None }
+    }
+}
+
+impl Scenario for CallServerCrash {
+    fn name(&self) -> &'static str {
+        "call-server-crash"
+    }
+
+    fn run(&mut self) {
+        self.result = None;
+        crate::task::create_task(Self::SERVER, "detsched-crasher", alloc::vec![Capability::IpcManage]);
+        crate::task::create_task(Self::CLIENT, "detsched-caller", alloc::vec![Capability::IpcManage]);
+        let channel = match ipc::kernel_allocate(Self::SERVER) {
+            Some(channel) => channel,
+            None => return,
+        };
+        self.channel = Some(channel);
+
+        let mut buf = [0u8; 8];
+        if run_as(Self::SERVER) {
+            syscall_dispatch(SYS_IPC_RECV, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+        }
+        let mut reply = [0u8; 8];
+        let mut call = new_call(&[1], &mut reply);
+        if !run_as(Self::CLIENT) || ipc_call(channel, &mut call) != E_BUSY {
+            return;
+        }
+        // Whether the server takes the request before it dies is up to the seed.
+        if scheduler::current_task_id() == Self::SERVER && detsched::pick(2) == 1 {
+            syscall_dispatch(SYS_IPC_RECV, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+        }
+        remove(Self::SERVER);
+        scheduler::schedule();
+        if run_as(Self::CLIENT) {
+            self.result = Some(ipc_call(channel, &mut call));
+        }
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        self.channel.ok_or_else(|| "no channel could be allocated".to_string())?;
+        match self.result {
+            Some(E_PEER_GONE) => Ok(()),
+            Some(code) => Err(format!("the call ended with {:#x}, expected E_PEER_GONE", code)),
+            None => Err(format!("the client was never woken; it is {:?}", state_of(Self::CLIENT))),
+        }
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::CLIENT);
+        self.channel = None;
+        scheduler::schedule();
+    }
+}
+
+/// A task starts under a filter of `SYS_TIME` and `SYS_IPC_RECV_NONBLOCKING`,
+/// like a V-Node the loader gave an allowlist, then narrows it itself.
+/// Invariant: the allowed syscall works; a filtered send returns
+/// `E_SYSCALL_FILTERED` and queues nothing; once dropped, `SYS_TIME` can't be
+/// got back by restricting to everything; each refusal is counted.
+pub struct SyscallFilter {
+    channel: Option<ChannelId>,
+    results: Option<[u64; 5]>, // time, send, restrict, widen, time again
+}
+
+impl SyscallFilter {
+    const TASK: TaskId = scenario_task(10);
+    const ALLOWED: u64 = (1 << SYS_TIME) | (1 << SYS_IPC_RECV_NONBLOCKING);
+
+    pub fn new() -> Self {
+        Self { channel: None, results: None }
+    }
+}
+
+impl Scenario for SyscallFilter {
+    fn name(&self) -> &'static str {
+        "syscall-filter"
+    }
+
+    fn run(&mut self) {
+        self.results = None;
+        crate::task::create_task(Self::TASK, "detsched-filtered", alloc::vec![Capability::IpcManage, Capability::TimeRead]);
+        crate::task::restrict_syscall_filter(Self::TASK, Self::ALLOWED);
+        let channel = match ipc::kernel_allocate(Self::TASK) {
+            Some(channel) => channel,
+            None => return,
+        };
+        self.channel = Some(channel);
+        if !run_as(Self::TASK) {
+            return;
+        }
+        let message = [0x5Eu8];
+        self.results = Some([
+            syscall_dispatch(SYS_TIME, 0, 0, 0),
+            syscall_dispatch(SYS_IPC_SEND, channel.raw() as u64, message.as_ptr() as u64, message.len() as u64),
+            syscall_dispatch(SYS_FILTER_RESTRICT, 1 << SYS_IPC_RECV_NONBLOCKING, 0, 0),
+            syscall_dispatch(SYS_FILTER_RESTRICT, ALL_SYSCALLS, 0, 0),
+            syscall_dispatch(SYS_TIME, 0, 0, 0),
+        ]);
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        let channel = self.channel.ok_or_else(|| "no channel could be allocated".to_string())?;
+        let [time, send, restrict, widen, time_again] = self.results.ok_or_else(|| format!("the task never ran; it is {:?}", state_of(Self::TASK)))?;
+        if time == E_SYSCALL_FILTERED {
+            return Err("SYS_TIME was filtered although the filter allows it".to_string());
+        }
+        if send != E_SYSCALL_FILTERED {
+            return Err(format!("SYS_IPC_SEND outside the filter returned {:#x}, expected E_SYSCALL_FILTERED", send));
+        }
+        if ipc::kernel_peek(channel) {
+            return Err(format!("the filtered send still queued a message on channel {}", channel));
+        }
+        if restrict != SUCCESS || widen != SUCCESS {
+            return Err(format!("SYS_FILTER_RESTRICT returned {:#x} and {:#x}, expected SUCCESS", restrict, widen));
+        }
+        if time_again != E_SYSCALL_FILTERED {
+            return Err(format!("SYS_TIME returned {:#x} after being dropped; restricting to every syscall brought it back", time_again));
+        }
+        let stats = crate::task::task_stats(Self::TASK).ok_or_else(|| "the task is gone".to_string())?;
+        if stats.flags & TASK_FLAG_FILTERED == 0 || stats.filter_violations != 2 {
+            return Err(format!("TaskStats shows flags {:#x} and {} violations, expected the filter flag and 2", stats.flags, stats.filter_violations));
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::TASK);
+        self.channel = None;
+        scheduler::schedule();
+    }
+}
+
+/// A package declares `NetworkAccess` and `StorageAccess`; the user grants
+/// the first and denies the second, and the task starts with the approved
+/// set narrowed by a configuration that lists both, the way init starts a
+/// package service from the installed-packages index.
+/// Invariant: `SYS_NET_TX` (`NetworkAccess`) is allowed and
+/// `SYS_SHARE_PAGES` (`StorageAccess`) returns `E_ACC_DENIED`.
+pub struct PackageGrants {
+    results: Option<[u64; 2]>, // net, storage
+}
+
+impl PackageGrants {
+    const TASK: TaskId = scenario_task(16);
+
+    pub fn new() -> Self {
+        Self { results: None }
+    }
+}
+
+impl Scenario for PackageGrants {
+    fn name(&self) -> &'static str {
+        "package-grants"
+    }
+
+    fn run(&mut self) {
+        self.results = None;
+        let requested = DeclaredCapabilities {
+            grants: alloc::vec![Grant::new(declared::Capability::NetworkAccess), Grant::new(declared::Capability::StorageAccess)],
+            syscalls: Vec::new(),
+        };
+        // What `ConfirmGrants` with `StorageAccess` denied records.
+        let mut approved = requested.clone();
+        approved.grants.retain(|grant| grant.capability != declared::Capability::StorageAccess);
+        let granted = approved.narrowed_to(&requested);
+        let mut capabilities = alloc::vec![Capability::IpcManage];
+        capabilities.extend(granted.grants.iter().map(|grant| Capability::from(grant.capability)));
+        crate::task::create_task(Self::TASK, "detsched-package", capabilities);
+        if !run_as(Self::TASK) {
+            return;
+        }
+        self.results = Some([
+            syscall_dispatch(SYS_NET_TX, 0, 0, 0),
+            syscall_dispatch(SYS_SHARE_PAGES, 0, 0, 0),
+        ]);
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        let [net, storage] = self.results.ok_or_else(|| format!("the task never ran; it is {:?}", state_of(Self::TASK)))?;
+        if net == E_ACC_DENIED {
+            return Err("SYS_NET_TX was denied although NetworkAccess was approved".to_string());
+        }
+        if storage != E_ACC_DENIED {
+            return Err(format!("SYS_SHARE_PAGES returned {:#x} with StorageAccess denied, expected E_ACC_DENIED", storage));
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        remove(Self::TASK);
+        scheduler::schedule();
+    }
+}
+
+/// A shell asks a file manager, which relays the request to the VFS and
+/// logs out right after sending. Meanwhile a forger sends the VFS a payload
+/// that is a credentials record naming init with the system identity.
+/// Invariant: each receiver's `SYS_IPC_CREDS` names the task that sent the
+/// message, with its name and the identity it had when sending: the file
+/// manager sees the shell, the VFS sees the file manager (not the shell) and
+/// the forger as unauthenticated.
+pub struct IpcCredsRelay {
+    channels: Option<(ChannelId, ChannelId)>, // File manager's, VFS's
+    shell_sent: bool,
+    forged: bool,
+    at_file_manager: Option<IpcCreds>,
+    at_vfs: Vec<(usize, IpcCreds)>, // Payload length and credentials
+}
+
+impl IpcCredsRelay {
+    const SHELL: TaskId = scenario_task(11);
+    const FILE_MANAGER: TaskId = scenario_task(12);
+    const VFS: TaskId = scenario_task(13);
+    const FORGER: TaskId = scenario_task(14);
+    const SHELL_AID: [u8; 32] = [0x5A; 32];
+    const FILE_MANAGER_AID: [u8; 32] = [0xF1; 32];
+    const MAX_STEPS: usize = 32;
+
+    pub fn new() -> Self {
+        Self { channels: None, shell_sent: false, forged: false, at_file_manager: None, at_vfs: Vec::new() }
+    }
+
+    fn creds() -> Option<IpcCreds> {
+        let mut record = [0u8; IPC_CREDS_LEN];
+        match syscall_dispatch(SYS_IPC_CREDS, record.as_mut_ptr() as u64, record.len() as u64, 0) {
+            len if len == IPC_CREDS_LEN as u64 => IpcCreds::from_bytes(&record),
+            _ => None,
+        }
+    }
+
+    /// One turn of `task`, which is current.
+    fn step(&mut self, task: TaskId, (fm_chan, vfs_chan): (ChannelId, ChannelId)) {
+        let mut buf = [0u8; 128];
+        match task {
+            Self::SHELL if !self.shell_sent => {
+                syscall_dispatch(SYS_IPC_SEND, fm_chan.raw() as u64, b"S".as_ptr() as u64, 1);
+                self.shell_sent = true;
+            },
+            Self::FORGER if !self.forged => {
+                let mut name = [0u8; 32];
+                name[..4].copy_from_slice(b"init");
+                let forged = IpcCreds { sender: 1000, name, aid: Some([0; 32]) }.to_bytes();
+                syscall_dispatch(SYS_IPC_SEND, vfs_chan.raw() as u64, forged.as_ptr() as u64, forged.len() as u64);
+                self.forged = true;
+            },
+            Self::FILE_MANAGER if self.at_file_manager.is_none() => {
+                if syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, fm_chan.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64) == 1 {
+                    self.at_file_manager = Self::creds();
+                    syscall_dispatch(SYS_IPC_SEND, vfs_chan.raw() as u64, b"R".as_ptr() as u64, 1);
+                    crate::task::set_task_identity(Self::FILE_MANAGER, None);
+                }
+            },
+            Self::VFS => {
+                loop {
+                    let len = syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, vfs_chan.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+                    if len == SUCCESS || len > buf.len() as u64 {
+                        break;
+                    }
+                    match Self::creds() {
+                        Some(creds) => self.at_vfs.push((len as usize, creds)),
+                        None => break,
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Checks that `creds` name `task` as `name` with `aid`.
+fn check_creds(what: &str, creds: &IpcCreds, task: TaskId, name: &str, aid: Option<[u8; 32]>) -> Result<(), String> {
+    if creds.sender != task.raw() || creds.name() != name || creds.aid != aid {
+        return Err(format!("{} carried task {} ({}) with Aid {:?}, expected task {} ({}) with {:?}",
+            what, creds.sender, creds.name(), creds.aid.map(|aid| aid[0]), task, name, aid.map(|aid| aid[0])));
+    }
+    Ok(())
+}
+
+impl Scenario for IpcCredsRelay {
+    fn name(&self) -> &'static str {
+        "ipc-creds-relay"
+    }
+
+    fn run(&mut self) {
+        *self = Self::new();
+        crate::task::create_task(Self::SHELL, "detsched-shell", alloc::vec![Capability::IpcManage]);
+        crate::task::create_task(Self::FILE_MANAGER, "detsched-file-manager", alloc::vec![Capability::IpcManage]);
+        crate::task::create_task(Self::VFS, "detsched-vfs", alloc::vec![Capability::IpcManage]);
+        crate::task::create_task(Self::FORGER, "detsched-forger", alloc::vec![Capability::IpcManage]);
+        crate::task::set_task_identity(Self::SHELL, Some(Self::SHELL_AID));
+        crate::task::set_task_identity(Self::FILE_MANAGER, Some(Self::FILE_MANAGER_AID));
+        let channels = match (ipc::kernel_allocate(Self::FILE_MANAGER), ipc::kernel_allocate(Self::VFS)) {
+            (Some(fm_chan), Some(vfs_chan)) => (fm_chan, vfs_chan),
+            _ => return,
+        };
+        self.channels = Some(channels);
+
+        // Whoever the scheduler picks takes its next step; then everyone finishes in order.
+        for _ in 0..Self::MAX_STEPS {
+            scheduler::schedule();
+            self.step(scheduler::current_task_id(), channels);
+        }
+        for task in [Self::SHELL, Self::FORGER, Self::FILE_MANAGER, Self::VFS] {
+            if run_as(task) {
+                self.step(task, channels);
+            }
+        }
+    }
+
+    fn invariant(&self) -> Result<(), String> {
+        self.channels.ok_or_else(|| "no channels could be allocated".to_string())?;
+        let at_file_manager = self.at_file_manager.ok_or_else(|| "the file manager never got the shell's request".to_string())?;
+        check_creds("the shell's request", &at_file_manager, Self::SHELL, "detsched-shell", Some(Self::SHELL_AID))?;
+        let relayed = self.at_vfs.iter().find(|(len, _)| *len == 1).ok_or_else(|| "the VFS never got the relayed request".to_string())?;
+        // Sent before the file manager logged out, so it still carries its identity.
+        check_creds("the relayed request", &relayed.1, Self::FILE_MANAGER, "detsched-file-manager", Some(Self::FILE_MANAGER_AID))?;
+        let forged = self.at_vfs.iter().find(|(len, _)| *len == IPC_CREDS_LEN).ok_or_else(|| "the VFS never got the forged request".to_string())?;
+        check_creds("the forged request", &forged.1, Self::FORGER, "detsched-forger", None)?;
+        if self.at_vfs.len() != 2 {
+            return Err(format!("the VFS received {} messages, expected 2", self.at_vfs.len()));
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        for task in [Self::SHELL, Self::FILE_MANAGER, Self::VFS, Self::FORGER] {
+            remove(task);
+        }
+        self.channels = None;
+        scheduler::schedule();
+    }
+}
+
+/// Round trips timed by `bench_ipc_round_trip`.
+const BENCH_ROUND_TRIPS: u64 = 32;
+
+/// Scheduler passes, handoffs and nanoseconds.
+#[derive(Clone, Copy, Default)]
+struct Cost {
+    passes: u64,
+    handoffs: u64,
+    nanos: u64,
+}
+
+impl Cost {
+    fn now() -> Self {
+        let (passes, handoffs) = scheduler::switch_counts();
+        Self { passes, handoffs, nanos: timer::nanos() }
+    }
+
+    fn since(start: Self) -> Self {
+        let end = Self::now();
+        Self {
+            passes: end.passes - start.passes,
+            handoffs: end.handoffs - start.handoffs,
+            nanos: end.nanos - start.nanos,
+        }
+    }
+
+    fn add(&mut self, other: Self) {
+        self.passes += other.passes;
+        self.handoffs += other.handoffs;
+        self.nanos += other.nanos;
+    }
+}
+
+/// Times no-op RPCs from a client to a server waiting in a blocking receive:
+/// first over the mailbox path, a send on the server's channel and a receive
+/// on the client's, then with `SYS_IPC_CALL` and `SYS_IPC_REPLY`. Each round
+/// trip is measured from the client's request until it has the reply; the
+/// server getting back into its receive is outside that. Logs the average
+/// scheduler passes, handoffs and nanoseconds per round trip. Runs outside a
+/// `detsched` run, so the scheduler picks in queue order.
+pub fn bench_ipc_round_trip() {
+    const SERVER: TaskId = scenario_task(8);
+    const CLIENT: TaskId = scenario_task(9);
+    crate::task::create_task(SERVER, "ipc-bench-server", alloc::vec![Capability::IpcManage]);
+    crate::task::create_task(CLIENT, "ipc-bench-client", alloc::vec![Capability::IpcManage]);
+    let (requests, replies) = match (ipc::kernel_allocate(SERVER), ipc::kernel_allocate(CLIENT)) {
+        (Some(requests), Some(replies)) => (requests, replies),
+        _ => {
+            kprintln!("[kernel] ipc-bench: No free channels; skipped.");
+            remove(CLIENT);
+            remove(SERVER);
+            scheduler::schedule();
+            return;
+        }
+    };
+    let recv = |channel: ChannelId, buf: &mut [u8]| syscall_dispatch(SYS_IPC_RECV, channel.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+    let send = |channel: ChannelId, data: &[u8]| syscall_dispatch(SYS_IPC_SEND, channel.raw() as u64, data.as_ptr() as u64, data.len() as u64);
+    let mut buf = [0u8; 8];
+
+    // The server waits for requests in a blocking receive throughout.
+    let round_trips = if run_as(SERVER) && recv(requests, &mut buf) == SUCCESS { BENCH_ROUND_TRIPS } else { 0 };
+
+    let mut mailbox = Cost::default();
+    let mut mailbox_trips = 0;
+    while mailbox_trips < round_trips && run_as(CLIENT) {
+        let start = Cost::now();
+        send(requests, &[0]);
+        recv(replies, &mut buf); // Blocks; the server runs
+        if scheduler::current_task_id() != SERVER || recv(requests, &mut buf) != 1 {
+            break;
+        }
+        send(replies, &[0]);
+        recv(requests, &mut buf); // Blocks again; the client runs
+        if scheduler::current_task_id() != CLIENT || recv(replies, &mut buf) != 1 {
+            break;
+        }
+        mailbox.add(Cost::since(start));
+        mailbox_trips += 1;
+    }
+
+    let mut call_path = Cost::default();
+    let mut call_trips = 0;
+    let mut reply = [0u8; 8];
+    while call_trips < round_trips && run_as(CLIENT) {
+        let start = Cost::now();
+        let mut call = new_call(&[0], &mut reply);
+        ipc_call(requests, &mut call); // Hands off to the server
+        if scheduler::current_task_id() != SERVER || recv(requests, &mut buf) != 1 {
+            break;
+        }
+        let token = syscall_dispatch(SYS_IPC_REPLY_TOKEN, 0, 0, 0);
+        syscall_dispatch(SYS_IPC_REPLY, token, [0u8].as_ptr() as u64, 1); // Hands back
+        if scheduler::current_task_id() != CLIENT || ipc_call(requests, &mut call) != SUCCESS {
+            break;
+        }
+        call_path.add(Cost::since(start));
+        call_trips += 1;
+        // The server was queued by the handoff back; let it return to its receive.
+        if !run_as(SERVER) || recv(requests, &mut buf) != SUCCESS {
+            break;
+        }
+    }
+
+    for (path, cost, trips) in [("Mailbox", mailbox, mailbox_trips), ("Call", call_path, call_trips)] {
+        kprintln!(
+            "[kernel] ipc-bench: {} path: {} round trips, {} scheduler passes and {} handoffs in total, {} ns each.",
+            path, trips, cost.passes, cost.handoffs, cost.nanos / trips.max(1)
+        );
+    }
+    if timer::clock_source() == ClockSource::Ticks {
+        kprintln!("[kernel] ipc-bench: The clock has tick resolution; the times are not meaningful.");
+    }
+    if mailbox_trips < BENCH_ROUND_TRIPS || call_trips < BENCH_ROUND_TRIPS {
+        kprintln!("[kernel] ipc-bench: WARNING: A round trip didn't go as expected; the numbers cover only the completed ones.");
+    }
+
+    remove(CLIENT);
+    remove(SERVER);
+    scheduler::schedule();
+}
+
+/// PIT window `check_clock` measures; five calibration windows.
+const CLOCK_CHECK_WINDOW_MS: u64 = 50;
+/// How far that window may read off, in parts per million (0.5 %).
+const CLOCK_CHECK_TOLERANCE_PPM: u64 = 5_000;
+/// Consecutive reads that must not go backwards.
+const CLOCK_CHECK_READS: u32 = 10_000;
+
+/// Checks the nanosecond clock: `mul_div` at extreme values, the calibrated
+/// TSC frequency against a fresh, longer PIT window, and that `SYS_TIME`
+/// never runs backwards. Not a `detsched` scenario: it involves one task and
+/// no scheduling.
+pub fn check_clock() -> Result<(), String> {
+    const TASK: TaskId = scenario_task(15);
+    crate::task::create_task(TASK, "clock-check", alloc::vec![Capability::TimeRead]);
+    let result = if run_as(TASK) { check_clock_as_current() } else { Err("the check task never ran".to_string()) };
+    remove(TASK);
+    scheduler::schedule();
+    result
+}
+
+fn check_clock_as_current() -> Result<(), String> {
+    const FIVE_GHZ: u64 = 5_000_000_000;
+    let conversions = [
+        ((u64::MAX, u64::MAX, u64::MAX), u64::MAX),
+        ((u64::MAX, 2, 1), u64::MAX),
+        ((u64::MAX, u64::MAX - 1, u64::MAX), u64::MAX - 1),
+        ((1, 1, 0), u64::MAX),
+        ((0, u64::MAX, 1), 0),
+        ((86_400 * FIVE_GHZ, timer::NANOS_PER_SECOND, FIVE_GHZ), 86_400 * timer::NANOS_PER_SECOND),
+    ];
+    for ((a, b, c), expected) in conversions {
+        let got = timer::mul_div(a, b, c);
+        if got != expected {
+            return Err(format!("mul_div({}, {}, {}) is {}, expected {}", a, b, c, got, expected));
+        }
+    }
+
+    if let ClockSource::Tsc { hz } = timer::clock_source() {
+        let cycles = timer::measure_tsc_window(CLOCK_CHECK_WINDOW_MS).ok_or_else(|| "the PIT didn't count down".to_string())?;
+        let measured = timer::mul_div(cycles, timer::NANOS_PER_SECOND, hz);
+        let window = CLOCK_CHECK_WINDOW_MS * 1_000_000;
+        if measured.abs_diff(window) > window / 1_000_000 * CLOCK_CHECK_TOLERANCE_PPM {
+            return Err(format!("a {} ms PIT window measured {} ns at {} Hz", CLOCK_CHECK_WINDOW_MS, measured, hz));
+        }
+    }
+
+    let mut last = syscall_dispatch(SYS_TIME, TIME_NANOS, 0, 0);
+    for _ in 0..CLOCK_CHECK_READS {
+        let now = syscall_dispatch(SYS_TIME, TIME_NANOS, 0, 0);
+        if now < last {
+            return Err(format!("TIME_NANOS went back from {} to {}", last, now));
+        }
+        last = now;
+    }
+    let before = syscall_dispatch(SYS_TIME, TIME_NANOS, 0, 0);
+    let (secs, nanos) = split_secs_nanos(syscall_dispatch(SYS_TIME, TIME_SECS_NANOS, 0, 0));
+    let after = syscall_dispatch(SYS_TIME, TIME_NANOS, 0, 0);
+    let combined = secs * timer::NANOS_PER_SECOND + nanos as u64;
+    if nanos as u64 >= timer::NANOS_PER_SECOND || combined < before || combined > after {
+        return Err(format!("TIME_SECS_NANOS read {}.{:09} s between {} and {} ns", secs, nanos, before, after));
+    }
+    Ok(())
+}
+
+/// Checks the syscall ABI at the dispatcher: `SYS_ABI_VERSION` answers this
+/// build's version, which is at least the client library's minimum, a
+/// non-zero reserved argument or flag bit is `E_INVALID_ARG` before the
+/// syscall does anything, and a number past the table is `E_UNKNOWN_SYSCALL`.
+/// Not a `detsched` scenario: it involves one task and no scheduling.
+pub fn check_abi() -> Result<(), String> {
+    const TASK: TaskId = scenario_task(23);
+    crate::task::create_task(TASK, "abi-check", Vec::new());
+    let result = if run_as(TASK) { check_abi_as_current() } else { Err("the check task never ran".to_string()) };
+    remove(TASK);
+    scheduler::schedule();
+    result
+}
+
+fn check_abi_as_current() -> Result<(), String> {
+    let version = syscall_dispatch(SYS_ABI_VERSION, 0, 0, 0);
+    if version != ABI_VERSION || version < MIN_KERNEL_ABI_VERSION {
+        return Err(format!("SYS_ABI_VERSION returned {}, expected {} (minimum {})", version, ABI_VERSION, MIN_KERNEL_ABI_VERSION));
+    }
+    // Had the dispatcher ignored the reserved argument, SYS_BLOCK_ON_CHAN would block the check task.
+    let rejected = [
+        ("SYS_ABI_VERSION with arg1 set", syscall_dispatch(SYS_ABI_VERSION, 1, 0, 0)),
+        ("SYS_TIME with a stale arg3", syscall_dispatch(SYS_TIME, TIME_NANOS, 0, 0xdead)),
+        ("SYS_BLOCK_ON_CHAN with arg2 set", syscall_dispatch(SYS_BLOCK_ON_CHAN, FIRST_DYNAMIC_CHANNEL as u64, 1, 0)),
+        ("SYS_FILTER_RESTRICT with a bit past the last syscall", syscall_dispatch(SYS_FILTER_RESTRICT, 1 << SYSCALL_COUNT, 0, 0)),
+    ];
+    for (what, result) in rejected {
+        if result != E_INVALID_ARG {
+            return Err(format!("{} returned {:#x}, expected E_INVALID_ARG", what, result));
+        }
+    }
+    let unknown = syscall_dispatch(SYSCALL_COUNT as u64, 0, 0, 0);
+    if unknown != E_UNKNOWN_SYSCALL {
+        return Err(format!("syscall {} returned {:#x}, expected E_UNKNOWN_SYSCALL", SYSCALL_COUNT, unknown));
+    }
+    Ok(())
+}
+
+/// Checks the `SYS_LOG` flood summary: past the burst messages are dropped
+/// but still succeed, and the timer takes their summary once the report
+/// interval is over, although the task logs nothing more. A well-behaved task
+/// logging during the flood gets every message through, and the console
+/// output of the flood stays within its burst. Not a `detsched` scenario: the
+/// two tasks only take turns. The summaries are taken with times past the
+/// interval, as the timer would, and printed as the idle loop would, so the
+/// check doesn't wait.
+pub fn check_log_flood() -> Result<(), String> {
+    const FLOODER: TaskId = scenario_task(24);
+    const QUIET: TaskId = scenario_task(25);
+    crate::task::create_task(FLOODER, "log-flood-check", alloc::vec![Capability::LogWrite]);
+    crate::task::create_task(QUIET, "log-quiet-check", alloc::vec![Capability::LogWrite]);
+    // A small bucket keeps the flood's printed part short.
+    scheduler::with_task_mut(FLOODER, |tcb| {
+        tcb.limits.log_burst = FLOOD_CHECK_BURST;
+        tcb.limits.log_rate_per_sec = 1;
+        tcb.log_limiter = LogRateLimiter::new(FLOOD_CHECK_BURST);
+    });
+    let result = check_log_flood_between(FLOODER, QUIET);
+    remove(FLOODER);
+    remove(QUIET);
+    scheduler::schedule();
+    result
+}
+
+const FLOOD_CHECK_BURST: u32 = 2;
+const FLOOD_MESSAGE: &str = "log flood check";
+
+fn pending_summary(task_id: TaskId) -> u64 {
+    scheduler::with_task_mut(task_id, |tcb| tcb.log_limiter.suppressed_since_report).unwrap_or(0)
+}
+
+fn suppressed_total(task_id: TaskId) -> u64 {
+    scheduler::with_task_mut(task_id, |tcb| tcb.log_limiter.suppressed_total).unwrap_or(0)
+}
+
+/// Logs `message` `count` times as the current task and fails unless every call succeeded.
+fn log_as_current(message: &str, count: usize) -> Result<(), String> {
+    for i in 0..count {
+        let result = syscall_dispatch(SYS_LOG, message.as_ptr() as u64, message.len() as u64, 0);
+        if result != SUCCESS {
+            return Err(format!("SYS_LOG {} of \"{}\" returned {:#x}", i, message, result));
+        }
+    }
+    Ok(())
+}
+
+/// Logs `message` `count` times as `task_id`.
+fn log_as(task_id: TaskId, message: &str, count: usize) -> Result<(), String> {
+    if !run_as(task_id) {
+        return Err(format!("task {} never ran", task_id));
+    }
+    log_as_current(message, count)
+}
+
+/// Whether `line` is among the newest lines of the kernel log.
+fn logged(line: &str) -> bool {
+    let mut tail = alloc::vec![0u8; 4096];
+    let len = crate::klog::read(&mut tail).min(tail.len());
+    core::str::from_utf8(&tail[..len]).map_or(false, |text| text.contains(line))
+}
+
+fn logged_summary(task_id: TaskId, suppressed: u64) -> bool {
+    logged(&format!("task {}: suppressed {} messages.", task_id, suppressed))
+}
+
+/// Bytes printed since boot. Everything printed goes to the serial port and
+/// the kernel log alike, so this is the serial output too.
+fn console_bytes() -> u64 {
+    crate::klog::with_ring(|ring| ring.written())
+}
+
+/// Takes the due summaries as the timer does at `now`, and prints them as the idle loop does.
+fn report_at(now: Ticks) {
+    crate::task::collect_suppressed_logs(now);
+    crate::task::print_suppressed_logs();
+}
+
+fn check_log_flood_between(flooder: TaskId, quiet: TaskId) -> Result<(), String> {
+    const ROUNDS: usize = 4;
+    const PER_ROUND: usize = 50;
+    // Both tasks take turns: the flooder's messages past its burst are
+    // dropped, and all of the quiet task's make it.
+    let printed_before = console_bytes();
+    for round in 0..ROUNDS {
+        log_as(flooder, FLOOD_MESSAGE, PER_ROUND)?;
+        log_as(quiet, &format!("log quiet check {}", round), 1)?;
+    }
+    let printed = console_bytes() - printed_before;
+    let shown = crate::bootinfo::log_floor() <= LOG_SEVERITY_INFO;
+    if suppressed_total(quiet) != 0 || (shown && !(0..ROUNDS).all(|round| logged(&format!("] log quiet check {}\n", round)))) {
+        return Err(format!("{} of the quiet task's messages were dropped during the flood", suppressed_total(quiet)));
+    }
+    let suppressed = pending_summary(flooder);
+    if suppressed == 0 {
+        return Err(format!("{} messages past a burst of {} were all written", ROUNDS * PER_ROUND, FLOOD_CHECK_BURST));
+    }
+    // The flooder's burst, a token it may have earned back meanwhile, and
+    // the quiet task's lines; the whole flood would be 200 lines.
+    let flood_line = format!("[V-Node Log {}] {}\n", flooder, FLOOD_MESSAGE).len();
+    let quiet_line = format!("[V-Node Log {}] log quiet check 0\n", quiet).len();
+    let bound = (FLOOD_CHECK_BURST as usize + 1) * flood_line + ROUNDS * quiet_line;
+    if printed > bound as u64 {
+        return Err(format!("the flood printed {} bytes, more than the {} its burst allows", printed, bound));
+    }
+
+    // The flood is over. The next interval start reports it, with no SYS_LOG from the task.
+    if !run_as(flooder) {
+        return Err("the flooding task never ran again".to_string());
+    }
+    let interval = Ticks::from_secs(LOG_SUPPRESSION_REPORT_INTERVAL_SECS);
+    let first_report = timer::get_current_ticks().saturating_add(interval);
+    report_at(first_report);
+    if pending_summary(flooder) != 0 || !logged_summary(flooder, suppressed) {
+        return Err(format!("the timer didn't report the {} suppressed messages once the flood ended", suppressed));
+    }
+
+    // Another flood in the same interval waits for the interval to end.
+    log_as_current(FLOOD_MESSAGE, 20)?;
+    let suppressed = pending_summary(flooder);
+    report_at(first_report.saturating_add(Ticks::from_raw(interval.raw() - 1)));
+    if pending_summary(flooder) != suppressed {
+        return Err("a second summary was printed within one report interval".to_string());
+    }
+    report_at(first_report.saturating_add(interval));
+    if pending_summary(flooder) != 0 || !logged_summary(flooder, suppressed) {
+        return Err(format!("the {} messages suppressed by the second flood were never reported", suppressed));
+    }
+    Ok(())
+}
+
+/// Checks DMA domains: a mapping in two pieces is bounced and round-trips,
+/// a write between `map` and `sync_for_device` reaches the device while one
+/// the device makes stays out of the CPU's memory until `sync_for_cpu`, a
+/// domain that can't reach a bounce buffer refuses the mapping, and
+/// destroying a domain frees its buffers and every mapping left in it. Not a
+/// `detsched` scenario: no task runs. A device access is a write or read at
+/// the device address, which is the kernel address under the identity translation.
+pub fn check_dma() -> Result<(), String> {
+    const OWNER: TaskId = scenario_task(17);
+    let domain = dma::create_domain(OWNER, "dma-check", dma::ADDRESS_BITS_ALL);
+    if let Err(message) = check_dma_round_trip(domain) {
+        dma::destroy_domain(domain);
+        return Err(message);
+    }
+
+    let mut heap = alloc::vec![1u8; 16]; // The heap, and so any bounce buffer, is above 4 GiB
+    let narrow = dma::create_domain(OWNER, "dma-check-32", 32);
+    let refused = dma::map(narrow, &[DmaSegment::of(&mut heap)], DmaDirection::ToDevice);
+    let leaked = dma::destroy_domain(narrow);
+    if refused.is_ok() || leaked != 0 {
+        return Err(format!("a 32-bit domain mapped heap memory: {:?}, {} left mapped", refused, leaked));
+    }
+
+    let mut head = [1u8; 16];
+    let mut tail = [2u8; 8];
+    let handle = dma::alloc_dma_buffer_in(domain, 64).ok_or_else(|| "no buffer in the domain".to_string())?;
+    let split = dma::map(domain, &[DmaSegment::of(&mut head), DmaSegment::of(&mut tail)], DmaDirection::FromDevice);
+    let whole = dma::map(domain, &[DmaSegment::of(&mut tail)], DmaDirection::ToDevice);
+    let mapped = dma::count_task_mappings(OWNER);
+    let leaked = dma::destroy_domain(domain);
+    if split.is_err() || whole.is_err() || mapped != 3 || leaked != 2 {
+        return Err(format!("teardown of {} mappings reported {} leaks, expected 3 and 2", mapped, leaked));
+    }
+    if dma::get_dma_buffer_ptr(handle).is_some() || dma::count_task_buffers(OWNER) != 0 || dma::count_task_mappings(OWNER) != 0 {
+        return Err("the domain's buffer or mappings outlived it".to_string());
+    }
+    Ok(())
+}
+
+fn check_dma_round_trip(domain: u64) -> Result<(), String> {
+    let mut whole = [0u8; 32];
+    let device = dma::map(domain, &[DmaSegment::of(&mut whole)], DmaDirection::ToDevice).map_err(|e| format!("map: {:?}", e))?;
+    if device != whole.as_ptr() as u64 {
+        return Err(format!("a contiguous buffer at {:p} was mapped at {:#x}", whole.as_ptr(), device));
+    }
+    dma::unmap(domain, device).map_err(|e| format!("unmap: {:?}", e))?;
+
+    let mut head = [1u8; 16];
+    let mut tail = [2u8; 8];
+    let segments = [DmaSegment::of(&mut head), DmaSegment::of(&mut tail)];
+    let device = dma::map(domain, &segments, DmaDirection::Bidirectional).map_err(|e| format!("map: {:?}", e))?;
+    if device == segments[0].addr as u64 {
+        return Err("a buffer in two pieces wasn't bounced".to_string());
+    }
+    // SAFETY: A bounced mapping of 24 bytes, at its kernel address, until the unmap below.
+    let seen = unsafe { core::slice::from_raw_parts_mut(device as *mut u8, 24) };
+
+    head[0] = 9;
+    tail[7] = 8;
+    dma::sync_for_device(domain, device).map_err(|e| format!("sync_for_device: {:?}", e))?;
+    if seen[0] != 9 || seen[1..16].iter().any(|&b| b != 1) || seen[16..23].iter().any(|&b| b != 2) || seen[23] != 8 {
+        return Err(format!("the device saw {:?} after sync_for_device", seen));
+    }
+
+    for (i, b) in seen.iter_mut().enumerate() {
+        *b = 100 + i as u8;
+    }
+    if head[0] != 9 || tail[0] != 2 {
+        return Err("a device write reached the CPU's memory before sync_for_cpu".to_string());
+    }
+    dma::sync_for_cpu(domain, device).map_err(|e| format!("sync_for_cpu: {:?}", e))?;
+    let back: Vec<u8> = head.iter().chain(tail.iter()).copied().collect();
+    if back.iter().enumerate().any(|(i, &b)| b != 100 + i as u8) {
+        return Err(format!("the CPU read {:?} after sync_for_cpu", back));
+    }
+    dma::unmap(domain, device).map_err(|e| format!("unmap: {:?}", e))
+}
+
+/// Storm threshold the fault check sets, in faults per second.
+const FAULT_CHECK_THRESHOLD: u32 = 50;
+
+/// Checks fault accounting: faults of each kind are counted against the task
+/// that took them and show up in its `TaskStats`, a task crossing the storm
+/// threshold raises exactly one storm naming it, staying above it raises no
+/// more, and a threshold of 0 raises none. The faults go through
+/// `faults::record`, which is what the exception handlers call; raising them
+/// for real would kill the check task, or the kernel. NMIs and machine checks
+/// aren't covered: a machine check panics. Not a `detsched` scenario: it
+/// involves one task and no scheduling.
+pub fn check_faults() -> Result<(), String> {
+    const TASK: TaskId = scenario_task(18);
+    crate::task::create_task(TASK, "fault-check", alloc::vec![Capability::TaskMonitor]);
+    let previous = faults::storm_threshold();
+    let result = if run_as(TASK) { check_faults_as_current(TASK) } else { Err("the check task never ran".to_string()) };
+    faults::set_storm_threshold(previous);
+    remove(TASK);
+    scheduler::schedule();
+    result
+}
+
+/// Sets the storm threshold and takes the pending storms, with `SYS_FAULT_STORMS`.
+fn take_fault_storms(threshold: u32) -> Result<Vec<FaultStorm>, String> {
+    const MAX: usize = 4;
+    let mut buf = [0u8; MAX * FAULT_STORM_LEN];
+    let res = syscall_dispatch(SYS_FAULT_STORMS, threshold as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+    if res as usize > MAX {
+        return Err(format!("SYS_FAULT_STORMS returned {:#x}", res));
+    }
+    Ok(buf.chunks(FAULT_STORM_LEN).take(res as usize).filter_map(FaultStorm::from_bytes).collect())
+}
+
+fn check_faults_as_current(task: TaskId) -> Result<(), String> {
+    // Nothing raised a storm before the V-Nodes start, so this only sets the threshold.
+    take_fault_storms(FAULT_CHECK_THRESHOLD)?;
+    let kinds = [(FaultKind::PageResolved, 30), (FaultKind::PageFatal, 1), (FaultKind::GeneralProtection, 2), (FaultKind::InvalidOpcode, 3), (FaultKind::AlignmentCheck, 4)];
+    for (kind, count) in kinds {
+        for _ in 0..count {
+            faults::record(task, kind);
+        }
+    }
+    let stats = crate::task::task_stats(task).ok_or_else(|| "the check task has no stats".to_string())?;
+    let counted = (stats.page_faults_resolved, stats.page_faults_fatal, stats.general_protection_faults, stats.invalid_opcodes, stats.alignment_checks);
+    if counted != (30, 1, 2, 3, 4) {
+        return Err(format!("faults were counted as {:?}, expected (30, 1, 2, 3, 4)", counted));
+    }
+    let storms = take_fault_storms(FAULT_CHECK_THRESHOLD)?;
+    if !storms.is_empty() {
+        return Err(format!("40 faults raised {:?} at a threshold of {}", storms, FAULT_CHECK_THRESHOLD));
+    }
+
+    for _ in 0..11 {
+        faults::record(task, FaultKind::PageResolved);
+    }
+    let storms = take_fault_storms(FAULT_CHECK_THRESHOLD)?;
+    match storms.as_slice() {
+        [storm] if storm.task == task.raw() && storm.faults == 51 && storm.threshold == FAULT_CHECK_THRESHOLD && storm.name() == "fault-check" => {}
+        _ => return Err(format!("51 faults raised {:?}, expected one storm of task {} at 51", storms, task)),
+    }
+    for _ in 0..100 {
+        faults::record(task, FaultKind::PageResolved);
+    }
+    let storms = take_fault_storms(0)?;
+    if !storms.is_empty() {
+        return Err(format!("a storm that went on raised {:?} again", storms));
+    }
+    for _ in 0..100 {
+        faults::record(task, FaultKind::PageResolved);
+    }
+    let storms = take_fault_storms(0)?;
+    if !storms.is_empty() {
+        return Err(format!("the alarm was off and {:?} were raised", storms));
+    }
+    Ok(())
+}
+
+/// Where the core dump check's target has its image, and the marker its
+/// data segment holds.
+const DUMP_IMAGE_START: u64 = 0x40_0000;
+const DUMP_MARKER_ADDR: u64 = DUMP_IMAGE_START + 0x1010;
+const DUMP_MARKER: &[u8] = b"AETHER-CORE-MARKER";
+
+/// Checks core dumps: a dump of a faulting task has the registers of the
+/// fault over the ones saved at its last switch, its three segments with
+/// their permissions and the marker in its data segment. A small cap cuts
+/// the big segment and keeps the small one whole, `SYS_DEBUG_DUMP` dumps
+/// the full saved registers, and a task with the no-dump flag gets a header
+/// only and is refused to the debugger. The fault goes through
+/// `coredump::capture_fault`, which is what the exception handlers call.
+/// The target gets a made-up image, since the check can't load a binary.
+pub fn check_core_dumps() -> Result<(), String> {
+    const CHECKER: TaskId = scenario_task(19);
+    const TARGET: TaskId = scenario_task(20);
+    crate::task::create_task(CHECKER, "dump-check", alloc::vec![Capability::TaskMonitor, Capability::Debug]);
+    crate::task::create_task(TARGET, "dump-target", Vec::new());
+    let previous = coredump::size_cap_kb();
+    let result = if run_as(CHECKER) { check_core_dumps_as_current(TARGET) } else { Err("the check task never ran".to_string()) };
+    coredump::set_size_cap_kb(previous);
+    remove(TARGET);
+    remove(CHECKER);
+    scheduler::schedule();
+    result
+}
+
+/// Sets the size cap and takes the oldest dump, with `SYS_CORE_DUMP_TAKE`:
+/// once to learn its length and once to copy it.
+fn take_core_dump(cap_kb: u32) -> Result<Option<CoreDump>, String> {
+    let len = syscall_dispatch(SYS_CORE_DUMP_TAKE, cap_kb as u64, 0, 0);
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > 1 << 20 {
+        return Err(format!("SYS_CORE_DUMP_TAKE returned {:#x}", len));
+    }
+    let mut buf = alloc::vec![0u8; len as usize];
+    let copied = syscall_dispatch(SYS_CORE_DUMP_TAKE, cap_kb as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+    if copied != len {
+        return Err(format!("the dump was {} bytes, then {}", len, copied));
+    }
+    CoreDump::parse(&buf).map(Some).map_err(|e| format!("the dump doesn't parse: {:?}", e))
+}
+
+fn check_core_dumps_as_current(target: TaskId) -> Result<(), String> {
+    // Nothing crashed before the V-Nodes start, so this only sets the cap.
+    take_core_dump(64)?;
+    let mut memory = alloc::vec![0u8; 0x4000];
+    let marker_at = (DUMP_MARKER_ADDR - DUMP_IMAGE_START) as usize;
+    memory[marker_at..marker_at + DUMP_MARKER.len()].copy_from_slice(DUMP_MARKER);
+    let segment = |offset: u64, memsz: u64, writable: bool, executable: bool| Segment { vaddr: DUMP_IMAGE_START + offset, memsz, readable: true, writable, executable };
+    task_memory::install(target, LoadedImage {
+        elf_type: ElfType::PositionIndependent,
+        load_base: DUMP_IMAGE_START,
+        entry_point: DUMP_IMAGE_START,
+        segments: alloc::vec![segment(0, 0x1000, false, true), segment(0x1000, 0x100, true, false), segment(0x2000, 0x2000, true, false)],
+        image_start: DUMP_IMAGE_START,
+        memory,
+        relocations: 0,
+    });
+    let saved = RegisterFrame { rip: 0x1, rsp: 0x2, rax: 0xA1, rbx: 0xB2, r15: 0xF15, ..RegisterFrame::default() };
+    scheduler::with_task_mut(target, |tcb| tcb.regs = saved);
+    let context = FaultContext { rip: DUMP_IMAGE_START + 0x123, rsp: 0x7FF0, rflags: 0x202, cs: 0x23, ss: 0x1B, addr: DUMP_MARKER_ADDR, error_code: 6 };
+
+    coredump::capture_fault(target, CORE_REASON_PAGE_FAULT, context);
+    let dump = take_core_dump(64)?.ok_or_else(|| "a fault queued no dump".to_string())?;
+    let (header, regs) = (dump.header, dump.registers);
+    if header.task != target.raw() || header.name() != "dump-target" || header.reason != CORE_REASON_PAGE_FAULT || header.fault_addr != DUMP_MARKER_ADDR || header.error_code != 6 {
+        return Err(format!("the fault's header is {:?}", header));
+    }
+    if header.flags != CORE_FLAG_PARTIAL_REGISTERS {
+        return Err(format!("the fault's dump has flags {:#x}, expected only CORE_FLAG_PARTIAL_REGISTERS", header.flags));
+    }
+    if (regs.rip, regs.rsp, regs.rflags, regs.cs, regs.ss) != (context.rip, context.rsp, context.rflags, context.cs, context.ss) || (regs.rax, regs.rbx, regs.r15) != (0xA1, 0xB2, 0xF15) {
+        return Err(format!("the fault's registers are {:?}", regs));
+    }
+    let layout: Vec<(u64, u64, u32, usize)> = dump.regions.iter().map(|region| (region.header.vaddr, region.header.memsz, region.header.flags, region.contents.len())).collect();
+    let expected = alloc::vec![
+        (DUMP_IMAGE_START, 0x1000, REGION_READ | REGION_EXECUTE, 0),
+        (DUMP_IMAGE_START + 0x1000, 0x100, REGION_READ | REGION_WRITE, 0x100),
+        (DUMP_IMAGE_START + 0x2000, 0x2000, REGION_READ | REGION_WRITE, 0x2000),
+    ];
+    if layout != expected {
+        return Err(format!("the regions are {:x?}, expected {:x?}", layout, expected));
+    }
+    if dump.bytes_at(DUMP_MARKER_ADDR).map(|bytes| bytes.starts_with(DUMP_MARKER)) != Some(true) {
+        return Err("the marker isn't in the dump".to_string());
+    }
+
+    // 3 KiB leave room for the data segment and part of the other.
+    take_core_dump(3)?;
+    coredump::capture_fault(target, CORE_REASON_PAGE_FAULT, context);
+    let dump = take_core_dump(3)?.ok_or_else(|| "a capped fault queued no dump".to_string())?;
+    let stored: Vec<usize> = dump.regions.iter().map(|region| region.contents.len()).collect();
+    if dump.header.flags & CORE_FLAG_TRUNCATED == 0 || dump.to_bytes().len() > 3 * 1024 || stored[1] != 0x100 || stored[2] == 0 || stored[2] >= 0x2000 {
+        return Err(format!("a 3 KiB cap stored {:x?} with flags {:#x}", stored, dump.header.flags));
+    }
+    take_core_dump(64)?;
+
+    if syscall_dispatch(SYS_DEBUG_DUMP, target.raw(), 0, 0) != SUCCESS {
+        return Err("SYS_DEBUG_DUMP refused a stopped task".to_string());
+    }
+    let dump = take_core_dump(64)?.ok_or_else(|| "SYS_DEBUG_DUMP queued no dump".to_string())?;
+    if dump.header.reason != CORE_REASON_REQUESTED || dump.header.flags != 0 || dump.registers != saved {
+        return Err(format!("the requested dump has {:?} and {:?}", dump.header, dump.registers));
+    }
+
+    crate::task::set_no_core_dump(target);
+    coredump::capture_fault(target, CORE_REASON_PAGE_FAULT, context);
+    let dump = take_core_dump(64)?.ok_or_else(|| "a no-dump task's fault queued nothing".to_string())?;
+    if dump.header.flags != CORE_FLAG_WITHHELD || !dump.regions.is_empty() || dump.registers != RegisterFrame::default() {
+        return Err(format!("a no-dump task's dump has flags {:#x}, {} regions and {:?}", dump.header.flags, dump.regions.len(), dump.registers));
+    }
+    if syscall_dispatch(SYS_DEBUG_DUMP, target.raw(), 0, 0) != E_ACC_DENIED {
+        return Err("SYS_DEBUG_DUMP dumped a no-dump task".to_string());
+    }
+    if take_core_dump(64)?.is_some() {
+        return Err("a refused SYS_DEBUG_DUMP queued a dump".to_string());
+    }
+    Ok(())
+}
+
+/// Pages of "file" the file mapping check shares.
+const MAP_CHECK_PAGES: usize = 2;
+
+/// Checks file mappings: a shared range that isn't page-aligned, wraps, or
+/// isn't the owner's own memory is refused; a mapping shows the same bytes
+/// as a read of the owner's memory; writes to it are refused; the backing
+/// can't be dropped while mapped; unmapping revokes access; and a page of an
+/// on-demand backing blocks its reader until the owner supplies it, or until
+/// the owner exits. The owner's "file" is a made-up image over real kernel
+/// memory, so the mapped bytes can be read back directly. Faults go through
+/// `file_map::handle_fault`, which is what the page fault handler calls; no
+/// fault ever pages in, which would remap that kernel memory read-only. Not
+/// a `detsched` scenario: no task runs.
+pub fn check_file_maps() -> Result<(), String> {
+    const OWNER: TaskId = scenario_task(21);
+    const READER: TaskId = scenario_task(22);
+    crate::task::create_task(OWNER, "map-owner", alloc::vec![Capability::StorageAccess]);
+    crate::task::create_task(READER, "map-reader", Vec::new());
+    let result = check_file_maps_as(OWNER, READER);
+    remove(READER);
+    remove(OWNER);
+    result
+}
+
+fn check_file_maps_as(owner: TaskId, reader: TaskId) -> Result<(), String> {
+    let len = MAP_CHECK_PAGES * file_map::PAGE_SIZE;
+    // One spare page, so the file can start on a page boundary inside it.
+    let mut memory = alloc::vec![0u8; len + file_map::PAGE_SIZE];
+    let image_start = memory.as_ptr() as usize;
+    let base = image_start.next_multiple_of(file_map::PAGE_SIZE);
+    for (i, byte) in memory[base - image_start..][..len].iter_mut().enumerate() {
+        *byte = (i % 251) as u8;
+    }
+    task_memory::install(owner, LoadedImage {
+        elf_type: ElfType::PositionIndependent,
+        load_base: image_start as u64,
+        entry_point: image_start as u64,
+        segments: alloc::vec![Segment { vaddr: base as u64, memsz: len as u64, readable: true, writable: true, executable: false }],
+        image_start: image_start as u64,
+        memory,
+        relocations: 0,
+    });
+
+    let refused = [
+        ("a misaligned base", file_map::share_pages(owner, reader, base + 1, 16)),
+        ("a range that wraps", file_map::share_pages(owner, reader, usize::MAX & !(file_map::PAGE_SIZE - 1), 2 * file_map::PAGE_SIZE)),
+        ("a range past the owner's memory", file_map::share_pages(owner, reader, base, len + 1)),
+        ("another task's memory", file_map::share_pages(reader, owner, base, len)),
+    ];
+    for (what, result) in refused {
+        if result.is_ok() {
+            return Err(format!("sharing {} was allowed", what));
+        }
+    }
+
+    let backing = file_map::share_pages(owner, reader, base, len).map_err(|e| format!("share_pages: {:?}", e))?;
+    if file_map::map_file(owner, backing, 0, len).is_ok() {
+        return Err("a task other than the grantee mapped the backing".to_string());
+    }
+    let addr = file_map::map_file(reader, backing, 0, len).map_err(|e| format!("map_file: {:?}", e))?;
+    // SAFETY: the mapping is at the backing's own address, inside `memory`, which the owner's image keeps alive.
+    let mapped = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    let mut read = alloc::vec![0u8; len];
+    if task_memory::read(owner, base as u64, &mut read) != Some(len) || mapped != read.as_slice() {
+        return Err("the mapped bytes differ from a read of the file".to_string());
+    }
+    if file_map::handle_fault(reader, addr + 8, true) != FaultResolution::WriteToReadOnly {
+        return Err("a write to the mapping wasn't refused".to_string());
+    }
+    if file_map::pin_count(backing) != Some(1) || file_map::unshare_pages(owner, backing) != Err(KernelError::Busy) {
+        return Err(format!("the owner dropped a mapped backing (pins: {:?})", file_map::pin_count(backing)));
+    }
+    file_map::unmap(reader, addr, len).map_err(|e| format!("unmap: {:?}", e))?;
+    if file_map::handle_fault(reader, addr, false) != FaultResolution::NotMapped || file_map::unmap(reader, addr, len).is_ok() {
+        return Err("the mapping outlived SYS_UNMAP".to_string());
+    }
+    if file_map::pin_count(backing) != Some(0) || file_map::unshare_pages(owner, backing).is_err() || file_map::pin_count(backing).is_some() {
+        return Err("the owner couldn't drop an unmapped backing".to_string());
+    }
+
+    let backing = file_map::share_pages_on_demand(owner, reader, base, len).map_err(|e| format!("share_pages_on_demand: {:?}", e))?;
+    let addr = file_map::map_file(reader, backing, 0, len).map_err(|e| format!("map_file: {:?}", e))?;
+    let second = file_map::PAGE_SIZE;
+    if file_map::handle_fault(reader, addr + second + 4, false) != FaultResolution::Waiting || state_of(reader) != Some(TaskState::Blocked) {
+        return Err("a missing page didn't block its reader".to_string());
+    }
+    let requests = file_map::page_requests(owner, 4);
+    if requests != alloc::vec![(backing, second)] || !file_map::page_requests(owner, 4).is_empty() {
+        return Err(format!("the owner was asked for {:x?}, expected page {:#x} of backing {} once", requests, second, backing));
+    }
+    if file_map::supply_page(reader, backing, second).is_ok() || file_map::supply_page(owner, backing, second + 1).is_ok() {
+        return Err("a page was supplied by another task, or at a misaligned offset".to_string());
+    }
+    if file_map::supply_page(owner, backing, second) != Ok(1) || state_of(reader) != Some(TaskState::Ready) {
+        return Err("supplying the page didn't wake its reader".to_string());
+    }
+    if file_map::handle_fault(reader, addr, false) != FaultResolution::Waiting {
+        return Err("the first page was paged in before it was supplied".to_string());
+    }
+    file_map::release_task_mappings(owner);
+    if state_of(reader) != Some(TaskState::Ready) || file_map::handle_fault(reader, addr, false) != FaultResolution::OwnerGone {
+        return Err("a reader waiting on an exited owner wasn't released to be killed".to_string());
+    }
+    file_map::unmap(reader, addr, len).map_err(|e| format!("unmap: {:?}", e))?;
+    Ok(())
+}
+
+/// Checks that killing a task releases everything it held. A synthetic
+/// driver registers an IRQ, receives on a channel, allocates a DMA buffer,
+/// maps memory in a DMA domain, waits on a call to a peer and maps a page
+/// the peer shares. After `kill_task` nothing is attributed to it any more,
+/// its buffer is freed, and the peer's page is unpinned. Not a `detsched`
+/// scenario: the driver never runs.
+pub fn check_task_release() -> Result<(), String> {
+    const DRIVER: TaskId = scenario_task(26);
+    const PEER: TaskId = scenario_task(27);
+    crate::task::create_task(DRIVER, "release-driver", alloc::vec![Capability::IpcManage]);
+    crate::task::create_task(PEER, "release-peer", alloc::vec![Capability::StorageAccess]);
+    let result = check_task_release_of(DRIVER, PEER);
+    if crate::task::task_exists(DRIVER) {
+        remove(DRIVER);
+    }
+    remove(PEER);
+    scheduler::schedule();
+    result
+}
+
+/// What `task_id` holds: IRQs, DMA buffers, DMA mappings, mailboxes, IPC calls and file mappings.
+fn held_by(task_id: TaskId) -> [usize; 6] {
+    [
+        irq::count_task_irqs(task_id),
+        dma::count_task_buffers(task_id),
+        dma::count_task_mappings(task_id),
+        ipc::mailbox::count_task_mailboxes(task_id),
+        ipc::call::pending_calls_of(task_id),
+        file_map::count_task_mappings(task_id),
+    ]
+}
+
+fn check_task_release_of(driver: TaskId, peer: TaskId) -> Result<(), String> {
+    const IRQ: u8 = 15;
+    let (channel, peer_channel) = match (ipc::kernel_allocate(driver), ipc::kernel_allocate(peer)) {
+        (Some(channel), Some(peer_channel)) => (channel, peer_channel),
+        _ => return Err("no channels could be allocated".to_string()),
+    };
+    irq::register_irq_handler(IRQ, channel, driver, true).map_err(|e| format!("register_irq_handler: {:?}", e))?;
+    let buffer = dma::alloc_dma_buffer(64, driver).ok_or_else(|| "no DMA buffer".to_string())?;
+    let domain = dma::create_domain(driver, "release-check", dma::ADDRESS_BITS_ALL);
+    let mut descriptors = alloc::vec![0u8; 32];
+    dma::map(domain, &[DmaSegment::of(&mut descriptors)], DmaDirection::ToDevice).map_err(|e| format!("map: {:?}", e))?;
+
+    // A page the peer shares, as a file server would, which the driver maps.
+    let mut memory = alloc::vec![0u8; 2 * file_map::PAGE_SIZE];
+    let image_start = memory.as_ptr() as usize;
+    let base = image_start.next_multiple_of(file_map::PAGE_SIZE);
+    task_memory::install(peer, LoadedImage {
+        elf_type: ElfType::PositionIndependent,
+        load_base: image_start as u64,
+        entry_point: image_start as u64,
+        segments: alloc::vec![Segment { vaddr: base as u64, memsz: file_map::PAGE_SIZE as u64, readable: true, writable: true, executable: false }],
+        image_start: image_start as u64,
+        memory,
+        relocations: 0,
+    });
+    let backing = file_map::share_pages(peer, driver, base, file_map::PAGE_SIZE).map_err(|e| format!("share_pages: {:?}", e))?;
+    file_map::map_file(driver, backing, 0, file_map::PAGE_SIZE).map_err(|e| format!("map_file: {:?}", e))?;
+
+    // Last, since the call leaves the driver blocked until the peer answers.
+    ipc::call::start(peer_channel, driver, b"release check", 16).map_err(|e| format!("call: {}", e))?;
+
+    let held = held_by(driver);
+    if held.iter().any(|count| *count == 0) {
+        return Err(format!("the driver holds {:?} before it is killed, expected something of each", held));
+    }
+    crate::task::kill_task(driver);
+    let held = held_by(driver);
+    if held != [0; 6] {
+        return Err(format!("a killed driver still holds {:?} (IRQs, DMA buffers, DMA mappings, mailboxes, calls, file mappings)", held));
+    }
+    if dma::get_dma_buffer_ptr(buffer).is_some() {
+        return Err("the killed driver's DMA buffer wasn't freed".to_string());
+    }
+    if file_map::pin_count(backing) != Some(0) {
+        return Err(format!("the peer's page is still pinned by the killed driver: {:?}", file_map::pin_count(backing)));
+    }
+    Ok(())
+}
+
+/// Three instances of an echo service are spawned the way the loader spawns
+/// V-Nodes, echo to each other, and one is stopped by its task ID.
+/// Checks: the instances have distinct task IDs and channels, each finds its
+/// own in its spawn arguments, and the two left keep echoing once the third
+/// is gone and its channel closed.
+pub fn check_vnode_instances() -> Result<(), String> {
+    let mut instances = Vec::new();
+    let mut result = Ok(());
+    for _ in 0..3 {
+        match vnode_loader::spawn_instance("detsched-echo", alloc::vec![Capability::IpcManage]) {
+            Ok(instance) => instances.push(instance),
+            Err(e) => {
+                result = Err(e);
+                break;
+            },
+        }
+    }
+    if result.is_ok() {
+        result = check_vnode_instances_of(&instances);
+    }
+    for instance in &instances {
+        if crate::task::task_exists(instance.task_id) {
+            remove(instance.task_id);
+        }
+    }
+    scheduler::schedule();
+    result
+}
+
+fn check_vnode_instances_of(instances: &[SpawnedVNode]) -> Result<(), String> {
+    for (i, instance) in instances.iter().enumerate() {
+        if instance.channel_id < FIRST_DYNAMIC_CHANNEL {
+            return Err(format!("instance {} got channel {}, below the dynamic range", instance.task_id, instance.channel_id));
+        }
+        if let Some(other) = instances[..i].iter().find(|other| other.task_id == instance.task_id || other.channel_id == instance.channel_id) {
+            return Err(format!("instances {:?} and {:?} share a task ID or channel", other, instance));
+        }
+        // Every instance's first message is its spawn arguments.
+        let mut args = [0u8; 64];
+        if !run_as(instance.task_id) {
+            return Err(format!("instance {} never ran", instance.task_id));
+        }
+        let len = syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, instance.channel_id.raw() as u64, args.as_mut_ptr() as u64, args.len() as u64);
+        if len < 16 || len > args.len() as u64 {
+            return Err(format!("instance {} found no spawn arguments on channel {}", instance.task_id, instance.channel_id));
+        }
+        let task_id = u64::from_le_bytes(args[..8].try_into().unwrap());
+        let channel_id = u32::from_le_bytes(args[8..12].try_into().unwrap());
+        if (task_id, channel_id) != (instance.task_id.raw(), instance.channel_id.raw()) {
+            return Err(format!("instance {:?} was spawned with task {} and channel {}", instance, task_id, channel_id));
+        }
+    }
+    for (from, to) in [(0, 1), (1, 2), (2, 0)] {
+        echo(instances[from], instances[to])?;
+    }
+
+    let stopped = instances[1];
+    crate::task::kill_task(stopped.task_id);
+    if crate::task::task_exists(stopped.task_id) || ipc::mailbox::count_task_mailboxes(stopped.task_id) != 0 {
+        return Err(format!("instance {} still runs or holds its channel after being stopped", stopped.task_id));
+    }
+    for (from, to) in [(0, 2), (2, 0)] {
+        echo(instances[from], instances[to])?;
+    }
+    Ok(())
+}
+
+/// `client` sends a ping to `server`'s channel, naming its own channel for the
+/// answer, and `server` echoes it there.
+fn echo(client: SpawnedVNode, server: SpawnedVNode) -> Result<(), String> {
+    let mut request = client.channel_id.raw().to_le_bytes().to_vec();
+    request.extend_from_slice(b"ping");
+    let mut buf = [0u8; 16];
+    if !run_as(client.task_id) {
+        return Err(format!("instance {} never ran", client.task_id));
+    }
+    syscall_dispatch(SYS_IPC_SEND, server.channel_id.raw() as u64, request.as_ptr() as u64, request.len() as u64);
+
+    if !run_as(server.task_id) {
+        return Err(format!("instance {} never ran", server.task_id));
+    }
+    let len = syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, server.channel_id.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+    if len != request.len() as u64 {
+        return Err(format!("instance {} got no ping from instance {}", server.task_id, client.task_id));
+    }
+    let reply_channel = u32::from_le_bytes(buf[..4].try_into().unwrap());
+    syscall_dispatch(SYS_IPC_SEND, reply_channel as u64, buf[4..].as_ptr() as u64, 4);
+
+    if !run_as(client.task_id) {
+        return Err(format!("instance {} never ran", client.task_id));
+    }
+    let len = syscall_dispatch(SYS_IPC_RECV_NONBLOCKING, client.channel_id.raw() as u64, buf.as_mut_ptr() as u64, buf.len() as u64);
+    if len != 4 || &buf[..4] != b"ping" {
+        return Err(format!("instance {} got no echo from instance {}", client.task_id, server.task_id));
+    }
+    Ok(())
+}
+
+/// Feeds `bytes` to a fresh decoder for `set`, one per tick, and returns the
+/// transitions and the resyncs. `gap_before` is an index whose byte comes a
+/// second late.
+fn decode_scancodes(set: ScancodeSet, bytes: &[u8], gap_before: Option<usize>) -> (Vec<(u16, bool)>, u64) {
+    let mut decoder = ScancodeDecoder::new(set);
+    let mut now = Ticks::from_raw(1);
+    let mut transitions = Vec::new();
+    for (i, byte) in bytes.iter().enumerate() {
+        now = now + if gap_before == Some(i) { Millis::from_raw(1000).to_ticks_ceil() } else { Ticks::from_raw(1) };
+        transitions.extend(decoder.push(*byte, now).map(|t| (t.keycode, t.pressed)));
+    }
+    (transitions, decoder.resyncs())
+}
+
+/// Checks the PS/2 scancode decoder on byte sequences from both sets: make
+/// and break codes, E0-prefixed keys, the fake shifts around Print Screen,
+/// the Pause sequence, and getting back in sync after an error byte or a
+/// prefix whose key never came. Not a `detsched` scenario: it involves no
+/// tasks.
+pub fn check_scancodes() -> Result<(), String> {
+    use common::keys::*;
+    use ScancodeSet::{One, Two};
+    let cases: [(&str, ScancodeSet, &[u8], Option<usize>, &[(u16, bool)], u64); 14] = [
+        ("set 1 make and break", One, &[0x1E, 0x9E], None, &[(KEY_A, true), (KEY_A, false)], 0),
+        ("set 1 E0 keys", One, &[0xE0, 0x48, 0xE0, 0xC8, 0x1D, 0xE0, 0x1D], None, &[(KEY_UP, true), (KEY_UP, false), (KEY_LEFT_CTRL, true), (KEY_RIGHT_CTRL, true)], 0),
+        ("set 1 Print Screen", One, &[0xE0, 0x2A, 0xE0, 0x37, 0xE0, 0xB7, 0xE0, 0xAA], None, &[(KEY_PRINT_SCREEN, true), (KEY_PRINT_SCREEN, false)], 0),
+        ("set 1 Pause", One, &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5, 0x1E], None, &[(KEY_PAUSE, true), (KEY_A, true)], 0),
+        ("set 1 Left Shift release", One, &[0x2A, 0xAA], None, &[(KEY_LEFT_SHIFT, true), (KEY_LEFT_SHIFT, false)], 0),
+        ("set 1 error byte", One, &[0xE0, 0x00, 0x48], None, &[(KEY_KP_8, true)], 1),
+        ("set 1 stale prefix", One, &[0xE0, 0x48], Some(1), &[(KEY_KP_8, true)], 1),
+        ("set 1 command replies", One, &[0xFA, 0xFE, 0xEE, 0x1E], None, &[(KEY_A, true)], 0),
+        ("set 2 make and break", Two, &[0x1C, 0xF0, 0x1C], None, &[(KEY_A, true), (KEY_A, false)], 0),
+        ("set 2 E0 keys", Two, &[0xE0, 0x75, 0xE0, 0xF0, 0x75, 0x14, 0xE0, 0x14], None, &[(KEY_UP, true), (KEY_UP, false), (KEY_LEFT_CTRL, true), (KEY_RIGHT_CTRL, true)], 0),
+        ("set 2 Print Screen", Two, &[0xE0, 0x12, 0xE0, 0x7C, 0xE0, 0xF0, 0x7C, 0xE0, 0xF0, 0x12], None, &[(KEY_PRINT_SCREEN, true), (KEY_PRINT_SCREEN, false)], 0),
+        ("set 2 Pause", Two, &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77, 0x1C], None, &[(KEY_PAUSE, true), (KEY_A, true)], 0),
+        ("set 2 self-test reply", Two, &[0xE0, 0xAA, 0x1C], None, &[(KEY_A, true)], 1),
+        ("set 2 stale release prefix", Two, &[0xF0, 0x1C], Some(1), &[(KEY_A, true)], 1),
+    ];
+    for (name, set, bytes, gap_before, expected, resyncs) in cases {
+        let (transitions, got_resyncs) = decode_scancodes(set, bytes, gap_before);
+        if transitions != expected || got_resyncs != resyncs {
+            return Err(format!("{}: {:02X?} decoded to {:?} with {} resyncs, expected {:?} with {}", name, bytes, transitions, got_resyncs, expected, resyncs));
+        }
+    }
+    Ok(())
+}
+
+fn report_failure(scenario: &mut dyn Scenario, failure: detsched::Failure) {
+    kprintln!("[kernel] detsched: {} FAILED with seed {:#018x}: {}.", scenario.name(), failure.seed, failure.message);
+    kprintln!("[kernel] detsched: Decisions: {}", detsched::format_decisions(&failure.recording.decisions));
+    let trace = &failure.recording.trace;
+    for event in trace.iter().skip(trace.len().saturating_sub(FAILURE_TRACE_EVENTS)) {
+        kprintln!("[kernel] detsched:   {:?}", event);
+    }
+    let (replayed, recording) = detsched::replay(scenario, failure.recording.decisions);
+    match replayed {
+        Err(_) if !recording.diverged => kprintln!("[kernel] detsched: Replay reproduces the failure."),
+        _ => kprintln!("[kernel] detsched: WARNING: Replay did not reproduce the failure."),
+    }
+}
+
+/// Sweeps every scenario over `SEEDS_PER_SCENARIO` seeds. The first seed is
+/// random if the CPU has RDRAND, so each boot explores new interleavings, and
+/// is logged so a failure can be rerun.
+pub fn run_all() {
+    let mut first = [0u8; 8];
+    let base = if rng::fill(&mut first) { u64::from_le_bytes(first) } else { 0 };
+    kprintln!("[kernel] detsched: Sweeping scenarios from seed {:#018x}.", base);
+
+    let mut irq_wakeup = IrqWakeup::new();
+    let mut messages_once = MessagesOnce::new();
+    let mut call_fallback = CallFallback::new();
+    let mut call_server_crash = CallServerCrash::new();
+    let mut syscall_filter = SyscallFilter::new();
+    let mut package_grants = PackageGrants::new();
+    let mut ipc_creds_relay = IpcCredsRelay::new();
+    let scenarios: [&mut dyn Scenario; 7] = [&mut irq_wakeup, &mut messages_once, &mut call_fallback, &mut call_server_crash, &mut syscall_filter, &mut package_grants, &mut ipc_creds_relay];
+    for scenario in scenarios {
+        match detsched::sweep(scenario, base..base.saturating_add(SEEDS_PER_SCENARIO)) {
+            Ok(passed) => kprintln!("[kernel] detsched: {} passed {} seeds.", scenario.name(), passed),
+            Err(failure) => report_failure(scenario, failure),
+        }
+    }
+    match check_clock() {
+        Ok(()) => kprintln!("[kernel] clock: {:?} passed.", timer::clock_source()),
+        Err(message) => kprintln!("[kernel] clock: FAILED: {}.", message),
+    }
+    match check_abi() {
+        Ok(()) => kprintln!("[kernel] abi: ABI version {} checks passed.", ABI_VERSION),
+        Err(message) => kprintln!("[kernel] abi: FAILED: {}.", message),
+    }
+    match check_log_flood() {
+        Ok(()) => kprintln!("[kernel] log: Flood summary checks passed."),
+        Err(message) => kprintln!("[kernel] log: FAILED: {}.", message),
+    }
+    match check_dma() {
+        Ok(()) => kprintln!("[kernel] dma: Domain checks passed."),
+        Err(message) => kprintln!("[kernel] dma: FAILED: {}.", message),
+    }
+    match check_faults() {
+        Ok(()) => kprintln!("[kernel] faults: Fault accounting checks passed."),
+        Err(message) => kprintln!("[kernel] faults: FAILED: {}.", message),
+    }
+    match check_core_dumps() {
+        Ok(()) => kprintln!("[kernel] coredump: Core dump checks passed."),
+        Err(message) => kprintln!("[kernel] coredump: FAILED: {}.", message),
+    }
+    match check_file_maps() {
+        Ok(()) => kprintln!("[kernel] file_map: File mapping checks passed."),
+        Err(message) => kprintln!("[kernel] file_map: FAILED: {}.", message),
+    }
+    match check_task_release() {
+        Ok(()) => kprintln!("[kernel] task: Resource release checks passed."),
+        Err(message) => kprintln!("[kernel] task: FAILED: {}.", message),
+    }
+    match check_vnode_instances() {
+        Ok(()) => kprintln!("[kernel] vnode_loader: Instance checks passed."),
+        Err(message) => kprintln!("[kernel] vnode_loader: FAILED: {}.", message),
+    }
+    match check_scancodes() {
+        Ok(()) => kprintln!("[kernel] ps2_keyboard: Scancode decoder checks passed."),
+        Err(message) => kprintln!("[kernel] ps2_keyboard: FAILED: {}.", message),
+    }
+    bench_ipc_round_trip();
+}
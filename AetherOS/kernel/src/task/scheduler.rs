@@ -1,21 +1,76 @@
 #![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
 
 extern crate alloc;
-use alloc::collections::{BTreeMap, VecDeque};
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
 
+use crate::arch::x86_64::smp;
+use crate::config::MAX_CPUS;
 use crate::kprintln;
-use crate::task::tcb::{TaskControlBlock, TaskState};
+use crate::task::cpu::{self, CpuId, CpuLocal, CpuMask};
+use crate::task::runqueue::RunQueues;
+use crate::task::tcb::{TaskControlBlock, TaskId, TaskState};
+#[cfg(feature = "det-sched")]
+use crate::task::detsched::{self, Event};
 
-/// The run queue holds task IDs of tasks that are ready to be scheduled.
-/// This uses a simple `VecDeque` for a round-robin like behavior.
-static RUN_QUEUE: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+// Lock order: TASKS before RUN_QUEUES.
+
+/// Ready tasks, one round-robin queue per CPU. A single lock covers all
+/// queues for now; stealing needs two of them at once, and TASKS serializes
+/// scheduling anyway.
+static RUN_QUEUES: Mutex<RunQueues> = Mutex::new(RunQueues::new());
 
 /// A map of all active tasks, indexed by their ID.
-static TASKS: Mutex<BTreeMap<u64, TaskControlBlock>> = Mutex::new(BTreeMap::new());
+static TASKS: Mutex<BTreeMap<TaskId, TaskControlBlock>> = Mutex::new(BTreeMap::new());
+
+/// The ID of the task each CPU is executing. Atomics hold the raw ID; `load`
+/// and `store` convert.
+static CURRENT_TASK_ID: CpuLocal<AtomicU64> = CpuLocal::new([const { AtomicU64::new(0) }; MAX_CPUS]); // CPU 0 starts with kernel as task 0
 
-/// The ID of the currently executing task.
-static CURRENT_TASK_ID: Mutex<u64> = Mutex::new(0); // Starts with kernel as task 0
+/// The task each CPU runs when it has nothing else to do. It is never queued.
+/// On CPU 0 this is the kernel task.
+static IDLE_TASK_ID: CpuLocal<AtomicU64> = CpuLocal::new([const { AtomicU64::new(0) }; MAX_CPUS]);
+
+fn load(id: &AtomicU64) -> TaskId {
+    TaskId::from_raw(id.load(Ordering::Acquire))
+}
+
+fn store(id: &AtomicU64, task_id: TaskId) {
+    id.store(task_id.raw(), Ordering::Release);
+}
+
+/// Scheduler passes and direct switches (`handoff`) since boot.
+static PASSES: AtomicU64 = AtomicU64::new(0);
+static HANDOFFS: AtomicU64 = AtomicU64::new(0);
+
+/// Queues a ready task on the CPU its affinity and history suggest, and wakes
+/// that CPU if it is idling elsewhere.
+fn enqueue(queues: &mut RunQueues, task: &TaskControlBlock) {
+    let online = cpu::online_mask();
+    let target = queues.place(task.affinity, task.last_cpu, online).unwrap_or_else(|| {
+        // `set_affinity` refuses masks without an online CPU, so this is a bug.
+        kprintln!("[kernel] scheduler: WARNING: Task {} may not run on any online CPU; queuing it here.", task.id);
+        cpu::current_cpu()
+    });
+    queues.push(target, task.id, task.affinity);
+    // A busy CPU picks the task up at its next schedule; an idle one is halted until interrupted.
+    if target != cpu::current_cpu() && load(CURRENT_TASK_ID.on(target)) == load(IDLE_TASK_ID.on(target)) {
+        smp::send_reschedule_ipi(target);
+    }
+}
+
+/// Starts scheduling on `cpu` with `idle_task` as its idle task. The idle
+/// task is known like any other task but never queued. CPU 0 is set up by
+/// `init`; the `smp` bring-up calls this for the others.
+pub fn init_cpu(cpu: CpuId, idle_task: TaskControlBlock) {
+    let idle_task_id = idle_task.id;
+    TASKS.lock().insert(idle_task_id, idle_task);
+    store(IDLE_TASK_ID.on(cpu), idle_task_id);
+    store(CURRENT_TASK_ID.on(cpu), idle_task_id);
+    cpu::set_online(cpu);
+    kprintln!("[kernel] scheduler: CPU {} online (idle task ID: {}).", cpu, idle_task_id);
+}
 
 /// Initializes the scheduler, setting up necessary data structures.
 pub fn init() {
@@ -24,12 +79,13 @@ pub fn init() {
     // Create a dummy kernel task and add it to the task list.
     // In a real system, the initial kernel thread would be set up differently.
     let kernel_task = TaskControlBlock::new(
-        0,
+        TaskId::KERNEL,
         alloc::string::String::from("kernel"),
         // Grant full capabilities to the kernel task for simulation purposes.
         // This will be refined as specific capabilities are designed.
         alloc::vec![
             crate::caps::Capability::LogWrite,
+            crate::caps::Capability::LogRead,
             crate::caps::Capability::TimeRead,
             crate::caps::Capability::NetworkAccess,
             crate::caps::Capability::IrqRegister(0),
@@ -41,12 +97,7 @@ pub fn init() {
         ],
     );
 
-    {
-        let mut tasks = TASKS.lock();
-        tasks.insert(kernel_task.id, kernel_task.clone());
-    }
-
-    *CURRENT_TASK_ID.lock() = kernel_task.id;
+    init_cpu(0, kernel_task);
 
     kprintln!("[kernel] scheduler: Initialized kernel task (ID: 0).");
 }
@@ -59,46 +110,123 @@ pub fn add_task(task: TaskControlBlock) {
         task.name,
         task_id
     );
-    TASKS.lock().insert(task_id, task);
-    RUN_QUEUE.lock().push_back(task_id);
+    let mut tasks = TASKS.lock();
+    enqueue(&mut RUN_QUEUES.lock(), &task);
+    tasks.insert(task_id, task);
 }
 
 /// Removes a task from the scheduler's management.
-pub fn remove_task(task_id: u64) {
+pub fn remove_task(task_id: TaskId) {
     kprintln!("[kernel] scheduler: Removing task ID {}.", task_id);
     TASKS.lock().remove(&task_id);
-    // Also remove from run queue if it's there (optional for simple stub)
-    RUN_QUEUE.lock().retain(|&id| id != task_id);
+    // Also remove from its run queue if it's there
+    RUN_QUEUES.lock().remove(task_id);
+}
+
+/// Returns true if a task with the given ID is known to the scheduler.
+pub fn task_exists(task_id: TaskId) -> bool {
+    TASKS.lock().contains_key(&task_id)
+}
+
+/// Runs `f` with mutable access to the TCB of `task_id`.
+/// Used for per-task counters that must persist (unlike the clone returned by `get_current_task_tcb`).
+pub fn with_task_mut<R>(task_id: TaskId, f: impl FnOnce(&mut TaskControlBlock) -> R) -> Option<R> {
+    TASKS.lock().get_mut(&task_id).map(f)
+}
+
+/// Calls `f` for every task known to the scheduler, in ID order.
+pub fn for_each_task(mut f: impl FnMut(&TaskControlBlock)) {
+    for task in TASKS.lock().values() {
+        f(task);
+    }
+}
+
+/// Like `for_each_task` with mutable access, for the timer interrupt: gives
+/// up instead of spinning when the interrupted code holds the task table.
+/// Returns false if it gave up.
+pub fn try_for_each_task_mut(mut f: impl FnMut(&mut TaskControlBlock)) -> bool {
+    match TASKS.try_lock() {
+        Some(mut tasks) => {
+            for task in tasks.values_mut() {
+                f(task);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parks a task for a debugger. It keeps its state (Ready or Blocked) but is
+/// not scheduled until `resume_task`. Returns false if the task doesn't exist.
+pub fn suspend_task(task_id: TaskId) -> bool {
+    let mut tasks = TASKS.lock();
+    match tasks.get_mut(&task_id) {
+        Some(task) => {
+            task.suspended = true;
+            RUN_QUEUES.lock().remove(task_id);
+            kprintln!("[kernel] scheduler: Task '{}' (ID: {}) suspended.", task.name, task_id);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Releases a task parked by `suspend_task`. A Ready task goes back on the
+/// run queue; a Blocked one waits for its wakeup as before.
+pub fn resume_task(task_id: TaskId) -> bool {
+    let mut tasks = TASKS.lock();
+    match tasks.get_mut(&task_id) {
+        Some(task) => {
+            if task.suspended && task.state == TaskState::Ready {
+                enqueue(&mut RUN_QUEUES.lock(), task);
+            }
+            task.suspended = false;
+            kprintln!("[kernel] scheduler: Task '{}' (ID: {}) resumed.", task.name, task_id);
+            true
+        }
+        None => false,
+    }
 }
 
 /// Blocks the current task and adds it back to the queue as 'Blocked'.
 /// In a real system, this would involve saving context and performing a context switch.
 pub fn block_current_task() {
-    let current_id = *CURRENT_TASK_ID.lock();
+    mark_blocked(load(CURRENT_TASK_ID.get()));
 
-    {
-        let mut tasks = TASKS.lock();
-        if let Some(task) = tasks.get_mut(&current_id) {
+    // Trigger a schedule immediately if blocking.
+    schedule();
+}
+
+/// Marks a task Blocked without switching away from it; the caller schedules
+/// once it has released its own locks. A wakeup in between makes the task
+/// Ready again and queues it, and `schedule` then leaves it queued. Returns
+/// false if the task doesn't exist.
+pub fn mark_blocked(task_id: TaskId) -> bool {
+    let mut tasks = TASKS.lock();
+    match tasks.get_mut(&task_id) {
+        Some(task) => {
             task.state = TaskState::Blocked;
             kprintln!(
                 "[kernel] scheduler: Task '{}' (ID: {}) blocked.",
                 task.name,
-                current_id
+                task_id
             );
+            true
         }
+        None => false,
     }
-
-    // Trigger a schedule immediately if blocking.
-    schedule();
 }
 
 /// Marks a blocked task as ready and adds it to the run queue.
-pub fn unblock_task(task_id: u64) {
+pub fn unblock_task(task_id: TaskId) {
     let mut tasks = TASKS.lock();
     if let Some(task) = tasks.get_mut(&task_id) {
         if task.state == TaskState::Blocked {
             task.state = TaskState::Ready;
-            RUN_QUEUE.lock().push_back(task_id);
+            // A suspended task stays off the queue; SYS_DEBUG_RESUME queues it.
+            if !task.suspended {
+                enqueue(&mut RUN_QUEUES.lock(), task);
+            }
             kprintln!(
                 "[kernel] scheduler: Task '{}' (ID: {}) unblocked.",
                 task.name,
@@ -108,51 +236,155 @@ pub fn unblock_task(task_id: u64) {
     }
 }
 
-/// Simulates a context switch to the next ready task (round-robin).
+/// Restricts the CPUs a task may run on. A queued task moves to an allowed
+/// CPU right away; a running one when it is next switched out. Returns false
+/// if the task doesn't exist or `affinity` names no online CPU.
+pub fn set_affinity(task_id: TaskId, affinity: CpuMask) -> bool {
+    if affinity & cpu::online_mask() == 0 {
+        return false;
+    }
+    let mut tasks = TASKS.lock();
+    let task = match tasks.get_mut(&task_id) {
+        Some(task) => task,
+        None => return false,
+    };
+    task.affinity = affinity;
+    let mut queues = RUN_QUEUES.lock();
+    if queues.cpu_of(task_id).map_or(false, |queued_on| affinity & cpu::mask_of(queued_on) == 0) {
+        queues.remove(task_id);
+        enqueue(&mut queues, task);
+    }
+    kprintln!("[kernel] scheduler: Task '{}' (ID: {}) affinity set to {:#x}.", task.name, task_id, affinity);
+    true
+}
+
+/// Simulates a context switch on this CPU to the next ready task (round-robin).
+/// With an empty queue the CPU steals work from another one, and failing
+/// that runs its idle task.
 pub fn schedule() {
-    let mut run_queue = RUN_QUEUE.lock();
-    let mut current_id_guard = CURRENT_TASK_ID.lock();
+    PASSES.fetch_add(1, Ordering::Relaxed);
+    let cpu = cpu::current_cpu();
     let mut tasks = TASKS.lock();
+    let mut queues = RUN_QUEUES.lock();
+    let current = CURRENT_TASK_ID.get();
+    let idle_task_id = load(IDLE_TASK_ID.get());
 
-    let old_task_id = *current_id_guard;
+    let old_task_id = load(current);
 
-    // If the old task is still running, set its state to Ready and put it back in the queue.
-    // (Unless it explicitly blocked itself)
+    // If the old task is still running, set its state to Ready and put it back in a queue.
+    // (Unless it explicitly blocked itself, or is the idle task)
     if let Some(old_task) = tasks.get_mut(&old_task_id) {
         if old_task.state == TaskState::Running {
             old_task.state = TaskState::Ready;
-            run_queue.push_back(old_task_id);
+            if old_task_id != idle_task_id {
+                enqueue(&mut queues, old_task);
+            }
         }
     }
 
-    // Get the next task from the run queue.
-    while let Some(next_task_id) = run_queue.pop_front() {
-        if let Some(next_task) = tasks.get_mut(&next_task_id) {
+    // Get the next task from this CPU's run queue, or another CPU's.
+    while let Some(next) = next_queued(&mut queues, cpu) {
+        if let Some(next_task) = tasks.get_mut(&next.task_id) {
+            if next_task.suspended {
+                continue; // Dropped from the queue; resume_task queues it again
+            }
             next_task.state = TaskState::Running;
-            *current_id_guard = next_task_id;
+            next_task.last_cpu = Some(cpu);
+            store(current, next.task_id);
             kprintln!(
-                "[kernel] scheduler: Context switch: from {} to {}.",
+                "[kernel] scheduler: Context switch on CPU {}: from {} to {}.",
+                cpu,
                 old_task_id,
-                next_task_id
+                next.task_id
             );
-            // In a real scheduler, actual CPU context switch would occur here.
+            // In a real scheduler, actual CPU context switch would occur here,
+            // saving the old task's registers into its `regs`.
             return;
         }
 
         kprintln!(
             "[kernel] scheduler: ERROR: Next task ID {} not found in TASKS. Skipping.",
-            next_task_id
+            next.task_id
         );
     }
 
-    // No tasks in run queue. System might idle or panic.
+    // No tasks in any run queue. Run the idle task until something is queued.
+    if let Some(idle_task) = tasks.get_mut(&idle_task_id) {
+        idle_task.state = TaskState::Running;
+        idle_task.last_cpu = Some(cpu);
+    }
+    store(current, idle_task_id);
     kprintln!("[kernel] scheduler: Run queue empty. Idling.");
-    // In a real system, this would ideally lead to an idle loop or halt.
+}
+
+/// Switches this CPU straight to `to`, a Blocked task, without a pass over
+/// the run queues: the IPC call path hands the caller's CPU to the server
+/// and back. The current task keeps the state it has; if it is still
+/// Running it becomes Ready and is queued as in `schedule`. Returns false,
+/// changing nothing, if `to` isn't Blocked, is suspended or may not run here.
+///
+/// There are no time slices or priorities yet, so what the server gets is
+/// the rest of the caller's turn on this CPU.
+pub fn handoff(to: TaskId) -> bool {
+    let cpu = cpu::current_cpu();
+    let mut tasks = TASKS.lock();
+    let eligible = tasks.get(&to).map_or(false, |task| {
+        task.state == TaskState::Blocked && !task.suspended && task.affinity & cpu::mask_of(cpu) != 0
+    });
+    if !eligible {
+        return false;
+    }
+    let current = CURRENT_TASK_ID.get();
+    let from = load(current);
+    if let Some(old_task) = tasks.get_mut(&from) {
+        if old_task.state == TaskState::Running {
+            old_task.state = TaskState::Ready;
+            if from != load(IDLE_TASK_ID.get()) {
+                enqueue(&mut RUN_QUEUES.lock(), old_task);
+            }
+        }
+    }
+    if let Some(next_task) = tasks.get_mut(&to) {
+        next_task.state = TaskState::Running;
+        next_task.last_cpu = Some(cpu);
+    }
+    store(current, to);
+    HANDOFFS.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "det-sched")]
+    detsched::record(Event::Handoff { cpu, from, to });
+    kprintln!("[kernel] scheduler: Handoff on CPU {}: from {} to {}.", cpu, from, to);
+    true
+}
+
+/// How many scheduler passes and handoffs there have been since boot.
+pub fn switch_counts() -> (u64, u64) {
+    (PASSES.load(Ordering::Relaxed), HANDOFFS.load(Ordering::Relaxed))
+}
+
+/// The next task for `cpu`. Normally the front of its queue; under
+/// deterministic scheduling, whichever queued task `detsched` picks.
+fn next_queued(queues: &mut RunQueues, cpu: CpuId) -> Option<crate::task::runqueue::Queued> {
+    #[cfg(feature = "det-sched")]
+    if detsched::active() && queues.len(cpu) > 1 {
+        let of = queues.len(cpu);
+        let next = queues.take(cpu, detsched::pick(of));
+        if let Some(next) = next {
+            detsched::record(Event::Picked { cpu, task: next.task_id, of });
+        }
+        return next;
+    }
+    queues.pop(cpu, cpu::online_mask())
+}
+
+/// The ID of the task running on this CPU. Takes no lock, so the heap can
+/// use it to attribute allocations.
+pub fn current_task_id() -> TaskId {
+    load(CURRENT_TASK_ID.get())
 }
 
 /// Returns a cloned `TaskControlBlock` for the currently executing task.
 pub fn get_current_task_tcb() -> TaskControlBlock {
-    let current_id = *CURRENT_TASK_ID.lock();
+    let current_id = load(CURRENT_TASK_ID.get());
     TASKS.lock().get(&current_id).cloned().unwrap_or_else(|| {
         // Fallback for when current_id might not be in TASKS (e.g., during early boot)
         kprintln!(
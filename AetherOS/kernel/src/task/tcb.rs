@@ -4,7 +4,18 @@ extern crate alloc;
 use alloc::string::String;
 use alloc::vec::Vec;
 
+use common::abi::{syscall_set_contains, IpcCreds, RegisterFrame, SyscallSet, TASK_CPU_NONE, TASK_FLAG_FILTERED, TASK_FLAG_NO_CORE_DUMP, TASK_FLAG_SUSPENDED, TASK_NAME_LEN};
+use common::abi::{TASK_STATE_BLOCKED, TASK_STATE_EXITED, TASK_STATE_READY, TASK_STATE_RUNNING};
+
 use crate::caps::Capability;
+use crate::config::{DEFAULT_LOG_BURST, DEFAULT_LOG_RATE_PER_SEC};
+use crate::task::cpu::{CpuId, CpuMask, ALL_CPUS};
+use crate::task::faults::FaultCounters;
+use crate::task::ratelimit::LogRateLimiter;
+
+// The layout is part of the syscall ABI, so it lives in `common::abi`.
+pub use common::abi::TaskStats;
+pub use common::ids::TaskId;
 
 /// Represents the possible states of a task.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -15,28 +26,173 @@ pub enum TaskState {
     Exited,
 }
 
+/// Per-task resource limits enforced by the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    /// Sustained SYS_LOG rate, in messages per second.
+    pub log_rate_per_sec: u32,
+    /// Number of SYS_LOG messages that may be written in a burst.
+    pub log_burst: u32,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            log_rate_per_sec: DEFAULT_LOG_RATE_PER_SEC,
+            log_burst: DEFAULT_LOG_BURST,
+        }
+    }
+}
+
 /// A simplified Task Control Block (TCB) for a V-Node or kernel thread.
 /// In a real microkernel, this would hold much more state (registers, memory map, capabilities).
+/// Raw bytes of the Aid (AetherOS identity) a task acts on behalf of.
+pub type Identity = [u8; 32];
+
 /// For initial implementation, focus on `id`, `name`, `state`, and `capabilities` as placeholders.
 #[derive(Debug, Clone)] // Derive Clone for easier passing around in mocks/stubs
 pub struct TaskControlBlock {
-    pub id: u64,
+    pub id: TaskId,
     pub name: String,
     pub state: TaskState,
     pub capabilities: Vec<Capability>,
-    // pub stack_pointer: usize, // Conceptual for context switching
-    // pub cpu_state: CpuState, // Conceptual for saving registers
+    pub limits: ResourceLimits,
+    pub log_limiter: LogRateLimiter,
+    /// Identity bound by init at spawn or by the session service at login; None = unauthenticated.
+    pub identity: Option<Identity>,
+    /// Credentials stamped on the last IPC message this task received, for
+    /// SYS_IPC_CREDS and SYS_IPC_LAST_SENDER.
+    pub last_creds: Option<IpcCreds>,
+    /// Reply token of that message if it was an IPC call, for SYS_IPC_REPLY_TOKEN.
+    pub reply_token: Option<u64>,
+    /// Registers saved when the task was last switched out. Only meaningful
+    /// while the task isn't running.
+    pub regs: RegisterFrame,
+    /// Parked by SYS_DEBUG_SUSPEND; the scheduler skips the task until SYS_DEBUG_RESUME.
+    pub suspended: bool,
+    /// CPUs the task may be placed on.
+    pub affinity: CpuMask,
+    /// The CPU the task last ran on; None until it first runs.
+    pub last_cpu: Option<CpuId>,
+    /// Where the task starts executing; 0 for kernel tasks.
+    pub entry_point: u64,
+    /// Initial stack pointer; 0 for kernel tasks.
+    pub stack_top: u64,
+    /// Address of the args page in the task; 0 if it has none.
+    pub args_addr: u64,
+    /// Contents of the args page: the encoded `common::startup::StartupInfo`.
+    /// Until tasks have their own page tables the page lives here, and
+    /// `SYS_GET_STARTUP_INFO` copies it out.
+    pub args: Vec<u8>,
+    /// Syscalls the task may make; None = unfiltered. Installed by the loader
+    /// and only ever narrowed afterwards, by SYS_FILTER_RESTRICT.
+    pub syscall_filter: Option<SyscallSet>,
+    /// Syscalls the filter turned away.
+    pub filter_violations: u64,
+    /// CPU exceptions the task took, by kind.
+    pub faults: FaultCounters,
+    /// Its service holds keys that mustn't end up in a core dump. Set by the
+    /// loader; a dump of the task is the header only.
+    pub no_core_dump: bool,
+}
+
+/// Where a loaded V-Node starts, set by the loader before it first runs.
+#[derive(Debug, Clone)]
+pub struct TaskLaunch {
+    pub entry_point: u64,
+    pub stack_top: u64,
+    pub args_addr: u64,
+    pub args: Vec<u8>,
 }
 
 impl TaskControlBlock {
     /// Creates a new TaskControlBlock with the given parameters.
-    pub fn new(id: u64, name: String, capabilities: Vec<Capability>) -> Self {
+    pub fn new(id: TaskId, name: String, capabilities: Vec<Capability>) -> Self {
         Self {
             id,
             name,
             state: TaskState::Ready, // New tasks start in the Ready state
             capabilities,
+            limits: ResourceLimits::default(),
+            log_limiter: LogRateLimiter::new(DEFAULT_LOG_BURST),
+            identity: None,
+            last_creds: None,
+            reply_token: None,
+            regs: RegisterFrame::default(),
+            suspended: false,
+            affinity: ALL_CPUS,
+            last_cpu: None,
+            entry_point: 0,
+            stack_top: 0,
+            args_addr: 0,
+            args: Vec::new(),
+            syscall_filter: None,
+            filter_violations: 0,
+            faults: FaultCounters::default(),
+            no_core_dump: false,
         }
     }
+
+    /// Whether the task's filter lets it make syscall `number`.
+    pub fn may_call(&self, number: u64) -> bool {
+        self.syscall_filter.map_or(true, |allowed| syscall_set_contains(allowed, number))
+    }
+
+    /// Narrows the filter to the syscalls also in `allowed`. An unfiltered
+    /// task starts from every syscall; nothing is ever added back.
+    pub fn restrict_filter(&mut self, allowed: SyscallSet) {
+        self.syscall_filter = Some(self.syscall_filter.unwrap_or(!0) & allowed);
+    }
+
+    /// Sets up the registers the first context switch restores: the entry
+    /// point, the stack, and the args page address as the first argument.
+    pub fn launch(&mut self, launch: TaskLaunch) {
+        self.regs.rip = launch.entry_point;
+        self.regs.rsp = launch.stack_top;
+        self.regs.rdi = launch.args_addr;
+        self.entry_point = launch.entry_point;
+        self.stack_top = launch.stack_top;
+        self.args_addr = launch.args_addr;
+        self.args = launch.args;
+    }
+
+    /// Returns the task's exported statistics.
+    pub fn stats(&self) -> TaskStats {
+        let mut name = [0u8; TASK_NAME_LEN];
+        let len = self.name.len().min(TASK_NAME_LEN);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        TaskStats {
+            id: self.id.raw(),
+            log_messages: self.log_limiter.allowed_total,
+            log_suppressed: self.log_limiter.suppressed_total,
+            state: match self.state {
+                TaskState::Running => TASK_STATE_RUNNING,
+                TaskState::Ready => TASK_STATE_READY,
+                TaskState::Blocked => TASK_STATE_BLOCKED,
+                TaskState::Exited => TASK_STATE_EXITED,
+            },
+            flags: (if self.suspended { TASK_FLAG_SUSPENDED } else { 0 })
+                | (if self.syscall_filter.is_some() { TASK_FLAG_FILTERED } else { 0 })
+                | (if self.no_core_dump { TASK_FLAG_NO_CORE_DUMP } else { 0 }),
+            last_cpu: self.last_cpu.map_or(TASK_CPU_NONE, |cpu| cpu as u32),
+            reserved: 0,
+            affinity: self.affinity,
+            name,
+            filter_violations: self.filter_violations,
+            page_faults_resolved: self.faults.page_resolved,
+            page_faults_fatal: self.faults.page_fatal,
+            general_protection_faults: self.faults.general_protection,
+            invalid_opcodes: self.faults.invalid_opcode,
+            alignment_checks: self.faults.alignment_check,
+        }
+    }
+
+    /// What a message sent by this task says about its sender right now.
+    pub fn creds(&self) -> IpcCreds {
+        let mut name = [0u8; TASK_NAME_LEN];
+        let len = self.name.len().min(TASK_NAME_LEN);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        IpcCreds { sender: self.id.raw(), name, aid: self.identity }
+    }
 }
 
@@ -2,29 +2,183 @@
 
 #![allow(dead_code)] // Allow dead code for now as not all functions might be used immediately
 
+//! Timer ticks and the high-resolution clock.
+//!
+//! Ticks drive scheduling, timeouts and key repeat. `nanos` is a monotonic
+//! clock for measuring durations: at boot `init` calibrates the TSC against
+//! PIT channel 2, and from then on `nanos` is the TSC converted to
+//! nanoseconds. The TSC is only used if the CPU reports it as invariant, i.e.
+//! it ticks at a constant rate through frequency changes and sleep states.
+//! Otherwise `nanos` falls back to ticks and has their 10 ms resolution.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
 use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::config::LOG_SUPPRESSION_REPORT_INTERVAL_SECS;
 use crate::kprintln;
 
+/// Nominal timer interrupt frequency, and the tick and millisecond types.
+/// Tick-based rates and timeouts are derived from it.
+pub use common::ids::{Millis, Ticks, TICKS_PER_SECOND};
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+pub const NANOS_PER_TICK: u64 = NANOS_PER_SECOND / TICKS_PER_SECOND;
+
 /// Global monotonic tick counter.
 /// Incremented by the timer interrupt handler.
 pub static TICKS: AtomicU64 = AtomicU64::new(0);
 
-/// Initializes the Programmable Interrupt Timer (PIT) or other timer hardware.
-/// For a real system, this would configure the timer frequency.
+const CPUID_EXT_MAX: u32 = 0x8000_0000;
+const CPUID_EXT_POWER: u32 = 0x8000_0007;
+const EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+/// The PIT's input clock.
+pub const PIT_HZ: u64 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary.
+const PIT_CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+/// Port B of the keyboard controller: the channel 2 gate, speaker enable and output.
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT2: u8 = 1 << 5;
+
+/// Length of one calibration window.
+pub const CALIBRATION_WINDOW_MS: u64 = 10;
+/// Windows measured at boot. The shortest wins: an SMI or NMI during a
+/// window can only make it look longer.
+const CALIBRATION_WINDOWS: u32 = 3;
+/// Polls of the PIT output before a window is given up on (no PIT, or stuck).
+const CALIBRATION_POLL_LIMIT: u64 = 50_000_000;
+
+/// Calibrated TSC frequency in Hz. 0 while `nanos` runs on ticks.
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// TSC value `nanos` counts from.
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Ticks at `TSC_BASE`, so the TSC clock picks up where ticks left off.
+static TICKS_AT_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Where `nanos` gets its time from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The invariant TSC, calibrated at boot.
+    Tsc { hz: u64 },
+    /// Timer ticks: the TSC isn't invariant or calibration failed.
+    Ticks,
+}
+
+/// `a * b / c` without intermediate overflow, saturating at `u64::MAX`.
+/// A divisor of 0 also saturates.
+pub fn mul_div(a: u64, b: u64, c: u64) -> u64 {
+    if c == 0 {
+        return u64::MAX;
+    }
+    let q = (a as u128 * b as u128) / c as u128;
+    if q > u64::MAX as u128 { u64::MAX } else { q as u64 }
+}
+
+/// Whether the CPU's TSC runs at a constant rate in every P-, C- and T-state.
+pub fn has_invariant_tsc() -> bool {
+    // SAFETY: CPUID 0x80000000 exists on every x86_64 CPU; 0x80000007 is only
+    // read if it reports that leaf.
+    unsafe { __cpuid(CPUID_EXT_MAX).eax >= CPUID_EXT_POWER && __cpuid(CPUID_EXT_POWER).edx & EDX_INVARIANT_TSC != 0 }
+}
+
+fn rdtsc() -> u64 {
+    // SAFETY: RDTSC has no side effects and is available on every x86_64 CPU.
+    unsafe { _rdtsc() }
+}
+
+/// TSC cycles that elapse while PIT channel 2 counts down `window_ms`, or
+/// `None` if its output never goes high. Leaves the speaker off.
+pub fn measure_tsc_window(window_ms: u64) -> Option<u64> {
+    let latch = (PIT_HZ * window_ms / 1000).min(u16::MAX as u64) as u16;
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel2 = Port::<u8>::new(PIT_CHANNEL2);
+    // SAFETY: Channel 2 and port B only drive the PC speaker, which stays disabled.
+    unsafe {
+        let saved = port_b.read();
+        port_b.write((saved & !PORT_B_SPEAKER) | PORT_B_GATE);
+        command.write(PIT_CHANNEL2_ONESHOT);
+        channel2.write(latch as u8);
+        channel2.write((latch >> 8) as u8); // Starts the count
+        let start = rdtsc();
+        let mut polls = 0;
+        while port_b.read() & PORT_B_OUT2 == 0 {
+            polls += 1;
+            if polls == CALIBRATION_POLL_LIMIT {
+                port_b.write(saved & !PORT_B_SPEAKER);
+                return None;
+            }
+        }
+        let end = rdtsc();
+        port_b.write(saved & !PORT_B_SPEAKER);
+        Some(end.wrapping_sub(start))
+    }
+}
+
+/// TSC frequency from the shortest of `CALIBRATION_WINDOWS` PIT windows.
+fn calibrate_tsc() -> Option<u64> {
+    let cycles = (0..CALIBRATION_WINDOWS).filter_map(|_| measure_tsc_window(CALIBRATION_WINDOW_MS)).min()?;
+    let hz = mul_div(cycles, 1000, CALIBRATION_WINDOW_MS);
+    (hz != 0).then_some(hz)
+}
+
+/// Initializes the Programmable Interrupt Timer (PIT) or other timer hardware
+/// and picks the clock source for `nanos`.
 pub fn init() {
     // In a real kernel, this would configure the PIT or other timer hardware
     // to generate interrupts at a regular interval (e.g., 100 Hz).
     kprintln!("[kernel] timer: Initialized (conceptual).");
+    if !has_invariant_tsc() {
+        kprintln!("[kernel] timer: The TSC isn't invariant; nanosecond time has tick resolution.");
+        return;
+    }
+    match calibrate_tsc() {
+        Some(hz) => {
+            TICKS_AT_BASE.store(TICKS.load(Ordering::SeqCst), Ordering::SeqCst);
+            TSC_BASE.store(rdtsc(), Ordering::SeqCst);
+            TSC_HZ.store(hz, Ordering::SeqCst);
+            kprintln!("[kernel] timer: TSC runs at {}.{:03} MHz.", hz / 1_000_000, hz / 1000 % 1000);
+        }
+        None => kprintln!("[kernel] timer: The PIT didn't count down; nanosecond time has tick resolution."),
+    }
 }
 
 /// Called by the timer interrupt handler.
-/// Increments the global tick counter.
+/// Increments the global tick counter and runs the work due on this tick.
 pub fn tick() {
-    TICKS.fetch_add(1, Ordering::SeqCst);
+    let now = Ticks::from_raw(TICKS.fetch_add(1, Ordering::SeqCst) + 1);
+    crate::drivers::ps2_keyboard::on_tick(now); // Key repeat
+    // Summaries fall due on whole report intervals, so checking at their starts is enough.
+    if now.raw() % Ticks::from_secs(LOG_SUPPRESSION_REPORT_INTERVAL_SECS).raw() == 0 {
+        crate::task::collect_suppressed_logs(now); // Printed by the idle loop
+    }
     // kprintln!("[kernel] timer: Tick! {}", TICKS.load(Ordering::SeqCst)); // Uncomment for noisy debug
 }
 
 /// Returns the current number of ticks since boot.
-pub fn get_current_ticks() -> u64 {
-    TICKS.load(Ordering::SeqCst)
+pub fn get_current_ticks() -> Ticks {
+    Ticks::from_raw(TICKS.load(Ordering::SeqCst))
+}
+
+pub fn clock_source() -> ClockSource {
+    match TSC_HZ.load(Ordering::SeqCst) {
+        0 => ClockSource::Ticks,
+        hz => ClockSource::Tsc { hz },
+    }
+}
+
+/// Nanoseconds since boot. Monotonic; resolution is one TSC cycle, or one
+/// tick without an invariant TSC.
+pub fn nanos() -> u64 {
+    match clock_source() {
+        ClockSource::Tsc { hz } => {
+            let cycles = rdtsc().saturating_sub(TSC_BASE.load(Ordering::SeqCst));
+            TICKS_AT_BASE.load(Ordering::SeqCst).saturating_mul(NANOS_PER_TICK).saturating_add(mul_div(cycles, NANOS_PER_SECOND, hz))
+        }
+        ClockSource::Ticks => TICKS.load(Ordering::SeqCst).saturating_mul(NANOS_PER_TICK),
+    }
 }
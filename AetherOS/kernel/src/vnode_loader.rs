@@ -9,6 +9,60 @@ use crate::kprintln;
 use crate::elf;
 use crate::task;
 use crate::caps::Capability;
+use crate::ipc;
+use crate::memory::task_memory;
+use crate::task::tcb::{TaskId, TaskLaunch};
+use common::abi::{SyscallSet, ALL_SYSCALLS, STARTUP_INFO_MAX_LEN, SYSCALL_COUNT};
+use common::startup::{StartupInfo, SELF_CHANNEL};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Task IDs handed to V-Node instances. Decoupled from the binary name so the
+/// same binary can run as several independent instances.
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1000);
+
+/// Load bases for position-independent V-Nodes are handed out from this range
+/// by bump allocation, so no two instances share a base.
+// TODO: Randomize the base within the range once SYS_RANDOM exists.
+const PIE_REGION_START: u64 = 0x0000_4000_0000_0000;
+const PIE_REGION_END: u64 = 0x0000_6000_0000_0000;
+/// Bases are aligned to 2 MiB so the image can later be mapped with large pages.
+const PIE_BASE_ALIGN: u64 = 0x20_0000;
+static NEXT_PIE_BASE: AtomicU64 = AtomicU64::new(PIE_REGION_START);
+
+/// Every instance gets a stack of this size with its args page right above it,
+/// reserved from the PIE region like an image.
+const STACK_SIZE: u64 = 64 * 1024;
+const ARGS_PAGE_SIZE: u64 = STARTUP_INFO_MAX_LEN as u64;
+
+/// Reserves `size` bytes of the PIE region and returns the base of the reservation.
+fn allocate_load_base(size: u64) -> Result<u64, String> {
+    let reserved = size.checked_add(PIE_BASE_ALIGN - 1).map(|s| s & !(PIE_BASE_ALIGN - 1))
+        .ok_or_else(|| format!("Image size {:#x} is too large.", size))?;
+    let base = NEXT_PIE_BASE.fetch_add(reserved, Ordering::SeqCst);
+    if base.checked_add(reserved).map_or(true, |end| end > PIE_REGION_END) {
+        return Err(format!("PIE region exhausted (need {:#x} bytes at {:#x}).", reserved, base));
+    }
+    Ok(base)
+}
+
+/// Identity of a freshly spawned V-Node instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnedVNode {
+    pub task_id: TaskId,
+    /// Private channel allocated for this instance; its spawn arguments are queued on it.
+    pub channel_id: ipc::ChannelId,
+}
+
+/// Encodes the spawn-arguments message queued as the first message on an instance's channel:
+/// `[task_id: u64 LE][channel_id: u32 LE][name_len: u32 LE][name bytes]`.
+fn encode_spawn_args(task_id: TaskId, channel_id: ipc::ChannelId, vnode_name: &str) -> Vec<u8> {
+    let mut args = Vec::with_capacity(16 + vnode_name.len());
+    args.extend_from_slice(&task_id.raw().to_le_bytes());
+    args.extend_from_slice(&channel_id.raw().to_le_bytes());
+    args.extend_from_slice(&(vnode_name.len() as u32).to_le_bytes());
+    args.extend_from_slice(vnode_name.as_bytes());
+    args
+}
 
 /// Initializes the V-Node loader.
 pub fn init() {
@@ -24,32 +78,103 @@ pub fn init() {
 /// - Copying ELF segments into the V-Node's memory.
 /// - Setting up V-Node specific capabilities based on its manifest.
 /// - Creating a new CPU context (task) for the V-Node.
-pub fn load_vnode(vnode_name: &str, capabilities: Vec<Capability>) -> Result<(), String> {
+///
+/// Every call creates a new instance with its own task ID and channel, even if
+/// the same binary is already running. `startup` is what init assigned to the
+/// instance; the loader adds the instance's own channel as `SELF_CHANNEL` and
+/// writes it to the args page, where `SYS_GET_STARTUP_INFO` reads it.
+///
+/// `syscall_filter` is the allowlist from the service's configuration, if it
+/// has one. It is in place before the task first runs. With `no_core_dump`
+/// the task's core dumps are the header only (see `task::coredump`).
+pub fn load_vnode(vnode_name: &str, capabilities: Vec<Capability>, syscall_filter: Option<SyscallSet>, no_core_dump: bool, mut startup: StartupInfo) -> Result<SpawnedVNode, String> {
     kprintln!("[kernel] vnode_loader: Loading V-Node: {}...", vnode_name);
 
     // 1. Construct path for the V-Node's binary.
     let vnode_path = format!("/initrd/{}.bin", vnode_name);
     kprintln!("[kernel] vnode_loader: Attempting to load from path: {}.", vnode_path);
 
-    // 2. Use ElfLoader to simulate loading the binary.
-    let elf_header = match elf::ElfLoader::load_elf(&vnode_path) {
-        Ok(header) => header,
+    // 2. Pick a load base. PIEs get their own slot of the PIE region; fixed-address
+    //    binaries load at their link address and may collide with other V-Nodes.
+    let (elf_header, image_size) = elf::ElfLoader::inspect(&vnode_path).map_err(|e| {
+        kprintln!("[kernel] vnode_loader: Failed to load ELF for {}: {}.", vnode_name, e);
+        format!("Failed to load V-Node ELF: {}.", e)
+    })?;
+    let base = match elf_header.elf_type {
+        elf::ElfType::PositionIndependent => allocate_load_base(image_size)?,
+        elf::ElfType::Executable => {
+            kprintln!("[kernel] vnode_loader: {} is not position-independent; loading at its link address. Rebuild it as a PIE.", vnode_name);
+            0
+        }
+    };
+
+    // 3. Lay out the segments and apply relocations.
+    let image = match elf::ElfLoader::load_elf(&vnode_path, base) {
+        Ok(image) => image,
         Err(e) => {
             kprintln!("[kernel] vnode_loader: Failed to load ELF for {}: {}.", vnode_name, e);
             return Err(format!("Failed to load V-Node ELF: {}.", e));
         }
     };
-    kprintln!("[kernel] vnode_loader: ELF loaded for {}. Entry point: {:#x}.", vnode_name, elf_header.entry_point);
+    kprintln!("[kernel] vnode_loader: ELF loaded for {}. Base: {:#x}, entry point: {:#x}.", vnode_name, image.load_base, image.entry_point);
 
-    // 3. Create a new task (V-Node) for the loaded ELF.
-    // Assign a dummy task ID for now. In a real system, task IDs would be managed centrally.
-    let dummy_task_id = 1000 + vnode_name.as_bytes()[0] as u64; // Simple dummy ID
-    task::create_task(dummy_task_id, vnode_name, capabilities);
-    kprintln!("[kernel] vnode_loader: Task created for V-Node {} (ID: {}).", vnode_name, dummy_task_id);
+    // 4. Create a new task (V-Node) for the loaded ELF, with its own task ID and channel.
+    let SpawnedVNode { task_id, channel_id } = spawn_instance(vnode_name, capabilities)?;
+    if let Some(allowed) = syscall_filter {
+        task::restrict_syscall_filter(task_id, allowed);
+        kprintln!("[kernel] vnode_loader: V-Node {} may make {} of {} syscalls.", vnode_name, (allowed & ALL_SYSCALLS).count_ones(), SYSCALL_COUNT);
+    }
+    if no_core_dump {
+        task::set_no_core_dump(task_id);
+    }
 
-    // TODO: In a real system, the V-Node's entry point would be set up as the task's starting point.
-    // For this conceptual stub, we just simulate the loading process.
+    // 5. Write the startup info and reserve the stack and args page.
+    startup.assigned_channels.insert(SELF_CHANNEL.to_string(), channel_id.raw());
+    let args = match startup.to_bytes() {
+        Some(args) => args,
+        None => {
+            task::kill_task(task_id);
+            return Err(format!("Startup info for V-Node {} is larger than {} bytes.", vnode_name, STARTUP_INFO_MAX_LEN));
+        }
+    };
+    let stack_base = match allocate_load_base(STACK_SIZE + ARGS_PAGE_SIZE) {
+        Ok(base) => base,
+        Err(e) => {
+            task::kill_task(task_id);
+            return Err(format!("No room for the stack of V-Node {}: {}", vnode_name, e));
+        }
+    };
+    let stack_top = stack_base + STACK_SIZE;
+
+    // 6. Record the image as the task's memory and start it at the entry point,
+    //    with the args page address as its first argument.
+    // TODO: Map `image`, the stack and the args page into a per-task address space once tasks have their own page tables.
+    task::set_launch(task_id, TaskLaunch { entry_point: image.entry_point, stack_top, args_addr: stack_top, args });
+    task_memory::install(task_id, image);
 
-    kprintln!("[kernel] vnode_loader: V-Node {} loaded successfully.", vnode_name);
-    Ok(())
+    kprintln!("[kernel] vnode_loader: V-Node {} loaded successfully (ID: {}, channel: {}).", vnode_name, task_id, channel_id);
+    Ok(SpawnedVNode { task_id, channel_id })
+}
+
+/// Creates the task for a new instance of `vnode_name` with a fresh task ID,
+/// allocates its channel and queues its spawn arguments there. The task is
+/// killed again if either step fails.
+pub(crate) fn spawn_instance(vnode_name: &str, capabilities: Vec<Capability>) -> Result<SpawnedVNode, String> {
+    let task_id = TaskId::from_raw(NEXT_TASK_ID.fetch_add(1, Ordering::SeqCst));
+    task::create_task(task_id, vnode_name, capabilities);
+    kprintln!("[kernel] vnode_loader: Task created for V-Node {} (ID: {}).", vnode_name, task_id);
+
+    let channel_id = match ipc::kernel_allocate(task_id) {
+        Some(id) => id,
+        None => {
+            task::kill_task(task_id);
+            return Err(format!("No free IPC channel for V-Node {}.", vnode_name));
+        }
+    };
+    let args = encode_spawn_args(task_id, channel_id, vnode_name);
+    if ipc::kernel_send(channel_id, TaskId::KERNEL, &args).is_err() {
+        task::kill_task(task_id);
+        return Err(format!("Failed to deliver spawn arguments to V-Node {}.", vnode_name));
+    }
+    Ok(SpawnedVNode { task_id, channel_id })
 }
@@ -14,8 +14,13 @@ use serde::{Deserialize, Serialize};
 pub enum DnsRequest {
     /// Request to resolve a hostname to an IPv4 address.
     ResolveHostname { hostname: String },
-    /// Request to reverse resolve an IPv4 address to a hostname.
+    /// Request every IPv4 address of a hostname, most preferred first.
+    ResolveAll { hostname: String },
+    // Request to reverse resolve an IPv4 address to a hostname.
     // ReverseResolveIp { ip_address: [u8; 4] },
+    /// Drop every cached name, so the next lookup of each asks again. Only
+    /// the system identity may.
+    FlushCache,
 }
 
 /// Represents a DNS response from the DNS Resolver V-Node to a client V-Node.
@@ -23,10 +28,22 @@ pub enum DnsRequest {
 pub enum DnsResponse {
     /// Successful resolution of a hostname to an IPv4 address.
     ResolvedHostname { hostname: String, ip_address: [u8; 4] },
-    /// Successful reverse resolution of an IP address to a hostname.
+    /// Answers `ResolveAll`. Never empty; a name without addresses is `NotFound`.
+    ResolvedAddresses { hostname: String, addresses: Vec<[u8; 4]> },
+    // Successful reverse resolution of an IP address to a hostname.
     // ResolvedIp { ip_address: [u8; 4], hostname: String },
     /// Indicates that the hostname or IP could not be resolved.
     NotFound { query: String },
     /// Indicates an error occurred during the resolution process.
     Error { message: String },
+    /// Answers `FlushCache` with how many names were dropped.
+    CacheFlushed { entries: u32 },
+}
+
+/// Payload of the `dns.mdns.renamed` event, published when another host on
+/// the LAN claimed our mDNS hostname and we moved to a suffixed one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MdnsRenamed {
+    pub from: String, // e.g. "aether-1a2b3c.local"
+    pub to: String, // e.g. "aether-1a2b3c-2.local"
 }
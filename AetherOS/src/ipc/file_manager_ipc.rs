@@ -12,7 +12,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::ipc::vfs_ipc::VfsMetadata; // Reusing VfsMetadata
 
-/// Represents requests from client V-Nodes to the File Manager V-Node.
+/// Represents requests from client V-Nodes to the File Manager V-Node. Both
+/// directions travel in a `common::ipc::envelope::Envelope`, so a client can
+/// set a deadline and cancel a long `Copy`.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum FileManagerRequest {
     /// Browse the contents of a directory.
@@ -21,10 +23,57 @@ pub enum FileManagerRequest {
     Copy { source: String, destination: String },
     /// Move a file or directory.
     Move { source: String, destination: String },
-    /// Delete a file or directory.
-    Delete { path: String },
+    /// Delete a file or directory. Unless `permanent`, it is moved to the
+    /// caller's trash and can be restored.
+    Delete { path: String, permanent: bool },
     /// Create a new directory.
     CreateDirectory { path: String },
+    /// List the caller's trash, oldest first.
+    ListTrash,
+    /// Move a trashed entry back to where it was deleted from. Fails if
+    /// something else now exists there.
+    Restore { trash_id: TrashId },
+    /// Permanently delete trashed entries: all of them, or those deleted more
+    /// than `older_than_days` days ago.
+    EmptyTrash { older_than_days: Option<u32> },
+    /// Search the contents of the files below `root` for lines containing
+    /// `pattern`. Only files whose name matches one of `include_globs` (`*`
+    /// and `?`) are read, or every file if there are none. Files that look
+    /// binary are skipped. Matches come back in `ContentMatches` batches; at
+    /// most `max_matches` of them, or `DEFAULT_MAX_MATCHES` if 0.
+    SearchContent { root: String, pattern: String, case_insensitive: bool, max_matches: u32, include_globs: Vec<String> },
+}
+
+/// `max_matches` of a `SearchContent` that gives 0.
+pub const DEFAULT_MAX_MATCHES: u32 = 100;
+
+/// A line that matched a `SearchContent`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentMatch {
+    pub path: String,
+    /// 1-based.
+    pub line_number: u64,
+    /// The part of the line around the match, at most `MAX_EXCERPT_LEN` bytes.
+    pub excerpt: String,
+}
+
+/// Longest `ContentMatch::excerpt`, so a batch of matches fits in one message.
+pub const MAX_EXCERPT_LEN: usize = 160;
+
+/// Identifies an entry in an identity's trash. Not reused within that trash.
+pub type TrashId = u64;
+
+/// A file or directory in the trash, as recorded in the trash index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: TrashId,
+    /// Name inside the trash directory: `<deleted_at>-<original name>`.
+    pub name: String,
+    pub original_path: String,
+    /// Unix timestamp of the deletion.
+    pub deleted_at: u64,
+    /// Bytes, including everything below a directory.
+    pub size: u64,
 }
 
 /// Represents responses from the File Manager V-Node to client V-Nodes.
@@ -36,4 +85,14 @@ pub enum FileManagerResponse {
     Error(String),
     /// Returns a list of directory entries (name, metadata).
     DirectoryEntries(BTreeMap<String, VfsMetadata>),
+    /// Answers `ListTrash`.
+    TrashEntries(Vec<TrashEntry>),
+    /// The request was cancelled or its deadline passed. Whatever it had done
+    /// was undone; a cancelled copy leaves no destination file behind.
+    Cancelled,
+    /// A batch of `SearchContent` matches. More batches follow until `done`;
+    /// `truncated` is set on the last one if the search stopped at `max_matches`.
+    ContentMatches { matches: Vec<ContentMatch>, done: bool, truncated: bool },
+    /// A finished `Copy`, with the number of bytes copied.
+    Copied { message: String, bytes: u64 },
 }
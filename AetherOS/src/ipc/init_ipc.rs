@@ -6,20 +6,65 @@
 extern crate alloc;
 use alloc::vec::Vec;
 use alloc::string::String;
+use core::fmt::Write;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ids::Ticks;
+
+/// Selects the running instances a Stop/Status/Restart request applies to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServiceTarget {
+    /// A single instance, by the id returned from `ServiceStart`.
+    Instance(u64),
+    /// Every running instance of the named service.
+    AllInstances(String),
+}
+
+/// Describes one running instance of a service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub instance_id: u64,
+    pub service_name: String,
+    pub label: Option<String>,
+    pub channel: u32, // Channel allocated to this instance at spawn
+}
+
 /// Represents requests from client V-Nodes to the init-service V-Node.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum InitRequest {
-    /// Start a V-Node by its name.
-    ServiceStart { service_name: String },
-    /// Get the status of a V-Node.
-    ServiceStatus { service_name: String },
-    /// Restart a V-Node.
-    ServiceRestart { service_name: String },
-    /// Stop a V-Node.
-    ServiceStop { service_name: String },
+    /// Start a new instance of a V-Node. Each call starts another instance,
+    /// even if the service is already running.
+    ServiceStart { service_name: String, instance_label: Option<String> },
+    /// Get the status of one or all instances of a V-Node.
+    ServiceStatus { target: ServiceTarget },
+    /// Restart one or all instances of a V-Node.
+    ServiceRestart { target: ServiceTarget },
+    /// Stop one or all instances of a V-Node.
+    ServiceStop { target: ServiceTarget },
+    /// List the names of all configured services.
+    ListServices,
+    /// Get the boot timeline so far, with the total boot time and the slowest services.
+    BootReport,
+    /// Start the members of an application group that aren't running, in the group's order.
+    GroupStart { name: String },
+    /// Stop every member of a group, in reverse order.
+    GroupStop { name: String },
+    /// Get a group's members and their combined state.
+    GroupStatus { name: String },
+    /// List every configured group with its status.
+    ListGroups,
+    /// Like `ListServices`, with the group each service belongs to.
+    ListServicesWithGroups,
+    /// Stop every service, sync the VFS and power off or reboot. Init
+    /// answers before it starts; once the services are stopped it doesn't
+    /// take requests any more. With `force`, a service gets a moment to stop
+    /// instead of its configured timeout.
+    SystemShutdown { reboot: bool, force: bool },
+    /// List the crashes init has seen core dumps of, newest first, for one
+    /// service or all. A crashed instance is gone from `ServiceStatus`; this
+    /// is what's left of it.
+    Crashes { service_name: Option<String> },
 }
 
 /// Represents responses from the init-service V-Node to client V-Nodes.
@@ -27,8 +72,230 @@ pub enum InitRequest {
 pub enum InitResponse {
     /// Indicates successful operation.
     Success(String), // Success message
-    /// Returns the status of a V-Node.
-    Status { service_name: String, is_running: bool, pid: Option<u64> },
+    /// A new instance was started.
+    InstanceStarted { service_name: String, instance_id: u64, channel: u32 },
+    /// Returns the running instances matched by a `ServiceStatus` target (empty if none).
+    Instances(Vec<InstanceInfo>),
+    /// Returns the names of all configured services.
+    ServiceList(Vec<String>),
+    /// Answers `BootReport`.
+    BootReport(BootReport),
     /// Indicates an error occurred.
     Error(String), // Error message
+    /// Answers `GroupStart` and `GroupStatus`.
+    GroupStatus(GroupInfo),
+    /// Answers `ListGroups`, in name order.
+    Groups(Vec<GroupInfo>),
+    /// Answers `ListServicesWithGroups`: each configured service and its group, if any.
+    ServiceGroupList(Vec<(String, Option<String>)>),
+    /// Answers `Crashes`.
+    Crashes(Vec<CrashReport>),
+}
+
+/// What a group does when one of its members exits without being asked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupCrashPolicy {
+    /// Start the member again; the others keep running.
+    RestartMember,
+    /// Stop the other members and start the whole group again.
+    RestartGroup,
+    /// Stop the other members.
+    StopGroup,
+}
+
+/// Whether a member's crash is acted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemberRestart {
+    /// The group's `GroupCrashPolicy` applies.
+    OnCrash,
+    /// The member stays stopped and the group runs degraded.
+    Never,
+}
+
+/// A group's members combined: all running, some, or none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupState {
+    Running,
+    Degraded,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMemberInfo {
+    pub service_name: String,
+    pub restart: MemberRestart,
+    pub instance_id: Option<u64>, // None while the member isn't running
+}
+
+/// One application group, as returned by `GroupStatus` and `ListGroups`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupInfo {
+    pub name: String,
+    pub state: GroupState,
+    pub crash_policy: GroupCrashPolicy,
+    pub members: Vec<GroupMemberInfo>, // In start order
+}
+
+/// Event bus topic init publishes a `BootProgress` on at every step of the boot.
+pub const BOOT_PROGRESS_TOPIC: &str = "boot.progress";
+
+/// Event bus topics init publishes a `ServiceStateChanged` on when an instance
+/// is started, stopped or restarted on request. Boot steps only go to `BOOT_PROGRESS_TOPIC`.
+pub const SERVICE_STARTED_TOPIC: &str = "service.started";
+pub const SERVICE_STOPPED_TOPIC: &str = "service.stopped";
+pub const SERVICE_RESTARTED_TOPIC: &str = "service.restarted";
+
+/// Event bus topics init publishes a `GroupStateChanged` on. A member's crash
+/// is `group.member_crashed`, followed by whatever the group's policy did about it.
+pub const GROUP_STARTED_TOPIC: &str = "group.started";
+pub const GROUP_STOPPED_TOPIC: &str = "group.stopped";
+pub const GROUP_RESTARTED_TOPIC: &str = "group.restarted";
+pub const GROUP_MEMBER_CRASHED_TOPIC: &str = "group.member_crashed";
+
+/// Event bus topic init publishes a `TaskFaultStorm` on when the kernel
+/// reports a task faulting faster than the `tasks.fault_storm_per_sec` setting.
+pub const TASK_FAULTSTORM_TOPIC: &str = "task.faultstorm";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskFaultStorm {
+    pub task_id: u64,
+    pub task_name: String,
+    /// The service the task is an instance of; None if init didn't start it.
+    pub service_name: Option<String>,
+    /// Faults the task took within the second that crossed the threshold.
+    pub faults: u32,
+    /// The threshold at the time, in faults per second.
+    pub threshold: u32,
+}
+
+/// Event bus topic init publishes a `CrashReport` on for every core dump it
+/// takes from the kernel: a task killed for a fault, or one a debugger dumped.
+pub const SERVICE_CRASHED_TOPIC: &str = "service.crashed";
+
+/// How many crashes init remembers for `Crashes`.
+pub const MAX_CRASH_REPORTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub task_id: u64,
+    pub task_name: String,
+    /// The service the task is an instance of; None if init didn't start it.
+    pub service_name: Option<String>,
+    /// `common::coredump::reason_name` of the dump's reason, e.g. "page fault".
+    pub reason: String,
+    /// Where the task was: the faulting instruction, or the saved one for a requested dump.
+    pub rip: u64,
+    /// The address a page fault was on; 0 otherwise.
+    pub fault_addr: u64,
+    pub tick: u64,
+    /// The file under `/data/crash` the dump was written to; None if the
+    /// service is loaded with the no-dump flag or the write failed.
+    pub core_dump: Option<String>,
+    /// Some contents were left out to stay under `tasks.core_dump_max_kb`.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupStateChanged {
+    pub group: String,
+    /// The group's state after the change.
+    pub state: GroupState,
+    /// The member that crashed or was restarted on its own; None for the whole group.
+    pub member: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStateChanged {
+    pub service_name: String,
+    pub instance_id: u64,
+    /// For a restart, the instance that was replaced.
+    pub previous_instance_id: Option<u64>,
+}
+
+/// How many services `BootReport::slowest` lists.
+pub const BOOT_REPORT_SLOWEST: usize = 5;
+
+/// Where one service stands in the boot sequence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootState {
+    Starting,
+    Started,
+    /// The service didn't start, or one it depends on didn't; the reason says which.
+    Failed(String),
+    /// The service was stopped at shutdown; `forced` if it didn't answer in time.
+    Stopped { forced: bool },
+}
+
+/// One step of the boot: the payload of `boot.progress` and an entry of the timeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootProgress {
+    pub service: String,
+    pub index: u32, // 1-based position in the boot order; in the stop order for `Stopped`
+    pub total: u32, // Services in the boot order
+    pub state: BootState,
+    pub tick: u64, // Timer tick (10 ms) of the step
+}
+
+/// How long a service took from `Starting` to `Started`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceBootTime {
+    pub service: String,
+    pub ticks: Ticks,
+}
+
+/// The boot so far, as returned by `InitRequest::BootReport`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootReport {
+    pub timeline: Vec<BootProgress>,
+    pub total: u32, // Services in the boot order
+    pub finished: u32, // Services that have started or failed
+    pub total_ticks: Ticks, // From the first step to the last
+    pub slowest: Vec<ServiceBootTime>, // Slowest first, at most BOOT_REPORT_SLOWEST
+    pub failed: Vec<(String, String)>, // Service and reason, in boot order
+}
+
+impl BootReport {
+    /// Every service has started or failed.
+    pub fn done(&self) -> bool {
+        self.finished == self.total
+    }
+
+    /// The boot is over and nothing failed.
+    pub fn passed(&self) -> bool {
+        self.done() && self.failed.is_empty()
+    }
+
+    /// A few lines for the serial diagnostics path. The first line starts
+    /// with `boot: passed`, `boot: failed` or `boot: in progress`; after a
+    /// shutdown the last says how it went.
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        let secs = |ticks: Ticks| alloc::format!("{}.{:02} s", ticks.to_secs(), ticks.to_millis().raw() % 1000 / 10);
+        let _ = match (self.done(), self.failed.len()) {
+            (true, 0) => writeln!(out, "boot: passed in {} ({} services)", secs(self.total_ticks), self.total),
+            (true, failed) => writeln!(out, "boot: failed in {} ({} services, {} failed)", secs(self.total_ticks), self.total, failed),
+            (false, _) => writeln!(out, "boot: in progress, {} of {} services after {}", self.finished, self.total, secs(self.total_ticks)),
+        };
+        if !self.slowest.is_empty() {
+            let slowest: Vec<String> = self.slowest.iter().map(|time| alloc::format!("{} {}", time.service, secs(time.ticks))).collect();
+            let _ = writeln!(out, "slowest: {}", slowest.join(", "));
+        }
+        for (service, reason) in &self.failed {
+            let _ = writeln!(out, "failed: {}: {}", service, reason);
+        }
+        let stopped: Vec<&BootProgress> = self.timeline.iter().filter(|step| matches!(step.state, BootState::Stopped { .. })).collect();
+        if let (Some(first), Some(last)) = (stopped.first(), stopped.last()) {
+            let forced: Vec<&str> = stopped.iter()
+                .filter(|step| step.state == BootState::Stopped { forced: true })
+                .map(|step| step.service.as_str())
+                .collect();
+            let _ = write!(out, "shutdown: {} services stopped in {}", stopped.len(), secs(Ticks::from_raw(last.tick).saturating_sub(Ticks::from_raw(first.tick))));
+            if forced.is_empty() {
+                let _ = writeln!(out);
+            } else {
+                let _ = writeln!(out, ", {} forced: {}", forced.len(), forced.join(", "));
+            }
+        }
+        out
+    }
 }
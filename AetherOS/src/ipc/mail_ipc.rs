@@ -10,22 +10,60 @@ use alloc::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::session_ipc::AidBytes;
+
 /// Represents requests from client V-Nodes to the Mail V-Node.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum MailRequest {
-    /// Send a new mail message.
+    /// Send a new mail message. A bare name or `name@local` is delivered to
+    /// that local identity's Inbox; any other address goes over the network.
     SendMail {
         recipient: String,
         subject: String,
         body: String,
     },
-    /// List available mailboxes for the current user.
+    /// List available mailboxes of the calling task's identity.
     ListMailboxes,
     /// Read a specific message from a given mailbox.
     ReadMessage {
         mailbox: String,
         message_id: u32,
     },
+    /// Search the caller's mailboxes. `query` holds free-text terms matched
+    /// against message bodies and `from:`, `subject:`, `mailbox:`, `before:`
+    /// and `after:` filters (see docs/apps/mail.md). Results are newest
+    /// first; `offset` skips that many and `limit` caps the page at
+    /// `MAX_SEARCH_RESULTS` (0 for the cap).
+    Search {
+        query: String,
+        offset: u32,
+        limit: u32,
+    },
+    /// Rebuild the search index of one of the caller's mailboxes, or of all of them.
+    RebuildIndex {
+        mailbox: Option<String>,
+    },
+    /// Delete a message from a mailbox, and from its search index.
+    DeleteMessage {
+        mailbox: String,
+        message_id: u32,
+    },
+}
+
+/// Most results returned by one `MailRequest::Search`.
+pub const MAX_SEARCH_RESULTS: u32 = 50;
+
+/// One message found by `MailRequest::Search`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub mailbox: String,
+    pub message_id: u32,
+    /// Epoch seconds of the `Date` header, or 0 if it has none that parses.
+    pub date: u64,
+    pub from: String,
+    pub subject: String,
+    /// The start of the body text, whitespace collapsed.
+    pub snippet: String,
 }
 
 /// Represents responses from the Mail V-Node to client V-Nodes.
@@ -39,4 +77,29 @@ pub enum MailResponse {
     Message(String),
     /// Indicates an error occurred during the operation.
     Error(String),
+    /// The calling task has no identity bound, so it has no mailboxes.
+    Unauthenticated,
+    /// A local address names neither an alias nor a known identity.
+    UnknownRecipient(String),
+    /// A page of search results. `total` counts all matches, not just this page.
+    SearchResults {
+        total: u32,
+        hits: Vec<SearchHit>,
+    },
+    /// The search query doesn't parse. `position` is the byte offset of the
+    /// token at fault.
+    BadQuery {
+        position: u32,
+        message: String,
+    },
+}
+
+/// Payload of the "mail.received" event, published when a message lands in a
+/// local Inbox. Event-bus subscribers aren't scoped to an identity, so the
+/// event says where the message is, not what it says or who sent it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailReceived {
+    pub recipient: AidBytes,
+    pub mailbox: String,
+    pub message_id: u32,
 }
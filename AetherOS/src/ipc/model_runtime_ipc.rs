@@ -9,6 +9,11 @@ use alloc::string::String;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+
+/// Most requests one `Batch` may carry.
+pub const MAX_BATCH_ITEMS: u32 = 32;
+
 /// Represents requests from client V-Nodes to the Model Runtime V-Node for inference.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum InferRequest {
@@ -16,6 +21,12 @@ pub enum InferRequest {
     ImageClassification { model_id: String, image_data: Vec<u8> },
     /// Request for text generation.
     TextGeneration { model_id: String, prompt: String, max_tokens: u32 },
+    /// Several requests of one kind against one model, answered together
+    /// with a `BatchResult` in the same order. The model is set up once for
+    /// all of them. A batch can't contain another batch.
+    Batch { requests: Vec<InferRequest> },
+    /// Reads model-runtime's queue and slice metrics.
+    Metrics(MetricsRequest),
     // Add more inference types as needed (e.g., ObjectDetection, SpeechToText)
 }
 
@@ -26,6 +37,33 @@ pub enum InferResponse {
     ImageClassificationResult { class_labels: Vec<String>, probabilities: Vec<f32> },
     /// Result for text generation.
     TextGenerationResult { generated_text: String },
+    /// The request violates one of the model's declared input constraints.
+    InvalidInput { constraint: InputConstraint },
     /// Indicates an error occurred during inference.
     Error { message: String },
+    /// One response per request of a `Batch`, in the batch's order. Each item
+    /// succeeds or fails on its own.
+    BatchResult { results: Vec<InferResponse> },
+    /// The client already has `limit` requests queued or running; nothing was
+    /// queued. Retry once one of them is answered.
+    Busy { in_flight: u32, limit: u32 },
+    Metrics(MetricsResponse),
+}
+
+/// The input constraint a rejected request violated, with the model's limit
+/// and what the request actually carried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputConstraint {
+    /// `image_data` is larger than the model's `max_image_bytes`.
+    MaxImageBytes { limit: u64, actual: u64 },
+    /// The prompt has more characters than the model's `max_prompt_chars`.
+    MaxPromptChars { limit: u64, actual: u64 },
+    /// `max_tokens` exceeds the model's `max_tokens` ceiling, or is zero.
+    MaxTokens { limit: u32, requested: u32 },
+    /// The model's metadata declares no limits for this kind of request.
+    UnsupportedInput,
+    /// A batch is empty or carries more than `MAX_BATCH_ITEMS` requests.
+    BatchSize { limit: u32, actual: u32 },
+    /// A batch mixes request kinds or models, or contains a batch.
+    MixedBatch,
 }
@@ -2,10 +2,18 @@
 // src/ipc/net_ipc.rs
 
 extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ipc::socket_ipc::{ListenerInfo, SendStats, SocketOption};
+
+/// The largest TCP listen backlog. Each slot is a socket with its own buffers.
+pub const MAX_BACKLOG: u32 = 32;
+
 // IPC message format for data plane operations between net-bridge and aethernet-service
 #[derive(Debug, Serialize, Deserialize)]
 pub enum NetPacketMsg {
@@ -32,7 +40,263 @@ pub enum NetStackRequest {
     Send(u32, Vec<u8>), // socket_handle, data
     SendTo(u32, [u8; 4], u16, Vec<u8>), // socket_handle, remote_ip, remote_port, data (new variant)
     Recv(u32), // socket_handle
-    CloseSocket(u32), // socket_handle
+    CloseSocket(u32), // socket_handle; for a listener, also drops connections not yet accepted
+    /// Opens a TCP listener on `port` that completes up to `backlog` handshakes
+    /// before any is accepted. Answered with `SocketOpened`.
+    Listen { port: u16, backlog: u32 },
+    /// Takes an established connection off a listener. Answered with `Accepted`,
+    /// or `Error(114)` if none is waiting.
+    Accept(u32), // listener handle
+    GetSocketInfo(u32), // socket_handle
+    /// Starts a TCP handshake with `remote_ip:remote_port` from an ephemeral
+    /// port. Answered with `Success` once the SYN is queued; the outcome is
+    /// read with `ConnectStatus`.
+    Connect { handle: u32, remote_ip: [u8; 4], remote_port: u16 },
+    /// Where the handshake started by `Connect` stands. Answered with `Connection`.
+    ConnectStatus(u32), // socket_handle
+    /// Scrape the network stack's metrics (socket counts, quota rejections, limits).
+    Metrics(MetricsRequest),
+    GetNeighbors,
+    /// Adds a static ARP entry. Requires the system identity.
+    AddStaticNeighbor { ip: [u8; 4], mac: [u8; 6] },
+    /// Removes a static or dynamic ARP entry. Requires the system identity.
+    RemoveNeighbor { ip: [u8; 4] },
+    /// Drops dynamic ARP entries, and static ones too if `force` is set. Requires the system identity.
+    FlushNeighbors { force: bool },
+    /// Makes UDP socket `handle` receive datagrams sent to the IPv4 multicast `group`.
+    /// The membership ends with `LeaveMulticastGroup` or when the socket is closed.
+    JoinMulticastGroup { handle: u32, group: [u8; 4] },
+    LeaveMulticastGroup { handle: u32, group: [u8; 4] },
+    /// Returns the interface's hardware and IPv4 address.
+    GetInterface,
+    /// Takes the interface administratively down or brings it back up.
+    /// Requires the system identity. Asking for the state it is already in
+    /// succeeds and does nothing.
+    SetInterfaceState { up: bool },
+    /// Starts copying frames that pass `filter` (all if None) into a new
+    /// capture ring of at most `max_bytes`, each cut to `snaplen` bytes.
+    /// Replaces the previous capture. Requires the system identity.
+    CaptureStart { filter: Option<CaptureFilter>, snaplen: u32, max_bytes: u32 },
+    /// Stops copying frames; the ring is kept for `CaptureDump`. Requires the system identity.
+    CaptureStop,
+    /// Writes the stopped capture to `vfs_path` as a pcap file. Requires the system identity.
+    CaptureDump { vfs_path: String },
+    /// The connections tracked now and the last `max` that closed, most
+    /// recent first. Requires the system identity.
+    GetConnectionHistory { max: u32 },
+    /// Sets an option of TCP socket `handle`. Answered with `Success`.
+    SetSockOpt { handle: u32, option: SocketOption },
+    /// Hands TCP everything socket `handle` holds back, whatever its mode.
+    /// Answered with `Flushed`.
+    Flush(u32), // socket_handle
+    /// Asks for one `DataReady { handle }` on `reply_chan` once socket
+    /// `handle` has data to receive, or its stream has ended. Sent right
+    /// away if it has already. Answered with `Success`; ask again for the
+    /// next one.
+    WatchRecv { handle: u32, reply_chan: u32 },
+    /// Takes the next datagram off UDP socket `handle`. Answered with
+    /// `DataFrom`, empty with address 0.0.0.0:0 if none is queued.
+    RecvFrom(u32), // socket_handle
+    /// `request` on behalf of client `task`: sockets it opens are charged to
+    /// `task`, and only `task`'s sockets can be used. Honoured only from a
+    /// task init reports as an instance of socket-api; anyone else gets
+    /// `Error(105)`.
+    ForTask { task: u64, request: Box<NetStackRequest> },
+}
+
+/// The service whose instances the network stack takes `ForTask` from.
+pub const SOCKET_API_SERVICE: &str = "socket-api";
+
+/// The most a capture ring may hold, counting the 16-byte pcap header of each frame.
+pub const MAX_CAPTURE_BYTES: u32 = 4 * 1024 * 1024;
+/// The largest snap length; longer frames don't exist on the interface anyway.
+pub const MAX_SNAPLEN: u32 = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CaptureDirection {
+    Rx,
+    Tx,
+}
+
+/// Which frames a capture keeps. Every field that is set must match; a
+/// `port` matches either the source or the destination port of TCP and UDP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CaptureFilter {
+    pub direction: Option<CaptureDirection>,
+    pub ethertype: Option<u16>,
+    pub ip_protocol: Option<u8>, // IPv4 frames only, e.g. 6 for TCP, 17 for UDP
+    pub port: Option<u16>,
+}
+
+/// The capture ring, as answered by the `Capture*` requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CaptureStats {
+    pub running: bool,
+    pub packets: u32, // Frames in the ring
+    pub bytes: u64, // Ring bytes in use, pcap record headers included
+    pub matched: u64, // Frames that passed the filter since the start
+    pub dropped: u64, // Matching frames not copied because the ring was full
+}
+
+/// Closed connections the network stack remembers; older ones are evicted.
+pub const CONNECTION_HISTORY_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FlowProtocol {
+    Tcp,
+    /// A UDP flow: datagrams between one local port and one remote address and port.
+    Udp,
+}
+
+/// A TCP connection's state, as smoltcp reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TcpConnState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Why a tracked connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CloseReason {
+    /// Closed with FINs.
+    Fin,
+    ResetReceived,
+    /// This end reset it, e.g. a SYN to a port nobody listens on, or an aborted socket.
+    ResetSent,
+    /// A UDP flow or an unanswered TCP connection saw no traffic for too
+    /// long, or smoltcp gave up retransmitting.
+    IdleTimeout,
+    /// The interface went down while it was open.
+    InterfaceDown,
+    /// Its socket was closed before the connection had ended.
+    ClosedLocally,
+}
+
+/// A TCP state change, at the timer tick it was seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateChange {
+    pub state: TcpConnState,
+    pub tick: u64,
+}
+
+/// A tracked connection. `closed_tick` and `reason` are set once it ended.
+/// Bytes are TCP or UDP payload; "in" is received, "out" sent.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionRecord {
+    pub protocol: FlowProtocol,
+    pub local_port: u16,
+    pub remote_ip: [u8; 4],
+    pub remote_port: u16,
+    pub opened_tick: u64,
+    pub closed_tick: Option<u64>,
+    pub reason: Option<CloseReason>,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// TCP only, oldest first.
+    pub transitions: Vec<StateChange>,
+    /// State changes not recorded because the record was full.
+    pub transitions_dropped: u32,
+}
+
+/// Answers `GetConnectionHistory`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionHistory {
+    /// False if `net.connection_history` turned tracking off; nothing else is set then.
+    pub enabled: bool,
+    pub open: Vec<ConnectionRecord>,
+    /// Most recent first.
+    pub closed: Vec<ConnectionRecord>,
+    /// Closed records pushed out of the ring since startup.
+    pub evicted: u64,
+    /// Connections not tracked because the table of open ones was full.
+    pub untracked: u64,
+}
+
+/// Which socket limit an `OpenSocket` ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SocketQuota {
+    /// The requesting task already holds its maximum number of sockets.
+    PerTask,
+    /// The stack as a whole is at its socket ceiling.
+    Global,
+}
+
+/// Progress of an outgoing TCP connection, reported by `ConnectStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ConnectState {
+    /// The SYN is out and no answer has come yet.
+    Connecting,
+    Established,
+    /// The peer answered with a reset, or smoltcp gave up on the handshake.
+    Refused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NeighborState {
+    /// Learned from ARP traffic; forgotten 60 seconds after it was last seen.
+    Dynamic,
+    /// Configured with `AddStaticNeighbor`; kept until removed.
+    Static,
+}
+
+/// An entry of the ARP neighbor table, reported by `GetNeighbors`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NeighborEntry {
+    pub ip: [u8; 4],
+    pub mac: [u8; 6],
+    pub state: NeighborState,
+    pub age_ms: u64, // Since last seen (dynamic) or added (static)
+}
+
+/// The network interface, reported by `GetInterface`.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct InterfaceInfo {
+    pub mac: [u8; 6],
+    pub ip: [u8; 4],
+    pub prefix_len: u8,
+    /// False while the interface is administratively down.
+    pub up: bool,
+}
+
+/// Published on the event bus by the network stack once the interface is
+/// down, after the `SOCKET_CLOSED_TOPIC` events. Payload: `InterfaceInfo`.
+pub const NET_DOWN_TOPIC: &str = "net.down";
+/// Published once the interface is configured again. Payload: `InterfaceInfo`.
+pub const NET_UP_TOPIC: &str = "net.up";
+/// Published for every socket the stack closed because the interface went
+/// down. Payload: `SocketClosed`.
+pub const SOCKET_CLOSED_TOPIC: &str = "net.socket_closed";
+
+/// Published when a connection completes its handshake on a listener's
+/// backlog slot, once per connection. Payload: `ConnectionReady`.
+pub const CONNECTION_READY_TOPIC: &str = "net.connection_ready";
+
+/// A connection waiting on a listener for `Accept`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConnectionReady {
+    pub listener: u32,
+    /// The task that opened the listener.
+    pub owner: u64,
+    pub peer_addr: [u8; 4],
+    pub peer_port: u16,
+}
+
+/// A socket the network stack closed on its own. The handle is not valid anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SocketClosed {
+    pub handle: u32,
+    /// The task that opened it.
+    pub owner: u64,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -41,4 +305,27 @@ pub enum NetStackResponse {
     Data(Vec<u8>),
     Error(u32), // error_code
     Success,
+    QuotaExceeded(SocketQuota, u32), // which limit, and its value
+    Metrics(MetricsResponse),
+    Neighbors(Vec<NeighborEntry>),
+    Interface(InterfaceInfo),
+    /// A new socket for an accepted connection, and its peer.
+    Accepted { handle: u32, remote_ip: [u8; 4], remote_port: u16 },
+    /// `listener` is set for listener handles.
+    SocketInfo { local_port: u16, listener: Option<ListenerInfo> },
+    Connection(ConnectState),
+    /// The interface is down; `Send`, `SendTo`, `Connect` and multicast joins are refused.
+    InterfaceDown,
+    /// Answers `CaptureStart`, `CaptureStop` and `CaptureDump`.
+    Capture(CaptureStats),
+    ConnectionHistory(ConnectionHistory),
+    /// Answers `GetSocketInfo` for a TCP socket that isn't a listener.
+    StreamInfo { local_port: u16, send: SendStats },
+    /// Answers `Flush`: bytes still queued because TCP's send buffer is full.
+    /// They go out as it drains, whatever the socket's mode.
+    Flushed { queued: u32 },
+    /// Sent unasked on the channel given to `WatchRecv`, never as an answer.
+    DataReady { handle: u32 },
+    /// Answers `RecvFrom`: a datagram and the endpoint it came from.
+    DataFrom { data: Vec<u8>, addr: [u8; 4], port: u16 },
 }
@@ -9,6 +9,8 @@ use alloc::string::String;
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::session_ipc::AidBytes;
+
 /// Represents requests from client V-Nodes (e.g., AetherTerminal, other V-Nodes) to the Shell V-Node.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ShellRequest {
@@ -18,6 +20,21 @@ pub enum ShellRequest {
     ChangeDirectory { path: String },
     /// Request to get the current working directory.
     GetCurrentDirectory,
+    /// Request completion candidates for the word under the cursor (e.g., on Tab).
+    /// `cursor_pos` is a byte offset into `line`.
+    Complete { line: String, cursor_pos: u32 },
+    /// The user's reply to a `Prompt` response.
+    Answer { text: String },
+    /// Request to execute a whole command line as typed. The shell splits it
+    /// into words and expands unquoted wildcards in the arguments against the VFS.
+    ExecuteLine { line: String },
+    /// The slowest of the recent commands, slowest first, at most `limit`.
+    GetTimings { limit: u32 },
+    /// Ask svc://session for a nonce to log this shell in with. Answered with `Challenge`.
+    LoginChallenge,
+    /// Log this shell in as `aid`: `proof` is the nonce from `Challenge`,
+    /// signed with the Aid's key. Commands run after it act for the Aid.
+    Login { aid: AidBytes, proof: Vec<u8> },
 }
 
 /// Represents responses from the Shell V-Node to client V-Nodes.
@@ -29,6 +46,28 @@ pub enum ShellResponse {
     Success(String),
     /// Returns the current working directory.
     CurrentDirectory(String),
+    /// Completion candidates for a `Complete` request. The client replaces the text
+    /// from `word_start` up to the cursor with `common_prefix`; `candidates` are
+    /// meant for display when the prefix is ambiguous.
+    Completions { word_start: u32, common_prefix: String, candidates: Vec<String> },
+    /// The command needs a decision from the user. The client shows `message`
+    /// and sends the typed reply back as `Answer`.
+    Prompt { message: String },
     /// Indicates an error occurred during the operation.
     Error(String),
+    /// Answers `GetTimings`.
+    Timings(Vec<CommandTiming>),
+    /// Answers `LoginChallenge`.
+    Challenge { nonce: AidBytes },
+}
+
+/// How long a command from the history took to execute, not counting time
+/// spent waiting for the user to answer a `Prompt`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandTiming {
+    /// The command line as executed, after wildcard expansion.
+    pub line: String,
+    pub elapsed_nanos: u64,
+    /// Request/reply round trips the shell made on the command's behalf.
+    pub round_trips: u64,
 }
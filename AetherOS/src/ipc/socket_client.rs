@@ -0,0 +1,147 @@
+// src/ipc/socket_client.rs
+
+#![no_std]
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::ipc::socket_ipc::{AttemptError, ConnectAttempt, SocketFd, SocketOption, SocketRequest, SocketResponse};
+use crate::ipc::vnode::VNodeChannel;
+
+/// Common socket-api calls in one step, over a channel to svc://socket-api.
+///
+/// ```ignore
+/// let mut client = SocketClient::new(&mut socket_chan);
+/// let conn = client.tcp_connect_host("smtp.example.com", 25, 2000)?;
+/// // ... talk on conn.fd ...
+/// client.close(conn.fd);
+/// ```
+pub struct SocketClient<'a> {
+    chan: &'a mut VNodeChannel,
+}
+
+/// A connected TCP socket and the address it reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpConnection {
+    pub fd: SocketFd,
+    pub addr: [u8; 4],
+    pub port: u16,
+}
+
+/// Why `tcp_connect_host` didn't connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectHostError {
+    /// The hostname didn't resolve.
+    Resolve { hostname: String, reason: String },
+    /// At least one address refused the connection and none accepted it.
+    Refused { attempts: Vec<ConnectAttempt> },
+    /// No address answered in time.
+    TimedOut { attempts: Vec<ConnectAttempt> },
+    /// Every address failed some other way, e.g. the network policy denied it.
+    Failed { attempts: Vec<ConnectAttempt> },
+    /// The socket couldn't be created, or socket-api didn't answer properly.
+    Socket { errno: i32, message: String },
+}
+
+impl ConnectHostError {
+    /// Sorts the per-address failures of a `ConnectFailed`. A refusal means
+    /// some host is up, so it says more than a timeout.
+    fn from_attempts(attempts: Vec<ConnectAttempt>) -> Self {
+        if attempts.iter().any(|attempt| attempt.error == AttemptError::Refused) {
+            Self::Refused { attempts }
+        } else if attempts.iter().any(|attempt| attempt.error == AttemptError::TimedOut) {
+            Self::TimedOut { attempts }
+        } else {
+            Self::Failed { attempts }
+        }
+    }
+}
+
+impl fmt::Display for ConnectHostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (summary, attempts) = match self {
+            Self::Resolve { hostname, reason } => return write!(f, "cannot resolve {}: {}", hostname, reason),
+            Self::Socket { errno, message } => return write!(f, "{} ({})", message, errno),
+            Self::Refused { attempts } => ("connection refused", attempts),
+            Self::TimedOut { attempts } => ("connection timed out", attempts),
+            Self::Failed { attempts } => ("cannot connect", attempts),
+        };
+        write!(f, "{}", summary)?;
+        for (i, attempt) in attempts.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " (" } else { "; " }, attempt)?;
+        }
+        if !attempts.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SocketClient<'a> {
+    pub fn new(chan: &'a mut VNodeChannel) -> Self {
+        Self { chan }
+    }
+
+    /// Opens a TCP socket and connects it to the first address of `host` that
+    /// accepts, waiting up to `attempt_timeout_ms` per address (0 for the
+    /// default). The socket is closed again if nothing connects.
+    pub fn tcp_connect_host(&mut self, host: &str, port: u16, attempt_timeout_ms: u32) -> Result<TcpConnection, ConnectHostError> {
+        let fd = match self.request(&SocketRequest::Socket { domain: 2, ty: 1, protocol: 0 }) {
+            SocketResponse::Success(fd) => SocketFd::from_raw(fd as u32),
+            other => return Err(socket_error(other)),
+        };
+        let request = SocketRequest::ConnectHost { fd, hostname: host.to_string(), port, attempt_timeout_ms };
+        let error = match self.request(&request) {
+            SocketResponse::ConnectedHost { addr, port } => return Ok(TcpConnection { fd, addr, port }),
+            SocketResponse::ResolveFailed { hostname, reason } => ConnectHostError::Resolve { hostname, reason },
+            SocketResponse::ConnectFailed { attempts } => ConnectHostError::from_attempts(attempts),
+            other => socket_error(other),
+        };
+        self.close(fd);
+        Err(error)
+    }
+
+    /// Sets `option` on TCP socket `fd`. The error is socket-api's errno and message.
+    pub fn set_option(&mut self, fd: SocketFd, option: SocketOption) -> Result<(), (i32, String)> {
+        match self.request(&SocketRequest::SetSockOpt { fd, option }) {
+            SocketResponse::Success(_) => Ok(()),
+            other => Err(errno_of(other)),
+        }
+    }
+
+    /// Sends whatever TCP socket `fd` is holding back. Returns the bytes that
+    /// are still queued behind a full send buffer.
+    pub fn flush(&mut self, fd: SocketFd) -> Result<u32, (i32, String)> {
+        match self.request(&SocketRequest::Flush { fd }) {
+            SocketResponse::Success(queued) => Ok(queued as u32),
+            other => Err(errno_of(other)),
+        }
+    }
+
+    /// Closes `fd`. There is nothing useful to do if that fails.
+    pub fn close(&mut self, fd: SocketFd) {
+        let _ = self.request(&SocketRequest::Close { fd });
+    }
+
+    fn request(&mut self, request: &SocketRequest) -> SocketResponse {
+        self.chan.send_and_recv::<SocketRequest, SocketResponse>(request)
+            .unwrap_or_else(|_| SocketResponse::Error(-1, "No response from socket-api".to_string()))
+    }
+}
+
+fn socket_error(response: SocketResponse) -> ConnectHostError {
+    let (errno, message) = errno_of(response);
+    ConnectHostError::Socket { errno, message }
+}
+
+/// A failed response as errno and message.
+fn errno_of(response: SocketResponse) -> (i32, String) {
+    match response {
+        SocketResponse::Error(errno, message) => (errno, message),
+        SocketResponse::PolicyDenied { rule } => (13, alloc::format!("Denied by {}", rule)), // EACCES
+        SocketResponse::NetworkDown => (100, "Network is down".to_string()), // ENETDOWN
+        other => (-1, alloc::format!("Unexpected response from socket-api: {:?}", other)),
+    }
+}
@@ -6,11 +6,14 @@
 extern crate alloc;
 use alloc::vec::Vec;
 use alloc::string::String;
+use core::fmt;
 
 use serde::{Deserialize, Serialize};
 
-/// Represents a socket file descriptor within the socket-api V-Node.
-pub type SocketFd = u32;
+/// Represents a socket file descriptor within the socket-api V-Node. It is a
+/// `SocketHandle`, so it can't be mixed up with a net-stack handle or a
+/// channel ID; on the wire it is still a plain `u32`.
+pub type SocketFd = crate::ids::SocketHandle;
 
 /// Represents requests from client V-Nodes to the socket-api V-Node.
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,12 +28,42 @@ pub enum SocketRequest {
     Accept { fd: SocketFd },
     /// Connect a socket to a remote address.
     Connect { fd: SocketFd, addr: [u8; 4], port: u16 },
+    /// Resolve `hostname` and connect TCP socket `fd` to the first of its
+    /// addresses that accepts, trying them in the resolver's order. Each
+    /// attempt gets `attempt_timeout_ms`, or `DEFAULT_CONNECT_TIMEOUT_MS` if 0.
+    ConnectHost { fd: SocketFd, hostname: String, port: u16, attempt_timeout_ms: u32 },
+    /// The address a socket is connected to.
+    GetPeerName { fd: SocketFd },
     /// Send data over a socket.
     Send { fd: SocketFd, data: Vec<u8> },
+    /// Send a datagram on a UDP socket to the given address, which may be a multicast group.
+    SendTo { fd: SocketFd, addr: [u8; 4], port: u16, data: Vec<u8> },
     /// Receive data from a socket.
     Recv { fd: SocketFd, len: u32 },
-    /// Close a socket.
+    /// Receive datagrams sent to an IPv4 multicast group on a bound UDP socket.
+    JoinMulticast { fd: SocketFd, group: [u8; 4] },
+    /// Stop receiving datagrams sent to a group joined with `JoinMulticast`.
+    LeaveMulticast { fd: SocketFd, group: [u8; 4] },
+    /// Close a socket. Closing a listening socket resets connections not yet accepted.
     Close { fd: SocketFd },
+    /// Describe a socket: its type, local port and, if listening, its backlog.
+    GetSocketInfo { fd: SocketFd },
+    /// List the network policy in effect for every service that has one.
+    GetPolicy,
+    /// Set an option of a TCP socket. Answered with `Success(0)`.
+    SetSockOpt { fd: SocketFd, option: SocketOption },
+    /// Hand TCP everything a TCP socket is holding back, corked or not.
+    /// Answered with `Success` and the bytes still queued, which go out as
+    /// the send buffer drains.
+    Flush { fd: SocketFd },
+    /// Asks for one `DataAvailable { fd }` on `reply_chan` once `fd` has data
+    /// for `Recv`, or its stream has ended, instead of polling with `Recv`.
+    /// Sent right away if it has already. Answered with `Success(0)`; ask
+    /// again after reading.
+    RecvAsync { fd: SocketFd, reply_chan: u32 },
+    /// Receive one datagram on a UDP socket, with the address it came from.
+    /// Answered with `DataFrom`, empty if nothing is queued.
+    RecvFrom { fd: SocketFd, len: u32 },
 }
 
 /// Represents responses from the socket-api V-Node to client V-Nodes.
@@ -44,4 +77,175 @@ pub enum SocketResponse {
     Error(i32, String), // errno, error_message
     /// For accept, returns the new socket fd and remote address/port.
     Accepted { new_fd: SocketFd, remote_addr: [u8; 4], remote_port: u16 },
+    /// The caller's network policy refused a `Connect`, `SendTo` or `Bind`.
+    /// `rule` names the matched rule, e.g. "mail-service rule 2 (deny 10.0.0.0/8)".
+    PolicyDenied { rule: String },
+    /// Answers `GetPolicy`.
+    Policy(Vec<ServicePolicy>),
+    /// Answers `GetSocketInfo`.
+    SocketInfo { ty: i32, local_port: u16, listener: Option<ListenerInfo> },
+    /// Answers `ConnectHost`: the address that accepted the connection.
+    ConnectedHost { addr: [u8; 4], port: u16 },
+    /// `ConnectHost` couldn't resolve the hostname.
+    ResolveFailed { hostname: String, reason: String },
+    /// `ConnectHost` resolved the hostname but no address accepted. One entry
+    /// per address, in the order they were tried.
+    ConnectFailed { attempts: Vec<ConnectAttempt> },
+    /// Answers `GetPeerName`.
+    PeerName { addr: [u8; 4], port: u16 },
+    /// The network interface is down. Refuses `Connect`, `ConnectHost`, `Send`
+    /// and `SendTo` until it is back up.
+    NetworkDown,
+    /// Answers `GetSocketInfo` for a TCP socket that isn't listening.
+    StreamInfo { local_port: u16, send: SendStats },
+    /// Sent unasked on the channel given to `RecvAsync`, never as an answer:
+    /// the next `Recv` on `fd` returns data, or nothing if the stream ended.
+    DataAvailable { fd: SocketFd },
+    /// Answers `RecvFrom`: the datagram and its sender, or no data and
+    /// 0.0.0.0:0 if none was queued.
+    DataFrom { data: Vec<u8>, addr: [u8; 4], port: u16 },
+}
+
+/// Per-attempt timeout of a `ConnectHost` that doesn't give one.
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u32 = 3000;
+
+/// One failed address of a `ConnectHost`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectAttempt {
+    pub addr: [u8; 4],
+    pub error: AttemptError,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttemptError {
+    /// The host answered with a reset.
+    Refused,
+    /// No answer within the attempt's timeout.
+    TimedOut,
+    /// The caller's network policy doesn't allow the address; it wasn't tried.
+    PolicyDenied { rule: String },
+    /// The interface went down; no further address was tried.
+    NetworkDown,
+    /// Anything else, as errno and message.
+    Other(i32, String),
+}
+
+impl fmt::Display for ConnectAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.addr;
+        write!(f, "{}.{}.{}.{}: ", a, b, c, d)?;
+        match &self.error {
+            AttemptError::Refused => write!(f, "connection refused"),
+            AttemptError::TimedOut => write!(f, "timed out"),
+            AttemptError::PolicyDenied { rule } => write!(f, "denied by {}", rule),
+            AttemptError::NetworkDown => write!(f, "network is down"),
+            AttemptError::Other(errno, message) => write!(f, "{} ({})", message, errno),
+        }
+    }
+}
+
+/// The backlog of a listening TCP socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerInfo {
+    /// Connections that may complete their handshake before one is accepted.
+    pub backlog: u32,
+    /// Slots waiting for a connection.
+    pub available: u32,
+    /// Connections established and waiting for `Accept`.
+    pub pending: u32,
+}
+
+/// An option set with `SetSockOpt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SocketOption {
+    /// TCP_NODELAY: every write goes to TCP at once, and Nagle's algorithm is off.
+    NoDelay(bool),
+    /// TCP_CORK: writes are held until a full segment's worth is queued, the
+    /// socket is uncorked or flushed. Takes precedence over `NoDelay`.
+    Cork(bool),
+    /// O_NONBLOCK for `Connect`: instead of waiting for the handshake, it is
+    /// answered with EINPROGRESS (115) and the caller sends the same
+    /// `Connect` again to poll. Can be set before the socket is connected.
+    /// Kept by socket-api; the network stack never sees it.
+    NonBlocking(bool),
+}
+
+/// When a TCP socket's writes go out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendMode {
+    /// The default. A write to an idle connection goes out at once; writes
+    /// made while earlier data is unacknowledged are merged for a short while.
+    Coalesce,
+    NoDelay,
+    Cork,
+}
+
+/// A TCP socket's sending, as reported by `GetSocketInfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendStats {
+    pub mode: SendMode,
+    /// Bytes written but not handed to TCP yet.
+    pub queued: u32,
+    /// Pieces handed to TCP, each at most a segment's payload.
+    pub segments: u64,
+    /// Payload bytes handed to TCP.
+    pub bytes: u64,
+}
+
+impl SendStats {
+    /// Average payload of the segments sent so far; 0 before the first.
+    pub fn average_payload(&self) -> u64 {
+        if self.segments == 0 { 0 } else { self.bytes / self.segments }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+}
+
+/// One policy rule: an action for an IPv4 network and a port range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetRule {
+    pub action: PolicyAction,
+    pub network: [u8; 4],
+    pub prefix_len: u8,
+    pub first_port: u16,
+    pub last_port: u16,
+}
+
+impl NetRule {
+    pub fn matches(&self, addr: [u8; 4], port: u16) -> bool {
+        let mask = if self.prefix_len == 0 { 0 } else { u32::MAX << (32 - self.prefix_len as u32) };
+        u32::from_be_bytes(addr) & mask == u32::from_be_bytes(self.network) & mask
+            && (self.first_port..=self.last_port).contains(&port)
+    }
+}
+
+/// Same syntax as in the `net.policy` setting: `deny 10.0.0.0/8`, `allow 0.0.0.0/0:53`,
+/// `allow 192.168.1.0/24:8000-8080`.
+impl fmt::Display for NetRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            PolicyAction::Allow => "allow",
+            PolicyAction::Deny => "deny",
+        };
+        let [a, b, c, d] = self.network;
+        write!(f, "{} {}.{}.{}.{}/{}", action, a, b, c, d, self.prefix_len)?;
+        match (self.first_port, self.last_port) {
+            (0, u16::MAX) => Ok(()),
+            (first, last) if first == last => write!(f, ":{}", first),
+            (first, last) => write!(f, ":{}-{}", first, last),
+        }
+    }
+}
+
+/// The rules of one service, checked in order; the first match decides.
+/// `default` applies when no rule matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServicePolicy {
+    pub service: String,
+    pub default: PolicyAction,
+    pub rules: Vec<NetRule>,
 }
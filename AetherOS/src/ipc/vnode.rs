@@ -4,7 +4,7 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 use crate::ipc::{IpcSend, IpcRecv};
-use crate::syscall::{syscall2, syscall3, SYS_IPC_SEND, SYS_IPC_RECV, SYS_BLOCK_ON_CHAN, SYS_IPC_RECV_NONBLOCKING};
+use crate::syscall::{syscall3, SYS_IPC_SEND, SYS_IPC_RECV, SYS_BLOCK_ON_CHAN, SYS_IPC_RECV_NONBLOCKING};
 
 pub struct VNodeChannel {
     pub id: u32,
@@ -30,10 +30,11 @@ impl VNodeChannel {
                 return Ok(self.buffer[..len as usize].to_vec());
             } else if len == 0 { // No message, block
                 unsafe {
-                    syscall2(
+                    syscall3(
                         SYS_BLOCK_ON_CHAN,
                         self.id as u64,
                         0,
+                        0,
                     );
                 }
                 // Scheduler will run other tasks, then eventually this task will resume.
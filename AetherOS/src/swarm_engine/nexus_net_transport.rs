@@ -4,6 +4,7 @@
 #![no_std]
 
 extern crate alloc;
+use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::format;
 
@@ -11,6 +12,67 @@ use crate::cid::Cid;
 use crate::swarm_engine::{SwarmTransport, SwarmError};
 use crate::arp_dht::PeerInfo;
 use libnexus_net::{NetClient, NetError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// UDP port nodes serve chunks on.
+pub const SWARM_PORT: u16 = 60000;
+/// UDP port nodes answer `SearchMessage::Query` on.
+pub const SEARCH_PORT: u16 = 60001;
+/// How often a fetch is retried when the peer answers `Busy`.
+const MAX_BUSY_RETRIES: u32 = 3;
+
+/// A peer's answer to a chunk request (the postcard-encoded CID).
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ChunkReply {
+    Data(Vec<u8>),
+    NotFound,
+    /// The peer is over its upload limit; ask again after this many ticks.
+    Busy { retry_after_ticks: u64 },
+}
+
+/// Federated search between registries, and the pings that check a peer
+/// is still there.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SearchMessage {
+    /// Packages matching `query`, at most `limit`. `hops` is the forwarding
+    /// budget; searches start with 1, and a node answering from its own
+    /// catalog never forwards, so one search costs one message per peer asked.
+    Query { id: u64, query: String, limit: u32, hops: u8 },
+    Results { id: u64, hits: Vec<SearchHit> },
+    /// The peer is over its rate limit for searches.
+    Busy { id: u64 },
+    /// Liveness probe. `aid` and `port`, the port it serves chunks on,
+    /// introduce the sender, so the peer can add it to the peers it knows.
+    Ping { id: u64, aid: [u8; 32], port: u16 },
+    /// Answer to `Ping`, introducing the answering node the same way.
+    Pong { id: u64, aid: [u8; 32], port: u16 },
+    /// The sender's view of how retrievable packages are, sent with each
+    /// `Ping` and `Pong` and refreshed periodically. Only ever a hint.
+    Health { summaries: Vec<HealthSummary> },
+}
+
+/// One package in a peer's answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub root_cid: Cid,
+    pub score: u32,
+}
+
+/// One package in a `SearchMessage::Health`. The last fetch is sent as an
+/// age rather than a timestamp, so nodes needn't agree on the time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthSummary {
+    pub root_cid: Cid,
+    /// Whether the sender serves its chunks itself.
+    pub seeding: bool,
+    /// The sender's estimate of how many nodes serve it.
+    pub seeders: u32,
+    pub fetched_secs_ago: Option<u32>,
+}
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -50,28 +112,97 @@ impl SwarmTransport for NexusNetTransport {
         // Serialize CID for sending
         let request_payload = postcard::to_allocvec(&cid).map_err(|_| SwarmError::NetworkError)?;
 
-        // Send CID request to the peer over UDP
-        self.net_client.send_to(
-            self.udp_socket_handle,
-            peer.ip_address,
-            peer.port,
-            request_payload
-        ).map_err(|e| {
-            log(&alloc::format!("NexusNetTransport: Failed to send request: {:?}", e));
-            SwarmError::NetworkError
-        })?;
-
-        // Receive the response (chunk data)
-        // This will block until a response is received or a timeout occurs
-        // In a real system, we'd have a more robust async receive with timeouts
-        let response_payload = self.net_client.recv(self.udp_socket_handle).map_err(|e| {
-            log(&alloc::format!("NexusNetTransport: Failed to receive response: {:?}", e));
-            SwarmError::NetworkError
-        })?;
-
-        // In a real scenario, the response payload would be verified and parsed to extract the chunk data.
-        // For this sketch, we assume the response_payload IS the chunk data.
-        log(&alloc::format!("NexusNetTransport: Received {} bytes for chunk {}", response_payload.len(), alloc::format!("{:?}", cid.as_bytes())));
-        Ok(response_payload)
+        for _ in 0..=MAX_BUSY_RETRIES {
+            // Send CID request to the peer over UDP
+            self.net_client.send_to(
+                self.udp_socket_handle,
+                peer.ip_address,
+                peer.port,
+                request_payload.clone()
+            ).map_err(|e| {
+                log(&alloc::format!("NexusNetTransport: Failed to send request: {:?}", e));
+                SwarmError::NetworkError
+            })?;
+
+            // Receive the response
+            // This will block until a response is received or a timeout occurs
+            // In a real system, we'd have a more robust async receive with timeouts
+            let response_payload = self.net_client.recv(self.udp_socket_handle).map_err(|e| {
+                log(&alloc::format!("NexusNetTransport: Failed to receive response: {:?}", e));
+                SwarmError::NetworkError
+            })?;
+
+            // The chunk is verified against its CID by the SwarmEngine.
+            match postcard::from_bytes::<ChunkReply>(&response_payload) {
+                Ok(ChunkReply::Data(data)) => {
+                    log(&alloc::format!("NexusNetTransport: Received {} bytes for chunk {}", data.len(), alloc::format!("{:?}", cid.as_bytes())));
+                    return Ok(data);
+                },
+                Ok(ChunkReply::Busy { retry_after_ticks }) => {
+                    log(&alloc::format!("NexusNetTransport: Peer {}:{} is busy; retrying in {} ticks.", peer.ip_address[0], peer.port, retry_after_ticks));
+                    wait_ticks(retry_after_ticks);
+                },
+                Ok(ChunkReply::NotFound) => return Err(SwarmError::NetworkError),
+                Err(_) => {
+                    log("NexusNetTransport: Malformed chunk reply.");
+                    return Err(SwarmError::NetworkError);
+                },
+            }
+        }
+        Err(SwarmError::NetworkError)
+    }
+}
+
+/// Yields until `ticks` timer ticks have passed.
+pub fn wait_ticks(ticks: u64) {
+    let start = unsafe { crate::syscall::syscall3(crate::syscall::SYS_TIME, 0, 0, 0) };
+    while unsafe { crate::syscall::syscall3(crate::syscall::SYS_TIME, 0, 0, 0) }.saturating_sub(start) < ticks {}
+}
+
+/// The socket this node answers chunk requests on.
+pub struct NexusNetServer {
+    net_client: NetClient,
+    udp_socket_handle: u32,
+}
+
+impl NexusNetServer {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut net_client = NetClient::new();
+        let udp_socket_handle = net_client.open_udp_socket(port)?;
+        log(&alloc::format!("NexusNetServer: Serving chunks on UDP port {}.", port));
+        Ok(NexusNetServer { net_client, udp_socket_handle })
+    }
+
+    /// The next chunk request, with the address it came from, if one is waiting.
+    /// Datagrams that aren't a CID are dropped.
+    pub fn poll_request(&mut self) -> Option<(([u8; 4], u16), Cid)> {
+        self.poll_message::<Cid>()
+    }
+
+    pub fn reply(&mut self, to: ([u8; 4], u16), reply: &ChunkReply) {
+        self.send_message(to, reply);
+    }
+
+    /// The next datagram that decodes as an `M`, with the address it came from.
+    /// Datagrams that don't are dropped.
+    pub fn poll_message<M: DeserializeOwned>(&mut self) -> Option<(([u8; 4], u16), M)> {
+        let (payload, addr, port) = self.net_client.try_recv_from(self.udp_socket_handle).ok()??;
+        match postcard::from_bytes::<M>(&payload) {
+            Ok(message) => Some(((addr, port), message)),
+            Err(_) => {
+                log(&alloc::format!("NexusNetServer: Dropping malformed request from {:?}:{}.", addr, port));
+                None
+            },
+        }
+    }
+
+    pub fn send_message<M: Serialize>(&mut self, to: ([u8; 4], u16), message: &M) {
+        let payload = match postcard::to_allocvec(message) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        if let Err(e) = self.net_client.send_to(self.udp_socket_handle, to.0, to.1, payload) {
+            log(&alloc::format!("NexusNetServer: Failed to send to {:?}:{}: {:?}", to.0, to.1, e));
+        }
     }
 }
@@ -0,0 +1,424 @@
+// tools/axpkg/src/main.rs
+
+//! Builds what AetherOS installs and boots from, on the host:
+//!
+//! ```text
+//! axpkg pack <dir> --meta <meta.toml> [-o <out.ax>]
+//! axpkg unpack <pkg.ax> -o <dir>
+//! axpkg verify <pkg.ax>...            (also: axpkg --verify <pkg.ax>...)
+//! axpkg bundle -o <out.axb> <pkg.ax>...
+//! axpkg inspect <bundle.axb>
+//! axpkg initrd -o <image> [--vnode [<name>=]<elf>]... [--etc <dir>] [--locale <dir>] [--keymaps <dir>] [--cmdline <args>]
+//! axpkg check-protocols [--regenerate]
+//! ```
+//!
+//! Exit status is 0 on success, 1 if a package fails verification or a
+//! protocol no longer matches its fixtures and 2 for usage and I/O errors, so
+//! `verify`, `inspect` and `check-protocols` can gate a CI job.
+
+mod meta;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use common::ax;
+use common::bundle;
+use common::capability::Grant;
+use common::cmdline::{BootArgs, BOOT_CMDLINE_PATH};
+use common::i18n::{Catalog, CATALOG_FILE, LOCALE_DIR};
+use common::initrd::{self, Initrd, ETC_DIR, VNODE_DIR};
+use common::keymap::{Keymap, KEYMAP_DIR, KEYMAP_EXTENSION};
+use common::ipc::compat;
+use common::manifest::{ManifestBuilder, PackageManifest};
+use common::semver::SemVer;
+use common::trust::{Aid, TrustStore};
+use ed25519_dalek::Signer;
+
+use meta::{encode_hex, load_signing_key, PackageMeta};
+
+const USAGE: &str = "usage:
+  axpkg pack <dir> --meta <meta.toml> [-o <out.ax>]
+  axpkg unpack <pkg.ax> -o <dir>
+  axpkg verify <pkg.ax>...
+  axpkg bundle -o <out.axb> <pkg.ax>...
+  axpkg inspect <bundle.axb>
+  axpkg initrd -o <image> [--vnode [<name>=]<elf>]... [--etc <dir>] [--locale <dir>] [--keymaps <dir>] [--cmdline <args>]
+  axpkg check-protocols [--regenerate]";
+
+enum Failure {
+    /// The input is well-formed but doesn't check out.
+    Invalid(String),
+    Usage(String),
+    Io(String),
+}
+
+impl Failure {
+    fn io(path: &Path, e: std::io::Error) -> Self {
+        Failure::Io(format!("{}: {}", path.display(), e))
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("pack") => pack(&args[1..]),
+        Some("unpack") => unpack(&args[1..]),
+        Some("verify") | Some("--verify") => verify(&args[1..]),
+        Some("bundle") => build_bundle(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("initrd") => build_initrd(&args[1..]),
+        Some("check-protocols") => check_protocols(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => Err(Failure::Usage(String::from("missing or unknown command"))),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Invalid(message)) => {
+            eprintln!("axpkg: {}", message);
+            ExitCode::from(1)
+        },
+        Err(Failure::Usage(message)) => {
+            eprintln!("axpkg: {}\n{}", message, USAGE);
+            ExitCode::from(2)
+        },
+        Err(Failure::Io(message)) => {
+            eprintln!("axpkg: {}", message);
+            ExitCode::from(2)
+        },
+    }
+}
+
+/// Splits arguments into positionals and `(flag, value)` pairs. Every flag takes a value.
+fn parse_args<'a>(args: &'a [String], flags: &[&str]) -> Result<(Vec<&'a str>, Vec<(&'a str, &'a str)>), Failure> {
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if flags.contains(&arg.as_str()) {
+            let value = iter.next().ok_or_else(|| Failure::Usage(format!("{} needs a value", arg)))?;
+            options.push((arg.as_str(), value.as_str()));
+        } else if arg.starts_with('-') {
+            return Err(Failure::Usage(format!("unknown option {}", arg)));
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+    Ok((positional, options))
+}
+
+fn option<'a>(options: &[(&str, &'a str)], names: &[&str]) -> Option<&'a str> {
+    options.iter().rev().find(|(flag, _)| names.contains(flag)).map(|(_, value)| *value)
+}
+
+/// Files under `dir` as `(relative path with '/' separators, contents)`, sorted.
+fn collect_files(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, Failure> {
+    fn walk(root: &Path, dir: &Path, out: &mut Vec<(String, Vec<u8>)>) -> Result<(), Failure> {
+        for entry in fs::read_dir(dir).map_err(|e| Failure::io(dir, e))? {
+            let path = entry.map_err(|e| Failure::io(dir, e))?.path();
+            if path.is_dir() {
+                walk(root, &path, out)?;
+                continue;
+            }
+            let relative = path.strip_prefix(root).expect("walked path is under the root");
+            let parts: Option<Vec<&str>> = relative.components().map(|part| part.as_os_str().to_str()).collect();
+            let name = parts.ok_or_else(|| Failure::Io(format!("{}: path is not UTF-8", path.display())))?.join("/");
+            out.push((name, fs::read(&path).map_err(|e| Failure::io(&path, e))?));
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// Compiles each `<lang>.msg` directly under `dir` into its binary catalog.
+/// Other files are ignored, so the sources can sit next to notes for translators.
+fn compile_catalogs(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, Failure> {
+    let mut catalogs = Vec::new();
+    for (name, source) in collect_files(dir)? {
+        let Some(lang) = name.strip_suffix(".msg").filter(|lang| !lang.contains('/')) else { continue };
+        let path = dir.join(&name);
+        let source = String::from_utf8(source).map_err(|_| Failure::Invalid(format!("{}: not UTF-8", path.display())))?;
+        let catalog = Catalog::parse(&source).map_err(|e| Failure::Invalid(format!("{}: {}", path.display(), e)))?;
+        catalogs.push((String::from(lang), catalog.to_bytes()));
+    }
+    Ok(catalogs)
+}
+
+/// The `<name>.keymap` files directly in `dir`, checked but shipped as
+/// written: the display owner parses them when the layout is selected.
+fn check_keymaps(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, Failure> {
+    let mut keymaps = Vec::new();
+    for (file, source) in collect_files(dir)? {
+        let Some(name) = file.strip_suffix(".keymap").filter(|name| !name.contains('/')) else { continue };
+        let path = dir.join(&file);
+        let text = std::str::from_utf8(&source).map_err(|_| Failure::Invalid(format!("{}: not UTF-8", path.display())))?;
+        Keymap::parse(name, text).map_err(|e| Failure::Invalid(format!("{}: {}", path.display(), e)))?;
+        keymaps.push((String::from(name), source));
+    }
+    Ok(keymaps)
+}
+
+/// Everything a loader checks: the archive, the manifest, each chunk's CID,
+/// and the publisher's signature over the root CID.
+fn check(path: &Path) -> Result<ax::Package, Failure> {
+    let bytes = fs::read(path).map_err(|e| Failure::io(path, e))?;
+    let package = ax::unpack(&bytes).map_err(|e| Failure::Invalid(format!("{}: {}", path.display(), e)))?;
+    let manifest = &package.manifest;
+    if !TrustStore::new().verify_signature(&manifest.publisher, manifest.root_cid.as_bytes(), &manifest.signature) {
+        return Err(Failure::Invalid(format!("{}: signature does not match publisher {}", path.display(), encode_hex(&manifest.publisher.0))));
+    }
+    Ok(package)
+}
+
+fn describe(manifest: &PackageManifest) -> String {
+    let mut text = format!(
+        "{} {}: {} files, {} chunks, root {}, publisher {}",
+        manifest.name, manifest.version, manifest.files.len(), manifest.chunk_cids.len(),
+        encode_hex(manifest.root_cid.as_bytes()), encode_hex(&manifest.publisher.0),
+    );
+    let declared = &manifest.capabilities;
+    if !declared.is_empty() {
+        let grants: Vec<String> = declared.grants.iter().map(Grant::to_string).collect();
+        text.push_str(&format!(", asks for [{}] and {} syscalls", grants.join(", "), declared.syscalls.len()));
+    }
+    text
+}
+
+fn pack(args: &[String]) -> Result<(), Failure> {
+    let (positional, options) = parse_args(args, &["--meta", "-o", "--output"])?;
+    let dir = match positional.as_slice() {
+        [dir] => Path::new(dir),
+        _ => return Err(Failure::Usage(String::from("pack takes one directory"))),
+    };
+    let meta_path = option(&options, &["--meta"]).ok_or_else(|| Failure::Usage(String::from("pack needs --meta")))?;
+    let meta = PackageMeta::load(Path::new(meta_path)).map_err(Failure::Io)?;
+    let key = load_signing_key(&meta.key).map_err(Failure::Io)?;
+    let version = SemVer::parse(&meta.version).map_err(|e| Failure::Invalid(format!("{}: version: {}", meta_path, e)))?;
+
+    let files = collect_files(dir)?;
+    let mut builder = ManifestBuilder::new(&meta.name, version)
+        .description(&meta.description)
+        .publisher(Aid(key.verifying_key().to_bytes()));
+    for tag in &meta.tags {
+        builder = builder.tag(tag);
+    }
+    for (path, contents) in &files {
+        builder = builder.file(path, contents);
+    }
+    for spec in &meta.capabilities {
+        let grant = Grant::parse(spec).ok_or_else(|| Failure::Invalid(format!("{}: capabilities: '{}' is not a capability", meta_path, spec)))?;
+        builder = builder.grant(grant);
+    }
+    for name in &meta.syscalls {
+        builder = builder.syscall(name);
+    }
+    let (mut manifest, chunks) = builder.build().map_err(|e| Failure::Invalid(format!("{}: {}", dir.display(), e)))?;
+    manifest.signature = key.sign(manifest.root_cid.as_bytes()).to_bytes().to_vec();
+
+    let out = match option(&options, &["-o", "--output"]) {
+        Some(out) => PathBuf::from(out),
+        None => PathBuf::from(format!("{}-{}.ax", manifest.name, manifest.version)),
+    };
+    fs::write(&out, ax::pack(&manifest, &chunks)).map_err(|e| Failure::io(&out, e))?;
+    // Read it back the way an installer would, so a bad archive never leaves the build.
+    check(&out)?;
+    println!("{}: {}", out.display(), describe(&manifest));
+    Ok(())
+}
+
+fn unpack(args: &[String]) -> Result<(), Failure> {
+    let (positional, options) = parse_args(args, &["-o", "--output"])?;
+    let archive = match positional.as_slice() {
+        [archive] => Path::new(archive),
+        _ => return Err(Failure::Usage(String::from("unpack takes one archive"))),
+    };
+    let out = Path::new(option(&options, &["-o", "--output"]).ok_or_else(|| Failure::Usage(String::from("unpack needs -o")))?);
+    let package = check(archive)?;
+    // Manifest validation already rejected absolute paths and `..`.
+    for (path, contents) in &package.files {
+        let target = out.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| Failure::io(parent, e))?;
+        }
+        fs::write(&target, contents).map_err(|e| Failure::io(&target, e))?;
+    }
+    println!("{}: {}", out.display(), describe(&package.manifest));
+    Ok(())
+}
+
+fn verify(args: &[String]) -> Result<(), Failure> {
+    let (archives, _) = parse_args(args, &[])?;
+    if archives.is_empty() {
+        return Err(Failure::Usage(String::from("verify takes at least one archive")));
+    }
+    // Check them all, so one run reports every bad package.
+    let mut failed = 0;
+    for archive in &archives {
+        match check(Path::new(archive)) {
+            Ok(package) => println!("ok   {}: {}", archive, describe(&package.manifest)),
+            Err(Failure::Invalid(message)) => {
+                println!("FAIL {}", message);
+                failed += 1;
+            },
+            Err(other) => return Err(other),
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(Failure::Invalid(format!("{} of {} packages failed verification", failed, archives.len()))),
+    }
+}
+
+fn build_bundle(args: &[String]) -> Result<(), Failure> {
+    let (archives, options) = parse_args(args, &["-o", "--output"])?;
+    let out = Path::new(option(&options, &["-o", "--output"]).ok_or_else(|| Failure::Usage(String::from("bundle needs -o")))?);
+    if archives.is_empty() {
+        return Err(Failure::Usage(String::from("bundle takes at least one archive")));
+    }
+    let mut packages = Vec::with_capacity(archives.len());
+    for archive in &archives {
+        let package = check(Path::new(archive))?;
+        packages.push((package.manifest, package.chunks));
+    }
+    let image = bundle::build(&packages);
+    fs::write(out, &image).map_err(|e| Failure::io(out, e))?;
+    // As with pack: read back what the registry will read.
+    let parsed = bundle::parse(&image).map_err(|e| Failure::Invalid(format!("built bundle doesn't parse: {}", e)))?;
+    for package in &parsed {
+        println!("{}", describe(&package.manifest));
+    }
+    println!("{}: {} packages, {} bytes", out.display(), parsed.len(), image.len());
+    Ok(())
+}
+
+/// Runs the checks `ImportBundle` does: structure, checksum, chunks and every signature.
+fn inspect(args: &[String]) -> Result<(), Failure> {
+    let (positional, _) = parse_args(args, &[])?;
+    let path = match positional.as_slice() {
+        [path] => Path::new(path),
+        _ => return Err(Failure::Usage(String::from("inspect takes one bundle"))),
+    };
+    let bytes = fs::read(path).map_err(|e| Failure::io(path, e))?;
+    let packages = bundle::parse(&bytes).map_err(|e| Failure::Invalid(format!("{}: {}", path.display(), e)))?;
+    let trust = TrustStore::new();
+    let mut failed = 0;
+    for package in &packages {
+        let manifest = &package.manifest;
+        if trust.verify_signature(&manifest.publisher, manifest.root_cid.as_bytes(), &manifest.signature) {
+            println!("ok   {}", describe(manifest));
+        } else {
+            println!("FAIL {}: signature does not match publisher {}", manifest.name, encode_hex(&manifest.publisher.0));
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => {
+            println!("{}: {} packages, {} bytes", path.display(), packages.len(), bytes.len());
+            Ok(())
+        },
+        _ => Err(Failure::Invalid(format!("{}: {} of {} packages have bad signatures", path.display(), failed, packages.len()))),
+    }
+}
+
+fn build_initrd(args: &[String]) -> Result<(), Failure> {
+    let (positional, options) = parse_args(args, &["-o", "--output", "--vnode", "--etc", "--locale", "--keymaps", "--cmdline"])?;
+    if !positional.is_empty() {
+        return Err(Failure::Usage(String::from("initrd takes its inputs as --vnode, --etc, --locale, --keymaps and --cmdline")));
+    }
+    let out = Path::new(option(&options, &["-o", "--output"]).ok_or_else(|| Failure::Usage(String::from("initrd needs -o")))?);
+
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for (flag, value) in &options {
+        match *flag {
+            "--vnode" => {
+                // The loader looks for /initrd/<name>.bin; the name defaults to the file's stem.
+                let (name, elf) = match value.split_once('=') {
+                    Some((name, elf)) => (String::from(name), Path::new(elf)),
+                    None => {
+                        let elf = Path::new(*value);
+                        let stem = elf.file_stem().and_then(|stem| stem.to_str())
+                            .ok_or_else(|| Failure::Usage(format!("can't name V-Node {}; use <name>=<elf>", value)))?;
+                        (String::from(stem), elf)
+                    },
+                };
+                let binary = fs::read(elf).map_err(|e| Failure::io(elf, e))?;
+                if !binary.starts_with(b"\x7fELF") {
+                    return Err(Failure::Invalid(format!("{}: not an ELF binary", elf.display())));
+                }
+                files.push((format!("{}/{}.bin", VNODE_DIR, name), binary));
+            },
+            "--etc" => {
+                for (path, contents) in collect_files(Path::new(value))? {
+                    files.push((format!("{}/{}", ETC_DIR, path), contents));
+                }
+            },
+            "--locale" => {
+                for (lang, catalog) in compile_catalogs(Path::new(value))? {
+                    files.push((format!("{}/{}/{}", LOCALE_DIR, lang, CATALOG_FILE), catalog));
+                }
+            },
+            "--keymaps" => {
+                for (name, source) in check_keymaps(Path::new(value))? {
+                    files.push((format!("{}/{}.{}", KEYMAP_DIR, name, KEYMAP_EXTENSION), source));
+                }
+            },
+            "--cmdline" => {
+                // Checked with the kernel's own parser, so a typo fails here and not at boot.
+                BootArgs::parse(value).map_err(|e| Failure::Invalid(format!("--cmdline: {}", e)))?;
+                files.push((String::from(BOOT_CMDLINE_PATH), value.as_bytes().to_vec()));
+            },
+            _ => {},
+        }
+    }
+
+    let image = initrd::build(&files).map_err(|e| Failure::Invalid(e.to_string()))?;
+    let parsed = Initrd::parse(&image).map_err(|e| Failure::Invalid(format!("built image doesn't parse: {}", e)))?;
+    fs::write(out, &image).map_err(|e| Failure::io(out, e))?;
+    for (path, data) in parsed.files() {
+        println!("{:>10}  {}", data.len(), path);
+    }
+    println!("{}: {} files, {} bytes", out.display(), parsed.files().len(), image.len());
+    Ok(())
+}
+
+/// Checks every IPC protocol fixture (`common::ipc::compat`). With
+/// `--regenerate`, prints today's encoding of every fixture instead, to paste
+/// over the old bytes after an intentional break.
+fn check_protocols(args: &[String]) -> Result<(), Failure> {
+    let regenerate = match args {
+        [] => false,
+        [flag] if flag == "--regenerate" => true,
+        _ => return Err(Failure::Usage(String::from("check-protocols only takes --regenerate"))),
+    };
+    let fixtures = compat::fixtures();
+    if regenerate {
+        for (index, fixture) in fixtures.iter().enumerate() {
+            println!("{:3} {} => {}", index + 1, fixture.name, fixture.regenerated());
+        }
+        return Ok(());
+    }
+    let mut failed = 0;
+    for (index, fixture) in fixtures.iter().enumerate().filter(|(_, fixture)| !fixture.passed()) {
+        println!("FAIL {:3} {}", index + 1, fixture.name);
+        if fixture.encoded != fixture.golden {
+            println!("     encodes as {}, expected {:?}", fixture.regenerated(), fixture.golden);
+        }
+        if let Some(error) = &fixture.decode_error {
+            println!("     golden bytes {}", error);
+        }
+        failed += 1;
+    }
+    match failed {
+        0 => {
+            println!("ok   {} protocol fixtures", fixtures.len());
+            Ok(())
+        },
+        _ => Err(Failure::Invalid(format!("{} of {} protocol fixtures changed; see docs/system/ipc-compat.md", failed, fixtures.len()))),
+    }
+}
@@ -0,0 +1,79 @@
+// tools/axpkg/src/meta.rs
+
+//! The package metadata file given to `axpkg pack`, and the publisher key it
+//! points to.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::SigningKey;
+use serde::Deserialize;
+
+/// `meta.toml`:
+///
+/// ```toml
+/// name = "hello"
+/// version = "1.0.0"
+/// description = "Prints a greeting."
+/// tags = ["demo"]
+/// key = "keys/publisher.key"
+/// capabilities = ["NetworkAccess", "StorageAccess:/data/hello"]
+/// syscalls = ["SYS_NET_TX"]
+/// ```
+///
+/// `key` is relative to the metadata file. `capabilities` and `syscalls` are
+/// what the package's service asks for, written the way init's service
+/// configuration writes them; packages that don't run as a service leave
+/// them out.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageMeta {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub syscalls: Vec<String>,
+}
+
+impl PackageMeta {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut meta: PackageMeta = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if meta.key.is_relative() {
+            meta.key = path.parent().unwrap_or(Path::new(".")).join(&meta.key);
+        }
+        Ok(meta)
+    }
+}
+
+/// Reads an ed25519 secret key: the 32-byte seed, either raw or as 64 hex
+/// digits (surrounding whitespace is ignored). `head -c 32 /dev/urandom`
+/// makes a new one.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let seed: Vec<u8> = if bytes.len() == 32 {
+        bytes
+    } else {
+        let text = std::str::from_utf8(&bytes).map(str::trim).unwrap_or("");
+        decode_hex(text).ok_or_else(|| format!("{}: expected a 32-byte ed25519 seed, raw or as 64 hex digits", path.display()))?
+    };
+    let seed: [u8; 32] = seed.try_into().map_err(|_| format!("{}: expected a 32-byte ed25519 seed", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,191 @@
+// tools/coredump-inspect/src/main.rs
+
+//! Prints an AetherOS core dump, as init writes them to `/data/crash`:
+//!
+//! ```text
+//! coredump-inspect <file.core> [--around <addr>] [--bytes <n>]
+//! ```
+//!
+//! It shows the header, the registers and the regions, then a hex view of
+//! the memory around the fault address, or the instruction pointer if there
+//! is none, or around `--around`. Exit status is 0 on success, 1 if the file
+//! isn't a dump it can read and 2 for usage and I/O errors.
+
+use std::fs;
+use std::process::ExitCode;
+
+use common::coredump::{self, CoreDump, CORE_FLAG_PARTIAL_REGISTERS, CORE_FLAG_TRUNCATED, CORE_FLAG_WITHHELD, REGION_EXECUTE, REGION_FILE, REGION_READ, REGION_WRITE};
+
+const USAGE: &str = "usage:
+  coredump-inspect <file.core> [--around <addr>] [--bytes <n>]";
+
+/// Bytes shown around the address by default.
+const DEFAULT_BYTES: u64 = 128;
+
+enum Failure {
+    /// The file was read but isn't a dump.
+    Invalid(String),
+    Usage(String),
+    Io(String),
+}
+
+struct Options {
+    path: String,
+    around: Option<u64>,
+    bytes: u64,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|options| inspect(&options));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Invalid(message)) => {
+            eprintln!("coredump-inspect: {}", message);
+            ExitCode::from(1)
+        },
+        Err(Failure::Usage(message)) => {
+            eprintln!("coredump-inspect: {}\n{}", message, USAGE);
+            ExitCode::from(2)
+        },
+        Err(Failure::Io(message)) => {
+            eprintln!("coredump-inspect: {}", message);
+            ExitCode::from(2)
+        },
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, Failure> {
+    let mut path = None;
+    let mut around = None;
+    let mut bytes = DEFAULT_BYTES;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--around" => {
+                let value = iter.next().ok_or_else(|| Failure::Usage(String::from("--around needs an address")))?;
+                around = Some(parse_number(value).ok_or_else(|| Failure::Usage(format!("bad address '{}'", value)))?);
+            },
+            "--bytes" => {
+                let value = iter.next().ok_or_else(|| Failure::Usage(String::from("--bytes needs a count")))?;
+                bytes = parse_number(value).ok_or_else(|| Failure::Usage(format!("bad count '{}'", value)))?;
+            },
+            "help" | "--help" | "-h" => return Err(Failure::Usage(String::from("help"))),
+            flag if flag.starts_with("--") => return Err(Failure::Usage(format!("unknown option {}", flag))),
+            file if path.is_none() => path = Some(file.to_string()),
+            _ => return Err(Failure::Usage(String::from("more than one file"))),
+        }
+    }
+    let path = path.ok_or_else(|| Failure::Usage(String::from("missing the dump file")))?;
+    Ok(Options { path, around, bytes })
+}
+
+/// Decimal, or hex with a `0x` prefix.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => text.replace('_', "").parse().ok(),
+    }
+}
+
+fn inspect(options: &Options) -> Result<(), Failure> {
+    let bytes = fs::read(&options.path).map_err(|e| Failure::Io(format!("{}: {}", options.path, e)))?;
+    let dump = CoreDump::parse(&bytes).map_err(|e| Failure::Invalid(format!("{}: not a core dump it can read: {:?}", options.path, e)))?;
+    let header = &dump.header;
+
+    println!("task       {} ({})", header.task, header.name());
+    println!("reason     {}", coredump::reason_name(header.reason));
+    if header.fault_addr != 0 {
+        println!("fault addr {:#018x}", header.fault_addr);
+    }
+    println!("error code {:#x}", header.error_code);
+    println!("tick       {}", header.tick);
+    let mut notes = Vec::new();
+    if header.flags & CORE_FLAG_WITHHELD != 0 {
+        notes.push("withheld: the service is loaded with the no-dump flag");
+    }
+    if header.flags & CORE_FLAG_TRUNCATED != 0 {
+        notes.push("truncated: some memory was left out to stay under the size cap");
+    }
+    if header.flags & CORE_FLAG_PARTIAL_REGISTERS != 0 {
+        notes.push("partial registers: only rip, rsp, rflags, cs and ss are from the fault");
+    }
+    for note in &notes {
+        println!("note       {}", note);
+    }
+    if header.flags & CORE_FLAG_WITHHELD != 0 {
+        return Ok(());
+    }
+
+    let regs = &dump.registers;
+    println!();
+    println!("registers");
+    let rows = [
+        [("rip", regs.rip), ("rsp", regs.rsp), ("rbp", regs.rbp), ("rflags", regs.rflags)],
+        [("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx), ("rdx", regs.rdx)],
+        [("rsi", regs.rsi), ("rdi", regs.rdi), ("r8", regs.r8), ("r9", regs.r9)],
+        [("r10", regs.r10), ("r11", regs.r11), ("r12", regs.r12), ("r13", regs.r13)],
+        [("r14", regs.r14), ("r15", regs.r15), ("cs", regs.cs), ("ss", regs.ss)],
+    ];
+    for row in rows {
+        let cells: Vec<String> = row.iter().map(|(name, value)| format!("{:>6} {:#018x}", name, value)).collect();
+        println!("  {}", cells.join("  "));
+    }
+
+    println!();
+    println!("regions");
+    for region in &dump.regions {
+        let record = &region.header;
+        println!(
+            "  {:#018x}-{:#018x} {}  {} of {} bytes stored",
+            record.vaddr, record.vaddr + record.memsz, permissions(record.flags), record.stored, record.memsz
+        );
+    }
+
+    let addr = options.around.unwrap_or(if header.fault_addr != 0 { header.fault_addr } else { regs.rip });
+    println!();
+    match dump.region_at(addr) {
+        None => println!("{:#x} is in no region", addr),
+        Some(region) if region.contents.is_empty() => println!("{:#x} is in a region whose contents aren't in the dump", addr),
+        Some(region) => {
+            // Half the view before the address and half after, lined up on 16
+            // bytes; an address past what was stored gets the last of it.
+            let stored_end = region.header.vaddr + region.contents.len() as u64;
+            let start = (addr.min(stored_end - 1).saturating_sub(options.bytes / 2) & !0xf).max(region.header.vaddr);
+            let end = start.saturating_add(options.bytes).min(stored_end);
+            println!("memory around {:#x}", addr);
+            hex_dump(&region.contents[(start - region.header.vaddr) as usize..(end - region.header.vaddr) as usize], start, addr);
+            if addr >= end {
+                println!("{:#x} is past what was stored of its region", addr);
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Like `r-x`, plus `f` for a file mapping.
+fn permissions(flags: u32) -> String {
+    let mut out = String::new();
+    out.push(if flags & REGION_READ != 0 { 'r' } else { '-' });
+    out.push(if flags & REGION_WRITE != 0 { 'w' } else { '-' });
+    out.push(if flags & REGION_EXECUTE != 0 { 'x' } else { '-' });
+    out.push(if flags & REGION_FILE != 0 { 'f' } else { '-' });
+    out
+}
+
+/// 16 bytes a line, with the byte at `mark` in brackets.
+fn hex_dump(bytes: &[u8], base: u64, mark: u64) {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let line_addr = base + line as u64 * 16;
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if line_addr + i as u64 == mark {
+                hex.push_str(&format!("[{:02x}]", byte));
+            } else {
+                hex.push_str(&format!(" {:02x} ", byte));
+            }
+        }
+        let text: String = chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        println!("  {:#018x} {:<64} {}", line_addr, hex, text);
+    }
+}
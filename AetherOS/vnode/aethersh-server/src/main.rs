@@ -0,0 +1,436 @@
+// vnode/aethersh-server/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::vnode::VNodeChannel;
+use common::ipc::IpcSend;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::aethersh_ipc::{self, ClientFrame, FrameReader, ServerFrame, AUTHORIZED_PATH, PROTOCOL_VERSION};
+use common::ipc::init_ipc::{InitRequest, InitResponse, ServiceTarget};
+use common::ipc::session_ipc::{self, AidBytes};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use common::ipc::shell_ipc::{ShellRequest, ShellResponse};
+use common::ipc::socket_ipc::{SocketFd, SocketRequest, SocketResponse};
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::ids::TICKS_PER_SECOND;
+use common::startup;
+use common::trust::{Aid, TrustStore};
+
+mod session;
+use session::{reply_frame, Phase, Session};
+
+/// The init service that backs each connection with a shell.
+const SHELL_SERVICE: &str = "shell-session";
+/// Connections waiting to be accepted.
+const LISTEN_BACKLOG: i32 = 4;
+/// Bytes asked for per `Recv`.
+const RECV_CHUNK: u32 = 4096;
+/// Largest authorized file read.
+const MAX_AUTHORIZED_LEN: u32 = 64 * 1024;
+
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_MAX_SESSIONS: usize = 4;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+/// Ticks since boot; a tick is 10 ms.
+fn now() -> u64 {
+    unsafe { syscall3(SYS_TIME, 0, 0, 0) }
+}
+
+struct AetherShServer {
+    socket_chan: VNodeChannel, // Channel to svc://socket-api
+    init_chan: VNodeChannel, // Channel to svc://init-service, for a shell per session
+    settings_chan: VNodeChannel, // Channel to svc://settings
+    vfs_chan: VNodeChannel, // Channel to svc://vfs, for the authorized file
+    lifecycle: Lifecycle,
+    trust_store: TrustStore, // Checks proofs before they are relayed to the shell
+
+    listener: Option<SocketFd>, // None if the port couldn't be opened; the server then idles
+    sessions: BTreeMap<SocketFd, Session>,
+}
+
+impl AetherShServer {
+    fn new(socket_chan_id: u32, init_chan_id: u32, settings_chan_id: u32, vfs_chan_id: u32, lifecycle_chan_id: Option<u32>) -> Self {
+        log("aethersh-server: Initializing...");
+        let mut server = Self {
+            socket_chan: VNodeChannel::new(socket_chan_id),
+            init_chan: VNodeChannel::new(init_chan_id),
+            settings_chan: VNodeChannel::new(settings_chan_id),
+            vfs_chan: VNodeChannel::new(vfs_chan_id),
+            lifecycle: Lifecycle::new(lifecycle_chan_id),
+            trust_store: TrustStore::new(),
+            listener: None,
+            sessions: BTreeMap::new(),
+        };
+        let port = server.int_setting(aethersh_ipc::PORT_KEY).and_then(|port| u16::try_from(port).ok()).unwrap_or(aethersh_ipc::DEFAULT_PORT);
+        match server.listen(port) {
+            Ok(fd) => {
+                log(&format!("aethersh-server: Listening on port {}. Only loopback clients are served until there is TLS.", port));
+                server.listener = Some(fd);
+            },
+            Err(e) => log(&format!("aethersh-server: Cannot listen on port {}: {}. Serving nothing.", port, e)),
+        }
+        server
+    }
+
+    fn int_setting(&mut self, key: &str) -> Option<i64> {
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Int(value), .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A TCP socket on `port` of every address. Connections from outside
+    /// loopback are still accepted, and refused once their address is known.
+    fn listen(&mut self, port: u16) -> Result<SocketFd, String> {
+        let fd = match self.socket_request(&SocketRequest::Socket { domain: 2, ty: 1, protocol: 0 }) {
+            SocketResponse::Success(fd) => SocketFd::from_raw(fd as u32),
+            other => return Err(format!("{:?}", other)),
+        };
+        for request in [SocketRequest::Bind { fd, addr: [0, 0, 0, 0], port }, SocketRequest::Listen { fd, backlog: LISTEN_BACKLOG }] {
+            match self.socket_request(&request) {
+                SocketResponse::Success(_) => {},
+                SocketResponse::PolicyDenied { rule } => {
+                    self.close_socket(fd);
+                    return Err(format!("denied by network policy rule '{}'", rule));
+                },
+                other => {
+                    self.close_socket(fd);
+                    return Err(format!("{:?}", other));
+                },
+            }
+        }
+        Ok(fd)
+    }
+
+    fn socket_request(&mut self, request: &SocketRequest) -> SocketResponse {
+        self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(request)
+            .unwrap_or_else(|_| SocketResponse::Error(-1, "No response from socket-api".to_string()))
+    }
+
+    fn close_socket(&mut self, fd: SocketFd) {
+        let _ = self.socket_request(&SocketRequest::Close { fd });
+    }
+
+    fn send_frame(&mut self, fd: SocketFd, frame: &ServerFrame) -> Result<(), String> {
+        match self.socket_request(&SocketRequest::Send { fd, data: aethersh_ipc::encode(frame) }) {
+            SocketResponse::Success(_) => Ok(()),
+            SocketResponse::Error(_, message) => Err(message),
+            other => Err(format!("{:?}", other)),
+        }
+    }
+
+    /// Tells a client why it can't have a session and hangs up.
+    fn refuse(&mut self, fd: SocketFd, reason: &str) {
+        let _ = self.send_frame(fd, &ServerFrame::Refused { reason: reason.to_string() });
+        self.close_socket(fd);
+    }
+
+    /// Accepts the connections waiting on the listener and starts a session
+    /// for each one that may have one.
+    fn accept_connections(&mut self) {
+        let Some(listener) = self.listener else { return };
+        loop {
+            let (fd, addr, port) = match self.socket_request(&SocketRequest::Accept { fd: listener }) {
+                SocketResponse::Accepted { new_fd, remote_addr, remote_port } => (new_fd, remote_addr, remote_port),
+                SocketResponse::Error(11, _) => return, // EWOULDBLOCK: nobody is waiting
+                // socket-api has reset the connection already.
+                SocketResponse::PolicyDenied { rule } => {
+                    log(&format!("aethersh-server: A connection was refused by network policy rule '{}'.", rule));
+                    continue;
+                },
+                other => {
+                    log(&format!("aethersh-server: Accept failed: {:?}.", other));
+                    return;
+                },
+            };
+            let [a, b, c, d] = addr;
+            if !aethersh_ipc::is_loopback(addr) {
+                log(&format!("aethersh-server: Refused {}.{}.{}.{}:{}: not a loopback address.", a, b, c, d, port));
+                self.refuse(fd, "without TLS only loopback connections are accepted");
+                continue;
+            }
+            let max_sessions = self.int_setting(aethersh_ipc::MAX_SESSIONS_KEY).map_or(DEFAULT_MAX_SESSIONS, |max| max.max(0) as usize);
+            if self.sessions.len() >= max_sessions {
+                log(&format!("aethersh-server: Refused {}.{}.{}.{}:{}: {} sessions are open.", a, b, c, d, port, self.sessions.len()));
+                self.refuse(fd, "too many sessions");
+                continue;
+            }
+            match self.open_session(fd, (addr, port)) {
+                Ok(session) => {
+                    log(&format!("aethersh-server: {} connected; shell instance {}.", session.peer_name(), session.instance_id));
+                    self.sessions.insert(fd, session);
+                },
+                Err(e) => {
+                    log(&format!("aethersh-server: Refused {}.{}.{}.{}:{}: {}.", a, b, c, d, port, e));
+                    self.refuse(fd, "cannot start a shell");
+                },
+            }
+        }
+    }
+
+    /// Starts the connection's shell and sends the client the challenge the
+    /// shell got from svc://session.
+    fn open_session(&mut self, fd: SocketFd, peer: ([u8; 4], u16)) -> Result<Session, String> {
+        let label = format!("aethersh:{}.{}.{}.{}:{}", peer.0[0], peer.0[1], peer.0[2], peer.0[3], peer.1);
+        let request = InitRequest::ServiceStart { service_name: SHELL_SERVICE.to_string(), instance_label: Some(label) };
+        let (instance_id, channel) = match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&request) {
+            Ok(InitResponse::InstanceStarted { instance_id, channel, .. }) => (instance_id, channel),
+            Ok(InitResponse::Error(message)) => return Err(message),
+            _ => return Err("unexpected response from init".to_string()),
+        };
+        let mut shell = VNodeChannel::new(channel);
+        let result = match shell.send_and_recv::<ShellRequest, ShellResponse>(&ShellRequest::LoginChallenge) {
+            Ok(ShellResponse::Challenge { nonce }) => self.send_frame(fd, &ServerFrame::Challenge { version: PROTOCOL_VERSION, nonce }).map(|()| nonce),
+            Ok(ShellResponse::Error(message)) => Err(message),
+            _ => Err("unexpected response from the shell".to_string()),
+        };
+        let nonce = match result {
+            Ok(nonce) => nonce,
+            Err(e) => {
+                self.stop_shell(instance_id);
+                return Err(e);
+            },
+        };
+        let idle_secs = self.int_setting(aethersh_ipc::IDLE_TIMEOUT_KEY).map_or(DEFAULT_IDLE_TIMEOUT_SECS, |secs| secs.max(0) as u64);
+        let now = now();
+        Ok(Session {
+            peer,
+            instance_id,
+            shell,
+            reader: FrameReader::new(),
+            phase: Phase::Authenticating,
+            nonce,
+            aid: None,
+            opened: now,
+            last_activity: now,
+            idle_timeout_ticks: idle_secs * TICKS_PER_SECOND,
+        })
+    }
+
+    fn stop_shell(&mut self, instance_id: u64) {
+        let request = InitRequest::ServiceStop { target: ServiceTarget::Instance(instance_id) };
+        if !matches!(self.init_chan.send_and_recv::<InitRequest, InitResponse>(&request), Ok(InitResponse::Success(_))) {
+            log(&format!("aethersh-server: Could not stop shell instance {}.", instance_id));
+        }
+    }
+
+    /// Sends `frame`, if any, closes the connection and stops its shell.
+    fn end_session(&mut self, fd: SocketFd, frame: Option<ServerFrame>) {
+        let Some(session) = self.sessions.remove(&fd) else { return };
+        if let Some(frame) = frame {
+            let _ = self.send_frame(fd, &frame);
+        }
+        self.close_socket(fd);
+        self.stop_shell(session.instance_id);
+        match session.aid {
+            Some(aid) => log(&format!("aethersh-server: Session of {} from {} ended.", session_ipc::aid_to_hex(&aid), session.peer_name())),
+            None => log(&format!("aethersh-server: Session with {} ended before logging in.", session.peer_name())),
+        }
+    }
+
+    /// The Aids in the authorized file, read anew for every login so that
+    /// removing a line takes effect at once. A missing file authorizes nobody.
+    fn authorized(&mut self) -> Vec<AidBytes> {
+        let open = VfsRequest::Open { path: AUTHORIZED_PATH.to_string(), flags: 0 };
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&open) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            _ => return Vec::new(),
+        };
+        let read = VfsRequest::Read { fd, len: MAX_AUTHORIZED_LEN, offset: 0 };
+        let text = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&read) {
+            Ok(VfsResponse::Data(data)) => String::from_utf8_lossy(&data).into_owned(),
+            _ => String::new(),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        let (aids, rejected) = aethersh_ipc::parse_authorized(&text);
+        for line in rejected {
+            log(&format!("aethersh-server: {} line {} is not an Aid; skipped.", AUTHORIZED_PATH, line));
+        }
+        aids
+    }
+
+    /// Logs the session's shell in as `aid`, once the Aid is authorized and
+    /// `proof` signs the challenge's nonce. svc://session checks the proof
+    /// again when the shell relays it, and binds the Aid to the shell.
+    fn authenticate(&mut self, fd: SocketFd, aid: AidBytes, proof: Vec<u8>) -> Result<String, String> {
+        if !self.authorized().contains(&aid) {
+            return Err(format!("{} is not authorized", session_ipc::aid_to_hex(&aid)));
+        }
+        let Some(session) = self.sessions.get_mut(&fd) else { return Err("no session".to_string()) };
+        if !self.trust_store.verify_signature(&Aid(aid), &session.nonce, &proof) {
+            return Err("the proof doesn't match the Aid".to_string());
+        }
+        match session.shell.send_and_recv::<ShellRequest, ShellResponse>(&ShellRequest::Login { aid, proof }) {
+            Ok(ShellResponse::Success(_)) => {},
+            Ok(ShellResponse::Error(message)) => return Err(message),
+            _ => return Err("unexpected response from the shell".to_string()),
+        }
+        session.aid = Some(aid);
+        match session.shell.send_and_recv::<ShellRequest, ShellResponse>(&ShellRequest::GetCurrentDirectory) {
+            Ok(ShellResponse::CurrentDirectory(cwd)) => Ok(cwd),
+            _ => Ok(session_ipc::home_dir(&aid)),
+        }
+    }
+
+    /// Moves a session along: reads from its socket, hands complete frames
+    /// to the shell, and relays the shell's reply. Ends it on a timeout, a
+    /// protocol error or `Bye`.
+    fn poll_session(&mut self, fd: SocketFd) {
+        let data = match self.socket_request(&SocketRequest::Recv { fd, len: RECV_CHUNK }) {
+            SocketResponse::Data(data) => data,
+            other => {
+                log(&format!("aethersh-server: Connection lost: {:?}.", other));
+                return self.end_session(fd, None);
+            },
+        };
+        let Some(session) = self.sessions.get_mut(&fd) else { return };
+        let now = now();
+        session.reader.push(&data);
+
+        if session.phase == Phase::Running {
+            let reply = match session.shell.recv_non_blocking() {
+                Ok(Some(reply)) => reply,
+                Ok(None) => return,
+                Err(()) => return self.end_session(fd, Some(ServerFrame::Closed { reason: "the shell is gone".to_string() })),
+            };
+            let frame = match postcard::from_bytes::<ShellResponse>(&reply) {
+                Ok(response) => reply_frame(response),
+                Err(_) => return,
+            };
+            session.phase = if matches!(frame, ServerFrame::Prompt { .. }) { Phase::Prompted } else { Phase::Idle };
+            session.last_activity = now;
+            if let Err(e) = self.send_frame(fd, &frame) {
+                log(&format!("aethersh-server: Could not send a reply: {}.", e));
+                return self.end_session(fd, None);
+            }
+            return;
+        }
+
+        let frame = match session.reader.next::<ClientFrame>() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                let timeout = match session.phase {
+                    Phase::Authenticating if now.saturating_sub(session.opened) >= aethersh_ipc::AUTH_TIMEOUT_SECS * TICKS_PER_SECOND => Some(ServerFrame::Refused { reason: "authentication timed out".to_string() }),
+                    // There is no way to tell that a client hung up without saying
+                    // Bye, so this is also how such a session ends.
+                    Phase::Idle | Phase::Prompted if now.saturating_sub(session.last_activity) >= session.idle_timeout_ticks => Some(ServerFrame::Closed { reason: format!("idle for {} s", session.idle_timeout_ticks / TICKS_PER_SECOND) }),
+                    _ => None,
+                };
+                if timeout.is_some() {
+                    log(&format!("aethersh-server: {} timed out.", session.peer_name()));
+                    self.end_session(fd, timeout);
+                }
+                return;
+            },
+            Err(e) => {
+                log(&format!("aethersh-server: Bad frame from {}: {:?}.", session.peer_name(), e));
+                return self.end_session(fd, Some(ServerFrame::Closed { reason: "malformed frame".to_string() }));
+            },
+        };
+        session.last_activity = now;
+        let request = match (session.phase, frame) {
+            (_, ClientFrame::Bye) => return self.end_session(fd, None),
+            (Phase::Authenticating, ClientFrame::Auth { aid, proof }) => {
+                let peer = session.peer_name();
+                match self.authenticate(fd, aid, proof) {
+                    Ok(cwd) => {
+                        log(&format!("aethersh-server: {} logged in as {}.", peer, session_ipc::aid_to_hex(&aid)));
+                        if let Some(session) = self.sessions.get_mut(&fd) {
+                            session.phase = Phase::Idle;
+                        }
+                        if self.send_frame(fd, &ServerFrame::Authenticated { cwd }).is_err() {
+                            self.end_session(fd, None);
+                        }
+                    },
+                    Err(e) => {
+                        log(&format!("aethersh-server: Login from {} refused: {}.", peer, e));
+                        self.end_session(fd, Some(ServerFrame::Refused { reason: "authentication failed".to_string() }));
+                    },
+                }
+                return;
+            },
+            (Phase::Authenticating, _) => return self.end_session(fd, Some(ServerFrame::Refused { reason: "authenticate first".to_string() })),
+            (_, ClientFrame::Auth { .. }) => return self.end_session(fd, Some(ServerFrame::Closed { reason: "already authenticated".to_string() })),
+            (_, ClientFrame::Line { line }) => ShellRequest::ExecuteLine { line },
+            (_, ClientFrame::Answer { text }) => ShellRequest::Answer { text },
+        };
+        // The reply is picked up by later polls, so a long command doesn't hold up other sessions.
+        if session.shell.send(&request).is_err() {
+            return self.end_session(fd, Some(ServerFrame::Closed { reason: "the shell is gone".to_string() }));
+        }
+        session.phase = Phase::Running;
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("aethersh-server: Entering main event loop.");
+        loop {
+            self.accept_connections();
+            let open: Vec<SocketFd> = self.sessions.keys().copied().collect();
+            for fd in open {
+                self.poll_session(fd);
+            }
+
+            if let Some(request) = self.lifecycle.shutdown_requested() {
+                let open: Vec<SocketFd> = self.sessions.keys().copied().collect();
+                for fd in open {
+                    self.end_session(fd, Some(ServerFrame::Closed { reason: "the server is shutting down".to_string() }));
+                }
+                if let Some(listener) = self.listener.take() {
+                    self.close_socket(listener);
+                }
+                self.lifecycle.stopped(request);
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            now();
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback:
+    // 4 for the Socket API
+    // 6 for the Init Service
+    // 14 for Settings
+    // 7 for the VFS
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut server = AetherShServer::new(
+        channel("socket-api", 4),
+        channel("init-service", 6),
+        channel("settings", 14),
+        channel("vfs", 7),
+        channels.get(LIFECYCLE_CHANNEL).copied(),
+    );
+    server.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("aethersh-server V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
@@ -0,0 +1,98 @@
+// vnode/aethersh-server/src/session.rs
+
+//! One connection: its socket, the shell-session instance behind it, and
+//! how far the conversation has got. main.rs moves frames between them;
+//! what a shell reply becomes on the wire is decided here.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use common::ipc::aethersh_ipc::{FrameReader, ServerFrame, MAX_FRAME_LEN};
+use common::ipc::session_ipc::AidBytes;
+use common::ipc::shell_ipc::ShellResponse;
+use common::ipc::vnode::VNodeChannel;
+
+/// Room left in a frame for everything but the output text.
+const FRAME_OVERHEAD: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// `Challenge` sent; nothing but `Auth` is accepted.
+    Authenticating,
+    /// Waiting for the client's next `Line`.
+    Idle,
+    /// The shell is running a line or an answer. Further frames wait in
+    /// the reader until it replies.
+    Running,
+    /// The shell asked a question; the client's `Answer` goes to it.
+    Prompted,
+}
+
+/// A connection, keyed by its socket in the server's session table.
+pub struct Session {
+    pub peer: ([u8; 4], u16),
+    /// The shell-session instance, and its channel.
+    pub instance_id: u64,
+    pub shell: VNodeChannel,
+    pub reader: FrameReader,
+    pub phase: Phase,
+    /// The nonce in the `Challenge` this client was sent.
+    pub nonce: AidBytes,
+    /// Set once the shell is logged in.
+    pub aid: Option<AidBytes>,
+    /// When the connection was accepted, and when the client last sent a
+    /// frame or got a reply, in ticks.
+    pub opened: u64,
+    pub last_activity: u64,
+    pub idle_timeout_ticks: u64,
+}
+
+impl Session {
+    /// `a.b.c.d:port`, for log lines.
+    pub fn peer_name(&self) -> String {
+        let ([a, b, c, d], port) = self.peer;
+        format!("{}.{}.{}.{}:{}", a, b, c, d, port)
+    }
+}
+
+/// What the client is sent for the shell's reply to a line or an answer.
+pub fn reply_frame(response: ShellResponse) -> ServerFrame {
+    let (stdout, stderr, exit_code) = match response {
+        ShellResponse::Prompt { message } => return ServerFrame::Prompt { message },
+        ShellResponse::CommandOutput { stdout, stderr, exit_code } => (stdout, stderr, exit_code),
+        ShellResponse::Success(message) if message.is_empty() => (message, String::new(), 0),
+        ShellResponse::Success(message) => (format!("{}\n", message), String::new(), 0),
+        ShellResponse::Error(message) => (String::new(), format!("{}\n", message), 1),
+        other => (String::new(), format!("aethersh: unexpected reply from the shell: {:?}\n", other), 1),
+    };
+    let (stdout, stderr) = fit_output(stdout, stderr);
+    ServerFrame::Output { stdout, stderr, exit_code }
+}
+
+/// Cuts output that wouldn't fit in a frame, stdout before stderr, and
+/// says so at the end of stderr.
+fn fit_output(mut stdout: String, mut stderr: String) -> (String, String) {
+    let total = stdout.len() + stderr.len();
+    let limit = MAX_FRAME_LEN - FRAME_OVERHEAD;
+    if total <= limit {
+        return (stdout, stderr);
+    }
+    let note = format!("aethersh: output cut to {} of {} bytes\n", limit, total);
+    let limit = limit - note.len();
+    truncate_at_char(&mut stderr, limit);
+    truncate_at_char(&mut stdout, limit - stderr.len());
+    stderr.push_str(&note);
+    (stdout, stderr)
+}
+
+fn truncate_at_char(text: &mut String, max: usize) {
+    if text.len() > max {
+        let mut end = max;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+}
@@ -0,0 +1,293 @@
+// vnode/audio-mixer/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::format;
+use alloc::string::ToString;
+
+use common::channels;
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, SYS_AUDIO_OPEN, SYS_AUDIO_QUEUE, SYS_GET_DMA_BUF_PTR, E_BUSY, E_ERROR, AudioRing, AUDIO_RING_LEN};
+use common::ipc::audio_ipc::{AudioRequest, AudioResponse, AudioStats, MASTER_VOLUME_KEY, MAX_PCM_CHUNK};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue, SettingChanged};
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::startup::{self, SELF_CHANNEL};
+
+mod mixer;
+use mixer::Mixer;
+
+/// Master volume until the settings service says otherwise.
+const DEFAULT_MASTER_VOLUME: u8 = 80;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+/// The sound card's output ring, and how far through it we are.
+struct Device {
+    ring: AudioRing,
+    base: *mut u8,
+    queued: u64, // Periods queued since open
+    completed: u64, // Periods played, as far as we know
+    next_period: u32,
+}
+
+impl Device {
+    /// Takes over the sound card's output. Completions arrive on `irq_chan_id`.
+    fn open(irq_chan_id: u32) -> Result<Self, u64> {
+        let mut record = [0u8; AUDIO_RING_LEN];
+        let res = unsafe { syscall3(SYS_AUDIO_OPEN, irq_chan_id as u64, record.as_mut_ptr() as u64, record.len() as u64) };
+        if res != SUCCESS {
+            return Err(res);
+        }
+        let ring = AudioRing::from_bytes(&record).ok_or(E_ERROR)?;
+        let base = unsafe { syscall3(SYS_GET_DMA_BUF_PTR, ring.handle, 0, 0) };
+        if base == E_ERROR {
+            return Err(E_ERROR);
+        }
+        Ok(Self { ring, base: base as *mut u8, queued: 0, completed: 0, next_period: 0 })
+    }
+
+    fn in_flight(&self) -> u64 {
+        self.queued - self.completed
+    }
+
+    /// The next period to fill, as samples.
+    fn next_period_samples(&mut self) -> &mut [i16] {
+        let period_bytes = self.ring.period_bytes as usize;
+        // SAFETY: The ring is `periods * period_bytes` bytes, 16-bit samples
+        // aligned, and the card doesn't read a period until it is queued.
+        unsafe {
+            let start = self.base.add(self.next_period as usize * period_bytes) as *mut i16;
+            core::slice::from_raw_parts_mut(start, period_bytes / 2)
+        }
+    }
+
+    /// Queues the period just filled. False if the ring is full.
+    fn queue_next(&mut self) -> Result<bool, u64> {
+        let res = unsafe { syscall3(SYS_AUDIO_QUEUE, self.next_period as u64, self.ring.period_bytes as u64, 0) };
+        if res == E_BUSY {
+            return Ok(false);
+        }
+        if res > u64::MAX / 2 {
+            return Err(res);
+        }
+        self.completed = res;
+        self.queued += 1;
+        self.next_period = (self.next_period + 1) % self.ring.periods;
+        Ok(true)
+    }
+}
+
+struct AudioMixerService {
+    client_chan: VNodeChannel, // PlayPcm, PlayTone and the rest from apps
+    irq_chan: VNodeChannel, // A notification per period the card finished
+    bus_events_chan: VNodeChannel, // settings.audio.* changes
+    lifecycle: Lifecycle, // Shutdown requests from init
+    device: Option<Device>,
+    mixer: Mixer,
+    master_volume: u8,
+    playing: bool, // Something was queued and hasn't all played
+    periods_played: u64,
+    underruns: u64,
+}
+
+impl AudioMixerService {
+    fn new(client_chan_id: u32, irq_chan_id: u32, event_bus_chan_id: u32, settings_chan_id: u32, bus_events_chan_id: u32, lifecycle_chan_id: Option<u32>) -> Self {
+        log("Audio Mixer: Initializing...");
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let bus_events_chan = VNodeChannel::new(bus_events_chan_id);
+
+        let device = match Device::open(irq_chan_id) {
+            Ok(device) => {
+                log(&format!("Audio Mixer: Output is {} Hz, {} channels, {} periods of {} bytes.", device.ring.sample_rate, device.ring.channels, device.ring.periods, device.ring.period_bytes));
+                Some(device)
+            },
+            Err(e) => {
+                log(&format!("Audio Mixer: No audio output ({:#x}); requests to play will fail.", e));
+                None
+            },
+        };
+        let mixer = match &device {
+            Some(device) => Mixer::new(device.ring.sample_rate, device.ring.channels as usize),
+            None => Mixer::new(48_000, 2),
+        };
+
+        let master_volume = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: MASTER_VOLUME_KEY.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Int(volume), .. }) => volume.clamp(0, 100) as u8,
+            _ => DEFAULT_MASTER_VOLUME,
+        };
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: "settings.audio.".to_string(), reply_chan: bus_events_chan.id };
+        if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("Audio Mixer: Failed to subscribe to settings changes; the master volume stays as it is.");
+        }
+
+        Self {
+            client_chan: VNodeChannel::new(client_chan_id),
+            irq_chan: VNodeChannel::new(irq_chan_id),
+            bus_events_chan,
+            lifecycle: Lifecycle::new(lifecycle_chan_id),
+            device,
+            mixer,
+            master_volume,
+            playing: false,
+            periods_played: 0,
+            underruns: 0,
+        }
+    }
+
+    fn handle_request(&mut self, request: AudioRequest) -> AudioResponse {
+        if self.device.is_none() && !matches!(request, AudioRequest::Stats) {
+            return AudioResponse::Error("There is no audio output device.".to_string());
+        }
+        match request {
+            AudioRequest::PlayPcm { sample_rate, channels, volume, data, more } => {
+                if data.len() > MAX_PCM_CHUNK {
+                    return AudioResponse::Error(format!("At most {} bytes of PCM per request.", MAX_PCM_CHUNK));
+                }
+                match self.mixer.play_pcm(sample_rate, channels, volume, &data, more) {
+                    Ok(id) => AudioResponse::Playing { id },
+                    Err(e) => AudioResponse::Error(e.to_string()),
+                }
+            },
+            AudioRequest::AppendPcm { id, data, more } => match self.mixer.append_pcm(id, &data, more) {
+                Ok(()) => AudioResponse::Success,
+                Err(e) => AudioResponse::Error(e.to_string()),
+            },
+            AudioRequest::PlayTone { freq_hz, duration_ms, volume } => match self.mixer.play_tone(freq_hz, duration_ms, volume) {
+                Ok(id) => AudioResponse::Playing { id },
+                Err(e) => AudioResponse::Error(e.to_string()),
+            },
+            AudioRequest::Stop { id } => {
+                if self.mixer.stop(id) {
+                    AudioResponse::Success
+                } else {
+                    AudioResponse::Error(format!("Stream {} is not playing.", id))
+                }
+            },
+            AudioRequest::Stats => AudioResponse::Stats(AudioStats {
+                device: self.device.is_some(),
+                sample_rate: self.device.as_ref().map_or(0, |device| device.ring.sample_rate),
+                active_streams: self.mixer.active() as u32,
+                master_volume: self.master_volume,
+                periods_played: self.periods_played,
+                underruns: self.underruns,
+                starved: self.mixer.starved(),
+            }),
+        }
+    }
+
+    fn handle_bus_events(&mut self) {
+        while let Ok(Some(event_data)) = self.bus_events_chan.recv_non_blocking() {
+            let Ok(event) = postcard::from_bytes::<Event>(&event_data) else {
+                continue;
+            };
+            if event.topic != format!("settings.{}", MASTER_VOLUME_KEY) {
+                continue;
+            }
+            if let Ok(SettingChanged { value: SettingValue::Int(volume), .. }) = postcard::from_bytes(&event.payload) {
+                self.master_volume = volume.clamp(0, 100) as u8;
+                log(&format!("Audio Mixer: Master volume set to {}%.", self.master_volume));
+            }
+        }
+    }
+
+    /// Counts finished periods and keeps the ring full while anything plays.
+    fn pump(&mut self) {
+        let Some(device) = self.device.as_mut() else {
+            return;
+        };
+        // Each notification is at least one finished period; queueing returns the exact count.
+        while let Ok(Some(_)) = self.irq_chan.recv_non_blocking() {
+            device.completed = (device.completed + 1).min(device.queued);
+        }
+        while self.mixer.active() > 0 && device.in_flight() < device.ring.periods as u64 {
+            if self.playing && device.in_flight() == 0 {
+                // The card played everything we gave it before we gave it more.
+                self.underruns += 1;
+                if self.underruns == 1 {
+                    log("Audio Mixer: Output underrun; further underruns are only counted.");
+                }
+            }
+            self.mixer.mix(device.next_period_samples(), self.master_volume);
+            match device.queue_next() {
+                Ok(true) => {
+                    self.playing = true;
+                    self.periods_played += 1;
+                },
+                Ok(false) => break, // Full after all; the next notification makes room
+                Err(e) => {
+                    log(&format!("Audio Mixer: Failed to queue a period: {:#x}.", e));
+                    break;
+                },
+            }
+        }
+        if self.mixer.active() == 0 && device.in_flight() == 0 {
+            self.playing = false;
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Audio Mixer: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                if let Ok(request) = postcard::from_bytes::<AudioRequest>(&req_data) {
+                    let response = self.handle_request(request);
+                    self.client_chan.send(&response).unwrap_or_else(|_| log("Audio Mixer: Failed to send response to client."));
+                } else {
+                    log("Audio Mixer: Failed to deserialize AudioRequest.");
+                }
+            }
+
+            self.handle_bus_events();
+            self.pump();
+
+            // Nothing new gets queued, so the card stops once the periods already queued have played.
+            if let Some(request) = self.lifecycle.shutdown_requested() {
+                log(&format!("Audio Mixer: Stopping {} streams for shutdown.", self.mixer.active()));
+                self.mixer.stop_all();
+                self.lifecycle.stopped(request);
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let mut service = AudioMixerService::new(
+        channel(SELF_CHANNEL, channels::AUDIO_MIXER),
+        channels::AUDIO_MIXER_IRQ,
+        channel("event-bus", channels::EVENT_BUS),
+        channel("settings", channels::SETTINGS),
+        channels::AUDIO_MIXER_EVENTS,
+        assigned.get(LIFECYCLE_CHANNEL).copied(),
+    );
+    service.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Audio Mixer V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
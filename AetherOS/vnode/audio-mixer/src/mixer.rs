@@ -0,0 +1,225 @@
+// vnode/audio-mixer/src/mixer.rs
+
+//! Streams and how they are mixed, apart from the device and IPC around it.
+//!
+//! Every stream is converted to the output format as it is mixed: its
+//! samples are resampled by linear interpolation to the output rate, mono is
+//! spread over both output channels and stereo is folded for a mono output.
+//! Each frame is scaled by the stream's volume, the streams are summed, and
+//! the sum is scaled by the master volume and clamped to 16 bits.
+//!
+//! A PCM stream ends when its data has played, unless it is still open for
+//! more. An open stream that runs dry plays silence until more arrives, and
+//! counts as starved once per gap. Tones are a triangle wave with 5 ms fades
+//! at both ends, so they don't click.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use common::ipc::audio_ipc::{StreamId, MAX_SAMPLE_RATE, MAX_TONE_HZ, MAX_TONE_MS, MIN_SAMPLE_RATE, MIN_TONE_HZ};
+
+/// Streams playing at once; more are refused.
+pub const MAX_STREAMS: usize = 16;
+/// Data a PCM stream may have waiting, in bytes.
+pub const MAX_BUFFERED_BYTES: usize = 256 * 1024;
+
+const FRACTION_BITS: u32 = 32; // Stream positions are 32.32 fixed point, in source frames
+const FADE_MS: u32 = 5;
+
+enum Source {
+    Pcm { frames: VecDeque<[i16; 2]>, channels: u8, more: bool },
+    Tone { phase: u32, step: u32, played: u64, total: u64, fade: u64 },
+}
+
+struct Stream {
+    id: StreamId,
+    volume: i32, // Percent
+    step: u64, // Source frames per output frame, 32.32
+    position: u64, // Fraction of the way from `frames[0]` to `frames[1]`
+    starved: bool,
+    source: Source,
+}
+
+impl Stream {
+    /// The next output frame, or `None` once the stream is over.
+    fn next_frame(&mut self) -> Option<[i32; 2]> {
+        match &mut self.source {
+            Source::Pcm { frames, more, .. } => {
+                let Some(first) = frames.front().copied() else {
+                    if !*more {
+                        return None;
+                    }
+                    self.starved = true;
+                    return Some([0, 0]);
+                };
+                self.starved = false;
+                let second = frames.get(1).copied().unwrap_or(first);
+                let fraction = (self.position & ((1 << FRACTION_BITS) - 1)) as i64;
+                let frame = [0, 1].map(|c| {
+                    let (a, b) = (first[c] as i64, second[c] as i64);
+                    (a + (((b - a) * fraction) >> FRACTION_BITS)) as i32
+                });
+                self.position += self.step;
+                let consumed = (self.position >> FRACTION_BITS) as usize;
+                frames.drain(..consumed.min(frames.len()));
+                self.position &= (1 << FRACTION_BITS) - 1;
+                Some(frame)
+            },
+            Source::Tone { phase, step, played, total, fade } => {
+                if *played >= *total {
+                    return None;
+                }
+                // Triangle wave over the full 16-bit range.
+                let p = (*phase >> 16) as i32;
+                let wave = if p < 0x8000 { p * 2 - 0x8000 } else { (0xFFFF - p) * 2 - 0x8000 };
+                let envelope = (*played + 1).min(*total - *played).min(*fade) as i32;
+                let sample = (wave as i64 * envelope as i64 / *fade as i64) as i32;
+                *phase = phase.wrapping_add(*step);
+                *played += 1;
+                Some([sample, sample])
+            },
+        }
+    }
+
+    fn finished(&self) -> bool {
+        match &self.source {
+            Source::Pcm { frames, more, .. } => frames.is_empty() && !*more,
+            Source::Tone { played, total, .. } => played >= total,
+        }
+    }
+}
+
+pub struct Mixer {
+    output_rate: u32,
+    output_channels: usize, // 1 or 2
+    streams: Vec<Stream>,
+    next_id: StreamId,
+    starved: u64,
+}
+
+impl Mixer {
+    pub fn new(output_rate: u32, output_channels: usize) -> Self {
+        Self { output_rate, output_channels: output_channels.clamp(1, 2), streams: Vec::new(), next_id: 1, starved: 0 }
+    }
+
+    pub fn active(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Times an open stream ran dry.
+    pub fn starved(&self) -> u64 {
+        self.starved
+    }
+
+    fn add(&mut self, volume: u8, step: u64, source: Source) -> Result<StreamId, &'static str> {
+        if self.streams.len() >= MAX_STREAMS {
+            return Err("Too many streams are playing.");
+        }
+        if volume > 100 {
+            return Err("Volume is a percentage, 0 to 100.");
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.streams.push(Stream { id, volume: volume as i32, step, position: 0, starved: false, source });
+        Ok(id)
+    }
+
+    /// Starts a PCM stream. See `AudioRequest::PlayPcm`.
+    pub fn play_pcm(&mut self, sample_rate: u32, channels: u8, volume: u8, data: &[u8], more: bool) -> Result<StreamId, &'static str> {
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+            return Err("Unsupported sample rate.");
+        }
+        if channels != 1 && channels != 2 {
+            return Err("Only mono and stereo are supported.");
+        }
+        let mut frames = VecDeque::new();
+        decode(data, channels, &mut frames)?;
+        let step = ((sample_rate as u64) << FRACTION_BITS) / self.output_rate as u64;
+        self.add(volume, step, Source::Pcm { frames, channels, more })
+    }
+
+    /// Adds data to a stream started with `more`.
+    pub fn append_pcm(&mut self, id: StreamId, data: &[u8], more: bool) -> Result<(), &'static str> {
+        let stream = self.streams.iter_mut().find(|stream| stream.id == id).ok_or("No such stream.")?;
+        let Source::Pcm { frames, channels, more: open } = &mut stream.source else {
+            return Err("Not a PCM stream.");
+        };
+        if !*open {
+            return Err("The stream was not left open for more data.");
+        }
+        if (frames.len() * 4) + data.len() > MAX_BUFFERED_BYTES {
+            return Err("Too much data is waiting; try again once some has played.");
+        }
+        decode(data, *channels, frames)?;
+        *open = more;
+        Ok(())
+    }
+
+    /// Starts a tone. See `AudioRequest::PlayTone`.
+    pub fn play_tone(&mut self, freq_hz: u32, duration_ms: u32, volume: u8) -> Result<StreamId, &'static str> {
+        if !(MIN_TONE_HZ..=MAX_TONE_HZ).contains(&freq_hz) || duration_ms == 0 || duration_ms > MAX_TONE_MS {
+            return Err("Tone frequency or length out of range.");
+        }
+        let rate = self.output_rate as u64;
+        let total = rate * duration_ms as u64 / 1000;
+        let fade = (rate * FADE_MS as u64 / 1000).min(total / 2).max(1);
+        let step = ((freq_hz as u64) << 32) / rate;
+        self.add(volume, 0, Source::Tone { phase: 0, step: step as u32, played: 0, total, fade })
+    }
+
+    pub fn stop(&mut self, id: StreamId) -> bool {
+        let before = self.streams.len();
+        self.streams.retain(|stream| stream.id != id);
+        self.streams.len() != before
+    }
+
+    pub fn stop_all(&mut self) {
+        self.streams.clear();
+    }
+
+    /// Mixes the next `out.len()` samples (interleaved frames) at
+    /// `master_volume` percent. What no stream covers is silence. Finished
+    /// streams are dropped afterwards.
+    pub fn mix(&mut self, out: &mut [i16], master_volume: u8) {
+        let channels = self.output_channels;
+        let mut sum = vec![0i32; out.len()];
+        for stream in &mut self.streams {
+            let was_starved = stream.starved;
+            for frame in sum.chunks_mut(channels) {
+                let Some([left, right]) = stream.next_frame() else {
+                    break;
+                };
+                if channels == 2 {
+                    frame[0] += left * stream.volume / 100;
+                    frame[1] += right * stream.volume / 100;
+                } else {
+                    frame[0] += (left + right) / 2 * stream.volume / 100;
+                }
+            }
+            if stream.starved && !was_starved {
+                self.starved += 1;
+            }
+        }
+        for (sample, total) in out.iter_mut().zip(sum) {
+            *sample = (total * master_volume as i32 / 100).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        self.streams.retain(|stream| !stream.finished());
+    }
+}
+
+/// Appends 16-bit little-endian samples to `frames`, spreading mono to both sides.
+fn decode(data: &[u8], channels: u8, frames: &mut VecDeque<[i16; 2]>) -> Result<(), &'static str> {
+    let frame_bytes = 2 * channels as usize;
+    if data.len() % frame_bytes != 0 {
+        return Err("PCM data must be whole 16-bit frames.");
+    }
+    for frame in data.chunks_exact(frame_bytes) {
+        let left = i16::from_le_bytes([frame[0], frame[1]]);
+        let right = if channels == 2 { i16::from_le_bytes([frame[2], frame[3]]) } else { left };
+        frames.push_back([left, right]);
+    }
+    Ok(())
+}
@@ -0,0 +1,296 @@
+// vnode/dns-resolver/src/cache.rs
+
+//! The resolver's cache, for unicast and mDNS answers alike.
+//!
+//! A name maps to its addresses, to the name it is an alias of (a CNAME), or
+//! to the fact that it has no address. `get` follows aliases to the
+//! addresses. Every entry expires on its own. The cache holds at most
+//! `MAX_CACHE_ENTRIES` names; to make room the expired ones go first, then
+//! the one closest to expiring.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::wire::Answer;
+
+pub const MAX_CACHE_ENTRIES: usize = 256;
+/// Most aliases `get` follows, in case the cached ones form a loop.
+const MAX_ALIAS_HOPS: usize = 8;
+
+/// How long "no such host" is cached when the server sent no SOA record to
+/// say, and the most it is cached for whatever the SOA says.
+pub const NEGATIVE_TTL_SECS: u32 = 30;
+pub const MAX_NEGATIVE_TTL_SECS: u32 = 300;
+
+/// How long a negative answer is cached, given the TTL the server's SOA
+/// record allows, if it sent one.
+pub fn negative_ttl_secs(soa_ttl_secs: Option<u32>) -> u32 {
+    soa_ttl_secs.unwrap_or(NEGATIVE_TTL_SECS).min(MAX_NEGATIVE_TTL_SECS)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cached {
+    /// The name's addresses, in preference order.
+    Addresses(Vec<[u8; 4]>),
+    /// The name is an alias of this one.
+    Alias(String),
+    /// The name doesn't exist, or has no address.
+    NotFound,
+}
+
+/// What the cache knows about a name.
+pub enum Lookup {
+    Addresses(Vec<[u8; 4]>),
+    NotFound,
+    Miss,
+}
+
+struct Entry {
+    value: Cached,
+    expires_at_ms: u64,
+}
+
+#[derive(Default)]
+pub struct DnsCache {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl DnsCache {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Drops every entry. Returns how many there were.
+    pub fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.remove(name);
+    }
+
+    /// What is cached for `name`, following aliases. An expired entry on the
+    /// way is dropped and makes it a miss.
+    pub fn get(&mut self, name: &str, now_ms: u64) -> Lookup {
+        let mut name = String::from(name);
+        for _ in 0..=MAX_ALIAS_HOPS {
+            let target = match self.entries.get(&name) {
+                None => return Lookup::Miss,
+                Some(entry) if entry.expires_at_ms <= now_ms => {
+                    self.entries.remove(&name);
+                    return Lookup::Miss;
+                },
+                Some(Entry { value: Cached::Addresses(addresses), .. }) => return Lookup::Addresses(addresses.clone()),
+                Some(Entry { value: Cached::NotFound, .. }) => return Lookup::NotFound,
+                Some(Entry { value: Cached::Alias(target), .. }) => target.clone(),
+            };
+            name = target;
+        }
+        Lookup::Miss
+    }
+
+    /// Caches `value` for `name` until `expires_at_ms`, replacing what was there.
+    pub fn insert(&mut self, name: String, value: Cached, expires_at_ms: u64, now_ms: u64) {
+        if !self.entries.contains_key(&name) {
+            self.make_room(now_ms);
+        }
+        self.entries.insert(name, Entry { value, expires_at_ms });
+    }
+
+    /// Caches a unicast server's answer for `name`. Each alias is cached for
+    /// its own TTL, so the chain is followed from the cache while every link
+    /// of it is fresh, and the addresses under the name at its end. "No such
+    /// host" is cached for `negative_ttl_secs`. A TTL of 0 means the record
+    /// may only be used for this answer, so it isn't cached.
+    pub fn insert_answer(&mut self, name: &str, answer: &Answer, now_ms: u64) {
+        let expires = |ttl_secs: u32| now_ms + ttl_secs as u64 * 1000;
+        match answer {
+            Answer::Addresses { aliases, addresses, ttl_secs } => {
+                for alias in aliases.iter().filter(|alias| alias.ttl_secs > 0) {
+                    self.insert(alias.name.clone(), Cached::Alias(alias.target.clone()), expires(alias.ttl_secs), now_ms);
+                }
+                let canonical = aliases.last().map_or(name, |alias| alias.target.as_str());
+                if *ttl_secs > 0 {
+                    self.insert(canonical.to_string(), Cached::Addresses(addresses.clone()), expires(*ttl_secs), now_ms);
+                }
+            },
+            Answer::NotFound { ttl_secs } => {
+                let ttl_secs = negative_ttl_secs(*ttl_secs);
+                if ttl_secs > 0 {
+                    self.insert(name.to_string(), Cached::NotFound, expires(ttl_secs), now_ms);
+                }
+            },
+        }
+    }
+
+    /// Adds `ip` to the addresses of `name`, keeping those already there in
+    /// their order, and keeps the entry until at least `expires_at_ms`. For
+    /// mDNS, where a host may answer with its addresses one at a time.
+    pub fn add_address(&mut self, name: String, ip: [u8; 4], expires_at_ms: u64, now_ms: u64) {
+        match self.entries.get_mut(&name) {
+            Some(Entry { value: Cached::Addresses(addresses), expires_at_ms: expires }) if *expires > now_ms => {
+                if !addresses.contains(&ip) {
+                    addresses.push(ip);
+                }
+                *expires = (*expires).max(expires_at_ms);
+            },
+            _ => self.insert(name, Cached::Addresses(alloc::vec![ip]), expires_at_ms, now_ms),
+        }
+    }
+
+    /// Lets the entry for `name` expire at `at_ms` at the latest.
+    pub fn expire_by(&mut self, name: &str, at_ms: u64) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.expires_at_ms = entry.expires_at_ms.min(at_ms);
+        }
+    }
+
+    /// Makes room for one more entry: drops the expired ones and, if the
+    /// cache is still full, the one that would expire first.
+    fn make_room(&mut self, now_ms: u64) {
+        if self.entries.len() < MAX_CACHE_ENTRIES {
+            return;
+        }
+        self.entries.retain(|_, entry| entry.expires_at_ms > now_ms);
+        if self.entries.len() >= MAX_CACHE_ENTRIES {
+            let first = self.entries.iter().min_by_key(|(_, entry)| entry.expires_at_ms).map(|(name, _)| name.clone());
+            if let Some(name) = first {
+                self.entries.remove(&name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::vec;
+    use crate::wire::Alias;
+
+    const SECOND: u64 = 1000;
+
+    fn addresses(addresses: &[[u8; 4]], ttl_secs: u32) -> Answer {
+        Answer::Addresses { aliases: Vec::new(), addresses: addresses.to_vec(), ttl_secs }
+    }
+
+    fn cached(cache: &mut DnsCache, name: &str, now_ms: u64) -> Option<Vec<[u8; 4]>> {
+        match cache.get(name, now_ms) {
+            Lookup::Addresses(addresses) => Some(addresses),
+            _ => None,
+        }
+    }
+
+    fn not_found(cache: &mut DnsCache, name: &str, now_ms: u64) -> bool {
+        matches!(cache.get(name, now_ms), Lookup::NotFound)
+    }
+
+    #[test]
+    fn no_such_host_is_cached_for_the_soa_ttl_within_bounds() {
+        let mut cache = DnsCache::default();
+        cache.insert_answer("gone.example", &Answer::NotFound { ttl_secs: Some(120) }, 0);
+        assert!(not_found(&mut cache, "gone.example", 119 * SECOND));
+        assert!(matches!(cache.get("gone.example", 120 * SECOND), Lookup::Miss));
+
+        // Without an SOA record: 30 seconds. Whatever the SOA says: at most 300.
+        assert_eq!(negative_ttl_secs(None), 30);
+        assert_eq!(negative_ttl_secs(Some(3600)), 300);
+        cache.insert_answer("nosoa.example", &Answer::NotFound { ttl_secs: None }, 0);
+        cache.insert_answer("long.example", &Answer::NotFound { ttl_secs: Some(3600) }, 0);
+        assert!(not_found(&mut cache, "nosoa.example", 29 * SECOND));
+        assert!(not_found(&mut cache, "long.example", 299 * SECOND));
+        assert!(matches!(cache.get("nosoa.example", 30 * SECOND), Lookup::Miss));
+        assert!(matches!(cache.get("long.example", 300 * SECOND), Lookup::Miss));
+
+        // A TTL of 0 is not cached at all.
+        cache.insert_answer("zero.example", &Answer::NotFound { ttl_secs: Some(0) }, 0);
+        assert!(matches!(cache.get("zero.example", 0), Lookup::Miss));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn each_link_of_an_alias_chain_expires_on_its_own() {
+        let mut cache = DnsCache::default();
+        let answer = Answer::Addresses {
+            aliases: vec![
+                Alias { name: "www.example.com".to_string(), target: "cdn.example.net".to_string(), ttl_secs: 60 },
+                Alias { name: "cdn.example.net".to_string(), target: "edge.example.net".to_string(), ttl_secs: 0 },
+            ],
+            addresses: vec![[93, 184, 216, 34]],
+            ttl_secs: 300,
+        };
+        cache.insert_answer("www.example.com", &answer, 0);
+        // The link with TTL 0 wasn't kept, so the chain breaks there.
+        assert!(matches!(cache.get("www.example.com", SECOND), Lookup::Miss));
+        assert_eq!(cached(&mut cache, "edge.example.net", SECOND), Some(vec![[93, 184, 216, 34]]));
+
+        cache.insert("cdn.example.net".to_string(), Cached::Alias("edge.example.net".to_string()), 600 * SECOND, SECOND);
+        assert_eq!(cached(&mut cache, "www.example.com", 59 * SECOND), Some(vec![[93, 184, 216, 34]]));
+        assert!(matches!(cache.get("www.example.com", 60 * SECOND), Lookup::Miss));
+        assert_eq!(cached(&mut cache, "cdn.example.net", 60 * SECOND), Some(vec![[93, 184, 216, 34]]));
+
+        // Aliases that loop are a miss, not a hang.
+        cache.insert("a.example".to_string(), Cached::Alias("b.example".to_string()), 600 * SECOND, 0);
+        cache.insert("b.example".to_string(), Cached::Alias("a.example".to_string()), 600 * SECOND, 0);
+        assert!(matches!(cache.get("a.example", SECOND), Lookup::Miss));
+    }
+
+    #[test]
+    fn answers_are_cached_for_their_ttl_and_refreshed_by_the_next() {
+        let mut cache = DnsCache::default();
+        cache.insert_answer("example.com", &addresses(&[[1, 1, 1, 1]], 60), 0);
+        assert_eq!(cached(&mut cache, "example.com", 59 * SECOND), Some(vec![[1, 1, 1, 1]]));
+        // Expired: a miss, and the entry is gone.
+        assert!(matches!(cache.get("example.com", 60 * SECOND), Lookup::Miss));
+        assert_eq!(cache.len(), 0);
+
+        // The answer after the miss replaces the entry and its expiry, also
+        // when the name had been "not found".
+        cache.insert_answer("example.com", &Answer::NotFound { ttl_secs: Some(60) }, 60 * SECOND);
+        cache.insert_answer("example.com", &addresses(&[[2, 2, 2, 2]], 30), 70 * SECOND);
+        assert_eq!(cached(&mut cache, "example.com", 99 * SECOND), Some(vec![[2, 2, 2, 2]]));
+        assert!(matches!(cache.get("example.com", 100 * SECOND), Lookup::Miss));
+
+        // A TTL of 0 is used for this answer only.
+        cache.insert_answer("once.example", &addresses(&[[3, 3, 3, 3]], 0), 0);
+        assert!(matches!(cache.get("once.example", 0), Lookup::Miss));
+    }
+
+    #[test]
+    fn mdns_addresses_add_up_and_goodbyes_shorten() {
+        let mut cache = DnsCache::default();
+        cache.add_address("printer.local".to_string(), [192, 168, 1, 20], 120 * SECOND, 0);
+        cache.add_address("printer.local".to_string(), [192, 168, 1, 21], 60 * SECOND, SECOND);
+        cache.add_address("printer.local".to_string(), [192, 168, 1, 20], 180 * SECOND, 2 * SECOND);
+        assert_eq!(cached(&mut cache, "printer.local", 179 * SECOND), Some(vec![[192, 168, 1, 20], [192, 168, 1, 21]]));
+        // A goodbye lets it expire a second later; a later expiry doesn't extend it.
+        cache.expire_by("printer.local", 181 * SECOND);
+        cache.expire_by("printer.local", 900 * SECOND);
+        assert!(matches!(cache.get("printer.local", 181 * SECOND), Lookup::Miss));
+    }
+
+    #[test]
+    fn a_full_cache_drops_the_expired_then_the_closest_to_expiring() {
+        let mut cache = DnsCache::default();
+        for i in 0..MAX_CACHE_ENTRIES as u64 {
+            cache.insert(format!("host{}.example", i), Cached::NotFound, (100 + i) * SECOND, 0);
+        }
+        cache.insert("one-more.example".to_string(), Cached::NotFound, 500 * SECOND, SECOND);
+        assert_eq!(cache.len(), MAX_CACHE_ENTRIES);
+        assert!(matches!(cache.get("host0.example", SECOND), Lookup::Miss));
+        assert!(not_found(&mut cache, "host1.example", SECOND));
+
+        // At 110 s, host1 to host10 have expired: all of them go, nothing fresh
+        // does, and the new name takes one of their places.
+        cache.insert("late.example".to_string(), Cached::NotFound, 500 * SECOND, 110 * SECOND);
+        assert_eq!(cache.len(), MAX_CACHE_ENTRIES - 9);
+        assert!(not_found(&mut cache, "host11.example", 110 * SECOND));
+        // Replacing a cached name makes no room.
+        cache.insert("late.example".to_string(), Cached::NotFound, 600 * SECOND, 110 * SECOND);
+        assert_eq!(cache.len(), MAX_CACHE_ENTRIES - 9);
+        assert_eq!(cache.clear(), MAX_CACHE_ENTRIES - 9);
+    }
+}
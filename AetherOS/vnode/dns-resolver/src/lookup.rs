@@ -0,0 +1,225 @@
+// vnode/dns-resolver/src/lookup.rs
+
+//! Asking the unicast servers in turn until one answers.
+//!
+//! Each server is asked until it answers or has timed out `retries` more
+//! times, every attempt with a new transaction ID. A server that fails, or
+//! answers with something that can't be used, is given up on at once and the
+//! next one is asked. How an attempt travels (UDP, TCP after a truncated
+//! answer, TCP only) is up to the `Transport`, so the order of attempts can
+//! be tested without a network.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::wire::{self, Answer};
+
+/// A unicast server the resolver queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsServer {
+    pub addr: [u8; 4],
+    /// Queried over TCP only, never UDP.
+    pub tcp_only: bool,
+}
+
+/// How long one attempt at a server waits for its answer, and how many more
+/// attempts it gets after timing out.
+#[derive(Clone, Copy)]
+pub struct Retry {
+    pub timeout_ms: u64,
+    pub retries: u32,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self { timeout_ms: 3000, retries: 2 }
+    }
+}
+
+/// Why one attempt at a server brought no answer.
+pub enum AttemptError {
+    /// Nothing came back in time; the server is asked again.
+    TimedOut,
+    /// The socket or the connection failed; the next server is asked.
+    Failed(String),
+}
+
+pub trait Transport {
+    /// One attempt at `server` with `query`, whose transaction ID is `id`.
+    /// Returns the response as received; `query_servers` checks it.
+    fn ask(&mut self, server: DnsServer, id: u16, query: Vec<u8>, hostname: &str) -> Result<Vec<u8>, AttemptError>;
+}
+
+/// Asks `servers` in order for the A records of `hostname`, with a
+/// transaction ID from `next_id` for each attempt. The error is why the
+/// last attempt brought no answer.
+pub fn query_servers(transport: &mut impl Transport, servers: &[DnsServer], retries: u32, hostname: &str, mut next_id: impl FnMut() -> u16) -> Result<Answer, String> {
+    let mut last_failure = "no DNS server configured".to_string();
+    for server in servers {
+        for _ in 0..=retries {
+            let id = next_id();
+            let query = wire::query(id, hostname).map_err(|e| e.describe())?;
+            let response = match transport.ask(*server, id, query, hostname) {
+                Ok(response) => response,
+                Err(AttemptError::TimedOut) => {
+                    last_failure = "timed out".to_string();
+                    continue;
+                },
+                Err(AttemptError::Failed(reason)) => {
+                    last_failure = reason;
+                    break;
+                },
+            };
+            match wire::parse_response(id, hostname, &response) {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    last_failure = e.describe();
+                    break;
+                },
+            }
+        }
+    }
+    Err(last_failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::{BTreeMap, VecDeque};
+    use alloc::vec;
+
+    const NAME: &str = "example.com";
+    const FIRST: DnsServer = DnsServer { addr: [10, 0, 2, 3], tcp_only: false };
+    const SECOND: DnsServer = DnsServer { addr: [1, 1, 1, 1], tcp_only: false };
+
+    /// What a fake server does with one attempt.
+    enum Reply {
+        TimedOut,
+        Failed,
+        Address([u8; 4]),
+        NxDomain,
+        /// A late answer to an earlier attempt.
+        StaleId,
+        Servfail,
+    }
+
+    /// Servers that reply from a script, attempt by attempt, and time out
+    /// once it runs out.
+    #[derive(Default)]
+    struct FakeTransport {
+        script: BTreeMap<[u8; 4], VecDeque<Reply>>,
+        /// Every attempt: the server and the transaction ID.
+        asked: Vec<([u8; 4], u16)>,
+    }
+
+    impl FakeTransport {
+        fn new(script: Vec<(DnsServer, Vec<Reply>)>) -> Self {
+            Self { script: script.into_iter().map(|(server, replies)| (server.addr, replies.into())).collect(), asked: Vec::new() }
+        }
+
+        fn servers_asked(&self) -> Vec<[u8; 4]> {
+            self.asked.iter().map(|(addr, _)| *addr).collect()
+        }
+    }
+
+    /// The response to `query` with RCODE `rcode` and an A record, if any.
+    fn response(query: &[u8], rcode: u16, address: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = query.to_vec();
+        out[2..4].copy_from_slice(&(0x8180 | rcode).to_be_bytes());
+        if let Some(address) = address {
+            out[6..8].copy_from_slice(&1u16.to_be_bytes());
+            out.extend_from_slice(&[0xC0, 12]);
+            for field in [wire::TYPE_A, wire::CLASS_IN] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(&300u32.to_be_bytes());
+            out.extend_from_slice(&4u16.to_be_bytes());
+            out.extend_from_slice(&address);
+        }
+        out
+    }
+
+    impl Transport for FakeTransport {
+        fn ask(&mut self, server: DnsServer, id: u16, query: Vec<u8>, _hostname: &str) -> Result<Vec<u8>, AttemptError> {
+            self.asked.push((server.addr, id));
+            match self.script.get_mut(&server.addr).and_then(VecDeque::pop_front).unwrap_or(Reply::TimedOut) {
+                Reply::TimedOut => Err(AttemptError::TimedOut),
+                Reply::Failed => Err(AttemptError::Failed("connection refused".to_string())),
+                Reply::Address(address) => Ok(response(&query, 0, Some(address))),
+                Reply::NxDomain => Ok(response(&query, 3, None)),
+                Reply::StaleId => {
+                    let mut stale = response(&query, 0, Some([6, 6, 6, 6]));
+                    stale[..2].copy_from_slice(&id.wrapping_sub(1).to_be_bytes());
+                    Ok(stale)
+                },
+                Reply::Servfail => Ok(response(&query, 2, None)),
+            }
+        }
+    }
+
+    fn lookup(transport: &mut FakeTransport, servers: &[DnsServer], retries: u32) -> Result<Answer, String> {
+        let mut id = 0x4000;
+        query_servers(transport, servers, retries, NAME, || {
+            id += 1;
+            id
+        })
+    }
+
+    fn addresses(answer: Result<Answer, String>) -> Vec<[u8; 4]> {
+        match answer {
+            Ok(Answer::Addresses { addresses, .. }) => addresses,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn timeouts_are_retried_with_new_ids_before_failing_over() {
+        let mut transport = FakeTransport::new(vec![
+            (FIRST, vec![Reply::TimedOut, Reply::TimedOut, Reply::TimedOut]),
+            (SECOND, vec![Reply::Address([93, 184, 216, 34])]),
+        ]);
+        assert_eq!(addresses(lookup(&mut transport, &[FIRST, SECOND], 2)), vec![[93, 184, 216, 34]]);
+        assert_eq!(transport.servers_asked(), vec![FIRST.addr, FIRST.addr, FIRST.addr, SECOND.addr]);
+        let ids: Vec<u16> = transport.asked.iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids, vec![0x4001, 0x4002, 0x4003, 0x4004]);
+    }
+
+    #[test]
+    fn an_answer_after_a_timeout_ends_the_lookup() {
+        let mut transport = FakeTransport::new(vec![(FIRST, vec![Reply::TimedOut, Reply::Address([10, 0, 0, 1])])]);
+        assert_eq!(addresses(lookup(&mut transport, &[FIRST, SECOND], 2)), vec![[10, 0, 0, 1]]);
+        assert_eq!(transport.servers_asked(), vec![FIRST.addr, FIRST.addr]);
+    }
+
+    #[test]
+    fn failures_and_unusable_answers_fail_over_at_once() {
+        for first in [Reply::Failed, Reply::StaleId, Reply::Servfail] {
+            let mut transport = FakeTransport::new(vec![(FIRST, vec![first]), (SECOND, vec![Reply::Address([1, 2, 3, 4])])]);
+            assert_eq!(addresses(lookup(&mut transport, &[FIRST, SECOND], 2)), vec![[1, 2, 3, 4]]);
+            assert_eq!(transport.servers_asked(), vec![FIRST.addr, SECOND.addr]);
+        }
+    }
+
+    #[test]
+    fn no_such_host_is_an_answer() {
+        let mut transport = FakeTransport::new(vec![(FIRST, vec![Reply::NxDomain]), (SECOND, vec![Reply::Address([1, 2, 3, 4])])]);
+        assert!(matches!(lookup(&mut transport, &[FIRST, SECOND], 2), Ok(Answer::NotFound { ttl_secs: None })));
+        assert_eq!(transport.servers_asked(), vec![FIRST.addr]);
+    }
+
+    #[test]
+    fn the_last_failure_is_reported() {
+        // Every server times out on every attempt: 1 + retries each.
+        let mut transport = FakeTransport::default();
+        assert_eq!(lookup(&mut transport, &[FIRST, SECOND], 1), Err("timed out".to_string()));
+        assert_eq!(transport.servers_asked(), vec![FIRST.addr, FIRST.addr, SECOND.addr, SECOND.addr]);
+
+        let mut transport = FakeTransport::new(vec![(FIRST, vec![Reply::TimedOut]), (SECOND, vec![Reply::Servfail])]);
+        assert_eq!(lookup(&mut transport, &[FIRST, SECOND], 0), Err("server error (RCODE 2)".to_string()));
+        assert_eq!(transport.servers_asked(), vec![FIRST.addr, SECOND.addr]);
+
+        let mut transport = FakeTransport::default();
+        assert_eq!(lookup(&mut transport, &[], 2), Err("no DNS server configured".to_string()));
+        assert!(transport.asked.is_empty());
+    }
+}
@@ -7,14 +7,40 @@ extern crate alloc;
 
 use core::panic::PanicInfo;
 use alloc::vec::Vec;
-use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
 
+use common::channels;
 use common::ipc::vnode::VNodeChannel;
 use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ipc::socket_ipc::{SocketRequest, SocketResponse, SocketFd};
-use common::ipc::dns_ipc::{DnsRequest, DnsResponse};
+use common::ipc::socket_ipc::{SocketRequest, SocketResponse, SocketFd, DEFAULT_CONNECT_TIMEOUT_MS};
+use common::ipc::dns_ipc::{DnsRequest, DnsResponse, MdnsRenamed};
+use common::ipc::net_ipc::{NetStackRequest, NetStackResponse, NET_DOWN_TOPIC, NET_UP_TOPIC};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use common::ipc::session_ipc::{self, AidBytes, SYSTEM_AID};
+use common::startup::{self, SELF_CHANNEL};
+use common::{random, time};
+
+mod mdns;
+use mdns::{Mdns, MdnsEvent, MDNS_GROUP, MDNS_PORT};
+mod tcp;
+use tcp::{TcpError, DNS_PORT};
+mod ready;
+use ready::Readiness;
+mod wire;
+use wire::Answer;
+mod cache;
+use cache::{DnsCache, Lookup};
+mod lookup;
+use lookup::{AttemptError, DnsServer, Retry, Transport};
+
+/// Most mDNS packets taken off the socket per pass of the event loop, so a
+/// chatty LAN can't starve client requests.
+const MDNS_PACKETS_PER_POLL: usize = 16;
+
+/// Servers asked when `dns.servers` is unset or holds no valid address.
+const DEFAULT_DNS_SERVERS: [[u8; 4]; 2] = [[8, 8, 8, 8], [1, 1, 1, 1]];
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -29,10 +55,120 @@ fn log(msg: &str) {
     }
 }
 
-// Placeholder for DNS cache entry
-struct DnsCacheEntry {
-    ip_address: [u8; 4],
-    expires_at_ms: u64,
+/// Opens the UDP socket unicast queries go out on.
+fn open_dns_socket(socket_chan: &mut VNodeChannel) -> Result<SocketFd, String> {
+    match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Socket { domain: 2, ty: 2, protocol: 0 }) {
+        Ok(SocketResponse::Success(fd)) => Ok(SocketFd::from_raw(fd as u32)),
+        Ok(SocketResponse::Error(err_code, msg)) => Err(format!("{} ({})", msg, err_code)),
+        _ => Err("unexpected response from socket-api".to_string()),
+    }
+}
+
+/// Opens the mDNS socket, joins the mDNS group and starts claiming our
+/// hostname: `dns.mdns_hostname` if set, otherwise one derived from the
+/// interface's MAC address.
+fn start_mdns(socket_chan: &mut VNodeChannel, net_chan: &mut VNodeChannel, settings_chan: &mut VNodeChannel, now_ms: u64) -> Result<(SocketFd, Mdns), String> {
+    let interface = match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::GetInterface) {
+        Ok(NetStackResponse::Interface(interface)) => interface,
+        _ => return Err("could not read the interface address".to_string()),
+    };
+    let hostname = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: "dns.mdns_hostname".into() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Str(name), .. }) if mdns::is_valid_hostname(&name) => name,
+        Ok(SettingsResponse::Value { value: SettingValue::Str(name), .. }) if !name.is_empty() => {
+            log(&format!("DNS Resolver: '{}' is not a valid mDNS hostname, using the default.", name));
+            mdns::default_hostname(&interface.mac)
+        },
+        _ => mdns::default_hostname(&interface.mac),
+    };
+
+    let mut request = |request: SocketRequest| match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&request) {
+        Ok(SocketResponse::Success(value)) => Ok(value),
+        Ok(SocketResponse::Error(code, message)) => Err(format!("{} ({})", message, code)),
+        _ => Err("unexpected response from socket-api".to_string()),
+    };
+    let fd = SocketFd::from_raw(request(SocketRequest::Socket { domain: 2, ty: 2, protocol: 0 })? as u32);
+    let joined = request(SocketRequest::Bind { fd, addr: [0, 0, 0, 0], port: MDNS_PORT })
+        .and_then(|_| request(SocketRequest::JoinMulticast { fd, group: MDNS_GROUP }));
+    if let Err(e) = joined {
+        let _ = request(SocketRequest::Close { fd });
+        return Err(e);
+    }
+    Ok((fd, Mdns::new(&hostname, interface.ip, now_ms)))
+}
+
+/// Reads `dns.tcp_only`: whether the unicast servers are queried over TCP
+/// straight away instead of UDP first.
+fn read_tcp_only(settings_chan: &mut VNodeChannel) -> bool {
+    matches!(
+        settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: "dns.tcp_only".into() }),
+        Ok(SettingsResponse::Value { value: SettingValue::Bool(true), .. })
+    )
+}
+
+fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut addr = [0u8; 4];
+    let mut parts = text.split('.');
+    for byte in addr.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(addr)
+}
+
+/// Reads the unicast servers, in the order they are asked: `dns.servers`, a
+/// comma-separated list of IPv4 addresses, and `dns.tcp_only`. Entries that
+/// aren't addresses are skipped.
+fn read_servers(settings_chan: &mut VNodeChannel) -> Vec<DnsServer> {
+    let tcp_only = read_tcp_only(settings_chan);
+    let mut addrs = Vec::new();
+    if let Ok(SettingsResponse::Value { value: SettingValue::Str(list), .. }) = settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: "dns.servers".into() }) {
+        for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match parse_ipv4(entry) {
+                Some(addr) => addrs.push(addr),
+                None => log(&format!("DNS Resolver: Ignoring '{}' in dns.servers; not an IPv4 address.", entry)),
+            }
+        }
+    }
+    if addrs.is_empty() {
+        addrs.extend_from_slice(&DEFAULT_DNS_SERVERS);
+    }
+    addrs.into_iter().map(|addr| DnsServer { addr, tcp_only }).collect()
+}
+
+/// Reads `dns.query_timeout_ms` and `dns.query_retries`, keeping the defaults
+/// for any that can't be fetched.
+fn read_retry(settings_chan: &mut VNodeChannel) -> Retry {
+    let mut fetch = |key: &str| match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.into() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Int(n), .. }) if n >= 0 => Some(n as u64),
+        _ => None,
+    };
+    let mut retry = Retry::default();
+    if let Some(timeout_ms) = fetch("dns.query_timeout_ms") {
+        retry.timeout_ms = timeout_ms;
+    }
+    if let Some(retries) = fetch("dns.query_retries") {
+        retry.retries = retries as u32;
+    }
+    retry
+}
+
+/// A transaction ID for a unicast query. Random, so an off-path host can't
+/// easily guess it; from the clock if the kernel has no random numbers.
+fn transaction_id() -> u16 {
+    let mut id = [0u8; 2];
+    if random::fill(&mut id) {
+        u16::from_be_bytes(id)
+    } else {
+        time::ticks().raw() as u16
+    }
+}
+
+impl From<TcpError> for AttemptError {
+    fn from(e: TcpError) -> Self {
+        match e {
+            TcpError::TimedOut => AttemptError::TimedOut,
+            e => AttemptError::Failed(e.describe()),
+        }
+    }
 }
 
 // Main struct for the DNS Resolver V-Node logic
@@ -40,171 +176,465 @@ struct DnsResolver {
     client_chan: VNodeChannel,
     socket_chan: VNodeChannel,
     aetherfs_chan: VNodeChannel,
-    dns_cache: BTreeMap<String, DnsCacheEntry>,
-    dns_servers: Vec<[u8; 4]>,
+    dns_cache: DnsCache,
+    dns_servers: Vec<DnsServer>, // Asked in order until one answers
+    retry: Retry,
     dns_socket_fd: SocketFd,
+    mdns_socket_fd: Option<SocketFd>, // None if mDNS could not be started
+    mdns: Option<Mdns>,
+    event_bus_chan: VNodeChannel,
+    net_chan: VNodeChannel, // For the interface address when mDNS restarts
+    settings_chan: VNodeChannel,
+    events_chan: VNodeChannel, // net.up and net.down from the network stack
+    ready: Readiness, // Which sockets socket-api said have data
 }
 
 impl DnsResolver {
-    fn new(client_chan_id: u32, socket_chan_id: u32, aetherfs_chan_id: u32) -> Self {
+    fn new(client_chan_id: u32, socket_chan_id: u32, aetherfs_chan_id: u32, net_chan_id: u32, settings_chan_id: u32, event_bus_chan_id: u32, events_chan_id: u32, ready_chan_id: u32) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
         let mut socket_chan = VNodeChannel::new(socket_chan_id);
         let aetherfs_chan = VNodeChannel::new(aetherfs_chan_id);
+        let mut net_chan = VNodeChannel::new(net_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let events_chan = VNodeChannel::new(events_chan_id);
 
         log("DNS Resolver: Initializing...");
 
-        // Conceptual: Read /etc/network/resolv.conf for DNS server addresses.
-        // For now, hardcode a dummy DNS server.
-        let mut dns_servers = Vec::new();
-        // Using Google DNS as a dummy, typically this would be configured by DHCP or admin.
-        dns_servers.push([8, 8, 8, 8]);
-        log(&alloc::format!("DNS Resolver: Using DNS server: {}.{}.{}.{}", dns_servers[0][0], dns_servers[0][1], dns_servers[0][2], dns_servers[0][3]));
+        let dns_servers = read_servers(&mut settings_chan);
+        let retry = read_retry(&mut settings_chan);
+        for server in &dns_servers {
+            let addr = server.addr;
+            log(&alloc::format!("DNS Resolver: Using DNS server: {}.{}.{}.{}{}", addr[0], addr[1], addr[2], addr[3], if server.tcp_only { " (TCP only)" } else { "" }));
+        }
 
         // Open a UDP socket with `socket-api` for sending DNS queries.
-        let dns_socket_fd: SocketFd = match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Socket { domain: 2, ty: 2, protocol: 0 }) {
-            Ok(SocketResponse::Success(fd)) => {
+        let dns_socket_fd = match open_dns_socket(&mut socket_chan) {
+            Ok(fd) => {
                 log(&alloc::format!("DNS Resolver: Opened UDP socket with fd: {}.", fd));
-                fd as SocketFd
+                fd
             },
-            Ok(SocketResponse::Error(err_code, msg)) => {
-                log(&alloc::format!("DNS Resolver: Failed to open UDP socket with socket-api. Error {}: {}. Fatal error.", err_code, msg));
+            Err(e) => {
+                log(&alloc::format!("DNS Resolver: Failed to open UDP socket with socket-api: {}. Fatal error.", e));
                 panic!("Failed to open UDP socket");
             },
-            _ => {
-                log("DNS Resolver: Unexpected response from socket-api when opening UDP socket. Fatal error.");
-                panic!("Unexpected socket-api response");
-            }
+        };
+
+        // Cached answers and our mDNS claim belong to the network we were on.
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: "net.".to_string(), reply_chan: events_chan.id };
+        if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("DNS Resolver: Failed to subscribe to interface events; restart the resolver after the network comes back up.");
+        }
+
+        let now_ms = time::ticks().to_millis().raw();
+        let mut ready = Readiness::new(ready_chan_id);
+        let (mdns_socket_fd, mdns) = match start_mdns(&mut socket_chan, &mut net_chan, &mut settings_chan, now_ms) {
+            Ok((fd, mdns)) => {
+                log(&format!("DNS Resolver: mDNS started on fd {}, claiming {}.", fd, mdns.hostname()));
+                ready.watch(&mut socket_chan, fd);
+                (Some(fd), Some(mdns))
+            },
+            Err(e) => {
+                log(&format!("DNS Resolver: mDNS disabled: {}. .local names will not resolve.", e));
+                (None, None)
+            },
         };
 
         Self {
             client_chan,
             socket_chan,
             aetherfs_chan,
-            dns_cache: BTreeMap::new(),
+            dns_cache: DnsCache::default(),
             dns_servers,
+            retry,
             dns_socket_fd,
+            mdns_socket_fd,
+            mdns,
+            event_bus_chan,
+            net_chan,
+            settings_chan,
+            events_chan,
+            ready,
+        }
+    }
+
+    fn close_socket(&mut self, fd: SocketFd) {
+        let _ = self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Close { fd });
+    }
+
+    /// Follows the interface state. Going down, the network stack closed our
+    /// sockets, so mDNS stops. Coming back up, the cache is flushed, since the
+    /// answers in it may be from another network, the unicast socket is
+    /// reopened, the server settings are read again and mDNS starts over: it probes
+    /// for our hostname and announces it again.
+    fn handle_net_events(&mut self, now_ms: u64) {
+        while let Ok(Some(event_data)) = self.events_chan.recv_non_blocking() {
+            let topic = match postcard::from_bytes::<Event>(&event_data) {
+                Ok(event) => event.topic,
+                Err(_) => continue,
+            };
+            if topic == NET_DOWN_TOPIC {
+                log("DNS Resolver: The network is down; mDNS stopped.");
+                self.mdns = None;
+                if let Some(fd) = self.mdns_socket_fd.take() {
+                    self.close_socket(fd);
+                }
+            } else if topic == NET_UP_TOPIC {
+                log(&format!("DNS Resolver: The network is up; flushing {} cached names.", self.dns_cache.len()));
+                self.dns_cache.clear();
+                let old_fd = self.dns_socket_fd;
+                self.close_socket(old_fd);
+                match open_dns_socket(&mut self.socket_chan) {
+                    Ok(fd) => self.dns_socket_fd = fd,
+                    Err(e) => log(&format!("DNS Resolver: Could not reopen the UDP socket: {}. Unicast lookups will fail.", e)),
+                }
+                self.dns_servers = read_servers(&mut self.settings_chan);
+                self.retry = read_retry(&mut self.settings_chan);
+                if let Some(fd) = self.mdns_socket_fd.take() {
+                    self.close_socket(fd);
+                }
+                match start_mdns(&mut self.socket_chan, &mut self.net_chan, &mut self.settings_chan, now_ms) {
+                    Ok((fd, mdns)) => {
+                        log(&format!("DNS Resolver: mDNS restarted on fd {}, claiming {}.", fd, mdns.hostname()));
+                        self.ready.watch(&mut self.socket_chan, fd);
+                        self.mdns_socket_fd = Some(fd);
+                        self.mdns = Some(mdns);
+                    },
+                    Err(e) => log(&format!("DNS Resolver: mDNS disabled: {}. .local names will not resolve.", e)),
+                }
+            }
+        }
+    }
+
+    /// Feeds received mDNS packets to the responder/resolver and runs its timers.
+    fn poll_mdns(&mut self, now_ms: u64) {
+        let fd = match self.mdns_socket_fd {
+            Some(fd) => fd,
+            None => return,
+        };
+        let mut events = Vec::new();
+        self.ready.collect();
+        if self.ready.take(fd) {
+            let mut drained = false;
+            for _ in 0..MDNS_PACKETS_PER_POLL {
+                let packet = match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Recv { fd, len: 1500 }) {
+                    Ok(SocketResponse::Data(packet)) if !packet.is_empty() => packet,
+                    _ => {
+                        drained = true;
+                        break;
+                    },
+                };
+                if let Some(mdns) = self.mdns.as_mut() {
+                    events.extend(mdns.handle_packet(&packet, now_ms));
+                }
+            }
+            // Read empty, it is watched again; otherwise there is more for the next pass.
+            if drained {
+                self.ready.watch(&mut self.socket_chan, fd);
+            } else {
+                self.ready.mark(fd);
+            }
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            events.extend(mdns.poll(now_ms));
+        }
+        self.handle_mdns_events(events, now_ms);
+    }
+
+    fn handle_mdns_events(&mut self, events: Vec<MdnsEvent>, now_ms: u64) {
+        for event in events {
+            match event {
+                MdnsEvent::Send(packet) => {
+                    let fd = match self.mdns_socket_fd {
+                        Some(fd) => fd,
+                        None => continue,
+                    };
+                    let request = SocketRequest::SendTo { fd, addr: MDNS_GROUP, port: MDNS_PORT, data: packet };
+                    if !matches!(self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&request), Ok(SocketResponse::Success(_))) {
+                        log("DNS Resolver: Failed to send mDNS packet.");
+                    }
+                },
+                MdnsEvent::Answer { name, ttl_secs: 0, .. } => {
+                    // A goodbye: the host is giving the name up. RFC 6762 keeps it for one more second.
+                    self.dns_cache.expire_by(&name, now_ms + 1000);
+                },
+                MdnsEvent::Answer { name, ip, ttl_secs } => {
+                    log(&format!("DNS Resolver: mDNS: {} is {}.{}.{}.{} (TTL {}s).", name, ip[0], ip[1], ip[2], ip[3], ttl_secs));
+                    // A host may answer with several addresses; keep them all, in the order they came.
+                    self.dns_cache.add_address(name, ip, now_ms + ttl_secs as u64 * 1000, now_ms);
+                },
+                MdnsEvent::NotFound { name } => log(&format!("DNS Resolver: mDNS: No answer for {}.", name)),
+                MdnsEvent::Renamed { from, to } => {
+                    log(&format!("DNS Resolver: mDNS: Another host claimed {}, renaming to {}.", from, to));
+                    self.dns_cache.remove(&from);
+                    self.publish_rename(from, to);
+                },
+            }
+        }
+    }
+
+    fn publish_rename(&mut self, from: String, to: String) {
+        let payload = match postcard::to_allocvec(&MdnsRenamed { from, to }) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: "dns.mdns.renamed".to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log("DNS Resolver: Failed to publish the mDNS rename event.");
+        }
+    }
+
+    /// Resolves a ".local" name with mDNS. Keeps answering other hosts' queries
+    /// while it waits. Never falls back to the unicast servers.
+    fn resolve_local(&mut self, hostname: &String, current_time_ms: u64) -> DnsResponse {
+        let events = match self.mdns.as_mut() {
+            Some(mdns) => mdns.lookup(hostname, current_time_ms),
+            None => return DnsResponse::Error { message: "mDNS is not available".to_string() },
+        };
+        self.handle_mdns_events(events, current_time_ms);
+        loop {
+            let now_ms = time::ticks().to_millis().raw();
+            if let Lookup::Addresses(addresses) = self.dns_cache.get(hostname, now_ms) {
+                return DnsResponse::ResolvedAddresses { hostname: hostname.clone(), addresses };
+            }
+            if !self.mdns.as_ref().map_or(false, |mdns| mdns.is_pending(hostname)) {
+                return DnsResponse::NotFound { query: hostname.clone() };
+            }
+            // SYS_TIME also yields, so this doesn't spin while the answer is outstanding.
+            self.poll_mdns(time::ticks().to_millis().raw());
+        }
+    }
+
+    /// Resolves `hostname` to all its addresses, from the cache or the network.
+    fn resolve(&mut self, hostname: &String, current_time_ms: u64) -> DnsResponse {
+        match self.dns_cache.get(hostname, current_time_ms) {
+            Lookup::Addresses(addresses) => {
+                log(&alloc::format!("DNS Resolver: Cache hit for {} ({} addresses).", hostname, addresses.len()));
+                DnsResponse::ResolvedAddresses { hostname: hostname.clone(), addresses }
+            },
+            Lookup::NotFound => {
+                log(&alloc::format!("DNS Resolver: Cache hit for {}: no such host.", hostname));
+                DnsResponse::NotFound { query: hostname.clone() }
+            },
+            Lookup::Miss => {
+                log(&alloc::format!("DNS Resolver: Cache miss for {}, performing network lookup.", hostname));
+                self.lookup(hostname, current_time_ms)
+            },
+        }
+    }
+
+    /// Looks a cache miss up with mDNS or the unicast servers, depending on the name.
+    fn lookup(&mut self, hostname: &String, current_time_ms: u64) -> DnsResponse {
+        if mdns::is_local_name(hostname) {
+            self.resolve_local(hostname, current_time_ms)
+        } else {
+            self.perform_network_lookup(hostname, current_time_ms)
         }
     }
 
     // This function encapsulates the network lookup logic for a hostname
     fn perform_network_lookup(&mut self, hostname: &String, current_time_ms: u64) -> DnsResponse {
         log(&alloc::format!("DNS Resolver: Performing network lookup for {}.", hostname));
+        if let Err(e) = wire::query(0, hostname) {
+            log(&alloc::format!("DNS Resolver: Cannot look up {}: {}.", hostname, e.describe()));
+            return DnsResponse::Error { message: alloc::format!("Cannot look up {}: {}", hostname, e.describe()) };
+        }
 
-        // For now, let's simulate a successful lookup for "example.com" and a failure for others.
-        // In a real system, we'd construct a proper DNS query packet (e.g., using a DNS library).
-        let dns_query_payload = alloc::format!("DNS_QUERY:{}", hostname).as_bytes().to_vec();
-
-        // Use the first configured DNS server.
-        let dns_server_ip = self.dns_servers[0];
-        const DNS_PORT: u16 = 53; // Standard DNS port
-
-        // 1. "Connect" the UDP socket to the remote DNS server. For UDP, this just sets the default peer.
-        match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Connect { fd: self.dns_socket_fd, addr: dns_server_ip, port: DNS_PORT }) {
-            Ok(SocketResponse::Success(_)) => log(&alloc::format!("DNS Resolver: UDP socket {} connected to {}:{}", self.dns_socket_fd, dns_server_ip[0], DNS_PORT)),
-            Ok(SocketResponse::Error(err_code, msg)) => {
-                log(&alloc::format!("DNS Resolver: Failed to connect UDP socket to DNS server. Error {}: {}.", err_code, msg));
-                return DnsResponse::Error { message: "Failed to set remote DNS server".to_string() };
+        // Each server is asked until it answers or has timed out `retries` more
+        // times; a server that fails or answers with an error is given up on at once.
+        let servers = self.dns_servers.clone();
+        let retries = self.retry.retries;
+        match lookup::query_servers(self, &servers, retries, hostname, transaction_id) {
+            Ok(answer) => {
+                self.dns_cache.insert_answer(hostname, &answer, current_time_ms);
+                match answer {
+                    Answer::Addresses { aliases, addresses, ttl_secs } => {
+                        for alias in aliases {
+                            log(&alloc::format!("DNS Resolver: {} is an alias of {} (cached for {} s).", alias.name, alias.target, alias.ttl_secs));
+                        }
+                        log(&alloc::format!("DNS Resolver: Resolved {} to {:?} (cached for {} s).", hostname, addresses, ttl_secs));
+                        DnsResponse::ResolvedAddresses { hostname: hostname.clone(), addresses }
+                    },
+                    Answer::NotFound { ttl_secs } => {
+                        log(&alloc::format!("DNS Resolver: Hostname {} not found by external server (cached for {} s).", hostname, cache::negative_ttl_secs(ttl_secs)));
+                        DnsResponse::NotFound { query: hostname.clone() }
+                    },
+                }
+            },
+            Err(last_failure) => {
+                log(&alloc::format!("DNS Resolver: No DNS server answered for {} (last: {}).", hostname, last_failure));
+                DnsResponse::Error { message: alloc::format!("No DNS server answered for {}: {}", hostname, last_failure) }
             },
-            _ => {
-                log("DNS Resolver: Unexpected response during UDP connect to DNS server.");
-                return DnsResponse::Error { message: "Unexpected response during UDP connect".to_string() };
-            }
         }
+    }
 
-        // 2. Send the simulated DNS query packet over UDP.
-        match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Send { fd: self.dns_socket_fd, data: dns_query_payload }) {
-            Ok(SocketResponse::Success(bytes_sent)) => log(&alloc::format!("DNS Resolver: Sent {} bytes DNS query for {}.", bytes_sent, hostname)),
+    /// Sends the query with transaction ID `id` to `server` over UDP and waits
+    /// for the response until `deadline_ms`.
+    fn query_udp(&mut self, server: [u8; 4], id: u16, dns_query_payload: Vec<u8>, hostname: &str, deadline_ms: u64) -> Result<Vec<u8>, AttemptError> {
+        // 1. Send the DNS query over UDP to the server.
+        let fd = self.dns_socket_fd;
+        match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::SendTo { fd, addr: server, port: DNS_PORT, data: dns_query_payload }) {
+            Ok(SocketResponse::Success(bytes_sent)) => log(&alloc::format!("DNS Resolver: Sent {} bytes DNS query for {} to {}.{}.{}.{}.", bytes_sent, hostname, server[0], server[1], server[2], server[3])),
             Ok(SocketResponse::Error(err_code, msg)) => {
                 log(&alloc::format!("DNS Resolver: Failed to send DNS query for {}. Error {}: {}.", hostname, err_code, msg));
-                return DnsResponse::Error { message: "Failed to send DNS query".to_string() };
+                return Err(AttemptError::Failed(alloc::format!("failed to send the query: {} ({})", msg, err_code)));
             },
             _ => {
                 log("DNS Resolver: Unexpected response during DNS query send.");
-                return DnsResponse::Error { message: "Unexpected response during DNS query send".to_string() };
+                return Err(AttemptError::Failed("unexpected response during DNS query send".to_string()));
             }
         }
 
-        // 3. Receive the simulated DNS response.
-        // In a real system, there would be a timeout here.
-        match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Recv { fd: self.dns_socket_fd, len: 512 }) {
-            Ok(SocketResponse::Data(response_payload)) => {
-                // Conceptual: Parse the DNS response.
-                let response_str = alloc::string::String::from_utf8_lossy(&response_payload);
-                log(&alloc::format!("DNS Resolver: Received DNS response: {}.", response_str));
-
-                if response_str.contains("IP:192.0.2.1") && hostname == "example.com" {
-                    let ip_addr = [192, 0, 2, 1]; // Dummy IP for example.com
-                    let expires_at_ms = current_time_ms + 60_000; // Cache for 60 seconds
-                    self.dns_cache.insert(hostname.clone(), DnsCacheEntry { ip_address: ip_addr, expires_at_ms });
-                    log(&alloc::format!("DNS Resolver: Resolved {} to {}.{}.{}.{} (cached).", hostname, ip_addr[0], ip_addr[1], ip_addr[2], ip_addr[3]));
-                    DnsResponse::ResolvedHostname { hostname: hostname.clone(), ip_address: ip_addr }
-                } else if response_str.contains("NOT_FOUND") {
-                    log(&alloc::format!("DNS Resolver: Hostname {} not found by external server.", hostname));
-                    DnsResponse::NotFound { query: hostname.clone() }
-                } else {
-                    log(&alloc::format!("DNS Resolver: Unknown response format or unexpected result for {}.", hostname));
-                    DnsResponse::Error { message: alloc::format!("Unknown DNS response for {}.", hostname) }
+        // 2. Receive the DNS response, once socket-api says it is there. Only a
+        // datagram from the server we asked, with our ID, is taken as its answer;
+        // a late answer to an earlier query is dropped.
+        self.ready.watch(&mut self.socket_chan, fd);
+        loop {
+            self.ready.collect();
+            if self.ready.take(fd) {
+                match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::RecvFrom { fd, len: 512 }) {
+                    Ok(SocketResponse::DataFrom { data, addr, port }) if addr == server && port == DNS_PORT && data.get(..2) == Some(&id.to_be_bytes()[..]) => return Ok(data),
+                    Ok(SocketResponse::DataFrom { data, addr, port }) if !data.is_empty() => {
+                        log(&alloc::format!("DNS Resolver: Dropped {} bytes from {}.{}.{}.{}:{} while waiting for {}.{}.{}.{}.", data.len(), addr[0], addr[1], addr[2], addr[3], port, server[0], server[1], server[2], server[3]));
+                        // More may be queued behind it.
+                        self.ready.mark(fd);
+                    },
+                    // Someone else read it first; wait for the next.
+                    Ok(SocketResponse::DataFrom { .. }) => self.ready.watch(&mut self.socket_chan, fd),
+                    Ok(SocketResponse::Error(err_code, msg)) => {
+                        log(&alloc::format!("DNS Resolver: Failed to receive DNS response for {}. Error {}: {}.", hostname, err_code, msg));
+                        return Err(AttemptError::Failed(alloc::format!("failed to receive the response: {} ({})", msg, err_code)));
+                    },
+                    _ => {
+                        log("DNS Resolver: Unexpected response during DNS response receive.");
+                        return Err(AttemptError::Failed("unexpected response during DNS response receive".to_string()));
+                    }
                 }
+            }
+            // Measured on the tick clock. SYS_TIME also yields, so this doesn't
+            // spin while the answer is outstanding.
+            if time::ticks().to_millis().raw() >= deadline_ms {
+                log(&alloc::format!("DNS Resolver: No DNS response for {} within {} ms.", hostname, self.retry.timeout_ms));
+                return Err(AttemptError::TimedOut);
+            }
+        }
+    }
+
+    /// Answers one client request. `caller` is the identity of the task that
+    /// sent it; only the system identity may flush the cache.
+    fn handle_request(&mut self, request: DnsRequest, caller: Option<AidBytes>, current_time_ms: u64) -> DnsResponse {
+        let (hostname, all) = match request {
+            DnsRequest::ResolveHostname { hostname } => (hostname, false),
+            DnsRequest::ResolveAll { hostname } => (hostname, true),
+            DnsRequest::FlushCache if caller != Some(SYSTEM_AID) => {
+                log("DNS Resolver: Refused to flush the cache for a caller without the system identity.");
+                return DnsResponse::Error { message: "permission denied".to_string() };
             },
-            Ok(SocketResponse::Error(err_code, msg)) => {
-                log(&alloc::format!("DNS Resolver: Failed to receive DNS response for {}. Error {}: {}.", hostname, err_code, msg));
-                DnsResponse::Error { message: "Failed to receive DNS response".to_string() }
+            DnsRequest::FlushCache => {
+                let entries = self.dns_cache.clear();
+                log(&alloc::format!("DNS Resolver: Flushed {} cached names.", entries));
+                return DnsResponse::CacheFlushed { entries: entries as u32 };
             },
-            _ => {
-                log("DNS Resolver: Unexpected response during DNS response receive.");
-                DnsResponse::Error { message: "Unexpected response during DNS response receive".to_string() };
-            }
+        };
+        // Names are case-insensitive and cached in lower case, as the wire
+        // module reads them. The reply carries the name as the client wrote
+        // it, since that is what the client matches it against.
+        let name = hostname.trim_end_matches('.').to_ascii_lowercase();
+        match self.resolve(&name, current_time_ms) {
+            DnsResponse::ResolvedAddresses { addresses, .. } if all => DnsResponse::ResolvedAddresses { hostname, addresses },
+            // A plain `ResolveHostname` gets the most preferred address.
+            DnsResponse::ResolvedAddresses { addresses, .. } => match addresses.first() {
+                Some(&ip_address) => DnsResponse::ResolvedHostname { hostname, ip_address },
+                None => DnsResponse::NotFound { query: hostname },
+            },
+            DnsResponse::NotFound { .. } => DnsResponse::NotFound { query: hostname },
+            response => response,
         }
     }
 
     fn run_loop(&mut self) -> ! {
         log("DNS Resolver: Entering main event loop.");
         loop {
-            let current_time_ms = unsafe { syscall3(SYS_TIME, 0, 0, 0) * 10 }; // Assuming 1 tick = 10 ms
+            let current_time_ms = time::ticks().to_millis().raw();
 
             // 1. Process incoming DNS queries from client V-Nodes
             if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
                 if let Ok(request) = postcard::from_bytes::<DnsRequest>(&req_data) {
                     log(&alloc::format!("DNS Resolver: Received DnsRequest: {:?}.", request));
 
-                    let response = match request {
-                        DnsRequest::ResolveHostname { hostname } => {
-                            // Check cache first
-                            if let Some(entry) = self.dns_cache.get(&hostname) {
-                                if current_time_ms < entry.expires_at_ms {
-                                    log(&alloc::format!("DNS Resolver: Cache hit for {}: {}.{}.{}.{}.", hostname, entry.ip_address[0], entry.ip_address[1], entry.ip_address[2], entry.ip_address[3]));
-                                    DnsResponse::ResolvedHostname { hostname: hostname.clone(), ip_address: entry.ip_address }
-                                } else {
-                                    log(&alloc::format!("DNS Resolver: Cache expired for {}.", hostname));
-                                    self.dns_cache.remove(&hostname);
-                                    // Fall through to network lookup
-                                    self.perform_network_lookup(&hostname, current_time_ms)
-                                }
-                            } else {
-                                log(&alloc::format!("DNS Resolver: Cache miss for {}, performing network lookup.", hostname));
-                                self.perform_network_lookup(&hostname, current_time_ms)
-                            }
-                        },
-                    };
+                    // Before any other IPC, which would replace the last sender.
+                    let caller = session_ipc::caller_identity();
+                    let response = self.handle_request(request, caller, current_time_ms);
                     self.client_chan.send(&response).unwrap_or_else(|_| log("DNS Resolver: Failed to send response to client."));
                 } else {
                     log("DNS Resolver: Failed to deserialize DnsRequest from client.");
                 }
             }
 
+            // 2. Follow the interface going down and coming back up
+            self.handle_net_events(current_time_ms);
+
+            // 3. Answer other hosts' mDNS queries and keep our hostname claimed
+            self.poll_mdns(current_time_ms);
+
             // Yield to other V-Nodes to prevent busy-waiting
             unsafe { syscall3(SYS_TIME, 0, 0, 0); } // This will cause a context switch
         }
     }
 }
 
+impl Transport for DnsResolver {
+    /// One attempt at `server`: over UDP, and again over TCP if the answer was
+    /// truncated, or over TCP only. UDP waits `timeout_ms`; TCP gets the
+    /// connect timeout on top.
+    fn ask(&mut self, server: DnsServer, id: u16, dns_query_payload: Vec<u8>, hostname: &str) -> Result<Vec<u8>, AttemptError> {
+        let timeout_ms = self.retry.timeout_ms;
+        let tcp_deadline_ms = || time::ticks().to_millis().raw() + DEFAULT_CONNECT_TIMEOUT_MS as u64 + timeout_ms;
+        if server.tcp_only {
+            return tcp::query(&mut self.socket_chan, server.addr, &dns_query_payload, tcp_deadline_ms()).map_err(|e| {
+                log(&alloc::format!("DNS Resolver: DNS over TCP for {} failed: {}.", hostname, e.describe()));
+                AttemptError::from(e)
+            });
+        }
+
+        let udp_deadline_ms = time::ticks().to_millis().raw() + timeout_ms;
+        let udp_payload = self.query_udp(server.addr, id, dns_query_payload.clone(), hostname, udp_deadline_ms)?;
+        if !wire::is_truncated(&udp_payload) {
+            return Ok(udp_payload);
+        }
+        // The answer didn't fit in a datagram; ask again over TCP.
+        log(&alloc::format!("DNS Resolver: Response for {} was truncated, retrying over TCP.", hostname));
+        match tcp::query(&mut self.socket_chan, server.addr, &dns_query_payload, tcp_deadline_ms()) {
+            Ok(response_payload) => Ok(response_payload),
+            Err(e) if matches!(wire::parse_response(id, hostname, &udp_payload), Ok(Answer::Addresses { .. })) => {
+                log(&alloc::format!("DNS Resolver: DNS over TCP for {} failed: {}. Using the truncated answer.", hostname, e.describe()));
+                Ok(udp_payload)
+            },
+            Err(e) => {
+                log(&alloc::format!("DNS Resolver: DNS over TCP for {} failed: {}.", hostname, e.describe()));
+                Err(AttemptError::from(e))
+            },
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel IDs:
-    // 5 for DNS Resolver Service client requests
-    // 4 for Socket API Service
-    // 6 for AetherFS (for config reads, currently conceptual)
-    let mut dns_resolver = DnsResolver::new(5, 4, 6);
+    // Channels come from init; the well-known IDs are the fallback. Config
+    // reads go to AetherFS on init's channel (currently conceptual), and the
+    // network stack gives the interface address for mDNS.
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let mut dns_resolver = DnsResolver::new(
+        channel(SELF_CHANNEL, channels::DNS_RESOLVER),
+        channel("socket-api", channels::SOCKET_API),
+        channels::INIT_SERVICE,
+        channel("aethernet-service", channels::NET_STACK),
+        channel("settings", channels::SETTINGS),
+        channel("event-bus", channels::EVENT_BUS),
+        channels::DNS_RESOLVER_EVENTS,
+        channels::DNS_RESOLVER_READY,
+    );
     dns_resolver.run_loop();
 }
 
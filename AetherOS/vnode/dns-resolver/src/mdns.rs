@@ -0,0 +1,527 @@
+// vnode/dns-resolver/src/mdns.rs
+
+//! Multicast DNS (RFC 6762) for names under ".local".
+//!
+//! Nothing in here touches a socket. `Mdns` takes received packets and the
+//! current time and returns `MdnsEvent`s: packets to multicast, records for
+//! the cache, lookups that timed out and renames. main.rs moves packets
+//! between it and the mDNS socket.
+//!
+//! As a responder it claims `<hostname>.local` for the node's IPv4 address. It
+//! probes for the name three times, 250 ms apart, announces it twice, a second
+//! apart, and from then on answers A and ANY queries for it. If another host
+//! answers for the name with a different address, during probing or later,
+//! the name gets a numeric suffix ("aether-1a2b3c-2", "-3", ...) and probing
+//! starts over.
+//!
+//! As a resolver it multicasts a query, repeats it after 1 and 3 seconds and
+//! gives up 5 seconds after the first one.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::wire::{read_name, read_u16, read_u32, write_name, CLASS_IN, TYPE_A};
+
+pub const MDNS_GROUP: [u8; 4] = [224, 0, 0, 251];
+pub const MDNS_PORT: u16 = 5353;
+
+/// TTL of the host record we announce, and the longest we cache an answer.
+pub const HOST_RECORD_TTL_SECS: u32 = 120;
+
+const PROBE_COUNT: u32 = 3;
+const PROBE_INTERVAL_MS: u64 = 250;
+const ANNOUNCE_COUNT: u32 = 2;
+const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+
+/// When a lookup's query is repeated, in ms after the first one.
+const QUERY_RETRY_AT_MS: [u64; 2] = [1000, 3000];
+const QUERY_TIMEOUT_MS: u64 = 5000;
+
+const TYPE_ANY: u16 = 255;
+const CLASS_MASK: u16 = 0x7FFF; // The top bit is cache-flush (records) or unicast-response (questions)
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAGS_RESPONSE: u16 = 0x8400; // QR and AA
+const FLAG_QR: u16 = 0x8000;
+
+/// Whether `name` is resolved with mDNS rather than unicast DNS.
+pub fn is_local_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    name.len() > ".local".len() && name[name.len() - ".local".len()..].eq_ignore_ascii_case(".local")
+}
+
+/// The hostname used when none is configured, from the low three bytes of
+/// the interface's MAC address, which identify the node on the LAN.
+pub fn default_hostname(mac: &[u8; 6]) -> String {
+    format!("aether-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
+
+/// Whether `label` can be used as our hostname: one DNS label of letters,
+/// digits and hyphens, with room left for a "-NN" suffix.
+pub fn is_valid_hostname(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 59
+        && !label.starts_with('-')
+        && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Question {
+    name: String,
+    qtype: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    name: String,
+    rtype: u16,
+    ttl: u32,
+    data: Vec<u8>,
+}
+
+/// The parts of a DNS message mDNS needs. Additional records are folded into
+/// `answers`, since responders put address records in either section.
+#[derive(Debug, Default)]
+struct Message {
+    response: bool,
+    questions: Vec<Question>,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+}
+
+fn read_record(packet: &[u8], pos: usize) -> Option<(Record, usize)> {
+    let (name, pos) = read_name(packet, pos)?;
+    let rtype = read_u16(packet, pos)?;
+    let class = read_u16(packet, pos + 2)? & CLASS_MASK;
+    let ttl = read_u32(packet, pos + 4)?;
+    let len = read_u16(packet, pos + 8)? as usize;
+    let data = packet.get(pos + 10..pos + 10 + len)?.to_vec();
+    // Records of other classes are skipped, not rejected, so one odd record
+    // doesn't hide the rest of the packet.
+    let rtype = if class == CLASS_IN { rtype } else { 0 };
+    Some((Record { name, rtype, ttl, data }, pos + 10 + len))
+}
+
+fn parse(packet: &[u8]) -> Option<Message> {
+    let flags = read_u16(packet, 2)?;
+    let counts = [read_u16(packet, 4)?, read_u16(packet, 6)?, read_u16(packet, 8)?, read_u16(packet, 10)?];
+    let mut message = Message { response: flags & FLAG_QR != 0, ..Message::default() };
+    let mut pos = 12;
+    for _ in 0..counts[0] {
+        let (name, next) = read_name(packet, pos)?;
+        let qtype = read_u16(packet, next)?;
+        message.questions.push(Question { name, qtype });
+        pos = next + 4;
+    }
+    for (section, count) in counts.iter().enumerate().skip(1) {
+        for _ in 0..*count {
+            let (record, next) = read_record(packet, pos)?;
+            if section == 2 {
+                message.authority.push(record);
+            } else {
+                message.answers.push(record);
+            }
+            pos = next;
+        }
+    }
+    Some(message)
+}
+
+/// Our records are unique to us, so answers carry the cache-flush bit. The
+/// records in a probe's authority section must not.
+fn write_record(out: &mut Vec<u8>, record: &Record, cache_flush: bool) {
+    write_name(out, &record.name);
+    out.extend_from_slice(&record.rtype.to_be_bytes());
+    out.extend_from_slice(&(if cache_flush { CLASS_IN | CACHE_FLUSH } else { CLASS_IN }).to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+    out.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+    out.extend_from_slice(&record.data);
+}
+
+/// Encodes a message without name compression. mDNS messages use ID 0.
+fn encode(message: &Message) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64);
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(if message.response { FLAGS_RESPONSE } else { 0 }).to_be_bytes());
+    for count in [message.questions.len(), message.answers.len(), message.authority.len(), 0] {
+        out.extend_from_slice(&(count as u16).to_be_bytes());
+    }
+    for question in &message.questions {
+        write_name(&mut out, &question.name);
+        out.extend_from_slice(&question.qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for record in &message.answers {
+        write_record(&mut out, record, true);
+    }
+    for record in &message.authority {
+        write_record(&mut out, record, false);
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MdnsEvent {
+    /// Multicast this packet to `MDNS_GROUP:MDNS_PORT`.
+    Send(Vec<u8>),
+    /// An address record seen on the network. A TTL of 0 withdraws the name.
+    Answer { name: String, ip: [u8; 4], ttl_secs: u32 },
+    /// Nobody answered the lookup for `name` in time.
+    NotFound { name: String },
+    /// Another host holds our name; we now use `to`. Both are full ".local" names.
+    Renamed { from: String, to: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Claim {
+    Probing { sent: u32, next_at: u64 },
+    Announcing { sent: u32, next_at: u64 },
+    Claimed,
+}
+
+struct Lookup {
+    started_at: u64,
+    retries_sent: usize,
+}
+
+pub struct Mdns {
+    base: String, // Configured hostname label, without a conflict suffix
+    suffix: u32, // 1 until the first conflict
+    ip: [u8; 4],
+    claim: Claim,
+    lookups: BTreeMap<String, Lookup>,
+}
+
+impl Mdns {
+    /// Starts claiming `hostname` (a single label) for `ip`. The first probe
+    /// goes out on the first `poll`.
+    pub fn new(hostname: &str, ip: [u8; 4], now: u64) -> Self {
+        Self {
+            base: hostname.to_ascii_lowercase(),
+            suffix: 1,
+            ip,
+            claim: Claim::Probing { sent: 0, next_at: now },
+            lookups: BTreeMap::new(),
+        }
+    }
+
+    /// Our full name, e.g. "aether-1a2b3c.local".
+    pub fn hostname(&self) -> String {
+        if self.suffix == 1 {
+            format!("{}.local", self.base)
+        } else {
+            format!("{}-{}.local", self.base, self.suffix)
+        }
+    }
+
+    pub fn is_claimed(&self) -> bool {
+        self.claim == Claim::Claimed
+    }
+
+    pub fn is_pending(&self, name: &str) -> bool {
+        self.lookups.contains_key(name)
+    }
+
+    fn own_record(&self, ttl: u32) -> Record {
+        Record { name: self.hostname(), rtype: TYPE_A, ttl, data: self.ip.to_vec() }
+    }
+
+    /// Starts resolving `name` (lower case, ending in ".local"). Our own name
+    /// is answered directly once claimed.
+    pub fn lookup(&mut self, name: &str, now: u64) -> Vec<MdnsEvent> {
+        if self.is_claimed() && name == self.hostname() {
+            return vec![MdnsEvent::Answer { name: String::from(name), ip: self.ip, ttl_secs: HOST_RECORD_TTL_SECS }];
+        }
+        if self.lookups.contains_key(name) {
+            return Vec::new();
+        }
+        self.lookups.insert(String::from(name), Lookup { started_at: now, retries_sent: 0 });
+        vec![MdnsEvent::Send(query(name, TYPE_A))]
+    }
+
+    /// Sends due probes, announcements and query retries, and fails lookups
+    /// that timed out.
+    pub fn poll(&mut self, now: u64) -> Vec<MdnsEvent> {
+        let mut events = Vec::new();
+        match self.claim {
+            Claim::Probing { sent, next_at } if now >= next_at => {
+                if sent < PROBE_COUNT {
+                    let probe = Message {
+                        questions: vec![Question { name: self.hostname(), qtype: TYPE_ANY }],
+                        authority: vec![self.own_record(HOST_RECORD_TTL_SECS)],
+                        ..Message::default()
+                    };
+                    events.push(MdnsEvent::Send(encode(&probe)));
+                    self.claim = Claim::Probing { sent: sent + 1, next_at: now + PROBE_INTERVAL_MS };
+                } else {
+                    // Nobody objected within an interval of the last probe.
+                    self.claim = Claim::Announcing { sent: 0, next_at: now };
+                }
+            },
+            _ => {},
+        }
+        if let Claim::Announcing { sent, next_at } = self.claim {
+            if now >= next_at {
+                let announcement = Message { response: true, answers: vec![self.own_record(HOST_RECORD_TTL_SECS)], ..Message::default() };
+                events.push(MdnsEvent::Send(encode(&announcement)));
+                self.claim = if sent + 1 < ANNOUNCE_COUNT {
+                    Claim::Announcing { sent: sent + 1, next_at: now + ANNOUNCE_INTERVAL_MS }
+                } else {
+                    Claim::Claimed
+                };
+            }
+        }
+
+        let mut failed = Vec::new();
+        for (name, lookup) in self.lookups.iter_mut() {
+            let elapsed = now.saturating_sub(lookup.started_at);
+            if elapsed >= QUERY_TIMEOUT_MS {
+                failed.push(name.clone());
+            } else if QUERY_RETRY_AT_MS.get(lookup.retries_sent).map_or(false, |at| elapsed >= *at) {
+                lookup.retries_sent += 1;
+                events.push(MdnsEvent::Send(query(name, TYPE_A)));
+            }
+        }
+        for name in failed {
+            self.lookups.remove(&name);
+            events.push(MdnsEvent::NotFound { name });
+        }
+        events
+    }
+
+    /// Processes a packet received on the mDNS socket. Malformed packets are ignored.
+    pub fn handle_packet(&mut self, packet: &[u8], now: u64) -> Vec<MdnsEvent> {
+        let message = match parse(packet) {
+            Some(message) => message,
+            None => return Vec::new(),
+        };
+        if message.response {
+            self.handle_response(message, now)
+        } else {
+            self.handle_query(message, now)
+        }
+    }
+
+    fn handle_response(&mut self, message: Message, now: u64) -> Vec<MdnsEvent> {
+        let own_name = self.hostname();
+        let mut events = Vec::new();
+        for record in message.answers {
+            if record.rtype != TYPE_A || record.data.len() != 4 {
+                continue;
+            }
+            let ip = [record.data[0], record.data[1], record.data[2], record.data[3]];
+            if record.name == own_name {
+                if ip != self.ip {
+                    events.push(self.rename(now));
+                    break;
+                }
+                continue;
+            }
+            if !is_local_name(&record.name) {
+                continue;
+            }
+            self.lookups.remove(&record.name);
+            events.push(MdnsEvent::Answer { name: record.name, ip, ttl_secs: record.ttl.min(HOST_RECORD_TTL_SECS) });
+        }
+        events
+    }
+
+    fn handle_query(&mut self, message: Message, now: u64) -> Vec<MdnsEvent> {
+        let own_name = self.hostname();
+        let asks_for_us = message.questions.iter().any(|q| q.name == own_name && (q.qtype == TYPE_A || q.qtype == TYPE_ANY));
+        if !asks_for_us {
+            return Vec::new();
+        }
+        match self.claim {
+            Claim::Probing { .. } => {
+                // Simultaneous probe tie-break (RFC 6762 section 8.2): the
+                // lexicographically greater record data wins.
+                let theirs = message.authority.iter().find(|r| r.name == own_name && r.rtype == TYPE_A);
+                match theirs {
+                    Some(theirs) if theirs.data.as_slice() > &self.ip[..] => vec![self.rename(now)],
+                    _ => Vec::new(),
+                }
+            },
+            Claim::Announcing { .. } | Claim::Claimed => {
+                // Known-answer suppression: the asker already has our record
+                // with at least half its lifetime left.
+                let known = message.answers.iter().any(|r| {
+                    r.name == own_name && r.rtype == TYPE_A && r.data == self.ip && r.ttl >= HOST_RECORD_TTL_SECS / 2
+                });
+                if known {
+                    return Vec::new();
+                }
+                let response = Message { response: true, answers: vec![self.own_record(HOST_RECORD_TTL_SECS)], ..Message::default() };
+                vec![MdnsEvent::Send(encode(&response))]
+            },
+        }
+    }
+
+    /// Gives up our name for the next suffix and starts probing for that.
+    fn rename(&mut self, now: u64) -> MdnsEvent {
+        let from = self.hostname();
+        self.suffix += 1;
+        self.claim = Claim::Probing { sent: 0, next_at: now };
+        MdnsEvent::Renamed { from, to: self.hostname() }
+    }
+}
+
+fn query(name: &str, qtype: u16) -> Vec<u8> {
+    encode(&Message { questions: vec![Question { name: String::from(name), qtype }], ..Message::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUR_IP: [u8; 4] = [10, 0, 2, 15];
+
+    fn a_record(name: &str, ip: [u8; 4], ttl: u32) -> Record {
+        Record { name: String::from(name), rtype: TYPE_A, ttl, data: ip.to_vec() }
+    }
+
+    /// Another host's answer for `name`.
+    fn response(name: &str, ip: [u8; 4], ttl: u32) -> Vec<u8> {
+        encode(&Message { response: true, answers: vec![a_record(name, ip, ttl)], ..Message::default() })
+    }
+
+    /// A query for `name`, with `known` as the asker's known answers.
+    fn query_with(name: &str, known: Vec<Record>) -> Vec<u8> {
+        encode(&Message { questions: vec![Question { name: String::from(name), qtype: TYPE_A }], answers: known, ..Message::default() })
+    }
+
+    fn sent(events: &[MdnsEvent]) -> Vec<Message> {
+        events.iter().filter_map(|event| match event {
+            MdnsEvent::Send(packet) => parse(packet),
+            _ => None,
+        }).collect()
+    }
+
+    /// Runs the probes and announcements through to a claimed name.
+    fn claimed(hostname: &str) -> Mdns {
+        let mut mdns = Mdns::new(hostname, OUR_IP, 0);
+        for now in [0, 250, 500, 750, 1750] {
+            mdns.poll(now);
+        }
+        assert!(mdns.is_claimed());
+        mdns
+    }
+
+    #[test]
+    fn the_name_is_probed_three_times_then_announced_twice() {
+        let mut mdns = Mdns::new("Aether", OUR_IP, 0);
+        for now in [0, 250, 500] {
+            let probes = sent(&mdns.poll(now));
+            assert_eq!(probes.len(), 1);
+            assert!(!probes[0].response);
+            assert_eq!(probes[0].questions, vec![Question { name: String::from("aether.local"), qtype: TYPE_ANY }]);
+            assert_eq!(probes[0].authority, vec![a_record("aether.local", OUR_IP, HOST_RECORD_TTL_SECS)]);
+            assert!(mdns.poll(now + PROBE_INTERVAL_MS - 1).is_empty());
+        }
+        let announcement = sent(&mdns.poll(750));
+        assert!(announcement[0].response);
+        assert_eq!(announcement[0].answers, vec![a_record("aether.local", OUR_IP, HOST_RECORD_TTL_SECS)]);
+        assert!(!mdns.is_claimed());
+        assert!(mdns.poll(1749).is_empty());
+        assert_eq!(sent(&mdns.poll(1750)).len(), 1);
+        assert!(mdns.is_claimed());
+        assert!(mdns.poll(10_000).is_empty());
+    }
+
+    #[test]
+    fn a_conflicting_answer_while_probing_renames_and_probes_again() {
+        let mut mdns = Mdns::new("aether", OUR_IP, 0);
+        mdns.poll(0);
+        let events = mdns.handle_packet(&response("aether.local", [10, 0, 2, 99], 120), 100);
+        assert_eq!(events, vec![MdnsEvent::Renamed { from: String::from("aether.local"), to: String::from("aether-2.local") }]);
+        let probes = sent(&mdns.poll(100));
+        assert_eq!(probes[0].questions[0].name, "aether-2.local");
+
+        mdns.handle_packet(&response("aether-2.local", [10, 0, 2, 98], 120), 200);
+        assert_eq!(mdns.hostname(), "aether-3.local");
+    }
+
+    #[test]
+    fn a_claimed_name_is_given_up_only_for_a_different_address() {
+        let mut mdns = claimed("aether");
+        assert!(mdns.handle_packet(&response("aether.local", OUR_IP, 120), 2000).is_empty());
+        assert!(mdns.is_claimed());
+        assert_eq!(mdns.handle_packet(&response("aether.local", [10, 0, 2, 99], 120), 2000).len(), 1);
+        assert_eq!(mdns.hostname(), "aether-2.local");
+        assert!(!mdns.is_claimed());
+    }
+
+    #[test]
+    fn simultaneous_probes_are_won_by_the_greater_address() {
+        let probe_from = |ip: [u8; 4]| encode(&Message {
+            questions: vec![Question { name: String::from("aether.local"), qtype: TYPE_ANY }],
+            authority: vec![a_record("aether.local", ip, 120)],
+            ..Message::default()
+        });
+        let mut mdns = Mdns::new("aether", OUR_IP, 0);
+        mdns.poll(0);
+        assert!(mdns.handle_packet(&probe_from([10, 0, 2, 14]), 100).is_empty());
+        assert_eq!(mdns.hostname(), "aether.local");
+        assert_eq!(mdns.handle_packet(&probe_from([10, 0, 2, 16]), 100).len(), 1);
+        assert_eq!(mdns.hostname(), "aether-2.local");
+    }
+
+    #[test]
+    fn known_answers_with_half_their_lifetime_left_suppress_ours() {
+        let mut mdns = claimed("aether");
+        let answered = |mdns: &mut Mdns, known: Vec<Record>| sent(&mdns.handle_packet(&query_with("aether.local", known), 2000));
+
+        let answer = answered(&mut mdns, Vec::new());
+        assert_eq!(answer[0].answers, vec![a_record("aether.local", OUR_IP, HOST_RECORD_TTL_SECS)]);
+        assert!(answered(&mut mdns, vec![a_record("aether.local", OUR_IP, HOST_RECORD_TTL_SECS / 2)]).is_empty());
+        assert_eq!(answered(&mut mdns, vec![a_record("aether.local", OUR_IP, HOST_RECORD_TTL_SECS / 2 - 1)]).len(), 1);
+        assert_eq!(answered(&mut mdns, vec![a_record("aether.local", [10, 0, 2, 99], 120)]).len(), 1);
+        assert!(sent(&mdns.handle_packet(&query_with("other.local", Vec::new()), 2000)).is_empty());
+    }
+
+    #[test]
+    fn lookups_are_repeated_then_given_up() {
+        let mut mdns = Mdns::new("aether", OUR_IP, 0);
+        let first = sent(&mdns.lookup("printer.local", 0));
+        assert_eq!(first[0].questions, vec![Question { name: String::from("printer.local"), qtype: TYPE_A }]);
+        assert!(mdns.lookup("printer.local", 10).is_empty(), "one query for a pending lookup");
+
+        let queries = |events: Vec<MdnsEvent>| sent(&events).iter().filter(|m| m.questions.iter().any(|q| q.name == "printer.local")).count();
+        assert_eq!(queries(mdns.poll(999)), 0);
+        assert_eq!(queries(mdns.poll(1000)), 1);
+        assert_eq!(queries(mdns.poll(2000)), 0);
+        assert_eq!(queries(mdns.poll(3000)), 1);
+        assert!(mdns.is_pending("printer.local"));
+        assert!(mdns.poll(5000).contains(&MdnsEvent::NotFound { name: String::from("printer.local") }));
+        assert!(!mdns.is_pending("printer.local"));
+    }
+
+    #[test]
+    fn an_answer_ends_the_lookup_with_its_ttl_capped() {
+        let mut mdns = claimed("aether");
+        mdns.lookup("printer.local", 0);
+        let events = mdns.handle_packet(&response("printer.local", [10, 0, 2, 40], 4500), 100);
+        assert_eq!(events, vec![MdnsEvent::Answer { name: String::from("printer.local"), ip: [10, 0, 2, 40], ttl_secs: HOST_RECORD_TTL_SECS }]);
+        assert!(!mdns.is_pending("printer.local"));
+        assert!(mdns.handle_packet(&response("example.com", [1, 2, 3, 4], 60), 100).is_empty());
+
+        assert_eq!(mdns.lookup("aether.local", 200), vec![MdnsEvent::Answer { name: String::from("aether.local"), ip: OUR_IP, ttl_secs: HOST_RECORD_TTL_SECS }]);
+    }
+
+    #[test]
+    fn names_and_hostnames() {
+        assert!(is_local_name("printer.local"));
+        assert!(is_local_name("Printer.LOCAL."));
+        assert!(!is_local_name(".local"));
+        assert!(!is_local_name("example.com"));
+        assert_eq!(default_hostname(&[0x52, 0x54, 0x00, 0x1a, 0x2b, 0x3c]), "aether-1a2b3c");
+        assert!(is_valid_hostname("aether-1a2b3c"));
+        assert!(!is_valid_hostname("-aether"));
+        assert!(!is_valid_hostname("aether.local"));
+        assert!(!is_valid_hostname(&"a".repeat(60)));
+    }
+}
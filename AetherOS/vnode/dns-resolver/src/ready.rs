@@ -0,0 +1,57 @@
+// vnode/dns-resolver/src/ready.rs
+
+//! Which of the resolver's UDP sockets have something to receive.
+//!
+//! Instead of asking socket-api with a `Recv` on every pass of the loop, the
+//! resolver watches a socket with `RecvAsync` and receives only once
+//! socket-api has sent `DataAvailable` for it. A watch is answered once, so
+//! after reading a socket empty it is watched again. Data that arrived in
+//! between is reported right away, so none is missed.
+
+use alloc::vec::Vec;
+
+use common::ipc::socket_ipc::{SocketFd, SocketRequest, SocketResponse};
+use common::ipc::vnode::VNodeChannel;
+
+pub struct Readiness {
+    chan: VNodeChannel, // Where socket-api sends `DataAvailable`
+    ready: Vec<SocketFd>,
+}
+
+impl Readiness {
+    pub fn new(chan_id: u32) -> Self {
+        Self { chan: VNodeChannel::new(chan_id), ready: Vec::new() }
+    }
+
+    /// Asks socket-api for a `DataAvailable` once `fd` has data. If it won't,
+    /// `fd` counts as always ready, and is polled as before.
+    pub fn watch(&mut self, socket_chan: &mut VNodeChannel, fd: SocketFd) {
+        let request = SocketRequest::RecvAsync { fd, reply_chan: self.chan.id };
+        if !matches!(socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&request), Ok(SocketResponse::Success(_))) {
+            self.mark(fd);
+        }
+    }
+
+    /// Takes in the `DataAvailable`s socket-api has sent.
+    pub fn collect(&mut self) {
+        while let Ok(Some(data)) = self.chan.recv_non_blocking() {
+            if let Ok(SocketResponse::DataAvailable { fd }) = postcard::from_bytes::<SocketResponse>(&data) {
+                self.mark(fd);
+            }
+        }
+    }
+
+    /// Whether `fd` has data, forgetting that it had.
+    pub fn take(&mut self, fd: SocketFd) -> bool {
+        let before = self.ready.len();
+        self.ready.retain(|&ready| ready != fd);
+        self.ready.len() != before
+    }
+
+    /// Notes that `fd` has data, e.g. more than one pass of the loop took off it.
+    pub fn mark(&mut self, fd: SocketFd) {
+        if !self.ready.contains(&fd) {
+            self.ready.push(fd);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// vnode/dns-resolver/src/tcp.rs
+
+//! DNS over TCP (RFC 1035 section 4.2.2, RFC 7766).
+//!
+//! Used when a UDP answer comes back truncated or the server is configured
+//! TCP-only. Each message on the stream is preceded by its length as two
+//! big-endian bytes. One connection carries one query: it is opened, the
+//! query written, the answer read and the connection closed again.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::socket_ipc::{SocketFd, SocketRequest, SocketResponse, DEFAULT_CONNECT_TIMEOUT_MS};
+use common::ipc::vnode::VNodeChannel;
+use common::time;
+
+/// The port DNS servers listen on, for UDP and TCP alike.
+pub const DNS_PORT: u16 = 53;
+
+/// Most bytes asked of socket-api per `Recv`.
+const RECV_CHUNK: u32 = 1024;
+
+/// Prefixes `message` with its length, as DNS messages are sent over TCP.
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// Reassembles one length-prefixed message from reads of any size. The
+/// stream may split anywhere, between the two length bytes included.
+#[derive(Default)]
+pub struct Reassembler {
+    buf: Vec<u8>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the bytes of one read. Returns the message once all of it is
+    /// here; anything after it is not ours and is dropped.
+    pub fn push(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.buf.extend_from_slice(bytes);
+        let len = self.expected()?;
+        if self.buf.len() < 2 + len {
+            return None;
+        }
+        Some(self.buf[2..2 + len].to_vec())
+    }
+
+    /// The length of the message, once both prefix bytes are in.
+    fn expected(&self) -> Option<usize> {
+        match self.buf.as_slice() {
+            [hi, lo, ..] => Some(u16::from_be_bytes([*hi, *lo]) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Why a query over TCP got no answer.
+#[derive(Debug)]
+pub enum TcpError {
+    /// The server reset the connection attempt (ECONNREFUSED).
+    Refused,
+    /// Too little of the query's budget was left to connect, or the answer
+    /// didn't arrive before the deadline. A server that closes the connection
+    /// mid-answer ends up here too: socket-api reads a closed stream as empty.
+    TimedOut,
+    /// socket-api refused or failed a request.
+    Socket(i32, String),
+}
+
+impl TcpError {
+    pub fn describe(&self) -> String {
+        match self {
+            TcpError::Refused => "connection refused".to_string(),
+            TcpError::TimedOut => "timed out".to_string(),
+            TcpError::Socket(code, message) => format!("{} ({})", message, code),
+        }
+    }
+}
+
+/// Sends `query` to `server` over TCP and returns the answer, unframed.
+/// Gives up at `deadline_ms`. A connect can take socket-api's whole
+/// handshake timeout, so it isn't started when less than that is left.
+pub fn query(socket_chan: &mut VNodeChannel, server: [u8; 4], query: &[u8], deadline_ms: u64) -> Result<Vec<u8>, TcpError> {
+    if now_ms() + DEFAULT_CONNECT_TIMEOUT_MS as u64 > deadline_ms {
+        return Err(TcpError::TimedOut);
+    }
+    let fd = match request(socket_chan, SocketRequest::Socket { domain: 2, ty: 1, protocol: 0 })? {
+        SocketResponse::Success(fd) => SocketFd::from_raw(fd as u32),
+        _ => return Err(unexpected()),
+    };
+    let result = exchange(socket_chan, fd, server, query, deadline_ms);
+    let _ = socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Close { fd });
+    result
+}
+
+fn exchange(socket_chan: &mut VNodeChannel, fd: SocketFd, server: [u8; 4], query: &[u8], deadline_ms: u64) -> Result<Vec<u8>, TcpError> {
+    match request(socket_chan, SocketRequest::Connect { fd, addr: server, port: DNS_PORT }) {
+        Ok(SocketResponse::Success(_)) => {},
+        Err(TcpError::Socket(111, _)) => return Err(TcpError::Refused),
+        Err(TcpError::Socket(110, _)) => return Err(TcpError::TimedOut),
+        Err(e) => return Err(e),
+        Ok(_) => return Err(unexpected()),
+    }
+
+    let mut framed = frame(query);
+    while !framed.is_empty() {
+        match request(socket_chan, SocketRequest::Send { fd, data: framed.clone() })? {
+            SocketResponse::Success(sent) if sent > 0 => { framed.drain(..(sent as usize).min(framed.len())); },
+            SocketResponse::Success(_) if now_ms() < deadline_ms => {},
+            SocketResponse::Success(_) => return Err(TcpError::TimedOut),
+            _ => return Err(unexpected()),
+        }
+    }
+
+    let mut answer = Reassembler::new();
+    loop {
+        match request(socket_chan, SocketRequest::Recv { fd, len: RECV_CHUNK })? {
+            SocketResponse::Data(bytes) if !bytes.is_empty() => {
+                if let Some(message) = answer.push(&bytes) {
+                    return Ok(message);
+                }
+            },
+            SocketResponse::Data(_) => {},
+            _ => return Err(unexpected()),
+        }
+        // Reading SYS_TIME yields, so waiting for the rest doesn't spin.
+        if now_ms() >= deadline_ms {
+            return Err(TcpError::TimedOut);
+        }
+    }
+}
+
+/// Sends one request to socket-api. Its refusals become `TcpError::Socket`.
+fn request(socket_chan: &mut VNodeChannel, request: SocketRequest) -> Result<SocketResponse, TcpError> {
+    match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&request) {
+        Ok(SocketResponse::Error(code, message)) => Err(TcpError::Socket(code, message)),
+        Ok(SocketResponse::PolicyDenied { .. }) => Err(TcpError::Socket(1, "denied by network policy".to_string())),
+        Ok(SocketResponse::NetworkDown) => Err(TcpError::Socket(100, "network is down".to_string())),
+        Ok(response) => Ok(response),
+        Err(_) => Err(TcpError::Socket(5, "socket-api did not answer".to_string())),
+    }
+}
+
+fn unexpected() -> TcpError {
+    TcpError::Socket(5, "unexpected response from socket-api".to_string())
+}
+
+fn now_ms() -> u64 {
+    time::ticks().to_millis().raw()
+}
@@ -0,0 +1,473 @@
+// vnode/dns-resolver/src/wire.rs
+
+//! The DNS message format (RFC 1035 section 4): unicast queries and their
+//! answers, and the name and integer readers mDNS uses too.
+//!
+//! Nothing in here touches a socket. `query` builds the question for a
+//! name's A records and `parse_response` reads what the server sent back:
+//! the addresses and how long they may be cached, following CNAMEs to them,
+//! or that there are none and how long that may be cached. Anything in a
+//! response that doesn't add up is a `WireError`, never a panic.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+pub const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+pub const CLASS_IN: u16 = 1;
+
+const HEADER_LEN: usize = 12;
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_MASK: u16 = 0x000F;
+const RCODE_NXDOMAIN: u16 = 3;
+/// Longest name on the wire, counting the length bytes and the root label.
+const MAX_NAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+/// TTLs with the top bit set are read as 0 (RFC 2181 section 8).
+const MAX_TTL: u32 = 0x7FFF_FFFF;
+/// Most CNAMEs followed from the name asked; a longer chain is likely a loop.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Why a name can't be asked for, or a response can't be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// The name has an empty label or one over 63 bytes, or is over 255
+    /// bytes encoded.
+    BadName,
+    /// The response ends inside its header or a record, has a name that
+    /// doesn't parse, or a CNAME chain longer than 8.
+    Malformed,
+    /// Not the answer to our query: another ID or question, or not a response.
+    Mismatch,
+    /// The server couldn't answer. Its RCODE: 2 SERVFAIL, 4 NOTIMP, 5 REFUSED, ...
+    Server(u16),
+}
+
+impl WireError {
+    pub fn describe(&self) -> String {
+        match self {
+            WireError::BadName => "not a valid hostname".to_string(),
+            WireError::Malformed => "malformed response".to_string(),
+            WireError::Mismatch => "response to a different query".to_string(),
+            WireError::Server(rcode) => format!("server error (RCODE {})", rcode),
+        }
+    }
+}
+
+/// A CNAME record: `name` is another name for `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    pub target: String,
+    pub ttl_secs: u32,
+}
+
+/// What a response says about the name that was asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    /// The A records of the name, or of the name its `aliases` lead to, in
+    /// the order the server gave them, and the lowest of their TTLs. The
+    /// aliases are in chain order, starting with the name asked.
+    Addresses { aliases: Vec<Alias>, addresses: Vec<[u8; 4]>, ttl_secs: u32 },
+    /// The name doesn't exist (NXDOMAIN), or has no A record. `ttl_secs` is
+    /// how long that may be cached, from the SOA record in the authority
+    /// section (RFC 2308), if there was one.
+    NotFound { ttl_secs: Option<u32> },
+}
+
+/// A resource record. `data` is where its RDATA is in the packet, since
+/// names in it may point elsewhere in the packet.
+struct Rr {
+    owner: String,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    data: Range<usize>,
+}
+
+pub fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]))
+}
+
+pub fn read_u32(packet: &[u8], pos: usize) -> Option<u32> {
+    Some(((read_u16(packet, pos)? as u32) << 16) | read_u16(packet, pos + 2)? as u32)
+}
+
+/// Reads a possibly compressed name at `pos`. Returns it in lower case without
+/// the trailing dot, and the position after it.
+pub fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // Each pointer must go backwards, so this many jumps means a loop.
+    for _ in 0..packet.len() {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            let target = (read_u16(packet, pos)? & 0x3FFF) as usize;
+            if target >= pos {
+                return None;
+            }
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += 1 + len;
+    }
+    None
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+/// Whether the server cut the response short to fit in a datagram (TC).
+pub fn is_truncated(response: &[u8]) -> bool {
+    read_u16(response, 2).map_or(false, |flags| flags & FLAG_TC != 0)
+}
+
+/// A recursive query with transaction ID `id` for the A records of `name`.
+pub fn query(id: u16, name: &str) -> Result<Vec<u8>, WireError> {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.len() + 2 > MAX_NAME_LEN || name.split('.').any(|label| label.is_empty() || label.len() > MAX_LABEL_LEN) {
+        return Err(WireError::BadName);
+    }
+    let mut out = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&FLAG_RD.to_be_bytes());
+    for count in [1u16, 0, 0, 0] {
+        out.extend_from_slice(&count.to_be_bytes());
+    }
+    write_name(&mut out, name);
+    out.extend_from_slice(&TYPE_A.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(out)
+}
+
+/// Reads the response to the query `query(id, name)` built. Records for
+/// other names, types or classes are skipped. In a truncated response the
+/// last records may be cut off; the ones that are whole still count.
+pub fn parse_response(id: u16, name: &str, packet: &[u8]) -> Result<Answer, WireError> {
+    if packet.len() < HEADER_LEN {
+        return Err(WireError::Malformed);
+    }
+    let flags = read_u16(packet, 2).ok_or(WireError::Malformed)?;
+    let question_count = read_u16(packet, 4).ok_or(WireError::Malformed)?;
+    let answer_count = read_u16(packet, 6).ok_or(WireError::Malformed)?;
+    let authority_count = read_u16(packet, 8).ok_or(WireError::Malformed)?;
+    if read_u16(packet, 0) != Some(id) || flags & FLAG_QR == 0 || flags & OPCODE_MASK != 0 || question_count != 1 {
+        return Err(WireError::Mismatch);
+    }
+
+    let (qname, pos) = read_name(packet, HEADER_LEN).ok_or(WireError::Malformed)?;
+    let qtype = read_u16(packet, pos).ok_or(WireError::Malformed)?;
+    let qclass = read_u16(packet, pos + 2).ok_or(WireError::Malformed)?;
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if qname != name || qtype != TYPE_A || qclass != CLASS_IN {
+        return Err(WireError::Mismatch);
+    }
+    let rcode = flags & RCODE_MASK;
+    if rcode != 0 && rcode != RCODE_NXDOMAIN {
+        return Err(WireError::Server(rcode));
+    }
+
+    let truncated = flags & FLAG_TC != 0;
+    let mut answers = Vec::new();
+    let mut authority = Vec::new();
+    let mut pos = pos + 4;
+    for i in 0..answer_count as usize + authority_count as usize {
+        let Some((record, next)) = read_record(packet, pos) else {
+            if truncated {
+                break;
+            }
+            return Err(WireError::Malformed);
+        };
+        if record.class == CLASS_IN {
+            if i < answer_count as usize { answers.push(record) } else { authority.push(record) }
+        }
+        pos = next;
+    }
+
+    // NXDOMAIN is about the end of the chain, so the aliases don't matter then.
+    let mut aliases = Vec::new();
+    let mut canonical = name;
+    if rcode == 0 {
+        while let Some(cname) = answers.iter().find(|record| record.owner == canonical && record.rtype == TYPE_CNAME) {
+            if aliases.len() == MAX_CNAME_CHAIN {
+                return Err(WireError::Malformed);
+            }
+            let (target, _) = read_name(packet, cname.data.start).ok_or(WireError::Malformed)?;
+            aliases.push(Alias { name: canonical, target: target.clone(), ttl_secs: clamp_ttl(cname.ttl) });
+            canonical = target;
+        }
+    }
+    let mut addresses = Vec::new();
+    let mut ttl_secs = MAX_TTL;
+    for record in answers.iter().filter(|record| rcode == 0 && record.owner == canonical && record.rtype == TYPE_A && record.data.len() == 4) {
+        let data = &packet[record.data.clone()];
+        addresses.push([data[0], data[1], data[2], data[3]]);
+        ttl_secs = ttl_secs.min(clamp_ttl(record.ttl));
+    }
+    if addresses.is_empty() {
+        Ok(Answer::NotFound { ttl_secs: negative_ttl(packet, &authority) })
+    } else {
+        Ok(Answer::Addresses { aliases, addresses, ttl_secs })
+    }
+}
+
+fn clamp_ttl(ttl: u32) -> u32 {
+    if ttl > MAX_TTL { 0 } else { ttl }
+}
+
+/// How long a negative answer may be cached: the lower of the SOA record's
+/// own TTL and its MINIMUM field (RFC 2308 section 5).
+fn negative_ttl(packet: &[u8], authority: &[Rr]) -> Option<u32> {
+    let soa = authority.iter().find(|record| record.rtype == TYPE_SOA)?;
+    let (_, pos) = read_name(packet, soa.data.start)?; // MNAME
+    let (_, pos) = read_name(packet, pos)?; // RNAME
+    // SERIAL, REFRESH, RETRY and EXPIRE come before MINIMUM.
+    let minimum = read_u32(packet, pos + 16).filter(|_| pos + 20 <= soa.data.end)?;
+    Some(clamp_ttl(soa.ttl).min(clamp_ttl(minimum)))
+}
+
+/// The resource record at `pos`, and the position after it.
+fn read_record(packet: &[u8], pos: usize) -> Option<(Rr, usize)> {
+    let (owner, pos) = read_name(packet, pos)?;
+    let rtype = read_u16(packet, pos)?;
+    let class = read_u16(packet, pos + 2)?;
+    let ttl = read_u32(packet, pos + 4)?;
+    let len = read_u16(packet, pos + 8)? as usize;
+    let data = pos + 10..pos + 10 + len;
+    if data.end > packet.len() {
+        return None;
+    }
+    Some((Rr { owner, rtype, class, ttl, data: data.clone() }, data.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const ID: u16 = 0x1234;
+    /// Flags of a recursive response: QR, RD and RA.
+    const RESPONSE: u16 = FLAG_QR | FLAG_RD | 0x0080;
+    /// A pointer to the question's name, right after the header.
+    const TO_QUESTION: [u8; 2] = [0xC0, HEADER_LEN as u8];
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_name(&mut out, name);
+        out
+    }
+
+    fn record(owner: &[u8], rtype: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+        let mut out = owner.to_vec();
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn soa(owner: &[u8], ttl: u32, minimum: u32) -> Vec<u8> {
+        let mut data = name("ns.example.com");
+        data.extend(name("admin.example.com"));
+        for field in [1u32, 7200, 3600, 1_209_600, minimum] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        record(owner, TYPE_SOA, ttl, &data)
+    }
+
+    /// A response to `query(id, qname)` with the given flags and sections.
+    fn response(id: u16, flags: u16, qname: &str, answers: &[Vec<u8>], authority: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = query(id, qname).unwrap();
+        out[2..4].copy_from_slice(&flags.to_be_bytes());
+        out[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        out[8..10].copy_from_slice(&(authority.len() as u16).to_be_bytes());
+        for record in answers.iter().chain(authority) {
+            out.extend_from_slice(record);
+        }
+        out
+    }
+
+    #[test]
+    fn queries_ask_recursively_for_one_a_record() {
+        let packet = query(ID, "Example.com.").unwrap();
+        assert_eq!(&packet[..HEADER_LEN], &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(read_name(&packet, HEADER_LEN), Some((String::from("example.com"), HEADER_LEN + 13)));
+        assert_eq!(&packet[HEADER_LEN + 13..], &[0, 1, 0, 1]);
+        assert!(!is_truncated(&packet));
+    }
+
+    #[test]
+    fn names_that_cant_be_encoded_are_refused() {
+        assert_eq!(query(ID, ""), Err(WireError::BadName));
+        assert_eq!(query(ID, "a..b"), Err(WireError::BadName));
+        assert_eq!(query(ID, &"a".repeat(64)), Err(WireError::BadName));
+        let long = ["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(62)].join(".");
+        assert_eq!(query(ID, &long), Err(WireError::BadName));
+        assert!(query(ID, &"a".repeat(63)).is_ok());
+    }
+
+    #[test]
+    fn compressed_owner_names_point_back_to_the_question() {
+        let packet = response(ID, RESPONSE, "example.com", &[
+            record(&TO_QUESTION, TYPE_A, 300, &[93, 184, 216, 34]),
+            record(&TO_QUESTION, TYPE_A, 60, &[93, 184, 216, 35]),
+        ], &[]);
+        assert_eq!(parse_response(ID, "example.com", &packet), Ok(Answer::Addresses {
+            aliases: Vec::new(),
+            addresses: vec![[93, 184, 216, 34], [93, 184, 216, 35]],
+            ttl_secs: 60,
+        }));
+    }
+
+    #[test]
+    fn cname_targets_are_followed_through_pointers() {
+        // "www" + a pointer to "example.com" inside the question.
+        let mut target = vec![3, b'w', b'w', b'w'];
+        target.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        let mut packet = response(ID, RESPONSE, "example.com", &[], &[]);
+        let cname_at = packet.len();
+        packet.extend(record(&TO_QUESTION, TYPE_CNAME, 30, &target));
+        // The CNAME's RDATA starts 12 bytes into its record.
+        let target_at = (cname_at + 2 + 10) as u8;
+        packet.extend(record(&[0xC0, target_at], TYPE_A, 120, &[10, 0, 0, 1]));
+        packet[7] = 2;
+        assert_eq!(parse_response(ID, "example.com", &packet), Ok(Answer::Addresses {
+            aliases: vec![Alias { name: String::from("example.com"), target: String::from("www.example.com"), ttl_secs: 30 }],
+            addresses: vec![[10, 0, 0, 1]],
+            ttl_secs: 120,
+        }));
+    }
+
+    #[test]
+    fn pointer_loops_and_forward_pointers_are_malformed() {
+        // A pointer to itself, and one to a pointer after it.
+        let mut packet = response(ID, RESPONSE, "example.com", &[], &[]);
+        let at = packet.len();
+        packet.extend(record(&[0xC0, at as u8], TYPE_A, 60, &[1, 2, 3, 4]));
+        packet[7] = 1;
+        assert_eq!(read_name(&packet, at), None);
+        assert_eq!(parse_response(ID, "example.com", &packet), Err(WireError::Malformed));
+
+        let mut packet = response(ID, RESPONSE, "example.com", &[], &[]);
+        let at = packet.len();
+        packet.extend(record(&[0xC0, at as u8 + 2], TYPE_A, 60, &[1, 2, 3, 4]));
+        packet[7] = 1;
+        assert_eq!(parse_response(ID, "example.com", &packet), Err(WireError::Malformed));
+    }
+
+    #[test]
+    fn cname_loops_are_malformed() {
+        let a = name("a.example");
+        let b = name("b.example");
+        let packet = response(ID, RESPONSE, "a.example", &[
+            record(&a, TYPE_CNAME, 60, &b),
+            record(&b, TYPE_CNAME, 60, &a),
+        ], &[]);
+        assert_eq!(parse_response(ID, "a.example", &packet), Err(WireError::Malformed));
+    }
+
+    #[test]
+    fn answers_to_another_query_are_mismatches() {
+        let answer = [record(&TO_QUESTION, TYPE_A, 60, &[1, 2, 3, 4])];
+        let packet = response(ID, RESPONSE, "example.com", &answer, &[]);
+        assert_eq!(parse_response(ID + 1, "example.com", &packet), Err(WireError::Mismatch));
+        assert_eq!(parse_response(ID, "example.org", &packet), Err(WireError::Mismatch));
+        // The question is compared without case or the trailing dot.
+        assert!(parse_response(ID, "EXAMPLE.com.", &packet).is_ok());
+
+        let not_a_response = response(ID, FLAG_RD, "example.com", &answer, &[]);
+        assert_eq!(parse_response(ID, "example.com", &not_a_response), Err(WireError::Mismatch));
+
+        let mut other_type = packet.clone();
+        let qtype_at = HEADER_LEN + 13;
+        other_type[qtype_at..qtype_at + 2].copy_from_slice(&28u16.to_be_bytes()); // AAAA
+        assert_eq!(parse_response(ID, "example.com", &other_type), Err(WireError::Mismatch));
+
+        assert_eq!(parse_response(ID, "example.com", &packet[..HEADER_LEN - 1]), Err(WireError::Malformed));
+    }
+
+    #[test]
+    fn rcodes_map_to_server_errors_or_not_found() {
+        for rcode in [1, 2, 4, 5] {
+            let packet = response(ID, RESPONSE | rcode, "example.com", &[], &[]);
+            assert_eq!(parse_response(ID, "example.com", &packet), Err(WireError::Server(rcode)));
+        }
+        let nxdomain = response(ID, RESPONSE | RCODE_NXDOMAIN, "nope.example.com", &[], &[soa(&name("example.com"), 900, 60)]);
+        assert_eq!(parse_response(ID, "nope.example.com", &nxdomain), Ok(Answer::NotFound { ttl_secs: Some(60) }));
+        let bare = response(ID, RESPONSE | RCODE_NXDOMAIN, "nope.example.com", &[], &[]);
+        assert_eq!(parse_response(ID, "nope.example.com", &bare), Ok(Answer::NotFound { ttl_secs: None }));
+        // An A record in an NXDOMAIN answer doesn't count.
+        let odd = response(ID, RESPONSE | RCODE_NXDOMAIN, "example.com", &[record(&TO_QUESTION, TYPE_A, 60, &[1, 2, 3, 4])], &[]);
+        assert_eq!(parse_response(ID, "example.com", &odd), Ok(Answer::NotFound { ttl_secs: None }));
+        // No A record: NotFound too, with the SOA's TTL when it is the lower one.
+        let empty = response(ID, RESPONSE, "example.com", &[], &[soa(&name("example.com"), 30, 600)]);
+        assert_eq!(parse_response(ID, "example.com", &empty), Ok(Answer::NotFound { ttl_secs: Some(30) }));
+    }
+
+    #[test]
+    fn truncated_responses_keep_their_whole_records() {
+        let answers = [
+            record(&TO_QUESTION, TYPE_A, 60, &[1, 2, 3, 4]),
+            record(&TO_QUESTION, TYPE_A, 60, &[5, 6, 7, 8]),
+        ];
+        let whole = response(ID, RESPONSE | FLAG_TC, "example.com", &answers, &[]);
+        assert!(is_truncated(&whole));
+        let cut = &whole[..whole.len() - 3];
+        assert_eq!(parse_response(ID, "example.com", cut), Ok(Answer::Addresses {
+            aliases: Vec::new(),
+            addresses: vec![[1, 2, 3, 4]],
+            ttl_secs: 60,
+        }));
+
+        // Without TC the same cut is an error.
+        let untruncated = response(ID, RESPONSE, "example.com", &answers, &[]);
+        assert!(!is_truncated(&untruncated));
+        assert_eq!(parse_response(ID, "example.com", &untruncated[..untruncated.len() - 3]), Err(WireError::Malformed));
+        assert!(!is_truncated(&[0x12]));
+    }
+
+    #[test]
+    fn ttls_with_the_top_bit_set_count_as_zero() {
+        let packet = response(ID, RESPONSE, "example.com", &[
+            record(&TO_QUESTION, TYPE_A, 0x8000_0000, &[1, 2, 3, 4]),
+            record(&TO_QUESTION, TYPE_A, 300, &[5, 6, 7, 8]),
+        ], &[]);
+        assert!(matches!(parse_response(ID, "example.com", &packet), Ok(Answer::Addresses { ttl_secs: 0, .. })));
+    }
+
+    #[test]
+    fn records_for_other_names_types_or_sizes_are_skipped() {
+        let packet = response(ID, RESPONSE, "example.com", &[
+            record(&name("other.com"), TYPE_A, 60, &[9, 9, 9, 9]),
+            record(&TO_QUESTION, 28, 60, &[0; 16]),
+            record(&TO_QUESTION, TYPE_A, 60, &[1, 2, 3]),
+            record(&TO_QUESTION, TYPE_A, 45, &[1, 2, 3, 4]),
+        ], &[]);
+        assert_eq!(parse_response(ID, "example.com", &packet), Ok(Answer::Addresses {
+            aliases: Vec::new(),
+            addresses: vec![[1, 2, 3, 4]],
+            ttl_secs: 45,
+        }));
+    }
+}
@@ -0,0 +1,357 @@
+// vnode/event-bus/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod persist;
+mod retention;
+mod subscriptions;
+
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event, DurableDelivery, ReplayFrom};
+use common::random;
+use common::time;
+
+use persist::{Done, Persister};
+use retention::{DurableLog, Next, Retention, MAX_RETAINED_EVENTS};
+use subscriptions::Subscriptions;
+
+const WEEK_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Durable topics every bus has: init's boot steps and service and group
+/// changes, which services that start late or restart most need to catch up on.
+const BUILTIN_DURABLE: &[(&str, Retention, bool)] = &[
+    ("boot.progress", Retention { max_events: 512, max_age_secs: WEEK_SECS }, true),
+    ("service.", Retention { max_events: 256, max_age_secs: WEEK_SECS }, true),
+    ("group.", Retention { max_events: 256, max_age_secs: WEEK_SECS }, true),
+];
+
+/// Events a durable subscription gets per pass of the loop, so one replay
+/// doesn't hold up the others or the requests.
+const REPLAY_BATCH: usize = 32;
+
+/// How often changed persistent logs are written out.
+const FLUSH_INTERVAL_NANOS: u64 = 1_000_000_000;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+/// A subscription fed from a durable topic's log.
+struct DurableSubscription {
+    topic_prefix: String,
+    reply_chan: u32,
+    log: String, // The durable topic's prefix
+    next: u64, // Sequence number of the next event to look at
+    live: bool, // Told it has caught up
+}
+
+struct DurableTopic {
+    log: DurableLog,
+    // While its saved log is being read, publishes are held here: topic,
+    // payload and time. None once it is loaded.
+    loading: Option<Vec<(String, Vec<u8>, u64)>>,
+}
+
+struct EventBus {
+    client_chan: VNodeChannel,
+    subscriptions: Subscriptions,
+    durable: BTreeMap<String, DurableTopic>, // Keyed by prefix
+    durable_subscriptions: Vec<DurableSubscription>,
+    persister: Persister,
+    last_flush: u64, // Monotonic nanoseconds
+}
+
+/// A new log's epoch: random, so a cursor from a log's earlier life never
+/// matches a new one.
+fn new_epoch() -> u64 {
+    let mut bytes = [0u8; 8];
+    if random::fill(&mut bytes) {
+        u64::from_le_bytes(bytes)
+    } else {
+        time::monotonic_nanos()
+    }
+}
+
+impl EventBus {
+    fn new(client_chan_id: u32, vfs_chan_id: u32) -> Self {
+        let client_chan = VNodeChannel::new(client_chan_id);
+        log("Event Bus: Initializing...");
+
+        let mut bus = Self {
+            client_chan,
+            subscriptions: Subscriptions::new(),
+            durable: BTreeMap::new(),
+            durable_subscriptions: Vec::new(),
+            persister: Persister::new(vfs_chan_id),
+            last_flush: time::monotonic_nanos(),
+        };
+        for (prefix, retention, persistent) in BUILTIN_DURABLE {
+            bus.declare_durable(prefix.to_string(), *retention, *persistent);
+        }
+        bus
+    }
+
+    /// Adds a durable topic, or changes its retention. A persistent one
+    /// starts by reading its saved log.
+    fn declare_durable(&mut self, prefix: String, retention: Retention, persistent: bool) {
+        if let Some(topic) = self.durable.get_mut(&prefix) {
+            topic.log.retention = retention;
+            topic.log.persistent = persistent;
+            topic.log.trim(time::now_secs());
+            topic.log.dirty = persistent;
+            return;
+        }
+        log(&format!("Event Bus: Keeping up to {} events of '{}*'{}.", retention.max_events, prefix, if persistent { " across restarts" } else { "" }));
+        let loading = if persistent {
+            self.persister.load(&prefix);
+            Some(Vec::new())
+        } else {
+            None
+        };
+        let log = DurableLog::new(prefix.clone(), retention, persistent, new_epoch());
+        self.durable.insert(prefix, DurableTopic { log, loading });
+    }
+
+    /// The durable topic holding `topic` or, for a subscription, every topic starting with it.
+    fn durable_topic_of(&self, topic: &str) -> Option<&str> {
+        self.durable.keys().find(|prefix| topic.starts_with(prefix.as_str())).map(String::as_str)
+    }
+
+    fn handle_request(&mut self, request: EventBusRequest) -> EventBusResponse {
+        match request {
+            EventBusRequest::Subscribe { topic_prefix, reply_chan } => {
+                if self.subscriptions.subscribe(topic_prefix.clone(), reply_chan) {
+                    log(&format!("Event Bus: Channel {} subscribed to '{}*'.", reply_chan, topic_prefix));
+                }
+                EventBusResponse::Success(0)
+            },
+            EventBusRequest::Unsubscribe { topic_prefix, reply_chan } => {
+                let live = self.subscriptions.unsubscribe(&topic_prefix, reply_chan);
+                let before = self.durable_subscriptions.len();
+                self.durable_subscriptions.retain(|s| !(s.topic_prefix == topic_prefix && s.reply_chan == reply_chan));
+                match live {
+                    None if self.durable_subscriptions.len() == before => {
+                        EventBusResponse::Error(format!("No subscription to '{}' on channel {}.", topic_prefix, reply_chan))
+                    },
+                    Some(sub) if sub.dropped > 0 => {
+                        log(&format!("Event Bus: Channel {} unsubscribed from '{}*' after losing {} of {} events.", reply_chan, topic_prefix, sub.dropped, sub.delivered + sub.dropped));
+                        EventBusResponse::Success(0)
+                    },
+                    _ => {
+                        log(&format!("Event Bus: Channel {} unsubscribed from '{}*'.", reply_chan, topic_prefix));
+                        EventBusResponse::Success(0)
+                    },
+                }
+            },
+            EventBusRequest::Publish { topic, payload } => {
+                let mut delivered = 0;
+                if let Some(prefix) = self.durable_topic_of(&topic).map(str::to_string) {
+                    let now = time::now_secs();
+                    let durable = self.durable.get_mut(&prefix).expect("durable topic");
+                    match &mut durable.loading {
+                        Some(pending) => {
+                            pending.push((topic.clone(), payload.clone(), now));
+                            // More than the log would keep is trimmed anyway.
+                            if pending.len() > durable.log.retention.max_events as usize {
+                                pending.remove(0);
+                            }
+                        },
+                        None => { durable.log.append(topic.clone(), payload.clone(), now); },
+                    }
+                    // Durable subscribers get it from the log, in order.
+                    delivered += self.durable_subscriptions.iter().filter(|s| topic.starts_with(s.topic_prefix.as_str())).count() as u32;
+                }
+                let event = Event { topic: topic.clone(), payload };
+                let published = self.subscriptions.publish(&topic, |reply_chan| VNodeChannel::new(reply_chan).send(&event).is_ok());
+                for reply_chan in &published.started_dropping {
+                    log(&format!("Event Bus: Channel {} isn't taking events; dropping them from '{}' on.", reply_chan, topic));
+                }
+                for (reply_chan, missed) in &published.recovered {
+                    log(&format!("Event Bus: Channel {} takes events again after {} were dropped.", reply_chan, missed));
+                }
+                delivered += published.delivered;
+                if published.dropped > 0 {
+                    log(&format!("Event Bus: Published '{}' to {} subscribers, dropped for {} ({} dropped in all).", topic, delivered, published.dropped, self.subscriptions.dropped()));
+                } else {
+                    log(&format!("Event Bus: Published '{}' to {} subscribers.", topic, delivered));
+                }
+                EventBusResponse::Success(delivered)
+            },
+            EventBusRequest::DeclareDurable { topic_prefix, max_events, max_age_secs, persistent } => {
+                if topic_prefix.is_empty() || max_events == 0 || max_events > MAX_RETAINED_EVENTS {
+                    return EventBusResponse::Error(format!("A durable topic needs a prefix and 1 to {} events.", MAX_RETAINED_EVENTS));
+                }
+                if let Some(other) = self.durable.keys().find(|p| **p != topic_prefix && (p.starts_with(topic_prefix.as_str()) || topic_prefix.starts_with(p.as_str()))) {
+                    return EventBusResponse::Error(format!("'{}' overlaps the durable topic '{}'.", topic_prefix, other));
+                }
+                self.declare_durable(topic_prefix, Retention { max_events, max_age_secs }, persistent);
+                EventBusResponse::Success(0)
+            },
+            EventBusRequest::SubscribeDurable { topic_prefix, reply_chan, from } => self.subscribe_durable(topic_prefix, reply_chan, from),
+        }
+    }
+
+    fn subscribe_durable(&mut self, topic_prefix: String, reply_chan: u32, from: ReplayFrom) -> EventBusResponse {
+        let Some(prefix) = self.durable_topic_of(&topic_prefix).map(str::to_string) else {
+            return EventBusResponse::Error(format!("'{}' is not within a durable topic.", topic_prefix));
+        };
+        let durable = &self.durable[&prefix];
+        if durable.loading.is_some() {
+            return EventBusResponse::Error(format!("The log of '{}' is still being read; try again.", prefix));
+        }
+        let next = match durable.log.start(&from) {
+            Ok(next) => next,
+            Err(oldest) => {
+                log(&format!("Event Bus: Channel {} asked for '{}*' after an expired cursor.", reply_chan, topic_prefix));
+                return EventBusResponse::CursorExpired { oldest };
+            },
+        };
+        let replay = durable.log.count_matching(next, &topic_prefix);
+        let head = durable.log.head();
+        self.durable_subscriptions.retain(|s| !(s.topic_prefix == topic_prefix && s.reply_chan == reply_chan));
+        log(&format!("Event Bus: Channel {} subscribed to '{}*' durably, replaying {} events.", reply_chan, topic_prefix, replay));
+        self.durable_subscriptions.push(DurableSubscription { topic_prefix, reply_chan, log: prefix, next, live: false });
+        EventBusResponse::Subscribed { head, replay }
+    }
+
+    /// Feeds each durable subscription from its log, up to `REPLAY_BATCH`
+    /// events. One whose channel is full is retried on the next pass from
+    /// the same event.
+    fn deliver_durable(&mut self) {
+        let now = time::now_secs();
+        for durable in self.durable.values_mut().filter(|d| d.loading.is_none()) {
+            durable.log.trim(now);
+        }
+        for sub in self.durable_subscriptions.iter_mut() {
+            let Some(durable) = self.durable.get(&sub.log).filter(|d| d.loading.is_none()) else {
+                continue;
+            };
+            let mut chan = VNodeChannel::new(sub.reply_chan);
+            for _ in 0..REPLAY_BATCH {
+                match durable.log.next(sub.next) {
+                    Next::Gap { missed, resume } => {
+                        let next = resume.seq + 1;
+                        if chan.send(&DurableDelivery::Gap { missed, resume }).is_err() {
+                            break;
+                        }
+                        log(&format!("Event Bus: Channel {} missed {} events of '{}*'.", sub.reply_chan, missed, sub.log));
+                        sub.next = next;
+                    },
+                    Next::Event(entry) => {
+                        if entry.topic.starts_with(sub.topic_prefix.as_str()) {
+                            let delivery = DurableDelivery::Event { cursor: durable.log.cursor_of(entry), topic: entry.topic.clone(), payload: entry.payload.clone() };
+                            if chan.send(&delivery).is_err() {
+                                break;
+                            }
+                        }
+                        sub.next += 1;
+                    },
+                    Next::CaughtUp => {
+                        if !sub.live && chan.send(&DurableDelivery::Live { cursor: durable.log.head() }).is_ok() {
+                            sub.live = true;
+                        }
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    /// Takes over the saved logs as they are read, and writes out the
+    /// persistent logs that changed, at most once per `FLUSH_INTERVAL_NANOS`.
+    fn persist(&mut self) {
+        for done in self.persister.poll() {
+            match done {
+                Done::Loaded { prefix, data } => {
+                    let Some(durable) = self.durable.get_mut(&prefix) else { continue };
+                    let pending = durable.loading.take().unwrap_or_default();
+                    match data {
+                        Some(data) => match durable.log.restore(&data, pending, time::now_secs()) {
+                            Ok(()) => log(&format!("Event Bus: Restored {} events of '{}*'.", durable.log.len(), prefix)),
+                            Err(e) => log(&format!("Event Bus: Starting '{}*' over; its saved log is {}.", prefix, e)),
+                        },
+                        None => {
+                            for (topic, payload, published_at) in pending {
+                                durable.log.append(topic, payload, published_at);
+                            }
+                        },
+                    }
+                },
+                Done::Saved { prefix, result: Err(e) } => {
+                    log(&format!("Event Bus: Failed to save the log of '{}*': {}.", prefix, e));
+                    if let Some(durable) = self.durable.get_mut(&prefix) {
+                        durable.log.dirty = true;
+                    }
+                },
+                Done::Saved { result: Ok(()), .. } => {},
+            }
+        }
+        let now = time::monotonic_nanos();
+        if now.saturating_sub(self.last_flush) < FLUSH_INTERVAL_NANOS {
+            return;
+        }
+        self.last_flush = now;
+        for (prefix, durable) in self.durable.iter_mut() {
+            if !durable.log.dirty || durable.loading.is_some() || self.persister.saving(prefix) {
+                continue;
+            }
+            if let Some(data) = durable.log.encode() {
+                self.persister.save(prefix, data);
+                durable.log.dirty = false;
+            }
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Event Bus: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                if let Ok(request) = postcard::from_bytes::<EventBusRequest>(&req_data) {
+                    let response = self.handle_request(request);
+                    self.client_chan.send(&response).unwrap_or_else(|_| log("Event Bus: Failed to send response to client."));
+                } else {
+                    log("Event Bus: Failed to deserialize EventBusRequest from client.");
+                }
+            }
+
+            self.deliver_durable();
+            self.persist();
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Assuming channel ID 13 for Event Bus requests
+    // Assuming channel ID 7 for the VFS, for the logs of persistent topics
+    let mut event_bus = EventBus::new(13, 7);
+    event_bus.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Event Bus V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
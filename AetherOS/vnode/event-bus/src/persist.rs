@@ -0,0 +1,195 @@
+// vnode/event-bus/src/persist.rs
+
+//! Reads and writes the logs of persistent durable topics in the VFS.
+//!
+//! The bus must not block on the VFS: the VFS publishes `storage.*` events
+//! through the bus, and each waiting for the other would stop both. So the
+//! requests go out with a plain send and `poll` picks up the answers as
+//! they arrive, like the VFS does with the settings service. One job runs
+//! at a time, with one request in flight.
+//!
+//! A log is written in a VFS transaction, so a crash mid-write leaves the
+//! previous one in place.
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::vfs_ipc::{Fd, TxId, VfsRequest, VfsResponse};
+use common::ipc::vnode::VNodeChannel;
+
+/// Where the logs are kept, one file per durable topic.
+pub const LOG_DIR: &str = "/data/eventbus";
+/// Bytes per Read and Write request, well under `MAX_MESSAGE_SIZE`.
+const CHUNK: usize = 3072;
+
+/// `/data/eventbus/<prefix>.log`, without the prefix's trailing dot.
+pub fn log_path(prefix: &str) -> String {
+    format!("{}/{}.log", LOG_DIR, prefix.trim_end_matches('.'))
+}
+
+/// A finished job.
+pub enum Done {
+    /// `data` is None if there is no saved log, or it couldn't be read.
+    Loaded { prefix: String, data: Option<Vec<u8>> },
+    Saved { prefix: String, result: Result<(), String> },
+}
+
+enum LoadStage { Open, Read, Close, CloseAfterError }
+
+enum SaveStage { CreateDirectory, Begin, Open, Write, Close, Commit, Abort(String) }
+
+enum Job {
+    Load { prefix: String, stage: LoadStage, fd: Fd, data: Vec<u8> },
+    Save { prefix: String, stage: SaveStage, tx: TxId, fd: Fd, data: Vec<u8>, written: usize },
+}
+
+impl Job {
+    fn prefix(&self) -> &str {
+        match self {
+            Job::Load { prefix, .. } | Job::Save { prefix, .. } => prefix,
+        }
+    }
+}
+
+pub struct Persister {
+    chan: VNodeChannel,
+    queue: VecDeque<Job>,
+    current: Option<Job>, // Its request is in flight
+}
+
+impl Persister {
+    pub fn new(vfs_chan_id: u32) -> Self {
+        Self { chan: VNodeChannel::new(vfs_chan_id), queue: VecDeque::new(), current: None }
+    }
+
+    pub fn load(&mut self, prefix: &str) {
+        self.queue.push_back(Job::Load { prefix: prefix.to_string(), stage: LoadStage::Open, fd: 0, data: Vec::new() });
+    }
+
+    /// Queues a save of `data`, replacing one of the same log that hasn't started.
+    pub fn save(&mut self, prefix: &str, data: Vec<u8>) {
+        self.queue.retain(|job| !matches!(job, Job::Save { prefix: queued, .. } if queued == prefix));
+        self.queue.push_back(Job::Save { prefix: prefix.to_string(), stage: SaveStage::CreateDirectory, tx: 0, fd: 0, data, written: 0 });
+    }
+
+    /// Whether a save of `prefix` is queued or running.
+    pub fn saving(&self, prefix: &str) -> bool {
+        self.current.iter().chain(self.queue.iter()).any(|job| matches!(job, Job::Save { .. }) && job.prefix() == prefix)
+    }
+
+    /// Handles the answer to the request in flight, if it came, and sends
+    /// the next request. Returns the jobs that finished.
+    pub fn poll(&mut self) -> Vec<Done> {
+        let mut done = Vec::new();
+        if self.current.is_none() {
+            if let Some(job) = self.queue.pop_front() {
+                self.begin(job, &mut done);
+            }
+            return done;
+        }
+        let response = match self.chan.recv_non_blocking() {
+            Ok(Some(data)) => match postcard::from_bytes::<VfsResponse>(&data) {
+                Ok(response) => response,
+                Err(_) => VfsResponse::Error { code: 5, message: "undecodable answer from the VFS".to_string() },
+            },
+            _ => return done,
+        };
+        if let Some(job) = self.current.take() {
+            self.advance(job, response, &mut done);
+        }
+        done
+    }
+
+    fn send(&mut self, job: Job, request: VfsRequest, done: &mut Vec<Done>) {
+        if self.chan.send(&request).is_ok() {
+            self.current = Some(job);
+            return;
+        }
+        match job {
+            // The VFS starts after the bus; a log isn't given up for lost
+            // before it is there to ask.
+            Job::Load { prefix, stage: LoadStage::Open, .. } => self.load(&prefix),
+            Job::Load { prefix, .. } => done.push(Done::Loaded { prefix, data: None }),
+            Job::Save { prefix, .. } => done.push(Done::Saved { prefix, result: Err("could not reach the VFS".to_string()) }),
+        }
+    }
+
+    fn begin(&mut self, job: Job, done: &mut Vec<Done>) {
+        let request = match &job {
+            Job::Load { prefix, .. } => VfsRequest::Open { path: log_path(prefix), flags: 0 /* O_RDONLY */ },
+            Job::Save { .. } => VfsRequest::CreateDirectory { path: LOG_DIR.to_string() },
+        };
+        self.send(job, request, done);
+    }
+
+    fn advance(&mut self, job: Job, response: VfsResponse, done: &mut Vec<Done>) {
+        match job {
+            Job::Load { prefix, stage, fd, mut data } => match (stage, response) {
+                (LoadStage::Open, VfsResponse::Success(fd)) => {
+                    let fd = fd as Fd;
+                    self.send(Job::Load { prefix, stage: LoadStage::Read, fd, data }, VfsRequest::Read { fd, len: CHUNK as u32, offset: 0 }, done);
+                },
+                (LoadStage::Read, VfsResponse::Data(chunk)) => {
+                    let more = chunk.len() == CHUNK;
+                    data.extend_from_slice(&chunk);
+                    if more {
+                        let offset = data.len() as u64;
+                        self.send(Job::Load { prefix, stage: LoadStage::Read, fd, data }, VfsRequest::Read { fd, len: CHUNK as u32, offset }, done);
+                    } else {
+                        self.send(Job::Load { prefix, stage: LoadStage::Close, fd, data }, VfsRequest::Close { fd }, done);
+                    }
+                },
+                (LoadStage::Read, _) => self.send(Job::Load { prefix, stage: LoadStage::CloseAfterError, fd, data: Vec::new() }, VfsRequest::Close { fd }, done),
+                (LoadStage::Close, _) => done.push(Done::Loaded { prefix, data: Some(data) }),
+                // Not found, or the VFS refused or failed.
+                (LoadStage::Open, _) | (LoadStage::CloseAfterError, _) => done.push(Done::Loaded { prefix, data: None }),
+            },
+            Job::Save { prefix, stage, tx, fd, data, written } => match (stage, response) {
+                // It may well exist already.
+                (SaveStage::CreateDirectory, VfsResponse::CreateDirectorySuccess) | (SaveStage::CreateDirectory, VfsResponse::Error { code: 17, .. }) => {
+                    self.send(Job::Save { prefix, stage: SaveStage::Begin, tx, fd, data, written }, VfsRequest::TxBegin, done);
+                },
+                (SaveStage::Begin, VfsResponse::TxBegun { id }) => {
+                    let open = VfsRequest::Open { path: log_path(&prefix), flags: 1 /* O_WRONLY | O_CREAT | O_TRUNC */ };
+                    self.send(Job::Save { prefix, stage: SaveStage::Open, tx: id, fd, data, written }, VfsRequest::InTx { id, request: Box::new(open) }, done);
+                },
+                (SaveStage::Open, VfsResponse::Success(fd)) => self.write_next(prefix, tx, fd as Fd, data, written, done),
+                (SaveStage::Write, VfsResponse::Success(_)) => self.write_next(prefix, tx, fd, data, written, done),
+                (SaveStage::Close, VfsResponse::Success(_)) => {
+                    self.send(Job::Save { prefix, stage: SaveStage::Commit, tx, fd, data, written }, VfsRequest::TxCommit { id: tx }, done);
+                },
+                (SaveStage::Commit, VfsResponse::Success(_)) => done.push(Done::Saved { prefix, result: Ok(()) }),
+                (SaveStage::Abort(error), _) => done.push(Done::Saved { prefix, result: Err(error) }),
+                (stage, other) => {
+                    let error = match other {
+                        VfsResponse::Error { message, .. } => message,
+                        other => format!("unexpected answer from the VFS: {:?}", other),
+                    };
+                    match stage {
+                        // The transaction is open; give it up.
+                        SaveStage::Open | SaveStage::Write | SaveStage::Close => {
+                            self.send(Job::Save { prefix, stage: SaveStage::Abort(error), tx, fd, data: Vec::new(), written }, VfsRequest::TxAbort { id: tx }, done);
+                        },
+                        _ => done.push(Done::Saved { prefix, result: Err(error) }),
+                    }
+                },
+            },
+        }
+    }
+
+    /// Sends the Write of the chunk at `written`, or the Close once all is written.
+    fn write_next(&mut self, prefix: String, tx: TxId, fd: Fd, data: Vec<u8>, written: usize, done: &mut Vec<Done>) {
+        if written < data.len() {
+            let end = (written + CHUNK).min(data.len());
+            let write = VfsRequest::Write { fd, data: data[written..end].to_vec(), offset: written as u64 };
+            self.send(Job::Save { prefix, stage: SaveStage::Write, tx, fd, data, written: end }, VfsRequest::InTx { id: tx, request: Box::new(write) }, done);
+        } else {
+            let close = VfsRequest::Close { fd };
+            self.send(Job::Save { prefix, stage: SaveStage::Close, tx, fd, data, written }, VfsRequest::InTx { id: tx, request: Box::new(close) }, done);
+        }
+    }
+}
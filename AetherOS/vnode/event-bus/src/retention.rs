@@ -0,0 +1,364 @@
+// vnode/event-bus/src/retention.rs
+
+//! Retention logs of durable topics.
+//!
+//! Every event published on a durable topic is appended to its log with the
+//! next sequence number, then trimmed to the topic's retention. Durable
+//! subscribers are fed from the log only, never straight from `Publish`, so
+//! an event published while one is replaying lands behind the replay and is
+//! delivered once, in order.
+//!
+//! A cursor names the last event a subscriber has. It stays good while the
+//! events after it are retained: `trimmed_through` is the newest sequence
+//! number trimmed, and a cursor before it has missed events.
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use common::ipc::event_ipc::{Cursor, ReplayFrom};
+
+/// Most events a durable topic keeps.
+pub const MAX_RETAINED_EVENTS: u32 = 4096;
+/// Most payload bytes a durable topic keeps, whatever its retention says;
+/// the oldest events go first.
+pub const MAX_RETAINED_BYTES: usize = 64 * 1024;
+/// Bumped whenever `StoredLog` changes.
+const LOG_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub max_events: u32,
+    /// 0 keeps events for as long as they fit.
+    pub max_age_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    /// Epoch seconds; 0 if the bus had no clock.
+    pub published_at: u64,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+/// What a subscriber at `next` gets next.
+pub enum Next<'a> {
+    Event(&'a Entry),
+    /// Events from `next` through the log's `trimmed_through` are gone.
+    Gap { missed: u64, resume: Cursor },
+    /// `next` is past the newest event.
+    CaughtUp,
+}
+
+/// A log as it is written to the VFS.
+#[derive(Serialize, Deserialize)]
+struct StoredLog {
+    version: u32,
+    epoch: u64,
+    next_seq: u64,
+    trimmed_through: u64,
+    entries: Vec<Entry>,
+}
+
+pub struct DurableLog {
+    pub prefix: String,
+    pub retention: Retention,
+    pub persistent: bool,
+    epoch: u64,
+    next_seq: u64, // The next event's; the first is 1
+    trimmed_through: u64, // Newest trimmed; 0 if none was
+    entries: VecDeque<Entry>,
+    bytes: usize, // Payload bytes in `entries`
+    pub dirty: bool, // Changed since it was last saved
+}
+
+impl DurableLog {
+    pub fn new(prefix: String, retention: Retention, persistent: bool, epoch: u64) -> Self {
+        Self { prefix, retention, persistent, epoch, next_seq: 1, trimmed_through: 0, entries: VecDeque::new(), bytes: 0, dirty: false }
+    }
+
+    fn cursor(&self, seq: u64) -> Cursor {
+        Cursor { topic: self.prefix.clone(), epoch: self.epoch, seq }
+    }
+
+    /// The newest event's cursor, or the log's start if it has none.
+    pub fn head(&self) -> Cursor {
+        self.cursor(self.next_seq - 1)
+    }
+
+    /// Where the retained events start: subscribing after it replays them all.
+    pub fn oldest(&self) -> Cursor {
+        self.cursor(self.trimmed_through)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Appends an event and trims the log. Returns its sequence number.
+    pub fn append(&mut self, topic: String, payload: Vec<u8>, now: u64) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.bytes += payload.len();
+        self.entries.push_back(Entry { seq, published_at: now, topic, payload });
+        self.trim(now);
+        self.dirty = self.persistent;
+        seq
+    }
+
+    /// Drops the events past the retention, oldest first. Returns how many.
+    pub fn trim(&mut self, now: u64) -> usize {
+        let mut removed = 0;
+        while let Some(front) = self.entries.front() {
+            let too_many = self.entries.len() > self.retention.max_events.min(MAX_RETAINED_EVENTS) as usize;
+            let too_big = self.bytes > MAX_RETAINED_BYTES;
+            // Events from a boot without a clock have no age.
+            let too_old = self.retention.max_age_secs != 0 && now != 0 && front.published_at != 0
+                && now.saturating_sub(front.published_at) > self.retention.max_age_secs;
+            if !(too_many || too_big || too_old) {
+                break;
+            }
+            self.bytes -= front.payload.len();
+            self.trimmed_through = front.seq;
+            self.entries.pop_front();
+            removed += 1;
+        }
+        if removed > 0 {
+            self.dirty = self.persistent;
+        }
+        removed
+    }
+
+    /// The sequence number a subscription starting at `from` is fed from,
+    /// or the cursor to start over from if `from` is a cursor that expired.
+    pub fn start(&self, from: &ReplayFrom) -> Result<u64, Cursor> {
+        match from {
+            ReplayFrom::Beginning => Ok(self.trimmed_through + 1),
+            ReplayFrom::Since(secs) => Ok(self.entries.iter().find(|entry| entry.published_at >= *secs).map_or(self.next_seq, |entry| entry.seq)),
+            ReplayFrom::After(cursor) => {
+                // Before `trimmed_through`, the events right after the cursor
+                // are gone; past `next_seq` it is from some other log.
+                let valid = cursor.topic == self.prefix && cursor.epoch == self.epoch
+                    && cursor.seq >= self.trimmed_through && cursor.seq < self.next_seq;
+                if valid { Ok(cursor.seq + 1) } else { Err(self.oldest()) }
+            },
+            ReplayFrom::Now => Ok(self.next_seq),
+        }
+    }
+
+    /// The events from `next` on that a subscription to `topic_prefix` gets.
+    pub fn count_matching(&self, next: u64, topic_prefix: &str) -> u32 {
+        self.entries.iter().filter(|entry| entry.seq >= next && entry.topic.starts_with(topic_prefix)).count() as u32
+    }
+
+    /// What a subscriber whose next event is `next` gets.
+    pub fn next(&self, next: u64) -> Next<'_> {
+        if next <= self.trimmed_through {
+            return Next::Gap { missed: self.trimmed_through + 1 - next, resume: self.oldest() };
+        }
+        // Retained events are numbered without holes from trimmed_through + 1.
+        match self.entries.get((next - self.trimmed_through - 1) as usize) {
+            Some(entry) => Next::Event(entry),
+            None => Next::CaughtUp,
+        }
+    }
+
+    pub fn cursor_of(&self, entry: &Entry) -> Cursor {
+        self.cursor(entry.seq)
+    }
+
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let stored = StoredLog {
+            version: LOG_FORMAT_VERSION,
+            epoch: self.epoch,
+            next_seq: self.next_seq,
+            trimmed_through: self.trimmed_through,
+            entries: self.entries.iter().cloned().collect(),
+        };
+        postcard::to_allocvec(&stored).ok()
+    }
+
+    /// Takes over a log saved by an earlier life of the bus: its epoch and
+    /// numbering, so saved cursors stay good, and its events, followed by
+    /// `pending`, the ones published while it was being read. Leaves the
+    /// log as it is if `data` isn't a saved log.
+    pub fn restore(&mut self, data: &[u8], pending: Vec<(String, Vec<u8>, u64)>, now: u64) -> Result<(), &'static str> {
+        let result = match postcard::from_bytes::<StoredLog>(data) {
+            Ok(stored) if stored.version == LOG_FORMAT_VERSION => {
+                let numbered = stored.entries.iter().enumerate().all(|(i, entry)| entry.seq == stored.trimmed_through + 1 + i as u64);
+                if numbered && stored.trimmed_through + stored.entries.len() as u64 + 1 == stored.next_seq {
+                    self.epoch = stored.epoch;
+                    self.next_seq = stored.next_seq;
+                    self.trimmed_through = stored.trimmed_through;
+                    self.bytes = stored.entries.iter().map(|entry| entry.payload.len()).sum();
+                    self.entries = stored.entries.into();
+                    Ok(())
+                } else {
+                    Err("its events are misnumbered")
+                }
+            },
+            Ok(_) => Err("unknown format version"),
+            Err(_) => Err("undecodable"),
+        };
+        for (topic, payload, published_at) in pending {
+            self.append(topic, payload, published_at);
+        }
+        self.trim(now);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    const NOW: u64 = 1_700_000_000;
+    const EPOCH: u64 = 77;
+
+    fn log_of(max_events: u32, max_age_secs: u64) -> DurableLog {
+        DurableLog::new("service.".to_string(), Retention { max_events, max_age_secs }, true, EPOCH)
+    }
+
+    fn publish(log: &mut DurableLog, topics: &[&str], now: u64) {
+        for topic in topics {
+            log.append(topic.to_string(), vec![0; 8], now);
+        }
+    }
+
+    /// What a subscriber to `prefix` at `next` is fed until it catches up:
+    /// the sequence numbers of its events, and a gap as `0` followed by how
+    /// many it missed.
+    fn feed(log: &DurableLog, mut next: u64, prefix: &str) -> Vec<u64> {
+        let mut got = Vec::new();
+        loop {
+            match log.next(next) {
+                Next::Event(entry) => {
+                    if entry.topic.starts_with(prefix) {
+                        got.push(entry.seq);
+                    }
+                    next += 1;
+                },
+                Next::Gap { missed, resume } => {
+                    got.extend([0, missed]);
+                    next = resume.seq + 1;
+                },
+                Next::CaughtUp => return got,
+            }
+        }
+    }
+
+    #[test]
+    fn replays_only_the_topics_under_the_subscribed_prefix() {
+        let mut log = log_of(16, 0);
+        publish(&mut log, &["service.started", "service.stopped", "service.started", "service.restarted"], NOW);
+        assert_eq!(log.count_matching(1, "service."), 4);
+        assert_eq!(log.count_matching(1, "service.started"), 2);
+        assert_eq!(log.count_matching(3, "service.started"), 1);
+        assert_eq!(log.count_matching(1, "service.crashed"), 0);
+        assert_eq!(feed(&log, 1, "service.started"), vec![1, 3]);
+        assert_eq!(feed(&log, 1, "service.s"), vec![1, 2, 3]);
+        assert_eq!(feed(&log, 5, "service."), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn overflowing_the_event_count_trims_the_oldest() {
+        let mut log = log_of(4, 0);
+        publish(&mut log, &["service.a"; 10], NOW);
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.oldest().seq, 6);
+        assert_eq!(log.head().seq, 10);
+        // A subscriber that has had the 3rd event missed the 4th to the 6th.
+        assert_eq!(feed(&log, 4, "service."), vec![0, 3, 7, 8, 9, 10]);
+        assert_eq!(feed(&log, 7, "service."), vec![7, 8, 9, 10]);
+        assert_eq!(log.count_matching(1, "service."), 4);
+        // The retention can't be raised past the hard limit.
+        let mut huge = log_of(u32::MAX, 0);
+        for _ in 0..MAX_RETAINED_EVENTS + 5 {
+            huge.append("service.a".to_string(), Vec::new(), NOW);
+        }
+        assert_eq!(huge.len(), MAX_RETAINED_EVENTS as usize);
+    }
+
+    #[test]
+    fn overflowing_the_byte_limit_trims_the_oldest() {
+        let mut log = log_of(100, 0);
+        let chunk = MAX_RETAINED_BYTES / 4;
+        for _ in 0..6 {
+            log.append("service.a".to_string(), vec![0; chunk], NOW);
+        }
+        assert_eq!(log.len(), 4);
+        assert_eq!(log.oldest().seq, 2);
+        log.append("service.a".to_string(), vec![0; MAX_RETAINED_BYTES + 1], NOW);
+        assert_eq!(log.len(), 0);
+        assert_eq!(log.oldest().seq, 7);
+    }
+
+    #[test]
+    fn old_events_age_out_unless_there_was_no_clock() {
+        let mut log = log_of(100, 60);
+        publish(&mut log, &["service.a"], 0);
+        publish(&mut log, &["service.b"], NOW);
+        publish(&mut log, &["service.c"], NOW + 30);
+        assert_eq!(log.trim(NOW + 60), 0);
+        // The event without a time stays at the front and holds the others back.
+        assert_eq!(log.trim(NOW + 61), 0);
+
+        let mut log = log_of(100, 60);
+        publish(&mut log, &["service.b"], NOW);
+        publish(&mut log, &["service.c"], NOW + 30);
+        assert_eq!(log.trim(0), 0);
+        assert_eq!(log.trim(NOW + 61), 1);
+        assert_eq!(log.trim(NOW + 91), 1);
+        assert_eq!(log.len(), 0);
+        assert!(log.dirty);
+        // Trimmed events are counted as a gap for whoever hadn't had them.
+        assert_eq!(feed(&log, 1, "service."), vec![0, 2]);
+    }
+
+    #[test]
+    fn subscriptions_start_where_they_ask() {
+        let mut log = log_of(4, 0);
+        publish(&mut log, &["service.a"], NOW);
+        publish(&mut log, &["service.b"], NOW + 10);
+        publish(&mut log, &["service.c"], NOW + 20);
+        assert_eq!(log.start(&ReplayFrom::Beginning), Ok(1));
+        assert_eq!(log.start(&ReplayFrom::Since(NOW + 5)), Ok(2));
+        assert_eq!(log.start(&ReplayFrom::Since(NOW + 99)), Ok(4));
+        assert_eq!(log.start(&ReplayFrom::Now), Ok(4));
+        assert_eq!(log.start(&ReplayFrom::After(log.head())), Ok(4));
+        assert_eq!(log.start(&ReplayFrom::After(log.cursor(1))), Ok(2));
+
+        publish(&mut log, &["service.d", "service.e", "service.f"], NOW + 30);
+        // Events after the cursor were trimmed, or it is from another life of the log.
+        assert_eq!(log.start(&ReplayFrom::After(log.cursor(1))), Err(log.oldest()));
+        assert_eq!(log.start(&ReplayFrom::After(log.oldest())), Ok(3));
+        let foreign = Cursor { topic: "service.".to_string(), epoch: EPOCH + 1, seq: 4 };
+        assert_eq!(log.start(&ReplayFrom::After(foreign)), Err(log.oldest()));
+        let ahead = Cursor { topic: "service.".to_string(), epoch: EPOCH, seq: 99 };
+        assert_eq!(log.start(&ReplayFrom::After(ahead)), Err(log.oldest()));
+    }
+
+    #[test]
+    fn a_saved_log_keeps_its_numbering_and_takes_what_was_published_meanwhile() {
+        let mut saved = log_of(4, 0);
+        publish(&mut saved, &["service.a"; 6], NOW);
+        let data = saved.encode().unwrap();
+
+        let mut restored = DurableLog::new("service.".to_string(), saved.retention, true, EPOCH + 1);
+        let pending = vec![("service.late".to_string(), vec![1], NOW)];
+        assert_eq!(restored.restore(&data, pending, NOW), Ok(()));
+        assert_eq!(restored.head(), Cursor { topic: "service.".to_string(), epoch: EPOCH, seq: 7 });
+        assert_eq!(restored.len(), 4);
+        assert_eq!(restored.start(&ReplayFrom::After(saved.head())), Ok(7));
+
+        let mut fresh = log_of(4, 0);
+        assert_eq!(fresh.restore(b"junk", vec![("service.x".to_string(), Vec::new(), NOW)], NOW), Err("undecodable"));
+        assert_eq!((fresh.head().seq, fresh.len()), (1, 1));
+    }
+}
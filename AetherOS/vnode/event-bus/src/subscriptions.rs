@@ -0,0 +1,175 @@
+// vnode/event-bus/src/subscriptions.rs
+
+//! Live subscriptions and what was delivered to them.
+//!
+//! A subscription gets every event whose topic starts with its prefix; an
+//! empty prefix gets them all. Delivery is a plain send, so an event for a
+//! subscriber whose channel is full is dropped. Each subscription counts the
+//! events it was sent and the ones it lost. A run of drops is reported when
+//! it starts and once more when it ends, with how many events it took, so a
+//! stuck subscriber doesn't cost a log line per event.
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub struct Subscription {
+    pub topic_prefix: String,
+    pub reply_chan: u32,
+    pub delivered: u64,
+    pub dropped: u64,
+    dropping: u64, // Dropped since the last event that got through
+}
+
+/// What publishing one event did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Published {
+    pub delivered: u32,
+    pub dropped: u32,
+    /// Channels whose event was the first of a run of drops.
+    pub started_dropping: Vec<u32>,
+    /// Channels that took an event again, with how many they lost before it.
+    pub recovered: Vec<(u32, u64)>,
+}
+
+#[derive(Default)]
+pub struct Subscriptions {
+    subs: Vec<Subscription>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a subscription. Returns false if the channel already had this prefix.
+    pub fn subscribe(&mut self, topic_prefix: String, reply_chan: u32) -> bool {
+        if self.subs.iter().any(|s| s.topic_prefix == topic_prefix && s.reply_chan == reply_chan) {
+            return false;
+        }
+        self.subs.push(Subscription { topic_prefix, reply_chan, delivered: 0, dropped: 0, dropping: 0 });
+        true
+    }
+
+    /// Removes a subscription and returns it, with its counts.
+    pub fn unsubscribe(&mut self, topic_prefix: &str, reply_chan: u32) -> Option<Subscription> {
+        let index = self.subs.iter().position(|s| s.topic_prefix == topic_prefix && s.reply_chan == reply_chan)?;
+        Some(self.subs.remove(index))
+    }
+
+    /// Sends `topic` to every matching subscription with `send`, in
+    /// subscription order, once per matching prefix of a channel. `send`
+    /// returns false if the channel didn't take it.
+    pub fn publish(&mut self, topic: &str, mut send: impl FnMut(u32) -> bool) -> Published {
+        let mut published = Published::default();
+        for sub in self.subs.iter_mut().filter(|s| topic.starts_with(s.topic_prefix.as_str())) {
+            if send(sub.reply_chan) {
+                sub.delivered += 1;
+                published.delivered += 1;
+                if sub.dropping > 0 {
+                    published.recovered.push((sub.reply_chan, sub.dropping));
+                    sub.dropping = 0;
+                }
+            } else {
+                sub.dropped += 1;
+                published.dropped += 1;
+                if sub.dropping == 0 {
+                    published.started_dropping.push(sub.reply_chan);
+                }
+                sub.dropping += 1;
+            }
+        }
+        published
+    }
+
+    /// Events lost by all current subscriptions.
+    pub fn dropped(&self) -> u64 {
+        self.subs.iter().map(|s| s.dropped).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    /// The channels `topic` is sent to.
+    fn receivers(subs: &mut Subscriptions, topic: &str) -> Vec<u32> {
+        let mut sent = Vec::new();
+        subs.publish(topic, |chan| {
+            sent.push(chan);
+            true
+        });
+        sent
+    }
+
+    fn subscriptions(list: &[(&str, u32)]) -> Subscriptions {
+        let mut subs = Subscriptions::new();
+        for (prefix, chan) in list {
+            assert!(subs.subscribe(prefix.to_string(), *chan));
+        }
+        subs
+    }
+
+    #[test]
+    fn topics_go_to_the_prefixes_they_start_with() {
+        let mut subs = subscriptions(&[("settings.", 10), ("settings.terminal.", 11), ("mail", 12), ("", 13)]);
+        let mut to = |topic: &str| receivers(&mut subs, topic);
+        assert_eq!(to("settings.terminal.font_scale"), vec![10, 11, 13]);
+        assert_eq!(to("settings.locale.language"), vec![10, 13]);
+        assert_eq!(to("mail.received"), vec![12, 13]);
+        assert_eq!(to("mailbox"), vec![12, 13]);
+        // Prefixes are compared as they are: no case folding and no implied dot.
+        assert_eq!(to("Settings.x"), vec![13]);
+        assert_eq!(to("settings"), vec![13]);
+        assert_eq!(to(""), vec![13]);
+    }
+
+    #[test]
+    fn subscribing_twice_is_one_subscription() {
+        let mut subs = subscriptions(&[("service.", 10)]);
+        assert!(!subs.subscribe("service.".to_string(), 10));
+        assert!(subs.subscribe("service.".to_string(), 11));
+        assert!(subs.subscribe("service.started".to_string(), 10));
+        // A channel with two matching prefixes gets the event twice.
+        assert_eq!(receivers(&mut subs, "service.started"), vec![10, 11, 10]);
+
+        assert!(subs.unsubscribe("service.", 10).is_some());
+        assert!(subs.unsubscribe("service.", 10).is_none());
+        assert!(subs.unsubscribe("service", 11).is_none());
+        assert_eq!(receivers(&mut subs, "service.stopped"), vec![11]);
+    }
+
+    #[test]
+    fn a_full_channel_drops_events_and_counts_them() {
+        let mut subs = subscriptions(&[("a.", 10), ("a.", 11), ("b.", 12)]);
+        let full = |chan: u32| chan != 11;
+        assert_eq!(subs.publish("a.1", full), Published { delivered: 1, dropped: 1, started_dropping: vec![11], recovered: vec![] });
+        // The run goes on without being reported again.
+        assert_eq!(subs.publish("a.2", full), Published { delivered: 1, dropped: 1, ..Published::default() });
+        assert_eq!(subs.publish("a.3", full).started_dropping, Vec::<u32>::new());
+        // Events for other prefixes don't touch the run.
+        assert_eq!(subs.publish("b.1", full), Published { delivered: 1, ..Published::default() });
+
+        assert_eq!(subs.publish("a.4", |_| true), Published { delivered: 2, dropped: 0, started_dropping: vec![], recovered: vec![(11, 3)] });
+        assert_eq!(subs.publish("a.5", |_| true).recovered, vec![]);
+        assert_eq!(subs.dropped(), 3);
+
+        // A new run starts from zero.
+        subs.publish("a.6", full);
+        let gone = subs.unsubscribe("a.", 11).unwrap();
+        assert_eq!((gone.delivered, gone.dropped, gone.dropping), (2, 4, 1));
+        assert_eq!(subs.dropped(), 0);
+        let kept = subs.unsubscribe("a.", 10).unwrap();
+        assert_eq!((kept.delivered, kept.dropped), (6, 0));
+    }
+
+    #[test]
+    fn publishing_with_no_match_sends_nothing() {
+        let mut subs = subscriptions(&[("a.", 10)]);
+        assert_eq!(receivers(&mut subs, "b.1"), Vec::<u32>::new());
+        assert_eq!(subs.publish("b.1", |_| true), Published::default());
+        assert_eq!(Subscriptions::new().publish("a.1", |_| true), Published::default());
+    }
+}
@@ -12,9 +12,23 @@ use alloc::format;
 use alloc::string::{String, ToString};
 
 use common::ipc::vnode::VNodeChannel;
-use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ipc::file_manager_ipc::{FileManagerRequest, FileManagerResponse};
-use common::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata};
+use common::syscall::{syscall3, SYS_LOG, SUCCESS};
+use common::ipc::file_manager_ipc::{FileManagerRequest, FileManagerResponse, DEFAULT_MAX_MATCHES};
+use common::ipc::vfs_ipc::{self, AllocateMode, VfsRequest, VfsResponse, Fd, VfsMetadata, XattrNamespace};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::ipc::server::{self, Call, Framing, Outcome, ServerError, Service};
+use common::ipc::session_ipc::{self, AidBytes};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use common::ids::Ticks;
+use common::startup;
+use common::channels;
+use common::time;
+
+mod trash;
+use trash::TrashIndex;
+mod search;
+use search::{Search, Substring};
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -30,24 +44,297 @@ fn log(msg: &str) {
 }
 
 struct FileManagerService {
-    client_chan: VNodeChannel, // Channel for AetherTerminal or other client V-Nodes
     vfs_chan: VNodeChannel, // Channel to svc://vfs
+    settings_chan: VNodeChannel, // Channel to svc://settings, for the trash limits
+    trash_retention_days: u32,
+    trash_max_bytes: u64,
+    next_sweep_at: u64, // Tick of the next trash expiry and eviction pass
+}
+
+enum CopyError {
+    /// The client cancelled or the deadline passed.
+    Cancelled,
+    Failed(String),
 }
 
 impl FileManagerService {
-    fn new(client_chan_id: u32, vfs_chan_id: u32) -> Self {
-        let client_chan = VNodeChannel::new(client_chan_id);
+    fn new(vfs_chan_id: u32, settings_chan_id: u32) -> Self {
         let vfs_chan = VNodeChannel::new(vfs_chan_id);
+        let settings_chan = VNodeChannel::new(settings_chan_id);
 
         log("File Manager Service: Initializing...");
 
         Self {
-            client_chan,
             vfs_chan,
+            settings_chan,
+            trash_retention_days: trash::DEFAULT_RETENTION_DAYS,
+            trash_max_bytes: trash::DEFAULT_MAX_BYTES,
+            next_sweep_at: 0,
+        }
+    }
+
+    fn get_int_setting(&mut self, key: &str) -> Option<i64> {
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Int(n), .. }) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Re-reads the trash retention and size cap; the defaults stay if svc://settings doesn't answer.
+    fn load_trash_settings(&mut self) {
+        if let Some(days) = self.get_int_setting("files.trash_retention_days") {
+            self.trash_retention_days = days.max(0) as u32;
+        }
+        if let Some(mib) = self.get_int_setting("files.trash_max_mb") {
+            self.trash_max_bytes = mib.max(1) as u64 * 1024 * 1024;
+        }
+    }
+
+    /// Moves `path` into the caller's trash, evicting the oldest entries first
+    /// if it wouldn't fit under the size cap.
+    fn move_to_trash(&mut self, caller: Option<AidBytes>, path: &str) -> FileManagerResponse {
+        let aid = match caller {
+            Some(aid) => aid,
+            None => return FileManagerResponse::Error("Only authenticated identities have a trash; delete permanently instead".to_string()),
+        };
+        let dir = trash::trash_dir(&aid);
+        let path = path.trim_end_matches('/');
+        let name = match trash::file_name(path) {
+            Some(name) => name.to_string(),
+            None => return FileManagerResponse::Error(format!("Cannot move {} to the trash", path)),
+        };
+        if path == dir || path.starts_with(&format!("{}/", dir)) || dir.starts_with(&format!("{}/", path)) {
+            return FileManagerResponse::Error(format!("{} is or contains the trash; delete it permanently instead", path));
+        }
+        let size = match trash::entry_size(&mut self.vfs_chan, path) {
+            Ok(size) => size,
+            Err(e) => return FileManagerResponse::Error(format!("Failed to delete {}: {}", path, e)),
+        };
+        if size > self.trash_max_bytes {
+            return FileManagerResponse::Error(format!("{} ({} bytes) is larger than the trash ({} bytes); delete it permanently instead", path, size, self.trash_max_bytes));
+        }
+        let mut index = match trash::load(&mut self.vfs_chan, &dir) {
+            Ok(index) => index,
+            Err(e) => return FileManagerResponse::Error(format!("Failed to read the trash: {}", e)),
+        };
+
+        // The directory is created outside the transaction; an empty one is harmless if the move fails.
+        if index.entries.is_empty() {
+            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::CreateDirectory { path: dir.clone() });
+        }
+        let evicted: Vec<_> = index.evictions_for(size, self.trash_max_bytes).into_iter().filter_map(|id| index.remove(id)).collect();
+        let deleted_at = time::now_secs();
+        let trash_name = index.free_name(deleted_at, &name);
+        let id = index.add(trash_name.clone(), path.to_string(), deleted_at, size);
+        let moved = trash::update(&mut self.vfs_chan, &dir, &index, |tx| {
+            for entry in &evicted {
+                tx.delete(&format!("{}/{}", dir, entry.name))?;
+            }
+            tx.rename(path, &format!("{}/{}", dir, trash_name))
+        });
+        match moved {
+            Ok(()) => {
+                for entry in &evicted {
+                    log(&alloc::format!("File Manager: Evicted {} ({} bytes) from the trash of {}.", entry.original_path, entry.size, session_ipc::aid_to_hex(&aid)));
+                }
+                log(&alloc::format!("File Manager: Moved {} to the trash as entry {}.", path, id));
+                FileManagerResponse::Success(format!("Moved {} to the trash (entry {})", path, id))
+            },
+            Err(e) => {
+                log(&alloc::format!("File Manager: Failed to move {} to the trash: {}.", path, e));
+                FileManagerResponse::Error(format!("Failed to move {} to the trash: {}", path, e))
+            },
+        }
+    }
+
+    fn restore_from_trash(&mut self, caller: Option<AidBytes>, trash_id: u64) -> FileManagerResponse {
+        let dir = match caller {
+            Some(aid) => trash::trash_dir(&aid),
+            None => return FileManagerResponse::Error("Not authenticated".to_string()),
+        };
+        let mut index = match trash::load(&mut self.vfs_chan, &dir) {
+            Ok(index) => index,
+            Err(e) => return FileManagerResponse::Error(format!("Failed to read the trash: {}", e)),
+        };
+        let entry = match index.remove(trash_id) {
+            Some(entry) => entry,
+            None => return FileManagerResponse::Error(format!("No entry {} in the trash", trash_id)),
+        };
+        // The VFS would replace whatever is there now.
+        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: entry.original_path.clone() }) {
+            Ok(response) if trash::is_not_found(&response) => {},
+            Ok(VfsResponse::Metadata(_)) => {
+                return FileManagerResponse::Error(format!("Cannot restore entry {}: {} already exists; move it away first", trash_id, entry.original_path));
+            },
+            _ => return FileManagerResponse::Error(format!("Cannot restore entry {}: failed to check {}", trash_id, entry.original_path)),
+        }
+        let restored = trash::update(&mut self.vfs_chan, &dir, &index, |tx| {
+            tx.rename(&format!("{}/{}", dir, entry.name), &entry.original_path)
+        });
+        match restored {
+            Ok(()) => {
+                log(&alloc::format!("File Manager: Restored trash entry {} to {}.", trash_id, entry.original_path));
+                FileManagerResponse::Success(format!("Restored {}", entry.original_path))
+            },
+            Err(e) => FileManagerResponse::Error(format!("Failed to restore {}: {}", entry.original_path, e)),
+        }
+    }
+
+    fn empty_trash(&mut self, caller: Option<AidBytes>, older_than_days: Option<u32>) -> FileManagerResponse {
+        let dir = match caller {
+            Some(aid) => trash::trash_dir(&aid),
+            None => return FileManagerResponse::Error("Not authenticated".to_string()),
+        };
+        let index = match trash::load(&mut self.vfs_chan, &dir) {
+            Ok(index) => index,
+            Err(e) => return FileManagerResponse::Error(format!("Failed to read the trash: {}", e)),
+        };
+        let ids = match older_than_days {
+            Some(days) => index.older_than(time::now_secs(), days as u64 * time::SECS_PER_DAY),
+            None => index.entries.iter().map(|entry| entry.id).collect(),
+        };
+        match trash::purge(&mut self.vfs_chan, &dir, index, &ids) {
+            Ok((count, bytes)) => FileManagerResponse::Success(format!("Removed {} entries ({} bytes) from the trash", count, bytes)),
+            Err(e) => FileManagerResponse::Error(format!("Failed to empty the trash: {}", e)),
+        }
+    }
+
+    /// Expires old entries and enforces the size cap in every trash, e.g. after
+    /// the cap was lowered. Trashes this service may not read are skipped.
+    fn sweep_trash(&mut self) {
+        self.load_trash_settings();
+        let homes = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: "/home".to_string() }) {
+            Ok(VfsResponse::DirectoryEntries(entries)) => entries,
+            _ => return,
+        };
+        let now = time::now_secs();
+        for aid in homes.keys().filter_map(|name| session_ipc::aid_from_hex(name)) {
+            let dir = trash::trash_dir(&aid);
+            let index = match trash::load(&mut self.vfs_chan, &dir) {
+                Ok(index) if !index.entries.is_empty() => index,
+                _ => continue,
+            };
+            // Expired entries are the oldest, so they are the first evictions as well.
+            let mut ids = index.expired(now, self.trash_retention_days);
+            let expired = ids.len();
+            let evictions = index.evictions_for(0, self.trash_max_bytes);
+            if evictions.len() > expired {
+                ids = evictions;
+            }
+            match trash::purge(&mut self.vfs_chan, &dir, index, &ids) {
+                Ok((0, _)) => {},
+                Ok((count, bytes)) => log(&alloc::format!("File Manager: Purged {} trash entries ({} expired, {} bytes) of {}.",
+                    count, expired, bytes, session_ipc::aid_to_hex(&aid))),
+                Err(e) => log(&alloc::format!("File Manager: Failed to purge the trash of {}: {}.", session_ipc::aid_to_hex(&aid), e)),
+            }
+        }
+    }
+
+    /// Copies all `size` bytes of `src_fd` into `dest_fd`, which must be empty,
+    /// and closes both. Returns the size copied. The VFS pushes the source while
+    /// we push the destination, so the copy takes a round trip per few chunks
+    /// rather than two per chunk. Only the source's data is read, and chunks of
+    /// it that are all zeros aren't written, so holes stay holes in the copy.
+    /// `stop` is asked between chunks; closing the fds then ends both streams in the VFS.
+    fn copy_streams(vfs_chan: &mut VNodeChannel, src_fd: Fd, dest_fd: Fd, size: u64, mut stop: impl FnMut() -> bool) -> Result<u64, CopyError> {
+        let mut streams = VfsStreams::new(vfs_chan);
+        let copied = (|| {
+            let mut pos = 0;
+            let mut written_to = 0;
+            while let Some((start, end)) = Self::next_data(&mut streams, src_fd, pos).map_err(CopyError::Failed)? {
+                let reader = streams.open_read(src_fd, start, end - start).map_err(CopyError::Failed)?;
+                let mut writer = None;
+                let mut at = start;
+                while let Some(chunk) = streams.read(reader).map_err(CopyError::Failed)? {
+                    if stop() {
+                        return Err(CopyError::Cancelled);
+                    }
+                    let len = chunk.len() as u64;
+                    if chunk.iter().all(|byte| *byte == 0) {
+                        // Skipped over, so it becomes a hole; the next data starts a new stream.
+                        if let Some(writer) = writer.take() {
+                            streams.finish(writer).map_err(CopyError::Failed)?;
+                        }
+                    } else {
+                        let id = match writer {
+                            Some(id) => id,
+                            None => *writer.insert(streams.open_write(dest_fd, at).map_err(CopyError::Failed)?),
+                        };
+                        streams.write(id, chunk).map_err(CopyError::Failed)?;
+                        written_to = at + len;
+                    }
+                    at += len;
+                }
+                if let Some(writer) = writer {
+                    streams.finish(writer).map_err(CopyError::Failed)?;
+                }
+                pos = end;
+            }
+            // Nothing was written for a trailing hole, so give the copy its length.
+            if written_to < size {
+                match streams.request(&VfsRequest::Allocate { fd: dest_fd, offset: written_to, len: size - written_to, mode: AllocateMode::ZeroRange }) {
+                    Ok(VfsResponse::Success(_)) => {},
+                    Ok(VfsResponse::Error { message, .. }) => return Err(CopyError::Failed(message)),
+                    Ok(_) => return Err(CopyError::Failed("Unexpected response from VFS".to_string())),
+                    Err(e) => return Err(CopyError::Failed(e)),
+                }
+            }
+            Ok(size)
+        })();
+        // Closing ends any stream still running, and chunks already on their way are set aside.
+        let _ = streams.request(&VfsRequest::Close { fd: src_fd });
+        let _ = streams.request(&VfsRequest::Close { fd: dest_fd });
+        copied
+    }
+
+    /// The next run of data in `fd` at or after `offset`, as `(start, end)`, or
+    /// `None` if only holes are left.
+    fn next_data(streams: &mut VfsStreams, fd: Fd, offset: u64) -> Result<Option<(u64, u64)>, String> {
+        let start = match streams.request(&VfsRequest::SeekData { fd, offset })? {
+            VfsResponse::Offset(start) => start,
+            VfsResponse::Error { code: 6, .. } => return Ok(None), // ENXIO
+            VfsResponse::Error { message, .. } => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        match streams.request(&VfsRequest::SeekHole { fd, offset: start })? {
+            VfsResponse::Offset(end) => Ok(Some((start, end))),
+            VfsResponse::Error { message, .. } => Err(message),
+            _ => Err("Unexpected response from VFS".to_string()),
+        }
+    }
+
+    /// Gives `destination` the `user.*` attributes of `source`. `system.*` ones
+    /// describe where the original came from, so the copy doesn't get them.
+    /// Returns the number copied.
+    fn copy_user_xattrs(vfs_chan: &mut VNodeChannel, source: &str, destination: &str) -> Result<usize, String> {
+        let names: Vec<String> = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::ListXattrs { path: source.to_string() }) {
+            Ok(VfsResponse::XattrNames(names)) => names.into_iter()
+                .filter(|name| vfs_ipc::xattr_namespace(name) == Some(XattrNamespace::User))
+                .collect(),
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        if names.is_empty() {
+            return Ok(0);
+        }
+        let xattrs = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::StatWithXattrs { path: source.to_string(), names }) {
+            Ok(VfsResponse::MetadataWithXattrs { xattrs, .. }) => xattrs,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let count = xattrs.len();
+        for (name, value) in xattrs {
+            match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::SetXattr { path: destination.to_string(), name, value }) {
+                Ok(VfsResponse::Success(_)) => {},
+                Ok(VfsResponse::Error { message, .. }) => return Err(message),
+                _ => return Err("Unexpected response from VFS".to_string()),
+            }
         }
+        Ok(count)
     }
 
-    fn handle_request(&mut self, request: FileManagerRequest) -> FileManagerResponse {
+    fn handle_request(&mut self, call: &mut Call, request: FileManagerRequest) -> FileManagerResponse {
+        let caller = call.caller();
         match request {
             FileManagerRequest::Browse { path } => {
                 log(&alloc::format!("File Manager: Browse request for path: {}.", path));
@@ -76,6 +363,19 @@ impl FileManagerService {
                     _ => return FileManagerResponse::Error("Unexpected VFS response opening source file".to_string()),
                 };
 
+                // Step 1b: Its length, which the copy gets even if it ends in a hole
+                let size = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: source.clone() }) {
+                    Ok(VfsResponse::Metadata(metadata)) => metadata.size,
+                    Ok(VfsResponse::Error { message, .. }) => {
+                        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
+                        return FileManagerResponse::Error(format!("Failed to stat source file {}: {}", source, message));
+                    },
+                    _ => {
+                        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
+                        return FileManagerResponse::Error("Unexpected VFS response for source file metadata".to_string());
+                    },
+                };
+
                 // Step 2: Open destination file for writing (create if not exists, truncate if exists)
                 let dest_fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: destination.clone(), flags: 1 /* O_WRONLY | O_CREAT | O_TRUNC */ }) {
                     Ok(VfsResponse::Success(fd)) => fd as Fd,
@@ -90,58 +390,25 @@ impl FileManagerService {
                     },
                 };
 
-                let mut offset = 0;
-                let mut bytes_copied = 0;
-                const CHUNK_SIZE: u32 = 4096; // Read/write in 4KB chunks
-
-                loop {
-                    // Read a chunk from source
-                    let read_resp = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd: src_fd, len: CHUNK_SIZE, offset });
-                    let data = match read_resp {
-                        Ok(VfsResponse::Data(d)) => d,
-                        Ok(VfsResponse::Error { message, .. }) => {
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: dest_fd });
-                            return FileManagerResponse::Error(format!("Error reading from source {}: {}", source, message));
-                        },
-                        _ => {
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: dest_fd });
-                            return FileManagerResponse::Error("Unexpected VFS response reading source file".to_string());
-                        },
-                    };
-
-                    if data.is_empty() {
-                        // End of file
-                        break;
-                    }
-
-                    // Write the chunk to destination
-                    let write_resp = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Write { fd: dest_fd, data: data.clone(), offset });
-                    match write_resp {
-                        Ok(VfsResponse::Success(bytes_written)) if bytes_written as usize == data.len() => {
-                            offset += data.len() as u64;
-                            bytes_copied += data.len();
-                        },
-                        Ok(VfsResponse::Error { message, .. }) => {
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: dest_fd });
-                            return FileManagerResponse::Error(format!("Error writing to destination {}: {}", destination, message));
-                        },
-                        _ => {
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
-                            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: dest_fd });
-                            return FileManagerResponse::Error("Unexpected VFS response writing destination file".to_string());
-                        },
-                    };
+                // Step 3: Stream the data across, leaving holes where the source has them; this closes both files
+                let stop = || call.stop();
+                let bytes_copied = match Self::copy_streams(&mut self.vfs_chan, src_fd, dest_fd, size, stop) {
+                    Ok(bytes) => bytes,
+                    Err(CopyError::Cancelled) => {
+                        // Don't leave half a file behind.
+                        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Delete { path: destination.clone() });
+                        log(&alloc::format!("File Manager: Copy of {} to {} cancelled; removed the partial copy.", source, destination));
+                        return FileManagerResponse::Cancelled;
+                    },
+                    Err(CopyError::Failed(e)) => return FileManagerResponse::Error(format!("Failed to copy {} to {}: {}", source, destination, e)),
+                };
+                // Step 4: The user.* attributes go with the data
+                if let Err(e) = Self::copy_user_xattrs(&mut self.vfs_chan, &source, &destination) {
+                    return FileManagerResponse::Error(format!("Copied {} to {}, but not its attributes: {}", source, destination, e));
                 }
 
-                // Close both files
-                let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: src_fd });
-                let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd: dest_fd });
-
                 log(&alloc::format!("File Manager: Successfully copied {} bytes from {} to {}.", bytes_copied, source, destination));
-                FileManagerResponse::Success(format!("Successfully copied {} to {} ({} bytes)", source, destination, bytes_copied))
+                FileManagerResponse::Copied { message: format!("Successfully copied {} to {} ({} bytes)", source, destination, bytes_copied), bytes: bytes_copied }
             },
             FileManagerRequest::Move { source, destination } => {
                 log(&alloc::format!("File Manager: Move request from {} to {}.", source, destination));
@@ -160,7 +427,11 @@ impl FileManagerService {
                     },
                 }
             },
-            FileManagerRequest::Delete { path } => {
+            FileManagerRequest::Delete { path, permanent: false } => {
+                log(&alloc::format!("File Manager: Trash request for path: {}.", path));
+                self.move_to_trash(caller, &path)
+            },
+            FileManagerRequest::Delete { path, permanent: true } => {
                 log(&alloc::format!("File Manager: Delete request for path: {}.", path));
                 match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Delete { path: path.clone() }) {
                     Ok(VfsResponse::DeleteSuccess) => {
@@ -194,40 +465,68 @@ impl FileManagerService {
                     },
                 }
             },
+            FileManagerRequest::ListTrash => match caller {
+                Some(aid) => match trash::load(&mut self.vfs_chan, &trash::trash_dir(&aid)) {
+                    Ok(TrashIndex { entries, .. }) => FileManagerResponse::TrashEntries(entries),
+                    Err(e) => FileManagerResponse::Error(format!("Failed to read the trash: {}", e)),
+                },
+                None => FileManagerResponse::Error("Not authenticated".to_string()),
+            },
+            FileManagerRequest::Restore { trash_id } => self.restore_from_trash(caller, trash_id),
+            FileManagerRequest::EmptyTrash { older_than_days } => self.empty_trash(caller, older_than_days),
+            FileManagerRequest::SearchContent { root, pattern, case_insensitive, max_matches, include_globs } => {
+                log(&alloc::format!("File Manager: Search request for '{}' in {}.", pattern, root));
+                if pattern.is_empty() {
+                    return FileManagerResponse::Error("The search pattern is empty".to_string());
+                }
+                let max_matches = if max_matches == 0 { DEFAULT_MAX_MATCHES } else { max_matches };
+                let matcher = Substring::new(&pattern, case_insensitive);
+                Search::new(call, matcher, include_globs, max_matches as usize)
+                    .run(&mut self.vfs_chan, &root)
+            },
         }
     }
+}
 
-    fn run_loop(&mut self) -> ! {
-        log("File Manager Service: Entering main event loop.");
-        loop {
-            // Process incoming requests from client V-Nodes
-            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
-                if let Ok(request) = postcard::from_bytes::<FileManagerRequest>(&req_data) {
-                    log(&alloc::format!("File Manager Service: Received FileManagerRequest: {:?}.", request));
-                    let response = self.handle_request(request);
-                    self.client_chan.send(&response).unwrap_or_else(|_| log("File Manager Service: Failed to send response to client."));
-                } else {
-                    log("File Manager Service: Failed to deserialize FileManagerRequest.");
-                }
-            }
+impl Service for FileManagerService {
+    type Request = FileManagerRequest;
+    type Response = FileManagerResponse;
+
+    const NAME: &'static str = "File Manager Service";
+    const FRAMING: Framing = Framing::Enveloped;
+
+    fn handle(&mut self, call: &mut Call, request: FileManagerRequest) -> Outcome<FileManagerResponse> {
+        Outcome::Reply(self.handle_request(call, request))
+    }
+
+    fn error(error: ServerError) -> FileManagerResponse {
+        match error {
+            // Nobody is waiting for the answer any more.
+            ServerError::Expired => FileManagerResponse::Cancelled,
+            error => FileManagerResponse::Error(error.describe().to_string()),
+        }
+    }
 
-            // Yield to other V-Nodes to prevent busy-waiting
-            unsafe { syscall3(SYS_TIME, 0, 0, 0); } // This will cause a context switch
+    fn tick(&mut self, now: Ticks) {
+        // Expire old trash entries and keep every trash under its size cap
+        if now.raw() >= self.next_sweep_at {
+            self.sweep_trash();
+            self.next_sweep_at = now.raw() + trash::SWEEP_INTERVAL_TICKS;
         }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel IDs:
-    // 9 for File Manager Service client requests
-    // 7 for VFS Service
-    let mut file_manager_service = FileManagerService::new(9, 7);
-    file_manager_service.run_loop();
+    let mut client_chan = VNodeChannel::new(channels::FILE_MANAGER);
+    let mut file_manager_service = FileManagerService::new(channels::VFS, channels::SETTINGS);
+    let lifecycle = Lifecycle::new(startup::channels().get(LIFECYCLE_CHANNEL).copied());
+    server::serve_loop(&mut client_chan, lifecycle, &mut file_manager_service);
 }
 
 #[panic_handler]
 pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    server::answer_in_flight();
     log(&alloc::format!("File Manager V-Node panicked! Info: {:?}.", info));
     loop {}
 }
\ No newline at end of file
@@ -0,0 +1,342 @@
+// vnode/file-manager/src/search.rs
+
+//! Content search: `SearchContent`.
+//!
+//! The tree below the root is walked depth first, in name order, to at most
+//! `MAX_DEPTH` levels. The VFS has no links, so a directory can only come back
+//! round if a listing names it or its parent; `.` and `..` are skipped and the
+//! depth limit stops anything else. Each file whose name matches the include
+//! globs is streamed from the VFS and scanned line by line. A line cut in two
+//! by a chunk boundary is put back together before it is matched, and a file
+//! with a NUL byte in its first `BINARY_PROBE_LEN` bytes is taken to be binary
+//! and skipped.
+//!
+//! Matches go to the client in batches as they are found, so results show up
+//! early and no reply outgrows an IPC message. The client's cancel and
+//! deadline are checked before every directory, file and chunk.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::glob::glob_match;
+use common::ipc::file_manager_ipc::{ContentMatch, FileManagerResponse, MAX_EXCERPT_LEN};
+use common::ipc::server::Call;
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse, TO_EOF};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::vnode::VNodeChannel;
+
+use crate::log;
+
+/// Deepest level below the root that is searched.
+pub const MAX_DEPTH: usize = 32;
+
+/// A file with a NUL byte this close to its start is binary.
+pub const BINARY_PROBE_LEN: usize = 512;
+
+/// Past this length a line is matched in pieces, so one huge line can't use
+/// up the heap. Pieces overlap so that no match is lost between them.
+pub const MAX_LINE_LEN: usize = 16 * 1024;
+
+/// Bytes of matches gathered before a batch is sent, well under one message.
+const BATCH_BYTES: usize = 2048;
+
+/// Finds a pattern in a line. A regex matcher would implement this too.
+pub trait Matcher {
+    /// Offset of the first match in `line`.
+    fn find(&self, line: &[u8]) -> Option<usize>;
+    /// Most bytes a match can span.
+    fn max_len(&self) -> usize;
+}
+
+/// Plain substring search, optionally ignoring the case of ASCII letters.
+pub struct Substring {
+    needle: Vec<u8>, // Lowercase if case_insensitive
+    case_insensitive: bool,
+}
+
+impl Substring {
+    pub fn new(pattern: &str, case_insensitive: bool) -> Self {
+        let needle = if case_insensitive { pattern.as_bytes().to_ascii_lowercase() } else { pattern.as_bytes().to_vec() };
+        Self { needle, case_insensitive }
+    }
+}
+
+impl Matcher for Substring {
+    fn find(&self, line: &[u8]) -> Option<usize> {
+        let (first, rest) = match self.needle.split_first() {
+            Some(split) => split,
+            None => return Some(0),
+        };
+        let first_upper = first.to_ascii_uppercase();
+        let last_start = line.len().checked_sub(self.needle.len())?;
+        let mut start = 0;
+        while start <= last_start {
+            // Skip straight to the next place the first byte occurs, then compare the rest.
+            let offset = line[start..=last_start].iter()
+                .position(|b| b == first || (self.case_insensitive && *b == first_upper))?;
+            let candidate = start + offset;
+            let tail = &line[candidate + 1..candidate + self.needle.len()];
+            let equal = if self.case_insensitive { tail.eq_ignore_ascii_case(rest) } else { tail == rest };
+            if equal {
+                return Some(candidate);
+            }
+            start = candidate + 1;
+        }
+        None
+    }
+
+    fn max_len(&self) -> usize {
+        self.needle.len()
+    }
+}
+
+/// Whether a file starting with `first_chunk` looks binary.
+pub fn looks_binary(first_chunk: &[u8]) -> bool {
+    first_chunk.iter().take(BINARY_PROBE_LEN).any(|b| *b == 0)
+}
+
+/// Up to `MAX_EXCERPT_LEN` bytes of `line` from a little before `at`.
+fn excerpt(line: &[u8], at: usize) -> String {
+    let start = at.saturating_sub(MAX_EXCERPT_LEN / 4);
+    let end = (start + MAX_EXCERPT_LEN).min(line.len());
+    // Invalid UTF-8 grows when replaced, so cut again afterwards.
+    let mut text = String::from_utf8_lossy(&line[start..end]).into_owned();
+    let mut len = text.len().min(MAX_EXCERPT_LEN);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    text.truncate(len);
+    text
+}
+
+/// Splits the chunks of a file into lines and matches each one, wherever the
+/// chunk boundaries fall.
+pub struct LineScanner {
+    line: Vec<u8>, // The current line so far, or the unmatched tail of a long one
+    line_number: u64, // 1-based
+    matched: bool, // The current line has been reported
+}
+
+impl LineScanner {
+    pub fn new() -> Self {
+        Self { line: Vec::new(), line_number: 1, matched: false }
+    }
+
+    /// Scans the next chunk, calling `found` with the number and excerpt of
+    /// each matching line. Stops and returns false as soon as `found` does.
+    pub fn feed(&mut self, matcher: &impl Matcher, chunk: &[u8], found: &mut impl FnMut(u64, String) -> bool) -> bool {
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+            self.line.extend_from_slice(&rest[..end]);
+            rest = &rest[end + 1..];
+            if !self.end_line(matcher, found) {
+                return false;
+            }
+        }
+        // The rest belongs to a line the next chunk finishes.
+        self.line.extend_from_slice(rest);
+        if self.line.len() > MAX_LINE_LEN {
+            let go_on = self.check(matcher, found);
+            // Keep just enough for a match that starts here and ends in the next chunk.
+            let keep = if self.matched { 0 } else { matcher.max_len().saturating_sub(1).min(self.line.len()) };
+            self.line.drain(..self.line.len() - keep);
+            return go_on;
+        }
+        true
+    }
+
+    /// Matches the last line if the file doesn't end with a newline.
+    pub fn finish(&mut self, matcher: &impl Matcher, found: &mut impl FnMut(u64, String) -> bool) -> bool {
+        self.line.is_empty() || self.end_line(matcher, found)
+    }
+
+    fn end_line(&mut self, matcher: &impl Matcher, found: &mut impl FnMut(u64, String) -> bool) -> bool {
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        let go_on = self.check(matcher, found);
+        self.line.clear();
+        self.line_number += 1;
+        self.matched = false;
+        go_on
+    }
+
+    fn check(&mut self, matcher: &impl Matcher, found: &mut impl FnMut(u64, String) -> bool) -> bool {
+        if self.matched {
+            return true;
+        }
+        match matcher.find(&self.line) {
+            Some(at) => {
+                self.matched = true;
+                found(self.line_number, excerpt(&self.line, at))
+            },
+            None => true,
+        }
+    }
+}
+
+/// Why a search ended early.
+enum Stop {
+    /// The client cancelled or the deadline passed.
+    Cancelled,
+    /// `max_matches` were found.
+    Full,
+}
+
+/// One `SearchContent` request in progress.
+pub struct Search<'a, 'c, M: Matcher> {
+    call: &'a mut Call<'c>, // The SearchContent request, for its cancel and the batches
+    matcher: M,
+    include_globs: Vec<String>,
+    max_matches: usize,
+    found: usize,
+    batch: Vec<ContentMatch>, // Found since the last batch was sent
+    batch_bytes: usize,
+}
+
+impl<'a, 'c, M: Matcher> Search<'a, 'c, M> {
+    pub fn new(call: &'a mut Call<'c>, matcher: M, include_globs: Vec<String>, max_matches: usize) -> Self {
+        Self { call, matcher, include_globs, max_matches, found: 0, batch: Vec::new(), batch_bytes: 0 }
+    }
+
+    /// Searches `root`, a directory or a single file, sending all but the last
+    /// batch of matches on the way. Returns the final answer.
+    pub fn run(&mut self, vfs_chan: &mut VNodeChannel, root: &str) -> FileManagerResponse {
+        let is_dir = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: root.to_string() }) {
+            Ok(VfsResponse::Metadata(metadata)) => metadata.is_dir,
+            Ok(VfsResponse::Error { message, .. }) => return FileManagerResponse::Error(format!("Failed to search {}: {}", root, message)),
+            _ => return FileManagerResponse::Error("Unexpected response from VFS during search".to_string()),
+        };
+        let ended = if is_dir { self.walk(vfs_chan, root) } else { self.search_file(vfs_chan, root) };
+        match ended {
+            Ok(()) => self.finish(false),
+            Err(Stop::Full) => self.finish(true),
+            Err(Stop::Cancelled) => FileManagerResponse::Cancelled,
+        }
+    }
+
+    fn walk(&mut self, vfs_chan: &mut VNodeChannel, root: &str) -> Result<(), Stop> {
+        let mut pending = alloc::vec![(root.trim_end_matches('/').to_string(), 0)]; // Directories still to walk, next last
+        while let Some((dir, depth)) = pending.pop() {
+            if self.stopped() {
+                return Err(Stop::Cancelled);
+            }
+            let entries = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: if dir.is_empty() { "/".to_string() } else { dir.clone() } }) {
+                Ok(VfsResponse::DirectoryEntries(entries)) => entries,
+                _ => {
+                    log(&format!("File Manager: Search skipped {}: it couldn't be listed.", dir));
+                    continue;
+                },
+            };
+            let mut subdirs = Vec::new();
+            for (name, metadata) in entries {
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let path = format!("{}/{}", dir, name);
+                if metadata.is_dir {
+                    if depth < MAX_DEPTH {
+                        subdirs.push(path);
+                    }
+                } else if metadata.size > 0 && self.included(&name) {
+                    self.search_file(vfs_chan, &path)?;
+                }
+            }
+            pending.extend(subdirs.into_iter().rev().map(|path| (path, depth + 1)));
+        }
+        Ok(())
+    }
+
+    fn included(&self, name: &str) -> bool {
+        self.include_globs.is_empty() || self.include_globs.iter().any(|glob| glob_match(glob, name))
+    }
+
+    fn search_file(&mut self, vfs_chan: &mut VNodeChannel, path: &str) -> Result<(), Stop> {
+        if self.stopped() {
+            return Err(Stop::Cancelled);
+        }
+        let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            _ => {
+                log(&format!("File Manager: Search skipped {}: it couldn't be opened.", path));
+                return Ok(());
+            },
+        };
+        let mut streams = VfsStreams::new(vfs_chan);
+        let scanned = self.scan(&mut streams, fd, path);
+        // Closing ends the stream if the scan stopped early.
+        let _ = streams.request(&VfsRequest::Close { fd });
+        scanned
+    }
+
+    fn scan(&mut self, streams: &mut VfsStreams, fd: Fd, path: &str) -> Result<(), Stop> {
+        let reader = match streams.open_read(fd, 0, TO_EOF) {
+            Ok(reader) => reader,
+            Err(e) => {
+                log(&format!("File Manager: Search skipped {}: {}.", path, e));
+                return Ok(());
+            },
+        };
+        let mut scanner = LineScanner::new();
+        let mut first = true;
+        loop {
+            let chunk = match streams.read(reader) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    log(&format!("File Manager: Search stopped reading {}: {}.", path, e));
+                    return Ok(());
+                },
+            };
+            if self.stopped() {
+                return Err(Stop::Cancelled);
+            }
+            if first && looks_binary(&chunk) {
+                return Ok(());
+            }
+            first = false;
+            let room = self.max_matches - self.found;
+            let mut lines = Vec::new();
+            scanner.feed(&self.matcher, &chunk, &mut |line_number, excerpt| {
+                lines.push((line_number, excerpt));
+                lines.len() < room
+            });
+            self.add(path, lines)?;
+        }
+        let room = self.max_matches - self.found;
+        let mut lines = Vec::new();
+        scanner.finish(&self.matcher, &mut |line_number, excerpt| {
+            lines.push((line_number, excerpt));
+            lines.len() < room
+        });
+        self.add(path, lines)
+    }
+
+    /// Records matching lines of `path`, sending a batch whenever one is full.
+    fn add(&mut self, path: &str, lines: Vec<(u64, String)>) -> Result<(), Stop> {
+        for (line_number, excerpt) in lines {
+            self.batch_bytes += path.len() + excerpt.len() + 16;
+            self.batch.push(ContentMatch { path: path.to_string(), line_number, excerpt });
+            self.found += 1;
+            if self.batch_bytes >= BATCH_BYTES {
+                let matches = core::mem::take(&mut self.batch);
+                self.batch_bytes = 0;
+                let batch = FileManagerResponse::ContentMatches { matches, done: false, truncated: false };
+                self.call.send_partial(batch).unwrap_or_else(|_| log("File Manager: Failed to send search results to client."));
+            }
+        }
+        if self.found >= self.max_matches { Err(Stop::Full) } else { Ok(()) }
+    }
+
+    fn finish(&mut self, truncated: bool) -> FileManagerResponse {
+        log(&format!("File Manager: Search found {} matches{}.", self.found, if truncated { ", stopping at the limit" } else { "" }));
+        FileManagerResponse::ContentMatches { matches: core::mem::take(&mut self.batch), done: true, truncated }
+    }
+
+    fn stopped(&mut self) -> bool {
+        self.call.stop()
+    }
+}
@@ -0,0 +1,215 @@
+// vnode/file-manager/src/trash.rs
+
+//! Per-identity trash.
+//!
+//! A `Delete` that isn't permanent moves the entry into `/home/<aid hex>/.trash/`
+//! as `<deleted_at>-<name>`. If that name is taken, e.g. by another
+//! `config.txt` deleted in the same second, it becomes `<deleted_at>-2-<name>`,
+//! `-3-`, and so on. The index file next to the entries, `.index`, records each
+//! one's original path, deletion time and size. Every change moves entries and
+//! rewrites the index in one VFS transaction, so the two never disagree.
+//!
+//! Entries expire after `files.trash_retention_days`. The trash is also kept
+//! under `files.trash_max_mb`, evicting the oldest entries first, so that it
+//! can't take the owner over its storage quota by itself.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use common::ipc::file_manager_ipc::{TrashEntry, TrashId};
+use common::ipc::session_ipc::{self, AidBytes};
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use common::ipc::vfs_tx::VfsTx;
+use common::ipc::vnode::VNodeChannel;
+use common::time::SECS_PER_DAY;
+
+/// Name of the index file inside a trash directory. Entry names start with a
+/// timestamp, so they can't collide with it.
+pub const INDEX_NAME: &str = ".index";
+
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// How often the run loop expires and evicts entries: 10 minutes at 100 ticks/s.
+pub const SWEEP_INTERVAL_TICKS: u64 = 60_000;
+
+/// Bytes asked for per `Read` of the index, well under one IPC message.
+const READ_CHUNK: u32 = 2048;
+
+/// The trash directory of an identity: `/home/<aid hex>/.trash`.
+pub fn trash_dir(aid: &AidBytes) -> String {
+    format!("{}/.trash", session_ipc::home_dir(aid))
+}
+
+/// Last component of `path`, ignoring a trailing slash.
+pub fn file_name(path: &str) -> Option<&str> {
+    path.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+/// The contents of a trash's `.index`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrashIndex {
+    pub next_id: TrashId,
+    pub entries: Vec<TrashEntry>, // Oldest first
+}
+
+impl TrashIndex {
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    pub fn get(&self, id: TrashId) -> Option<&TrashEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn remove(&mut self, id: TrashId) -> Option<TrashEntry> {
+        let position = self.entries.iter().position(|entry| entry.id == id)?;
+        Some(self.entries.remove(position))
+    }
+
+    /// A name for `name`, deleted at `deleted_at`, that no entry uses yet.
+    pub fn free_name(&self, deleted_at: u64, name: &str) -> String {
+        let taken = |candidate: &str| self.entries.iter().any(|entry| entry.name == candidate);
+        let mut candidate = format!("{}-{}", deleted_at, name);
+        let mut n = 2;
+        while taken(&candidate) {
+            candidate = format!("{}-{}-{}", deleted_at, n, name);
+            n += 1;
+        }
+        candidate
+    }
+
+    /// Records a new entry and returns its id. Entries stay ordered by
+    /// deletion time even if the clock went backwards.
+    pub fn add(&mut self, name: String, original_path: String, deleted_at: u64, size: u64) -> TrashId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let position = self.entries.partition_point(|entry| entry.deleted_at <= deleted_at);
+        self.entries.insert(position, TrashEntry { id, name, original_path, deleted_at, size });
+        id
+    }
+
+    /// Entries deleted at least `age_secs` before `now`, oldest first.
+    pub fn older_than(&self, now: u64, age_secs: u64) -> Vec<TrashId> {
+        self.entries.iter()
+            .filter(|entry| now.saturating_sub(entry.deleted_at) >= age_secs)
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    /// Entries past a retention of `retention_days`. None expire with a
+    /// retention of 0, or while the clock is unavailable (`now` is 0).
+    pub fn expired(&self, now: u64, retention_days: u32) -> Vec<TrashId> {
+        if retention_days == 0 || now == 0 {
+            return Vec::new();
+        }
+        self.older_than(now, retention_days as u64 * SECS_PER_DAY)
+    }
+
+    /// The oldest entries that must go for `incoming` more bytes to fit under
+    /// `max_bytes`, in eviction order. `incoming` alone must fit.
+    pub fn evictions_for(&self, incoming: u64, max_bytes: u64) -> Vec<TrashId> {
+        let mut total = self.total_bytes() + incoming;
+        let mut evicted = Vec::new();
+        for entry in &self.entries {
+            if total <= max_bytes {
+                break;
+            }
+            total -= entry.size;
+            evicted.push(entry.id);
+        }
+        evicted
+    }
+}
+
+/// Whether the VFS answer means the path doesn't exist.
+pub fn is_not_found(response: &VfsResponse) -> bool {
+    matches!(response, VfsResponse::Error { code: 2, .. }) // ENOENT
+}
+
+fn describe(response: Result<VfsResponse, ()>) -> String {
+    match response {
+        Ok(VfsResponse::Error { message, .. }) => message,
+        Ok(VfsResponse::Unauthenticated) => "Not authenticated".to_string(),
+        Ok(_) => "Unexpected response from VFS".to_string(),
+        Err(_) => "No response from VFS".to_string(),
+    }
+}
+
+/// Reads the index of the trash in `dir`. A trash that doesn't exist yet is empty.
+pub fn load(vfs_chan: &mut VNodeChannel, dir: &str) -> Result<TrashIndex, String> {
+    let path = format!("{}/{}", dir, INDEX_NAME);
+    let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.clone(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as Fd,
+        Ok(response) if is_not_found(&response) => return Ok(TrashIndex::default()),
+        other => return Err(format!("Failed to open {}: {}", path, describe(other))),
+    };
+    let mut data = Vec::new();
+    let read = loop {
+        match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: READ_CHUNK, offset: data.len() as u64 }) {
+            Ok(VfsResponse::Data(chunk)) => {
+                let done = chunk.len() < READ_CHUNK as usize;
+                data.extend_from_slice(&chunk);
+                if done {
+                    break Ok(());
+                }
+            },
+            other => break Err(format!("Failed to read {}: {}", path, describe(other))),
+        }
+    };
+    let _ = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+    read?;
+    postcard::from_bytes(&data).map_err(|_| format!("{} is corrupt", path))
+}
+
+/// Size of a file, or of everything below a directory.
+pub fn entry_size(vfs_chan: &mut VNodeChannel, path: &str) -> Result<u64, String> {
+    let metadata = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: path.to_string() }) {
+        Ok(VfsResponse::Metadata(metadata)) => metadata,
+        other => return Err(describe(other)),
+    };
+    if !metadata.is_dir {
+        return Ok(metadata.size);
+    }
+    let entries = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: path.to_string() }) {
+        Ok(VfsResponse::DirectoryEntries(entries)) => entries,
+        other => return Err(describe(other)),
+    };
+    let mut total = 0;
+    for (name, metadata) in entries {
+        total += if metadata.is_dir {
+            entry_size(vfs_chan, &format!("{}/{}", path.trim_end_matches('/'), name))?
+        } else {
+            metadata.size
+        };
+    }
+    Ok(total)
+}
+
+/// Runs `changes` and rewrites the index of the trash in `dir` in one
+/// transaction. Nothing is changed if any step fails.
+pub fn update(vfs_chan: &mut VNodeChannel, dir: &str, index: &TrashIndex, changes: impl FnOnce(&mut VfsTx) -> Result<(), String>) -> Result<(), String> {
+    let data = postcard::to_allocvec(index).map_err(|_| "Failed to encode the trash index".to_string())?;
+    let mut tx = VfsTx::begin(vfs_chan)?;
+    changes(&mut tx)?;
+    tx.write_file(&format!("{}/{}", dir, INDEX_NAME), data)?;
+    tx.commit()
+}
+
+/// Permanently deletes the entries `ids` from the trash in `dir`. Returns how
+/// many entries and bytes were freed.
+pub fn purge(vfs_chan: &mut VNodeChannel, dir: &str, mut index: TrashIndex, ids: &[TrashId]) -> Result<(usize, u64), String> {
+    let removed: Vec<TrashEntry> = ids.iter().filter_map(|id| index.remove(*id)).collect();
+    if removed.is_empty() {
+        return Ok((0, 0));
+    }
+    update(vfs_chan, dir, &index, |tx| {
+        removed.iter().try_for_each(|entry| tx.delete(&format!("{}/{}", dir, entry.name)))
+    })?;
+    Ok((removed.len(), removed.iter().map(|entry| entry.size).sum()))
+}
@@ -0,0 +1,139 @@
+// vnode/init-service/src/boot.rs
+
+//! Boot ordering and the boot timeline.
+//!
+//! The boot starts every configured service once, each after the services it
+//! depends on. A service whose dependency failed is not started; it fails
+//! too, naming the dependency. Every step is recorded with its timer tick,
+//! which is what `InitRequest::BootReport` is built from. The shutdown adds
+//! a `Stopped` step for each service it stops; they don't count towards the
+//! boot time.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ids::Ticks;
+use common::ipc::init_ipc::{BootProgress, BootReport, BootState, ServiceBootTime, BOOT_REPORT_SLOWEST};
+
+/// The boot targets `init.target=` on the kernel command line picks from,
+/// with the services each starts besides their dependencies. `None` is every
+/// configured service.
+pub const TARGETS: [(&str, Option<&[&str]>); 2] = [
+    ("default", None),
+    ("minimal", Some(&["event-bus", "logd", "session", "settings"])),
+];
+pub const DEFAULT_TARGET: &str = "default";
+
+/// Keeps the services `target` starts: its roots and everything they
+/// depend on, transitively. `None` when there is no such target. A root
+/// that isn't configured is left out; `boot_order` reports missing
+/// dependencies.
+pub fn select_target(depends_on: &BTreeMap<String, Vec<String>>, target: &str) -> Option<BTreeMap<String, Vec<String>>> {
+    let (_, roots) = TARGETS.iter().find(|(name, _)| *name == target)?;
+    let Some(roots) = roots else { return Some(depends_on.clone()) };
+    let mut keep = BTreeSet::new();
+    let mut todo: Vec<String> = roots.iter().map(|root| root.to_string()).collect();
+    while let Some(service) = todo.pop() {
+        if let Some(deps) = depends_on.get(&service) {
+            if keep.insert(service) {
+                todo.extend(deps.iter().cloned());
+            }
+        }
+    }
+    Some(depends_on.iter().filter(|(service, _)| keep.contains(*service)).map(|(service, deps)| (service.clone(), deps.clone())).collect())
+}
+
+/// Orders the services so that each comes after its dependencies. Among
+/// services that are ready at the same point, names sort alphabetically, so
+/// the order is the same on every boot.
+pub fn boot_order(depends_on: &BTreeMap<String, Vec<String>>) -> Result<Vec<String>, String> {
+    for (service, deps) in depends_on {
+        if let Some(missing) = deps.iter().find(|dep| !depends_on.contains_key(*dep)) {
+            return Err(format!("{} depends on {}, which is not configured", service, missing));
+        }
+    }
+    let mut placed = BTreeSet::new();
+    let mut order = Vec::with_capacity(depends_on.len());
+    while order.len() < depends_on.len() {
+        let next = depends_on.iter()
+            .find(|(service, deps)| !placed.contains(*service) && deps.iter().all(|dep| placed.contains(dep)))
+            .map(|(service, _)| service.clone());
+        match next {
+            Some(service) => {
+                placed.insert(service.clone());
+                order.push(service);
+            },
+            None => {
+                let stuck: Vec<&str> = depends_on.keys().filter(|service| !placed.contains(*service)).map(String::as_str).collect();
+                return Err(format!("dependency cycle among {}", stuck.join(", ")));
+            },
+        }
+    }
+    Ok(order)
+}
+
+/// Every step of the boot, in the order they happened.
+pub struct BootTimeline {
+    steps: Vec<BootProgress>,
+    total: u32,
+}
+
+impl BootTimeline {
+    pub fn new(total: u32) -> Self {
+        Self { steps: Vec::new(), total }
+    }
+
+    /// Records a step and returns it, for publishing.
+    pub fn record(&mut self, service: &str, index: u32, state: BootState, tick: u64) -> BootProgress {
+        let step = BootProgress { service: service.to_string(), index, total: self.total, state, tick };
+        self.steps.push(step.clone());
+        step
+    }
+
+    /// The first failure, for the status line.
+    pub fn first_failure(&self) -> Option<(&str, &str)> {
+        self.steps.iter().find_map(|step| match &step.state {
+            BootState::Failed(reason) => Some((step.service.as_str(), reason.as_str())),
+            _ => None,
+        })
+    }
+
+    pub fn report(&self) -> BootReport {
+        let mut starting: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut times = Vec::new();
+        let mut failed = Vec::new();
+        let mut finished = 0;
+        for step in &self.steps {
+            match &step.state {
+                BootState::Starting => {
+                    starting.insert(&step.service, step.tick);
+                },
+                BootState::Started => {
+                    finished += 1;
+                    if let Some(start) = starting.get(step.service.as_str()) {
+                        times.push(ServiceBootTime { service: step.service.clone(), ticks: Ticks::from_raw(step.tick.saturating_sub(*start)) });
+                    }
+                },
+                BootState::Failed(reason) => {
+                    finished += 1;
+                    failed.push((step.service.clone(), reason.clone()));
+                },
+                // Shutdown steps; `render_text` sums them up.
+                BootState::Stopped { .. } => {},
+            }
+        }
+        // Slowest first; equal times in name order.
+        times.sort_by(|a, b| b.ticks.cmp(&a.ticks).then_with(|| a.service.cmp(&b.service)));
+        times.truncate(BOOT_REPORT_SLOWEST);
+        let boot_steps = self.steps.iter().filter(|step| !matches!(step.state, BootState::Stopped { .. }));
+        let total_ticks = match (boot_steps.clone().next(), boot_steps.last()) {
+            (Some(first), Some(last)) => Ticks::from_raw(last.tick.saturating_sub(first.tick)),
+            _ => Ticks::ZERO,
+        };
+        BootReport { timeline: self.steps.clone(), total: self.total, finished, total_ticks, slowest: times, failed }
+    }
+}
@@ -0,0 +1,49 @@
+// vnode/init-service/src/grants.rs
+
+//! Capabilities and syscall filters of services installed from packages.
+//!
+//! A package declares what its service needs and the user approves some or
+//! all of it at install time; the registry records the approved set in its
+//! installed-packages index. Init starts the service with that set, narrowed
+//! by the service's configuration and, for a group member, by the group's
+//! ceiling. The configuration can take grants away or limit them to a
+//! narrower scope, but never adds any: a configured capability the package
+//! wasn't approved for is dropped.
+//!
+//! Configured entries that aren't capabilities, like `IPC_CONNECT:vfs`, wire
+//! the service to others and are kept as configured.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::capability::{DeclaredCapabilities, Grant};
+
+pub struct Effective {
+    /// The configured non-capability entries, then the grants as specs.
+    pub capabilities: Vec<String>,
+    /// `base` followed by the approved syscalls the configuration allows.
+    pub syscalls: Vec<String>,
+}
+
+/// What a package service runs with. `limits` are the configured
+/// capabilities first, then the group's ceiling if there is one;
+/// `configured_syscalls` limits the approved syscalls if given.
+pub fn derive(approved: &DeclaredCapabilities, limits: &[&[String]], configured_syscalls: Option<&[String]>, base: &[&str]) -> Effective {
+    let mut granted = approved.clone();
+    for limit in limits {
+        let limit = DeclaredCapabilities { grants: limit.iter().filter_map(|spec| Grant::parse(spec)).collect(), syscalls: granted.syscalls.clone() };
+        granted = granted.narrowed_to(&limit);
+    }
+    if let Some(allowed) = configured_syscalls {
+        granted.syscalls.retain(|name| allowed.contains(name));
+    }
+
+    let configured = limits.first().copied().unwrap_or(&[]);
+    let mut capabilities: Vec<String> = configured.iter().filter(|spec| Grant::parse(spec).is_none()).cloned().collect();
+    capabilities.extend(granted.grants.iter().map(Grant::spec));
+    let mut syscalls: Vec<String> = base.iter().map(|name| name.to_string()).collect();
+    syscalls.extend(granted.syscalls.into_iter().filter(|name| !base.contains(&name.as_str())));
+    Effective { capabilities, syscalls }
+}
@@ -0,0 +1,175 @@
+// vnode/init-service/src/group.rs
+
+//! Application groups: several services started, stopped and watched as one.
+//!
+//! A group lists its members in start order and stops them in reverse. Each
+//! member's configured capabilities must be within the group's ceiling, so
+//! the group as a whole never holds more than it was granted. A service is
+//! a member of one group at most, and the boot leaves members alone: they
+//! run when their group is started.
+//!
+//! When a member exits without being asked to, `on_crash` says what to do:
+//! nothing if the member doesn't restart, otherwise what the group's policy
+//! says. A group that has to restart more than `MAX_GROUP_RESTARTS` times
+//! within `GROUP_RESTART_WINDOW_TICKS` is stopped instead, so a member that
+//! can't stay up doesn't restart forever.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ipc::init_ipc::{GroupCrashPolicy, GroupState, MemberRestart};
+
+use crate::VNodeConfig;
+
+/// Restarts a group may need within `GROUP_RESTART_WINDOW_TICKS` before it is stopped.
+pub const MAX_GROUP_RESTARTS: usize = 5;
+pub const GROUP_RESTART_WINDOW_TICKS: u64 = 6000; // 60 s of 10 ms ticks
+
+#[derive(Debug, Clone)]
+pub struct GroupMember {
+    pub service: String,
+    pub restart: MemberRestart,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupConfig {
+    pub members: Vec<GroupMember>, // In start order
+    pub capability_ceiling: Vec<String>, // Every member's capabilities must be among these
+    pub crash_policy: GroupCrashPolicy,
+}
+
+impl GroupConfig {
+    pub fn contains(&self, service: &str) -> bool {
+        self.members.iter().any(|member| member.service == service)
+    }
+}
+
+/// Checks group `name` against the configured services and the other groups.
+/// `member_of` holds the members of the groups already accepted.
+fn check(name: &str, group: &GroupConfig, services: &BTreeMap<String, VNodeConfig>, groups: &BTreeMap<String, GroupConfig>, member_of: &BTreeMap<String, String>) -> Result<(), String> {
+    if name.is_empty() || name.contains('@') {
+        return Err(format!("'{}' is not a valid group name", name));
+    }
+    if group.members.is_empty() {
+        return Err(String::from("it has no members"));
+    }
+    for (i, member) in group.members.iter().enumerate() {
+        let Some(config) = services.get(&member.service) else {
+            return Err(format!("member {} is not configured", member.service));
+        };
+        if group.members[..i].iter().any(|earlier| earlier.service == member.service) {
+            return Err(format!("it lists {} twice", member.service));
+        }
+        if let Some(other) = member_of.get(&member.service) {
+            return Err(format!("{} is already a member of {}", member.service, other));
+        }
+        if let Some(capability) = config.capabilities.iter().find(|capability| !group.capability_ceiling.contains(*capability)) {
+            return Err(format!("{} needs {}, which is outside the group's ceiling", member.service, capability));
+        }
+        // Dependencies are started first: the boot's services are, and so are earlier members.
+        for dep in &config.depends_on {
+            if group.contains(dep) {
+                if !group.members[..i].iter().any(|earlier| earlier.service == *dep) {
+                    return Err(format!("{} depends on {}, which comes after it", member.service, dep));
+                }
+            } else if let Some((other, _)) = groups.iter().find(|(other, config)| other.as_str() != name && config.contains(dep)) {
+                return Err(format!("{} depends on {}, a member of {}", member.service, dep, other));
+            }
+        }
+    }
+    // The boot doesn't start members, so nothing outside the group may depend on one.
+    for (service, config) in services {
+        if group.contains(service) {
+            continue;
+        }
+        if let Some(dep) = config.depends_on.iter().find(|dep| group.contains(dep)) {
+            return Err(format!("{} depends on member {}", service, dep));
+        }
+    }
+    Ok(())
+}
+
+pub struct LoadedGroups {
+    pub groups: BTreeMap<String, GroupConfig>,
+    pub member_of: BTreeMap<String, String>, // Service -> its group
+    pub rejected: Vec<(String, String)>, // Group and reason
+}
+
+/// Keeps the configured groups that pass the checks. Where two groups claim
+/// the same service, the first by name keeps it.
+pub fn load(configured: &BTreeMap<String, GroupConfig>, services: &BTreeMap<String, VNodeConfig>) -> LoadedGroups {
+    let mut loaded = LoadedGroups { groups: BTreeMap::new(), member_of: BTreeMap::new(), rejected: Vec::new() };
+    for (name, group) in configured {
+        match check(name, group, services, configured, &loaded.member_of) {
+            Ok(()) => {
+                for member in &group.members {
+                    loaded.member_of.insert(member.service.clone(), name.clone());
+                }
+                loaded.groups.insert(name.clone(), group.clone());
+            },
+            Err(reason) => loaded.rejected.push((name.clone(), reason)),
+        }
+    }
+    loaded
+}
+
+/// Combines the members' states: all running, some, or none.
+pub fn state(running: usize, members: usize) -> GroupState {
+    match running {
+        0 => GroupState::Stopped,
+        n if n == members => GroupState::Running,
+        _ => GroupState::Degraded,
+    }
+}
+
+/// What to do about a member that exited without being asked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashAction {
+    /// The member doesn't restart; the rest of the group keeps running.
+    LeaveStopped,
+    RestartMember,
+    RestartGroup,
+    StopGroup,
+    /// The policy says to restart, but the group has restarted too often.
+    GiveUp,
+}
+
+/// When a running group last had to restart, for the restart limit.
+#[derive(Debug, Default)]
+pub struct RestartBudget {
+    restarts: VecDeque<u64>, // Ticks, oldest first, within the window
+}
+
+impl RestartBudget {
+    /// Counts a restart at `now`. False if it would be one too many.
+    fn spend(&mut self, now: u64) -> bool {
+        while self.restarts.front().map_or(false, |tick| now.saturating_sub(*tick) >= GROUP_RESTART_WINDOW_TICKS) {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= MAX_GROUP_RESTARTS {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
+
+/// Decides what a crash of `member` at tick `now` means for `group`.
+pub fn on_crash(group: &GroupConfig, member: &str, budget: &mut RestartBudget, now: u64) -> CrashAction {
+    let restart = group.members.iter()
+        .find(|candidate| candidate.service == member)
+        .map_or(MemberRestart::Never, |candidate| candidate.restart);
+    if restart == MemberRestart::Never {
+        return CrashAction::LeaveStopped;
+    }
+    match group.crash_policy {
+        GroupCrashPolicy::StopGroup => CrashAction::StopGroup,
+        _ if !budget.spend(now) => CrashAction::GiveUp,
+        GroupCrashPolicy::RestartMember => CrashAction::RestartMember,
+        GroupCrashPolicy::RestartGroup => CrashAction::RestartGroup,
+    }
+}
@@ -5,15 +5,45 @@
 
 extern crate alloc;
 
+mod boot;
+mod grants;
+mod group;
+mod shutdown;
+
 use core::panic::PanicInfo;
 use alloc::vec::Vec;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::format;
 use alloc::string::{String, ToString};
 
 use common::ipc::vnode::VNodeChannel;
-use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ipc::init_ipc::{InitRequest, InitResponse};
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, SYS_SET_IDENTITY, SYS_BOOT_STATUS, BOOT_STATUS_CLEAR, BOOT_STATUS_FAILED, SYS_SYSTEM_POWER, POWER_REBOOT, POWER_SHUTDOWN};
+use common::abi::{syscall_set_from_names, SyscallSet};
+use common::ipc::init_ipc::{InitRequest, InitResponse, InstanceInfo, ServiceTarget, BootProgress, BootState, BOOT_PROGRESS_TOPIC};
+use common::ipc::init_ipc::{ServiceStateChanged, SERVICE_STARTED_TOPIC, SERVICE_STOPPED_TOPIC, SERVICE_RESTARTED_TOPIC};
+use common::ipc::init_ipc::{GroupInfo, GroupMemberInfo, GroupState, GroupStateChanged, GROUP_STARTED_TOPIC, GROUP_STOPPED_TOPIC, GROUP_RESTARTED_TOPIC, GROUP_MEMBER_CRASHED_TOPIC};
+use common::ipc::init_ipc::{TaskFaultStorm, TASK_FAULTSTORM_TOPIC};
+use common::ipc::init_ipc::{CrashReport, MAX_CRASH_REPORTS, SERVICE_CRASHED_TOPIC};
+use common::ipc::session_ipc::{self, AidBytes, SYSTEM_AID};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use common::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
+use common::ipc::envelope;
+use common::ipc::lifecycle_ipc::{LifecycleRequest, LifecycleResponse, LIFECYCLE_CHANNEL};
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse, STREAM_CHUNK_SIZE};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::registry_ipc::{InstalledIndex, INSTALLED_INDEX_PATH};
+use common::capability::DeclaredCapabilities;
+use common::channels::{self, FIRST_DYNAMIC_CHANNEL, MAX_CHANNELS};
+use common::cmdline;
+use common::coredump::{self, CoreHeader, CORE_FLAG_TRUNCATED, CORE_FLAG_WITHHELD, CORE_HEADER_LEN, CRASH_DIR};
+use common::i18n;
+use common::{tr, tr_plural};
+use common::startup::StartupInfo;
+use common::tasks;
+use common::time;
+
+use boot::BootTimeline;
+use group::{CrashAction, GroupConfig, RestartBudget};
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -28,37 +58,121 @@ fn log(msg: &str) {
     }
 }
 
+/// Draws the boot status line on the kernel console. Does nothing once the
+/// compositor has the display.
+fn show_boot_status(text: &str, flags: u64) {
+    unsafe { syscall3(SYS_BOOT_STATUS, text.as_ptr() as u64, text.len() as u64, flags); }
+}
+
+/// A ten-cell progress bar, e.g. "[####------]".
+fn progress_bar(done: u32, total: u32) -> String {
+    let filled = if total == 0 { 10 } else { (done * 10 / total) as usize };
+    alloc::format!("[{}{}]", "#".repeat(filled), "-".repeat(10 - filled))
+}
+
 // Placeholder for V-Node Configuration parsed from /etc/services
 #[derive(Debug, Clone)]
 struct VNodeConfig {
     entrypoint: String,
     capabilities: Vec<String>, // Simplified for now
+    identity: Option<AidBytes>, // Bound to every instance at spawn; None = unauthenticated
+    depends_on: Vec<String>, // Services the boot starts before this one
+    syscalls: Option<Vec<String>>, // Syscall allowlist installed at spawn; None = unfiltered
+    stop_timeout_ms: Option<u32>, // How long it gets to answer a shutdown; None = it isn't asked
+    package: Option<String>, // Installed package the service comes from; its approved grants bound the capabilities above
+    no_core_dump: bool, // Its memory never goes into a core dump; for services holding keys
     // Add more config fields as needed
 }
 
-// Placeholder for a running V-Node's state
+/// What the V-Node client library itself calls: logging, timers, startup
+/// info, the ABI check and IPC, including caller identities. A filtered
+/// service starts from this and adds what it needs.
+/// The boot target from `init.target=` on the kernel command line, if any.
+/// A command line that can't be read boots the default target.
+fn boot_target() -> Option<String> {
+    match cmdline::read() {
+        Ok(args) => args.value("init.target").map(|target| target.to_string()),
+        Err(e) => {
+            log(&alloc::format!("Init Service: Cannot read the boot arguments ({:?}); booting '{}'.", e, boot::DEFAULT_TARGET));
+            None
+        }
+    }
+}
+
+const BASE_SYSCALLS: &[&str] = &[
+    "SYS_LOG", "SYS_TIME", "SYS_CLOCK_GETTIME", "SYS_ABI_VERSION", "SYS_GET_STARTUP_INFO",
+    "SYS_IPC_SEND", "SYS_IPC_RECV", "SYS_IPC_RECV_NONBLOCKING", "SYS_BLOCK_ON_CHAN",
+    "SYS_IPC_CALL", "SYS_IPC_REPLY", "SYS_IPC_REPLY_TOKEN", "SYS_IPC_LAST_SENDER", "SYS_IPC_CREDS", "SYS_GET_IDENTITY",
+];
+
+/// Setting with the fault storm threshold init gives the kernel, and its
+/// value until the settings service is up.
+const FAULT_STORM_KEY: &str = "tasks.fault_storm_per_sec";
+const DEFAULT_FAULT_STORM_PER_SEC: u32 = 1000;
+
+/// Setting with the cap on a core dump's size init gives the kernel, in
+/// KiB, and its value until the settings service is up.
+const CORE_DUMP_KEY: &str = "tasks.core_dump_max_kb";
+const DEFAULT_CORE_DUMP_MAX_KB: u32 = 1024;
+
+/// Services only started when asked for, never at boot: aethersh-server
+/// starts a shell-session for each connection.
+const ON_DEMAND_SERVICES: &[&str] = &["shell-session"];
+
+/// Largest installed-packages index init reads.
+const MAX_INSTALLED_INDEX_SIZE: u32 = 1024 * 1024;
+
+// Placeholder for a running V-Node instance's state
 #[derive(Debug, Clone)]
 struct RunningVNode {
-    pid: u64, // Conceptual Process ID/handle from kernel
-    status_channel: u32, // IPC channel for monitoring status or sending signals
+    instance_id: u64, // Task ID returned by the kernel's V-Node loader
+    service_name: String,
+    label: Option<String>, // Optional caller-supplied label, e.g. "user:alice"
+    channel: u32, // IPC channel allocated to this instance at spawn
+    lifecycle_channel: u32, // Where init asks it to shut down
     config: VNodeConfig,
+    group: Option<String>, // Set for instances started as a group member
+}
+
+impl RunningVNode {
+    fn info(&self) -> InstanceInfo {
+        InstanceInfo {
+            instance_id: self.instance_id,
+            service_name: self.service_name.clone(),
+            label: self.label.clone(),
+            channel: self.channel,
+        }
+    }
 }
 
 struct InitService {
     client_chan: VNodeChannel,
     aetherfs_chan: VNodeChannel,
+    event_bus_chan: VNodeChannel, // For boot.progress and service.*
+    settings_chan: VNodeChannel, // For the locale, once settings is up
+    bus_events_chan: VNodeChannel, // Locale, fault storm threshold and core dump cap changes from the event bus
     // Conceptual channel to kernel-vnode-manager
     // kernel_vnode_manager_chan: VNodeChannel,
     
     service_configs: BTreeMap<String, VNodeConfig>,
-    running_vnodes: BTreeMap<String, RunningVNode>,
-    next_pid: u64, // Counter for dummy PIDs
+    groups: BTreeMap<String, GroupConfig>,
+    member_of: BTreeMap<String, String>, // Service -> the group it belongs to
+    group_budgets: BTreeMap<String, RestartBudget>, // Per started group
+    running_vnodes: BTreeMap<u64, RunningVNode>, // Keyed by instance ID
+    boot_order: Vec<String>, // What the boot started, in order; the shutdown stops it in reverse
+    pending_power: Option<(bool, bool)>, // A SystemShutdown to carry out once it is answered: reboot, force
+    next_instance_id: u64, // Counter for dummy instance IDs
+    boot: BootTimeline,
+    fault_storm_threshold: u32, // Faults per second; passed to the kernel with every SYS_FAULT_STORMS
+    core_dump_max_kb: u32, // Passed to the kernel with every SYS_CORE_DUMP_TAKE
+    crashes: VecDeque<CrashReport>, // Newest first, at most MAX_CRASH_REPORTS
 }
 
 impl InitService {
-    fn new(client_chan_id: u32, aetherfs_chan_id: u32) -> Self {
+    fn new(client_chan_id: u32, aetherfs_chan_id: u32, event_bus_chan_id: u32, settings_chan_id: u32, bus_events_chan_id: u32) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
         let aetherfs_chan = VNodeChannel::new(aetherfs_chan_id);
+        let event_bus_chan = VNodeChannel::new(event_bus_chan_id);
 
         log("Init Service: Initializing...");
 
@@ -69,6 +183,12 @@ impl InitService {
             VNodeConfig {
                 entrypoint: "bin/aethernet-service.vnode".to_string(),
                 capabilities: vec!["NetworkAccess".to_string()],
+                identity: None,
+                depends_on: Vec::new(),
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                no_core_dump: false,
             },
         );
         service_configs.insert(
@@ -76,6 +196,12 @@ impl InitService {
             VNodeConfig {
                 entrypoint: "bin/socket-api.vnode".to_string(),
                 capabilities: vec!["IPC_CONNECT:aethernet".to_string()],
+                identity: None,
+                depends_on: vec!["aethernet-service".to_string(), "settings".to_string(), "event-bus".to_string()],
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                no_core_dump: false,
             },
         );
         service_configs.insert(
@@ -83,88 +209,923 @@ impl InitService {
             VNodeConfig {
                 entrypoint: "bin/dns-resolver.vnode".to_string(),
                 capabilities: vec!["IPC_CONNECT:socket-api".to_string()],
+                identity: None,
+                depends_on: vec!["socket-api".to_string(), "settings".to_string(), "event-bus".to_string()],
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "event-bus".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/event-bus.vnode".to_string(),
+                // No dependency on the VFS, which publishes through the bus:
+                // the bus reads its saved logs once the VFS is up.
+                capabilities: vec!["IPC_ACCEPT".to_string(), "IPC_CONNECT:vfs".to_string()],
+                identity: None,
+                depends_on: Vec::new(),
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "settings".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/settings.vnode".to_string(),
+                capabilities: vec!["IPC_CONNECT:vfs".to_string(), "IPC_CONNECT:event-bus".to_string()],
+                identity: None,
+                depends_on: vec!["event-bus".to_string()],
+                syscalls: None,
+                stop_timeout_ms: Some(2000),
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "session".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/session.vnode".to_string(),
+                capabilities: vec!["IPC_ACCEPT".to_string(), "IDENTITY_ADMIN".to_string()],
+                identity: None,
+                depends_on: Vec::new(),
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                // Holds its signing keypair in memory.
+                no_core_dump: true,
+            },
+        );
+        service_configs.insert(
+            "mail-service".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/mail-service.vnode".to_string(),
+                capabilities: vec!["IPC_CONNECT:vfs".to_string(), "IPC_CONNECT:socket-api".to_string(), "IPC_CONNECT:dns-resolver".to_string()],
+                // Stores mail under every user's home directory on their behalf.
+                identity: Some(SYSTEM_AID),
+                depends_on: vec!["socket-api".to_string(), "session".to_string(), "settings".to_string(), "event-bus".to_string()],
+                // Parses messages from the network; it needs nothing beyond IPC.
+                syscalls: Some(BASE_SYSCALLS.iter().map(|name| name.to_string()).collect()),
+                stop_timeout_ms: Some(5000),
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "audio-mixer".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/audio-mixer.vnode".to_string(),
+                capabilities: vec!["IPC_ACCEPT".to_string(), "AudioOutput".to_string(), "DmaAccess".to_string(), "IPC_CONNECT:event-bus".to_string(), "IPC_CONNECT:settings".to_string()],
+                identity: None,
+                depends_on: vec!["event-bus".to_string(), "settings".to_string()],
+                // Mixes and feeds the sound card; IPC plus the output ring.
+                syscalls: Some(BASE_SYSCALLS.iter().chain(["SYS_AUDIO_OPEN", "SYS_AUDIO_QUEUE", "SYS_GET_DMA_BUF_PTR"].iter()).map(|name| name.to_string()).collect()),
+                stop_timeout_ms: Some(500), // Drops its streams first
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "notifications".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/notifications.vnode".to_string(),
+                capabilities: vec!["IPC_ACCEPT".to_string(), "IPC_CONNECT:event-bus".to_string(), "IPC_CONNECT:settings".to_string(), "IPC_CONNECT:audio-mixer".to_string(), "IPC_CONNECT:vfs".to_string()],
+                identity: None,
+                depends_on: vec!["event-bus".to_string(), "settings".to_string(), "audio-mixer".to_string()],
+                syscalls: None,
+                stop_timeout_ms: None,
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "logd".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/logd.vnode".to_string(),
+                capabilities: vec!["IPC_CONNECT:vfs".to_string(), "IPC_CONNECT:settings".to_string(), "LogRead".to_string()],
+                // Owns /data/log, whoever the messages come from.
+                identity: Some(SYSTEM_AID),
+                depends_on: vec!["settings".to_string()],
+                // Receives every SYS_LOG message and writes files through the VFS;
+                // takes loglevel= from the boot arguments.
+                syscalls: Some(BASE_SYSCALLS.iter().chain(["SYS_LOG_FORWARD", "SYS_BOOT_ARGS"].iter()).map(|name| name.to_string()).collect()),
+                stop_timeout_ms: Some(2000), // Writes out the last second of lines
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "aethersh-server".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/aethersh-server.vnode".to_string(),
+                capabilities: vec!["IPC_CONNECT:socket-api".to_string(), "IPC_CONNECT:init-service".to_string(), "IPC_CONNECT:shell-session".to_string(), "IPC_CONNECT:settings".to_string(), "IPC_CONNECT:vfs".to_string()],
+                // Reads /etc/aethersh/authorized, which only the system may change.
+                identity: Some(SYSTEM_AID),
+                depends_on: vec!["socket-api".to_string(), "session".to_string(), "settings".to_string()],
+                // Accepts connections from the network and relays them to shells over IPC.
+                syscalls: Some(BASE_SYSCALLS.iter().map(|name| name.to_string()).collect()),
+                stop_timeout_ms: Some(2000), // Tells each client the session is closing
+                package: None,
+                no_core_dump: false,
+            },
+        );
+        service_configs.insert(
+            "shell-session".to_string(),
+            VNodeConfig {
+                entrypoint: "bin/shell.vnode".to_string(),
+                capabilities: vec![
+                    "IPC_ACCEPT".to_string(), "IPC_CONNECT:vfs".to_string(), "IPC_CONNECT:session".to_string(),
+                    "IPC_CONNECT:settings".to_string(), "IPC_CONNECT:init-service".to_string(), "IPC_CONNECT:dns-resolver".to_string(),
+                    "IPC_CONNECT:registry".to_string(), "IPC_CONNECT:socket-api".to_string(), "IPC_CONNECT:file-manager".to_string(),
+                    "LogRead".to_string(),
+                ],
+                // Runs as whoever logs in through aethersh-server.
+                identity: None,
+                depends_on: vec!["session".to_string(), "settings".to_string(), "socket-api".to_string(), "dns-resolver".to_string(), "event-bus".to_string()],
+                syscalls: None,
+                stop_timeout_ms: Some(500),
+                package: None,
+                no_core_dump: false,
             },
         );
         log(&alloc::format!("Init Service: Loaded {} service configurations.", service_configs.len()));
 
+        // Application groups, also from /etc/services. None are configured yet; the
+        // first will be a webview and its network helper, e.g.:
+        //   "browser" => GroupConfig { members: [webview (OnCrash), web-net (OnCrash)],
+        //                capability_ceiling: [...], crash_policy: RestartGroup }
+        let configured_groups: BTreeMap<String, GroupConfig> = BTreeMap::new();
+        let loaded = group::load(&configured_groups, &service_configs);
+        for (name, reason) in &loaded.rejected {
+            log(&alloc::format!("Init Service: Ignoring group '{}': {}.", name, reason));
+        }
+        log(&alloc::format!("Init Service: Loaded {} application groups.", loaded.groups.len()));
+
         Self {
             client_chan,
             aetherfs_chan,
+            event_bus_chan,
+            settings_chan: VNodeChannel::new(settings_chan_id),
+            bus_events_chan: VNodeChannel::new(bus_events_chan_id),
             service_configs,
+            groups: loaded.groups,
+            member_of: loaded.member_of,
+            group_budgets: BTreeMap::new(),
             running_vnodes: BTreeMap::new(),
-            next_pid: 1000,
+            boot_order: Vec::new(),
+            pending_power: None,
+            next_instance_id: 1000,
+            boot: BootTimeline::new(0),
+            fault_storm_threshold: DEFAULT_FAULT_STORM_PER_SEC,
+            core_dump_max_kb: DEFAULT_CORE_DUMP_MAX_KB,
+            crashes: VecDeque::new(),
         }
     }
 
-    fn handle_request(&mut self, request: InitRequest) -> InitResponse {
-        match request {
-            InitRequest::ServiceStart { service_name } => {
-                if self.running_vnodes.contains_key(&service_name) {
-                    log(&alloc::format!("Init Service: Service '{}' is already running.", service_name));
-                    return InitResponse::Error(alloc::format!("Service {} is already running.", service_name));
+    /// Returns the IDs of the running instances selected by `target`.
+    fn resolve_target(&self, target: &ServiceTarget) -> Vec<u64> {
+        match target {
+            ServiceTarget::Instance(id) => {
+                if self.running_vnodes.contains_key(id) { vec![*id] } else { Vec::new() }
+            },
+            ServiceTarget::AllInstances(name) => self.running_vnodes.values()
+                .filter(|vnode| &vnode.service_name == name)
+                .map(|vnode| vnode.instance_id)
+                .collect(),
+        }
+    }
+
+    fn describe_target(target: &ServiceTarget) -> String {
+        match target {
+            ServiceTarget::Instance(id) => tr!("init.target.instance", "instance {0}", id),
+            ServiceTarget::AllInstances(name) => tr!("init.target.service", "service '{0}'", name),
+        }
+    }
+
+    /// What a new instance of `service_name` is told at spawn: the channels of
+    /// init and of the services it depends on, taken from their first
+    /// running instance, and its lifecycle channel. The loader adds the
+    /// instance's own channel.
+    fn startup_info(&self, service_name: &str, config: &VNodeConfig, label: Option<&str>, lifecycle_channel: u32) -> StartupInfo {
+        let mut assigned_channels = BTreeMap::new();
+        assigned_channels.insert("init-service".to_string(), self.client_chan.id);
+        assigned_channels.insert(LIFECYCLE_CHANNEL.to_string(), lifecycle_channel);
+        for dep in &config.depends_on {
+            if let Some(vnode) = self.running_vnodes.values().find(|vnode| vnode.service_name == *dep) {
+                assigned_channels.insert(dep.clone(), vnode.channel);
+            }
+        }
+        StartupInfo {
+            instance_id: label.unwrap_or(service_name).to_string(),
+            assigned_channels,
+            config_blob: Vec::new(),
+        }
+    }
+
+    /// What the user approved for `package` when it was installed, from the
+    /// registry's installed-packages index.
+    fn approved_grants(&mut self, package: &str) -> Result<DeclaredCapabilities, String> {
+        let fd = match self.aetherfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: INSTALLED_INDEX_PATH.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as u32,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("unexpected response from the VFS".to_string()),
+        };
+        let read = self.aetherfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_INSTALLED_INDEX_SIZE, offset: 0 });
+        let _ = self.aetherfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        let index = match read {
+            Ok(VfsResponse::Data(data)) => InstalledIndex::decode(&data)?,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("unexpected response from the VFS".to_string()),
+        };
+        index.packages.get(package).map(|installed| installed.approved.clone()).ok_or_else(|| format!("'{}' is not installed", package))
+    }
+
+    /// The lowest `count` channels of the dynamic range no running instance
+    /// holds, so a stopped instance's channels are handed out again. `None`
+    /// if fewer than `count` are free.
+    fn free_channels(&self, count: usize) -> Option<Vec<u32>> {
+        let taken: Vec<u32> = self.running_vnodes.values().flat_map(|vnode| [vnode.channel, vnode.lifecycle_channel]).collect();
+        let free: Vec<u32> = (FIRST_DYNAMIC_CHANNEL..MAX_CHANNELS).filter(|id| !taken.contains(id)).take(count).collect();
+        (free.len() == count).then_some(free)
+    }
+
+    /// Starts a new instance of `service_name`, as a member of `group` if
+    /// given. Returns the new instance's state.
+    fn start_instance(&mut self, service_name: &str, label: Option<String>, group: Option<String>) -> Result<RunningVNode, String> {
+        let mut config = match self.service_configs.get(service_name) {
+            Some(config) => config.clone(),
+            None => {
+                log(&alloc::format!("Init Service: Service '{}' not found in configuration.", service_name));
+                return Err(tr!("init.service.not_configured", "Service '{0}' not found in configuration.", service_name));
+            }
+        };
+
+        // A package service gets what was approved at install time, within its configuration.
+        if let Some(package) = config.package.clone() {
+            let approved = match self.approved_grants(&package) {
+                Ok(approved) => approved,
+                Err(e) => {
+                    log(&alloc::format!("Init Service: No approved capabilities for '{}' from package '{}': {}.", service_name, package, e));
+                    return Err(tr!("init.service.package_unapproved", "Service '{0}' has no approved capabilities from package '{1}': {2}", service_name, package, e));
+                },
+            };
+            let ceiling = group.as_ref().and_then(|name| self.groups.get(name)).map(|group| group.capability_ceiling.clone());
+            let mut limits: Vec<&[String]> = vec![config.capabilities.as_slice()];
+            limits.extend(ceiling.as_deref());
+            let effective = grants::derive(&approved, &limits, config.syscalls.as_deref(), BASE_SYSCALLS);
+            log(&alloc::format!("Init Service: '{}' runs with [{}] from package '{}'.", service_name, effective.capabilities.join(", "), package));
+            config.capabilities = effective.capabilities;
+            config.syscalls = Some(effective.syscalls);
+        }
+
+        // A misspelt syscall would leave the service unable to make it, so refuse to start instead.
+        let syscall_filter: Option<SyscallSet> = match &config.syscalls {
+            Some(names) => match syscall_set_from_names(names.iter().map(|name| name.as_str())) {
+                Ok(allowed) => Some(allowed),
+                Err(name) => {
+                    log(&alloc::format!("Init Service: Service '{}' allows unknown syscall '{}'.", service_name, name));
+                    return Err(tr!("init.service.unknown_syscall", "Service '{0}' allows unknown syscall '{1}'.", service_name, name));
+                },
+            },
+            None => None,
+        };
+
+        // Conceptual: Send IPC to kernel-vnode-manager, which allocates the lifecycle
+        // channel, calls vnode_loader::load_vnode with the capabilities, the syscall filter,
+        // the no-dump flag and the startup info and returns the new task ID and the channel it allocated for the instance.
+        // For now, simulate all three.
+        let Some(free) = self.free_channels(2) else {
+            log(&alloc::format!("Init Service: No free channels for an instance of '{}'.", service_name));
+            return Err(tr!("init.service.no_channels", "No free channels to start '{0}'.", service_name));
+        };
+        let (lifecycle_channel, channel) = (free[0], free[1]);
+        let startup = self.startup_info(service_name, &config, label.as_deref(), lifecycle_channel);
+        let instance_id = self.next_instance_id;
+        self.next_instance_id += 1;
+        log(&alloc::format!("Init Service: (Conceptual) Starting instance {} of '{}' on channel {} with {} assigned channels.", instance_id, service_name, channel, startup.assigned_channels.len()));
+        if let Some(allowed) = syscall_filter {
+            log(&alloc::format!("Init Service: Instance {} of '{}' runs with a filter of {} syscalls.", instance_id, service_name, allowed.count_ones()));
+        }
+
+        // Bind the configured identity before the instance handles its first request.
+        if let Some(aid) = &config.identity {
+            let res = unsafe { syscall3(SYS_SET_IDENTITY, instance_id, aid.as_ptr() as u64, 0) };
+            if res != SUCCESS {
+                log(&alloc::format!("Init Service: Failed to bind identity to instance {} of '{}'.", instance_id, service_name));
+            }
+        }
+
+        let vnode = RunningVNode {
+            instance_id,
+            service_name: service_name.to_string(),
+            label,
+            channel,
+            lifecycle_channel,
+            config,
+            group,
+        };
+        self.running_vnodes.insert(instance_id, vnode.clone());
+        Ok(vnode)
+    }
+
+    /// Starts every configured service once, in dependency order, reporting
+    /// each step on the event bus and the kernel console. Group members are
+    /// left for their group, and `ON_DEMAND_SERVICES` for whoever asks.
+    fn boot(&mut self) {
+        let depends_on: BTreeMap<String, Vec<String>> = self.service_configs.iter()
+            .filter(|(name, _)| !self.member_of.contains_key(*name) && !ON_DEMAND_SERVICES.contains(&name.as_str()))
+            .map(|(name, config)| (name.clone(), config.depends_on.clone()))
+            .collect();
+        let depends_on = match boot_target() {
+            Some(target) => match boot::select_target(&depends_on, &target) {
+                Some(selected) => {
+                    log(&alloc::format!("Init Service: Boot target '{}'.", target));
+                    selected
+                },
+                None => {
+                    log(&alloc::format!("Init Service: Unknown boot target '{}'; booting '{}'.", target, boot::DEFAULT_TARGET));
+                    depends_on
+                },
+            },
+            None => depends_on,
+        };
+        let order = match boot::boot_order(&depends_on) {
+            Ok(order) => order,
+            Err(e) => {
+                log(&alloc::format!("Init Service: Cannot boot: {}.", e));
+                show_boot_status(&tr!("init.boot.failed", "Boot failed: {0}", e), BOOT_STATUS_FAILED);
+                return;
+            }
+        };
+        let total = order.len() as u32;
+        self.boot = BootTimeline::new(total);
+        self.boot_order = order.clone();
+        log(&alloc::format!("Init Service: Booting {} services: {}.", total, order.join(", ")));
+
+        let mut failed: Vec<String> = Vec::new();
+        for (i, service) in order.iter().enumerate() {
+            let index = i as u32 + 1;
+            if let Some(dep) = depends_on[service].iter().find(|dep| failed.contains(*dep)) {
+                self.report_boot_step(service, index, BootState::Failed(tr!("init.boot.dependency_failed", "dependency {0} failed", dep)));
+                failed.push(service.clone());
+                continue;
+            }
+            self.report_boot_step(service, index, BootState::Starting);
+            match self.start_instance(service, None, None) {
+                Ok(_) => {
+                    self.report_boot_step(service, index, BootState::Started);
+                    // From here on the status line can be in the user's language.
+                    if service == "settings" {
+                        self.follow_locale();
+                        self.follow_fault_storm_threshold();
+                        self.follow_core_dump_cap();
+                    }
+                },
+                Err(e) => {
+                    self.report_boot_step(service, index, BootState::Failed(e));
+                    failed.push(service.clone());
+                },
+            }
+        }
+
+        let report = self.boot.report();
+        log(&alloc::format!("Init Service: {}", report.render_text().trim_end()));
+        match self.boot.first_failure() {
+            Some((service, reason)) => show_boot_status(&tr!("init.boot.finished_with_failures", "Boot finished, {0} of {1} failed. {2}: {3}", failed.len(), total, service, reason), BOOT_STATUS_FAILED),
+            None => show_boot_status("", BOOT_STATUS_CLEAR),
+        }
+    }
+
+    /// Records a boot step, publishes it as `boot.progress` and updates the status line.
+    fn report_boot_step(&mut self, service: &str, index: u32, state: BootState) {
+        let tick = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+        let step = self.boot.record(service, index, state, tick);
+        self.publish_boot_step(&step);
+        match &step.state {
+            BootState::Starting => {
+                let mut text = tr!("init.boot.starting", "Starting {0} ({1}/{2}) {3}", service, index, step.total, progress_bar(index - 1, step.total));
+                // Once something has failed the line stays red and says what.
+                let flags = match self.boot.first_failure() {
+                    Some((failed, reason)) => {
+                        text.push_str("  ");
+                        text.push_str(&tr!("init.boot.step_failed", "{0} failed: {1}", failed, reason));
+                        BOOT_STATUS_FAILED
+                    },
+                    None => 0,
+                };
+                show_boot_status(&text, flags);
+            },
+            BootState::Started => log(&alloc::format!("Init Service: [{}/{}] {} started.", index, step.total, service)),
+            BootState::Failed(reason) => {
+                log(&alloc::format!("Init Service: [{}/{}] {} failed: {}.", index, step.total, service, reason));
+                show_boot_status(&tr!("init.boot.step_failed", "{0} failed: {1}", service, reason), BOOT_STATUS_FAILED);
+            },
+            BootState::Stopped { .. } => {}, // Only the shutdown records these, without publishing
+        }
+    }
+
+    /// Loads the configured locale and subscribes to its changes. Called once
+    /// the settings service is up; until then messages are in English.
+    fn follow_locale(&mut self) {
+        i18n::load_from_settings(&mut self.settings_chan, &mut self.aetherfs_chan);
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: i18n::locale_topic(), reply_chan: self.bus_events_chan.id };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("Init Service: Could not subscribe to locale changes.");
+        }
+    }
+
+    /// Reads `tasks.fault_storm_per_sec` and subscribes to its changes.
+    fn follow_fault_storm_threshold(&mut self) {
+        let get = SettingsRequest::Get { key: FAULT_STORM_KEY.to_string() };
+        if let Ok(SettingsResponse::Value { value: SettingValue::Int(per_sec), .. }) = self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&get) {
+            self.fault_storm_threshold = per_sec.clamp(0, u32::MAX as i64) as u32;
+        }
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: alloc::format!("settings.{}", FAULT_STORM_KEY), reply_chan: self.bus_events_chan.id };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("Init Service: Could not subscribe to fault storm threshold changes.");
+        }
+    }
+
+    /// Reads `tasks.core_dump_max_kb` and subscribes to its changes.
+    fn follow_core_dump_cap(&mut self) {
+        let get = SettingsRequest::Get { key: CORE_DUMP_KEY.to_string() };
+        if let Ok(SettingsResponse::Value { value: SettingValue::Int(kb), .. }) = self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&get) {
+            self.core_dump_max_kb = kb.clamp(0, u32::MAX as i64) as u32;
+        }
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: alloc::format!("settings.{}", CORE_DUMP_KEY), reply_chan: self.bus_events_chan.id };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("Init Service: Could not subscribe to core dump cap changes.");
+        }
+    }
+
+    /// Takes the core dumps the kernel queued, writes each to `/data/crash`
+    /// unless its service is loaded with the no-dump flag, remembers the
+    /// crash for `Crashes` and publishes it as `service.crashed`.
+    fn save_core_dumps(&mut self) {
+        while let Some(dump) = tasks::take_core_dump(self.core_dump_max_kb) {
+            let header = match CoreHeader::from_bytes(&dump) {
+                Ok(header) => header,
+                Err(e) => {
+                    log(&alloc::format!("Init Service: Dropping a core dump the kernel queued: {:?}.", e));
+                    continue;
                 }
+            };
+            let service_name = self.running_vnodes.get(&header.task).map(|vnode| vnode.service_name.clone());
+            let registers = coredump::registers_from_bytes(&dump[CORE_HEADER_LEN..]).unwrap_or_default();
+            let core_dump = if header.flags & CORE_FLAG_WITHHELD != 0 {
+                None
+            } else {
+                let path = alloc::format!("{}/{}-{}-{}.core", CRASH_DIR, service_name.as_deref().unwrap_or(header.name()), header.task, header.tick);
+                match self.write_core_dump(&path, dump) {
+                    Ok(()) => Some(path),
+                    Err(e) => {
+                        log(&alloc::format!("Init Service: Failed to write {}: {}.", path, e));
+                        None
+                    }
+                }
+            };
+            log(&alloc::format!(
+                "Init Service: Task {} ({}) {} at {:#x}; core dump {}.",
+                header.task, service_name.as_deref().unwrap_or(header.name()), coredump::reason_name(header.reason), registers.rip,
+                core_dump.as_deref().unwrap_or("not written")
+            ));
+            let report = CrashReport {
+                task_id: header.task,
+                task_name: header.name().to_string(),
+                service_name,
+                reason: coredump::reason_name(header.reason).to_string(),
+                rip: registers.rip,
+                fault_addr: header.fault_addr,
+                tick: header.tick,
+                core_dump,
+                truncated: header.flags & CORE_FLAG_TRUNCATED != 0,
+            };
+            if let Ok(payload) = postcard::to_allocvec(&report) {
+                let request = EventBusRequest::Publish { topic: SERVICE_CRASHED_TOPIC.to_string(), payload };
+                if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+                    log(&alloc::format!("Init Service: Could not publish {} for task {}.", SERVICE_CRASHED_TOPIC, header.task));
+                }
+            }
+            self.crashes.push_front(report);
+            self.crashes.truncate(MAX_CRASH_REPORTS);
+        }
+    }
 
-                if let Some(config) = self.service_configs.get(&service_name) {
-                    // Conceptual: Send IPC to kernel-vnode-manager to start the V-Node
-                    // For now, simulate success and assign a dummy PID.
-                    let pid = self.next_pid;
-                    self.next_pid += 1;
-                    log(&alloc::format!("Init Service: (Conceptual) Starting service '{}' (PID: {}).", service_name, pid));
-
-                    let new_vnode = RunningVNode {
-                        pid,
-                        status_channel: 0, // Placeholder for actual status channel if any
-                        config: config.clone(),
-                    };
-                    self.running_vnodes.insert(service_name.clone(), new_vnode);
-                    InitResponse::Success(alloc::format!("Service '{}' started with PID {}.", service_name, pid))
-                } else {
-                    log(&alloc::format!("Init Service: Service '{}' not found in configuration.", service_name));
-                    InitResponse::Error(alloc::format!("Service '{}' not found in configuration.", service_name))
+    fn write_core_dump(&mut self, path: &str, dump: Vec<u8>) -> Result<(), String> {
+        match self.aetherfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::CreateDirectory { path: CRASH_DIR.to_string() }) {
+            Ok(VfsResponse::CreateDirectorySuccess) | Ok(VfsResponse::Error { code: 17, .. }) => {}, // EEXIST
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("unexpected response from the VFS".to_string()),
+        }
+        let fd = match self.aetherfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("unexpected response from the VFS".to_string()),
+        };
+        let mut streams = VfsStreams::new(&mut self.aetherfs_chan);
+        let written = (|| {
+            let writer = streams.open_write(fd, 0)?;
+            for chunk in dump.chunks(STREAM_CHUNK_SIZE) {
+                streams.write(writer, chunk.to_vec())?;
+            }
+            streams.finish(writer)
+        })();
+        let _ = streams.request(&VfsRequest::Close { fd });
+        written.map(|_| ())
+    }
+
+    /// Hands the kernel the current threshold and publishes a `task.faultstorm`
+    /// for each storm it reports. What to do about a storming task is up to
+    /// the subscribers; init only says which service the task belongs to.
+    fn publish_fault_storms(&mut self) {
+        let Some(storms) = tasks::take_fault_storms(self.fault_storm_threshold) else {
+            return;
+        };
+        for storm in storms {
+            let service_name = self.running_vnodes.get(&storm.task).map(|vnode| vnode.service_name.clone());
+            log(&alloc::format!(
+                "Init Service: Task {} ({}) took {} faults within a second (threshold {}).",
+                storm.task, service_name.as_deref().unwrap_or(storm.name()), storm.faults, storm.threshold
+            ));
+            let event = TaskFaultStorm { task_id: storm.task, task_name: storm.name().to_string(), service_name, faults: storm.faults, threshold: storm.threshold };
+            let Ok(payload) = postcard::to_allocvec(&event) else {
+                continue;
+            };
+            let request = EventBusRequest::Publish { topic: TASK_FAULTSTORM_TOPIC.to_string(), payload };
+            if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+                log(&alloc::format!("Init Service: Could not publish {} for task {}.", TASK_FAULTSTORM_TOPIC, storm.task));
+            }
+        }
+    }
+
+    fn publish_boot_step(&mut self, step: &BootProgress) {
+        let payload = match postcard::to_allocvec(step) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: BOOT_PROGRESS_TOPIC.to_string(), payload };
+        // Steps before the event bus itself is up have nowhere to go; the timeline still has them.
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log(&alloc::format!("Init Service: Could not publish boot progress for {}.", step.service));
+        }
+    }
+
+    /// Publishes a `service.*` event for an instance started, stopped or restarted on request.
+    fn publish_service_state(&mut self, topic: &str, service_name: &str, instance_id: u64, previous_instance_id: Option<u64>) {
+        let changed = ServiceStateChanged { service_name: service_name.to_string(), instance_id, previous_instance_id };
+        let payload = match postcard::to_allocvec(&changed) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: topic.to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log(&alloc::format!("Init Service: Could not publish {} for instance {} of '{}'.", topic, instance_id, service_name));
+        }
+    }
+
+    /// Publishes a `group.*` event with the group's state after the change.
+    fn publish_group_state(&mut self, topic: &str, group: &str, member: Option<&str>) {
+        let Some(info) = self.group_info(group) else {
+            return;
+        };
+        let changed = GroupStateChanged { group: group.to_string(), state: info.state, member: member.map(str::to_string) };
+        let payload = match postcard::to_allocvec(&changed) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: topic.to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log(&alloc::format!("Init Service: Could not publish {} for group '{}'.", topic, group));
+        }
+    }
+
+    /// The running instance of `service` started as a member of `group`.
+    fn member_instance(&self, group: &str, service: &str) -> Option<u64> {
+        self.running_vnodes.values()
+            .find(|vnode| vnode.group.as_deref() == Some(group) && vnode.service_name == service)
+            .map(|vnode| vnode.instance_id)
+    }
+
+    fn group_info(&self, name: &str) -> Option<GroupInfo> {
+        let group = self.groups.get(name)?;
+        let members: Vec<GroupMemberInfo> = group.members.iter()
+            .map(|member| GroupMemberInfo {
+                service_name: member.service.clone(),
+                restart: member.restart,
+                instance_id: self.member_instance(name, &member.service),
+            })
+            .collect();
+        let running = members.iter().filter(|member| member.instance_id.is_some()).count();
+        Some(GroupInfo { name: name.to_string(), state: group::state(running, members.len()), crash_policy: group.crash_policy, members })
+    }
+
+    /// Starts the members of `name` that aren't running, in order. If one
+    /// fails to start, the members already running are stopped again.
+    fn start_group(&mut self, name: &str) -> Result<GroupInfo, String> {
+        let group = self.groups.get(name).cloned().ok_or_else(|| tr!("init.group.not_configured", "No group '{0}' in configuration.", name))?;
+        for member in &group.members {
+            if self.member_instance(name, &member.service).is_some() {
+                continue;
+            }
+            if let Err(e) = self.start_instance(&member.service, None, Some(name.to_string())) {
+                log(&alloc::format!("Init Service: Group '{}' failed to start at {}.", name, member.service));
+                self.stop_group_members(name);
+                self.group_budgets.remove(name);
+                return Err(tr!("init.group.start_failed", "Group '{0}' did not start: {1}", name, e));
+            }
+        }
+        self.group_budgets.entry(name.to_string()).or_default();
+        log(&alloc::format!("Init Service: Group '{}' started ({} members).", name, group.members.len()));
+        self.group_info(name).ok_or_else(|| tr!("init.group.not_configured", "No group '{0}' in configuration.", name))
+    }
+
+    /// Stops the running members of `name` in reverse order. Returns how many were running.
+    fn stop_group_members(&mut self, name: &str) -> usize {
+        let Some(group) = self.groups.get(name) else {
+            return 0;
+        };
+        let ids: Vec<u64> = group.members.iter().rev()
+            .filter_map(|member| self.member_instance(name, &member.service))
+            .collect();
+        for id in &ids {
+            if let Some(vnode) = self.running_vnodes.remove(id) {
+                // Conceptual: Ask the instance to shut down and give it a moment to finish, then have
+                // kernel-vnode-manager kill the task if it is still there.
+                log(&alloc::format!("Init Service: (Conceptual) Shutting down instance {} of '{}' (group '{}').", id, vnode.service_name, name));
+                self.publish_service_state(SERVICE_STOPPED_TOPIC, &vnode.service_name, *id, None);
+            }
+        }
+        ids.len()
+    }
+
+    fn stop_group(&mut self, name: &str) {
+        let stopped = self.stop_group_members(name);
+        self.group_budgets.remove(name);
+        log(&alloc::format!("Init Service: Group '{}' stopped ({} members were running).", name, stopped));
+        self.publish_group_state(GROUP_STOPPED_TOPIC, name, None);
+    }
+
+    /// Finds group members that exited without being asked to and applies
+    /// their group's crash policy.
+    fn watch_groups(&mut self) {
+        let crashed: Vec<RunningVNode> = self.running_vnodes.values()
+            .filter(|vnode| vnode.group.is_some() && !session_ipc::task_alive(vnode.instance_id))
+            .cloned()
+            .collect();
+        for vnode in crashed {
+            // A group restarted or stopped for an earlier crash has already dropped this one.
+            if self.running_vnodes.remove(&vnode.instance_id).is_none() {
+                continue;
+            }
+            let Some(name) = vnode.group.clone() else {
+                continue;
+            };
+            let Some(group) = self.groups.get(&name).cloned() else {
+                continue;
+            };
+            log(&alloc::format!("Init Service: Instance {} of '{}' (group '{}') exited unexpectedly.", vnode.instance_id, vnode.service_name, name));
+            self.publish_service_state(SERVICE_STOPPED_TOPIC, &vnode.service_name, vnode.instance_id, None);
+            self.publish_group_state(GROUP_MEMBER_CRASHED_TOPIC, &name, Some(&vnode.service_name));
+
+            let now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+            let budget = self.group_budgets.entry(name.clone()).or_default();
+            match group::on_crash(&group, &vnode.service_name, budget, now) {
+                CrashAction::LeaveStopped => {
+                    log(&alloc::format!("Init Service: '{}' doesn't restart; group '{}' is degraded.", vnode.service_name, name));
+                },
+                CrashAction::RestartMember => match self.start_instance(&vnode.service_name, vnode.label.clone(), Some(name.clone())) {
+                    Ok(restarted) => {
+                        self.publish_service_state(SERVICE_RESTARTED_TOPIC, &vnode.service_name, restarted.instance_id, Some(vnode.instance_id));
+                        self.publish_group_state(GROUP_RESTARTED_TOPIC, &name, Some(&vnode.service_name));
+                    },
+                    Err(e) => {
+                        log(&alloc::format!("Init Service: Could not restart '{}': {}.", vnode.service_name, e));
+                        self.stop_group(&name);
+                    },
+                },
+                CrashAction::RestartGroup => {
+                    self.stop_group_members(&name);
+                    match self.start_group(&name) {
+                        Ok(_) => self.publish_group_state(GROUP_RESTARTED_TOPIC, &name, None),
+                        Err(e) => {
+                            log(&alloc::format!("Init Service: {}.", e));
+                            self.publish_group_state(GROUP_STOPPED_TOPIC, &name, None);
+                        },
+                    }
+                },
+                CrashAction::StopGroup => self.stop_group(&name),
+                CrashAction::GiveUp => {
+                    log(&alloc::format!("Init Service: Group '{}' restarted {} times within {} ticks; stopping it.", name, group::MAX_GROUP_RESTARTS, group::GROUP_RESTART_WINDOW_TICKS));
+                    self.stop_group(&name);
+                },
+            }
+        }
+    }
+
+    /// Asks `vnode` to stop and waits until it answers or its stop timeout
+    /// is up. Returns false if it didn't answer in time.
+    fn stop_gracefully(&mut self, vnode: &RunningVNode, reboot: bool, force: bool) -> bool {
+        let Some(timeout_ms) = vnode.config.stop_timeout_ms else {
+            return true;
+        };
+        let deadline = time::ticks() + shutdown::stop_ticks(timeout_ms, force);
+        let mut chan = VNodeChannel::new(vnode.lifecycle_channel);
+        let request = LifecycleRequest::Shutdown { reboot };
+        match envelope::call::<LifecycleRequest, LifecycleResponse>(&mut chan, envelope::next_request_id(), Some(deadline), &request, || false) {
+            Ok(LifecycleResponse::Stopped) => true,
+            Ok(response) => {
+                log(&alloc::format!("Init Service: Instance {} of '{}' answered its shutdown with {:?}.", vnode.instance_id, vnode.service_name, response));
+                false
+            },
+            Err(e) => {
+                log(&alloc::format!("Init Service: Instance {} of '{}' didn't stop in time ({:?}).", vnode.instance_id, vnode.service_name, e));
+                false
+            },
+        }
+    }
+
+    /// Sends `SyncAll` to the VFS and waits up to `VFS_SYNC_TIMEOUT`
+    /// for it to confirm. A plain send and a poll, so a hung VFS can't hold
+    /// up the shutdown.
+    fn sync_vfs(&mut self) -> bool {
+        if self.aetherfs_chan.send(&VfsRequest::SyncAll).is_err() {
+            return false;
+        }
+        let deadline = time::ticks() + shutdown::VFS_SYNC_TIMEOUT;
+        while time::ticks() < deadline {
+            if let Ok(Some(data)) = self.aetherfs_chan.recv_non_blocking() {
+                return matches!(postcard::from_bytes::<VfsResponse>(&data), Ok(VfsResponse::Success(_)));
+            }
+        }
+        false
+    }
+
+    /// Stops every running instance in reverse dependency order, syncs the
+    /// VFS and has the kernel power off or reboot. Only returns if the kernel
+    /// refuses; by then the services are gone.
+    fn shut_down(&mut self, reboot: bool, force: bool) {
+        let running: Vec<(u64, String)> = self.running_vnodes.values().map(|vnode| (vnode.instance_id, vnode.service_name.clone())).collect();
+        let order = shutdown::stop_order(&running, &self.boot_order);
+        let total = order.len() as u32;
+        log(&alloc::format!("Init Service: {} ({}); stopping {} instances.", if reboot { "Rebooting" } else { "Shutting down" }, if force { "forced" } else { "orderly" }, total));
+
+        let mut forced = 0;
+        for (i, id) in order.iter().enumerate() {
+            let Some(vnode) = self.running_vnodes.remove(id) else {
+                continue;
+            };
+            let index = i as u32 + 1;
+            show_boot_status(&tr!("init.shutdown.stopping", "Stopping {0} ({1}/{2}) {3}", vnode.service_name, index, total, progress_bar(index - 1, total)), 0);
+            let answered = self.stop_gracefully(&vnode, reboot, force);
+            // Conceptual: Have kernel-vnode-manager kill the task, whether or not it answered.
+            // No service.stopped events: the event bus is on its way out too.
+            log(&alloc::format!("Init Service: (Conceptual) Stopping instance {} of '{}'.", id, vnode.service_name));
+            let tick = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+            self.boot.record(&vnode.service_name, index, BootState::Stopped { forced: !answered }, tick);
+            if !answered {
+                forced += 1;
+            }
+        }
+
+        // Nothing writes through the VFS any more; make what it holds durable.
+        show_boot_status(&tr!("init.shutdown.syncing", "Writing files to disk"), 0);
+        if self.sync_vfs() {
+            log("Init Service: VFS synced.");
+        } else {
+            log("Init Service: The VFS didn't confirm the sync; files written last may be lost.");
+        }
+
+        let report = self.boot.report();
+        log(&alloc::format!("Init Service: {}", report.render_text().trim_end()));
+        let (action, text) = if reboot {
+            (POWER_REBOOT, tr!("init.shutdown.rebooting", "Restarting"))
+        } else {
+            (POWER_SHUTDOWN, tr!("init.shutdown.powering_off", "Powering off"))
+        };
+        let flags = if forced > 0 { BOOT_STATUS_FAILED } else { 0 };
+        show_boot_status(&text, flags);
+        let res = unsafe { syscall3(SYS_SYSTEM_POWER, action, 0, 0) };
+        log(&alloc::format!("Init Service: The kernel refused to {}: {:#x}.", if reboot { "reboot" } else { "power off" }, res));
+        show_boot_status(&tr!("init.shutdown.failed", "Services stopped, but the system could not power off."), BOOT_STATUS_FAILED);
+    }
+
+    fn handle_request(&mut self, request: InitRequest) -> InitResponse {
+        match request {
+            InitRequest::ServiceStart { service_name, instance_label } => {
+                match self.start_instance(&service_name, instance_label, None) {
+                    Ok(vnode) => {
+                        self.publish_service_state(SERVICE_STARTED_TOPIC, &service_name, vnode.instance_id, None);
+                        InitResponse::InstanceStarted {
+                            service_name,
+                            instance_id: vnode.instance_id,
+                            channel: vnode.channel,
+                        }
+                    },
+                    Err(e) => InitResponse::Error(e),
                 }
             },
-            InitRequest::ServiceStatus { service_name } => {
-                if let Some(vnode) = self.running_vnodes.get(&service_name) {
-                    log(&alloc::format!("Init Service: Status request for '{}': Running (PID: {}).", service_name, vnode.pid));
-                    InitResponse::Status {
-                        service_name: service_name.clone(),
-                        is_running: true,
-                        pid: Some(vnode.pid),
+            InitRequest::ServiceStatus { target } => {
+                let instances: Vec<InstanceInfo> = self.resolve_target(&target).iter()
+                    .filter_map(|id| self.running_vnodes.get(id))
+                    .map(RunningVNode::info)
+                    .collect();
+                log(&alloc::format!("Init Service: Status request for {}: {} running instance(s).", Self::describe_target(&target), instances.len()));
+                InitResponse::Instances(instances)
+            },
+            InitRequest::ServiceRestart { target } => {
+                let ids = self.resolve_target(&target);
+                if ids.is_empty() {
+                    log(&alloc::format!("Init Service: {} not running, cannot restart.", Self::describe_target(&target)));
+                    return InitResponse::Error(tr!("init.restart.not_running", "No running instances of {0} to restart.", Self::describe_target(&target)));
+                }
+                // Simulate stop then start; the replacement keeps the label but gets a new ID and channel.
+                let mut restarted = Vec::new();
+                for id in ids {
+                    if let Some(old) = self.running_vnodes.remove(&id) {
+                        log(&alloc::format!("Init Service: Instance {} of '{}' stopped for restart.", id, old.service_name));
+                        match self.start_instance(&old.service_name, old.label, old.group) {
+                            Ok(vnode) => {
+                                self.publish_service_state(SERVICE_RESTARTED_TOPIC, &vnode.service_name, vnode.instance_id, Some(id));
+                                restarted.push(alloc::format!("{} -> {}", id, vnode.instance_id));
+                            },
+                            Err(e) => return InitResponse::Error(e),
+                        }
                     }
-                } else {
-                    log(&alloc::format!("Init Service: Status request for '{}': Not running.", service_name));
-                    InitResponse::Status {
-                        service_name: service_name.clone(),
-                        is_running: false,
-                        pid: None,
+                }
+                InitResponse::Success(tr!("init.restart.done", "Restarted {0} (instances {1}).", Self::describe_target(&target), restarted.join(", ")))
+            },
+            InitRequest::ServiceStop { target } => {
+                let ids = self.resolve_target(&target);
+                if ids.is_empty() {
+                    log(&alloc::format!("Init Service: {} not running, cannot stop.", Self::describe_target(&target)));
+                    return InitResponse::Error(tr!("init.stop.not_running", "No running instances of {0}.", Self::describe_target(&target)));
+                }
+                for id in &ids {
+                    if let Some(vnode) = self.running_vnodes.remove(id) {
+                        // Conceptual: Send IPC to kernel-vnode-manager to kill the task; the kernel
+                        // releases its channel along with its other resources.
+                        log(&alloc::format!("Init Service: (Conceptual) Stopping instance {} of '{}'.", id, vnode.service_name));
+                        self.publish_service_state(SERVICE_STOPPED_TOPIC, &vnode.service_name, *id, None);
                     }
                 }
+                InitResponse::Success(tr_plural!("init.stop.done", ids.len(), "Stopped {0} instance of {1}.", "Stopped {0} instances of {1}.", ids.len(), Self::describe_target(&target)))
             },
-            InitRequest::ServiceRestart { service_name } => {
-                log(&alloc::format!("Init Service: (Conceptual) Restarting service '{}'.", service_name));
-                // Simulate stop then start
-                if self.running_vnodes.remove(&service_name).is_some() {
-                    log(&alloc::format!("Init Service: Service '{}' stopped for restart.", service_name));
-                    self.handle_request(InitRequest::ServiceStart { service_name: service_name.clone() })
-                } else {
-                    log(&alloc::format!("Init Service: Service '{}' not running, cannot restart.", service_name));
-                    InitResponse::Error(alloc::format!("Service '{}' not running to restart.", service_name))
+            InitRequest::ListServices => {
+                let names: Vec<String> = self.service_configs.keys().cloned().collect();
+                log(&alloc::format!("Init Service: Listing {} configured services.", names.len()));
+                InitResponse::ServiceList(names)
+            },
+            InitRequest::BootReport => InitResponse::BootReport(self.boot.report()),
+            InitRequest::GroupStart { name } => {
+                if self.group_info(&name).map_or(false, |info| info.state == GroupState::Running) {
+                    return InitResponse::Error(tr!("init.group.already_running", "Group '{0}' is already running.", name));
+                }
+                match self.start_group(&name) {
+                    Ok(info) => {
+                        self.publish_group_state(GROUP_STARTED_TOPIC, &name, None);
+                        InitResponse::GroupStatus(info)
+                    },
+                    Err(e) => InitResponse::Error(e),
                 }
             },
-            InitRequest::ServiceStop { service_name } => {
-                if self.running_vnodes.remove(&service_name).is_some() {
-                    // Conceptual: Send IPC to kernel-vnode-manager to stop the V-Node
-                    log(&alloc::format!("Init Service: (Conceptual) Stopping service '{}'.", service_name));
-                    InitResponse::Success(alloc::format!("Service '{}' stopped.", service_name))
-                } else {
-                    log(&alloc::format!("Init Service: Service '{}' not running, cannot stop.", service_name));
-                    InitResponse::Error(alloc::format!("Service '{}' not running.", service_name))
+            InitRequest::GroupStop { name } => {
+                match self.group_info(&name) {
+                    None => InitResponse::Error(tr!("init.group.not_configured", "No group '{0}' in configuration.", name)),
+                    Some(info) if info.state == GroupState::Stopped => InitResponse::Error(tr!("init.group.not_running", "Group '{0}' is not running.", name)),
+                    Some(info) => {
+                        self.stop_group(&name);
+                        InitResponse::Success(tr!("init.group.stopped", "Stopped group '{0}' ({1} of {2} members were running).", name, info.members.iter().filter(|member| member.instance_id.is_some()).count(), info.members.len()))
+                    },
                 }
             },
+            InitRequest::GroupStatus { name } => match self.group_info(&name) {
+                Some(info) => InitResponse::GroupStatus(info),
+                None => InitResponse::Error(tr!("init.group.not_configured", "No group '{0}' in configuration.", name)),
+            },
+            InitRequest::ListGroups => InitResponse::Groups(self.groups.keys().filter_map(|name| self.group_info(name)).collect()),
+            InitRequest::ListServicesWithGroups => InitResponse::ServiceGroupList(
+                self.service_configs.keys().map(|name| (name.clone(), self.member_of.get(name).cloned())).collect(),
+            ),
+            InitRequest::Crashes { service_name } => InitResponse::Crashes(
+                self.crashes.iter().filter(|crash| service_name.is_none() || crash.service_name == service_name).cloned().collect()
+            ),
+            InitRequest::SystemShutdown { reboot, force } => {
+                // Carried out by the run loop once this answer is sent; the caller would never get one otherwise.
+                self.pending_power = Some((reboot, force));
+                InitResponse::Success(if reboot {
+                    tr!("init.shutdown.reboot_accepted", "The system is going down for reboot.")
+                } else {
+                    tr!("init.shutdown.accepted", "The system is going down for power-off.")
+                })
+            },
         }
     }
 
     fn run_loop(&mut self) -> ! {
+        self.boot();
         log("Init Service: Entering main event loop.");
         loop {
             // 1. Process incoming requests from client V-Nodes
@@ -173,12 +1134,41 @@ impl InitService {
                     log(&alloc::format!("Init Service: Received InitRequest: {:?}.", request));
                     let response = self.handle_request(request);
                     self.client_chan.send(&response).unwrap_or_else(|_| log("Init Service: Failed to send response to client."));
+                    if let Some((reboot, force)) = self.pending_power.take() {
+                        // Only returns if the kernel refused; keep taking requests, e.g. to try again.
+                        self.shut_down(reboot, force);
+                    }
                 } else {
                     log("Init Service: Failed to deserialize InitRequest from client.");
                 }
             }
 
-            // Conceptual: Monitor running V-Nodes (e.g., check their status channels, or poll kernel-vnode-manager)
+            // 2. Apply the crash policy of groups whose members exited
+            self.watch_groups();
+
+            // 3. Reload the catalog when the locale changes, and follow the fault storm threshold and the core dump cap
+            while let Ok(Some(event_data)) = self.bus_events_chan.recv_non_blocking() {
+                let Ok(event) = postcard::from_bytes::<Event>(&event_data) else { continue };
+                if let Some(lang) = i18n::locale_changed(&event) {
+                    if let Err(e) = i18n::set_locale(&mut self.aetherfs_chan, &lang) {
+                        log(&alloc::format!("Init Service: Keeping the current locale: {}.", e));
+                    }
+                } else if let Ok(SettingChanged { key, value: SettingValue::Int(value) }) = postcard::from_bytes::<SettingChanged>(&event.payload) {
+                    if key == FAULT_STORM_KEY {
+                        self.fault_storm_threshold = value.clamp(0, u32::MAX as i64) as u32;
+                    } else if key == CORE_DUMP_KEY {
+                        self.core_dump_max_kb = value.clamp(0, u32::MAX as i64) as u32;
+                    }
+                }
+            }
+
+            // 4. Publish the fault storms the kernel saw
+            self.publish_fault_storms();
+
+            // 5. Save the core dumps of crashed tasks
+            self.save_core_dumps();
+
+            // Conceptual: Monitor the other running V-Nodes (e.g., check their status channels, or poll kernel-vnode-manager)
             // For now, this is a placeholder.
 
             // Yield to other V-Nodes to prevent busy-waiting
@@ -189,9 +1179,10 @@ impl InitService {
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel ID 6 for init-service for client requests
-    // Assuming channel ID 7 for aetherfs for config reads (conceptual)
-    let mut init_service = InitService::new(6, 7);
+    // Well-known channels: init-service for client requests, aetherfs for config reads
+    // (conceptual), the event bus (boot.progress), settings, and the events the bus
+    // delivers (locale changes)
+    let mut init_service = InitService::new(channels::INIT_SERVICE, channels::VFS, channels::EVENT_BUS, channels::SETTINGS, channels::INIT_EVENTS);
     init_service.run_loop();
 }
 
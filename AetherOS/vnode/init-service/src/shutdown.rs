@@ -0,0 +1,41 @@
+// vnode/init-service/src/shutdown.rs
+
+//! Stop order and deadlines for the system shutdown.
+//!
+//! Services stop in the reverse of the boot order, so each one stops while
+//! the services it depends on are still there to take its last writes.
+//! Instances the boot didn't start, group members and extra instances started
+//! on request, go first, newest first: nothing the boot started depends on them.
+//!
+//! A service that takes part in the shutdown protocol gets its configured
+//! stop timeout to answer; one that doesn't answer is stopped anyway when the
+//! time is up. A forced shutdown gives every service `FORCED_STOP_TIMEOUT_MS`
+//! at most.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ids::{Millis, Ticks};
+
+/// Longest a service is waited for in a forced shutdown.
+pub const FORCED_STOP_TIMEOUT_MS: u32 = 100;
+
+/// Longest the VFS is waited for to confirm the final sync.
+pub const VFS_SYNC_TIMEOUT: Ticks = Ticks::from_secs(10);
+
+/// The running instances, as (instance ID, service) pairs, in the order to stop them.
+pub fn stop_order(running: &[(u64, String)], boot_order: &[String]) -> Vec<u64> {
+    // Later in the boot order stops earlier; not in it at all stops first.
+    let rank = |service: &str| boot_order.iter().position(|booted| booted == service).map_or(usize::MAX, |i| i);
+    let mut running: Vec<&(u64, String)> = running.iter().collect();
+    running.sort_by(|(a_id, a), (b_id, b)| rank(b).cmp(&rank(a)).then_with(|| b_id.cmp(a_id)));
+    running.into_iter().map(|(id, _)| *id).collect()
+}
+
+/// Ticks a service gets to stop, rounded up so a timeout is never cut short.
+pub fn stop_ticks(timeout_ms: u32, force: bool) -> Ticks {
+    let timeout_ms = if force { timeout_ms.min(FORCED_STOP_TIMEOUT_MS) } else { timeout_ms };
+    Millis::from_raw(timeout_ms as u64).to_ticks_ceil()
+}
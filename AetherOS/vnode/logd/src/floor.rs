@@ -0,0 +1,58 @@
+// vnode/logd/src/floor.rs
+
+//! The lowest severity written to each service's file.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::abi::LOG_SEVERITY_INFO;
+use common::ipc::log_ipc::parse_severity;
+
+#[derive(Debug)]
+pub struct Floors {
+    default: u64,
+    overrides: Vec<(String, u64)>,
+}
+
+impl Default for Floors {
+    fn default() -> Self {
+        Self { default: LOG_SEVERITY_INFO, overrides: Vec::new() }
+    }
+}
+
+impl Floors {
+    /// Floors from `log.floor` and `log.floor_overrides` (`<service>=<level>,...`).
+    /// Entries that don't parse are skipped and returned, so they can be reported.
+    pub fn parse(default: &str, overrides: &str) -> (Self, Vec<String>) {
+        let mut floors = Self::default();
+        let mut rejected = Vec::new();
+        match parse_severity(default) {
+            Some(severity) => floors.default = severity,
+            None => rejected.push(default.to_string()),
+        }
+        for entry in overrides.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(service, level)| {
+                let service = service.trim();
+                Some((service, parse_severity(level.trim())?)).filter(|_| !service.is_empty())
+            });
+            match parsed {
+                Some((service, severity)) => {
+                    floors.overrides.retain(|(existing, _)| existing != service);
+                    floors.overrides.push((service.to_string(), severity));
+                },
+                None => rejected.push(entry.to_string()),
+            }
+        }
+        (floors, rejected)
+    }
+
+    /// Whether a message of `severity` from `service` is written.
+    pub fn passes(&self, service: &str, severity: u64) -> bool {
+        let floor = self.overrides.iter()
+            .find(|(name, _)| name == service)
+            .map_or(self.default, |(_, floor)| *floor);
+        severity >= floor
+    }
+}
@@ -0,0 +1,314 @@
+// vnode/logd/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::abi::{LogRecord, LOG_SEVERITY_WARN};
+use common::channels;
+use common::ids::Ticks;
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::log_ipc::{self, LOG_DIR, FLOOR_KEY, FLOOR_OVERRIDES_KEY, MAX_FILE_KB_KEY, KEEP_FILES_KEY};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse, STREAM_CHUNK_SIZE};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::klog::{self, ForwardError};
+use common::cmdline;
+use common::startup;
+
+mod floor;
+mod rotate;
+use floor::Floors;
+
+/// Channel the kernel queues `LogRecord`s on. It is not one init hands out;
+/// logd names it to the kernel itself.
+const RECORDS_CHANNEL: u32 = channels::LOGD_RECORDS;
+/// Lines are written out this often (1 s), or sooner once a service has
+/// `FLUSH_BYTES` waiting.
+const FLUSH_TICKS: u64 = 100;
+const FLUSH_BYTES: usize = 16 * 1024;
+/// Lines a service may have waiting before further ones are dropped.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+/// How often the log.* settings are read again (30 s). logd has no channel
+/// left for settings events.
+const SETTINGS_POLL_TICKS: u64 = 3000;
+
+const DEFAULT_MAX_FILE_KB: u64 = 256;
+const DEFAULT_KEEP_FILES: u32 = 3;
+
+/// Where logd's own notes about lost messages go.
+const OWN_SERVICE: &str = "logd";
+
+// Temporary log function for V-Nodes. logd's own messages are printed but
+// never forwarded back to it.
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+fn now() -> u64 {
+    unsafe { syscall3(SYS_TIME, 0, 0, 0) }
+}
+
+/// `[secs.ms] LEVEL message`, one line however the message ends.
+fn format_line(ticks: u64, severity: u64, message: &str) -> String {
+    let ms = Ticks::from_raw(ticks).to_millis().raw();
+    let message = message.trim_end_matches(['\n', '\r']).replace('\n', " ");
+    format!("[{}.{:03}] {} {}\n", ms / 1000, ms % 1000, log_ipc::severity_label(severity), message)
+}
+
+/// Lines of one service waiting for the next flush.
+#[derive(Default)]
+struct Pending {
+    lines: Vec<String>,
+    bytes: usize,
+    /// Lines dropped because `MAX_PENDING_BYTES` were already waiting.
+    dropped: u64,
+}
+
+/// `loglevel=` from the kernel command line, which replaces the `log.floor`
+/// setting until the next boot. Per-service floors still apply.
+fn boot_floor() -> Option<String> {
+    let args = cmdline::read().ok()?;
+    let level = args.value("loglevel")?;
+    if log_ipc::parse_severity(level).is_none() {
+        log(&format!("logd: Ignoring loglevel={}; expected debug|info|warn|error.", level));
+        return None;
+    }
+    log(&format!("logd: Floor {} from the boot arguments.", level));
+    Some(level.to_string())
+}
+
+struct LogdService {
+    records_chan: VNodeChannel, // LogRecords from the kernel
+    vfs_chan: VNodeChannel, // Appends to and rotates the log files
+    settings_chan: VNodeChannel, // log.* settings, polled
+    lifecycle: Lifecycle, // Shutdown requests from init
+    floors: Floors,
+    boot_floor: Option<String>, // loglevel= from the boot arguments, over log.floor
+    max_file_bytes: u64,
+    keep_files: u32,
+    pending: BTreeMap<String, Pending>,
+    last_flush: u64,
+    last_settings: u64,
+}
+
+impl LogdService {
+    fn new(vfs_chan_id: u32, settings_chan_id: u32, lifecycle_chan_id: Option<u32>) -> Self {
+        log("logd: Initializing...");
+        let mut service = Self {
+            records_chan: VNodeChannel::new(RECORDS_CHANNEL),
+            vfs_chan: VNodeChannel::new(vfs_chan_id),
+            settings_chan: VNodeChannel::new(settings_chan_id),
+            lifecycle: Lifecycle::new(lifecycle_chan_id),
+            floors: Floors::default(),
+            boot_floor: boot_floor(),
+            max_file_bytes: DEFAULT_MAX_FILE_KB * 1024,
+            keep_files: DEFAULT_KEEP_FILES,
+            pending: BTreeMap::new(),
+            last_flush: now(),
+            last_settings: now(),
+        };
+        service.load_settings();
+        match service.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::CreateDirectory { path: LOG_DIR.to_string() }) {
+            Ok(VfsResponse::CreateDirectorySuccess) | Ok(VfsResponse::Error { code: 17, .. }) => {}, // EEXIST
+            Ok(VfsResponse::Error { message, .. }) => log(&format!("logd: Failed to create {}: {}.", LOG_DIR, message)),
+            _ => log(&format!("logd: Unexpected response creating {}.", LOG_DIR)),
+        }
+        match klog::forward_to(RECORDS_CHANNEL) {
+            Ok(()) => log(&format!("logd: Receiving log messages on channel {}.", RECORDS_CHANNEL)),
+            Err(ForwardError::Busy) => log("logd: Another task already receives the log messages; no files will be written."),
+            Err(ForwardError::Denied) => log("logd: The kernel refused to forward log messages; no files will be written."),
+        }
+        service
+    }
+
+    fn setting(&mut self, key: &str) -> Option<SettingValue> {
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.to_string() }) {
+            Ok(SettingsResponse::Value { value, .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Reads the log.* settings. Ones that can't be read keep their current values.
+    fn load_settings(&mut self) {
+        if let Some(SettingValue::Int(kb)) = self.setting(MAX_FILE_KB_KEY) {
+            self.max_file_bytes = (kb.max(1) as u64) * 1024;
+        }
+        if let Some(SettingValue::Int(keep)) = self.setting(KEEP_FILES_KEY) {
+            self.keep_files = keep.clamp(0, u32::MAX as i64) as u32;
+        }
+        let default = match (&self.boot_floor, self.setting(FLOOR_KEY)) {
+            (Some(level), _) => level.clone(),
+            (None, Some(SettingValue::Str(level))) => level,
+            _ => "info".to_string(),
+        };
+        let overrides = match self.setting(FLOOR_OVERRIDES_KEY) {
+            Some(SettingValue::Str(overrides)) => overrides,
+            _ => String::new(),
+        };
+        let (floors, rejected) = Floors::parse(&default, &overrides);
+        for entry in rejected {
+            log(&format!("logd: Ignoring log floor '{}'; expected <service>=debug|info|warn|error.", entry));
+        }
+        self.floors = floors;
+    }
+
+    /// Queues a line for `service`'s file, or counts it as dropped if too much is waiting.
+    fn queue(&mut self, service: &str, line: String) {
+        let pending = self.pending.entry(service.to_string()).or_default();
+        if pending.bytes + line.len() > MAX_PENDING_BYTES {
+            pending.dropped += 1;
+            return;
+        }
+        pending.bytes += line.len();
+        pending.lines.push(line);
+    }
+
+    fn handle_records(&mut self) {
+        while let Ok(Some(data)) = self.records_chan.recv_non_blocking() {
+            let Some((record, message)) = LogRecord::from_bytes(&data) else {
+                log("logd: Dropping a malformed log record.");
+                continue;
+            };
+            if record.dropped > 0 {
+                let note = format!("The kernel dropped {} messages while logd fell behind.", record.dropped);
+                self.queue(OWN_SERVICE, format_line(record.ticks, LOG_SEVERITY_WARN, &note));
+            }
+            let service = log_ipc::sanitize_service_name(record.name(), record.task);
+            if !self.floors.passes(&service, u64::from(record.severity)) {
+                continue;
+            }
+            let line = format_line(record.ticks, u64::from(record.severity), &String::from_utf8_lossy(message));
+            self.queue(&service, line);
+        }
+    }
+
+    /// Writes out every service's waiting lines.
+    fn flush(&mut self) {
+        let now = now();
+        let pending = core::mem::take(&mut self.pending);
+        for (service, mut pending) in pending {
+            if pending.dropped > 0 {
+                let note = format!("logd dropped {} messages from this service while writing fell behind.", pending.dropped);
+                pending.lines.push(format_line(now, LOG_SEVERITY_WARN, &note));
+            }
+            if let Err(e) = self.write_lines(&service, &pending.lines) {
+                log(&format!("logd: Lost {} lines for {}: {}.", pending.lines.len(), service, e));
+            }
+        }
+        self.last_flush = now;
+    }
+
+    fn write_lines(&mut self, service: &str, lines: &[String]) -> Result<(), String> {
+        let path = log_ipc::log_file_path(service);
+        let size = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: path.clone() }) {
+            Ok(VfsResponse::Metadata(metadata)) => metadata.size,
+            Ok(VfsResponse::Error { code: 2, .. }) => 0, // ENOENT: the first line
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let mut offset = size;
+        for segment in rotate::segments(size, lines, self.max_file_bytes) {
+            if segment.rotate_first {
+                self.rotate(service);
+                offset = 0;
+            }
+            offset += self.append(&path, offset, segment.data)?;
+        }
+        Ok(())
+    }
+
+    /// Shifts `service`'s files one place older, making room for a new live file.
+    fn rotate(&mut self, service: &str) {
+        let (delete, moves) = rotate::plan(service, self.keep_files);
+        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Delete { path: delete.clone() }) {
+            Ok(VfsResponse::DeleteSuccess) | Ok(VfsResponse::Error { code: 2, .. }) => {},
+            _ => log(&format!("logd: Failed to delete {} while rotating.", delete)),
+        }
+        for (source, destination) in moves {
+            match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Move { source: source.clone(), destination }) {
+                Ok(VfsResponse::MoveSuccess) | Ok(VfsResponse::Error { code: 2, .. }) => {},
+                _ => log(&format!("logd: Failed to move {} while rotating.", source)),
+            }
+        }
+    }
+
+    /// Appends `data` to `path` at `offset`, creating the file if needed. Returns the bytes written.
+    fn append(&mut self, path: &str, offset: u64, data: Vec<u8>) -> Result<u64, String> {
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let mut streams = VfsStreams::new(&mut self.vfs_chan);
+        let written = (|| {
+            let writer = streams.open_write(fd, offset)?;
+            for chunk in data.chunks(STREAM_CHUNK_SIZE) {
+                streams.write(writer, chunk.to_vec())?;
+            }
+            streams.finish(writer)
+        })();
+        let _ = streams.request(&VfsRequest::Close { fd });
+        written
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("logd: Entering main event loop.");
+        loop {
+            self.handle_records();
+
+            let now = now();
+            let full = self.pending.values().any(|pending| pending.bytes >= FLUSH_BYTES);
+            if !self.pending.is_empty() && (full || now.saturating_sub(self.last_flush) >= FLUSH_TICKS) {
+                self.flush();
+            }
+            if now.saturating_sub(self.last_settings) >= SETTINGS_POLL_TICKS {
+                self.load_settings();
+                self.last_settings = now;
+            }
+
+            if let Some(request) = self.lifecycle.shutdown_requested() {
+                self.handle_records();
+                self.flush();
+                self.lifecycle.stopped(request);
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback. The
+    // kernel's log records come on RECORDS_CHANNEL.
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let mut service = LogdService::new(
+        channel("vfs", channels::VFS),
+        channel("settings", channels::SETTINGS),
+        assigned.get(LIFECYCLE_CHANNEL).copied(),
+    );
+    service.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("logd V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
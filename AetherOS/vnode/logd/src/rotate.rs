@@ -0,0 +1,58 @@
+// vnode/logd/src/rotate.rs
+
+//! Deciding where lines go when a log file fills up.
+//!
+//! Lines are never split across files. A file is rotated before the line
+//! that would take it past the limit, unless the file is still empty: a
+//! single line longer than the limit gets a file of its own.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ipc::log_ipc::{log_file_path, rotated_path};
+
+/// Lines to append to the live file, after rotating it if `rotate_first`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub rotate_first: bool,
+    pub data: Vec<u8>,
+}
+
+/// Splits `lines` into segments for a live file already `size` bytes long,
+/// so that no file grows past `max_bytes` except by a line longer than that.
+pub fn segments(mut size: u64, lines: &[String], max_bytes: u64) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for line in lines {
+        let len = line.len() as u64;
+        let rotate = size > 0 && size + len > max_bytes;
+        if rotate || segments.is_empty() {
+            segments.push(Segment { rotate_first: rotate, data: Vec::new() });
+        }
+        if rotate {
+            size = 0;
+        }
+        if let Some(segment) = segments.last_mut() {
+            segment.data.extend_from_slice(line.as_bytes());
+        }
+        size += len;
+    }
+    segments
+}
+
+/// What rotating `service`'s log takes, in order: the file to delete, then
+/// the moves that shift every file one place older. With `keep` at 0 the
+/// live file is deleted and nothing moves. Files that don't exist yet are
+/// in the plan anyway; the VFS's "not found" for them is expected.
+pub fn plan(service: &str, keep: u32) -> (String, Vec<(String, String)>) {
+    if keep == 0 {
+        return (log_file_path(service), Vec::new());
+    }
+    let mut moves = Vec::new();
+    for n in (1..keep).rev() {
+        moves.push((rotated_path(service, n), rotated_path(service, n + 1)));
+    }
+    moves.push((log_file_path(service), rotated_path(service, 1)));
+    (rotated_path(service, keep), moves)
+}
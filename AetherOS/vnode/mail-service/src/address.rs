@@ -0,0 +1,71 @@
+// vnode/mail-service/src/address.rs
+
+//! Recipient addresses, and the system alias table from the `mail.aliases`
+//! setting.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// The domain that marks an address as local to this node.
+pub const LOCAL_DOMAIN: &str = "local";
+
+/// Aliases may point at other aliases; lookups give up after this many hops.
+const MAX_ALIAS_HOPS: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Recipient {
+    /// A name to resolve on this node, lowercased.
+    Local(String),
+    /// An address to deliver over the network.
+    Remote(String),
+}
+
+/// Classifies an address. A bare name and `name@local` are local; anything
+/// else with a domain is remote.
+pub fn parse(address: &str) -> Recipient {
+    let address = address.trim();
+    match address.rsplit_once('@') {
+        None => Recipient::Local(address.to_ascii_lowercase()),
+        Some((name, domain)) if domain.eq_ignore_ascii_case(LOCAL_DOMAIN) => Recipient::Local(name.to_ascii_lowercase()),
+        Some(_) => Recipient::Remote(address.to_string()),
+    }
+}
+
+/// The alias table, parsed from comma-separated `<alias>=<name>` entries.
+#[derive(Default)]
+pub struct Aliases {
+    targets: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    /// Parses the setting. Returns the table and the entries that were rejected.
+    pub fn parse(value: &str) -> (Self, Vec<String>) {
+        let mut aliases = Self::default();
+        let mut invalid = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=').map(|(alias, target)| (alias.trim(), target.trim())) {
+                Some((alias, target)) if !alias.is_empty() && !target.is_empty() => {
+                    aliases.targets.insert(alias.to_ascii_lowercase(), target.to_ascii_lowercase());
+                },
+                _ => invalid.push(entry.to_string()),
+            }
+        }
+        (aliases, invalid)
+    }
+
+    /// Follows aliases from `name` to a name that isn't one. `None` if the
+    /// chain loops or is too long.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let mut current = name;
+        for _ in 0..=MAX_ALIAS_HOPS {
+            match self.targets.get(current) {
+                Some(target) => current = target,
+                None => return Some(current.to_string()),
+            }
+        }
+        None
+    }
+}
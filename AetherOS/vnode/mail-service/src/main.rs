@@ -5,6 +5,10 @@
 
 extern crate alloc;
 
+mod address;
+mod mime;
+mod search;
+
 use core::panic::PanicInfo;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
@@ -13,10 +17,29 @@ use alloc::string::{String, ToString};
 
 use common::ipc::vnode::VNodeChannel;
 use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ipc::mail_ipc::{MailRequest, MailResponse};
+use common::ipc::mail_ipc::{MailRequest, MailResponse, MailReceived, SearchHit, MAX_SEARCH_RESULTS};
 use common::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata};
-use common::ipc::socket_ipc::{SocketRequest, SocketResponse, SocketFd};
-use common::ipc::dns_ipc::{DnsRequest, DnsResponse};
+use common::ipc::vfs_lock;
+use common::ipc::vfs_tx::VfsTx;
+use common::ipc::socket_client::SocketClient;
+use common::ipc::socket_ipc::SocketOption;
+use common::ipc::session_ipc::{self, AidBytes, SessionRequest, SessionResponse};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse};
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::startup::{self, SELF_CHANNEL};
+use common::time;
+
+use address::{Aliases, Recipient, LOCAL_DOMAIN};
+use search::{Query, TermIndex, INDEX_FILE};
+
+const SMTP_PORT: u16 = 25;
+/// Per-address connect timeout for outgoing mail.
+const SMTP_CONNECT_TIMEOUT_MS: u32 = 5000;
+/// Largest message file read when an index is rebuilt; larger ones aren't indexed.
+const MAX_MESSAGE_LEN: u32 = 256 * 1024;
+/// Largest mailbox `index` or `terms` file read.
+const MAX_INDEX_LEN: u32 = 4 * 1024 * 1024;
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -36,6 +59,7 @@ fn log(msg: &str) {
 struct Mailbox {
     messages: BTreeMap<u32, String>, // message_id -> message_content
     next_message_id: u32,
+    terms: Option<TermIndex>, // Loaded when the mailbox is first searched or changed
 }
 
 impl Mailbox {
@@ -43,6 +67,7 @@ impl Mailbox {
         Self {
             messages: BTreeMap::new(),
             next_message_id: 1,
+            terms: None,
         }
     }
 
@@ -52,64 +77,410 @@ impl Mailbox {
         self.next_message_id += 1;
         id
     }
+
+    fn remove_message(&mut self, id: u32) {
+        self.messages.remove(&id);
+    }
+
+    /// Contents of the mailbox's `index` file: one message id per line.
+    fn index(&self) -> String {
+        self.messages.keys().map(|id| alloc::format!("{}\n", id)).collect()
+    }
 }
 
 struct MailService {
     client_chan: VNodeChannel, // Channel for AetherTerminal or other client V-Nodes
     vfs_chan: VNodeChannel, // Channel to svc://vfs for local mail storage
     socket_chan: VNodeChannel, // Channel to svc://socket-api for network mail protocols
-    dns_chan: VNodeChannel, // Channel to svc://dns-resolver for mail server lookups
+    session_chan: VNodeChannel, // Channel to svc://session to resolve local names
+    settings_chan: VNodeChannel, // Channel to svc://settings for the alias table
+    event_bus_chan: VNodeChannel, // Channel to svc://event-bus for "mail.received"
+    lifecycle: Lifecycle, // Shutdown requests from init
 
-    // Conceptual local mail storage for the user
+    // Conceptual local mail storage, per identity
     // In a real system, this would be backed by VFS operations directly.
-    user_mailboxes: BTreeMap<String, Mailbox>, // mailbox_name -> Mailbox
+    user_mailboxes: BTreeMap<AidBytes, BTreeMap<String, Mailbox>>, // aid -> mailbox_name -> Mailbox
+    messages_composed: u64, // Makes Message-Id headers unique within a second
+}
+
+/// One stored copy of a message, for `persist_messages`.
+struct StoredCopy {
+    aid: AidBytes,
+    mailbox: &'static str,
+    message_id: u32,
+    index: String,
+    terms: Vec<u8>, // The mailbox's updated search index
 }
 
 impl MailService {
-    fn new(client_chan_id: u32, vfs_chan_id: u32, socket_chan_id: u32, dns_chan_id: u32) -> Self {
+    fn new(client_chan_id: u32, vfs_chan_id: u32, socket_chan_id: u32, session_chan_id: u32, settings_chan_id: u32, event_bus_chan_id: u32, lifecycle_chan_id: Option<u32>) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
         let vfs_chan = VNodeChannel::new(vfs_chan_id);
         let socket_chan = VNodeChannel::new(socket_chan_id);
-        let dns_chan = VNodeChannel::new(dns_chan_id);
+        let session_chan = VNodeChannel::new(session_chan_id);
+        let settings_chan = VNodeChannel::new(settings_chan_id);
+        let event_bus_chan = VNodeChannel::new(event_bus_chan_id);
 
         log("Mail Service: Initializing...");
 
-        // Conceptual: Initialize user's default mailboxes (e.g., Inbox, Sent)
-        let mut user_mailboxes = BTreeMap::new();
-        user_mailboxes.insert("Inbox".to_string(), Mailbox::new());
-        user_mailboxes.insert("Sent".to_string(), Mailbox::new());
-
         Self {
             client_chan,
             vfs_chan,
             socket_chan,
-            dns_chan,
-            user_mailboxes,
+            session_chan,
+            settings_chan,
+            event_bus_chan,
+            lifecycle: Lifecycle::new(lifecycle_chan_id),
+            user_mailboxes: BTreeMap::new(),
+            messages_composed: 0,
+        }
+    }
+
+    /// Returns the mailboxes of an identity, creating the default ones (Inbox, Sent) on first use.
+    fn mailboxes_for(&mut self, aid: AidBytes) -> &mut BTreeMap<String, Mailbox> {
+        self.user_mailboxes.entry(aid).or_insert_with(|| {
+            let mut mailboxes = BTreeMap::new();
+            mailboxes.insert("Inbox".to_string(), Mailbox::new());
+            mailboxes.insert("Sent".to_string(), Mailbox::new());
+            mailboxes
+        })
+    }
+
+    /// Writes each copy of a message to /home/<aid>/mail/<mailbox>/<id>.msg
+    /// together with the mailbox's updated `index` and search index, in one VFS
+    /// transaction. Once the commit returns all of them are on disk; if it
+    /// fails, none changed, so an index never lists a message that isn't
+    /// there and a local delivery never leaves a Sent copy without the recipient's. The indexes are
+    /// locked exclusively meanwhile, since mail clients rewrite them too.
+    fn persist_messages(&mut self, content: &str, copies: &[&StoredCopy]) -> Result<(), String> {
+        let dirs: Vec<String> = copies.iter().map(|copy| mailbox_dir(&copy.aid, copy.mailbox)).collect();
+        let indexes: Vec<String> = dirs.iter().map(|dir| alloc::format!("{}/index", dir)).collect();
+        let index_paths: Vec<&str> = indexes.iter().map(String::as_str).collect();
+        vfs_lock::with_locks(&mut self.vfs_chan, &index_paths, true, |chan| {
+            let mut tx = VfsTx::begin(chan)?;
+            for ((copy, dir), index) in copies.iter().zip(&dirs).zip(&indexes) {
+                tx.write_file(&alloc::format!("{}/{}.msg", dir, copy.message_id), content.as_bytes().to_vec())?;
+                tx.write_file(index, copy.index.as_bytes().to_vec())?;
+                tx.write_file(&alloc::format!("{}/{}", dir, INDEX_FILE), copy.terms.clone())?;
+            }
+            tx.commit()
+        })
+    }
+
+    /// Adds a message to one of an identity's mailboxes in memory. Returns its
+    /// id and the mailbox's new index and search index.
+    fn add_to_mailbox(&mut self, aid: AidBytes, mailbox: &'static str, content: String) -> StoredCopy {
+        self.mailboxes_for(aid).entry(mailbox.to_string()).or_insert_with(Mailbox::new);
+        self.terms_for(aid, mailbox);
+        let mb = self.mailboxes_for(aid).get_mut(mailbox).unwrap();
+        let message_id = mb.add_message(content.clone());
+        let index = mb.index();
+        let terms = mb.terms.get_or_insert_with(TermIndex::default);
+        terms.insert(message_id, &content);
+        StoredCopy { aid, mailbox, message_id, index, terms: terms.encode() }
+    }
+
+    fn remove_from_mailbox(&mut self, copy: &StoredCopy) {
+        if let Some(mb) = self.mailboxes_for(copy.aid).get_mut(copy.mailbox) {
+            mb.remove_message(copy.message_id);
+            if let Some(terms) = mb.terms.as_mut() {
+                terms.remove(copy.message_id);
+            }
+        }
+    }
+
+    /// Reads a file of at most `max_len` bytes from VFS.
+    fn read_file(&mut self, path: &str, max_len: u32) -> Result<Vec<u8>, String> {
+        let fd: Fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let result = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: max_len, offset: 0 }) {
+            Ok(VfsResponse::Data(data)) => Ok(data),
+            Ok(VfsResponse::Error { message, .. }) => Err(message),
+            _ => Err("Unexpected response from VFS".to_string()),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// The message ids a mailbox's `index` file lists; none if it has no index yet.
+    fn stored_ids(&mut self, dir: &str) -> Vec<u32> {
+        match self.read_file(&alloc::format!("{}/index", dir), MAX_INDEX_LEN) {
+            Ok(data) => String::from_utf8_lossy(&data).lines().filter_map(|line| line.trim().parse().ok()).collect(),
+            Err(_) => Vec::new(),
         }
     }
 
-    fn handle_request(&mut self, request: MailRequest) -> MailResponse {
+    /// The search index of a mailbox from its `terms` file, or rebuilt from
+    /// its messages if that is missing, damaged, from another version or
+    /// doesn't list the same messages as `index`.
+    fn load_terms(&mut self, aid: &AidBytes, mailbox: &str) -> TermIndex {
+        let dir = mailbox_dir(aid, mailbox);
+        let ids = self.stored_ids(&dir);
+        let problem = match self.read_file(&alloc::format!("{}/{}", dir, INDEX_FILE), MAX_INDEX_LEN) {
+            Ok(data) => match TermIndex::decode(&data) {
+                Ok(terms) if terms.ids().eq(ids.iter().copied()) => return terms,
+                Ok(_) => "it lists other messages than the mailbox".to_string(),
+                Err(e) => e.to_string(),
+            },
+            Err(_) if ids.is_empty() => return TermIndex::default(),
+            Err(e) => alloc::format!("it can't be read: {}", e),
+        };
+        log(&alloc::format!("Mail: Rebuilding the search index of {} because {}.", dir, problem));
+        self.rebuild_terms(&dir, &ids)
+    }
+
+    /// Indexes a mailbox's messages one at a time from VFS and writes the
+    /// new `terms` file. Messages that can't be read are left out.
+    fn rebuild_terms(&mut self, dir: &str, ids: &[u32]) -> TermIndex {
+        let mut terms = TermIndex::default();
+        for &id in ids {
+            match self.read_file(&alloc::format!("{}/{}.msg", dir, id), MAX_MESSAGE_LEN) {
+                Ok(data) => terms.insert(id, &String::from_utf8_lossy(&data)),
+                Err(e) => log(&alloc::format!("Mail: Not indexing {}/{}.msg: {}.", dir, id, e)),
+            }
+        }
+        let path = alloc::format!("{}/{}", dir, INDEX_FILE);
+        let encoded = terms.encode();
+        let written = vfs_lock::with_lock(&mut self.vfs_chan, &alloc::format!("{}/index", dir), true, |chan| {
+            let mut tx = VfsTx::begin(chan)?;
+            tx.write_file(&path, encoded)?;
+            tx.commit()
+        });
+        if let Err(e) = written {
+            log(&alloc::format!("Mail: Failed to write the rebuilt index {}: {}.", path, e));
+        }
+        terms
+    }
+
+    /// The search index of one of an identity's mailboxes, loaded on first use.
+    /// `None` if there is no such mailbox.
+    fn terms_for(&mut self, aid: AidBytes, mailbox: &str) -> Option<&mut TermIndex> {
+        if self.mailboxes_for(aid).get(mailbox)?.terms.is_none() {
+            let terms = self.load_terms(&aid, mailbox);
+            self.mailboxes_for(aid).get_mut(mailbox)?.terms = Some(terms);
+        }
+        self.mailboxes_for(aid).get_mut(mailbox)?.terms.as_mut()
+    }
+
+    /// Runs a search over an identity's mailboxes, newest first.
+    fn search(&mut self, aid: AidBytes, query: &str, offset: u32, limit: u32) -> MailResponse {
+        let query = match Query::parse(query) {
+            Ok(query) => query,
+            Err(e) => return MailResponse::BadQuery { position: e.position as u32, message: e.message },
+        };
+        let names: Vec<String> = self.mailboxes_for(aid).keys()
+            .filter(|name| query.mailbox.as_ref().map_or(true, |wanted| wanted.eq_ignore_ascii_case(name)))
+            .cloned()
+            .collect();
+        let mut hits = Vec::new();
+        for name in names {
+            let Some(terms) = self.terms_for(aid, &name) else {
+                continue;
+            };
+            hits.extend(terms.search(&query).map(|(message_id, message)| SearchHit {
+                mailbox: name.clone(),
+                message_id,
+                date: message.date,
+                from: message.from.clone(),
+                subject: message.subject.clone(),
+                snippet: message.snippet.clone(),
+            }));
+        }
+        hits.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.mailbox.cmp(&b.mailbox)).then_with(|| b.message_id.cmp(&a.message_id)));
+        let limit = if limit == 0 { MAX_SEARCH_RESULTS } else { limit.min(MAX_SEARCH_RESULTS) };
+        let total = hits.len() as u32;
+        let hits = hits.into_iter().skip(offset as usize).take(limit as usize).collect();
+        MailResponse::SearchResults { total, hits }
+    }
+
+    /// Rebuilds the search index of one mailbox or all of them.
+    fn rebuild_index(&mut self, aid: AidBytes, mailbox: Option<String>) -> MailResponse {
+        let names: Vec<String> = match mailbox {
+            Some(name) if !self.mailboxes_for(aid).contains_key(&name) => {
+                return MailResponse::Error(alloc::format!("Mailbox {} not found.", name));
+            }
+            Some(name) => alloc::vec![name],
+            None => self.mailboxes_for(aid).keys().cloned().collect(),
+        };
+        let mut messages = 0;
+        for name in &names {
+            let dir = mailbox_dir(&aid, name);
+            let ids = self.stored_ids(&dir);
+            let terms = self.rebuild_terms(&dir, &ids);
+            messages += terms.len();
+            if let Some(mb) = self.mailboxes_for(aid).get_mut(name) {
+                mb.terms = Some(terms);
+            }
+        }
+        MailResponse::Success(alloc::format!("Rebuilt the search index of {} mailboxes ({} messages).", names.len(), messages))
+    }
+
+    /// Deletes a message, and updates the mailbox's `index` and search index
+    /// in the same VFS transaction.
+    fn delete_message(&mut self, aid: AidBytes, mailbox: &str, message_id: u32) -> MailResponse {
+        if self.terms_for(aid, mailbox).is_none() {
+            return MailResponse::Error(alloc::format!("Mailbox {} not found.", mailbox));
+        }
+        let mb = self.mailboxes_for(aid).get_mut(mailbox).unwrap();
+        let Some(content) = mb.messages.remove(&message_id) else {
+            return MailResponse::Error(alloc::format!("Message {} not found in mailbox {}.", message_id, mailbox));
+        };
+        let index = mb.index();
+        let terms = mb.terms.as_mut().unwrap();
+        terms.remove(message_id);
+        let encoded = terms.encode();
+
+        let dir = mailbox_dir(&aid, mailbox);
+        let index_path = alloc::format!("{}/index", dir);
+        let deleted = vfs_lock::with_lock(&mut self.vfs_chan, &index_path, true, |chan| {
+            let mut tx = VfsTx::begin(chan)?;
+            tx.delete(&alloc::format!("{}/{}.msg", dir, message_id))?;
+            tx.write_file(&index_path, index.into_bytes())?;
+            tx.write_file(&alloc::format!("{}/{}", dir, INDEX_FILE), encoded)?;
+            tx.commit()
+        });
+        if let Err(e) = deleted {
+            // Nothing changed on disk, so put it back in memory too.
+            let mb = self.mailboxes_for(aid).get_mut(mailbox).unwrap();
+            if let Some(terms) = mb.terms.as_mut() {
+                terms.insert(message_id, &content);
+            }
+            mb.messages.insert(message_id, content);
+            return MailResponse::Error(alloc::format!("Failed to delete message {}: {}", message_id, e));
+        }
+        MailResponse::Success(alloc::format!("Message {} deleted from {}.", message_id, mailbox))
+    }
+
+    /// Formats a message the way it is stored, whether it was sent from this
+    /// node or received from another. Dates are in UTC; readers apply the
+    /// display offset.
+    fn compose(&mut self, sender: &AidBytes, recipient: &str, subject: &str, body: &str) -> String {
+        let now = time::now_secs();
+        self.messages_composed += 1;
+        let sender_hex = session_ipc::aid_to_hex(sender);
+        alloc::format!(
+            "Message-Id: <{}.{}.{}@{}>\nDate: {}\nFrom: {}@{}\nTo: {}\nSubject: {}\n\n{}",
+            now, self.messages_composed, &sender_hex[..16], LOCAL_DOMAIN,
+            time::format_rfc2822(now, 0), sender_hex, LOCAL_DOMAIN, recipient, subject, body,
+        )
+    }
+
+    /// The alias table from the `mail.aliases` setting. Read for every local
+    /// delivery, so changes apply to the next message.
+    fn load_aliases(&mut self) -> Aliases {
+        let value = match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: "mail.aliases".to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Str(value), .. }) => value,
+            _ => return Aliases::default(),
+        };
+        let (aliases, invalid) = Aliases::parse(&value);
+        for entry in invalid {
+            log(&alloc::format!("Mail: Ignoring invalid alias entry '{}'.", entry));
+        }
+        aliases
+    }
+
+    /// Resolves a local name, after aliases, to an identity through the session
+    /// service's name directory.
+    fn resolve_local(&mut self, name: &str) -> Result<AidBytes, MailResponse> {
+        let unknown = || MailResponse::UnknownRecipient(alloc::format!("{}@{}", name, LOCAL_DOMAIN));
+        let target = match self.load_aliases().resolve(name) {
+            Some(target) => target,
+            None => {
+                log(&alloc::format!("Mail: Alias '{}' loops or is nested too deeply.", name));
+                return Err(unknown());
+            }
+        };
+        match self.session_chan.send_and_recv::<SessionRequest, SessionResponse>(&SessionRequest::Resolve { name: target }) {
+            Ok(SessionResponse::Resolved { aid, .. }) => Ok(aid),
+            Ok(SessionResponse::UnknownName(_)) => Err(unknown()),
+            _ => Err(MailResponse::Error("Could not reach the session service to resolve the recipient.".to_string())),
+        }
+    }
+
+    /// Publishes "mail.received" for a message that landed in a local Inbox.
+    fn publish_received(&mut self, copy: &StoredCopy) {
+        let received = MailReceived { recipient: copy.aid, mailbox: copy.mailbox.to_string(), message_id: copy.message_id };
+        let payload = match postcard::to_allocvec(&received) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: "mail.received".to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log("Mail: Failed to publish the mail.received event.");
+        }
+    }
+
+    /// Delivers a message into a local identity's Inbox, with the sender's
+    /// Sent copy, without touching the network.
+    fn deliver_local(&mut self, sender: AidBytes, name: &str, subject: &str, body: &str) -> MailResponse {
+        let recipient = match self.resolve_local(name) {
+            Ok(aid) => aid,
+            Err(response) => {
+                log(&alloc::format!("Mail: No local recipient '{}'.", name));
+                return response;
+            }
+        };
+        let message = self.compose(&sender, &alloc::format!("{}@{}", name, LOCAL_DOMAIN), subject, body);
+        let inbox = self.add_to_mailbox(recipient, "Inbox", message.clone());
+        let sent = self.add_to_mailbox(sender, "Sent", message.clone());
+        if let Err(e) = self.persist_messages(&message, &[&inbox, &sent]) {
+            // Keep memory and disk consistent: the message wasn't delivered.
+            self.remove_from_mailbox(&inbox);
+            self.remove_from_mailbox(&sent);
+            log(&alloc::format!("Mail: Local delivery to '{}' failed: {}.", name, e));
+            return MailResponse::Error(alloc::format!("Failed to deliver mail: {}", e));
+        }
+        log(&alloc::format!("Mail: Delivered message {} to the Inbox of '{}'.", inbox.message_id, name));
+        self.publish_received(&inbox);
+        MailResponse::Success(alloc::format!("Mail to {}@{} delivered.", name, LOCAL_DOMAIN))
+    }
+
+    fn handle_request(&mut self, caller: Option<AidBytes>, request: MailRequest) -> MailResponse {
+        // Every mail operation is scoped to the caller's identity.
+        let aid = match caller {
+            Some(aid) => aid,
+            None => {
+                log("Mail: Rejecting request from unauthenticated task.");
+                return MailResponse::Unauthenticated;
+            }
+        };
         match request {
             MailRequest::SendMail { recipient, subject, body } => {
                 log(&alloc::format!("Mail: Sending mail to {}: Subject: {}.", recipient, subject));
+                let recipient = match address::parse(&recipient) {
+                    Recipient::Local(name) => return self.deliver_local(aid, &name, &subject, &body),
+                    Recipient::Remote(address) => address,
+                };
                 
-                // Conceptual: Resolve recipient's mail server via DNS
-                // let mail_server_hostname = "smtp.example.com"; // Derived from recipient
-                // match self.dns_chan.send_and_recv::<DnsRequest, DnsResponse>(&DnsRequest::ResolveHostname { hostname: mail_server_hostname.to_string() }) {
-                //     Ok(DnsResponse::ResolvedHostname { ip_address, .. }) => {
-                //         log!("Resolved mail server to: {:?}", ip_address);
-                //         // Conceptual: Open socket connection and send mail via SMTP commands
-                //         // For now, just simulate success.
-                //         MailResponse::Success(alloc::format!("Mail to {} sent successfully (conceptual).", recipient))
-                //     },
-                //     _ => MailResponse::Error(alloc::format!("Failed to resolve mail server for {}.", recipient)),
-                // }
+                // Without MX lookups, the recipient's domain is taken to be its mail server.
+                let domain = recipient.rsplit_once('@').map_or(recipient.as_str(), |(_, domain)| domain);
+                let mut sockets = SocketClient::new(&mut self.socket_chan);
+                match sockets.tcp_connect_host(domain, SMTP_PORT, SMTP_CONNECT_TIMEOUT_MS) {
+                    Ok(conn) => {
+                        log(&alloc::format!("Mail: Connected to {} at {}.{}.{}.{}.", domain, conn.addr[0], conn.addr[1], conn.addr[2], conn.addr[3]));
+                        // SMTP is lock-step: each command waits for the reply to the last, so
+                        // holding a short command back for coalescing only adds its delay.
+                        if let Err((errno, message)) = sockets.set_option(conn.fd, SocketOption::NoDelay(true)) {
+                            log(&alloc::format!("Mail: Could not set NoDelay on the SMTP connection: {} ({}).", message, errno));
+                        }
+                        // Conceptual: the SMTP conversation itself. For now, just simulate success.
+                        sockets.close(conn.fd);
+                    },
+                    Err(e) => {
+                        log(&alloc::format!("Mail: Could not reach the mail server for {}: {}.", recipient, e));
+                        return MailResponse::Error(alloc::format!("Failed to reach the mail server for {}: {}", recipient, e));
+                    },
+                }
 
                 // Simulate storing a copy in 'Sent' mailbox
-                let full_message = alloc::format!("To: {}\nSubject: {}\n\n{}", recipient, subject, body);
-                if let Some(mailbox) = self.user_mailboxes.get_mut("Sent") {
-                    mailbox.add_message(full_message);
-                    log("Mail: Stored copy in 'Sent' mailbox.");
+                let full_message = self.compose(&aid, &recipient, &subject, &body);
+                let sent = self.add_to_mailbox(aid, "Sent", full_message.clone());
+                match self.persist_messages(&full_message, &[&sent]) {
+                    Ok(()) => log("Mail: Stored copy in 'Sent' mailbox."),
+                    Err(e) => log(&alloc::format!("Mail: Failed to write 'Sent' copy to disk: {}.", e)),
                 }
 
                 MailResponse::Success(alloc::format!("Mail to {} sent successfully (conceptual).", recipient))
@@ -117,13 +488,13 @@ impl MailService {
             MailRequest::ListMailboxes => {
                 log("Mail: Listing mailboxes.");
                 // Conceptual: Interact with VFS to list directories under /home/<AID>/mail/
-                let mailboxes: Vec<String> = self.user_mailboxes.keys().cloned().collect();
+                let mailboxes: Vec<String> = self.mailboxes_for(aid).keys().cloned().collect();
                 MailResponse::Mailboxes(mailboxes)
             },
             MailRequest::ReadMessage { mailbox, message_id } => {
                 log(&alloc::format!("Mail: Reading message {} from mailbox {}.", message_id, mailbox));
                 // Conceptual: Interact with VFS to read file content from /home/<AID>/mail/<mailbox>/<message_id>.msg
-                if let Some(mb) = self.user_mailboxes.get(&mailbox) {
+                if let Some(mb) = self.mailboxes_for(aid).get(&mailbox) {
                     if let Some(message) = mb.messages.get(&message_id) {
                         MailResponse::Message(message.clone())
                     } else {
@@ -133,6 +504,15 @@ impl MailService {
                     MailResponse::Error(alloc::format!("Mailbox {} not found.", mailbox))
                 }
             },
+            MailRequest::Search { query, offset, limit } => {
+                log(&alloc::format!("Mail: Searching for '{}'.", query));
+                self.search(aid, &query, offset, limit)
+            },
+            MailRequest::RebuildIndex { mailbox } => self.rebuild_index(aid, mailbox),
+            MailRequest::DeleteMessage { mailbox, message_id } => {
+                log(&alloc::format!("Mail: Deleting message {} from mailbox {}.", message_id, mailbox));
+                self.delete_message(aid, &mailbox, message_id)
+            },
         }
     }
 
@@ -143,7 +523,7 @@ impl MailService {
             if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
                 if let Ok(request) = postcard::from_bytes::<MailRequest>(&req_data) {
                     log(&alloc::format!("Mail Service: Received MailRequest: {:?}.", request));
-                    let response = self.handle_request(request);
+                    let response = self.handle_request(session_ipc::caller_identity(), request);
                     self.client_chan.send(&response).unwrap_or_else(|_| log("Mail Service: Failed to send response to client."));
                 } else {
                     log("Mail Service: Failed to deserialize MailRequest.");
@@ -153,20 +533,43 @@ impl MailService {
             // Conceptual: Periodically check for new incoming mail (via socket-api, DNS)
             // This would involve polling a mail server (e.g., POP3, IMAP).
 
+            // Messages are stored before their request is answered, so between requests none is half-written.
+            if let Some(request) = self.lifecycle.shutdown_requested() {
+                log("Mail Service: Stopping for shutdown.");
+                self.lifecycle.stopped(request);
+            }
+
             // Yield to other V-Nodes to prevent busy-waiting
             unsafe { syscall3(SYS_TIME, 0, 0, 0); } // This will cause a context switch
         }
     }
 }
 
+/// Where a mailbox's files are: /home/<aid>/mail/<mailbox>.
+fn mailbox_dir(aid: &AidBytes, mailbox: &str) -> String {
+    alloc::format!("{}/mail/{}", session_ipc::home_dir(aid), mailbox)
+}
+
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel IDs:
+    // Channels come from init; the well-known IDs are the fallback:
     // 10 for Mail Service client requests
     // 7 for VFS Service
     // 4 for Socket API Service
-    // 5 for DNS Resolver Service
-    let mut mail_service = MailService::new(10, 7, 4, 5);
+    // 15 for Session Service
+    // 14 for Settings Service
+    // 13 for Event Bus Service
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut mail_service = MailService::new(
+        channel(SELF_CHANNEL, 10),
+        channel("vfs", 7),
+        channel("socket-api", 4),
+        channel("session", 15),
+        channel("settings", 14),
+        channel("event-bus", 13),
+        channels.get(LIFECYCLE_CHANNEL).copied(),
+    );
     mail_service.run_loop();
 }
 
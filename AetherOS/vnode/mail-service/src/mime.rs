@@ -0,0 +1,193 @@
+// vnode/mail-service/src/mime.rs
+
+//! Just enough MIME to index a message: its unfolded headers and the decoded
+//! text of its first text/plain part. A message without a Content-Type is
+//! plain text. Multipart messages are walked depth first, and parts marked as
+//! attachments are skipped. Quoted-printable and base64 parts are decoded.
+//! Text in a charset other than UTF-8 or US-ASCII comes out with replacement
+//! characters where it isn't valid UTF-8.
+
+extern crate alloc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Multipart levels followed before a part is given up on.
+const MAX_DEPTH: usize = 4;
+
+pub struct Message {
+    /// Header names and values in order, continuation lines unfolded.
+    pub headers: Vec<(String, String)>,
+    /// The first text/plain part, decoded; empty if there is none.
+    pub text: String,
+}
+
+impl Message {
+    pub fn parse(raw: &str) -> Self {
+        let (head, body) = split_head(raw);
+        let headers = parse_headers(head);
+        let text = text_of(&headers, body, 0).unwrap_or_default();
+        Message { headers, text }
+    }
+
+    /// The first header called `name`, compared without case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+/// Splits at the first empty line. Without one it is all headers.
+fn split_head(raw: &str) -> (&str, &str) {
+    let mut at = 0;
+    for line in raw.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
+            return (&raw[..at], &raw[at + line.len()..]);
+        }
+        at += line.len();
+    }
+    (raw, "")
+}
+
+fn parse_headers(head: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// The value of parameter `name` in a header like Content-Type, unquoted.
+fn param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|part| {
+        let (key, value) = part.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn text_of(headers: &[(String, String)], body: &str, depth: usize) -> Option<String> {
+    let content_type = header(headers, "Content-Type").unwrap_or("text/plain");
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let attachment = header(headers, "Content-Disposition")
+        .is_some_and(|disposition| disposition.trim_start().to_ascii_lowercase().starts_with("attachment"));
+    if attachment {
+        return None;
+    }
+
+    if media_type.starts_with("multipart/") {
+        if depth >= MAX_DEPTH {
+            return None;
+        }
+        let boundary = param(content_type, "boundary")?;
+        return parts(body, &boundary).find_map(|part| {
+            let (head, body) = split_head(part);
+            text_of(&parse_headers(head), body, depth + 1)
+        });
+    }
+    if media_type != "text/plain" {
+        return None;
+    }
+    let encoding = header(headers, "Content-Transfer-Encoding").unwrap_or("7bit").trim().to_ascii_lowercase();
+    let text = match encoding.as_str() {
+        "quoted-printable" => String::from_utf8_lossy(&decode_quoted_printable(body)).into_owned(),
+        "base64" => String::from_utf8_lossy(&decode_base64(body)).into_owned(),
+        _ => body.to_string(),
+    };
+    Some(text)
+}
+
+/// The parts of a multipart body, between `--boundary` lines, without the
+/// preamble and epilogue.
+fn parts<'a>(body: &'a str, boundary: &str) -> impl Iterator<Item = &'a str> {
+    let delimiter = alloc::format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut at = 0;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed.starts_with(&delimiter) {
+            if let Some(start) = start {
+                parts.push(&body[start..at]);
+            }
+            if trimmed[delimiter.len()..].starts_with("--") {
+                break;
+            }
+            start = Some(at + line.len());
+        }
+        at += line.len();
+    }
+    parts.into_iter()
+}
+
+fn decode_quoted_printable(body: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len());
+    for line in body.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let (content, soft_break) = match content.trim_end().strip_suffix('=') {
+            Some(content) => (content, true),
+            None => (content, false),
+        };
+        let bytes = content.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            match (bytes[i], bytes.get(i + 1).copied().and_then(hex), bytes.get(i + 2).copied().and_then(hex)) {
+                (b'=', Some(high), Some(low)) => {
+                    out.push((high * 16 + low) as u8);
+                    i += 3;
+                }
+                (b, _, _) => {
+                    out.push(b);
+                    i += 1;
+                }
+            }
+        }
+        if !soft_break && line.ends_with('\n') {
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+/// Decodes base64, skipping whitespace and stopping at padding or anything
+/// that isn't in the alphabet.
+fn decode_base64(body: &str) -> Vec<u8> {
+    let value = |b: u8| match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut out = Vec::with_capacity(body.len() / 4 * 3);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+    for b in body.bytes().filter(|b| !b.is_ascii_whitespace()) {
+        let Some(v) = value(b) else {
+            break;
+        };
+        bits = bits << 6 | v as u32;
+        count += 1;
+        if count == 4 {
+            out.extend_from_slice(&[(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]);
+            bits = 0;
+            count = 0;
+        }
+    }
+    match count {
+        2 => out.push((bits >> 4) as u8),
+        3 => out.extend_from_slice(&[(bits >> 10) as u8, (bits >> 2) as u8]),
+        _ => {}
+    }
+    out
+}
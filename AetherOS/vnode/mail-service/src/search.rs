@@ -0,0 +1,453 @@
+// vnode/mail-service/src/search.rs
+
+//! Mail search: the query language and the per-mailbox term index.
+//!
+//! A query is a list of free-text terms and field filters, all of which a
+//! message has to match. Terms are matched against the words of the decoded
+//! text/plain body; `from:` and `subject:` against the words of those
+//! headers; `mailbox:` picks one mailbox; `before:` and `after:` take a
+//! `YYYY-MM-DD` date in UTC, before that day or from that day on. A value
+//! with spaces goes in double quotes, and every word of it has to match.
+//!
+//! Each mailbox keeps its index in a `terms` file next to its `index`. It is
+//! rewritten in the VFS transaction that stores or deletes a message, so it
+//! lists the same messages as `index`. A file with another version, a bad
+//! checksum or a different set of messages is rebuilt from the messages
+//! themselves instead of being used.
+
+extern crate alloc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use common::cid::compute_cid;
+use common::text;
+use common::time::{self, DateTime};
+
+use crate::mime;
+
+/// Name of the index file in a mailbox directory.
+pub const INDEX_FILE: &str = "terms";
+/// Bumped when the index format or tokenizing changes; older files are rebuilt.
+pub const INDEX_VERSION: u32 = 1;
+const INDEX_MAGIC: &[u8; 4] = b"AXMI";
+/// Length of the checksum, a CID, at the end of the file.
+const CID_LEN: usize = 32;
+
+/// Words shorter than this aren't indexed or searched for.
+const MIN_TERM_CHARS: usize = 2;
+/// Longer words are cut to this many characters, in the index and in queries alike.
+const MAX_TERM_CHARS: usize = 32;
+/// Body words read per message; words after them aren't searchable.
+const MAX_BODY_WORDS: usize = 4096;
+/// Characters of body text kept as the snippet.
+const SNIPPET_CHARS: usize = 100;
+
+/// The lowercase words of `text`, split at anything that isn't a letter or digit.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TERM_CHARS)
+        .map(|word| text::truncate_to_chars(word, MAX_TERM_CHARS).to_lowercase())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Words the body has to contain.
+    pub terms: Vec<String>,
+    pub from: Vec<String>,
+    pub subject: Vec<String>,
+    pub mailbox: Option<String>,
+    /// Epoch seconds: dates at or after `after` and before `before` match.
+    pub after: Option<u64>,
+    pub before: Option<u64>,
+}
+
+/// Why a query didn't parse, and the byte offset of the token at fault.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let mut query = Query::default();
+        for (position, token) in split_tokens(input)? {
+            let error = |message: String| QueryError { position, message };
+            let (field, value) = match token.split_once(':') {
+                Some((field, value)) if !field.starts_with('"') => (Some(field.to_ascii_lowercase()), value),
+                _ => (None, token),
+            };
+            let value = unquote(value);
+            if value.is_empty() {
+                return Err(error(format!("'{}' needs a value", token)));
+            }
+            match field.as_deref() {
+                None => query.terms.extend(words(value).map_err(error)?),
+                Some("from") => query.from.extend(words(value).map_err(error)?),
+                Some("subject") => query.subject.extend(words(value).map_err(error)?),
+                Some("mailbox") if query.mailbox.is_some() => return Err(error("only one mailbox: can be given".to_string())),
+                Some("mailbox") => query.mailbox = Some(value.to_string()),
+                Some("before") => query.before = Some(parse_date(value).ok_or_else(|| error(format!("'{}' is not a YYYY-MM-DD date", value)))?),
+                Some("after") => query.after = Some(parse_date(value).ok_or_else(|| error(format!("'{}' is not a YYYY-MM-DD date", value)))?),
+                Some(other) => return Err(error(format!("unknown field '{}'", other))),
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Splits a query at whitespace outside double quotes, with the byte offset of each token.
+fn split_tokens(input: &str) -> Result<Vec<(usize, &str)>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut quote: Option<usize> = None;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => quote = if quote.is_some() { None } else { Some(i) },
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(s) = start.take() {
+                    tokens.push((s, &input[s..i]));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(position) = quote {
+        return Err(QueryError { position, message: "unterminated quote".to_string() });
+    }
+    if let Some(s) = start {
+        tokens.push((s, &input[s..]));
+    }
+    Ok(tokens)
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+}
+
+/// The words of a query value; an error if it has none long enough to search for.
+fn words(value: &str) -> Result<Vec<String>, String> {
+    let words: Vec<String> = tokenize(value).collect();
+    if words.is_empty() {
+        return Err(format!("'{}' has no word of {} or more letters or digits", value, MIN_TERM_CHARS));
+    }
+    Ok(words)
+}
+
+/// Epoch seconds of the start of a `YYYY-MM-DD` day in UTC.
+fn parse_date(value: &str) -> Option<u64> {
+    let mut parts = value.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    DateTime { year: year.parse().ok()?, month: month.parse().ok()?, day: day.parse().ok()?, hour: 0, minute: 0, second: 0 }.to_epoch()
+}
+
+/// What the index keeps of one message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedMessage {
+    pub date: u64,
+    pub from: String,
+    pub subject: String,
+    pub snippet: String,
+    from_terms: BTreeSet<String>,
+    subject_terms: BTreeSet<String>,
+    body_terms: BTreeSet<String>,
+}
+
+impl IndexedMessage {
+    fn new(raw: &str) -> Self {
+        let message = mime::Message::parse(raw);
+        let from = message.header("From").unwrap_or("").to_string();
+        let subject = message.header("Subject").unwrap_or("").to_string();
+        let collapsed: String = message.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        IndexedMessage {
+            date: message.header("Date").and_then(time::parse_rfc2822).unwrap_or(0),
+            from_terms: tokenize(&from).collect(),
+            subject_terms: tokenize(&subject).collect(),
+            body_terms: tokenize(&message.text).take(MAX_BODY_WORDS).collect(),
+            snippet: text::truncate_to_chars(&collapsed, SNIPPET_CHARS).to_string(),
+            from,
+            subject,
+        }
+    }
+
+    /// Whether the message passes the query's filters, apart from `mailbox:`
+    /// and the body terms, which the postings answer.
+    fn matches(&self, query: &Query) -> bool {
+        query.from.iter().all(|term| self.from_terms.contains(term))
+            && query.subject.iter().all(|term| self.subject_terms.contains(term))
+            && query.after.map_or(true, |after| self.date >= after)
+            && query.before.map_or(true, |before| self.date < before)
+    }
+}
+
+/// Why a stored index wasn't used.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IndexError {
+    /// Not an index file, or cut short.
+    Malformed,
+    /// Written by another version of the mail service.
+    Version(u32),
+    /// The contents don't match the checksum.
+    Checksum,
+}
+
+impl core::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("not an index file"),
+            Self::Version(version) => write!(f, "index version {}, expected {}", version, INDEX_VERSION),
+            Self::Checksum => f.write_str("index checksum does not match"),
+        }
+    }
+}
+
+/// The search index of one mailbox.
+#[derive(Debug, Default)]
+pub struct TermIndex {
+    messages: BTreeMap<u32, IndexedMessage>,
+    /// Body word -> messages containing it.
+    postings: BTreeMap<String, BTreeSet<u32>>,
+}
+
+impl TermIndex {
+    /// Indexes (or reindexes) message `id` from its stored text.
+    pub fn insert(&mut self, id: u32, raw: &str) {
+        self.remove(id);
+        self.add(id, IndexedMessage::new(raw));
+    }
+
+    fn add(&mut self, id: u32, message: IndexedMessage) {
+        for term in &message.body_terms {
+            self.postings.entry(term.clone()).or_default().insert(id);
+        }
+        self.messages.insert(id, message);
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        let Some(message) = self.messages.remove(&id) else {
+            return;
+        };
+        for term in &message.body_terms {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    /// IDs of the indexed messages, in order.
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.messages.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// The messages matching `query`, in ID order. `mailbox:` is the caller's business.
+    pub fn search<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = (u32, &'a IndexedMessage)> + 'a {
+        // Candidates come from the rarest term's postings; with no terms, every message.
+        let rarest = query.terms.iter().map(|term| self.postings.get(term)).min_by_key(|ids| ids.map_or(0, BTreeSet::len));
+        let candidates: Vec<u32> = match rarest {
+            Some(Some(ids)) => ids.iter().copied().collect(),
+            Some(None) => Vec::new(), // A term no message has
+            None => self.ids().collect(),
+        };
+        candidates.into_iter().filter_map(move |id| {
+            let message = &self.messages[&id];
+            let has_terms = query.terms.iter().all(|term| message.body_terms.contains(term));
+            (has_terms && message.matches(query)).then_some((id, message))
+        })
+    }
+
+    /// The `terms` file: magic, version, the postcard-encoded messages, and
+    /// the CID of everything before it as a checksum. Postings aren't stored;
+    /// `decode` derives them.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(INDEX_MAGIC);
+        out.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+        out.extend_from_slice(&postcard::to_allocvec(&self.messages).unwrap_or_default());
+        let checksum = compute_cid(&out);
+        out.extend_from_slice(checksum.as_bytes());
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, IndexError> {
+        if bytes.len() < INDEX_MAGIC.len() + 4 + CID_LEN || &bytes[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+            return Err(IndexError::Malformed);
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != INDEX_VERSION {
+            return Err(IndexError::Version(version));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CID_LEN);
+        if compute_cid(body).as_bytes()[..] != *checksum {
+            return Err(IndexError::Checksum);
+        }
+        let messages: BTreeMap<u32, IndexedMessage> = postcard::from_bytes(&body[8..]).map_err(|_| IndexError::Malformed)?;
+        let mut index = TermIndex::default();
+        for (id, message) in messages {
+            index.add(id, message);
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const OCT_1: u64 = 1_790_812_800;
+    const OCT_5: u64 = 1_791_158_400;
+    const OCT_17: u64 = 1_792_195_200;
+
+    const PLAIN: &str = "From: Alice <alice@example.com>\r\nSubject: Lunch plans\r\nDate: Thu, 1 Oct 2026 12:00:00 +0000\r\n\r\nShall we meet for lunch at noon?\r\n";
+    const QUOTED_PRINTABLE: &str = "From: Bob <bob@example.com>\r\nSubject: Report\r\nDate: Sun, 4 Oct 2026 23:59:59 +0000\r\n\
+Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\nThe quarterly re=\r\nport is ready. Caf=C3=A9 budget.\r\n";
+    const BASE64: &str = "From: Carol <carol@example.com>\r\nSubject: Lunch menu\r\nDate: Mon, 5 Oct 2026 00:00:00 +0000\r\n\
+Content-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n\r\nUGl6emEgYW5kIHNhbGFkIHRvZGF5Lgo=\r\n";
+    const MULTIPART: &str = "From: alice@example.com\r\nSubject: Invoice\r\nDate: Sat, 17 Oct 2026 00:00:00 +0000\r\n\
+Content-Type: multipart/mixed; boundary=\"b1\"\r\n\r\npreamble\r\n--b1\r\nContent-Type: text/plain\r\n\r\nSee the attached invoice.\r\n\
+--b1\r\nContent-Type: text/plain\r\nContent-Disposition: attachment; filename=notes.txt\r\n\r\nsecretword\r\n--b1--\r\n";
+
+    fn fixture() -> TermIndex {
+        let mut index = TermIndex::default();
+        for (id, raw) in [PLAIN, QUOTED_PRINTABLE, BASE64, MULTIPART].iter().enumerate() {
+            index.insert(id as u32 + 1, raw);
+        }
+        index
+    }
+
+    fn found(index: &TermIndex, query: &str) -> Vec<u32> {
+        let query = Query::parse(query).unwrap();
+        index.search(&query).map(|(id, _)| id).collect()
+    }
+
+    fn error_at(query: &str) -> usize {
+        Query::parse(query).unwrap_err().position
+    }
+
+    #[test]
+    fn words_are_lowercased_and_bounded() {
+        let words: Vec<String> = tokenize("Hello, WORLD! a I 42 Übergrößenträger x-ray").collect();
+        assert_eq!(words, vec!["hello", "world", "42", "übergrößenträger", "ray"]);
+        let long = "a".repeat(40);
+        assert_eq!(tokenize(&long).next().unwrap().chars().count(), MAX_TERM_CHARS);
+    }
+
+    #[test]
+    fn queries_parse_into_terms_and_filters() {
+        let query = Query::parse("  Lunch FROM:Alice subject:\"Lunch Plans\" mailbox:Sent after:2026-10-01 before:2026-10-17 ").unwrap();
+        assert_eq!(query, Query {
+            terms: vec!["lunch".into()],
+            from: vec!["alice".into()],
+            subject: vec!["lunch".into(), "plans".into()],
+            mailbox: Some("Sent".into()),
+            after: Some(OCT_1),
+            before: Some(OCT_17),
+        });
+        assert_eq!(Query::parse("").unwrap(), Query::default());
+        // A quoted free-text value may contain a colon.
+        assert_eq!(Query::parse("\"re: lunch\"").unwrap().terms, vec!["re", "lunch"]);
+    }
+
+    #[test]
+    fn bad_queries_report_the_token_at_fault() {
+        assert_eq!(error_at("lunch bogus:x"), 6);
+        assert_eq!(error_at("from:alice subject:\"lunch plans"), 19);
+        assert_eq!(error_at("after:2026-13-01"), 0);
+        assert_eq!(error_at("xy before:2026-1-01"), 3);
+        assert_eq!(error_at("before:yesterday"), 0);
+        assert_eq!(error_at("mailbox:Inbox mailbox:Sent"), 14);
+        assert_eq!(error_at("lunch from:"), 6);
+        assert_eq!(error_at("a"), 0);
+        assert_eq!(Query::parse("lunch bogus:x").unwrap_err().message, "unknown field 'bogus'");
+    }
+
+    #[test]
+    fn headers_and_bodies_match_in_every_encoding() {
+        let index = fixture();
+        assert_eq!(found(&index, "from:alice"), vec![1, 4]);
+        assert_eq!(found(&index, "from:alice subject:lunch"), vec![1]);
+        assert_eq!(found(&index, "subject:\"lunch plans\""), vec![1]);
+        assert_eq!(found(&index, "subject:\"lunch report\""), Vec::<u32>::new());
+        assert_eq!(found(&index, "subject:lunch"), vec![1, 3]);
+        // Quoted-printable, with a soft line break inside a word and UTF-8 escapes.
+        assert_eq!(found(&index, "report"), vec![2]);
+        assert_eq!(found(&index, "café"), vec![2]);
+        assert_eq!(found(&index, "pizza salad"), vec![3]);
+        assert_eq!(found(&index, "invoice"), vec![4]);
+        // Attachments aren't searched, and neither is the preamble.
+        assert_eq!(found(&index, "secretword"), Vec::<u32>::new());
+        assert_eq!(found(&index, "preamble"), Vec::<u32>::new());
+        // Header words aren't body words.
+        assert_eq!(found(&index, "alice"), Vec::<u32>::new());
+        assert_eq!(found(&index, "lunch"), vec![1]);
+        assert_eq!(found(&index, "lunch from:carol"), Vec::<u32>::new());
+        assert_eq!(found(&index, ""), vec![1, 2, 3, 4]);
+
+        let query = Query::parse("invoice").unwrap();
+        let (_, message) = index.search(&query).next().unwrap();
+        assert_eq!((message.date, message.subject.as_str(), message.snippet.as_str()), (OCT_17, "Invoice", "See the attached invoice."));
+    }
+
+    #[test]
+    fn date_bounds_include_after_and_exclude_before() {
+        let index = fixture();
+        assert_eq!(found(&index, "after:2026-10-05"), vec![3, 4]);
+        assert_eq!(found(&index, "before:2026-10-05"), vec![1, 2]);
+        assert_eq!(found(&index, "after:2026-10-17"), vec![4]);
+        assert_eq!(found(&index, "after:2026-10-01 before:2026-10-17"), vec![1, 2, 3]);
+        assert_eq!(found(&index, "before:2026-10-01"), Vec::<u32>::new());
+        assert_eq!(parse_date("2026-10-05"), Some(OCT_5));
+    }
+
+    #[test]
+    fn removed_and_replaced_messages_leave_the_postings() {
+        let mut index = fixture();
+        index.remove(1);
+        index.remove(1);
+        assert_eq!(found(&index, "lunch"), Vec::<u32>::new());
+        assert_eq!(found(&index, "from:alice"), vec![4]);
+        assert!(!index.postings.contains_key("noon"));
+
+        index.insert(3, PLAIN);
+        assert_eq!(found(&index, "pizza"), Vec::<u32>::new());
+        assert_eq!(found(&index, "noon"), vec![3]);
+        assert_eq!(index.ids().collect::<Vec<_>>(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn the_index_file_round_trips_and_refuses_damage() {
+        let index = fixture();
+        let bytes = index.encode();
+        let decoded = TermIndex::decode(&bytes).unwrap();
+        assert_eq!(decoded.messages, index.messages);
+        assert_eq!(decoded.postings, index.postings);
+        assert_eq!(found(&decoded, "from:alice café"), Vec::<u32>::new());
+        assert_eq!(found(&decoded, "café"), vec![2]);
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert_eq!(TermIndex::decode(&flipped).unwrap_err(), IndexError::Checksum);
+        let mut other_version = bytes.clone();
+        other_version[4] = INDEX_VERSION as u8 + 1;
+        assert_eq!(TermIndex::decode(&other_version).unwrap_err(), IndexError::Version(INDEX_VERSION + 1));
+        assert_eq!(TermIndex::decode(&bytes[..10]).unwrap_err(), IndexError::Malformed);
+        assert_eq!(TermIndex::decode(b"XXXX\x01\x00\x00\x00").unwrap_err(), IndexError::Malformed);
+        assert_eq!(TermIndex::decode(&TermIndex::default().encode()).unwrap().len(), 0);
+    }
+}
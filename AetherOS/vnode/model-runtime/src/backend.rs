@@ -0,0 +1,132 @@
+// vnode/model-runtime/src/backend.rs
+
+//! Inference backends: what turns a model file's bytes into answers.
+//!
+//! A model's metadata names its backend (`backend=gguf`). The backend parses
+//! the weights once in `load` and returns a `Model` that keeps only what it
+//! learned from them (tensor offsets, labels, vocabulary), never the bytes:
+//! those stay in the mapped or copied file, and every call gets them again.
+//! That way a mapped model is never copied, and a model can't outlive them.
+//!
+//! Adding a backend means implementing `InferenceBackend` and listing it in
+//! `BACKENDS`.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::gguf_backend::GgufBackend;
+
+/// What a text generation call may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerationParams {
+    /// Tokens to generate at most. Generation stops earlier at an end-of-text token.
+    pub max_tokens: u32,
+}
+
+/// How far a text generation got. model-runtime generates a few tokens at a
+/// time and keeps this between the slices; a generation run in one call and
+/// one run in slices produce the same tokens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Generation {
+    pub produced: u32,
+    /// The token to continue from, once the prompt has been read. What it
+    /// indexes is up to the backend.
+    pub last_token: Option<usize>,
+    pub finished: bool,
+}
+
+/// Labels with their probabilities, in the model's label order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub class_labels: Vec<String>,
+    pub probabilities: Vec<f32>,
+}
+
+pub trait InferenceBackend {
+    /// The name metadata files select the backend by, e.g. "gguf".
+    fn name(&self) -> &'static str;
+    /// Checks and parses `weights`. Errors name what is wrong with the file.
+    fn load(&self, weights: &[u8]) -> Result<Box<dyn Model>, String>;
+}
+
+/// A model as a backend loaded it. `weights` is always the slice `load` was given.
+pub trait Model {
+    fn infer_image(&self, weights: &[u8], image: &[u8]) -> Result<Classification, String>;
+    /// Generates up to `budget` more tokens after `prompt`, continuing from
+    /// `state` and handing each to `on_token` as it is produced. Sets
+    /// `state.finished` at `max_tokens` or an end-of-text token.
+    fn generate_text(&self, weights: &[u8], prompt: &str, params: GenerationParams, state: &mut Generation, budget: u32, on_token: &mut dyn FnMut(&str)) -> Result<(), String>;
+}
+
+/// Every backend a metadata file may name.
+pub static BACKENDS: &[&(dyn InferenceBackend + Sync)] = &[&GgufBackend, &MockBackend];
+
+pub fn find(name: &str) -> Option<&'static (dyn InferenceBackend + Sync)> {
+    BACKENDS.iter().copied().find(|backend| backend.name() == name)
+}
+
+/// Canned answers that don't depend on the weights, for testing clients and
+/// the service around the backends.
+pub struct MockBackend;
+
+struct MockModel;
+
+impl InferenceBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn load(&self, _weights: &[u8]) -> Result<Box<dyn Model>, String> {
+        Ok(Box::new(MockModel))
+    }
+}
+
+impl Model for MockModel {
+    fn infer_image(&self, _weights: &[u8], _image: &[u8]) -> Result<Classification, String> {
+        Ok(Classification { class_labels: vec!["cat".to_string(), "dog".to_string()], probabilities: vec![0.9, 0.1] })
+    }
+
+    fn generate_text(&self, _weights: &[u8], prompt: &str, _params: GenerationParams, state: &mut Generation, _budget: u32, on_token: &mut dyn FnMut(&str)) -> Result<(), String> {
+        on_token(&alloc::format!("This is a generated text based on the prompt: '{}'.", prompt));
+        state.produced += 1;
+        state.finished = true;
+        Ok(())
+    }
+}
+
+/// `e^x`, to within a few ulps over the range softmax uses. There is no libm here.
+pub fn exp(x: f32) -> f32 {
+    if x < -87.0 {
+        return 0.0;
+    }
+    if x > 88.0 {
+        return f32::INFINITY;
+    }
+    // e^x = 2^k * e^r with |r| <= ln(2) / 2, and e^r from its Taylor series.
+    let k = (x * core::f32::consts::LOG2_E + if x < 0.0 { -0.5 } else { 0.5 }) as i32;
+    let r = x - k as f32 * core::f32::consts::LN_2;
+    let mut term = 1.0f32;
+    let mut sum = 1.0f32;
+    for n in 1..10 {
+        term *= r / n as f32;
+        sum += term;
+    }
+    sum * f32::from_bits(((k + 127) as u32) << 23)
+}
+
+/// Turns logits into probabilities that add up to 1.
+pub fn softmax(logits: &mut [f32]) {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut total = 0.0;
+    for logit in logits.iter_mut() {
+        *logit = exp(*logit - max);
+        total += *logit;
+    }
+    for logit in logits.iter_mut() {
+        *logit /= total;
+    }
+}
@@ -0,0 +1,404 @@
+// vnode/model-runtime/src/gguf.rs
+
+//! The GGUF container: a header, metadata key/value pairs, a tensor
+//! directory, and the tensor data.
+//!
+//! Everything is little-endian. After the magic `GGUF`, a version (2 or 3)
+//! and the tensor and key/value counts (u64 each) come the key/value pairs:
+//! a string key, a u32 value type and the value. Strings are a u64 length
+//! and that many UTF-8 bytes. Then, per tensor, its name, the number of
+//! dimensions (u32), each dimension (u64, fastest-varying first), the ggml
+//! type (u32) and the offset of its data (u64). The data section starts at
+//! the next multiple of `general.alignment` (32 if unset), and each offset
+//! counts from there.
+//!
+//! `parse` checks all of it against the file, including that every tensor
+//! lies inside it, so code using a parsed file can slice without checking
+//! again. Counts and lengths are capped before anything is allocated, so a
+//! crafted header can't exhaust memory.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+/// Caps on what a header may declare, far above anything a model here needs.
+const MAX_TENSORS: u64 = 65_536;
+const MAX_METADATA_KVS: u64 = 65_536;
+const MAX_STRING_BYTES: u64 = 65_536;
+const MAX_ARRAY_LEN: u64 = 1 << 20;
+const MAX_DIMS: u32 = 4;
+/// Arrays of arrays are allowed, but not deeper than this.
+const MAX_ARRAY_DEPTH: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GgufError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The file ends before the structure it declares does.
+    Truncated,
+    /// A count or length over its cap.
+    TooLarge(&'static str),
+    UnknownValueType(u32),
+    UnsupportedTensorType { tensor: String, ggml_type: u32 },
+    /// The data of `tensor` isn't aligned or doesn't fit in the file.
+    BadTensorData(String),
+    Malformed(&'static str),
+}
+
+impl fmt::Display for GgufError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not a GGUF file"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported GGUF version {}", version),
+            Self::Truncated => f.write_str("file is truncated"),
+            Self::TooLarge(what) => write!(f, "too many {}", what),
+            Self::UnknownValueType(value_type) => write!(f, "unknown metadata value type {}", value_type),
+            Self::UnsupportedTensorType { tensor, ggml_type } => write!(f, "tensor {} has unsupported type {}", tensor, ggml_type),
+            Self::BadTensorData(tensor) => write!(f, "data of tensor {} is misaligned or outside the file", tensor),
+            Self::Malformed(what) => write!(f, "malformed GGUF: {}", what),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Any unsigned integer value that fits.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::U8(v) => Some(v as u64),
+            Value::U16(v) => Some(v as u64),
+            Value::U32(v) => Some(v as u64),
+            Value::U64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// The tensor element types this runtime can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TensorType {
+    F32,
+    F16,
+    /// Blocks of 32 4-bit values with an f16 scale.
+    Q4_0,
+    /// Blocks of 32 4-bit values with an f16 scale and an f16 minimum.
+    Q4_1,
+    /// Blocks of 32 8-bit values with an f16 scale.
+    Q8_0,
+    I8,
+}
+
+impl TensorType {
+    /// The type for a ggml type number.
+    pub fn from_ggml(ggml_type: u32) -> Option<Self> {
+        match ggml_type {
+            0 => Some(TensorType::F32),
+            1 => Some(TensorType::F16),
+            2 => Some(TensorType::Q4_0),
+            3 => Some(TensorType::Q4_1),
+            8 => Some(TensorType::Q8_0),
+            24 => Some(TensorType::I8),
+            _ => None,
+        }
+    }
+
+    /// Elements per block and bytes per block. Unquantized types have one-element blocks.
+    pub fn block(&self) -> (u64, u64) {
+        match self {
+            TensorType::F32 => (1, 4),
+            TensorType::F16 => (1, 2),
+            TensorType::Q4_0 => (32, 18),
+            TensorType::Q4_1 => (32, 20),
+            TensorType::Q8_0 => (32, 34),
+            TensorType::I8 => (1, 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    /// Fastest-varying first: a matrix with `dims[1]` rows of `dims[0]` elements.
+    pub dims: Vec<u64>,
+    pub dtype: TensorType,
+    /// From the start of the file; `parse` has checked `offset + size` is inside it.
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl TensorInfo {
+    /// Bytes of one row of `dims[0]` elements.
+    pub fn row_size(&self) -> usize {
+        let (block_elements, block_bytes) = self.dtype.block();
+        (self.dims[0] / block_elements * block_bytes) as usize
+    }
+
+    /// The tensor's bytes in `weights`, the file it was parsed from.
+    pub fn data<'a>(&self, weights: &'a [u8]) -> &'a [u8] {
+        &weights[self.offset as usize..(self.offset + self.size) as usize]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GgufFile {
+    pub version: u32,
+    pub metadata: BTreeMap<String, Value>,
+    pub tensors: Vec<TensorInfo>,
+}
+
+impl GgufFile {
+    pub fn tensor(&self, name: &str) -> Option<&TensorInfo> {
+        self.tensors.iter().find(|tensor| tensor.name == name)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: u64) -> Result<&'a [u8], GgufError> {
+        let end = usize::try_from(len).ok().and_then(|len| self.pos.checked_add(len)).filter(|end| *end <= self.data.len());
+        let end = end.ok_or(GgufError::Truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GgufError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.bytes(N as u64)?);
+        Ok(out)
+    }
+
+    fn u32(&mut self) -> Result<u32, GgufError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, GgufError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, GgufError> {
+        let len = self.u64()?;
+        if len > MAX_STRING_BYTES {
+            return Err(GgufError::TooLarge("bytes in a string"));
+        }
+        let bytes = self.bytes(len)?;
+        core::str::from_utf8(bytes).map(String::from).map_err(|_| GgufError::Malformed("string is not UTF-8"))
+    }
+
+    fn value(&mut self, value_type: u32, depth: u32) -> Result<Value, GgufError> {
+        Ok(match value_type {
+            0 => Value::U8(self.array::<1>()?[0]),
+            1 => Value::I8(self.array::<1>()?[0] as i8),
+            2 => Value::U16(u16::from_le_bytes(self.array()?)),
+            3 => Value::I16(i16::from_le_bytes(self.array()?)),
+            4 => Value::U32(self.u32()?),
+            5 => Value::I32(i32::from_le_bytes(self.array()?)),
+            6 => Value::F32(f32::from_le_bytes(self.array()?)),
+            7 => match self.array::<1>()?[0] {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                _ => return Err(GgufError::Malformed("bool is neither 0 nor 1")),
+            },
+            8 => Value::String(self.string()?),
+            9 => {
+                if depth >= MAX_ARRAY_DEPTH {
+                    return Err(GgufError::TooLarge("nested arrays"));
+                }
+                let element_type = self.u32()?;
+                let len = self.u64()?;
+                if len > MAX_ARRAY_LEN {
+                    return Err(GgufError::TooLarge("array elements"));
+                }
+                // Every element takes at least a byte, so a length the file can't hold is caught before allocating.
+                if len > (self.data.len() - self.pos) as u64 {
+                    return Err(GgufError::Truncated);
+                }
+                let mut values = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    values.push(self.value(element_type, depth + 1)?);
+                }
+                Value::Array(values)
+            },
+            10 => Value::U64(self.u64()?),
+            11 => Value::I64(i64::from_le_bytes(self.array()?)),
+            12 => Value::F64(f64::from_le_bytes(self.array()?)),
+            other => return Err(GgufError::UnknownValueType(other)),
+        })
+    }
+}
+
+/// Parses and checks a GGUF file.
+pub fn parse(data: &[u8]) -> Result<GgufFile, GgufError> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(4).map_err(|_| GgufError::BadMagic)? != GGUF_MAGIC {
+        return Err(GgufError::BadMagic);
+    }
+    let version = reader.u32()?;
+    if version != 2 && version != 3 {
+        return Err(GgufError::UnsupportedVersion(version));
+    }
+    let tensor_count = reader.u64()?;
+    if tensor_count > MAX_TENSORS {
+        return Err(GgufError::TooLarge("tensors"));
+    }
+    let kv_count = reader.u64()?;
+    if kv_count > MAX_METADATA_KVS {
+        return Err(GgufError::TooLarge("metadata keys"));
+    }
+
+    let mut metadata = BTreeMap::new();
+    for _ in 0..kv_count {
+        let key = reader.string()?;
+        let value_type = reader.u32()?;
+        let value = reader.value(value_type, 0)?;
+        if metadata.insert(key, value).is_some() {
+            return Err(GgufError::Malformed("duplicate metadata key"));
+        }
+    }
+    let alignment = match metadata.get("general.alignment") {
+        None => DEFAULT_ALIGNMENT,
+        Some(Value::U32(alignment)) if alignment.is_power_of_two() => *alignment as u64,
+        Some(_) => return Err(GgufError::Malformed("general.alignment is not a power of two")),
+    };
+
+    let mut tensors: Vec<TensorInfo> = Vec::new();
+    for _ in 0..tensor_count {
+        let name = reader.string()?;
+        let n_dims = reader.u32()?;
+        if n_dims == 0 || n_dims > MAX_DIMS {
+            return Err(GgufError::Malformed("tensor has no dimensions or more than 4"));
+        }
+        let mut dims = Vec::with_capacity(n_dims as usize);
+        for _ in 0..n_dims {
+            dims.push(reader.u64()?);
+        }
+        let ggml_type = reader.u32()?;
+        let offset = reader.u64()?;
+        let dtype = TensorType::from_ggml(ggml_type).ok_or_else(|| GgufError::UnsupportedTensorType { tensor: name.clone(), ggml_type })?;
+        let (block_elements, block_bytes) = dtype.block();
+        // Rows are whole blocks, and the element count can't overflow.
+        let elements = dims.iter().try_fold(1u64, |total, dim| total.checked_mul(*dim).filter(|_| *dim > 0));
+        let size = match elements {
+            Some(elements) if dims[0] % block_elements == 0 => (elements / block_elements).checked_mul(block_bytes),
+            _ => None,
+        };
+        let size = size.ok_or_else(|| GgufError::BadTensorData(name.clone()))?;
+        if tensors.iter().any(|tensor| tensor.name == name) {
+            return Err(GgufError::Malformed("duplicate tensor name"));
+        }
+        tensors.push(TensorInfo { name, dims, dtype, offset, size });
+    }
+
+    // The data section, and every tensor in it, must be inside the file.
+    let data_start = (reader.pos as u64).div_ceil(alignment) * alignment;
+    for tensor in &mut tensors {
+        let start = data_start.checked_add(tensor.offset).filter(|_| tensor.offset % alignment == 0);
+        let end = start.and_then(|start| start.checked_add(tensor.size));
+        match (start, end) {
+            (Some(start), Some(end)) if end <= data.len() as u64 => tensor.offset = start,
+            _ => return Err(GgufError::BadTensorData(tensor.name.clone())),
+        }
+    }
+    Ok(GgufFile { version, metadata, tensors })
+}
+
+/// IEEE half precision to single.
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exponent = ((bits >> 10) & 0x1F) as u32;
+    let mantissa = (bits & 0x3FF) as u32;
+    let single = match (exponent, mantissa) {
+        (0, 0) => sign,
+        // Subnormal: the value is mantissa * 2^-24 exactly.
+        (0, _) => {
+            let magnitude = mantissa as f32 / 16_777_216.0;
+            return if sign != 0 { -magnitude } else { magnitude };
+        },
+        (0x1F, _) => sign | 0x7F80_0000 | (mantissa << 13), // Infinity or NaN
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(single)
+}
+
+/// Dequantizes one row of `dtype` elements from `bytes` into `out`, which
+/// holds as many elements as `bytes` encodes.
+pub fn dequantize(dtype: TensorType, bytes: &[u8], out: &mut [f32]) {
+    let half = |b: &[u8]| f16_to_f32(u16::from_le_bytes([b[0], b[1]]));
+    match dtype {
+        TensorType::F32 => {
+            for (value, b) in out.iter_mut().zip(bytes.chunks_exact(4)) {
+                *value = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            }
+        },
+        TensorType::F16 => {
+            for (value, b) in out.iter_mut().zip(bytes.chunks_exact(2)) {
+                *value = half(b);
+            }
+        },
+        TensorType::I8 => {
+            for (value, b) in out.iter_mut().zip(bytes) {
+                *value = *b as i8 as f32;
+            }
+        },
+        TensorType::Q8_0 => {
+            for (values, block) in out.chunks_exact_mut(32).zip(bytes.chunks_exact(34)) {
+                let scale = half(&block[0..2]);
+                for (value, q) in values.iter_mut().zip(&block[2..]) {
+                    *value = *q as i8 as f32 * scale;
+                }
+            }
+        },
+        TensorType::Q4_0 | TensorType::Q4_1 => {
+            // The low nibbles are elements 0..16 of the block, the high ones 16..32.
+            let header = if dtype == TensorType::Q4_0 { 2 } else { 4 };
+            for (values, block) in out.chunks_exact_mut(32).zip(bytes.chunks_exact(header + 16)) {
+                let scale = half(&block[0..2]);
+                let (bias, min) = if dtype == TensorType::Q4_0 { (8.0, 0.0) } else { (0.0, half(&block[2..4])) };
+                for (j, q) in block[header..].iter().enumerate() {
+                    values[j] = ((q & 0x0F) as f32 - bias) * scale + min;
+                    values[j + 16] = ((q >> 4) as f32 - bias) * scale + min;
+                }
+            }
+        },
+    }
+}
@@ -0,0 +1,192 @@
+// vnode/model-runtime/src/gguf_backend.rs
+
+//! The `gguf` backend: models in GGUF files, with weights in any type
+//! `gguf::TensorType` reads.
+//!
+//! `general.architecture` says what the tensors mean. Two small ones are
+//! implemented so far:
+//!
+//! * `linear-classifier`: `classifier.weight` (`[inputs, classes]`),
+//!   `classifier.bias` (`[classes]`) and the labels in `classifier.labels`.
+//!   The input is the image data as `inputs` bytes, each scaled to 0..1; the
+//!   output is the softmax of `weight * input + bias`.
+//! * `bigram`: the vocabulary in `tokenizer.ggml.tokens` and
+//!   `output.weight` (`[vocab, vocab]`), whose row for a token holds the
+//!   logits of the token that follows it. The prompt is split into the
+//!   longest vocabulary tokens, and generation greedily picks the likeliest
+//!   next token, stopping at `tokenizer.ggml.eos_token_id` if it is set.
+//!
+//! Both dequantize one row at a time, so a quantized model takes no more
+//! memory than its file plus a row.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::backend::{self, Classification, Generation, GenerationParams, InferenceBackend, Model};
+use crate::gguf::{self, GgufFile, TensorInfo};
+
+pub struct GgufBackend;
+
+impl InferenceBackend for GgufBackend {
+    fn name(&self) -> &'static str {
+        "gguf"
+    }
+
+    fn load(&self, weights: &[u8]) -> Result<Box<dyn Model>, String> {
+        let file = gguf::parse(weights).map_err(|e| format!("{}", e))?;
+        match file.metadata.get("general.architecture").and_then(|value| value.as_str()) {
+            Some("linear-classifier") => Ok(Box::new(LinearClassifier::new(&file)?)),
+            Some("bigram") => Ok(Box::new(Bigram::new(&file)?)),
+            Some(other) => Err(format!("unsupported architecture '{}'", other)),
+            None => Err(String::from("general.architecture is not set")),
+        }
+    }
+}
+
+/// The tensor `name`, checked to have the given dimensions.
+fn tensor(file: &GgufFile, name: &str, dims: &[u64]) -> Result<TensorInfo, String> {
+    let tensor = file.tensor(name).ok_or_else(|| format!("tensor {} is missing", name))?;
+    if tensor.dims != dims {
+        return Err(format!("tensor {} is {:?}, expected {:?}", name, tensor.dims, dims));
+    }
+    Ok(tensor.clone())
+}
+
+/// The strings in the metadata array `key`.
+fn strings(file: &GgufFile, key: &str) -> Result<Vec<String>, String> {
+    let values = file.metadata.get(key).and_then(|value| value.as_array()).ok_or_else(|| format!("{} is missing", key))?;
+    values.iter()
+        .map(|value| value.as_str().map(String::from).ok_or_else(|| format!("{} holds something other than strings", key)))
+        .collect()
+}
+
+/// Row `index` of a matrix, dequantized into `out`.
+fn row(weights: &[u8], matrix: &TensorInfo, index: usize, out: &mut [f32]) {
+    let size = matrix.row_size();
+    gguf::dequantize(matrix.dtype, &matrix.data(weights)[index * size..(index + 1) * size], out);
+}
+
+struct LinearClassifier {
+    weight: TensorInfo,
+    bias: TensorInfo,
+    labels: Vec<String>,
+}
+
+impl LinearClassifier {
+    fn new(file: &GgufFile) -> Result<Self, String> {
+        let labels = strings(file, "classifier.labels")?;
+        let classes = labels.len() as u64;
+        let inputs = file.tensor("classifier.weight").map_or(0, |weight| weight.dims[0]);
+        Ok(Self {
+            weight: tensor(file, "classifier.weight", &[inputs, classes])?,
+            bias: tensor(file, "classifier.bias", &[classes])?,
+            labels,
+        })
+    }
+}
+
+impl Model for LinearClassifier {
+    fn infer_image(&self, weights: &[u8], image: &[u8]) -> Result<Classification, String> {
+        let inputs = self.weight.dims[0] as usize;
+        if image.len() != inputs {
+            return Err(format!("model takes {} bytes of input, got {}", inputs, image.len()));
+        }
+        let input: Vec<f32> = image.iter().map(|byte| *byte as f32 / 255.0).collect();
+        let mut logits = vec![0.0f32; self.labels.len()];
+        gguf::dequantize(self.bias.dtype, self.bias.data(weights), &mut logits);
+        let mut weight_row = vec![0.0f32; inputs];
+        for (class, logit) in logits.iter_mut().enumerate() {
+            row(weights, &self.weight, class, &mut weight_row);
+            *logit += weight_row.iter().zip(&input).map(|(w, x)| w * x).sum::<f32>();
+        }
+        backend::softmax(&mut logits);
+        Ok(Classification { class_labels: self.labels.clone(), probabilities: logits })
+    }
+
+    fn generate_text(&self, _weights: &[u8], _prompt: &str, _params: GenerationParams, _state: &mut Generation, _budget: u32, _on_token: &mut dyn FnMut(&str)) -> Result<(), String> {
+        Err(String::from("a linear classifier doesn't generate text"))
+    }
+}
+
+struct Bigram {
+    tokens: Vec<String>,
+    eos: Option<usize>,
+    output: TensorInfo,
+}
+
+impl Bigram {
+    fn new(file: &GgufFile) -> Result<Self, String> {
+        let tokens = strings(file, "tokenizer.ggml.tokens")?;
+        let vocab = tokens.len() as u64;
+        let eos = match file.metadata.get("tokenizer.ggml.eos_token_id") {
+            None => None,
+            Some(value) => Some(value.as_u64().filter(|id| *id < vocab).ok_or("tokenizer.ggml.eos_token_id is not a token")? as usize),
+        };
+        Ok(Self { output: tensor(file, "output.weight", &[vocab, vocab])?, tokens, eos })
+    }
+
+    /// The prompt's last token, splitting it into the longest tokens it starts with.
+    /// Text no token starts is skipped a character at a time.
+    fn last_token(&self, prompt: &str) -> Option<usize> {
+        let mut rest = prompt;
+        let mut last = None;
+        while let Some(c) = rest.chars().next() {
+            let longest = self.tokens.iter().enumerate()
+                .filter(|(_, token)| !token.is_empty() && rest.starts_with(token.as_str()))
+                .max_by_key(|(id, token)| (token.len(), core::cmp::Reverse(*id)));
+            match longest {
+                Some((id, token)) => {
+                    last = Some(id);
+                    rest = &rest[token.len()..];
+                },
+                None => rest = &rest[c.len_utf8()..],
+            }
+        }
+        last
+    }
+}
+
+impl Model for Bigram {
+    fn infer_image(&self, _weights: &[u8], _image: &[u8]) -> Result<Classification, String> {
+        Err(String::from("a bigram model doesn't classify images"))
+    }
+
+    fn generate_text(&self, weights: &[u8], prompt: &str, params: GenerationParams, state: &mut Generation, budget: u32, on_token: &mut dyn FnMut(&str)) -> Result<(), String> {
+        let mut current = match state.last_token {
+            Some(token) => token,
+            None => self.last_token(prompt).ok_or("the prompt contains no known token")?,
+        };
+        let mut logits = vec![0.0f32; self.tokens.len()];
+        for _ in 0..budget {
+            if state.produced >= params.max_tokens {
+                state.finished = true;
+                break;
+            }
+            row(weights, &self.output, current, &mut logits);
+            // The first of equally likely tokens wins, so the output is deterministic.
+            let mut next = 0;
+            for (id, logit) in logits.iter().enumerate() {
+                if *logit > logits[next] {
+                    next = id;
+                }
+            }
+            if Some(next) == self.eos {
+                state.finished = true;
+                break;
+            }
+            on_token(&self.tokens[next]);
+            current = next;
+            state.produced += 1;
+        }
+        state.last_token = Some(current);
+        if state.produced >= params.max_tokens {
+            state.finished = true;
+        }
+        Ok(())
+    }
+}
@@ -6,15 +6,30 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
 
 use common::ipc::vnode::VNodeChannel;
-use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, SYS_MAP_FILE, SYS_UNMAP, E_ERROR, E_INVALID_ARG};
+use common::abi::IpcCreds;
 use common::ipc::model_runtime_ipc::{InferRequest, InferResponse};
+use common::ipc::vnode::ReplyToken;
+use common::metrics::{Counter, Gauge, Histogram, Registry, DURATION_BOUNDS};
+use common::time;
 use common::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata}; // For loading models
+use common::ipc::vfs_stream::VfsStreams;
+
+mod backend;
+mod gguf;
+mod gguf_backend;
+mod queue;
+mod validation;
+use backend::{GenerationParams, Model};
+use queue::{ClientId, Job, WorkQueue, MAX_IN_FLIGHT, SLICE_TOKENS};
+use validation::ModelMeta;
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -29,11 +44,35 @@ fn log(msg: &str) {
     }
 }
 
-// Placeholder for a loaded ML model
+/// Largest metadata file accepted from `/models/<id>/meta`.
+const MAX_META_BYTES: u32 = 4096;
+/// Clients that get a wait-time histogram of their own; later ones share
+/// the one labelled `client="other"`.
+const MAX_CLIENT_METRICS: usize = 16;
+const WAIT_METRIC: &str = "model_runtime_wait_nanoseconds";
+const WAIT_HELP: &str = "Time from queuing a request to its first slice, in nanoseconds.";
+
+/// Raw model bytes: mapped read-only from a VFS pin, or copied in over IPC.
+enum ModelData {
+    Mapped { addr: u64, len: usize, backing: u64 },
+    Copied(Vec<u8>),
+}
+
+impl ModelData {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            // SAFETY: the kernel keeps `len` bytes at `addr` mapped read-only until we unmap them.
+            ModelData::Mapped { addr, len, .. } => unsafe { core::slice::from_raw_parts(*addr as *const u8, *len) },
+            ModelData::Copied(data) => data,
+        }
+    }
+}
+
 struct LoadedModel {
     model_id: String,
-    data: Vec<u8>, // Raw model bytes
-    // Add more metadata, e.g., type of model, input/output shapes
+    data: ModelData, // Raw model bytes
+    meta: ModelMeta, // Declared input constraints
+    model: Box<dyn Model>, // What the backend made of `data`; it is handed `data` on every call
 }
 
 struct ModelRuntimeService {
@@ -41,6 +80,15 @@ struct ModelRuntimeService {
     vfs_chan: VNodeChannel,    // Channel to svc://vfs for loading models
 
     loaded_models: BTreeMap<String, LoadedModel>, // model_id -> LoadedModel
+    disabled_models: BTreeMap<String, String>, // model_id -> reason; models whose metadata failed to parse
+
+    queue: WorkQueue, // Requests waiting for or between slices
+    metrics: Registry,
+    queue_depth: Gauge,
+    slices: Counter,
+    busy: Counter,
+    client_wait: BTreeMap<ClientId, Histogram>,
+    other_wait: Histogram,
 }
 
 impl ModelRuntimeService {
@@ -50,102 +98,288 @@ impl ModelRuntimeService {
 
         log("Model Runtime Service: Initializing...");
 
+        let mut metrics = Registry::new("model-runtime");
+        let queue_depth = metrics.gauge("model_runtime_queue_depth", "Requests queued or running.");
+        let slices = metrics.counter("model_runtime_slices_total", "Slices of inference work run.");
+        let busy = metrics.counter("model_runtime_busy_total", "Requests refused because the client had too many in flight.");
+        let other_wait = metrics.histogram_with(WAIT_METRIC, WAIT_HELP, &DURATION_BOUNDS, &[("client", "other")]);
+
         Self {
             client_chan,
             vfs_chan,
             loaded_models: BTreeMap::new(),
+            disabled_models: BTreeMap::new(),
+            queue: WorkQueue::new(),
+            metrics,
+            queue_depth,
+            slices,
+            busy,
+            client_wait: BTreeMap::new(),
+            other_wait,
         }
     }
 
-    // Conceptual: Load a model from VFS
-    fn load_model(&mut self, model_id: &str, path: &str) -> Result<&LoadedModel, String> {
-        if let Some(model) = self.loaded_models.get(model_id) {
-            log(&alloc::format!("Model Runtime: Model '{}' already loaded.", model_id));
-            return Ok(model);
-        }
+    /// Reads a file of at most `max_len` bytes from VFS, streamed so a large
+    /// model takes a round trip per few chunks instead of one per chunk.
+    fn read_file(&mut self, path: &str, max_len: u32) -> Result<Vec<u8>, String> {
+        let open_req = VfsRequest::Open { path: path.to_string(), flags: 0 }; // 0 for O_RDONLY
+        let fd: Fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&open_req) {
+            Ok(VfsResponse::Success(file_fd)) => file_fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(alloc::format!("Failed to open '{}': {}.", path, message)),
+            _ => return Err(alloc::format!("Unexpected VFS response while opening '{}'.", path)),
+        };
 
-        log(&alloc::format!("Model Runtime: Loading model '{}' from VFS path '{}'.", model_id, path));
-        
-        // Simulate opening the model file
+        let mut streams = VfsStreams::new(&mut self.vfs_chan);
+        // One byte more than allowed, so an oversized file is told apart from one that fits exactly.
+        let result = streams.open_read(fd, 0, max_len as u64 + 1)
+            .and_then(|stream| streams.read_to_end(stream, max_len as usize))
+            .map_err(|e| alloc::format!("Failed to read '{}': {}.", path, e));
+        let _ = streams.request(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// Maps a file read-only via a VFS pin. Returns `None` if the VFS can't pin it
+    /// (e.g., the file is too large), so the caller falls back to `read_file`.
+    fn map_file(&mut self, path: &str) -> Option<ModelData> {
         let open_req = VfsRequest::Open { path: path.to_string(), flags: 0 }; // 0 for O_RDONLY
         let fd: Fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&open_req) {
             Ok(VfsResponse::Success(file_fd)) => file_fd as Fd,
-            Ok(VfsResponse::Error { message, .. }) => return Err(alloc::format!("Failed to open model file: {}.", message)),
-            _ => return Err(String::from("Unexpected VFS response during model open.")),
+            _ => return None,
         };
+        let pinned = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Pin { fd });
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
 
-        // Simulate reading the model data
-        let read_req = VfsRequest::Read { fd, len: 1_000_000, offset: 0 }; // Assume max model size 1MB
-        let model_data: Vec<u8> = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&read_req) {
-            Ok(VfsResponse::Data(data)) => data,
+        let (backing, size) = match pinned {
+            Ok(VfsResponse::Pinned { backing, size }) => (backing, size),
             Ok(VfsResponse::Error { message, .. }) => {
-                let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
-                return Err(alloc::format!("Failed to read model data: {}.", message));
-            },
-            _ => {
-                let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
-                return Err(String::from("Unexpected VFS response during model read.")),
+                log(&alloc::format!("Model Runtime: Cannot pin '{}' ({}); copying instead.", path, message));
+                return None;
             },
+            _ => return None,
         };
+        let addr = if size == 0 { E_ERROR } else { unsafe { syscall3(SYS_MAP_FILE, backing, 0, size) } };
+        if addr <= E_ERROR || addr >= E_INVALID_ARG {
+            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Unpin { backing });
+            return None;
+        }
+        log(&alloc::format!("Model Runtime: Mapped '{}' ({} bytes) at {:#x}.", path, size, addr));
+        Some(ModelData::Mapped { addr, len: size as usize, backing })
+    }
 
-        // Close the model file
-        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+    /// Gives back a model file's memory: unmaps and unpins it if it was mapped.
+    fn release(&mut self, data: ModelData) {
+        if let ModelData::Mapped { addr, len, backing } = data {
+            unsafe { syscall3(SYS_UNMAP, addr, len as u64, 0); }
+            let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Unpin { backing });
+        }
+    }
+
+    /// Disables `model_id` until model-runtime restarts, for a problem that
+    /// won't go away by retrying.
+    fn disable(&mut self, model_id: &str, reason: String) -> String {
+        log(&alloc::format!("Model Runtime: Disabling model '{}': {}", model_id, reason));
+        self.disabled_models.insert(model_id.to_string(), reason.clone());
+        alloc::format!("Model '{}' is disabled: {}", model_id, reason)
+    }
+
+    // Load a model and its metadata from VFS
+    fn load_model(&mut self, model_id: &str, path: &str) -> Result<&LoadedModel, String> {
+        if self.loaded_models.contains_key(model_id) {
+            log(&alloc::format!("Model Runtime: Model '{}' already loaded.", model_id));
+            return Ok(self.loaded_models.get(model_id).unwrap());
+        }
+        if let Some(reason) = self.disabled_models.get(model_id) {
+            return Err(alloc::format!("Model '{}' is disabled: {}", model_id, reason));
+        }
+
+        // The metadata is read first: a model is never usable without validated limits.
+        let meta_path = alloc::format!("/models/{}/meta", model_id);
+        let meta_bytes = self.read_file(&meta_path, MAX_META_BYTES)?;
+        let parsed = core::str::from_utf8(&meta_bytes)
+            .map_err(|_| String::from("metadata is not valid UTF-8"))
+            .and_then(validation::parse_meta);
+        let meta = match parsed {
+            Ok(meta) => meta,
+            Err(e) => return Err(self.disable(model_id, alloc::format!("invalid metadata in {}: {}.", meta_path, e))),
+        };
+        let backend = match meta.backend.as_deref().map(|name| (name, backend::find(name))) {
+            Some((_, Some(backend))) => backend,
+            Some((name, None)) => return Err(self.disable(model_id, alloc::format!("unknown backend '{}' in {}.", name, meta_path))),
+            None => return Err(self.disable(model_id, alloc::format!("no backend in {}.", meta_path))),
+        };
 
-        if model_data.is_empty() {
+        log(&alloc::format!("Model Runtime: Loading model '{}' from VFS path '{}'.", model_id, path));
+        let model_data = match self.map_file(path) {
+            Some(mapped) => mapped,
+            None => ModelData::Copied(self.read_file(path, 1_000_000)?), // Assume max model size 1MB
+        };
+        if model_data.bytes().is_empty() {
+            self.release(model_data);
             return Err(String::from("Model file is empty."));
         }
+        // A bad file isn't cached: it may be being replaced, and the next request tries again.
+        let model = match backend.load(model_data.bytes()) {
+            Ok(model) => model,
+            Err(e) => {
+                self.release(model_data);
+                return Err(alloc::format!("'{}' is not a valid {} model: {}.", path, backend.name(), e));
+            }
+        };
+        log(&alloc::format!("Model Runtime: Loaded model '{}' with the {} backend.", model_id, backend.name()));
 
-        let loaded_model = LoadedModel { model_id: model_id.to_string(), data: model_data };
+        let loaded_model = LoadedModel { model_id: model_id.to_string(), data: model_data, meta, model };
         self.loaded_models.insert(model_id.to_string(), loaded_model);
         Ok(self.loaded_models.get(model_id).unwrap())
     }
 
-    fn handle_request(&mut self, request: InferRequest) -> InferResponse {
+    /// The model a request needs and the file it is loaded from.
+    fn model_path(request: &InferRequest) -> Option<(String, String)> {
         match request {
-            InferRequest::ImageClassification { model_id, image_data } => {
-                log(&alloc::format!("Model Runtime: Image classification request for model '{}'.", model_id));
-                
-                // Attempt to load the model (or retrieve from cache)
-                let model = match self.load_model(&model_id, &alloc::format!("/models/{}/image_classifier.bin", model_id)) {
-                    Ok(m) => m,
-                    Err(e) => return InferResponse::Error(alloc::format!("Failed to load model: {}.", e)),
-                };
-
-                // Simulate inference
-                log(&alloc::format!("Model Runtime: Performing image classification on {} bytes of image data using model '{}'.", image_data.len(), model.model_id));
-                InferResponse::ImageClassificationResult {
-                    class_labels: vec!["cat".to_string(), "dog".to_string()],
-                    probabilities: vec![0.9, 0.1],
+            InferRequest::ImageClassification { model_id, .. } => Some((model_id.clone(), alloc::format!("/models/{}/image_classifier.bin", model_id))),
+            InferRequest::TextGeneration { model_id, .. } => Some((model_id.clone(), alloc::format!("/models/{}/text_generator.bin", model_id))),
+            InferRequest::Batch { .. } | InferRequest::Metrics(_) => None,
+        }
+    }
+
+    /// Queues a request, or answers it straight away if it is for metrics,
+    /// malformed or over the client's in-flight limit.
+    fn accept(&mut self, request: InferRequest, token: Option<ReplyToken>, creds: IpcCreds) {
+        log(&alloc::format!("Model Runtime Service: Received {} from {}.", validation::describe(&request), creds.name()));
+        let (items, batch) = match request {
+            InferRequest::Metrics(request) => {
+                let response = InferResponse::Metrics(self.metrics.handle(&request));
+                self.reply(token, &response);
+                return;
+            },
+            InferRequest::Batch { requests } => {
+                if let Err(constraint) = validation::check_batch(&requests) {
+                    self.reply(token, &InferResponse::InvalidInput { constraint });
+                    return;
                 }
+                (requests, true)
             },
-            InferRequest::TextGeneration { model_id, prompt, max_tokens } => {
-                log(&alloc::format!("Model Runtime: Text generation request for model '{}' with prompt: '{}'.", model_id, prompt));
-                
-                // Attempt to load the model (or retrieve from cache)
-                let model = match self.load_model(&model_id, &alloc::format!("/models/{}/text_generator.bin", model_id)) {
-                    Ok(m) => m,
-                    Err(e) => return InferResponse::Error(alloc::format!("Failed to load model: {}.", e)),
-                };
-
-                // Simulate inference
-                log(&alloc::format!("Model Runtime: Generating {} tokens for prompt: '{}' using model '{}'.", max_tokens, prompt, model.model_id));
-                InferResponse::TextGenerationResult { generated_text: alloc::format!("This is a generated text based on the prompt: '{}'. It is generated by model {}.", prompt, model.model_id) }
+            request => (alloc::vec![request], false),
+        };
+        let job = Job::new(ClientId::from_creds(&creds), creds.sender, creds.name().to_string(), token, items, batch, time::monotonic_nanos());
+        match self.queue.push(job) {
+            Ok(()) => self.queue_depth.set(self.queue.depth() as i64),
+            Err((job, in_flight)) => {
+                self.busy.inc();
+                self.reply(job.token, &InferResponse::Busy { in_flight, limit: MAX_IN_FLIGHT });
             },
         }
     }
 
+    /// Answers a request. Returns whether the answer went out.
+    fn reply(&mut self, token: Option<ReplyToken>, response: &InferResponse) -> bool {
+        let sent = self.client_chan.reply(token, response).is_ok();
+        if !sent {
+            log("Model Runtime Service: Failed to send response to client.");
+        }
+        sent
+    }
+
+    /// The wait-time histogram for a client, registered the first time it
+    /// is seen. Past `MAX_CLIENT_METRICS` clients share one.
+    fn wait_histogram(&mut self, client: ClientId, name: &str) -> Histogram {
+        if let Some(histogram) = self.client_wait.get(&client) {
+            return histogram.clone();
+        }
+        if self.client_wait.len() >= MAX_CLIENT_METRICS {
+            return self.other_wait.clone();
+        }
+        let histogram = self.metrics.histogram_with(WAIT_METRIC, WAIT_HELP, &DURATION_BOUNDS, &[("client", name)]);
+        self.client_wait.insert(client, histogram.clone());
+        histogram
+    }
+
+    /// Runs one slice of `job`: the next item's classification, or up to
+    /// `SLICE_TOKENS` of its generation. The item's model is loaded first if
+    /// it isn't yet, which for a batch happens once.
+    fn run_slice(&mut self, job: &mut Job) {
+        if job.slices == 0 {
+            self.wait_histogram(job.client, &job.client_name).observe_since(job.queued_at);
+        }
+        job.slices += 1;
+        let Some(request) = job.items.get(job.results.len()) else { return };
+        let Some((model_id, path)) = Self::model_path(request) else {
+            job.results.push(InferResponse::Error { message: String::from("Not an inference request.") });
+            return;
+        };
+        let model = match self.load_model(&model_id, &path) {
+            Ok(m) => m,
+            Err(e) => {
+                job.generation = None;
+                job.results.push(InferResponse::Error { message: alloc::format!("Failed to load model: {}", e) });
+                return;
+            }
+        };
+
+        if job.generation.is_none() {
+            if let Err(constraint) = validation::validate(&model.meta, request) {
+                log(&alloc::format!("Model Runtime: Rejected request for model '{}': {:?}.", validation::preview(&model_id), constraint));
+                job.results.push(InferResponse::InvalidInput { constraint });
+                return;
+            }
+        }
+
+        let response = match request {
+            InferRequest::ImageClassification { image_data, .. } => {
+                log(&alloc::format!("Model Runtime: Performing image classification on {} bytes of image data using model '{}'.", image_data.len(), model.model_id));
+                match model.model.infer_image(model.data.bytes(), image_data) {
+                    Ok(result) => InferResponse::ImageClassificationResult { class_labels: result.class_labels, probabilities: result.probabilities },
+                    Err(e) => InferResponse::Error { message: alloc::format!("Inference failed: {}", e) },
+                }
+            },
+            InferRequest::TextGeneration { prompt, max_tokens, .. } => {
+                if job.generation.is_none() {
+                    log(&alloc::format!("Model Runtime: Generating {} tokens for prompt '{}' using model '{}'.", max_tokens, validation::preview(prompt), model.model_id));
+                }
+                let (state, generated_text) = job.generation.get_or_insert_with(Default::default);
+                let params = GenerationParams { max_tokens: *max_tokens };
+                let result = model.model.generate_text(model.data.bytes(), prompt, params, state, SLICE_TOKENS, &mut |token| generated_text.push_str(token));
+                match result {
+                    Ok(()) if !state.finished => return,
+                    Ok(()) => InferResponse::TextGenerationResult { generated_text: core::mem::take(generated_text) },
+                    Err(e) => InferResponse::Error { message: alloc::format!("Inference failed: {}", e) },
+                }
+            },
+            InferRequest::Batch { .. } | InferRequest::Metrics(_) => InferResponse::Error { message: String::from("Not an inference request.") },
+        };
+        job.generation = None;
+        job.results.push(response);
+    }
+
     fn run_loop(&mut self) -> ! {
         log("Model Runtime Service: Entering main event loop.");
         loop {
-            // Process incoming requests from client V-Nodes
-            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
-                if let Ok(request) = postcard::from_bytes::<InferRequest>(&req_data) {
-                    log(&alloc::format!("Model Runtime Service: Received InferRequest: {:?}.", request));
-                    let response = self.handle_request(request);
-                    self.client_chan.send(&response).unwrap_or_else(|_| log("Model Runtime Service: Failed to send response to client."));
-                } else {
-                    log("Model Runtime Service: Failed to deserialize InferRequest.");
+            // Take in everything that has arrived; queuing is cheap.
+            loop {
+                match self.client_chan.recv_call_with_creds_non_blocking() {
+                    Ok(Some((req_data, token, creds))) => match postcard::from_bytes::<InferRequest>(&req_data) {
+                        Ok(request) => self.accept(request, token, creds),
+                        Err(_) => log("Model Runtime Service: Failed to deserialize InferRequest."),
+                    },
+                    _ => break,
+                }
+            }
+
+            // One slice for the client whose turn it is, then the channel again.
+            if let Some(mut job) = self.queue.next() {
+                self.run_slice(&mut job);
+                self.slices.inc();
+                if let Some(job) = self.queue.finish_slice(job) {
+                    let (client, task, token) = (job.client, job.task, job.token);
+                    // A call that can't be answered means its task is gone,
+                    // and nobody is waiting for the rest of its work either.
+                    if !self.reply(token, &job.into_response()) && token.is_some() {
+                        let cancelled = self.queue.cancel(&client, task);
+                        if !cancelled.is_empty() {
+                            log(&alloc::format!("Model Runtime Service: Cancelled {} queued requests of task {}.", cancelled.len(), task));
+                        }
+                    }
                 }
+                self.queue_depth.set(self.queue.depth() as i64);
             }
 
             // Yield to other V-Nodes to prevent busy-waiting
@@ -0,0 +1,330 @@
+// vnode/model-runtime/src/queue.rs
+
+//! The work queue: requests wait here, per client, and run a slice at a time.
+//!
+//! A slice is one classification, or up to `SLICE_TOKENS` tokens of a text
+//! generation. After each slice the service answers what finished and reads
+//! its channel again, so a long generation never holds up the channel for
+//! more than a slice.
+//!
+//! Clients are served round-robin: each client with work gets one slice of
+//! its oldest request per turn, whatever it has queued behind it. A client
+//! is an identity, the Aid the kernel stamped on the request or, for a task
+//! without one, its task ID, so several tasks of one user share a turn. A
+//! client may have `MAX_IN_FLIGHT` requests queued or running; more are
+//! refused with `Busy`. A task that can no longer be answered has its
+//! other requests cancelled before they run.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::abi::IpcCreds;
+use common::ipc::model_runtime_ipc::{InferRequest, InferResponse};
+use common::ipc::vnode::ReplyToken;
+
+use crate::backend::Generation;
+
+/// Tokens a text generation produces per slice.
+pub const SLICE_TOKENS: u32 = 8;
+/// Requests one client may have queued or running.
+pub const MAX_IN_FLIGHT: u32 = 4;
+
+/// Who a request is from, for fairness and the in-flight limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ClientId {
+    Aid([u8; 32]),
+    Task(u64),
+}
+
+impl ClientId {
+    pub fn from_creds(creds: &IpcCreds) -> Self {
+        match creds.aid {
+            Some(aid) => ClientId::Aid(aid),
+            None => ClientId::Task(creds.sender),
+        }
+    }
+}
+
+/// A queued request: a single one is a batch of one that isn't answered as a batch.
+pub struct Job {
+    pub client: ClientId,
+    /// The task that sent the request, which the answer goes back to.
+    pub task: u64,
+    /// The task name the request came from, for the wait-time metric.
+    pub client_name: String,
+    pub token: Option<ReplyToken>,
+    pub items: Vec<InferRequest>,
+    pub batch: bool,
+    /// One per finished item, in order.
+    pub results: Vec<InferResponse>,
+    /// The current item's generation and its text so far, between slices.
+    pub generation: Option<(Generation, String)>,
+    /// `time::monotonic_nanos` when it was queued.
+    pub queued_at: u64,
+    pub slices: u32,
+}
+
+impl Job {
+    pub fn new(client: ClientId, task: u64, client_name: String, token: Option<ReplyToken>, items: Vec<InferRequest>, batch: bool, queued_at: u64) -> Self {
+        Self { client, task, client_name, token, items, batch, results: Vec::new(), generation: None, queued_at, slices: 0 }
+    }
+
+    pub fn done(&self) -> bool {
+        self.results.len() >= self.items.len()
+    }
+
+    pub fn into_response(mut self) -> InferResponse {
+        if self.batch {
+            return InferResponse::BatchResult { results: self.results };
+        }
+        self.results.pop().unwrap_or(InferResponse::Error { message: String::from("Request produced no result.") })
+    }
+}
+
+#[derive(Default)]
+pub struct WorkQueue {
+    jobs: BTreeMap<ClientId, VecDeque<Job>>,
+    /// Clients with work, in the order they get their next slice.
+    turns: VecDeque<ClientId>,
+}
+
+impl WorkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests `client` has queued or running.
+    pub fn in_flight(&self, client: &ClientId) -> u32 {
+        self.jobs.get(client).map_or(0, |jobs| jobs.len() as u32)
+    }
+
+    /// Requests queued or running, for every client.
+    pub fn depth(&self) -> usize {
+        self.jobs.values().map(VecDeque::len).sum()
+    }
+
+    /// Queues `job` behind the client's others. Gives it back with the
+    /// client's in-flight count if that is already `MAX_IN_FLIGHT`.
+    pub fn push(&mut self, job: Job) -> Result<(), (Job, u32)> {
+        let in_flight = self.in_flight(&job.client);
+        if in_flight >= MAX_IN_FLIGHT {
+            return Err((job, in_flight));
+        }
+        let client = job.client;
+        let jobs = self.jobs.entry(client).or_default();
+        jobs.push_back(job);
+        if jobs.len() == 1 {
+            self.turns.push_back(client);
+        }
+        Ok(())
+    }
+
+    /// Takes the oldest job of the client whose turn it is. Hand it back with
+    /// `finish_slice` once its slice has run.
+    pub fn next(&mut self) -> Option<Job> {
+        let client = self.turns.pop_front()?;
+        self.jobs.get_mut(&client)?.pop_front()
+    }
+
+    /// Puts a job back after its slice: at the front of its client's queue
+    /// if it isn't done, and the client at the back of the turns if it still
+    /// has work. A job that is done is returned, to be answered.
+    pub fn finish_slice(&mut self, job: Job) -> Option<Job> {
+        let client = job.client;
+        let jobs = self.jobs.entry(client).or_default();
+        let done = if job.done() {
+            Some(job)
+        } else {
+            jobs.push_front(job);
+            None
+        };
+        if jobs.is_empty() {
+            self.jobs.remove(&client);
+        } else {
+            self.turns.push_back(client);
+        }
+        done
+    }
+
+    /// Drops the jobs `task` has queued under `client`, e.g. once a reply to
+    /// it fails because it exited. Other tasks sharing the client keep theirs.
+    pub fn cancel(&mut self, client: &ClientId, task: u64) -> Vec<Job> {
+        let Some(jobs) = self.jobs.get_mut(client) else { return Vec::new() };
+        let (cancelled, kept): (VecDeque<Job>, VecDeque<Job>) = core::mem::take(jobs).into_iter().partition(|job| job.task == task);
+        *jobs = kept;
+        if jobs.is_empty() {
+            self.jobs.remove(client);
+            self.turns.retain(|turn| turn != client);
+        }
+        cancelled.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const ALICE: ClientId = ClientId::Aid([1; 32]);
+    const BOB: ClientId = ClientId::Task(20);
+
+    fn classify(label: &str) -> InferRequest {
+        InferRequest::ImageClassification { model_id: String::from(label), image_data: Vec::new() }
+    }
+
+    fn generate(max_tokens: u32) -> InferRequest {
+        InferRequest::TextGeneration { model_id: String::from("gen"), prompt: String::new(), max_tokens }
+    }
+
+    fn job(client: ClientId, task: u64, items: Vec<InferRequest>) -> Job {
+        let batch = items.len() > 1;
+        Job::new(client, task, String::from("test"), Some(task), items, batch, 0)
+    }
+
+    /// Stands in for `run_slice`: a classification finishes in one slice, a
+    /// generation after one slice per `SLICE_TOKENS` tokens. Results carry
+    /// the request's model ID as their label.
+    fn run_slice(job: &mut Job) {
+        job.slices += 1;
+        let finished = match &job.items[job.results.len()] {
+            InferRequest::ImageClassification { model_id, .. } => Some(model_id.clone()),
+            InferRequest::TextGeneration { model_id, max_tokens, .. } => (job.slices * SLICE_TOKENS >= *max_tokens).then(|| model_id.clone()),
+            _ => None,
+        };
+        if let Some(label) = finished {
+            job.results.push(InferResponse::ImageClassificationResult { class_labels: vec![label], probabilities: Vec::new() });
+        }
+    }
+
+    /// Runs slices until the queue is empty. Returns the slice number each
+    /// job finished on, with the job.
+    fn drain(queue: &mut WorkQueue) -> Vec<(u32, Job)> {
+        let mut finished = Vec::new();
+        let mut slice = 0;
+        while let Some(mut job) = queue.next() {
+            slice += 1;
+            run_slice(&mut job);
+            if let Some(job) = queue.finish_slice(job) {
+                finished.push((slice, job));
+            }
+        }
+        finished
+    }
+
+    fn label(response: &InferResponse) -> &str {
+        match response {
+            InferResponse::ImageClassificationResult { class_labels, .. } => &class_labels[0],
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn short_requests_interleave_with_a_long_generation() {
+        let mut queue = WorkQueue::new();
+        assert!(queue.push(job(ALICE, 10, vec![generate(256)])).is_ok());
+        for label in ["b1", "b2", "b3"] {
+            assert!(queue.push(job(BOB, 20, vec![classify(label)])).is_ok());
+        }
+        let finished = drain(&mut queue);
+        let order: Vec<(u32, u64)> = finished.iter().map(|(slice, job)| (*slice, job.task)).collect();
+        assert_eq!(order, [(2, 20), (4, 20), (6, 20), (35, 10)]);
+        assert_eq!(finished[3].1.slices, 32);
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[test]
+    fn a_clients_requests_finish_in_order() {
+        let mut queue = WorkQueue::new();
+        for label in ["one", "two", "three"] {
+            assert!(queue.push(job(BOB, 20, vec![classify(label)])).is_ok());
+        }
+        let labels: Vec<String> = drain(&mut queue).into_iter()
+            .map(|(_, job)| String::from(label(&job.into_response())))
+            .collect();
+        assert_eq!(labels, ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn batches_answer_in_item_order() {
+        let mut queue = WorkQueue::new();
+        assert!(queue.push(job(BOB, 20, vec![classify("x"), classify("y"), classify("z")])).is_ok());
+        let mut finished = drain(&mut queue);
+        let (slice, job) = finished.pop().unwrap();
+        assert_eq!(slice, 3);
+        match job.into_response() {
+            InferResponse::BatchResult { results } => {
+                assert_eq!(results.iter().map(label).collect::<Vec<_>>(), ["x", "y", "z"]);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn in_flight_limit_is_per_client() {
+        let mut queue = WorkQueue::new();
+        // Two tasks of one Aid share the limit.
+        for task in [10, 11, 10, 11] {
+            assert!(queue.push(job(ALICE, task, vec![classify("a")])).is_ok());
+        }
+        let (refused, in_flight) = queue.push(job(ALICE, 12, vec![classify("a")])).unwrap_err();
+        assert_eq!((refused.task, in_flight), (12, MAX_IN_FLIGHT));
+        assert_eq!(queue.in_flight(&ALICE), MAX_IN_FLIGHT);
+        // Another client isn't affected.
+        assert!(queue.push(job(BOB, 20, vec![classify("b")])).is_ok());
+
+        let mut first = queue.next().unwrap();
+        run_slice(&mut first);
+        assert!(queue.finish_slice(first).is_some());
+        assert_eq!(queue.in_flight(&ALICE), MAX_IN_FLIGHT - 1);
+        assert!(queue.push(job(ALICE, 12, vec![classify("a")])).is_ok());
+        assert_eq!(queue.depth(), 5);
+    }
+
+    #[test]
+    fn tasks_of_one_aid_share_a_turn() {
+        let mut queue = WorkQueue::new();
+        assert!(queue.push(job(ALICE, 10, vec![classify("a")])).is_ok());
+        assert!(queue.push(job(ALICE, 11, vec![classify("a")])).is_ok());
+        assert!(queue.push(job(BOB, 20, vec![classify("b")])).is_ok());
+        let tasks: Vec<u64> = drain(&mut queue).iter().map(|(_, job)| job.task).collect();
+        assert_eq!(tasks, [10, 20, 11]);
+    }
+
+    #[test]
+    fn cancel_drops_only_that_tasks_jobs() {
+        let mut queue = WorkQueue::new();
+        assert!(queue.push(job(ALICE, 10, vec![classify("a")])).is_ok());
+        assert!(queue.push(job(ALICE, 11, vec![classify("a")])).is_ok());
+        assert!(queue.push(job(ALICE, 10, vec![classify("a")])).is_ok());
+        assert!(queue.push(job(BOB, 20, vec![classify("b")])).is_ok());
+
+        let cancelled = queue.cancel(&ALICE, 10);
+        assert_eq!(cancelled.len(), 2);
+        assert!(cancelled.iter().all(|job| job.task == 10));
+        assert_eq!(queue.in_flight(&ALICE), 1);
+        assert!(queue.cancel(&ALICE, 99).is_empty());
+        assert!(queue.cancel(&ClientId::Task(99), 99).is_empty());
+
+        let tasks: Vec<u64> = drain(&mut queue).iter().map(|(_, job)| job.task).collect();
+        assert_eq!(tasks, [11, 20]);
+    }
+
+    #[test]
+    fn cancelling_a_clients_last_job_gives_up_its_turn() {
+        let mut queue = WorkQueue::new();
+        assert!(queue.push(job(ALICE, 10, vec![generate(64)])).is_ok());
+        assert!(queue.push(job(BOB, 20, vec![classify("b")])).is_ok());
+        let mut running = queue.next().unwrap();
+        run_slice(&mut running);
+        assert!(queue.finish_slice(running).is_none());
+
+        assert_eq!(queue.cancel(&ALICE, 10).len(), 1);
+        assert_eq!(queue.in_flight(&ALICE), 0);
+        assert_eq!(queue.depth(), 1);
+        assert_eq!(queue.next().map(|job| job.task), Some(20));
+        assert!(queue.next().is_none());
+    }
+}
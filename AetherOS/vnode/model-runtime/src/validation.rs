@@ -0,0 +1,252 @@
+// vnode/model-runtime/src/validation.rs
+
+//! Per-model input constraints, loaded from `/models/<id>/meta`.
+//!
+//! The metadata file is plain `key=value` lines; blank lines and lines starting
+//! with `#` are ignored:
+//!
+//! ```text
+//! backend=gguf
+//! max_image_bytes=262144
+//! image_width=224
+//! image_height=224
+//! max_prompt_chars=2048
+//! max_tokens=256
+//! ```
+//!
+//! `backend` names the inference backend that reads the weights (see
+//! `backend::BACKENDS`) and is required. A model only accepts the request
+//! kinds its metadata declares limits for.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use common::ipc::model_runtime_ipc::{InferRequest, InputConstraint, MAX_BATCH_ITEMS};
+
+/// Longest prefix of a client-supplied payload that is written to the log.
+pub const LOG_PREVIEW_CHARS: usize = 64;
+
+/// Input limits declared by a model.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelMeta {
+    /// Checked against the known backends when the model is loaded.
+    pub backend: Option<String>,
+    pub max_image_bytes: Option<u64>,
+    /// Expected input dimensions. Parsed now; enforced once there is a real image decoder.
+    pub image_width: Option<u32>,
+    pub image_height: Option<u32>,
+    pub max_prompt_chars: Option<u64>,
+    pub max_tokens: Option<u32>,
+}
+
+/// Parses a metadata file. Unknown keys and malformed values are errors, so a
+/// typo can't silently leave a limit unset.
+pub fn parse_meta(text: &str) -> Result<ModelMeta, String> {
+    let mut meta = ModelMeta::default();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| format!("line {}: expected key=value", number + 1))?;
+        let (key, value) = (key.trim(), value.trim());
+        let bad_value = || format!("line {}: invalid value '{}' for '{}'", number + 1, value, key);
+        match key {
+            "backend" if !value.is_empty() => meta.backend = Some(String::from(value)),
+            "backend" => return Err(bad_value()),
+            "max_image_bytes" => meta.max_image_bytes = Some(value.parse().map_err(|_| bad_value())?),
+            "image_width" => meta.image_width = Some(value.parse().map_err(|_| bad_value())?),
+            "image_height" => meta.image_height = Some(value.parse().map_err(|_| bad_value())?),
+            "max_prompt_chars" => meta.max_prompt_chars = Some(value.parse().map_err(|_| bad_value())?),
+            "max_tokens" => meta.max_tokens = Some(value.parse().map_err(|_| bad_value())?),
+            _ => return Err(format!("line {}: unknown key '{}'", number + 1, key)),
+        }
+    }
+    Ok(meta)
+}
+
+/// Checks a request against the model's declared limits.
+pub fn validate(meta: &ModelMeta, request: &InferRequest) -> Result<(), InputConstraint> {
+    match request {
+        InferRequest::ImageClassification { image_data, .. } => {
+            let limit = meta.max_image_bytes.ok_or(InputConstraint::UnsupportedInput)?;
+            let actual = image_data.len() as u64;
+            if actual > limit {
+                return Err(InputConstraint::MaxImageBytes { limit, actual });
+            }
+        },
+        InferRequest::TextGeneration { prompt, max_tokens, .. } => {
+            let (char_limit, token_limit) = match (meta.max_prompt_chars, meta.max_tokens) {
+                (Some(chars), Some(tokens)) => (chars, tokens),
+                _ => return Err(InputConstraint::UnsupportedInput),
+            };
+            let actual = prompt.chars().count() as u64;
+            if actual > char_limit {
+                return Err(InputConstraint::MaxPromptChars { limit: char_limit, actual });
+            }
+            if *max_tokens == 0 || *max_tokens > token_limit {
+                return Err(InputConstraint::MaxTokens { limit: token_limit, requested: *max_tokens });
+            }
+        },
+        // Their items are checked one by one; see `check_batch` for the batch itself.
+        InferRequest::Batch { .. } | InferRequest::Metrics(_) => {},
+    }
+    Ok(())
+}
+
+/// The model and kind of request an item asks for; `None` for a batch or
+/// a metrics request, which can't be batch items.
+pub fn target(request: &InferRequest) -> Option<(&str, u8)> {
+    match request {
+        InferRequest::ImageClassification { model_id, .. } => Some((model_id, 0)),
+        InferRequest::TextGeneration { model_id, .. } => Some((model_id, 1)),
+        InferRequest::Batch { .. } | InferRequest::Metrics(_) => None,
+    }
+}
+
+/// Checks that a batch has between 1 and `MAX_BATCH_ITEMS` requests, all of
+/// one kind against one model.
+pub fn check_batch(requests: &[InferRequest]) -> Result<(), InputConstraint> {
+    let actual = requests.len().min(u32::MAX as usize) as u32;
+    if actual == 0 || actual > MAX_BATCH_ITEMS {
+        return Err(InputConstraint::BatchSize { limit: MAX_BATCH_ITEMS, actual });
+    }
+    let first = target(&requests[0]).ok_or(InputConstraint::MixedBatch)?;
+    if requests[1..].iter().any(|request| target(request) != Some(first)) {
+        return Err(InputConstraint::MixedBatch);
+    }
+    Ok(())
+}
+
+/// Shortens client text for logging, marking how much was cut.
+pub fn preview(text: &str) -> String {
+    let total = text.chars().count();
+    if total <= LOG_PREVIEW_CHARS {
+        return String::from(text);
+    }
+    format!("{}... ({} more chars)", common::text::truncate_to_chars(text, LOG_PREVIEW_CHARS), total - LOG_PREVIEW_CHARS)
+}
+
+/// One-line summary of a request that never includes raw payload bytes.
+pub fn describe(request: &InferRequest) -> String {
+    match request {
+        InferRequest::ImageClassification { model_id, image_data } => {
+            format!("ImageClassification {{ model_id: '{}', image_data: {} bytes }}", preview(model_id), image_data.len())
+        },
+        InferRequest::TextGeneration { model_id, prompt, max_tokens } => {
+            format!("TextGeneration {{ model_id: '{}', prompt: '{}', max_tokens: {} }}", preview(model_id), preview(prompt), max_tokens)
+        },
+        InferRequest::Batch { requests } => match requests.first() {
+            Some(first) => format!("Batch of {} {{ first: {} }}", requests.len(), describe(first)),
+            None => String::from("Batch of 0"),
+        },
+        InferRequest::Metrics(_) => String::from("Metrics"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn text_meta() -> ModelMeta {
+        ModelMeta { max_prompt_chars: Some(8), max_tokens: Some(16), ..ModelMeta::default() }
+    }
+
+    fn image(model_id: &str, bytes: usize) -> InferRequest {
+        InferRequest::ImageClassification { model_id: String::from(model_id), image_data: vec![0; bytes] }
+    }
+
+    fn text(model_id: &str, prompt: &str, max_tokens: u32) -> InferRequest {
+        InferRequest::TextGeneration { model_id: String::from(model_id), prompt: String::from(prompt), max_tokens }
+    }
+
+    #[test]
+    fn parse_meta_reads_every_key() {
+        let meta = parse_meta("# resnet\n\nbackend = gguf\nmax_image_bytes=262144\nimage_width=224\nimage_height=224\nmax_prompt_chars=2048\nmax_tokens=256\n").unwrap();
+        assert_eq!(meta, ModelMeta {
+            backend: Some(String::from("gguf")),
+            max_image_bytes: Some(262144),
+            image_width: Some(224),
+            image_height: Some(224),
+            max_prompt_chars: Some(2048),
+            max_tokens: Some(256),
+        });
+        assert_eq!(parse_meta("").unwrap(), ModelMeta::default());
+    }
+
+    #[test]
+    fn parse_meta_rejects_typos_and_bad_values() {
+        assert_eq!(parse_meta("backend=gguf\nmax_tokns=5").unwrap_err(), "line 2: unknown key 'max_tokns'");
+        assert_eq!(parse_meta("max_tokens").unwrap_err(), "line 1: expected key=value");
+        assert_eq!(parse_meta("max_tokens=-1").unwrap_err(), "line 1: invalid value '-1' for 'max_tokens'");
+        assert_eq!(parse_meta("image_width=4294967296").unwrap_err(), "line 1: invalid value '4294967296' for 'image_width'");
+        assert!(parse_meta("backend=").is_err());
+    }
+
+    #[test]
+    fn image_size_is_checked_against_the_limit() {
+        let meta = ModelMeta { max_image_bytes: Some(100), ..ModelMeta::default() };
+        assert_eq!(validate(&meta, &image("m", 100)), Ok(()));
+        assert_eq!(validate(&meta, &image("m", 101)), Err(InputConstraint::MaxImageBytes { limit: 100, actual: 101 }));
+        assert_eq!(validate(&text_meta(), &image("m", 1)), Err(InputConstraint::UnsupportedInput));
+    }
+
+    #[test]
+    fn prompt_length_counts_chars_not_bytes() {
+        let meta = text_meta();
+        assert_eq!(validate(&meta, &text("m", "\u{4E2D}\u{6587}\u{4E2D}\u{6587}\u{4E2D}\u{6587}\u{4E2D}\u{6587}", 1)), Ok(()));
+        assert_eq!(validate(&meta, &text("m", "123456789", 1)), Err(InputConstraint::MaxPromptChars { limit: 8, actual: 9 }));
+    }
+
+    #[test]
+    fn max_tokens_must_be_between_one_and_the_ceiling() {
+        let meta = text_meta();
+        assert_eq!(validate(&meta, &text("m", "hi", 16)), Ok(()));
+        assert_eq!(validate(&meta, &text("m", "hi", 17)), Err(InputConstraint::MaxTokens { limit: 16, requested: 17 }));
+        assert_eq!(validate(&meta, &text("m", "hi", 0)), Err(InputConstraint::MaxTokens { limit: 16, requested: 0 }));
+    }
+
+    #[test]
+    fn text_needs_both_limits_declared() {
+        let chars_only = ModelMeta { max_prompt_chars: Some(8), ..ModelMeta::default() };
+        assert_eq!(validate(&chars_only, &text("m", "hi", 1)), Err(InputConstraint::UnsupportedInput));
+        let image_only = ModelMeta { max_image_bytes: Some(100), ..ModelMeta::default() };
+        assert_eq!(validate(&image_only, &text("m", "hi", 1)), Err(InputConstraint::UnsupportedInput));
+    }
+
+    #[test]
+    fn batch_size_is_bounded() {
+        assert_eq!(check_batch(&[]), Err(InputConstraint::BatchSize { limit: MAX_BATCH_ITEMS, actual: 0 }));
+        let full: Vec<_> = (0..MAX_BATCH_ITEMS).map(|_| text("m", "hi", 1)).collect();
+        assert_eq!(check_batch(&full), Ok(()));
+        let over: Vec<_> = (0..=MAX_BATCH_ITEMS).map(|_| text("m", "hi", 1)).collect();
+        assert_eq!(check_batch(&over), Err(InputConstraint::BatchSize { limit: MAX_BATCH_ITEMS, actual: MAX_BATCH_ITEMS + 1 }));
+    }
+
+    #[test]
+    fn batch_items_share_one_model_and_kind() {
+        assert_eq!(check_batch(&[image("a", 1), image("a", 2)]), Ok(()));
+        assert_eq!(check_batch(&[image("a", 1), image("b", 1)]), Err(InputConstraint::MixedBatch));
+        assert_eq!(check_batch(&[image("a", 1), text("a", "hi", 1)]), Err(InputConstraint::MixedBatch));
+        let nested = InferRequest::Batch { requests: vec![image("a", 1)] };
+        assert_eq!(check_batch(&[nested]), Err(InputConstraint::MixedBatch));
+        let nested = InferRequest::Batch { requests: vec![image("a", 1)] };
+        assert_eq!(check_batch(&[image("a", 1), nested]), Err(InputConstraint::MixedBatch));
+    }
+
+    #[test]
+    fn previews_cut_long_text_without_raw_bytes() {
+        assert_eq!(preview("short"), "short");
+        let long = "\u{E9}".repeat(LOG_PREVIEW_CHARS + 5);
+        let cut = preview(&long);
+        assert!(cut.starts_with(&long[..LOG_PREVIEW_CHARS * 2]));
+        assert!(cut.ends_with("... (5 more chars)"));
+        assert_eq!(describe(&image("m", 3)), "ImageClassification { model_id: 'm', image_data: 3 bytes }");
+        assert_eq!(describe(&InferRequest::Batch { requests: Vec::new() }), "Batch of 0");
+    }
+}
@@ -12,6 +12,7 @@ use alloc::format;
 use common::ipc::vnode::VNodeChannel;
 use common::syscall::{syscall3, SYS_LOG, SYS_IRQ_REGISTER, SYS_NET_RX_POLL, SUCCESS, E_ERROR, SYS_NET_ALLOC_BUF, SYS_NET_FREE_BUF, SYS_NET_TX, SYS_IRQ_ACK, SYS_GET_DMA_BUF_PTR, SYS_SET_DMA_BUF_LEN, SYS_IPC_RECV_NONBLOCKING};
 use common::ipc::net_ipc::NetPacketMsg;
+use common::channels;
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -68,14 +69,12 @@ fn net_tx(iface_id: u64, buf_handle: u64, len: u64) -> Result<(), u64> {
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Net-bridge's own channel ID (to receive IRQ events from kernel)
-    // For simplicity, we'll hardcode it to 2. This channel also receives
-    // TxPacket messages from the AetherNet service.
-    let mut own_chan = VNodeChannel::new(2);
+    // Net-bridge's own channel (to receive IRQ events from kernel). This
+    // channel also receives TxPacket messages from the AetherNet service.
+    let mut own_chan = VNodeChannel::new(channels::NET_BRIDGE);
 
     // Channel to the AetherNet service V-Node (for sending RxPacket and receiving TxPacket messages)
-    // Hardcoded to 3 as defined for aethernet-service's client_chan in its main.rs.
-    let mut net_stack_chan = VNodeChannel::new(3);
+    let mut net_stack_chan = VNodeChannel::new(channels::NET_STACK);
 
     log("Net-Bridge V-Node starting up...");
 
@@ -99,7 +98,7 @@ pub extern "C" fn _start() -> ! {
             SYS_IRQ_REGISTER,
             11 as u64, // IRQ number for VirtIO-Net
             own_chan.id as u64, // Channel ID to route IRQ events
-            0 // arg3 is unused
+            0 // flags: don't take over IRQ 11 if another live driver owns it
         );
         if res == SUCCESS {
             log("Net-Bridge: Registered IRQ 11 successfully.");
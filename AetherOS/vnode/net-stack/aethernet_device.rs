@@ -9,7 +9,7 @@ use smoltcp::time::Instant;
 use smoltcp::wire::{EthernetAddress, HardwareAddress};
 
 use crate::ipc::vnode::VNodeChannel;
-use crate::syscall::{syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_NET_ALLOC_BUF, SYS_NET_FREE_BUF, SYS_GET_DMA_BUF_PTR, syscall2, E_ACC_DENIED, SYS_SET_DMA_BUF_LEN};
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_NET_ALLOC_BUF, SYS_NET_FREE_BUF, SYS_GET_DMA_BUF_PTR, E_ACC_DENIED, SYS_SET_DMA_BUF_LEN};
 use crate::ipc::net_ipc::NetPacketMsg;
 
 // Temporary log function for V-Nodes
@@ -28,7 +28,7 @@ fn log(msg: &str) {
 // Temporary syscall for allocating a DMA buffer
 pub fn net_alloc_buf(size: usize) -> Result<u64, u64> {
     unsafe {
-        let handle = syscall2(SYS_NET_ALLOC_BUF, size as u64, 0);
+        let handle = syscall3(SYS_NET_ALLOC_BUF, size as u64, 0, 0);
         if handle == E_ERROR || handle == E_ACC_DENIED { Err(handle) } else { Ok(handle) }
     }
 }
@@ -36,7 +36,7 @@ pub fn net_alloc_buf(size: usize) -> Result<u64, u64> {
 // Temporary syscall for freeing a DMA buffer
 pub fn net_free_buf(handle: u64) -> Result<(), u64> {
     unsafe {
-        let res = syscall2(SYS_NET_FREE_BUF, handle, 0);
+        let res = syscall3(SYS_NET_FREE_BUF, handle, 0, 0);
         if res != SUCCESS { Err(res) } else { Ok(()) }
     }
 }
@@ -53,7 +53,7 @@ pub fn net_tx(_iface_id: u64, _buf_handle: u64, _len: u64) -> Result<(), u64> {
 // Temporary syscall to get pointer to DMA buffer
 pub fn get_dma_buffer_ptr(handle: u64) -> Result<*mut u8, u64> {
     unsafe {
-        let ptr = syscall2(SYS_GET_DMA_BUF_PTR, handle, 0);
+        let ptr = syscall3(SYS_GET_DMA_BUF_PTR, handle, 0, 0);
         if ptr == E_ERROR || ptr == E_ACC_DENIED { Err(ptr) } else { Ok(ptr as *mut u8) }
     }
 }
@@ -61,7 +61,7 @@ pub fn get_dma_buffer_ptr(handle: u64) -> Result<*mut u8, u64> {
 // Temporary syscall to set DMA buffer length
 pub fn set_dma_buffer_len(handle: u64, len: usize) -> Result<(), u64> {
     unsafe {
-        let res = syscall2(SYS_SET_DMA_BUF_LEN, handle, len as u64);
+        let res = syscall3(SYS_SET_DMA_BUF_LEN, handle, len as u64, 0);
         if res != SUCCESS { Err(res) } else { Ok(()) }
     }
 }
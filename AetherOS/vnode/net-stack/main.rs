@@ -15,7 +15,7 @@ use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, Ipv4Add
 use smoltcp::time::Instant;
 
 use crate::ipc::vnode::VNodeChannel;
-use crate::syscall::{syscall2, syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_TIME};
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_TIME};
 use crate::ipc::net_ipc::{NetPacketMsg, NetStackRequest, NetStackResponse};
 
 mod aethernet_device;
@@ -37,7 +37,7 @@ fn log(msg: &str) {
 // Get current time from kernel
 fn get_current_time_ms() -> u64 {
     // Assuming SYS_TIME returns ticks, convert to ms for smoltcp Instant
-    unsafe { syscall2(SYS_TIME, 0, 0) * 10 } // Assuming 1 tick = 10 ms for demo
+    unsafe { syscall3(SYS_TIME, 0, 0, 0) * 10 } // Assuming 1 tick = 10 ms for demo
 }
 
 #[no_mangle]
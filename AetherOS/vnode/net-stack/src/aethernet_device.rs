@@ -12,7 +12,10 @@ use smoltcp::wire::{EthernetAddress, HardwareAddress};
 
 use crate::ipc::vnode::VNodeChannel;
 use crate::syscall::{syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_NET_ALLOC_BUF, SYS_NET_FREE_BUF, SYS_GET_DMA_BUF_PTR, SYS_SET_DMA_BUF_LEN, SYS_NET_TX};
-use crate::ipc::net_ipc::NetPacketMsg;
+use crate::ipc::net_ipc::{CaptureDirection, NetPacketMsg};
+use crate::neighbors::NeighborTable;
+use crate::capture::Capture;
+use crate::conntrack::ConnTracker;
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -62,7 +65,7 @@ pub fn set_dma_buffer_len(handle: u64, len: usize) -> Result<(), u64> {
 /// Represents a single received packet buffer for smoltcp.
 pub struct PacketRxToken<'a> {
     buffer: &'a mut [u8],
-    dma_handle: u64,
+    dma_handle: Option<u64>, // None for frames injected by the stack itself
 }
 
 impl<'a> RxToken for PacketRxToken<'a> {
@@ -73,8 +76,10 @@ impl<'a> RxToken for PacketRxToken<'a> {
         // The smoltcp stack consumes the packet data
         let result = f(self.buffer);
         // After consumption, free the DMA buffer
-        if let Err(e) = net_free_buf(self.dma_handle) {
-            log(&alloc::format!("AetherNetDevice: Failed to free RX DMA buffer (handle {}): {:?}", self.dma_handle, e));
+        if let Some(dma_handle) = self.dma_handle {
+            if let Err(e) = net_free_buf(dma_handle) {
+                log(&alloc::format!("AetherNetDevice: Failed to free RX DMA buffer (handle {}): {:?}", dma_handle, e));
+            }
         }
         result
     }
@@ -87,10 +92,12 @@ pub struct PacketTxToken<'a> {
     len: usize,
     iface_id: u64,
     net_bridge_chan_id: u32, // Channel ID to net-bridge V-Node
+    capture: &'a mut Capture,
+    connections: &'a mut ConnTracker,
 }
 
 impl<'a> TxToken for PacketTxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> R
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
@@ -105,6 +112,9 @@ impl<'a> TxToken for PacketTxToken<'a> {
             return result;
         }
 
+        self.capture.tap(CaptureDirection::Tx, &self.buffer[..self.len], crate::instant_ticks(timestamp));
+        self.connections.observe(CaptureDirection::Tx, &self.buffer[..self.len], crate::instant_ticks(timestamp));
+
         // Send the filled buffer's DMA handle and length to net-bridge for transmission
         let mut net_bridge_chan = VNodeChannel::new(self.net_bridge_chan_id);
         let msg = NetPacketMsg::TxPacket { dma_handle: self.dma_handle, len: self.len as u64 };
@@ -121,6 +131,11 @@ pub struct AetherNetDevice {
     iface_id: u64, // Interface ID, typically 0 for the first NIC
     net_bridge_chan_id: u32, // Channel ID to net-bridge V-Node for TxPacket and RxPacket
     rx_packet_queue: VecDeque<(u64, u64)>, // Queue of (dma_handle, len) for received packets
+    injected: VecDeque<Vec<u8>>, // Frames the stack feeds to smoltcp itself (static ARP entries)
+    current_injected: Vec<u8>, // The injected frame handed out by the last `receive`
+    neighbors: NeighborTable, // Sees every received frame before smoltcp does
+    capture: Capture, // Sees every frame from and to net-bridge
+    connections: ConnTracker, // Sees the frames from and to net-bridge that reach smoltcp
 }
 
 impl AetherNetDevice {
@@ -129,12 +144,66 @@ impl AetherNetDevice {
             iface_id,
             net_bridge_chan_id: net_bridge_channel_id,
             rx_packet_queue: VecDeque::new(),
+            injected: VecDeque::new(),
+            current_injected: Vec::new(),
+            neighbors: NeighborTable::new(),
+            capture: Capture::new(),
+            connections: ConnTracker::disabled(),
         }
     }
 
     pub fn enqueue_rx_packet(&mut self, dma_handle: u64, len: u64) {
         self.rx_packet_queue.push_back((dma_handle, len));
     }
+
+    /// Queues a frame for smoltcp as if it had arrived from the wire. It is
+    /// delivered ahead of frames from net-bridge and bypasses the neighbor table.
+    pub fn inject_rx_frame(&mut self, frame: Vec<u8>) {
+        self.injected.push_back(frame);
+    }
+
+    /// Drops every received frame smoltcp hasn't taken yet, freeing the DMA
+    /// buffers of those from net-bridge. Returns how many buffers were freed.
+    pub fn discard_rx(&mut self) -> usize {
+        self.injected.clear();
+        let mut freed = 0;
+        while let Some((dma_handle, _len)) = self.rx_packet_queue.pop_front() {
+            match net_free_buf(dma_handle) {
+                Ok(()) => freed += 1,
+                Err(e) => log(&alloc::format!("AetherNetDevice: Failed to free RX DMA buffer (handle {}): {:?}", dma_handle, e)),
+            }
+        }
+        freed
+    }
+
+    pub fn neighbors(&self) -> &NeighborTable {
+        &self.neighbors
+    }
+
+    pub fn neighbors_mut(&mut self) -> &mut NeighborTable {
+        &mut self.neighbors
+    }
+
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+
+    pub fn capture_mut(&mut self) -> &mut Capture {
+        &mut self.capture
+    }
+
+    /// Replaces the connection tracker, e.g. with an enabled one once the setting is read.
+    pub fn track_connections(&mut self, tracker: ConnTracker) {
+        self.connections = tracker;
+    }
+
+    pub fn connections(&self) -> &ConnTracker {
+        &self.connections
+    }
+
+    pub fn connections_mut(&mut self) -> &mut ConnTracker {
+        &mut self.connections
+    }
 }
 
 impl<'a> Device<'a> for AetherNetDevice {
@@ -150,15 +219,33 @@ impl<'a> Device<'a> for AetherNetDevice {
         caps
     }
 
-    fn receive(&'a mut self, _timestamp: Instant) -> Option<(Self::RxToken, Self::TxToken)> {
+    fn receive(&'a mut self, timestamp: Instant) -> Option<(Self::RxToken, Self::TxToken)> {
+        if let Some(frame) = self.injected.pop_front() {
+            self.current_injected = frame;
+            return Some((
+                PacketRxToken { buffer: &mut self.current_injected[..], dma_handle: None },
+                PacketTxToken { buffer: &mut [], dma_handle: 0, len: 0, iface_id: self.iface_id, net_bridge_chan_id: self.net_bridge_chan_id, capture: &mut self.capture, connections: &mut self.connections },
+            ));
+        }
+
         // Consume from the queue of packets pushed by net-bridge
-        if let Some((dma_handle, len)) = self.rx_packet_queue.pop_front() {
+        while let Some((dma_handle, len)) = self.rx_packet_queue.pop_front() {
             if let Ok(buf_ptr) = get_dma_buffer_ptr(dma_handle) {
                 // SAFETY: `buf_ptr` is obtained from a kernel DMA manager, pointing to a valid buffer.
                 // `len` is also provided by the kernel, guaranteeing the slice is within bounds.
                 let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr, len as usize) };
-                Some((
-                    PacketRxToken { buffer, dma_handle }, 
+                self.capture.tap(CaptureDirection::Rx, buffer, crate::instant_ticks(timestamp));
+                if !self.neighbors.observe_frame(buffer, timestamp.total_millis() as u64) {
+                    // ARP traffic contradicting a static entry never reaches smoltcp.
+                    log(&alloc::format!("AetherNetDevice: Dropped ARP frame conflicting with a static neighbor (handle {}).", dma_handle));
+                    if let Err(e) = net_free_buf(dma_handle) {
+                        log(&alloc::format!("AetherNetDevice: Failed to free RX DMA buffer (handle {}): {:?}", dma_handle, e));
+                    }
+                    continue;
+                }
+                self.connections.observe(CaptureDirection::Rx, buffer, crate::instant_ticks(timestamp));
+                return Some((
+                    PacketRxToken { buffer, dma_handle: Some(dma_handle) }, 
                     // Dummy TxToken for receive path, as receive doesn't directly transmit
                     PacketTxToken {
                         buffer: &mut [],
@@ -166,20 +253,21 @@ impl<'a> Device<'a> for AetherNetDevice {
                         len: 0,
                         iface_id: self.iface_id,
                         net_bridge_chan_id: self.net_bridge_chan_id,
+                        capture: &mut self.capture,
+                        connections: &mut self.connections,
                     }
-                ))
+                ));
             } else {
                 log(&alloc::format!("AetherNetDevice: Failed to get buffer pointer for RX DMA handle {}. Freeing it.", dma_handle));
                 // Free the DMA buffer if ptr is invalid, as it's unusable.
                 if let Err(e) = net_free_buf(dma_handle) { 
                     log(&alloc::format!("AetherNetDevice: Failed to free RX DMA buffer (ptr error, queue) {}: {:?}", dma_handle, e)); 
                 }
-                None
+                return None;
             }
-        } else {
-            // No packets from net-bridge in queue
-            None
         }
+        // No packets from net-bridge in queue
+        None
     }
 
     fn transmit(&'a mut self, _timestamp: Instant) -> Option<Self::TxToken> {
@@ -195,7 +283,7 @@ impl<'a> Device<'a> for AetherNetDevice {
             // SAFETY: `buf_ptr` is obtained from a kernel DMA manager, pointing to a valid buffer.
             // `TX_BUFFER_SIZE` is the allocated capacity, guaranteeing the slice is within bounds.
             let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr, TX_BUFFER_SIZE) };
-            Some(PacketTxToken { buffer, dma_handle, len: 0, iface_id: self.iface_id, net_bridge_chan_id: self.net_bridge_chan_id })
+            Some(PacketTxToken { buffer, dma_handle, len: 0, iface_id: self.iface_id, net_bridge_chan_id: self.net_bridge_chan_id, capture: &mut self.capture, connections: &mut self.connections })
         } else {
             log(&alloc::format!("AetherNetDevice: Failed to get buffer pointer for TX DMA handle {}. Freeing it.", dma_handle));
             // If we can't get a pointer, the buffer is unusable, so free it.
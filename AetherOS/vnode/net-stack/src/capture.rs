@@ -0,0 +1,171 @@
+// vnode/net-stack/src/capture.rs
+
+//! Packet capture: copies of the frames the device receives and transmits.
+//!
+//! The device hands every frame from net-bridge, and every frame it sends
+//! there, to `Capture::tap`. While a capture runs, frames that pass its
+//! filter are cut to the snap length and copied into the ring with the tick
+//! they were seen at. The ring never grows past its byte limit and nothing
+//! in it is evicted: once a copy doesn't fit it is dropped and counted, and
+//! the frame itself goes on as if there were no capture. Frames the stack
+//! injects for smoltcp (static ARP entries) never came from the wire and
+//! aren't captured.
+//!
+//! `pcap_header` and `pcap_records` give the ring as a classic pcap file
+//! (microsecond timestamps, little-endian, link type Ethernet), which
+//! Wireshark and tcpdump read. Timestamps count from boot, not from 1970.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::ipc::net_ipc::{CaptureDirection, CaptureFilter, CaptureStats};
+
+pub const PCAP_HEADER_LEN: usize = 24;
+/// Each frame's header in the file; the ring counts it against its limit too.
+pub const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4; // Microsecond timestamps
+const PCAP_VERSION: (u16, u16) = (2, 4);
+const LINKTYPE_ETHERNET: u32 = 1;
+const TICK_US: u64 = 10_000; // One timer tick is 10 ms
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+struct Record {
+    ticks: u64,
+    orig_len: u32, // The frame's length before the snap length cut it
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct Capture {
+    running: bool,
+    filter: Option<CaptureFilter>,
+    snaplen: u32,
+    max_bytes: u32,
+    records: Vec<Record>,
+    bytes: u64, // Record headers and data in `records`
+    matched: u64,
+    dropped: u64,
+}
+
+impl Capture {
+    /// An idle capture with an empty ring.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new capture, dropping what the previous one kept.
+    pub fn start(&mut self, filter: Option<CaptureFilter>, snaplen: u32, max_bytes: u32) {
+        *self = Self { running: true, filter, snaplen, max_bytes, ..Self::default() };
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        CaptureStats {
+            running: self.running,
+            packets: self.records.len() as u32,
+            bytes: self.bytes,
+            matched: self.matched,
+            dropped: self.dropped,
+        }
+    }
+
+    /// Copies `frame` into the ring if a capture is running, the frame passes
+    /// the filter and its copy fits. Never waits and never fails the frame.
+    pub fn tap(&mut self, direction: CaptureDirection, frame: &[u8], ticks: u64) {
+        if !self.running || frame.is_empty() {
+            return;
+        }
+        if let Some(filter) = &self.filter {
+            if !matches(filter, direction, frame) {
+                return;
+            }
+        }
+        self.matched += 1;
+        let kept = frame.len().min(self.snaplen as usize);
+        let cost = (PCAP_RECORD_HEADER_LEN + kept) as u64;
+        if self.bytes + cost > self.max_bytes as u64 {
+            self.dropped += 1;
+            return;
+        }
+        self.records.push(Record { ticks, orig_len: frame.len() as u32, data: frame[..kept].to_vec() });
+        self.bytes += cost;
+    }
+
+    /// The pcap global header for this capture's snap length.
+    pub fn pcap_header(&self) -> [u8; PCAP_HEADER_LEN] {
+        let mut header = [0u8; PCAP_HEADER_LEN];
+        header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&PCAP_VERSION.0.to_le_bytes());
+        header[6..8].copy_from_slice(&PCAP_VERSION.1.to_le_bytes());
+        // Bytes 8..16, the time zone offset and timestamp accuracy, stay zero.
+        header[16..20].copy_from_slice(&self.snaplen.to_le_bytes());
+        header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        header
+    }
+
+    /// Each captured frame as a pcap record: its header followed by the kept bytes.
+    pub fn pcap_records(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.records.iter().map(|record| {
+            let mut out = Vec::with_capacity(PCAP_RECORD_HEADER_LEN + record.data.len());
+            out.extend_from_slice(&((record.ticks * TICK_US / 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&((record.ticks * TICK_US % 1_000_000) as u32).to_le_bytes());
+            out.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&record.orig_len.to_le_bytes());
+            out.extend_from_slice(&record.data);
+            out
+        })
+    }
+}
+
+/// Whether `frame` passes `filter`. A frame too short to have the field a
+/// filter asks about doesn't pass.
+pub fn matches(filter: &CaptureFilter, direction: CaptureDirection, frame: &[u8]) -> bool {
+    if filter.direction.map_or(false, |wanted| wanted != direction) {
+        return false;
+    }
+    let Some(ethertype) = frame.get(12..14).map(|b| u16::from_be_bytes([b[0], b[1]])) else {
+        return false;
+    };
+    if filter.ethertype.map_or(false, |wanted| wanted != ethertype) {
+        return false;
+    }
+    if filter.ip_protocol.is_none() && filter.port.is_none() {
+        return true;
+    }
+    if ethertype != ETHERTYPE_IPV4 {
+        return false;
+    }
+    let ip = &frame[ETHERNET_HEADER_LEN..];
+    let (Some(&version_ihl), Some(&protocol)) = (ip.first(), ip.get(9)) else {
+        return false;
+    };
+    if filter.ip_protocol.map_or(false, |wanted| wanted != protocol) {
+        return false;
+    }
+    let Some(port) = filter.port else {
+        return true;
+    };
+    // Only the first fragment carries the ports.
+    let fragment_offset = ip.get(6..8).map_or(1, |b| u16::from_be_bytes([b[0], b[1]]) & 0x1FFF);
+    if (protocol != IP_PROTOCOL_TCP && protocol != IP_PROTOCOL_UDP) || fragment_offset != 0 {
+        return false;
+    }
+    let header_len = (version_ihl & 0x0F) as usize * 4;
+    match ip.get(header_len..header_len + 4) {
+        Some(ports) => u16::from_be_bytes([ports[0], ports[1]]) == port || u16::from_be_bytes([ports[2], ports[3]]) == port,
+        None => false,
+    }
+}
@@ -0,0 +1,374 @@
+// vnode/net-stack/src/conntrack.rs
+
+//! Connection tracking: what happened to each TCP connection and UDP flow,
+//! kept after it ended.
+//!
+//! The device shows the tracker every IPv4 frame it exchanges with net-bridge.
+//! A TCP connection is tracked from its first SYN, in either direction; a UDP
+//! flow, keyed by local port and remote address and port, from its first
+//! datagram. Each frame adds to its connection's packet and payload byte
+//! counts. After every poll, `sync` compares the TCP sockets' states with the
+//! last ones seen and records the changes with their tick.
+//!
+//! A connection ends when a reset goes either way, when its socket reaches
+//! `TimeWait` or `Closed`, or when its socket is closed. UDP flows, and TCP
+//! connections no socket ever took up, end after `IDLE_TICKS` without a frame.
+//! While the interface goes down, everything that ends counts as ended by
+//! that. Ended connections move to a ring of the last `CONNECTION_HISTORY_LEN`.
+//!
+//! Records are fixed-size; a packet never allocates. Opening a connection
+//! adds a table entry, and no more than `MAX_OPEN` are tracked at once:
+//! connections beyond that are counted and otherwise ignored. Nothing is
+//! written to storage.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use smoltcp::socket::TcpState;
+
+use crate::ipc::net_ipc::{
+    CaptureDirection, CloseReason, ConnectionHistory, ConnectionRecord, FlowProtocol, StateChange, TcpConnState, CONNECTION_HISTORY_LEN,
+};
+use crate::metrics::{Counter, Gauge, Registry};
+
+/// The most connections tracked at once.
+pub const MAX_OPEN: usize = 1024;
+/// How long a UDP flow, or a TCP connection without a socket, lives without traffic.
+pub const IDLE_TICKS: u64 = 6_000; // 60 s
+/// TCP state changes kept per connection; a normal close takes five.
+const MAX_TRANSITIONS: usize = 8;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+const IN: usize = 0;
+const OUT: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct FlowKey {
+    protocol: u8, // IP protocol number
+    local_port: u16,
+    remote_ip: [u8; 4],
+    remote_port: u16,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    key: FlowKey,
+    opened_tick: u64,
+    closed_tick: u64,
+    reason: Option<CloseReason>,
+    packets: [u64; 2], // Indexed by IN and OUT
+    bytes: [u64; 2],
+    transitions: [StateChange; MAX_TRANSITIONS],
+    transition_count: usize,
+    transitions_dropped: u32,
+}
+
+impl Record {
+    fn push_transition(&mut self, state: TcpConnState, tick: u64) {
+        if self.transition_count == MAX_TRANSITIONS {
+            self.transitions_dropped += 1;
+            return;
+        }
+        self.transitions[self.transition_count] = StateChange { state, tick };
+        self.transition_count += 1;
+    }
+
+    fn to_wire(&self) -> ConnectionRecord {
+        ConnectionRecord {
+            protocol: if self.key.protocol == IP_PROTOCOL_TCP { FlowProtocol::Tcp } else { FlowProtocol::Udp },
+            local_port: self.key.local_port,
+            remote_ip: self.key.remote_ip,
+            remote_port: self.key.remote_port,
+            opened_tick: self.opened_tick,
+            closed_tick: self.reason.map(|_| self.closed_tick),
+            reason: self.reason,
+            packets_in: self.packets[IN],
+            packets_out: self.packets[OUT],
+            bytes_in: self.bytes[IN],
+            bytes_out: self.bytes[OUT],
+            transitions: self.transitions[..self.transition_count].to_vec(),
+            transitions_dropped: self.transitions_dropped,
+        }
+    }
+}
+
+struct Flow {
+    record: Record,
+    last_seen: u64,
+    state: Option<TcpConnState>, // Last state `sync` saw
+    fins: [bool; 2], // FIN seen, by direction
+    has_socket: bool, // A socket took it up
+    synced: u64, // The `sync` round that last saw its socket
+}
+
+struct TrackerMetrics {
+    tracked: Gauge,
+    closed: [Counter; 6], // Indexed by `CloseReason as usize`
+    untracked: Counter,
+}
+
+pub struct ConnTracker {
+    metrics: Option<TrackerMetrics>, // None while tracking is turned off
+    open: BTreeMap<FlowKey, Flow>,
+    closed: VecDeque<Record>, // Oldest first
+    evicted: u64,
+    untracked: u64,
+    going_down: bool,
+    round: u64,
+}
+
+impl ConnTracker {
+    /// A tracker that records nothing, for `net.connection_history` off.
+    pub fn disabled() -> Self {
+        Self { metrics: None, open: BTreeMap::new(), closed: VecDeque::new(), evicted: 0, untracked: 0, going_down: false, round: 0 }
+    }
+
+    pub fn new(metrics: &mut Registry) -> Self {
+        const CLOSED: &str = "Tracked connections that ended, by why.";
+        let reason = |metrics: &mut Registry, label| metrics.counter_with("net_connections_closed_total", CLOSED, &[("reason", label)]);
+        let metrics = TrackerMetrics {
+            tracked: metrics.gauge("net_connections_tracked", "TCP connections and UDP flows tracked now."),
+            closed: [
+                reason(metrics, "fin"),
+                reason(metrics, "reset_received"),
+                reason(metrics, "reset_sent"),
+                reason(metrics, "idle_timeout"),
+                reason(metrics, "interface_down"),
+                reason(metrics, "closed_locally"),
+            ],
+            untracked: metrics.counter("net_connections_untracked_total", "Connections not tracked because the table was full."),
+        };
+        Self { metrics: Some(metrics), closed: VecDeque::with_capacity(CONNECTION_HISTORY_LEN), ..Self::disabled() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.metrics.is_some()
+    }
+
+    /// Counts a frame from or to net-bridge against its connection, starting
+    /// to track one for a SYN or a new UDP flow. Ends it on a reset.
+    pub fn observe(&mut self, direction: CaptureDirection, frame: &[u8], tick: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        let Some(segment) = parse_segment(frame) else {
+            return;
+        };
+        let (side, key) = match direction {
+            CaptureDirection::Rx => (IN, FlowKey { protocol: segment.protocol, local_port: segment.dst_port, remote_ip: segment.src_ip, remote_port: segment.src_port }),
+            CaptureDirection::Tx => (OUT, FlowKey { protocol: segment.protocol, local_port: segment.src_port, remote_ip: segment.dst_ip, remote_port: segment.dst_port }),
+        };
+        let tcp = segment.protocol == IP_PROTOCOL_TCP;
+        if !self.open.contains_key(&key) {
+            // Stray TCP segments of connections not tracked, or ended, are left alone.
+            if tcp && (segment.flags & TCP_SYN == 0 || segment.flags & TCP_ACK != 0) {
+                return;
+            }
+            if self.open.len() >= MAX_OPEN {
+                self.untracked += 1;
+                self.with_metrics(|metrics| metrics.untracked.inc());
+                return;
+            }
+            let record = Record {
+                key,
+                opened_tick: tick,
+                closed_tick: 0,
+                reason: None,
+                packets: [0; 2],
+                bytes: [0; 2],
+                transitions: [StateChange { state: TcpConnState::Closed, tick: 0 }; MAX_TRANSITIONS],
+                transition_count: 0,
+                transitions_dropped: 0,
+            };
+            self.open.insert(key, Flow { record, last_seen: tick, state: None, fins: [false; 2], has_socket: false, synced: 0 });
+            self.update_gauge();
+        }
+
+        let flow = self.open.get_mut(&key).expect("just checked or inserted");
+        flow.record.packets[side] += 1;
+        flow.record.bytes[side] += segment.payload_len as u64;
+        flow.last_seen = tick;
+        if tcp && segment.flags & TCP_FIN != 0 {
+            flow.fins[side] = true;
+        }
+        if tcp && segment.flags & TCP_RST != 0 {
+            self.end(key, tick, if side == IN { CloseReason::ResetReceived } else { CloseReason::ResetSent });
+        }
+    }
+
+    /// Records the state of every TCP socket with a peer, as local port,
+    /// remote address, remote port and state, and ends the connections
+    /// whose socket is done or gone. Call after every poll.
+    pub fn sync(&mut self, sockets: impl Iterator<Item = (u16, [u8; 4], u16, TcpState)>, tick: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.round += 1;
+        for (local_port, remote_ip, remote_port, state) in sockets {
+            let key = FlowKey { protocol: IP_PROTOCOL_TCP, local_port, remote_ip, remote_port };
+            let (Some(flow), Some(state)) = (self.open.get_mut(&key), conn_state(state)) else {
+                continue;
+            };
+            flow.has_socket = true;
+            flow.synced = self.round;
+            if flow.state != Some(state) {
+                flow.state = Some(state);
+                flow.record.push_transition(state, tick);
+            }
+        }
+
+        let round = self.round;
+        let ended: Vec<(FlowKey, CloseReason)> = self.open.iter().filter(|(_, flow)| flow.has_socket).filter_map(|(key, flow)| {
+            let finished = flow.fins == [true; 2];
+            let reason = match flow.state {
+                Some(TcpConnState::TimeWait) => CloseReason::Fin,
+                _ if flow.synced != round => if finished { CloseReason::Fin } else { CloseReason::ClosedLocally },
+                // Closed without a reset: both FINs, or smoltcp gave up retransmitting.
+                Some(TcpConnState::Closed) => if finished { CloseReason::Fin } else { CloseReason::IdleTimeout },
+                _ => return None,
+            };
+            Some((*key, reason))
+        }).collect();
+        for (key, reason) in ended {
+            self.end(key, tick, reason);
+        }
+    }
+
+    /// Ends UDP flows, and TCP connections no socket took up, that have been
+    /// quiet for `IDLE_TICKS`.
+    pub fn expire(&mut self, tick: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        let idle: Vec<FlowKey> = self.open.iter()
+            .filter(|(_, flow)| !flow.has_socket && tick.saturating_sub(flow.last_seen) >= IDLE_TICKS)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in idle {
+            self.end(key, tick, CloseReason::IdleTimeout);
+        }
+    }
+
+    /// Connections that end from now until `end_all` ended because the
+    /// interface went down, whatever the frames say.
+    pub fn begin_interface_down(&mut self) {
+        self.going_down = true;
+    }
+
+    /// Ends every connection still open, as ended by the interface going down.
+    pub fn end_all(&mut self, tick: u64) {
+        let keys: Vec<FlowKey> = self.open.keys().copied().collect();
+        for key in keys {
+            self.end(key, tick, CloseReason::InterfaceDown);
+        }
+        self.going_down = false;
+    }
+
+    fn end(&mut self, key: FlowKey, tick: u64, reason: CloseReason) {
+        let Some(flow) = self.open.remove(&key) else {
+            return;
+        };
+        let reason = if self.going_down { CloseReason::InterfaceDown } else { reason };
+        let mut record = flow.record;
+        record.closed_tick = tick;
+        record.reason = Some(reason);
+        if self.closed.len() == CONNECTION_HISTORY_LEN {
+            self.closed.pop_front();
+            self.evicted += 1;
+        }
+        self.closed.push_back(record);
+        self.with_metrics(|metrics| metrics.closed[reason as usize].inc());
+        self.update_gauge();
+    }
+
+    /// The open connections, oldest first, and the last `max` closed ones, most recent first.
+    pub fn history(&self, max: usize) -> ConnectionHistory {
+        let mut open: Vec<&Flow> = self.open.values().collect();
+        open.sort_by_key(|flow| flow.record.opened_tick);
+        ConnectionHistory {
+            enabled: self.is_enabled(),
+            open: open.into_iter().map(|flow| flow.record.to_wire()).collect(),
+            closed: self.closed.iter().rev().take(max).map(Record::to_wire).collect(),
+            evicted: self.evicted,
+            untracked: self.untracked,
+        }
+    }
+
+    fn update_gauge(&self) {
+        let open = self.open.len() as i64;
+        if let Some(metrics) = &self.metrics {
+            metrics.tracked.set(open);
+        }
+    }
+
+    fn with_metrics(&self, f: impl FnOnce(&TrackerMetrics)) {
+        if let Some(metrics) = &self.metrics {
+            f(metrics);
+        }
+    }
+}
+
+fn conn_state(state: TcpState) -> Option<TcpConnState> {
+    Some(match state {
+        TcpState::Listen => return None,
+        TcpState::SynSent => TcpConnState::SynSent,
+        TcpState::SynReceived => TcpConnState::SynReceived,
+        TcpState::Established => TcpConnState::Established,
+        TcpState::FinWait1 => TcpConnState::FinWait1,
+        TcpState::FinWait2 => TcpConnState::FinWait2,
+        TcpState::CloseWait => TcpConnState::CloseWait,
+        TcpState::Closing => TcpConnState::Closing,
+        TcpState::LastAck => TcpConnState::LastAck,
+        TcpState::TimeWait => TcpConnState::TimeWait,
+        TcpState::Closed => TcpConnState::Closed,
+    })
+}
+
+struct Segment {
+    protocol: u8,
+    src_ip: [u8; 4],
+    dst_ip: [u8; 4],
+    src_port: u16,
+    dst_port: u16,
+    flags: u8, // TCP only
+    payload_len: usize,
+}
+
+/// The addresses, ports and payload length of an IPv4 TCP or UDP frame.
+/// Fragments after the first carry no ports and aren't counted.
+fn parse_segment(frame: &[u8]) -> Option<Segment> {
+    if frame.get(12..14)? != ETHERTYPE_IPV4.to_be_bytes() {
+        return None;
+    }
+    let ip = &frame[ETHERNET_HEADER_LEN..];
+    let header_len = (*ip.first()? & 0x0F) as usize * 4;
+    let total_len = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+    let fragment_offset = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x1FFF;
+    let protocol = *ip.get(9)?;
+    if fragment_offset != 0 || (protocol != IP_PROTOCOL_TCP && protocol != IP_PROTOCOL_UDP) {
+        return None;
+    }
+    let src_ip: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+    let dst_ip: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+    let transport = ip.get(header_len..total_len.min(ip.len()))?;
+    let src_port = u16::from_be_bytes([*transport.first()?, *transport.get(1)?]);
+    let dst_port = u16::from_be_bytes([*transport.get(2)?, *transport.get(3)?]);
+    let (flags, payload_len) = if protocol == IP_PROTOCOL_TCP {
+        let data_offset = (*transport.get(12)? >> 4) as usize * 4;
+        (*transport.get(13)?, transport.len().checked_sub(data_offset)?)
+    } else {
+        let udp_len = u16::from_be_bytes([*transport.get(4)?, *transport.get(5)?]) as usize;
+        (0, udp_len.checked_sub(8)?)
+    };
+    Some(Segment { protocol, src_ip, dst_ip, src_port, dst_port, flags, payload_len })
+}
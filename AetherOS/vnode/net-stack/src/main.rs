@@ -7,19 +7,54 @@ use core::panic::PanicInfo;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::format;
+use alloc::string::{String, ToString};
 
-use smoltcp::iface::{Config, Interface, SocketSet, QueryInterface};
+use smoltcp::iface::{Config, Interface, QueryInterface};
 use smoltcp::phy::Checksum;
-use smoltcp::socket::{TcpSocket, UdpSocket};
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, Ipv4Address, ETHERNET_MTU};
+use smoltcp::socket::UdpSocket;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, ETHERNET_MTU};
 use smoltcp::time::Instant;
 
 use crate::ipc::vnode::VNodeChannel;
 use crate::syscall::{syscall3, SYS_LOG, SUCCESS, E_ERROR, SYS_TIME};
-use crate::ipc::net_ipc::{NetPacketMsg, NetStackRequest, NetStackResponse};
+use crate::ipc::net_ipc::{ConnectionReady, InterfaceInfo, NetPacketMsg, NetStackRequest, NetStackResponse, SocketClosed, MAX_BACKLOG};
+use crate::ipc::net_ipc::{SOCKET_API_SERVICE, CONNECTION_READY_TOPIC, NET_DOWN_TOPIC, NET_UP_TOPIC, SOCKET_CLOSED_TOPIC, MAX_CAPTURE_BYTES, MAX_SNAPLEN, CONNECTION_HISTORY_LEN};
+use crate::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse, STREAM_CHUNK_SIZE};
+use crate::ipc::vfs_stream::VfsStreams;
+use crate::ipc::event_ipc::{EventBusRequest, EventBusResponse};
+use crate::ipc::init_ipc::{InitRequest, InitResponse, ServiceTarget};
+use crate::ipc::session_ipc::{self, SYSTEM_AID};
+use crate::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use crate::metrics::Registry;
+use crate::channels;
+use crate::abi::TASK_STATE_EXITED;
+use crate::ids::{Millis, Ticks};
+use crate::tasks;
+use crate::time;
 
 mod aethernet_device;
-use aethernet_device::AetherNetDevice;
+use aethernet_device::{net_free_buf, AetherNetDevice};
+
+mod sockets;
+use sockets::{new_tcp_socket, SendError, SocketQuotas, SocketTable};
+
+mod sendq;
+
+mod neighbors;
+
+mod capture;
+use capture::{Capture, PCAP_RECORD_HEADER_LEN};
+
+mod conntrack;
+use conntrack::ConnTracker;
+
+const OWN_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+const OWN_IP: [u8; 4] = [10, 0, 2, 15];
+const OWN_PREFIX_LEN: u8 = 24;
+/// How long startup waits for svc://settings before keeping the default limits (5 s).
+const SETTINGS_WAIT: Ticks = Ticks::from_secs(5);
+/// The task name of svc://settings.
+const SETTINGS_TASK_NAME: &str = "settings";
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -34,17 +69,212 @@ fn log(msg: &str) {
     }
 }
 
-// Get current time from kernel (assuming 1 tick = 10 ms for demo)
+// Get current time from kernel, in milliseconds since boot.
 fn get_current_time_ms() -> u64 {
-    unsafe { syscall3(SYS_TIME, 0, 0, 0) * 10 }
+    time::ticks().to_millis().raw()
+}
+
+/// The tick a smoltcp timestamp falls in, which is what the connection
+/// tracker and captures record.
+fn instant_ticks(timestamp: Instant) -> u64 {
+    Millis::from_raw(timestamp.total_millis() as u64).to_ticks_floor().raw()
+}
+
+/// Creates the smoltcp interface with our hardware and IP address. Called again
+/// by `FlushNeighbors`, since smoltcp offers no other way to clear its neighbor cache.
+fn new_interface(device: &mut AetherNetDevice) -> Interface {
+    let config = Config::new(HardwareAddress::Ethernet(EthernetAddress(OWN_MAC)));
+    let mut iface = Interface::new(config, device, Instant::from_millis(get_current_time_ms()));
+    iface.update_ip_addrs(|addrs| {
+        addrs.push(IpCidr::new(IpAddress::v4(OWN_IP[0], OWN_IP[1], OWN_IP[2], OWN_IP[3]), OWN_PREFIX_LEN)).unwrap();
+    });
+    iface
+}
+
+/// Takes the interface down. Connections are ended and a last poll sends
+/// their FINs and RSTs; the tracker records them all as ended by the
+/// interface going down. Then every socket is freed, received frames still
+/// queued are dropped with their DMA buffers, and dynamic neighbors are
+/// forgotten. Returns the closed sockets with their owners.
+fn take_down(iface: &mut Interface, device: &mut AetherNetDevice, sockets: &mut SocketTable, timestamp: Instant) -> Vec<(u32, u64)> {
+    device.connections_mut().begin_interface_down();
+    let (fins, resets) = sockets.shut_down_all();
+    iface.poll(timestamp, device, sockets.set_mut());
+    let closed = sockets.drain();
+    device.connections_mut().end_all(instant_ticks(timestamp));
+    let freed = device.discard_rx();
+    let forgotten = device.neighbors_mut().flush(false);
+    log(&alloc::format!("AetherNet: Interface down. Closed {} sockets ({} FIN, {} RST), dropped {} queued frames, forgot {} neighbors.", closed.len(), fins, resets, freed, forgotten));
+    closed
+}
+
+/// Configures the interface again. The address is static, so this is a fresh
+/// smoltcp interface with it, and the static neighbors pushed into it; the
+/// old interface's neighbor cache went stale while it was down.
+fn bring_up(device: &mut AetherNetDevice) -> Interface {
+    let iface = new_interface(device);
+    for (ip, mac) in device.neighbors().statics() {
+        push_static_neighbor(device, ip, mac);
+    }
+    log(&alloc::format!("AetherNet: Interface up at {}.", IpAddress::v4(OWN_IP[0], OWN_IP[1], OWN_IP[2], OWN_IP[3])));
+    iface
+}
+
+fn interface_info(up: bool) -> InterfaceInfo {
+    InterfaceInfo { mac: OWN_MAC, ip: OWN_IP, prefix_len: OWN_PREFIX_LEN, up }
+}
+
+/// Publishes an event on svc://event-bus. Failures are logged; the state
+/// change stands either way.
+fn publish<T: serde::Serialize>(event_bus_chan: &mut VNodeChannel, topic: &str, payload: &T) {
+    let request = match postcard::to_allocvec(payload) {
+        Ok(payload) => EventBusRequest::Publish { topic: topic.into(), payload },
+        Err(_) => return,
+    };
+    if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+        log(&alloc::format!("AetherNet: Failed to publish {}.", topic));
+    }
+}
+
+/// Makes smoltcp learn `ip` -> `mac` by feeding it an ARP reply from that neighbor.
+fn push_static_neighbor(device: &mut AetherNetDevice, ip: [u8; 4], mac: [u8; 6]) {
+    device.inject_rx_frame(neighbors::arp_reply_frame(ip, mac, OWN_IP, OWN_MAC));
+}
+
+/// Whether `ip` is an IPv4 multicast address (224.0.0.0/4).
+fn is_multicast(ip: &[u8; 4]) -> bool {
+    ip[0] & 0xF0 == 0xE0
+}
+
+/// Joins or leaves a multicast group on the interface. smoltcp sends the IGMP
+/// report or leave message itself.
+fn set_membership(iface: &mut Interface, device: &mut AetherNetDevice, group: [u8; 4], join: bool, timestamp: Instant) -> bool {
+    let addr = Ipv4Address::new(group[0], group[1], group[2], group[3]);
+    let result = if join {
+        iface.join_multicast_group(device, addr, timestamp)
+    } else {
+        iface.leave_multicast_group(device, addr, timestamp)
+    };
+    if let Err(e) = &result {
+        log(&alloc::format!("AetherNet: Could not {} multicast group {}: {:?}", if join { "join" } else { "leave" }, addr, e));
+    }
+    result.is_ok()
+}
+
+/// Whether `task` is an instance of socket-api, the one service trusted to
+/// ask on behalf of other tasks. Init started it, so init's answer counts,
+/// not the name the task runs under. Task IDs are never reused, so answers
+/// are cached; a task init doesn't know is asked about again.
+fn is_socket_api(task: u64, init_chan: &mut VNodeChannel, known: &mut BTreeMap<u64, bool>) -> bool {
+    if let Some(&relay) = known.get(&task) {
+        return relay;
+    }
+    match init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ServiceStatus { target: ServiceTarget::Instance(task) }) {
+        Ok(InitResponse::Instances(instances)) if !instances.is_empty() => {
+            let relay = instances.iter().any(|instance| instance.service_name == SOCKET_API_SERVICE);
+            known.insert(task, relay);
+            relay
+        },
+        _ => false,
+    }
+}
+
+/// Waits up to `SETTINGS_WAIT` for svc://settings to be running. A call to it
+/// before then would block until it starts, which it may never do.
+fn wait_for_settings() -> bool {
+    let deadline = time::ticks().saturating_add(SETTINGS_WAIT);
+    loop {
+        let running = tasks::list()
+            .into_iter()
+            .filter_map(tasks::stats)
+            .any(|stats| stats.name() == SETTINGS_TASK_NAME && stats.state != TASK_STATE_EXITED);
+        if running {
+            return true;
+        }
+        if time::ticks() >= deadline {
+            return false;
+        }
+        unsafe { syscall3(SYS_TIME, 0, 0, 0); } // Yield to other V-Nodes
+    }
+}
+
+/// Reads the socket limits from svc://settings, keeping the defaults for any
+/// value that can't be fetched, and for all of them if settings isn't up
+/// within `SETTINGS_WAIT`.
+fn load_socket_quotas(settings_chan: &mut VNodeChannel, settings_up: bool) -> SocketQuotas {
+    let mut quotas = SocketQuotas::default();
+    if !settings_up {
+        return quotas;
+    }
+    let mut fetch = |key: &str| match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.into() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Int(n), .. }) if n > 0 => Some(n as u32),
+        _ => None,
+    };
+    if let Some(per_task) = fetch("net.max_sockets_per_task") {
+        quotas.per_task = per_task;
+    }
+    if let Some(total) = fetch("net.max_sockets_total") {
+        quotas.total = total;
+    }
+    quotas
+}
+
+/// Whether `net.connection_history` allows tracking connections. On unless
+/// the setting says otherwise, or settings isn't up.
+fn load_connection_history(settings_chan: &mut VNodeChannel, settings_up: bool) -> bool {
+    !settings_up || !matches!(
+        settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: "net.connection_history".into() }),
+        Ok(SettingsResponse::Value { value: SettingValue::Bool(false), .. })
+    )
+}
+
+/// Answers a send-side request that `SocketTable` refused.
+fn send_error(handle: u32, requester: u64, error: SendError) -> NetStackResponse {
+    match error {
+        SendError::NotFound => {
+            log(&alloc::format!("AetherNet: Socket {} not found for task {}.", handle, requester));
+            NetStackResponse::Error(103)
+        },
+        SendError::NotTcp => NetStackResponse::Error(102), // Not a TCP socket
+        SendError::NotConnected | SendError::QueueFull => {
+            log(&alloc::format!("AetherNet: TCP socket {} cannot send ({:?}).", handle, error));
+            NetStackResponse::Error(104) // Cannot send
+        },
+    }
+}
+
+/// Writes the capture ring to `path` as a pcap file through svc://vfs,
+/// replacing the file if it exists. Returns the bytes written.
+fn dump_capture(vfs_chan: &mut VNodeChannel, capture: &Capture, path: &str) -> Result<u64, String> {
+    let fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 1 /* O_WRONLY | O_CREAT | O_TRUNC */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as Fd,
+        Ok(VfsResponse::Error { message, .. }) => return Err(message),
+        _ => return Err("Unexpected response from VFS".to_string()),
+    };
+    let mut streams = VfsStreams::new(vfs_chan);
+    let written = (|| {
+        let writer = streams.open_write(fd, 0)?;
+        // Records go out batched into chunks rather than one message each.
+        let mut chunk = capture.pcap_header().to_vec();
+        for record in capture.pcap_records() {
+            if chunk.len() + record.len() > STREAM_CHUNK_SIZE {
+                streams.write(writer, core::mem::take(&mut chunk))?;
+            }
+            chunk.extend_from_slice(&record);
+        }
+        streams.write(writer, chunk)?;
+        streams.finish(writer)
+    })();
+    let _ = streams.request(&VfsRequest::Close { fd });
+    written
 }
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     // Channel for requests from other V-Nodes (Socket API)
-    let mut own_chan = VNodeChannel::new(3);
+    let mut own_chan = VNodeChannel::new(channels::NET_STACK);
     // Channel for data plane communication with net-bridge (RxPackets, TxPacketAcks)
-    let mut bridge_data_chan = VNodeChannel::new(2);
+    let mut bridge_data_chan = VNodeChannel::new(channels::NET_BRIDGE);
 
     log("AetherNet Service V-Node starting up...");
 
@@ -52,34 +282,61 @@ pub extern "C" fn _start() -> ! {
     // Pass the channel ID for net-bridge communication
     let mut device = AetherNetDevice::new(0, bridge_data_chan.id);
 
-    // 2. Configure smoltcp interface
-    let ethernet_addr = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
-    let config = Config::new(HardwareAddress::Ethernet(ethernet_addr));
-    let mut iface = Interface::new(config, &mut device, Instant::from_millis(get_current_time_ms()));
+    // 2. Configure smoltcp interface with a static IP address
+    let mut iface = new_interface(&mut device);
+    log(&alloc::format!("AetherNet: IP Address set to {}", IpAddress::v4(OWN_IP[0], OWN_IP[1], OWN_IP[2], OWN_IP[3])));
 
-    // Assign a static IP address
-    iface.update_ip_addrs(|addrs| {
-        addrs.push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24)).unwrap();
-    });
-    log(&alloc::format!("AetherNet: IP Address set to {}", IpAddress::v4(10,0,2,15)));
+    // 3. Socket management. Storage grows on demand; limits come from svc://settings.
+    let mut settings_chan = VNodeChannel::new(channels::SETTINGS);
+    let settings_up = wait_for_settings();
+    if !settings_up {
+        log("AetherNet: svc://settings isn't running; using the default socket limits.");
+    }
+    let quotas = load_socket_quotas(&mut settings_chan, settings_up);
+    log(&alloc::format!("AetherNet: Socket limits: {} per task, {} total.", quotas.per_task, quotas.total));
+    let mut metrics = Registry::new("net-stack");
+    let mut sockets = SocketTable::new(quotas, &mut metrics);
+    if load_connection_history(&mut settings_chan, settings_up) {
+        device.track_connections(ConnTracker::new(&mut metrics));
+    } else {
+        log("AetherNet: Connection history is turned off (net.connection_history).");
+    }
+
+    // Interface state changes and the sockets they close are announced on svc://event-bus.
+    let mut event_bus_chan = VNodeChannel::new(channels::EVENT_BUS);
+    let mut iface_up = true;
 
-    // 3. Initialize smoltcp SocketSet
-    let mut sockets_storage_tcp = [None; 8]; // Example: 8 TCP sockets
-    let mut sockets_storage_udp = [None; 8]; // Example: 8 UDP sockets
-    let mut sockets = SocketSet::new(sockets_storage_tcp.iter_mut().chain(sockets_storage_udp.iter_mut()));
+    // Packet captures are dumped to files through svc://vfs.
+    let mut vfs_chan = VNodeChannel::new(channels::VFS);
 
-    // 4. Socket Management
-    let mut next_socket_handle: u32 = 1;
-    let mut smoltcp_sockets_map: BTreeMap<u32, smoltcp::socket::SocketHandle> = BTreeMap::new(); // Maps our handle to smoltcp's
+    // Init tells which tasks are socket-api, whose `ForTask` is honoured.
+    let mut init_chan = VNodeChannel::new(channels::INIT_SERVICE);
+    let mut relays: BTreeMap<u64, bool> = BTreeMap::new();
 
     // Main event loop for the network stack
     loop {
-        let timestamp = Instant::from_millis(get_current_time_ms());
+        let now_ms = get_current_time_ms();
+        let timestamp = Instant::from_millis(now_ms);
+
+        // Forget neighbors smoltcp has forgotten, and keep static ones in its cache.
+        device.neighbors_mut().expire(now_ms);
+        device.connections_mut().expire(instant_ticks(timestamp));
+        if iface_up {
+            for (ip, mac) in device.neighbors_mut().statics_to_refresh(now_ms) {
+                push_static_neighbor(&mut device, ip, mac);
+            }
+        }
 
         // --- Handle Incoming Messages from net-bridge V-Node via IPC --- (from net-bridge to aethernet_device)
         if let Ok(Some(net_msg_data)) = bridge_data_chan.recv_non_blocking() {
             if let Ok(net_packet_msg) = postcard::from_bytes::<NetPacketMsg>(&net_msg_data) {
                 match net_packet_msg {
+                    NetPacketMsg::RxPacket { dma_handle, .. } if !iface_up => {
+                        // Nobody polls the device while the interface is down; the frame is dropped.
+                        if let Err(e) = net_free_buf(dma_handle) {
+                            log(&alloc::format!("AetherNet: Failed to free RX DMA buffer (handle {}) while down: {:?}", dma_handle, e));
+                        }
+                    },
                     NetPacketMsg::RxPacket { dma_handle, len } => {
                         log(&alloc::format!("AetherNet: Received RxPacket from net-bridge for handle: {}, len: {}", dma_handle, len));
                         // Enqueue the received packet handle into the device for smoltcp to consume
@@ -98,26 +355,54 @@ pub extern "C" fn _start() -> ! {
 
         // 1. Poll smoltcp interface for network events (e.g., ARP, ICMP, TCP/UDP activity)
         // This call will trigger device.receive() and device.transmit() internally
-        iface.poll(timestamp, &mut device, &mut sockets);
+        if iface_up {
+            // Coalescing timers that ran out, and held data that now fits, go out with this poll.
+            sockets.pump_sends(now_ms);
+            iface.poll(timestamp, &mut device, sockets.set_mut());
+            device.connections_mut().sync(sockets.tcp_peers(), instant_ticks(timestamp));
+            // Listeners' owners learn of connections waiting for them without polling Accept.
+            for (listener, owner, remote) in sockets.newly_established() {
+                let peer_addr = match remote.addr {
+                    IpAddress::Ipv4(addr) => addr.0,
+                    #[allow(unreachable_patterns)]
+                    _ => [0; 4], // The interface only has an IPv4 address
+                };
+                publish(&mut event_bus_chan, CONNECTION_READY_TOPIC, &ConnectionReady { listener, owner, peer_addr, peer_port: remote.port });
+            }
+            for (handle, reply_chan) in sockets.take_readable() {
+                if VNodeChannel::new(reply_chan).send(&NetStackResponse::DataReady { handle }).is_err() {
+                    log(&alloc::format!("AetherNet: Could not tell channel {} that socket {} is readable.", reply_chan, handle));
+                }
+            }
+        }
 
         // 2. Process incoming requests from other V-Nodes (Socket API) -- on own_chan
         if let Ok(Some(req_data)) = own_chan.recv_non_blocking() {
             if let Ok(request) = postcard::from_bytes::<NetStackRequest>(&req_data) {
-                log(&alloc::format!("AetherNet: Received request from another V-Node: {:?}", request));
+                // Sockets are charged to, and only usable by, the task that opened them.
+                // socket-api names the client it relays for; anyone else asks for itself.
+                let sender = session_ipc::last_creds();
+                let (requester, request) = match request {
+                    NetStackRequest::ForTask { task, request } if sender.map_or(false, |creds| is_socket_api(creds.sender, &mut init_chan, &mut relays)) => (task, *request),
+                    request => (sender.map_or(0, |creds| creds.sender), request),
+                };
+                log(&alloc::format!("AetherNet: Received request from task {}: {:?}", requester, request));
                 let response = match request {
-                    NetStackRequest::OpenSocket(sock_type, local_port) => {
-                        let handle = next_socket_handle;
-                        next_socket_handle += 1;
-
-                        let socket_to_add = match sock_type {
+                    NetStackRequest::Send(..)
+                    | NetStackRequest::SendTo(..)
+                    | NetStackRequest::Connect { .. }
+                    | NetStackRequest::JoinMulticastGroup { .. } if !iface_up => NetStackResponse::InterfaceDown,
+                    NetStackRequest::OpenSocket(sock_type, local_port) => match sockets.check_quota(requester, 1) {
+                        Err((quota, limit)) => {
+                            log(&alloc::format!("AetherNet: Task {} hit the {:?} socket limit ({}).", requester, quota, limit));
+                            NetStackResponse::QuotaExceeded(quota, limit)
+                        },
+                        Ok(()) => match sock_type {
                             0 => { // TCP
                                 log(&alloc::format!("AetherNet: Opening TCP socket on port {}", local_port));
-                                let mut socket = TcpSocket::new(
-                                    smoltcp::socket::TcpSocketBuffer::new(alloc::vec![0; 1024]), // Rx buffer
-                                    smoltcp::socket::TcpSocketBuffer::new(alloc::vec![0; 1024]), // Tx buffer
-                                );
+                                let mut socket = new_tcp_socket();
                                 if local_port != 0 { socket.listen(local_port).unwrap(); }
-                                socket
+                                NetStackResponse::SocketOpened(sockets.insert(requester, socket))
                             },
                             1 => { // UDP
                                 log(&alloc::format!("AetherNet: Opening UDP socket on port {}", local_port));
@@ -126,136 +411,108 @@ pub extern "C" fn _start() -> ! {
                                     smoltcp::socket::UdpSocketBuffer::new(alloc::vec![0; 1024]), // Tx buffer
                                 );
                                 if local_port != 0 { socket.bind(local_port).unwrap(); }
-                                socket
+                                NetStackResponse::SocketOpened(sockets.insert(requester, socket))
                             },
                             _ => {
                                 log(&alloc::format!("AetherNet: Invalid socket type {}", sock_type));
                                 NetStackResponse::Error(100) // Invalid socket type, cannot create socket
                             }
-                        };
-
-                        if let NetStackResponse::Error(_) = socket_to_add {
-                            socket_to_add // Propagate error if socket creation failed
-                        } else {
-                            // Add socket to management
-                            let smoltcp_socket_handle = sockets.add(socket_to_add.unwrap()); // Unwrap because we know it's not an Error
-                            smoltcp_sockets_map.insert(handle, smoltcp_socket_handle);
-                            NetStackResponse::SocketOpened(handle)
-                        }
+                        },
                     },
                     NetStackRequest::Send(handle, data) => {
                         log(&alloc::format!("AetherNet: Sending {} bytes on socket {}", data.len(), handle));
-                        if let Some(smoltcp_handle) = smoltcp_sockets_map.get(&handle) {
-                            if let Some(socket) = sockets.get_mut(*smoltcp_handle) {
-                                match socket {
-                                    smoltcp::socket::Socket::Tcp(s) => {
-                                        if s.can_send() {
-                                            s.send_slice(&data).unwrap_or(0); // Send data, ignoring partial sends for now
-                                            NetStackResponse::Success
-                                        } else {
-                                            log(&alloc::format!("AetherNet: TCP socket {} cannot send (buffer full or not connected)", handle));
-                                            NetStackResponse::Error(104) // Cannot send
-                                        }
-                                    },
-                                    _ => {
-                                        log(&alloc::format!("AetherNet: Socket {} is not a TCP socket for Send request.", handle));
-                                        NetStackResponse::Error(102) // Not a TCP/UDP socket
-                                    },
-                                }
-                            } else {
-                                log(&alloc::format!("AetherNet: Smoltcp Socket not found for handle {}.", handle));
-                                NetStackResponse::Error(103)
-                            }
-                        } else {
-                            log(&alloc::format!("AetherNet: Our handle {} not found in map.", handle));
-                            NetStackResponse::Error(103)
+                        match sockets.send(handle, requester, &data, now_ms) {
+                            Ok(()) => NetStackResponse::Success,
+                            Err(e) => send_error(handle, requester, e),
                         }
                     },
                     NetStackRequest::SendTo(handle, remote_ip, remote_port, data) => {
                         log(&alloc::format!("AetherNet: Sending {} bytes to {}.{}.{}:{}{} on UDP socket {}", data.len(), remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3], remote_port, handle));
-                        if let Some(smoltcp_handle) = smoltcp_sockets_map.get(&handle) {
-                            if let Some(socket) = sockets.get_mut(*smoltcp_handle) {
-                                match socket {
-                                    smoltcp::socket::Socket::Udp(s) => {
-                                        let remote_endpoint = smoltcp::wire::IpEndpoint::new(
-                                            IpAddress::v4(remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3]),
-                                            remote_port
-                                        );
-                                        if s.can_send() {
-                                            s.send_slice(data.as_slice(), remote_endpoint).unwrap_or(0);
-                                            NetStackResponse::Success
-                                        } else {
-                                            log(&alloc::format!("AetherNet: UDP socket {} cannot send (buffer full)", handle));
-                                            NetStackResponse::Error(104) // Cannot send
-                                        }
-                                    },
-                                    _ => {
-                                        log(&alloc::format!("AetherNet: Socket {} is not a UDP socket for SendTo request.", handle));
-                                        NetStackResponse::Error(102) // Not a UDP socket
-                                    },
-                                }
-                            } else {
-                                log(&alloc::format!("AetherNet: Smoltcp Socket not found for handle {}.", handle));
-                                NetStackResponse::Error(103)
+                        // An unbound socket gets an ephemeral port no other UDP socket is bound to,
+                        // so answers have somewhere to go.
+                        let unbound = matches!(sockets.get_mut(handle, requester), Some(smoltcp::socket::Socket::Udp(s)) if !s.is_open());
+                        let ephemeral = if unbound { sockets.udp_ephemeral_port() } else { None };
+                        if unbound && ephemeral.is_none() {
+                            log(&alloc::format!("AetherNet: No free ephemeral port to bind UDP socket {} to.", handle));
+                            NetStackResponse::Error(120) // No free port
+                        } else if let Some(socket) = sockets.get_mut(handle, requester) {
+                            match socket {
+                                smoltcp::socket::Socket::Udp(s) => {
+                                    if let Some(port) = ephemeral {
+                                        s.bind(port).unwrap_or_else(|_| log(&alloc::format!("AetherNet: Could not bind UDP socket {} to port {}.", handle, port)));
+                                    }
+                                    let remote_endpoint = smoltcp::wire::IpEndpoint::new(
+                                        IpAddress::v4(remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3]),
+                                        remote_port
+                                    );
+                                    if s.can_send() {
+                                        s.send_slice(data.as_slice(), remote_endpoint).unwrap_or(0);
+                                        NetStackResponse::Success
+                                    } else {
+                                        log(&alloc::format!("AetherNet: UDP socket {} cannot send (buffer full)", handle));
+                                        NetStackResponse::Error(104) // Cannot send
+                                    }
+                                },
+                                _ => {
+                                    log(&alloc::format!("AetherNet: Socket {} is not a UDP socket for SendTo request.", handle));
+                                    NetStackResponse::Error(102) // Not a UDP socket
+                                },
                             }
                         } else {
-                            log(&alloc::format!("AetherNet: Our handle {} not found in map.", handle));
+                            log(&alloc::format!("AetherNet: Socket {} not found for task {}.", handle, requester));
                             NetStackResponse::Error(103)
                         }
                     },
                     NetStackRequest::Recv(handle) => {
                         log(&alloc::format!("AetherNet: Receiving on socket {}", handle));
-                        if let Some(smoltcp_handle) = smoltcp_sockets_map.get(&handle) {
-                             if let Some(socket) = sockets.get_mut(*smoltcp_handle) {
-                                match socket {
-                                    smoltcp::socket::Socket::Tcp(s) => {
-                                        if s.can_recv() {
-                                            let mut buffer = alloc::vec![0; s.recv_capacity()];
-                                            if let Ok(size) = s.recv_slice(&mut buffer) {
-                                                buffer.truncate(size);
-                                                NetStackResponse::Data(buffer)
-                                            } else {
-                                                log(&alloc::format!("AetherNet: Failed to recv from TCP socket {} (no data or error)", handle));
-                                                NetStackResponse::Data(alloc::vec![]) // No data
-                                            }
+                        if let Some(socket) = sockets.get_mut(handle, requester) {
+                            match socket {
+                                smoltcp::socket::Socket::Tcp(s) => {
+                                    if s.can_recv() {
+                                        let mut buffer = alloc::vec![0; s.recv_capacity()];
+                                        if let Ok(size) = s.recv_slice(&mut buffer) {
+                                            buffer.truncate(size);
+                                            NetStackResponse::Data(buffer)
                                         } else {
-                                            log(&alloc::format!("AetherNet: TCP socket {} cannot recv (buffer empty or not connected)", handle));
+                                            log(&alloc::format!("AetherNet: Failed to recv from TCP socket {} (no data or error)", handle));
                                             NetStackResponse::Data(alloc::vec![]) // No data
                                         }
-                                    },
-                                    smoltcp::socket::Socket::Udp(s) => {
-                                        if s.can_recv() {
-                                            let mut buffer = alloc::vec![0; s.recv_capacity()];
-                                            if let Ok((size, _endpoint)) = s.recv_slice(&mut buffer) {
-                                                buffer.truncate(size);
-                                                NetStackResponse::Data(buffer)
-                                            } else {
-                                                log(&alloc::format!("AetherNet: Failed to recv from UDP socket {} (no data or error)", handle));
-                                                NetStackResponse::Data(alloc::vec![])
-                                            }
+                                    } else {
+                                        log(&alloc::format!("AetherNet: TCP socket {} cannot recv (buffer empty or not connected)", handle));
+                                        NetStackResponse::Data(alloc::vec![]) // No data
+                                    }
+                                },
+                                smoltcp::socket::Socket::Udp(s) => {
+                                    if s.can_recv() {
+                                        let mut buffer = alloc::vec![0; s.recv_capacity()];
+                                        if let Ok((size, _endpoint)) = s.recv_slice(&mut buffer) {
+                                            buffer.truncate(size);
+                                            NetStackResponse::Data(buffer)
                                         } else {
-                                            log(&alloc::format!("AetherNet: UDP socket {} cannot recv (buffer empty)", handle));
+                                            log(&alloc::format!("AetherNet: Failed to recv from UDP socket {} (no data or error)", handle));
                                             NetStackResponse::Data(alloc::vec![])
                                         }
-                                    },
-                                    _ => {
-                                        log(&alloc::format!("AetherNet: Socket {} is not a TCP/UDP socket for Recv request.", handle));
-                                        NetStackResponse::Error(102) // Not a TCP/UDP socket
-                                    },
-                                }
-                            } else {
-                                log(&alloc::format!("AetherNet: Smoltcp Socket not found for handle {}.", handle));
-                                NetStackResponse::Error(103)
+                                    } else {
+                                        log(&alloc::format!("AetherNet: UDP socket {} cannot recv (buffer empty)", handle));
+                                        NetStackResponse::Data(alloc::vec![])
+                                    }
+                                },
+                                _ => {
+                                    log(&alloc::format!("AetherNet: Socket {} is not a TCP/UDP socket for Recv request.", handle));
+                                    NetStackResponse::Error(102) // Not a TCP/UDP socket
+                                },
                             }
                         } else {
-                            log(&alloc::format!("AetherNet: Our handle {} not found in map.", handle));
+                            log(&alloc::format!("AetherNet: Socket {} not found for task {}.", handle, requester));
                             NetStackResponse::Error(103)
                         }
                     },
                     NetStackRequest::CloseSocket(handle) => {
                         log(&alloc::format!("AetherNet: Closing socket {}", handle));
-                        if let Some(smoltcp_handle) = smoltcp_sockets_map.remove(&handle) {
-                            sockets.remove(*smoltcp_handle);
+                        if let Some(unused_groups) = sockets.remove(handle, requester) {
+                            for group in unused_groups {
+                                set_membership(&mut iface, &mut device, group, false, timestamp);
+                            }
                             NetStackResponse::Success
                         }
                         else {
@@ -263,6 +520,241 @@ pub extern "C" fn _start() -> ! {
                             NetStackResponse::Error(103) // Socket not found
                         }
                     },
+                    NetStackRequest::Listen { port, backlog } => {
+                        if port == 0 || backlog == 0 || backlog > MAX_BACKLOG {
+                            NetStackResponse::Error(113) // Bad port or backlog
+                        } else {
+                            match sockets.check_quota(requester, backlog) {
+                                Err((quota, limit)) => {
+                                    log(&alloc::format!("AetherNet: Task {} hit the {:?} socket limit ({}) opening a backlog of {}.", requester, quota, limit, backlog));
+                                    NetStackResponse::QuotaExceeded(quota, limit)
+                                },
+                                Ok(()) => {
+                                    log(&alloc::format!("AetherNet: Listening on TCP port {} with a backlog of {}", port, backlog));
+                                    NetStackResponse::SocketOpened(sockets.insert_listener(requester, port, backlog))
+                                },
+                            }
+                        }
+                    },
+                    NetStackRequest::Accept(handle) => match sockets.accept(handle, requester) {
+                        Ok(Some((accepted, remote))) => {
+                            let remote_ip = match remote.addr {
+                                IpAddress::Ipv4(addr) => addr.0,
+                                #[allow(unreachable_patterns)]
+                                _ => [0; 4], // The interface only has an IPv4 address
+                            };
+                            log(&alloc::format!("AetherNet: Listener {} accepted {} as socket {}.", handle, remote, accepted));
+                            NetStackResponse::Accepted { handle: accepted, remote_ip, remote_port: remote.port }
+                        },
+                        Ok(None) => NetStackResponse::Error(114), // No connection waiting
+                        Err(()) => NetStackResponse::Error(103), // Not a listener of this task
+                    },
+                    NetStackRequest::Connect { handle, remote_ip, remote_port } => {
+                        let local_port = sockets.ephemeral_port();
+                        match sockets.get_mut(handle, requester) {
+                            Some(smoltcp::socket::Socket::Tcp(s)) => {
+                                let remote = IpEndpoint::new(IpAddress::v4(remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3]), remote_port);
+                                match s.connect(iface.context(), remote, local_port) {
+                                    Ok(()) => {
+                                        log(&alloc::format!("AetherNet: Socket {} connecting to {} from port {}.", handle, remote, local_port));
+                                        NetStackResponse::Success
+                                    },
+                                    Err(e) => {
+                                        log(&alloc::format!("AetherNet: Socket {} cannot connect to {}: {:?}", handle, remote, e));
+                                        NetStackResponse::Error(115) // Already connected, listening, or a bad address
+                                    },
+                                }
+                            },
+                            Some(_) => NetStackResponse::Error(102), // Not a TCP socket
+                            None => NetStackResponse::Error(103),
+                        }
+                    },
+                    NetStackRequest::ConnectStatus(handle) => match sockets.connect_state(handle, requester) {
+                        Some(state) => NetStackResponse::Connection(state),
+                        None => NetStackResponse::Error(103),
+                    },
+                    NetStackRequest::GetSocketInfo(handle) => match sockets.listener_info(handle, requester) {
+                        Some((local_port, info)) => NetStackResponse::SocketInfo { local_port, listener: Some(info) },
+                        None => match (sockets.local_port(handle, requester), sockets.send_stats(handle, requester)) {
+                            (Some(local_port), Some(send)) => NetStackResponse::StreamInfo { local_port, send },
+                            (Some(local_port), None) => NetStackResponse::SocketInfo { local_port, listener: None },
+                            (None, _) => NetStackResponse::Error(103),
+                        },
+                    },
+                    NetStackRequest::Metrics(request) => NetStackResponse::Metrics(metrics.handle(&request)),
+                    NetStackRequest::GetNeighbors => NetStackResponse::Neighbors(device.neighbors().list(now_ms)),
+                    NetStackRequest::AddStaticNeighbor { .. }
+                    | NetStackRequest::RemoveNeighbor { .. }
+                    | NetStackRequest::FlushNeighbors { .. }
+                    | NetStackRequest::SetInterfaceState { .. }
+                    | NetStackRequest::CaptureStart { .. }
+                    | NetStackRequest::CaptureStop
+                    | NetStackRequest::CaptureDump { .. }
+                    | NetStackRequest::GetConnectionHistory { .. } if session_ipc::identity_of(requester) != Some(SYSTEM_AID) => {
+                        log(&alloc::format!("AetherNet: Task {} may not change the neighbor table or the interface state, capture packets or read the connection history.", requester));
+                        NetStackResponse::Error(105) // Permission denied
+                    },
+                    NetStackRequest::AddStaticNeighbor { ip, mac } => {
+                        let subnet = IpCidr::new(IpAddress::v4(OWN_IP[0], OWN_IP[1], OWN_IP[2], OWN_IP[3]), OWN_PREFIX_LEN);
+                        if ip == OWN_IP {
+                            NetStackResponse::Error(106) // Address belongs to this interface
+                        } else if !neighbors::is_unicast_mac(&mac) {
+                            NetStackResponse::Error(107) // Not a unicast MAC address
+                        } else if !subnet.contains_addr(&IpAddress::v4(ip[0], ip[1], ip[2], ip[3])) {
+                            NetStackResponse::Error(108) // Not on a directly connected subnet; smoltcp would ignore it
+                        } else {
+                            log(&alloc::format!("AetherNet: Static neighbor {}.{}.{}.{} -> {:02x?}.", ip[0], ip[1], ip[2], ip[3], mac));
+                            device.neighbors_mut().add_static(ip, mac, now_ms);
+                            push_static_neighbor(&mut device, ip, mac);
+                            NetStackResponse::Success
+                        }
+                    },
+                    NetStackRequest::RemoveNeighbor { ip } => {
+                        if device.neighbors_mut().remove(&ip) {
+                            // smoltcp's copy lapses on its own; without our refreshes it is not renewed.
+                            NetStackResponse::Success
+                        } else {
+                            NetStackResponse::Error(109) // No such neighbor
+                        }
+                    },
+                    NetStackRequest::FlushNeighbors { force } => {
+                        let removed = device.neighbors_mut().flush(force);
+                        iface = new_interface(&mut device);
+                        for (ip, mac) in device.neighbors().statics() {
+                            push_static_neighbor(&mut device, ip, mac);
+                        }
+                        // The new interface starts without multicast memberships.
+                        for group in sockets.joined_groups() {
+                            set_membership(&mut iface, &mut device, group, true, timestamp);
+                        }
+                        log(&alloc::format!("AetherNet: Flushed {} neighbors (force: {}).", removed, force));
+                        NetStackResponse::Success
+                    },
+                    NetStackRequest::JoinMulticastGroup { group, .. } | NetStackRequest::LeaveMulticastGroup { group, .. } if !is_multicast(&group) => {
+                        NetStackResponse::Error(110) // Not a multicast address
+                    },
+                    NetStackRequest::JoinMulticastGroup { handle, group } => {
+                        if !matches!(sockets.get_mut(handle, requester), Some(smoltcp::socket::Socket::Udp(_))) {
+                            NetStackResponse::Error(102) // Not a UDP socket, or not found
+                        } else {
+                            match sockets.join_group(handle, requester, group) {
+                                Some(true) if !set_membership(&mut iface, &mut device, group, true, timestamp) => {
+                                    sockets.leave_group(handle, requester, group);
+                                    NetStackResponse::Error(111) // The interface can't join more groups
+                                },
+                                Some(_) => {
+                                    log(&alloc::format!("AetherNet: Socket {} joined {}.{}.{}.{}.", handle, group[0], group[1], group[2], group[3]));
+                                    NetStackResponse::Success
+                                },
+                                None => NetStackResponse::Error(103),
+                            }
+                        }
+                    },
+                    NetStackRequest::LeaveMulticastGroup { handle, group } => match sockets.leave_group(handle, requester, group) {
+                        Some(last) => {
+                            if last {
+                                set_membership(&mut iface, &mut device, group, false, timestamp);
+                            }
+                            NetStackResponse::Success
+                        },
+                        None => NetStackResponse::Error(112), // Socket not found or not a member
+                    },
+                    NetStackRequest::GetInterface => NetStackResponse::Interface(interface_info(iface_up)),
+                    NetStackRequest::SetInterfaceState { up: false } if iface_up => {
+                        let closed = take_down(&mut iface, &mut device, &mut sockets, timestamp);
+                        iface_up = false;
+                        // Owners hear about their sockets before the interface itself.
+                        for (handle, owner) in closed {
+                            publish(&mut event_bus_chan, SOCKET_CLOSED_TOPIC, &SocketClosed { handle, owner });
+                        }
+                        publish(&mut event_bus_chan, NET_DOWN_TOPIC, &interface_info(iface_up));
+                        NetStackResponse::Success
+                    },
+                    NetStackRequest::SetInterfaceState { up: true } if !iface_up => {
+                        iface = bring_up(&mut device);
+                        iface_up = true;
+                        publish(&mut event_bus_chan, NET_UP_TOPIC, &interface_info(iface_up));
+                        NetStackResponse::Success
+                    },
+                    NetStackRequest::SetInterfaceState { .. } => NetStackResponse::Success, // Already in that state
+                    NetStackRequest::CaptureStart { .. } if device.capture().is_running() => NetStackResponse::Error(116), // A capture is already running
+                    NetStackRequest::CaptureStart { filter, snaplen, max_bytes } => {
+                        if snaplen == 0 || snaplen > MAX_SNAPLEN || (max_bytes as usize) < PCAP_RECORD_HEADER_LEN || max_bytes > MAX_CAPTURE_BYTES {
+                            NetStackResponse::Error(117) // Bad snap length or ring size
+                        } else {
+                            log(&alloc::format!("AetherNet: Capture started (snap length {}, ring of {} bytes, filter {:?}).", snaplen, max_bytes, filter));
+                            device.capture_mut().start(filter, snaplen, max_bytes);
+                            NetStackResponse::Capture(device.capture().stats())
+                        }
+                    },
+                    NetStackRequest::CaptureStop => {
+                        device.capture_mut().stop();
+                        let stats = device.capture().stats();
+                        log(&alloc::format!("AetherNet: Capture stopped with {} frames ({} dropped).", stats.packets, stats.dropped));
+                        NetStackResponse::Capture(stats)
+                    },
+                    NetStackRequest::CaptureDump { .. } if device.capture().is_running() => NetStackResponse::Error(118), // Stop the capture first
+                    NetStackRequest::CaptureDump { vfs_path } => match dump_capture(&mut vfs_chan, device.capture(), &vfs_path) {
+                        Ok(written) => {
+                            log(&alloc::format!("AetherNet: Wrote {} bytes of capture to {}.", written, vfs_path));
+                            NetStackResponse::Capture(device.capture().stats())
+                        },
+                        Err(e) => {
+                            log(&alloc::format!("AetherNet: Failed to write capture to {}: {}", vfs_path, e));
+                            NetStackResponse::Error(119) // The VFS refused or failed the write
+                        },
+                    },
+                    NetStackRequest::GetConnectionHistory { max } => {
+                        NetStackResponse::ConnectionHistory(device.connections().history((max as usize).min(CONNECTION_HISTORY_LEN)))
+                    },
+                    NetStackRequest::SetSockOpt { handle, option } => match sockets.set_option(handle, requester, option, now_ms) {
+                        Ok(()) => {
+                            log(&alloc::format!("AetherNet: Socket {} set {:?}.", handle, option));
+                            NetStackResponse::Success
+                        },
+                        Err(e) => send_error(handle, requester, e),
+                    },
+                    NetStackRequest::WatchRecv { handle, reply_chan } => {
+                        if sockets.watch_recv(handle, requester, reply_chan) {
+                            NetStackResponse::Success
+                        } else {
+                            NetStackResponse::Error(103)
+                        }
+                    },
+                    NetStackRequest::Flush(handle) => match sockets.flush(handle, requester, now_ms) {
+                        Ok(queued) => NetStackResponse::Flushed { queued: queued as u32 },
+                        Err(e) => send_error(handle, requester, e),
+                    },
+                    NetStackRequest::RecvFrom(handle) => match sockets.get_mut(handle, requester) {
+                        Some(smoltcp::socket::Socket::Udp(s)) => {
+                            let mut buffer = alloc::vec![0; s.recv_capacity()];
+                            match s.recv_slice(&mut buffer) {
+                                Ok((size, meta)) => {
+                                    buffer.truncate(size);
+                                    let addr = match meta.endpoint.addr {
+                                        IpAddress::Ipv4(addr) => addr.0,
+                                        #[allow(unreachable_patterns)]
+                                        _ => [0; 4], // The interface only has an IPv4 address
+                                    };
+                                    NetStackResponse::DataFrom { data: buffer, addr, port: meta.endpoint.port }
+                                },
+                                // Nothing queued.
+                                Err(_) => NetStackResponse::DataFrom { data: alloc::vec![], addr: [0; 4], port: 0 },
+                            }
+                        },
+                        Some(_) => {
+                            log(&alloc::format!("AetherNet: Socket {} is not a UDP socket for RecvFrom request.", handle));
+                            NetStackResponse::Error(102) // Not a UDP socket
+                        },
+                        None => {
+                            log(&alloc::format!("AetherNet: Socket {} not found for task {}.", handle, requester));
+                            NetStackResponse::Error(103)
+                        },
+                    },
+                    NetStackRequest::ForTask { .. } => {
+                        log(&alloc::format!("AetherNet: Task {} may not ask on behalf of another task.", requester));
+                        NetStackResponse::Error(105) // Permission denied
+                    },
                 };
                 own_chan.send(&response).unwrap_or_else(|_| log("AetherNet: Failed to send response to client."));
             } else {
@@ -0,0 +1,260 @@
+// vnode/net-stack/src/neighbors.rs
+
+//! ARP neighbor table: what the stack knows about IPv4 -> MAC mappings on the LAN.
+//!
+//! smoltcp keeps its neighbor cache private, so the stack keeps this mirror.
+//! The device shows it every received frame before smoltcp does. ARP requests
+//! and replies (the only frames smoltcp learns neighbors from) are recorded
+//! here as dynamic entries.
+//!
+//! Static entries are pushed into smoltcp as synthesized ARP replies when they
+//! are added and again every `STATIC_REFRESH_MS`. That is well inside smoltcp's
+//! 60 s entry lifetime, so an evicted static entry is back within one refresh.
+//! ARP frames that claim a statically mapped address for a different MAC are
+//! dropped before smoltcp sees them, so a static entry always wins over a
+//! conflicting dynamic one.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::ipc::net_ipc::{NeighborEntry, NeighborState};
+
+/// How long smoltcp keeps a learned neighbor. Dynamic entries are dropped
+/// from the table at the same age.
+pub const ENTRY_LIFETIME_MS: u64 = 60_000;
+
+/// How often static entries are pushed into smoltcp again.
+pub const STATIC_REFRESH_MS: u64 = 10_000;
+
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ARP_FRAME_LEN: usize = 14 + 28; // Ethernet header + ARP for IPv4 over Ethernet
+
+struct Neighbor {
+    mac: [u8; 6],
+    is_static: bool,
+    updated_ms: u64, // Last time the mapping was seen (dynamic) or added (static)
+}
+
+pub struct NeighborTable {
+    entries: BTreeMap<[u8; 4], Neighbor>,
+    next_refresh_ms: u64,
+}
+
+impl NeighborTable {
+    pub fn new() -> Self {
+        Self { entries: BTreeMap::new(), next_refresh_ms: 0 }
+    }
+
+    /// Looks at a received frame. Records the sender of ARP frames, and returns
+    /// false for frames that contradict a static entry and must be dropped.
+    pub fn observe_frame(&mut self, frame: &[u8], now_ms: u64) -> bool {
+        let (ip, mac) = match parse_arp_sender(frame) {
+            Some(sender) => sender,
+            None => return true,
+        };
+        match self.entries.get_mut(&ip) {
+            Some(entry) if entry.is_static => entry.mac == mac,
+            Some(entry) => {
+                entry.mac = mac;
+                entry.updated_ms = now_ms;
+                true
+            },
+            None => {
+                self.entries.insert(ip, Neighbor { mac, is_static: false, updated_ms: now_ms });
+                true
+            },
+        }
+    }
+
+    /// Adds or replaces a static entry, overriding any dynamic one for `ip`.
+    /// The caller must push it into smoltcp with `arp_reply_frame`.
+    pub fn add_static(&mut self, ip: [u8; 4], mac: [u8; 6], now_ms: u64) {
+        self.entries.insert(ip, Neighbor { mac, is_static: true, updated_ms: now_ms });
+    }
+
+    /// Removes the entry for `ip`, static or dynamic.
+    pub fn remove(&mut self, ip: &[u8; 4]) -> bool {
+        self.entries.remove(ip).is_some()
+    }
+
+    /// Drops all dynamic entries, and the static ones too if `force` is set.
+    /// Returns the number of entries removed.
+    pub fn flush(&mut self, force: bool) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.is_static && !force);
+        before - self.entries.len()
+    }
+
+    /// Drops dynamic entries smoltcp has forgotten by now.
+    pub fn expire(&mut self, now_ms: u64) {
+        self.entries.retain(|_, entry| entry.is_static || now_ms.saturating_sub(entry.updated_ms) < ENTRY_LIFETIME_MS);
+    }
+
+    pub fn list(&self, now_ms: u64) -> Vec<NeighborEntry> {
+        self.entries.iter().map(|(ip, entry)| NeighborEntry {
+            ip: *ip,
+            mac: entry.mac,
+            state: if entry.is_static { NeighborState::Static } else { NeighborState::Dynamic },
+            age_ms: now_ms.saturating_sub(entry.updated_ms),
+        }).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Static entries to push into smoltcp, if a refresh is due.
+    pub fn statics_to_refresh(&mut self, now_ms: u64) -> Vec<([u8; 4], [u8; 6])> {
+        if now_ms < self.next_refresh_ms {
+            return Vec::new();
+        }
+        self.next_refresh_ms = now_ms + STATIC_REFRESH_MS;
+        self.statics()
+    }
+
+    pub fn statics(&self) -> Vec<([u8; 4], [u8; 6])> {
+        self.entries.iter().filter(|(_, entry)| entry.is_static).map(|(ip, entry)| (*ip, entry.mac)).collect()
+    }
+}
+
+/// Whether `mac` can be a neighbor's address: not all-zero, broadcast or multicast.
+pub fn is_unicast_mac(mac: &[u8; 6]) -> bool {
+    *mac != [0; 6] && mac[0] & 0x01 == 0
+}
+
+/// Sender IP and MAC of an Ethernet/IPv4 ARP frame.
+fn parse_arp_sender(frame: &[u8]) -> Option<([u8; 4], [u8; 6])> {
+    if frame.len() < ARP_FRAME_LEN || u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_ARP {
+        return None;
+    }
+    let arp = &frame[14..];
+    // Hardware type 1 (Ethernet), protocol 0x0800 (IPv4), address lengths 6 and 4.
+    if arp[0..6] != [0x00, 0x01, 0x08, 0x00, 6, 4] {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&arp[8..14]);
+    let mut ip = [0u8; 4];
+    ip.copy_from_slice(&arp[14..18]);
+    Some((ip, mac))
+}
+
+/// An ARP reply from (`ip`, `mac`) to this interface, for injection into the
+/// receive path so smoltcp learns the mapping.
+pub fn arp_reply_frame(ip: [u8; 4], mac: [u8; 6], own_ip: [u8; 4], own_mac: [u8; 6]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ARP_FRAME_LEN);
+    frame.extend_from_slice(&own_mac); // Ethernet destination
+    frame.extend_from_slice(&mac); // Ethernet source
+    frame.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+    frame.extend_from_slice(&[0x00, 0x01, 0x08, 0x00, 6, 4]);
+    frame.extend_from_slice(&2u16.to_be_bytes()); // Operation: reply
+    frame.extend_from_slice(&mac);
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&own_mac);
+    frame.extend_from_slice(&own_ip);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWN_IP: [u8; 4] = [10, 0, 2, 15];
+    const OWN_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    const GATEWAY: [u8; 4] = [10, 0, 2, 2];
+    const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0A, 0x00, 0x02, 0x02];
+    const OTHER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x99];
+
+    /// An ARP frame from (`ip`, `mac`), as the device would receive it.
+    fn arp_from(ip: [u8; 4], mac: [u8; 6]) -> Vec<u8> {
+        arp_reply_frame(ip, mac, OWN_IP, OWN_MAC)
+    }
+
+    fn entry(table: &NeighborTable, ip: [u8; 4], now_ms: u64) -> Option<NeighborEntry> {
+        table.list(now_ms).into_iter().find(|entry| entry.ip == ip)
+    }
+
+    #[test]
+    fn a_learned_neighbor_ages_until_it_is_forgotten() {
+        let mut table = NeighborTable::new();
+        assert!(table.observe_frame(&arp_from(GATEWAY, GATEWAY_MAC), 1_000));
+        let learned = entry(&table, GATEWAY, 1_000).unwrap();
+        assert_eq!((learned.mac, learned.state, learned.age_ms), (GATEWAY_MAC, NeighborState::Dynamic, 0));
+
+        // Unseen, it ages, and is kept until smoltcp would have dropped it.
+        table.expire(1_000 + ENTRY_LIFETIME_MS - 1);
+        assert_eq!(entry(&table, GATEWAY, 1_000 + ENTRY_LIFETIME_MS - 1).unwrap().age_ms, ENTRY_LIFETIME_MS - 1);
+        table.expire(1_000 + ENTRY_LIFETIME_MS);
+        assert!(entry(&table, GATEWAY, 1_000 + ENTRY_LIFETIME_MS).is_none());
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn seeing_a_neighbor_again_keeps_it_and_follows_a_new_mac() {
+        let mut table = NeighborTable::new();
+        table.observe_frame(&arp_from(GATEWAY, GATEWAY_MAC), 0);
+        table.observe_frame(&arp_from(GATEWAY, OTHER_MAC), 50_000);
+        table.expire(ENTRY_LIFETIME_MS + 1);
+        let entry = entry(&table, GATEWAY, ENTRY_LIFETIME_MS + 1).unwrap();
+        assert_eq!((entry.mac, entry.age_ms), (OTHER_MAC, ENTRY_LIFETIME_MS + 1 - 50_000));
+    }
+
+    #[test]
+    fn frames_other_than_arp_teach_nothing() {
+        let mut table = NeighborTable::new();
+        let mut ipv4 = arp_from(GATEWAY, GATEWAY_MAC);
+        ipv4[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        assert!(table.observe_frame(&ipv4, 0));
+        assert!(table.observe_frame(&arp_from(GATEWAY, GATEWAY_MAC)[..ARP_FRAME_LEN - 1], 0));
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn a_static_entry_wins_over_arp_and_never_expires() {
+        let mut table = NeighborTable::new();
+        table.observe_frame(&arp_from(GATEWAY, OTHER_MAC), 0);
+        table.add_static(GATEWAY, GATEWAY_MAC, 10);
+        assert!(!table.observe_frame(&arp_from(GATEWAY, OTHER_MAC), 20), "a conflicting frame is dropped");
+        assert!(table.observe_frame(&arp_from(GATEWAY, GATEWAY_MAC), 30));
+        table.expire(10 * ENTRY_LIFETIME_MS);
+        let entry = entry(&table, GATEWAY, 10 * ENTRY_LIFETIME_MS).unwrap();
+        assert_eq!((entry.mac, entry.state), (GATEWAY_MAC, NeighborState::Static));
+    }
+
+    #[test]
+    fn flush_evicts_dynamic_entries_and_statics_only_when_forced() {
+        let mut table = NeighborTable::new();
+        table.add_static(GATEWAY, GATEWAY_MAC, 0);
+        for host in 3..6 {
+            table.observe_frame(&arp_from([10, 0, 2, host], OTHER_MAC), 0);
+        }
+        assert_eq!(table.flush(false), 3);
+        assert_eq!(table.statics(), alloc::vec![(GATEWAY, GATEWAY_MAC)]);
+        assert_eq!(table.flush(true), 1);
+        assert_eq!(table.len(), 0);
+
+        table.observe_frame(&arp_from(GATEWAY, GATEWAY_MAC), 0);
+        assert!(table.remove(&GATEWAY));
+        assert!(!table.remove(&GATEWAY));
+    }
+
+    #[test]
+    fn statics_are_pushed_again_every_refresh_interval() {
+        let mut table = NeighborTable::new();
+        table.add_static(GATEWAY, GATEWAY_MAC, 0);
+        assert_eq!(table.statics_to_refresh(0), alloc::vec![(GATEWAY, GATEWAY_MAC)]);
+        assert!(table.statics_to_refresh(STATIC_REFRESH_MS - 1).is_empty());
+        assert_eq!(table.statics_to_refresh(STATIC_REFRESH_MS), alloc::vec![(GATEWAY, GATEWAY_MAC)]);
+    }
+
+    #[test]
+    fn a_pushed_reply_is_learned_as_the_mapping_it_carries() {
+        assert_eq!(parse_arp_sender(&arp_from(GATEWAY, GATEWAY_MAC)), Some((GATEWAY, GATEWAY_MAC)));
+        assert!(is_unicast_mac(&GATEWAY_MAC));
+        assert!(!is_unicast_mac(&[0; 6]));
+        assert!(!is_unicast_mac(&[0xFF; 6]));
+        assert!(!is_unicast_mac(&[0x01, 0x00, 0x5E, 0x00, 0x00, 0xFB]));
+    }
+}
@@ -0,0 +1,132 @@
+// vnode/net-stack/src/sendq.rs
+
+//! The pending sends of a TCP socket, and when they go out.
+//!
+//! `Send` doesn't write to smoltcp directly. The data is queued on the
+//! socket, and `pump` hands TCP what the socket's mode lets go, at most a
+//! segment's payload per piece:
+//!
+//! *   `NoDelay`: everything, at once.
+//! *   `Cork`: only full segments. The rest waits for `Flush`, for the
+//!     socket to be uncorked, or for enough further writes to fill a segment.
+//! *   `Coalesce`, the default: everything, if TCP has nothing unacknowledged,
+//!     so a small write to an idle connection isn't delayed. Otherwise full
+//!     segments go at once and the rest is held for `COALESCE_MS` after the
+//!     oldest held write, merging what else is written meanwhile.
+//!
+//! The queue does the coalescing that Nagle's algorithm would do, with a
+//! bound on the delay, so smoltcp's Nagle is only on while a socket is
+//! corked, where it keeps the held partial segment from being split further.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use smoltcp::socket::TcpSocket;
+
+use crate::ipc::socket_ipc::{SendMode, SendStats, SocketOption};
+
+/// Payload of a piece handed to TCP: the MSS every TCP peer accepts, so a
+/// piece is never split into a full segment and a runt.
+pub const SEGMENT_SIZE: usize = 536;
+/// How long `Coalesce` holds a partial segment while data is in flight.
+pub const COALESCE_MS: u64 = 20;
+/// Bytes a socket may have queued; a `Send` beyond this is refused.
+pub const MAX_QUEUED: usize = 8192;
+
+#[derive(Debug, Default)]
+pub struct SendQueue {
+    no_delay: bool,
+    cork: bool,
+    pending: VecDeque<u8>,
+    /// When the oldest byte still held was written; `None` while nothing is.
+    held_since_ms: Option<u64>,
+    /// Bytes at the front of `pending` that go out whatever the mode, set by `flush`.
+    forced: usize,
+    segments: u64,
+    bytes: u64,
+}
+
+impl SendQueue {
+    pub fn mode(&self) -> SendMode {
+        if self.cork {
+            SendMode::Cork
+        } else if self.no_delay {
+            SendMode::NoDelay
+        } else {
+            SendMode::Coalesce
+        }
+    }
+
+    pub fn queued(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Queues `data` written at `now_ms`. Refused if the queue would grow past `MAX_QUEUED`.
+    pub fn push(&mut self, data: &[u8], now_ms: u64) -> Result<(), ()> {
+        if self.pending.len() + data.len() > MAX_QUEUED {
+            return Err(());
+        }
+        if self.pending.is_empty() {
+            self.held_since_ms = Some(now_ms);
+        }
+        self.pending.extend(data);
+        Ok(())
+    }
+
+    /// Applies `option`, and smoltcp's half of it to `socket`.
+    pub fn set_option(&mut self, option: SocketOption, socket: &mut TcpSocket) {
+        match option {
+            SocketOption::NoDelay(on) => self.no_delay = on,
+            SocketOption::Cork(on) => self.cork = on,
+            SocketOption::NonBlocking(_) => {}, // socket-api's
+        }
+        socket.set_nagle_enabled(self.cork);
+    }
+
+    /// Makes everything queued now go out regardless of the mode.
+    pub fn flush(&mut self) {
+        self.forced = self.pending.len();
+    }
+
+    /// How many queued bytes may go to TCP now.
+    fn sendable(&self, tcp_idle: bool, now_ms: u64) -> usize {
+        let whole_segments = self.pending.len() / SEGMENT_SIZE * SEGMENT_SIZE;
+        let held_out = self.held_since_ms.map_or(false, |since| now_ms.saturating_sub(since) >= COALESCE_MS);
+        let all = match self.mode() {
+            SendMode::NoDelay => true,
+            SendMode::Coalesce => tcp_idle || held_out,
+            SendMode::Cork => false,
+        };
+        if all { self.pending.len() } else { whole_segments.max(self.forced) }
+    }
+
+    /// Hands `socket` what the mode lets go at `now_ms`, as far as its send buffer takes it.
+    pub fn pump(&mut self, socket: &mut TcpSocket, now_ms: u64) {
+        if self.pending.is_empty() || !socket.may_send() {
+            return;
+        }
+        let mut sendable = self.sendable(socket.send_queue() == 0, now_ms);
+        while sendable > 0 && socket.can_send() {
+            let piece: Vec<u8> = self.pending.iter().take(sendable.min(SEGMENT_SIZE)).copied().collect();
+            let sent = match socket.send_slice(&piece) {
+                Ok(sent) if sent > 0 => sent,
+                _ => break,
+            };
+            self.pending.drain(..sent);
+            self.forced = self.forced.saturating_sub(sent);
+            self.segments += 1;
+            self.bytes += sent as u64;
+            sendable -= sent;
+        }
+        // What is left keeps the time of the oldest write, which can only make it go sooner.
+        if self.pending.is_empty() {
+            self.held_since_ms = None;
+        }
+    }
+
+    pub fn stats(&self) -> SendStats {
+        SendStats { mode: self.mode(), queued: self.pending.len() as u32, segments: self.segments, bytes: self.bytes }
+    }
+}
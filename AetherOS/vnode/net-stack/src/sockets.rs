@@ -0,0 +1,816 @@
+// vnode/net-stack/src/sockets.rs
+
+//! Socket bookkeeping for the network stack.
+//!
+//! The smoltcp `SocketSet` owns a growable `Vec`, so there is no fixed socket
+//! limit baked into storage; slots freed by `close` are reused by later opens.
+//! Clients never see smoltcp's handles. They get our own `u32` handles, which
+//! are handed out in increasing order and never reused, so a stale handle from
+//! a closed socket can't reach a socket opened later.
+//!
+//! Each socket is charged to the task that opened it (the sender the kernel
+//! stamped on the `OpenSocket` message). Opens beyond the per-task or global
+//! limit are refused with `QuotaExceeded` before any buffers are allocated.
+//! The table's counts are registered as `net_socket*` metrics.
+//!
+//! Multicast memberships are recorded per socket as well. The interface stays
+//! in a group while at least one socket is a member of it.
+//!
+//! A listening TCP port is a listener: one handle for a group of smoltcp
+//! sockets in the listen state, one per backlog slot, so that many handshakes
+//! can complete before the owner accepts any of them. `accept` turns an
+//! established slot into a socket of its own and puts a fresh listening
+//! socket in its place. Every slot counts against the quotas like any other
+//! socket. With all slots taken, further SYNs find no listening socket and
+//! smoltcp answers them with a reset. `newly_established` reports each
+//! connection once its handshake is done, so the owner can be told.
+//!
+//! Outgoing TCP connections take their local port from the ephemeral range,
+//! in turn, so a port is not reused until the range has gone round. A UDP
+//! socket that sends before it is bound takes its port the same way, skipping
+//! the ports other UDP sockets are bound to.
+//!
+//! A socket's owner can ask to be told when it has data to receive
+//! (`watch_recv`); `take_readable` runs after every poll and reports each
+//! watch once.
+//!
+//! Data sent on a TCP socket waits in the socket's `SendQueue` (see
+//! `sendq.rs`) until its mode lets it go; `pump_sends` runs before every poll.
+//!
+//! Taking the interface down empties the table: `shut_down_all` ends the TCP
+//! connections, and after a last poll has sent their FINs and RSTs, `drain`
+//! frees every socket and reports whose they were.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::socket::{AnySocket, Socket, TcpSocket, TcpSocketBuffer, TcpState};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use crate::ipc::net_ipc::{ConnectState, SocketQuota};
+use crate::ipc::socket_ipc::{ListenerInfo, SendStats, SocketOption};
+use crate::metrics::{Counter, Gauge, Registry};
+use crate::sendq::SendQueue;
+
+pub const DEFAULT_MAX_SOCKETS_PER_TASK: u32 = 64;
+pub const DEFAULT_MAX_SOCKETS_TOTAL: u32 = 512;
+
+const TCP_BUFFER_SIZE: usize = 1024;
+/// The IANA dynamic port range, which outgoing connections are bound from.
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// A TCP socket with the stack's usual buffers. Nagle's algorithm is off
+/// until the socket is corked; its `SendQueue` coalesces instead.
+pub fn new_tcp_socket<'a>() -> TcpSocket<'a> {
+    let mut socket = TcpSocket::new(
+        TcpSocketBuffer::new(alloc::vec![0; TCP_BUFFER_SIZE]), // Rx buffer
+        TcpSocketBuffer::new(alloc::vec![0; TCP_BUFFER_SIZE]), // Tx buffer
+    );
+    socket.set_nagle_enabled(false);
+    socket
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SocketQuotas {
+    pub per_task: u32,
+    pub total: u32,
+}
+
+impl Default for SocketQuotas {
+    fn default() -> Self {
+        Self { per_task: DEFAULT_MAX_SOCKETS_PER_TASK, total: DEFAULT_MAX_SOCKETS_TOTAL }
+    }
+}
+
+struct Entry {
+    handle: SocketHandle,
+    owner: u64,
+    groups: Vec<[u8; 4]>, // Multicast groups this socket joined
+    send: SendQueue, // TCP only
+    recv_waiter: Option<u32>, // Channel told once the socket is readable
+}
+
+/// Why a send-side request on a socket failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    NotFound,
+    NotTcp,
+    /// Not connected, or this end has closed.
+    NotConnected,
+    /// The socket's `SendQueue` is full.
+    QueueFull,
+}
+
+struct Listener {
+    owner: u64,
+    port: u16,
+    backlog: u32,
+    slots: Vec<SocketHandle>, // Fewer than `backlog` while the owner is at its quota
+    announced: Vec<SocketHandle>, // Slots already reported by `newly_established`
+}
+
+struct SocketMetrics {
+    live: Gauge,
+    tasks: Gauge,
+    opened: Counter,
+    closed: Counter,
+    per_task_rejections: Counter,
+    global_rejections: Counter,
+}
+
+pub struct SocketTable<'a> {
+    set: SocketSet<'a>,
+    entries: BTreeMap<u32, Entry>, // Our handle -> smoltcp handle and owning task
+    listeners: BTreeMap<u32, Listener>, // Our handle -> backlog slots; shares the handle space with `entries`
+    per_task: BTreeMap<u64, u32>, // Owning task -> number of open sockets
+    next_handle: u32,
+    next_ephemeral: u16,
+    quotas: SocketQuotas,
+    metrics: SocketMetrics,
+}
+
+impl<'a> SocketTable<'a> {
+    pub fn new(quotas: SocketQuotas, metrics: &mut Registry) -> Self {
+        const REJECTIONS: &str = "Socket opens refused by a quota, by the limit that was hit.";
+        const LIMITS: &str = "Configured socket limits.";
+        metrics.gauge_with("net_socket_limit", LIMITS, &[("scope", "per_task")]).set(quotas.per_task as i64);
+        metrics.gauge_with("net_socket_limit", LIMITS, &[("scope", "total")]).set(quotas.total as i64);
+        let metrics = SocketMetrics {
+            live: metrics.gauge("net_sockets_live", "Open sockets."),
+            tasks: metrics.gauge("net_tasks_with_sockets", "Tasks holding at least one socket."),
+            opened: metrics.counter("net_sockets_opened_total", "Sockets opened since startup."),
+            closed: metrics.counter("net_sockets_closed_total", "Sockets closed since startup."),
+            per_task_rejections: metrics.counter_with("net_socket_quota_rejections_total", REJECTIONS, &[("limit", "per_task")]),
+            global_rejections: metrics.counter_with("net_socket_quota_rejections_total", REJECTIONS, &[("limit", "total")]),
+        };
+        Self {
+            set: SocketSet::new(Vec::new()),
+            entries: BTreeMap::new(),
+            listeners: BTreeMap::new(),
+            per_task: BTreeMap::new(),
+            next_handle: 1,
+            next_ephemeral: *EPHEMERAL_PORTS.start(),
+            quotas,
+            metrics,
+        }
+    }
+
+    /// smoltcp sockets in use, listener slots included.
+    fn live(&self) -> usize {
+        self.entries.len() + self.listeners.values().map(|listener| listener.slots.len()).sum::<usize>()
+    }
+
+    fn update_gauges(&self) {
+        self.metrics.live.set(self.live() as i64);
+        self.metrics.tasks.set(self.per_task.len() as i64);
+    }
+
+    /// Checks whether `owner` may open `count` more sockets. Call before
+    /// building them, so a refused open doesn't allocate buffers.
+    pub fn check_quota(&mut self, owner: u64, count: u32) -> Result<(), (SocketQuota, u32)> {
+        let result = self.fits(owner, count);
+        match result {
+            Err((SocketQuota::PerTask, _)) => self.metrics.per_task_rejections.inc(),
+            Err((SocketQuota::Global, _)) => self.metrics.global_rejections.inc(),
+            Ok(()) => {},
+        }
+        result
+    }
+
+    fn fits(&self, owner: u64, count: u32) -> Result<(), (SocketQuota, u32)> {
+        if self.live() + count as usize > self.quotas.total as usize {
+            Err((SocketQuota::Global, self.quotas.total))
+        } else if self.owned_by(owner) + count > self.quotas.per_task {
+            Err((SocketQuota::PerTask, self.quotas.per_task))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds a socket owned by `owner` and returns its handle. The caller must
+    /// have passed `check_quota` first.
+    pub fn insert<T: AnySocket<'a>>(&mut self, owner: u64, socket: T) -> u32 {
+        let handle = self.allocate_handle();
+        // Record the handle smoltcp actually returns; it may reuse a freed slot.
+        let smoltcp_handle = self.set.add(socket);
+        self.entries.insert(handle, Entry { handle: smoltcp_handle, owner, groups: Vec::new(), send: SendQueue::default(), recv_waiter: None });
+        self.charge(owner, 1);
+        handle
+    }
+
+    fn allocate_handle(&mut self) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    fn charge(&mut self, owner: u64, count: u32) {
+        *self.per_task.entry(owner).or_insert(0) += count;
+        self.metrics.opened.add(count as u64);
+        self.update_gauges();
+    }
+
+    fn release(&mut self, owner: u64, count: u32) {
+        if let Some(owned) = self.per_task.get_mut(&owner) {
+            *owned -= count;
+            if *owned == 0 {
+                self.per_task.remove(&owner);
+            }
+        }
+        self.metrics.closed.add(count as u64);
+        self.update_gauges();
+    }
+
+    /// Adds a listening socket in the set, on `port`.
+    fn add_listening_slot(&mut self, port: u16) -> SocketHandle {
+        let mut socket = new_tcp_socket();
+        socket.listen(port).expect("a fresh socket can listen on a nonzero port");
+        self.set.add(socket)
+    }
+
+    /// Creates a listener with `backlog` slots on `port` and returns its
+    /// handle. The caller must have passed `check_quota` for `backlog` sockets.
+    pub fn insert_listener(&mut self, owner: u64, port: u16, backlog: u32) -> u32 {
+        let handle = self.allocate_handle();
+        let slots = (0..backlog).map(|_| self.add_listening_slot(port)).collect();
+        self.listeners.insert(handle, Listener { owner, port, backlog, slots, announced: Vec::new() });
+        self.charge(owner, backlog);
+        handle
+    }
+
+    /// Takes an established connection off listener `handle` of `owner`, as a
+    /// socket of its own, and tops the backlog back up as far as the quotas
+    /// allow. Returns the new socket's handle and the peer, `Ok(None)` if no
+    /// connection is waiting, or `Err(())` if the listener doesn't exist.
+    pub fn accept(&mut self, handle: u32, owner: u64) -> Result<Option<(u32, IpEndpoint)>, ()> {
+        let listener = self.listeners.get(&handle).filter(|listener| listener.owner == owner).ok_or(())?;
+        let port = listener.port;
+        // Slots whose connection ended before it was accepted go back to listening.
+        let dead: Vec<usize> = (0..listener.slots.len()).filter(|&i| {
+            let socket = self.set.get::<TcpSocket>(listener.slots[i]);
+            !socket.is_active() && !socket.is_listening()
+        }).collect();
+        for i in dead {
+            let fresh = self.add_listening_slot(port);
+            let listener = self.listeners.get_mut(&handle).unwrap();
+            let old = core::mem::replace(&mut listener.slots[i], fresh);
+            listener.announced.retain(|&slot| slot != old);
+            self.set.remove(old);
+        }
+
+        let listener = self.listeners.get(&handle).unwrap();
+        let ready = listener.slots.iter().position(|&slot| self.set.get::<TcpSocket>(slot).may_send());
+        let accepted = match ready {
+            Some(i) => {
+                let listener = self.listeners.get_mut(&handle).unwrap();
+                let slot = listener.slots.remove(i);
+                listener.announced.retain(|&announced| announced != slot);
+                let remote = self.set.get::<TcpSocket>(slot).remote_endpoint().expect("an established socket has a peer");
+                // The slot's quota charge moves to the new socket.
+                let accepted = self.allocate_handle();
+                self.entries.insert(accepted, Entry { handle: slot, owner, groups: Vec::new(), send: SendQueue::default(), recv_waiter: None });
+                Some((accepted, remote))
+            },
+            None => None,
+        };
+        self.refill(handle);
+        Ok(accepted)
+    }
+
+    /// The connections established on listener slots since the last call:
+    /// the listener, its owner and the peer. Each is reported once.
+    pub fn newly_established(&mut self) -> Vec<(u32, u64, IpEndpoint)> {
+        let mut ready = Vec::new();
+        for (&handle, listener) in self.listeners.iter_mut() {
+            for &slot in &listener.slots {
+                let socket = self.set.get::<TcpSocket>(slot);
+                if !socket.may_send() || listener.announced.contains(&slot) {
+                    continue;
+                }
+                if let Some(remote) = socket.remote_endpoint() {
+                    listener.announced.push(slot);
+                    ready.push((handle, listener.owner, remote));
+                }
+            }
+        }
+        ready
+    }
+
+    /// Adds slots to listener `handle` until it has its full backlog or the
+    /// owner runs into a quota.
+    fn refill(&mut self, handle: u32) {
+        let Some(listener) = self.listeners.get(&handle) else { return };
+        let (owner, port, missing) = (listener.owner, listener.port, listener.backlog - listener.slots.len() as u32);
+        for _ in 0..missing {
+            // Not counted as a rejection: nobody asked for this socket.
+            if self.fits(owner, 1).is_err() {
+                break;
+            }
+            let slot = self.add_listening_slot(port);
+            self.listeners.get_mut(&handle).unwrap().slots.push(slot);
+            self.charge(owner, 1);
+        }
+    }
+
+    /// The backlog of listener `handle` of `owner`: its size, the slots still
+    /// waiting for a SYN, and the connections established but not accepted.
+    pub fn listener_info(&self, handle: u32, owner: u64) -> Option<(u16, ListenerInfo)> {
+        let listener = self.listeners.get(&handle).filter(|listener| listener.owner == owner)?;
+        let sockets = listener.slots.iter().map(|&slot| self.set.get::<TcpSocket>(slot));
+        let (mut available, mut pending) = (0, 0);
+        for socket in sockets {
+            if socket.is_listening() {
+                available += 1;
+            } else if socket.may_send() {
+                pending += 1;
+            }
+        }
+        Some((listener.port, ListenerInfo { backlog: listener.backlog, available, pending }))
+    }
+
+    /// Closes a socket of `owner` and frees its storage. Returns the multicast
+    /// groups that no socket is a member of anymore, which the interface should
+    /// leave, or `None` if the handle is unknown or belongs to another task.
+    /// Closing a listener drops every slot, and with them any connection not
+    /// yet accepted; connections already accepted stay open.
+    pub fn remove(&mut self, handle: u32, owner: u64) -> Option<Vec<[u8; 4]>> {
+        if self.listeners.get(&handle).map(|listener| listener.owner) == Some(owner) {
+            let listener = self.listeners.remove(&handle).unwrap();
+            for &slot in &listener.slots {
+                self.set.remove(slot);
+            }
+            self.release(owner, listener.slots.len() as u32);
+            return Some(Vec::new());
+        }
+        if self.entries.get(&handle).map(|entry| entry.owner) != Some(owner) {
+            return None;
+        }
+        let entry = self.entries.remove(&handle).unwrap();
+        self.set.remove(entry.handle);
+        self.release(owner, 1);
+        Some(entry.groups.into_iter().filter(|group| self.members(group) == 0).collect())
+    }
+
+    /// Ends every TCP connection for an interface going down. One that is
+    /// established with nothing left to send is closed with a FIN; any other
+    /// open one, listener slots included, is aborted with an RST. The segments
+    /// go out on the next poll, after which `drain` frees the sockets.
+    /// Returns how many got a FIN and how many an RST.
+    pub fn shut_down_all(&mut self) -> (usize, usize) {
+        let (mut fins, mut resets) = (0, 0);
+        // Data still in a `SendQueue` is as unsent as data in smoltcp's buffer.
+        let handles = self.entries.values().map(|entry| (entry.handle, entry.send.queued()))
+            .chain(self.listeners.values().flat_map(|listener| listener.slots.iter().map(|&slot| (slot, 0))));
+        for (handle, queued) in handles {
+            if let Some(Socket::Tcp(socket)) = self.set.get_mut(handle) {
+                match socket.state() {
+                    TcpState::Closed | TcpState::TimeWait => {},
+                    TcpState::Established | TcpState::CloseWait if socket.send_queue() == 0 && queued == 0 => {
+                        socket.close();
+                        fins += 1;
+                    },
+                    _ => {
+                        socket.abort();
+                        resets += 1;
+                    },
+                }
+            }
+        }
+        (fins, resets)
+    }
+
+    /// Closes every socket and listener, whoever owns it. Returns their
+    /// handles with the owning tasks.
+    pub fn drain(&mut self) -> Vec<(u32, u64)> {
+        let closed: Vec<(u32, u64)> = self.entries.iter().map(|(&handle, entry)| (handle, entry.owner))
+            .chain(self.listeners.iter().map(|(&handle, listener)| (handle, listener.owner)))
+            .collect();
+        for &(handle, owner) in &closed {
+            self.remove(handle, owner);
+        }
+        closed
+    }
+
+    /// Where socket `handle` of `owner` is bound, if it exists.
+    pub fn local_port(&mut self, handle: u32, owner: u64) -> Option<u16> {
+        match self.get_mut(handle, owner)? {
+            Socket::Tcp(socket) => Some(socket.local_endpoint().map_or(0, |endpoint| endpoint.port)),
+            Socket::Udp(socket) => Some(socket.endpoint().port),
+            _ => Some(0),
+        }
+    }
+
+    /// The next local port for an outgoing connection.
+    pub fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_ephemeral;
+        self.next_ephemeral = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+        port
+    }
+
+    /// The next local port for an unbound UDP socket: the next ephemeral port
+    /// no UDP socket is bound to, or `None` if the whole range is.
+    pub fn udp_ephemeral_port(&mut self) -> Option<u16> {
+        let bound: BTreeSet<u16> = self.set.iter().filter_map(|(_, socket)| match socket {
+            Socket::Udp(socket) if socket.is_open() => Some(socket.endpoint().port),
+            _ => None,
+        }).collect();
+        let range = EPHEMERAL_PORTS.len();
+        (0..range).map(|_| self.ephemeral_port()).find(|port| !bound.contains(port))
+    }
+
+    /// Where the handshake of TCP socket `handle` stands, or `None` if `owner`
+    /// has no such TCP socket.
+    pub fn connect_state(&mut self, handle: u32, owner: u64) -> Option<ConnectState> {
+        match self.get_mut(handle, owner)? {
+            Socket::Tcp(socket) => Some(match socket.state() {
+                TcpState::SynSent | TcpState::SynReceived => ConnectState::Connecting,
+                // Closed after a `Connect`: reset by the peer, or the SYN retransmissions ran out.
+                TcpState::Closed => ConnectState::Refused,
+                _ => ConnectState::Established,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Queues `data` on TCP socket `handle` of `owner` and hands TCP what
+    /// the socket's mode lets go now.
+    pub fn send(&mut self, handle: u32, owner: u64, data: &[u8], now_ms: u64) -> Result<(), SendError> {
+        let (entry, socket) = self.tcp_entry(handle, owner)?;
+        if !socket.may_send() {
+            return Err(SendError::NotConnected);
+        }
+        entry.send.push(data, now_ms).map_err(|()| SendError::QueueFull)?;
+        entry.send.pump(socket, now_ms);
+        Ok(())
+    }
+
+    /// Sets `option` on TCP socket `handle` of `owner`. Uncorking or
+    /// turning `NoDelay` on lets held data go at once.
+    pub fn set_option(&mut self, handle: u32, owner: u64, option: SocketOption, now_ms: u64) -> Result<(), SendError> {
+        let (entry, socket) = self.tcp_entry(handle, owner)?;
+        entry.send.set_option(option, socket);
+        entry.send.pump(socket, now_ms);
+        Ok(())
+    }
+
+    /// Hands TCP everything queued on socket `handle` of `owner`, whatever
+    /// its mode. Returns the bytes that didn't fit in the send buffer; they
+    /// follow as it drains.
+    pub fn flush(&mut self, handle: u32, owner: u64, now_ms: u64) -> Result<usize, SendError> {
+        let (entry, socket) = self.tcp_entry(handle, owner)?;
+        entry.send.flush();
+        entry.send.pump(socket, now_ms);
+        Ok(entry.send.queued())
+    }
+
+    /// The sending of TCP socket `handle` of `owner`, or `None` if there is
+    /// no such TCP socket.
+    pub fn send_stats(&mut self, handle: u32, owner: u64) -> Option<SendStats> {
+        self.tcp_entry(handle, owner).ok().map(|(entry, _)| entry.send.stats())
+    }
+
+    /// Hands TCP what every socket's mode lets go at `now_ms`: coalescing
+    /// timers that ran out, and room freed in send buffers. Runs before each poll.
+    pub fn pump_sends(&mut self, now_ms: u64) {
+        for entry in self.entries.values_mut() {
+            if entry.send.queued() == 0 {
+                continue;
+            }
+            if let Some(Socket::Tcp(socket)) = self.set.get_mut(entry.handle) {
+                entry.send.pump(socket, now_ms);
+            }
+        }
+    }
+
+    fn tcp_entry(&mut self, handle: u32, owner: u64) -> Result<(&mut Entry, &mut TcpSocket<'a>), SendError> {
+        let entry = self.entries.get_mut(&handle).filter(|entry| entry.owner == owner).ok_or(SendError::NotFound)?;
+        match self.set.get_mut(entry.handle) {
+            Some(Socket::Tcp(socket)) => Ok((entry, socket)),
+            Some(_) => Err(SendError::NotTcp),
+            None => Err(SendError::NotFound),
+        }
+    }
+
+    /// Adds socket `handle` of `owner` to `group`. Returns whether it is the
+    /// group's first member, i.e. the interface has to join it, or `None` if
+    /// the socket doesn't exist.
+    pub fn join_group(&mut self, handle: u32, owner: u64, group: [u8; 4]) -> Option<bool> {
+        let first = self.members(&group) == 0;
+        let entry = self.entries.get_mut(&handle).filter(|entry| entry.owner == owner)?;
+        if entry.groups.contains(&group) {
+            return Some(false);
+        }
+        entry.groups.push(group);
+        Some(first)
+    }
+
+    /// Removes socket `handle` of `owner` from `group`. Returns whether that
+    /// left the group without members, or `None` if the socket doesn't exist
+    /// or wasn't a member.
+    pub fn leave_group(&mut self, handle: u32, owner: u64, group: [u8; 4]) -> Option<bool> {
+        let entry = self.entries.get_mut(&handle).filter(|entry| entry.owner == owner)?;
+        let position = entry.groups.iter().position(|joined| *joined == group)?;
+        entry.groups.remove(position);
+        Some(self.members(&group) == 0)
+    }
+
+    /// Every group at least one socket is a member of.
+    pub fn joined_groups(&self) -> Vec<[u8; 4]> {
+        let mut groups: Vec<[u8; 4]> = self.entries.values().flat_map(|entry| entry.groups.iter().copied()).collect();
+        groups.sort_unstable();
+        groups.dedup();
+        groups
+    }
+
+    fn members(&self, group: &[u8; 4]) -> usize {
+        self.entries.values().filter(|entry| entry.groups.contains(group)).count()
+    }
+
+    /// Looks up a socket of `owner`. Other tasks' sockets are reported as missing.
+    pub fn get_mut(&mut self, handle: u32, owner: u64) -> Option<&mut Socket<'a>> {
+        let smoltcp_handle = self.entries.get(&handle).filter(|entry| entry.owner == owner)?.handle;
+        self.set.get_mut(smoltcp_handle)
+    }
+
+    /// Has socket `handle` of `owner` reported to `reply_chan` once it is
+    /// readable, replacing an earlier watch. False if it isn't the owner's.
+    pub fn watch_recv(&mut self, handle: u32, owner: u64, reply_chan: u32) -> bool {
+        match self.entries.get_mut(&handle).filter(|entry| entry.owner == owner) {
+            Some(entry) => {
+                entry.recv_waiter = Some(reply_chan);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The watched sockets that have data to receive, or whose TCP stream
+    /// has ended, as handle and the channel to tell. Each watch is reported once.
+    pub fn take_readable(&mut self) -> Vec<(u32, u32)> {
+        let mut readable = Vec::new();
+        for (&handle, entry) in self.entries.iter_mut() {
+            let Some(reply_chan) = entry.recv_waiter else { continue };
+            let ready = match self.set.get::<Socket>(entry.handle) {
+                Socket::Tcp(socket) => socket.can_recv() || matches!(socket.state(), TcpState::CloseWait | TcpState::LastAck | TcpState::Closing | TcpState::TimeWait | TcpState::Closed),
+                Socket::Udp(socket) => socket.can_recv(),
+                #[allow(unreachable_patterns)]
+                _ => false,
+            };
+            if ready {
+                entry.recv_waiter = None;
+                readable.push((handle, reply_chan));
+            }
+        }
+        readable
+    }
+
+    /// Every TCP socket with a peer, listener slots included, as local port,
+    /// remote address, remote port and state.
+    pub fn tcp_peers(&self) -> impl Iterator<Item = (u16, [u8; 4], u16, TcpState)> + '_ {
+        self.set.iter().filter_map(|(_, socket)| match socket {
+            Socket::Tcp(socket) => {
+                let (local, remote) = (socket.local_endpoint()?, socket.remote_endpoint()?);
+                let remote_ip = match remote.addr {
+                    IpAddress::Ipv4(addr) => addr.0,
+                    #[allow(unreachable_patterns)]
+                    _ => return None, // The interface only has an IPv4 address
+                };
+                Some((local.port, remote_ip, remote.port, socket.state()))
+            },
+            _ => None,
+        })
+    }
+
+    /// The underlying set, for `Interface::poll`.
+    pub fn set_mut(&mut self) -> &mut SocketSet<'a> {
+        &mut self.set
+    }
+
+    pub fn owned_by(&self, owner: u64) -> u32 {
+        self.per_task.get(&owner).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeSet;
+    use smoltcp::iface::{Config, Interface};
+    use smoltcp::phy::{Loopback, Medium};
+    use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{EthernetAddress, HardwareAddress, IpCidr};
+
+    fn table(per_task: u32, total: u32) -> SocketTable<'static> {
+        SocketTable::new(SocketQuotas { per_task, total }, &mut Registry::new("net-stack"))
+    }
+
+    fn open(sockets: &mut SocketTable<'static>, owner: u64) -> Result<u32, (SocketQuota, u32)> {
+        sockets.check_quota(owner, 1)?;
+        Ok(sockets.insert(owner, new_tcp_socket()))
+    }
+
+    /// A UDP socket with small buffers, bound to `port` unless it is 0.
+    fn udp(port: u16) -> UdpSocket<'static> {
+        let buffer = || UdpSocketBuffer::new(alloc::vec![UdpPacketMetadata::EMPTY; 1], alloc::vec![0; 16]);
+        let mut socket = UdpSocket::new(buffer(), buffer());
+        if port != 0 {
+            socket.bind(port).unwrap();
+        }
+        socket
+    }
+
+    fn assert_live(sockets: &SocketTable<'static>, count: usize) {
+        assert_eq!(sockets.live(), count);
+        assert_eq!(sockets.set.iter().count(), count, "closed sockets keep their storage");
+        assert_eq!(sockets.metrics.live.get(), count as i64);
+    }
+
+    #[test]
+    fn two_hundred_sockets_opened_and_closed_in_varied_orders() {
+        let mut sockets = table(200, 200);
+        let mut handed_out = BTreeSet::new();
+        let mut open_handles: Vec<u32> = Vec::new();
+        for round in 0..4 {
+            while open_handles.len() < 200 {
+                let handle = open(&mut sockets, 1).unwrap();
+                assert!(handed_out.insert(handle), "handle {} was handed out twice", handle);
+                open_handles.push(handle);
+            }
+            assert_live(&sockets, 200);
+            assert_eq!(sockets.owned_by(1), 200);
+            assert_eq!(open(&mut sockets, 1), Err((SocketQuota::Global, 200)));
+
+            let closing: Vec<u32> = match round {
+                0 => open_handles.iter().copied().step_by(2).collect(), // Every other one
+                1 => open_handles.iter().rev().take(150).copied().collect(), // Newest first
+                2 => open_handles.iter().take(37).copied().collect(), // Oldest first
+                _ => open_handles.clone(), // All of them
+            };
+            for handle in &closing {
+                assert_eq!(sockets.remove(*handle, 1), Some(Vec::new()));
+                assert_eq!(sockets.remove(*handle, 1), None, "handle {} closed twice", handle);
+            }
+            open_handles.retain(|handle| !closing.contains(handle));
+            assert_live(&sockets, open_handles.len());
+            assert_eq!(sockets.owned_by(1) as usize, open_handles.len());
+        }
+        assert_eq!(sockets.metrics.opened.get(), sockets.metrics.closed.get());
+    }
+
+    #[test]
+    fn quotas_apply_exactly_at_the_limit() {
+        let mut sockets = table(3, 5);
+        let first: Vec<u32> = (0..3).map(|_| open(&mut sockets, 1).unwrap()).collect();
+        assert_eq!(open(&mut sockets, 1), Err((SocketQuota::PerTask, 3)));
+        open(&mut sockets, 2).unwrap();
+        open(&mut sockets, 2).unwrap();
+        assert_eq!(open(&mut sockets, 3), Err((SocketQuota::Global, 5)));
+        assert_live(&sockets, 5);
+        assert_eq!(sockets.metrics.per_task_rejections.get(), 1);
+        assert_eq!(sockets.metrics.global_rejections.get(), 1);
+
+        // Another task can't close them, and closing one frees exactly one place.
+        assert_eq!(sockets.remove(first[0], 2), None);
+        sockets.remove(first[0], 1).unwrap();
+        open(&mut sockets, 3).unwrap();
+        assert_eq!(open(&mut sockets, 3), Err((SocketQuota::Global, 5)));
+    }
+
+    #[test]
+    fn listener_slots_count_against_the_quotas() {
+        let mut sockets = table(4, 10);
+        assert_eq!(sockets.check_quota(1, 5), Err((SocketQuota::PerTask, 4)));
+        sockets.check_quota(1, 3).unwrap();
+        let listener = sockets.insert_listener(1, 8080, 3);
+        assert_eq!(sockets.owned_by(1), 3);
+        open(&mut sockets, 1).unwrap();
+        assert_eq!(open(&mut sockets, 1), Err((SocketQuota::PerTask, 4)));
+        sockets.remove(listener, 1).unwrap();
+        assert_live(&sockets, 1);
+        assert_eq!(sockets.owned_by(1), 1);
+    }
+
+    /// An interface at 10.0.2.15 on smoltcp's loopback device, so what it
+    /// sends to its own address comes straight back in.
+    fn loopback() -> (Interface, Loopback) {
+        let mut device = Loopback::new(Medium::Ethernet);
+        let config = Config::new(HardwareAddress::Ethernet(EthernetAddress([0x02, 0, 0, 0, 0, 0x01])));
+        let mut iface = Interface::new(config, &mut device, Instant::from_millis(0));
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24)).unwrap();
+        });
+        (iface, device)
+    }
+
+    /// Polls as the stack's loop does, 10 ms apart, for a second.
+    fn run(iface: &mut Interface, device: &mut Loopback, sockets: &mut SocketTable<'static>, now_ms: &mut u64) {
+        for _ in 0..100 {
+            *now_ms += 10;
+            sockets.pump_sends(*now_ms);
+            iface.poll(Instant::from_millis(*now_ms as i64), device, sockets.set_mut());
+        }
+    }
+
+    fn connect(sockets: &mut SocketTable<'static>, iface: &mut Interface, handle: u32, owner: u64, port: u16) -> u16 {
+        let local_port = sockets.ephemeral_port();
+        let remote = IpEndpoint::new(IpAddress::v4(10, 0, 2, 15), port);
+        match sockets.get_mut(handle, owner) {
+            Some(Socket::Tcp(socket)) => socket.connect(iface.context(), remote, local_port).unwrap(),
+            _ => panic!("socket {} is not a TCP socket of {}", handle, owner),
+        }
+        local_port
+    }
+
+    fn recv(sockets: &mut SocketTable<'static>, handle: u32, owner: u64) -> Vec<u8> {
+        let mut buf = [0u8; 64];
+        match sockets.get_mut(handle, owner) {
+            Some(Socket::Tcp(socket)) => {
+                let len = socket.recv_slice(&mut buf).unwrap();
+                buf[..len].to_vec()
+            },
+            _ => panic!("socket {} is not a TCP socket of {}", handle, owner),
+        }
+    }
+
+    #[test]
+    fn loopback_connection_between_two_tasks() {
+        const SERVER: u64 = 1;
+        const CLIENT: u64 = 2;
+        let (mut iface, mut device) = loopback();
+        let mut sockets = table(8, 16);
+        let mut now_ms = 0;
+
+        sockets.check_quota(SERVER, 2).unwrap();
+        let listener = sockets.insert_listener(SERVER, 7000, 2);
+        let client = open(&mut sockets, CLIENT).unwrap();
+        let local_port = connect(&mut sockets, &mut iface, client, CLIENT, 7000);
+        assert!(EPHEMERAL_PORTS.contains(&local_port));
+        assert_eq!(sockets.connect_state(client, CLIENT), Some(ConnectState::Connecting));
+
+        run(&mut iface, &mut device, &mut sockets, &mut now_ms);
+        assert_eq!(sockets.connect_state(client, CLIENT), Some(ConnectState::Established));
+        let announced = sockets.newly_established();
+        assert_eq!(announced.len(), 1);
+        assert_eq!((announced[0].0, announced[0].1, announced[0].2.port), (listener, SERVER, local_port));
+        assert!(sockets.newly_established().is_empty(), "a connection is announced once");
+
+        // Only the listener's owner can accept.
+        assert_eq!(sockets.accept(listener, CLIENT), Err(()));
+        let (accepted, peer) = sockets.accept(listener, SERVER).unwrap().unwrap();
+        assert_eq!(peer, IpEndpoint::new(IpAddress::v4(10, 0, 2, 15), local_port));
+        assert_eq!(sockets.local_port(accepted, SERVER), Some(7000));
+        assert_eq!(sockets.local_port(client, CLIENT), Some(local_port));
+        assert_eq!(sockets.accept(listener, SERVER), Ok(None));
+        assert_eq!(sockets.owned_by(SERVER), 3, "the backlog is topped up after an accept");
+
+        sockets.send(client, CLIENT, b"ping", now_ms).unwrap();
+        assert_eq!(sockets.flush(client, CLIENT, now_ms), Ok(0));
+        sockets.send(accepted, SERVER, b"pong", now_ms).unwrap();
+        assert_eq!(sockets.flush(accepted, SERVER, now_ms), Ok(0));
+        run(&mut iface, &mut device, &mut sockets, &mut now_ms);
+        assert_eq!(recv(&mut sockets, accepted, SERVER), b"ping");
+        assert_eq!(recv(&mut sockets, client, CLIENT), b"pong");
+    }
+
+    #[test]
+    fn loopback_connect_to_a_closed_port_is_refused() {
+        let (mut iface, mut device) = loopback();
+        let mut sockets = table(8, 16);
+        let mut now_ms = 0;
+        let client = open(&mut sockets, 2).unwrap();
+        connect(&mut sockets, &mut iface, client, 2, 7001);
+        run(&mut iface, &mut device, &mut sockets, &mut now_ms);
+        assert_eq!(sockets.connect_state(client, 2), Some(ConnectState::Refused));
+    }
+
+    #[test]
+    fn udp_ephemeral_ports_skip_bound_ones() {
+        let mut sockets = table(8, 8);
+        let start = *EPHEMERAL_PORTS.start();
+        sockets.insert(1, udp(start));
+        sockets.insert(2, udp(start + 2));
+        // An unbound socket holds no port.
+        sockets.insert(3, udp(0));
+        assert_eq!(sockets.udp_ephemeral_port(), Some(start + 1));
+        assert_eq!(sockets.udp_ephemeral_port(), Some(start + 3));
+        // TCP ports still go round in turn, whatever UDP has bound.
+        assert_eq!(sockets.ephemeral_port(), start + 4);
+    }
+
+    #[test]
+    fn udp_ephemeral_ports_wrap_and_run_out() {
+        let ports = EPHEMERAL_PORTS.len() as u32;
+        let mut sockets = table(ports, ports);
+        let last = *EPHEMERAL_PORTS.end();
+        for port in EPHEMERAL_PORTS.filter(|&port| port != last - 1) {
+            sockets.insert(1, udp(port));
+        }
+        assert_eq!(sockets.udp_ephemeral_port(), Some(last - 1));
+        sockets.insert(1, udp(last - 1));
+        assert_eq!(sockets.udp_ephemeral_port(), None);
+    }
+}
@@ -0,0 +1,124 @@
+// vnode/notifications/src/center.rs
+
+//! What is on screen and what was posted, apart from the IPC around it.
+//!
+//! Every notification goes into the history. It is also shown unless
+//! do-not-disturb is on and it isn't `Critical`. A shown notification stays
+//! until it expires, is clicked or is dismissed; `Critical` ones don't expire.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ids::Millis;
+use common::ipc::notification_ipc::{NotificationAction, NotificationId, NotificationRecord, Urgency, DEFAULT_TIMEOUT_MS};
+
+/// Notifications kept for `ListRecent`. The oldest are dropped first.
+pub const HISTORY_LEN: usize = 100;
+
+/// A notification on screen.
+pub struct Active {
+    pub id: NotificationId,
+    pub reply_chan: Option<u32>, // Where ActionInvoked goes
+    pub critical: bool,
+    expires_at: Option<u64>, // Tick; None for Critical
+}
+
+/// A notification as posted, before it has an ID.
+pub struct Post {
+    pub source: String,
+    pub summary: String,
+    pub body: String,
+    pub urgency: Urgency,
+    pub timeout_ms: u32,
+    pub actions: Vec<NotificationAction>,
+    pub reply_chan: Option<u32>,
+}
+
+pub struct Center {
+    active: Vec<Active>, // Oldest first
+    history: VecDeque<NotificationRecord>, // Oldest first
+    next_id: NotificationId,
+    dnd: bool,
+}
+
+impl Center {
+    pub fn new(dnd: bool) -> Self {
+        Self { active: Vec::new(), history: VecDeque::new(), next_id: 1, dnd }
+    }
+
+    /// Records a notification and decides whether to show it. Returns its
+    /// ID, and whether it went on screen.
+    pub fn post(&mut self, post: Post, now: u64, posted_at: u64) -> (NotificationId, bool) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let critical = post.urgency == Urgency::Critical;
+        let shown = critical || !self.dnd;
+        if shown {
+            let timeout_ms = if post.timeout_ms == 0 { DEFAULT_TIMEOUT_MS } else { post.timeout_ms };
+            let expires_at = if critical { None } else { Some(now + Millis::from_raw(timeout_ms as u64).to_ticks_ceil().raw()) };
+            self.active.push(Active { id, reply_chan: post.reply_chan, critical, expires_at });
+        }
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(NotificationRecord {
+            id,
+            source: post.source,
+            summary: post.summary,
+            body: post.body,
+            urgency: post.urgency,
+            posted_at,
+            shown,
+        });
+        (id, shown)
+    }
+
+    /// Takes down the notifications whose time is up at tick `now`.
+    pub fn expire(&mut self, now: u64) -> Vec<NotificationId> {
+        let mut expired = Vec::new();
+        self.active.retain(|active| match active.expires_at {
+            Some(at) if at <= now => {
+                expired.push(active.id);
+                false
+            },
+            _ => true,
+        });
+        expired
+    }
+
+    /// Takes a notification off the screen, when clicked or dismissed.
+    pub fn close(&mut self, id: NotificationId) -> Option<Active> {
+        let position = self.active.iter().position(|active| active.id == id)?;
+        Some(self.active.remove(position))
+    }
+
+    /// Whether `id` was ever handed out.
+    pub fn is_known(&self, id: NotificationId) -> bool {
+        id != 0 && id < self.next_id
+    }
+
+    /// Turns do-not-disturb on or off. Turning it on takes down everything
+    /// shown except `Critical` notifications; their IDs are returned.
+    pub fn set_dnd(&mut self, on: bool) -> Vec<NotificationId> {
+        self.dnd = on;
+        if !on {
+            return Vec::new();
+        }
+        let mut hidden = Vec::new();
+        self.active.retain(|active| {
+            if !active.critical {
+                hidden.push(active.id);
+            }
+            active.critical
+        });
+        hidden
+    }
+
+    /// The latest `limit` notifications, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<NotificationRecord> {
+        self.history.iter().rev().take(limit).cloned().collect()
+    }
+}
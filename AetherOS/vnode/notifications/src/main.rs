@@ -0,0 +1,308 @@
+// vnode/notifications/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::format;
+use alloc::string::ToString;
+
+use common::channels;
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::notification_ipc::{NotificationRequest, NotificationResponse, NotificationEvent, NotificationId, Urgency, DO_NOT_DISTURB_KEY};
+use common::ipc::ui_protocol::{UiRequest, UiResponse, UiEvent, NotificationButton, ScreenshotSaved, SCREENSHOT_SAVED_TOPIC};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue, SettingChanged};
+use common::ipc::mail_ipc::MailReceived;
+use common::ipc::registry_ipc::{PackageInstalled, PACKAGE_INSTALLED_TOPIC};
+use common::ipc::init_ipc::{CrashReport, ServiceStateChanged, SERVICE_CRASHED_TOPIC, SERVICE_STOPPED_TOPIC, SERVICE_RESTARTED_TOPIC};
+use common::coredump::{self, CORE_REASON_REQUESTED};
+use common::ipc::vfs_ipc::{StorageFault, STORAGE_DEGRADED_TOPIC, STORAGE_ERROR_TOPIC};
+use common::ipc::session_ipc;
+use common::ipc::audio_ipc::{AudioRequest, AudioResponse, ALERT_TONE_HZ, ALERT_TONE_MS, ALERT_TONE_VOLUME};
+use common::startup::{self, SELF_CHANNEL};
+use common::time;
+use common::i18n;
+use common::tr;
+
+mod center;
+use center::{Center, Post};
+
+/// Event bus topics turned into notifications, plus do-not-disturb and locale changes.
+const SUBSCRIPTIONS: [&str; 7] = ["mail.received", "package.", "service.", "screenshot.", "storage.", "settings.notifications.", "settings.locale."];
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+/// The notification for a system event, if it deserves one. Services
+/// starting are routine and don't.
+fn post_for(event: &Event) -> Option<Post> {
+    let (summary, body, urgency) = match event.topic.as_str() {
+        "mail.received" => {
+            let received: MailReceived = postcard::from_bytes(&event.payload).ok()?;
+            (tr!("notifications.mail.summary", "New mail"), tr!("notifications.mail.body", "A message arrived in {0}.", received.mailbox), Urgency::Normal)
+        },
+        PACKAGE_INSTALLED_TOPIC => {
+            let installed: PackageInstalled = postcard::from_bytes(&event.payload).ok()?;
+            (tr!("notifications.package.summary", "Package installed"), tr!("notifications.package.body", "{0} is ready to use.", installed.package_name), Urgency::Low)
+        },
+        SERVICE_RESTARTED_TOPIC => {
+            let changed: ServiceStateChanged = postcard::from_bytes(&event.payload).ok()?;
+            let body = match changed.previous_instance_id {
+                Some(previous) => tr!("notifications.restarted.body_replaced", "{0} was restarted (instance {1} replaced {2}).", changed.service_name, changed.instance_id, previous),
+                None => tr!("notifications.restarted.body", "{0} was restarted.", changed.service_name),
+            };
+            (tr!("notifications.restarted.summary", "Service restarted"), body, Urgency::Normal)
+        },
+        SERVICE_STOPPED_TOPIC => {
+            let changed: ServiceStateChanged = postcard::from_bytes(&event.payload).ok()?;
+            (tr!("notifications.stopped.summary", "Service stopped"), tr!("notifications.stopped.body", "{0} (instance {1}) stopped.", changed.service_name, changed.instance_id), Urgency::Normal)
+        },
+        SERVICE_CRASHED_TOPIC => {
+            let crash: CrashReport = postcard::from_bytes(&event.payload).ok()?;
+            // A debugger asked for that dump; nothing crashed.
+            if crash.reason == coredump::reason_name(CORE_REASON_REQUESTED) {
+                return None;
+            }
+            let name = crash.service_name.unwrap_or(crash.task_name);
+            let body = match crash.core_dump {
+                Some(path) => tr!("notifications.crashed.body", "{0} crashed ({1}). Its core dump is in {2}.", name, crash.reason, path),
+                None => tr!("notifications.crashed.body_no_dump", "{0} crashed ({1}).", name, crash.reason),
+            };
+            (tr!("notifications.crashed.summary", "Service crashed"), body, Urgency::Critical)
+        },
+        SCREENSHOT_SAVED_TOPIC => {
+            let saved: ScreenshotSaved = postcard::from_bytes(&event.payload).ok()?;
+            (tr!("notifications.screenshot.summary", "Screenshot saved"), tr!("notifications.screenshot.body", "Saved to {0}.", saved.path), Urgency::Low)
+        },
+        STORAGE_ERROR_TOPIC => {
+            let fault: StorageFault = postcard::from_bytes(&event.payload).ok()?;
+            // A failed write is told by the storage.degraded that follows it.
+            if fault.write {
+                return None;
+            }
+            let body = tr!("notifications.storage_error.body", "Part of {0} can't be read ({1}). The disk may be failing; back up your data.", fault.path, fault.cause.describe());
+            (tr!("notifications.storage_error.summary", "Storage error"), body, Urgency::Critical)
+        },
+        STORAGE_DEGRADED_TOPIC => {
+            let fault: StorageFault = postcard::from_bytes(&event.payload).ok()?;
+            (tr!("notifications.storage_degraded.summary", "Storage is read-only"), tr!("notifications.storage_degraded.body", "Writing {0} failed, so nothing more is saved until restart.", fault.path), Urgency::Critical)
+        },
+        _ => return None,
+    };
+    Some(Post { source: event.topic.clone(), summary, body, urgency, timeout_ms: 0, actions: alloc::vec::Vec::new(), reply_chan: None })
+}
+
+struct NotificationService {
+    client_chan: VNodeChannel, // Notify, Dismiss and ListRecent from apps
+    compositor_chan: VNodeChannel, // Draws the bubbles
+    ui_events_chan: VNodeChannel, // NotificationClicked from the compositor
+    bus_events_chan: VNodeChannel, // Events we subscribed to on the event bus
+    audio_chan: VNodeChannel, // The alert tone for critical notifications
+    vfs_chan: VNodeChannel, // Reads message catalogs when the locale changes
+    center: Center,
+    now: u64, // Timer ticks as of the last SYS_TIME call
+}
+
+impl NotificationService {
+    fn new(client_chan_id: u32, compositor_chan_id: u32, ui_events_chan_id: u32, event_bus_chan_id: u32, settings_chan_id: u32, bus_events_chan_id: u32, audio_chan_id: u32, vfs_chan_id: u32) -> Self {
+        log("Notifications: Initializing...");
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let bus_events_chan = VNodeChannel::new(bus_events_chan_id);
+        let mut vfs_chan = VNodeChannel::new(vfs_chan_id);
+        i18n::load_from_settings(&mut settings_chan, &mut vfs_chan);
+
+        let dnd = matches!(
+            settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: DO_NOT_DISTURB_KEY.to_string() }),
+            Ok(SettingsResponse::Value { value: SettingValue::Bool(true), .. })
+        );
+        for topic_prefix in SUBSCRIPTIONS {
+            let subscribe = EventBusRequest::Subscribe { topic_prefix: topic_prefix.to_string(), reply_chan: bus_events_chan.id };
+            if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+                log(&format!("Notifications: Failed to subscribe to '{}'; those events won't be shown.", topic_prefix));
+            }
+        }
+        if dnd {
+            log("Notifications: Do-not-disturb is on; only critical notifications will be shown.");
+        }
+
+        Self {
+            client_chan: VNodeChannel::new(client_chan_id),
+            compositor_chan: VNodeChannel::new(compositor_chan_id),
+            ui_events_chan: VNodeChannel::new(ui_events_chan_id),
+            bus_events_chan,
+            audio_chan: VNodeChannel::new(audio_chan_id),
+            vfs_chan,
+            center: Center::new(dnd),
+            now: unsafe { syscall3(SYS_TIME, 0, 0, 0) },
+        }
+    }
+
+    /// Records `post` and puts it on screen unless do-not-disturb holds it back.
+    fn post(&mut self, post: Post) -> (NotificationId, bool) {
+        let summary = post.summary.clone();
+        let body = post.body.clone();
+        let critical = post.urgency == Urgency::Critical;
+        let actions = post.actions.iter().map(|action| NotificationButton { key: action.key.clone(), label: action.label.clone() }).collect();
+        let (id, shown) = self.center.post(post, self.now, time::now_secs());
+        if shown {
+            let show = UiRequest::ShowNotification { id, summary, body, critical, actions, events_chan: self.ui_events_chan.id };
+            if !matches!(self.compositor_chan.send_and_recv::<UiRequest, UiResponse>(&show), Ok(UiResponse::Success { .. })) {
+                log(&format!("Notifications: The compositor didn't show notification {}; it is in the history only.", id));
+            }
+            if critical {
+                self.alert(id);
+            }
+        }
+        (id, shown)
+    }
+
+    /// Sounds the alert tone. Without sound the bubble is all there is.
+    fn alert(&mut self, id: NotificationId) {
+        let tone = AudioRequest::PlayTone { freq_hz: ALERT_TONE_HZ, duration_ms: ALERT_TONE_MS, volume: ALERT_TONE_VOLUME };
+        if !matches!(self.audio_chan.send_and_recv::<AudioRequest, AudioResponse>(&tone), Ok(AudioResponse::Playing { .. })) {
+            log(&format!("Notifications: No alert tone for critical notification {}.", id));
+        }
+    }
+
+    fn hide(&mut self, id: NotificationId) {
+        let hide = UiRequest::HideNotification { id };
+        if !matches!(self.compositor_chan.send_and_recv::<UiRequest, UiResponse>(&hide), Ok(UiResponse::Success { .. })) {
+            log(&format!("Notifications: The compositor didn't take notification {} down.", id));
+        }
+    }
+
+    fn handle_request(&mut self, request: NotificationRequest) -> NotificationResponse {
+        match request {
+            NotificationRequest::Notify { summary, body, urgency, timeout_ms, actions, reply_chan } => {
+                if summary.is_empty() {
+                    return NotificationResponse::Error("A notification needs a summary.".to_string());
+                }
+                let source = match session_ipc::last_sender() {
+                    Some(task_id) => format!("task {}", task_id),
+                    None => "unknown".to_string(),
+                };
+                let (id, shown) = self.post(Post { source, summary, body, urgency, timeout_ms, actions, reply_chan });
+                NotificationResponse::Posted { id, shown }
+            },
+            NotificationRequest::Dismiss { id } => {
+                if self.center.close(id).is_some() {
+                    self.hide(id);
+                    NotificationResponse::Success
+                } else if self.center.is_known(id) {
+                    NotificationResponse::Success // Already gone
+                } else {
+                    NotificationResponse::Error(format!("Notification {} not found.", id))
+                }
+            },
+            NotificationRequest::ListRecent { limit } => NotificationResponse::Recent(self.center.recent(limit as usize)),
+        }
+    }
+
+    /// A click takes the bubble down in the compositor; ours goes too. A
+    /// click on an action button is passed on to whoever posted it.
+    fn handle_ui_events(&mut self) {
+        while let Ok(Some(event_data)) = self.ui_events_chan.recv_non_blocking() {
+            let (id, action) = match postcard::from_bytes::<UiEvent>(&event_data) {
+                Ok(UiEvent::NotificationClicked { id, action }) => (id, action),
+                _ => continue,
+            };
+            let active = match self.center.close(id) {
+                Some(active) => active,
+                None => continue,
+            };
+            if let (Some(action), Some(reply_chan)) = (action, active.reply_chan) {
+                let mut chan = VNodeChannel::new(reply_chan);
+                if chan.send(&NotificationEvent::ActionInvoked { id, action }).is_err() {
+                    log(&format!("Notifications: Failed to deliver the action of notification {} on channel {}.", id, reply_chan));
+                }
+            }
+        }
+    }
+
+    fn handle_bus_events(&mut self) {
+        while let Ok(Some(event_data)) = self.bus_events_chan.recv_non_blocking() {
+            let event = match postcard::from_bytes::<Event>(&event_data) {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            if event.topic == format!("settings.{}", DO_NOT_DISTURB_KEY) {
+                if let Ok(SettingChanged { value: SettingValue::Bool(on), .. }) = postcard::from_bytes(&event.payload) {
+                    log(&format!("Notifications: Do-not-disturb turned {}.", if on { "on" } else { "off" }));
+                    for id in self.center.set_dnd(on) {
+                        self.hide(id);
+                    }
+                }
+            } else if let Some(lang) = i18n::locale_changed(&event) {
+                // Bubbles already on screen keep their language; new ones use the new one.
+                if let Err(e) = i18n::set_locale(&mut self.vfs_chan, &lang) {
+                    log(&format!("Notifications: Keeping the current locale: {}.", e));
+                }
+            } else if let Some(post) = post_for(&event) {
+                self.post(post);
+            }
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Notifications: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                if let Ok(request) = postcard::from_bytes::<NotificationRequest>(&req_data) {
+                    let response = self.handle_request(request);
+                    self.client_chan.send(&response).unwrap_or_else(|_| log("Notifications: Failed to send response to client."));
+                } else {
+                    log("Notifications: Failed to deserialize NotificationRequest.");
+                }
+            }
+
+            self.handle_ui_events();
+            self.handle_bus_events();
+
+            for id in self.center.expire(self.now) {
+                self.hide(id);
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            self.now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let mut service = NotificationService::new(
+        channel(SELF_CHANNEL, channels::NOTIFICATIONS),
+        channel("compositor", channels::DISPLAY_COMPOSITOR),
+        channels::NOTIFICATIONS_CLICKS,
+        channel("event-bus", channels::EVENT_BUS),
+        channel("settings", channels::SETTINGS),
+        channels::NOTIFICATIONS_EVENTS,
+        channel("audio-mixer", channels::AUDIO_MIXER),
+        channel("vfs", channels::VFS),
+    );
+    service.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Notifications V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
@@ -0,0 +1,223 @@
+// vnode/registry/src/bandwidth.rs
+
+//! Bandwidth accounting for the swarm: aggregate upload and download limits,
+//! and per-peer traffic counters.
+//!
+//! Limits are token buckets refilled from the timer. A bucket may go into
+//! debt: a chunk is sent or fetched whole once the bucket is out of debt, and
+//! its size is charged afterwards. Chunks are at most 256 KiB, so the rate
+//! over any few seconds stays at the limit even though single chunks burst.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::ipc::registry_ipc::PeerTraffic;
+use crate::metrics::{Counter, Gauge, Registry};
+
+/// Timer ticks per second (10 ms ticks).
+pub const TICKS_PER_SECOND: u64 = 100;
+
+/// Rates are averaged over this many ticks: one minute.
+pub const RATE_WINDOW_TICKS: u64 = 60 * TICKS_PER_SECOND;
+const RATE_SLOTS: usize = 6;
+const SLOT_TICKS: u64 = RATE_WINDOW_TICKS / RATE_SLOTS as u64;
+
+/// A peer is identified by the address it talks to us from.
+pub type PeerAddr = ([u8; 4], u16);
+
+/// Limits `rate` bytes per second, allowing bursts of one second's worth.
+/// A rate of 0 means unlimited.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: u64,
+    tokens: i64,
+    last_refill: u64,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, now: u64) -> Self {
+        Self { rate, tokens: rate as i64, last_refill: now }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Changes the limit. Debt is kept, so lowering the limit slows the next
+    /// transfer down immediately, but any saved-up burst above the new limit is dropped.
+    pub fn set_rate(&mut self, rate: u64, now: u64) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as i64);
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.last_refill);
+        if elapsed == 0 {
+            return;
+        }
+        self.last_refill = now;
+        let earned = self.rate.saturating_mul(elapsed) / TICKS_PER_SECOND;
+        self.tokens = self.tokens.saturating_add(earned.min(i64::MAX as u64) as i64).min(self.rate as i64);
+    }
+
+    /// Whether a transfer may start now.
+    pub fn ready(&mut self, now: u64) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        self.refill(now);
+        self.tokens >= 0
+    }
+
+    /// Charges `bytes` that were just transferred.
+    pub fn consume(&mut self, bytes: u64, now: u64) {
+        if self.rate == 0 {
+            return;
+        }
+        self.refill(now);
+        self.tokens = self.tokens.saturating_sub(bytes.min(i64::MAX as u64) as i64);
+    }
+
+    /// Ticks until `ready` will return true, for reporting a retry hint.
+    pub fn wait_ticks(&mut self, now: u64) -> u64 {
+        if self.ready(now) {
+            return 0;
+        }
+        ((-self.tokens) as u64 * TICKS_PER_SECOND).div_ceil(self.rate)
+    }
+}
+
+/// Bytes moved in the last `RATE_WINDOW_TICKS`, kept in fixed slots so old
+/// traffic drops out without storing every transfer.
+#[derive(Debug, Clone, Default)]
+pub struct RollingRate {
+    slots: [u64; RATE_SLOTS],
+    current_slot: u64, // now / SLOT_TICKS of the last update
+}
+
+impl RollingRate {
+    fn advance(&mut self, now: u64) {
+        let slot = now / SLOT_TICKS;
+        let stale = slot.saturating_sub(self.current_slot).min(RATE_SLOTS as u64);
+        for i in 1..=stale {
+            self.slots[((self.current_slot + i) % RATE_SLOTS as u64) as usize] = 0;
+        }
+        self.current_slot = self.current_slot.max(slot);
+    }
+
+    pub fn record(&mut self, bytes: u64, now: u64) {
+        self.advance(now);
+        let index = (self.current_slot % RATE_SLOTS as u64) as usize;
+        self.slots[index] = self.slots[index].saturating_add(bytes);
+    }
+
+    /// Average bytes per second over the window.
+    pub fn per_second(&mut self, now: u64) -> u64 {
+        self.advance(now);
+        self.slots.iter().sum::<u64>() / (RATE_WINDOW_TICKS / TICKS_PER_SECOND)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerStats {
+    pub bytes_served: u64,
+    pub chunks_served: u64,
+    pub bytes_fetched: u64,
+    pub chunks_fetched: u64,
+    served: RollingRate,
+    fetched: RollingRate,
+}
+
+struct BandwidthMetrics {
+    bytes_served: Counter,
+    chunks_served: Counter,
+    bytes_fetched: Counter,
+    chunks_fetched: Counter,
+    upload_limit: Gauge,
+    download_limit: Gauge,
+}
+
+/// The aggregate limits and the traffic of every peer we have exchanged chunks with.
+pub struct Bandwidth {
+    pub upload: TokenBucket,
+    pub download: TokenBucket,
+    upload_rate: RollingRate,
+    download_rate: RollingRate,
+    peers: BTreeMap<PeerAddr, PeerStats>,
+    metrics: BandwidthMetrics,
+}
+
+impl Bandwidth {
+    /// Limits are in bytes per second; 0 is unlimited.
+    pub fn new(upload_limit: u64, download_limit: u64, now: u64, metrics: &mut Registry) -> Self {
+        let metrics = BandwidthMetrics {
+            bytes_served: metrics.counter("swarm_bytes_served_total", "Chunk bytes uploaded to peers."),
+            chunks_served: metrics.counter("swarm_chunks_served_total", "Chunks uploaded to peers."),
+            bytes_fetched: metrics.counter("swarm_bytes_fetched_total", "Chunk bytes downloaded from peers."),
+            chunks_fetched: metrics.counter("swarm_chunks_fetched_total", "Chunks downloaded from peers."),
+            upload_limit: metrics.gauge("swarm_upload_limit_bytes", "Upload limit in bytes per second; 0 is unlimited."),
+            download_limit: metrics.gauge("swarm_download_limit_bytes", "Download limit in bytes per second; 0 is unlimited."),
+        };
+        metrics.upload_limit.set(upload_limit as i64);
+        metrics.download_limit.set(download_limit as i64);
+        Self {
+            upload: TokenBucket::new(upload_limit, now),
+            download: TokenBucket::new(download_limit, now),
+            upload_rate: RollingRate::default(),
+            download_rate: RollingRate::default(),
+            peers: BTreeMap::new(),
+            metrics,
+        }
+    }
+
+    /// Applies new limits at once; transfers in progress finish at the old rate.
+    pub fn set_limits(&mut self, upload_limit: u64, download_limit: u64, now: u64) {
+        self.upload.set_rate(upload_limit, now);
+        self.download.set_rate(download_limit, now);
+        self.metrics.upload_limit.set(upload_limit as i64);
+        self.metrics.download_limit.set(download_limit as i64);
+    }
+
+    pub fn record_served(&mut self, peer: PeerAddr, bytes: u64, now: u64) {
+        self.upload.consume(bytes, now);
+        self.upload_rate.record(bytes, now);
+        let stats = self.peers.entry(peer).or_default();
+        stats.bytes_served += bytes;
+        stats.chunks_served += 1;
+        stats.served.record(bytes, now);
+        self.metrics.bytes_served.add(bytes);
+        self.metrics.chunks_served.inc();
+    }
+
+    pub fn record_fetched(&mut self, peer: PeerAddr, bytes: u64, now: u64) {
+        self.download.consume(bytes, now);
+        self.download_rate.record(bytes, now);
+        let stats = self.peers.entry(peer).or_default();
+        stats.bytes_fetched += bytes;
+        stats.chunks_fetched += 1;
+        stats.fetched.record(bytes, now);
+        self.metrics.bytes_fetched.add(bytes);
+        self.metrics.chunks_fetched.inc();
+    }
+
+    /// Aggregate upload and download rates over the last minute, in bytes per second.
+    pub fn rates(&mut self, now: u64) -> (u64, u64) {
+        (self.upload_rate.per_second(now), self.download_rate.per_second(now))
+    }
+
+    pub fn peer_traffic(&mut self, now: u64) -> Vec<PeerTraffic> {
+        self.peers.iter_mut().map(|((addr, port), stats)| PeerTraffic {
+            addr: *addr,
+            port: *port,
+            bytes_served: stats.bytes_served,
+            chunks_served: stats.chunks_served,
+            bytes_fetched: stats.bytes_fetched,
+            chunks_fetched: stats.chunks_fetched,
+            serve_rate: stats.served.per_second(now),
+            fetch_rate: stats.fetched.per_second(now),
+        }).collect()
+    }
+}
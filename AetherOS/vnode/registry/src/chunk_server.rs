@@ -0,0 +1,198 @@
+// vnode/registry/src/chunk_server.rs
+
+//! Serves package chunks to other nodes, within the upload limit and fairly
+//! between peers.
+//!
+//! A request is answered right away while the upload bucket allows it.
+//! Otherwise it waits in a per-peer queue; queues are drained round-robin, so
+//! a peer sending many requests only delays its own. A peer may queue at most
+//! its fair share of `MAX_QUEUED` (split evenly among the peers waiting), and
+//! gets `Busy` beyond that or when the queue is full.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use crate::bandwidth::{Bandwidth, PeerAddr};
+use crate::cid::Cid;
+use crate::manifest::PackageManifest;
+use crate::metrics::{Counter, Gauge, Registry};
+use crate::swarm_engine::nexus_net_transport::ChunkReply;
+
+/// Most requests waiting across all peers.
+pub const MAX_QUEUED: usize = 64;
+/// A peer may always queue this many, however many peers are waiting.
+pub const MIN_SHARE: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Answer now.
+    Serve,
+    /// Queued behind the upload limit.
+    Deferred,
+    /// The peer is over its share or the queue is full.
+    Busy,
+}
+
+/// Requests waiting for upload capacity, one FIFO per peer.
+#[derive(Default)]
+pub struct ServeQueue {
+    queues: BTreeMap<PeerAddr, VecDeque<Cid>>,
+    last_served: Option<PeerAddr>,
+    len: usize,
+}
+
+impl ServeQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The most `peer` may have queued right now.
+    fn share(&self, peer: PeerAddr) -> usize {
+        let waiting = self.queues.len() + usize::from(!self.queues.contains_key(&peer));
+        (MAX_QUEUED / waiting).max(MIN_SHARE)
+    }
+
+    /// Decides what to do with a request from `peer`. `upload_ready` is
+    /// whether the upload bucket allows a transfer now.
+    pub fn admit(&mut self, peer: PeerAddr, cid: Cid, upload_ready: bool) -> Admission {
+        if upload_ready && self.len == 0 {
+            return Admission::Serve;
+        }
+        let queued = self.queues.get(&peer).map_or(0, VecDeque::len);
+        if self.len >= MAX_QUEUED || queued >= self.share(peer) {
+            return Admission::Busy;
+        }
+        self.queues.entry(peer).or_default().push_back(cid);
+        self.len += 1;
+        Admission::Deferred
+    }
+
+    /// The next request in round-robin order: the first peer after the one
+    /// served last.
+    pub fn pop(&mut self) -> Option<(PeerAddr, Cid)> {
+        let peer = match self.last_served {
+            Some(last) => self.queues.range((core::ops::Bound::Excluded(last), core::ops::Bound::Unbounded)).next()
+                .or_else(|| self.queues.iter().next())
+                .map(|(peer, _)| *peer)?,
+            None => *self.queues.keys().next()?,
+        };
+        let queue = self.queues.get_mut(&peer)?;
+        let cid = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&peer);
+        }
+        self.len -= 1;
+        self.last_served = Some(peer);
+        Some((peer, cid))
+    }
+}
+
+struct ServerMetrics {
+    deferred: Counter,
+    rejected: Counter,
+    queue_depth: Gauge,
+}
+
+/// The chunks this node can serve, and the queue in front of them.
+pub struct ChunkServer {
+    chunks: BTreeMap<Vec<u8>, Vec<u8>>, // By CID bytes
+    roots: BTreeMap<Vec<u8>, Cid>, // Each chunk's package, by chunk CID bytes
+    served: Vec<Cid>, // Root CIDs of the chunks served since `take_served`
+    queue: ServeQueue,
+    metrics: ServerMetrics,
+    pub deferred_total: u64,
+    pub rejected_total: u64,
+}
+
+impl ChunkServer {
+    pub fn new(metrics: &mut Registry) -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            roots: BTreeMap::new(),
+            served: Vec::new(),
+            queue: ServeQueue::default(),
+            metrics: ServerMetrics {
+                deferred: metrics.counter("swarm_serve_deferred_total", "Chunk requests queued behind the upload limit."),
+                rejected: metrics.counter("swarm_serve_rejected_total", "Chunk requests answered Busy."),
+                queue_depth: metrics.gauge("swarm_serve_queue_depth", "Chunk requests waiting for upload capacity."),
+            },
+            deferred_total: 0,
+            rejected_total: 0,
+        }
+    }
+
+    /// Makes the chunks of a package available to peers. `chunks` is in
+    /// `manifest.chunk_cids` order, as `ManifestBuilder::build` returns them.
+    pub fn add_package(&mut self, manifest: &PackageManifest, chunks: Vec<Vec<u8>>) {
+        for (cid, chunk) in manifest.chunk_cids.iter().zip(chunks) {
+            self.chunks.insert(cid.as_bytes().to_vec(), chunk);
+            self.roots.insert(cid.as_bytes().to_vec(), manifest.root_cid);
+        }
+    }
+
+    /// The packages of the chunks served since the last call, one entry per
+    /// chunk, for the health counters.
+    pub fn take_served(&mut self) -> Vec<Cid> {
+        core::mem::take(&mut self.served)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn reply_for(&self, cid: &Cid) -> ChunkReply {
+        match self.chunks.get(cid.as_bytes()) {
+            Some(data) => ChunkReply::Data(data.clone()),
+            None => ChunkReply::NotFound,
+        }
+    }
+
+    fn serve(&mut self, peer: PeerAddr, cid: &Cid, bandwidth: &mut Bandwidth, now: u64) -> ChunkReply {
+        let reply = self.reply_for(cid);
+        if let ChunkReply::Data(data) = &reply {
+            bandwidth.record_served(peer, data.len() as u64, now);
+            self.served.extend(self.roots.get(cid.as_bytes()).copied());
+        }
+        reply
+    }
+
+    /// Handles a request that just arrived. Returns the reply to send now, if any;
+    /// a deferred request is answered later by `drain`.
+    pub fn request(&mut self, peer: PeerAddr, cid: Cid, bandwidth: &mut Bandwidth, now: u64) -> Option<ChunkReply> {
+        // Unknown chunks cost nothing to refuse, so they never wait in the queue.
+        if !self.chunks.contains_key(cid.as_bytes()) {
+            return Some(ChunkReply::NotFound);
+        }
+        let reply = match self.queue.admit(peer, cid, bandwidth.upload.ready(now)) {
+            Admission::Serve => Some(self.serve(peer, &cid, bandwidth, now)),
+            Admission::Deferred => {
+                self.deferred_total += 1;
+                self.metrics.deferred.inc();
+                None
+            },
+            Admission::Busy => {
+                self.rejected_total += 1;
+                self.metrics.rejected.inc();
+                Some(ChunkReply::Busy { retry_after_ticks: bandwidth.upload.wait_ticks(now).max(1) })
+            },
+        };
+        self.metrics.queue_depth.set(self.queue.len() as i64);
+        reply
+    }
+
+    /// Answers queued requests while the upload bucket allows, in round-robin order.
+    pub fn drain(&mut self, bandwidth: &mut Bandwidth, now: u64) -> Vec<(PeerAddr, ChunkReply)> {
+        let mut replies = Vec::new();
+        while bandwidth.upload.ready(now) {
+            let (peer, cid) = match self.queue.pop() {
+                Some(next) => next,
+                None => break,
+            };
+            replies.push((peer, self.serve(peer, &cid, bandwidth, now)));
+        }
+        self.metrics.queue_depth.set(self.queue.len() as i64);
+        replies
+    }
+}
@@ -0,0 +1,172 @@
+// vnode/registry/src/confirm.rs
+
+//! Installs parked until the user decides whether to trust the publisher,
+//! or which of the capabilities the package declares to grant it.
+//!
+//! A ticket is bound to the task that sent `Install` and to the identity that
+//! task had at the time. Any other task, or the same task after a logout or
+//! login, cannot answer it. Tickets that are not answered in time are dropped
+//! together with their downloaded data.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::registry_ipc::InstalledPackage;
+use crate::ipc::session_ipc::AidBytes;
+
+/// How long a ticket stays valid: 60 seconds at 100 ticks/s.
+pub const CONFIRMATION_TIMEOUT_TICKS: u64 = 6000;
+
+/// Most installs waiting for an answer at once. Further untrusted installs are
+/// refused until a ticket is answered or expires.
+pub const MAX_PENDING: usize = 8;
+
+pub struct PendingInstall {
+    pub requester: u64,
+    pub identity: Option<AidBytes>,
+    pub package_name: String,
+    pub publisher: AidBytes,
+    pub data: Vec<u8>,
+    /// What goes into the installed-packages index if the user proceeds
+    /// without denying anything.
+    pub installed: InstalledPackage,
+    expires_at: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TakeError {
+    /// No such ticket, or it has expired.
+    Unknown,
+    /// The ticket belongs to another task or identity. It stays pending.
+    WrongRequester,
+}
+
+pub struct PendingInstalls {
+    tickets: BTreeMap<u64, PendingInstall>,
+    next_ticket: u64,
+}
+
+impl PendingInstalls {
+    pub fn new() -> Self {
+        Self { tickets: BTreeMap::new(), next_ticket: 1 }
+    }
+
+    /// Parks an install and returns its ticket, or `None` if too many are pending.
+    pub fn park(&mut self, requester: u64, identity: Option<AidBytes>, package_name: String, publisher: AidBytes, data: Vec<u8>, installed: InstalledPackage, now: u64) -> Option<u64> {
+        if self.tickets.len() >= MAX_PENDING {
+            return None;
+        }
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        let expires_at = now + CONFIRMATION_TIMEOUT_TICKS;
+        self.tickets.insert(ticket, PendingInstall { requester, identity, package_name, publisher, data, installed, expires_at });
+        Some(ticket)
+    }
+
+    /// Removes and returns the install behind `ticket` if `requester` (with
+    /// `identity`) is the task it was issued to and it has not expired.
+    pub fn take(&mut self, ticket: u64, requester: u64, identity: Option<AidBytes>, now: u64) -> Result<PendingInstall, TakeError> {
+        match self.tickets.get(&ticket) {
+            None => Err(TakeError::Unknown),
+            Some(pending) if pending.expires_at <= now => {
+                self.tickets.remove(&ticket);
+                Err(TakeError::Unknown)
+            },
+            Some(pending) if pending.requester != requester || pending.identity != identity => Err(TakeError::WrongRequester),
+            Some(_) => Ok(self.tickets.remove(&ticket).unwrap()),
+        }
+    }
+
+    /// Puts an install back under its old ticket, e.g. after a failed attempt to
+    /// persist the publisher. The original expiry is kept.
+    pub fn restore(&mut self, ticket: u64, pending: PendingInstall) {
+        self.tickets.insert(ticket, pending);
+    }
+
+    /// Drops expired tickets and their data. Returns the names of the packages dropped.
+    pub fn expire(&mut self, now: u64) -> Vec<String> {
+        let expired: Vec<u64> = self.tickets.iter()
+            .filter(|(_, pending)| pending.expires_at <= now)
+            .map(|(ticket, _)| *ticket)
+            .collect();
+        expired.into_iter().filter_map(|ticket| self.tickets.remove(&ticket)).map(|pending| pending.package_name).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::capability::DeclaredCapabilities;
+
+    const ALICE: AidBytes = [0xaa; 32];
+    const PUBLISHER: AidBytes = [0x11; 32];
+
+    fn installed() -> InstalledPackage {
+        InstalledPackage { version: String::from("1.0.0"), requested: DeclaredCapabilities::default(), approved: DeclaredCapabilities::default() }
+    }
+
+    fn park(pending: &mut PendingInstalls, requester: u64, name: &str, now: u64) -> Option<u64> {
+        pending.park(requester, Some(ALICE), String::from(name), PUBLISHER, vec![1, 2, 3], installed(), now)
+    }
+
+    #[test]
+    fn the_requester_takes_its_ticket_once() {
+        let mut pending = PendingInstalls::new();
+        let ticket = park(&mut pending, 7, "editor", 100).unwrap();
+        let install = pending.take(ticket, 7, Some(ALICE), 200).unwrap();
+        assert_eq!(install.package_name, "editor");
+        assert_eq!(install.data, vec![1, 2, 3]);
+        assert_eq!(pending.take(ticket, 7, Some(ALICE), 200).err(), Some(TakeError::Unknown));
+    }
+
+    #[test]
+    fn other_tasks_and_identities_cant_answer() {
+        let mut pending = PendingInstalls::new();
+        let ticket = park(&mut pending, 7, "editor", 100).unwrap();
+        assert_eq!(pending.take(ticket, 8, Some(ALICE), 200).err(), Some(TakeError::WrongRequester));
+        assert_eq!(pending.take(ticket, 7, None, 200).err(), Some(TakeError::WrongRequester));
+        assert_eq!(pending.take(ticket, 7, Some([0xbb; 32]), 200).err(), Some(TakeError::WrongRequester));
+        // The ticket is still there for the right task.
+        assert!(pending.take(ticket, 7, Some(ALICE), 200).is_ok());
+    }
+
+    #[test]
+    fn tickets_expire_after_the_timeout() {
+        let mut pending = PendingInstalls::new();
+        let ticket = park(&mut pending, 7, "editor", 100).unwrap();
+        let late = 100 + CONFIRMATION_TIMEOUT_TICKS;
+        assert_eq!(pending.take(ticket, 7, Some(ALICE), late).err(), Some(TakeError::Unknown));
+
+        let first = park(&mut pending, 7, "first", 100).unwrap();
+        let second = park(&mut pending, 7, "second", 200).unwrap();
+        assert_eq!(pending.expire(late - 1), Vec::<String>::new());
+        assert_eq!(pending.expire(late), vec![String::from("first")]);
+        assert_eq!(pending.take(first, 7, Some(ALICE), late).err(), Some(TakeError::Unknown));
+        assert!(pending.take(second, 7, Some(ALICE), late).is_ok());
+    }
+
+    #[test]
+    fn no_more_than_max_pending_wait_at_once() {
+        let mut pending = PendingInstalls::new();
+        let tickets: Vec<u64> = (0..MAX_PENDING).map(|i| park(&mut pending, i as u64, "package", 0).unwrap()).collect();
+        assert_eq!(park(&mut pending, 99, "one too many", 0), None);
+        // Tickets are never reused, even after one is answered.
+        pending.take(tickets[0], 0, Some(ALICE), 1).unwrap();
+        let next = park(&mut pending, 99, "package", 1).unwrap();
+        assert!(!tickets.contains(&next));
+    }
+
+    #[test]
+    fn a_restored_ticket_keeps_its_expiry() {
+        let mut pending = PendingInstalls::new();
+        let ticket = park(&mut pending, 7, "editor", 100).unwrap();
+        let install = pending.take(ticket, 7, Some(ALICE), 150).unwrap();
+        pending.restore(ticket, install);
+        assert_eq!(pending.expire(100 + CONFIRMATION_TIMEOUT_TICKS - 1), Vec::<String>::new());
+        assert_eq!(pending.expire(100 + CONFIRMATION_TIMEOUT_TICKS), vec![String::from("editor")]);
+    }
+}
@@ -0,0 +1,214 @@
+// vnode/registry/src/health.rs
+
+//! Popularity and health signals: how many nodes serve a package and when
+//! anyone last fetched it, so search can tell a widely mirrored package from
+//! one nobody can actually fetch.
+//!
+//! Each node counts, per root CID, the chunks it served and the fetches it
+//! made, successful or not. It gossips a `HealthSummary` per package it seeds
+//! or fetched on the search port: after every `Ping` and `Pong`, and to the
+//! search peers every `GOSSIP_INTERVAL_TICKS`. Summaries only carry what the
+//! sender knows first-hand, never numbers it heard from others, so a made-up
+//! number isn't passed on and kept fresh by the echo. What peers send is kept
+//! per peer and aggregated when asked: the seeder count is the larger of the
+//! seeders we know of directly (ourselves and every peer saying it seeds) and
+//! the largest count any peer sent; the last fetch is the most recent anyone
+//! reported.
+//!
+//! Gossip is only ever a hint. A reported count above `MAX_SEEDERS` is
+//! clamped to it and an age beyond `REPORT_TTL_TICKS` dropped, reports are
+//! forgotten `REPORT_TTL_TICKS` after they arrived, and a newer report from
+//! the same peer replaces the older one rather than adding to it. At most
+//! `MAX_REPORTERS` peers are kept per package and `MAX_PACKAGES` packages in
+//! all, evicting whatever was heard of longest ago, so a peer inventing CIDs
+//! can't grow the table.
+//!
+//! All times are in timer ticks.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use crate::bandwidth::{PeerAddr, TICKS_PER_SECOND};
+use crate::cid::Cid;
+use crate::ipc::registry_ipc::PackageHealth;
+use crate::swarm_engine::nexus_net_transport::HealthSummary;
+
+/// Largest seeder count believed from anyone, and the most shown.
+pub const MAX_SEEDERS: u32 = 64;
+/// How long a report counts, and the oldest last fetch taken from one: six hours.
+pub const REPORT_TTL_TICKS: u64 = 6 * 60 * 60 * TICKS_PER_SECOND;
+/// Peers whose reports are kept per package.
+pub const MAX_REPORTERS: usize = 16;
+/// Packages with reports kept at once.
+pub const MAX_PACKAGES: usize = 512;
+/// Summaries in one `SearchMessage::Health`, sent or read.
+pub const MAX_SUMMARIES: usize = 64;
+/// How often the search peers get our summaries: ten minutes.
+pub const GOSSIP_INTERVAL_TICKS: u64 = 10 * 60 * TICKS_PER_SECOND;
+
+/// Ranking boost for a package with at least one known seeder.
+pub const RETRIEVABLE_BOOST: u32 = 20;
+/// Extra boost per known seeder, up to `BOOSTED_SEEDERS` of them.
+pub const SEEDER_BOOST: u32 = 2;
+pub const BOOSTED_SEEDERS: u32 = 8;
+/// Extra boost for a package someone fetched within `RECENT_TICKS`.
+pub const RECENT_BOOST: u32 = 10;
+pub const RECENT_TICKS: u64 = 24 * 60 * 60 * TICKS_PER_SECOND;
+
+/// What is added to a result's text score when ranking it. A package with no
+/// known seeder gets nothing: it may well match, but it can't be fetched.
+pub fn boost(health: &PackageHealth) -> u32 {
+    if health.seeders == 0 {
+        return 0;
+    }
+    let recent = health.last_fetched_secs_ago.map_or(false, |ago| ago.saturating_mul(TICKS_PER_SECOND) <= RECENT_TICKS);
+    RETRIEVABLE_BOOST + health.seeders.min(BOOSTED_SEEDERS) * SEEDER_BOOST + if recent { RECENT_BOOST } else { 0 }
+}
+
+/// This node's own counters for one package.
+#[derive(Default)]
+struct Local {
+    root: Option<Cid>,
+    chunks_served: u64,
+    fetch_failures: u32,
+    last_success: Option<u64>, // A fetch by us, or a chunk served to a peer
+}
+
+/// One peer's latest summary for one package, clamped.
+struct Report {
+    seeding: bool,
+    seeders: u32,
+    last_fetched: Option<u64>,
+    received: u64,
+}
+
+#[derive(Default)]
+pub struct Health {
+    local: BTreeMap<Vec<u8>, Local>, // By root CID bytes
+    seeding: BTreeMap<Vec<u8>, Cid>, // Packages whose chunks we serve
+    reports: BTreeMap<Vec<u8>, BTreeMap<PeerAddr, Report>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes that we serve the chunks of `root`.
+    pub fn seed(&mut self, root: Cid) {
+        self.seeding.insert(root.as_bytes().to_vec(), root);
+    }
+
+    fn local(&mut self, root: &Cid) -> &mut Local {
+        let local = self.local.entry(root.as_bytes().to_vec()).or_default();
+        local.root = Some(*root);
+        local
+    }
+
+    /// A chunk of `root` went to a peer, which counts as a successful fetch.
+    pub fn served(&mut self, root: &Cid, now: u64) {
+        let local = self.local(root);
+        local.chunks_served += 1;
+        local.last_success = Some(now);
+    }
+
+    pub fn fetched(&mut self, root: &Cid, now: u64) {
+        self.local(root).last_success = Some(now);
+    }
+
+    pub fn fetch_failed(&mut self, root: &Cid) {
+        let local = self.local(root);
+        local.fetch_failures = local.fetch_failures.saturating_add(1);
+    }
+
+    /// Takes in a peer's summaries, clamping what it claims.
+    pub fn heard(&mut self, peer: PeerAddr, summaries: &[HealthSummary], now: u64) {
+        for summary in summaries.iter().take(MAX_SUMMARIES) {
+            let fetched_ago = summary.fetched_secs_ago.map(|secs| secs as u64 * TICKS_PER_SECOND).filter(|&ago| ago <= REPORT_TTL_TICKS);
+            let report = Report {
+                seeding: summary.seeding,
+                seeders: summary.seeders.min(MAX_SEEDERS),
+                last_fetched: fetched_ago.map(|ago| now.saturating_sub(ago)),
+                received: now,
+            };
+            let key = summary.root_cid.as_bytes().to_vec();
+            if !self.reports.contains_key(&key) && self.reports.len() >= MAX_PACKAGES {
+                self.evict_package();
+            }
+            let reporters = self.reports.entry(key).or_default();
+            if !reporters.contains_key(&peer) && reporters.len() >= MAX_REPORTERS {
+                let oldest = reporters.iter().min_by_key(|(_, report)| report.received).map(|(addr, _)| *addr);
+                if let Some(oldest) = oldest {
+                    reporters.remove(&oldest);
+                }
+            }
+            reporters.insert(peer, report);
+        }
+    }
+
+    /// Drops the package whose newest report is the oldest.
+    fn evict_package(&mut self) {
+        let newest = |reporters: &BTreeMap<PeerAddr, Report>| reporters.values().map(|report| report.received).max().unwrap_or(0);
+        let stale = self.reports.iter().min_by_key(|(_, reporters)| newest(reporters)).map(|(key, _)| key.clone());
+        if let Some(stale) = stale {
+            self.reports.remove(&stale);
+        }
+    }
+
+    /// Forgets reports older than `REPORT_TTL_TICKS`.
+    pub fn expire(&mut self, now: u64) {
+        for reporters in self.reports.values_mut() {
+            reporters.retain(|_, report| now.saturating_sub(report.received) < REPORT_TTL_TICKS);
+        }
+        self.reports.retain(|_, reporters| !reporters.is_empty());
+    }
+
+    fn fresh_reports<'a>(&'a self, key: &[u8], now: u64) -> impl Iterator<Item = (&'a PeerAddr, &'a Report)> + 'a {
+        self.reports.get(key).into_iter().flatten().filter(move |(_, report)| now.saturating_sub(report.received) < REPORT_TTL_TICKS)
+    }
+
+    /// The seeders we know of ourselves: this node if it serves the package,
+    /// `reported_by` and every peer whose fresh report says it seeds.
+    fn direct_seeders(&self, key: &[u8], reported_by: &[PeerAddr], now: u64) -> u32 {
+        let mut peers: BTreeSet<PeerAddr> = reported_by.iter().copied().collect();
+        peers.extend(self.fresh_reports(key, now).filter(|(_, report)| report.seeding).map(|(peer, _)| *peer));
+        (peers.len() as u32 + self.seeding.contains_key(key) as u32).min(MAX_SEEDERS)
+    }
+
+    /// The aggregate for `root`. `reported_by` are peers that just listed it
+    /// in a search answer, which serve it too.
+    pub fn of(&self, root: &Cid, reported_by: &[PeerAddr], now: u64) -> PackageHealth {
+        let key = root.as_bytes();
+        let local = self.local.get(key);
+        let direct = self.direct_seeders(key, reported_by, now);
+        let claimed = self.fresh_reports(key, now).map(|(_, report)| report.seeders).max().unwrap_or(0);
+        let last_fetched = self.fresh_reports(key, now).map(|(_, report)| report).filter_map(|report| report.last_fetched)
+            .chain(local.and_then(|local| local.last_success))
+            .max();
+        PackageHealth {
+            seeders: direct.max(claimed).min(MAX_SEEDERS),
+            last_fetched_secs_ago: last_fetched.map(|at| now.saturating_sub(at) / TICKS_PER_SECOND),
+            chunks_served: local.map_or(0, |local| local.chunks_served),
+            fetch_failures: local.map_or(0, |local| local.fetch_failures),
+        }
+    }
+
+    /// What we gossip, at most `MAX_SUMMARIES`, the packages we seed first.
+    pub fn summaries(&self, now: u64) -> Vec<HealthSummary> {
+        let mut roots: Vec<Cid> = self.seeding.values().copied().collect();
+        roots.extend(self.local.iter().filter(|(key, _)| !self.seeding.contains_key(*key)).filter_map(|(_, local)| local.root));
+        roots.into_iter().take(MAX_SUMMARIES).map(|root| {
+            let key = root.as_bytes();
+            let seeding = self.seeding.contains_key(key);
+            HealthSummary {
+                root_cid: root,
+                seeding,
+                seeders: self.direct_seeders(key, &[], now),
+                fetched_secs_ago: self.local.get(key).and_then(|local| local.last_success)
+                    .map(|at| (now.saturating_sub(at) / TICKS_PER_SECOND).min(u32::MAX as u64) as u32),
+            }
+        }).collect()
+    }
+}
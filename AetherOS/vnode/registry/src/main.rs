@@ -7,20 +7,63 @@ use core::panic::PanicInfo;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use core::cell::RefCell;
 
+use crate::channels;
 use crate::ipc::vnode::VNodeChannel;
-use crate::syscall::{syscall3, SYS_LOG, SUCCESS};
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use crate::ipc::registry_ipc::{self, RegistryRequest, RegistryResponse, InstallDecision, SwarmStats, BundleProblem, ImportOutcome, ImportResult};
+use crate::ipc::registry_ipc::{PackageInstalled, PACKAGE_INSTALLED_TOPIC};
+use crate::ipc::registry_ipc::{InstalledIndex, InstalledPackage, INSTALLED_INDEX_PATH};
+use crate::ipc::event_ipc::{EventBusRequest, EventBusResponse};
+use crate::ipc::settings_ipc::{SettingValue, SettingsRequest, SettingsResponse};
+use crate::ipc::session_ipc::{self, AidBytes};
+use crate::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse, XATTR_CID, XATTR_PACKAGE};
+use crate::ipc::vfs_lock;
+use crate::ipc::vfs_stream::VfsStreams;
+use crate::ipc::vfs_tx::VfsTx;
+use crate::ax;
+use crate::bundle::{self, BundleError};
+use crate::cid::compute_cid;
+use crate::manifest::PackageManifest;
+use crate::metrics::Registry;
 // RegistryService is a placeholder for future, more complex registry logic.
 // use crate::registry_service::RegistryService;
 use crate::swarm_engine::{SwarmEngine, SwarmTransport};
-use crate::arp_dht::{InMemoryDht, PeerInfo, NodeId};
-use crate::trust::{TrustStore, Aid};
+use crate::arp_dht::{InMemoryDht, DhtValue, NodeId};
+use crate::trust::{TrustStore, Aid, LocalIdentity};
 
 // Import NexusNetTransport - our concrete implementation of SwarmTransport using libnexus-net
-use crate::swarm_engine::nexus_net_transport::NexusNetTransport;
+use crate::swarm_engine::nexus_net_transport::{NexusNetTransport, NexusNetServer, SWARM_PORT, SEARCH_PORT};
 // Import GlobalSearchService for demonstrating search capabilities
 use crate::swarm_engine::global_search::GlobalSearchService;
 
+mod bandwidth;
+mod chunk_server;
+mod confirm;
+mod health;
+mod pacing;
+mod publishers;
+mod search;
+mod swarm_state;
+
+use bandwidth::Bandwidth;
+use chunk_server::ChunkServer;
+use confirm::{PendingInstalls, TakeError};
+use pacing::{LimitWatch, PacedTransport};
+use publishers::{TrustedPublishers, TRUSTED_PUBLISHERS_PATH};
+use search::Search;
+use swarm_state::{SwarmState, BOOTSTRAP_PEERS_KEY};
+
+const PACKAGES_DIR: &str = "/var/aether/registry/packages";
+/// This node's keypair; created on first start.
+const IDENTITY_PATH: &str = "/var/aether/registry/identity";
+const MAX_TRUST_FILE_SIZE: u32 = 64 * 1024;
+const MAX_BUNDLE_SIZE: usize = 64 * 1024 * 1024;
+const MAX_INDEX_SIZE: usize = 1024 * 1024;
+
 // Temporary log function for V-Nodes. This sends a syscall to the kernel for logging.
 fn log(msg: &str) {
     unsafe {
@@ -34,12 +77,526 @@ fn log(msg: &str) {
     }
 }
 
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Serving chunks to peers, and the accounting shared with `PacedTransport`.
+struct SwarmTraffic {
+    bandwidth: Rc<RefCell<Bandwidth>>,
+    limits: Rc<RefCell<LimitWatch>>,
+    server: ChunkServer,
+    socket: Option<NexusNetServer>, // None if the serving port couldn't be opened
+}
+
+struct RegistryService<T: SwarmTransport> {
+    own_chan: VNodeChannel,
+    vfs_chan: VNodeChannel,
+    event_bus_chan: VNodeChannel, // For package.installed
+    swarm: SwarmEngine<PacedTransport<T>>,
+    trust_store: TrustStore,
+    traffic: SwarmTraffic,
+    search: Search,
+    swarm_state: SwarmState, // Peers and stored values, saved across reboots
+    metrics: Registry,
+
+    catalog: BTreeMap<String, PackageManifest>, // Known packages by name
+    trusted: TrustedPublishers,
+    installed: InstalledIndex, // What is installed, with the capabilities approved for it
+    pending: PendingInstalls,
+    now: u64, // Timer ticks as of the last SYS_TIME call
+}
+
+impl<T: SwarmTransport> RegistryService<T> {
+    fn new(own_chan: VNodeChannel, vfs_chan_id: u32, event_bus_chan: VNodeChannel, swarm: SwarmEngine<PacedTransport<T>>, trust_store: TrustStore, traffic: SwarmTraffic, search: Search, swarm_state: SwarmState, metrics: Registry) -> Self {
+        let mut service = Self {
+            own_chan,
+            vfs_chan: VNodeChannel::new(vfs_chan_id),
+            event_bus_chan,
+            swarm,
+            trust_store,
+            traffic,
+            search,
+            swarm_state,
+            metrics,
+            catalog: BTreeMap::new(),
+            trusted: TrustedPublishers::default(),
+            installed: InstalledIndex::new(),
+            pending: PendingInstalls::new(),
+            now: unsafe { syscall3(SYS_TIME, 0, 0, 0) },
+        };
+        service.load_trusted();
+        service.load_installed();
+        service
+    }
+
+    fn add_to_catalog(&mut self, manifest: PackageManifest) {
+        self.catalog.insert(manifest.name.clone(), manifest);
+    }
+
+    /// Loads the trusted publishers list. A missing file means nobody is trusted yet.
+    fn load_trusted(&mut self) {
+        let contents = match self.read_file(TRUSTED_PUBLISHERS_PATH) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log(&format!("Registry: No trusted publishers loaded ({}).", e));
+                return;
+            }
+        };
+        let (trusted, invalid) = TrustedPublishers::parse(&contents);
+        for line in invalid {
+            log(&format!("Registry: Ignoring invalid entry '{}' in {}.", line, TRUSTED_PUBLISHERS_PATH));
+        }
+        self.trusted = trusted;
+        log(&format!("Registry: Loaded {} trusted publishers.", self.trusted.len()));
+    }
+
+    /// Loads the installed-packages index. Without one, no package has any
+    /// capabilities approved, and the next install of each asks again.
+    fn load_installed(&mut self) {
+        match self.read_bytes(INSTALLED_INDEX_PATH, MAX_INDEX_SIZE).and_then(|data| InstalledIndex::decode(&data)) {
+            Ok(index) => {
+                self.installed = index;
+                log(&format!("Registry: {} packages in the installed-packages index.", self.installed.packages.len()));
+            },
+            Err(e) => log(&format!("Registry: No installed-packages index loaded ({}).", e)),
+        }
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<String, String> {
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as u32,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let result = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_TRUST_FILE_SIZE, offset: 0 }) {
+            Ok(VfsResponse::Data(data)) => String::from_utf8(data).map_err(|_| "File is not valid UTF-8".to_string()),
+            Ok(VfsResponse::Error { message, .. }) => Err(message),
+            Ok(VfsResponse::MediaError { offset, len, cause, .. }) => Err(format!("Can't read {} bytes at {} ({})", len, offset, cause.describe())),
+            _ => Err("Unexpected response from VFS".to_string()),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// Reads a whole file of at most `max_len` bytes, streamed.
+    fn read_bytes(&mut self, path: &str, max_len: usize) -> Result<Vec<u8>, String> {
+        let fd: Fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let mut streams = VfsStreams::new(&mut self.vfs_chan);
+        // One byte over the limit, so an oversized file is told apart from one that fits exactly.
+        let result = streams.open_read(fd, 0, max_len as u64 + 1)
+            .and_then(|stream| streams.read_to_end(stream, max_len));
+        let _ = streams.request(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// Writes the package, its entry in the installed-packages index, and the
+    /// trusted publishers list too if `with_trusted` is set, in one VFS
+    /// transaction: if any write fails, none happens. The archive is stamped
+    /// with the package it belongs to and its CID, so `Verify` can tell it
+    /// from files nobody installed. All files are locked exclusively while
+    /// they are written, so other writers that lock them wait instead of
+    /// interleaving their updates.
+    fn store_package(&mut self, package_name: &str, data: Vec<u8>, with_trusted: bool, installed: InstalledPackage) -> RegistryResponse {
+        let path = format!("{}/{}.ax", PACKAGES_DIR, package_name);
+        let trusted = if with_trusted { Some(self.trusted.format()) } else { None };
+        let cid = hex(compute_cid(&data).as_bytes());
+        let mut index = self.installed.clone();
+        index.packages.insert(package_name.to_string(), installed);
+        let encoded_index = match index.encode() {
+            Ok(encoded) => encoded,
+            Err(e) => return RegistryResponse::Error(format!("Failed to store '{}': {}", package_name, e)),
+        };
+        let locked: &[&str] = if with_trusted { &[path.as_str(), INSTALLED_INDEX_PATH, TRUSTED_PUBLISHERS_PATH] } else { &[path.as_str(), INSTALLED_INDEX_PATH] };
+        let stored = vfs_lock::with_locks(&mut self.vfs_chan, locked, true, |chan| {
+            let mut tx = VfsTx::begin(chan)?;
+            tx.write_file(&path, data)?;
+            tx.set_xattr(&path, XATTR_PACKAGE, package_name.as_bytes().to_vec())?;
+            tx.set_xattr(&path, XATTR_CID, cid.into_bytes())?;
+            tx.write_file(INSTALLED_INDEX_PATH, encoded_index)?;
+            if let Some(contents) = trusted {
+                tx.write_file(TRUSTED_PUBLISHERS_PATH, contents.into_bytes())?;
+            }
+            tx.commit()
+        });
+        match stored {
+            Ok(()) => {
+                self.installed = index;
+                log(&format!("Registry: Installed '{}' to {}.", package_name, path));
+                self.publish_installed(package_name);
+                RegistryResponse::Installed { package_name: package_name.to_string() }
+            },
+            Err(e) => RegistryResponse::Error(format!("Failed to store '{}': {}", package_name, e)),
+        }
+    }
+
+    /// Publishes "package.installed", e.g. for the notifications service.
+    fn publish_installed(&mut self, package_name: &str) {
+        let payload = match postcard::to_allocvec(&PackageInstalled { package_name: package_name.to_string() }) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: PACKAGE_INSTALLED_TOPIC.to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log("Registry: Failed to publish the package.installed event.");
+        }
+    }
+
+    fn handle_install(&mut self, package_name: String, requester: u64) -> RegistryResponse {
+        let manifest = match self.catalog.get(&package_name) {
+            Some(manifest) => manifest.clone(),
+            None => return RegistryResponse::Error(format!("Unknown package '{}'.", package_name)),
+        };
+        // The signature covers the root CID, and with it every chunk of the package.
+        let publisher: AidBytes = manifest.publisher.0;
+        if !self.trust_store.verify_signature(&manifest.publisher, manifest.root_cid.as_bytes(), &manifest.signature) {
+            log(&format!("Registry: Rejected '{}': invalid signature for publisher {}.", package_name, registry_ipc::fingerprint(&publisher)));
+            return RegistryResponse::Error(format!("Package '{}' has an invalid signature.", package_name));
+        }
+
+        // Only a warning: the signals are hints, and the fetch may find a seeder anyway.
+        if self.search.health.of(&manifest.root_cid, &[], self.now).seeders == 0 {
+            log(&format!("Registry: No known seeders for '{}'; the fetch may fail.", package_name));
+        }
+        let data = match self.swarm.fetch_package(&manifest) {
+            Ok(data) => data,
+            Err(e) => {
+                self.search.health.fetch_failed(&manifest.root_cid);
+                return RegistryResponse::Error(format!("Failed to fetch '{}': {:?}", package_name, e));
+            },
+        };
+        self.search.health.fetched(&manifest.root_cid, self.now);
+        // Stored as an .ax archive, the same format `axpkg pack` produces.
+        let data = match ax::pack_contents(&manifest, &data) {
+            Ok(archive) => archive,
+            Err(e) => return RegistryResponse::Error(format!("Fetched '{}' doesn't match its manifest: {}", package_name, e)),
+        };
+
+        self.install_verified(&manifest, data, requester)
+    }
+
+    /// Installs a package archive whose signature has been checked: right
+    /// away if the publisher is trusted and the package asks for no
+    /// capabilities the user hasn't approved for it before, otherwise once
+    /// `requester` confirms.
+    fn install_verified(&mut self, manifest: &PackageManifest, data: Vec<u8>, requester: u64) -> RegistryResponse {
+        let package_name = manifest.name.clone();
+        let publisher: AidBytes = manifest.publisher.0;
+        let requested = manifest.capabilities.clone();
+        // An earlier approval stands as long as the package asks for no more than it did then.
+        let (approved, review) = match self.installed.packages.get(&package_name) {
+            Some(previous) if requested.is_within(&previous.requested) => (previous.approved.narrowed_to(&requested), false),
+            _ => (requested.clone(), !requested.is_empty()),
+        };
+        let installed = InstalledPackage { version: manifest.version.to_string(), requested, approved };
+        let publisher_trusted = self.trusted.contains(&publisher);
+        if publisher_trusted && !review {
+            return self.store_package(&package_name, data, false, installed);
+        }
+
+        let identity = session_ipc::identity_of(requester);
+        // Shown in `approved` order, which `ConfirmGrants` indexes.
+        let grants: Vec<String> = installed.approved.grants.iter().map(|grant| grant.to_string()).collect();
+        let syscalls = installed.approved.syscalls.clone();
+        match self.pending.park(requester, identity, package_name.clone(), publisher, data, installed, self.now) {
+            Some(ticket) if review => {
+                log(&format!("Registry: '{}' asks for {} capabilities and {} syscalls not approved yet; waiting for task {} to review them (ticket {}).", package_name, grants.len(), syscalls.len(), requester, ticket));
+                RegistryResponse::ReviewRequired { ticket, publisher_aid: publisher, package_name, fingerprint: registry_ipc::fingerprint(&publisher), publisher_trusted, grants, syscalls }
+            },
+            Some(ticket) => {
+                log(&format!("Registry: '{}' is signed by untrusted publisher {}; waiting for task {} to confirm (ticket {}).", package_name, registry_ipc::fingerprint(&publisher), requester, ticket));
+                RegistryResponse::ConfirmationRequired { ticket, publisher_aid: publisher, package_name, fingerprint: registry_ipc::fingerprint(&publisher) }
+            },
+            None => RegistryResponse::Error("Too many installs are waiting for confirmation; answer or let them expire first.".to_string()),
+        }
+    }
+
+    fn handle_export(&mut self, names: Vec<String>, dest_path: String) -> RegistryResponse {
+        let mut packages = Vec::with_capacity(names.len());
+        for name in &names {
+            let path = format!("{}/{}.ax", PACKAGES_DIR, name);
+            let package = self.read_bytes(&path, MAX_BUNDLE_SIZE)
+                .and_then(|data| ax::unpack(&data).map_err(|e| e.to_string()));
+            match package {
+                Ok(package) => packages.push((package.manifest, package.chunks)),
+                Err(e) => return RegistryResponse::Error(format!("Cannot export '{}': {}", name, e)),
+            }
+        }
+
+        let data = bundle::build(&packages);
+        let bytes = data.len() as u64;
+        let chunks = packages.iter().map(|(manifest, _)| manifest.chunk_cids.len() as u32).sum();
+        if let Err(e) = VfsTx::begin(&mut self.vfs_chan).and_then(|mut tx| {
+            tx.write_file(&dest_path, data)?;
+            tx.commit()
+        }) {
+            return RegistryResponse::Error(format!("Failed to write {}: {}", dest_path, e));
+        }
+        log(&format!("Registry: Exported {} packages ({} bytes) to {}.", packages.len(), bytes, dest_path));
+        RegistryResponse::Exported { path: dest_path, packages: packages.len() as u32, chunks, bytes }
+    }
+
+    /// Checks a bundle completely, signatures included, before installing any
+    /// of it, so a bad bundle never leaves a partial install behind.
+    fn handle_import(&mut self, path: String, verify_only: bool, requester: u64) -> RegistryResponse {
+        let data = match self.read_bytes(&path, MAX_BUNDLE_SIZE) {
+            Ok(data) => data,
+            Err(e) => return RegistryResponse::Error(format!("Failed to read {}: {}", path, e)),
+        };
+        let packages = match bundle::parse(&data) {
+            Ok(packages) => packages,
+            Err(e) => {
+                log(&format!("Registry: Rejected bundle {}: {}.", path, e));
+                let problem = match e {
+                    BundleError::Checksum => BundleProblem::Checksum,
+                    BundleError::ChunkMismatch { index } => BundleProblem::CorruptChunk { chunk: index as u32 },
+                    BundleError::MissingChunk { package, index } => BundleProblem::MissingChunk { package, chunk: index as u32 },
+                    other => BundleProblem::Format(other.to_string()),
+                };
+                return RegistryResponse::InvalidBundle { path, problem };
+            },
+        };
+        for package in &packages {
+            let manifest = &package.manifest;
+            if !self.trust_store.verify_signature(&manifest.publisher, manifest.root_cid.as_bytes(), &manifest.signature) {
+                log(&format!("Registry: Rejected bundle {}: invalid signature on '{}'.", path, manifest.name));
+                return RegistryResponse::InvalidBundle { path, problem: BundleProblem::BadSignature { package: manifest.name.clone() } };
+            }
+        }
+
+        let mut results = Vec::with_capacity(packages.len());
+        for package in packages {
+            let manifest = package.manifest;
+            let package_name = manifest.name.clone();
+            let version = manifest.version.to_string();
+            let outcome = if verify_only {
+                ImportOutcome::Verified
+            } else {
+                let data = ax::pack(&manifest, &package.chunks);
+                let installed = self.install_verified(&manifest, data, requester);
+                self.add_to_catalog(manifest);
+                match installed {
+                    RegistryResponse::Installed { .. } => ImportOutcome::Installed,
+                    RegistryResponse::ConfirmationRequired { ticket, publisher_aid, fingerprint, .. } => ImportOutcome::ConfirmationRequired { ticket, publisher_aid, fingerprint },
+                    RegistryResponse::ReviewRequired { ticket, publisher_aid, fingerprint, publisher_trusted, grants, syscalls, .. } => {
+                        ImportOutcome::ReviewRequired { ticket, publisher_aid, fingerprint, publisher_trusted, grants, syscalls }
+                    },
+                    RegistryResponse::Error(e) => ImportOutcome::Failed(e),
+                    _ => ImportOutcome::Failed("Unexpected install result".to_string()),
+                }
+            };
+            results.push(ImportResult { package_name, version, outcome });
+        }
+        log(&format!("Registry: {} bundle {} ({} packages).", if verify_only { "Verified" } else { "Imported" }, path, results.len()));
+        RegistryResponse::Imported { results }
+    }
+
+    /// Answers a ticket. The grants at the positions in `denied` are left out
+    /// of what is approved for the package.
+    fn handle_confirm(&mut self, ticket: u64, decision: InstallDecision, remember: bool, denied: &[u32], requester: u64) -> RegistryResponse {
+        let pending = match self.pending.take(ticket, requester, session_ipc::identity_of(requester), self.now) {
+            Ok(pending) => pending,
+            Err(TakeError::WrongRequester) => {
+                // Left pending, so another task can't cancel someone else's install either.
+                log(&format!("Registry: Task {} tried to answer ticket {}, which belongs to another task.", requester, ticket));
+                return RegistryResponse::InvalidTicket { ticket };
+            },
+            Err(TakeError::Unknown) => return RegistryResponse::InvalidTicket { ticket },
+        };
+
+        if decision == InstallDecision::Cancel {
+            log(&format!("Registry: Install of '{}' cancelled; discarded {} bytes.", pending.package_name, pending.data.len()));
+            return RegistryResponse::Cancelled { package_name: pending.package_name };
+        }
+
+        let mut installed = pending.installed.clone();
+        let grant_count = installed.approved.grants.len();
+        if let Some(index) = denied.iter().find(|index| **index as usize >= grant_count) {
+            let error = format!("'{}' asks for {} capabilities; there is no number {} to deny.", pending.package_name, grant_count, index);
+            self.pending.restore(ticket, pending);
+            return RegistryResponse::Error(error);
+        }
+        installed.approved.grants = installed.approved.grants.into_iter().enumerate()
+            .filter(|(index, _)| !denied.contains(&(*index as u32)))
+            .map(|(_, grant)| grant)
+            .collect();
+        if installed.approved.grants.len() < grant_count {
+            log(&format!("Registry: Installing '{}' with {} of its {} capabilities denied.", pending.package_name, grant_count - installed.approved.grants.len(), grant_count));
+        }
+
+        if !(remember && self.trusted.insert(pending.publisher)) {
+            return self.store_package(&pending.package_name, pending.data, false, installed);
+        }
+        // The package and the updated trusted list are saved together.
+        match self.store_package(&pending.package_name, pending.data.clone(), true, installed) {
+            RegistryResponse::Error(e) => {
+                // Nothing was saved; keep the ticket so the user can retry or answer without "always".
+                self.trusted.remove(&pending.publisher);
+                self.pending.restore(ticket, pending);
+                RegistryResponse::Error(e)
+            },
+            installed => {
+                log(&format!("Registry: Publisher {} is now trusted.", registry_ipc::fingerprint(&pending.publisher)));
+                installed
+            },
+        }
+    }
+
+    /// Finds files in the package store that no install accounts for: ones
+    /// without `system.package`, or stamped for a package of another name.
+    fn handle_verify(&mut self) -> RegistryResponse {
+        let request = VfsRequest::ListWithXattrs { path: PACKAGES_DIR.to_string(), names: alloc::vec![XATTR_PACKAGE.to_string()] };
+        let entries = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&request) {
+            Ok(VfsResponse::DirectoryEntriesWithXattrs(entries)) => entries,
+            Ok(VfsResponse::Error { message, .. }) => return RegistryResponse::Error(format!("Failed to list {}: {}", PACKAGES_DIR, message)),
+            _ => return RegistryResponse::Error("Unexpected response from VFS".to_string()),
+        };
+        let mut packages = 0;
+        let mut orphans = Vec::new();
+        for (name, (metadata, xattrs)) in entries {
+            if metadata.is_dir {
+                continue;
+            }
+            let stamped = xattrs.get(XATTR_PACKAGE).and_then(|value| core::str::from_utf8(value).ok());
+            match (stamped, name.strip_suffix(".ax")) {
+                (Some(package), Some(stem)) if package == stem => packages += 1,
+                _ => orphans.push(format!("{}/{}", PACKAGES_DIR, name)),
+            }
+        }
+        log(&format!("Registry: Verified {} packages; {} orphaned files.", packages, orphans.len()));
+        RegistryResponse::Verified { packages, orphans }
+    }
+
+    fn swarm_stats(&mut self) -> SwarmStats {
+        let mut bandwidth = self.traffic.bandwidth.borrow_mut();
+        let (upload_rate, download_rate) = bandwidth.rates(self.now);
+        let counts = self.swarm_state.counts();
+        SwarmStats {
+            upload_limit: bandwidth.upload.rate(),
+            download_limit: bandwidth.download.rate(),
+            upload_rate,
+            download_rate,
+            queued: self.traffic.server.queued() as u32,
+            deferred_total: self.traffic.server.deferred_total,
+            rejected_total: self.traffic.server.rejected_total,
+            peers: bandwidth.peer_traffic(self.now),
+            restored_peers: counts.restored,
+            bootstrap_peers: counts.bootstrap,
+            discovered_peers: counts.discovered,
+            dead_peers: counts.dead,
+        }
+    }
+
+    /// Keeps the swarm state current: notes who pinged or answered, pings
+    /// peers not heard from yet, gives up on ones that didn't answer, and
+    /// saves when due.
+    fn tend_swarm_state(&mut self) {
+        let now_secs = crate::time::now_secs();
+        let mut peers_changed = false;
+        for heard in self.search.take_heard() {
+            peers_changed |= self.swarm_state.heard(heard, now_secs);
+        }
+        let search = &mut self.search;
+        self.swarm_state.probe(self.now, |peer| search.ping(peer));
+        for peer in self.swarm_state.expire_probes(self.now) {
+            log(&format!("Registry: Peer {:?}:{} didn't answer its ping; marked dead.", peer.ip_address, peer.port));
+            peers_changed = true;
+        }
+        if peers_changed {
+            self.search.set_peers(&self.swarm_state.live_peers());
+        }
+        // Init has no shutdown handshake yet, so this is the only save; at most
+        // `SAVE_INTERVAL_TICKS` of changes are lost to a stop.
+        if self.swarm_state.save_due(self.now) {
+            if let Err(e) = self.swarm_state.save(&mut self.vfs_chan, self.now) {
+                log(&format!("Registry: Failed to save the swarm state: {}.", e));
+            }
+        }
+    }
+
+    /// Answers chunk requests from peers: new ones if the upload limit allows,
+    /// then queued ones as capacity frees up.
+    fn serve_chunks(&mut self) {
+        let traffic = &mut self.traffic;
+        let socket = match traffic.socket.as_mut() {
+            Some(socket) => socket,
+            None => return,
+        };
+        let mut bandwidth = traffic.bandwidth.borrow_mut();
+        traffic.limits.borrow_mut().poll(&mut bandwidth, self.now);
+        while let Some((peer, cid)) = socket.poll_request() {
+            if let Some(reply) = traffic.server.request(peer, cid, &mut bandwidth, self.now) {
+                socket.reply(peer, &reply);
+            }
+        }
+        for (peer, reply) in traffic.server.drain(&mut bandwidth, self.now) {
+            socket.reply(peer, &reply);
+        }
+        for root in traffic.server.take_served() {
+            self.search.health.served(&root, self.now);
+        }
+    }
+
+    fn handle_request(&mut self, request: RegistryRequest, requester: u64) -> RegistryResponse {
+        match request {
+            RegistryRequest::Install { package_name } => self.handle_install(package_name, requester),
+            RegistryRequest::ConfirmInstall { ticket, decision, remember } => self.handle_confirm(ticket, decision, remember, &[], requester),
+            RegistryRequest::ConfirmGrants { ticket, decision, remember, denied } => self.handle_confirm(ticket, decision, remember, &denied, requester),
+            RegistryRequest::SwarmStats => RegistryResponse::SwarmStats(self.swarm_stats()),
+            RegistryRequest::Search { query, limit, local_only } => {
+                let (results, peers_asked, peers_answered) = self.search.search(&self.catalog, &query, limit, local_only);
+                log(&format!("Registry: Search '{}' found {} packages; {} of {} peers answered.", query, results.len(), peers_answered, peers_asked));
+                RegistryResponse::SearchResults { results, peers_asked, peers_answered }
+            },
+            RegistryRequest::Export { names, dest_path } => self.handle_export(names, dest_path),
+            RegistryRequest::ImportBundle { path, verify_only } => self.handle_import(path, verify_only, requester),
+            RegistryRequest::Metrics(request) => RegistryResponse::Metrics(self.metrics.handle(&request)),
+            RegistryRequest::Verify => self.handle_verify(),
+            RegistryRequest::PackageHealth { package_name } => match self.catalog.get(&package_name) {
+                Some(manifest) => RegistryResponse::PackageHealth { health: self.search.health.of(&manifest.root_cid, &[], self.now), package_name },
+                None => RegistryResponse::Error(format!("Unknown package '{}'.", package_name)),
+            },
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Registry V-Node entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.own_chan.recv_non_blocking() {
+                match (postcard::from_bytes::<RegistryRequest>(&req_data), session_ipc::last_sender()) {
+                    (Ok(request), Some(requester)) => {
+                        log(&format!("Registry: Received RegistryRequest from task {}: {:?}.", requester, request));
+                        let response = self.handle_request(request, requester);
+                        self.own_chan.send(&response).unwrap_or_else(|_| log("Registry: Failed to send response to client."));
+                    },
+                    (Ok(_), None) => log("Registry: Could not determine the sender of a request."),
+                    (Err(_), _) => log("Registry: Failed to deserialize RegistryRequest from client."),
+                }
+            }
+
+            for package_name in self.pending.expire(self.now) {
+                log(&format!("Registry: Confirmation for '{}' timed out; discarded downloaded data.", package_name));
+            }
+
+            self.serve_chunks();
+            self.search.serve(&self.catalog);
+            self.search.gossip(self.now);
+            self.tend_swarm_state();
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            self.now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     // The Registry V-Node's dedicated IPC channel for receiving requests.
-    // Assuming channel ID 1 is reserved for the Registry service.
-    let mut own_chan = VNodeChannel::new(1);
-
+    let own_chan = VNodeChannel::new(channels::REGISTRY);
     log("Registry V-Node starting up...");
 
     // 1. Initialize NexusNetTransport (which internally uses libnexus-net and talks to svc://aethernet).
@@ -57,28 +614,91 @@ pub extern "C" fn _start() -> ! {
         }
     };
 
+    // --- Bandwidth Limits ---
+    // Limits come from svc://settings; changes arrive from svc://event-bus on REGISTRY_EVENTS.
+    let mut metrics = Registry::new("registry");
+    let mut settings_chan = VNodeChannel::new(channels::SETTINGS);
+    let mut event_bus_chan = VNodeChannel::new(channels::EVENT_BUS);
+    let limits = LimitWatch::start(&mut settings_chan, &mut event_bus_chan, VNodeChannel::new(channels::REGISTRY_EVENTS));
+    let start = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+    let bandwidth = Rc::new(RefCell::new(Bandwidth::new(limits.upload, limits.download, start, &mut metrics)));
+    let limits = Rc::new(RefCell::new(limits));
+    let transport = PacedTransport::new(transport, bandwidth.clone(), limits.clone());
+
+    // Peers fetch the chunks of packages we publish from this socket.
+    let socket = match NexusNetServer::bind(SWARM_PORT) {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            log(&alloc::format!("Registry: Failed to open the chunk server port: {:?}. Not serving chunks.", e));
+            None
+        }
+    };
+    let mut traffic = SwarmTraffic { bandwidth, limits, server: ChunkServer::new(&mut metrics), socket };
+
     // --- Swarm Engine Initialization ---
-    // These are dummy values for demonstration. In a real system, AID and NodeId
-    // would be derived from user identity and system configuration.
+    // The node's identity is a service identity: stored without a passphrase,
+    // protected by the VFS. The NodeId is derived from it.
     let trust_store = TrustStore::new();
-    let local_aid = Aid([0xCD; 32]); // Dummy local AID
-    let local_node_id = NodeId([0; 32]); // Dummy NodeId for local DHT
+    let local_aid = match LocalIdentity::load_or_create(&mut VNodeChannel::new(channels::VFS), IDENTITY_PATH, None) {
+        Ok(identity) => {
+            log(&format!("Registry: Node identity {}.", registry_ipc::fingerprint(&identity.aid().0)));
+            identity.aid()
+        },
+        Err(e) => {
+            // Without a key of its own the node can still install packages, but
+            // peers can't tell it apart from any other node in the same state.
+            log(&format!("Registry: No node identity ({}). Using a placeholder.", e));
+            Aid([0xCD; 32])
+        }
+    };
+    let local_node_id = NodeId(local_aid.node_id());
+
+    // The DHT starts from what the last run saved, checked again, plus the
+    // bootstrap peers from svc://settings. Restored peers are pinged from the run loop.
+    let mut vfs_chan = VNodeChannel::new(channels::VFS);
+    let (mut swarm_state, restored) = SwarmState::load(&mut vfs_chan, &trust_store, crate::time::now_secs(), start);
+    log(&format!("Registry: Restored {} peers ({} stale dropped) and {} stored values ({} failed verification).",
+        restored.peers, restored.stale_peers, restored.values, restored.rejected_values));
+    let bootstrap = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: BOOTSTRAP_PEERS_KEY.to_string() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Str(value), .. }) => value,
+        _ => {
+            log(&format!("Registry: Could not read {}; starting without bootstrap peers.", BOOTSTRAP_PEERS_KEY));
+            String::new()
+        },
+    };
+    let (bootstrap_peers, invalid) = swarm_state::parse_bootstrap(&bootstrap);
+    for entry in invalid {
+        log(&format!("Registry: Ignoring invalid entry '{}' in {}.", entry, BOOTSTRAP_PEERS_KEY));
+    }
+    swarm_state.add_bootstrap(bootstrap_peers);
 
-    // Initialize an in-memory DHT for local testing. This would eventually be persistent.
     let mut dht_for_init = InMemoryDht::new(local_node_id.clone());
+    let known_peers = swarm_state.live_peers();
+    for peer in &known_peers {
+        dht_for_init.add_peer(peer.clone());
+    }
+    // Lookups for these are answered locally, without asking the network.
+    for manifest in swarm_state.values() {
+        dht_for_init.store(manifest.root_cid, DhtValue::Manifest(manifest.clone()));
+    }
 
-    // Add some dummy peers to simulate a network presence for the DHT.
-    dht_for_init.add_peer(PeerInfo {
-        id: NodeId([0xAA; 32]),
-        aid: crate::trust::Aid([0xBB; 32]),
-        ip_address: [10, 0, 2, 1], // Example peer IP (could be QEMU host or another V-Node)
-        port: 60000, // Example peer port for swarm communication
-    });
+    // Searches go to the closest known peers, and theirs come in on this socket.
+    let search_socket = match NexusNetServer::bind(SEARCH_PORT) {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            log(&alloc::format!("Registry: Failed to open the search port: {:?}. Searching the local catalog only.", e));
+            None
+        }
+    };
+    let mut search = Search::new(search_socket, local_node_id.clone(), local_aid.clone(), &known_peers);
 
     // Load a dummy package manifest for demonstration purposes. This package's CID
     // can be 'looked up' and 'fetched' by the SwarmEngine.
-    let (manifest, _chunks) = crate::examples::hello_package::make_hello_package();
-    dht_for_init.store(manifest.root_cid, crate::arp_dht::DhtValue::Manifest(manifest.clone()));
+    let (manifest, chunks) = crate::examples::hello_package::make_hello_package();
+    dht_for_init.store(manifest.root_cid, DhtValue::Manifest(manifest.clone()));
+    swarm_state.stored(manifest.clone());
+    traffic.server.add_package(&manifest, chunks);
+    search.health.seed(manifest.root_cid);
 
     // Instantiate GlobalSearchService and SwarmEngine with the initialized components.
     let global_search_service = GlobalSearchService::new(dht_for_init.clone(), trust_store.clone(), local_aid.clone());
@@ -108,19 +728,10 @@ pub extern "C" fn _start() -> ! {
     log(&alloc::format!("Registry: Global Search Response: {:?}", search_response));
 
     // --- Main Event Loop ---
-    loop {
-        // The Registry V-Node would typically be idling here, waiting for IPC requests
-        // from other V-Nodes (e.g., AetherShell requesting a package install, or
-        // the kernel notifying of a new network event relevant to swarm discovery).
-        log("Registry V-Node idling, waiting for IPC requests...");
-        
-        // This call blocks the V-Node until an IPC message arrives on its channel (ID 1).
-        // This prevents busy-waiting and allows the kernel to schedule other V-Nodes.
-        let _ = own_chan.recv_blocking();
-
-        // In a more advanced implementation, the loop might also periodically trigger
-        // background swarm maintenance tasks (e.g., DHT refreshes, peer discovery).
-    }
+    // Channel 7 is the VFS, used to persist the trusted publishers list and installed packages.
+    let mut registry = RegistryService::new(own_chan, 7, event_bus_chan, swarm, trust_store, traffic, search, swarm_state, metrics);
+    registry.add_to_catalog(manifest);
+    registry.run_loop();
 }
 
 #[panic_handler]
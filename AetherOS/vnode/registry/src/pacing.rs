@@ -0,0 +1,118 @@
+// vnode/registry/src/pacing.rs
+
+//! Keeps our own downloads within `swarm.download_limit_kbps`, and both
+//! limits in step with the settings service.
+//!
+//! `PacedTransport` wraps the transport the `SwarmEngine` fetches through. It
+//! waits before each chunk until the download bucket is out of debt, and
+//! charges the chunk once it arrives. While it waits it also applies limit
+//! changes, so raising the limit speeds up a download already in progress.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::arp_dht::PeerInfo;
+use crate::bandwidth::Bandwidth;
+use crate::cid::Cid;
+use crate::ipc::event_ipc::{Event, EventBusRequest, EventBusResponse};
+use crate::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
+use crate::ipc::vnode::VNodeChannel;
+use crate::swarm_engine::{SwarmError, SwarmTransport};
+use crate::syscall::{syscall3, SYS_TIME};
+use crate::log;
+
+pub const UPLOAD_LIMIT_KEY: &str = "swarm.upload_limit_kbps";
+pub const DOWNLOAD_LIMIT_KEY: &str = "swarm.download_limit_kbps";
+
+fn now() -> u64 {
+    unsafe { syscall3(SYS_TIME, 0, 0, 0) }
+}
+
+fn kbps_to_bytes(kbps: i64) -> u64 {
+    (kbps.max(0) as u64).saturating_mul(1024)
+}
+
+/// The limits as configured, and the channel their changes arrive on.
+pub struct LimitWatch {
+    events_chan: VNodeChannel,
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl LimitWatch {
+    /// Reads both limits from the settings service and subscribes to changes.
+    /// Settings that can't be read leave that direction unlimited.
+    pub fn start(settings_chan: &mut VNodeChannel, event_bus_chan: &mut VNodeChannel, events_chan: VNodeChannel) -> Self {
+        let mut read = |key: &str| match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Int(kbps), .. }) => kbps_to_bytes(kbps),
+            _ => {
+                log(&format!("Registry: Could not read {}; leaving it unlimited.", key));
+                0
+            },
+        };
+        let upload = read(UPLOAD_LIMIT_KEY);
+        let download = read(DOWNLOAD_LIMIT_KEY);
+        let subscribe = EventBusRequest::Subscribe { topic_prefix: "settings.swarm.".to_string(), reply_chan: events_chan.id };
+        if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+            log("Registry: Failed to subscribe to swarm limit changes; restart the registry to apply them.");
+        }
+        Self { events_chan, upload, download }
+    }
+
+    /// Applies any limit changes that have arrived. Returns true if one did.
+    pub fn poll(&mut self, bandwidth: &mut Bandwidth, now: u64) -> bool {
+        let mut changed = false;
+        while let Ok(Some(event_data)) = self.events_chan.recv_non_blocking() {
+            let update = postcard::from_bytes::<Event>(&event_data).ok()
+                .and_then(|event| postcard::from_bytes::<SettingChanged>(&event.payload).ok());
+            match update {
+                Some(SettingChanged { key, value: SettingValue::Int(kbps) }) if key == UPLOAD_LIMIT_KEY => self.upload = kbps_to_bytes(kbps),
+                Some(SettingChanged { key, value: SettingValue::Int(kbps) }) if key == DOWNLOAD_LIMIT_KEY => self.download = kbps_to_bytes(kbps),
+                _ => {
+                    log("Registry: Ignoring unexpected event on the swarm limits channel.");
+                    continue;
+                },
+            }
+            changed = true;
+        }
+        if changed {
+            bandwidth.set_limits(self.upload, self.download, now);
+            log(&format!("Registry: Swarm limits now {} B/s up, {} B/s down (0 = unlimited).", self.upload, self.download));
+        }
+        changed
+    }
+}
+
+/// A transport whose fetches respect the download limit and are counted per peer.
+pub struct PacedTransport<T: SwarmTransport> {
+    inner: T,
+    bandwidth: Rc<RefCell<Bandwidth>>,
+    limits: Rc<RefCell<LimitWatch>>,
+}
+
+impl<T: SwarmTransport> PacedTransport<T> {
+    pub fn new(inner: T, bandwidth: Rc<RefCell<Bandwidth>>, limits: Rc<RefCell<LimitWatch>>) -> Self {
+        Self { inner, bandwidth, limits }
+    }
+}
+
+impl<T: SwarmTransport> SwarmTransport for PacedTransport<T> {
+    fn fetch_chunk_from_peer(&self, peer: &PeerInfo, cid: Cid) -> Result<Vec<u8>, SwarmError> {
+        loop {
+            let now = now(); // Also yields to other V-Nodes while we wait
+            let mut bandwidth = self.bandwidth.borrow_mut();
+            self.limits.borrow_mut().poll(&mut bandwidth, now);
+            if bandwidth.download.ready(now) {
+                break;
+            }
+        }
+        let data = self.inner.fetch_chunk_from_peer(peer, cid)?;
+        self.bandwidth.borrow_mut().record_fetched((peer.ip_address, peer.port), data.len() as u64, now());
+        Ok(data)
+    }
+}
@@ -0,0 +1,66 @@
+// vnode/registry/src/publishers.rs
+
+//! Publishers the user chose to always trust.
+//!
+//! Stored in `/var/aether/registry/trusted_publishers` as one lowercase hex Aid
+//! per line. Blank lines and lines starting with `#` are ignored, so the file
+//! can be edited by hand.
+
+extern crate alloc;
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::session_ipc::{aid_from_hex, aid_to_hex, AidBytes};
+
+pub const TRUSTED_PUBLISHERS_PATH: &str = "/var/aether/registry/trusted_publishers";
+
+#[derive(Default)]
+pub struct TrustedPublishers {
+    aids: BTreeSet<AidBytes>,
+}
+
+impl TrustedPublishers {
+    /// Parses the file contents. Returns the list and the lines that weren't valid Aids.
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut list = Self::default();
+        let mut invalid = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match aid_from_hex(line) {
+                Some(aid) => { list.aids.insert(aid); },
+                None => invalid.push(String::from(line)),
+            }
+        }
+        (list, invalid)
+    }
+
+    pub fn format(&self) -> String {
+        let mut out = String::from("# Publishers trusted for package installs, one Aid per line.\n");
+        for aid in &self.aids {
+            out.push_str(&aid_to_hex(aid));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn contains(&self, aid: &AidBytes) -> bool {
+        self.aids.contains(aid)
+    }
+
+    /// Adds `aid`. Returns false if it was already trusted.
+    pub fn insert(&mut self, aid: AidBytes) -> bool {
+        self.aids.insert(aid)
+    }
+
+    pub fn remove(&mut self, aid: &AidBytes) -> bool {
+        self.aids.remove(aid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.aids.len()
+    }
+}
@@ -0,0 +1,304 @@
+// vnode/registry/src/search.rs
+
+//! Package search, federated across the nearest peers.
+//!
+//! A search matches the local catalog and, unless it is local-only, sends the
+//! query to the `MAX_SEARCH_PEERS` known peers whose node IDs are closest to
+//! ours. Answers are collected until every peer has replied or
+//! `SEARCH_DEADLINE_TICKS` have passed, whichever comes first; a slow or
+//! offline peer costs at most the deadline. Results are merged by root CID,
+//! keeping the best score and every peer that reported the package.
+//!
+//! Queries from other nodes are answered from the local catalog only and are
+//! never forwarded. Each peer may send `MAX_QUERIES_PER_WINDOW` queries per
+//! `QUERY_WINDOW_TICKS` and gets `Busy` beyond that; answers carry at most
+//! `MAX_RESULTS_SERVED` hits.
+//!
+//! The search port also carries the pings `swarm_state` checks peers with.
+//! Pings are answered right away; pings and answers alike are handed to the
+//! run loop through `take_heard`. Each ping and pong is followed by our
+//! health summaries (see `health`), which the search peers also get every
+//! `GOSSIP_INTERVAL_TICKS`. Results are ranked by score plus the health boost.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::arp_dht::{NodeId, PeerInfo};
+use crate::bandwidth::{PeerAddr, TICKS_PER_SECOND};
+use crate::health::{self, Health, GOSSIP_INTERVAL_TICKS};
+use crate::ipc::registry_ipc::{PackageHealth, SearchResult};
+use crate::manifest::PackageManifest;
+use crate::swarm_engine::nexus_net_transport::{NexusNetServer, SearchHit, SearchMessage, SEARCH_PORT, SWARM_PORT};
+use crate::swarm_state::Heard;
+use crate::trust::Aid;
+use crate::syscall::{syscall3, SYS_TIME};
+use crate::log;
+
+/// Peers asked per search.
+pub const MAX_SEARCH_PEERS: usize = 8;
+/// How long a search waits for peers: two seconds.
+pub const SEARCH_DEADLINE_TICKS: u64 = 2 * TICKS_PER_SECOND;
+/// Results a search returns at most, whatever the caller asks for.
+pub const MAX_RESULTS: u32 = 50;
+/// Hits in one answer to a peer.
+pub const MAX_RESULTS_SERVED: u32 = 20;
+pub const MAX_QUERIES_PER_WINDOW: u32 = 10;
+pub const QUERY_WINDOW_TICKS: u64 = TICKS_PER_SECOND;
+const MAX_QUERY_LEN: usize = 128;
+
+fn now() -> u64 {
+    unsafe { syscall3(SYS_TIME, 0, 0, 0) }
+}
+
+/// How well `manifest` matches `query`, or `None` if it doesn't. Every word
+/// of the query has to match the name, a tag or the description; the score
+/// adds up each word's best match.
+pub fn score(manifest: &PackageManifest, query: &str) -> Option<u32> {
+    let name = manifest.name.to_ascii_lowercase();
+    let description = manifest.description.to_ascii_lowercase();
+    let mut total = 0;
+    for word in query.split_whitespace().map(str::to_ascii_lowercase) {
+        let best = if name == word {
+            100
+        } else if name.starts_with(&word) {
+            60
+        } else if name.contains(&word) {
+            40
+        } else if manifest.tags.iter().any(|tag| tag.eq_ignore_ascii_case(&word)) {
+            30
+        } else if description.contains(&word) {
+            10
+        } else {
+            return None;
+        };
+        total += best;
+    }
+    (total > 0).then_some(total)
+}
+
+/// The catalog's matches for `query`, best first, at most `limit`.
+pub fn search_catalog<'a>(catalog: impl Iterator<Item = &'a PackageManifest>, query: &str, limit: u32) -> Vec<SearchHit> {
+    let mut hits: Vec<SearchHit> = catalog.filter_map(|manifest| {
+        score(manifest, query).map(|score| SearchHit {
+            name: manifest.name.clone(),
+            version: manifest.version.to_string(),
+            description: manifest.description.clone(),
+            root_cid: manifest.root_cid,
+            score,
+        })
+    }).collect();
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    hits.truncate(limit as usize);
+    hits
+}
+
+/// Merges local hits with each peer's, one result per root CID, best first
+/// once each result's health boost is added to its score.
+pub fn merge(local: Vec<SearchHit>, remote: Vec<(PeerAddr, Vec<SearchHit>)>, limit: u32, health: &Health, now: u64) -> Vec<SearchResult> {
+    let mut merged: BTreeMap<Vec<u8>, SearchResult> = BTreeMap::new();
+    let into_result = |hit: SearchHit, local: bool| SearchResult {
+        name: hit.name,
+        version: hit.version,
+        description: hit.description,
+        root_cid: hit.root_cid,
+        score: hit.score,
+        local,
+        peers: Vec::new(),
+        health: PackageHealth::default(),
+    };
+    for hit in local {
+        merged.insert(hit.root_cid.as_bytes().to_vec(), into_result(hit, true));
+    }
+    for (peer, hits) in remote {
+        for hit in hits {
+            let result = merged.entry(hit.root_cid.as_bytes().to_vec()).or_insert_with(|| into_result(hit.clone(), false));
+            result.score = result.score.max(hit.score);
+            if !result.peers.contains(&peer) {
+                result.peers.push(peer);
+            }
+        }
+    }
+    let mut results: Vec<SearchResult> = merged.into_values().collect();
+    for result in &mut results {
+        result.health = health.of(&result.root_cid, &result.peers, now);
+    }
+    let ranked = |result: &SearchResult| result.score + health::boost(&result.health);
+    results.sort_by(|a, b| ranked(b).cmp(&ranked(a)).then_with(|| a.name.cmp(&b.name)));
+    results.truncate(limit as usize);
+    results
+}
+
+/// The `count` peers closest to `own` by XOR distance of node IDs, as the
+/// addresses their search port listens on.
+pub fn closest_peers(own: &NodeId, peers: &[PeerInfo], count: usize) -> Vec<PeerAddr> {
+    let distance = |peer: &PeerInfo| -> [u8; 32] { core::array::from_fn(|i| own.0[i] ^ peer.id.0[i]) };
+    let mut sorted: Vec<&PeerInfo> = peers.iter().collect();
+    sorted.sort_by_key(|peer| distance(peer));
+    sorted.into_iter().take(count).map(|peer| (peer.ip_address, SEARCH_PORT)).collect()
+}
+
+/// Queries each peer sent in the current window.
+#[derive(Default)]
+struct RateLimiter {
+    window_start: u64,
+    counts: BTreeMap<PeerAddr, u32>,
+}
+
+impl RateLimiter {
+    fn allow(&mut self, peer: PeerAddr, now: u64) -> bool {
+        if now.saturating_sub(self.window_start) >= QUERY_WINDOW_TICKS {
+            self.window_start = now;
+            self.counts.clear();
+        }
+        let count = self.counts.entry(peer).or_insert(0);
+        *count += 1;
+        *count <= MAX_QUERIES_PER_WINDOW
+    }
+}
+
+pub struct Search {
+    socket: Option<NexusNetServer>, // None if the search port couldn't be opened
+    own_id: NodeId,
+    own_aid: Aid, // Introduces us in pings and their answers
+    peers: Vec<PeerAddr>, // Closest first
+    limiter: RateLimiter,
+    next_id: u64, // Shared by searches and pings
+    heard: Vec<Heard>, // Pings and answers not yet taken
+    pub health: Health,
+    last_gossip: u64,
+}
+
+impl Search {
+    pub fn new(socket: Option<NexusNetServer>, own_id: NodeId, own_aid: Aid, peers: &[PeerInfo]) -> Self {
+        let peers = closest_peers(&own_id, peers, MAX_SEARCH_PEERS);
+        Self { socket, own_id, own_aid, peers, limiter: RateLimiter::default(), next_id: 1, heard: Vec::new(), health: Health::new(), last_gossip: now() }
+    }
+
+    /// Searches the closest of `peers` from now on.
+    pub fn set_peers(&mut self, peers: &[PeerInfo]) {
+        self.peers = closest_peers(&self.own_id, peers, MAX_SEARCH_PEERS);
+    }
+
+    /// Pings a peer's search port. Returns the ping's ID, or `None` without a socket.
+    pub fn ping(&mut self, peer: PeerAddr) -> Option<u64> {
+        let socket = self.socket.as_mut()?;
+        let id = self.next_id;
+        self.next_id += 1;
+        socket.send_message(peer, &SearchMessage::Ping { id, aid: self.own_aid.0, port: SWARM_PORT });
+        self.send_health(peer);
+        Some(id)
+    }
+
+    fn send_health(&mut self, peer: PeerAddr) {
+        let summaries = self.health.summaries(now());
+        if summaries.is_empty() {
+            return;
+        }
+        if let Some(socket) = self.socket.as_mut() {
+            socket.send_message(peer, &SearchMessage::Health { summaries });
+        }
+    }
+
+    /// Sends our health summaries to the search peers if
+    /// `GOSSIP_INTERVAL_TICKS` have passed since the last time, and forgets
+    /// reports that aged out.
+    pub fn gossip(&mut self, now: u64) {
+        if now.saturating_sub(self.last_gossip) < GOSSIP_INTERVAL_TICKS {
+            return;
+        }
+        self.last_gossip = now;
+        self.health.expire(now);
+        for peer in self.peers.clone() {
+            self.send_health(peer);
+        }
+    }
+
+    /// The pings and answers received since the last call.
+    pub fn take_heard(&mut self) -> Vec<Heard> {
+        core::mem::take(&mut self.heard)
+    }
+
+    /// Answers the queries waiting on the search port.
+    pub fn serve(&mut self, catalog: &BTreeMap<String, PackageManifest>) {
+        while let Some((peer, message)) = self.socket.as_mut().and_then(|socket| socket.poll_message::<SearchMessage>()) {
+            self.answer(peer, message, catalog);
+        }
+    }
+
+    fn answer(&mut self, peer: PeerAddr, message: SearchMessage, catalog: &BTreeMap<String, PackageManifest>) {
+        let (id, query, limit) = match message {
+            SearchMessage::Ping { id, aid, port } => {
+                self.heard.push(Heard { from: peer, ping_id: None, aid: Aid(aid), port });
+                if let Some(socket) = self.socket.as_mut() {
+                    socket.send_message(peer, &SearchMessage::Pong { id, aid: self.own_aid.0, port: SWARM_PORT });
+                }
+                self.send_health(peer);
+                return;
+            },
+            SearchMessage::Pong { id, aid, port } => {
+                self.heard.push(Heard { from: peer, ping_id: Some(id), aid: Aid(aid), port });
+                return;
+            },
+            SearchMessage::Health { summaries } => {
+                self.health.heard(peer, &summaries, now());
+                return;
+            },
+            SearchMessage::Query { hops: 0, .. } => return,
+            SearchMessage::Query { query, .. } if query.len() > MAX_QUERY_LEN => return,
+            SearchMessage::Query { id, query, limit, .. } => (id, query, limit),
+            // Answers that arrive after their search's deadline.
+            SearchMessage::Results { .. } | SearchMessage::Busy { .. } => return,
+        };
+        let Some(socket) = self.socket.as_mut() else { return };
+        if !self.limiter.allow(peer, now()) {
+            socket.send_message(peer, &SearchMessage::Busy { id });
+            return;
+        }
+        let hits = search_catalog(catalog.values(), &query, limit.min(MAX_RESULTS_SERVED));
+        socket.send_message(peer, &SearchMessage::Results { id, hits });
+    }
+
+    /// Runs a search. Returns the merged results, the number of peers asked
+    /// and the number that answered in time.
+    pub fn search(&mut self, catalog: &BTreeMap<String, PackageManifest>, query: &str, limit: u32, local_only: bool) -> (Vec<SearchResult>, u32, u32) {
+        let limit = limit.clamp(1, MAX_RESULTS);
+        let local = search_catalog(catalog.values(), query, limit);
+        if local_only || self.socket.is_none() || self.peers.is_empty() {
+            return (merge(local, Vec::new(), limit, &self.health, now()), 0, 0);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let message = SearchMessage::Query { id, query: query.to_string(), limit, hops: 1 };
+        let mut waiting: BTreeSet<PeerAddr> = BTreeSet::new();
+        for &peer in &self.peers {
+            self.socket.as_mut().unwrap().send_message(peer, &message);
+            waiting.insert(peer);
+        }
+        let asked = waiting.len() as u32;
+
+        let deadline = now() + SEARCH_DEADLINE_TICKS;
+        let mut remote = Vec::new();
+        while !waiting.is_empty() && now() < deadline {
+            while let Some((peer, message)) = self.socket.as_mut().unwrap().poll_message::<SearchMessage>() {
+                match message {
+                    SearchMessage::Results { id: answer, hits } if answer == id && waiting.remove(&peer) => remote.push((peer, hits)),
+                    SearchMessage::Busy { id: answer } if answer == id && waiting.remove(&peer) => {
+                        log(&format!("Registry: Peer {:?}:{} is too busy to search.", peer.0, peer.1));
+                    },
+                    // Keep answering others, so two nodes searching each other both get answers.
+                    other => self.answer(peer, other, catalog),
+                }
+            }
+        }
+        for peer in &waiting {
+            log(&format!("Registry: Peer {:?}:{} didn't answer search {} in time.", peer.0, peer.1, id));
+        }
+        let answered = remote.len() as u32;
+        (merge(local, remote, limit, &self.health, now()), asked, answered)
+    }
+}
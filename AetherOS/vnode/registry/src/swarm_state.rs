@@ -0,0 +1,385 @@
+// vnode/registry/src/swarm_state.rs
+
+//! What the registry knows about the swarm, kept across reboots.
+//!
+//! The DHT lives in memory, so the registry keeps its own record of the peers
+//! it knows, with when each was last heard from, and of the values it stored
+//! in the DHT. Both are written to `/data/swarm/` in one VFS transaction, at
+//! most every `SAVE_INTERVAL_TICKS` while anything changed and once the
+//! liveness sweep is over.
+//!
+//! At startup `load` reads them back. Peers not heard from within
+//! `PEER_STALE_SECS` are dropped, and every stored manifest has its CID and
+//! its publisher's signature checked again before it goes back into the DHT.
+//! The bootstrap peers from `swarm.bootstrap_peers` are merged in.
+//!
+//! A restored peer may be long gone, so the run loop pings every peer it
+//! hasn't heard from yet, `MAX_PINGS_IN_FLIGHT` at a time, without holding up
+//! startup. One that doesn't answer within `PING_DEADLINE_TICKS` is marked
+//! dead: it is no longer searched and is left out of the next save. A node
+//! that pings us is added as a discovered peer.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::arp_dht::{NodeId, PeerInfo};
+use crate::bandwidth::{PeerAddr, TICKS_PER_SECOND};
+use crate::ipc::session_ipc::aid_from_hex;
+use crate::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use crate::ipc::vfs_stream::VfsStreams;
+use crate::ipc::vfs_tx::VfsTx;
+use crate::ipc::vnode::VNodeChannel;
+use crate::manifest::PackageManifest;
+use crate::swarm_engine::nexus_net_transport::{SEARCH_PORT, SWARM_PORT};
+use crate::time::SECS_PER_DAY;
+use crate::trust::{Aid, TrustStore};
+use crate::log;
+
+pub const SWARM_DIR: &str = "/data/swarm";
+pub const PEERS_PATH: &str = "/data/swarm/peers";
+pub const VALUES_PATH: &str = "/data/swarm/values";
+/// Comma-separated `<aid hex>@<ip>:<port>` entries, in the settings service.
+pub const BOOTSTRAP_PEERS_KEY: &str = "swarm.bootstrap_peers";
+
+/// Restored peers last heard from longer ago than this are dropped: a week.
+pub const PEER_STALE_SECS: u64 = 7 * SECS_PER_DAY;
+/// Changes are saved at most this often: five minutes.
+pub const SAVE_INTERVAL_TICKS: u64 = 5 * 60 * TICKS_PER_SECOND;
+/// How long a pinged peer has to answer.
+pub const PING_DEADLINE_TICKS: u64 = 5 * TICKS_PER_SECOND;
+pub const MAX_PINGS_IN_FLIGHT: usize = 4;
+const FORMAT_VERSION: u32 = 1;
+const MAX_STATE_FILE_SIZE: usize = 4 * 1024 * 1024;
+const ENOENT: i32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct SavedPeers {
+    version: u32,
+    peers: Vec<SavedPeer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPeer {
+    id: [u8; 32],
+    aid: [u8; 32],
+    ip_address: [u8; 4],
+    port: u16,
+    last_seen: u64, // Epoch seconds
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedValues {
+    version: u32,
+    manifests: Vec<Vec<u8>>, // `PackageManifest::to_canonical_bytes`
+}
+
+/// How the registry came to know a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerOrigin {
+    /// Saved by an earlier run.
+    Restored,
+    /// From `swarm.bootstrap_peers`. Takes precedence over `Restored`.
+    Bootstrap,
+    /// Pinged us during this run.
+    Discovered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Liveness {
+    Unprobed,
+    Probing { ping_id: u64, deadline: u64 },
+    Alive,
+    /// Didn't answer its ping; evicted at the next save.
+    Dead,
+}
+
+pub struct KnownPeer {
+    pub info: PeerInfo,
+    pub last_seen: u64, // Epoch seconds; 0 if never heard from
+    pub origin: PeerOrigin,
+    liveness: Liveness,
+}
+
+/// Live peers by how they were found, and the dead ones.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PeerCounts {
+    pub restored: u32,
+    pub bootstrap: u32,
+    pub discovered: u32,
+    pub dead: u32,
+}
+
+/// What `load` found.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub peers: usize,
+    pub stale_peers: usize,
+    pub values: usize,
+    /// Manifests that failed their CID or signature check.
+    pub rejected_values: usize,
+}
+
+/// A `Ping` or `Pong` from another node, as the search socket received it.
+pub struct Heard {
+    pub from: PeerAddr,
+    /// The ping this answers, for a `Pong`.
+    pub ping_id: Option<u64>,
+    pub aid: Aid,
+    /// The port the node serves chunks on.
+    pub port: u16,
+}
+
+pub struct SwarmState {
+    peers: BTreeMap<[u8; 32], KnownPeer>, // By node ID
+    values: BTreeMap<Vec<u8>, PackageManifest>, // Stored manifests by root CID
+    dirty: bool,
+    save_now: bool, // The sweep just finished
+    last_save: u64,
+}
+
+impl SwarmState {
+    /// Reads the saved state. A missing file is an empty state; one that
+    /// can't be read or decoded is logged and treated the same, so a damaged
+    /// file never keeps the registry from starting.
+    pub fn load(vfs_chan: &mut VNodeChannel, trust_store: &TrustStore, now_secs: u64, now: u64) -> (Self, LoadReport) {
+        let mut state = Self { peers: BTreeMap::new(), values: BTreeMap::new(), dirty: false, save_now: false, last_save: now };
+        let mut report = LoadReport::default();
+
+        match read_state::<SavedPeers>(vfs_chan, PEERS_PATH) {
+            Ok(Some(saved)) if saved.version == FORMAT_VERSION => {
+                for peer in saved.peers {
+                    // Without a wall clock nothing can be told stale.
+                    if now_secs != 0 && now_secs.saturating_sub(peer.last_seen) > PEER_STALE_SECS {
+                        report.stale_peers += 1;
+                        continue;
+                    }
+                    let info = PeerInfo { id: NodeId(peer.id), aid: Aid(peer.aid), ip_address: peer.ip_address, port: peer.port };
+                    state.peers.insert(peer.id, KnownPeer { info, last_seen: peer.last_seen, origin: PeerOrigin::Restored, liveness: Liveness::Unprobed });
+                    report.peers += 1;
+                }
+            },
+            Ok(Some(saved)) => log(&format!("Registry: Ignoring {}: format version {} is not {}.", PEERS_PATH, saved.version, FORMAT_VERSION)),
+            Ok(None) => {},
+            Err(e) => log(&format!("Registry: Not restoring peers: {}.", e)),
+        }
+
+        match read_state::<SavedValues>(vfs_chan, VALUES_PATH) {
+            Ok(Some(saved)) if saved.version == FORMAT_VERSION => {
+                for bytes in saved.manifests {
+                    // The file is only as trustworthy as the disk; check each manifest as if a peer had sent it.
+                    let verified = PackageManifest::from_canonical_bytes(&bytes).ok()
+                        .filter(|manifest| manifest.validate().is_ok())
+                        .filter(|manifest| trust_store.verify_signature(&manifest.publisher, manifest.root_cid.as_bytes(), &manifest.signature));
+                    match verified {
+                        Some(manifest) => {
+                            state.values.insert(manifest.root_cid.as_bytes().to_vec(), manifest);
+                            report.values += 1;
+                        },
+                        None => report.rejected_values += 1,
+                    }
+                }
+            },
+            Ok(Some(saved)) => log(&format!("Registry: Ignoring {}: format version {} is not {}.", VALUES_PATH, saved.version, FORMAT_VERSION)),
+            Ok(None) => {},
+            Err(e) => log(&format!("Registry: Not restoring stored values: {}.", e)),
+        }
+
+        // What was dropped on the way in shouldn't come back from the old files.
+        state.dirty = report.stale_peers > 0 || report.rejected_values > 0;
+        (state, report)
+    }
+
+    /// Merges in the configured bootstrap peers. They are pinged like restored ones.
+    pub fn add_bootstrap(&mut self, peers: Vec<PeerInfo>) {
+        for info in peers {
+            let last_seen = self.peers.get(&info.id.0).map_or(0, |peer| peer.last_seen);
+            self.peers.insert(info.id.0, KnownPeer { info, last_seen, origin: PeerOrigin::Bootstrap, liveness: Liveness::Unprobed });
+        }
+    }
+
+    /// Peers not known to be dead, for the DHT and search.
+    pub fn live_peers(&self) -> Vec<PeerInfo> {
+        self.peers.values().filter(|peer| peer.liveness != Liveness::Dead).map(|peer| peer.info.clone()).collect()
+    }
+
+    /// The manifests to put back into the DHT.
+    pub fn values(&self) -> impl Iterator<Item = &PackageManifest> {
+        self.values.values()
+    }
+
+    /// Records a manifest stored in the DHT, so it is saved with the rest.
+    pub fn stored(&mut self, manifest: PackageManifest) {
+        self.values.insert(manifest.root_cid.as_bytes().to_vec(), manifest);
+        self.dirty = true;
+    }
+
+    /// Takes a `Ping` or `Pong` into account. A pong marks the peer that was
+    /// pinged alive. A ping from an unknown node adds it, and one from a dead
+    /// peer brings it back. Returns true if the set of live peers changed.
+    pub fn heard(&mut self, heard: Heard, now_secs: u64) -> bool {
+        if let Some(answer) = heard.ping_id {
+            let pinged = self.peers.values_mut().find(|peer| {
+                peer.info.ip_address == heard.from.0 && matches!(peer.liveness, Liveness::Probing { ping_id, .. } if ping_id == answer)
+            });
+            if let Some(peer) = pinged {
+                peer.liveness = Liveness::Alive;
+                peer.last_seen = now_secs;
+                self.dirty = true;
+                self.save_now = self.sweep_done();
+            }
+            return false;
+        }
+        let id = heard.aid.node_id();
+        let info = PeerInfo { id: NodeId(id), aid: heard.aid, ip_address: heard.from.0, port: heard.port };
+        self.dirty = true;
+        match self.peers.get_mut(&id) {
+            Some(peer) => {
+                let revived = peer.liveness == Liveness::Dead;
+                peer.info = info;
+                peer.last_seen = now_secs;
+                peer.liveness = Liveness::Alive;
+                revived
+            },
+            None => {
+                self.peers.insert(id, KnownPeer { info, last_seen: now_secs, origin: PeerOrigin::Discovered, liveness: Liveness::Alive });
+                true
+            },
+        }
+    }
+
+    /// Pings peers not heard from yet, keeping at most `MAX_PINGS_IN_FLIGHT`
+    /// outstanding. `ping` sends one and returns its ID, or `None` if it can't.
+    pub fn probe(&mut self, now: u64, mut ping: impl FnMut(PeerAddr) -> Option<u64>) {
+        let in_flight = self.peers.values().filter(|peer| matches!(peer.liveness, Liveness::Probing { .. })).count();
+        let unprobed = self.peers.values_mut().filter(|peer| peer.liveness == Liveness::Unprobed);
+        for peer in unprobed.take(MAX_PINGS_IN_FLIGHT.saturating_sub(in_flight)) {
+            match ping((peer.info.ip_address, SEARCH_PORT)) {
+                Some(ping_id) => peer.liveness = Liveness::Probing { ping_id, deadline: now + PING_DEADLINE_TICKS },
+                None => return,
+            }
+        }
+    }
+
+    /// Marks peers whose ping went unanswered as dead. Returns them.
+    pub fn expire_probes(&mut self, now: u64) -> Vec<PeerInfo> {
+        let mut dead = Vec::new();
+        for peer in self.peers.values_mut() {
+            if matches!(peer.liveness, Liveness::Probing { deadline, .. } if now >= deadline) {
+                peer.liveness = Liveness::Dead;
+                dead.push(peer.info.clone());
+            }
+        }
+        if !dead.is_empty() {
+            self.dirty = true;
+            self.save_now = self.sweep_done();
+        }
+        dead
+    }
+
+    /// True once every peer has answered or been given up on.
+    pub fn sweep_done(&self) -> bool {
+        self.peers.values().all(|peer| matches!(peer.liveness, Liveness::Alive | Liveness::Dead))
+    }
+
+    pub fn counts(&self) -> PeerCounts {
+        let mut counts = PeerCounts::default();
+        for peer in self.peers.values() {
+            match (peer.liveness, peer.origin) {
+                (Liveness::Dead, _) => counts.dead += 1,
+                (_, PeerOrigin::Restored) => counts.restored += 1,
+                (_, PeerOrigin::Bootstrap) => counts.bootstrap += 1,
+                (_, PeerOrigin::Discovered) => counts.discovered += 1,
+            }
+        }
+        counts
+    }
+
+    /// True if there are unsaved changes and it is time to write them.
+    pub fn save_due(&self, now: u64) -> bool {
+        self.dirty && (self.save_now || now.saturating_sub(self.last_save) >= SAVE_INTERVAL_TICKS)
+    }
+
+    /// Writes the live peers and the stored values, both or neither.
+    pub fn save(&mut self, vfs_chan: &mut VNodeChannel, now: u64) -> Result<(), String> {
+        let peers = SavedPeers {
+            version: FORMAT_VERSION,
+            peers: self.peers.values()
+                .filter(|peer| peer.liveness != Liveness::Dead)
+                .map(|peer| SavedPeer { id: peer.info.id.0, aid: peer.info.aid.0, ip_address: peer.info.ip_address, port: peer.info.port, last_seen: peer.last_seen })
+                .collect(),
+        };
+        let values = SavedValues { version: FORMAT_VERSION, manifests: self.values.values().map(PackageManifest::to_canonical_bytes).collect() };
+        let encode = |what: &str, bytes: postcard::Result<Vec<u8>>| bytes.map_err(|_| format!("Failed to encode the {}", what));
+        let (peers, values) = (encode("peers", postcard::to_allocvec(&peers))?, encode("stored values", postcard::to_allocvec(&values))?);
+
+        // The directory is created outside the transaction; an empty one is harmless if the writes fail.
+        let _ = vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::CreateDirectory { path: SWARM_DIR.to_string() });
+        VfsTx::begin(vfs_chan).and_then(|mut tx| {
+            tx.write_file(PEERS_PATH, peers)?;
+            tx.write_file(VALUES_PATH, values)?;
+            tx.commit()
+        })?;
+        self.dirty = false;
+        self.save_now = false;
+        self.last_save = now;
+        Ok(())
+    }
+}
+
+/// Reads and decodes one of the state files. `None` if it doesn't exist.
+fn read_state<T: serde::de::DeserializeOwned>(vfs_chan: &mut VNodeChannel, path: &str) -> Result<Option<T>, String> {
+    let fd: Fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as Fd,
+        Ok(VfsResponse::Error { code: ENOENT, .. }) => return Ok(None),
+        Ok(VfsResponse::Error { message, .. }) => return Err(format!("{}: {}", path, message)),
+        _ => return Err("Unexpected response from VFS".to_string()),
+    };
+    let mut streams = VfsStreams::new(vfs_chan);
+    let data = streams.open_read(fd, 0, MAX_STATE_FILE_SIZE as u64 + 1)
+        .and_then(|stream| streams.read_to_end(stream, MAX_STATE_FILE_SIZE));
+    let _ = streams.request(&VfsRequest::Close { fd });
+    let data = data.map_err(|e| format!("{}: {}", path, e))?;
+    postcard::from_bytes(&data).map(Some).map_err(|_| format!("{} is corrupt", path))
+}
+
+/// Parses `swarm.bootstrap_peers`. Returns the peers and the entries that
+/// weren't valid. A peer's node ID is derived from its Aid.
+pub fn parse_bootstrap(value: &str) -> (Vec<PeerInfo>, Vec<String>) {
+    let mut peers = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match parse_peer(entry) {
+            Some(peer) => peers.push(peer),
+            None => invalid.push(String::from(entry)),
+        }
+    }
+    (peers, invalid)
+}
+
+fn parse_peer(entry: &str) -> Option<PeerInfo> {
+    let (aid, addr) = entry.split_once('@')?;
+    let (ip, port) = match addr.rsplit_once(':') {
+        Some((ip, port)) => (ip, port.parse::<u16>().ok().filter(|port| *port != 0)?),
+        None => (addr, SWARM_PORT),
+    };
+    let aid = Aid(aid_from_hex(aid)?);
+    Some(PeerInfo { id: NodeId(aid.node_id()), aid, ip_address: parse_ipv4(ip)?, port })
+}
+
+fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut octets = [0u8; 4];
+    let mut parts = text.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(octets)
+}
@@ -0,0 +1,209 @@
+// vnode/session/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod names;
+
+use core::panic::PanicInfo;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, SYS_SET_IDENTITY};
+use common::random;
+use common::ipc::session_ipc::{self, AidBytes, SessionRequest, SessionResponse};
+use common::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use common::trust::{TrustStore, Aid, LocalIdentity};
+
+use names::{NameDirectory, IDENTITIES_PATH};
+
+/// Largest `/etc/identities` that is read.
+const MAX_IDENTITIES_FILE_SIZE: u32 = 64 * 1024;
+
+/// The session service's own keypair; created on first start.
+const IDENTITY_PATH: &str = "/var/aether/session/identity";
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+struct SessionService {
+    client_chan: VNodeChannel,
+    vfs_chan: VNodeChannel, // Channel to svc://vfs for the name directory
+    trust_store: TrustStore,
+    #[allow(dead_code)] // Nothing is signed as the session service yet
+    identity: Option<LocalIdentity>,
+
+    nonces: BTreeMap<u64, AidBytes>, // task_id -> outstanding login nonce
+}
+
+impl SessionService {
+    fn new(client_chan_id: u32, vfs_chan_id: u32) -> Self {
+        let client_chan = VNodeChannel::new(client_chan_id);
+        let mut vfs_chan = VNodeChannel::new(vfs_chan_id);
+
+        log("Session Service: Initializing...");
+
+        let identity = match LocalIdentity::load_or_create(&mut vfs_chan, IDENTITY_PATH, None) {
+            Ok(identity) => {
+                log(&format!("Session Service: Service identity {}.", session_ipc::aid_to_hex(&identity.aid().0)));
+                Some(identity)
+            },
+            Err(e) => {
+                log(&format!("Session Service: No service identity ({}).", e));
+                None
+            }
+        };
+        Self {
+            client_chan,
+            vfs_chan,
+            trust_store: TrustStore::new(),
+            identity,
+            nonces: BTreeMap::new(),
+        }
+    }
+
+    /// A fresh random nonce, or `None` if the kernel has no random number
+    /// generator. Predictable nonces would let a recorded proof be replayed.
+    fn next_nonce(&mut self) -> Option<AidBytes> {
+        let mut nonce = [0u8; 32];
+        random::fill(&mut nonce).then_some(nonce)
+    }
+
+    fn set_identity(task_id: u64, aid: Option<&AidBytes>) -> bool {
+        let ptr = aid.map_or(0, |aid| aid.as_ptr() as u64);
+        unsafe { syscall3(SYS_SET_IDENTITY, task_id, ptr, 0) == SUCCESS }
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<String, String> {
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as u32,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let result = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_IDENTITIES_FILE_SIZE, offset: 0 }) {
+            Ok(VfsResponse::Data(data)) => String::from_utf8(data).map_err(|_| "File is not valid UTF-8".to_string()),
+            Ok(VfsResponse::Error { message, .. }) => Err(message),
+            _ => Err("Unexpected response from VFS".to_string()),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// Reads the name directory. It is read for every lookup rather than
+    /// cached, so edits to the file apply without restarting the service.
+    fn load_names(&mut self) -> NameDirectory {
+        let contents = match self.read_file(IDENTITIES_PATH) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log(&format!("Session Service: No name directory loaded ({}).", e));
+                return NameDirectory::default();
+            }
+        };
+        let (directory, invalid) = NameDirectory::parse(&contents);
+        for line in invalid {
+            log(&format!("Session Service: Ignoring invalid entry '{}' in {}.", line, IDENTITIES_PATH));
+        }
+        directory
+    }
+
+    fn handle_request(&mut self, sender: u64, request: SessionRequest) -> SessionResponse {
+        match request {
+            SessionRequest::Challenge => {
+                let nonce = match self.next_nonce() {
+                    Some(nonce) => nonce,
+                    None => return SessionResponse::Error("No random number generator; login is unavailable.".to_string()),
+                };
+                self.nonces.insert(sender, nonce);
+                SessionResponse::Nonce(nonce)
+            },
+            SessionRequest::Login { aid, proof } => {
+                // Each nonce is good for exactly one attempt, successful or not.
+                let nonce = match self.nonces.remove(&sender) {
+                    Some(nonce) => nonce,
+                    None => return SessionResponse::Error("No outstanding challenge; send Challenge first.".to_string()),
+                };
+                if aid == session_ipc::SYSTEM_AID {
+                    log(&format!("Session Service: Task {} tried to log in as the system identity.", sender));
+                    return SessionResponse::Error("The system identity cannot be used for interactive login.".to_string());
+                }
+                if !self.trust_store.verify_signature(&Aid(aid), &nonce, &proof) {
+                    log(&format!("Session Service: Login proof from task {} rejected.", sender));
+                    return SessionResponse::Error("Login proof rejected.".to_string());
+                }
+                if !Self::set_identity(sender, Some(&aid)) {
+                    return SessionResponse::Error("Kernel refused to bind identity.".to_string());
+                }
+                log(&format!("Session Service: Task {} logged in as {}.", sender, session_ipc::aid_to_hex(&aid)));
+                SessionResponse::Success
+            },
+            SessionRequest::Logout => {
+                if Self::set_identity(sender, None) {
+                    log(&format!("Session Service: Task {} logged out.", sender));
+                    SessionResponse::Success
+                } else {
+                    SessionResponse::Error("Kernel refused to clear identity.".to_string())
+                }
+            },
+            SessionRequest::GetIdentity { task_id } => {
+                match session_ipc::identity_of(task_id) {
+                    Some(aid) => SessionResponse::Identity { task_id, aid },
+                    None => SessionResponse::Unauthenticated,
+                }
+            },
+            SessionRequest::Resolve { name } => {
+                match self.load_names().resolve(&name) {
+                    Some(aid) => SessionResponse::Resolved { name, aid },
+                    None => SessionResponse::UnknownName(name),
+                }
+            },
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Session Service: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                match (postcard::from_bytes::<SessionRequest>(&req_data), session_ipc::last_sender()) {
+                    (Ok(request), Some(sender)) => {
+                        log(&format!("Session Service: Received SessionRequest from task {}: {:?}.", sender, request));
+                        let response = self.handle_request(sender, request);
+                        self.client_chan.send(&response).unwrap_or_else(|_| log("Session Service: Failed to send response to client."));
+                    },
+                    _ => log("Session Service: Failed to deserialize SessionRequest or identify its sender."),
+                }
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Assuming channel IDs:
+    // 15 for Session Service client requests
+    // 7 for VFS Service
+    let mut session_service = SessionService::new(15, 7);
+    session_service.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Session V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
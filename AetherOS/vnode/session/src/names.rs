@@ -0,0 +1,54 @@
+// vnode/session/src/names.rs
+
+//! The local name directory: which identity a name like `alice` refers to.
+//!
+//! Stored in `/etc/identities` as `<name> <aid hex>` lines. Blank lines and
+//! lines starting with `#` are ignored. Names are matched case-insensitively
+//! and may use letters, digits, `.`, `_` and `-`.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ipc::session_ipc::{aid_from_hex, AidBytes, SYSTEM_AID};
+
+pub const IDENTITIES_PATH: &str = "/etc/identities";
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+}
+
+#[derive(Default)]
+pub struct NameDirectory {
+    names: BTreeMap<String, AidBytes>, // Lowercase name -> identity
+}
+
+impl NameDirectory {
+    /// Parses the file contents. Returns the directory and the lines that were
+    /// rejected. The system identity can't be given a name.
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut directory = Self::default();
+        let mut invalid = Vec::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let entry = match (fields.next(), fields.next().and_then(aid_from_hex), fields.next()) {
+                (Some(name), Some(aid), None) if is_valid_name(name) && aid != SYSTEM_AID => Some((name.to_ascii_lowercase(), aid)),
+                _ => None,
+            };
+            match entry {
+                Some((name, aid)) => { directory.names.insert(name, aid); },
+                None => invalid.push(String::from(line)),
+            }
+        }
+        (directory, invalid)
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<AidBytes> {
+        self.names.get(&name.to_ascii_lowercase()).copied()
+    }
+}
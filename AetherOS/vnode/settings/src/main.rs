@@ -0,0 +1,265 @@
+// vnode/settings/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue, SettingEntry, SettingChanged};
+use common::ipc::event_ipc::{EventBusRequest, EventBusResponse};
+use common::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use common::ipc::vfs_lock;
+use common::ipc::vfs_tx::VfsTx;
+use common::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use common::startup::{self, SELF_CHANNEL};
+
+mod schema;
+
+const SETTINGS_PATH: &str = "/data/settings.cfg";
+const MAX_SETTINGS_FILE_SIZE: u32 = 64 * 1024;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+struct SettingsService {
+    client_chan: VNodeChannel,
+    vfs_chan: VNodeChannel,
+    event_bus_chan: VNodeChannel,
+    lifecycle: Lifecycle, // Shutdown requests from init
+
+    values: BTreeMap<String, SettingValue>, // Current values of schema keys
+    unknown: BTreeMap<String, String>, // Keys from the settings file not in the schema, preserved verbatim
+}
+
+impl SettingsService {
+    fn new(client_chan_id: u32, vfs_chan_id: u32, event_bus_chan_id: u32, lifecycle_chan_id: Option<u32>) -> Self {
+        let client_chan = VNodeChannel::new(client_chan_id);
+        let vfs_chan = VNodeChannel::new(vfs_chan_id);
+        let event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+
+        log("Settings Service: Initializing...");
+
+        let mut service = Self {
+            client_chan,
+            vfs_chan,
+            event_bus_chan,
+            lifecycle: Lifecycle::new(lifecycle_chan_id),
+            values: schema::SCHEMA.iter().map(|def| (def.key.to_string(), schema::default_value(def))).collect(),
+            unknown: BTreeMap::new(),
+        };
+        service.load();
+        service
+    }
+
+    /// Loads /data/settings.cfg, migrating it to the current schema version.
+    /// Invalid values fall back to defaults; unknown keys are kept and flagged.
+    fn load(&mut self) {
+        let contents = match self.read_file(SETTINGS_PATH) {
+            Ok(contents) => contents,
+            Err(e) => {
+                log(&format!("Settings Service: No settings file loaded ({}), using defaults.", e));
+                return;
+            }
+        };
+
+        let mut raw = schema::parse_file(&contents);
+        let from_version = raw.version;
+        let migrated = schema::migrate(&mut raw);
+        if migrated {
+            log(&format!("Settings Service: Migrated settings from schema v{} to v{}.", from_version, schema::SCHEMA_VERSION));
+        }
+
+        for key in schema::unknown_keys(&raw.entries) {
+            log(&format!("Settings Service: Unknown key '{}' in settings file; preserving it.", key));
+        }
+
+        for (key, raw_value) in raw.entries {
+            match schema::find(&key) {
+                Some(def) => match schema::parse_value(def, &raw_value) {
+                    Ok(value) => { self.values.insert(key, value); },
+                    Err(e) => log(&format!("Settings Service: Ignoring invalid stored value: {}.", e)),
+                },
+                None => { self.unknown.insert(key, raw_value); },
+            }
+        }
+
+        if migrated {
+            // Write the upgraded file back so the migration only runs once.
+            if let Err(e) = self.persist() {
+                log(&format!("Settings Service: Failed to persist migrated settings: {}.", e));
+            }
+        }
+        log(&format!("Settings Service: Loaded {} settings ({} unknown).", self.values.len(), self.unknown.len()));
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<String, String> {
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as u32,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let result = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Read { fd, len: MAX_SETTINGS_FILE_SIZE, offset: 0 }) {
+            Ok(VfsResponse::Data(data)) => String::from_utf8(data).map_err(|_| "Settings file is not valid UTF-8".to_string()),
+            Ok(VfsResponse::Error { message, .. }) => Err(message),
+            _ => Err("Unexpected response from VFS".to_string()),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// Writes all settings in a VFS transaction, so readers see either the old
+    /// file or the new one and a crash mid-write leaves the old one in place.
+    /// The file is locked exclusively meanwhile, for tools that edit it too.
+    fn persist(&mut self) -> Result<(), String> {
+        let mut entries: BTreeMap<String, String> = self.unknown.clone();
+        for (key, value) in &self.values {
+            entries.insert(key.clone(), schema::format_value(value));
+        }
+        let contents = schema::format_file(&entries);
+
+        vfs_lock::with_lock(&mut self.vfs_chan, SETTINGS_PATH, true, |chan| {
+            let mut tx = VfsTx::begin(chan)?;
+            tx.write_file(SETTINGS_PATH, contents.into_bytes())?;
+            tx.commit()
+        })
+    }
+
+    /// Publishes a "settings.<key>" change event so consumers can react without restarting.
+    fn publish_change(&mut self, key: &str, value: &SettingValue) {
+        let changed = SettingChanged { key: key.to_string(), value: value.clone() };
+        let payload = match postcard::to_allocvec(&changed) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let request = EventBusRequest::Publish { topic: format!("settings.{}", key), payload };
+        match self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request) {
+            Ok(EventBusResponse::Success(_)) => {},
+            _ => log(&format!("Settings Service: Failed to publish change event for '{}'.", key)),
+        }
+    }
+
+    /// Stores a validated value, persists it and notifies subscribers.
+    fn apply(&mut self, key: String, value: SettingValue) -> SettingsResponse {
+        let previous = self.values.insert(key.clone(), value.clone());
+        if let Err(e) = self.persist() {
+            // Keep memory and disk consistent: undo the change if it couldn't be saved.
+            if let Some(previous) = previous {
+                self.values.insert(key.clone(), previous);
+            }
+            log(&format!("Settings Service: Failed to persist '{}': {}.", key, e));
+            return SettingsResponse::Error(format!("Failed to save settings: {}", e));
+        }
+        log(&format!("Settings Service: '{}' set to {:?}.", key, value));
+        self.publish_change(&key, &value);
+        SettingsResponse::Success
+    }
+
+    fn handle_request(&mut self, request: SettingsRequest) -> SettingsResponse {
+        match request {
+            SettingsRequest::Get { key } => {
+                if let Some(value) = self.values.get(&key) {
+                    SettingsResponse::Value { key, value: value.clone() }
+                } else if let Some(raw) = self.unknown.get(&key) {
+                    SettingsResponse::Value { key, value: SettingValue::Str(raw.clone()) }
+                } else {
+                    SettingsResponse::Error(format!("Unknown setting '{}'.", key))
+                }
+            },
+            SettingsRequest::Set { key, value } => {
+                let def = match schema::find(&key) {
+                    Some(def) => def,
+                    None => return SettingsResponse::Error(format!("Unknown setting '{}'.", key)),
+                };
+                match schema::validate(def, value) {
+                    Ok(value) => self.apply(key, value),
+                    Err(e) => SettingsResponse::Error(e),
+                }
+            },
+            SettingsRequest::List => {
+                let mut entries: Vec<SettingEntry> = schema::SCHEMA.iter().map(|def| SettingEntry {
+                    key: def.key.to_string(),
+                    value: self.values.get(def.key).cloned().unwrap_or_else(|| schema::default_value(def)),
+                    kind: schema::describe_type(&def.ty),
+                    default: Some(schema::default_value(def)),
+                    description: def.description.to_string(),
+                    unknown: false,
+                }).collect();
+                entries.extend(self.unknown.iter().map(|(key, raw)| SettingEntry {
+                    key: key.clone(),
+                    value: SettingValue::Str(raw.clone()),
+                    kind: String::new(),
+                    default: None,
+                    description: "Not in the current schema; preserved from the settings file.".to_string(),
+                    unknown: true,
+                }));
+                SettingsResponse::Settings(entries)
+            },
+            SettingsRequest::ResetToDefault { key } => {
+                match schema::find(&key) {
+                    Some(def) => self.apply(key, schema::default_value(def)),
+                    None => SettingsResponse::Error(format!("Unknown setting '{}'.", key)),
+                }
+            },
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Settings Service: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                if let Ok(request) = postcard::from_bytes::<SettingsRequest>(&req_data) {
+                    log(&format!("Settings Service: Received SettingsRequest: {:?}.", request));
+                    let response = self.handle_request(request);
+                    self.client_chan.send(&response).unwrap_or_else(|_| log("Settings Service: Failed to send response to client."));
+                } else {
+                    log("Settings Service: Failed to deserialize SettingsRequest from client.");
+                }
+            }
+
+            // Every change is on disk by the time its request is answered, so there is nothing to flush.
+            if let Some(request) = self.lifecycle.shutdown_requested() {
+                log("Settings Service: Stopping for shutdown.");
+                self.lifecycle.stopped(request);
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback:
+    // 14 for Settings Service client requests
+    // 7 for VFS Service
+    // 13 for Event Bus
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut settings_service = SettingsService::new(channel(SELF_CHANNEL, 14), channel("vfs", 7), channel("event-bus", 13), channels.get(LIFECYCLE_CHANNEL).copied());
+    settings_service.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Settings V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
@@ -0,0 +1,647 @@
+// vnode/settings/src/schema.rs
+
+//! Central declaration of all settings, plus validation, the on-disk format
+//! and schema migrations.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::settings_ipc::SettingValue;
+
+/// Current version of the settings schema, written to the settings file.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The type of a setting and its allowed values.
+#[derive(Debug)]
+pub enum SettingType {
+    Bool,
+    Int { min: i64, max: i64 },
+    Str { max_len: usize },
+    Enum(&'static [&'static str]),
+    /// An opaque RGB color as `#RRGGBB`, the form the compositor parses.
+    Color,
+}
+
+/// A declared setting.
+#[derive(Debug)]
+pub struct SettingDef {
+    pub key: &'static str,
+    pub ty: SettingType,
+    pub default: &'static str, // In settings-file syntax, parsed with `parse_value`
+    pub description: &'static str,
+}
+
+/// All known settings. Add new preferences here rather than in individual services.
+pub static SCHEMA: &[SettingDef] = &[
+    SettingDef {
+        key: "aethersh.idle_timeout_secs",
+        ty: SettingType::Int { min: 30, max: 86400 },
+        default: "600",
+        description: "Seconds an aethersh session may go without a command before the server closes it. Applies to new sessions.",
+    },
+    SettingDef {
+        key: "aethersh.max_sessions",
+        ty: SettingType::Int { min: 0, max: 16 },
+        default: "4",
+        description: "Aethersh sessions the server holds at once; further connections are refused. 0 refuses all. Applies to new connections.",
+    },
+    SettingDef {
+        key: "aethersh.port",
+        ty: SettingType::Int { min: 1, max: 65535 },
+        default: "2222",
+        description: "TCP port the aethersh server listens on. Applies when the server restarts.",
+    },
+    SettingDef {
+        key: "audio.master_volume",
+        ty: SettingType::Int { min: 0, max: 100 },
+        default: "80",
+        description: "Volume of everything the audio mixer plays, in percent. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.background_color",
+        ty: SettingType::Color,
+        default: "#202030",
+        description: "Desktop background color as #RRGGBB, shown around the wallpaper if there is one. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.wallpaper",
+        ty: SettingType::Str { max_len: 255 },
+        default: "",
+        description: "VFS path of a PPM or BMP image drawn over the background color, or empty for none. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.wallpaper_fit",
+        ty: SettingType::Enum(&["center", "fit", "fill", "stretch"]),
+        default: "fill",
+        description: "How the wallpaper is laid out on a display of a different size. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.display_mode",
+        ty: SettingType::Str { max_len: 9 },
+        default: "",
+        description: "Resolution of the primary display as WIDTHxHEIGHT, or empty for the mode the bootloader set. Ignored where the display can't switch modes.",
+    },
+    SettingDef {
+        key: "compositor.hotkey.switch_window",
+        ty: SettingType::Str { max_len: 31 },
+        default: "Alt+Tab",
+        description: "Key combination that cycles through the windows, e.g. Meta+Tab. A combination another client holds is ignored. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.hotkey.screenshot",
+        ty: SettingType::Str { max_len: 31 },
+        default: "PrintScreen",
+        description: "Key combination that saves the screen to /data/screenshots, e.g. Ctrl+Shift+S. A combination another client holds is ignored. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.close_timeout_secs",
+        ty: SettingType::Int { min: 1, max: 60 },
+        default: "3",
+        description: "Seconds a window's owner has to answer a click on the close button before the compositor closes the window itself. Applies immediately.",
+    },
+    SettingDef {
+        key: "ui.scale",
+        ty: SettingType::Enum(&["1", "1.5", "2"]),
+        default: "1",
+        description: "Scale factor for window decorations, UI text and layout. Applies immediately.",
+    },
+    SettingDef {
+        key: "compositor.show_decorations",
+        ty: SettingType::Bool,
+        default: "true",
+        description: "Draw title bars and borders around windows.",
+    },
+    SettingDef {
+        key: "terminal.font_scale",
+        ty: SettingType::Int { min: 1, max: 4 },
+        default: "1",
+        description: "Integer scale factor applied to the 8x16 terminal font.",
+    },
+    SettingDef {
+        key: "dns.preferred_family",
+        ty: SettingType::Enum(&["ipv4", "ipv6", "any"]),
+        default: "any",
+        description: "Address family preferred when a hostname has both A and AAAA records.",
+    },
+    SettingDef {
+        key: "dns.mdns_hostname",
+        ty: SettingType::Str { max_len: 59 },
+        default: "",
+        description: "Hostname this node answers to as <name>.local. Empty derives one from the MAC address. Read at dns-resolver startup.",
+    },
+    SettingDef {
+        key: "dns.tcp_only",
+        ty: SettingType::Bool,
+        default: "false",
+        description: "Query the unicast DNS servers over TCP instead of UDP. Read at dns-resolver startup and when the network comes up.",
+    },
+    SettingDef {
+        key: "dns.servers",
+        ty: SettingType::Str { max_len: 255 },
+        default: "8.8.8.8,1.1.1.1",
+        description: "Unicast DNS servers, comma-separated IPv4 addresses, asked in order until one answers. Read at dns-resolver startup and when the network comes up.",
+    },
+    SettingDef {
+        key: "dns.query_timeout_ms",
+        ty: SettingType::Int { min: 100, max: 30000 },
+        default: "3000",
+        description: "How long the resolver waits for a DNS server's answer before asking again. Read at dns-resolver startup and when the network comes up.",
+    },
+    SettingDef {
+        key: "dns.query_retries",
+        ty: SettingType::Int { min: 0, max: 5 },
+        default: "2",
+        description: "How many more times a DNS server that didn't answer in time is asked before the next one. Read at dns-resolver startup and when the network comes up.",
+    },
+    SettingDef {
+        key: "files.trash_retention_days",
+        ty: SettingType::Int { min: 0, max: 3650 },
+        default: "30",
+        description: "Days the file manager keeps deleted files in the trash. 0 keeps them until the size cap evicts them.",
+    },
+    SettingDef {
+        key: "files.trash_max_mb",
+        ty: SettingType::Int { min: 1, max: 16 * 1024 * 1024 },
+        default: "512",
+        description: "Largest size, in MiB, of each identity's trash. The oldest entries are evicted to make room.",
+    },
+    SettingDef {
+        key: "keyboard.compose_key",
+        ty: SettingType::Enum(&["menu", "none", "right_alt", "right_ctrl", "right_meta"]),
+        default: "menu",
+        description: "Key that starts a compose sequence, e.g. Compose, o, / for ø. Applied by the display owner.",
+    },
+    SettingDef {
+        key: "keyboard.layout",
+        ty: SettingType::Str { max_len: 31 },
+        default: "us",
+        description: "Keyboard layout, e.g. de. Needs a keymap under /keymaps; an unknown name keeps the current layout. Applied by the display owner.",
+    },
+    SettingDef {
+        key: "keyboard.repeat_delay_ms",
+        ty: SettingType::Int { min: 100, max: 2000 },
+        default: "500",
+        description: "How long a key is held before it starts repeating, in milliseconds. Applied by the display owner.",
+    },
+    SettingDef {
+        key: "keyboard.repeat_rate",
+        ty: SettingType::Int { min: 0, max: 50 },
+        default: "25",
+        description: "Key repeats per second while a key is held. 0 turns repeat off. Applied by the display owner.",
+    },
+    SettingDef {
+        key: "locale.language",
+        ty: SettingType::Str { max_len: 15 },
+        default: "en",
+        description: "Language of messages, e.g. de. Needs a catalog under /locale; a regional variant such as de-AT falls back to de. Applies immediately.",
+    },
+    SettingDef {
+        key: "log.floor",
+        ty: SettingType::Enum(&["debug", "info", "warn", "error"]),
+        default: "info",
+        description: "Lowest severity logd writes to /data/log. Messages below it are still printed to the console. Applies within 30 seconds.",
+    },
+    SettingDef {
+        key: "log.floor_overrides",
+        ty: SettingType::Str { max_len: 1024 },
+        default: "",
+        description: "Per-service floors as comma-separated <service>=<level> entries, e.g. vfs=debug,net-stack=warn. Applies within 30 seconds.",
+    },
+    SettingDef {
+        key: "log.keep_files",
+        ty: SettingType::Int { min: 0, max: 16 },
+        default: "3",
+        description: "Rotated files logd keeps per service, as <service>.log.1 (newest) and up; 0 discards a full log. Applies at the next rotation.",
+    },
+    SettingDef {
+        key: "log.max_file_kb",
+        ty: SettingType::Int { min: 4, max: 65536 },
+        default: "256",
+        description: "Size in KiB at which logd rotates a service's log file. Applies within 30 seconds.",
+    },
+    SettingDef {
+        key: "mail.aliases",
+        ty: SettingType::Str { max_len: 4096 },
+        default: "",
+        description: "System mail aliases as comma-separated <alias>=<name> entries, e.g. admin=alice. A target may be another alias. Applies to the next local delivery.",
+    },
+    SettingDef {
+        key: "mail.poll_interval_secs",
+        ty: SettingType::Int { min: 10, max: 86400 },
+        default: "300",
+        description: "How often the mail service checks remote mailboxes, in seconds.",
+    },
+    SettingDef {
+        key: "net.connection_history",
+        ty: SettingType::Bool,
+        default: "true",
+        description: "Keep a record of recent TCP connections and UDP flows in the network stack's memory, for netstat --history. Read at net-stack startup.",
+    },
+    SettingDef {
+        key: "net.max_sockets_per_task",
+        ty: SettingType::Int { min: 1, max: 4096 },
+        default: "64",
+        description: "Most sockets one task may hold open in the network stack. Read at net-stack startup.",
+    },
+    SettingDef {
+        key: "net.max_sockets_total",
+        ty: SettingType::Int { min: 16, max: 65536 },
+        default: "512",
+        description: "Most sockets the network stack holds open across all tasks. Read at net-stack startup.",
+    },
+    SettingDef {
+        key: "net.policy",
+        ty: SettingType::Str { max_len: 4096 },
+        default: "",
+        description: "Per-service network policy enforced by socket-api, as ;-separated <service>=<allow|deny>[,<allow|deny> <cidr>[:<ports>]...] entries. Empty allows everything.",
+    },
+    SettingDef {
+        key: "notifications.do_not_disturb",
+        ty: SettingType::Bool,
+        default: "false",
+        description: "Keep notifications off the screen, except critical ones. They are still recorded in the history.",
+    },
+    SettingDef {
+        key: "shell.failglob",
+        ty: SettingType::Bool,
+        default: "false",
+        description: "Make a shell wildcard that matches nothing an error, instead of passing it on as typed.",
+    },
+    SettingDef {
+        key: "swarm.bootstrap_peers",
+        ty: SettingType::Str { max_len: 4096 },
+        default: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb@10.0.2.1:60000",
+        description: "Peers the registry starts from besides the ones it saved, as comma-separated <aid hex>@<ip>[:<port>] entries. Read at registry startup.",
+    },
+    SettingDef {
+        key: "swarm.download_limit_kbps",
+        ty: SettingType::Int { min: 0, max: 10_000_000 },
+        default: "0",
+        description: "Aggregate rate the registry fetches package chunks at, in KiB/s. 0 is unlimited. Applies immediately.",
+    },
+    SettingDef {
+        key: "swarm.upload_limit_kbps",
+        ty: SettingType::Int { min: 0, max: 10_000_000 },
+        default: "0",
+        description: "Aggregate rate the registry serves chunks to peers at, in KiB/s. 0 is unlimited. Applies immediately.",
+    },
+    SettingDef {
+        key: "tasks.core_dump_max_kb",
+        ty: SettingType::Int { min: 0, max: 16 * 1024 },
+        default: "1024",
+        description: "Largest core dump written to /data/crash for a crashed task, in KiB. Memory past it is left out; 0 keeps only the registers and the region list. Applies to the next crash.",
+    },
+    SettingDef {
+        key: "tasks.fault_storm_per_sec",
+        ty: SettingType::Int { min: 0, max: 1_000_000 },
+        default: "1000",
+        description: "Faults per second above which a task raises a task.faultstorm event. 0 turns the alarm off. Applies immediately.",
+    },
+    SettingDef {
+        key: "time.utc_offset_minutes",
+        ty: SettingType::Int { min: -840, max: 840 },
+        default: "0",
+        description: "Local time offset from UTC, in minutes, used when displaying times. Stored times stay in UTC.",
+    },
+    SettingDef {
+        key: "vfs.quota_default_mb",
+        ty: SettingType::Int { min: 1, max: 16 * 1024 * 1024 },
+        default: "1024",
+        description: "Storage quota, in MiB, for identities without an override. The VFS re-reads it every 30 seconds.",
+    },
+    SettingDef {
+        key: "vfs.quota_overrides",
+        ty: SettingType::Str { max_len: 4096 },
+        default: "",
+        description: "Per-identity storage quotas as comma-separated <aid hex>=<MiB> entries.",
+    },
+    SettingDef {
+        key: "webview.block_third_party_cookies",
+        ty: SettingType::Bool,
+        default: "false",
+        description: "Neither store nor send cookies for a domain the page's host isn't part of. Applies immediately.",
+    },
+];
+
+pub fn find(key: &str) -> Option<&'static SettingDef> {
+    SCHEMA.iter().find(|def| def.key == key)
+}
+
+/// Human-readable type description used in `List` output and error messages.
+pub fn describe_type(ty: &SettingType) -> String {
+    match ty {
+        SettingType::Bool => "bool".to_string(),
+        SettingType::Int { min, max } => format!("int({}..={})", min, max),
+        SettingType::Str { max_len } => format!("string(max {})", max_len),
+        SettingType::Enum(values) => format!("enum({})", values.join("|")),
+        SettingType::Color => "color(#RRGGBB)".to_string(),
+    }
+}
+
+/// Parses a value in settings-file syntax according to the setting's type.
+pub fn parse_value(def: &SettingDef, raw: &str) -> Result<SettingValue, String> {
+    let raw = raw.trim();
+    let value = match def.ty {
+        SettingType::Bool => match raw {
+            "true" => SettingValue::Bool(true),
+            "false" => SettingValue::Bool(false),
+            _ => return Err(format!("{}: expected true or false, got '{}'", def.key, raw)),
+        },
+        SettingType::Int { .. } => match raw.parse::<i64>() {
+            Ok(n) => SettingValue::Int(n),
+            Err(_) => return Err(format!("{}: expected an integer, got '{}'", def.key, raw)),
+        },
+        SettingType::Str { .. } | SettingType::Enum(_) | SettingType::Color => SettingValue::Str(raw.to_string()),
+    };
+    validate(def, value)
+}
+
+/// Checks a value against the schema. String values are accepted for any type
+/// and parsed, so text-only clients such as the shell can set typed settings.
+pub fn validate(def: &SettingDef, value: SettingValue) -> Result<SettingValue, String> {
+    match (&def.ty, value) {
+        (SettingType::Bool, SettingValue::Bool(b)) => Ok(SettingValue::Bool(b)),
+        (SettingType::Int { min, max }, SettingValue::Int(n)) => {
+            if n < *min || n > *max {
+                Err(format!("{}: {} is out of range {}..={}", def.key, n, min, max))
+            } else {
+                Ok(SettingValue::Int(n))
+            }
+        },
+        (SettingType::Str { max_len }, SettingValue::Str(s)) => {
+            if s.len() > *max_len {
+                Err(format!("{}: value is longer than {} bytes", def.key, max_len))
+            } else if s.contains('\n') {
+                Err(format!("{}: value must not contain newlines", def.key))
+            } else {
+                Ok(SettingValue::Str(s))
+            }
+        },
+        (SettingType::Enum(allowed), SettingValue::Str(s)) => {
+            if allowed.contains(&s.as_str()) {
+                Ok(SettingValue::Str(s))
+            } else {
+                Err(format!("{}: '{}' is not one of {}", def.key, s, allowed.join(", ")))
+            }
+        },
+        (SettingType::Color, SettingValue::Str(s)) => {
+            if is_color(&s) {
+                Ok(SettingValue::Str(s))
+            } else {
+                Err(format!("{}: '{}' is not a color as #RRGGBB", def.key, s))
+            }
+        },
+        (SettingType::Bool, SettingValue::Str(s)) | (SettingType::Int { .. }, SettingValue::Str(s)) => parse_value(def, &s),
+        (ty, value) => Err(format!("{}: expected {}, got {:?}", def.key, describe_type(ty), value)),
+    }
+}
+
+/// True for `#` followed by exactly six hex digits.
+fn is_color(s: &str) -> bool {
+    s.strip_prefix('#').map_or(false, |hex| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+pub fn default_value(def: &SettingDef) -> SettingValue {
+    // Schema defaults are expected to be valid; fall back to the raw string otherwise.
+    parse_value(def, def.default).unwrap_or_else(|_| SettingValue::Str(def.default.to_string()))
+}
+
+/// Formats a value in settings-file syntax.
+pub fn format_value(value: &SettingValue) -> String {
+    match value {
+        SettingValue::Bool(b) => b.to_string(),
+        SettingValue::Int(n) => n.to_string(),
+        SettingValue::Str(s) => s.clone(),
+    }
+}
+
+/// Contents of the settings file before validation: the schema version it was
+/// written with and its raw `key=value` pairs.
+pub struct RawSettings {
+    pub version: u32,
+    pub entries: BTreeMap<String, String>,
+}
+
+/// Parses the `key=value` settings file. Blank lines and `#` comments are ignored.
+/// A missing `schema_version` line means version 1.
+pub fn parse_file(contents: &str) -> RawSettings {
+    let mut version = 1;
+    let mut entries = BTreeMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim();
+            if key == "schema_version" {
+                version = value.trim().parse().unwrap_or(1);
+            } else {
+                entries.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+    }
+    RawSettings { version, entries }
+}
+
+/// Serializes settings (known and preserved unknown keys) into the file format.
+pub fn format_file(entries: &BTreeMap<String, String>) -> String {
+    let mut out = String::from("# AetherOS settings. Managed by svc://settings.\n");
+    out.push_str(&format!("schema_version={}\n", SCHEMA_VERSION));
+    for (key, value) in entries {
+        out.push_str(key);
+        out.push('=');
+        out.push_str(value);
+        out.push('\n');
+    }
+    out
+}
+
+/// One schema upgrade step, applied to raw entries written with version `from`.
+struct Migration {
+    from: u32,
+    apply: fn(&mut BTreeMap<String, String>),
+}
+
+/// v1 stored the terminal font size in pixels; v2 stores an integer scale of the 8x16 font.
+fn migrate_v1_font_size(entries: &mut BTreeMap<String, String>) {
+    if let Some(px) = entries.remove("terminal.font_size") {
+        let scale = px.trim().parse::<i64>().map(|px| (px / 16).clamp(1, 4)).unwrap_or(1);
+        entries.insert("terminal.font_scale".to_string(), scale.to_string());
+    }
+}
+
+static MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, apply: migrate_v1_font_size },
+];
+
+/// Upgrades raw entries to `SCHEMA_VERSION`. Returns true if anything was migrated.
+pub fn migrate(raw: &mut RawSettings) -> bool {
+    let mut migrated = false;
+    while raw.version < SCHEMA_VERSION {
+        for migration in MIGRATIONS.iter().filter(|m| m.from == raw.version) {
+            (migration.apply)(&mut raw.entries);
+            migrated = true;
+        }
+        raw.version += 1;
+    }
+    migrated
+}
+
+/// Keys present in the entries but not declared in the schema.
+pub fn unknown_keys(entries: &BTreeMap<String, String>) -> Vec<String> {
+    entries.keys().filter(|key| find(key).is_none()).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(key: &str) -> &'static SettingDef {
+        find(key).unwrap()
+    }
+
+    fn text(s: &str) -> SettingValue {
+        SettingValue::Str(s.to_string())
+    }
+
+    #[test]
+    fn every_default_is_valid() {
+        for def in SCHEMA {
+            assert!(parse_value(def, def.default).is_ok(), "default of {}", def.key);
+        }
+    }
+
+    #[test]
+    fn keys_are_declared_once() {
+        for (i, def) in SCHEMA.iter().enumerate() {
+            assert!(SCHEMA[i + 1..].iter().all(|other| other.key != def.key), "{} is declared twice", def.key);
+        }
+    }
+
+    #[test]
+    fn ints_are_checked_against_their_range() {
+        let font_scale = def("terminal.font_scale");
+        assert_eq!(validate(font_scale, SettingValue::Int(1)), Ok(SettingValue::Int(1)));
+        assert_eq!(validate(font_scale, SettingValue::Int(4)), Ok(SettingValue::Int(4)));
+        assert!(validate(font_scale, SettingValue::Int(0)).unwrap_err().contains("out of range 1..=4"));
+        assert!(validate(font_scale, SettingValue::Int(5)).is_err());
+        let poll = def("mail.poll_interval_secs");
+        assert_eq!(validate(poll, SettingValue::Int(86400)), Ok(SettingValue::Int(86400)));
+        assert!(validate(poll, SettingValue::Int(9)).is_err());
+        assert!(validate(poll, SettingValue::Int(-10)).is_err());
+    }
+
+    #[test]
+    fn wrong_types_are_rejected() {
+        assert!(validate(def("terminal.font_scale"), SettingValue::Bool(true)).unwrap_err().contains("expected int(1..=4)"));
+        assert!(validate(def("compositor.show_decorations"), SettingValue::Int(1)).unwrap_err().contains("expected bool"));
+        assert!(validate(def("dns.preferred_family"), SettingValue::Int(4)).is_err());
+        assert!(validate(def("compositor.background_color"), SettingValue::Int(0x202030)).is_err());
+    }
+
+    #[test]
+    fn strings_are_parsed_for_typed_settings() {
+        assert_eq!(validate(def("compositor.show_decorations"), text("true")), Ok(SettingValue::Bool(true)));
+        assert_eq!(validate(def("compositor.show_decorations"), text(" false ")), Ok(SettingValue::Bool(false)));
+        assert!(validate(def("compositor.show_decorations"), text("yes")).unwrap_err().contains("expected true or false"));
+        assert_eq!(validate(def("terminal.font_scale"), text("3")), Ok(SettingValue::Int(3)));
+        assert!(validate(def("terminal.font_scale"), text("three")).unwrap_err().contains("expected an integer"));
+        // A parsed value is still range-checked.
+        assert!(validate(def("terminal.font_scale"), text("9")).unwrap_err().contains("out of range"));
+    }
+
+    #[test]
+    fn strings_keep_to_their_length_and_a_single_line() {
+        let layout = def("keyboard.layout");
+        assert_eq!(validate(layout, text("de")), Ok(text("de")));
+        assert!(validate(layout, text(&"x".repeat(32))).unwrap_err().contains("longer than 31 bytes"));
+        assert!(validate(layout, text("de\nus")).unwrap_err().contains("newlines"));
+    }
+
+    #[test]
+    fn enums_take_only_their_values() {
+        let family = def("dns.preferred_family");
+        for value in ["ipv4", "ipv6", "any"] {
+            assert_eq!(validate(family, text(value)), Ok(text(value)));
+        }
+        assert!(validate(family, text("IPv4")).unwrap_err().contains("is not one of ipv4, ipv6, any"));
+        assert!(validate(family, text("")).is_err());
+    }
+
+    #[test]
+    fn colors_must_be_hash_and_six_hex_digits() {
+        let color = def("compositor.background_color");
+        assert_eq!(describe_type(&color.ty), "color(#RRGGBB)");
+        for value in ["#202030", "#ABCDEF", "#abcdef", "#000000"] {
+            assert_eq!(validate(color, text(value)), Ok(text(value)));
+        }
+        for value in ["", "#", "202030", "#20203", "#2020300", "#20203G", "red", "#+12345", "#\u{E9}1234"] {
+            assert!(validate(color, text(value)).unwrap_err().contains("#RRGGBB"), "{:?}", value);
+        }
+        assert_eq!(parse_value(color, " #102030 "), Ok(text("#102030")));
+    }
+
+    #[test]
+    fn types_are_described_for_list() {
+        assert_eq!(describe_type(&SettingType::Bool), "bool");
+        assert_eq!(describe_type(&def("terminal.font_scale").ty), "int(1..=4)");
+        assert_eq!(describe_type(&def("keyboard.layout").ty), "string(max 31)");
+        assert_eq!(describe_type(&def("dns.preferred_family").ty), "enum(ipv4|ipv6|any)");
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let mut entries = BTreeMap::new();
+        entries.insert("compositor.show_decorations".to_string(), "true".to_string());
+        entries.insert("compositor.background_color".to_string(), "#102030".to_string());
+        entries.insert("legacy.key".to_string(), "a=b".to_string());
+        let raw = parse_file(&format_file(&entries));
+        assert_eq!(raw.version, SCHEMA_VERSION);
+        assert_eq!(raw.entries, entries);
+    }
+
+    #[test]
+    fn files_skip_comments_and_blank_lines() {
+        let raw = parse_file("# comment\n\n  compositor.show_decorations = true \nno separator\nschema_version=2\n");
+        assert_eq!(raw.version, 2);
+        assert_eq!(raw.entries.len(), 1);
+        assert_eq!(raw.entries["compositor.show_decorations"], "true");
+        // Without a version line the file is from version 1.
+        assert_eq!(parse_file("compositor.show_decorations=true\n").version, 1);
+    }
+
+    #[test]
+    fn version_1_font_sizes_become_scales() {
+        for (px, scale) in [("16", "1"), ("32", "2"), ("40", "2"), ("8", "1"), ("200", "4"), ("big", "1")] {
+            let mut raw = parse_file(&format!("terminal.font_size={}\n", px));
+            assert!(migrate(&mut raw));
+            assert_eq!(raw.version, SCHEMA_VERSION);
+            assert!(!raw.entries.contains_key("terminal.font_size"));
+            assert_eq!(raw.entries["terminal.font_scale"], scale, "{}px", px);
+        }
+    }
+
+    #[test]
+    fn current_files_are_not_migrated() {
+        let mut raw = parse_file(&format!("schema_version={}\nterminal.font_size=32\n", SCHEMA_VERSION));
+        assert!(!migrate(&mut raw));
+        assert_eq!(raw.entries["terminal.font_size"], "32");
+        // A version 1 file without the old key still counts as migrated, so
+        // it is written back with the current version.
+        let mut raw = parse_file("compositor.show_decorations=true\n");
+        assert!(migrate(&mut raw));
+        assert_eq!(raw.version, SCHEMA_VERSION);
+        assert_eq!(raw.entries.len(), 1);
+    }
+
+    #[test]
+    fn unknown_keys_are_flagged() {
+        let raw = parse_file("compositor.show_decorations=true\nlegacy.key=1\nterminal.font_size=16\n");
+        assert_eq!(unknown_keys(&raw.entries), ["legacy.key", "terminal.font_size"]);
+    }
+}
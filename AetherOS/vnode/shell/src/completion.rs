@@ -0,0 +1,355 @@
+// vnode/shell/src/completion.rs
+
+//! Line parsing for `ShellRequest::ExecuteLine` and `ShellRequest::Complete`,
+//! and candidate filtering for the latter. The IPC lookups (VFS listings,
+//! init service names) live in main.rs; this module only deals with text.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Built-in commands offered when completing the first word of a line.
+pub const BUILTIN_COMMANDS: &[&str] = &["aethersh", "apkg", "arp", "cd", "cp", "date", "dbg", "display", "dmesg", "dns", "du", "grep", "history", "hotkeys", "ifdown", "ifup", "latency", "logs", "ls", "netpolicy", "netstat", "ping", "ps", "quota", "reboot", "rm", "settings", "shutdown", "start", "stat", "stop", "swarm", "tcpdump", "time", "trash"];
+
+/// Commands whose first argument is a service name known to init-service.
+pub const SERVICE_COMMANDS: &[&str] = &["start", "stop"];
+
+/// The word under the cursor, as seen by the completer.
+#[derive(Debug)]
+pub struct WordContext {
+    /// Index of the word being completed (0 = command name).
+    pub word_index: usize,
+    /// The command name, if the word being completed is an argument.
+    pub command: Option<String>,
+    /// The unescaped text of the word typed so far.
+    pub prefix: String,
+    /// Byte offset in the line where the word (including any opening quote) starts.
+    pub start: usize,
+    /// The quote character if the cursor is inside an unterminated quoted string.
+    pub quote: Option<char>,
+}
+
+/// Splits `line` up to `cursor_pos` into words, honouring single/double quotes
+/// and backslash escapes, and returns the context of the last (current) word.
+pub fn parse_line(line: &str, cursor_pos: usize) -> WordContext {
+    let mut end = cursor_pos.min(line.len());
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut words: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut start = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (i, c) in line[..end].char_indices() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '\\') => {
+                if !in_word { in_word = true; start = i; }
+                escaped = true;
+            }
+            (None, '\'') | (None, '"') => {
+                if !in_word { in_word = true; start = i; }
+                quote = Some(c);
+            }
+            (None, ' ') | (None, '\t') => {
+                if in_word {
+                    words.push(core::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                if !in_word { in_word = true; start = i; }
+                current.push(c);
+            }
+        }
+    }
+
+    if !in_word {
+        start = end;
+    }
+
+    WordContext {
+        word_index: words.len(),
+        command: words.first().cloned(),
+        prefix: current,
+        start,
+        quote,
+    }
+}
+
+/// A word of a command line, with its quotes and escapes removed.
+#[derive(Debug)]
+pub struct Word {
+    pub text: String,
+    /// Whether any part of the word was quoted or escaped. Such words are
+    /// passed on as typed, without glob expansion.
+    pub quoted: bool,
+}
+
+/// Splits a whole command line into words, with the same quoting rules as
+/// `parse_line`. An unterminated quote or a trailing backslash is an error.
+pub fn split_words(line: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut current = Word { text: String::new(), quoted: false };
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in line.chars() {
+        if escaped {
+            current.text.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.text.push(c),
+            (None, '\\') => {
+                in_word = true;
+                current.quoted = true;
+                escaped = true;
+            }
+            (None, '\'') | (None, '"') => {
+                in_word = true;
+                current.quoted = true;
+                quote = Some(c);
+            }
+            (None, ' ') | (None, '\t') => {
+                if in_word {
+                    words.push(core::mem::replace(&mut current, Word { text: String::new(), quoted: false }));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                in_word = true;
+                current.text.push(c);
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(alloc::format!("unterminated {} quote", q));
+    }
+    if escaped {
+        return Err(String::from("trailing backslash"));
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+/// Returns the longest prefix shared by all candidates.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let mut iter = candidates.iter();
+    let mut prefix = match iter.next() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+    for candidate in iter {
+        let shared = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| prefix.len().min(candidate.len()));
+        prefix.truncate(shared);
+    }
+    prefix
+}
+
+/// Filters `names` down to those starting with `prefix`. Hidden entries
+/// (leading '.') are only offered when the prefix itself starts with a dot.
+pub fn filter_by_prefix<'a, I>(names: I, prefix: &str) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let show_hidden = prefix.starts_with('.');
+    let mut matches: Vec<String> = names
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .filter(|name| show_hidden || !name.starts_with('.'))
+        .map(String::from)
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// Splits a path prefix into the directory part (kept verbatim, including the
+/// trailing slash) and the partial entry name being completed.
+pub fn split_path_prefix(prefix: &str) -> (&str, &str) {
+    match prefix.rfind('/') {
+        Some(idx) => (&prefix[..idx + 1], &prefix[idx + 1..]),
+        None => ("", prefix),
+    }
+}
+
+/// Resolves the directory part of a path prefix against the shell's current directory
+/// into the path to send with `VfsRequest::List`.
+pub fn resolve_dir(current_dir: &str, dir_part: &str) -> String {
+    let mut path = if dir_part.starts_with('/') {
+        String::from(dir_part)
+    } else {
+        let mut path = String::from(current_dir);
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(dir_part);
+        path
+    };
+    while path.len() > 1 && path.ends_with('/') {
+        path.pop();
+    }
+    path
+}
+
+/// Re-encodes completed text so it can be inserted at `ctx.start` without
+/// breaking the quoting the user already typed.
+pub fn encode_word(ctx: &WordContext, text: &str, is_final: bool) -> String {
+    let mut out = String::new();
+    match ctx.quote {
+        Some(q) => {
+            out.push(q);
+            for c in text.chars() {
+                // Single-quoted strings cannot contain escapes in this shell; double quotes can.
+                if c == q && q == '"' {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            if is_final {
+                out.push(q);
+            }
+        }
+        None => {
+            for c in text.chars() {
+                if matches!(c, ' ' | '\t' | '\'' | '"' | '\\') {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+        }
+    }
+    if is_final {
+        out.push(' ');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().copied().map(String::from).collect()
+    }
+
+    #[test]
+    fn empty_line_completes_the_command_name() {
+        let ctx = parse_line("", 0);
+        assert_eq!(ctx.word_index, 0);
+        assert_eq!(ctx.command, None);
+        assert_eq!(ctx.prefix, "");
+        assert_eq!(ctx.start, 0);
+        assert_eq!(filter_by_prefix(BUILTIN_COMMANDS.iter().copied(), "").len(), BUILTIN_COMMANDS.len());
+    }
+
+    #[test]
+    fn cursor_after_a_space_starts_a_new_word() {
+        let ctx = parse_line("ls ", 3);
+        assert_eq!(ctx.word_index, 1);
+        assert_eq!(ctx.command.as_deref(), Some("ls"));
+        assert_eq!(ctx.prefix, "");
+        assert_eq!(ctx.start, 3);
+    }
+
+    #[test]
+    fn only_text_before_the_cursor_counts() {
+        let ctx = parse_line("cat docs/readme", 8);
+        assert_eq!(ctx.word_index, 1);
+        assert_eq!(ctx.prefix, "docs");
+        assert_eq!(ctx.start, 4);
+        // A cursor inside a multi-byte char backs off to its start.
+        let ctx = parse_line("ls \u{E9}t\u{E9}", 5);
+        assert_eq!(ctx.prefix, "\u{E9}");
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_removed_from_the_prefix() {
+        let ctx = parse_line("cat \"my fi", 10);
+        assert_eq!(ctx.prefix, "my fi");
+        assert_eq!(ctx.quote, Some('"'));
+        assert_eq!(ctx.start, 4);
+        let ctx = parse_line("cat my\\ fi", 10);
+        assert_eq!(ctx.prefix, "my fi");
+        assert_eq!(ctx.quote, None);
+    }
+
+    #[test]
+    fn prefix_filters_and_sorts_candidates() {
+        let names = ["stop", "start", "stat", "ls", ".hidden", "start"];
+        assert_eq!(filter_by_prefix(names, "st"), strings(&["start", "stat", "stop"]));
+        assert_eq!(filter_by_prefix(names, "sta"), strings(&["start", "stat"]));
+        assert_eq!(filter_by_prefix(names, "x"), Vec::<String>::new());
+        assert_eq!(filter_by_prefix(names, ""), strings(&["ls", "start", "stat", "stop"]));
+        assert_eq!(filter_by_prefix(names, "."), strings(&[".hidden"]));
+    }
+
+    #[test]
+    fn ambiguous_candidates_complete_to_their_shared_prefix() {
+        assert_eq!(common_prefix(&strings(&["start", "stat"])), "sta");
+        assert_eq!(common_prefix(&strings(&["start", "stop", "stat"])), "st");
+        assert_eq!(common_prefix(&strings(&["ls", "cd"])), "");
+        assert_eq!(common_prefix(&strings(&["stat", "status"])), "stat");
+        assert_eq!(common_prefix(&strings(&["only"])), "only");
+        assert_eq!(common_prefix(&[]), "");
+        // Stops at a char boundary, not inside a shared lead byte.
+        assert_eq!(common_prefix(&strings(&["a\u{E9}", "a\u{E8}"])), "a");
+    }
+
+    #[test]
+    fn path_prefixes_split_and_resolve() {
+        assert_eq!(split_path_prefix("docs/re"), ("docs/", "re"));
+        assert_eq!(split_path_prefix("/"), ("/", ""));
+        assert_eq!(split_path_prefix("re"), ("", "re"));
+        assert_eq!(resolve_dir("/home/user", "docs/"), "/home/user/docs");
+        assert_eq!(resolve_dir("/home/user", ""), "/home/user");
+        assert_eq!(resolve_dir("/", ""), "/");
+        assert_eq!(resolve_dir("/home", "/etc/"), "/etc");
+    }
+
+    #[test]
+    fn completions_keep_the_typed_quoting() {
+        let plain = parse_line("cat my", 6);
+        assert_eq!(encode_word(&plain, "my file", true), "my\\ file ");
+        assert_eq!(encode_word(&plain, "my dir/", false), "my\\ dir/");
+        let quoted = parse_line("cat \"my", 7);
+        assert_eq!(encode_word(&quoted, "my \"file\"", true), "\"my \\\"file\\\"\" ");
+        let single = parse_line("cat 'my", 7);
+        assert_eq!(encode_word(&single, "my dir/", false), "'my dir/");
+    }
+
+    #[test]
+    fn whole_lines_split_into_words() {
+        let words = split_words("  cp 'a b' c\\ d *.txt ").unwrap();
+        let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(texts, vec!["cp", "a b", "c d", "*.txt"]);
+        assert_eq!(words.iter().map(|word| word.quoted).collect::<Vec<_>>(), vec![false, true, true, false]);
+        assert!(split_words("").unwrap().is_empty());
+        assert_eq!(split_words("echo 'oops").unwrap_err(), "unterminated ' quote");
+        assert_eq!(split_words("echo oops\\").unwrap_err(), "trailing backslash");
+    }
+}
@@ -12,12 +12,54 @@ use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
 
+use crate::channels;
 use crate::ipc::vnode::VNodeChannel;
-use crate::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, E_ACC_DENIED, E_BUSY, E_INVALID_ARG};
 use crate::ipc::shell_ipc::{ShellRequest, ShellResponse};
-use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata};
-use crate::ipc::init_ipc::{InitRequest, InitResponse};
+use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata, VfsUsage};
+use crate::ipc::session_ipc::{self, SessionRequest, SessionResponse, AidBytes};
+use crate::ipc::aethersh_ipc::{self, ClientFrame, ServerFrame};
+use crate::ipc::init_ipc::{InitRequest, InitResponse, ServiceTarget, GroupInfo, GroupState};
 use crate::ipc::dns_ipc::{DnsRequest, DnsResponse};
+use crate::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use crate::ipc::registry_ipc::{RegistryRequest, RegistryResponse, InstallDecision, PackageHealth};
+use crate::ipc::ui_protocol::{UiRequest, UiResponse, WallpaperFit, HotkeyAction};
+use crate::ipc::net_ipc::{NetStackRequest, NetStackResponse, NeighborState, CaptureDirection, CaptureFilter, MAX_SNAPLEN};
+use crate::ipc::net_ipc::{CloseReason, ConnectionRecord, FlowProtocol, CONNECTION_HISTORY_LEN};
+use crate::ipc::socket_ipc::{SocketRequest, SocketResponse, PolicyAction, ServicePolicy};
+use crate::ipc::file_manager_ipc::{FileManagerRequest, FileManagerResponse};
+use crate::ipc::envelope;
+use crate::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use crate::ipc::log_ipc;
+use crate::startup::{self, SELF_CHANNEL};
+use crate::trust::LocalIdentity;
+use crate::ui::latency::{PipelineLatency, Stage};
+use crate::time;
+use crate::ids::Ticks;
+use crate::ansi;
+use crate::keys;
+use crate::glob;
+use crate::debug;
+use crate::abi::{RegisterFrame, TaskStats, LAST_BOOT_PANIC, LAST_BOOT_SHUTDOWN, TASK_CPU_NONE, TASK_FLAG_FILTERED, TASK_FLAG_SUSPENDED};
+use crate::abi::{TASK_STATE_BLOCKED, TASK_STATE_EXITED, TASK_STATE_READY, TASK_STATE_RUNNING};
+use crate::tasks;
+use crate::klog::{self, KlogError};
+use crate::i18n;
+use crate::tr;
+
+mod completion;
+mod timing;
+mod remote;
+use completion::{Word, WordContext, BUILTIN_COMMANDS, SERVICE_COMMANDS};
+use timing::{Cost, HistoryEntry, Stopwatch};
+use remote::RemoteShell;
+
+/// Most arguments wildcards may expand a command line to.
+const MAX_GLOB_MATCHES: usize = 1000;
+/// Whether a wildcard that matches nothing is an error rather than kept as typed.
+const FAILGLOB_KEY: &str = "shell.failglob";
+/// How long the registry keeps an install waiting for an answer.
+const INSTALL_ANSWER_TIMEOUT: Ticks = Ticks::from_secs(60);
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -38,88 +80,100 @@ struct ShellService {
     vfs_chan: VNodeChannel, // Channel to svc://vfs
     init_chan: VNodeChannel, // Channel to svc://init-service
     dns_chan: VNodeChannel, // Channel to svc://dns-resolver
+    settings_chan: VNodeChannel, // Channel to svc://settings
+    registry_chan: VNodeChannel, // Channel to svc://registry
+    compositor_chan: VNodeChannel, // Channel to svc://display-compositor
+    net_chan: VNodeChannel, // Channel to svc://aethernet-service, for `arp`
+    socket_chan: VNodeChannel, // Channel to svc://socket-api, for `netpolicy`
+    file_manager_chan: VNodeChannel, // Channel to svc://file-manager, for `rm` and `trash`
+    session_chan: VNodeChannel, // Channel to svc://session, for `Login`
+    bus_events_chan: Option<VNodeChannel>, // Locale changes from the event bus; the console shell only
+    log_watch_chan: Option<VNodeChannel>, // Changes to the log file `logs --follow` is showing; the console shell only
 
     current_dir: String,
-    command_history: Vec<String>,
+    pending_install: Option<PendingInstall>, // The install awaiting the user's answer
+    command_history: Vec<HistoryEntry>,
+    paused: Option<Paused>, // The command waiting for an `Answer`, if any
+    bytes_processed: Option<u64>, // What the running command's services reported processing, for `time`
+    identity: Option<AidBytes>, // Who this shell logged in as with `Login`
+    remote: Option<RemoteShell>, // The `aethersh` session input goes to, if any
     // Add more state as needed, e.g., environmental variables
 }
 
 impl ShellService {
-    fn new(client_chan_id: u32, vfs_chan_id: u32, init_chan_id: u32, dns_chan_id: u32) -> Self {
+    /// `bus_events_chan_id` and `log_watch_chan_id` are fixed channels only
+    /// one shell can own, so shells that init starts, one per aethersh
+    /// session, go without: they stay in the boot locale and can't follow logs.
+    fn new(client_chan_id: u32, vfs_chan_id: u32, init_chan_id: u32, dns_chan_id: u32, settings_chan_id: u32, registry_chan_id: u32, compositor_chan_id: u32, net_chan_id: u32, socket_chan_id: u32, file_manager_chan_id: u32, event_bus_chan_id: u32, session_chan_id: u32, bus_events_chan_id: Option<u32>, log_watch_chan_id: Option<u32>) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
-        let vfs_chan = VNodeChannel::new(vfs_chan_id);
+        let mut vfs_chan = VNodeChannel::new(vfs_chan_id);
         let init_chan = VNodeChannel::new(init_chan_id);
         let dns_chan = VNodeChannel::new(dns_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let registry_chan = VNodeChannel::new(registry_chan_id);
+        let compositor_chan = VNodeChannel::new(compositor_chan_id);
+        let net_chan = VNodeChannel::new(net_chan_id);
+        let socket_chan = VNodeChannel::new(socket_chan_id);
+        let file_manager_chan = VNodeChannel::new(file_manager_chan_id);
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let bus_events_chan = bus_events_chan_id.map(VNodeChannel::new);
 
         log("Shell Service: Initializing...");
 
+        i18n::load_from_settings(&mut settings_chan, &mut vfs_chan);
+        if let Some(bus_events_chan) = &bus_events_chan {
+            let subscribe = EventBusRequest::Subscribe { topic_prefix: i18n::locale_topic(), reply_chan: bus_events_chan.id };
+            if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+                log("Shell Service: Could not subscribe to locale changes; messages stay in the boot locale.");
+            }
+        }
+
         Self {
             client_chan,
             vfs_chan,
             init_chan,
             dns_chan,
+            settings_chan,
+            registry_chan,
+            compositor_chan,
+            net_chan,
+            socket_chan,
+            file_manager_chan,
+            session_chan: VNodeChannel::new(session_chan_id),
+            bus_events_chan,
+            log_watch_chan: log_watch_chan_id.map(VNodeChannel::new),
             current_dir: String::from("/"), // Default to root
+            pending_install: None,
             command_history: Vec::new(),
+            paused: None,
+            bytes_processed: None,
+            identity: None,
+            remote: None,
         }
     }
 
+    /// The install waiting for an answer, unless the registry has given up on it.
+    fn waiting_install(&self) -> Option<&PendingInstall> {
+        self.pending_install.as_ref().filter(|pending| time::ticks().saturating_sub(pending.asked_at) < INSTALL_ANSWER_TIMEOUT)
+    }
+
     fn handle_request(&mut self, request: ShellRequest) -> ShellResponse {
         match request {
-            ShellRequest::ExecuteCommand { command, args } => {
-                self.command_history.push(format!("{} {}", command, args.join(" ")));
-                log(&alloc::format!("Shell: Executing command: {} with args: {:?}", command, args));
-
-                // Conceptual: Implement built-in commands or forward to init-service
-                match command.as_str() {
-                    "cd" => {
-                        if let Some(path) = args.get(0) {
-                            return self.handle_change_directory(path.to_string());
-                        } else {
-                            return ShellResponse::Error("cd: missing argument".to_string());
-                        }
-                    },
-                    "ls" => {
-                        // Conceptual: IPC to VFS to list directory
-                        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: self.current_dir.clone() }) {
-                            Ok(VfsResponse::DirectoryEntries(entries)) => {
-                                let mut output = String::new();
-                                for (name, _) in entries {
-                                    output.push_str(&name);
-                                    output.push_str("\n");
-                                }
-                                ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
-                            },
-                            Ok(VfsResponse::Error { message, .. }) => ShellResponse::Error(format!("ls: {}", message)),
-                            _ => ShellResponse::Error("ls: Unexpected response from VFS".to_string()),
-                        }
-                    },
-                    "ping" => {
-                        if let Some(hostname) = args.get(0) {
-                            match self.dns_chan.send_and_recv::<DnsRequest, DnsResponse>(&DnsRequest::ResolveHostname { hostname: hostname.clone() }) {
-                                Ok(DnsResponse::ResolvedHostname { ip_address, .. }) => {
-                                    ShellResponse::CommandOutput { stdout: format!("Pinging {} ({}.{}.{}.{})", hostname, ip_address[0], ip_address[1], ip_address[2], ip_address[3]), stderr: String::new(), exit_code: 0 }
-                                },
-                                Ok(DnsResponse::NotFound { query }) => ShellResponse::Error(format!("ping: Host '{}' not found.", query)),
-                                Ok(DnsResponse::Error { message }) => ShellResponse::Error(format!("ping: DNS error: {}", message)),
-                                _ => ShellResponse::Error("ping: Unexpected response from DNS Resolver".to_string()),
-                            }
-                        } else {
-                            ShellResponse::Error("ping: missing hostname".to_string())
-                        }
-                    },
-                    "start" => {
-                        if let Some(service_name) = args.get(0) {
-                            match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ServiceStart { service_name: service_name.clone() }) {
-                                Ok(InitResponse::Success(msg)) => ShellResponse::Success(msg),
-                                Ok(InitResponse::Error(msg)) => ShellResponse::Error(format!("start: {}", msg)),
-                                _ => ShellResponse::Error("start: Unexpected response from Init Service".to_string()),
-                            }
-                        } else {
-                            ShellResponse::Error("start: missing service name".to_string())
-                        }
-                    }
-                    // Add more built-in commands or forward to init-service for app execution
-                    _ => ShellResponse::CommandOutput { stdout: format!("Command '{}' not found.\n", command), stderr: String::new(), exit_code: 127 },
+            // The client has already split the line, so nothing here says which
+            // arguments were quoted; they are used as they are.
+            ShellRequest::ExecuteCommand { command, args } => self.execute(command, args),
+            ShellRequest::ExecuteLine { line } => {
+                let mut words = match completion::split_words(&line) {
+                    Ok(words) => words.into_iter(),
+                    Err(e) => return ShellResponse::Error(format!("shell: {}", e)),
+                };
+                let command = match words.next() {
+                    Some(word) => word.text,
+                    None => return ShellResponse::Success(String::new()),
+                };
+                match self.expand_globs(&command, words.collect()) {
+                    Ok(args) => self.execute(command, args),
+                    Err(e) => e,
                 }
             },
             ShellRequest::ChangeDirectory { path } => {
@@ -128,6 +182,1524 @@ impl ShellService {
             ShellRequest::GetCurrentDirectory => {
                 ShellResponse::CurrentDirectory(self.current_dir.clone())
             },
+            ShellRequest::Answer { text } => {
+                // The command resumes; its cost so far was recorded when it prompted.
+                let paused = self.paused.take();
+                let watch = Stopwatch::start();
+                let response = self.handle_answer(&text);
+                match paused {
+                    Some(paused) => self.finish_command(paused, watch.stop(), response),
+                    None => response,
+                }
+            },
+            ShellRequest::Complete { line, cursor_pos } => {
+                self.handle_complete(&line, cursor_pos as usize)
+            },
+            ShellRequest::GetTimings { limit } => ShellResponse::Timings(timing::slowest(&self.command_history, limit as usize)),
+            // Relayed so that svc://session binds the Aid to this shell's task,
+            // not to whoever asked; aethersh-server logs its sessions in this way.
+            ShellRequest::LoginChallenge => match self.session_chan.send_and_recv::<SessionRequest, SessionResponse>(&SessionRequest::Challenge) {
+                Ok(SessionResponse::Nonce(nonce)) => ShellResponse::Challenge { nonce },
+                Ok(SessionResponse::Error(message)) => ShellResponse::Error(format!("login: {}", message)),
+                _ => unexpected_response("login", "Session"),
+            },
+            ShellRequest::Login { aid, proof } => match self.session_chan.send_and_recv::<SessionRequest, SessionResponse>(&SessionRequest::Login { aid, proof }) {
+                Ok(SessionResponse::Success) => {
+                    self.identity = Some(aid);
+                    self.current_dir = session_ipc::home_dir(&aid);
+                    ShellResponse::Success(format!("Logged in as {}", session_ipc::aid_to_hex(&aid)))
+                },
+                Ok(SessionResponse::Error(message)) => ShellResponse::Error(format!("login: {}", message)),
+                _ => unexpected_response("login", "Session"),
+            },
+        }
+    }
+
+    /// Runs a command and records it in the history with what it cost.
+    /// `time <command...>` runs the command the same way and appends that
+    /// cost to its output; `time time ls` is a single `time ls`.
+    fn execute(&mut self, command: String, args: Vec<String>) -> ShellResponse {
+        let line = if args.is_empty() { command.clone() } else { format!("{} {}", command, args.join(" ")) };
+        self.command_history.push(HistoryEntry { line, cost: None });
+        let paused = Paused { entry: self.command_history.len() - 1, timed: command == "time" };
+
+        let mut words = core::iter::once(command).chain(args).skip_while(|word| paused.timed && word == "time");
+        let Some(command) = words.next() else {
+            self.command_history[paused.entry].cost = Some(Cost::default());
+            return usage("time <command> [args...]");
+        };
+        let args: Vec<String> = words.collect();
+        log(&alloc::format!("Shell: Executing command: {} with args: {:?}", command, args));
+
+        // A command that prompted and was never answered is abandoned; its cost stays as it was.
+        self.paused = None;
+        if let Some(remote) = self.remote.take() {
+            remote.close(&mut self.socket_chan);
+        }
+        self.bytes_processed = None;
+        let watch = Stopwatch::start();
+        let response = self.dispatch(command, args);
+        self.finish_command(paused, watch.stop(), response)
+    }
+
+    /// Adds `cost` to the command's history entry. A `Prompt` pauses it until
+    /// the answer; otherwise it is done, and a timed command gets its report.
+    fn finish_command(&mut self, paused: Paused, cost: Cost, response: ShellResponse) -> ShellResponse {
+        let total = self.command_history[paused.entry].cost.get_or_insert_with(Cost::default);
+        total.add(cost);
+        let total = *total;
+        if let ShellResponse::Prompt { .. } = response {
+            self.paused = Some(paused);
+            return response;
+        }
+        if !paused.timed {
+            return response;
+        }
+        let report = timing::report(&total, self.bytes_processed.take(), format_bytes);
+        match response {
+            ShellResponse::CommandOutput { stdout, mut stderr, exit_code } => {
+                stderr.push_str(&report);
+                ShellResponse::CommandOutput { stdout, stderr, exit_code }
+            },
+            ShellResponse::Success(message) => {
+                let stdout = if message.is_empty() { message } else { format!("{}\n", message) };
+                ShellResponse::CommandOutput { stdout, stderr: report, exit_code: 0 }
+            },
+            ShellResponse::Error(message) => {
+                let stderr = format!("{}\n{}", ansi::paint(&message, ansi::RED), report);
+                ShellResponse::CommandOutput { stdout: String::new(), stderr, exit_code: 1 }
+            },
+            response => response,
+        }
+    }
+
+    fn dispatch(&mut self, command: String, args: Vec<String>) -> ShellResponse {
+        // Conceptual: Implement built-in commands or forward to init-service
+        match command.as_str() {
+            "cd" => {
+                if let Some(path) = args.get(0) {
+                    return self.handle_change_directory(path.to_string());
+                } else {
+                    return ShellResponse::Error(tr!("shell.cd.missing_argument", "cd: missing argument"));
+                }
+            },
+            "ls" => {
+                // Conceptual: IPC to VFS to list directory
+                match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: self.current_dir.clone() }) {
+                    Ok(VfsResponse::DirectoryEntries(entries)) => {
+                        let mut output = String::new();
+                        for (name, metadata) in entries {
+                            if metadata.is_dir {
+                                output.push_str(&ansi::paint(&name, ansi::BLUE));
+                            } else {
+                                output.push_str(&name);
+                            }
+                            output.push_str("\n");
+                        }
+                        ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+                    },
+                    Ok(VfsResponse::Error { message, .. }) => ShellResponse::Error(format!("ls: {}", message)),
+                    _ => unexpected_response("ls", "VFS"),
+                }
+            },
+            "ping" => {
+                if let Some(hostname) = args.get(0) {
+                    match self.dns_chan.send_and_recv::<DnsRequest, DnsResponse>(&DnsRequest::ResolveHostname { hostname: hostname.clone() }) {
+                        Ok(DnsResponse::ResolvedHostname { ip_address, .. }) => {
+                            ShellResponse::CommandOutput { stdout: format!("Pinging {} ({}.{}.{}.{})", hostname, ip_address[0], ip_address[1], ip_address[2], ip_address[3]), stderr: String::new(), exit_code: 0 }
+                        },
+                        Ok(DnsResponse::NotFound { query }) => ShellResponse::Error(tr!("shell.ping.host_not_found", "ping: Host '{0}' not found.", query)),
+                        Ok(DnsResponse::Error { message }) => ShellResponse::Error(tr!("shell.ping.dns_error", "ping: DNS error: {0}", message)),
+                        _ => unexpected_response("ping", "DNS Resolver"),
+                    }
+                } else {
+                    ShellResponse::Error(tr!("shell.ping.missing_hostname", "ping: missing hostname"))
+                }
+            },
+            "start" => {
+                if let Some(group) = args.get(0).and_then(|arg| arg.strip_prefix('@')) {
+                    match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::GroupStart { name: group.to_string() }) {
+                        Ok(InitResponse::GroupStatus(info)) => {
+                            ShellResponse::CommandOutput { stdout: format_group(&info), stderr: String::new(), exit_code: 0 }
+                        },
+                        Ok(InitResponse::Error(msg)) => ShellResponse::Error(format!("start: {}", msg)),
+                        _ => unexpected_response("start", "Init Service"),
+                    }
+                } else if let Some(service_name) = args.get(0) {
+                    let request = InitRequest::ServiceStart { service_name: service_name.clone(), instance_label: args.get(1).cloned() };
+                    match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&request) {
+                        Ok(InitResponse::InstanceStarted { service_name, instance_id, channel }) => {
+                            ShellResponse::CommandOutput { stdout: format!("Started {} (instance {}, channel {}).\n", service_name, instance_id, channel), stderr: String::new(), exit_code: 0 }
+                        },
+                        Ok(InitResponse::Error(msg)) => ShellResponse::Error(format!("start: {}", msg)),
+                        _ => unexpected_response("start", "Init Service"),
+                    }
+                } else {
+                    ShellResponse::Error(tr!("shell.start.missing_service", "start: missing service name"))
+                }
+            },
+            "stop" => {
+                if let Some(target) = args.get(0) {
+                    // `@name` stops a group; a numeric argument names a single instance; anything
+                    // else stops every instance of that service.
+                    let request = match (target.strip_prefix('@'), target.parse::<u64>()) {
+                        (Some(group), _) => InitRequest::GroupStop { name: group.to_string() },
+                        (None, Ok(id)) => InitRequest::ServiceStop { target: ServiceTarget::Instance(id) },
+                        (None, Err(_)) => InitRequest::ServiceStop { target: ServiceTarget::AllInstances(target.clone()) },
+                    };
+                    match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&request) {
+                        Ok(InitResponse::Success(msg)) => ShellResponse::Success(msg),
+                        Ok(InitResponse::Error(msg)) => ShellResponse::Error(format!("stop: {}", msg)),
+                        _ => unexpected_response("stop", "Init Service"),
+                    }
+                } else {
+                    ShellResponse::Error(tr!("shell.stop.missing_target", "stop: missing instance id, service name or @group"))
+                }
+            }
+            "settings" => self.handle_settings_command(&args),
+            "shutdown" | "reboot" => self.handle_power_command(&command, &args),
+            "apkg" => self.handle_apkg_command(&args),
+            "latency" => self.handle_latency_command(),
+            "display" => self.handle_display_command(&args),
+            "hotkeys" => self.handle_hotkeys_command(&args),
+            "arp" => self.handle_arp_command(&args),
+            "dns" => self.handle_dns_command(&args),
+            "netstat" => self.handle_netstat_command(&args),
+            "rm" => self.handle_rm_command(&args),
+            "trash" => self.handle_trash_command(&args),
+            "grep" => self.handle_grep_command(&args),
+            "tcpdump" => self.handle_tcpdump_command(&args),
+            "ifup" => self.handle_ifstate_command("ifup", true),
+            "ifdown" => self.handle_ifstate_command("ifdown", false),
+            "netpolicy" => self.handle_netpolicy_command(&args),
+            "swarm" => self.handle_swarm_command(&args),
+            "date" => self.handle_date_command(&args),
+            "dbg" => self.handle_dbg_command(&args),
+            "ps" => self.handle_ps_command(&args),
+            "dmesg" => self.handle_dmesg_command(&args),
+            "logs" => self.handle_logs_command(&args),
+            "aethersh" => self.handle_aethersh_command(&args),
+            "du" => match self.fetch_usage("du", None) {
+                Ok(usage) => ShellResponse::CommandOutput {
+                    stdout: format!("{} in {} files\n", format_bytes(usage.used_bytes), usage.file_count),
+                    stderr: String::new(),
+                    exit_code: 0,
+                },
+                Err(e) => e,
+            },
+            "quota" => self.handle_quota_command(&args),
+            "cp" => self.handle_cp_command(&args),
+            "stat" => self.handle_stat_command(&args),
+            "history" => self.handle_history_command(&args),
+            // Add more built-in commands or forward to init-service for app execution
+            _ => ShellResponse::CommandOutput { stdout: format!("{}\n", tr!("shell.command_not_found", "Command '{0}' not found.", command)), stderr: String::new(), exit_code: 127 },
+        }
+    }
+
+    /// `settings [list]`, `settings get <key>`, `settings set <key> <value>`, `settings reset <key>`.
+    fn handle_settings_command(&mut self, args: &[String]) -> ShellResponse {
+        let request = match (args.get(0).map(|s| s.as_str()), args.get(1), args.get(2)) {
+            (None, _, _) | (Some("list"), _, _) => SettingsRequest::List,
+            (Some("get"), Some(key), _) => SettingsRequest::Get { key: key.clone() },
+            (Some("set"), Some(key), Some(value)) => SettingsRequest::Set { key: key.clone(), value: SettingValue::Str(value.clone()) },
+            (Some("reset"), Some(key), _) => SettingsRequest::ResetToDefault { key: key.clone() },
+            _ => return usage("settings [list | get <key> | set <key> <value> | reset <key>]"),
+        };
+
+        let format_value = |value: &SettingValue| match value {
+            SettingValue::Bool(b) => b.to_string(),
+            SettingValue::Int(n) => n.to_string(),
+            SettingValue::Str(s) => s.clone(),
+        };
+
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request) {
+            Ok(SettingsResponse::Settings(entries)) => {
+                let mut output = String::new();
+                for entry in entries {
+                    let flag = if entry.unknown { "  (unknown key)" } else { "" };
+                    output.push_str(&format!("{} = {}  [{}]{}\n", entry.key, format_value(&entry.value), entry.kind, flag));
+                }
+                ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+            },
+            Ok(SettingsResponse::Value { key, value }) => {
+                ShellResponse::CommandOutput { stdout: format!("{} = {}\n", key, format_value(&value)), stderr: String::new(), exit_code: 0 }
+            },
+            Ok(SettingsResponse::Success) => ShellResponse::Success("settings: updated".to_string()),
+            Ok(SettingsResponse::Error(msg)) => ShellResponse::Error(format!("settings: {}", msg)),
+            _ => unexpected_response("settings", "Settings Service"),
+        }
+    }
+
+    /// `apkg install <package>` or `apkg search [--local-only] <words...>`.
+    fn handle_apkg_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "apkg install <package> | apkg search [--local-only] <words...>";
+        match (args.get(0).map(|s| s.as_str()), args.get(1)) {
+            (Some("install"), Some(name)) => self.install_package(name),
+            (Some("search"), Some(_)) => {
+                let local_only = args[1] == "--local-only";
+                let words = &args[if local_only { 2 } else { 1 }..];
+                if words.is_empty() {
+                    return usage(USAGE);
+                }
+                self.search_packages(words.join(" "), local_only)
+            },
+            _ => usage(USAGE),
+        }
+    }
+
+    /// Installs `name`, warning first when no node is known to serve it. The
+    /// install goes ahead either way: the signals are only hints.
+    fn install_package(&mut self, name: &str) -> ShellResponse {
+        // One question at a time: an answer always goes to the install it was asked for.
+        if let Some(pending) = self.waiting_install() {
+            return ShellResponse::Error(format!("apkg: the install of {} is waiting for an answer; answer it first", pending.package_name));
+        }
+        let seeders = match self.registry_chan.send_and_recv::<RegistryRequest, RegistryResponse>(&RegistryRequest::PackageHealth { package_name: name.to_string() }) {
+            Ok(RegistryResponse::PackageHealth { health, .. }) => Some(health.seeders),
+            _ => None, // Install reports unknown packages itself
+        };
+        let response = self.send_registry_request(&RegistryRequest::Install { package_name: name.to_string() });
+        if seeders != Some(0) {
+            return response;
+        }
+        let warning = format!("apkg: warning: no known seeders for {}; the download may fail\n", name);
+        match response {
+            ShellResponse::Success(message) => ShellResponse::Success(format!("{}{}", warning, message)),
+            ShellResponse::Prompt { message } => ShellResponse::Prompt { message: format!("{}{}", warning, message) },
+            ShellResponse::Error(message) => ShellResponse::Error(format!("{}{}", warning, message)),
+            other => other,
+        }
+    }
+
+    fn search_packages(&mut self, query: String, local_only: bool) -> ShellResponse {
+        let request = RegistryRequest::Search { query, limit: 20, local_only };
+        let (results, peers_asked, peers_answered) = match self.registry_chan.send_and_recv::<RegistryRequest, RegistryResponse>(&request) {
+            Ok(RegistryResponse::SearchResults { results, peers_asked, peers_answered }) => (results, peers_asked, peers_answered),
+            Ok(RegistryResponse::Error(msg)) => return ShellResponse::Error(format!("apkg: {}", msg)),
+            _ => return unexpected_response("apkg", "Registry"),
+        };
+        let mut output = String::new();
+        for result in &results {
+            let location = match (result.local, result.peers.len()) {
+                (true, 0) => "local".to_string(),
+                (true, peers) => format!("local, {} peers", peers),
+                (false, peers) => format!("{} peers", peers),
+            };
+            output.push_str(&format!("{} {}  [{}]  {}\n    {}\n", result.name, result.version, location, format_health(&result.health), result.description));
+        }
+        if results.is_empty() {
+            output.push_str("No packages found.\n");
+        }
+        if peers_asked > 0 {
+            output.push_str(&format!("{} of {} peers answered.\n", peers_answered, peers_asked));
+        }
+        ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// Handles the reply to an install confirmation prompt: y(es), n(o) or
+    /// a(lways). When capabilities are under review, the numbers of the ones
+    /// to grant are an answer too, and deny the others.
+    fn handle_answer(&mut self, text: &str) -> ShellResponse {
+        if self.remote.is_some() {
+            return self.handle_remote_input(text);
+        }
+        let (ticket, grants) = match &self.pending_install {
+            Some(pending) => (pending.ticket, pending.grants),
+            None => return ShellResponse::Error("Nothing is waiting for an answer.".to_string()),
+        };
+        let answer = text.trim().to_ascii_lowercase();
+        let (decision, remember) = match answer.as_str() {
+            "y" | "yes" => (InstallDecision::Proceed, false),
+            "a" | "always" => (InstallDecision::Proceed, true),
+            "n" | "no" | "" => (InstallDecision::Cancel, false),
+            _ => {
+                let chosen: Option<Vec<usize>> = answer.split([' ', ',']).filter(|word| !word.is_empty())
+                    .map(|word| word.parse().ok().filter(|n| (1..=grants).contains(n)))
+                    .collect();
+                let Some(chosen) = chosen.filter(|_| grants > 0) else {
+                    let numbers = if grants > 0 { ", the numbers of the capabilities to grant" } else { "" };
+                    return ShellResponse::Prompt { message: format!("Please answer y (install once), a (always trust this publisher){} or n (cancel): ", numbers) };
+                };
+                let denied = (1..=grants).filter(|n| !chosen.contains(n)).map(|n| (n - 1) as u32).collect();
+                self.pending_install = None;
+                return self.send_registry_request(&RegistryRequest::ConfirmGrants { ticket, decision: InstallDecision::Proceed, remember: false, denied });
+            },
+        };
+        self.pending_install = None;
+        self.send_registry_request(&RegistryRequest::ConfirmInstall { ticket, decision, remember })
+    }
+
+    fn send_registry_request(&mut self, request: &RegistryRequest) -> ShellResponse {
+        match self.registry_chan.send_and_recv::<RegistryRequest, RegistryResponse>(request) {
+            Ok(RegistryResponse::Installed { package_name }) => ShellResponse::Success(format!("apkg: installed {}", package_name)),
+            Ok(RegistryResponse::ConfirmationRequired { ticket, package_name, fingerprint, .. }) => {
+                self.pending_install = Some(PendingInstall { ticket, grants: 0, package_name: package_name.clone(), asked_at: time::ticks() });
+                ShellResponse::Prompt {
+                    message: format!(
+                        "Package '{}' is signed by a publisher you don't trust yet.\n  Publisher fingerprint: {}\nInstall it? [y]es / [n]o / [a]lways trust this publisher: ",
+                        package_name, fingerprint
+                    ),
+                }
+            },
+            Ok(RegistryResponse::ReviewRequired { ticket, package_name, fingerprint, publisher_trusted, grants, syscalls, .. }) => {
+                self.pending_install = Some(PendingInstall { ticket, grants: grants.len(), package_name: package_name.clone(), asked_at: time::ticks() });
+                let mut message = format!("Package '{}' asks for:\n", package_name);
+                for (i, grant) in grants.iter().enumerate() {
+                    message.push_str(&format!("  {}. {}\n", i + 1, grant));
+                }
+                if !syscalls.is_empty() {
+                    message.push_str(&format!("  and the syscalls {}\n", syscalls.join(", ")));
+                }
+                if publisher_trusted {
+                    message.push_str("Install it? [y]es / [n]o / the numbers of the capabilities to grant: ");
+                } else {
+                    message.push_str(&format!("It is signed by a publisher you don't trust yet.\n  Publisher fingerprint: {}\n", fingerprint));
+                    message.push_str("Install it? [y]es / [n]o / [a]lways trust this publisher / the numbers of the capabilities to grant: ");
+                }
+                ShellResponse::Prompt { message }
+            },
+            Ok(RegistryResponse::Cancelled { package_name }) => ShellResponse::Success(format!("apkg: install of {} cancelled", package_name)),
+            Ok(RegistryResponse::InvalidTicket { .. }) => ShellResponse::Error("apkg: the confirmation expired; run the install again".to_string()),
+            Ok(RegistryResponse::Error(msg)) => ShellResponse::Error(format!("apkg: {}", msg)),
+            _ => unexpected_response("apkg", "Registry"),
+        }
+    }
+
+    /// `quota [aid hex]`: usage against the limit, for the caller or (system only) another identity.
+    fn handle_quota_command(&mut self, args: &[String]) -> ShellResponse {
+        let owner = match args.get(0) {
+            Some(hex) => match session_ipc::aid_from_hex(hex) {
+                Some(aid) => Some(aid),
+                None => return usage("quota [aid hex]"),
+            },
+            None => None,
+        };
+        let usage = match self.fetch_usage("quota", owner) {
+            Ok(usage) => usage,
+            Err(e) => return e,
+        };
+        let percent = if usage.limit_bytes == 0 { 100 } else { usage.used_bytes.saturating_mul(100) / usage.limit_bytes };
+        let stdout = format!("{}: {} of {} used ({}%), {} files\n",
+            session_ipc::aid_to_hex(&usage.owner), format_bytes(usage.used_bytes), format_bytes(usage.limit_bytes), percent, usage.file_count);
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    fn fetch_usage(&mut self, command: &str, owner: Option<session_ipc::AidBytes>) -> Result<VfsUsage, ShellResponse> {
+        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::GetUsage { owner }) {
+            Ok(VfsResponse::Usage(usage)) => Ok(usage),
+            Ok(VfsResponse::Unauthenticated) => Err(ShellResponse::Error(format!("{}: not logged in", command))),
+            Ok(VfsResponse::Error { message, .. }) => Err(ShellResponse::Error(format!("{}: {}", command, message))),
+            _ => Err(unexpected_response(command, "VFS")),
+        }
+    }
+
+    /// `tcpdump [-t <seconds>] [-s <snaplen>] [-w <path>] [--rx | --tx] [--ether <type>] [--proto <proto>] [--port <port>]`:
+    /// captures frames in the network stack for a while and writes them to a pcap file.
+    fn handle_tcpdump_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "tcpdump [-t <seconds>] [-s <snaplen>] [-w <path>] [--rx | --tx] [--ether <hex type>] [--proto tcp|udp|icmp|<n>] [--port <n>]";
+        const MAX_SECONDS: u64 = 300;
+        const RING_BYTES: u32 = 1024 * 1024;
+        let mut seconds = 10;
+        let mut snaplen = MAX_SNAPLEN;
+        let mut path = "capture.pcap".to_string();
+        let mut filter = CaptureFilter { direction: None, ethertype: None, ip_protocol: None, port: None };
+        let mut rest = args.iter().map(String::as_str);
+        while let Some(flag) = rest.next() {
+            let ok = match flag {
+                "--rx" => filter.direction.replace(CaptureDirection::Rx).is_none(),
+                "--tx" => filter.direction.replace(CaptureDirection::Tx).is_none(),
+                "-t" => rest.next().and_then(|v| v.parse().ok()).filter(|v| (1..=MAX_SECONDS).contains(v)).map(|v| seconds = v).is_some(),
+                "-s" => rest.next().and_then(|v| v.parse().ok()).filter(|v| (1..=MAX_SNAPLEN).contains(v)).map(|v| snaplen = v).is_some(),
+                "-w" => rest.next().map(|v| path = v.to_string()).is_some(),
+                "--ether" => rest.next().and_then(|v| u16::from_str_radix(v.trim_start_matches("0x"), 16).ok()).map(|v| filter.ethertype = Some(v)).is_some(),
+                "--proto" => rest.next().and_then(|v| match v {
+                    "icmp" => Some(1),
+                    "tcp" => Some(6),
+                    "udp" => Some(17),
+                    n => n.parse().ok(),
+                }).map(|v| filter.ip_protocol = Some(v)).is_some(),
+                "--port" => rest.next().and_then(|v| v.parse().ok()).map(|v| filter.port = Some(v)).is_some(),
+                _ => false,
+            };
+            if !ok {
+                return usage(USAGE);
+            }
+        }
+        let path = self.absolute_path(&path);
+        let filter = Some(filter).filter(|f| f.direction.is_some() || f.ethertype.is_some() || f.ip_protocol.is_some() || f.port.is_some());
+
+        let describe = |code: u32| match code {
+            105 => "permission denied",
+            116 => "a capture is already running",
+            117 => "bad snap length",
+            119 => "could not write the file",
+            _ => "request failed",
+        };
+        match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::CaptureStart { filter, snaplen, max_bytes: RING_BYTES }) {
+            Ok(NetStackResponse::Capture(_)) => {},
+            Ok(NetStackResponse::Error(code)) => return ShellResponse::Error(format!("tcpdump: {}", describe(code))),
+            _ => return unexpected_response("tcpdump", "the network stack"),
+        }
+        // Each SYS_TIME yields; a tick is 10 ms.
+        let until = unsafe { syscall3(SYS_TIME, 0, 0, 0) } + seconds * 100;
+        while unsafe { syscall3(SYS_TIME, 0, 0, 0) } < until {}
+
+        let stats = match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::CaptureStop) {
+            Ok(NetStackResponse::Capture(stats)) => stats,
+            _ => return unexpected_response("tcpdump", "the network stack"),
+        };
+        match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::CaptureDump { vfs_path: path.clone() }) {
+            Ok(NetStackResponse::Capture(_)) => ShellResponse::CommandOutput {
+                stdout: format!("{} frames written to {} ({} matched, {} dropped).\n", stats.packets, path, stats.matched, stats.dropped),
+                stderr: String::new(),
+                exit_code: 0,
+            },
+            Ok(NetStackResponse::Error(code)) => ShellResponse::Error(format!("tcpdump: {}: {}", path, describe(code))),
+            _ => unexpected_response("tcpdump", "the network stack"),
+        }
+    }
+
+    /// `swarm stats`: chunk traffic limits and rates, overall and per peer, and the known peers.
+    fn handle_swarm_command(&mut self, args: &[String]) -> ShellResponse {
+        if args.len() != 1 || args[0] != "stats" {
+            return usage("swarm stats");
+        }
+        let stats = match self.registry_chan.send_and_recv::<RegistryRequest, RegistryResponse>(&RegistryRequest::SwarmStats) {
+            Ok(RegistryResponse::SwarmStats(stats)) => stats,
+            _ => return unexpected_response("swarm", "Registry"),
+        };
+        let limit = |bytes: u64| if bytes == 0 { "unlimited".to_string() } else { format!("{}/s", format_bytes(bytes)) };
+        let mut output = format!("Upload:   {}/s (limit {})
+Download: {}/s (limit {})
+",
+            format_bytes(stats.upload_rate), limit(stats.upload_limit), format_bytes(stats.download_rate), limit(stats.download_limit));
+        output.push_str(&format!("Queued requests: {} ({} deferred, {} rejected busy since start)
+", stats.queued, stats.deferred_total, stats.rejected_total));
+        output.push_str(&format!("Known peers: {} restored, {} bootstrap, {} discovered ({} dead)
+", stats.restored_peers, stats.bootstrap_peers, stats.discovered_peers, stats.dead_peers));
+        if stats.peers.is_empty() {
+            output.push_str("No chunk traffic yet.
+");
+        } else {
+            output.push_str("Peer                  Served             Fetched            Up/s       Down/s
+");
+            for peer in stats.peers {
+                let addr = format!("{}.{}.{}.{}:{}", peer.addr[0], peer.addr[1], peer.addr[2], peer.addr[3], peer.port);
+                let served = format!("{} ({})", format_bytes(peer.bytes_served), peer.chunks_served);
+                let fetched = format!("{} ({})", format_bytes(peer.bytes_fetched), peer.chunks_fetched);
+                output.push_str(&format!("{:<21} {:<18} {:<18} {:<10} {}
+", addr, served, fetched, format_bytes(peer.serve_rate), format_bytes(peer.fetch_rate)));
+            }
+        }
+        ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `arp`, `arp -s <ip> <mac>`, `arp -d <ip>`, `arp flush [--force]`.
+    fn handle_arp_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "arp [-s <ip> <mac> | -d <ip> | flush [--force]]";
+        let request = match (args.get(0).map(|s| s.as_str()), args.get(1), args.get(2)) {
+            (None, _, _) => NetStackRequest::GetNeighbors,
+            (Some("-s"), Some(ip), Some(mac)) => match (parse_ipv4(ip), parse_mac(mac)) {
+                (Some(ip), Some(mac)) => NetStackRequest::AddStaticNeighbor { ip, mac },
+                _ => return usage(USAGE),
+            },
+            (Some("-d"), Some(ip), None) => match parse_ipv4(ip) {
+                Some(ip) => NetStackRequest::RemoveNeighbor { ip },
+                None => return usage(USAGE),
+            },
+            (Some("flush"), None, None) => NetStackRequest::FlushNeighbors { force: false },
+            (Some("flush"), Some(flag), None) if flag == "--force" => NetStackRequest::FlushNeighbors { force: true },
+            _ => return usage(USAGE),
+        };
+
+        match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&request) {
+            Ok(NetStackResponse::Neighbors(entries)) => {
+                let mut output = String::from("Address          HWaddress          State    Age\n");
+                for entry in entries {
+                    let ip = format!("{}.{}.{}.{}", entry.ip[0], entry.ip[1], entry.ip[2], entry.ip[3]);
+                    let mac = entry.mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":");
+                    let state = match entry.state {
+                        NeighborState::Dynamic => "dynamic",
+                        NeighborState::Static => "static",
+                    };
+                    output.push_str(&format!("{:<16} {:<18} {:<8} {}s\n", ip, mac, state, entry.age_ms / 1000));
+                }
+                ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+            },
+            Ok(NetStackResponse::Success) => ShellResponse::Success("arp: updated".to_string()),
+            Ok(NetStackResponse::Error(code)) => ShellResponse::Error(format!("arp: {}", match code {
+                105 => "permission denied",
+                106 => "that is this interface's own address",
+                107 => "not a unicast MAC address",
+                108 => "address is not on a local subnet",
+                109 => "no such entry",
+                _ => "request failed",
+            })),
+            _ => unexpected_response("arp", "the network stack"),
+        }
+    }
+
+    /// `dns flush`: drops every name the resolver has cached.
+    fn handle_dns_command(&mut self, args: &[String]) -> ShellResponse {
+        if !matches!(args, [sub] if sub == "flush") {
+            return usage("dns flush");
+        }
+        match self.dns_chan.send_and_recv::<DnsRequest, DnsResponse>(&DnsRequest::FlushCache) {
+            Ok(DnsResponse::CacheFlushed { entries }) => ShellResponse::Success(format!("dns: flushed {} cached names", entries)),
+            Ok(DnsResponse::Error { message }) => ShellResponse::Error(format!("dns: {}", message)),
+            _ => unexpected_response("dns", "DNS Resolver"),
+        }
+    }
+
+    /// `netstat` lists the connections the network stack tracks now;
+    /// `netstat --history [-n <max>]` the last ones that closed (20 by default).
+    fn handle_netstat_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "netstat [--history [-n <max>]]";
+        let (closed, max) = match args {
+            [] => (false, 0),
+            [flag] if flag == "--history" => (true, 20),
+            [flag, n, max] if flag == "--history" && n == "-n" => match max.parse::<u32>() {
+                Ok(max) if max > 0 && max as usize <= CONNECTION_HISTORY_LEN => (true, max),
+                _ => return usage(USAGE),
+            },
+            _ => return usage(USAGE),
+        };
+        let records = match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::GetConnectionHistory { max }) {
+            Ok(NetStackResponse::ConnectionHistory(connections)) if !connections.enabled => {
+                return ShellResponse::Error("netstat: connection tracking is turned off (net.connection_history)".to_string());
+            },
+            Ok(NetStackResponse::ConnectionHistory(connections)) => if closed { connections.closed } else { connections.open },
+            Ok(NetStackResponse::Error(105)) => return ShellResponse::Error("netstat: permission denied".to_string()),
+            Ok(NetStackResponse::Error(_)) => return ShellResponse::Error("netstat: request failed".to_string()),
+            _ => return unexpected_response("netstat", "the network stack"),
+        };
+        let mut stdout = format!("{:<5} {:<7} {:<21} {:<14} {:>9} {:>17} {:>17}\n", "PROTO", "LOCAL", "REMOTE", "STATE", "DURATION", "IN", "OUT");
+        for record in &records {
+            stdout.push_str(&format_connection(record));
+        }
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `path` made absolute against the current directory.
+    fn absolute_path(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.current_dir.trim_end_matches('/'), path)
+        }
+    }
+
+    fn file_manager_request(&mut self, command: &str, request: FileManagerRequest) -> Result<FileManagerResponse, ShellResponse> {
+        envelope::call(&mut self.file_manager_chan, envelope::next_request_id(), None, &request, || false)
+            .map_err(|_| no_response(command, "the File Manager"))
+    }
+
+    /// The arguments of an `ExecuteLine` with unquoted wildcards replaced by
+    /// the paths they match, in name order. A pattern that matches nothing
+    /// stays as typed, or is an error with `shell.failglob` on.
+    fn expand_globs(&mut self, command: &str, words: Vec<Word>) -> Result<Vec<String>, ShellResponse> {
+        let mut args = Vec::new();
+        let mut fail_on_no_match = None;
+        for word in words {
+            if word.quoted || !glob::has_wildcards(&word.text) {
+                args.push(word.text);
+                continue;
+            }
+            let matches = self.glob_paths(&word.text, MAX_GLOB_MATCHES - args.len().min(MAX_GLOB_MATCHES))
+                .ok_or_else(|| ShellResponse::Error(format!("{}: '{}' expands to more than {} arguments; narrow the pattern", command, word.text, MAX_GLOB_MATCHES)))?;
+            if !matches.is_empty() {
+                args.extend(matches);
+                continue;
+            }
+            if *fail_on_no_match.get_or_insert_with(|| self.failglob()) {
+                return Err(ShellResponse::Error(format!("{}: no match for '{}'", command, word.text)));
+            }
+            args.push(word.text);
+        }
+        Ok(args)
+    }
+
+    /// The paths `pattern` matches, written the way the pattern was (relative
+    /// stays relative). Wildcards may appear in any component; every component
+    /// but the last only matches directories. Names starting with a dot only
+    /// match a component that starts with one too. `None` if there are more
+    /// than `limit`.
+    fn glob_paths(&mut self, pattern: &str, limit: usize) -> Option<Vec<String>> {
+        let (mut prefixes, rest) = match pattern.strip_prefix('/') {
+            Some(rest) => (alloc::vec![String::from("/")], rest),
+            None => (alloc::vec![String::new()], pattern),
+        };
+        let components: Vec<&str> = rest.split('/').collect();
+        for (i, component) in components.iter().enumerate() {
+            let last = i + 1 == components.len();
+            if !glob::has_wildcards(component) {
+                for prefix in prefixes.iter_mut() {
+                    prefix.push_str(component);
+                    if !last {
+                        prefix.push('/');
+                    }
+                }
+                continue;
+            }
+            let mut matched = Vec::new();
+            for prefix in &prefixes {
+                let dir = completion::resolve_dir(&self.current_dir, prefix);
+                let entries = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: dir }) {
+                    Ok(VfsResponse::DirectoryEntries(entries)) => entries,
+                    _ => continue, // Not a directory, or gone: no matches below it
+                };
+                for (name, metadata) in entries {
+                    if name == "." || name == ".." || (name.starts_with('.') && !component.starts_with('.')) {
+                        continue;
+                    }
+                    if (!last && !metadata.is_dir) || !glob::glob_match(component, &name) {
+                        continue;
+                    }
+                    if matched.len() == limit {
+                        return None;
+                    }
+                    matched.push(if last { format!("{}{}", prefix, name) } else { format!("{}{}/", prefix, name) });
+                }
+            }
+            if matched.is_empty() {
+                return Some(Vec::new());
+            }
+            prefixes = matched;
+        }
+        Some(prefixes)
+    }
+
+    /// The `shell.failglob` setting; off if it can't be read.
+    fn failglob(&mut self) -> bool {
+        let request = SettingsRequest::Get { key: FAILGLOB_KEY.to_string() };
+        matches!(
+            self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request),
+            Ok(SettingsResponse::Value { value: SettingValue::Bool(true), .. })
+        )
+    }
+
+    /// `rm [--trash] <path>...`: deletes permanently, or moves to the trash with `--trash`.
+    fn handle_rm_command(&mut self, args: &[String]) -> ShellResponse {
+        let (permanent, paths) = match args {
+            [flag, paths @ ..] if flag == "--trash" => (false, paths),
+            paths => (true, paths),
+        };
+        if paths.is_empty() {
+            return usage("rm [--trash] <path>...");
+        }
+        let mut report = PathReport::new("rm");
+        for path in paths {
+            let request = FileManagerRequest::Delete { path: self.absolute_path(path), permanent };
+            report.file_manager(path, self.file_manager_request("rm", request));
+        }
+        report.finish()
+    }
+
+    /// `cp <source>... <destination>`: copies files through the File Manager.
+    /// When `destination` is a directory each source goes into it under its own
+    /// name; several sources need a directory.
+    fn handle_cp_command(&mut self, args: &[String]) -> ShellResponse {
+        let (destination, sources) = match args.split_last() {
+            Some((destination, sources)) if !sources.is_empty() => (destination, sources),
+            _ => return usage("cp <source>... <destination>"),
+        };
+        let target = self.absolute_path(destination);
+        let into_dir = matches!(
+            self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: target.clone() }),
+            Ok(VfsResponse::Metadata(metadata)) if metadata.is_dir
+        );
+        if sources.len() > 1 && !into_dir {
+            return ShellResponse::Error(format!("cp: {}: not a directory", destination));
+        }
+        let mut report = PathReport::new("cp");
+        for source in sources {
+            let destination = if into_dir {
+                let name = source.trim_end_matches('/').rsplit('/').next().unwrap_or(source);
+                format!("{}/{}", target.trim_end_matches('/'), name)
+            } else {
+                target.clone()
+            };
+            let request = FileManagerRequest::Copy { source: self.absolute_path(source), destination };
+            let response = self.file_manager_request("cp", request);
+            if let Ok(FileManagerResponse::Copied { bytes, .. }) = &response {
+                *self.bytes_processed.get_or_insert(0) += bytes;
+            }
+            report.file_manager(source, response);
+        }
+        report.finish()
+    }
+
+    /// `history [--times]`: the commands run so far, oldest first; with
+    /// `--times` also how long each took and how many IPC round trips it made.
+    fn handle_history_command(&mut self, args: &[String]) -> ShellResponse {
+        let times = match args {
+            [] => false,
+            [flag] if flag == "--times" => true,
+            _ => return usage("history [--times]"),
+        };
+        let mut stdout = String::new();
+        for (i, entry) in self.command_history.iter().enumerate() {
+            if !times {
+                stdout.push_str(&format!("{:>5}  {}\n", i + 1, entry.line));
+                continue;
+            }
+            // Only this `history` itself is still running.
+            let (elapsed, round_trips) = match entry.cost {
+                Some(cost) => (timing::format_duration(cost.nanos), cost.round_trips.to_string()),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            stdout.push_str(&format!("{:>5}  {:>10}  {:>4}  {}\n", i + 1, elapsed, round_trips, entry.line));
+        }
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `stat <path>...`: type, size, permissions and modification time of each path.
+    fn handle_stat_command(&mut self, args: &[String]) -> ShellResponse {
+        if args.is_empty() {
+            return usage("stat <path>...");
+        }
+        let offset = self.utc_offset_minutes();
+        let mut report = PathReport::new("stat");
+        for path in args {
+            let absolute = self.absolute_path(path);
+            match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: absolute }) {
+                Ok(VfsResponse::Metadata(metadata)) => report.ok(format!(
+                    "{}: {}, {}, mode {:o}, modified {}",
+                    path,
+                    if metadata.is_dir { "directory" } else { "file" },
+                    format_bytes(metadata.size),
+                    metadata.permissions,
+                    time::format_iso8601(metadata.modified, offset),
+                )),
+                Ok(VfsResponse::Error { message, .. }) => report.fail(path, &message),
+                _ => report.fail(path, "Unexpected response from VFS"),
+            }
+        }
+        report.finish()
+    }
+
+    /// `trash`, `trash restore <id>`, `trash empty [--older-than <days>]`.
+    fn handle_trash_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "trash [restore <id> | empty [--older-than <days>]]";
+        let request = match (args.get(0).map(|s| s.as_str()), args.get(1).map(|s| s.as_str()), args.get(2)) {
+            (None, _, _) => FileManagerRequest::ListTrash,
+            (Some("restore"), Some(id), None) => match id.parse() {
+                Ok(trash_id) => FileManagerRequest::Restore { trash_id },
+                Err(_) => return usage(USAGE),
+            },
+            (Some("empty"), None, None) => FileManagerRequest::EmptyTrash { older_than_days: None },
+            (Some("empty"), Some("--older-than"), Some(days)) => match days.parse() {
+                Ok(days) => FileManagerRequest::EmptyTrash { older_than_days: Some(days) },
+                Err(_) => return usage(USAGE),
+            },
+            _ => return usage(USAGE),
+        };
+        match self.file_manager_request("trash", request) {
+            Ok(FileManagerResponse::TrashEntries(entries)) => {
+                if entries.is_empty() {
+                    return ShellResponse::CommandOutput { stdout: "The trash is empty.\n".to_string(), stderr: String::new(), exit_code: 0 };
+                }
+                let offset = self.utc_offset_minutes();
+                let mut output = String::from("ID     Deleted                    Size        Original path\n");
+                for entry in entries {
+                    output.push_str(&format!("{:<6} {:<26} {:<11} {}\n", entry.id, time::format_iso8601(entry.deleted_at, offset), format_bytes(entry.size), entry.original_path));
+                }
+                ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+            },
+            Ok(FileManagerResponse::Success(msg)) => ShellResponse::Success(msg),
+            Ok(FileManagerResponse::Error(msg)) => ShellResponse::Error(format!("trash: {}", msg)),
+            Ok(_) => unexpected_response("trash", "the File Manager"),
+            Err(e) => e,
+        }
+    }
+
+    /// `grep -r [-i] [-m <max>] [--include=<glob>]... <pattern> <path>`: the
+    /// lines containing `pattern` in the files below `path`, searched by the
+    /// File Manager.
+    fn handle_grep_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "grep -r [-i] [-m <max>] [--include=<glob>]... <pattern> <path>";
+        let (mut recursive, mut case_insensitive, mut max_matches) = (false, false, 0);
+        let mut include_globs = Vec::new();
+        let mut operands = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-r" => recursive = true,
+                "-i" => case_insensitive = true,
+                "-ri" | "-ir" => {
+                    recursive = true;
+                    case_insensitive = true;
+                },
+                "-m" => match args.next().and_then(|max| max.parse().ok()) {
+                    Some(max) => max_matches = max,
+                    None => return usage(USAGE),
+                },
+                _ => match arg.strip_prefix("--include=") {
+                    Some(glob) => include_globs.push(glob.to_string()),
+                    None => operands.push(arg),
+                },
+            }
+        }
+        // Only the file search is built in; there are no pipelines to filter yet.
+        let (pattern, root) = match operands.as_slice() {
+            [pattern, path] if recursive => (pattern.to_string(), self.absolute_path(path)),
+            _ => return usage(USAGE),
+        };
+        let request_id = envelope::next_request_id();
+        let request = FileManagerRequest::SearchContent { root, pattern, case_insensitive, max_matches, include_globs };
+        let mut response = envelope::call(&mut self.file_manager_chan, request_id, None, &request, || false);
+        let mut output = String::new();
+        loop {
+            match response {
+                Ok(FileManagerResponse::ContentMatches { matches, done, truncated }) => {
+                    for found in matches {
+                        output.push_str(&format!("{}:{}:{}\n", found.path, found.line_number, found.excerpt));
+                    }
+                    if done {
+                        let stderr = if truncated { "grep: stopped at the match limit (-m)\n".to_string() } else { String::new() };
+                        let exit_code = if output.is_empty() { 1 } else { 0 };
+                        return ShellResponse::CommandOutput { stdout: output, stderr, exit_code };
+                    }
+                },
+                Ok(FileManagerResponse::Error(msg)) => return ShellResponse::Error(format!("grep: {}", msg)),
+                Ok(_) => return unexpected_response("grep", "the File Manager"),
+                Err(_) => return no_response("grep", "the File Manager"),
+            }
+            response = envelope::next_reply::<FileManagerRequest, FileManagerResponse>(&mut self.file_manager_chan, request_id, None, || false);
+        }
+    }
+
+    /// `ifup` / `ifdown`: bring the network interface up, or shut every socket
+    /// on it down and take it down.
+    fn handle_ifstate_command(&mut self, name: &str, up: bool) -> ShellResponse {
+        match self.net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::SetInterfaceState { up }) {
+            Ok(NetStackResponse::Success) => ShellResponse::Success(format!("{}: interface is {}", name, if up { "up" } else { "down" })),
+            Ok(NetStackResponse::Error(105)) => ShellResponse::Error(format!("{}: permission denied", name)),
+            Ok(NetStackResponse::Error(_)) => ShellResponse::Error(format!("{}: request failed", name)),
+            _ => unexpected_response(name, "the network stack"),
+        }
+    }
+
+    /// `netpolicy [service]`: the network policy socket-api enforces, for every
+    /// service or for the one given.
+    fn handle_netpolicy_command(&mut self, args: &[String]) -> ShellResponse {
+        let policies = match self.socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::GetPolicy) {
+            Ok(SocketResponse::Policy(policies)) => policies,
+            _ => return unexpected_response("netpolicy", "socket-api"),
+        };
+        let selected: Vec<&ServicePolicy> = match args.get(0) {
+            // A service without its own entry falls under `*`.
+            Some(service) => policies.iter().find(|p| &p.service == service)
+                .or_else(|| policies.iter().find(|p| p.service == "*"))
+                .into_iter().collect(),
+            None => policies.iter().collect(),
+        };
+        if selected.is_empty() {
+            let stdout = match args.get(0) {
+                Some(service) => format!("{}: unrestricted\n", service),
+                None => "No network policy set; all services are unrestricted.\n".to_string(),
+            };
+            return ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 };
+        }
+
+        let mut output = String::new();
+        for policy in selected {
+            let default = match policy.default {
+                PolicyAction::Allow => "allow",
+                PolicyAction::Deny => "deny",
+            };
+            output.push_str(&format!("{} (default {})\n", policy.service, default));
+            for (index, rule) in policy.rules.iter().enumerate() {
+                output.push_str(&format!("  {:>2}  {}\n", index + 1, rule));
+            }
+        }
+        ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `date [-u] [-R]`: the current time in ISO 8601, or RFC 2822 with `-R`.
+    /// Shown with the `time.utc_offset_minutes` offset unless `-u` asks for UTC.
+    fn handle_date_command(&mut self, args: &[String]) -> ShellResponse {
+        let (mut utc, mut rfc2822) = (false, false);
+        for arg in args {
+            match arg.as_str() {
+                "-u" => utc = true,
+                "-R" => rfc2822 = true,
+                _ => return usage("date [-u] [-R]"),
+            }
+        }
+        let clock = match time::now() {
+            Some(clock) => clock,
+            None => return ShellResponse::Error("date: The wall clock is not available".to_string()),
+        };
+        let offset = if utc { 0 } else { self.utc_offset_minutes() };
+        let stdout = if rfc2822 {
+            format!("{}\n", time::format_rfc2822(clock.secs, offset))
+        } else {
+            format!("{}\n", time::format_iso8601(clock.secs, offset))
+        };
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// The display offset from the `time.utc_offset_minutes` setting; 0 (UTC) if it can't be read.
+    fn utc_offset_minutes(&mut self) -> i32 {
+        let request = SettingsRequest::Get { key: "time.utc_offset_minutes".to_string() };
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request) {
+            Ok(SettingsResponse::Value { value: SettingValue::Int(minutes), .. }) => {
+                minutes.clamp(-(time::MAX_UTC_OFFSET_MINUTES as i64), time::MAX_UTC_OFFSET_MINUTES as i64) as i32
+            },
+            _ => 0,
+        }
+    }
+
+    /// `dbg suspend|resume|regs|bt|dump <task>` and `dbg mem <task> <addr> [len]`.
+    /// Needs CAP_DEBUG, which the shell only has in debug builds.
+    fn handle_dbg_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "dbg suspend|resume|regs|bt|dump <task> | dbg mem <task> <addr> [len]";
+        let (action, task) = match (args.get(0), args.get(1).and_then(|t| t.parse::<u64>().ok())) {
+            (Some(action), Some(task)) => (action.as_str(), task),
+            _ => return usage(USAGE),
+        };
+        let output = match action {
+            "suspend" => debug::suspend(task).map(|()| format!("Task {} suspended.\n", task)),
+            "resume" => debug::resume(task).map(|()| format!("Task {} resumed.\n", task)),
+            "regs" => debug::registers(task).map(|regs| format_registers(&regs)),
+            "dump" => debug::dump(task).map(|()| format!("Core dump of task {} queued; init writes it to /data/crash.\n", task)),
+            "bt" => debug::backtrace(task).map(|frames| {
+                frames.iter().enumerate().map(|(i, addr)| format!("#{:<2} {:#018x}\n", i, addr)).collect()
+            }),
+            "mem" => {
+                let addr = match args.get(2).and_then(|a| parse_number(a)) {
+                    Some(addr) => addr,
+                    None => return usage(USAGE),
+                };
+                let len = match args.get(3).map(|l| parse_number(l)) {
+                    None => 64,
+                    Some(Some(len)) if len <= 4096 => len as usize,
+                    Some(_) => return ShellResponse::Error("dbg: len must be at most 4096".to_string()),
+                };
+                debug::read_memory(task, addr, len).map(|access| {
+                    let mut dump = hex_dump(addr, &access.value);
+                    if access.truncated {
+                        dump.push_str(&format!("(unreadable from {:#x})\n", addr + access.value.len() as u64));
+                    }
+                    dump
+                })
+            },
+            _ => return usage(USAGE),
+        };
+        match output {
+            Ok(stdout) => ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 },
+            Err(E_ACC_DENIED) => ShellResponse::Error("dbg: permission denied (needs CAP_DEBUG; the kernel task can't be debugged)".to_string()),
+            Err(E_BUSY) => ShellResponse::Error(format!("dbg: task {} is running; suspend it first", task)),
+            Err(E_INVALID_ARG) => ShellResponse::Error(format!("dbg: no debuggable task {}", task)),
+            Err(code) => ShellResponse::Error(format!("dbg: failed ({:#x})", code)),
+        }
+    }
+
+    /// `ps`: every task with its state, the CPU it last ran on, the faults it took and the application group it belongs to.
+    fn handle_ps_command(&mut self, args: &[String]) -> ShellResponse {
+        if !args.is_empty() {
+            return usage("ps");
+        }
+        // Group members by instance (task) ID; without init the column is all "-".
+        let mut groups: BTreeMap<u64, String> = BTreeMap::new();
+        if let Ok(InitResponse::Groups(infos)) = self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ListGroups) {
+            for info in infos {
+                for member in &info.members {
+                    if let Some(id) = member.instance_id {
+                        groups.insert(id, info.name.clone());
+                    }
+                }
+            }
+        }
+        let mut stdout = format!("{:>6} {:>3} {:<9} {:>8} {:>6} {:>8} {:<12} {}\n", "ID", "CPU", "STATE", "LOGS", "FILTER", "FAULTS", "GROUP", "NAME");
+        // A task that exits between the list and its stats is simply left out.
+        for stats in tasks::list().into_iter().filter_map(tasks::stats) {
+            stdout.push_str(&format_task(&stats, groups.get(&stats.id).map_or("-", String::as_str)));
+        }
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `shutdown [--force]` and `reboot [--force]`: init stops the services,
+    /// syncs the VFS and powers off or resets. `--force` doesn't wait for
+    /// services that don't answer at once.
+    fn handle_power_command(&mut self, command: &str, args: &[String]) -> ShellResponse {
+        let force = match args {
+            [] => false,
+            [flag] if flag == "--force" => true,
+            _ => return usage(&format!("{} [--force]", command)),
+        };
+        let request = InitRequest::SystemShutdown { reboot: command == "reboot", force };
+        match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&request) {
+            Ok(InitResponse::Success(msg)) => ShellResponse::Success(msg),
+            Ok(InitResponse::Error(msg)) => ShellResponse::Error(format!("{}: {}", command, msg)),
+            _ => unexpected_response(command, "Init Service"),
+        }
+    }
+
+    /// `dmesg [--last-boot]`: the kernel log of this boot, or the one the previous boot left behind.
+    fn handle_dmesg_command(&mut self, args: &[String]) -> ShellResponse {
+        let result = match args {
+            [] => klog::read(),
+            [flag] if flag == "--last-boot" => klog::last_boot().map(|last| {
+                let how = match last.info.reason {
+                    LAST_BOOT_PANIC => "panicked",
+                    LAST_BOOT_SHUTDOWN => "shut down",
+                    _ => "last checkpoint",
+                };
+                format!("-- boot {} ({}) --\n{}", last.info.seq, how, last.text)
+            }),
+            _ => return usage("dmesg [--last-boot]"),
+        };
+        match result {
+            Ok(mut stdout) => {
+                if !stdout.is_empty() && !stdout.ends_with('\n') {
+                    stdout.push('\n');
+                }
+                ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+            },
+            Err(KlogError::NoLastBoot) => ShellResponse::Error("dmesg: no log from the previous boot".to_string()),
+            Err(KlogError::Denied) => ShellResponse::Error("dmesg: permission denied (needs CAP_LOG_READ)".to_string()),
+        }
+    }
+
+    /// `logs <service> [-n <lines>] [--follow [-t <seconds>]]`: the last lines
+    /// logd wrote for a service and, with `--follow`, what it writes in the
+    /// next few seconds. The shell answers once per command, so following
+    /// ends after `-t` seconds like `tcpdump`.
+    fn handle_logs_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "logs <service> [-n <lines>] [--follow [-t <seconds>]]";
+        const MAX_SECONDS: u64 = 300;
+        // Only this much of the end of the file is read for the last lines.
+        const TAIL_BYTES: u64 = 64 * 1024;
+        let (mut lines, mut follow, mut seconds) = (20usize, false, 10u64);
+        let mut timed = false;
+        let mut service = None;
+        let mut rest = args.iter().map(String::as_str);
+        while let Some(arg) = rest.next() {
+            let ok = match arg {
+                "-n" => rest.next().and_then(|v| v.parse().ok()).map(|v| lines = v).is_some(),
+                "--follow" | "-f" => !core::mem::replace(&mut follow, true),
+                "-t" => rest.next().and_then(|v| v.parse().ok()).filter(|v| (1..=MAX_SECONDS).contains(v)).map(|v| { seconds = v; timed = true; }).is_some(),
+                name if !name.starts_with('-') => service.replace(name).is_none(),
+                _ => false,
+            };
+            if !ok {
+                return usage(USAGE);
+            }
+        }
+        let service = match service {
+            Some(service) if follow || !timed => service,
+            _ => return usage(USAGE),
+        };
+        let path = log_ipc::log_file_path(&log_ipc::sanitize_service_name(service, 0));
+
+        let size = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Stat { path: path.clone() }) {
+            Ok(VfsResponse::Metadata(metadata)) => metadata.size,
+            Ok(VfsResponse::Error { code: 2, .. }) if !follow => return ShellResponse::Error(format!("logs: nothing logged for {} yet", service)),
+            Ok(VfsResponse::Error { code: 2, .. }) => 0, // ENOENT: follow it from its first line
+            Ok(VfsResponse::Error { message, .. }) => return ShellResponse::Error(format!("logs: {}: {}", path, message)),
+            _ => return unexpected_response("logs", "VFS"),
+        };
+        let start = size.saturating_sub(TAIL_BYTES);
+        // Opening creates a missing file, so an empty one isn't opened at all.
+        let tail = match size {
+            0 => String::new(),
+            _ => match self.read_range(&path, start, size) {
+                Ok(tail) => tail,
+                Err(e) => return ShellResponse::Error(format!("logs: {}: {}", path, e)),
+            },
+        };
+        // A read that starts mid-file starts mid-line; that line is left out.
+        let whole = if start > 0 { tail.split_once('\n').map_or("", |(_, rest)| rest) } else { tail.as_str() };
+        let all: Vec<&str> = whole.lines().collect();
+        let mut stdout = String::new();
+        for line in &all[all.len().saturating_sub(lines)..] {
+            stdout.push_str(line);
+            stdout.push('\n');
+        }
+        if !follow {
+            return ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 };
+        }
+        let Some(watch_chan_id) = self.log_watch_chan.as_ref().map(|chan| chan.id) else {
+            return ShellResponse::Error("logs: --follow is only available in the console shell".to_string());
+        };
+
+        let watch = VfsRequest::Watch { path: path.clone(), reply_chan: watch_chan_id };
+        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&watch) {
+            Ok(VfsResponse::Success(_)) => {},
+            Ok(VfsResponse::Error { message, .. }) => return ShellResponse::Error(format!("logs: cannot follow {}: {}", path, message)),
+            _ => return unexpected_response("logs", "VFS"),
+        }
+        let mut stderr = String::new();
+        let mut offset = size;
+        // Each SYS_TIME yields; a tick is 10 ms.
+        let until = unsafe { syscall3(SYS_TIME, 0, 0, 0) } + seconds * 100;
+        while unsafe { syscall3(SYS_TIME, 0, 0, 0) } < until {
+            while let Some(Ok(Some(data))) = self.log_watch_chan.as_mut().map(|chan| chan.recv_non_blocking()) {
+                let size = match postcard::from_bytes::<VfsResponse>(&data) {
+                    Ok(VfsResponse::Changed { path: changed, size }) if changed == path => size,
+                    _ => continue,
+                };
+                // A smaller file is a new one: logd rotated the log.
+                if size < offset {
+                    offset = 0;
+                }
+                if size > offset {
+                    match self.read_range(&path, offset, size) {
+                        Ok(appended) => stdout.push_str(&appended),
+                        Err(e) => stderr.push_str(&format!("logs: {}: {}\n", path, e)),
+                    }
+                    offset = size;
+                }
+            }
+        }
+        let unwatch = VfsRequest::Unwatch { path, reply_chan: watch_chan_id };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&unwatch);
+        // Notices sent before the watch ended aren't wanted by the next --follow.
+        while let Some(Ok(Some(_))) = self.log_watch_chan.as_mut().map(|chan| chan.recv_non_blocking()) {}
+        ShellResponse::CommandOutput { stdout, stderr, exit_code: 0 }
+    }
+
+    /// Bytes `start..end` of the file at `path`, as text.
+    fn read_range(&mut self, path: &str, start: u64, end: u64) -> Result<String, String> {
+        let fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("unexpected response from VFS".to_string()),
+        };
+        let read = VfsRequest::Read { fd, len: end.saturating_sub(start) as u32, offset: start };
+        let result = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&read) {
+            Ok(VfsResponse::Data(data)) => Ok(String::from_utf8_lossy(&data).into_owned()),
+            Ok(VfsResponse::Error { message, .. }) => Err(message),
+            _ => Err("unexpected response from VFS".to_string()),
+        };
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Close { fd });
+        result
+    }
+
+    /// `aethersh [-i <identity file>] [-p <port>] <host> [command...]`: runs
+    /// `command` on `host`'s aethersh-server and prints its output, or with
+    /// no command opens a session whose prompts take the following lines
+    /// until `exit`. Authenticates with the key in `identity_path` of the
+    /// Aid this shell is logged in as, unless `-i` names another file.
+    fn handle_aethersh_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "aethersh [-i <identity file>] [-p <port>] <host> [command...]";
+        let (mut identity_file, mut port) = (None, None);
+        let mut rest = args.iter();
+        let host = loop {
+            let ok = match rest.next().map(String::as_str) {
+                Some("-i") => rest.next().map(|v| identity_file = Some(self.absolute_path(v))).is_some(),
+                Some("-p") => rest.next().and_then(|v| v.parse().ok()).filter(|v| *v != 0).map(|v| port = Some(v)).is_some(),
+                Some(host) if !host.starts_with('-') => break host,
+                _ => false,
+            };
+            if !ok {
+                return usage(USAGE);
+            }
+        };
+        let command: Vec<&str> = rest.map(String::as_str).collect();
+
+        let Some(path) = identity_file.or_else(|| self.identity.as_ref().map(aethersh_ipc::identity_path)) else {
+            return ShellResponse::Error("aethersh: log in first, or name an identity file with -i".to_string());
+        };
+        let identity = match LocalIdentity::load(&mut self.vfs_chan, &path, None) {
+            Ok(identity) => identity,
+            Err(e) => return ShellResponse::Error(format!("aethersh: {}: {}", path, e)),
+        };
+        let port = port.unwrap_or_else(|| {
+            let request = SettingsRequest::Get { key: aethersh_ipc::PORT_KEY.to_string() };
+            match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request) {
+                Ok(SettingsResponse::Value { value: SettingValue::Int(port), .. }) => u16::try_from(port).unwrap_or(aethersh_ipc::DEFAULT_PORT),
+                _ => aethersh_ipc::DEFAULT_PORT,
+            }
+        });
+        let (mut remote, cwd) = match RemoteShell::open(&mut self.socket_chan, host, port, &identity) {
+            Ok(session) => session,
+            Err(e) => return ShellResponse::Error(format!("aethersh: {}: {}", host, e)),
+        };
+
+        if command.is_empty() {
+            let message = format!("Connected to {} as {}, in {}. Type exit to leave.\n{}$ ", remote.host, session_ipc::aid_to_hex(&identity.aid().0), cwd, remote.host);
+            self.remote = Some(remote);
+            return ShellResponse::Prompt { message };
+        }
+        let reply = remote.send(&mut self.socket_chan, &ClientFrame::Line { line: command.join(" ") })
+            .and_then(|()| remote.wait(&mut self.socket_chan, aethersh_ipc::REPLY_TIMEOUT_SECS));
+        remote.close(&mut self.socket_chan);
+        match reply {
+            Ok(ServerFrame::Output { stdout, stderr, exit_code }) => ShellResponse::CommandOutput { stdout, stderr, exit_code },
+            Ok(ServerFrame::Prompt { .. }) => ShellResponse::Error(format!("aethersh: '{}' asks a question; run it in a session (aethersh {})", command.join(" "), host)),
+            Ok(ServerFrame::Closed { reason }) => ShellResponse::Error(format!("aethersh: {} closed the session: {}", host, reason)),
+            Ok(_) => ShellResponse::Error(format!("aethersh: unexpected frame from {}", host)),
+            Err(e) => ShellResponse::Error(format!("aethersh: {}: {}", host, e)),
+        }
+    }
+
+    /// A line typed at an `aethersh` session's prompt: the answer to the
+    /// remote command's question if it asked one, `exit`, or a command.
+    /// Output comes back in front of the next prompt.
+    fn handle_remote_input(&mut self, text: &str) -> ShellResponse {
+        let Some(mut remote) = self.remote.take() else {
+            return ShellResponse::Error("Nothing is waiting for an answer.".to_string());
+        };
+        let frame = if remote.answering {
+            ClientFrame::Answer { text: text.to_string() }
+        } else {
+            match text.trim() {
+                "" => {
+                    let message = format!("{}$ ", remote.host);
+                    self.remote = Some(remote);
+                    return ShellResponse::Prompt { message };
+                },
+                "exit" | "logout" => {
+                    let host = remote.host.clone();
+                    remote.close(&mut self.socket_chan);
+                    return ShellResponse::Success(format!("Connection to {} closed.", host));
+                },
+                line => ClientFrame::Line { line: line.to_string() },
+            }
+        };
+        let reply = remote.send(&mut self.socket_chan, &frame)
+            .and_then(|()| remote.wait(&mut self.socket_chan, aethersh_ipc::REPLY_TIMEOUT_SECS));
+        let message = match reply {
+            Ok(ServerFrame::Output { stdout, stderr, .. }) => {
+                remote.answering = false;
+                Ok(format!("{}{}{}$ ", stdout, stderr, remote.host))
+            },
+            Ok(ServerFrame::Prompt { message }) => {
+                remote.answering = true;
+                Ok(message)
+            },
+            Ok(ServerFrame::Closed { reason }) => Err(format!("{} closed the session: {}", remote.host, reason)),
+            Ok(_) => Err(format!("unexpected frame from {}", remote.host)),
+            Err(e) => Err(format!("{}: {}", remote.host, e)),
+        };
+        match message {
+            Ok(message) => {
+                self.remote = Some(remote);
+                ShellResponse::Prompt { message }
+            },
+            Err(e) => {
+                remote.close(&mut self.socket_chan);
+                ShellResponse::Error(format!("aethersh: {}", e))
+            },
+        }
+    }
+
+    /// `latency`: input latency per pipeline stage, overall and per window.
+    fn handle_latency_command(&mut self) -> ShellResponse {
+        let stats = match self.compositor_chan.send_and_recv::<UiRequest, UiResponse>(&UiRequest::GetStats) {
+            Ok(UiResponse::Stats(stats)) => stats,
+            Ok(UiResponse::Error { message }) => return ShellResponse::Error(format!("latency: {}", message)),
+            _ => return unexpected_response("latency", "Display Compositor"),
+        };
+
+        let mut output = String::from("All windows:\n");
+        format_latency(&mut output, &stats.latency);
+        for window in &stats.windows {
+            output.push_str(&format!("Window {} '{}':\n", window.window_id, window.title));
+            format_latency(&mut output, &window.latency);
+        }
+        ShellResponse::CommandOutput { stdout: output, stderr: String::new(), exit_code: 0 }
+    }
+
+    /// `display`: the display configuration. Changes go to the compositor
+    /// first, so a bad wallpaper or an unsupported mode is reported here, and
+    /// are then saved as settings. The compositor follows those settings, but
+    /// they match what it already shows, so nothing is redrawn twice.
+    fn handle_display_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "display [mode <width>x<height> | background <#rrggbb | path> [center|fit|fill|stretch] | scale <1|1.5|2>]";
+        let request = match (args.get(0).map(|s| s.as_str()), args.get(1), args.get(2).map(|s| s.as_str())) {
+            (None, _, _) => UiRequest::GetDisplayInfo,
+            (Some("mode"), Some(mode), None) => match mode.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?))) {
+                Some((width, height)) => UiRequest::SetDisplayMode { width, height },
+                None => return usage(USAGE),
+            },
+            (Some("background"), Some(value), fit) => {
+                let fit = match fit.map(|name| WALLPAPER_FITS.into_iter().find(|fit| fit_name(*fit) == name)) {
+                    None => WallpaperFit::Fill,
+                    Some(Some(fit)) => fit,
+                    Some(None) => return usage(USAGE),
+                };
+                let value = if value.starts_with('#') { value.clone() } else { self.absolute_path(value) };
+                UiRequest::SetBackground { color_or_image_path: value, fit }
+            },
+            // The scale is a setting only; every scaled UI follows it.
+            (Some("scale"), Some(scale), None) if ["1", "1.5", "2"].contains(&scale.as_str()) => {
+                return match self.save_settings("display", &[("ui.scale", scale.clone())]) {
+                    Ok(()) => ShellResponse::Success(format!("display: UI scale is now {}x", scale)),
+                    Err(e) => e,
+                };
+            },
+            _ => return usage(USAGE),
+        };
+
+        let settings = match &request {
+            UiRequest::SetDisplayMode { width, height } => alloc::vec![("compositor.display_mode", format!("{}x{}", width, height))],
+            UiRequest::SetBackground { color_or_image_path, .. } if color_or_image_path.starts_with('#') => {
+                alloc::vec![("compositor.background_color", color_or_image_path.clone()), ("compositor.wallpaper", String::new())]
+            },
+            UiRequest::SetBackground { color_or_image_path, fit } => {
+                alloc::vec![("compositor.wallpaper_fit", fit_name(*fit).to_string()), ("compositor.wallpaper", color_or_image_path.clone())]
+            },
+            _ => Vec::new(),
+        };
+        let info = match self.compositor_chan.send_and_recv::<UiRequest, UiResponse>(&request) {
+            Ok(UiResponse::DisplayInfo(info)) => Some(info),
+            Ok(UiResponse::Success { .. }) => None,
+            Ok(UiResponse::NotSupported { message }) => return ShellResponse::Error(format!("display: {}", message)),
+            Ok(UiResponse::Error { message }) => return ShellResponse::Error(format!("display: {}", message)),
+            _ => return unexpected_response("display", "Display Compositor"),
+        };
+        if let Err(e) = self.save_settings("display", &settings) {
+            return e;
+        }
+        let Some(info) = info else {
+            return ShellResponse::Success("display: background updated".to_string());
+        };
+
+        let mode = if info.mode_switching {
+            let modes: Vec<String> = info.modes.iter().map(|m| format!("{}x{}", m.width, m.height)).collect();
+            format!("{}x{} (available: {})", info.width, info.height, modes.join(", "))
+        } else {
+            format!("{}x{} (set by the bootloader, can't be changed)", info.width, info.height)
+        };
+        let wallpaper = if info.wallpaper.is_empty() {
+            "none".to_string()
+        } else {
+            format!("{} ({})", info.wallpaper, fit_name(info.fit))
+        };
+        let stdout = format!(
+            "Resolution: {}\nBackground: {}\nWallpaper:  {}\nScale:      {}%\n",
+            mode, info.background_color, wallpaper, info.scale_percent,
+        );
+        ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 }
+    }
+
+    fn handle_hotkeys_command(&mut self, args: &[String]) -> ShellResponse {
+        const USAGE: &str = "hotkeys [switch-window | screenshot <combination>]";
+        let hotkeys = match self.compositor_chan.send_and_recv::<UiRequest, UiResponse>(&UiRequest::ListHotkeys) {
+            Ok(UiResponse::Hotkeys(hotkeys)) => hotkeys,
+            Ok(UiResponse::Error { message }) => return ShellResponse::Error(format!("hotkeys: {}", message)),
+            _ => return unexpected_response("hotkeys", "Display Compositor"),
+        };
+
+        let (action, setting) = match (args.get(0).map(|s| s.as_str()), args.len()) {
+            (None, _) => {
+                let mut stdout = String::new();
+                for hotkey in &hotkeys {
+                    let action = match hotkey.action {
+                        HotkeyAction::SwitchWindow => "switch-window".to_string(),
+                        HotkeyAction::Screenshot => "screenshot".to_string(),
+                        HotkeyAction::Client { tag } => format!("tag {}", tag),
+                    };
+                    stdout.push_str(&format!("{:<20} {:<16} {}\n", keys::combo_name(hotkey.modifiers, hotkey.keycode), action, hotkey.owner));
+                }
+                return ShellResponse::CommandOutput { stdout, stderr: String::new(), exit_code: 0 };
+            },
+            (Some("switch-window"), 2) => (HotkeyAction::SwitchWindow, "compositor.hotkey.switch_window"),
+            (Some("screenshot"), 2) => (HotkeyAction::Screenshot, "compositor.hotkey.screenshot"),
+            _ => return usage(USAGE),
+        };
+
+        // The compositor only logs a setting it can't apply, so catch the
+        // usual mistakes here where they can be reported.
+        let Some((modifiers, keycode)) = keys::parse_combo(&args[1]) else {
+            return ShellResponse::Error(format!("hotkeys: {}: not a key combination, e.g. Ctrl+Alt+T", args[1]));
+        };
+        let name = keys::combo_name(modifiers, keycode);
+        if let Some(holder) = hotkeys.iter().find(|h| h.modifiers == modifiers && h.keycode == keycode && h.action != action) {
+            return ShellResponse::Error(format!("hotkeys: {} is taken by {}", name, holder.owner));
+        }
+        match self.save_settings("hotkeys", &[(setting, name.clone())]) {
+            Ok(()) => ShellResponse::Success(format!("hotkeys: {} is now {}", args[0], name)),
+            Err(e) => e,
+        }
+    }
+
+    /// Saves `settings` in order, stopping at the first the settings service refuses.
+    fn save_settings(&mut self, command: &str, settings: &[(&str, String)]) -> Result<(), ShellResponse> {
+        for (key, value) in settings {
+            let request = SettingsRequest::Set { key: key.to_string(), value: SettingValue::Str(value.clone()) };
+            match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request) {
+                Ok(SettingsResponse::Success) => {},
+                Ok(SettingsResponse::Error(msg)) => return Err(ShellResponse::Error(format!("{}: {}: {}", command, key, msg))),
+                _ => return Err(unexpected_response(command, "Settings Service")),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_complete(&mut self, line: &str, cursor_pos: usize) -> ShellResponse {
+        let ctx = completion::parse_line(line, cursor_pos);
+
+        let is_service_arg = ctx.word_index == 1
+            && ctx.command.as_deref().map_or(false, |cmd| SERVICE_COMMANDS.contains(&cmd));
+
+        // The word after `time` is a command too.
+        let is_command = ctx.word_index == 0 || (ctx.word_index == 1 && ctx.command.as_deref() == Some("time"));
+        let candidates = if is_command {
+            completion::filter_by_prefix(BUILTIN_COMMANDS.iter().copied(), &ctx.prefix)
+        } else if is_service_arg {
+            self.complete_service_name(&ctx)
+        } else {
+            self.complete_path(&ctx)
+        };
+
+        let common = completion::common_prefix(&candidates);
+        // A unique match that is not a directory is final: close any open quote and add a space.
+        let is_final = candidates.len() == 1 && !common.ends_with('/');
+        let common_prefix = if candidates.is_empty() {
+            String::new()
+        } else {
+            completion::encode_word(&ctx, &common, is_final)
+        };
+
+        log(&alloc::format!("Shell: Completion for '{}' yielded {} candidates.", ctx.prefix, candidates.len()));
+        ShellResponse::Completions { word_start: ctx.start as u32, common_prefix, candidates }
+    }
+
+    fn complete_service_name(&mut self, ctx: &WordContext) -> Vec<String> {
+        if let Some(prefix) = ctx.prefix.strip_prefix('@') {
+            return match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ListGroups) {
+                Ok(InitResponse::Groups(groups)) => completion::filter_by_prefix(groups.iter().map(|group| group.name.as_str()), prefix)
+                    .into_iter()
+                    .map(|name| format!("@{}", name))
+                    .collect(),
+                _ => Vec::new(),
+            };
+        }
+        match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ListServices) {
+            Ok(InitResponse::ServiceList(names)) => {
+                completion::filter_by_prefix(names.iter().map(|n| n.as_str()), &ctx.prefix)
+            },
+            _ => {
+                log("Shell: Failed to list services from Init Service for completion.");
+                Vec::new()
+            }
+        }
+    }
+
+    fn complete_path(&mut self, ctx: &WordContext) -> Vec<String> {
+        let (dir_part, name_prefix) = completion::split_path_prefix(&ctx.prefix);
+        let dir = completion::resolve_dir(&self.current_dir, dir_part);
+
+        match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::List { path: dir }) {
+            Ok(VfsResponse::DirectoryEntries(entries)) => {
+                completion::filter_by_prefix(entries.keys().map(|n| n.as_str()), name_prefix)
+                    .into_iter()
+                    .map(|name| {
+                        let is_dir = entries.get(&name).map_or(false, |meta| meta.is_dir);
+                        let mut candidate = format!("{}{}", dir_part, name);
+                        if is_dir {
+                            candidate.push('/');
+                        }
+                        candidate
+                    })
+                    .collect()
+            },
+            _ => Vec::new(),
         }
     }
 
@@ -163,25 +1735,308 @@ impl ShellService {
             if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
                 if let Ok(request) = postcard::from_bytes::<ShellRequest>(&req_data) {
                     log(&alloc::format!("Shell Service: Received ShellRequest: {:?}", request));
-                    let response = self.handle_request(request);
+                    let response = match self.handle_request(request) {
+                        ShellResponse::Error(message) => ShellResponse::Error(ansi::paint(&message, ansi::RED)),
+                        response => response,
+                    };
                     self.client_chan.send(&response).unwrap_or_else(|_| log("Shell Service: Failed to send response to client."));
                 }
             }
 
+            // A locale change reloads the catalog; the next message is in the new language.
+            while let Some(Ok(Some(event_data))) = self.bus_events_chan.as_mut().map(|chan| chan.recv_non_blocking()) {
+                let Some(lang) = postcard::from_bytes::<Event>(&event_data).ok().and_then(|event| i18n::locale_changed(&event)) else { continue };
+                if let Err(e) = i18n::set_locale(&mut self.vfs_chan, &lang) {
+                    log(&alloc::format!("Shell Service: Keeping the current locale: {}", e));
+                }
+            }
+
             // Yield to other V-Nodes to prevent busy-waiting
             unsafe { syscall3(SYS_TIME, 0, 0, 0); }
         }
     }
 }
 
+/// An install the registry parked until the user answers its prompt.
+struct PendingInstall {
+    ticket: u64,
+    grants: usize, // Capabilities under review, numbered from 1 in the prompt
+    package_name: String,
+    asked_at: Ticks,
+}
+
+/// A command whose cost is still being added up: its history entry, and
+/// whether it runs under `time`.
+#[derive(Clone, Copy)]
+struct Paused {
+    entry: usize,
+    timed: bool,
+}
+
+/// Per-path results of a built-in that takes several paths. One path
+/// failing doesn't stop the rest; the exit code is 1 if any did.
+struct PathReport {
+    command: &'static str,
+    stdout: String,
+    stderr: String,
+}
+
+impl PathReport {
+    fn new(command: &'static str) -> Self {
+        Self { command, stdout: String::new(), stderr: String::new() }
+    }
+
+    fn ok(&mut self, line: String) {
+        self.stdout.push_str(&line);
+        self.stdout.push('\n');
+    }
+
+    fn fail(&mut self, path: &str, message: &str) {
+        self.stderr.push_str(&format!("{}: {}: {}\n", self.command, path, message));
+    }
+
+    fn file_manager(&mut self, path: &str, response: Result<FileManagerResponse, ShellResponse>) {
+        match response {
+            Ok(FileManagerResponse::Success(msg)) | Ok(FileManagerResponse::Copied { message: msg, .. }) => self.ok(msg),
+            Ok(FileManagerResponse::Error(msg)) => self.fail(path, &msg),
+            Ok(_) => self.fail(path, "Unexpected response from the File Manager"),
+            Err(_) => self.fail(path, "No response from the File Manager"),
+        }
+    }
+
+    fn finish(self) -> ShellResponse {
+        let exit_code = if self.stderr.is_empty() { 0 } else { 1 };
+        ShellResponse::CommandOutput { stdout: self.stdout, stderr: self.stderr, exit_code }
+    }
+}
+
+fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut ip = [0u8; 4];
+    let mut parts = text.split('.');
+    for byte in ip.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() { None } else { Some(ip) }
+}
+
+/// Parses `aa:bb:cc:dd:ee:ff` (or with dashes).
+fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = text.split(|c| c == ':' || c == '-');
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() { None } else { Some(mac) }
+}
+
+/// Parses a decimal number or a hex one with a `0x` prefix.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// What `start @group` prints: the group's state, then each member and its instance.
+fn format_group(info: &GroupInfo) -> String {
+    let state = match info.state {
+        GroupState::Running => "running",
+        GroupState::Degraded => "degraded",
+        GroupState::Stopped => "stopped",
+    };
+    let mut out = format!("Group {} is {}.\n", info.name, state);
+    for member in &info.members {
+        let instance = member.instance_id.map_or("not running".to_string(), |id| format!("instance {}", id));
+        out.push_str(&format!("  {} ({})\n", member.service_name, instance));
+    }
+    out
+}
+
+/// One `ps` line. Suspended tasks show their state with a `+`.
+fn format_task(stats: &TaskStats, group: &str) -> String {
+    let state = match stats.state {
+        TASK_STATE_RUNNING => "running",
+        TASK_STATE_READY => "ready",
+        TASK_STATE_BLOCKED => "blocked",
+        TASK_STATE_EXITED => "exited",
+        _ => "?",
+    };
+    let suspended = if stats.flags & TASK_FLAG_SUSPENDED != 0 { "+" } else { "" };
+    let cpu = if stats.last_cpu == TASK_CPU_NONE { "-".to_string() } else { stats.last_cpu.to_string() };
+    // Unfiltered tasks show "-"; filtered ones how many syscalls were turned away.
+    let filter = if stats.flags & TASK_FLAG_FILTERED != 0 { stats.filter_violations.to_string() } else { "-".to_string() };
+    // Every fault the task took; /proc/tasks has them by kind.
+    let faults = stats.page_faults_resolved + stats.page_faults_fatal + stats.general_protection_faults + stats.invalid_opcodes + stats.alignment_checks;
+    format!("{:>6} {:>3} {:<9} {:>8} {:>6} {:>8} {:<12} {}\n", stats.id, cpu, format!("{}{}", state, suspended), stats.log_messages, filter, faults, group, stats.name())
+}
+
+fn format_registers(regs: &RegisterFrame) -> String {
+    let named = [
+        ("rip", regs.rip), ("rsp", regs.rsp), ("rbp", regs.rbp), ("rflags", regs.rflags),
+        ("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx), ("rdx", regs.rdx),
+        ("rsi", regs.rsi), ("rdi", regs.rdi), ("r8", regs.r8), ("r9", regs.r9),
+        ("r10", regs.r10), ("r11", regs.r11), ("r12", regs.r12), ("r13", regs.r13),
+        ("r14", regs.r14), ("r15", regs.r15), ("cs", regs.cs), ("ss", regs.ss),
+    ];
+    let mut output = String::new();
+    for row in named.chunks(4) {
+        let line: Vec<String> = row.iter().map(|(name, value)| format!("{:>6} {:#018x}", name, value)).collect();
+        output.push_str(&line.join("  "));
+        output.push('\n');
+    }
+    output
+}
+
+/// 16 bytes per line: address, hex bytes, then printable ASCII.
+fn hex_dump(addr: u64, bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        output.push_str(&format!("{:016x}  {:<47}  |{}|\n", addr + i as u64 * 16, hex.join(" "), ascii));
+    }
+    output
+}
+
+/// A built-in's usage error, e.g. `usage("ps")` for "usage: ps". The synopsis isn't translated.
+const WALLPAPER_FITS: [WallpaperFit; 4] = [WallpaperFit::Center, WallpaperFit::Fit, WallpaperFit::Fill, WallpaperFit::Stretch];
+
+/// The value of `compositor.wallpaper_fit` for `fit`.
+fn fit_name(fit: WallpaperFit) -> &'static str {
+    match fit {
+        WallpaperFit::Center => "center",
+        WallpaperFit::Fit => "fit",
+        WallpaperFit::Fill => "fill",
+        WallpaperFit::Stretch => "stretch",
+    }
+}
+
+fn usage(synopsis: &str) -> ShellResponse {
+    ShellResponse::Error(tr!("shell.usage", "usage: {0}", synopsis))
+}
+
+fn unexpected_response(command: &str, service: &str) -> ShellResponse {
+    ShellResponse::Error(tr!("shell.unexpected_response", "{0}: Unexpected response from {1}", command, service))
+}
+
+fn no_response(command: &str, service: &str) -> ShellResponse {
+    ShellResponse::Error(tr!("shell.no_response", "{0}: No response from {1}", command, service))
+}
+
+/// A `netstat` line, and for a TCP connection a second one with its state
+/// changes in milliseconds after it opened. A closed connection's state is
+/// why it closed.
+fn format_connection(record: &ConnectionRecord) -> String {
+    let protocol = match record.protocol {
+        FlowProtocol::Tcp => "tcp",
+        FlowProtocol::Udp => "udp",
+    };
+    let remote = format!("{}.{}.{}.{}:{}", record.remote_ip[0], record.remote_ip[1], record.remote_ip[2], record.remote_ip[3], record.remote_port);
+    let state = match (record.reason, record.transitions.last()) {
+        (Some(reason), _) => match reason {
+            CloseReason::Fin => "fin".to_string(),
+            CloseReason::ResetReceived => "reset-received".to_string(),
+            CloseReason::ResetSent => "reset-sent".to_string(),
+            CloseReason::IdleTimeout => "idle-timeout".to_string(),
+            CloseReason::InterfaceDown => "interface-down".to_string(),
+            CloseReason::ClosedLocally => "closed-locally".to_string(),
+        },
+        (None, Some(change)) => format!("{:?}", change.state),
+        (None, None) => "-".to_string(),
+    };
+    let duration = record.closed_tick.map_or("-".to_string(), |closed| format!("{}ms", Ticks::from_raw(closed - record.opened_tick).to_millis()));
+    let input = format!("{}/{}", record.packets_in, format_bytes(record.bytes_in));
+    let output = format!("{}/{}", record.packets_out, format_bytes(record.bytes_out));
+    let mut line = format!("{:<5} {:<7} {:<21} {:<14} {:>9} {:>17} {:>17}\n", protocol, record.local_port, remote, state, duration, input, output);
+    if !record.transitions.is_empty() {
+        let changes: Vec<String> = record.transitions.iter().map(|change| format!("{:?} +{}ms", change.state, Ticks::from_raw(change.tick - record.opened_tick).to_millis())).collect();
+        let dropped = if record.transitions_dropped > 0 { format!(" (+{} more)", record.transitions_dropped) } else { String::new() };
+        line.push_str(&format!("      {}{}\n", changes.join(", "), dropped));
+    }
+    line
+}
+
+/// A search result's health, e.g. "seeders: ~4, last fetched 2h ago".
+fn format_health(health: &PackageHealth) -> String {
+    let seeders = match health.seeders {
+        0 => "no known seeders".to_string(),
+        seeders => format!("seeders: ~{}", seeders),
+    };
+    match health.last_fetched_secs_ago {
+        Some(secs) => format!("{}, last fetched {} ago", seeders, format_age(secs)),
+        None => seeders,
+    }
+}
+
+/// An age in its largest whole unit, e.g. "2h".
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. "1.5 MiB".
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// One line per stage: p50/p95/p99 in milliseconds (bucket upper bounds) and the sample count.
+fn format_latency(output: &mut String, latency: &PipelineLatency) {
+    // Buckets are in nanoseconds; overflow means more than the last bucket bound (1 s).
+    let ms = |p: Option<u64>| match p {
+        None => "-".to_string(),
+        Some(u64::MAX) => ">1000ms".to_string(),
+        Some(nanos) => format!("{}.{:02}ms", nanos / 1_000_000, nanos / 10_000 % 100),
+    };
+    for stage in Stage::ALL {
+        let histogram = latency.stage(stage);
+        output.push_str(&format!("  {:<18} p50 {:>7}  p95 {:>7}  p99 {:>7}  ({} samples)\n",
+            stage.name(), ms(histogram.percentile(50)), ms(histogram.percentile(95)), ms(histogram.percentile(99)), histogram.count));
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel IDs:
-    // 8 for Shell Service client requests (e.g., AetherTerminal)
-    // 7 for VFS Service
-    // 6 for Init Service
-    // 5 for DNS Resolver
-    let mut shell_service = ShellService::new(8, 7, 6, 5);
+    // Channels come from init; the well-known IDs are the fallback.
+    // Without startup info this is the console shell, which the kernel
+    // starts from the initrd; with it, a session shell init started. Only
+    // the console shell follows bus events and `logs --follow` notices.
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let console = !assigned.contains_key(SELF_CHANNEL);
+    let mut shell_service = ShellService::new(
+        channel(SELF_CHANNEL, channels::SHELL),
+        channel("vfs", channels::VFS),
+        channel("init-service", channels::INIT_SERVICE),
+        channel("dns-resolver", channels::DNS_RESOLVER),
+        channel("settings", channels::SETTINGS),
+        channel("registry", channels::REGISTRY),
+        channel("display-compositor", channels::DISPLAY_COMPOSITOR),
+        channel("aethernet-service", channels::NET_STACK),
+        channel("socket-api", channels::SOCKET_API),
+        channel("file-manager", channels::FILE_MANAGER),
+        channel("event-bus", channels::EVENT_BUS),
+        channel("session", channels::SESSION),
+        console.then_some(channels::SHELL_EVENTS),
+        console.then_some(channels::SHELL_VFS_WATCH),
+    );
     shell_service.run_loop();
 }
 
@@ -0,0 +1,105 @@
+// vnode/shell/src/remote.rs
+
+//! The client side of `aethersh`: a session with another node's
+//! aethersh-server, over a socket from svc://socket-api. The protocol is in
+//! `common/src/ipc/aethersh_ipc.rs`. The built-in itself, and how the
+//! session's prompts reach the user, live in main.rs.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use crate::ipc::aethersh_ipc::{self, ClientFrame, FrameReader, ServerFrame, PROTOCOL_VERSION};
+use crate::ipc::socket_client::SocketClient;
+use crate::ipc::socket_ipc::{SocketFd, SocketRequest, SocketResponse};
+use crate::ipc::vnode::VNodeChannel;
+use crate::syscall::{syscall3, SYS_TIME};
+use crate::trust::LocalIdentity;
+
+/// Bytes asked for per `Recv`.
+const RECV_CHUNK: u32 = 4096;
+
+/// An authenticated session.
+pub struct RemoteShell {
+    fd: SocketFd,
+    pub host: String,
+    reader: FrameReader,
+    /// The last reply was a `Prompt`, so the next input is its answer.
+    pub answering: bool,
+}
+
+impl RemoteShell {
+    /// Connects to `host` and authenticates as `identity`. Returns the
+    /// session and the directory it starts in.
+    pub fn open(socket_chan: &mut VNodeChannel, host: &str, port: u16, identity: &LocalIdentity) -> Result<(Self, String), String> {
+        let conn = SocketClient::new(socket_chan).tcp_connect_host(host, port, 0).map_err(|e| e.to_string())?;
+        let mut remote = Self { fd: conn.fd, host: host.to_string(), reader: FrameReader::new(), answering: false };
+        // Nothing is sent before the address is checked, not even the Aid.
+        if !aethersh_ipc::is_loopback(conn.addr) {
+            SocketClient::new(socket_chan).close(conn.fd);
+            let [a, b, c, d] = conn.addr;
+            return Err(format!("{} is {}.{}.{}.{}; without TLS only loopback addresses are allowed", host, a, b, c, d));
+        }
+        match remote.authenticate(socket_chan, identity) {
+            Ok(cwd) => Ok((remote, cwd)),
+            Err(e) => {
+                SocketClient::new(socket_chan).close(remote.fd);
+                Err(e)
+            }
+        }
+    }
+
+    fn authenticate(&mut self, socket_chan: &mut VNodeChannel, identity: &LocalIdentity) -> Result<String, String> {
+        let nonce = match self.wait(socket_chan, aethersh_ipc::AUTH_TIMEOUT_SECS)? {
+            ServerFrame::Challenge { version, nonce } if version == PROTOCOL_VERSION => nonce,
+            ServerFrame::Challenge { version, .. } => return Err(format!("the server speaks protocol version {}, not {}", version, PROTOCOL_VERSION)),
+            ServerFrame::Refused { reason } => return Err(format!("refused: {}", reason)),
+            _ => return Err("the server didn't send a challenge".to_string()),
+        };
+        self.send(socket_chan, &ClientFrame::Auth { aid: identity.aid().0, proof: identity.sign(&nonce) })?;
+        match self.wait(socket_chan, aethersh_ipc::AUTH_TIMEOUT_SECS)? {
+            ServerFrame::Authenticated { cwd } => Ok(cwd),
+            ServerFrame::Refused { reason } => Err(format!("refused: {}", reason)),
+            _ => Err("unexpected answer to authentication".to_string()),
+        }
+    }
+
+    pub fn send(&mut self, socket_chan: &mut VNodeChannel, frame: &ClientFrame) -> Result<(), String> {
+        let request = SocketRequest::Send { fd: self.fd, data: aethersh_ipc::encode(frame) };
+        match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&request) {
+            Ok(SocketResponse::Success(_)) => Ok(()),
+            Ok(SocketResponse::Error(_, message)) => Err(message),
+            Ok(SocketResponse::NetworkDown) => Err("the network is down".to_string()),
+            _ => Err("unexpected response from socket-api".to_string()),
+        }
+    }
+
+    /// The next frame from the server, waiting up to `seconds` for it.
+    pub fn wait(&mut self, socket_chan: &mut VNodeChannel, seconds: u64) -> Result<ServerFrame, String> {
+        // Each SYS_TIME yields; a tick is 10 ms.
+        let until = unsafe { syscall3(SYS_TIME, 0, 0, 0) } + seconds * 100;
+        loop {
+            match self.reader.next::<ServerFrame>() {
+                Ok(Some(frame)) => return Ok(frame),
+                Ok(None) => {},
+                Err(e) => return Err(format!("bad frame from the server: {:?}", e)),
+            }
+            if unsafe { syscall3(SYS_TIME, 0, 0, 0) } >= until {
+                return Err(format!("no answer from {} in {} s", self.host, seconds));
+            }
+            match socket_chan.send_and_recv::<SocketRequest, SocketResponse>(&SocketRequest::Recv { fd: self.fd, len: RECV_CHUNK }) {
+                Ok(SocketResponse::Data(data)) => self.reader.push(&data),
+                Ok(SocketResponse::Error(_, message)) => return Err(message),
+                Ok(SocketResponse::NetworkDown) => return Err("the network is down".to_string()),
+                _ => return Err("unexpected response from socket-api".to_string()),
+            }
+        }
+    }
+
+    /// Says goodbye and closes the connection.
+    pub fn close(mut self, socket_chan: &mut VNodeChannel) {
+        let _ = self.send(socket_chan, &ClientFrame::Bye);
+        SocketClient::new(socket_chan).close(self.fd);
+    }
+}
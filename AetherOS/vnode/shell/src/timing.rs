@@ -0,0 +1,98 @@
+// vnode/shell/src/timing.rs
+
+//! What commands cost: execution time from the nanosecond clock and the IPC
+//! round trips counted by the client library. Only execution is measured; a
+//! command that stops at a `Prompt` is paused until the answer arrives, so
+//! the time the user takes to answer isn't counted. The dispatch and the
+//! `time` and `history` built-ins live in main.rs.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::shell_ipc::CommandTiming;
+use crate::ipc::vnode;
+use crate::time;
+
+/// How many of the latest history entries `slowest` looks at.
+pub const RECENT_COMMANDS: usize = 100;
+
+/// What some execution cost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cost {
+    pub nanos: u64,
+    pub round_trips: u64,
+}
+
+impl Cost {
+    pub fn add(&mut self, other: Cost) {
+        self.nanos = self.nanos.saturating_add(other.nanos);
+        self.round_trips = self.round_trips.saturating_add(other.round_trips);
+    }
+}
+
+/// Measures from `start` to `stop`.
+pub struct Stopwatch {
+    started_nanos: u64,
+    started_round_trips: u64,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self { started_nanos: time::monotonic_nanos(), started_round_trips: vnode::round_trips() }
+    }
+
+    pub fn stop(self) -> Cost {
+        Cost {
+            nanos: time::monotonic_nanos().saturating_sub(self.started_nanos),
+            round_trips: vnode::round_trips().saturating_sub(self.started_round_trips),
+        }
+    }
+}
+
+/// A command in the history. `cost` is `None` while it is still running,
+/// which is only ever the `history` command listing itself.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub line: String,
+    pub cost: Option<Cost>,
+}
+
+/// The slowest of the last `RECENT_COMMANDS` finished commands, slowest first.
+pub fn slowest(history: &[HistoryEntry], limit: usize) -> Vec<CommandTiming> {
+    let recent = &history[history.len().saturating_sub(RECENT_COMMANDS)..];
+    let mut timings: Vec<CommandTiming> = recent.iter()
+        .filter_map(|entry| entry.cost.map(|cost| CommandTiming { line: entry.line.clone(), elapsed_nanos: cost.nanos, round_trips: cost.round_trips }))
+        .collect();
+    // Stable, so equally slow commands stay in the order they ran.
+    timings.sort_by(|a, b| b.elapsed_nanos.cmp(&a.elapsed_nanos));
+    timings.truncate(limit);
+    timings
+}
+
+/// "12.345ms", "1.204s", "850ns": three decimals in the largest unit that fits.
+pub fn format_duration(nanos: u64) -> String {
+    match nanos {
+        0..=999 => format!("{}ns", nanos),
+        1_000..=999_999 => format!("{}.{:03}us", nanos / 1_000, nanos % 1_000),
+        1_000_000..=999_999_999 => format!("{}.{:03}ms", nanos / 1_000_000, nanos / 1_000 % 1_000),
+        _ => format!("{}.{:03}s", nanos / 1_000_000_000, nanos / 1_000_000 % 1_000),
+    }
+}
+
+/// The report `time` appends to a command's stderr. Bytes and throughput
+/// are only there for commands whose service reported what it processed.
+pub fn report(cost: &Cost, bytes: Option<u64>, format_bytes: impl Fn(u64) -> String) -> String {
+    let mut out = format!("\nreal  {}\nipc   {} round trip{}\n", format_duration(cost.nanos), cost.round_trips, if cost.round_trips == 1 { "" } else { "s" });
+    if let Some(bytes) = bytes {
+        out.push_str(&format!("bytes {}", format_bytes(bytes)));
+        if cost.nanos > 0 {
+            let per_second = (bytes as u128 * 1_000_000_000 / cost.nanos as u128).min(u64::MAX as u128) as u64;
+            out.push_str(&format!(" ({}/s)", format_bytes(per_second)));
+        }
+        out.push('\n');
+    }
+    out
+}
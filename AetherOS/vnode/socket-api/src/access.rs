@@ -0,0 +1,108 @@
+// vnode/socket-api/src/access.rs
+
+//! Which client may use which socket.
+//!
+//! A fd is a small number any client can guess, and the network stack
+//! charges a socket's work to the task that opened it. So every request on
+//! a fd comes from that task, or gets EBADF as if the fd didn't exist.
+
+extern crate alloc;
+
+use alloc::string::ToString;
+
+use crate::ipc::socket_ipc::{SocketFd, SocketRequest, SocketResponse};
+
+/// The socket a request operates on, if any.
+pub fn request_fd(request: &SocketRequest) -> Option<SocketFd> {
+    match request {
+        SocketRequest::Bind { fd, .. }
+        | SocketRequest::Listen { fd, .. }
+        | SocketRequest::Accept { fd }
+        | SocketRequest::Connect { fd, .. }
+        | SocketRequest::ConnectHost { fd, .. }
+        | SocketRequest::GetPeerName { fd }
+        | SocketRequest::Send { fd, .. }
+        | SocketRequest::SendTo { fd, .. }
+        | SocketRequest::Recv { fd, .. }
+        | SocketRequest::RecvAsync { fd, .. }
+        | SocketRequest::RecvFrom { fd, .. }
+        | SocketRequest::JoinMulticast { fd, .. }
+        | SocketRequest::LeaveMulticast { fd, .. }
+        | SocketRequest::Close { fd }
+        | SocketRequest::GetSocketInfo { fd }
+        | SocketRequest::SetSockOpt { fd, .. }
+        | SocketRequest::Flush { fd } => Some(*fd),
+        SocketRequest::Socket { .. } | SocketRequest::GetPolicy => None,
+    }
+}
+
+/// Checks that `sender` owns the fd `request` operates on. `owner_of` looks
+/// the owner up; a fd nobody owns is left to the request's own handling.
+pub fn check_owner(request: &SocketRequest, sender: u64, owner_of: impl FnOnce(SocketFd) -> Option<u64>) -> Result<(), SocketResponse> {
+    match request_fd(request).and_then(owner_of) {
+        Some(owner) if owner != sender => Err(SocketResponse::Error(9, "Bad file descriptor".to_string())), // EBADF
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::ipc::socket_ipc::SocketOption;
+
+    const ALICE: u64 = 7;
+    const BOB: u64 = 9;
+
+    fn every_request(fd: SocketFd) -> Vec<SocketRequest> {
+        vec![
+            SocketRequest::Bind { fd, addr: [10, 0, 2, 15], port: 7000 },
+            SocketRequest::Listen { fd, backlog: 4 },
+            SocketRequest::Accept { fd },
+            SocketRequest::Connect { fd, addr: [10, 0, 2, 2], port: 80 },
+            SocketRequest::ConnectHost { fd, hostname: "example.org".to_string(), port: 80, attempt_timeout_ms: 1000 },
+            SocketRequest::GetPeerName { fd },
+            SocketRequest::Send { fd, data: vec![1, 2, 3] },
+            SocketRequest::SendTo { fd, addr: [10, 0, 2, 2], port: 9000, data: vec![1] },
+            SocketRequest::Recv { fd, len: 64 },
+            SocketRequest::RecvAsync { fd, reply_chan: 40 },
+            SocketRequest::RecvFrom { fd, len: 64 },
+            SocketRequest::JoinMulticast { fd, group: [224, 0, 0, 251] },
+            SocketRequest::LeaveMulticast { fd, group: [224, 0, 0, 251] },
+            SocketRequest::Close { fd },
+            SocketRequest::GetSocketInfo { fd },
+            SocketRequest::SetSockOpt { fd, option: SocketOption::NonBlocking(true) },
+            SocketRequest::Flush { fd },
+        ]
+    }
+
+    fn check(owners: &BTreeMap<SocketFd, u64>, request: &SocketRequest, sender: u64) -> Result<(), SocketResponse> {
+        check_owner(request, sender, |fd| owners.get(&fd).copied())
+    }
+
+    #[test]
+    fn clients_cannot_use_each_others_fds() {
+        let alice_fd = SocketFd::from_raw(3);
+        let bob_fd = SocketFd::from_raw(4);
+        let owners = BTreeMap::from([(alice_fd, ALICE), (bob_fd, BOB)]);
+        for request in every_request(alice_fd) {
+            assert!(check(&owners, &request, ALICE).is_ok(), "{:?}", request);
+            assert!(matches!(check(&owners, &request, BOB), Err(SocketResponse::Error(9, _))), "{:?}", request);
+        }
+        for request in every_request(bob_fd) {
+            assert!(check(&owners, &request, BOB).is_ok(), "{:?}", request);
+            assert!(matches!(check(&owners, &request, ALICE), Err(SocketResponse::Error(9, _))), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn requests_without_an_owned_fd_pass() {
+        let owners = BTreeMap::from([(SocketFd::from_raw(3), ALICE)]);
+        assert!(check(&owners, &SocketRequest::Socket { domain: 2, ty: 1, protocol: 0 }, BOB).is_ok());
+        assert!(check(&owners, &SocketRequest::GetPolicy, BOB).is_ok());
+        // Unknown fds get their EBADF from the request's own handling.
+        assert!(check(&owners, &SocketRequest::Close { fd: SocketFd::from_raw(5) }, BOB).is_ok());
+    }
+}
@@ -4,15 +4,37 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::format;
 use alloc::string::{String, ToString};
 
+use crate::channels;
 use crate::ipc::vnode::VNodeChannel;
 use crate::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use crate::ipc::net_ipc::{NetStackRequest, NetStackResponse};
-use crate::ipc::socket_ipc::{SocketRequest, SocketResponse, SocketFd};
+use crate::abi::IpcCreds;
+use crate::ipc::net_ipc::{ConnectState, ConnectionReady, InterfaceInfo, NetStackRequest, NetStackResponse, SocketClosed, SocketQuota, MAX_BACKLOG};
+use crate::ipc::net_ipc::{CONNECTION_READY_TOPIC, NET_DOWN_TOPIC, NET_UP_TOPIC, SOCKET_CLOSED_TOPIC};
+use crate::ipc::socket_ipc::{AttemptError, ConnectAttempt, ListenerInfo, SocketOption, SocketRequest, SocketResponse, SocketFd, DEFAULT_CONNECT_TIMEOUT_MS};
+use crate::ipc::dns_ipc::{DnsRequest, DnsResponse};
+use crate::ipc::init_ipc::{InitRequest, InitResponse, ServiceTarget};
+use crate::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue, SettingChanged};
+use crate::ipc::event_ipc::{EventBusRequest, EventBusResponse, Event};
+use crate::time;
+
+mod access;
+mod policy;
+use access::request_fd;
+use policy::NetPolicy;
+
+const POLICY_KEY: &str = "net.policy";
+/// How long `ConnectHost` waits for the resolver.
+const RESOLVE_TIMEOUT_MS: u64 = 5000;
+
+fn now_ms() -> u64 {
+    time::ticks().to_millis().raw()
+}
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -27,249 +49,985 @@ fn log(msg: &str) {
     }
 }
 
+/// Maps a net-stack quota refusal to an errno: EMFILE when this service hit its own
+/// limit, ENFILE when the whole stack is full.
+fn quota_error(quota: SocketQuota, limit: u32) -> SocketResponse {
+    log(&alloc::format!("SocketAPI: AetherNet refused a socket: {:?} limit of {} reached.", quota, limit));
+    match quota {
+        SocketQuota::PerTask => SocketResponse::Error(24, alloc::format!("Too many open sockets (limit {})", limit)), // EMFILE
+        SocketQuota::Global => SocketResponse::Error(23, alloc::format!("Network stack socket limit reached ({})", limit)), // ENFILE
+    }
+}
+
+/// Parses a `net.policy` value, logging why it was rejected if it doesn't parse.
+fn parse_policy(text: &str) -> Option<NetPolicy> {
+    match NetPolicy::parse(text) {
+        Ok(policy) => {
+            log(&alloc::format!("SocketAPI: Network policy loaded ({} services).", policy.services().len()));
+            Some(policy)
+        },
+        Err(e) => {
+            log(&alloc::format!("SocketAPI: Ignoring invalid {}: {}", POLICY_KEY, e));
+            None
+        },
+    }
+}
+
+/// Reads the current policy from the settings service.
+fn fetch_policy(settings_chan: &mut VNodeChannel) -> Option<NetPolicy> {
+    match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: POLICY_KEY.to_string() }) {
+        Ok(SettingsResponse::Value { value: SettingValue::Str(text), .. }) => parse_policy(&text),
+        _ => {
+            log("SocketAPI: Could not read the network policy from Settings; leaving the network unrestricted.");
+            None
+        },
+    }
+}
+
+/// The service `task` is an instance of, as reported by init. Task IDs are
+/// never reused, so answers are cached for the life of socket-api.
+fn service_of(task: u64, init_chan: &mut VNodeChannel, cache: &mut BTreeMap<u64, String>) -> Option<String> {
+    if let Some(name) = cache.get(&task) {
+        return Some(name.clone());
+    }
+    match init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::ServiceStatus { target: ServiceTarget::Instance(task) }) {
+        Ok(InitResponse::Instances(instances)) => {
+            let name = instances.into_iter().next()?.service_name;
+            cache.insert(task, name.clone());
+            Some(name)
+        },
+        _ => None,
+    }
+}
+
+/// Destination checked against the network policy, for the operations it covers.
+fn policy_target(request: &SocketRequest) -> Option<([u8; 4], u16)> {
+    match request {
+        SocketRequest::Connect { addr, port, .. }
+        | SocketRequest::SendTo { addr, port, .. }
+        | SocketRequest::Bind { addr, port, .. } => Some((*addr, *port)),
+        _ => None,
+    }
+}
+
+/// The answer to a TCP `Connect` that failed.
+fn connect_error(error: AttemptError) -> SocketResponse {
+    match error {
+        AttemptError::Refused => SocketResponse::Error(111, "Connection refused".to_string()), // ECONNREFUSED
+        AttemptError::TimedOut => SocketResponse::Error(110, "Connection timed out".to_string()), // ETIMEDOUT
+        AttemptError::PolicyDenied { rule } => SocketResponse::PolicyDenied { rule },
+        AttemptError::NetworkDown => SocketResponse::NetworkDown,
+        AttemptError::Other(code, message) => SocketResponse::Error(code, message),
+    }
+}
+
 // Placeholder for socket state (simulated file descriptor management)
 #[derive(Debug, Clone)]
 struct SocketInfo {
     net_socket_handle: u32, // The handle given by svc://aethernet
+    owner: u64, // Client task that opened it; the network stack charges the socket to it
     socket_type: i32, // SOCK_STREAM or SOCK_DGRAM (as per SocketRequest `ty`)
     is_listening: bool,
-    // Add more state as needed, e.g., remote address for connected sockets
+    local_port: u16, // 0 until bound
+    peer: Option<([u8; 4], u16)>, // Set by a successful connect or accept
+    closed_by_network: bool, // The stack closed it when the interface went down; only Close is left
+    non_blocking: bool, // `SocketOption::NonBlocking`
+    connect_pending: Option<PendingConnect>, // A non-blocking connect still in its handshake
+    backlog: u32, // While listening
+    accept_queue: VecDeque<SocketFd>, // Connections taken off the listener, oldest first, waiting for `Accept`
+    recv_watcher: Option<u32>, // The client channel a `RecvAsync` asked us to tell
 }
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    // Channel for requests from client V-Nodes to this socket-api V-Node
-    let mut client_chan = VNodeChannel::new(4); // Assuming channel ID 4 for socket-api
+impl SocketInfo {
+    fn new(net_socket_handle: u32, owner: u64, socket_type: i32, local_port: u16, peer: Option<([u8; 4], u16)>) -> Self {
+        Self {
+            net_socket_handle,
+            owner,
+            socket_type,
+            is_listening: false,
+            local_port,
+            peer,
+            closed_by_network: false,
+            non_blocking: false,
+            connect_pending: None,
+            backlog: 0,
+            accept_queue: VecDeque::new(),
+            recv_watcher: None,
+        }
+    }
+}
 
-    // Channel to communicate with svc://aethernet-service
-    let mut net_chan = VNodeChannel::new(3); // Assuming channel ID 3 for aethernet-service
+/// A non-blocking TCP connect that was answered with EINPROGRESS.
+#[derive(Debug, Clone, Copy)]
+struct PendingConnect {
+    addr: [u8; 4],
+    port: u16,
+    deadline_ms: u64,
+}
 
-    log("Socket API V-Node starting up...");
+struct SocketApi {
+    client_chan: VNodeChannel,
+    net_chan: NetStackChannel,
+    init_chan: VNodeChannel,
+    dns_chan: Option<VNodeChannel>, // Opened by the first `ConnectHost`
+    policy: NetPolicy,
+    service_names: BTreeMap<u64, String>,
+    next_fd: SocketFd,
+    sockets: BTreeMap<SocketFd, SocketInfo>,
+    connecting: bool, // A connect is waiting; requests are served from inside it
+    events_chan: VNodeChannel, // Policy changes and the network stack's interface events
+    ready_chan: VNodeChannel, // The network stack's `DataReady` for sockets watched by `RecvAsync`
+    network_up: bool,
+}
 
-    let mut next_fd: SocketFd = 1;
-    let mut sockets: BTreeMap<SocketFd, SocketInfo> = BTreeMap::new();
-    // `pending_accept_fci` is not strictly needed if aethernet-service directly sends new connection info.
-    // For now, keep it simple by returning EWOULDBLOCK for accept.
+/// The channel to the network stack. Every request names the client task it
+/// is for, so sockets are charged to the client rather than to socket-api.
+struct NetStackChannel(VNodeChannel);
 
-    loop {
-        // 1. Process incoming requests from client V-Nodes
-        if let Ok(Some(req_data)) = client_chan.recv_non_blocking() {
+impl NetStackChannel {
+    fn call(&mut self, task: u64, request: NetStackRequest) -> Result<NetStackResponse, ()> {
+        self.0.send_and_recv(&NetStackRequest::ForTask { task, request: Box::new(request) })
+    }
+}
+
+impl SocketApi {
+    /// Handles the next client request, if one is waiting.
+    fn serve_one(&mut self) {
+        if let Ok(Some((req_data, token, creds))) = self.client_chan.recv_call_with_creds_non_blocking() {
             if let Ok(request) = postcard::from_bytes::<SocketRequest>(&req_data) {
                 log(&alloc::format!("SocketAPI: Received request from client: {:?}", request));
+                // The kernel's credentials, not the payload, say who is asking.
+                let response = self.handle_request(request, creds);
+                self.client_chan.reply(token, &response).unwrap_or_else(|_| log("SocketAPI: Failed to send response to client."));
+            } else {
+                log("SocketAPI: Failed to deserialize SocketRequest.");
+            }
+        }
+    }
 
-                let response = match request {
-                    SocketRequest::Socket { domain, ty, protocol } => {
-                        // For now, only AF_INET (domain 2), SOCK_STREAM (type 1), SOCK_DGRAM (type 2) are conceptual
-                        // Map our type to aethernet-service's type (0=TCP, 1=UDP)
-                        let net_sock_type = match ty {
-                            1 => 0, // SOCK_STREAM -> TCP
-                            2 => 1, // SOCK_DGRAM -> UDP
-                            _ => {
-                                log(&alloc::format!("SocketAPI: Unsupported socket type: {}", ty));
-                                return SocketResponse::Error(100, "Unsupported socket type".to_string());
-                            }
-                        };
+    /// Lets a connect wait a tick without stalling everyone else. The resolver
+    /// itself talks to us while it works on a `ConnectHost`'s lookup, so this
+    /// is not just politeness.
+    fn wait(&mut self) {
+        self.serve_one();
+        self.handle_events();
+        self.handle_ready();
+        unsafe { syscall3(SYS_TIME, 0, 0, 0); } // Yield to other V-Nodes
+    }
 
-                        match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::OpenSocket(net_sock_type, 0)) {
-                            Ok(NetStackResponse::SocketOpened(net_handle)) => {
-                                let fd = next_fd;
-                                next_fd += 1;
-                                sockets.insert(fd, SocketInfo { net_socket_handle: net_handle, socket_type: ty, is_listening: false });
-                                log(&alloc::format!("SocketAPI: Opened new socket with fd: {}, net_handle: {}", fd, net_handle));
-                                SocketResponse::Success(fd as i32)
-                            },
-                            Ok(NetStackResponse::Error(code)) => {
-                                log(&alloc::format!("SocketAPI: Failed to open socket in AetherNet. Error code: {}", code));
-                                SocketResponse::Error(code as i32, "Failed to open socket in AetherNet".to_string())
-                            },
-                            _ => {
-                                log("SocketAPI: Unexpected response from AetherNet during Socket open.");
-                                SocketResponse::Error(-1, "Unexpected response from AetherNet during Socket open".to_string())
-                            },
-                        }
+    fn handle_request(&mut self, request: SocketRequest, requester: IpcCreds) -> SocketResponse {
+        // Before anything else, so a guessed fd learns nothing and costs its owner nothing.
+        if let Err(response) = access::check_owner(&request, requester.sender, |fd| self.sockets.get(&fd).map(|socket_info| socket_info.owner)) {
+            log(&alloc::format!("SocketAPI: Refused task {} ({}) a socket it doesn't own: {:?}", requester.sender, requester.name(), request));
+            return response;
+        }
+
+        let denial = policy_target(&request).and_then(|(addr, port)| {
+            let service = service_of(requester.sender, &mut self.init_chan, &mut self.service_names);
+            self.policy.check(service.as_deref(), addr, port).err()
+        });
+
+        match request {
+            // Checked before the other arms so a denied operation never reaches AetherNet.
+            _ if denial.is_some() => {
+                let rule = denial.unwrap_or_default();
+                log(&alloc::format!("SocketAPI: Task {} ({}) denied by network policy: {}", requester.sender, requester.name(), rule));
+                SocketResponse::PolicyDenied { rule }
+            },
+            SocketRequest::Connect { .. }
+            | SocketRequest::ConnectHost { .. }
+            | SocketRequest::Send { .. }
+            | SocketRequest::SendTo { .. } if !self.network_up => SocketResponse::NetworkDown,
+            SocketRequest::Close { fd } if self.sockets.get(&fd).map_or(false, |socket_info| socket_info.closed_by_network) => {
+                // The network stack has freed it already.
+                self.sockets.remove(&fd);
+                log(&alloc::format!("SocketAPI: Closed socket fd {} (closed by the network going down)", fd));
+                SocketResponse::Success(0)
+            },
+            _ if request_fd(&request).and_then(|fd| self.sockets.get(&fd)).map_or(false, |socket_info| socket_info.closed_by_network) => {
+                SocketResponse::Error(103, "Connection aborted: the network went down".to_string()) // ECONNABORTED
+            },
+            SocketRequest::GetPolicy => SocketResponse::Policy(self.policy.services().to_vec()),
+            SocketRequest::Socket { domain, ty, protocol } => {
+                // For now, only AF_INET (domain 2), SOCK_STREAM (type 1), SOCK_DGRAM (type 2) are conceptual
+                // Map our type to aethernet-service's type (0=TCP, 1=UDP)
+                let net_sock_type = match ty {
+                    1 => 0, // SOCK_STREAM -> TCP
+                    2 => 1, // SOCK_DGRAM -> UDP
+                    _ => {
+                        log(&alloc::format!("SocketAPI: Unsupported socket type: {}", ty));
+                        return SocketResponse::Error(100, "Unsupported socket type".to_string());
+                    }
+                };
+
+                match self.net_chan.call(requester.sender, NetStackRequest::OpenSocket(net_sock_type, 0)) {
+                    Ok(NetStackResponse::SocketOpened(net_handle)) => {
+                        let fd = self.next_fd;
+                        self.next_fd = SocketFd::from_raw(fd.raw() + 1);
+                        self.sockets.insert(fd, SocketInfo::new(net_handle, requester.sender, ty, 0, None));
+                        log(&alloc::format!("SocketAPI: Opened new socket with fd: {}, net_handle: {}", fd, net_handle));
+                        SocketResponse::Success(fd.raw() as i32)
                     },
-                    SocketRequest::Bind { fd, addr, port } => {
-                        if let Some(socket_info) = sockets.get_mut(&fd) {
-                            // `aethernet-service`'s `OpenSocket` is used for both creation and binding to a local port.
-                            // So, we re-call `OpenSocket` with the existing socket_type and the new local_port.
-                            // This might create a new smoltcp socket and return a new handle, or reconfigure an existing one.
-                            let net_sock_type = match socket_info.socket_type {
-                                1 => 0, // SOCK_STREAM -> TCP
-                                2 => 1, // SOCK_DGRAM -> UDP
-                                _ => {
-                                    log(&alloc::format!("SocketAPI: Cannot bind unsupported socket type: {}", socket_info.socket_type));
-                                    return SocketResponse::Error(100, "Unsupported socket type for bind".to_string());
-                                }
-                            };
-                            match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::OpenSocket(net_sock_type, port)) {
-                                Ok(NetStackResponse::SocketOpened(new_net_handle)) => {
-                                    // Update the net_socket_handle if aethernet-service returned a new one after binding
-                                    socket_info.net_socket_handle = new_net_handle;
-                                    log(&alloc::format!("SocketAPI: Socket fd {} bound to {}:{}, new net_handle: {}", fd, addr[0], port, new_net_handle));
-                                    SocketResponse::Success(0)
-                                },
-                                Ok(NetStackResponse::Error(code)) => {
-                                    log(&alloc::format!("SocketAPI: Failed to bind socket fd {} in AetherNet. Error: {}", fd, code));
-                                    SocketResponse::Error(code as i32, "Failed to bind socket in AetherNet".to_string())
-                                },
-                                _ => {
-                                    log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Bind for fd {}.
-", fd));
-                                    SocketResponse::Error(-1, "Unexpected response from AetherNet during Bind".to_string())
-                                },
-                            }
-                        } else {
-                            log(&alloc::format!("SocketAPI: Bind failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                    Ok(NetStackResponse::QuotaExceeded(quota, limit)) => quota_error(quota, limit),
+                    Ok(NetStackResponse::Error(code)) => {
+                        log(&alloc::format!("SocketAPI: Failed to open socket in AetherNet. Error code: {}", code));
+                        SocketResponse::Error(code as i32, "Failed to open socket in AetherNet".to_string())
+                    },
+                    _ => {
+                        log("SocketAPI: Unexpected response from AetherNet during Socket open.");
+                        SocketResponse::Error(-1, "Unexpected response from AetherNet during Socket open".to_string())
+                    },
+                }
+            },
+            SocketRequest::Bind { fd, addr, port } => {
+                if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                    // `aethernet-service`'s `OpenSocket` is used for both creation and binding to a local port.
+                    // So, we re-call `OpenSocket` with the existing socket_type and the new local_port.
+                    // This might create a new smoltcp socket and return a new handle, or reconfigure an existing one.
+                    let net_sock_type = match socket_info.socket_type {
+                        1 => 0, // SOCK_STREAM -> TCP
+                        2 => 1, // SOCK_DGRAM -> UDP
+                        _ => {
+                            log(&alloc::format!("SocketAPI: Cannot bind unsupported socket type: {}", socket_info.socket_type));
+                            return SocketResponse::Error(100, "Unsupported socket type for bind".to_string());
                         }
+                    };
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::OpenSocket(net_sock_type, port)) {
+                        Ok(NetStackResponse::SocketOpened(new_net_handle)) => {
+                            // The bound socket replaces the unbound one; close the old one so it doesn't count against our quota.
+                            let old_net_handle = core::mem::replace(&mut socket_info.net_socket_handle, new_net_handle);
+                            let _ = self.net_chan.call(socket_info.owner, NetStackRequest::CloseSocket(old_net_handle));
+                            socket_info.local_port = port;
+                            log(&alloc::format!("SocketAPI: Socket fd {} bound to {}:{}, new net_handle: {}", fd, addr[0], port, new_net_handle));
+                            SocketResponse::Success(0)
+                        },
+                        Ok(NetStackResponse::QuotaExceeded(quota, limit)) => quota_error(quota, limit),
+                        Ok(NetStackResponse::Error(code)) => {
+                            log(&alloc::format!("SocketAPI: Failed to bind socket fd {} in AetherNet. Error: {}", fd, code));
+                            SocketResponse::Error(code as i32, "Failed to bind socket in AetherNet".to_string())
+                        },
+                        _ => {
+                            log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Bind for fd {}.
+", fd));
+                            SocketResponse::Error(-1, "Unexpected response from AetherNet during Bind".to_string())
+                        },
+                    }
+                } else {
+                    log(&alloc::format!("SocketAPI: Bind failed, bad file descriptor: {}", fd));
+                    SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+            SocketRequest::Listen { fd, backlog } => {
+                match self.sockets.get_mut(&fd) {
+                    Some(socket_info) if socket_info.socket_type != 1 => { // Only TCP sockets can listen
+                        log(&alloc::format!("SocketAPI: Socket fd {} cannot listen, not a TCP socket.", fd));
+                        SocketResponse::Error(105, "Only TCP sockets can listen".to_string())
                     },
-                    SocketRequest::Listen { fd, backlog: _ } => { // backlog is conceptual for smoltcp
-                        if let Some(socket_info) = sockets.get_mut(&fd) {
-                            // In smoltcp, `listen` is part of TcpSocket creation/configuration if a port is given.
-                            // Here, we just mark our internal state as listening.
-                            if socket_info.socket_type == 1 { // Only TCP sockets can listen
+                    Some(socket_info) if socket_info.local_port == 0 => {
+                        SocketResponse::Error(22, "Socket must be bound before it listens".to_string()) // EINVAL
+                    },
+                    Some(socket_info) => {
+                        // As on other systems, an out-of-range backlog is clamped rather than refused.
+                        let backlog = backlog.clamp(1, MAX_BACKLOG as i32) as u32;
+                        let listen = NetStackRequest::Listen { port: socket_info.local_port, backlog };
+                        match self.net_chan.call(socket_info.owner, listen) {
+                            Ok(NetStackResponse::SocketOpened(listener)) => {
+                                // The listener replaces the bound socket (or, on a second Listen, the old listener).
+                                let old_net_handle = core::mem::replace(&mut socket_info.net_socket_handle, listener);
+                                let _ = self.net_chan.call(socket_info.owner, NetStackRequest::CloseSocket(old_net_handle));
                                 socket_info.is_listening = true;
-                                log(&alloc::format!("SocketAPI: Socket fd {} marked as listening.", fd));
+                                socket_info.backlog = backlog;
+                                // Connections queued for the old listener were reset with it.
+                                let queued: Vec<SocketFd> = socket_info.accept_queue.drain(..).collect();
+                                log(&alloc::format!("SocketAPI: Socket fd {} listening on port {} with a backlog of {}.", fd, socket_info.local_port, backlog));
+                                self.drop_queued(&queued);
                                 SocketResponse::Success(0)
-                            } else {
-                                log(&alloc::format!("SocketAPI: Socket fd {} cannot listen, not a TCP socket.", fd));
-                                SocketResponse::Error(105, "Only TCP sockets can listen".to_string())
-                            }
-                        } else {
-                            log(&alloc::format!("SocketAPI: Listen failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                            },
+                            Ok(NetStackResponse::QuotaExceeded(quota, limit)) => quota_error(quota, limit),
+                            Ok(NetStackResponse::Error(code)) => {
+                                log(&alloc::format!("SocketAPI: Failed to listen on fd {} in AetherNet. Error: {}", fd, code));
+                                SocketResponse::Error(code as i32, "Failed to listen in AetherNet".to_string())
+                            },
+                            _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during Listen".to_string()),
                         }
                     },
-                    SocketRequest::Accept { fd } => {
-                        // This would typically involve blocking and waiting for a connection.
-                        // In a non-blocking loop, aethernet-service would send an IPC message
-                        // to socket-api when a connection is accepted, which socket-api would then relay.
-                        // For now, it's conceptual and returns EWOULDBLOCK.
-                        log(&alloc::format!("SocketAPI: Accept on fd {} is conceptual; requires AetherNet callback.", fd));
-                        SocketResponse::Error(11, "Operation would block (EWOULDBLOCK)".to_string()) // EWOULDBLOCK
+                    None => {
+                        log(&alloc::format!("SocketAPI: Listen failed, bad file descriptor: {}", fd));
+                        SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
                     },
-                    SocketRequest::Connect { fd, addr, port } => {
-                        if let Some(socket_info) = sockets.get_mut(&fd) {
-                            if socket_info.socket_type == 2 { // UDP
-                                // For UDP, 'connect' sets the default remote peer for future `send` calls.
-                                // We use `NetStackRequest::SendTo` with empty data to conceptually set the peer.
-                                match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::SendTo(socket_info.net_socket_handle, addr, port, Vec::new())) {
-                                    Ok(NetStackResponse::Success) => {
-                                        log(&alloc::format!("SocketAPI: UDP socket fd {} connected to {}:{}", fd, addr[0], port));
-                                        SocketResponse::Success(0)
-                                    },
-                                    Ok(NetStackResponse::Error(code)) => {
-                                        log(&alloc::format!("SocketAPI: Failed to connect UDP socket fd {} via AetherNet. Error: {}", fd, code));
-                                        SocketResponse::Error(code as i32, "Failed to connect UDP socket via AetherNet".to_string())
-                                    },
-                                    _ => {
-                                        log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during UDP Connect for fd {}.", fd));
-                                        SocketResponse::Error(-1, "Unexpected response from AetherNet during UDP Connect".to_string())
-                                    },
-                                }
-                            } else if socket_info.socket_type == 1 { // TCP
-                                // For TCP, this should trigger a connection handshake in AetherNet.
-                                // NetStackRequest currently lacks a specific 'Connect' variant for TCP with remote_ip/port.
-                                // This would require extending NetStackRequest.
-                                log(&alloc::format!("SocketAPI: TCP Connect on fd {} to {}:{} is conceptual and requires NetStackRequest extension.", fd, addr[0], port));
-                                SocketResponse::Error(106, "TCP Connect not fully implemented yet".to_string())
-                            } else {
-                                log(&alloc::format!("SocketAPI: Unsupported socket type {} for connect on fd {}.
-", socket_info.socket_type, fd));
-                                SocketResponse::Error(100, "Unsupported socket type for connect".to_string())
-                            }
-                        } else {
-                            log(&alloc::format!("SocketAPI: Connect failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+            SocketRequest::Accept { fd } => {
+                match self.sockets.get(&fd) {
+                    Some(socket_info) if socket_info.is_listening => {
+                        let local_port = socket_info.local_port;
+                        // Normally the queue was filled as `net.connection_ready` came in, up to
+                        // the backlog. A connection that waited in a slot for room, or whose
+                        // event got lost, is picked up here.
+                        if let Err(response) = self.take_ready(fd) {
+                            return response;
+                        }
+                        let Some(new_fd) = self.sockets.get_mut(&fd).and_then(|socket_info| socket_info.accept_queue.pop_front()) else {
+                            // No connection waiting; the caller polls again.
+                            return SocketResponse::Error(11, "Operation would block (EWOULDBLOCK)".to_string()); // EWOULDBLOCK
+                        };
+                        // The pop made room: move the next waiting connection in now. A broken
+                        // listener is reported by the next `Accept`.
+                        let _ = self.take_ready(fd);
+                        let (remote_ip, remote_port) = self.sockets.get(&new_fd).and_then(|socket_info| socket_info.peer).unwrap_or(([0; 4], 0));
+                        // A connection in is checked like one out: the peer's address, and our port.
+                        let service = service_of(requester.sender, &mut self.init_chan, &mut self.service_names);
+                        if let Err(rule) = self.policy.check(service.as_deref(), remote_ip, local_port) {
+                            self.drop_queued(&[new_fd]);
+                            log(&alloc::format!("SocketAPI: Refused {}.{}.{}.{}:{} on fd {} by network policy: {}", remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3], remote_port, fd, rule));
+                            return SocketResponse::PolicyDenied { rule };
                         }
+                        log(&alloc::format!("SocketAPI: Accepted {}.{}.{}.{}:{} on fd {} as fd {}", remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3], remote_port, fd, new_fd));
+                        SocketResponse::Accepted { new_fd, remote_addr: remote_ip, remote_port }
+                    },
+                    Some(_) => SocketResponse::Error(22, "Socket is not listening".to_string()), // EINVAL
+                    None => {
+                        log(&alloc::format!("SocketAPI: Accept failed, bad file descriptor: {}", fd));
+                        SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
                     },
-                    SocketRequest::Send { fd, data } => {
-                        if let Some(socket_info) = sockets.get(&fd) {
-                            let net_req = if socket_info.socket_type == 1 { // TCP
-                                NetStackRequest::Send(socket_info.net_socket_handle, data)
-                            } else if socket_info.socket_type == 2 { // UDP (assuming connect has set a default peer)
-                                // AetherNet's `Send` is generic enough to handle UDP send to default peer
-                                NetStackRequest::Send(socket_info.net_socket_handle, data)
-                            } else {
-                                log(&alloc::format!("SocketAPI: Unsupported socket type {} for send on fd {}.
+                }
+            },
+            SocketRequest::Connect { fd, addr, port } => {
+                if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                    if socket_info.socket_type == 2 { // UDP
+                        // For UDP, 'connect' only sets the default remote peer for future `send` calls.
+                        log(&alloc::format!("SocketAPI: UDP socket fd {} connected to {}.{}.{}.{}:{}", fd, addr[0], addr[1], addr[2], addr[3], port));
+                        socket_info.peer = Some((addr, port));
+                        SocketResponse::Success(0)
+                    } else if socket_info.socket_type == 1 { // TCP
+                        if socket_info.non_blocking || socket_info.connect_pending.is_some() {
+                            return self.connect_tcp_non_blocking(fd, addr, port);
+                        }
+                        if self.connecting {
+                            return SocketResponse::Error(11, "Another connection is in progress".to_string()); // EAGAIN
+                        }
+                        self.connecting = true;
+                        let result = self.connect_tcp(fd, addr, port, DEFAULT_CONNECT_TIMEOUT_MS);
+                        self.connecting = false;
+                        match result {
+                            Ok(()) => SocketResponse::Success(0),
+                            Err(error) => connect_error(error),
+                        }
+                    } else {
+                        log(&alloc::format!("SocketAPI: Unsupported socket type {} for connect on fd {}.
 ", socket_info.socket_type, fd));
-                                return SocketResponse::Error(100, "Unsupported socket type for send".to_string());
-                            };
-
-                            match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&net_req) {
-                                Ok(NetStackResponse::Success) => {
-                                    log(&alloc::format!("SocketAPI: Sent {} bytes on fd {}", data.len(), fd));
-                                    SocketResponse::Success(data.len() as i32)
-                                },
-                                Ok(NetStackResponse::Error(code)) => {
-                                    log(&alloc::format!("SocketAPI: Failed to send on fd {} via AetherNet. Error: {}", fd, code));
-                                    SocketResponse::Error(code as i32, "Failed to send via AetherNet".to_string())
-                                },
-                                _ => {
-                                    log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Send for fd {}.
+                        SocketResponse::Error(100, "Unsupported socket type for connect".to_string())
+                    }
+                } else {
+                    log(&alloc::format!("SocketAPI: Connect failed, bad file descriptor: {}", fd));
+                    SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+            SocketRequest::ConnectHost { fd, hostname, port, attempt_timeout_ms } => {
+                if self.connecting {
+                    SocketResponse::Error(11, "Another connection is in progress".to_string()) // EAGAIN
+                } else {
+                    self.connecting = true;
+                    let response = self.connect_host(fd, &hostname, port, attempt_timeout_ms, requester);
+                    self.connecting = false;
+                    response
+                }
+            },
+            SocketRequest::GetPeerName { fd } => match self.sockets.get(&fd) {
+                Some(SocketInfo { peer: Some((addr, port)), .. }) => SocketResponse::PeerName { addr: *addr, port: *port },
+                Some(_) => SocketResponse::Error(107, "Socket is not connected".to_string()), // ENOTCONN
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::Send { fd, data } => {
+                if let Some(socket_info) = self.sockets.get(&fd) {
+                    let len = data.len();
+                    let net_req = if socket_info.socket_type == 1 { // TCP
+                        NetStackRequest::Send(socket_info.net_socket_handle, data)
+                    } else if socket_info.socket_type == 2 { // UDP, to the peer set by connect
+                        let Some((addr, port)) = socket_info.peer else {
+                            return SocketResponse::Error(89, "Destination address required".to_string()); // EDESTADDRREQ
+                        };
+                        NetStackRequest::SendTo(socket_info.net_socket_handle, addr, port, data)
+                    } else {
+                        log(&alloc::format!("SocketAPI: Unsupported socket type {} for send on fd {}.
+", socket_info.socket_type, fd));
+                        return SocketResponse::Error(100, "Unsupported socket type for send".to_string());
+                    };
+
+                    match self.net_chan.call(socket_info.owner, net_req) {
+                        Ok(NetStackResponse::Success) => {
+                            log(&alloc::format!("SocketAPI: Sent {} bytes on fd {}", len, fd));
+                            SocketResponse::Success(len as i32)
+                        },
+                        Ok(NetStackResponse::InterfaceDown) => SocketResponse::NetworkDown,
+                        Ok(NetStackResponse::Error(code)) => {
+                            log(&alloc::format!("SocketAPI: Failed to send on fd {} via AetherNet. Error: {}", fd, code));
+                            SocketResponse::Error(code as i32, "Failed to send via AetherNet".to_string())
+                        },
+                        _ => {
+                            log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Send for fd {}.
 ", fd));
-                                    SocketResponse::Error(-1, "Unexpected response from AetherNet during Send".to_string())
-                                },
-                            }
-                        } else {
-                            log(&alloc::format!("SocketAPI: Send failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                            SocketResponse::Error(-1, "Unexpected response from AetherNet during Send".to_string())
+                        },
+                    }
+                } else {
+                    log(&alloc::format!("SocketAPI: Send failed, bad file descriptor: {}", fd));
+                    SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+            SocketRequest::SendTo { fd, addr, port, data } => {
+                match self.sockets.get(&fd) {
+                    Some(socket_info) if socket_info.socket_type == 2 => {
+                        let len = data.len();
+                        match self.net_chan.call(socket_info.owner, NetStackRequest::SendTo(socket_info.net_socket_handle, addr, port, data)) {
+                            Ok(NetStackResponse::Success) => SocketResponse::Success(len as i32),
+                            Ok(NetStackResponse::InterfaceDown) => SocketResponse::NetworkDown,
+                            Ok(NetStackResponse::Error(120)) => SocketResponse::Error(11, "No free local port".to_string()), // EAGAIN
+                            Ok(NetStackResponse::Error(code)) => {
+                                log(&alloc::format!("SocketAPI: Failed to send datagram on fd {} via AetherNet. Error: {}", fd, code));
+                                SocketResponse::Error(code as i32, "Failed to send via AetherNet".to_string())
+                            },
+                            _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during SendTo".to_string()),
                         }
                     },
-                    SocketRequest::Recv { fd, len: _ } => { // len is a hint, actual data len from NetStack
-                        if let Some(socket_info) = sockets.get(&fd) {
-                            match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::Recv(socket_info.net_socket_handle)) {
-                                Ok(NetStackResponse::Data(data)) => {
-                                    log(&alloc::format!("SocketAPI: Received {} bytes on fd {}", data.len(), fd));
-                                    SocketResponse::Data(data)
-                                },
-                                Ok(NetStackResponse::Error(code)) => {
-                                    log(&alloc::format!("SocketAPI: Failed to receive on fd {} via AetherNet. Error: {}", fd, code));
-                                    SocketResponse::Error(code as i32, "Failed to receive via AetherNet".to_string())
-                                },
-                                _ => {
-                                    log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Recv for fd {}.
-", fd));
-                                    SocketResponse::Error(-1, "Unexpected response from AetherNet during Recv".to_string())
-                                },
-                            }
+                    Some(_) => SocketResponse::Error(100, "SendTo requires a UDP socket".to_string()),
+                    None => {
+                        log(&alloc::format!("SocketAPI: SendTo failed, bad file descriptor: {}", fd));
+                        SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                    },
+                }
+            },
+            SocketRequest::RecvAsync { fd, reply_chan } => match self.sockets.get_mut(&fd) {
+                Some(socket_info) if socket_info.is_listening => SocketResponse::Error(22, "Socket is listening".to_string()), // EINVAL
+                Some(socket_info) => {
+                    let watch = NetStackRequest::WatchRecv { handle: socket_info.net_socket_handle, reply_chan: self.ready_chan.id };
+                    match self.net_chan.call(socket_info.owner, watch) {
+                        Ok(NetStackResponse::Success) => {
+                            socket_info.recv_watcher = Some(reply_chan);
+                            SocketResponse::Success(0)
+                        },
+                        Ok(NetStackResponse::Error(code)) => SocketResponse::Error(code as i32, "Failed to watch the socket in AetherNet".to_string()),
+                        _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during RecvAsync".to_string()),
+                    }
+                },
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::JoinMulticast { fd, group } | SocketRequest::LeaveMulticast { fd, group } => {
+                let join = matches!(request, SocketRequest::JoinMulticast { .. });
+                match self.sockets.get(&fd) {
+                    Some(socket_info) if socket_info.socket_type == 2 => {
+                        let handle = socket_info.net_socket_handle;
+                        let net_req = if join {
+                            NetStackRequest::JoinMulticastGroup { handle, group }
                         } else {
-                            log(&alloc::format!("SocketAPI: Recv failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                            NetStackRequest::LeaveMulticastGroup { handle, group }
+                        };
+                        match self.net_chan.call(socket_info.owner, net_req) {
+                            Ok(NetStackResponse::Success) => {
+                                log(&alloc::format!("SocketAPI: Socket fd {} {} {}.{}.{}.{}", fd, if join { "joined" } else { "left" }, group[0], group[1], group[2], group[3]));
+                                SocketResponse::Success(0)
+                            },
+                            Ok(NetStackResponse::Error(110)) => SocketResponse::Error(22, "Not a multicast address".to_string()), // EINVAL
+                            Ok(NetStackResponse::InterfaceDown) => SocketResponse::NetworkDown,
+                            Ok(NetStackResponse::Error(code)) => SocketResponse::Error(code as i32, "Failed to change multicast membership in AetherNet".to_string()),
+                            _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during multicast membership change".to_string()),
                         }
                     },
-                    SocketRequest::Close { fd } => {
-                        if let Some(socket_info) = sockets.remove(&fd) {
-                            match net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::CloseSocket(socket_info.net_socket_handle)) {
-                                Ok(NetStackResponse::Success) => {
-                                    log(&alloc::format!("SocketAPI: Closed socket fd {}", fd));
-                                    SocketResponse::Success(0)
-                                },
-                                Ok(NetStackResponse::Error(code)) => {
-                                    log(&alloc::format!("SocketAPI: Failed to close socket fd {} in AetherNet. Error: {}", fd, code));
-                                    SocketResponse::Error(code as i32, "Failed to close socket in AetherNet".to_string())
-                                },
-                                _ => {
-                                    log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Close for fd {}.
+                    Some(_) => SocketResponse::Error(100, "Multicast requires a UDP socket".to_string()),
+                    None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+                }
+            },
+            SocketRequest::Recv { fd, len: _ } => { // len is a hint, actual data len from NetStack
+                if let Some(socket_info) = self.sockets.get(&fd) {
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::Recv(socket_info.net_socket_handle)) {
+                        Ok(NetStackResponse::Data(data)) => {
+                            log(&alloc::format!("SocketAPI: Received {} bytes on fd {}", data.len(), fd));
+                            SocketResponse::Data(data)
+                        },
+                        Ok(NetStackResponse::Error(code)) => {
+                            log(&alloc::format!("SocketAPI: Failed to receive on fd {} via AetherNet. Error: {}", fd, code));
+                            SocketResponse::Error(code as i32, "Failed to receive via AetherNet".to_string())
+                        },
+                        _ => {
+                            log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Recv for fd {}.
 ", fd));
-                                    SocketResponse::Error(-1, "Unexpected response from AetherNet during Close".to_string())
-                                },
+                            SocketResponse::Error(-1, "Unexpected response from AetherNet during Recv".to_string())
+                        },
+                    }
+                } else {
+                    log(&alloc::format!("SocketAPI: Recv failed, bad file descriptor: {}", fd));
+                    SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+            SocketRequest::RecvFrom { fd, len } => match self.sockets.get(&fd) {
+                Some(socket_info) if socket_info.socket_type == 2 => {
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::RecvFrom(socket_info.net_socket_handle)) {
+                        Ok(NetStackResponse::DataFrom { mut data, addr, port }) => {
+                            if !data.is_empty() {
+                                log(&alloc::format!("SocketAPI: Received {} bytes on fd {} from {}.{}.{}.{}:{}", data.len(), fd, addr[0], addr[1], addr[2], addr[3], port));
                             }
-                        } else {
-                            log(&alloc::format!("SocketAPI: Close failed, bad file descriptor: {}", fd));
-                            SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                            // The stack has taken the whole datagram; what doesn't fit is lost, as with recvfrom(2).
+                            data.truncate(len as usize);
+                            SocketResponse::DataFrom { data, addr, port }
+                        },
+                        Ok(NetStackResponse::Error(code)) => {
+                            log(&alloc::format!("SocketAPI: Failed to receive on fd {} via AetherNet. Error: {}", fd, code));
+                            SocketResponse::Error(code as i32, "Failed to receive via AetherNet".to_string())
+                        },
+                        _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during RecvFrom".to_string()),
+                    }
+                },
+                Some(_) => SocketResponse::Error(100, "RecvFrom requires a UDP socket".to_string()),
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::GetSocketInfo { fd } => match self.sockets.get(&fd) {
+                Some(socket_info) => match self.net_chan.call(socket_info.owner, NetStackRequest::GetSocketInfo(socket_info.net_socket_handle)) {
+                    Ok(NetStackResponse::SocketInfo { local_port, listener }) => {
+                        // Connections socket-api has queued are pending too.
+                        let listener = listener.map(|info| ListenerInfo { pending: info.pending + socket_info.accept_queue.len() as u32, ..info });
+                        SocketResponse::SocketInfo { ty: socket_info.socket_type, local_port, listener }
+                    },
+                    Ok(NetStackResponse::StreamInfo { local_port, send }) => SocketResponse::StreamInfo { local_port, send },
+                    Ok(NetStackResponse::Error(code)) => SocketResponse::Error(code as i32, "Failed to query socket in AetherNet".to_string()),
+                    _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during GetSocketInfo".to_string()),
+                },
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::SetSockOpt { fd, option: SocketOption::NonBlocking(on) } => match self.sockets.get_mut(&fd) {
+                Some(socket_info) if socket_info.socket_type == 1 => {
+                    socket_info.non_blocking = on;
+                    log(&alloc::format!("SocketAPI: Socket fd {} set {:?}", fd, SocketOption::NonBlocking(on)));
+                    SocketResponse::Success(0)
+                },
+                Some(_) => SocketResponse::Error(92, "Option only applies to TCP sockets".to_string()), // ENOPROTOOPT
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::SetSockOpt { fd, option } => match self.sockets.get(&fd) {
+                Some(socket_info) if socket_info.socket_type == 1 && !socket_info.is_listening => {
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::SetSockOpt { handle: socket_info.net_socket_handle, option }) {
+                        Ok(NetStackResponse::Success) => {
+                            log(&alloc::format!("SocketAPI: Socket fd {} set {:?}", fd, option));
+                            SocketResponse::Success(0)
+                        },
+                        Ok(NetStackResponse::Error(code)) => SocketResponse::Error(code as i32, "Failed to set socket option in AetherNet".to_string()),
+                        _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during SetSockOpt".to_string()),
+                    }
+                },
+                Some(_) => SocketResponse::Error(92, "Option only applies to connected TCP sockets".to_string()), // ENOPROTOOPT
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::Flush { fd } => match self.sockets.get(&fd) {
+                Some(socket_info) if socket_info.socket_type == 1 && !socket_info.is_listening => {
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::Flush(socket_info.net_socket_handle)) {
+                        Ok(NetStackResponse::Flushed { queued }) => SocketResponse::Success(queued as i32),
+                        Ok(NetStackResponse::Error(code)) => SocketResponse::Error(code as i32, "Failed to flush socket in AetherNet".to_string()),
+                        _ => SocketResponse::Error(-1, "Unexpected response from AetherNet during Flush".to_string()),
+                    }
+                },
+                Some(_) => SocketResponse::Error(95, "Flush requires a connected TCP socket".to_string()), // EOPNOTSUPP
+                None => SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+            },
+            SocketRequest::Close { fd } => {
+                if let Some(socket_info) = self.sockets.remove(&fd) {
+                    let queued: Vec<SocketFd> = socket_info.accept_queue.iter().copied().collect();
+                    self.drop_queued(&queued);
+                    match self.net_chan.call(socket_info.owner, NetStackRequest::CloseSocket(socket_info.net_socket_handle)) {
+                        Ok(NetStackResponse::Success) => {
+                            log(&alloc::format!("SocketAPI: Closed socket fd {}", fd));
+                            SocketResponse::Success(0)
+                        },
+                        Ok(NetStackResponse::Error(code)) => {
+                            log(&alloc::format!("SocketAPI: Failed to close socket fd {} in AetherNet. Error: {}", fd, code));
+                            SocketResponse::Error(code as i32, "Failed to close socket in AetherNet".to_string())
+                        },
+                        _ => {
+                            log(&alloc::format!("SocketAPI: Unexpected response from AetherNet during Close for fd {}.
+", fd));
+                            SocketResponse::Error(-1, "Unexpected response from AetherNet during Close".to_string())
+                        },
+                    }
+                } else {
+                    log(&alloc::format!("SocketAPI: Close failed, bad file descriptor: {}", fd));
+                    SocketResponse::Error(9, "Bad file descriptor".to_string()) // EBADF
+                }
+            },
+        }
+    }
+
+    /// Applies events from the bus: `net.policy` changes, which take effect for
+    /// the next operation with open sockets kept, and the network stack's
+    /// interface events.
+    fn handle_events(&mut self) {
+        while let Ok(Some(event_data)) = self.events_chan.recv_non_blocking() {
+            let event = match postcard::from_bytes::<Event>(&event_data) {
+                Ok(event) => event,
+                Err(_) => {
+                    log("SocketAPI: Ignoring a malformed event.");
+                    continue;
+                },
+            };
+            match event.topic.as_str() {
+                CONNECTION_READY_TOPIC => match postcard::from_bytes::<ConnectionReady>(&event.payload) {
+                    Ok(ready) => {
+                        // Listener handles are never reused, so an unknown one belongs to another task.
+                        let listener = self.sockets.iter().find(|(_, socket_info)| socket_info.is_listening && socket_info.net_socket_handle == ready.listener).map(|(fd, _)| *fd);
+                        if let Some(fd) = listener {
+                            let _ = self.take_ready(fd);
+                        }
+                    },
+                    Err(_) => log("SocketAPI: Ignoring a malformed net.connection_ready event."),
+                },
+                SOCKET_CLOSED_TOPIC => match postcard::from_bytes::<SocketClosed>(&event.payload) {
+                    Ok(closed) => self.mark_closed_by_network(closed.handle),
+                    Err(_) => log("SocketAPI: Ignoring a malformed net.socket_closed event."),
+                },
+                NET_DOWN_TOPIC => {
+                    self.network_up = false;
+                    log("SocketAPI: The network is down; connects and sends are refused until it is back.");
+                },
+                NET_UP_TOPIC => {
+                    self.network_up = true;
+                    log("SocketAPI: The network is up again.");
+                },
+                _ => match postcard::from_bytes::<SettingChanged>(&event.payload) {
+                    Ok(SettingChanged { key, value: SettingValue::Str(text) }) if key == POLICY_KEY => {
+                        if let Some(updated) = parse_policy(&text) {
+                            self.policy = updated;
                         }
                     },
+                    _ => log("SocketAPI: Ignoring unexpected event on the events channel."),
+                },
+            }
+        }
+    }
+
+    /// Relays the network stack's `DataReady` to the clients that asked with
+    /// `RecvAsync`, as `DataAvailable`. Each `RecvAsync` gets one.
+    fn handle_ready(&mut self) {
+        while let Ok(Some(data)) = self.ready_chan.recv_non_blocking() {
+            let handle = match postcard::from_bytes::<NetStackResponse>(&data) {
+                Ok(NetStackResponse::DataReady { handle }) => handle,
+                _ => {
+                    log("SocketAPI: Ignoring an unexpected message on the readiness channel.");
+                    continue;
+                },
+            };
+            // The fd may have been closed, or given a new network socket, since it was watched.
+            let Some((fd, socket_info)) = self.sockets.iter_mut().find(|(_, socket_info)| socket_info.net_socket_handle == handle) else { continue };
+            let Some(reply_chan) = socket_info.recv_watcher.take() else { continue };
+            if VNodeChannel::new(reply_chan).send(&SocketResponse::DataAvailable { fd: *fd }).is_err() {
+                log(&alloc::format!("SocketAPI: Could not tell channel {} that fd {} is readable.", reply_chan, fd));
+            }
+        }
+    }
+
+    /// Takes the connections established on listening `fd` off its listener in
+    /// the network stack and queues each as an fd of its own, until the queue
+    /// holds `backlog`; the rest wait in the listener's slots. The error is
+    /// the answer for an `Accept` that finds the listener broken.
+    fn take_ready(&mut self, fd: SocketFd) -> Result<(), SocketResponse> {
+        loop {
+            let (listener, owner, local_port) = match self.sockets.get(&fd) {
+                Some(socket_info) if socket_info.is_listening && socket_info.accept_queue.len() < socket_info.backlog as usize => {
+                    (socket_info.net_socket_handle, socket_info.owner, socket_info.local_port)
+                },
+                _ => return Ok(()),
+            };
+            match self.net_chan.call(owner, NetStackRequest::Accept(listener)) {
+                Ok(NetStackResponse::Accepted { handle, remote_ip, remote_port }) => {
+                    let new_fd = self.next_fd;
+                    self.next_fd = SocketFd::from_raw(new_fd.raw() + 1);
+                    self.sockets.insert(new_fd, SocketInfo::new(handle, owner, 1, local_port, Some((remote_ip, remote_port))));
+                    if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                        socket_info.accept_queue.push_back(new_fd);
+                    }
+                    log(&alloc::format!("SocketAPI: Queued {}.{}.{}.{}:{} on fd {} as fd {}", remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3], remote_port, fd, new_fd));
+                },
+                Ok(NetStackResponse::Error(114)) => return Ok(()), // No connection waiting
+                Ok(NetStackResponse::Error(code)) => {
+                    log(&alloc::format!("SocketAPI: Failed to accept on fd {} in AetherNet. Error: {}", fd, code));
+                    return Err(SocketResponse::Error(code as i32, "Failed to accept in AetherNet".to_string()));
+                },
+                _ => return Err(SocketResponse::Error(-1, "Unexpected response from AetherNet during Accept".to_string())),
+            }
+        }
+    }
+
+    /// Resets queued connections nobody will accept and frees their fds.
+    fn drop_queued(&mut self, queued: &[SocketFd]) {
+        for fd in queued {
+            if let Some(socket_info) = self.sockets.remove(fd) {
+                let _ = self.net_chan.call(socket_info.owner, NetStackRequest::CloseSocket(socket_info.net_socket_handle));
+            }
+        }
+    }
+
+    /// Marks the fd whose network socket the stack closed. Its operations fail
+    /// with ECONNABORTED from now on, until the client closes it.
+    fn mark_closed_by_network(&mut self, handle: u32) {
+        // Network handles are never reused, so an unknown one belongs to another task.
+        if let Some((fd, socket_info)) = self.sockets.iter_mut().find(|(_, socket_info)| socket_info.net_socket_handle == handle) {
+            socket_info.closed_by_network = true;
+            log(&alloc::format!("SocketAPI: Socket fd {} was closed by the network going down.", fd));
+        }
+    }
+
+    fn dns_chan(&mut self) -> &mut VNodeChannel {
+        self.dns_chan.get_or_insert_with(|| {
+            log("SocketAPI: Opening the channel to svc://dns-resolver.");
+            VNodeChannel::new(channels::DNS_RESOLVER)
+        })
+    }
+
+    /// All addresses of `hostname`, most preferred first.
+    fn resolve(&mut self, hostname: &str) -> Result<Vec<[u8; 4]>, String> {
+        let request = DnsRequest::ResolveAll { hostname: hostname.to_string() };
+        self.dns_chan().send(&request).map_err(|_| "Failed to reach the resolver".to_string())?;
+        let deadline = now_ms() + RESOLVE_TIMEOUT_MS;
+        loop {
+            match self.dns_chan().recv_non_blocking() {
+                Ok(Some(data)) => match postcard::from_bytes::<DnsResponse>(&data) {
+                    Ok(DnsResponse::ResolvedAddresses { hostname: name, addresses }) if name == hostname => return Ok(addresses),
+                    // A late answer to a lookup that timed out earlier, or not one to a lookup at all.
+                    Ok(DnsResponse::ResolvedAddresses { .. }) | Ok(DnsResponse::ResolvedHostname { .. }) | Ok(DnsResponse::CacheFlushed { .. }) => continue,
+                    Ok(DnsResponse::NotFound { .. }) => return Err("No such host".to_string()),
+                    Ok(DnsResponse::Error { message }) => return Err(message),
+                    Err(_) => return Err("Malformed reply from the resolver".to_string()),
+                },
+                Ok(None) if now_ms() >= deadline => return Err("The resolver did not answer".to_string()),
+                Ok(None) => self.wait(),
+                Err(_) => return Err("Failed to reach the resolver".to_string()),
+            }
+        }
+    }
+
+    /// Connects TCP socket `fd` to `addr:port`, waiting up to `timeout_ms` for
+    /// the handshake. On failure the socket gets a fresh net-stack socket, so
+    /// it can be connected again.
+    fn connect_tcp(&mut self, fd: SocketFd, addr: [u8; 4], port: u16, timeout_ms: u32) -> Result<(), AttemptError> {
+        let (handle, owner) = match self.sockets.get(&fd) {
+            Some(socket_info) => (socket_info.net_socket_handle, socket_info.owner),
+            None => return Err(AttemptError::Other(9, "Bad file descriptor".to_string())), // EBADF
+        };
+        self.start_connect(handle, owner, addr, port)?;
+        let deadline = now_ms() + timeout_ms as u64;
+        let result = loop {
+            match self.connect_state(handle, owner) {
+                Ok(ConnectState::Established) => break Ok(()),
+                Ok(ConnectState::Refused) => break Err(AttemptError::Refused),
+                Ok(ConnectState::Connecting) if now_ms() >= deadline => break Err(AttemptError::TimedOut),
+                Ok(ConnectState::Connecting) => self.wait(),
+                Err(error) => break Err(error),
+            }
+            // The client may have closed the socket while we waited, or the network gone down.
+            match self.sockets.get(&fd) {
+                None => return Err(AttemptError::Other(9, "Socket closed during connect".to_string())), // EBADF
+                Some(socket_info) if socket_info.closed_by_network => return Err(AttemptError::NetworkDown),
+                Some(_) => {},
+            }
+        };
+        self.finish_connect(fd, addr, port, &result);
+        result
+    }
+
+    /// A TCP `Connect` on a non-blocking socket. The first answers EINPROGRESS
+    /// once the SYN is out; the same `Connect` again answers with the outcome,
+    /// or EALREADY while the handshake goes on. It times out like a blocking
+    /// one, `DEFAULT_CONNECT_TIMEOUT_MS` after the first.
+    fn connect_tcp_non_blocking(&mut self, fd: SocketFd, addr: [u8; 4], port: u16) -> SocketResponse {
+        let (handle, owner, pending, peer) = match self.sockets.get(&fd) {
+            Some(socket_info) => (socket_info.net_socket_handle, socket_info.owner, socket_info.connect_pending, socket_info.peer),
+            None => return SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+        };
+        let first = match pending {
+            Some(pending) if (pending.addr, pending.port) != (addr, port) => {
+                return SocketResponse::Error(114, "A connection to another address is in progress".to_string()); // EALREADY
+            },
+            Some(_) => false,
+            None if peer.is_some() => return SocketResponse::Error(106, "Socket is already connected".to_string()), // EISCONN
+            None => {
+                if let Err(error) = self.start_connect(handle, owner, addr, port) {
+                    return connect_error(error);
+                }
+                true
+            },
+        };
+        let deadline_ms = pending.map_or(now_ms() + DEFAULT_CONNECT_TIMEOUT_MS as u64, |pending| pending.deadline_ms);
+        let result = match self.connect_state(handle, owner) {
+            Ok(ConnectState::Connecting) if now_ms() < deadline_ms => {
+                if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                    socket_info.connect_pending = Some(PendingConnect { addr, port, deadline_ms });
+                }
+                return if first {
+                    SocketResponse::Error(115, "Operation now in progress".to_string()) // EINPROGRESS
+                } else {
+                    SocketResponse::Error(114, "Connection already in progress".to_string()) // EALREADY
                 };
-                client_chan.send(&response).unwrap_or_else(|_| log("SocketAPI: Failed to send response to client."));
-            } else {
-                log("SocketAPI: Failed to deserialize SocketRequest.");
+            },
+            Ok(ConnectState::Connecting) => Err(AttemptError::TimedOut),
+            Ok(ConnectState::Established) => Ok(()),
+            Ok(ConnectState::Refused) => Err(AttemptError::Refused),
+            Err(error) => Err(error),
+        };
+        if let Some(socket_info) = self.sockets.get_mut(&fd) {
+            socket_info.connect_pending = None;
+        }
+        self.finish_connect(fd, addr, port, &result);
+        match result {
+            Ok(()) => SocketResponse::Success(0),
+            Err(error) => connect_error(error),
+        }
+    }
+
+    /// Has the network stack send the SYN of `owner`'s `handle` to `addr:port`.
+    fn start_connect(&mut self, handle: u32, owner: u64, addr: [u8; 4], port: u16) -> Result<(), AttemptError> {
+        let connect = NetStackRequest::Connect { handle, remote_ip: addr, remote_port: port };
+        match self.net_chan.call(owner, connect) {
+            Ok(NetStackResponse::Success) => Ok(()),
+            Ok(NetStackResponse::InterfaceDown) => Err(AttemptError::NetworkDown),
+            // The stack's 115 is not EINPROGRESS; the socket can't connect in its state.
+            Ok(NetStackResponse::Error(115)) => Err(AttemptError::Other(106, "Socket is already connected or listening".to_string())), // EISCONN
+            Ok(NetStackResponse::Error(code)) => Err(AttemptError::Other(code as i32, "Failed to connect in AetherNet".to_string())),
+            _ => Err(AttemptError::Other(-1, "Unexpected response from AetherNet during Connect".to_string())),
+        }
+    }
+
+    fn connect_state(&mut self, handle: u32, owner: u64) -> Result<ConnectState, AttemptError> {
+        match self.net_chan.call(owner, NetStackRequest::ConnectStatus(handle)) {
+            Ok(NetStackResponse::Connection(state)) => Ok(state),
+            Ok(NetStackResponse::Error(code)) => Err(AttemptError::Other(code as i32, "Failed to query the connection in AetherNet".to_string())),
+            _ => Err(AttemptError::Other(-1, "Unexpected response from AetherNet during Connect".to_string())),
+        }
+    }
+
+    /// Records the peer of a connect that succeeded, or gives a failed one's
+    /// socket a fresh net-stack socket.
+    fn finish_connect(&mut self, fd: SocketFd, addr: [u8; 4], port: u16, result: &Result<(), AttemptError>) {
+        match result {
+            Ok(()) => {
+                if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                    socket_info.peer = Some((addr, port));
+                }
+                log(&alloc::format!("SocketAPI: TCP socket fd {} connected to {}.{}.{}.{}:{}", fd, addr[0], addr[1], addr[2], addr[3], port));
+            },
+            Err(_) => self.reset_tcp(fd),
+        }
+    }
+
+    /// Replaces the net-stack socket of `fd` after a failed connect, which may
+    /// have left it mid-handshake.
+    fn reset_tcp(&mut self, fd: SocketFd) {
+        let owner = match self.sockets.get(&fd) {
+            Some(socket_info) if !socket_info.closed_by_network => socket_info.owner,
+            _ => return, // Nothing left to replace; the fd only waits for its Close
+        };
+        match self.net_chan.call(owner, NetStackRequest::OpenSocket(0, 0)) {
+            Ok(NetStackResponse::SocketOpened(new_net_handle)) => {
+                if let Some(socket_info) = self.sockets.get_mut(&fd) {
+                    let old_net_handle = core::mem::replace(&mut socket_info.net_socket_handle, new_net_handle);
+                    let _ = self.net_chan.call(owner, NetStackRequest::CloseSocket(old_net_handle));
+                }
+            },
+            _ => log(&alloc::format!("SocketAPI: Could not replace the net-stack socket of fd {} after a failed connect.", fd)),
+        }
+    }
+
+    /// `ConnectHost`: resolves `hostname` and tries its addresses in order.
+    fn connect_host(&mut self, fd: SocketFd, hostname: &str, port: u16, attempt_timeout_ms: u32, requester: IpcCreds) -> SocketResponse {
+        match self.sockets.get(&fd) {
+            Some(socket_info) if socket_info.socket_type != 1 => return SocketResponse::Error(100, "ConnectHost requires a TCP socket".to_string()),
+            Some(socket_info) if socket_info.is_listening => return SocketResponse::Error(22, "Socket is listening".to_string()), // EINVAL
+            Some(_) => {},
+            None => return SocketResponse::Error(9, "Bad file descriptor".to_string()), // EBADF
+        }
+        let addresses = match self.resolve(hostname) {
+            Ok(addresses) if !addresses.is_empty() => addresses,
+            Ok(_) => return SocketResponse::ResolveFailed { hostname: hostname.to_string(), reason: "No such host".to_string() },
+            Err(reason) => {
+                log(&alloc::format!("SocketAPI: Could not resolve {}: {}", hostname, reason));
+                return SocketResponse::ResolveFailed { hostname: hostname.to_string(), reason };
+            },
+        };
+        let timeout_ms = if attempt_timeout_ms == 0 { DEFAULT_CONNECT_TIMEOUT_MS } else { attempt_timeout_ms };
+        let service = service_of(requester.sender, &mut self.init_chan, &mut self.service_names);
+        let mut attempts = Vec::new();
+        for addr in addresses {
+            let result = match self.policy.check(service.as_deref(), addr, port) {
+                Err(rule) => Err(AttemptError::PolicyDenied { rule }),
+                Ok(()) => self.connect_tcp(fd, addr, port, timeout_ms),
+            };
+            match result {
+                Ok(()) => return SocketResponse::ConnectedHost { addr, port },
+                Err(AttemptError::NetworkDown) => return SocketResponse::NetworkDown,
+                Err(error) => {
+                    let attempt = ConnectAttempt { addr, error };
+                    log(&alloc::format!("SocketAPI: Connecting fd {} to {} failed at {}", fd, hostname, attempt));
+                    // The socket is gone; the other addresses can't be tried either.
+                    let closed = matches!(attempt.error, AttemptError::Other(9, _));
+                    attempts.push(attempt);
+                    if closed {
+                        break;
+                    }
+                },
             }
         }
-        
-        // TODO: In a more complete implementation, this V-Node would also need to monitor
-        // the 'net_chan' for incoming unsolicited messages from aethernet-service (e.g.,
-        // for accepted connections, or asynchronous incoming data for non-blocking sockets).
+        SocketResponse::ConnectFailed { attempts }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Network policy: read from svc://settings, kept current through svc://event-bus,
+    // which delivers changes on our event channel, along with the network stack's
+    // interface events. svc://init-service names the caller's service. The network
+    // stack tells us on SOCKET_API_READY which sockets watched for `RecvAsync` are readable.
+    let mut settings_chan = VNodeChannel::new(channels::SETTINGS);
+    let mut event_bus_chan = VNodeChannel::new(channels::EVENT_BUS);
+    let events_chan = VNodeChannel::new(channels::SOCKET_API_EVENTS);
+
+    log("Socket API V-Node starting up...");
+
+    let policy = fetch_policy(&mut settings_chan).unwrap_or_default();
+    let subscribe = EventBusRequest::Subscribe { topic_prefix: alloc::format!("settings.{}", POLICY_KEY), reply_chan: events_chan.id };
+    if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+        log("SocketAPI: Failed to subscribe to policy changes; restart socket-api to apply them.");
+    }
+    let subscribe = EventBusRequest::Subscribe { topic_prefix: "net.".to_string(), reply_chan: events_chan.id };
+    if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+        log("SocketAPI: Failed to subscribe to interface events; sockets closed by the network will look open.");
+    }
+
+    let mut net_chan = VNodeChannel::new(channels::NET_STACK); // svc://aethernet-service
+    // The interface may have been taken down before socket-api started.
+    let network_up = !matches!(
+        net_chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::GetInterface),
+        Ok(NetStackResponse::Interface(InterfaceInfo { up: false, .. }))
+    );
+
+    let mut api = SocketApi {
+        client_chan: VNodeChannel::new(channels::SOCKET_API), // Requests from client V-Nodes
+        net_chan: NetStackChannel(net_chan),
+        init_chan: VNodeChannel::new(channels::INIT_SERVICE),
+        dns_chan: None,
+        policy,
+        service_names: BTreeMap::new(),
+        next_fd: SocketFd::from_raw(1),
+        sockets: BTreeMap::new(),
+        connecting: false,
+        events_chan,
+        ready_chan: VNodeChannel::new(channels::SOCKET_API_READY),
+        network_up,
+    };
+
+    loop {
+        // 1. Process incoming requests from client V-Nodes
+        api.serve_one();
+
+        // 2. Apply policy changes and interface events
+        api.handle_events();
+
+        // 3. Pass on which watched sockets have data
+        api.handle_ready();
 
         unsafe { syscall3(SYS_TIME, 0, 0, 0); } // Yield to other V-Nodes
     }
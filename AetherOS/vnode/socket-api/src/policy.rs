@@ -0,0 +1,123 @@
+// vnode/socket-api/src/policy.rs
+
+//! Per-service outgoing network policy.
+//!
+//! The policy comes from the `net.policy` setting, one entry per service
+//! separated by `;`:
+//!
+//! ```text
+//! mail-service=allow,deny 10.0.0.0/8;updater=deny,allow 192.168.1.0/24:443
+//! ```
+//!
+//! An entry is the service name, `=`, its default action, then its rules in
+//! order. A rule is `allow` or `deny`, an IPv4 network in CIDR notation and
+//! optionally `:<port>` or `:<first>-<last>`. The first matching rule decides;
+//! the default applies when none matches. The entry named `*` covers services
+//! without their own entry, and tasks whose service couldn't be determined.
+//! Without a `*` entry, such tasks are unrestricted.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::ipc::socket_ipc::{NetRule, PolicyAction, ServicePolicy};
+
+/// Entry applied to services without their own.
+pub const FALLBACK_SERVICE: &str = "*";
+
+#[derive(Debug, Default)]
+pub struct NetPolicy {
+    services: Vec<ServicePolicy>,
+}
+
+impl NetPolicy {
+    /// Parses the `net.policy` setting. An empty string is an empty policy,
+    /// which allows everything.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut services: Vec<ServicePolicy> = Vec::new();
+        for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (service, body) = entry.split_once('=').ok_or_else(|| format!("'{}': expected <service>=<default>[,<rule>...]", entry))?;
+            let service = service.trim();
+            if service.is_empty() {
+                return Err(format!("'{}': missing service name", entry));
+            }
+            if services.iter().any(|s| s.service == service) {
+                return Err(format!("{}: listed more than once", service));
+            }
+            let mut parts = body.split(',').map(str::trim);
+            let default = parse_action(parts.next().unwrap_or(""))
+                .ok_or_else(|| format!("{}: default must be allow or deny", service))?;
+            let rules = parts.map(|rule| parse_rule(rule).map_err(|e| format!("{}: '{}': {}", service, rule, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            services.push(ServicePolicy { service: service.to_string(), default, rules });
+        }
+        Ok(Self { services })
+    }
+
+    pub fn services(&self) -> &[ServicePolicy] {
+        &self.services
+    }
+
+    /// Checks an operation on `addr:port` by `service` (`None` if unknown).
+    /// Returns the description of the rule that denied it.
+    pub fn check(&self, service: Option<&str>, addr: [u8; 4], port: u16) -> Result<(), String> {
+        let policy = service.and_then(|name| self.services.iter().find(|s| s.service == name))
+            .or_else(|| self.services.iter().find(|s| s.service == FALLBACK_SERVICE));
+        let policy = match policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        match policy.rules.iter().enumerate().find(|(_, rule)| rule.matches(addr, port)) {
+            Some((_, rule)) if rule.action == PolicyAction::Allow => Ok(()),
+            Some((index, rule)) => Err(format!("{} rule {} ({})", policy.service, index + 1, rule)),
+            None if policy.default == PolicyAction::Allow => Ok(()),
+            None => Err(format!("{} default deny", policy.service)),
+        }
+    }
+}
+
+fn parse_action(text: &str) -> Option<PolicyAction> {
+    match text {
+        "allow" => Some(PolicyAction::Allow),
+        "deny" => Some(PolicyAction::Deny),
+        _ => None,
+    }
+}
+
+fn parse_rule(text: &str) -> Result<NetRule, String> {
+    let (action, target) = text.split_once(' ').ok_or("expected <allow|deny> <cidr>[:<ports>]")?;
+    let action = parse_action(action).ok_or("action must be allow or deny")?;
+    let target = target.trim();
+    let (cidr, ports) = match target.split_once(':') {
+        Some((cidr, ports)) => (cidr, Some(ports)),
+        None => (target, None),
+    };
+    let (addr, prefix_len) = cidr.split_once('/').ok_or("network must be in CIDR notation, e.g. 10.0.0.0/8")?;
+    let network = parse_ipv4(addr).ok_or("invalid IPv4 address")?;
+    let prefix_len = prefix_len.parse::<u8>().ok().filter(|len| *len <= 32).ok_or("prefix length must be 0-32")?;
+    let (first_port, last_port) = match ports {
+        None => (0, u16::MAX),
+        Some(ports) => {
+            let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+            match (first.parse::<u16>(), last.parse::<u16>()) {
+                (Ok(first), Ok(last)) if first <= last => (first, last),
+                _ => return Err("ports must be <port> or <first>-<last>".to_string()),
+            }
+        }
+    };
+    Ok(NetRule { action, network, prefix_len, first_port, last_port })
+}
+
+fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut octets = [0u8; 4];
+    let mut parts = text.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(octets)
+}
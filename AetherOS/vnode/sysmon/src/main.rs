@@ -0,0 +1,221 @@
+// vnode/sysmon/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use alloc::format;
+use alloc::string::ToString;
+
+use common::channels;
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
+use common::ipc::init_ipc::{InitRequest, InitResponse};
+use common::ipc::metrics_ipc::{MetricSample, MetricsRequest, MetricsResponse};
+use common::ipc::model_runtime_ipc::{InferRequest, InferResponse};
+use common::ipc::net_ipc::{NetStackRequest, NetStackResponse};
+use common::ipc::registry_ipc::{RegistryRequest, RegistryResponse};
+use common::ipc::sysmon_ipc::{SysmonRequest, SysmonResponse};
+use common::ipc::shell_ipc::{ShellRequest, ShellResponse};
+use common::ipc::vfs_ipc::{VfsRequest, VfsResponse};
+use common::metrics::{self, Counter, Registry};
+use common::ipc::ui_protocol::{UiRequest, UiResponse};
+use common::startup::{self, SELF_CHANNEL};
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+/// The services sysmon scrapes. Each wraps `MetricsRequest` in its own request enum.
+#[derive(Clone, Copy)]
+enum Target {
+    NetStack,
+    Vfs,
+    Compositor,
+    Registry,
+    ModelRuntime,
+}
+
+impl Target {
+    const ALL: [Target; 5] = [Target::NetStack, Target::Vfs, Target::Compositor, Target::Registry, Target::ModelRuntime];
+
+    fn name(self) -> &'static str {
+        match self {
+            Target::NetStack => "net-stack",
+            Target::Vfs => "vfs",
+            Target::Compositor => "display-compositor",
+            Target::Registry => "registry",
+            Target::ModelRuntime => "model-runtime",
+        }
+    }
+
+    fn scrape(self, chan: &mut VNodeChannel) -> Option<Vec<MetricSample>> {
+        let response = match self {
+            Target::NetStack => match chan.send_and_recv::<NetStackRequest, NetStackResponse>(&NetStackRequest::Metrics(MetricsRequest::Scrape)) {
+                Ok(NetStackResponse::Metrics(response)) => response,
+                _ => return None,
+            },
+            Target::Vfs => match chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Metrics(MetricsRequest::Scrape)) {
+                Ok(VfsResponse::Metrics(response)) => response,
+                _ => return None,
+            },
+            Target::Compositor => match chan.send_and_recv::<UiRequest, UiResponse>(&UiRequest::Metrics(MetricsRequest::Scrape)) {
+                Ok(UiResponse::Metrics(response)) => response,
+                _ => return None,
+            },
+            Target::Registry => match chan.send_and_recv::<RegistryRequest, RegistryResponse>(&RegistryRequest::Metrics(MetricsRequest::Scrape)) {
+                Ok(RegistryResponse::Metrics(response)) => response,
+                _ => return None,
+            },
+            Target::ModelRuntime => match chan.send_and_recv::<InferRequest, InferResponse>(&InferRequest::Metrics(MetricsRequest::Scrape)) {
+                Ok(InferResponse::Metrics(response)) => response,
+                _ => return None,
+            },
+        };
+        let MetricsResponse::Metrics(samples) = response;
+        Some(samples)
+    }
+}
+
+struct Source {
+    target: Target,
+    chan: VNodeChannel,
+    failures: Counter,
+}
+
+struct Sysmon {
+    client_chan: VNodeChannel,
+    init_chan: VNodeChannel, // For BootReport
+    shell_chan: VNodeChannel, // For SlowCommands
+    sources: Vec<Source>,
+    metrics: Registry,
+    scrapes: Counter,
+}
+
+impl Sysmon {
+    /// `target_chans` lists the channel of each `Target::ALL` entry, in order.
+    fn new(client_chan_id: u32, init_chan_id: u32, shell_chan_id: u32, target_chans: [u32; 5]) -> Self {
+        let client_chan = VNodeChannel::new(client_chan_id);
+        let init_chan = VNodeChannel::new(init_chan_id);
+        let shell_chan = VNodeChannel::new(shell_chan_id);
+        log("Sysmon: Initializing...");
+
+        let mut metrics = Registry::new("sysmon");
+        let scrapes = metrics.counter("sysmon_scrapes_total", "Combined scrapes served.");
+        let sources = Target::ALL.iter().zip(target_chans).map(|(target, chan_id)| Source {
+            target: *target,
+            chan: VNodeChannel::new(chan_id),
+            failures: metrics.counter_with("sysmon_scrape_failures_total", "Scrapes a service didn't answer.", &[("target", target.name())]),
+        }).collect();
+
+        Self { client_chan, init_chan, shell_chan, sources, metrics, scrapes }
+    }
+
+    /// Scrapes every source in turn. A service that doesn't answer is logged
+    /// and counted, and the report goes out without it.
+    fn collect(&mut self) -> Vec<MetricSample> {
+        self.scrapes.inc();
+        let mut samples = Vec::new();
+        for source in &mut self.sources {
+            match source.target.scrape(&mut source.chan) {
+                Some(scraped) => samples.extend(scraped),
+                None => {
+                    log(&format!("Sysmon: {} did not answer the metrics scrape.", source.target.name()));
+                    source.failures.inc();
+                },
+            }
+        }
+        samples.extend(self.metrics.scrape());
+        samples
+    }
+
+    fn boot_report(&mut self) -> SysmonResponse {
+        match self.init_chan.send_and_recv::<InitRequest, InitResponse>(&InitRequest::BootReport) {
+            Ok(InitResponse::BootReport(report)) => SysmonResponse::Text(report.render_text()),
+            _ => {
+                log("Sysmon: init-service did not answer the boot report request.");
+                SysmonResponse::Text("boot: unknown (init-service did not answer)\n".to_string())
+            },
+        }
+    }
+
+    fn slow_commands(&mut self, limit: u32) -> SysmonResponse {
+        let timings = match self.shell_chan.send_and_recv::<ShellRequest, ShellResponse>(&ShellRequest::GetTimings { limit }) {
+            Ok(ShellResponse::Timings(timings)) => timings,
+            _ => {
+                log("Sysmon: The shell did not answer the timings request.");
+                return SysmonResponse::Text("commands: unknown (shell did not answer)\n".to_string());
+            },
+        };
+        let mut text = format!("slowest {} of the recent shell commands:\n", timings.len());
+        for timing in &timings {
+            text.push_str(&format!("{:>8}.{:03}ms {:>5} ipc  {}\n", timing.elapsed_nanos / 1_000_000, timing.elapsed_nanos / 1_000 % 1_000, timing.round_trips, timing.line));
+        }
+        SysmonResponse::Text(text)
+    }
+
+    fn handle_request(&mut self, request: SysmonRequest) -> SysmonResponse {
+        match request {
+            SysmonRequest::Scrape => SysmonResponse::Metrics(self.collect()),
+            SysmonRequest::ScrapeText => SysmonResponse::Text(metrics::render_text(&self.collect())),
+            SysmonRequest::BootReport => self.boot_report(),
+            SysmonRequest::SlowCommands { limit } => self.slow_commands(limit),
+        }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("Sysmon: Entering main event loop.");
+        loop {
+            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+                if let Ok(request) = postcard::from_bytes::<SysmonRequest>(&req_data) {
+                    let response = self.handle_request(request);
+                    self.client_chan.send(&response).unwrap_or_else(|_| log("Sysmon: Failed to send response to client."));
+                } else {
+                    log("Sysmon: Failed to deserialize SysmonRequest from client.");
+                }
+            }
+
+            // Yield to other V-Nodes to prevent busy-waiting
+            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init, falling back to the well-known IDs: sysmon
+    // requests, init-service and the shell; then the services it scrapes
+    let assigned = startup::channels();
+    let channel = |name: &str, default: u32| assigned.get(name).copied().unwrap_or(default);
+    let mut sysmon = Sysmon::new(
+        channel(SELF_CHANNEL, channels::SYSMON),
+        channel("init-service", channels::INIT_SERVICE),
+        channel("shell", channels::SHELL),
+        [
+            channel("aethernet-service", channels::NET_STACK),
+            channel("vfs", channels::VFS),
+            channel("compositor", channels::DISPLAY_COMPOSITOR),
+            channel("registry", channels::REGISTRY),
+            channel("model-runtime", channels::MODEL_RUNTIME),
+        ],
+    );
+    sysmon.run_loop();
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&alloc::format!("Sysmon V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
@@ -0,0 +1,96 @@
+// vnode/vfs/src/access.rs
+
+//! Who may touch which path.
+//!
+//! `/home/<aid hex>/...` belongs to that identity. A caller with no identity
+//! bound gets `Unauthenticated` there, and any other identity gets EACCES.
+//! The system identity is no superuser: in another identity's home it may
+//! only reach `mail/`, where the mail service keeps that identity's
+//! mailboxes. Everything outside `/home/<aid hex>` is unrestricted for now.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::ToString;
+
+use crate::ipc::session_ipc::{self, AidBytes, SYSTEM_AID};
+use crate::ipc::vfs_ipc::{self, VfsResponse};
+
+/// The directory in every home that the system identity may use: the mail
+/// service's mailboxes, `/home/<aid hex>/mail/<mailbox>/`.
+pub const MAIL_DIR: &str = "mail";
+
+/// Checks whether `caller` may access `path`.
+pub fn check_path(caller: Option<&AidBytes>, path: &str) -> Result<(), VfsResponse> {
+    if let Err(reason) = vfs_ipc::validate_path(path) {
+        return Err(VfsResponse::InvalidName { path: path.to_string(), reason });
+    }
+    let mut components = match path.strip_prefix("/home/") {
+        Some(rest) => rest.split('/').filter(|component| !component.is_empty()),
+        None => return Ok(()),
+    };
+    let Some(owner) = components.next() else {
+        return Ok(()); // Listing /home itself is allowed
+    };
+    match caller {
+        None => Err(VfsResponse::Unauthenticated),
+        Some(aid) if session_ipc::aid_to_hex(aid) == owner => Ok(()),
+        Some(aid) if *aid == SYSTEM_AID && components.next() == Some(MAIL_DIR) => Ok(()),
+        Some(_) => Err(VfsResponse::Error { code: 13, message: format!("Permission denied: {}", path) }), // EACCES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    const ALICE: AidBytes = [0xaa; 32];
+    const BOB: AidBytes = [0xbb; 32];
+
+    fn home(aid: &AidBytes, rest: &str) -> String {
+        format!("{}{}", session_ipc::home_dir(aid), rest)
+    }
+
+    fn denied(result: Result<(), VfsResponse>) -> bool {
+        matches!(result, Err(VfsResponse::Error { code: 13, .. }))
+    }
+
+    #[test]
+    fn identities_see_disjoint_homes() {
+        for rest in ["", "/", "/notes.txt", "/mail/inbox/1.msg"] {
+            assert!(check_path(Some(&ALICE), &home(&ALICE, rest)).is_ok(), "{}", rest);
+            assert!(check_path(Some(&BOB), &home(&BOB, rest)).is_ok(), "{}", rest);
+            assert!(denied(check_path(Some(&ALICE), &home(&BOB, rest))), "{}", rest);
+            assert!(denied(check_path(Some(&BOB), &home(&ALICE, rest))), "{}", rest);
+        }
+    }
+
+    #[test]
+    fn no_identity_is_unauthenticated_in_homes() {
+        assert!(matches!(check_path(None, &home(&ALICE, "/notes.txt")), Err(VfsResponse::Unauthenticated)));
+        assert!(matches!(check_path(None, &home(&ALICE, "/mail/inbox/1.msg")), Err(VfsResponse::Unauthenticated)));
+        assert!(check_path(None, "/home").is_ok());
+        assert!(check_path(None, "/home/").is_ok());
+        assert!(check_path(None, "/etc/aether/config.toml").is_ok());
+    }
+
+    #[test]
+    fn system_identity_only_reaches_mail() {
+        for rest in ["/mail", "/mail/", "/mail/inbox", "/mail/inbox/1.msg", "/mail/inbox/index"] {
+            assert!(check_path(Some(&SYSTEM_AID), &home(&ALICE, rest)).is_ok(), "{}", rest);
+        }
+        for rest in ["", "/", "/notes.txt", "/.aether/identity", "/mailbox", "/docs/mail"] {
+            assert!(denied(check_path(Some(&SYSTEM_AID), &home(&ALICE, rest))), "{}", rest);
+        }
+        assert!(check_path(Some(&SYSTEM_AID), "/var/log/last-boot").is_ok());
+    }
+
+    #[test]
+    fn relative_components_cannot_leave_mail() {
+        let escape = home(&ALICE, "/mail/../.aether/identity");
+        assert!(matches!(check_path(Some(&SYSTEM_AID), &escape), Err(VfsResponse::InvalidName { .. })));
+        let across = home(&BOB, &format!("/../{}/notes.txt", session_ipc::aid_to_hex(&ALICE)));
+        assert!(matches!(check_path(Some(&BOB), &across), Err(VfsResponse::InvalidName { .. })));
+    }
+}
@@ -0,0 +1,527 @@
+// vnode/vfs/src/cache.rs
+
+//! Write-back block cache between the VFS and its storage backend.
+//!
+//! Writes are split into `BLOCK_SIZE` blocks and kept in memory until a flush.
+//! A block only partly written since it was cached remembers which bytes are
+//! new; the rest are the backend's, so reads take them from the backend and a
+//! flush writes just the new bytes. A flush always emits every data block of a file before the size update that
+//! makes those blocks reachable, so a crash between the two loses the new data
+//! but never leaves a file whose size points at unwritten blocks. Holes punched
+//! and blocks reserved by `Allocate` go out before the data blocks, so a block
+//! written after being punched ends up with the data.
+//!
+//! The cache's counters are registered with the VFS metrics registry under
+//! `vfs_cache_*` and `vfs_fsyncs_total`.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use common::metrics::{Counter, Gauge, Registry};
+
+use crate::sparse::Extents;
+
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Tuning knobs for the cache.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// Maximum bytes of dirty data held before a flush is forced.
+    pub dirty_budget_bytes: usize,
+    /// Dirty data older than this many timer ticks is flushed on the next `tick`.
+    pub max_dirty_age_ticks: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dirty_budget_bytes: 1024 * 1024,
+            max_dirty_age_ticks: 500, // 5 seconds at 100 ticks/s
+        }
+    }
+}
+
+/// A dirty block of one backend file.
+struct DirtyBlock {
+    data: Vec<u8>, // Always BLOCK_SIZE bytes; only the `written` ranges are meaningful
+    /// Byte ranges `[start, end)` written while cached, sorted and disjoint.
+    written: Vec<(usize, usize)>,
+}
+
+impl DirtyBlock {
+    fn new() -> Self {
+        Self { data: vec![0; BLOCK_SIZE], written: Vec::new() }
+    }
+
+    /// Records `[start, end)` as written, merging it with the ranges it touches.
+    fn mark(&mut self, start: usize, end: usize) {
+        let (mut start, mut end) = (start, end);
+        self.written.retain(|&(s, e)| {
+            if e < start || s > end {
+                return true;
+            }
+            start = start.min(s);
+            end = end.max(e);
+            false
+        });
+        let at = self.written.partition_point(|&(s, _)| s < start);
+        self.written.insert(at, (start, end));
+    }
+
+    fn is_full(&self) -> bool {
+        self.written == [(0, BLOCK_SIZE)]
+    }
+}
+
+/// Per-file dirty state, keyed by backend handle.
+struct DirtyFile {
+    blocks: BTreeMap<u64, DirtyBlock>, // block index -> block
+    /// New file size to record once the blocks are on disk, if the file grew.
+    pending_size: Option<u64>,
+    /// Blocks to free, and blocks to back with zeroed storage, before the data goes out.
+    punched: Extents,
+    reserved: Extents,
+    dirtied_at: u64,
+}
+
+impl DirtyFile {
+    fn new(now: u64) -> Self {
+        Self { blocks: BTreeMap::new(), pending_size: None, punched: Extents::new(), reserved: Extents::new(), dirtied_at: now }
+    }
+}
+
+/// A single backend operation produced by a flush, in the order it must be applied.
+#[derive(Debug)]
+pub enum FlushOp {
+    WriteBlock { handle: u64, index: u64, data: Vec<u8> },
+    /// Writes part of a block; the backend keeps the block's other bytes.
+    WriteBytes { handle: u64, offset: u64, data: Vec<u8> },
+    /// Frees blocks `[first, end)`; they read as zeros afterwards.
+    PunchHole { handle: u64, first: u64, end: u64 },
+    /// Backs blocks `[first, end)` with storage, zeroed where they were holes.
+    Reserve { handle: u64, first: u64, end: u64 },
+    /// Metadata update. Only emitted after all of the file's data writes.
+    SetSize { handle: u64, size: u64 },
+}
+
+struct CacheMetrics {
+    hits: Counter,
+    misses: Counter,
+    writes: Counter,
+    flushes: Counter,
+    blocks_flushed: Counter,
+    timed_flushes: Counter,
+    budget_flushes: Counter,
+    fsyncs: Counter,
+    dirty_bytes: Gauge,
+}
+
+impl CacheMetrics {
+    fn register(metrics: &mut Registry) -> Self {
+        const FLUSH_TRIGGERS: &str = "Flushes forced by the cache itself, by trigger.";
+        Self {
+            hits: metrics.counter("vfs_cache_hits_total", "Reads served entirely from dirty cached blocks."),
+            misses: metrics.counter("vfs_cache_misses_total", "Reads that needed the backend for at least one block."),
+            writes: metrics.counter("vfs_cache_writes_total", "Writes buffered in the cache."),
+            flushes: metrics.counter("vfs_cache_flushes_total", "Flushes that wrote at least one block or size update."),
+            blocks_flushed: metrics.counter("vfs_cache_blocks_flushed_total", "Blocks written to the backend."),
+            timed_flushes: metrics.counter_with("vfs_cache_forced_flushes_total", FLUSH_TRIGGERS, &[("trigger", "age")]),
+            budget_flushes: metrics.counter_with("vfs_cache_forced_flushes_total", FLUSH_TRIGGERS, &[("trigger", "budget")]),
+            fsyncs: metrics.counter("vfs_fsyncs_total", "Fsync and SyncAll requests."),
+            dirty_bytes: metrics.gauge("vfs_cache_dirty_bytes", "Dirty bytes currently held."),
+        }
+    }
+}
+
+pub struct WriteBackCache {
+    config: CacheConfig,
+    files: BTreeMap<u64, DirtyFile>,
+    dirty_bytes: usize,
+    oldest_dirty: Option<u64>,
+    metrics: CacheMetrics,
+}
+
+impl WriteBackCache {
+    pub fn new(config: CacheConfig, metrics: &mut Registry) -> Self {
+        Self {
+            config,
+            files: BTreeMap::new(),
+            dirty_bytes: 0,
+            oldest_dirty: None,
+            metrics: CacheMetrics::register(metrics),
+        }
+    }
+
+    /// Buffers `data` at `offset` of the file behind `handle`. `current_size` is the
+    /// file's size as known to the backend, used to track growth.
+    /// Returns true if the dirty budget is now exceeded and the caller should flush.
+    pub fn write(&mut self, handle: u64, current_size: u64, offset: u64, data: &[u8], now: u64) -> bool {
+        let file = self.files.entry(handle).or_insert_with(|| DirtyFile::new(now));
+        let mut written = 0usize;
+        while written < data.len() {
+            let pos = offset + written as u64;
+            let index = pos / BLOCK_SIZE as u64;
+            let block_offset = (pos % BLOCK_SIZE as u64) as usize;
+            let chunk = (BLOCK_SIZE - block_offset).min(data.len() - written);
+
+            let block = file.blocks.entry(index).or_insert_with(|| {
+                self.dirty_bytes += BLOCK_SIZE;
+                DirtyBlock::new()
+            });
+            block.data[block_offset..block_offset + chunk].copy_from_slice(&data[written..written + chunk]);
+            block.mark(block_offset, block_offset + chunk);
+            written += chunk;
+        }
+
+        let end = offset + data.len() as u64;
+        let known_size = file.pending_size.unwrap_or(current_size);
+        if end > known_size {
+            file.pending_size = Some(end);
+        }
+
+        self.metrics.writes.inc();
+        self.metrics.dirty_bytes.set(self.dirty_bytes as i64);
+        self.oldest_dirty = Some(self.oldest_dirty.map_or(now, |t| t.min(now)));
+        let over_budget = self.dirty_bytes > self.config.dirty_budget_bytes;
+        if over_budget {
+            self.metrics.budget_flushes.inc();
+        }
+        over_budget
+    }
+
+    /// Copies any cached bytes in `[offset, offset + buf.len())` over `buf`.
+    /// Returns true if every byte of the range was served from the cache.
+    pub fn read_into(&mut self, handle: u64, offset: u64, buf: &mut [u8]) -> bool {
+        let file = match self.files.get(&handle) {
+            Some(file) => file,
+            None => {
+                self.metrics.misses.inc();
+                return false;
+            }
+        };
+        let mut all_cached = true;
+        let mut done = 0usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / BLOCK_SIZE as u64;
+            let block_offset = (pos % BLOCK_SIZE as u64) as usize;
+            let chunk = (BLOCK_SIZE - block_offset).min(buf.len() - done);
+            let (want_start, want_end) = (block_offset, block_offset + chunk);
+            let mut covered = 0;
+            if let Some(block) = file.blocks.get(&index) {
+                for &(start, end) in &block.written {
+                    let (start, end) = (start.max(want_start), end.min(want_end));
+                    if start < end {
+                        buf[done + start - block_offset..done + end - block_offset].copy_from_slice(&block.data[start..end]);
+                        covered += end - start;
+                    }
+                }
+            }
+            if covered < chunk {
+                all_cached = false;
+            }
+            done += chunk;
+        }
+        if all_cached { self.metrics.hits.inc(); } else { self.metrics.misses.inc(); }
+        all_cached
+    }
+
+    /// Records that the file is `size` bytes long, unless it already is longer.
+    /// The bytes it grows by are a hole until written.
+    pub fn grow(&mut self, handle: u64, current_size: u64, size: u64, now: u64) {
+        let file = self.files.entry(handle).or_insert_with(|| DirtyFile::new(now));
+        if size > file.pending_size.unwrap_or(current_size) {
+            file.pending_size = Some(size);
+        }
+        self.oldest_dirty = Some(self.oldest_dirty.map_or(now, |t| t.min(now)));
+    }
+
+    /// Turns blocks `[first, end)` into a hole. Dirty data buffered for them is dropped.
+    pub fn punch(&mut self, handle: u64, first: u64, end: u64, now: u64) {
+        if first >= end {
+            return;
+        }
+        let file = self.files.entry(handle).or_insert_with(|| DirtyFile::new(now));
+        let dropped: Vec<u64> = file.blocks.range(first..end).map(|(index, _)| *index).collect();
+        for index in &dropped {
+            file.blocks.remove(index);
+        }
+        file.punched.add(first, end);
+        file.reserved.remove(first, end);
+        self.dirty_bytes -= dropped.len() * BLOCK_SIZE;
+        self.metrics.dirty_bytes.set(self.dirty_bytes as i64);
+        self.oldest_dirty = Some(self.oldest_dirty.map_or(now, |t| t.min(now)));
+    }
+
+    /// Backs blocks `[first, end)` with storage without changing what they read as.
+    pub fn reserve(&mut self, handle: u64, first: u64, end: u64, now: u64) {
+        if first >= end {
+            return;
+        }
+        let file = self.files.entry(handle).or_insert_with(|| DirtyFile::new(now));
+        file.reserved.add(first, end);
+        self.oldest_dirty = Some(self.oldest_dirty.map_or(now, |t| t.min(now)));
+    }
+
+    /// Size the file will have once its dirty data is flushed, if it grew.
+    pub fn pending_size(&self, handle: u64) -> Option<u64> {
+        self.files.get(&handle).and_then(|file| file.pending_size)
+    }
+
+    /// Returns the operations needed to make `handle` durable and forgets its dirty state.
+    pub fn flush_file(&mut self, handle: u64) -> Vec<FlushOp> {
+        let mut ops = Vec::new();
+        if let Some(file) = self.files.remove(&handle) {
+            self.emit(handle, file, &mut ops);
+        }
+        self.finish_flush(&ops);
+        ops
+    }
+
+    /// Returns the operations needed to make everything durable.
+    pub fn flush_all(&mut self) -> Vec<FlushOp> {
+        let mut ops = Vec::new();
+        let files = core::mem::take(&mut self.files);
+        for (handle, file) in files {
+            self.emit(handle, file, &mut ops);
+        }
+        self.finish_flush(&ops);
+        ops
+    }
+
+    /// Drops dirty data for a file that is being deleted or truncated away.
+    pub fn discard_file(&mut self, handle: u64) {
+        if let Some(file) = self.files.remove(&handle) {
+            self.dirty_bytes -= file.blocks.len() * BLOCK_SIZE;
+            self.metrics.dirty_bytes.set(self.dirty_bytes as i64);
+        }
+    }
+
+    /// Called periodically with the current tick. Returns a full flush if the
+    /// oldest dirty data has exceeded the age threshold.
+    pub fn tick(&mut self, now: u64) -> Option<Vec<FlushOp>> {
+        match self.oldest_dirty {
+            Some(oldest) if now.saturating_sub(oldest) >= self.config.max_dirty_age_ticks => {
+                self.metrics.timed_flushes.inc();
+                Some(self.flush_all())
+            },
+            _ => None,
+        }
+    }
+
+    pub fn record_fsync(&mut self) {
+        self.metrics.fsyncs.inc();
+    }
+
+    fn emit(&mut self, handle: u64, file: DirtyFile, ops: &mut Vec<FlushOp>) {
+        self.dirty_bytes -= file.blocks.len() * BLOCK_SIZE;
+        // Holes and reservations, then data, then the size update that references it.
+        for (first, end) in file.punched.runs() {
+            ops.push(FlushOp::PunchHole { handle, first, end });
+        }
+        for (first, end) in file.reserved.runs() {
+            ops.push(FlushOp::Reserve { handle, first, end });
+        }
+        for (index, block) in file.blocks {
+            if block.is_full() {
+                ops.push(FlushOp::WriteBlock { handle, index, data: block.data });
+                continue;
+            }
+            for (start, end) in block.written {
+                let offset = index * BLOCK_SIZE as u64 + start as u64;
+                ops.push(FlushOp::WriteBytes { handle, offset, data: block.data[start..end].to_vec() });
+            }
+        }
+        if let Some(size) = file.pending_size {
+            ops.push(FlushOp::SetSize { handle, size });
+        }
+    }
+
+    fn finish_flush(&mut self, ops: &[FlushOp]) {
+        if !ops.is_empty() {
+            self.metrics.flushes.inc();
+            self.metrics.blocks_flushed.add(ops.iter().filter(|op| matches!(op, FlushOp::WriteBlock { .. } | FlushOp::WriteBytes { .. })).count() as u64);
+        }
+        self.metrics.dirty_bytes.set(self.dirty_bytes as i64);
+        if self.files.is_empty() {
+            self.oldest_dirty = None;
+        } else {
+            self.oldest_dirty = self.files.values().map(|file| file.dirtied_at).min();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLE: u64 = 7;
+
+    fn cache() -> WriteBackCache {
+        WriteBackCache::new(CacheConfig::default(), &mut Registry::new("vfs-test"))
+    }
+
+    /// A backend file: its bytes and the size it records.
+    #[derive(Clone)]
+    struct Disk {
+        bytes: Vec<u8>,
+        size: u64,
+    }
+
+    impl Disk {
+        fn new(size: usize) -> Self {
+            Self { bytes: (0..size).map(|i| (i % 199) as u8 + 1).collect(), size: size as u64 }
+        }
+
+        fn put(&mut self, offset: u64, data: &[u8]) {
+            let offset = offset as usize;
+            if self.bytes.len() < offset + data.len() {
+                self.bytes.resize(offset + data.len(), 0);
+            }
+            self.bytes[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        fn apply(&mut self, op: &FlushOp) {
+            match op {
+                FlushOp::WriteBlock { index, data, .. } => self.put(index * BLOCK_SIZE as u64, data),
+                FlushOp::WriteBytes { offset, data, .. } => self.put(*offset, data),
+                FlushOp::PunchHole { first, end, .. } => {
+                    let zeros = vec![0; ((end - first) as usize) * BLOCK_SIZE];
+                    self.put(first * BLOCK_SIZE as u64, &zeros);
+                },
+                FlushOp::Reserve { .. } => {},
+                FlushOp::SetSize { size, .. } => self.size = *size,
+            }
+        }
+
+        /// The file as a reader sees it.
+        fn contents(&self) -> Vec<u8> {
+            let mut contents = self.bytes.clone();
+            contents.resize(self.size as usize, 0);
+            contents
+        }
+    }
+
+    /// Reads `len` bytes at `offset` the way the VFS does: the backend's copy,
+    /// then the cache over it.
+    fn read(cache: &mut WriteBackCache, disk: &Disk, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = disk.contents();
+        buf.resize(buf.len().max(offset as usize + len), 0);
+        let mut out = buf[offset as usize..offset as usize + len].to_vec();
+        cache.read_into(HANDLE, offset, &mut out);
+        out
+    }
+
+    #[test]
+    fn partial_write_keeps_the_rest_of_an_uncached_block() {
+        let disk = Disk::new(BLOCK_SIZE);
+        let mut cache = cache();
+        cache.write(HANDLE, disk.size, 100, &[0xEE; 10], 0);
+
+        let mut expected = disk.contents();
+        expected[100..110].fill(0xEE);
+        assert_eq!(read(&mut cache, &disk, 0, BLOCK_SIZE), expected);
+
+        let ops = cache.flush_file(HANDLE);
+        assert!(matches!(ops.as_slice(), [FlushOp::WriteBytes { offset: 100, data, .. }] if data.as_slice() == [0xEE; 10]));
+        let mut flushed = disk.clone();
+        ops.iter().for_each(|op| flushed.apply(op));
+        assert_eq!(flushed.contents(), expected);
+    }
+
+    #[test]
+    fn read_is_a_hit_only_when_every_byte_is_cached() {
+        let mut cache = cache();
+        cache.write(HANDLE, 0, 0, &[1; 64], 0);
+        assert!(cache.read_into(HANDLE, 0, &mut [0; 64]));
+        assert!(!cache.read_into(HANDLE, 0, &mut [0; 65]));
+        assert!(!cache.read_into(HANDLE, BLOCK_SIZE as u64, &mut [0; 1]));
+    }
+
+    #[test]
+    fn writes_that_fill_a_block_flush_it_whole() {
+        let mut cache = cache();
+        cache.write(HANDLE, 0, 0, &[1; 1000], 0);
+        cache.write(HANDLE, 0, 2000, &[3; BLOCK_SIZE - 2000], 0);
+        cache.write(HANDLE, 0, 1000, &[2; 1000], 0);
+        let ops = cache.flush_file(HANDLE);
+        match ops.as_slice() {
+            [FlushOp::WriteBlock { index: 0, data, .. }, FlushOp::SetSize { size, .. }] => {
+                assert_eq!((data[999], data[1000], data[1999], data[2000]), (1, 2, 2, 3));
+                assert_eq!(*size, BLOCK_SIZE as u64);
+            },
+            other => panic!("unexpected flush {:?}", other),
+        }
+    }
+
+    #[test]
+    fn separate_ranges_of_a_block_flush_separately() {
+        let mut cache = cache();
+        cache.write(HANDLE, BLOCK_SIZE as u64, 10, &[1; 5], 0);
+        cache.write(HANDLE, BLOCK_SIZE as u64, 30, &[2; 5], 0);
+        cache.write(HANDLE, BLOCK_SIZE as u64, 12, &[3; 10], 0);
+        let ranges: Vec<(u64, usize)> = cache
+            .flush_file(HANDLE)
+            .iter()
+            .map(|op| match op {
+                FlushOp::WriteBytes { offset, data, .. } => (*offset, data.len()),
+                other => panic!("unexpected op {:?}", other),
+            })
+            .collect();
+        assert_eq!(ranges, vec![(10, 12), (30, 5)]);
+    }
+
+    /// Drops the unflushed writes at every point of a flush, as a crash would.
+    /// Each byte must hold its old or its new value, the size must be the old
+    /// or the new one, and a new size may only be recorded once every byte it
+    /// takes in has reached the disk. Writes land at pseudo-random offsets,
+    /// most of them inside blocks, some past the end of the file.
+    #[test]
+    fn crash_at_any_point_of_a_flush_never_corrupts_the_file() {
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+        for round in 0..50 {
+            let before = Disk::new(3 * BLOCK_SIZE + 123);
+            let mut after = before.contents();
+            let mut cache = cache();
+            for i in 0..1 + next(12) {
+                let offset = next(5 * BLOCK_SIZE as u64);
+                let data = vec![(round + i) as u8 | 0x80; 1 + next(2 * BLOCK_SIZE as u64) as usize];
+                cache.write(HANDLE, before.size, offset, &data, 0);
+                let end = offset as usize + data.len();
+                if after.len() < end {
+                    after.resize(end, 0);
+                }
+                after[offset as usize..end].copy_from_slice(&data);
+            }
+            assert_eq!(read(&mut cache, &before, 0, after.len()), after, "round {}", round);
+
+            let ops = cache.flush_all();
+            let old = before.contents();
+            for cut in 0..=ops.len() {
+                let mut disk = before.clone();
+                ops[..cut].iter().for_each(|op| disk.apply(op));
+                let contents = disk.contents();
+                assert!(disk.size == before.size || disk.size == after.len() as u64, "round {} cut {}: size {}", round, cut, disk.size);
+                for (i, byte) in contents.iter().enumerate() {
+                    let was = old.get(i).copied();
+                    assert!(Some(*byte) == was || *byte == after[i], "round {} cut {}: byte {} is {:#x}", round, cut, i, byte);
+                    if i >= old.len() {
+                        assert_eq!(*byte, after[i], "round {} cut {}: size covers unwritten byte {}", round, cut, i);
+                    }
+                }
+            }
+            let mut disk = before.clone();
+            ops.iter().for_each(|op| disk.apply(op));
+            assert_eq!(disk.contents(), after, "round {}", round);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// vnode/vfs/src/health.rs
+
+//! Storage health: retrying what the device fails, the health record, and
+//! the simulated faults the host harness tests with.
+//!
+//! A read or write the device fails with a transient error (`BlockError::
+//! is_transient`) is sent again, up to `RETRIES` times. A read that still
+//! fails answers `MediaError` with the failed range narrowed down by
+//! `narrow`. A write that still fails makes the file system read-only for
+//! the rest of the boot: the cache has already given up the data, and
+//! writing on around a hole in what the device holds risks more than
+//! stopping.
+//!
+//! The record counts device operations, retried ones and unrecoverable ones
+//! across boots. It is saved to `HEALTH_PATH` at most every
+//! `HEALTH_SAVE_TICKS`, and right after an unrecoverable error. Since boot
+//! the same counts are registered as `vfs_storage_*` metrics.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::metrics::{Counter, Gauge, Registry};
+
+use crate::cache::BLOCK_SIZE;
+use crate::ipc::block_ipc::BlockError;
+use crate::ipc::vfs_ipc::StorageHealth;
+
+/// Tries after the first before an operation counts as unrecoverable.
+pub const RETRIES: u32 = 3;
+/// Where the record is kept.
+pub const HEALTH_PATH: &str = "/data/storage/health";
+/// How often a changed record is saved: 60 seconds at 100 ticks/s.
+pub const HEALTH_SAVE_TICKS: u64 = 6000;
+
+const BLOCK: u64 = BLOCK_SIZE as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageOp {
+    Read,
+    Write,
+}
+
+/// Runs `op` until it succeeds, fails with an error that won't go away, or
+/// has been retried `RETRIES` times. Returns the last result and how many
+/// retries it took.
+pub fn retry<T>(mut op: impl FnMut() -> Result<T, BlockError>) -> (Result<T, BlockError>, u32) {
+    let mut retries = 0;
+    loop {
+        match op() {
+            Err(error) if error.is_transient() && retries < RETRIES => retries += 1,
+            result => return (result, retries),
+        }
+    }
+}
+
+/// Narrows a failed read of `start..end` down to the bytes from the first
+/// failing block to the last, clipped to the range. `fails` tries one read
+/// of a range once. It takes two bisections, so a few dozen reads even for
+/// a large range. Blocks in between may be fine; telling which would take a
+/// read each. If no probe fails, the error was transient after all and the
+/// whole range is reported.
+pub fn narrow(start: u64, end: u64, mut fails: impl FnMut(u64, u64) -> bool) -> (u64, u64) {
+    if end <= start {
+        return (start, end);
+    }
+    let (first_block, last_block) = (start / BLOCK, (end - 1) / BLOCK);
+    let block_end = |block: u64| ((block + 1) * BLOCK).min(end);
+    let block_start = |block: u64| (block * BLOCK).max(start);
+
+    // The first block b for which start..end of b fails.
+    let (mut lo, mut hi) = (first_block, last_block);
+    if !fails(start, block_end(hi)) {
+        return (start, end);
+    }
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if fails(start, block_end(mid)) { hi = mid } else { lo = mid + 1 }
+    }
+    let first = lo;
+
+    // The last block b for which the start of b..end fails.
+    let (mut lo, mut hi) = (first, last_block);
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if fails(block_start(mid), end) { lo = mid } else { hi = mid - 1 }
+    }
+    (block_start(first), block_end(lo))
+}
+
+struct HealthMetrics {
+    reads: Counter,
+    writes: Counter,
+    retried: Counter,
+    unrecoverable: Counter,
+    read_only: Gauge,
+}
+
+pub struct Health {
+    record: StorageHealth,
+    dirty: bool, // Changed since the last save
+    save_at: u64, // Tick from which a changed record is saved
+    metrics: HealthMetrics,
+}
+
+impl Health {
+    pub fn new(metrics: &mut Registry) -> Self {
+        Self {
+            record: StorageHealth::default(),
+            dirty: false,
+            save_at: HEALTH_SAVE_TICKS,
+            metrics: HealthMetrics {
+                reads: metrics.counter("vfs_storage_reads_total", "Reads sent to the storage device, retries not counted."),
+                writes: metrics.counter("vfs_storage_writes_total", "Writes sent to the storage device, retries not counted."),
+                retried: metrics.counter("vfs_storage_retried_total", "Storage operations that failed at first and were tried again."),
+                unrecoverable: metrics.counter("vfs_storage_unrecoverable_total", "Storage operations that failed after every retry."),
+                read_only: metrics.gauge("vfs_storage_read_only", "1 once a failed write has made the file system read-only."),
+            },
+        }
+    }
+
+    pub fn record(&self) -> StorageHealth {
+        self.record
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.record.read_only
+    }
+
+    /// Counts a device operation that took `retries` retries and ended in
+    /// `error`, if it failed. Returns true if it is the first unrecoverable
+    /// error the record has.
+    pub fn completed(&mut self, op: StorageOp, retries: u32, error: Option<BlockError>, now_secs: u64) -> bool {
+        match op {
+            StorageOp::Read => { self.record.reads += 1; self.metrics.reads.inc(); },
+            StorageOp::Write => { self.record.writes += 1; self.metrics.writes.inc(); },
+        }
+        self.dirty = true;
+        if retries > 0 {
+            self.record.retried += 1;
+            self.metrics.retried.inc();
+        }
+        if retries > 0 || error.is_some() {
+            self.record.last_error = now_secs;
+        }
+        if error.is_none() {
+            return false;
+        }
+        self.record.unrecoverable += 1;
+        self.metrics.unrecoverable.inc();
+        self.save_at = 0;
+        self.record.unrecoverable == 1
+    }
+
+    /// Refuses changes from now on. Returns false if that was already so.
+    pub fn set_read_only(&mut self) -> bool {
+        let newly = !self.record.read_only;
+        self.record.read_only = true;
+        self.metrics.read_only.set(1);
+        newly
+    }
+
+    /// Whether the record should be saved now. Once read-only it can't be,
+    /// and stays in memory.
+    pub fn due(&self, now: u64) -> bool {
+        self.dirty && now >= self.save_at && !self.record.read_only
+    }
+
+    /// Notes that the record was saved. Saving is itself a write, so this
+    /// must come after it, or the record would be saved again every time.
+    pub fn saved(&mut self, now: u64) {
+        self.dirty = false;
+        self.save_at = now + HEALTH_SAVE_TICKS;
+    }
+
+    /// The record as saved: one `name value` line per count.
+    pub fn to_text(&self) -> String {
+        let r = &self.record;
+        format!("reads {}\nwrites {}\nretried {}\nunrecoverable {}\nlast_error {}\n", r.reads, r.writes, r.retried, r.unrecoverable, r.last_error)
+    }
+
+    /// Takes the counts of a saved record, as written by `to_text`. Lines
+    /// it doesn't know are skipped, so a damaged file costs counts, not the boot.
+    pub fn load(&mut self, text: &str) {
+        for line in text.lines() {
+            let Some((name, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<u64>() else {
+                continue;
+            };
+            match name {
+                "reads" => self.record.reads = value,
+                "writes" => self.record.writes = value,
+                "retried" => self.record.retried = value,
+                "unrecoverable" => self.record.unrecoverable = value,
+                "last_error" => self.record.last_error = value,
+                _ => {},
+            }
+        }
+    }
+}
+
+/// A simulated device fault: requests of kind `op` that touch `len` bytes
+/// at `offset` of `path` fail with `error`.
+#[derive(Debug, Clone)]
+pub struct Fault {
+    pub path: String,
+    pub op: StorageOp,
+    pub offset: u64,
+    pub len: u64,
+    pub error: BlockError,
+    /// How many requests fail before the fault clears, or `None` to fail for good.
+    pub times: Option<u32>,
+}
+
+/// Faults the device stand-in fails requests with. The host harness arms
+/// them; on a real boot the table stays empty and the device's own errors
+/// are all there is.
+#[derive(Default)]
+pub struct Faults {
+    armed: Vec<Fault>,
+}
+
+impl Faults {
+    #[allow(dead_code)] // Armed by the host harness
+    pub fn inject(&mut self, fault: Fault) {
+        self.armed.push(fault);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.armed.is_empty()
+    }
+
+    /// The error the device gives for `op` on `len` bytes at `offset` of
+    /// `path`, if a fault covers any of them. Each failure uses up one of a
+    /// clearing fault's.
+    pub fn check(&mut self, path: &str, op: StorageOp, offset: u64, len: u64) -> Result<(), BlockError> {
+        let end = offset.saturating_add(len);
+        let hit = self.armed.iter().position(|fault| {
+            fault.path == path && fault.op == op && fault.offset < end && offset < fault.offset.saturating_add(fault.len)
+        });
+        let Some(index) = hit else {
+            return Ok(());
+        };
+        let error = self.armed[index].error;
+        match &mut self.armed[index].times {
+            Some(0 | 1) => { self.armed.remove(index); },
+            Some(times) => *times -= 1,
+            None => {},
+        }
+        Err(error)
+    }
+}
@@ -0,0 +1,282 @@
+// vnode/vfs/src/lock.rs
+
+//! Advisory whole-file locks.
+//!
+//! Locks belong to the file, not the path: they are keyed by backend handle,
+//! which a rename carries along, and held through an fd by the task that
+//! sent the `Lock`. Any number of fds may share a file, or one may hold it
+//! exclusively. Nothing else in the VFS looks at them; they only coordinate
+//! clients that ask.
+//!
+//! A lock that can't be granted right away is either refused (`WouldBlock`)
+//! or queued with the caller's reply token, which keeps the caller blocked in
+//! its call until the lock is granted. The queue is strictly first come,
+//! first served: a request never overtakes one queued before it, even if it
+//! would be compatible with the current holders, so a stream of shared locks
+//! can't starve an exclusive one.
+//!
+//! Waits that could never end are refused with `Deadlock` instead of being
+//! queued: a task waiting for a file it already holds through another fd
+//! would wait for itself, and a shared holder upgrading while others hold the
+//! file too waits for them while they might be waiting to upgrade as well.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use crate::ipc::vfs_ipc::Fd;
+use crate::ipc::vnode::ReplyToken;
+
+#[derive(Debug)]
+struct Holder {
+    fd: Fd,
+    task: u64,
+    exclusive: bool,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    fd: Fd,
+    task: u64,
+    exclusive: bool,
+    token: ReplyToken, // Answered once the lock is granted
+}
+
+#[derive(Debug, Default)]
+struct FileLocks {
+    holders: Vec<Holder>,
+    waiters: VecDeque<Waiter>,
+}
+
+impl FileLocks {
+    fn compatible(&self, exclusive: bool) -> bool {
+        if exclusive { self.holders.is_empty() } else { self.holders.iter().all(|holder| !holder.exclusive) }
+    }
+
+    /// Grants queued locks from the front for as long as they fit.
+    fn grant(&mut self, granted: &mut Vec<ReplyToken>) {
+        while let Some(waiter) = self.waiters.front() {
+            if !self.compatible(waiter.exclusive) {
+                break;
+            }
+            let Some(waiter) = self.waiters.pop_front() else { break };
+            self.holders.push(Holder { fd: waiter.fd, task: waiter.task, exclusive: waiter.exclusive });
+            granted.push(waiter.token);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.holders.is_empty() && self.waiters.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockError {
+    WouldBlock,
+    Deadlock,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Locked {
+    Granted,
+    /// Queued; its token comes out of `take_granted` once it is granted.
+    Queued,
+}
+
+#[derive(Debug, Default)]
+pub struct LockTable {
+    files: BTreeMap<u64, FileLocks>,
+    granted: Vec<ReplyToken>, // Waits granted since the last `take_granted`
+}
+
+impl LockTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks `file` through `fd` for `task`. `wait` is the token to answer
+    /// once a conflicting lock is granted; without one a conflict is `WouldBlock`.
+    pub fn lock(&mut self, file: u64, fd: Fd, task: u64, exclusive: bool, wait: Option<ReplyToken>) -> Result<Locked, LockError> {
+        let locks = self.files.entry(file).or_default();
+        if let Some(index) = locks.holders.iter().position(|holder| holder.fd == fd) {
+            let held = &mut locks.holders[index];
+            if held.exclusive == exclusive {
+                return Ok(Locked::Granted);
+            }
+            if !exclusive {
+                // A downgrade always succeeds, and may let shared waiters in.
+                held.exclusive = false;
+                locks.grant(&mut self.granted);
+                return Ok(Locked::Granted);
+            }
+            if locks.holders.len() == 1 {
+                locks.holders[index].exclusive = true;
+                return Ok(Locked::Granted);
+            }
+            return Err(if wait.is_some() { LockError::Deadlock } else { LockError::WouldBlock });
+        }
+
+        if locks.waiters.is_empty() && locks.compatible(exclusive) {
+            locks.holders.push(Holder { fd, task, exclusive });
+            return Ok(Locked::Granted);
+        }
+        let token = match wait {
+            Some(token) => token,
+            None => return Err(LockError::WouldBlock),
+        };
+        if locks.holders.iter().any(|holder| holder.task == task) {
+            return Err(LockError::Deadlock);
+        }
+        locks.waiters.push_back(Waiter { fd, task, exclusive, token });
+        Ok(Locked::Queued)
+    }
+
+    /// Releases the lock held through `fd`. Returns whether there was one.
+    pub fn unlock(&mut self, file: u64, fd: Fd) -> bool {
+        let Some(locks) = self.files.get_mut(&file) else { return false };
+        let before = locks.holders.len();
+        locks.holders.retain(|holder| holder.fd != fd);
+        let released = locks.holders.len() != before;
+        locks.grant(&mut self.granted);
+        if locks.is_empty() {
+            self.files.remove(&file);
+        }
+        released
+    }
+
+    /// Drops the locks of tasks that have exited, held or waited for. Returns how many were dropped.
+    pub fn expire(&mut self, alive: impl Fn(u64) -> bool) -> usize {
+        let mut dropped = 0;
+        for locks in self.files.values_mut() {
+            let before = locks.holders.len() + locks.waiters.len();
+            locks.holders.retain(|holder| alive(holder.task));
+            locks.waiters.retain(|waiter| alive(waiter.task));
+            dropped += before - locks.holders.len() - locks.waiters.len();
+            locks.grant(&mut self.granted);
+        }
+        self.files.retain(|_, locks| !locks.is_empty());
+        dropped
+    }
+
+    /// Reply tokens of the waits granted since the last call, oldest first.
+    pub fn take_granted(&mut self) -> Vec<ReplyToken> {
+        core::mem::take(&mut self.granted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const FILE: u64 = 40;
+
+    #[test]
+    fn shared_locks_coexist_and_exclude_an_exclusive_one() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, false, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, false, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 3, 12, true, None), Err(LockError::WouldBlock));
+        // Another file is unaffected.
+        assert_eq!(locks.lock(FILE + 1, 3, 12, true, None), Ok(Locked::Granted));
+
+        assert!(locks.unlock(FILE, 1));
+        assert_eq!(locks.lock(FILE, 3, 12, true, None), Err(LockError::WouldBlock));
+        assert!(locks.unlock(FILE, 2));
+        assert_eq!(locks.lock(FILE, 3, 12, true, None), Ok(Locked::Granted));
+        assert!(locks.take_granted().is_empty());
+    }
+
+    #[test]
+    fn an_exclusive_lock_excludes_everyone_else() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, false, None), Err(LockError::WouldBlock));
+        assert_eq!(locks.lock(FILE, 2, 11, true, None), Err(LockError::WouldBlock));
+        // Asking again through the same fd is a no-op.
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Ok(Locked::Granted));
+
+        assert!(locks.unlock(FILE, 1));
+        assert!(!locks.unlock(FILE, 1));
+        assert_eq!(locks.lock(FILE, 2, 11, false, None), Ok(Locked::Granted));
+    }
+
+    #[test]
+    fn waiters_are_granted_in_arrival_order() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, true, Some(200)), Ok(Locked::Queued));
+        assert_eq!(locks.lock(FILE, 3, 12, false, Some(300)), Ok(Locked::Queued));
+        assert_eq!(locks.lock(FILE, 4, 13, false, Some(400)), Ok(Locked::Queued));
+        // Without a token nobody may overtake the queue, compatible or not.
+        assert_eq!(locks.lock(FILE, 5, 14, false, None), Err(LockError::WouldBlock));
+
+        locks.unlock(FILE, 1);
+        assert_eq!(locks.take_granted(), vec![200]);
+        locks.unlock(FILE, 2);
+        assert_eq!(locks.take_granted(), vec![300, 400]);
+        // Both shared waiters now hold the file.
+        assert_eq!(locks.lock(FILE, 6, 15, true, None), Err(LockError::WouldBlock));
+    }
+
+    #[test]
+    fn a_shared_request_waits_behind_a_queued_exclusive_one() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, false, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, true, Some(200)), Ok(Locked::Queued));
+        // Compatible with the holder, but it would starve the writer.
+        assert_eq!(locks.lock(FILE, 3, 12, false, Some(300)), Ok(Locked::Queued));
+
+        locks.unlock(FILE, 1);
+        assert_eq!(locks.take_granted(), vec![200]);
+        locks.unlock(FILE, 2);
+        assert_eq!(locks.take_granted(), vec![300]);
+    }
+
+    #[test]
+    fn upgrades_and_downgrades_change_the_mode_in_place() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, false, None), Ok(Locked::Granted));
+        // Alone on the file, an upgrade succeeds.
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, false, Some(200)), Ok(Locked::Queued));
+        // A downgrade lets the shared waiter in.
+        assert_eq!(locks.lock(FILE, 1, 10, false, None), Ok(Locked::Granted));
+        assert_eq!(locks.take_granted(), vec![200]);
+        // Now shared with fd 2: an upgrade can't be granted.
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Err(LockError::WouldBlock));
+        assert_eq!(locks.lock(FILE, 1, 10, true, Some(100)), Err(LockError::Deadlock));
+    }
+
+    #[test]
+    fn waiting_on_a_file_the_task_already_holds_is_a_deadlock() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, false, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 10, true, None), Err(LockError::WouldBlock));
+        assert_eq!(locks.lock(FILE, 2, 10, true, Some(200)), Err(LockError::Deadlock));
+        // A second shared fd of the same task is fine.
+        assert_eq!(locks.lock(FILE, 3, 10, false, Some(300)), Ok(Locked::Granted));
+    }
+
+    #[test]
+    fn locks_of_exited_tasks_are_released_and_waiters_granted() {
+        let mut locks = LockTable::new();
+        assert_eq!(locks.lock(FILE, 1, 10, true, None), Ok(Locked::Granted));
+        assert_eq!(locks.lock(FILE, 2, 11, true, Some(200)), Ok(Locked::Queued));
+        assert_eq!(locks.lock(FILE, 3, 12, false, Some(300)), Ok(Locked::Queued));
+        assert_eq!(locks.lock(FILE + 1, 4, 10, false, None), Ok(Locked::Granted));
+
+        assert_eq!(locks.expire(|_| true), 0);
+        assert!(locks.take_granted().is_empty());
+
+        // Task 10 dies holding both files; task 11 dies waiting.
+        assert_eq!(locks.expire(|task| task != 10 && task != 11), 3);
+        assert_eq!(locks.take_granted(), vec![300]);
+        assert!(!locks.unlock(FILE, 1));
+        assert!(!locks.unlock(FILE + 1, 4));
+        assert_eq!(locks.lock(FILE + 1, 5, 13, true, None), Ok(Locked::Granted));
+        assert!(locks.unlock(FILE, 3));
+        assert_eq!(locks.lock(FILE, 6, 13, true, None), Ok(Locked::Granted));
+    }
+}
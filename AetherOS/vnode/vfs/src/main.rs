@@ -9,18 +9,70 @@ use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
 
-use crate::ipc::vnode::VNodeChannel;
-use crate::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use crate::ipc::vfs_ipc::{VfsRequest, VfsResponse, Fd, VfsMetadata};
+use crate::ipc::vnode::{ReplyToken, VNodeChannel};
+use crate::syscall::{syscall3, SYS_LOG, SUCCESS};
+use crate::abi::{LOG_SEVERITY_DEBUG, LOG_SEVERITY_DEFAULT};
+use crate::ipc::vfs_ipc::{self, AllocateMode, VfsRequest, VfsResponse, Fd, StreamId, TxId, VfsMetadata, XattrNamespace, STREAM_CHUNK_SIZE, STREAM_WINDOW};
+use crate::ipc::vfs_ipc::{StorageFault, STORAGE_DEGRADED_TOPIC, STORAGE_ERROR_TOPIC};
+use crate::ipc::block_ipc::BlockError;
+use crate::ipc::event_ipc::{EventBusRequest, EventBusResponse};
+use crate::ipc::lifecycle_ipc::{Lifecycle, LIFECYCLE_CHANNEL};
+use crate::ipc::metrics_ipc::{MetricsRequest, MetricsResponse};
+use crate::ipc::server::{self, Call, Outcome, ServerError, Service};
+use crate::ipc::session_ipc::{self, AidBytes, SYSTEM_AID};
+use crate::ipc::settings_ipc::{SettingsRequest, SettingsResponse, SettingValue};
+use crate::abi::{LAST_BOOT_PANIC, LAST_BOOT_SHUTDOWN};
+use crate::abi::{TASK_STATE_BLOCKED, TASK_STATE_EXITED, TASK_STATE_READY, TASK_STATE_RUNNING};
+use crate::klog::{self, KlogError};
+use crate::ids::Ticks;
+use crate::metrics::Registry;
+use crate::startup;
+use crate::channels;
+use crate::tasks;
+use crate::time;
+
+mod access;
+mod cache;
+mod health;
+mod lock;
+mod pin;
+mod quota;
+mod sparse;
+mod stream;
+mod tx;
+mod watch;
+mod xattr;
+
+use cache::{CacheConfig, FlushOp, WriteBackCache, BLOCK_SIZE};
+use health::{Faults, Health, StorageOp, HEALTH_PATH};
+use lock::{LockError, LockTable, Locked};
+use pin::{PinError, PinSource, PinTable};
+use quota::{QuotaExceeded, QuotaTable, QUOTA_RELOAD_TICKS};
+use sparse::Extents;
+use stream::{Direction, ReadStream, StreamTable, WriteStream};
+use tx::{Charge, Resolved, StageError, Staged, TxError, TxOp, TxTable, MAX_TX_BYTES};
+use watch::WatchTable;
+use xattr::{XattrError, XattrTable};
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
+    log_at(LOG_SEVERITY_DEFAULT, msg);
+}
+
+/// Logs a line traced for every request. These are debug lines so the log
+/// daemon leaves them out by default: its own writes come through here, and
+/// storing their trace would make it write again, forever.
+fn debug(msg: &str) {
+    log_at(LOG_SEVERITY_DEBUG, msg);
+}
+
+fn log_at(severity: u64, msg: &str) {
     unsafe {
         let res = syscall3(
             SYS_LOG,
             msg.as_ptr() as u64,
             msg.len() as u64,
-            0 // arg3 is unused for SYS_LOG
+            severity
         );
         if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
     }
@@ -34,66 +86,915 @@ struct OpenFile {
     cursor: u64,
     // Conceptual: backend-specific handle (e.g., AetherFS handle, Ramdisk handle)
     backend_handle: u64, // Dummy handle for backend communication
+    owner: Option<AidBytes>, // Identity that opened the file; only it may use the fd
+    tx: Option<TxId>, // Transaction the fd was opened in; its reads and writes go through it
+}
+
+/// Timestamps of a file or directory created through this VFS, in UTC epoch
+/// seconds. 0 if the wall clock was unavailable.
+#[derive(Debug, Clone, Copy)]
+struct FileTimes {
+    created: u64,
+    modified: u64,
+    is_dir: bool,
+}
+
+/// Made at each read from the kernel's task stats, unlike the files in `proc_files`.
+const PROC_TASKS: &str = "/proc/tasks";
+
+/// `/proc/tasks`: a header line, then one line per task with its ID, state,
+/// the faults it took by kind and its name, separated by spaces.
+fn proc_tasks() -> Vec<u8> {
+    let mut out = String::from("id state page_resolved page_fatal general_protection invalid_opcode alignment_check name\n");
+    // A task that exits between the list and its stats is left out.
+    for stats in tasks::list().into_iter().filter_map(tasks::stats) {
+        let state = match stats.state {
+            TASK_STATE_RUNNING => "running",
+            TASK_STATE_READY => "ready",
+            TASK_STATE_BLOCKED => "blocked",
+            TASK_STATE_EXITED => "exited",
+            _ => "?",
+        };
+        out.push_str(&format!(
+            "{} {} {} {} {} {} {} {}\n",
+            stats.id, state, stats.page_faults_resolved, stats.page_faults_fatal, stats.general_protection_faults, stats.invalid_opcodes, stats.alignment_checks, stats.name()
+        ));
+    }
+    out.into_bytes()
 }
 
 struct VfsService {
-    client_chan: VNodeChannel,
     aetherfs_chan: VNodeChannel, // Channel to AetherFS backend
+    settings_chan: VNodeChannel, // Channel to svc://settings, for quota limits
+    event_bus_chan: VNodeChannel, // Channel to svc://event-bus, for storage.* events
     // ramdisk_chan: VNodeChannel, // Conceptual: Channel to RAM disk backend
     // disk_driver_chan: VNodeChannel, // Conceptual: Channel to block device backend
 
     next_fd: Fd,
     open_files: BTreeMap<Fd, OpenFile>,
+    // Backend handles by path, so every fd for the same file shares its cached blocks
+    backend_handles: BTreeMap<String, u64>,
+    next_backend_handle: u64,
+    // File sizes as last recorded on the backend (after flushed size updates)
+    backend_sizes: BTreeMap<u64, u64>,
+    // Allocated blocks of the files written through this VFS, by backend handle.
+    // A file without an entry is served wholly by the backend.
+    extents: BTreeMap<u64, Extents>,
+    // Conceptual: kept by the backend once it stores metadata
+    times: BTreeMap<String, FileTimes>,
+    // Conceptual: kept in the inode by the backend as well, next to the times
+    xattrs: XattrTable,
+    cache: WriteBackCache,
+    health: Health,
+    faults: Faults, // Simulated device faults; empty outside the host harness
+    pins: PinTable,
+    quota: QuotaTable,
+    quota_reload_at: u64, // Tick at which the quota settings are requested again
+    txs: TxTable,
+    streams: StreamTable,
+    locks: LockTable,
+    watches: WatchTable,
+    // Read-only files under /proc, kept in memory
+    proc_files: BTreeMap<String, Vec<u8>>,
+    metrics: Registry,
+    now: u64, // Timer ticks as of the server loop's last tick
+    sender: Option<u64>, // Task that sent the request being handled, from its kernel credentials
+    changed: Vec<String>, // Watched paths changed by requests answered since the last tick
+    ready: Vec<(Option<ReplyToken>, VfsResponse)>, // Granted locks and stream chunks, for the server loop to send
 }
 
 impl VfsService {
-    fn new(client_chan_id: u32, aetherfs_chan_id: u32) -> Self {
-        let client_chan = VNodeChannel::new(client_chan_id);
+    fn new(aetherfs_chan_id: u32, settings_chan_id: u32, event_bus_chan_id: u32) -> Self {
         let aetherfs_chan = VNodeChannel::new(aetherfs_chan_id);
+        let settings_chan = VNodeChannel::new(settings_chan_id);
+        let event_bus_chan = VNodeChannel::new(event_bus_chan_id);
 
         log("VFS Service: Initializing...");
 
+        let mut metrics = Registry::new("vfs");
+        let cache = WriteBackCache::new(CacheConfig::default(), &mut metrics);
+        let health = Health::new(&mut metrics);
+
         Self {
-            client_chan,
             aetherfs_chan,
+            settings_chan,
+            event_bus_chan,
             next_fd: 1,
             open_files: BTreeMap::new(),
+            backend_handles: BTreeMap::new(),
+            next_backend_handle: 1000,
+            backend_sizes: BTreeMap::new(),
+            extents: BTreeMap::new(),
+            times: BTreeMap::new(),
+            xattrs: XattrTable::new(),
+            cache,
+            health,
+            faults: Faults::default(),
+            pins: PinTable::default(),
+            quota: QuotaTable::new(),
+            quota_reload_at: 0,
+            txs: TxTable::new(),
+            streams: StreamTable::new(),
+            locks: LockTable::new(),
+            watches: WatchTable::new(),
+            proc_files: BTreeMap::new(),
+            metrics,
+            now: 0,
+            sender: None,
+            changed: Vec::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    fn backend_handle_for(&mut self, path: &str) -> u64 {
+        if let Some(handle) = self.backend_handles.get(path) {
+            return *handle;
+        }
+        // Conceptual: the backend would return its own handle when opening the file.
+        let handle = self.next_backend_handle;
+        self.next_backend_handle += 1;
+        self.backend_handles.insert(path.to_string(), handle);
+        handle
+    }
+
+    /// Applies flushed operations to the backend in order. The first one the
+    /// device fails for good ends the flush, since what follows may depend on
+    /// it, like a size update on its blocks: the rest is dropped and the file
+    /// system becomes read-only.
+    fn apply_flush(&mut self, ops: Vec<FlushOp>) -> Result<(), VfsResponse> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        // Changes are refused before they get here, but a commit's aren't known until it runs.
+        if self.health.read_only() {
+            log(&alloc::format!("VFS: Dropped {} flushed operations; the file system is read-only.", ops.len()));
+            return Err(VfsResponse::Error { code: 30, message: "Read-only file system after a storage error".to_string() }); // EROFS
+        }
+        let count = ops.len();
+        for (done, op) in ops.into_iter().enumerate() {
+            let (handle, offset, len) = match &op {
+                FlushOp::WriteBlock { handle, index, .. } => (*handle, index * BLOCK_SIZE as u64, BLOCK_SIZE as u64),
+                FlushOp::WriteBytes { handle, offset, data } => (*handle, *offset, data.len() as u64),
+                FlushOp::PunchHole { handle, first, end } | FlushOp::Reserve { handle, first, end } => {
+                    (*handle, first * BLOCK_SIZE as u64, (end - first) * BLOCK_SIZE as u64)
+                },
+                FlushOp::SetSize { handle, size } => (*handle, *size, 0),
+            };
+            if let Err(cause) = self.device_write(handle, offset, len) {
+                return Err(self.write_failed(handle, offset, len, cause, count - done));
+            }
+            match op {
+                FlushOp::WriteBlock { handle, index, data } => {
+                    // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::WriteBlock { handle, index, data })`
+                    let _ = (handle, index, data);
+                },
+                FlushOp::WriteBytes { handle, offset, data } => {
+                    // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Write { handle, offset, data })`.
+                    // AetherFS reads the block, merges the bytes into it and writes it back.
+                    let _ = (handle, offset, data);
+                },
+                FlushOp::PunchHole { handle, first, end } => {
+                    // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::PunchHole { handle, first, end })`.
+                    // AetherFS drops the blocks from the file's extent map and frees them.
+                    let _ = (handle, first, end);
+                },
+                FlushOp::Reserve { handle, first, end } => {
+                    // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Reserve { handle, first, end })`.
+                    // The new extents are marked unwritten, so they read as zeros until written.
+                    let _ = (handle, first, end);
+                },
+                FlushOp::SetSize { handle, size } => {
+                    // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::SetSize { handle, size })`.
+                    // Must not be sent before the WriteBlocks preceding it have been acknowledged.
+                    self.backend_sizes.insert(handle, size);
+                },
+            }
         }
+        debug(&alloc::format!("VFS: Flushed {} operations to backend.", count));
+        Ok(())
     }
 
-    fn handle_request(&mut self, request: VfsRequest) -> VfsResponse {
+    /// Path of the file behind a backend handle, or "" if none has it anymore.
+    fn path_of(&self, handle: u64) -> String {
+        self.backend_handles.iter().find(|(_, h)| **h == handle).map(|(path, _)| path.clone()).unwrap_or_default()
+    }
+
+    /// Whether the device takes a write of `len` bytes at `offset` of a file,
+    /// trying again on transient errors.
+    fn device_write(&mut self, handle: u64, offset: u64, len: u64) -> Result<(), BlockError> {
+        // Conceptual: AetherFS answers each flushed operation with the `BlockError`
+        // the disk driver reported for its blocks, if any. Until then only the
+        // simulated faults fail, and those are looked up by path.
+        let path = if self.faults.is_empty() { String::new() } else { self.path_of(handle) };
+        let faults = &mut self.faults;
+        let (result, retries) = health::retry(|| faults.check(&path, StorageOp::Write, offset, len));
+        self.record_device_op(StorageOp::Write, handle, offset, len, retries, result.err());
+        result
+    }
+
+    /// Reads `len` bytes at `offset` of a file from the device, trying again
+    /// on transient errors. A read that fails for good answers `MediaError`
+    /// with the range narrowed down to the failing blocks.
+    fn device_read(&mut self, handle: u64, path: &str, offset: u64, len: u32) -> Result<Vec<u8>, VfsResponse> {
+        // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Read { handle, offset, len })`,
+        // answered with the data or the `BlockError` the disk driver reported.
+        // For now, return dummy data and simulate backend read.
+        // A failed read is narrowed down within the file: past its end there is nothing to find.
+        let size = self.handle_size(handle);
+        let end = match offset.saturating_add(len as u64) {
+            end if size > offset => end.min(size),
+            end => end,
+        };
+        let faults = &mut self.faults;
+        let (result, retries) = health::retry(|| faults.check(path, StorageOp::Read, offset, len as u64));
+        let Err(cause) = result else {
+            self.record_device_op(StorageOp::Read, handle, offset, len as u64, retries, None);
+            let dummy_data = alloc::format!("dummy_data_from_file_{}_at_offset_{}", path, offset).into_bytes();
+            return Ok(dummy_data[..(len as usize).min(dummy_data.len())].to_vec());
+        };
+        let (start, end) = health::narrow(offset, end, |start, end| faults.check(path, StorageOp::Read, start, end - start).is_err());
+        self.record_device_op(StorageOp::Read, handle, start, end - start, retries, Some(cause));
+        log(&alloc::format!("VFS: Reading {} bytes at {} of {} failed after {} retries ({}).", end - start, start, path, retries, cause.describe()));
+        Err(VfsResponse::MediaError { path: path.to_string(), offset: start, len: end - start, cause })
+    }
+
+    /// Enters a device operation in the health record, and tells the event
+    /// bus about the first one ever to fail for good.
+    fn record_device_op(&mut self, op: StorageOp, handle: u64, offset: u64, len: u64, retries: u32, error: Option<BlockError>) {
+        if retries > 0 && error.is_none() {
+            log(&alloc::format!("VFS: {:?} of {} bytes at {} of {} succeeded after {} retries.", op, len, offset, self.path_of(handle), retries));
+        }
+        if self.health.completed(op, retries, error, time::now_secs()) {
+            if let Some(cause) = error {
+                let fault = StorageFault { path: self.path_of(handle), offset, len, write: op == StorageOp::Write, cause };
+                self.publish(STORAGE_ERROR_TOPIC, &fault);
+            }
+        }
+    }
+
+    /// Makes the file system read-only after the device failed a flushed
+    /// write, and answers the request that flushed it.
+    fn write_failed(&mut self, handle: u64, offset: u64, len: u64, cause: BlockError, dropped: usize) -> VfsResponse {
+        let path = self.path_of(handle);
+        log(&alloc::format!("VFS: Writing {} bytes at {} of {} failed ({}); {} flushed operations were dropped.", len, offset, path, cause.describe(), dropped));
+        if self.health.set_read_only() {
+            log("VFS: The file system is read-only until the next boot.");
+            self.publish(STORAGE_DEGRADED_TOPIC, &StorageFault { path: path.clone(), offset, len, write: true, cause });
+        }
+        VfsResponse::Error { code: 5, message: format!("I/O error writing {} ({}); the file system is now read-only", path, cause.describe()) } // EIO
+    }
+
+    fn publish(&mut self, topic: &str, fault: &StorageFault) {
+        let Ok(payload) = postcard::to_allocvec(fault) else {
+            return;
+        };
+        let request = EventBusRequest::Publish { topic: topic.to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&request), Ok(EventBusResponse::Success(_))) {
+            log(&alloc::format!("VFS: Could not publish {} for {}.", topic, fault.path));
+        }
+    }
+
+    /// Asks svc://settings for the quota limits. The replies are applied by
+    /// `apply_quota_setting` as they arrive: the VFS must not block on the
+    /// settings service, which keeps its own file here.
+    fn request_quota_settings(&mut self) {
+        for key in ["vfs.quota_default_mb", "vfs.quota_overrides"] {
+            let request = SettingsRequest::Get { key: key.to_string() };
+            if self.settings_chan.send(&request).is_err() {
+                log(&alloc::format!("VFS: Failed to request setting {}.", key));
+            }
+        }
+        self.quota_reload_at = self.now + QUOTA_RELOAD_TICKS;
+    }
+
+    /// Rebuilds the quota totals from the files' allocated sizes, so changes
+    /// made to the backend without going through the VFS are charged too.
+    fn recount_quota(&mut self) {
+        // Conceptual: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::ListAllocated)`, answered with
+        // every file and its allocated bytes as stored. Until the backend keeps them, the extent maps
+        // of the files written through this VFS are the listing.
+        let files: Vec<(String, u64)> = self.backend_handles.keys().map(|path| (path.clone(), self.allocated_size(path))).collect();
+        if self.quota.recount(files) {
+            log("VFS: Recounted storage usage; it had drifted from the files on the backend.");
+        }
+    }
+
+    fn apply_quota_setting(&mut self, response: SettingsResponse) {
+        match response {
+            SettingsResponse::Value { key, value: SettingValue::Int(mib) } if key == "vfs.quota_default_mb" && mib > 0 => {
+                self.quota.set_default_limit(mib as u64 * 1024 * 1024);
+            },
+            SettingsResponse::Value { key, value: SettingValue::Str(raw) } if key == "vfs.quota_overrides" => {
+                let (overrides, invalid) = quota::parse_overrides(&raw);
+                for entry in invalid {
+                    log(&alloc::format!("VFS: Ignoring invalid quota override '{}'.", entry));
+                }
+                self.quota.set_overrides(overrides);
+            },
+            other => log(&alloc::format!("VFS: Unexpected reply from Settings Service: {:?}.", other)),
+        }
+    }
+
+    fn quota_exceeded(path: &str, exceeded: QuotaExceeded) -> VfsResponse {
+        log(&alloc::format!("VFS: Quota of {} exceeded by {} ({} of {} bytes used).",
+            session_ipc::aid_to_hex(&exceeded.owner), path, exceeded.used, exceeded.limit));
+        VfsResponse::QuotaExceeded { owner: exceeded.owner, used: exceeded.used, limit: exceeded.limit }
+    }
+
+    fn busy(path: &str, holder: TxId) -> VfsResponse {
+        VfsResponse::Error { code: 16, message: format!("{} is locked by transaction {}", path, holder) } // EBUSY
+    }
+
+    fn tx_too_large(path: &str) -> VfsResponse {
+        VfsResponse::Error { code: 27, message: format!("Write to {} would stage more than {} bytes in the transaction", path, MAX_TX_BYTES) } // EFBIG
+    }
+
+    fn unknown_tx(id: TxId) -> VfsResponse {
+        VfsResponse::Error { code: 22, message: format!("No transaction {} held by this task", id) } // EINVAL
+    }
+
+    /// Records `path` as created now, unless it already exists.
+    fn stamp_created(&mut self, path: &str, is_dir: bool) {
+        if !self.times.contains_key(path) {
+            let now = time::now_secs();
+            self.times.insert(path.to_string(), FileTimes { created: now, modified: now, is_dir });
+        }
+    }
+
+    fn stamp_modified(&mut self, path: &str) {
+        let now = time::now_secs();
+        self.times.entry(path.to_string())
+            .and_modify(|times| times.modified = now)
+            .or_insert(FileTimes { created: now, modified: now, is_dir: false });
+    }
+
+    /// Drops the cached contents of a file and records it as empty.
+    fn apply_truncate(&mut self, handle: u64) {
+        self.cache.discard_file(handle);
+        self.backend_sizes.insert(handle, 0);
+        self.extents.entry(handle).or_default().truncate(0);
+    }
+
+    /// Logical size of a file: as recorded on the backend, or as it will be
+    /// once the cache is flushed if it grew since.
+    fn handle_size(&self, handle: u64) -> u64 {
+        self.cache.pending_size(handle).or_else(|| self.backend_sizes.get(&handle).copied()).unwrap_or(0)
+    }
+
+    fn file_size(&self, path: &str) -> u64 {
+        self.backend_handles.get(path).map_or(0, |handle| self.handle_size(*handle))
+    }
+
+    /// Bytes of the file at `path` with storage behind them.
+    fn allocated_size(&self, path: &str) -> u64 {
+        self.backend_handles.get(path)
+            .and_then(|handle| self.extents.get(handle))
+            .map_or(0, Extents::allocated_bytes)
+    }
+
+    /// A copy of the allocation of the file at `path`.
+    fn extents_of(&self, path: &str) -> Extents {
+        self.backend_handles.get(path).and_then(|handle| self.extents.get(handle)).cloned().unwrap_or_default()
+    }
+
+    fn apply_delete(&mut self, path: &str) {
+        if let Some(handle) = self.backend_handles.remove(path) {
+            self.cache.discard_file(handle);
+            self.backend_sizes.remove(&handle);
+            self.extents.remove(&handle);
+        }
+        self.times.remove(path);
+        self.xattrs.remove_file(path);
+        // Conceptual: Send IPC to backend to delete file/directory.
+    }
+
+    fn apply_move(&mut self, source: &str, destination: &str) {
+        if let Some(handle) = self.backend_handles.remove(source) {
+            if let Some(old) = self.backend_handles.insert(destination.to_string(), handle) {
+                self.backend_sizes.remove(&old);
+                self.extents.remove(&old);
+            }
+        }
+        // A rename keeps the creation time and doesn't count as a modification.
+        if let Some(times) = self.times.remove(source) {
+            self.times.insert(destination.to_string(), times);
+        }
+        self.xattrs.rename(source, destination); // Every namespace moves along
+        // Conceptual: Send IPC to backend to move/rename file/directory.
+    }
+
+    /// Reads `len` bytes at `offset` of a file, with buffered writes applied over
+    /// the backend's copy. Fails with `MediaError` if the device can't read it.
+    fn read_range(&mut self, handle: u64, path: &str, offset: u64, len: u32) -> Result<Vec<u8>, VfsResponse> {
+        if let Some(contents) = self.proc_file(path) {
+            let start = (offset as usize).min(contents.len());
+            let end = start.saturating_add(len as usize).min(contents.len());
+            return Ok(contents[start..end].to_vec());
+        }
+        // Holes read as zeros without asking the backend.
+        if let Some(extents) = self.extents.get(&handle) {
+            let end = offset.saturating_add(len as u64).min(self.handle_size(handle));
+            if extents.next_data(offset).map_or(true, |data| data >= end) {
+                return Ok(alloc::vec![0; end.saturating_sub(offset) as usize]);
+            }
+        }
+        let mut response_data = self.device_read(handle, path, offset, len)?;
+
+        // Data written but not yet flushed takes precedence over the backend's copy.
+        if let Some(size) = self.cache.pending_size(handle) {
+            let cached_len = size.saturating_sub(offset).min(len as u64) as usize;
+            if response_data.len() < cached_len {
+                response_data.resize(cached_len, 0);
+            }
+        }
+        if let Some(extents) = self.extents.get(&handle) {
+            extents.zero_holes(offset, &mut response_data);
+        }
+        self.cache.read_into(handle, offset, &mut response_data);
+        Ok(response_data)
+    }
+
+    /// Reads the pages of on-demand pins that mappings touched, with buffered
+    /// writes applied, and hands them to the kernel. A page the device fails
+    /// to read is retried on the next pass; its readers stay blocked until then.
+    fn fill_pages(&mut self) {
+        for (request, source) in self.pins.requests() {
+            match self.read_range(source.handle, &source.path, request.offset, pin::PAGE_SIZE as u32) {
+                Ok(data) => self.pins.fill(request, Some(&data)),
+                Err(_) => {
+                    log(&alloc::format!("VFS: Couldn't read page {:#x} of {} for a mapping; retrying.", request.offset, source.path));
+                    self.pins.fill(request, None);
+                },
+            }
+        }
+    }
+
+    /// Rejects requests the caller is not allowed to make, before any state is touched.
+    fn authorize(&self, caller: Option<&AidBytes>, request: &VfsRequest) -> Result<(), VfsResponse> {
+        match request {
+            VfsRequest::Open { path, .. }
+            | VfsRequest::List { path }
+            | VfsRequest::Stat { path }
+            | VfsRequest::Delete { path }
+            | VfsRequest::CreateDirectory { path }
+            | VfsRequest::Watch { path, .. }
+            | VfsRequest::Unwatch { path, .. } => access::check_path(caller, path),
+            VfsRequest::Move { source, destination } => {
+                access::check_path(caller, source)?;
+                access::check_path(caller, destination)
+            },
+            // Changing a user.* attribute takes what writing the file does; system.* ones
+            // are the system identity's. Names without a namespace fail with the request.
+            VfsRequest::SetXattr { path, name, .. } | VfsRequest::RemoveXattr { path, name } => {
+                access::check_path(caller, path)?;
+                match vfs_ipc::xattr_namespace(name) {
+                    Some(XattrNamespace::System) if caller != Some(&SYSTEM_AID) => {
+                        Err(VfsResponse::Error { code: 1, message: format!("Only the system identity may change {}", name) }) // EPERM
+                    },
+                    _ => Ok(()),
+                }
+            },
+            VfsRequest::GetXattr { path, .. }
+            | VfsRequest::ListXattrs { path }
+            | VfsRequest::StatWithXattrs { path, .. }
+            | VfsRequest::ListWithXattrs { path, .. } => access::check_path(caller, path),
+            VfsRequest::Read { fd, .. }
+            | VfsRequest::Write { fd, .. }
+            | VfsRequest::Close { fd }
+            | VfsRequest::Fsync { fd }
+            | VfsRequest::Pin { fd }
+            | VfsRequest::ReadStream { fd, .. }
+            | VfsRequest::WriteStream { fd, .. }
+            | VfsRequest::Lock { fd, .. }
+            | VfsRequest::Unlock { fd }
+            | VfsRequest::Allocate { fd, .. }
+            | VfsRequest::SeekData { fd, .. }
+            | VfsRequest::SeekHole { fd, .. } => match self.open_files.get(fd) {
+                // An fd opened under another identity is treated as nonexistent.
+                Some(file) if file.owner.as_ref() != caller => Err(VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }), // EBADF
+                _ => Ok(()),
+            },
+            // Anyone may ask about their own usage; only the system identity about others.
+            VfsRequest::GetUsage { owner } => match (caller, owner) {
+                (None, _) => Err(VfsResponse::Unauthenticated),
+                (Some(aid), Some(owner)) if *aid != SYSTEM_AID && aid != owner => {
+                    Err(VfsResponse::Error { code: 13, message: "Permission denied: usage of another identity".to_string() }) // EACCES
+                },
+                _ => Ok(()),
+            },
+            VfsRequest::InTx { request, .. } => self.authorize(caller, request),
+            // Unpin is checked against the requesting task by the pin table,
+            // transactions against the task and identity that started them, and
+            // stream messages against the task that started the stream.
+            VfsRequest::SyncAll | VfsRequest::Metrics(_) | VfsRequest::StorageHealth | VfsRequest::Unpin { .. }
+            | VfsRequest::TxBegin | VfsRequest::TxCommit { .. } | VfsRequest::TxAbort { .. }
+            | VfsRequest::StreamCredit { .. } | VfsRequest::StreamData { .. } => Ok(()),
+        }
+    }
+
+    /// The transaction an fd-based request belongs to, if its fd was opened in one.
+    fn fd_tx(&self, request: &VfsRequest) -> Option<TxId> {
+        match request {
+            VfsRequest::Read { fd, .. }
+            | VfsRequest::Write { fd, .. }
+            | VfsRequest::Close { fd }
+            | VfsRequest::Fsync { fd }
+            | VfsRequest::Pin { fd }
+            | VfsRequest::ReadStream { fd, .. }
+            | VfsRequest::WriteStream { fd, .. }
+            | VfsRequest::Lock { fd, .. }
+            | VfsRequest::Unlock { fd }
+            | VfsRequest::Allocate { fd, .. }
+            | VfsRequest::SeekData { fd, .. }
+            | VfsRequest::SeekHole { fd, .. } => self.open_files.get(fd).and_then(|file| file.tx),
+            _ => None,
+        }
+    }
+
+    /// The paths a request changes.
+    fn changed_paths<'a>(&'a self, request: &'a VfsRequest) -> Vec<&'a str> {
+        match request {
+            VfsRequest::Open { path, flags } if flags & 1 != 0 => alloc::vec![path.as_str()],
+            VfsRequest::Write { fd, .. } | VfsRequest::WriteStream { fd, .. } | VfsRequest::Allocate { fd, .. } => self.open_files.get(fd).map(|file| alloc::vec![file.path.as_str()]).unwrap_or_default(),
+            VfsRequest::Delete { path } | VfsRequest::CreateDirectory { path } => alloc::vec![path.as_str()],
+            VfsRequest::SetXattr { path, .. } | VfsRequest::RemoveXattr { path, .. } => alloc::vec![path.as_str()],
+            VfsRequest::Move { source, destination } => alloc::vec![source.as_str(), destination.as_str()],
+            VfsRequest::InTx { request, .. } => self.changed_paths(request),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Rejects changes outside a transaction to paths a transaction has locked.
+    fn check_unlocked(&self, request: &VfsRequest) -> Result<(), VfsResponse> {
+        match self.changed_paths(request).into_iter().find_map(|path| self.txs.lock_holder(path).map(|holder| (path, holder))) {
+            Some((path, holder)) => Err(Self::busy(path, holder)),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects changes under /proc, which only the VFS itself fills, and
+    /// every change once a failed write has made the file system read-only.
+    fn check_writable(&self, request: &VfsRequest) -> Result<(), VfsResponse> {
+        let changed = self.changed_paths(request);
+        if let Some(path) = changed.iter().find(|path| **path == "/proc" || path.starts_with("/proc/")) {
+            return Err(VfsResponse::Error { code: 30, message: format!("Read-only file system: {}", path) }); // EROFS
+        }
+        match changed.first() {
+            Some(path) if self.health.read_only() => {
+                Err(VfsResponse::Error { code: 30, message: format!("Read-only file system after a storage error: {}", path) }) // EROFS
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks `path` for transaction `id`, or reports which transaction holds it.
+    fn lock_for_tx(&mut self, id: TxId, path: &str) -> Result<(), VfsResponse> {
+        match self.txs.lock(id, path) {
+            Ok(()) => Ok(()),
+            Err(TxError::Locked { holder }) => Err(Self::busy(path, holder)),
+            Err(TxError::Unknown) => Err(Self::unknown_tx(id)),
+        }
+    }
+
+    /// Whether `path` is a file or directory the VFS knows of.
+    fn exists(&self, path: &str) -> bool {
+        self.times.contains_key(path) || path == PROC_TASKS || self.proc_files.contains_key(path)
+    }
+
+    /// Contents of the file at `path` under /proc, if there is one.
+    fn proc_file(&self, path: &str) -> Option<Vec<u8>> {
+        if path == PROC_TASKS {
+            return Some(proc_tasks());
+        }
+        self.proc_files.get(path).cloned()
+    }
+
+    /// Whether `path` exists as transaction `id` sees it, or `None` if there is no such transaction.
+    fn exists_in_tx(&self, id: TxId, task: u64, caller: Option<&AidBytes>, path: &str) -> Option<bool> {
+        match self.txs.get(id, task, caller).ok()?.resolve(path) {
+            Resolved::Staged(Staged::File(_)) | Resolved::Staged(Staged::Directory) => Some(true),
+            Resolved::Staged(Staged::Deleted) => Some(false),
+            Resolved::Staged(Staged::MovedFrom(source)) => Some(self.exists(source)),
+            Resolved::Committed(committed) => Some(self.exists(&committed)),
+        }
+    }
+
+    fn xattr_error(path: &str, name: &str, error: XattrError) -> VfsResponse {
+        let (code, message) = error.describe(path, name);
+        VfsResponse::Error { code, message }
+    }
+
+    fn close_tx_fds(&mut self, id: TxId) {
+        self.open_files.retain(|_, file| file.tx != Some(id));
+    }
+
+    /// Handles a request made inside transaction `id`: changes are staged in the
+    /// transaction, reads see them on top of the committed state.
+    fn handle_in_tx(&mut self, id: TxId, caller: Option<AidBytes>, request: VfsRequest) -> VfsResponse {
+        let task = self.sender.unwrap_or(0);
+        if self.txs.get(id, task, caller.as_ref()).is_err() {
+            return Self::unknown_tx(id);
+        }
         match request {
             VfsRequest::Open { path, flags } => {
-                log(&alloc::format!("VFS: Open request for path: {} with flags: {}.", path, flags));
+                if let Err(busy) = self.lock_for_tx(id, &path) {
+                    return busy;
+                }
+                if flags & 1 != 0 {
+                    if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                        tx.stage_truncate(&path);
+                    }
+                }
+                let fd = self.next_fd;
+                self.next_fd += 1;
+                // The backend handle is only looked up on commit; reads resolve the path first.
+                self.open_files.insert(fd, OpenFile { path: path.clone(), flags, cursor: 0, backend_handle: 0, owner: caller, tx: Some(id) });
+                log(&alloc::format!("VFS: Opened {} as fd {} in transaction {}.", path, fd, id));
+                VfsResponse::Success(fd as i32)
+            },
+            VfsRequest::Read { fd, len, offset } => {
+                let path = match self.open_files.get(&fd) {
+                    Some(file) if file.tx == Some(id) => file.path.clone(),
+                    _ => return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+                };
+                let committed = match self.txs.get(id, task, caller.as_ref()).map(|tx| tx.resolve(&path)) {
+                    Ok(Resolved::Staged(Staged::File(contents))) => {
+                        let start = (offset as usize).min(contents.len());
+                        let end = start.saturating_add(len as usize).min(contents.len());
+                        return VfsResponse::Data(contents[start..end].to_vec());
+                    },
+                    Ok(Resolved::Staged(Staged::MovedFrom(source))) => source.clone(),
+                    Ok(Resolved::Committed(committed)) => committed,
+                    Ok(Resolved::Staged(_)) => return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }, // ENOENT
+                    Err(_) => return Self::unknown_tx(id),
+                };
+                let handle = self.backend_handle_for(&committed);
+                match self.read_range(handle, &committed, offset, len) {
+                    Ok(data) => VfsResponse::Data(data),
+                    Err(failed) => failed,
+                }
+            },
+            VfsRequest::Write { fd, data, offset } => {
+                let path = match self.open_files.get(&fd) {
+                    Some(file) if file.tx == Some(id) => file.path.clone(),
+                    _ => return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+                };
+                // The first write to a file starts from its contents as the transaction sees them.
+                let committed = match self.txs.get(id, task, caller.as_ref()).map(|tx| tx.resolve(&path)) {
+                    Ok(Resolved::Staged(Staged::MovedFrom(source))) => Some(source.clone()),
+                    Ok(Resolved::Committed(committed)) => Some(committed),
+                    Ok(Resolved::Staged(_)) => None,
+                    Err(_) => return Self::unknown_tx(id),
+                };
+                let (base, committed_extents) = match committed {
+                    Some(committed) => {
+                        if self.file_size(&committed) > MAX_TX_BYTES {
+                            return Self::tx_too_large(&path);
+                        }
+                        let handle = self.backend_handle_for(&committed);
+                        match self.read_range(handle, &committed, 0, u32::MAX) {
+                            Ok(contents) => (Some(contents), self.extents_of(&committed)),
+                            Err(failed) => return failed,
+                        }
+                    },
+                    None => (None, Extents::new()),
+                };
+                let owner = self.quota.owner_of(&path).unwrap_or_else(|| QuotaTable::owner_for(&path, caller.as_ref()));
+                let len = data.len();
+                let charge = Charge { owner, committed: committed_extents, quota: &self.quota };
+                let staged = match self.txs.get_mut(id, task, caller.as_ref()) {
+                    Ok(tx) => tx.stage_write(&path, offset, data, base, charge),
+                    Err(_) => return Self::unknown_tx(id),
+                };
+                match staged {
+                    Ok(()) => {},
+                    Err(StageError::TooLarge) => return Self::tx_too_large(&path),
+                    Err(StageError::Quota(exceeded)) => return Self::quota_exceeded(&path, exceeded),
+                }
+                if let Some(file) = self.open_files.get_mut(&fd) {
+                    file.cursor = offset + len as u64;
+                }
+                VfsResponse::Success(len as i32)
+            },
+            VfsRequest::Close { fd } => match self.open_files.remove(&fd) {
+                Some(_) => VfsResponse::Success(0),
+                None => VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+            },
+            VfsRequest::Stat { path } => {
+                let committed = match self.txs.get(id, task, caller.as_ref()).map(|tx| tx.resolve(&path)) {
+                    Ok(Resolved::Staged(Staged::File(contents))) => {
+                        return VfsResponse::Metadata(VfsMetadata { is_dir: false, size: contents.len() as u64, created: 0, modified: 0, permissions: 0o644, allocated_size: contents.len() as u64 });
+                    },
+                    Ok(Resolved::Staged(Staged::Directory)) => {
+                        return VfsResponse::Metadata(VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                    },
+                    Ok(Resolved::Staged(Staged::Deleted)) => return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }, // ENOENT
+                    Ok(Resolved::Staged(Staged::MovedFrom(source))) => source.clone(),
+                    Ok(Resolved::Committed(committed)) => committed,
+                    Err(_) => return Self::unknown_tx(id),
+                };
+                self.handle_request(caller, VfsRequest::Stat { path: committed })
+            },
+            VfsRequest::Delete { path } => {
+                if let Err(busy) = self.lock_for_tx(id, &path) {
+                    return busy;
+                }
+                if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                    tx.stage_delete(&path);
+                }
+                VfsResponse::DeleteSuccess
+            },
+            VfsRequest::CreateDirectory { path } => {
+                if let Err(busy) = self.lock_for_tx(id, &path) {
+                    return busy;
+                }
+                if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                    tx.stage_create_directory(&path);
+                }
+                VfsResponse::CreateDirectorySuccess
+            },
+            VfsRequest::Move { source, destination } => {
+                if let Err(busy) = self.lock_for_tx(id, &source).and_then(|_| self.lock_for_tx(id, &destination)) {
+                    return busy;
+                }
+                if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                    tx.stage_move(&source, &destination);
+                }
+                VfsResponse::MoveSuccess
+            },
+            // Names and sizes are checked on commit, against the attributes as they are then.
+            VfsRequest::SetXattr { path, name, value } => {
+                if let Err(busy) = self.lock_for_tx(id, &path) {
+                    return busy;
+                }
+                match self.exists_in_tx(id, task, caller.as_ref(), &path) {
+                    Some(true) => {},
+                    Some(false) => return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }, // ENOENT
+                    None => return Self::unknown_tx(id),
+                }
+                if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                    tx.stage_set_xattr(&path, &name, value);
+                }
+                VfsResponse::Success(0)
+            },
+            VfsRequest::RemoveXattr { path, name } => {
+                if let Err(busy) = self.lock_for_tx(id, &path) {
+                    return busy;
+                }
+                if let Ok(tx) = self.txs.get_mut(id, task, caller.as_ref()) {
+                    tx.stage_remove_xattr(&path, &name);
+                }
+                VfsResponse::Success(0)
+            },
+            other => VfsResponse::Error { code: 22, message: format!("Not supported inside a transaction: {:?}", other) }, // EINVAL
+        }
+    }
+
+    /// Applies a transaction's operations. Quotas are checked against a copy of
+    /// the accounting first, so a commit that would exceed one changes nothing.
+    fn commit_tx(&mut self, id: TxId, identity: Option<AidBytes>, ops: Vec<TxOp>) -> VfsResponse {
+        // Attribute limits the same way, with the deletes and moves that carry attributes replayed too.
+        let mut xattrs = self.xattrs.clone();
+        for op in &ops {
+            let checked = match op {
+                TxOp::Delete { path } => {
+                    xattrs.remove_file(path);
+                    Ok(())
+                },
+                TxOp::Move { source, destination } => {
+                    xattrs.rename(source, destination);
+                    Ok(())
+                },
+                TxOp::SetXattr { path, name, value } => xattrs.set(path, name, value.clone()).map_err(|e| (path, name, e)),
+                TxOp::RemoveXattr { path, name } => xattrs.remove(path, name).map_err(|e| (path, name, e)),
+                _ => Ok(()),
+            };
+            if let Err((path, name, e)) = checked {
+                log(&alloc::format!("VFS: Transaction {} aborted on commit.", id));
+                return Self::xattr_error(path, name, e);
+            }
+        }
+
+        let mut quota = self.quota.clone();
+        // What each file will have allocated once the ops before it are applied.
+        let mut extents: BTreeMap<&str, Extents> = BTreeMap::new();
+        for op in &ops {
+            let charged = match op {
+                TxOp::Truncate { path } => {
+                    quota.track(path, QuotaTable::owner_for(path, identity.as_ref()));
+                    extents.insert(path, Extents::new());
+                    quota.resize(path, 0).map_err(|exceeded| (path, exceeded))
+                },
+                TxOp::Write { path, offset, data } => {
+                    quota.track(path, QuotaTable::owner_for(path, identity.as_ref()));
+                    let file = extents.entry(path).or_insert_with(|| self.extents_of(path));
+                    let (first, end) = sparse::covering(*offset, data.len() as u64);
+                    file.add(first, end);
+                    quota.resize(path, file.allocated_bytes()).map_err(|exceeded| (path, exceeded))
+                },
+                TxOp::Delete { path } => {
+                    quota.remove(path);
+                    extents.insert(path, Extents::new());
+                    Ok(())
+                },
+                TxOp::CreateDirectory { .. } | TxOp::SetXattr { .. } | TxOp::RemoveXattr { .. } => Ok(()),
+                TxOp::Move { source, destination } => {
+                    let moved = extents.remove(source.as_str()).unwrap_or_else(|| self.extents_of(source));
+                    extents.insert(source, Extents::new());
+                    extents.insert(destination, moved);
+                    quota.rename(source, destination).map_err(|exceeded| (destination, exceeded))
+                },
+            };
+            if let Err((path, exceeded)) = charged {
+                log(&alloc::format!("VFS: Transaction {} aborted on commit.", id));
+                return Self::quota_exceeded(path, exceeded);
+            }
+        }
+        self.quota = quota;
+
+        let count = ops.len();
+        for op in ops {
+            match op {
+                TxOp::Truncate { path } => {
+                    let handle = self.backend_handle_for(&path);
+                    self.apply_truncate(handle);
+                    self.stamp_modified(&path);
+                },
+                TxOp::Write { path, offset, data } => {
+                    let handle = self.backend_handle_for(&path);
+                    let current_size = self.backend_sizes.get(&handle).copied().unwrap_or(0);
+                    // The dirty budget is not enforced here; everything is flushed below anyway.
+                    let _ = self.cache.write(handle, current_size, offset, &data, self.now);
+                    let (first, end) = sparse::covering(offset, data.len() as u64);
+                    self.extents.entry(handle).or_default().add(first, end);
+                    self.stamp_modified(&path);
+                },
+                TxOp::Delete { path } => self.apply_delete(&path),
+                TxOp::CreateDirectory { path } => self.stamp_created(&path, true), // Conceptual: Send IPC to backend to create directory.
+                TxOp::Move { source, destination } => self.apply_move(&source, &destination),
+                // Checked above, so these can't fail now.
+                TxOp::SetXattr { path, name, value } => { let _ = self.xattrs.set(&path, &name, value); },
+                TxOp::RemoveXattr { path, name } => { let _ = self.xattrs.remove(&path, &name); },
+            }
+        }
+        // Conceptual: the backend receives the flushed blocks together with the
+        // deletes and moves above as one journaled batch, so a crash leaves it
+        // with all of the transaction or none of it.
+        let flushed = self.cache.flush_all();
+        if let Err(failed) = self.apply_flush(flushed) {
+            return failed;
+        }
+        log(&alloc::format!("VFS: Committed transaction {} ({} operations).", id, count));
+        VfsResponse::Success(0)
+    }
+
+    fn handle_request(&mut self, caller: Option<AidBytes>, request: VfsRequest) -> VfsResponse {
+        if let Err(denied) = self.authorize(caller.as_ref(), &request) {
+            log(&alloc::format!("VFS: Denied {:?}: {:?}.", request, denied));
+            return denied;
+        }
+        if let Err(read_only) = self.check_writable(&request) {
+            return read_only;
+        }
+        if let Some(id) = self.fd_tx(&request) {
+            return self.handle_in_tx(id, caller, request);
+        }
+        if let Err(busy) = self.check_unlocked(&request) {
+            return busy;
+        }
+        match request {
+            VfsRequest::Open { path, flags } => {
+                debug(&alloc::format!("VFS: Open request for path: {} with flags: {}.", path, flags));
                 // Conceptual: Send IPC to AetherFS or other backend to open/create file
                 // For now, simulate success and create a dummy OpenFile entry.
-                // In a real scenario, the backend would return its own handle.
-                let backend_handle = 1000 + self.next_fd as u64; // Dummy backend handle
+                let backend_handle = self.backend_handle_for(&path);
+                self.quota.track(&path, QuotaTable::owner_for(&path, caller.as_ref()));
+                self.stamp_created(&path, false);
+                if flags & 1 != 0 {
+                    // O_TRUNC: buffered data for the old contents is no longer wanted.
+                    self.apply_truncate(backend_handle);
+                    let _ = self.quota.resize(&path, 0); // Shrinking never fails
+                    self.stamp_modified(&path);
+                }
 
                 let fd = self.next_fd;
                 self.next_fd += 1;
-                self.open_files.insert(fd, OpenFile { path: path.clone(), flags, cursor: 0, backend_handle });
-                log(&alloc::format!("VFS: Opened {} as fd {}.", path, fd));
+                self.open_files.insert(fd, OpenFile { path: path.clone(), flags, cursor: 0, backend_handle, owner: caller, tx: None });
+                debug(&alloc::format!("VFS: Opened {} as fd {}.", path, fd));
                 VfsResponse::Success(fd as i32)
             },
             VfsRequest::Read { fd, len, offset } => {
-                if let Some(file) = self.open_files.get_mut(&fd) {
-                    log(&alloc::format!("VFS: Read request for fd: {}, len: {}, offset: {}.", fd, len, offset));
-                    // Conceptual: Send IPC to backend (e.g., AetherFS) to read data
-                    // For now, return dummy data and simulate backend read.
-                    // The actual `read` operation would involve sending a request to `aetherfs_chan`
-                    // with file.backend_handle, offset, and len.
-
-                    // Simulate reading from AetherFS backend
-                    // Example: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Read { handle: file.backend_handle, offset, len })`
-                    let dummy_data = alloc::format!("dummy_data_from_file_{}_at_offset_{}", file.path, offset).as_bytes().to_vec();
-
-                    let bytes_to_read = len.min(dummy_data.len() as u32) as usize;
-                    let mut response_data = Vec::with_capacity(bytes_to_read);
-                    response_data.extend_from_slice(&dummy_data[..bytes_to_read]);
-
-                    file.cursor = offset + response_data.len() as u64;
-                    log(&alloc::format!("VFS: Read {} bytes from fd {} at offset {}.", response_data.len(), fd, offset));
+                if let Some(file) = self.open_files.get(&fd) {
+                    debug(&alloc::format!("VFS: Read request for fd: {}, len: {}, offset: {}.", fd, len, offset));
+                    let (handle, path) = (file.backend_handle, file.path.clone());
+                    let response_data = match self.read_range(handle, &path, offset, len) {
+                        Ok(data) => data,
+                        Err(failed) => return failed,
+                    };
+                    if let Some(file) = self.open_files.get_mut(&fd) {
+                        file.cursor = offset + response_data.len() as u64;
+                    }
+                    debug(&alloc::format!("VFS: Read {} bytes from fd {} at offset {}.", response_data.len(), fd, offset));
                     VfsResponse::Data(response_data)
                 } else {
                     log(&alloc::format!("VFS: Read failed, bad fd: {}.", fd));
@@ -101,17 +1002,35 @@ impl VfsService {
                 }
             },
             VfsRequest::Write { fd, data, offset } => {
-                if let Some(file) = self.open_files.get_mut(&fd) {
-                    log(&alloc::format!("VFS: Write request for fd: {}, len: {}, offset: {}.", fd, data.len(), offset));
-                    // Conceptual: Send IPC to backend (e.g., AetherFS) to write data
-                    // The actual `write` operation would involve sending a request to `aetherfs_chan`
-                    // with file.backend_handle, offset, and data.
-
-                    // Simulate writing to AetherFS backend
-                    // Example: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Write { handle: file.backend_handle, offset, data })`
-
-                    file.cursor = offset + data.len() as u64;
-                    log(&alloc::format!("VFS: Wrote {} bytes to fd {} at offset {}.", data.len(), fd, offset));
+                if let Some(file) = self.open_files.get(&fd) {
+                    debug(&alloc::format!("VFS: Write request for fd: {}, len: {}, offset: {}.", fd, data.len(), offset));
+                    let (handle, path) = (file.backend_handle, file.path.clone());
+                    // Charge the blocks the write allocates to the file's owner before
+                    // anything is buffered. Blocks it skips over stay holes.
+                    let end = match offset.checked_add(data.len() as u64) {
+                        Some(end) => end,
+                        None => return VfsResponse::Error { code: 27, message: format!("Write of {} bytes at {} is past the largest file size", data.len(), offset) }, // EFBIG
+                    };
+                    let extents = self.extents.entry(handle).or_default();
+                    if let Err(exceeded) = self.quota.resize(&path, extents.allocated_bytes() + extents.growth(offset, data.len() as u64)) {
+                        return Self::quota_exceeded(&path, exceeded);
+                    }
+                    let (first, last) = sparse::covering(offset, data.len() as u64);
+                    extents.add(first, last);
+                    if let Some(file) = self.open_files.get_mut(&fd) {
+                        file.cursor = end;
+                    }
+                    self.stamp_modified(&path);
+                    // Buffer the write in the cache; it reaches the backend on the next flush.
+                    let current_size = self.backend_sizes.get(&handle).copied().unwrap_or(0);
+                    if self.cache.write(handle, current_size, offset, &data, self.now) {
+                        debug("VFS: Dirty cache budget exceeded, flushing.");
+                        let ops = self.cache.flush_all();
+                        if let Err(failed) = self.apply_flush(ops) {
+                            return failed;
+                        }
+                    }
+                    debug(&alloc::format!("VFS: Wrote {} bytes to fd {} at offset {}.", data.len(), fd, offset));
                     VfsResponse::Success(data.len() as i32)
                 } else {
                     log(&alloc::format!("VFS: Write failed, bad fd: {}.", fd));
@@ -119,36 +1038,52 @@ impl VfsService {
                 }
             },
             VfsRequest::List { path } => {
-                log(&alloc::format!("VFS: List request for path: {}.", path));
+                debug(&alloc::format!("VFS: List request for path: {}.", path));
                 // Conceptual: Send IPC to backend to list directory contents
                 // Example: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::ListDir { path: path.clone() })`
+                // Backend entry names arrive as raw bytes and go through `vfs_ipc::name_from_bytes`;
+                // entries that fail it are logged and left out of the listing.
                 let mut entries = BTreeMap::new();
                 if path == "/" {
-                    entries.insert("home".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755 });
-                    entries.insert("etc".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755 });
-                    entries.insert("bin".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755 });
-                    entries.insert("README.txt".to_string(), VfsMetadata { is_dir: false, size: 1024, created: 0, modified: 0, permissions: 0o644 });
+                    entries.insert("home".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                    entries.insert("etc".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                    entries.insert("bin".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                    entries.insert("README.txt".to_string(), VfsMetadata { is_dir: false, size: 1024, created: 0, modified: 0, permissions: 0o644, allocated_size: 1024 });
                 } else if path == "/home" {
-                    entries.insert("user".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755 });
+                    entries.insert("user".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                } else if path == "/proc" {
+                    for (name, contents) in &self.proc_files {
+                        let name = name.trim_start_matches("/proc/").to_string();
+                        entries.insert(name, VfsMetadata { is_dir: false, size: contents.len() as u64, created: 0, modified: 0, permissions: 0o444, allocated_size: contents.len() as u64 });
+                    }
+                    let tasks_len = proc_tasks().len() as u64;
+                    entries.insert(PROC_TASKS.trim_start_matches("/proc/").to_string(), VfsMetadata { is_dir: false, size: tasks_len, created: 0, modified: 0, permissions: 0o444, allocated_size: tasks_len });
                 } else if path == "/home/user" {
-                    entries.insert("documents".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755 });
-                    entries.insert("config.txt".to_string(), VfsMetadata { is_dir: false, size: 256, created: 0, modified: 0, permissions: 0o644 });
+                    entries.insert("documents".to_string(), VfsMetadata { is_dir: true, size: 0, created: 0, modified: 0, permissions: 0o755, allocated_size: 0 });
+                    entries.insert("config.txt".to_string(), VfsMetadata { is_dir: false, size: 256, created: 0, modified: 0, permissions: 0o644, allocated_size: 256 });
                 } else {
                     return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }; // ENOENT
                 }
-                log(&alloc::format!("VFS: Listed {} entries for path {}.", entries.len(), path));
+                debug(&alloc::format!("VFS: Listed {} entries for path {}.", entries.len(), path));
                 VfsResponse::DirectoryEntries(entries)
             },
             VfsRequest::Stat { path } => {
-                log(&alloc::format!("VFS: Stat request for path: {}.", path));
+                debug(&alloc::format!("VFS: Stat request for path: {}.", path));
                 // Conceptual: Send IPC to backend to get metadata
                 // Example: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Stat { path: path.clone() })`
-                if path == "/README.txt" {
-                    log(&alloc::format!("VFS: Returned metadata for {}.", path));
-                    VfsResponse::Metadata(VfsMetadata { is_dir: false, size: 1024, created: 1678886400, modified: 1678886400, permissions: 0o644 })
+                if let Some(contents) = self.proc_file(&path) {
+                    VfsResponse::Metadata(VfsMetadata { is_dir: false, size: contents.len() as u64, created: 0, modified: 0, permissions: 0o444, allocated_size: contents.len() as u64 })
+                } else if let Some(times) = self.times.get(&path) {
+                    let (size, allocated_size) = if times.is_dir { (0, 0) } else { (self.file_size(&path), self.allocated_size(&path)) };
+                    let permissions = if times.is_dir { 0o755 } else { 0o644 };
+                    debug(&alloc::format!("VFS: Returned metadata for {}.", path));
+                    VfsResponse::Metadata(VfsMetadata { is_dir: times.is_dir, size, created: times.created, modified: times.modified, permissions, allocated_size })
+                } else if path == "/README.txt" {
+                    debug(&alloc::format!("VFS: Returned metadata for {}.", path));
+                    VfsResponse::Metadata(VfsMetadata { is_dir: false, size: 1024, created: 1678886400, modified: 1678886400, permissions: 0o644, allocated_size: 1024 })
                 } else if path == "/home" {
-                    log(&alloc::format!("VFS: Returned metadata for {}.", path));
-                    VfsResponse::Metadata(VfsMetadata { is_dir: true, size: 0, created: 1678886400, modified: 1678886400, permissions: 0o755 })
+                    debug(&alloc::format!("VFS: Returned metadata for {}.", path));
+                    VfsResponse::Metadata(VfsMetadata { is_dir: true, size: 0, created: 1678886400, modified: 1678886400, permissions: 0o755, allocated_size: 0 })
                 } else {
                     log(&alloc::format!("VFS: Path not found for stat: {}.", path));
                     VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) } // ENOENT
@@ -156,7 +1091,14 @@ impl VfsService {
             },
             VfsRequest::Close { fd } => {
                 if let Some(file) = self.open_files.remove(&fd) {
-                    log(&alloc::format!("VFS: Closed fd {} (path: {}).", fd, file.path));
+                    debug(&alloc::format!("VFS: Closed fd {} (path: {}).", fd, file.path));
+                    let ended = self.streams.close_fd(fd);
+                    if ended > 0 {
+                        debug(&alloc::format!("VFS: Ended {} streams on fd {}.", ended, fd));
+                    }
+                    if self.locks.unlock(file.backend_handle, fd) {
+                        log(&alloc::format!("VFS: Released the lock held through fd {}.", fd));
+                    }
                     // Conceptual: Send IPC to backend to close file handle
                     // Example: `self.aetherfs_chan.send_and_recv(&AetherFsRequest::Close { handle: file.backend_handle })`
                     VfsResponse::Success(0)
@@ -166,56 +1108,744 @@ impl VfsService {
                 }
             },
             VfsRequest::Delete { path } => {
-                log(&alloc::format!("VFS: Delete request for path: {}.", path));
-                // Conceptual: Send IPC to backend to delete file/directory.
-                // For now, simulate success.
+                debug(&alloc::format!("VFS: Delete request for path: {}.", path));
+                // Allowed even over quota, so that space can always be freed.
+                self.quota.remove(&path);
+                self.apply_delete(&path);
                 VfsResponse::DeleteSuccess
             },
             VfsRequest::CreateDirectory { path } => {
-                log(&alloc::format!("VFS: Create directory request for path: {}.", path));
+                debug(&alloc::format!("VFS: Create directory request for path: {}.", path));
                 // Conceptual: Send IPC to backend to create directory.
                 // For now, simulate success.
+                self.stamp_created(&path, true);
                 VfsResponse::CreateDirectorySuccess
             },
             VfsRequest::Move { source, destination } => {
-                log(&alloc::format!("VFS: Move request from {} to {}.", source, destination));
-                // Conceptual: Send IPC to backend to move/rename file/directory.
-                // For now, simulate success.
+                debug(&alloc::format!("VFS: Move request from {} to {}.", source, destination));
+                if let Err(exceeded) = self.quota.rename(&source, &destination) {
+                    return Self::quota_exceeded(&destination, exceeded);
+                }
+                // The new directory entry must not become visible before the data it points
+                // to, so flush everything first. This is what makes write-tmp-then-rename atomic.
+                let ops = self.cache.flush_all();
+                if let Err(failed) = self.apply_flush(ops) {
+                    return failed;
+                }
+                self.apply_move(&source, &destination);
                 VfsResponse::MoveSuccess
             },
+            VfsRequest::Fsync { fd } => {
+                if let Some(file) = self.open_files.get(&fd) {
+                    let handle = file.backend_handle;
+                    let ops = self.cache.flush_file(handle);
+                    debug(&alloc::format!("VFS: Fsync fd {} ({} operations).", fd, ops.len()));
+                    if let Err(failed) = self.apply_flush(ops) {
+                        return failed;
+                    }
+                    self.cache.record_fsync();
+                    VfsResponse::Success(0)
+                } else {
+                    log(&alloc::format!("VFS: Fsync failed, bad fd: {}.", fd));
+                    VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() } // EBADF
+                }
+            },
+            VfsRequest::SyncAll => {
+                let ops = self.cache.flush_all();
+                debug(&alloc::format!("VFS: SyncAll ({} operations).", ops.len()));
+                if let Err(failed) = self.apply_flush(ops) {
+                    return failed;
+                }
+                self.cache.record_fsync();
+                VfsResponse::Success(0)
+            },
+            VfsRequest::Metrics(request) => VfsResponse::Metrics(self.metrics.handle(&request)),
+            VfsRequest::StorageHealth => VfsResponse::StorageHealth(self.health.record()),
+            VfsRequest::Pin { fd } => {
+                let (handle, path) = match self.open_files.get(&fd) {
+                    Some(file) => (file.backend_handle, file.path.clone()),
+                    None => return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+                };
+                let grantee = match self.sender {
+                    Some(task) => task,
+                    None => return VfsResponse::Error { code: 22, message: "Cannot identify the requesting task".to_string() }, // EINVAL
+                };
+                // In-memory files are copied now. Files on the device are read a page at a time as
+                // the mapping touches them (`fill_pages`), or copied on kernels that can't do that.
+                let on_demand = if self.proc_file(&path).is_none() {
+                    let size = self.handle_size(handle) as usize;
+                    match self.pins.pin_on_demand(size, PinSource { handle, path: path.clone() }, grantee) {
+                        Err(PinError::Unsupported) => None,
+                        result => Some(result.map(|backing| (backing, size))),
+                    }
+                } else {
+                    None
+                };
+                let result = match on_demand {
+                    Some(result) => result,
+                    None => {
+                        // The pin is a snapshot, so it must include writes still sitting in the cache.
+                        let contents = match self.read_range(handle, &path, 0, u32::MAX) {
+                            Ok(contents) => contents,
+                            Err(failed) => return failed,
+                        };
+                        self.pins.pin(&contents, grantee).map(|backing| (backing, contents.len()))
+                    },
+                };
+                match result {
+                    Ok((backing, size)) => {
+                        log(&alloc::format!("VFS: Pinned {} ({} bytes) for task {} as backing {}.", path, size, grantee, backing));
+                        VfsResponse::Pinned { backing, size: size as u64 }
+                    },
+                    Err(PinError::TooLarge) => VfsResponse::Error { code: 27, message: format!("File too large to pin: {}", path) }, // EFBIG
+                    Err(PinError::OutOfMemory) => VfsResponse::Error { code: 12, message: "Out of memory".to_string() }, // ENOMEM
+                    Err(PinError::Kernel(code)) => VfsResponse::Error { code: 95, message: format!("Kernel refused to share pages ({:#x})", code) }, // EOPNOTSUPP
+                    Err(PinError::Unsupported) => VfsResponse::Error { code: 95, message: "Kernel can't share pages".to_string() }, // EOPNOTSUPP
+                }
+            },
+            VfsRequest::Unpin { backing } => {
+                let requester = self.sender.unwrap_or(0);
+                if self.pins.unpin(backing, requester) {
+                    log(&alloc::format!("VFS: Unpinned backing {} ({} still mapped).", backing, self.pins.retired_count()));
+                    VfsResponse::Success(0)
+                } else {
+                    VfsResponse::Error { code: 22, message: format!("No pin {} held by this task", backing) } // EINVAL
+                }
+            },
+            VfsRequest::GetUsage { owner } => {
+                let owner = owner.or(caller).unwrap_or(SYSTEM_AID);
+                VfsResponse::Usage(self.quota.usage(&owner))
+            },
+            VfsRequest::TxBegin => match self.sender {
+                Some(task) => {
+                    let id = self.txs.begin(task, caller, self.now);
+                    log(&alloc::format!("VFS: Transaction {} started by task {}.", id, task));
+                    VfsResponse::TxBegun { id }
+                },
+                None => VfsResponse::Error { code: 22, message: "Cannot identify the requesting task".to_string() }, // EINVAL
+            },
+            VfsRequest::TxCommit { id } => {
+                let task = self.sender.unwrap_or(0);
+                match self.txs.take(id, task, caller.as_ref()) {
+                    Ok(tx) => {
+                        self.close_tx_fds(id);
+                        let identity = tx.identity;
+                        self.commit_tx(id, identity, tx.into_ops())
+                    },
+                    Err(_) => Self::unknown_tx(id),
+                }
+            },
+            VfsRequest::TxAbort { id } => {
+                let task = self.sender.unwrap_or(0);
+                match self.txs.take(id, task, caller.as_ref()) {
+                    Ok(_) => {
+                        self.close_tx_fds(id);
+                        log(&alloc::format!("VFS: Transaction {} aborted.", id));
+                        VfsResponse::Success(0)
+                    },
+                    Err(_) => Self::unknown_tx(id),
+                }
+            },
+            VfsRequest::InTx { id, request } => self.handle_in_tx(id, caller, *request),
+            VfsRequest::ReadStream { fd, offset, len } => {
+                let direction = Direction::Read(ReadStream { fd, offset, remaining: len, credit: STREAM_WINDOW, seq: 0 });
+                self.start_stream(fd, caller, direction)
+            },
+            VfsRequest::WriteStream { fd, offset } => {
+                let direction = Direction::Write(WriteStream { fd, offset, next_seq: 0, written: 0, unacked: 0 });
+                self.start_stream(fd, caller, direction)
+            },
+            VfsRequest::SetXattr { path, name, value } => {
+                if !self.exists(&path) {
+                    return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }; // ENOENT
+                }
+                let len = value.len();
+                match self.xattrs.set(&path, &name, value) {
+                    Ok(()) => {
+                        // Conceptual: Send IPC to backend to store the file's attributes with its
+                        // metadata; AetherFS journals the metadata block like any other.
+                        log(&alloc::format!("VFS: Set {} ({} bytes) on {}.", name, len, path));
+                        VfsResponse::Success(0)
+                    },
+                    Err(e) => Self::xattr_error(&path, &name, e),
+                }
+            },
+            VfsRequest::GetXattr { path, name } => {
+                if !self.exists(&path) {
+                    return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }; // ENOENT
+                }
+                match self.xattrs.get(&path, &name) {
+                    Ok(value) => VfsResponse::Data(value.to_vec()),
+                    Err(e) => Self::xattr_error(&path, &name, e),
+                }
+            },
+            VfsRequest::ListXattrs { path } => {
+                if !self.exists(&path) {
+                    return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }; // ENOENT
+                }
+                VfsResponse::XattrNames(self.xattrs.names(&path))
+            },
+            VfsRequest::RemoveXattr { path, name } => {
+                if !self.exists(&path) {
+                    return VfsResponse::Error { code: 2, message: format!("Path not found: {}", path) }; // ENOENT
+                }
+                match self.xattrs.remove(&path, &name) {
+                    Ok(()) => {
+                        // Conceptual: Send IPC to backend to store the file's attributes, as for SetXattr.
+                        log(&alloc::format!("VFS: Removed {} from {}.", name, path));
+                        VfsResponse::Success(0)
+                    },
+                    Err(e) => Self::xattr_error(&path, &name, e),
+                }
+            },
+            VfsRequest::StatWithXattrs { path, names } => match self.handle_request(caller, VfsRequest::Stat { path: path.clone() }) {
+                VfsResponse::Metadata(metadata) => VfsResponse::MetadataWithXattrs { metadata, xattrs: self.xattrs.select(&path, &names) },
+                other => other,
+            },
+            VfsRequest::ListWithXattrs { path, names } => match self.handle_request(caller, VfsRequest::List { path: path.clone() }) {
+                VfsResponse::DirectoryEntries(entries) => {
+                    let dir = path.trim_end_matches('/');
+                    let entries = entries.into_iter()
+                        .map(|(name, metadata)| {
+                            let xattrs = self.xattrs.select(&format!("{}/{}", dir, name), &names);
+                            (name, (metadata, xattrs))
+                        })
+                        .collect();
+                    VfsResponse::DirectoryEntriesWithXattrs(entries)
+                },
+                other => other,
+            },
+            VfsRequest::Unlock { fd } => match self.open_files.get(&fd) {
+                Some(file) if self.locks.unlock(file.backend_handle, fd) => VfsResponse::Success(0),
+                Some(_) => VfsResponse::Error { code: 37, message: format!("No lock held through fd {}", fd) }, // ENOLCK
+                None => VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+            },
+            // A lock without a reply token can't wait.
+            VfsRequest::Lock { fd, exclusive, wait } => self.lock_fd(fd, exclusive, wait, None).unwrap_or(VfsResponse::WouldBlock),
+            VfsRequest::Watch { path, reply_chan } => match self.sender {
+                Some(task) if self.watches.add(task, &path, reply_chan) => {
+                    log(&alloc::format!("VFS: Task {} watches {} on channel {}.", task, path, reply_chan));
+                    VfsResponse::Success(0)
+                },
+                Some(_) => VfsResponse::Error { code: 24, message: format!("Too many watches (at most {})", watch::MAX_WATCHES_PER_TASK) }, // EMFILE
+                None => VfsResponse::Error { code: 22, message: "Cannot identify the requesting task".to_string() }, // EINVAL
+            },
+            VfsRequest::Unwatch { path, reply_chan } => match self.sender {
+                Some(task) if self.watches.remove(task, &path, reply_chan) => VfsResponse::Success(0),
+                _ => VfsResponse::Error { code: 2, message: format!("No watch on {} for channel {}", path, reply_chan) }, // ENOENT
+            },
+            VfsRequest::Allocate { fd, offset, len, mode } => self.allocate(fd, offset, len, mode),
+            VfsRequest::SeekData { fd, offset } => self.seek(fd, offset, true),
+            VfsRequest::SeekHole { fd, offset } => self.seek(fd, offset, false),
+            // Handled by `handle_message` before they get here.
+            VfsRequest::StreamCredit { stream_id, .. } | VfsRequest::StreamData { stream_id, .. } => {
+                VfsResponse::StreamError { stream_id, code: 22, message: "Not a request".to_string() } // EINVAL
+            },
         }
     }
 
-    fn run_loop(&mut self) -> ! {
-        log("VFS Service: Entering main event loop.");
-        loop {
-            // Process incoming requests from client V-Nodes
-            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
-                if let Ok(request) = postcard::from_bytes::<VfsRequest>(&req_data) {
-                    log(&alloc::format!("VFS Service: Received VfsRequest: {:?}.", request));
-                    let response = self.handle_request(request);
-                    self.client_chan.send(&response).unwrap_or_else(|_| log("VFS Service: Failed to send response to client."));
-                } else {
-                    log("VFS Service: Failed to deserialize VfsRequest from client.");
+    /// Zeroes or reserves `[offset, offset + len)` of the file behind `fd`,
+    /// growing the file if the range ends past it.
+    fn allocate(&mut self, fd: Fd, offset: u64, len: u64, mode: AllocateMode) -> VfsResponse {
+        let (handle, path) = match self.open_files.get(&fd) {
+            Some(file) => (file.backend_handle, file.path.clone()),
+            None => return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+        };
+        let end = match offset.checked_add(len) {
+            Some(end) if len > 0 => end,
+            _ => return VfsResponse::Error { code: 22, message: format!("Invalid range: {} bytes at {}", len, offset) }, // EINVAL
+        };
+        let size = self.handle_size(handle);
+        let backend_size = self.backend_sizes.get(&handle).copied().unwrap_or(0);
+        let extents = self.extents.entry(handle).or_default();
+        match mode {
+            AllocateMode::Reserve => {
+                if let Err(exceeded) = self.quota.resize(&path, extents.allocated_bytes() + extents.growth(offset, len)) {
+                    return Self::quota_exceeded(&path, exceeded);
+                }
+                let (first, last) = sparse::covering(offset, len);
+                extents.add(first, last);
+                self.cache.reserve(handle, first, last, self.now);
+            },
+            AllocateMode::ZeroRange => {
+                let (first, last) = sparse::inside(offset, len);
+                extents.remove(first, last);
+                // Blocks the range only partly covers keep their storage and get
+                // zeros written; ones that are holes already read as zeros.
+                let block = BLOCK_SIZE as u64;
+                for (start, stop) in [(offset, (first * block).min(end)), ((last * block).max(offset), end)] {
+                    if start < stop && extents.is_allocated(start / block) {
+                        let zeros = alloc::vec![0; (stop - start) as usize];
+                        let _ = self.cache.write(handle, backend_size, start, &zeros, self.now);
+                    }
+                }
+                self.cache.punch(handle, first, last, self.now);
+                let _ = self.quota.resize(&path, extents.allocated_bytes()); // Shrinking never fails
+            },
+        }
+        if end > size {
+            self.cache.grow(handle, backend_size, end, self.now);
+        }
+        self.stamp_modified(&path);
+        debug(&alloc::format!("VFS: {:?} of {} bytes at {} on fd {}.", mode, len, offset, fd));
+        VfsResponse::Success(0)
+    }
+
+    /// The first offset at or after `offset` of the file behind `fd` that holds
+    /// data, or with `data` unset, that lies in a hole.
+    fn seek(&self, fd: Fd, offset: u64, data: bool) -> VfsResponse {
+        let file = match self.open_files.get(&fd) {
+            Some(file) => file,
+            None => return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }, // EBADF
+        };
+        let size = match self.proc_file(&file.path) {
+            Some(contents) => contents.len() as u64,
+            None => self.handle_size(file.backend_handle),
+        };
+        let past_end = || VfsResponse::Error { code: 6, message: format!("Offset {} is past the end of {}", offset, file.path) }; // ENXIO
+        if offset >= size {
+            return past_end();
+        }
+        let found = match (self.extents.get(&file.backend_handle), data) {
+            // Without an allocation map, every byte the backend has is data.
+            (None, true) => offset,
+            (None, false) => size,
+            (Some(extents), true) => match extents.next_data(offset) {
+                Some(at) if at < size => at,
+                _ => return past_end(),
+            },
+            (Some(extents), false) => extents.next_hole(offset).min(size),
+        };
+        VfsResponse::Offset(found)
+    }
+
+    fn start_stream(&mut self, fd: Fd, caller: Option<AidBytes>, direction: Direction) -> VfsResponse {
+        if !self.open_files.contains_key(&fd) {
+            return VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }; // EBADF
+        }
+        let task = match self.sender {
+            Some(task) => task,
+            None => return VfsResponse::Error { code: 22, message: "Cannot identify the requesting task".to_string() }, // EINVAL
+        };
+        match self.streams.start(task, caller, direction) {
+            Some(stream_id) => {
+                debug(&alloc::format!("VFS: Stream {} started on fd {} by task {}.", stream_id, fd, task));
+                VfsResponse::StreamStarted { stream_id }
+            },
+            None => VfsResponse::Error { code: 24, message: format!("Too many streams (at most {})", stream::MAX_STREAMS_PER_TASK) }, // EMFILE
+        }
+    }
+
+    /// Locks the file behind `fd` for the requesting task. Returns `None` if the
+    /// lock was queued; `token` is answered once it is granted.
+    fn lock_fd(&mut self, fd: Fd, exclusive: bool, wait: bool, token: Option<ReplyToken>) -> Option<VfsResponse> {
+        let handle = match self.open_files.get(&fd) {
+            Some(file) if file.tx.is_some() => {
+                return Some(VfsResponse::Error { code: 22, message: "Locks are not supported inside a transaction".to_string() }); // EINVAL
+            },
+            Some(file) => file.backend_handle,
+            None => return Some(VfsResponse::Error { code: 9, message: "Bad file descriptor".to_string() }), // EBADF
+        };
+        let task = match self.sender {
+            Some(task) => task,
+            None => return Some(VfsResponse::Error { code: 22, message: "Cannot identify the requesting task".to_string() }), // EINVAL
+        };
+        match self.locks.lock(handle, fd, task, exclusive, token.filter(|_| wait)) {
+            Ok(Locked::Granted) => Some(VfsResponse::Success(0)),
+            Ok(Locked::Queued) => {
+                log(&alloc::format!("VFS: Task {} waits for a lock on fd {}.", task, fd));
+                None
+            },
+            Err(LockError::WouldBlock) => Some(VfsResponse::WouldBlock),
+            Err(LockError::Deadlock) => {
+                log(&alloc::format!("VFS: Refused a lock on fd {} that task {} would wait for forever.", fd, task));
+                Some(VfsResponse::Deadlock)
+            },
+        }
+    }
+
+    /// Answers the lock requests granted since the last call.
+    fn reply_granted_locks(&mut self) {
+        for token in self.locks.take_granted() {
+            self.ready.push((Some(token), VfsResponse::Success(0)));
+        }
+    }
+
+    /// Handles a message from a client. Stream credit and data are not
+    /// answered one by one, so this returns `None` for most of them, and a
+    /// lock that has to wait returns `None` until it is granted.
+    fn handle_message(&mut self, caller: Option<AidBytes>, request: VfsRequest, token: Option<ReplyToken>) -> Option<VfsResponse> {
+        let task = self.sender.unwrap_or(0);
+        match request {
+            VfsRequest::Lock { fd, exclusive, wait } => match self.authorize(caller.as_ref(), &request) {
+                Ok(()) => self.lock_fd(fd, exclusive, wait, token),
+                Err(denied) => Some(denied),
+            },
+            VfsRequest::StreamCredit { stream_id, chunks } => {
+                // Credit can arrive just after the last chunk went out; it is simply dropped.
+                if !self.streams.credit(stream_id, task, chunks) {
+                    log(&alloc::format!("VFS: Ignoring credit for unknown stream {} from task {}.", stream_id, task));
                 }
+                None
+            },
+            VfsRequest::StreamData { stream_id, seq, data, eof } => self.stream_data(task, stream_id, seq, data, eof),
+            request => Some(self.handle_request(caller, request)),
+        }
+    }
+
+    /// Writes the next chunk of a write stream.
+    fn stream_data(&mut self, task: u64, stream_id: StreamId, seq: u64, data: Vec<u8>, eof: bool) -> Option<VfsResponse> {
+        let (fd, offset, caller) = match self.streams.get_mut(stream_id, task) {
+            Some(stream) => match &stream.direction {
+                Direction::Write(write) if write.next_seq == seq => (write.fd, write.offset, stream.caller),
+                Direction::Write(write) => {
+                    let message = format!("Expected chunk {}, got {}", write.next_seq, seq);
+                    self.streams.remove(stream_id);
+                    return Some(VfsResponse::StreamError { stream_id, code: 22, message }); // EINVAL
+                },
+                Direction::Read(_) => return Some(VfsResponse::StreamError { stream_id, code: 22, message: "Not a write stream".to_string() }), // EINVAL
+            },
+            None => return Some(VfsResponse::StreamError { stream_id, code: 22, message: format!("No stream {} held by this task", stream_id) }), // EINVAL
+        };
+        let len = data.len() as u64;
+        if len > 0 {
+            match self.handle_request(caller, VfsRequest::Write { fd, data, offset }) {
+                VfsResponse::Success(_) => {},
+                failed => {
+                    self.streams.remove(stream_id);
+                    let (code, message) = Self::error_parts(failed);
+                    log(&alloc::format!("VFS: Write stream {} failed at chunk {}: {}.", stream_id, seq, message));
+                    return Some(VfsResponse::StreamError { stream_id, code, message });
+                },
+            }
+        }
+        let Some(Direction::Write(write)) = self.streams.get_mut(stream_id, task).map(|stream| &mut stream.direction) else { return None };
+        write.offset += len;
+        write.written += len;
+        write.next_seq += 1;
+        write.unacked += 1;
+        let written = write.written;
+        if eof {
+            self.streams.remove(stream_id);
+            debug(&alloc::format!("VFS: Write stream {} finished ({} bytes).", stream_id, written));
+            Some(VfsResponse::StreamFinished { stream_id, written })
+        } else if write.unacked >= STREAM_WINDOW / 2 {
+            write.unacked = 0;
+            Some(VfsResponse::StreamAck { stream_id, seq, written })
+        } else {
+            None
+        }
+    }
+
+    /// Sends the next chunk of every read stream that has credit.
+    fn pump_streams(&mut self) {
+        for stream_id in self.streams.ready() {
+            let (fd, offset, remaining, seq, caller) = match self.streams.read_mut(stream_id) {
+                Some((read, caller)) => (read.fd, read.offset, read.remaining, read.seq, caller),
+                None => continue,
+            };
+            let len = remaining.min(STREAM_CHUNK_SIZE as u64) as u32;
+            let message = match self.handle_request(caller, VfsRequest::Read { fd, len, offset }) {
+                VfsResponse::Data(data) => {
+                    // A short read means the file ended.
+                    let eof = (data.len() as u32) < len || remaining == data.len() as u64;
+                    if eof {
+                        self.streams.remove(stream_id);
+                    } else if let Some((read, _)) = self.streams.read_mut(stream_id) {
+                        read.offset += data.len() as u64;
+                        read.remaining -= data.len() as u64;
+                        read.credit -= 1;
+                        read.seq += 1;
+                    }
+                    VfsResponse::StreamChunk { stream_id, seq, data, eof }
+                },
+                failed => {
+                    self.streams.remove(stream_id);
+                    let (code, message) = Self::error_parts(failed);
+                    log(&alloc::format!("VFS: Read stream {} failed at chunk {}: {}.", stream_id, seq, message));
+                    VfsResponse::StreamError { stream_id, code, message }
+                },
+            };
+            self.ready.push((None, message));
+        }
+    }
+
+    /// Watched paths `request` changes, as of before it is handled: a write
+    /// stream's chunk names no path, and a `Move` takes its source away.
+    fn watched_changes(&self, task: u64, request: &VfsRequest) -> Vec<String> {
+        if self.watches.is_empty() {
+            return Vec::new();
+        }
+        let paths = match request {
+            VfsRequest::StreamData { stream_id, .. } => self.streams.write_fd(*stream_id, task)
+                .and_then(|fd| self.open_files.get(&fd))
+                .map(|file| alloc::vec![file.path.as_str()])
+                .unwrap_or_default(),
+            request => self.changed_paths(request),
+        };
+        paths.into_iter().filter(|path| !self.watches.watchers(path).is_empty()).map(String::from).collect()
+    }
+
+    /// Tells the watchers of each path that it changed.
+    fn notify_watchers(&self, paths: Vec<String>) {
+        for path in paths {
+            let size = self.file_size(&path);
+            for reply_chan in self.watches.watchers(&path) {
+                let notice = VfsResponse::Changed { path: path.clone(), size };
+                // Not waited for: a watcher that fell behind catches up with a Stat.
+                if VNodeChannel::new(reply_chan).send(&notice).is_err() {
+                    debug(&alloc::format!("VFS: Couldn't tell channel {} that {} changed.", reply_chan, path));
+                }
+            }
+        }
+    }
+
+    /// Whether a response says the request did what it asked. `None`, a
+    /// stream chunk not answered yet, counts as done.
+    fn succeeded(response: Option<&VfsResponse>) -> bool {
+        !matches!(
+            response,
+            Some(VfsResponse::Error { .. } | VfsResponse::InvalidName { .. } | VfsResponse::QuotaExceeded { .. } | VfsResponse::Unauthenticated
+                | VfsResponse::StreamError { .. } | VfsResponse::WouldBlock | VfsResponse::Deadlock | VfsResponse::MediaError { .. })
+        )
+    }
+
+    /// The errno-like code and message of a failed request.
+    fn error_parts(response: VfsResponse) -> (i32, String) {
+        match response {
+            VfsResponse::Error { code, message } => (code, message),
+            VfsResponse::QuotaExceeded { used, limit, .. } => (122, format!("Storage quota exceeded ({} of {} bytes used)", used, limit)), // EDQUOT
+            VfsResponse::Unauthenticated => (13, "Not authenticated".to_string()), // EACCES
+            VfsResponse::InvalidName { path, reason } => (22, format!("Invalid path {}: {:?}", path, reason)), // EINVAL
+            VfsResponse::WouldBlock => (11, "The file is locked".to_string()), // EAGAIN
+            VfsResponse::Deadlock => (35, "Waiting for the lock would deadlock".to_string()), // EDEADLK
+            VfsResponse::MediaError { path, offset, len, cause } => (5, format!("Can't read {} bytes at {} of {}: {}", len, offset, path, cause.describe())), // EIO
+            other => (5, format!("Unexpected result: {:?}", other)), // EIO
+        }
+    }
+
+    /// Saves the log the kernel recovered from the previous boot to
+    /// /data/crash/lastlog-<seq>.txt. If that fails, the log is served at
+    /// /proc/lastlog instead, until the next boot.
+    fn save_last_boot_log(&mut self) {
+        let last = match klog::last_boot() {
+            Ok(last) => last,
+            Err(KlogError::NoLastBoot) => return,
+            Err(KlogError::Denied) => {
+                log("VFS: Not allowed to read the kernel log; the previous boot's log isn't saved.");
+                return;
+            },
+        };
+        let path = format!("/data/crash/lastlog-{}.txt", last.info.seq);
+        let how = match last.info.reason {
+            LAST_BOOT_PANIC => "panicked",
+            LAST_BOOT_SHUTDOWN => "shut down cleanly",
+            _ => "ended without a panic",
+        };
+        match self.write_system_file(&path, last.text.as_bytes()) {
+            Ok(()) => log(&alloc::format!("VFS: Saved the log of boot {}, which {}, to {}.", last.info.seq, how, path)),
+            Err(message) => {
+                log(&alloc::format!("VFS: Couldn't save the previous boot's log to {} ({}); it is at /proc/lastlog.", path, message));
+                self.proc_files.insert("/proc/lastlog".to_string(), last.text.into_bytes());
+            },
+        }
+    }
+
+    /// Creates `path` with `contents` as the system identity, creating its parent directories.
+    fn write_system_file(&mut self, path: &str, contents: &[u8]) -> Result<(), String> {
+        let caller = Some(SYSTEM_AID);
+        let parent = &path[..path.rfind('/').unwrap_or(0)];
+        for (end, _) in parent.match_indices('/').skip(1).chain(core::iter::once((parent.len(), ""))) {
+            match self.handle_request(caller, VfsRequest::CreateDirectory { path: parent[..end].to_string() }) {
+                VfsResponse::CreateDirectorySuccess => {},
+                failed => return Err(Self::error_parts(failed).1),
+            }
+        }
+        let fd = match self.handle_request(caller, VfsRequest::Open { path: path.to_string(), flags: 1 }) {
+            VfsResponse::Success(fd) => fd as Fd,
+            failed => return Err(Self::error_parts(failed).1),
+        };
+        let written = self.handle_request(caller, VfsRequest::Write { fd, data: contents.to_vec(), offset: 0 });
+        let synced = self.handle_request(caller, VfsRequest::Fsync { fd });
+        self.handle_request(caller, VfsRequest::Close { fd });
+        match (written, synced) {
+            (VfsResponse::Success(_), VfsResponse::Success(_)) => Ok(()),
+            (VfsResponse::Success(_), failed) | (failed, _) => Err(Self::error_parts(failed).1),
+        }
+    }
+
+    /// Reads the whole of `path` as the system identity.
+    fn read_system_file(&mut self, path: &str) -> Result<Vec<u8>, String> {
+        let caller = Some(SYSTEM_AID);
+        let fd = match self.handle_request(caller, VfsRequest::Open { path: path.to_string(), flags: 0 }) {
+            VfsResponse::Success(fd) => fd as Fd,
+            failed => return Err(Self::error_parts(failed).1),
+        };
+        let read = self.handle_request(caller, VfsRequest::Read { fd, len: u32::MAX, offset: 0 });
+        self.handle_request(caller, VfsRequest::Close { fd });
+        match read {
+            VfsResponse::Data(data) => Ok(data),
+            failed => Err(Self::error_parts(failed).1),
+        }
+    }
+
+    /// Takes up the storage health record where the previous boot left it.
+    fn load_health(&mut self) {
+        match self.read_system_file(HEALTH_PATH) {
+            Ok(data) => self.health.load(&String::from_utf8_lossy(&data)),
+            Err(message) => log(&alloc::format!("VFS: No storage health record loaded from {} ({}); counting from zero.", HEALTH_PATH, message)),
+        }
+    }
+
+    fn save_health(&mut self) {
+        let text = self.health.to_text();
+        if let Err(message) = self.write_system_file(HEALTH_PATH, text.as_bytes()) {
+            log(&alloc::format!("VFS: Couldn't save the storage health record to {} ({}).", HEALTH_PATH, message));
+        }
+        self.health.saved(self.now);
+    }
+
+    /// Work done once before the first request.
+    fn start(&mut self) {
+        self.load_health();
+        // The VFS is up once it gets here, so this is where the previous boot's log can be kept.
+        self.save_last_boot_log();
+    }
+}
+
+impl Service for VfsService {
+    type Request = VfsRequest;
+    type Response = VfsResponse;
+
+    const NAME: &'static str = "VFS Service";
+    /// The log daemon's own writes come through here; see `debug`.
+    const TRACE_SEVERITY: u64 = LOG_SEVERITY_DEBUG;
+
+    fn handle(&mut self, call: &mut Call, request: VfsRequest) -> Outcome<VfsResponse> {
+        // Ownership checks use the identity the kernel stamped on the message when it
+        // was sent, so a relayed request is checked against the relay, not its client.
+        self.sender = call.sender();
+        let changed = self.watched_changes(call.sender().unwrap_or(0), &request);
+        let response = self.handle_message(call.caller(), request, call.token);
+        if Self::succeeded(response.as_ref()) {
+            self.changed.extend(changed);
+        }
+        match response {
+            Some(response) => Outcome::Reply(response),
+            None => Outcome::Later,
+        }
+    }
+
+    fn error(error: ServerError) -> VfsResponse {
+        let code = match error {
+            ServerError::Malformed | ServerError::Expired => 22, // EINVAL
+            ServerError::Unimplemented => 38, // ENOSYS
+            ServerError::Panicked => 5, // EIO
+        };
+        VfsResponse::Error { code, message: error.describe().to_string() }
+    }
+
+    fn scrape(request: &VfsRequest) -> Option<(&MetricsRequest, fn(MetricsResponse) -> VfsResponse)> {
+        match request {
+            VfsRequest::Metrics(request) => Some((request, VfsResponse::Metrics)),
+            _ => None,
+        }
+    }
+
+    fn metrics(&self) -> Option<&Registry> {
+        Some(&self.metrics)
+    }
+
+    fn ready(&mut self) -> Vec<(Option<ReplyToken>, VfsResponse)> {
+        core::mem::take(&mut self.ready)
+    }
+
+    fn tick(&mut self, now: Ticks) {
+        self.now = now.raw();
+
+        // Watchers hear of a change once its request is answered
+        let changed = core::mem::take(&mut self.changed);
+        if !changed.is_empty() {
+            self.notify_watchers(changed);
+        }
+        self.reply_granted_locks();
+
+        // Quota limits from svc://settings, requested at startup and then periodically,
+        // each time after recounting the usage they are checked against
+        if self.now >= self.quota_reload_at {
+            self.recount_quota();
+            self.request_quota_settings();
+        }
+        if let Ok(Some(reply)) = self.settings_chan.recv_non_blocking() {
+            match postcard::from_bytes::<SettingsResponse>(&reply) {
+                Ok(response) => self.apply_quota_setting(response),
+                Err(_) => log("VFS Service: Failed to deserialize SettingsResponse."),
             }
+        }
+
+        // Abort transactions that timed out or whose task has exited
+        for id in self.txs.expire(self.now, session_ipc::task_alive) {
+            self.close_tx_fds(id);
+            log(&alloc::format!("VFS: Transaction {} expired and was aborted.", id));
+        }
+
+        // Push the next chunk of each read stream, then drop the streams of exited tasks
+        self.pump_streams();
+        let dropped = self.streams.expire(session_ipc::task_alive);
+        if dropped > 0 {
+            log(&alloc::format!("VFS: Dropped {} streams of exited tasks.", dropped));
+        }
+        let dropped = self.watches.expire(session_ipc::task_alive);
+        if dropped > 0 {
+            log(&alloc::format!("VFS: Dropped {} watches of exited tasks.", dropped));
+        }
+        let dropped = self.locks.expire(session_ipc::task_alive);
+        if dropped > 0 {
+            log(&alloc::format!("VFS: Dropped {} locks of exited tasks.", dropped));
+            self.reply_granted_locks();
+        }
+
+        // Read in the pages mappings are waiting for, then free unpinned file
+        // contents once nothing maps them anymore
+        self.fill_pages();
+        self.pins.tick();
 
-            // Yield to other V-Nodes to prevent busy-waiting
-            unsafe { syscall3(SYS_TIME, 0, 0, 0); } // This will cause a context switch
+        // Flush dirty data that has been buffered for too long
+        if let Some(ops) = self.cache.tick(self.now) {
+            debug("VFS: Flushing aged dirty data.");
+            // A failure is logged and makes the file system read-only; nobody is waiting for it.
+            let _ = self.apply_flush(ops);
+        }
+        if self.health.due(self.now) {
+            self.save_health();
+        }
+    }
+
+    /// Writes out everything the cache holds, and the health record.
+    fn shutdown(&mut self, _reboot: bool) {
+        let ops = self.cache.flush_all();
+        if self.apply_flush(ops).is_err() {
+            log("VFS Service: Couldn't write out the cache before stopping.");
+        }
+        if !self.health.read_only() {
+            self.save_health();
         }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel ID 7 for VFS Service for client requests
-    // Assuming channel ID 6 for AetherFS backend (conceptual)
-    let mut vfs_service = VfsService::new(7, 6);
-    vfs_service.run_loop();
+    // The AetherFS backend (conceptual) has no well-known channel yet; 6 is a placeholder.
+    let mut client_chan = VNodeChannel::new(channels::VFS);
+    let lifecycle = Lifecycle::new(startup::channels().get(LIFECYCLE_CHANNEL).copied());
+    let mut vfs_service = VfsService::new(6, channels::SETTINGS, channels::EVENT_BUS);
+    vfs_service.start();
+    server::serve_loop(&mut client_chan, lifecycle, &mut vfs_service);
 }
 
 #[panic_handler]
 pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    server::answer_in_flight();
     log(&alloc::format!("VFS V-Node panicked! Info: {:?}.", info));
     // In a production system, this might trigger a system-wide error handler or reboot.
     // For now, it enters an infinite loop to prevent further execution.
@@ -0,0 +1,213 @@
+// vnode/vfs/src/pin.rs
+
+//! File contents pinned in memory for read-only mapping by another task.
+//!
+//! A pin of an in-memory file copies it into page-aligned memory owned by the
+//! VFS and shares those pages with the kernel (`SYS_SHARE_PAGES`). The mapping
+//! task then sees a snapshot of the file as of the pin. A file on the block
+//! device is pinned on demand instead (`SYS_SHARE_PAGES_ON_DEMAND`): the pages
+//! start out empty and each is read from the device when a mapping first
+//! touches it (`requests`, then `fill`). The memory may only be freed once the
+//! kernel agrees nothing maps it anymore; until then an unpinned backing is
+//! kept on a retired list and released on a later `tick`.
+
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::abi::{PageRequest, PAGE_REQUEST_LEN, SYS_PAGE_REQUESTS, SYS_PAGE_SUPPLY, SYS_SHARE_PAGES_ON_DEMAND, E_UNKNOWN_SYSCALL};
+use crate::syscall::{syscall3, SYS_SHARE_PAGES, SYS_UNSHARE_PAGES, E_BUSY, E_ERROR, E_INVALID_ARG};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Largest file the VFS will pin. Bigger files are read through the copy path.
+pub const MAX_PIN_BYTES: usize = 16 * 1024 * 1024;
+
+/// Page requests taken from the kernel per `requests` call.
+const MAX_PAGE_REQUESTS: usize = 16;
+
+/// Page-aligned, heap-allocated copy of a file.
+struct PinnedPages {
+    ptr: *mut u8,
+    layout: Layout,
+    len: usize, // File size; the allocation is rounded up to whole pages
+}
+
+impl PinnedPages {
+    fn new(contents: &[u8]) -> Option<Self> {
+        let pages = Self::zeroed(contents.len())?;
+        // SAFETY: the allocation is at least `contents.len()` bytes and does not overlap `contents`.
+        unsafe { core::ptr::copy_nonoverlapping(contents.as_ptr(), pages.ptr, contents.len()); }
+        Some(pages)
+    }
+
+    /// Zeroed pages for a file of `len` bytes.
+    fn zeroed(len: usize) -> Option<Self> {
+        let size = len.max(1).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let layout = Layout::from_size_align(size, PAGE_SIZE).ok()?;
+        // SAFETY: `layout` has non-zero size.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        Some(Self { ptr, layout, len })
+    }
+
+    /// Copies `data` to `offset`, cut at the end of the file.
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let len = data.len().min(self.len.saturating_sub(offset));
+        // SAFETY: `offset + len` is within the file, and so within the allocation.
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset), len); }
+    }
+}
+
+impl Drop for PinnedPages {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with the same layout.
+        unsafe { dealloc(self.ptr, self.layout); }
+    }
+}
+
+#[derive(Debug)]
+pub enum PinError {
+    TooLarge,
+    OutOfMemory,
+    /// The kernel refused to share the pages (raw syscall result).
+    Kernel(u64),
+    /// The kernel can't fill pages on demand; pin a copy instead.
+    Unsupported,
+}
+
+/// Where the pages of an on-demand pin are read from.
+#[derive(Debug, Clone)]
+pub struct PinSource {
+    pub handle: u64,
+    pub path: String,
+}
+
+#[derive(Default)]
+pub struct PinTable {
+    pinned: BTreeMap<u64, (u64, PinnedPages)>, // backing handle -> (grantee task, pages)
+    /// Files behind on-demand pins, by backing handle.
+    sources: BTreeMap<u64, PinSource>,
+    /// Page requests taken from the kernel but not filled yet, e.g. after a device error.
+    pending: Vec<PageRequest>,
+    /// Unpinned but still mapped somewhere; freed once the kernel lets go.
+    retired: Vec<(u64, PinnedPages)>,
+}
+
+impl PinTable {
+    /// Pins `contents` for `grantee`. Returns the kernel backing handle.
+    pub fn pin(&mut self, contents: &[u8], grantee: u64) -> Result<u64, PinError> {
+        if contents.len() > MAX_PIN_BYTES {
+            return Err(PinError::TooLarge);
+        }
+        let pages = PinnedPages::new(contents).ok_or(PinError::OutOfMemory)?;
+        let handle = unsafe { syscall3(SYS_SHARE_PAGES, pages.ptr as u64, pages.len.max(1) as u64, grantee) };
+        if handle <= E_ERROR || handle >= E_INVALID_ARG {
+            return Err(PinError::Kernel(handle));
+        }
+        self.pinned.insert(handle, (grantee, pages));
+        Ok(handle)
+    }
+
+    /// Pins a file of `len` bytes for `grantee` without reading it: each page
+    /// is read from `source` when a mapping first touches it. Returns the
+    /// kernel backing handle.
+    pub fn pin_on_demand(&mut self, len: usize, source: PinSource, grantee: u64) -> Result<u64, PinError> {
+        if len > MAX_PIN_BYTES {
+            return Err(PinError::TooLarge);
+        }
+        let pages = PinnedPages::zeroed(len).ok_or(PinError::OutOfMemory)?;
+        let handle = unsafe { syscall3(SYS_SHARE_PAGES_ON_DEMAND, pages.ptr as u64, pages.len.max(1) as u64, grantee) };
+        if handle == E_UNKNOWN_SYSCALL {
+            return Err(PinError::Unsupported);
+        }
+        if handle <= E_ERROR || handle >= E_INVALID_ARG {
+            return Err(PinError::Kernel(handle));
+        }
+        self.pinned.insert(handle, (grantee, pages));
+        self.sources.insert(handle, source);
+        Ok(handle)
+    }
+
+    /// Pages mappings are waiting for, with the file each is read from:
+    /// those left over from the last call, then new ones from the kernel.
+    /// Each must be passed to `fill`, or it is asked for again next time.
+    pub fn requests(&mut self) -> Vec<(PageRequest, PinSource)> {
+        let mut buf = [0u8; MAX_PAGE_REQUESTS * PAGE_REQUEST_LEN];
+        let count = unsafe { syscall3(SYS_PAGE_REQUESTS, buf.as_mut_ptr() as u64, buf.len() as u64, 0) };
+        if count as usize <= MAX_PAGE_REQUESTS {
+            self.pending.extend(buf.chunks(PAGE_REQUEST_LEN).take(count as usize).filter_map(PageRequest::from_bytes));
+        }
+        let pending = core::mem::take(&mut self.pending);
+        // A request for a backing released since is dropped; nothing maps it anymore.
+        pending.into_iter().filter_map(|request| Some((request, self.sources.get(&request.backing)?.clone()))).collect()
+    }
+
+    /// Copies the contents of a requested page into the pinned memory and
+    /// wakes the tasks waiting for it. `data` of `None` means the read failed;
+    /// the request is kept and retried by the next `requests`.
+    pub fn fill(&mut self, request: PageRequest, data: Option<&[u8]>) {
+        let Some(data) = data else {
+            self.pending.push(request);
+            return;
+        };
+        let pages = match self.pinned.get_mut(&request.backing) {
+            Some((_, pages)) => Some(pages),
+            None => self.retired.iter_mut().find(|(backing, _)| *backing == request.backing).map(|(_, pages)| pages),
+        };
+        if let Some(pages) = pages {
+            pages.write(request.offset as usize, data);
+            unsafe { syscall3(SYS_PAGE_SUPPLY, request.backing, request.offset, 0) };
+        }
+    }
+
+    /// Drops a pin held for `grantee`. Returns false if there is no such pin. If the
+    /// backing is still mapped, its memory is kept until a later `tick` can release it.
+    pub fn unpin(&mut self, backing: u64, grantee: u64) -> bool {
+        if self.pinned.get(&backing).map(|(owner, _)| *owner) != Some(grantee) {
+            return false;
+        }
+        match self.pinned.remove(&backing) {
+            Some((_, pages)) => {
+                // A retired backing is still mapped, so its missing pages are still filled.
+                if Self::release(backing) {
+                    self.sources.remove(&backing);
+                } else {
+                    self.retired.push((backing, pages));
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Frees retired backings nobody maps anymore. Returns how many were freed.
+    pub fn tick(&mut self) -> usize {
+        let before = self.retired.len();
+        let sources = &mut self.sources;
+        self.retired.retain(|(backing, _)| {
+            let released = Self::release(*backing);
+            if released {
+                sources.remove(backing);
+            }
+            !released
+        });
+        before - self.retired.len()
+    }
+
+    /// Number of unpinned backings still held because they are mapped.
+    pub fn retired_count(&self) -> usize {
+        self.retired.len()
+    }
+
+    /// Asks the kernel to drop the backing. False means it is still mapped.
+    fn release(backing: u64) -> bool {
+        // Anything other than E_BUSY means the kernel no longer references the pages.
+        unsafe { syscall3(SYS_UNSHARE_PAGES, backing, 0, 0) != E_BUSY }
+    }
+}
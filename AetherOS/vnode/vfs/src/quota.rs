@@ -0,0 +1,379 @@
+// vnode/vfs/src/quota.rs
+
+//! Per-owner storage accounting and quotas.
+//!
+//! Every file the VFS writes is charged to one owner. Files under
+//! `/home/<aid hex>/` belong to that identity; anywhere else a file belongs to
+//! whoever created it, or to the system identity if the creator had none. A
+//! file keeps its owner until a `Move` puts it into another identity's home.
+//!
+//! A file is charged for the blocks it has allocated, not its logical size
+//! (see `sparse`): a 100 MB file with 1 MB written counts as 1 MB, and
+//! reserving space with `Allocate` counts as soon as it is reserved.
+//!
+//! Only growth is checked against the limit. Truncating and deleting always
+//! succeed, and so does a move that keeps the owner, even when the owner is
+//! already over quota (e.g. after its limit was lowered). Otherwise a full
+//! user could not free space, and the write-temporary-then-rename pattern
+//! would fail right when it is needed.
+//!
+//! The totals are kept up to date as requests change files, so anything that
+//! changes the backend behind the VFS's back isn't seen by them. `recount`
+//! rebuilds them from the allocated sizes of the files as they are; the VFS
+//! runs it at startup and whenever it re-reads the limits.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::session_ipc::{self, AidBytes, SYSTEM_AID};
+use crate::ipc::vfs_ipc::VfsUsage;
+
+/// Limit for owners without an override: 1 GiB.
+pub const DEFAULT_QUOTA_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// How often the limits are re-read from svc://settings: 30 seconds at 100 ticks/s.
+pub const QUOTA_RELOAD_TICKS: u64 = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub owner: AidBytes,
+    pub used: u64,
+    pub limit: u64,
+}
+
+#[derive(Clone)]
+struct TrackedFile {
+    owner: AidBytes,
+    size: u64, // Allocated bytes
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Usage {
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Clone)]
+pub struct QuotaTable {
+    files: BTreeMap<String, TrackedFile>, // Path -> owner and accounted size
+    usage: BTreeMap<AidBytes, Usage>, // Owner -> totals over its files
+    default_limit: u64,
+    overrides: BTreeMap<AidBytes, u64>, // Per-identity limits, in bytes
+}
+
+impl QuotaTable {
+    pub fn new() -> Self {
+        Self { files: BTreeMap::new(), usage: BTreeMap::new(), default_limit: DEFAULT_QUOTA_BYTES, overrides: BTreeMap::new() }
+    }
+
+    pub fn set_default_limit(&mut self, bytes: u64) {
+        self.default_limit = bytes;
+    }
+
+    pub fn set_overrides(&mut self, overrides: BTreeMap<AidBytes, u64>) {
+        self.overrides = overrides;
+    }
+
+    pub fn limit(&self, owner: &AidBytes) -> u64 {
+        self.overrides.get(owner).copied().unwrap_or(self.default_limit)
+    }
+
+    pub fn usage(&self, owner: &AidBytes) -> VfsUsage {
+        let usage = self.usage.get(owner).copied().unwrap_or_default();
+        VfsUsage { owner: *owner, used_bytes: usage.bytes, limit_bytes: self.limit(owner), file_count: usage.files }
+    }
+
+    /// Owner a new file at `path` is charged to.
+    pub fn owner_for(path: &str, creator: Option<&AidBytes>) -> AidBytes {
+        home_owner(path).or(creator.copied()).unwrap_or(SYSTEM_AID)
+    }
+
+    /// Owner `path` is charged to, if it is tracked.
+    pub fn owner_of(&self, path: &str) -> Option<AidBytes> {
+        self.files.get(path).map(|file| file.owner)
+    }
+
+    /// Starts accounting for `path` with size 0, unless it is already tracked.
+    pub fn track(&mut self, path: &str, owner: AidBytes) {
+        if self.files.contains_key(path) {
+            return;
+        }
+        self.files.insert(String::from(path), TrackedFile { owner, size: 0 });
+        self.usage.entry(owner).or_default().files += 1;
+    }
+
+    /// Sets the accounted size of `path`, its allocated bytes. If the file grows past its owner's
+    /// quota, nothing is changed and the error carries the owner's current usage.
+    pub fn resize(&mut self, path: &str, size: u64) -> Result<(), QuotaExceeded> {
+        let (owner, old) = match self.files.get(path) {
+            Some(file) => (file.owner, file.size),
+            None => return Ok(()),
+        };
+        if size > old {
+            self.check(&owner, size - old)?;
+        }
+        if let Some(file) = self.files.get_mut(path) {
+            file.size = size;
+        }
+        let usage = self.usage.entry(owner).or_default();
+        usage.bytes = usage.bytes.saturating_sub(old).saturating_add(size);
+        Ok(())
+    }
+
+    /// Replaces the accounting with `files`, each path with its allocated
+    /// bytes. Paths that were tracked keep their owner; new ones are charged as
+    /// `owner_for` says, to the system identity outside a home. Paths not in
+    /// `files` are no longer tracked. Returns whether any owner's totals changed.
+    pub fn recount<I: IntoIterator<Item = (String, u64)>>(&mut self, files: I) -> bool {
+        let mut recounted = BTreeMap::new();
+        for (path, size) in files {
+            let owner = self.owner_of(&path).unwrap_or_else(|| Self::owner_for(&path, None));
+            recounted.insert(path, TrackedFile { owner, size });
+        }
+        let before = core::mem::take(&mut self.usage);
+        self.files = recounted;
+        for file in self.files.values().cloned().collect::<Vec<_>>() {
+            self.charge(&file);
+        }
+        before != self.usage
+    }
+
+    /// Stops accounting for `path` and, if it is a directory, everything below it.
+    pub fn remove(&mut self, path: &str) {
+        for path in self.paths_under(path) {
+            if let Some(file) = self.files.remove(&path) {
+                self.uncharge(&file);
+            }
+        }
+    }
+
+    /// Moves the accounting for `source` (and everything below it) to
+    /// `destination`, replacing whatever was tracked there. Files that land in
+    /// another identity's home are transferred to it, which fails as a whole if
+    /// that identity can't take the extra bytes.
+    pub fn rename(&mut self, source: &str, destination: &str) -> Result<(), QuotaExceeded> {
+        let source = source.trim_end_matches('/');
+        let destination = destination.trim_end_matches('/');
+        if destination == source || is_under(destination, source) {
+            return Ok(()); // Not a valid move; the backend rejects it
+        }
+        let moved = self.paths_under(source);
+        let new_owner = home_owner(destination);
+        if let Some(new_owner) = new_owner {
+            let incoming: u64 = moved.iter()
+                .filter_map(|path| self.files.get(path))
+                .filter(|file| file.owner != new_owner)
+                .map(|file| file.size)
+                .sum();
+            if incoming > 0 {
+                self.check(&new_owner, incoming)?;
+            }
+        }
+
+        self.remove(destination);
+        for path in moved {
+            let mut file = match self.files.remove(&path) {
+                Some(file) => file,
+                None => continue,
+            };
+            if let Some(new_owner) = new_owner.filter(|owner| *owner != file.owner) {
+                self.uncharge(&file);
+                file.owner = new_owner;
+                self.charge(&file);
+            }
+            self.files.insert(format!("{}{}", destination, &path[source.len()..]), file);
+        }
+        Ok(())
+    }
+
+    /// Whether `owner` can take `growth` more bytes.
+    pub fn check(&self, owner: &AidBytes, growth: u64) -> Result<(), QuotaExceeded> {
+        let used = self.usage.get(owner).map(|usage| usage.bytes).unwrap_or(0);
+        let limit = self.limit(owner);
+        if used.saturating_add(growth) > limit {
+            Err(QuotaExceeded { owner: *owner, used, limit })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn charge(&mut self, file: &TrackedFile) {
+        let usage = self.usage.entry(file.owner).or_default();
+        usage.bytes += file.size;
+        usage.files += 1;
+    }
+
+    fn uncharge(&mut self, file: &TrackedFile) {
+        if let Some(usage) = self.usage.get_mut(&file.owner) {
+            usage.bytes = usage.bytes.saturating_sub(file.size);
+            usage.files = usage.files.saturating_sub(1);
+            if usage.files == 0 {
+                self.usage.remove(&file.owner);
+            }
+        }
+    }
+
+    /// `path` itself plus every tracked path below it.
+    fn paths_under(&self, path: &str) -> Vec<String> {
+        let path = path.trim_end_matches('/');
+        self.files.keys()
+            .filter(|tracked| tracked.as_str() == path || is_under(tracked, path))
+            .cloned()
+            .collect()
+    }
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).map_or(false, |rest| rest.starts_with('/'))
+}
+
+/// The identity whose home directory contains `path`, if any.
+fn home_owner(path: &str) -> Option<AidBytes> {
+    let name = path.strip_prefix("/home/")?.split('/').next()?;
+    session_ipc::aid_from_hex(name)
+}
+
+/// Parses `vfs.quota_overrides`: `<aid hex>=<MiB>` entries separated by commas.
+/// Returns the limits in bytes and the entries that couldn't be parsed.
+pub fn parse_overrides(raw: &str) -> (BTreeMap<AidBytes, u64>, Vec<String>) {
+    let mut overrides = BTreeMap::new();
+    let mut invalid = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let parsed = entry.split_once('=').and_then(|(aid, mib)| {
+            Some((session_ipc::aid_from_hex(aid.trim())?, mib.trim().parse::<u64>().ok()?))
+        });
+        match parsed {
+            Some((aid, mib)) => { overrides.insert(aid, mib.saturating_mul(1024 * 1024)); },
+            None => invalid.push(String::from(entry)),
+        }
+    }
+    (overrides, invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: AidBytes = [0xaa; 32];
+    const BOB: AidBytes = [0xbb; 32];
+    const ALICE_HOME: &str = "/home/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    fn table(limit: u64) -> QuotaTable {
+        let mut quota = QuotaTable::new();
+        quota.set_default_limit(limit);
+        quota
+    }
+
+    fn used(quota: &QuotaTable, owner: &AidBytes) -> (u64, u64) {
+        let usage = quota.usage(owner);
+        (usage.used_bytes, usage.file_count)
+    }
+
+    #[test]
+    fn growth_past_the_limit_changes_nothing() {
+        let mut quota = table(100);
+        quota.track("/tmp/a", ALICE);
+        quota.resize("/tmp/a", 60).unwrap();
+        let exceeded = quota.resize("/tmp/a", 101).unwrap_err();
+        assert_eq!(exceeded, QuotaExceeded { owner: ALICE, used: 60, limit: 100 });
+        assert_eq!(used(&quota, &ALICE), (60, 1));
+        quota.resize("/tmp/a", 100).unwrap();
+        assert_eq!(used(&quota, &ALICE), (100, 1));
+    }
+
+    #[test]
+    fn shrinking_and_removing_work_over_quota() {
+        let mut quota = table(100);
+        quota.track("/tmp/a", ALICE);
+        quota.resize("/tmp/a", 80).unwrap();
+        quota.set_default_limit(10);
+        quota.resize("/tmp/a", 50).unwrap();
+        assert_eq!(used(&quota, &ALICE), (50, 1));
+        quota.remove("/tmp/a");
+        assert_eq!(used(&quota, &ALICE), (0, 0));
+    }
+
+    #[test]
+    fn recount_picks_up_changes_made_behind_the_tables() {
+        let mut quota = table(1000);
+        quota.track("/tmp/a", ALICE);
+        quota.resize("/tmp/a", 100).unwrap();
+        quota.track("/tmp/gone", ALICE);
+        quota.resize("/tmp/gone", 50).unwrap();
+
+        // /tmp/a grew on the backend, /tmp/gone was deleted there and a file
+        // appeared in Alice's home and one outside any home.
+        let home_file = format!("{}/notes", ALICE_HOME);
+        let changed = quota.recount([
+            (String::from("/tmp/a"), 300),
+            (home_file.clone(), 20),
+            (String::from("/srv/data"), 7),
+        ]);
+        assert!(changed);
+        assert_eq!(used(&quota, &ALICE), (320, 2));
+        assert_eq!(used(&quota, &SYSTEM_AID), (7, 1));
+        assert_eq!(quota.owner_of("/tmp/a"), Some(ALICE));
+        assert_eq!(quota.owner_of(&home_file), Some(ALICE));
+        assert_eq!(quota.owner_of("/tmp/gone"), None);
+    }
+
+    #[test]
+    fn recount_keeps_owners_and_reports_no_change_when_in_step() {
+        let mut quota = table(1000);
+        quota.track("/tmp/a", BOB);
+        quota.resize("/tmp/a", 40).unwrap();
+        quota.track("/tmp/b", ALICE);
+        quota.resize("/tmp/b", 60).unwrap();
+        assert!(!quota.recount([(String::from("/tmp/a"), 40), (String::from("/tmp/b"), 60)]));
+        assert_eq!(used(&quota, &BOB), (40, 1));
+        assert_eq!(used(&quota, &ALICE), (60, 1));
+    }
+
+    #[test]
+    fn recount_can_leave_an_owner_over_quota_but_stops_further_growth() {
+        let mut quota = table(100);
+        quota.track("/tmp/a", ALICE);
+        assert!(quota.recount([(String::from("/tmp/a"), 150)]));
+        assert_eq!(used(&quota, &ALICE), (150, 1));
+        assert!(quota.resize("/tmp/a", 151).is_err());
+        quota.resize("/tmp/a", 90).unwrap();
+        assert_eq!(used(&quota, &ALICE), (90, 1));
+    }
+
+    #[test]
+    fn removing_after_a_recount_uncharges_the_recounted_size() {
+        let mut quota = table(1000);
+        quota.track("/tmp/a", ALICE);
+        quota.resize("/tmp/a", 10).unwrap();
+        quota.track("/tmp/b", ALICE);
+        quota.resize("/tmp/b", 500).unwrap();
+        quota.recount([(String::from("/tmp/a"), 10), (String::from("/tmp/b"), 5)]);
+        quota.remove("/tmp/b");
+        quota.resize("/tmp/a", 0).unwrap();
+        assert_eq!(used(&quota, &ALICE), (0, 1));
+    }
+
+    #[test]
+    fn a_move_into_another_home_transfers_the_charge() {
+        let mut quota = table(1000);
+        quota.track("/tmp/a", BOB);
+        quota.resize("/tmp/a", 30).unwrap();
+        let destination = format!("{}/a", ALICE_HOME);
+        quota.rename("/tmp/a", &destination).unwrap();
+        assert_eq!(used(&quota, &BOB), (0, 0));
+        assert_eq!(used(&quota, &ALICE), (30, 1));
+        assert_eq!(quota.owner_of(&destination), Some(ALICE));
+    }
+
+    #[test]
+    fn overrides_parse_and_report_bad_entries() {
+        let raw = format!("{}=5, nonsense, {}=x", &ALICE_HOME[6..], &ALICE_HOME[6..]);
+        let (overrides, invalid) = parse_overrides(&raw);
+        assert_eq!(overrides.get(&ALICE), Some(&(5 * 1024 * 1024)));
+        assert_eq!(invalid.len(), 2);
+    }
+}
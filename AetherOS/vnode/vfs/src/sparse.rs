@@ -0,0 +1,305 @@
+// vnode/vfs/src/sparse.rs
+
+//! Which blocks of a file have storage behind them.
+//!
+//! A file's logical size and its allocated blocks are tracked apart. A block
+//! nothing was written to is a hole: it reads as zeros, takes no space on the
+//! backend and isn't charged to the owner's quota. Writing past the end of a
+//! file leaves the skipped blocks as holes, and `Allocate` can punch new ones
+//! or back existing ones with storage ahead of time.
+//!
+//! `Extents` keeps the allocated blocks as runs, so a 100 MB file with one
+//! block of data at each end is two entries, not 25600. The block backend
+//! keeps the same map in the file's inode (conceptual until AetherFS stores
+//! one); the VFS mirrors it to answer `Stat`, `SeekData` and `SeekHole` and to
+//! serve hole reads without a backend round trip.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::cache::BLOCK_SIZE;
+
+const BLOCK: u64 = BLOCK_SIZE as u64;
+
+/// Blocks touched by `[offset, offset + len)`, partly or wholly.
+pub fn covering(offset: u64, len: u64) -> (u64, u64) {
+    if len == 0 {
+        return (offset / BLOCK, offset / BLOCK);
+    }
+    (offset / BLOCK, offset.saturating_add(len).div_ceil(BLOCK))
+}
+
+/// Blocks that lie wholly inside `[offset, offset + len)`. Empty when the
+/// range doesn't span a block boundary to boundary.
+pub fn inside(offset: u64, len: u64) -> (u64, u64) {
+    let first = offset.div_ceil(BLOCK);
+    let end = offset.saturating_add(len) / BLOCK;
+    (first, end.max(first))
+}
+
+/// Runs of allocated blocks of one file.
+#[derive(Debug, Clone, Default)]
+pub struct Extents {
+    runs: BTreeMap<u64, u64>, // First block -> one past the last; runs never overlap or touch
+}
+
+impl Extents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allocated_bytes(&self) -> u64 {
+        self.runs.iter().map(|(first, end)| end - first).sum::<u64>() * BLOCK
+    }
+
+    /// The runs, as `(first block, one past the last)`, in order.
+    pub fn runs(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.runs.iter().map(|(first, end)| (*first, *end))
+    }
+
+    /// Blocks of `[first, end)` that are allocated.
+    fn allocated_in(&self, first: u64, end: u64) -> u64 {
+        self.overlapping(first, end)
+            .map(|(run_first, run_end)| run_end.min(end) - run_first.max(first))
+            .sum()
+    }
+
+    /// Bytes that allocating the blocks under `[offset, offset + len)` would add.
+    pub fn growth(&self, offset: u64, len: u64) -> u64 {
+        let (first, end) = covering(offset, len);
+        (end - first - self.allocated_in(first, end)) * BLOCK
+    }
+
+    /// Marks blocks `[first, end)` allocated.
+    pub fn add(&mut self, first: u64, end: u64) {
+        if first >= end {
+            return;
+        }
+        // Runs that overlap or touch the new one are merged into it.
+        let mut merged = (first, end);
+        let touching: Vec<(u64, u64)> = self.runs.range(..=end)
+            .rev()
+            .take_while(|(_, run_end)| **run_end >= first)
+            .map(|(run_first, run_end)| (*run_first, *run_end))
+            .collect();
+        for (run_first, run_end) in touching {
+            self.runs.remove(&run_first);
+            merged = (merged.0.min(run_first), merged.1.max(run_end));
+        }
+        self.runs.insert(merged.0, merged.1);
+    }
+
+    /// Marks blocks `[first, end)` as holes.
+    pub fn remove(&mut self, first: u64, end: u64) {
+        if first >= end {
+            return;
+        }
+        let cut: Vec<(u64, u64)> = self.overlapping(first, end).collect();
+        for (run_first, run_end) in cut {
+            self.runs.remove(&run_first);
+            if run_first < first {
+                self.runs.insert(run_first, first);
+            }
+            if run_end > end {
+                self.runs.insert(end, run_end);
+            }
+        }
+    }
+
+    /// Drops the blocks wholly past `size`, as when the file is cut to that
+    /// length. A block `size` ends inside keeps its storage. Returns the bytes freed.
+    pub fn truncate(&mut self, size: u64) -> u64 {
+        let before = self.allocated_bytes();
+        self.remove(size.div_ceil(BLOCK), u64::MAX);
+        before - self.allocated_bytes()
+    }
+
+    pub fn is_allocated(&self, block: u64) -> bool {
+        self.runs.range(..=block).next_back().map_or(false, |(_, end)| *end > block)
+    }
+
+    /// The first allocated byte at or after `offset`, if there is one.
+    pub fn next_data(&self, offset: u64) -> Option<u64> {
+        let block = offset / BLOCK;
+        if self.is_allocated(block) {
+            return Some(offset);
+        }
+        self.runs.range(block + 1..).next().map(|(first, _)| first * BLOCK)
+    }
+
+    /// The first byte at or after `offset` that lies in a hole. Past the last
+    /// run everything is a hole, so there always is one; callers clamp it to
+    /// the file's size.
+    pub fn next_hole(&self, offset: u64) -> u64 {
+        let block = offset / BLOCK;
+        match self.runs.range(..=block).next_back() {
+            Some((_, end)) if *end > block => end * BLOCK,
+            _ => offset,
+        }
+    }
+
+    /// Zeroes the bytes of `buf`, which holds the file from `offset` on,
+    /// that fall in holes.
+    pub fn zero_holes(&self, offset: u64, buf: &mut [u8]) {
+        let end = offset + buf.len() as u64;
+        let mut pos = offset;
+        while pos < end {
+            let hole_start = self.next_hole(pos).min(end);
+            let hole_end = self.next_data(hole_start).unwrap_or(end).min(end);
+            buf[(hole_start - offset) as usize..(hole_end - offset) as usize].fill(0);
+            pos = hole_end;
+        }
+    }
+
+    fn overlapping(&self, first: u64, end: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        // The run starting before `first` may reach into the range; every other
+        // candidate starts inside it.
+        let before = self.runs.range(..first).next_back().filter(|(_, run_end)| **run_end > first);
+        before.into_iter()
+            .chain(self.runs.range(first..end))
+            .map(|(run_first, run_end)| (*run_first, *run_end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const KIB: u64 = 1024;
+    const MB: u64 = 1024 * KIB;
+
+    fn runs(extents: &Extents) -> Vec<(u64, u64)> {
+        extents.runs().collect()
+    }
+
+    /// A 100 MB file with 512 KiB written at each end.
+    fn sparse_file() -> Extents {
+        let mut extents = Extents::new();
+        let (first, end) = covering(0, 512 * KIB);
+        extents.add(first, end);
+        let (first, end) = covering(100 * MB - 512 * KIB, 512 * KIB);
+        extents.add(first, end);
+        extents
+    }
+
+    #[test]
+    fn ranges_map_to_the_blocks_they_touch_or_fill() {
+        assert_eq!(covering(0, BLOCK), (0, 1));
+        assert_eq!(covering(1, BLOCK), (0, 2));
+        assert_eq!(covering(BLOCK, 0), (1, 1));
+        assert_eq!(covering(u64::MAX - 1, 10).1, u64::MAX.div_ceil(BLOCK));
+        assert_eq!(inside(0, BLOCK), (0, 1));
+        assert_eq!(inside(1, 2 * BLOCK), (1, 2));
+        assert_eq!(inside(1, BLOCK), (1, 1));
+        assert_eq!(inside(10, 20), (1, 1));
+    }
+
+    #[test]
+    fn runs_merge_and_split() {
+        let mut extents = Extents::new();
+        extents.add(0, 2);
+        extents.add(4, 6);
+        extents.add(2, 4); // Touches both
+        assert_eq!(runs(&extents), vec![(0, 6)]);
+        extents.add(3, 5); // Already there
+        extents.add(9, 9); // Empty
+        assert_eq!(runs(&extents), vec![(0, 6)]);
+
+        extents.remove(2, 3);
+        assert_eq!(runs(&extents), vec![(0, 2), (3, 6)]);
+        extents.remove(1, 4);
+        assert_eq!(runs(&extents), vec![(0, 1), (4, 6)]);
+        assert_eq!(extents.allocated_bytes(), 3 * BLOCK);
+        assert_eq!(extents.growth(0, 6 * BLOCK), 3 * BLOCK);
+        assert_eq!(extents.growth(4 * BLOCK + 1, 10), 0);
+        assert!(extents.is_allocated(0) && !extents.is_allocated(1) && extents.is_allocated(5) && !extents.is_allocated(6));
+    }
+
+    #[test]
+    fn a_large_hole_costs_nothing() {
+        let extents = sparse_file();
+        assert_eq!(runs(&extents).len(), 2);
+        assert_eq!(extents.allocated_bytes(), MB);
+        assert_eq!(extents.growth(MB, 10 * MB), 10 * MB);
+    }
+
+    #[test]
+    fn hole_reads_are_zeros_up_to_the_data() {
+        let extents = sparse_file();
+        // A read straddling the end of the first write keeps its data and zeroes the rest.
+        let mut buf = vec![0xaa; 8 * KIB as usize];
+        extents.zero_holes(512 * KIB - 4 * KIB, &mut buf);
+        assert!(buf[..4 * KIB as usize].iter().all(|b| *b == 0xaa));
+        assert!(buf[4 * KIB as usize..].iter().all(|b| *b == 0));
+
+        // All hole: nothing of what was in the buffer survives.
+        let mut buf = vec![0xaa; 3000];
+        extents.zero_holes(50 * MB + 7, &mut buf);
+        assert!(buf.iter().all(|b| *b == 0));
+        assert_eq!(extents.next_data(50 * MB).map(|at| at >= 50 * MB + 3000), Some(true));
+
+        // A read running from the hole into the last write.
+        let mut buf = vec![0xaa; 2 * BLOCK as usize];
+        extents.zero_holes(100 * MB - 512 * KIB - BLOCK, &mut buf);
+        assert!(buf[..BLOCK as usize].iter().all(|b| *b == 0));
+        assert!(buf[BLOCK as usize..].iter().all(|b| *b == 0xaa));
+    }
+
+    #[test]
+    fn seek_data_and_seek_hole_walk_the_file() {
+        let extents = sparse_file();
+        assert_eq!(extents.next_data(0), Some(0));
+        assert_eq!(extents.next_hole(0), 512 * KIB);
+        assert_eq!(extents.next_data(512 * KIB), Some(100 * MB - 512 * KIB));
+        assert_eq!(extents.next_hole(512 * KIB + 5), 512 * KIB + 5);
+        assert_eq!(extents.next_data(100 * MB - KIB), Some(100 * MB - KIB));
+        // The last write runs to the end of the file; the caller clamps to its size.
+        assert_eq!(extents.next_hole(100 * MB - 512 * KIB), 100 * MB);
+        assert_eq!(extents.next_data(100 * MB), None);
+        assert_eq!(Extents::new().next_data(0), None);
+        assert_eq!(Extents::new().next_hole(123), 123);
+    }
+
+    #[test]
+    fn zeroing_a_range_frees_only_whole_blocks() {
+        let mut extents = sparse_file();
+        let (first, end) = inside(100, 512 * KIB - 200);
+        extents.remove(first, end);
+        // The first and last block of the first write are only partly covered and stay.
+        assert_eq!(runs(&extents)[..2], [(0, 1), (512 * KIB / BLOCK - 1, 512 * KIB / BLOCK)]);
+        assert_eq!(extents.allocated_bytes(), 512 * KIB + 2 * BLOCK);
+
+        // Zeroing inside the hole changes nothing.
+        let before = runs(&extents);
+        let (first, end) = inside(MB, 10 * MB);
+        extents.remove(first, end);
+        assert_eq!(runs(&extents), before);
+    }
+
+    #[test]
+    fn truncating_inside_a_hole_drops_the_data_after_it() {
+        let mut extents = sparse_file();
+        assert_eq!(extents.truncate(50 * MB + 1), 512 * KIB);
+        assert_eq!(runs(&extents), vec![(0, 512 * KIB / BLOCK)]);
+        assert_eq!(extents.next_data(512 * KIB), None);
+        assert_eq!(extents.next_hole(0), 512 * KIB);
+        // Growing the file again leaves the old data out: the gap is a hole.
+        let mut buf = vec![0xaa; 100];
+        extents.zero_holes(100 * MB - 100, &mut buf);
+        assert!(buf.iter().all(|b| *b == 0));
+        assert_eq!(extents.truncate(50 * MB), 0);
+    }
+
+    #[test]
+    fn truncating_inside_a_run_keeps_the_partial_block() {
+        let mut extents = sparse_file();
+        assert_eq!(extents.truncate(BLOCK + 1), MB - 2 * BLOCK);
+        assert_eq!(runs(&extents), vec![(0, 2)]);
+        assert_eq!(extents.truncate(2 * BLOCK), 0);
+        assert_eq!(extents.truncate(0), 2 * BLOCK);
+        assert_eq!(runs(&extents), vec![]);
+    }
+}
@@ -0,0 +1,144 @@
+// vnode/vfs/src/stream.rs
+
+//! Streams: large reads and writes without a round trip per chunk.
+//!
+//! A read stream is pushed to its client one `StreamChunk` per pass of the
+//! event loop, for as long as it has credit. Each chunk costs one credit; the
+//! client tops it up with `StreamCredit` as it consumes chunks, so a slow
+//! reader pauses the stream instead of flooding its channel. Passing over
+//! every stream in turn lets several share a channel.
+//!
+//! A write stream takes `StreamData` in sequence and acks every half window,
+//! which is what lets the writer keep sending while earlier chunks are written.
+//!
+//! Every chunk goes through the same checks as a plain `Read` or `Write` on
+//! the stream's fd, so a lock or a full quota stops the stream with
+//! `StreamError` at the chunk where it happens. Closing the fd ends its
+//! streams without a word; that is how a client abandons one.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::ipc::session_ipc::AidBytes;
+use crate::ipc::vfs_ipc::{Fd, StreamId, STREAM_WINDOW};
+
+/// Streams one task may have open at once.
+pub const MAX_STREAMS_PER_TASK: usize = 8;
+/// Credit a read stream can bank; more is ignored.
+pub const MAX_CREDIT: u32 = 4 * STREAM_WINDOW;
+
+#[derive(Debug)]
+pub struct ReadStream {
+    pub fd: Fd,
+    pub offset: u64, // Where the next chunk starts
+    pub remaining: u64,
+    pub credit: u32,
+    pub seq: u64, // Number of the next chunk
+}
+
+#[derive(Debug)]
+pub struct WriteStream {
+    pub fd: Fd,
+    pub offset: u64, // Where the next chunk goes
+    pub next_seq: u64,
+    pub written: u64,
+    pub unacked: u32, // Chunks written since the last ack
+}
+
+#[derive(Debug)]
+pub enum Direction {
+    Read(ReadStream),
+    Write(WriteStream),
+}
+
+#[derive(Debug)]
+pub struct Stream {
+    pub task: u64, // Only this task may credit or feed the stream
+    pub caller: Option<AidBytes>, // Identity each chunk is checked as
+    pub direction: Direction,
+}
+
+pub struct StreamTable {
+    streams: BTreeMap<StreamId, Stream>,
+    next_id: StreamId,
+}
+
+impl StreamTable {
+    pub fn new() -> Self {
+        Self { streams: BTreeMap::new(), next_id: 1 }
+    }
+
+    /// Adds a stream, or returns `None` if its task already has the maximum.
+    pub fn start(&mut self, task: u64, caller: Option<AidBytes>, direction: Direction) -> Option<StreamId> {
+        if self.streams.values().filter(|stream| stream.task == task).count() >= MAX_STREAMS_PER_TASK {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.streams.insert(id, Stream { task, caller, direction });
+        Some(id)
+    }
+
+    /// The stream, if it exists and belongs to `task`.
+    pub fn get_mut(&mut self, id: StreamId, task: u64) -> Option<&mut Stream> {
+        self.streams.get_mut(&id).filter(|stream| stream.task == task)
+    }
+
+    /// Adds credit to a read stream of `task`. Returns false if there is none with this ID.
+    pub fn credit(&mut self, id: StreamId, task: u64, chunks: u32) -> bool {
+        match self.get_mut(id, task).map(|stream| &mut stream.direction) {
+            Some(Direction::Read(read)) => {
+                read.credit = read.credit.saturating_add(chunks).min(MAX_CREDIT);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// The fd a write stream of `task` writes to.
+    pub fn write_fd(&self, id: StreamId, task: u64) -> Option<Fd> {
+        match self.streams.get(&id) {
+            Some(Stream { task: owner, direction: Direction::Write(write), .. }) if *owner == task => Some(write.fd),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, id: StreamId) -> Option<Stream> {
+        self.streams.remove(&id)
+    }
+
+    /// Read streams that may send a chunk now, in ID order.
+    pub fn ready(&self) -> Vec<StreamId> {
+        self.streams.iter()
+            .filter(|(_, stream)| matches!(&stream.direction, Direction::Read(read) if read.credit > 0))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// A read stream and the identity its chunks are read as.
+    pub fn read_mut(&mut self, id: StreamId) -> Option<(&mut ReadStream, Option<AidBytes>)> {
+        match self.streams.get_mut(&id) {
+            Some(Stream { caller, direction: Direction::Read(read), .. }) => Some((read, *caller)),
+            _ => None,
+        }
+    }
+
+    /// Drops the streams on `fd`, which is being closed. Returns how many there were.
+    pub fn close_fd(&mut self, fd: Fd) -> usize {
+        let before = self.streams.len();
+        self.streams.retain(|_, stream| match &stream.direction {
+            Direction::Read(read) => read.fd != fd,
+            Direction::Write(write) => write.fd != fd,
+        });
+        before - self.streams.len()
+    }
+
+    /// Drops the streams of tasks that have exited. Returns how many were dropped.
+    pub fn expire(&mut self, alive: impl Fn(u64) -> bool) -> usize {
+        let before = self.streams.len();
+        self.streams.retain(|_, stream| alive(stream.task));
+        before - self.streams.len()
+    }
+}
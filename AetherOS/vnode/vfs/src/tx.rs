@@ -0,0 +1,403 @@
+// vnode/vfs/src/tx.rs
+
+//! Transactions: groups of changes that other clients see all at once or not at all.
+//!
+//! Changes made inside a transaction are recorded as a list of operations and
+//! mirrored in a per-transaction view, so the owner reads its own staged state
+//! while everyone else still reads the committed one. `TxCommit` replays the
+//! operations in one step of the VFS event loop, which no other request can
+//! interleave with, and flushes them to the backend as one batch.
+//!
+//! Every path a transaction touches is locked, together with everything below
+//! it, until the transaction ends. Other transactions and plain writes to a
+//! locked path fail with EBUSY; plain reads are never blocked.
+//!
+//! Staged data is held in memory until the commit, so a transaction may stage
+//! at most `MAX_TX_BYTES`. Each write is also checked against its owner's
+//! quota as it is staged, counting the blocks the transaction's earlier writes
+//! allocate; the commit checks the exact total again.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::session_ipc::AidBytes;
+use crate::ipc::vfs_ipc::TxId;
+use crate::quota::{QuotaExceeded, QuotaTable};
+use crate::sparse::{self, Extents};
+
+/// Transactions not committed within 30 seconds (at 100 ticks/s) are aborted.
+pub const TX_TIMEOUT_TICKS: u64 = 3000;
+
+/// Most bytes a transaction may stage: the data of its writes, and the end of
+/// any file it writes. 16 MiB.
+pub const MAX_TX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A change recorded by a transaction, replayed in order on commit.
+#[derive(Debug)]
+pub enum TxOp {
+    Truncate { path: String },
+    Write { path: String, offset: u64, data: Vec<u8> },
+    Delete { path: String },
+    CreateDirectory { path: String },
+    Move { source: String, destination: String },
+    SetXattr { path: String, name: String, value: Vec<u8> },
+    RemoveXattr { path: String, name: String },
+}
+
+/// What a transaction's own view holds for a path.
+#[derive(Debug, Clone)]
+pub enum Staged {
+    File(Vec<u8>),
+    Directory,
+    Deleted,
+    /// Moved here from a path whose committed contents the transaction never touched.
+    MovedFrom(String),
+}
+
+/// Where a read inside a transaction gets its data from.
+pub enum Resolved<'a> {
+    Staged(&'a Staged),
+    /// The committed state of this path.
+    Committed(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxError {
+    /// No such transaction, or it belongs to another task or identity.
+    Unknown,
+    /// The path overlaps one locked by another transaction.
+    Locked { holder: TxId },
+}
+
+/// Why a write couldn't be staged. Nothing was staged.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StageError {
+    /// The write would take the transaction, or the file, past `MAX_TX_BYTES`.
+    TooLarge,
+    /// The blocks the write allocates would take the owner past its quota.
+    Quota(QuotaExceeded),
+}
+
+/// What a staged write is charged to.
+pub struct Charge<'a> {
+    pub owner: AidBytes,
+    /// The file's allocated blocks before the transaction.
+    pub committed: Extents,
+    pub quota: &'a QuotaTable,
+}
+
+pub struct Transaction {
+    pub task: u64,
+    pub identity: Option<AidBytes>,
+    expires_at: u64,
+    ops: Vec<TxOp>,
+    view: BTreeMap<String, Staged>,
+    locked: Vec<String>,
+    /// Bytes of data staged by writes.
+    staged_bytes: u64,
+    /// Blocks each written file will have allocated, as far as staging can tell.
+    allocated: BTreeMap<String, Extents>,
+    /// Bytes the staged writes add to each owner's usage.
+    charged: BTreeMap<AidBytes, u64>,
+}
+
+impl Transaction {
+    /// Resolves `path` against the staged changes. A deleted ancestor hides the
+    /// path; a moved ancestor redirects it to the committed source.
+    pub fn resolve(&self, path: &str) -> Resolved<'_> {
+        if let Some(staged) = self.view.get(path) {
+            return Resolved::Staged(staged);
+        }
+        let mut dir = path;
+        while let Some(slash) = dir.rfind('/') {
+            dir = &dir[..slash];
+            match self.view.get(dir) {
+                Some(Staged::Deleted) => return Resolved::Staged(&Staged::Deleted),
+                Some(Staged::MovedFrom(source)) => {
+                    let mut committed = source.clone();
+                    committed.push_str(&path[dir.len()..]);
+                    return Resolved::Committed(committed);
+                },
+                _ => {},
+            }
+        }
+        Resolved::Committed(String::from(path))
+    }
+
+    pub fn stage_truncate(&mut self, path: &str) {
+        self.view.insert(String::from(path), Staged::File(Vec::new()));
+        self.ops.push(TxOp::Truncate { path: String::from(path) });
+    }
+
+    /// Applies a write to the staged copy of `path`. `base` is the file's
+    /// contents as the transaction saw them before its first write. Fails,
+    /// staging nothing, if the write would exceed `MAX_TX_BYTES` or the quota.
+    pub fn stage_write(&mut self, path: &str, offset: u64, data: Vec<u8>, base: Option<Vec<u8>>, charge: Charge<'_>) -> Result<(), StageError> {
+        let len = data.len() as u64;
+        let end = offset.checked_add(len).ok_or(StageError::TooLarge)?;
+        let staged_bytes = self.staged_bytes.checked_add(len).ok_or(StageError::TooLarge)?;
+        if end > MAX_TX_BYTES || staged_bytes > MAX_TX_BYTES || base.as_ref().map_or(false, |base| base.len() as u64 > MAX_TX_BYTES) {
+            return Err(StageError::TooLarge);
+        }
+
+        let mut allocated = self.allocated.get(path).cloned().unwrap_or(charge.committed);
+        let growth = allocated.growth(offset, len);
+        let charged = self.charged.get(&charge.owner).copied().unwrap_or(0) + growth;
+        if growth > 0 {
+            charge.quota.check(&charge.owner, charged).map_err(StageError::Quota)?;
+        }
+        let (first, last) = sparse::covering(offset, len);
+        allocated.add(first, last);
+        self.allocated.insert(String::from(path), allocated);
+        self.charged.insert(charge.owner, charged);
+        self.staged_bytes = staged_bytes;
+
+        if !matches!(self.view.get(path), Some(Staged::File(_))) {
+            self.view.insert(String::from(path), Staged::File(base.unwrap_or_default()));
+        }
+        if let Some(Staged::File(contents)) = self.view.get_mut(path) {
+            let (start, end) = (offset as usize, end as usize);
+            if contents.len() < end {
+                contents.resize(end, 0);
+            }
+            contents[start..end].copy_from_slice(&data);
+        }
+        self.ops.push(TxOp::Write { path: String::from(path), offset, data });
+        Ok(())
+    }
+
+    pub fn stage_delete(&mut self, path: &str) {
+        self.view.retain(|staged, _| !overlaps_below(staged, path));
+        self.view.insert(String::from(path), Staged::Deleted);
+        self.ops.push(TxOp::Delete { path: String::from(path) });
+    }
+
+    pub fn stage_create_directory(&mut self, path: &str) {
+        self.view.insert(String::from(path), Staged::Directory);
+        self.ops.push(TxOp::CreateDirectory { path: String::from(path) });
+    }
+
+    pub fn stage_move(&mut self, source: &str, destination: &str) {
+        let resolved = match self.resolve(source) {
+            Resolved::Staged(staged) => staged.clone(),
+            Resolved::Committed(committed) => Staged::MovedFrom(committed),
+        };
+        let moved: Vec<String> = self.view.keys().filter(|staged| is_under(staged, source)).cloned().collect();
+        self.view.retain(|staged, _| !overlaps_below(staged, destination));
+        for path in moved {
+            if let Some(staged) = self.view.remove(&path) {
+                let mut renamed = String::from(destination);
+                renamed.push_str(&path[source.len()..]);
+                self.view.insert(renamed, staged);
+            }
+        }
+        self.view.insert(String::from(destination), resolved);
+        self.view.insert(String::from(source), Staged::Deleted);
+        self.ops.push(TxOp::Move { source: String::from(source), destination: String::from(destination) });
+    }
+
+    /// Attribute changes aren't mirrored in the view; they are checked
+    /// against the limits and applied on commit.
+    pub fn stage_set_xattr(&mut self, path: &str, name: &str, value: Vec<u8>) {
+        self.ops.push(TxOp::SetXattr { path: String::from(path), name: String::from(name), value });
+    }
+
+    pub fn stage_remove_xattr(&mut self, path: &str, name: &str) {
+        self.ops.push(TxOp::RemoveXattr { path: String::from(path), name: String::from(name) });
+    }
+
+    pub fn into_ops(self) -> Vec<TxOp> {
+        self.ops
+    }
+}
+
+pub struct TxTable {
+    txs: BTreeMap<TxId, Transaction>,
+    next_id: TxId,
+}
+
+impl TxTable {
+    pub fn new() -> Self {
+        Self { txs: BTreeMap::new(), next_id: 1 }
+    }
+
+    pub fn begin(&mut self, task: u64, identity: Option<AidBytes>, now: u64) -> TxId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let expires_at = now + TX_TIMEOUT_TICKS;
+        self.txs.insert(id, Transaction {
+            task,
+            identity,
+            expires_at,
+            ops: Vec::new(),
+            view: BTreeMap::new(),
+            locked: Vec::new(),
+            staged_bytes: 0,
+            allocated: BTreeMap::new(),
+            charged: BTreeMap::new(),
+        });
+        id
+    }
+
+    /// The transaction `id`, if `task` with `identity` started it.
+    pub fn get(&self, id: TxId, task: u64, identity: Option<&AidBytes>) -> Result<&Transaction, TxError> {
+        match self.txs.get(&id) {
+            Some(tx) if tx.task == task && tx.identity.as_ref() == identity => Ok(tx),
+            _ => Err(TxError::Unknown),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: TxId, task: u64, identity: Option<&AidBytes>) -> Result<&mut Transaction, TxError> {
+        match self.txs.get_mut(&id) {
+            Some(tx) if tx.task == task && tx.identity.as_ref() == identity => Ok(tx),
+            _ => Err(TxError::Unknown),
+        }
+    }
+
+    /// Ends the transaction `id` (for commit or abort) and releases its locks.
+    pub fn take(&mut self, id: TxId, task: u64, identity: Option<&AidBytes>) -> Result<Transaction, TxError> {
+        self.get(id, task, identity)?;
+        self.txs.remove(&id).ok_or(TxError::Unknown)
+    }
+
+    /// Locks `path` and everything below it for transaction `id`.
+    pub fn lock(&mut self, id: TxId, path: &str) -> Result<(), TxError> {
+        if let Some(holder) = self.lock_holder(path).filter(|holder| *holder != id) {
+            return Err(TxError::Locked { holder });
+        }
+        if let Some(tx) = self.txs.get_mut(&id) {
+            if !tx.locked.iter().any(|locked| locked == path) {
+                tx.locked.push(String::from(path));
+            }
+        }
+        Ok(())
+    }
+
+    /// The transaction whose locks overlap `path`, if any.
+    pub fn lock_holder(&self, path: &str) -> Option<TxId> {
+        self.txs.iter()
+            .find(|(_, tx)| tx.locked.iter().any(|locked| overlaps_below(path, locked) || overlaps_below(locked, path)))
+            .map(|(id, _)| *id)
+    }
+
+    /// Aborts transactions that timed out or whose task has exited. Returns their ids.
+    pub fn expire(&mut self, now: u64, task_alive: impl Fn(u64) -> bool) -> Vec<TxId> {
+        let expired: Vec<TxId> = self.txs.iter()
+            .filter(|(_, tx)| tx.expires_at <= now || !task_alive(tx.task))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.txs.remove(id);
+        }
+        expired
+    }
+}
+
+/// Whether `path` is `dir` or below it.
+fn overlaps_below(path: &str, dir: &str) -> bool {
+    path == dir || is_under(path, dir)
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).map_or(false, |rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const OWNER: AidBytes = [7; 32];
+    const BLOCK: u64 = crate::cache::BLOCK_SIZE as u64;
+
+    fn transaction() -> Transaction {
+        let mut txs = TxTable::new();
+        let id = txs.begin(1, None, 0);
+        txs.take(id, 1, None).unwrap()
+    }
+
+    fn quota(limit: u64) -> QuotaTable {
+        let mut quota = QuotaTable::new();
+        quota.set_default_limit(limit);
+        quota
+    }
+
+    fn charge(quota: &QuotaTable) -> Charge<'_> {
+        Charge { owner: OWNER, committed: Extents::new(), quota }
+    }
+
+    fn staged(tx: &Transaction, path: &str) -> Option<Vec<u8>> {
+        match tx.resolve(path) {
+            Resolved::Staged(Staged::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn writes_are_staged_over_the_base() {
+        let quota = quota(u64::MAX);
+        let mut tx = transaction();
+        tx.stage_write("/a", 2, b"xy".to_vec(), Some(b"abcdef".to_vec()), charge(&quota)).unwrap();
+        tx.stage_write("/a", 8, b"z".to_vec(), None, charge(&quota)).unwrap();
+        assert_eq!(staged(&tx, "/a").unwrap(), b"abxyef\0\0z");
+        assert_eq!(tx.into_ops().len(), 2);
+    }
+
+    #[test]
+    fn a_write_whose_end_overflows_is_refused() {
+        let quota = quota(u64::MAX);
+        let mut tx = transaction();
+        assert_eq!(tx.stage_write("/a", u64::MAX - 1, vec![1; 4], None, charge(&quota)), Err(StageError::TooLarge));
+        assert_eq!(staged(&tx, "/a"), None);
+        assert!(tx.into_ops().is_empty());
+    }
+
+    #[test]
+    fn a_file_may_not_grow_past_the_cap() {
+        let quota = quota(u64::MAX);
+        let mut tx = transaction();
+        assert_eq!(tx.stage_write("/a", MAX_TX_BYTES, vec![1], None, charge(&quota)), Err(StageError::TooLarge));
+        assert_eq!(tx.stage_write("/a", 0, vec![1], Some(vec![0; MAX_TX_BYTES as usize + 1]), charge(&quota)), Err(StageError::TooLarge));
+        assert!(tx.stage_write("/a", MAX_TX_BYTES - 1, vec![1], None, charge(&quota)).is_ok());
+    }
+
+    #[test]
+    fn the_cap_counts_every_write_of_the_transaction() {
+        let quota = quota(u64::MAX);
+        let mut tx = transaction();
+        let quarter = (MAX_TX_BYTES / 4) as usize;
+        for _ in 0..4 {
+            tx.stage_write("/a", 0, vec![1; quarter], None, charge(&quota)).unwrap();
+        }
+        assert_eq!(tx.stage_write("/b", 0, vec![1], None, charge(&quota)), Err(StageError::TooLarge));
+        assert_eq!(staged(&tx, "/b"), None);
+    }
+
+    #[test]
+    fn staging_checks_the_quota_with_earlier_writes_counted() {
+        let quota = quota(2 * BLOCK);
+        let mut tx = transaction();
+        tx.stage_write("/a", 0, vec![1; 10], None, charge(&quota)).unwrap();
+        tx.stage_write("/b", BLOCK, vec![1; 10], None, charge(&quota)).unwrap();
+        // Both blocks are already allocated by this transaction.
+        tx.stage_write("/a", 100, vec![2; 10], None, charge(&quota)).unwrap();
+        match tx.stage_write("/a", BLOCK, vec![3; 1], None, charge(&quota)) {
+            Err(StageError::Quota(exceeded)) => assert_eq!((exceeded.owner, exceeded.limit), (OWNER, 2 * BLOCK)),
+            other => panic!("expected a quota error, got {:?}", other),
+        }
+        assert_eq!(staged(&tx, "/a").unwrap().len(), 110);
+    }
+
+    #[test]
+    fn blocks_the_file_already_has_are_not_charged() {
+        let quota = quota(BLOCK);
+        let mut tx = transaction();
+        let mut committed = Extents::new();
+        committed.add(0, 4);
+        let charge = Charge { owner: OWNER, committed, quota: &quota };
+        assert!(tx.stage_write("/a", 0, vec![1; 4 * BLOCK as usize], None, charge).is_ok());
+    }
+}
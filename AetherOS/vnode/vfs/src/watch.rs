@@ -0,0 +1,89 @@
+// vnode/vfs/src/watch.rs
+
+//! Watches: change notices pushed to a client's channel.
+//!
+//! A task watching a path gets a `Changed` on its reply channel after every
+//! request that changes the path or, for a directory, anything below it:
+//! what `changed_paths` lists, plus each chunk of a write stream. The notice
+//! carries the path that changed and its size afterwards, so a client
+//! following a file knows whether it grew or was replaced. Notices are sent
+//! without waiting for the client; one that can't keep up misses some, and a
+//! `Stat` tells it where things stand. Watches end with `Unwatch` or when
+//! their task exits.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Watches one task may hold at once.
+pub const MAX_WATCHES_PER_TASK: usize = 16;
+
+#[derive(Debug)]
+struct Watch {
+    path: String,
+    reply_chan: u32,
+    task: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct WatchTable {
+    watches: Vec<Watch>,
+}
+
+impl WatchTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Adds a watch. Watching the same path on the same channel again is a
+    /// no-op. Returns false if `task` already holds the maximum.
+    pub fn add(&mut self, task: u64, path: &str, reply_chan: u32) -> bool {
+        if self.watches.iter().any(|watch| watch.path == path && watch.reply_chan == reply_chan) {
+            return true;
+        }
+        if self.watches.iter().filter(|watch| watch.task == task).count() >= MAX_WATCHES_PER_TASK {
+            return false;
+        }
+        self.watches.push(Watch { path: String::from(path), reply_chan, task });
+        true
+    }
+
+    /// Removes a watch of `task`. Returns false if it held none like it.
+    pub fn remove(&mut self, task: u64, path: &str, reply_chan: u32) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|watch| !(watch.task == task && watch.path == path && watch.reply_chan == reply_chan));
+        self.watches.len() != before
+    }
+
+    /// Channels to notify of a change to `path`, each once.
+    pub fn watchers(&self, path: &str) -> Vec<u32> {
+        let mut channels: Vec<u32> = self.watches.iter()
+            .filter(|watch| covers(&watch.path, path))
+            .map(|watch| watch.reply_chan)
+            .collect();
+        channels.sort_unstable();
+        channels.dedup();
+        channels
+    }
+
+    /// Drops the watches of tasks that have exited. Returns how many were dropped.
+    pub fn expire(&mut self, alive: impl Fn(u64) -> bool) -> usize {
+        let before = self.watches.len();
+        self.watches.retain(|watch| alive(watch.task));
+        before - self.watches.len()
+    }
+}
+
+/// Whether a watch on `watched` sees a change to `path`: the path itself or
+/// anything below it.
+fn covers(watched: &str, path: &str) -> bool {
+    match path.strip_prefix(watched) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || watched == "/",
+        None => false,
+    }
+}
@@ -0,0 +1,132 @@
+// vnode/vfs/src/xattr.rs
+
+//! Extended attributes: small named values kept with a file's metadata.
+//!
+//! Names are namespaced: `user.*` may be changed by anyone who may write the
+//! file, `system.*` only by the system identity (checked by the caller, see
+//! `VfsService::authorize`). Each value is at most `MAX_XATTR_VALUE_BYTES`, and
+//! a file's names and values together at most `MAX_XATTR_BYTES_PER_FILE`, so
+//! they fit in the file's metadata on the backend and in one IPC message.
+//!
+//! Attributes belong to a path the way the file's timestamps do: deleting
+//! the file drops them and moving it takes them along. A write doesn't touch
+//! them; whoever stamps a content-derived attribute such as `system.cid`
+//! stamps it again after changing the file.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::ipc::vfs_ipc::{self, Xattrs, MAX_XATTR_BYTES_PER_FILE, MAX_XATTR_VALUE_BYTES};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XattrError {
+    /// No known namespace, nothing after the namespace, or too long.
+    InvalidName,
+    ValueTooLarge,
+    /// The file's attributes would take `needed` bytes, over the per-file limit.
+    FileFull { needed: usize },
+    NotFound,
+}
+
+impl XattrError {
+    /// The errno-like code and message the VFS answers with.
+    pub fn describe(&self, path: &str, name: &str) -> (i32, String) {
+        match self {
+            XattrError::InvalidName => (95, format!("Unsupported attribute name {}", name)), // EOPNOTSUPP
+            XattrError::ValueTooLarge => (7, format!("Value of {} is over {} bytes", name, MAX_XATTR_VALUE_BYTES)), // E2BIG
+            XattrError::FileFull { needed } => (28, format!("Attributes of {} would take {} bytes, over {}", path, needed, MAX_XATTR_BYTES_PER_FILE)), // ENOSPC
+            XattrError::NotFound => (61, format!("No attribute {} on {}", name, path)), // ENODATA
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct XattrTable {
+    files: BTreeMap<String, Xattrs>, // Path -> its attributes; files without any aren't listed
+}
+
+impl XattrTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, path: &str, name: &str) -> Result<&[u8], XattrError> {
+        self.files.get(path).and_then(|xattrs| xattrs.get(name)).map(Vec::as_slice).ok_or(XattrError::NotFound)
+    }
+
+    pub fn names(&self, path: &str) -> Vec<String> {
+        self.files.get(path).map(|xattrs| xattrs.keys().cloned().collect()).unwrap_or_default()
+    }
+
+    /// The attributes of `path` among `names`. Names it doesn't have are left out.
+    pub fn select(&self, path: &str, names: &[String]) -> Xattrs {
+        let Some(xattrs) = self.files.get(path) else {
+            return Xattrs::new();
+        };
+        names.iter()
+            .filter_map(|name| xattrs.get(name).map(|value| (name.clone(), value.clone())))
+            .collect()
+    }
+
+    /// Sets `name` on `path`. Nothing is changed if the name or the sizes are rejected.
+    pub fn set(&mut self, path: &str, name: &str, value: Vec<u8>) -> Result<(), XattrError> {
+        if vfs_ipc::xattr_namespace(name).is_none() {
+            return Err(XattrError::InvalidName);
+        }
+        if value.len() > MAX_XATTR_VALUE_BYTES {
+            return Err(XattrError::ValueTooLarge);
+        }
+        let others: usize = self.files.get(path)
+            .map(|xattrs| xattrs.iter().filter(|(other, _)| other.as_str() != name).map(|(other, value)| other.len() + value.len()).sum())
+            .unwrap_or(0);
+        let needed = others + name.len() + value.len();
+        if needed > MAX_XATTR_BYTES_PER_FILE {
+            return Err(XattrError::FileFull { needed });
+        }
+        self.files.entry(String::from(path)).or_default().insert(String::from(name), value);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str, name: &str) -> Result<(), XattrError> {
+        let xattrs = self.files.get_mut(path).ok_or(XattrError::NotFound)?;
+        xattrs.remove(name).ok_or(XattrError::NotFound)?;
+        if xattrs.is_empty() {
+            self.files.remove(path);
+        }
+        Ok(())
+    }
+
+    /// Drops the attributes of `path` and, if it is a directory, of everything below it.
+    pub fn remove_file(&mut self, path: &str) {
+        let path = path.trim_end_matches('/');
+        self.files.retain(|tracked, _| tracked.as_str() != path && !is_under(tracked, path));
+    }
+
+    /// Moves the attributes of `source` (and everything below it) to
+    /// `destination`, replacing whatever was there.
+    pub fn rename(&mut self, source: &str, destination: &str) {
+        let source = source.trim_end_matches('/');
+        let destination = destination.trim_end_matches('/');
+        if destination == source || is_under(destination, source) {
+            return; // Not a valid move; the backend rejects it
+        }
+        let moved: Vec<String> = self.files.keys()
+            .filter(|tracked| tracked.as_str() == source || is_under(tracked, source))
+            .cloned()
+            .collect();
+        self.remove_file(destination);
+        for path in moved {
+            if let Some(xattrs) = self.files.remove(&path) {
+                self.files.insert(format!("{}{}", destination, &path[source.len()..]), xattrs);
+            }
+        }
+    }
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).map_or(false, |rest| rest.starts_with('/'))
+}
@@ -0,0 +1,204 @@
+// vnode/display-compositor/src/background.rs
+
+//! The desktop background: a solid color, with an optional wallpaper over it.
+//!
+//! A wallpaper is decoded once, when it is set, and scaled to an output the
+//! first time that output composites with it. Compositing copies damaged rows
+//! out of the scaled copy, so a small damage rectangle doesn't rescale the
+//! image. The copy is made again when the output's size or the fit changes.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::vnode::VNodeChannel;
+use common::ui::image::{self, Image};
+use common::ui_protocol::WallpaperFit;
+
+use crate::cursor::Rect;
+use crate::output::{Output, DESKTOP_COLOR};
+
+/// `DESKTOP_COLOR` as the `compositor.background_color` setting writes it.
+pub const DEFAULT_COLOR: &str = "#202030";
+/// Largest wallpaper file read from the VFS.
+pub const MAX_WALLPAPER_BYTES: usize = 8 * 1024 * 1024;
+
+/// Parses `#RRGGBB` into an opaque RGBA color.
+pub fn parse_color(text: &str) -> Option<[u8; 4]> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii())?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?, 0xFF])
+}
+
+/// The value of `compositor.wallpaper_fit` for `fit`.
+pub fn fit_name(fit: WallpaperFit) -> &'static str {
+    match fit {
+        WallpaperFit::Center => "center",
+        WallpaperFit::Fit => "fit",
+        WallpaperFit::Fill => "fill",
+        WallpaperFit::Stretch => "stretch",
+    }
+}
+
+pub fn fit_from_name(name: &str) -> Option<WallpaperFit> {
+    [WallpaperFit::Center, WallpaperFit::Fit, WallpaperFit::Fill, WallpaperFit::Stretch].into_iter().find(|fit| fit_name(*fit) == name)
+}
+
+/// A decoded wallpaper and the path it was read from.
+pub struct Wallpaper {
+    pub path: String,
+    image: Image,
+}
+
+impl Wallpaper {
+    /// Reads and decodes the image at `path`.
+    pub fn load(vfs_chan: &mut VNodeChannel, path: &str) -> Result<Self, String> {
+        let fd: Fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 0 /* O_RDONLY */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(format!("{}: {}", path, message)),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let mut streams = VfsStreams::new(vfs_chan);
+        let data = streams.open_read(fd, 0, MAX_WALLPAPER_BYTES as u64 + 1)
+            .and_then(|stream| streams.read_to_end(stream, MAX_WALLPAPER_BYTES));
+        let _ = streams.request(&VfsRequest::Close { fd });
+        let data = data.map_err(|e| format!("{}: {}", path, e))?;
+        let image = image::decode(&data).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Self { path: path.to_string(), image })
+    }
+}
+
+/// Where a `width x height` image goes on an output of `out_width x out_height`:
+/// its origin relative to the output's, which is negative where it is cropped,
+/// and its scaled size.
+pub fn placement(width: u32, height: u32, out_width: u32, out_height: u32, fit: WallpaperFit) -> (i64, i64, u32, u32) {
+    let (w, h) = (width.max(1) as u64, height.max(1) as u64);
+    let (ow, oh) = (out_width as u64, out_height as u64);
+    // The image is wider than the output, relative to their heights.
+    let wider = w * oh > ow * h;
+    let (sw, sh) = match fit {
+        WallpaperFit::Center => (w, h),
+        WallpaperFit::Stretch => (ow, oh),
+        // Fit matches the dimension that sticks out most, Fill the other one.
+        WallpaperFit::Fit if wider => (ow, h * ow / w),
+        WallpaperFit::Fit => (w * oh / h, oh),
+        WallpaperFit::Fill if wider => (w * oh / h, oh),
+        WallpaperFit::Fill => (ow, h * ow / w),
+    };
+    let (sw, sh) = (sw.max(1), sh.max(1));
+    ((ow as i64 - sw as i64) / 2, (oh as i64 - sh as i64) / 2, sw as u32, sh as u32)
+}
+
+/// A wallpaper rendered at one output's size, over the background color.
+struct Scaled {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+pub struct Background {
+    color: [u8; 4],
+    color_name: String,
+    wallpaper: Option<Wallpaper>,
+    fit: WallpaperFit,
+    scaled: BTreeMap<u32, Scaled>, // By output ID
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Self { color: DESKTOP_COLOR, color_name: DEFAULT_COLOR.to_string(), wallpaper: None, fit: WallpaperFit::Fill, scaled: BTreeMap::new() }
+    }
+
+    pub fn color_name(&self) -> &str {
+        &self.color_name
+    }
+
+    /// The wallpaper's path, empty if there is none.
+    pub fn wallpaper_path(&self) -> &str {
+        self.wallpaper.as_ref().map_or("", |wallpaper| wallpaper.path.as_str())
+    }
+
+    pub fn fit(&self) -> WallpaperFit {
+        self.fit
+    }
+
+    // The setters return whether the background looks different, in which
+    // case the caller damages every output once.
+
+    pub fn set_color(&mut self, color: [u8; 4], name: &str) -> bool {
+        self.color_name = name.to_string();
+        if self.color == color {
+            return false;
+        }
+        self.color = color;
+        self.scaled.clear();
+        true
+    }
+
+    pub fn set_wallpaper(&mut self, wallpaper: Option<Wallpaper>) -> bool {
+        if wallpaper.is_none() && self.wallpaper.is_none() {
+            return false;
+        }
+        self.wallpaper = wallpaper;
+        self.scaled.clear();
+        true
+    }
+
+    /// Only changes the look if there is a wallpaper.
+    pub fn set_fit(&mut self, fit: WallpaperFit) -> bool {
+        if self.fit == fit {
+            return false;
+        }
+        self.fit = fit;
+        self.scaled.clear();
+        self.wallpaper.is_some()
+    }
+
+    /// Drops the scaled copy for an output that is gone.
+    pub fn forget_output(&mut self, output_id: u32) {
+        self.scaled.remove(&output_id);
+    }
+
+    /// Paints the background into the part of `output` inside `damage`.
+    pub fn paint(&mut self, output: &mut Output, damage: Rect) {
+        let wallpaper = match &self.wallpaper {
+            Some(wallpaper) => wallpaper,
+            None => return output.fill(damage, damage, self.color),
+        };
+        let area = output.rect();
+        let fresh = self.scaled.get(&output.id).map_or(false, |scaled| (scaled.width, scaled.height) == (area.width, area.height));
+        if !fresh {
+            let pixels = render(&wallpaper.image, area.width, area.height, self.fit, self.color);
+            self.scaled.insert(output.id, Scaled { width: area.width, height: area.height, pixels });
+        }
+        let scaled = &self.scaled[&output.id];
+        let row_len = (scaled.width * 4) as usize;
+        output.draw(area, damage, |row| &scaled.pixels[row as usize * row_len..(row as usize + 1) * row_len]);
+    }
+}
+
+/// `image` laid out on a `width x height` output per `fit`, nearest-neighbour
+/// scaled, with `color` where it doesn't reach. Wallpapers are opaque.
+fn render(image: &Image, width: u32, height: u32, fit: WallpaperFit, color: [u8; 4]) -> Vec<u8> {
+    let (x0, y0, sw, sh) = placement(image.width, image.height, width, height, fit);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let (dx, dy) = (x - x0, y - y0);
+            if dx < 0 || dy < 0 || dx >= sw as i64 || dy >= sh as i64 {
+                pixels.extend_from_slice(&color);
+                continue;
+            }
+            let sx = dx as u64 * image.width as u64 / sw as u64;
+            let sy = dy as u64 * image.height as u64 / sh as u64;
+            let i = ((sy * image.width as u64 + sx) * 4) as usize;
+            pixels.extend_from_slice(&[image.pixels[i], image.pixels[i + 1], image.pixels[i + 2], 0xFF]);
+        }
+    }
+    pixels
+}
@@ -0,0 +1,295 @@
+// vnode/display-compositor/src/cursor.rs
+
+//! The mouse cursor: its on-screen position and the sprite drawn there.
+//!
+//! The kernel reports relative movement (`SYS_INPUT_READ`). The cursor turns
+//! it into an absolute position clamped to the desktop, the box around every
+//! output, and button state
+//! changes into the press/release events the compositor routes to windows.
+//! The position is the sprite's hotspot: the arrow's tip, the middle of the
+//! text beam, the hand's fingertip.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use common::abi::{MouseReport, MOUSE_BUTTON_LEFT, MOUSE_BUTTON_MIDDLE, MOUSE_BUTTON_RIGHT};
+use common::ui_protocol::{CursorShape, MouseEventType, BUTTON_LEFT, BUTTON_MIDDLE, BUTTON_RIGHT, SCROLL_DOWN, SCROLL_UP};
+
+/// A cursor sprite: 'X' is outline, '.' is fill, ' ' is transparent. Every
+/// row is as wide as the first.
+struct Sprite {
+    rows: &'static [&'static str],
+    /// The pixel that sits at the pointer position.
+    hotspot: (u32, u32),
+}
+
+const ARROW: Sprite = Sprite {
+    rows: &[
+        "X           ",
+        "XX          ",
+        "X.X         ",
+        "X..X        ",
+        "X...X       ",
+        "X....X      ",
+        "X.....X     ",
+        "X......X    ",
+        "X.......X   ",
+        "X........X  ",
+        "X.........X ",
+        "X......XXXXX",
+        "X...X..X    ",
+        "X..XX..X    ",
+        "X.X  X..X   ",
+        "XX   X..X   ",
+        "X     X..X  ",
+        "      X..X  ",
+        "       XX   ",
+    ],
+    hotspot: (0, 0),
+};
+
+const TEXT_BEAM: Sprite = Sprite {
+    rows: &[
+        "XXX XXX",
+        "X..X..X",
+        "XXX.XXX",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "  X.X  ",
+        "XXX.XXX",
+        "X..X..X",
+        "XXX XXX",
+    ],
+    hotspot: (3, 8),
+};
+
+const HAND: Sprite = Sprite {
+    rows: &[
+        "    XX         ",
+        "   X..X        ",
+        "   X..X        ",
+        "   X..X        ",
+        "   X..XXX      ",
+        "   X..X..XXX   ",
+        "   X..X..X..XX ",
+        "XX X..X..X..X.X",
+        "X..X..........X",
+        "X...X.........X",
+        " X............X",
+        "  X...........X",
+        "  X..........X ",
+        "   X.........X ",
+        "   X........X  ",
+        "    X.......X  ",
+        "    XXXXXXXXX  ",
+    ],
+    hotspot: (4, 0),
+};
+
+const RESIZE_HORIZONTAL: Sprite = Sprite {
+    rows: &[
+        "    X         X    ",
+        "   XX         XX   ",
+        "  X.XXXXXXXXXXX.X  ",
+        " X...............X ",
+        "X.................X",
+        " X...............X ",
+        "  X.XXXXXXXXXXX.X  ",
+        "   XX         XX   ",
+        "    X         X    ",
+    ],
+    hotspot: (9, 4),
+};
+
+const RESIZE_VERTICAL: Sprite = Sprite {
+    rows: &[
+        "    X    ",
+        "   X.X   ",
+        "  X...X  ",
+        " X.....X ",
+        "XXXX.XXXX",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "   X.X   ",
+        "XXXX.XXXX",
+        " X.....X ",
+        "  X...X  ",
+        "   X.X   ",
+        "    X    ",
+    ],
+    hotspot: (4, 9),
+};
+
+fn sprite(shape: CursorShape) -> &'static Sprite {
+    match shape {
+        CursorShape::Arrow => &ARROW,
+        CursorShape::TextBeam => &TEXT_BEAM,
+        CursorShape::Hand => &HAND,
+        CursorShape::ResizeHorizontal => &RESIZE_HORIZONTAL,
+        CursorShape::ResizeVertical => &RESIZE_VERTICAL,
+    }
+}
+
+/// Width and height of a shape's sprite.
+pub fn sprite_size(shape: CursorShape) -> (u32, u32) {
+    let rows = sprite(shape).rows;
+    (rows[0].len() as u32, rows.len() as u32)
+}
+
+const OUTLINE_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+const FILL_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const TRANSPARENT: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
+
+/// Renders a shape's sprite as RGBA rows. Transparent pixels have alpha 0
+/// and must be skipped when blitting, so the windows beneath show through.
+pub fn render_sprite(shape: CursorShape) -> Vec<u8> {
+    let (width, height) = sprite_size(shape);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for row in sprite(shape).rows {
+        for c in row.bytes() {
+            pixels.extend_from_slice(match c {
+                b'X' => &OUTLINE_COLOR,
+                b'.' => &FILL_COLOR,
+                _ => &TRANSPARENT,
+            });
+        }
+    }
+    pixels
+}
+
+/// A screen area, e.g. the pixels the cursor covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    /// The area both rectangles cover, if any.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let (right, bottom) = (self.right().min(other.right()), self.bottom().min(other.bottom()));
+        if right > x && bottom > y { Some(Rect { x, y, width: right - x, height: bottom - y }) } else { None }
+    }
+
+    /// The smallest rectangle covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect { x, y, width: self.right().max(other.right()) - x, height: self.bottom().max(other.bottom()) - y }
+    }
+}
+
+/// Pointer input in screen coordinates, ready for `handle_pointer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointerEvent {
+    pub x: u32,
+    pub y: u32,
+    pub button: u8,
+    pub event_type: MouseEventType,
+}
+
+pub struct Cursor {
+    pub x: u32,
+    pub y: u32,
+    pub shape: CursorShape,
+    buttons: u8, // MOUSE_BUTTON_* bits held down
+    screen_width: u32,
+    screen_height: u32,
+}
+
+impl Cursor {
+    /// A cursor in the middle of the screen.
+    pub fn new(screen_width: u32, screen_height: u32) -> Self {
+        Self { x: screen_width / 2, y: screen_height / 2, shape: CursorShape::Arrow, buttons: 0, screen_width, screen_height }
+    }
+
+    /// Changes the area the cursor moves in, e.g. when an output is added or
+    /// removed. A cursor left outside is pulled back in.
+    pub fn set_bounds(&mut self, screen_width: u32, screen_height: u32) {
+        self.screen_width = screen_width;
+        self.screen_height = screen_height;
+        self.warp(self.x, self.y);
+    }
+
+    /// Screen pixels the sprite covers, clipped to the screen. The sprite
+    /// sits with its hotspot on the cursor position.
+    pub fn rect(&self) -> Rect {
+        let (width, height) = sprite_size(self.shape);
+        let (hot_x, hot_y) = sprite(self.shape).hotspot;
+        let (left, top) = (self.x.saturating_sub(hot_x), self.y.saturating_sub(hot_y));
+        let (right, bottom) = ((self.x + width - hot_x).min(self.screen_width), (self.y + height - hot_y).min(self.screen_height));
+        Rect { x: left, y: top, width: right - left, height: bottom - top }
+    }
+
+    /// Switches to another sprite. Returns false if it was already showing.
+    pub fn set_shape(&mut self, shape: CursorShape) -> bool {
+        let changed = self.shape != shape;
+        self.shape = shape;
+        changed
+    }
+
+    /// Moves the cursor to an absolute position, e.g. one reported by the input bridge.
+    pub fn warp(&mut self, x: u32, y: u32) {
+        self.x = x.min(self.screen_width - 1);
+        self.y = y.min(self.screen_height - 1);
+    }
+
+    /// Applies one mouse report. Returns the resulting events in order: the
+    /// move first, so presses land where the pointer ended up, then presses
+    /// and releases, then scrolling.
+    pub fn apply(&mut self, report: &MouseReport) -> Vec<PointerEvent> {
+        let mut events = Vec::new();
+        let x = (self.x as i64 + report.dx as i64).clamp(0, self.screen_width as i64 - 1) as u32;
+        let y = (self.y as i64 + report.dy as i64).clamp(0, self.screen_height as i64 - 1) as u32;
+        if (x, y) != (self.x, self.y) {
+            self.x = x;
+            self.y = y;
+            events.push(self.event(0, MouseEventType::MouseMove));
+        }
+
+        let changed = self.buttons ^ report.buttons;
+        for (bit, button) in [(MOUSE_BUTTON_LEFT, BUTTON_LEFT), (MOUSE_BUTTON_RIGHT, BUTTON_RIGHT), (MOUSE_BUTTON_MIDDLE, BUTTON_MIDDLE)] {
+            if changed & bit != 0 {
+                let event_type = if report.buttons & bit != 0 { MouseEventType::MouseDown } else { MouseEventType::MouseUp };
+                events.push(self.event(button, event_type));
+            }
+        }
+        self.buttons = report.buttons;
+
+        if report.wheel != 0 {
+            let direction = if report.wheel > 0 { SCROLL_DOWN } else { SCROLL_UP };
+            for _ in 0..report.wheel.unsigned_abs() {
+                events.push(self.event(direction, MouseEventType::Scroll));
+            }
+        }
+        events
+    }
+
+    fn event(&self, button: u8, event_type: MouseEventType) -> PointerEvent {
+        PointerEvent { x: self.x, y: self.y, button, event_type }
+    }
+}
@@ -0,0 +1,254 @@
+// vnode/display-compositor/src/decorations.rs
+
+//! Server-side window decorations: title bar geometry, hit-testing and rendering.
+//!
+//! A decorated window occupies a frame of `width x (title_bar_height() + height)`
+//! starting at the window's (x, y). The client area sits directly below the
+//! title bar; clients only ever see client-relative coordinates.
+//!
+//! Decorations follow the UI scale: the constants below are the 1x sizes,
+//! and the functions next to them give the sizes in use.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use common::text;
+use common::ui::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use common::ui::scale::UiScale;
+
+use crate::cursor::Rect;
+
+/// Height of the title bar: one text line plus 2px padding above and below.
+pub const TITLE_BAR_HEIGHT: u32 = GLYPH_HEIGHT as u32 + 4;
+/// Side length of the square close button at the right end of the title bar.
+pub const CLOSE_BUTTON_SIZE: u32 = 16;
+/// Gap between the close button and the title bar's top, right and bottom edges.
+const CLOSE_BUTTON_MARGIN: u32 = (TITLE_BAR_HEIGHT - CLOSE_BUTTON_SIZE) / 2;
+/// Left padding before the title text.
+const TITLE_TEXT_PADDING: u32 = 6;
+/// Inset of the 'x' in the close button.
+const CROSS_INSET: u32 = 3;
+
+const TITLE_BAR_COLOR: [u8; 4] = [0x30, 0x30, 0x48, 0xFF];
+const TITLE_BAR_FOCUSED_COLOR: [u8; 4] = [0x38, 0x50, 0x88, 0xFF];
+const TITLE_TEXT_COLOR: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+const CLOSE_BUTTON_COLOR: [u8; 4] = [0xC0, 0x40, 0x40, 0xFF];
+
+/// Index of the current scale in `UiScale::ALL`.
+static SCALE: AtomicU8 = AtomicU8::new(0);
+
+pub fn scale() -> UiScale {
+    UiScale::ALL[SCALE.load(Ordering::Relaxed) as usize]
+}
+
+/// Sets the scale decorations are drawn and hit-tested at. Every window's
+/// frame changes size, so the caller damages and re-clamps them all.
+pub fn set_scale(scale: UiScale) {
+    let index = UiScale::ALL.iter().position(|s| *s == scale).unwrap_or(0);
+    SCALE.store(index as u8, Ordering::Relaxed);
+}
+
+pub fn title_bar_height() -> u32 {
+    scale().px(TITLE_BAR_HEIGHT)
+}
+
+fn close_button_size() -> u32 {
+    scale().px(CLOSE_BUTTON_SIZE)
+}
+
+fn close_button_margin() -> u32 {
+    scale().px(CLOSE_BUTTON_MARGIN)
+}
+
+/// Where a screen point falls relative to a decorated window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameHit {
+    CloseButton,
+    /// The draggable part of the title bar.
+    TitleBar,
+    /// Inside the client area, with client-relative coordinates.
+    Client { x: u32, y: u32 },
+    Outside,
+}
+
+/// Screen-space geometry of a decorated window.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32, // Client width (the title bar spans the same width)
+    pub height: u32, // Client height, excluding the title bar
+}
+
+impl Frame {
+    pub fn total_height(&self) -> u32 {
+        title_bar_height() + self.height
+    }
+
+    /// Everything the window draws: title bar and client area.
+    pub fn rect(&self) -> Rect {
+        Rect { x: self.x, y: self.y, width: self.width, height: self.total_height() }
+    }
+
+    /// Screen x of the close button's left edge.
+    fn close_button_x(&self) -> u32 {
+        (self.x + self.width).saturating_sub(close_button_margin() + close_button_size())
+    }
+
+    pub fn hit_test(&self, px: u32, py: u32) -> FrameHit {
+        if px < self.x || px >= self.x + self.width || py < self.y || py >= self.y + self.total_height() {
+            return FrameHit::Outside;
+        }
+        let (bar_height, button_size) = (title_bar_height(), close_button_size());
+        if py < self.y + bar_height {
+            let button_x = self.close_button_x();
+            let button_y = self.y + close_button_margin();
+            let in_button = px >= button_x && px < button_x + button_size
+                && py >= button_y && py < button_y + button_size;
+            return if in_button { FrameHit::CloseButton } else { FrameHit::TitleBar };
+        }
+        FrameHit::Client { x: px - self.x, y: py - self.y - bar_height }
+    }
+}
+
+/// The topmost of `frames` (given bottom to top) that the screen point
+/// hits, and where it hit it.
+pub fn topmost_hit<T, I>(frames: I, px: u32, py: u32) -> Option<(T, FrameHit)>
+where
+    I: DoubleEndedIterator<Item = (T, Frame)>,
+{
+    frames.rev().find_map(|(id, frame)| match frame.hit_test(px, py) {
+        FrameHit::Outside => None,
+        hit => Some((id, hit)),
+    })
+}
+
+/// Whether a window whose owner was sent `CloseRequested` at tick
+/// `requested_at` is due to be force-closed at `now`.
+pub fn close_overdue(requested_at: Option<u64>, now: u64, timeout_ticks: u64) -> bool {
+    requested_at.map_or(false, |at| now.saturating_sub(at) >= timeout_ticks)
+}
+
+/// Clamps a proposed frame origin so the whole title bar stays inside `area`,
+/// an output or the whole desktop (and can therefore always be grabbed again).
+pub fn clamp_origin(x: i64, y: i64, frame_width: u32, area: Rect) -> (u32, u32) {
+    let max_x = area.x as i64 + area.width.saturating_sub(frame_width) as i64;
+    let max_y = area.y as i64 + area.height.saturating_sub(title_bar_height()) as i64;
+    (x.clamp(area.x as i64, max_x) as u32, y.clamp(area.y as i64, max_y) as u32)
+}
+
+/// Renders the title bar of a window `width` pixels wide as RGBA rows.
+/// The title is clipped so it never runs under the close button.
+pub fn render_title_bar(title: &str, width: u32, focused: bool) -> Vec<u8> {
+    let scale = scale();
+    let (button_size, button_margin) = (close_button_size(), close_button_margin());
+    let w = width as usize;
+    let h = title_bar_height() as usize;
+    let mut pixels = Vec::with_capacity(w * h * 4);
+    let background = if focused { TITLE_BAR_FOCUSED_COLOR } else { TITLE_BAR_COLOR };
+    for _ in 0..w * h {
+        pixels.extend_from_slice(&background);
+    }
+
+    let mut put = |px: usize, py: usize, color: [u8; 4]| {
+        if px < w && py < h {
+            let i = (py * w + px) * 4;
+            pixels[i..i + 4].copy_from_slice(&color);
+        }
+    };
+
+    // Title text, one glyph cell per character, stopping short of the close button.
+    let (glyph_width, glyph_height) = (scale.px(GLYPH_WIDTH as u32) as usize, scale.px(GLYPH_HEIGHT as u32) as usize);
+    let padding = scale.px(TITLE_TEXT_PADDING) as usize;
+    let text_limit = (width.saturating_sub(button_size + 2 * button_margin)) as usize;
+    let text_top = (h - glyph_height) / 2;
+    let cells = text_limit.saturating_sub(padding) / glyph_width;
+    let mut cursor = padding;
+    for c in text::truncate_to_width(title, cells).chars() {
+        let width = text::char_width(c);
+        if width == 0 {
+            continue;
+        }
+        for gy in 0..glyph_height {
+            let row = font::glyph_row_scaled(c, gy, scale);
+            for gx in 0..glyph_width {
+                if row & (1 << gx) != 0 {
+                    put(cursor + gx, text_top + gy, TITLE_TEXT_COLOR);
+                }
+            }
+        }
+        cursor += width * glyph_width;
+    }
+
+    // Close button: a filled square with an 'x' drawn through it.
+    let bx = (width.saturating_sub(button_margin + button_size)) as usize;
+    let by = button_margin as usize;
+    let size = button_size as usize;
+    let inset = scale.px(CROSS_INSET) as usize;
+    for dy in 0..size {
+        for dx in 0..size {
+            let on_cross = dx >= inset && dx < size - inset && (dx == dy || dx == size - 1 - dy);
+            put(bx + dx, by + dy, if on_cross { TITLE_TEXT_COLOR } else { CLOSE_BUTTON_COLOR });
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // At 1x: a 20px title bar with the 16px close button 2px in from the top and right.
+    const FRAME: Frame = Frame { x: 100, y: 50, width: 200, height: 100 };
+
+    #[test]
+    fn hit_test_finds_each_part_of_the_frame() {
+        assert_eq!(FRAME.hit_test(99, 60), FrameHit::Outside);
+        assert_eq!(FRAME.hit_test(300, 60), FrameHit::Outside);
+        assert_eq!(FRAME.hit_test(150, 49), FrameHit::Outside);
+        assert_eq!(FRAME.hit_test(150, 50 + TITLE_BAR_HEIGHT + 100), FrameHit::Outside);
+        assert_eq!(FRAME.hit_test(100, 50), FrameHit::TitleBar);
+        assert_eq!(FRAME.hit_test(281, 60), FrameHit::TitleBar); // Just left of the close button
+        assert_eq!(FRAME.hit_test(282, 52), FrameHit::CloseButton);
+        assert_eq!(FRAME.hit_test(297, 67), FrameHit::CloseButton);
+        assert_eq!(FRAME.hit_test(298, 60), FrameHit::TitleBar); // The right margin
+        assert_eq!(FRAME.hit_test(290, 51), FrameHit::TitleBar); // The top margin
+        assert_eq!(FRAME.hit_test(100, 50 + TITLE_BAR_HEIGHT), FrameHit::Client { x: 0, y: 0 });
+        assert_eq!(FRAME.hit_test(299, 50 + TITLE_BAR_HEIGHT + 99), FrameHit::Client { x: 199, y: 99 });
+    }
+
+    #[test]
+    fn the_topmost_window_under_the_point_wins() {
+        let below = Frame { x: 0, y: 0, width: 300, height: 300 };
+        let above = Frame { x: 100, y: 100, width: 100, height: 100 };
+        let frames = [(1, below), (2, above)];
+        assert_eq!(topmost_hit(frames.into_iter(), 150, 100), Some((2, FrameHit::TitleBar)));
+        assert_eq!(topmost_hit(frames.into_iter(), 150, 100 + TITLE_BAR_HEIGHT + 10), Some((2, FrameHit::Client { x: 50, y: 10 })));
+        // Outside the top window the one below gets the point, in its own coordinates.
+        assert_eq!(topmost_hit(frames.into_iter(), 50, 100), Some((1, FrameHit::Client { x: 50, y: 100 - TITLE_BAR_HEIGHT })));
+        assert_eq!(topmost_hit(frames.into_iter(), 310, 10), None);
+        assert_eq!(topmost_hit(core::iter::empty::<(u32, Frame)>(), 0, 0), None);
+    }
+
+    #[test]
+    fn windows_are_force_closed_once_the_timeout_passes() {
+        assert!(!close_overdue(None, 10_000, 300));
+        assert!(!close_overdue(Some(1000), 1000, 300));
+        assert!(!close_overdue(Some(1000), 1299, 300));
+        assert!(close_overdue(Some(1000), 1300, 300));
+        assert!(close_overdue(Some(1000), 5000, 300));
+        // A clock that reads earlier than the request never closes early.
+        assert!(!close_overdue(Some(1000), 900, 300));
+    }
+
+    #[test]
+    fn clamped_origins_keep_the_title_bar_on_the_output() {
+        let area = Rect { x: 0, y: 0, width: 1024, height: 768 };
+        assert_eq!(clamp_origin(-50, -50, 200, area), (0, 0));
+        assert_eq!(clamp_origin(2000, 2000, 200, area), (824, 768 - TITLE_BAR_HEIGHT));
+        assert_eq!(clamp_origin(10, 20, 200, area), (10, 20));
+    }
+}
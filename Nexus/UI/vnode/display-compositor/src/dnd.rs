@@ -0,0 +1,139 @@
+// vnode/display-compositor/src/dnd.rs
+
+//! Drag and drop between windows.
+//!
+//! A drag starts when the source window sends `StartDrag` while the left
+//! button it was pressed with is still held. From then on pointer input moves
+//! the drag instead of going to windows: the window under the pointer gets
+//! `DragOver` as the drag enters it and on every move, and `DragLeave` when it
+//! leaves. A window takes the drop by answering `AcceptDrag`. On release the
+//! drop goes to the window under the pointer if it accepted, and the source
+//! learns from `DragEnded` whether anyone did. Everything else cancels.
+//!
+//! `Drag` only decides which events go to which window; the compositor looks
+//! up their channels and sends them.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ids::WindowId;
+use common::ui_protocol::{DragData, UiEvent};
+
+use crate::cursor::Rect;
+
+pub const GHOST_SIZE: u32 = 16;
+/// The ghost sits below and to the right of the pointer, clear of the cursor's hotspot.
+const GHOST_OFFSET: u32 = 12;
+
+/// A window under the pointer, with the client-relative point it is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Over {
+    pub window_id: WindowId,
+    pub x: u32,
+    pub y: u32,
+}
+
+pub struct Drag {
+    pub source: WindowId,
+    mime: String,
+    data: DragData,
+    over: Option<Over>,
+    accepted: bool, // The window in `over` answered its last DragOver with accept
+}
+
+impl Drag {
+    pub fn new(source: WindowId, mime: String, data: DragData) -> Self {
+        Self { source, mime, data, over: None, accepted: false }
+    }
+
+    /// The pointer moved to `over` (`None`: no window's client area). Returns
+    /// the events to send, each with the window it is for.
+    pub fn moved(&mut self, over: Option<Over>) -> Vec<(WindowId, UiEvent)> {
+        let mut events = Vec::new();
+        let previous = self.over.map(|over| over.window_id);
+        if previous != over.map(|over| over.window_id) {
+            if let Some(window_id) = previous {
+                events.push((window_id, UiEvent::DragLeave { window_id }));
+            }
+            // A window that hasn't answered yet doesn't take the drop.
+            self.accepted = false;
+        }
+        self.over = over;
+        if let Some(Over { window_id, x, y }) = over {
+            events.push((window_id, UiEvent::DragOver { window_id, x, y, mime: self.mime.clone() }));
+        }
+        events
+    }
+
+    /// `AcceptDrag` from `window_id`. Ignored unless the drag is over that window.
+    pub fn accept(&mut self, window_id: WindowId, accept: bool) -> bool {
+        if self.over.map(|over| over.window_id) != Some(window_id) {
+            return false;
+        }
+        self.accepted = accept;
+        true
+    }
+
+    /// Whether the window under the pointer takes the drop, for the ghost.
+    pub fn accepted(&self) -> bool {
+        self.accepted
+    }
+
+    /// The button was released: the drop goes to the window under the pointer
+    /// if it accepted, and the drag is cancelled otherwise.
+    pub fn release(self) -> Vec<(WindowId, UiEvent)> {
+        match self.over {
+            Some(Over { window_id, x, y }) if self.accepted => alloc::vec![
+                (window_id, UiEvent::Drop { window_id, x, y, mime: self.mime, data: self.data }),
+                (self.source, UiEvent::DragEnded { window_id: self.source, dropped: true }),
+            ],
+            _ => self.cancel(),
+        }
+    }
+
+    /// Ends the drag without a drop, e.g. on Escape or when the source closes.
+    pub fn cancel(self) -> Vec<(WindowId, UiEvent)> {
+        let mut events = Vec::new();
+        if let Some(Over { window_id, .. }) = self.over {
+            events.push((window_id, UiEvent::DragLeave { window_id }));
+        }
+        events.push((self.source, UiEvent::DragEnded { window_id: self.source, dropped: false }));
+        events
+    }
+
+    /// A window closed. A closed target drops out of the drag; a closed source
+    /// is the caller's to cancel.
+    pub fn window_closed(&mut self, window_id: WindowId) {
+        if self.over.map(|over| over.window_id) == Some(window_id) {
+            self.over = None;
+            self.accepted = false;
+        }
+    }
+}
+
+/// Where the ghost is drawn for a pointer at `x`/`y`.
+pub fn ghost_rect(x: u32, y: u32) -> Rect {
+    Rect { x: x + GHOST_OFFSET, y: y + GHOST_OFFSET, width: GHOST_SIZE, height: GHOST_SIZE }
+}
+
+const GHOST_FILL: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+const GHOST_LINES: [u8; 4] = [0xA0, 0xA0, 0xA0, 0xFF];
+const GHOST_BORDER: [u8; 4] = [0x60, 0x60, 0x60, 0xFF];
+const GHOST_BORDER_ACCEPTED: [u8; 4] = [0x20, 0xA0, 0x40, 0xFF];
+
+/// The ghost that follows the pointer: a page with lines on it, bordered
+/// green while the window under it accepts the drop. RGBA rows, opaque.
+pub fn render_ghost(accepted: bool) -> Vec<u8> {
+    let border = if accepted { GHOST_BORDER_ACCEPTED } else { GHOST_BORDER };
+    let mut pixels = Vec::with_capacity((GHOST_SIZE * GHOST_SIZE * 4) as usize);
+    for y in 0..GHOST_SIZE {
+        for x in 0..GHOST_SIZE {
+            let edge = x == 0 || y == 0 || x == GHOST_SIZE - 1 || y == GHOST_SIZE - 1;
+            let line = y % 3 == 0 && (3..GHOST_SIZE - 3).contains(&x) && (3..GHOST_SIZE - 2).contains(&y);
+            pixels.extend_from_slice(if edge { &border } else if line { &GHOST_LINES } else { &GHOST_FILL });
+        }
+    }
+    pixels
+}
@@ -0,0 +1,163 @@
+// vnode/display-compositor/src/hotkeys.rs
+
+//! Global shortcuts: key combinations the compositor takes before the
+//! focused window sees them.
+//!
+//! Two are built in: switching windows (Alt+Tab) and taking a screenshot
+//! (PrintScreen), rebound through the `compositor.hotkey.*` settings. The
+//! system UI claims others with `RegisterHotkey`. A combination has one
+//! owner at a time, and claiming a taken one fails with the owner's name. A
+//! client's combinations go when it unregisters them or its task exits.
+//!
+//! `Hotkeys` only decides who owns what; the compositor tracks the held
+//! modifiers, runs the built-ins and sends `HotkeyPressed`.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::keys::{self, Layout, KEY_PRINT_SCREEN, KEY_TAB, MOD_ALT, MOD_CTRL, MOD_META, MOD_SHIFT};
+use common::ui_protocol::{HotkeyAction, HotkeyInfo};
+
+/// Task names that may register hotkeys: the shell, which launches
+/// terminals, and the notifications service.
+pub const HOTKEY_CLIENTS: [&str; 2] = ["shell", "notifications"];
+/// Combinations one task may hold at once.
+pub const MAX_HOTKEYS_PER_TASK: usize = 16;
+/// Modifiers a combination is matched on. Caps Lock and AltGr are ignored.
+pub const HOTKEY_MODIFIERS: u8 = MOD_SHIFT | MOD_CTRL | MOD_ALT | MOD_META;
+/// Owner named for the built-ins.
+const COMPOSITOR: &str = "display-compositor";
+
+#[derive(Debug, Clone)]
+struct Binding {
+    modifiers: u8,
+    keycode: u16,
+    action: HotkeyAction,
+    owner: String,
+    task: u64, // 0 for the built-ins
+    events_chan: u32,
+}
+
+/// What a pressed combination does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    SwitchWindow,
+    Screenshot,
+    /// Send `HotkeyPressed { tag }` to `events_chan`.
+    Client { tag: u32, events_chan: u32 },
+}
+
+pub struct Hotkeys {
+    bindings: Vec<Binding>, // Built-ins first, then registrations in the order they came
+}
+
+impl Hotkeys {
+    /// The built-ins on their default combinations, nothing registered.
+    pub fn new() -> Self {
+        let builtin = |modifiers, keycode, action| Binding { modifiers, keycode, action, owner: COMPOSITOR.to_string(), task: 0, events_chan: 0 };
+        Self {
+            bindings: alloc::vec![
+                builtin(MOD_ALT, KEY_TAB, HotkeyAction::SwitchWindow),
+                builtin(0, KEY_PRINT_SCREEN, HotkeyAction::Screenshot),
+            ],
+        }
+    }
+
+    /// What `keycode` pressed with `modifiers` held does, if it is a hotkey.
+    pub fn find(&self, modifiers: u8, keycode: u16) -> Option<Hit> {
+        let binding = self.binding(modifiers, keycode)?;
+        Some(match binding.action {
+            HotkeyAction::SwitchWindow => Hit::SwitchWindow,
+            HotkeyAction::Screenshot => Hit::Screenshot,
+            HotkeyAction::Client { tag } => Hit::Client { tag, events_chan: binding.events_chan },
+        })
+    }
+
+    /// The combination a built-in is bound to.
+    pub fn builtin(&self, action: HotkeyAction) -> Option<(u8, u16)> {
+        self.bindings.iter().find(|binding| binding.action == action && binding.task == 0).map(|binding| (binding.modifiers, binding.keycode))
+    }
+
+    /// Gives `task` the combination. Registering a combination the task
+    /// already holds again updates its tag and channel.
+    pub fn register(&mut self, task: u64, owner: &str, modifiers: u8, keycode: u16, tag: u32, events_chan: u32) -> Result<(), String> {
+        let modifiers = modifiers & HOTKEY_MODIFIERS;
+        check_combination(modifiers, keycode)?;
+        if let Some(binding) = self.bindings.iter_mut().find(|b| b.modifiers == modifiers && b.keycode == keycode) {
+            if binding.task != task {
+                return Err(format!("{} is taken by {}.", keys::combo_name(modifiers, keycode), binding.owner));
+            }
+            binding.action = HotkeyAction::Client { tag };
+            binding.events_chan = events_chan;
+            return Ok(());
+        }
+        if self.bindings.iter().filter(|b| b.task == task).count() >= MAX_HOTKEYS_PER_TASK {
+            return Err(format!("{} already holds {} hotkeys.", owner, MAX_HOTKEYS_PER_TASK));
+        }
+        self.bindings.push(Binding { modifiers, keycode, action: HotkeyAction::Client { tag }, owner: owner.to_string(), task, events_chan });
+        Ok(())
+    }
+
+    /// Releases a combination `task` holds. Returns false if it held none like it.
+    pub fn unregister(&mut self, task: u64, modifiers: u8, keycode: u16) -> bool {
+        let modifiers = modifiers & HOTKEY_MODIFIERS;
+        let before = self.bindings.len();
+        self.bindings.retain(|b| !(b.task == task && task != 0 && b.modifiers == modifiers && b.keycode == keycode));
+        self.bindings.len() != before
+    }
+
+    /// Moves a built-in to another combination. Fails if a client holds it.
+    /// Returns whether the binding changed.
+    pub fn rebind(&mut self, action: HotkeyAction, modifiers: u8, keycode: u16) -> Result<bool, String> {
+        let modifiers = modifiers & HOTKEY_MODIFIERS;
+        check_combination(modifiers, keycode)?;
+        if let Some(binding) = self.bindings.iter().find(|b| b.modifiers == modifiers && b.keycode == keycode) {
+            if binding.action == action && binding.task == 0 {
+                return Ok(false);
+            }
+            return Err(format!("{} is taken by {}.", keys::combo_name(modifiers, keycode), binding.owner));
+        }
+        match self.bindings.iter_mut().find(|b| b.action == action && b.task == 0) {
+            Some(binding) => {
+                binding.modifiers = modifiers;
+                binding.keycode = keycode;
+                Ok(true)
+            },
+            None => Err("Not a built-in hotkey.".to_string()),
+        }
+    }
+
+    /// Drops the combinations of tasks that have exited. Returns how many were dropped.
+    pub fn expire(&mut self, alive: impl Fn(u64) -> bool) -> usize {
+        let before = self.bindings.len();
+        self.bindings.retain(|b| b.task == 0 || alive(b.task));
+        before - self.bindings.len()
+    }
+
+    pub fn list(&self) -> Vec<HotkeyInfo> {
+        self.bindings.iter()
+            .map(|b| HotkeyInfo { modifiers: b.modifiers, keycode: b.keycode, owner: b.owner.clone(), action: b.action })
+            .collect()
+    }
+
+    fn binding(&self, modifiers: u8, keycode: u16) -> Option<&Binding> {
+        let modifiers = modifiers & HOTKEY_MODIFIERS;
+        self.bindings.iter().find(|b| b.modifiers == modifiers && b.keycode == keycode)
+    }
+}
+
+/// Refuses combinations that would take typing away from windows: a
+/// modifier on its own, and a key that types something without Ctrl, Alt or
+/// Meta. Keys that don't type, like F1 or PrintScreen, may stand alone.
+fn check_combination(modifiers: u8, keycode: u16) -> Result<(), String> {
+    if keys::is_modifier(keycode) || keys::is_lock(keycode) {
+        return Err("A modifier or lock key can't be a hotkey on its own.".to_string());
+    }
+    if modifiers & (MOD_CTRL | MOD_ALT | MOD_META) == 0 && Layout::Us.translate(keycode, modifiers).is_some() {
+        return Err(format!("{} types text; add Ctrl, Alt or Meta.", keys::combo_name(modifiers, keycode)));
+    }
+    Ok(())
+}
@@ -10,10 +10,61 @@ use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::vec;
 
+use common::ipc::event_ipc::{Event, EventBusRequest, EventBusResponse};
+use common::ipc::session_ipc;
+use common::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use common::ipc::vfs_stream::VfsStreams;
 use common::ipc::vnode::VNodeChannel;
-use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ui_protocol::{UiRequest, UiResponse, WindowInfo, MouseEventType, KeyEventType};
+use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME, SYS_FB_ACQUIRE, SYS_INPUT_READ, E_ERROR, E_ACC_DENIED, E_UNKNOWN_SYSCALL};
+use common::abi::{IpcCreds, MouseReport, INPUT_EVENT_LEN};
+use common::keys::{self, KEY_ESCAPE, KEY_LEFT_CTRL};
+use common::ui_protocol::{UiRequest, UiResponse, UiEvent, WindowInfo, MouseEventType, KeyEventType, CompositorStats, WindowLatency, CursorShape, DragData, DisplayInfo, DisplayMode, HotkeyAction, ScreenshotSaved, BUTTON_LEFT, MAX_INLINE_DRAG_BYTES, SCREENSHOT_DIR, SCREENSHOT_SAVED_TOPIC};
+use common::ui::image;
+use common::ui::latency::{self, InputTiming, PipelineLatency, Stage, BUCKET_BOUNDS};
+use common::ui::scale::{UiScale, SCALE_SETTING};
+use common::metrics::{Counter, Gauge, Histogram, Registry, DURATION_BOUNDS};
+use common::startup::{self, SELF_CHANNEL};
+use common::ids::{Ticks, WindowId};
+use common::time;
+
+mod background;
+mod cursor;
+mod decorations;
+mod dnd;
+mod hotkeys;
+mod notifications;
+mod output;
+mod surface;
+mod switcher;
+
+use background::{Background, Wallpaper};
+use cursor::{Cursor, Rect};
+use decorations::{title_bar_height, Frame, FrameHit};
+use hotkeys::{Hit, Hotkeys, HOTKEY_CLIENTS};
+use notifications::Bubble;
+use output::{Output, Outputs, Target};
+use surface::Surface;
+use switcher::Switcher;
+
+// Size of the boot framebuffer output, assumed until the GPU driver reports the real display mode.
+const SCREEN_WIDTH: u32 = 1024;
+const SCREEN_HEIGHT: u32 = 768;
+// Time a client has to answer CloseRequested before the window is force-closed, until
+// `CLOSE_TIMEOUT_SETTING` says otherwise.
+const DEFAULT_CLOSE_TIMEOUT: Ticks = Ticks::from_secs(3);
+// Mouse reports read from the kernel per pass of the event loop.
+const INPUT_BATCH: usize = 32;
+// Settings applied at startup and on their change events, with `SCALE_SETTING`.
+const BACKGROUND_COLOR_SETTING: &str = "compositor.background_color";
+const WALLPAPER_SETTING: &str = "compositor.wallpaper";
+const WALLPAPER_FIT_SETTING: &str = "compositor.wallpaper_fit";
+const DISPLAY_MODE_SETTING: &str = "compositor.display_mode";
+const SWITCH_WINDOW_HOTKEY_SETTING: &str = "compositor.hotkey.switch_window";
+const SCREENSHOT_HOTKEY_SETTING: &str = "compositor.hotkey.screenshot";
+const CLOSE_TIMEOUT_SETTING: &str = "compositor.close_timeout_secs";
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -29,69 +80,1012 @@ fn log(msg: &str) {
 }
 
 struct WindowSurface {
-    id: u32,
+    id: WindowId,
     title: String,
     x: u32,
     y: u32,
-    width: u32,
-    height: u32,
-    // In a real system, this would point to a shared memory region for the framebuffer
-    // For this stub, we'll just acknowledge the pixels.
+    surface: Surface, // Client area, and its size; the title bar is drawn above it
+    output_id: u32, // Output the window was placed on; it may extend onto others
+    owner_chan: u32, // Channel UiEvents for this window are sent on
+    close_requested_at: Option<u64>, // Tick at which CloseRequested was sent, if pending
+    latency: PipelineLatency, // Input latency for events delivered to this window
+    cursor: CursorShape, // Shown while the pointer is over the client area
+}
+
+impl WindowSurface {
+    fn frame(&self) -> Frame {
+        Frame { x: self.x, y: self.y, width: self.surface.width(), height: self.surface.height() }
+    }
+}
+
+/// A title bar drag in progress: the pointer offset from the window origin when grabbed.
+struct Drag {
+    window_id: WindowId,
+    grab_dx: u32,
+    grab_dy: u32,
+}
+
+/// Compositor metrics. The latency histograms aggregate every window, like `latency`.
+struct CompositorMetrics {
+    registry: Registry,
+    stage_latency: [Histogram; 4], // Indexed by `Stage`
+    frame_time: Histogram,
+    windows: Gauge,
+    windows_created: Counter,
+    windows_force_closed: Counter,
+    mouse_reports: Counter,
+}
+
+impl CompositorMetrics {
+    fn new() -> Self {
+        let mut registry = Registry::new("display-compositor");
+        let stage_latency = Stage::ALL.map(|stage| registry.histogram_with(
+            "compositor_input_latency_nanoseconds", "Input pipeline stage durations in nanoseconds.", &BUCKET_BOUNDS, &[("stage", stage.name())]));
+        let frame_time = registry.histogram("compositor_frame_nanoseconds", "Time to composite a frame with damage, in nanoseconds.", &DURATION_BOUNDS);
+        let windows = registry.gauge("compositor_windows", "Open windows.");
+        let windows_created = registry.counter("compositor_windows_created_total", "Windows created since startup.");
+        let windows_force_closed = registry.counter("compositor_windows_force_closed_total", "Windows closed after their owner ignored CloseRequested.");
+        let mouse_reports = registry.counter("compositor_mouse_reports_total", "Mouse reports read from the kernel.");
+        Self { registry, stage_latency, frame_time, windows, windows_created, windows_force_closed, mouse_reports }
+    }
+
+    fn observe(&self, stage: Stage, nanos: u64) {
+        self.stage_latency[stage as usize].observe(nanos);
+    }
 }
 
 struct DisplayCompositor {
     client_chan: VNodeChannel, // Channel for communication with client UI V-Nodes
-    next_window_id: u32,
-    windows: BTreeMap<u32, WindowSurface>,
+    vfs_chan: VNodeChannel, // Wallpapers are read and screenshots written through it
+    event_bus_chan: VNodeChannel, // Where screenshot.saved is published
+    bus_events_chan: VNodeChannel, // settings.compositor.* and settings.ui.scale changes
+    sender: Option<IpcCreds>, // Who sent the request being handled
+    next_window_id: WindowId,
+    windows: BTreeMap<WindowId, WindowSurface>,
+    z_order: Vec<WindowId>, // Window IDs, bottom to top
+    focused: Option<WindowId>,
+    drag: Option<Drag>,
+    dnd: Option<dnd::Drag>, // Drag and drop between windows, see `dnd`
+    ghost: Option<Rect>, // Where the drag's ghost is drawn
+    pressed_in: Option<WindowId>, // Window the left button went down in, while it is held
+    close_timeout_ticks: u64,
+    now: u64, // Timer ticks as of the last SYS_TIME call
+    outputs: Outputs,
+    latency: PipelineLatency, // Input latency across all windows
+    cursor: Cursor,
+    cursor_sprite: Vec<u8>, // RGBA, rendered again when the shape changes
+    input_enabled: bool, // Cleared if the kernel refuses SYS_INPUT_READ
+    metrics: CompositorMetrics,
+    outbox: Vec<(u32, UiEvent)>, // Events raised while handling a request, sent after its response
+    notifications: notifications::Stack, // Bubbles above every window, on the primary output
+    background: Background, // Under every window, on every output
+    hotkeys: Hotkeys,
+    held_modifiers: u8, // Modifier keys held, bit n for keycode KEY_LEFT_CTRL + n
+    swallowed: Vec<u16>, // Keys whose key-down was a hotkey; their key-up goes nowhere either
+    switcher: Option<Switcher>, // Shown while the switch-window hotkey's modifiers are held
 }
 
 impl DisplayCompositor {
-    fn new(client_chan_id: u32) -> Self {
+    fn new(client_chan_id: u32, vfs_chan_id: u32, settings_chan_id: u32, event_bus_chan_id: u32, bus_events_chan_id: u32) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let bus_events_chan = VNodeChannel::new(bus_events_chan_id);
         log("Display Compositor: Initializing...");
 
-        Self {
+        // Subscribe before reading the settings, so a change in between isn't missed.
+        for topic_prefix in ["settings.compositor.".to_string(), format!("settings.{}", SCALE_SETTING)] {
+            let subscribe = EventBusRequest::Subscribe { topic_prefix, reply_chan: bus_events_chan.id };
+            if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+                log("Display Compositor: Failed to subscribe to settings changes; display settings apply after a restart.");
+            }
+        }
+        let settings: Vec<(&str, SettingValue)> = [BACKGROUND_COLOR_SETTING, WALLPAPER_FIT_SETTING, WALLPAPER_SETTING, DISPLAY_MODE_SETTING, SCALE_SETTING, SWITCH_WINDOW_HOTKEY_SETTING, SCREENSHOT_HOTKEY_SETTING, CLOSE_TIMEOUT_SETTING]
+            .into_iter()
+            .filter_map(|key| match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.to_string() }) {
+                Ok(SettingsResponse::Value { value, .. }) => Some((key, value)),
+                _ => None,
+            })
+            .collect();
+
+        // Take the framebuffer over from the kernel text console.
+        let res = unsafe { syscall3(SYS_FB_ACQUIRE, 0, 0, 0) };
+        let target = if res == E_ERROR || res == E_ACC_DENIED {
+            log("Display Compositor: Failed to acquire framebuffer, kernel console stays active.");
+            Target::Offscreen
+        } else {
+            log(&format!("Display Compositor: Acquired framebuffer at 0x{:x}.", res));
+            Target::Framebuffer(res)
+        };
+        let primary = Output::new(0, Rect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT }, target);
+
+        let mut compositor = Self {
             client_chan,
-            next_window_id: 1,
+            vfs_chan: VNodeChannel::new(vfs_chan_id),
+            event_bus_chan,
+            bus_events_chan,
+            sender: None,
+            next_window_id: WindowId::from_raw(1),
             windows: BTreeMap::new(),
+            z_order: Vec::new(),
+            focused: None,
+            drag: None,
+            dnd: None,
+            ghost: None,
+            pressed_in: None,
+            close_timeout_ticks: DEFAULT_CLOSE_TIMEOUT.raw(),
+            now: 0,
+            input_enabled: target != Target::Offscreen, // Input goes to the display owner
+            outputs: Outputs::new(primary),
+            latency: PipelineLatency::default(),
+            cursor: Cursor::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            cursor_sprite: cursor::render_sprite(CursorShape::Arrow),
+            metrics: CompositorMetrics::new(),
+            outbox: Vec::new(),
+            notifications: notifications::Stack::new(),
+            background: Background::new(),
+            hotkeys: Hotkeys::new(),
+            held_modifiers: 0,
+            swallowed: Vec::new(),
+            switcher: None,
+        };
+        for (key, value) in settings {
+            compositor.apply_setting(key, value);
         }
+        compositor
     }
 
-    fn handle_request(&mut self, request: UiRequest) -> UiResponse {
-        match request {
-            UiRequest::CreateWindow { title, width, height } => {
-                let id = self.next_window_id;
-                self.next_window_id += 1;
+    /// Redraws the damaged area of each output: background, then decorations
+    /// and client surfaces bottom to top, clipped to the output. Outputs
+    /// without damage are left alone. Notification bubbles go on top, then
+    /// the window switcher and a drag's ghost. Runs once per pass of the event loop.
+    fn composite(&mut self) {
+        let start = time::monotonic_nanos();
+        let mut drawn = false;
+        let Self { outputs, windows, z_order, focused, notifications, dnd: drag_and_drop, ghost, metrics, background, switcher, .. } = self;
+        let bubbles = notifications.layout(outputs.primary().rect());
+        let switcher = switcher.as_ref().map(|switcher| (switcher, switcher.rect(outputs.primary().rect())));
+        let ghost = drag_and_drop.as_ref().zip(*ghost).map(|(drag, area)| (area, dnd::render_ghost(drag.accepted())));
+        for output in outputs.iter_mut() {
+            let damage = match output.take_damage() {
+                Some(damage) => damage,
+                None => continue,
+            };
+            drawn = true;
+            background.paint(output, damage);
+            for id in z_order.iter() {
+                let window = match windows.get(id) {
+                    Some(window) => window,
+                    None => continue,
+                };
+                let frame = window.frame();
+                if frame.rect().intersection(&damage).is_none() {
+                    continue;
+                }
+                let surface = &window.surface;
+                let title_bar = decorations::render_title_bar(&window.title, surface.width(), *focused == Some(*id));
+                let row_len = (surface.width() * 4) as usize;
+                let bar_height = title_bar_height();
+                let bar = Rect { x: frame.x, y: frame.y, width: frame.width, height: bar_height };
+                output.draw(bar, damage, |row| &title_bar[row as usize * row_len..(row as usize + 1) * row_len]);
+                let client = Rect { x: frame.x, y: frame.y + bar_height, width: frame.width, height: frame.height };
+                output.draw(client, damage, |row| surface.row(row));
+            }
+            for (bubble, area) in bubbles.iter() {
+                output.draw(*area, damage, |row| bubble.row(row));
+            }
+            if let Some((switcher, area)) = &switcher {
+                output.draw(*area, damage, |row| switcher.row(row));
+            }
+            // A drag's ghost follows the pointer above everything but the cursor.
+            if let Some((area, pixels)) = &ghost {
+                let row_len = (dnd::GHOST_SIZE * 4) as usize;
+                output.draw(*area, damage, |row| &pixels[row as usize * row_len..(row as usize + 1) * row_len]);
+            }
+            // In a real system, a framebuffer output would now copy the damaged rows of
+            // its back buffer to the scanout, and the cursor would go on top of
+            // everything: a blit of `cursor_sprite` at `cursor.rect()`, skipping its
+            // transparent pixels. Offscreen outputs keep the back buffer only.
+            log(&alloc::format!("Display Compositor: Composited {}x{} at ({},{}) of output {}.", damage.width, damage.height, damage.x, damage.y, output.id));
+        }
+        if drawn {
+            metrics.frame_time.observe_since(start);
+        }
+    }
 
-                let new_window = WindowSurface { id, title: title.clone(), x: 0, y: 0, width, height };
-                self.windows.insert(id, new_window);
+    /// Marks everything a window draws for recompositing, on the outputs it covers.
+    fn damage_window(&mut self, window_id: WindowId) {
+        if let Some(window) = self.windows.get(&window_id) {
+            self.outputs.damage(window.frame().rect());
+        }
+    }
 
-                log(&alloc::format!("Display Compositor: Created window '{}' with ID: {}.", title, id));
-                UiResponse::Success { window_id: Some(id) }
+    /// Shows, replaces or hides a notification bubble. The others may move, so
+    /// the whole stack is damaged before and after.
+    fn update_notifications(&mut self, change: impl FnOnce(&mut notifications::Stack)) {
+        let area = self.outputs.primary().rect();
+        if let Some(before) = self.notifications.bounds(area) {
+            self.outputs.damage(before);
+        }
+        change(&mut self.notifications);
+        if let Some(after) = self.notifications.bounds(area) {
+            self.outputs.damage(after);
+        }
+    }
+
+    /// The output a window belongs to: the one under the middle of its title bar.
+    fn output_for(&self, frame: &Frame) -> u32 {
+        self.outputs.at(frame.x + frame.width / 2, frame.y + title_bar_height() / 2).id
+    }
+
+    /// The area a window's size and position are clamped to: its output.
+    fn output_area(&self, output_id: u32) -> Rect {
+        self.outputs.get(output_id).unwrap_or_else(|| self.outputs.primary()).rect()
+    }
+
+    /// Moves the cursor sprite. Only the two cursor rectangles are treated as
+    /// damage, so a pointer move doesn't recomposite the whole screen.
+    fn redraw_cursor(&self, old: Rect) {
+        let new = self.cursor.rect();
+        if old == new {
+            return;
+        }
+        // In a real system, this would recomposite `old` from the windows under it
+        // (bottom to top, restoring what the sprite covered) and then blit
+        // `cursor_sprite` at `new`, skipping its transparent pixels.
+        let _ = (old, new, &self.cursor_sprite);
+    }
+
+    /// The shape for the pointer where it is now: the window's own over its
+    /// client area, a hand over what can be clicked, the arrow elsewhere and
+    /// while anything is being dragged.
+    fn shape_at_cursor(&self) -> CursorShape {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if self.drag.is_some() || self.dnd.is_some() {
+            return CursorShape::Arrow;
+        }
+        if self.notifications.hit(self.outputs.primary().rect(), x, y).is_some() {
+            return CursorShape::Hand;
+        }
+        match self.window_at(x, y) {
+            Some((window_id, FrameHit::Client { .. })) => self.windows.get(&window_id).map_or(CursorShape::Arrow, |w| w.cursor),
+            Some((_, FrameHit::CloseButton)) => CursorShape::Hand,
+            _ => CursorShape::Arrow,
+        }
+    }
+
+    /// Switches the sprite if the shape under the pointer changed. Callers
+    /// redraw the cursor afterwards, as for a move.
+    fn update_cursor_shape(&mut self) {
+        let shape = self.shape_at_cursor();
+        if self.cursor.set_shape(shape) {
+            self.cursor_sprite = cursor::render_sprite(shape);
+        }
+    }
+
+    /// Queues the events a drag raised for the windows they are for. Windows
+    /// closed since are skipped.
+    fn queue_drag_events(&mut self, events: Vec<(WindowId, UiEvent)>) {
+        for (window_id, event) in events {
+            if let Some(owner_chan) = self.windows.get(&window_id).map(|w| w.owner_chan) {
+                self.outbox.push((owner_chan, event));
+            }
+        }
+    }
+
+    /// The window client area under a screen point, for a drag. Bubbles hide
+    /// what is below them.
+    fn drop_target_at(&self, x: u32, y: u32) -> Option<dnd::Over> {
+        if self.notifications.hit(self.outputs.primary().rect(), x, y).is_some() {
+            return None;
+        }
+        match self.window_at(x, y) {
+            Some((window_id, FrameHit::Client { x, y })) => Some(dnd::Over { window_id, x, y }),
+            _ => None,
+        }
+    }
+
+    /// Moves the drag's ghost to the pointer at `x`/`y`, damaging where it was
+    /// and where it goes.
+    fn move_ghost(&mut self, x: u32, y: u32) {
+        let area = dnd::ghost_rect(x, y);
+        if let Some(previous) = self.ghost.replace(area) {
+            self.outputs.damage(previous);
+        }
+        self.outputs.damage(area);
+    }
+
+    /// Ends the drag in progress, if any, with a drop (`release`) or without.
+    fn end_drag_and_drop(&mut self, release: bool) {
+        if let Some(drag) = self.dnd.take() {
+            if let Some(ghost) = self.ghost.take() {
+                self.outputs.damage(ghost);
+            }
+            let events = if release { drag.release() } else { drag.cancel() };
+            self.queue_drag_events(events);
+        }
+    }
+
+    /// Reads the mouse reports the kernel has queued and feeds them through the
+    /// same path as input bridge events.
+    fn poll_input(&mut self) {
+        if !self.input_enabled {
+            return;
+        }
+        let mut buffer = [0u8; INPUT_EVENT_LEN * INPUT_BATCH];
+        let res = unsafe { syscall3(SYS_INPUT_READ, buffer.as_mut_ptr() as u64, buffer.len() as u64, 0) };
+        if res == E_ACC_DENIED || res == E_UNKNOWN_SYSCALL {
+            log("Display Compositor: Kernel input is unavailable, relying on the input bridge.");
+            self.input_enabled = false;
+            return;
+        }
+        let old = self.cursor.rect();
+        for record in buffer.chunks_exact(INPUT_EVENT_LEN).take(res as usize) {
+            if let Some(report) = MouseReport::from_bytes(record) {
+                self.metrics.mouse_reports.inc();
+                for event in self.cursor.apply(&report) {
+                    self.handle_pointer(event.x, event.y, event.button, event.event_type, report.captured_at);
+                }
+            }
+        }
+        self.redraw_cursor(old);
+        self.flush_outbox();
+    }
+
+    /// Sends the events raised by the request just answered. They go out after
+    /// the response, so a client waiting in `send_and_recv` gets its answer first.
+    fn flush_outbox(&mut self) {
+        for (owner_chan, event) in core::mem::take(&mut self.outbox) {
+            self.send_event(owner_chan, event);
+        }
+    }
+
+    fn send_event(&self, owner_chan: u32, event: UiEvent) {
+        let mut chan = VNodeChannel::new(owner_chan);
+        if chan.send(&event).is_err() {
+            log(&alloc::format!("Display Compositor: Failed to deliver {:?} on channel {}.", event, owner_chan));
+        }
+    }
+
+    /// Brings a window to the top of the stack and gives it keyboard focus.
+    fn raise(&mut self, window_id: WindowId) {
+        // The previously focused title bar loses its highlight.
+        if let Some(previous) = self.focused {
+            self.damage_window(previous);
+        }
+        self.z_order.retain(|id| *id != window_id);
+        self.z_order.push(window_id);
+        self.focused = Some(window_id);
+        self.damage_window(window_id);
+    }
+
+    fn remove_window(&mut self, window_id: WindowId) -> bool {
+        self.damage_window(window_id);
+        if self.windows.remove(&window_id).is_none() {
+            return false;
+        }
+        self.z_order.retain(|id| *id != window_id);
+        self.metrics.windows.set(self.windows.len() as i64);
+        if self.focused == Some(window_id) {
+            self.focused = self.z_order.last().copied();
+        }
+        if self.drag.as_ref().map_or(false, |d| d.window_id == window_id) {
+            self.drag = None;
+        }
+        if self.pressed_in == Some(window_id) {
+            self.pressed_in = None;
+        }
+        if let Some(switcher) = &mut self.switcher {
+            let area = switcher.rect(self.outputs.primary().rect());
+            self.outputs.damage(area);
+            if !switcher.window_closed(window_id) {
+                self.switcher = None;
+            }
+        }
+        // A drag whose source is gone is cancelled; a closed target just drops out.
+        if self.dnd.as_ref().map_or(false, |d| d.source == window_id) {
+            self.end_drag_and_drop(false);
+        } else if let Some(drag) = &mut self.dnd {
+            drag.window_closed(window_id);
+        }
+        if let Some(focused) = self.focused {
+            self.damage_window(focused);
+        }
+        true
+    }
+
+    /// Resizes a window's client area, clamped to the minimum size and the
+    /// window's output, and tells the owner the size it got. The origin moves
+    /// if the window would otherwise hang off the output. Used for
+    /// `ResizeWindow` and for resizes the compositor starts itself. Returns
+    /// the new size.
+    fn resize_window(&mut self, window_id: WindowId, width: u32, height: u32) -> Option<(u32, u32)> {
+        let area = self.output_area(self.windows.get(&window_id)?.output_id);
+        let (width, height) = surface::clamp_size(width, height, area.width, area.height);
+        let window = self.windows.get(&window_id)?;
+        if (width, height) == (window.surface.width(), window.surface.height()) {
+            return Some((width, height));
+        }
+        self.damage_window(window_id);
+        let window = self.windows.get_mut(&window_id)?;
+        // Compositing only runs between requests, so it never sees the old
+        // buffer with the new size or the other way round.
+        window.surface.resize(width, height);
+        let (x, y) = decorations::clamp_origin(window.x as i64, window.y as i64, width, area);
+        window.x = x;
+        window.y = y;
+        let owner_chan = window.owner_chan;
+        log(&alloc::format!("Display Compositor: Resized window {} to {}x{}.", window_id, width, height));
+        self.outbox.push((owner_chan, UiEvent::Resized { window_id, width, height }));
+        self.damage_window(window_id);
+        Some((width, height))
+    }
+
+    /// Moves a window onto an output, at the same offset from the output's
+    /// origin as it had on `from`, clamped to fit. A window too large for the
+    /// output is shrunk, and its owner told.
+    fn move_to_output(&mut self, window_id: WindowId, from: Rect, output_id: u32) {
+        let to = self.output_area(output_id);
+        self.damage_window(window_id);
+        let (width, height) = match self.windows.get_mut(&window_id) {
+            Some(window) => {
+                let x = to.x as i64 + window.x as i64 - from.x as i64;
+                let y = to.y as i64 + window.y as i64 - from.y as i64;
+                let (x, y) = decorations::clamp_origin(x, y, window.surface.width(), to);
+                window.x = x;
+                window.y = y;
+                window.output_id = output_id;
+                (window.surface.width(), window.surface.height())
             },
-            UiRequest::DrawToSurface { window_id, x, y, width, height, pixels } => {
+            None => return,
+        };
+        self.damage_window(window_id);
+        self.resize_window(window_id, width, height);
+        log(&alloc::format!("Display Compositor: Moved window {} to output {}.", window_id, output_id));
+    }
+
+    /// Removes an output. Its windows move to the primary output rather than
+    /// disappearing, and the cursor is pulled back onto what remains.
+    fn remove_output(&mut self, output_id: u32) -> bool {
+        let removed = match self.outputs.remove(output_id) {
+            Some(removed) => removed,
+            None => return false,
+        };
+        let primary = self.outputs.primary().id;
+        let stranded: Vec<WindowId> = self.windows.values().filter(|w| w.output_id == output_id).map(|w| w.id).collect();
+        for window_id in stranded {
+            self.move_to_output(window_id, removed.rect(), primary);
+        }
+        self.background.forget_output(output_id);
+        let bounds = self.outputs.bounds();
+        self.cursor.set_bounds(bounds.width, bounds.height);
+        log(&alloc::format!("Display Compositor: Removed output {}.", output_id));
+        true
+    }
+
+    /// Topmost window under a screen point, with where the point hit it.
+    fn window_at(&self, x: u32, y: u32) -> Option<(WindowId, FrameHit)> {
+        let frames = self.z_order.iter().filter_map(|id| Some((*id, self.windows.get(id)?.frame())));
+        decorations::topmost_hit(frames, x, y)
+    }
+
+    /// Asks the owner to close a window; it is force-closed if the owner doesn't
+    /// respond within the close timeout (see `check_close_timeouts`).
+    fn request_close(&mut self, window_id: WindowId) {
+        let now = self.now;
+        let owner_chan = match self.windows.get_mut(&window_id) {
+            Some(window) => {
+                if window.close_requested_at.is_none() {
+                    window.close_requested_at = Some(now);
+                }
+                window.owner_chan
+            },
+            None => return,
+        };
+        log(&alloc::format!("Display Compositor: Close requested for window {}.", window_id));
+        self.send_event(owner_chan, UiEvent::CloseRequested { window_id });
+    }
+
+    fn check_close_timeouts(&mut self) {
+        let expired: Vec<WindowId> = self.windows.values()
+            .filter(|w| decorations::close_overdue(w.close_requested_at, self.now, self.close_timeout_ticks))
+            .map(|w| w.id)
+            .collect();
+        for window_id in expired {
+            log(&alloc::format!("Display Compositor: Window {} did not close in time, force-closing.", window_id));
+            self.remove_window(window_id);
+            self.metrics.windows_force_closed.inc();
+        }
+        self.flush_outbox();
+    }
+
+    /// Stamps an input event as dispatched now and records its capture->dispatch time.
+    fn dispatch_timing(&mut self, window_id: WindowId, captured_at: u64) -> InputTiming {
+        let now = time::monotonic_nanos();
+        let timing = InputTiming { captured_at, dispatched_at: now, ..InputTiming::default() };
+        if captured_at != 0 {
+            let delta = now.saturating_sub(captured_at);
+            self.latency.record(Stage::CaptureToDispatch, delta);
+            self.metrics.observe(Stage::CaptureToDispatch, delta);
+            if let Some(window) = self.windows.get_mut(&window_id) {
+                window.latency.record(Stage::CaptureToDispatch, delta);
+            }
+        }
+        timing
+    }
+
+    /// The `MOD_*` bits of the modifier keys held.
+    fn modifiers(&self) -> u8 {
+        (0..8u16)
+            .filter(|i| self.held_modifiers & (1 << i) != 0)
+            .fold(0, |bits, i| bits | keys::modifier_bit(KEY_LEFT_CTRL + i))
+    }
+
+    /// Takes keyboard input that is for the compositor rather than the
+    /// focused window: hotkeys, with their key-ups and repeats, and every key
+    /// while the window switcher is open. Modifier keys are tracked here but
+    /// always passed on. Returns true if the event was taken.
+    fn intercept_key(&mut self, keycode: u16, event_type: KeyEventType) -> bool {
+        let (switch_modifiers, switch_key) = self.hotkeys.builtin(HotkeyAction::SwitchWindow).unwrap_or_default();
+        if keys::is_modifier(keycode) {
+            let bit = 1 << (keycode - KEY_LEFT_CTRL);
+            match event_type {
+                KeyEventType::KeyDown => self.held_modifiers |= bit,
+                KeyEventType::KeyUp => self.held_modifiers &= !bit,
+            }
+            // Letting go of the switch-window modifiers picks the selected window.
+            if self.switcher.is_some() && self.modifiers() & switch_modifiers != switch_modifiers {
+                self.close_switcher(true);
+            }
+            return false;
+        }
+        if event_type == KeyEventType::KeyUp {
+            let before = self.swallowed.len();
+            self.swallowed.retain(|swallowed| *swallowed != keycode);
+            return self.swallowed.len() != before || self.switcher.is_some();
+        }
+        if self.switcher.is_some() {
+            match keycode {
+                KEY_ESCAPE => self.close_switcher(false),
+                _ if keycode == switch_key => self.advance_switcher(),
+                _ => {},
+            }
+            return true;
+        }
+        let Some(hit) = self.hotkeys.find(self.modifiers(), keycode) else {
+            return false;
+        };
+        // A key-down for a key that is already down is a repeat.
+        let repeat = self.swallowed.contains(&keycode);
+        if !repeat {
+            self.swallowed.push(keycode);
+        }
+        match hit {
+            Hit::SwitchWindow => self.open_switcher(),
+            Hit::Screenshot if !repeat => self.take_screenshot(),
+            Hit::Screenshot => {},
+            Hit::Client { tag, events_chan } => self.outbox.push((events_chan, UiEvent::HotkeyPressed { tag })),
+        }
+        true
+    }
+
+    /// Opens the window switcher on the windows, top of the stack first. A
+    /// switch-window hotkey without modifiers has nothing to hold, so there
+    /// the press switches straight to the window below the top.
+    fn open_switcher(&mut self) {
+        let windows = self.z_order.iter().rev().filter_map(|id| self.windows.get(id)).map(|w| (w.id, w.title.clone())).collect();
+        let Some(switcher) = Switcher::new(windows) else {
+            return;
+        };
+        self.outputs.damage(switcher.rect(self.outputs.primary().rect()));
+        self.switcher = Some(switcher);
+        if self.hotkeys.builtin(HotkeyAction::SwitchWindow).map_or(true, |(modifiers, _)| modifiers == 0) {
+            self.close_switcher(true);
+        }
+    }
+
+    fn advance_switcher(&mut self) {
+        if let Some(switcher) = &mut self.switcher {
+            switcher.advance();
+            let area = switcher.rect(self.outputs.primary().rect());
+            self.outputs.damage(area);
+        }
+    }
+
+    /// Closes the window switcher, raising the selected window if `switch`.
+    fn close_switcher(&mut self, switch: bool) {
+        if let Some(switcher) = self.switcher.take() {
+            self.outputs.damage(switcher.rect(self.outputs.primary().rect()));
+            if switch {
+                self.raise(switcher.selected());
+            }
+        }
+    }
+
+    /// What an output shows now, without the cursor: its size and RGBA pixels.
+    fn capture(&mut self, output_id: u32) -> Option<(u32, u32, Vec<u8>)> {
+        // Bring the output up to date with everything handled so far.
+        self.composite();
+        let output = self.outputs.get(output_id)?;
+        Some((output.rect().width, output.rect().height, output.pixels().to_vec()))
+    }
+
+    /// Saves what the primary output shows to `SCREENSHOT_DIR` as a PPM and
+    /// announces it on the event bus, where the notifications service picks
+    /// it up.
+    fn take_screenshot(&mut self) {
+        let output_id = self.outputs.primary().id;
+        let Some((width, height, pixels)) = self.capture(output_id) else {
+            return;
+        };
+        let path = format!("{}/screenshot-{}.ppm", SCREENSHOT_DIR, time::now_secs());
+        if let Err(e) = self.write_screenshot(&path, image::encode_ppm(width, height, &pixels)) {
+            log(&format!("Display Compositor: Couldn't save a screenshot to {}: {}.", path, e));
+            return;
+        }
+        log(&format!("Display Compositor: Saved a {}x{} screenshot to {}.", width, height, path));
+        let payload = postcard::to_allocvec(&ScreenshotSaved { path, width, height }).unwrap_or_default();
+        let publish = EventBusRequest::Publish { topic: SCREENSHOT_SAVED_TOPIC.to_string(), payload };
+        if !matches!(self.event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&publish), Ok(EventBusResponse::Success(_))) {
+            log("Display Compositor: Failed to announce the screenshot; no notification is shown.");
+        }
+    }
+
+    /// Writes `data` to `path` in `SCREENSHOT_DIR`, replacing the file.
+    fn write_screenshot(&mut self, path: &str, data: Vec<u8>) -> Result<(), String> {
+        // Fails harmlessly once the directory exists.
+        let _ = self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::CreateDirectory { path: SCREENSHOT_DIR.to_string() });
+        let fd: Fd = match self.vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: path.to_string(), flags: 1 /* O_WRONLY | O_CREAT | O_TRUNC */ }) {
+            Ok(VfsResponse::Success(fd)) => fd as Fd,
+            Ok(VfsResponse::Error { message, .. }) => return Err(message),
+            _ => return Err("Unexpected response from VFS".to_string()),
+        };
+        let mut streams = VfsStreams::new(&mut self.vfs_chan);
+        let written = streams.open_write(fd, 0).and_then(|writer| {
+            streams.write(writer, data)?;
+            streams.finish(writer)
+        });
+        let _ = streams.request(&VfsRequest::Close { fd });
+        written.map(|_| ())
+    }
+
+    /// Routes raw pointer input: decorations are handled here, everything else is
+    /// forwarded to the owning client with client-relative coordinates. The
+    /// cursor takes the shape for wherever the pointer ends up.
+    fn handle_pointer(&mut self, x: u32, y: u32, button: u8, event_type: MouseEventType, captured_at: u64) {
+        self.route_pointer(x, y, button, event_type, captured_at);
+        self.update_cursor_shape();
+    }
+
+    fn route_pointer(&mut self, x: u32, y: u32, button: u8, event_type: MouseEventType, captured_at: u64) {
+        if event_type == MouseEventType::MouseUp && button == BUTTON_LEFT {
+            self.pressed_in = None;
+        }
+
+        // While a drag and drop is in progress, the pointer moves it and no
+        // window sees pointer input.
+        if self.dnd.is_some() {
+            match event_type {
+                MouseEventType::MouseMove => {
+                    let over = self.drop_target_at(x, y);
+                    if let Some(drag) = &mut self.dnd {
+                        let events = drag.moved(over);
+                        self.queue_drag_events(events);
+                    }
+                    self.move_ghost(x, y);
+                },
+                MouseEventType::MouseUp if button == BUTTON_LEFT => self.end_drag_and_drop(true),
+                _ => {},
+            }
+            return;
+        }
+
+        if let Some(drag) = &self.drag {
+            match event_type {
+                MouseEventType::MouseMove => {
+                    let window_id = drag.window_id;
+                    let (new_x, new_y) = (x as i64 - drag.grab_dx as i64, y as i64 - drag.grab_dy as i64);
+                    // While dragging, a window may cross onto any output.
+                    let bounds = self.outputs.bounds();
+                    self.damage_window(window_id);
+                    if let Some(window) = self.windows.get_mut(&window_id) {
+                        let (cx, cy) = decorations::clamp_origin(new_x, new_y, window.surface.width(), bounds);
+                        window.x = cx;
+                        window.y = cy;
+                    }
+                    self.damage_window(window_id);
+                    return;
+                },
+                MouseEventType::MouseUp => {
+                    // The window now belongs to the output it was dropped on.
+                    let window_id = drag.window_id;
+                    self.drag = None;
+                    if let Some(frame) = self.windows.get(&window_id).map(WindowSurface::frame) {
+                        let output_id = self.output_for(&frame);
+                        if let Some(window) = self.windows.get_mut(&window_id) {
+                            window.output_id = output_id;
+                        }
+                    }
+                    return;
+                },
+                _ => {},
+            }
+        }
+
+        // Bubbles are above every window. A click takes one down and tells its
+        // owner; other pointer input over a bubble goes nowhere.
+        if let Some((id, events_chan, action)) = self.notifications.hit(self.outputs.primary().rect(), x, y) {
+            if event_type == MouseEventType::MouseDown && button == BUTTON_LEFT {
+                self.update_notifications(|stack| { stack.hide(id); });
+                self.send_event(events_chan, UiEvent::NotificationClicked { id, action });
+            }
+            return;
+        }
+
+        let (window_id, hit) = match self.window_at(x, y) {
+            Some(found) => found,
+            None => return, // Desktop background
+        };
+        match (hit, event_type) {
+            (FrameHit::CloseButton, MouseEventType::MouseDown) if button == BUTTON_LEFT => self.request_close(window_id),
+            (FrameHit::TitleBar, MouseEventType::MouseDown) if button == BUTTON_LEFT => {
+                self.raise(window_id);
                 if let Some(window) = self.windows.get(&window_id) {
+                    self.drag = Some(Drag { window_id, grab_dx: x - window.x, grab_dy: y - window.y });
+                }
+            },
+            (FrameHit::Client { x: cx, y: cy }, _) => {
+                if event_type == MouseEventType::MouseDown && self.focused != Some(window_id) {
+                    self.raise(window_id);
+                }
+                if event_type == MouseEventType::MouseDown && button == BUTTON_LEFT {
+                    self.pressed_in = Some(window_id);
+                }
+                if let Some(owner_chan) = self.windows.get(&window_id).map(|w| w.owner_chan) {
+                    let timing = self.dispatch_timing(window_id, captured_at);
+                    self.send_event(owner_chan, UiEvent::Mouse { window_id, x: cx, y: cy, button, event_type, timing });
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Damages every output whole, for a change to what is under or around
+    /// every window. Damage is merged until the next composite, so however
+    /// many such changes a request makes, each output is redrawn once.
+    fn damage_desktop(&mut self) {
+        let bounds = self.outputs.bounds();
+        self.outputs.damage(bounds);
+    }
+
+    /// Shows the wallpaper at `path`, or none for an empty path. Returns
+    /// whether the background changed; the same path isn't read again.
+    fn load_wallpaper(&mut self, path: &str) -> Result<bool, String> {
+        if path.is_empty() {
+            return Ok(self.background.set_wallpaper(None));
+        }
+        if path == self.background.wallpaper_path() {
+            return Ok(false);
+        }
+        let wallpaper = Wallpaper::load(&mut self.vfs_chan, path)?;
+        log(&format!("Display Compositor: Loaded wallpaper {}.", path));
+        Ok(self.background.set_wallpaper(Some(wallpaper)))
+    }
+
+    /// Switches the primary output to `width x height`. The kernel hands over
+    /// the framebuffer in the mode the bootloader set and has no call to
+    /// reprogram it, so only the current mode succeeds. A display driver that
+    /// can switch modes resizes the primary output here and lists its modes in
+    /// `display_info`.
+    fn set_display_mode(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let area = self.outputs.primary().rect();
+        if (width, height) == (area.width, area.height) {
+            return Ok(());
+        }
+        Err(format!("The display can't switch modes; it stays at {}x{}.", area.width, area.height))
+    }
+
+    /// Draws decorations at `scale`. Title bars change height, so every window
+    /// is clamped again to keep its title bar on its output. Returns whether
+    /// the scale changed.
+    fn set_scale(&mut self, scale: UiScale) -> bool {
+        if decorations::scale() == scale {
+            return false;
+        }
+        decorations::set_scale(scale);
+        let areas: Vec<(WindowId, Rect)> = self.windows.values().map(|w| (w.id, self.output_area(w.output_id))).collect();
+        for (window_id, area) in areas {
+            if let Some(window) = self.windows.get_mut(&window_id) {
+                (window.x, window.y) = decorations::clamp_origin(window.x as i64, window.y as i64, window.surface.width(), area);
+            }
+        }
+        log(&format!("Display Compositor: UI scale is now {}x.", scale.name()));
+        true
+    }
+
+    fn display_info(&self) -> DisplayInfo {
+        let area = self.outputs.primary().rect();
+        DisplayInfo {
+            width: area.width,
+            height: area.height,
+            modes: vec![DisplayMode { width: area.width, height: area.height }],
+            mode_switching: false, // See `set_display_mode`
+            background_color: self.background.color_name().to_string(),
+            wallpaper: self.background.wallpaper_path().to_string(),
+            fit: self.background.fit(),
+            scale_percent: decorations::scale().percent(),
+        }
+    }
+
+    /// Applies a display setting, read at startup or from a change event.
+    /// Values that don't parse or can't be carried out are logged and leave
+    /// the display as it is.
+    fn apply_setting(&mut self, key: &str, value: SettingValue) {
+        if key == CLOSE_TIMEOUT_SETTING {
+            match value {
+                SettingValue::Int(secs) if secs > 0 => self.close_timeout_ticks = Ticks::from_secs(secs as u64).raw(),
+                other => log(&format!("Display Compositor: Ignoring {} {:?}, not a number of seconds.", key, other)),
+            }
+            return;
+        }
+        let SettingValue::Str(value) = value else {
+            return;
+        };
+        let changed = match key {
+            BACKGROUND_COLOR_SETTING => match background::parse_color(&value) {
+                Some(color) => self.background.set_color(color, &value),
+                None => {
+                    log(&format!("Display Compositor: Ignoring {} '{}', not a #RRGGBB color.", key, value));
+                    false
+                }
+            },
+            WALLPAPER_SETTING => self.load_wallpaper(&value).unwrap_or_else(|e| {
+                log(&format!("Display Compositor: Not showing the wallpaper: {}.", e));
+                false
+            }),
+            WALLPAPER_FIT_SETTING => background::fit_from_name(&value).map_or(false, |fit| self.background.set_fit(fit)),
+            DISPLAY_MODE_SETTING => {
+                // Empty means the mode the bootloader set, which is the one in use.
+                let mode = value.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                match mode {
+                    Some((width, height)) => if let Err(e) = self.set_display_mode(width, height) {
+                        log(&format!("Display Compositor: Ignoring {} {}: {}", key, value, e));
+                    },
+                    None if value.is_empty() => {},
+                    None => log(&format!("Display Compositor: Ignoring {} '{}', not WIDTHxHEIGHT.", key, value)),
+                }
+                false
+            },
+            SCALE_SETTING => UiScale::from_name(&value).map_or(false, |scale| self.set_scale(scale)),
+            SWITCH_WINDOW_HOTKEY_SETTING | SCREENSHOT_HOTKEY_SETTING => {
+                let action = if key == SWITCH_WINDOW_HOTKEY_SETTING { HotkeyAction::SwitchWindow } else { HotkeyAction::Screenshot };
+                let rebound = keys::parse_combo(&value)
+                    .ok_or_else(|| "Not a key combination.".to_string())
+                    .and_then(|(modifiers, keycode)| self.hotkeys.rebind(action, modifiers, keycode));
+                match rebound {
+                    Ok(true) => log(&format!("Display Compositor: {} is now {}.", key, value)),
+                    Ok(false) => {},
+                    Err(e) => log(&format!("Display Compositor: Ignoring {} '{}': {}", key, value, e)),
+                }
+                false
+            },
+            _ => false,
+        };
+        if changed {
+            self.damage_desktop();
+        }
+    }
+
+    fn handle_bus_events(&mut self) {
+        while let Ok(Some(event_data)) = self.bus_events_chan.recv_non_blocking() {
+            let Ok(event) = postcard::from_bytes::<Event>(&event_data) else {
+                continue;
+            };
+            if let Ok(SettingChanged { key, value }) = postcard::from_bytes(&event.payload) {
+                self.apply_setting(&key, value);
+            }
+        }
+    }
+
+    /// Creates a window whose `UiEvent`s go to `owner_chan`.
+    fn create_window(&mut self, title: String, width: u32, height: u32, owner_chan: u32) -> UiResponse {
+        let id = self.next_window_id;
+        self.next_window_id = WindowId::from_raw(id.raw() + 1);
+
+        // New windows go on the output the cursor is on, cascaded from its
+        // origin so their title bars don't overlap exactly.
+        let output = self.outputs.at(self.cursor.x, self.cursor.y);
+        let (output_id, area) = (output.id, output.rect());
+        let (width, height) = surface::clamp_size(width, height, area.width, area.height);
+        let offset = ((id.raw() - 1) % 8) as i64 * title_bar_height() as i64;
+        let (x, y) = decorations::clamp_origin(area.x as i64 + offset, area.y as i64 + offset, width, area);
+        let new_window = WindowSurface { id, title: title.clone(), x, y, surface: Surface::new(width, height), output_id, owner_chan, close_requested_at: None, latency: PipelineLatency::default(), cursor: CursorShape::Arrow };
+        self.windows.insert(id, new_window);
+        self.metrics.windows.set(self.windows.len() as i64);
+        self.metrics.windows_created.inc();
+        self.raise(id);
+
+        log(&alloc::format!("Display Compositor: Created window '{}' with ID: {} ({}x{}) on output {}, events on channel {}.", title, id, width, height, output_id, owner_chan));
+        UiResponse::Success { window_id: Some(id) }
+    }
+
+    fn handle_request(&mut self, request: UiRequest) -> UiResponse {
+        match request {
+            UiRequest::CreateWindow { title, width, height } => self.create_window(title, width, height, self.client_chan.id),
+            UiRequest::CreateWindowWithEvents { title, width, height, events_chan } => self.create_window(title, width, height, events_chan),
+            UiRequest::DrawToSurface { window_id, x, y, width, height, pixels, input } => {
+                let now = time::monotonic_nanos();
+                if let Some(window) = self.windows.get_mut(&window_id) {
+                    // Coordinates are client-relative; the title bar is not drawable by clients.
+                    // A frame drawn for a size the window no longer has fails here too.
+                    if let Err(reason) = window.surface.blit(x, y, width, height, &pixels) {
+                        log(&alloc::format!("Display Compositor: DrawToSurface rejected for window {}: {}.", window_id, reason));
+                        return UiResponse::Error { message: alloc::format!("Region {}x{} at ({},{}) of window {} ({}x{} client area): {}.", width, height, x, y, window_id, window.surface.width(), window.surface.height(), reason) };
+                    }
                     log(&alloc::format!("Display Compositor: Drawing to window {} at ({},{}) with size {}x{}. Pixel data length: {}.",
                         window_id, x, y, width, height, pixels.len()));
-                    // In a real system, this would blit `pixels` to the framebuffer at the correct position.
+                    // The next composite shows the updated region, on whichever outputs it is.
+                    let region = Rect { x: window.x + x, y: window.y + title_bar_height() + y, width, height };
+                    self.outputs.damage(region);
+                    if let Some(timing) = input {
+                        // The blit above is the composite of the frame that answers this input.
+                        // capture->dispatch was already recorded when the event was sent.
+                        let app_side = InputTiming { captured_at: 0, ..timing };
+                        window.latency.record_timing(&app_side, now);
+                        self.latency.record_timing(&app_side, now);
+                        for (stage, nanos) in latency::stage_deltas(&app_side, now) {
+                            self.metrics.observe(stage, nanos);
+                        }
+                    }
                     UiResponse::Success { window_id: Some(window_id) }
                 } else {
                     log(&alloc::format!("Display Compositor: DrawToSurface failed, window {} not found.", window_id));
                     UiResponse::Error { message: alloc::format!("Window {} not found.", window_id) }
                 }
             },
-            UiRequest::MouseEvent { window_id, x, y, button, event_type } => {
-                log(&alloc::format!("Display Compositor: Mouse event {:?} on window {} at ({},{}) button {}.", event_type, window_id, x, y, button));
-                // In a real system, this would route the event to the appropriate V-Node (e.g., focused window).
-                UiResponse::Success { window_id: Some(window_id) }
+            UiRequest::MouseEvent { window_id: _, x, y, button, event_type, captured_at } => {
+                // Raw input from the input bridge; the target is found by hit-testing.
+                let old = self.cursor.rect();
+                self.cursor.warp(x, y);
+                self.handle_pointer(self.cursor.x, self.cursor.y, button, event_type, captured_at);
+                self.redraw_cursor(old);
+                UiResponse::Success { window_id: None }
             },
-            UiRequest::KeyEvent { window_id, keycode, event_type } => {
-                log(&alloc::format!("Display Compositor: Keyboard event {:?} on window {} for keycode {}.", event_type, window_id, keycode));
-                // In a real system, this would route the event to the appropriate V-Node.
-                UiResponse::Success { window_id: Some(window_id) }
+            UiRequest::KeyEvent { window_id: _, keycode, event_type, captured_at, text } => {
+                // Escape cancels a drag and drop instead of reaching the focused window.
+                if self.dnd.is_some() && keycode == KEY_ESCAPE {
+                    if event_type == KeyEventType::KeyDown {
+                        let old = self.cursor.rect();
+                        self.end_drag_and_drop(false);
+                        self.update_cursor_shape();
+                        self.redraw_cursor(old);
+                    }
+                    return UiResponse::Success { window_id: None };
+                }
+                // Hotkeys and the window switcher come before the focused window.
+                if self.intercept_key(keycode, event_type) {
+                    return UiResponse::Success { window_id: None };
+                }
+                // Keyboard input goes to the focused window, whatever the input bridge guessed.
+                match self.focused.and_then(|id| self.windows.get(&id)) {
+                    Some(window) => {
+                        let (window_id, owner_chan) = (window.id, window.owner_chan);
+                        let timing = self.dispatch_timing(window_id, captured_at);
+                        self.send_event(owner_chan, UiEvent::Key { window_id, keycode, event_type, timing, text });
+                        UiResponse::Success { window_id: Some(window_id) }
+                    },
+                    None => UiResponse::Success { window_id: None },
+                }
+            },
+            UiRequest::ResizeWindow { window_id, width, height } => match self.resize_window(window_id, width, height) {
+                Some(_) => UiResponse::Success { window_id: Some(window_id) },
+                None => {
+                    log(&alloc::format!("Display Compositor: ResizeWindow failed, window {} not found.", window_id));
+                    UiResponse::Error { message: alloc::format!("Window {} not found.", window_id) }
+                },
             },
             UiRequest::CloseWindow { window_id } => {
-                if self.windows.remove(&window_id).is_some() {
+                if self.remove_window(window_id) {
                     log(&alloc::format!("Display Compositor: Closed window {}.", window_id));
                     UiResponse::Success { window_id: Some(window_id) }
                 } else {
@@ -100,17 +1094,168 @@ impl DisplayCompositor {
                 }
             },
             UiRequest::GetWindows => {
-                let window_infos: Vec<WindowInfo> = self.windows.values().map(|w| WindowInfo {
+                let window_infos: Vec<WindowInfo> = self.z_order.iter().filter_map(|id| self.windows.get(id)).map(|w| WindowInfo {
                     id: w.id,
                     title: w.title.clone(),
                     x: w.x,
                     y: w.y,
-                    width: w.width,
-                    height: w.height,
+                    width: w.surface.width(),
+                    height: w.surface.height(),
+                    title_bar_height: title_bar_height(),
+                    output_id: w.output_id,
                 }).collect();
                 log(&alloc::format!("Display Compositor: Returning {} window infos.", window_infos.len()));
                 UiResponse::Windows(window_infos)
             },
+            UiRequest::ListOutputs => UiResponse::Outputs(self.outputs.iter().map(Output::info).collect()),
+            UiRequest::MoveWindowToOutput { window_id, output_id } => {
+                let from = match self.windows.get(&window_id) {
+                    Some(window) => self.output_area(window.output_id),
+                    None => return UiResponse::Error { message: alloc::format!("Window {} not found.", window_id) },
+                };
+                if self.outputs.get(output_id).is_none() {
+                    return UiResponse::Error { message: alloc::format!("Output {} not found.", output_id) };
+                }
+                self.move_to_output(window_id, from, output_id);
+                UiResponse::Success { window_id: Some(window_id) }
+            },
+            UiRequest::AddVirtualOutput { width, height } => {
+                let width = width.clamp(output::MIN_OUTPUT_WIDTH, output::MAX_OUTPUT_WIDTH);
+                let height = height.clamp(output::MIN_OUTPUT_HEIGHT, output::MAX_OUTPUT_HEIGHT);
+                let info = self.outputs.add(width, height, Target::Offscreen).info();
+                let bounds = self.outputs.bounds();
+                self.cursor.set_bounds(bounds.width, bounds.height);
+                log(&alloc::format!("Display Compositor: Added virtual output {} ({}x{} at {},{}).", info.id, width, height, info.x, info.y));
+                UiResponse::Output(info)
+            },
+            UiRequest::RemoveOutput { output_id } => {
+                if self.remove_output(output_id) {
+                    UiResponse::Success { window_id: None }
+                } else if self.outputs.get(output_id).is_some() {
+                    UiResponse::Error { message: "The last output can't be removed.".to_string() }
+                } else {
+                    UiResponse::Error { message: alloc::format!("Output {} not found.", output_id) }
+                }
+            },
+            UiRequest::CaptureScreen { output_id } => match self.capture(output_id) {
+                Some((width, height, pixels)) => UiResponse::Screen { output_id, width, height, pixels },
+                None => UiResponse::Error { message: alloc::format!("Output {} not found.", output_id) },
+            },
+            UiRequest::GetStats => {
+                let windows = self.z_order.iter().filter_map(|id| self.windows.get(id)).map(|w| WindowLatency {
+                    window_id: w.id,
+                    title: w.title.clone(),
+                    latency: w.latency,
+                }).collect();
+                UiResponse::Stats(CompositorStats { latency: self.latency, windows })
+            },
+            UiRequest::Metrics(request) => UiResponse::Metrics(self.metrics.registry.handle(&request)),
+            UiRequest::ShowNotification { id, summary, body, critical, actions, events_chan } => {
+                let bubble = Bubble::new(id, &summary, &body, critical, &actions, events_chan);
+                self.update_notifications(|stack| stack.show(bubble));
+                UiResponse::Success { window_id: None }
+            },
+            UiRequest::HideNotification { id } => {
+                self.update_notifications(|stack| { stack.hide(id); });
+                UiResponse::Success { window_id: None }
+            },
+            UiRequest::SetCursor { window_id, shape } => {
+                match self.windows.get_mut(&window_id) {
+                    Some(window) => window.cursor = shape,
+                    None => return UiResponse::Error { message: alloc::format!("Window {} not found.", window_id) },
+                }
+                // Takes effect now if the pointer is over the window.
+                let old = self.cursor.rect();
+                self.update_cursor_shape();
+                self.redraw_cursor(old);
+                UiResponse::Success { window_id: Some(window_id) }
+            },
+            UiRequest::StartDrag { window_id, mime, data } => {
+                if !self.windows.contains_key(&window_id) {
+                    return UiResponse::Error { message: alloc::format!("Window {} not found.", window_id) };
+                }
+                if self.dnd.is_some() {
+                    return UiResponse::Error { message: "A drag is already in progress.".to_string() };
+                }
+                // Only a press the user is still holding can turn into a drag.
+                if self.pressed_in != Some(window_id) {
+                    return UiResponse::Error { message: alloc::format!("The left button is not held down in window {}.", window_id) };
+                }
+                if let DragData::Inline(bytes) = &data {
+                    if bytes.len() > MAX_INLINE_DRAG_BYTES {
+                        return UiResponse::Error { message: alloc::format!("Inline drag data is {} bytes, the limit is {}.", bytes.len(), MAX_INLINE_DRAG_BYTES) };
+                    }
+                }
+                log(&alloc::format!("Display Compositor: Window {} started dragging '{}'.", window_id, mime));
+                let (x, y) = (self.cursor.x, self.cursor.y);
+                let mut drag = dnd::Drag::new(window_id, mime, data);
+                let events = drag.moved(self.drop_target_at(x, y));
+                self.dnd = Some(drag);
+                self.queue_drag_events(events);
+                self.move_ghost(x, y);
+                let old = self.cursor.rect();
+                self.update_cursor_shape();
+                self.redraw_cursor(old);
+                UiResponse::Success { window_id: Some(window_id) }
+            },
+            UiRequest::AcceptDrag { window_id, accept } => {
+                let answered = self.dnd.as_mut().map_or(false, |drag| drag.accept(window_id, accept));
+                if !answered {
+                    return UiResponse::Error { message: alloc::format!("No drag is over window {}.", window_id) };
+                }
+                // The ghost's border shows whether the drop would be taken.
+                if let Some(ghost) = self.ghost {
+                    self.outputs.damage(ghost);
+                }
+                UiResponse::Success { window_id: Some(window_id) }
+            },
+            UiRequest::SetBackground { color_or_image_path, fit } => {
+                let changed = if color_or_image_path.starts_with('#') {
+                    let Some(color) = background::parse_color(&color_or_image_path) else {
+                        return UiResponse::Error { message: alloc::format!("'{}' is not a #RRGGBB color.", color_or_image_path) };
+                    };
+                    // A color replaces the wallpaper.
+                    let wallpaper = self.background.set_wallpaper(None);
+                    self.background.set_color(color, &color_or_image_path) | wallpaper
+                } else {
+                    let wallpaper = match self.load_wallpaper(&color_or_image_path) {
+                        Ok(changed) => changed,
+                        Err(message) => return UiResponse::Error { message },
+                    };
+                    self.background.set_fit(fit) | wallpaper
+                };
+                if changed {
+                    self.damage_desktop();
+                }
+                UiResponse::Success { window_id: None }
+            },
+            UiRequest::GetDisplayInfo => UiResponse::DisplayInfo(self.display_info()),
+            UiRequest::SetDisplayMode { width, height } => match self.set_display_mode(width, height) {
+                Ok(()) => UiResponse::DisplayInfo(self.display_info()),
+                Err(message) => UiResponse::NotSupported { message },
+            },
+            UiRequest::RegisterHotkey { modifiers, keycode, tag, events_chan } => {
+                // The kernel's name for the sender, not anything the request claims.
+                let Some(creds) = self.sender.filter(|creds| HOTKEY_CLIENTS.contains(&creds.name())) else {
+                    return UiResponse::Error { message: "Only the system UI may register hotkeys.".to_string() };
+                };
+                match self.hotkeys.register(creds.sender, creds.name(), modifiers, keycode, tag, events_chan) {
+                    Ok(()) => {
+                        log(&alloc::format!("Display Compositor: {} registered hotkey {}.", creds.name(), keys::combo_name(modifiers, keycode)));
+                        UiResponse::Success { window_id: None }
+                    },
+                    Err(message) => UiResponse::Error { message },
+                }
+            },
+            UiRequest::UnregisterHotkey { modifiers, keycode } => {
+                let task = self.sender.map_or(0, |creds| creds.sender);
+                if self.hotkeys.unregister(task, modifiers, keycode) {
+                    UiResponse::Success { window_id: None }
+                } else {
+                    UiResponse::Error { message: alloc::format!("You haven't registered {}.", keys::combo_name(modifiers, keycode)) }
+                }
+            },
+            UiRequest::ListHotkeys => UiResponse::Hotkeys(self.hotkeys.list()),
         }
     }
 
@@ -118,26 +1263,55 @@ impl DisplayCompositor {
         log("Display Compositor: Entering main event loop.");
         loop {
             // Process incoming requests from client UI V-Nodes
-            if let Ok(Some(req_data)) = self.client_chan.recv_non_blocking() {
+            if let Ok(Some((req_data, creds))) = self.client_chan.recv_with_creds_non_blocking() {
                 if let Ok(request) = postcard::from_bytes::<UiRequest>(&req_data) {
-                    log(&alloc::format!("Display Compositor: Received UiRequest: {:?}.", request));
+                    log(&alloc::format!("Display Compositor: Received UiRequest from {}: {:?}.", creds.name(), request));
+                    self.sender = Some(creds);
                     let response = self.handle_request(request);
                     self.client_chan.send(&response).unwrap_or_else(|_| log("Display Compositor: Failed to send response to client."));
+                    self.flush_outbox();
                 } else {
                     log("Display Compositor: Failed to deserialize UiRequest.");
                 }
             }
 
+            // Mouse input decoded by the kernel
+            self.poll_input();
+
+            self.check_close_timeouts();
+
+            // Hotkeys go with the task that registered them
+            let dropped = self.hotkeys.expire(session_ipc::task_alive);
+            if dropped > 0 {
+                log(&alloc::format!("Display Compositor: Dropped {} hotkeys of exited tasks.", dropped));
+            }
+
+            self.handle_bus_events();
+
+            self.composite();
+
             // Yield to other V-Nodes to prevent busy-waiting
-            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+            self.now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
         }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel ID 12 for UI Compositor communication
-    let mut compositor_vnode = DisplayCompositor::new(12);
+    // Channels come from init; the well-known IDs are the fallback:
+    // 12 for UI Compositor communication
+    // 7 for the VFS
+    // 14 for Settings
+    // 13 for the Event Bus, 29 for the events it delivers
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut compositor_vnode = DisplayCompositor::new(
+        channel(SELF_CHANNEL, 12),
+        channel("vfs", 7),
+        channel("settings", 14),
+        channel("event-bus", 13),
+        29,
+    );
     compositor_vnode.run_loop();
 }
 
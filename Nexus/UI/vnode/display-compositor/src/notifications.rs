@@ -0,0 +1,260 @@
+// vnode/display-compositor/src/notifications.rs
+
+//! Notification bubbles: a stack in the top right corner of the primary
+//! output, drawn above every window.
+//!
+//! The notifications service decides what is shown and for how long; the
+//! compositor only lays the bubbles out, draws them and reports clicks. The
+//! newest bubble is at the top and older ones move down. Bubbles that don't
+//! fit below the others, or past `MAX_VISIBLE`, wait until one above goes.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::text;
+use common::ui::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use common::ui_protocol::NotificationButton;
+
+use crate::cursor::Rect;
+
+pub const BUBBLE_WIDTH: u32 = 320;
+/// Most bubbles on screen at once.
+pub const MAX_VISIBLE: usize = 5;
+/// Most lines of body text; the rest is cut with "...".
+pub const BODY_LINES: usize = 3;
+/// Distance from the output's top and right edges.
+const MARGIN: u32 = 12;
+/// Space between two bubbles.
+const GAP: u32 = 8;
+/// Space between a bubble's border and its contents.
+const PADDING: u32 = 8;
+const BUTTON_HEIGHT: u32 = GLYPH_HEIGHT as u32 + 6;
+const BUTTON_PADDING: u32 = 8;
+
+const BACKGROUND_COLOR: [u8; 4] = [0x28, 0x28, 0x3C, 0xFF];
+const BORDER_COLOR: [u8; 4] = [0x50, 0x60, 0x98, 0xFF];
+const CRITICAL_BORDER_COLOR: [u8; 4] = [0xC0, 0x40, 0x40, 0xFF];
+const SUMMARY_COLOR: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+const BODY_COLOR: [u8; 4] = [0xB8, 0xB8, 0xC8, 0xFF];
+const BUTTON_COLOR: [u8; 4] = [0x38, 0x50, 0x88, 0xFF];
+
+/// A notification on screen, rendered once when it is shown.
+pub struct Bubble {
+    pub id: u64,
+    pub events_chan: u32, // Where NotificationClicked goes
+    height: u32,
+    buttons: Vec<(String, Rect)>, // Action key and bubble-relative area
+    pixels: Vec<u8>, // RGBA, BUBBLE_WIDTH x height
+}
+
+impl Bubble {
+    pub fn new(id: u64, summary: &str, body: &str, critical: bool, actions: &[NotificationButton], events_chan: u32) -> Self {
+        let cells = ((BUBBLE_WIDTH - 2 * PADDING) / GLYPH_WIDTH as u32) as usize;
+        let lines = wrap(body, cells, BODY_LINES);
+        let text_height = (1 + lines.len() as u32) * GLYPH_HEIGHT as u32;
+        let buttons_height = if actions.is_empty() { 0 } else { PADDING + BUTTON_HEIGHT };
+        let height = 2 * PADDING + text_height + buttons_height;
+
+        let mut canvas = Canvas::new(BUBBLE_WIDTH, height, BACKGROUND_COLOR);
+        let border = if critical { CRITICAL_BORDER_COLOR } else { BORDER_COLOR };
+        canvas.outline(Rect { x: 0, y: 0, width: BUBBLE_WIDTH, height }, border);
+        canvas.text(PADDING, PADDING, text::truncate_to_width(summary, cells), SUMMARY_COLOR);
+        for (i, line) in lines.iter().enumerate() {
+            canvas.text(PADDING, PADDING + (i as u32 + 1) * GLYPH_HEIGHT as u32, line, BODY_COLOR);
+        }
+
+        // Action buttons in a row along the bottom, as many as fit.
+        let mut buttons = Vec::new();
+        let mut x = PADDING;
+        let y = height - PADDING - BUTTON_HEIGHT;
+        for action in actions {
+            let label = text::truncate_to_width(&action.label, cells);
+            let width = text::display_width(label) as u32 * GLYPH_WIDTH as u32 + 2 * BUTTON_PADDING;
+            if x + width > BUBBLE_WIDTH - PADDING {
+                break;
+            }
+            let area = Rect { x, y, width, height: BUTTON_HEIGHT };
+            canvas.fill(area, BUTTON_COLOR);
+            canvas.text(x + BUTTON_PADDING, y + 3, label, SUMMARY_COLOR);
+            buttons.push((action.key.clone(), area));
+            x += width + GAP;
+        }
+
+        Self { id, events_chan, height, buttons, pixels: canvas.pixels }
+    }
+
+    /// Row `n` of the rendered bubble.
+    pub fn row(&self, n: u32) -> &[u8] {
+        let len = (BUBBLE_WIDTH * 4) as usize;
+        &self.pixels[n as usize * len..(n as usize + 1) * len]
+    }
+
+    /// The action under a bubble-relative point: `Some(key)` on a button, `None` elsewhere.
+    fn action_at(&self, x: u32, y: u32) -> Option<String> {
+        self.buttons.iter()
+            .find(|(_, area)| x >= area.x && x < area.x + area.width && y >= area.y && y < area.y + area.height)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// The bubbles on screen or waiting for room, oldest first.
+pub struct Stack {
+    bubbles: Vec<Bubble>,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self { bubbles: Vec::new() }
+    }
+
+    /// Adds a bubble on top, or replaces the one with the same ID where it is.
+    pub fn show(&mut self, bubble: Bubble) {
+        match self.bubbles.iter_mut().find(|shown| shown.id == bubble.id) {
+            Some(shown) => *shown = bubble,
+            None => self.bubbles.push(bubble),
+        }
+    }
+
+    pub fn hide(&mut self, id: u64) -> Option<Bubble> {
+        let position = self.bubbles.iter().position(|bubble| bubble.id == id)?;
+        Some(self.bubbles.remove(position))
+    }
+
+    /// Where each visible bubble goes on the output `area`, newest first.
+    pub fn layout(&self, area: Rect) -> Vec<(&Bubble, Rect)> {
+        let x = (area.x + area.width).saturating_sub(MARGIN + BUBBLE_WIDTH).max(area.x);
+        let bottom = (area.y + area.height).saturating_sub(MARGIN);
+        let mut y = area.y + MARGIN;
+        let mut placed = Vec::new();
+        for bubble in self.bubbles.iter().rev().take(MAX_VISIBLE) {
+            if y + bubble.height > bottom {
+                break;
+            }
+            placed.push((bubble, Rect { x, y, width: BUBBLE_WIDTH, height: bubble.height }));
+            y += bubble.height + GAP;
+        }
+        placed
+    }
+
+    /// Everything the visible bubbles cover, to damage before and after a change.
+    pub fn bounds(&self, area: Rect) -> Option<Rect> {
+        self.layout(area).into_iter().map(|(_, rect)| rect).reduce(|bounds, rect| bounds.union(&rect))
+    }
+
+    /// The bubble under a screen point, and the action button under it if any.
+    pub fn hit(&self, area: Rect, x: u32, y: u32) -> Option<(u64, u32, Option<String>)> {
+        self.layout(area).into_iter()
+            .find(|(_, rect)| x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height)
+            .map(|(bubble, rect)| (bubble.id, bubble.events_chan, bubble.action_at(x - rect.x, y - rect.y)))
+    }
+}
+
+/// Splits `body` into at most `max_lines` lines of `cells` columns, breaking
+/// between words where it can. Text that doesn't fit ends with "...".
+fn wrap(body: &str, cells: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut cut = false;
+    'words: for word in body.split_whitespace() {
+        let mut word = word;
+        loop {
+            let needed = text::display_width(word) + if line.is_empty() { 0 } else { 1 };
+            if text::display_width(&line) + needed <= cells {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                break;
+            }
+            if lines.len() + 1 == max_lines {
+                cut = true;
+                break 'words;
+            }
+            if line.is_empty() {
+                // A word longer than a whole line is broken where the line ends.
+                let head = text::truncate_to_width(word, cells);
+                lines.push(String::from(head));
+                word = &word[head.len()..];
+            } else {
+                lines.push(core::mem::take(&mut line));
+            }
+        }
+    }
+    if cut {
+        let kept = text::truncate_to_width(&line, cells.saturating_sub(3)).len();
+        line.truncate(kept);
+        line.push_str("...");
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// An RGBA pixel buffer to draw a bubble into. The window switcher draws
+/// with it too.
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, color: [u8; 4]) -> Self {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&color);
+        }
+        Self { width, height, pixels }
+    }
+
+    pub fn put(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        if x < self.width && y < self.height {
+            let i = ((y * self.width + x) * 4) as usize;
+            self.pixels[i..i + 4].copy_from_slice(&color);
+        }
+    }
+
+    pub fn fill(&mut self, area: Rect, color: [u8; 4]) {
+        for y in area.y..area.y + area.height {
+            for x in area.x..area.x + area.width {
+                self.put(x, y, color);
+            }
+        }
+    }
+
+    /// A one pixel border just inside `area`.
+    pub fn outline(&mut self, area: Rect, color: [u8; 4]) {
+        let (right, bottom) = (area.x + area.width - 1, area.y + area.height - 1);
+        for x in area.x..=right {
+            self.put(x, area.y, color);
+            self.put(x, bottom, color);
+        }
+        for y in area.y..=bottom {
+            self.put(area.x, y, color);
+            self.put(right, y, color);
+        }
+    }
+
+    /// One line of text in the 8x16 font, its top left corner at (x, y).
+    pub fn text(&mut self, x: u32, y: u32, line: &str, color: [u8; 4]) {
+        let mut cursor = x;
+        for c in line.chars() {
+            let width = text::char_width(c);
+            if width == 0 {
+                continue;
+            }
+            for gy in 0..GLYPH_HEIGHT {
+                let row = font::glyph_row_char(c, gy);
+                for gx in 0..GLYPH_WIDTH {
+                    if row & (1 << gx) != 0 {
+                        self.put(cursor + gx as u32, y + gy as u32, color);
+                    }
+                }
+            }
+            cursor += (width * GLYPH_WIDTH) as u32;
+        }
+    }
+}
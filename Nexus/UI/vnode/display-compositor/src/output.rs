@@ -0,0 +1,196 @@
+// vnode/display-compositor/src/output.rs
+
+//! Outputs: the displays windows are composited onto.
+//!
+//! Every output is a rectangle in one global coordinate space shared with
+//! window positions and the cursor, so a window can straddle two outputs and
+//! each shows its own slice. An output composites into its own back buffer and
+//! tracks its own damage; a change on one output never redraws another.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use common::ui_protocol::OutputInfo;
+
+use crate::cursor::Rect;
+
+/// Default desktop background (`#202030`), where no window covers an output. See `background`.
+pub const DESKTOP_COLOR: [u8; 4] = [0x20, 0x20, 0x30, 0xFF];
+
+/// Sizes accepted for virtual outputs.
+pub const MIN_OUTPUT_WIDTH: u32 = 320;
+pub const MIN_OUTPUT_HEIGHT: u32 = 200;
+pub const MAX_OUTPUT_WIDTH: u32 = 4096;
+pub const MAX_OUTPUT_HEIGHT: u32 = 4096;
+
+/// Where an output's composited pixels end up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A scanout, given by the base address from `SYS_FB_ACQUIRE`.
+    Framebuffer(u64),
+    /// Nowhere but the back buffer, read back with `CaptureScreen`.
+    Offscreen,
+}
+
+pub struct Output {
+    pub id: u32,
+    rect: Rect, // Position and size in the global coordinate space
+    pub target: Target,
+    pixels: Vec<u8>, // RGBA back buffer, row by row
+    damage: Option<Rect>, // Global area to recomposite, clipped to `rect`
+}
+
+impl Output {
+    /// An output whose whole area starts out damaged, so the first composite fills it.
+    pub fn new(id: u32, rect: Rect, target: Target) -> Self {
+        let mut pixels = Vec::with_capacity((rect.width * rect.height * 4) as usize);
+        for _ in 0..rect.width * rect.height {
+            pixels.extend_from_slice(&DESKTOP_COLOR);
+        }
+        Self { id, rect, target, pixels, damage: Some(rect) }
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn info(&self) -> OutputInfo {
+        OutputInfo {
+            id: self.id,
+            x: self.rect.x,
+            y: self.rect.y,
+            width: self.rect.width,
+            height: self.rect.height,
+            offscreen: self.target == Target::Offscreen,
+        }
+    }
+
+    /// The composited image, as `CaptureScreen` returns it.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Marks the part of `area` on this output for recompositing.
+    pub fn damage(&mut self, area: Rect) {
+        if let Some(area) = area.intersection(&self.rect) {
+            self.damage = Some(self.damage.map_or(area, |damage| damage.union(&area)));
+        }
+    }
+
+    pub fn take_damage(&mut self) -> Option<Rect> {
+        self.damage.take()
+    }
+
+    /// Fills the part of `area` inside `clip` with one color.
+    pub fn fill(&mut self, area: Rect, clip: Rect, color: [u8; 4]) {
+        let area = match area.intersection(&clip).and_then(|area| area.intersection(&self.rect)) {
+            Some(area) => area,
+            None => return,
+        };
+        for y in area.y..area.y + area.height {
+            let start = self.offset(area.x, y);
+            for pixel in self.pixels[start..start + (area.width * 4) as usize].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Copies the part of a block at `area` that lies inside `clip`. `row(n)`
+    /// gives the block's n-th row, `area.width` pixels long.
+    pub fn draw<'a>(&mut self, area: Rect, clip: Rect, row: impl Fn(u32) -> &'a [u8]) {
+        let visible = match area.intersection(&clip).and_then(|visible| visible.intersection(&self.rect)) {
+            Some(visible) => visible,
+            None => return,
+        };
+        let skip = ((visible.x - area.x) * 4) as usize;
+        let len = (visible.width * 4) as usize;
+        for y in visible.y..visible.y + visible.height {
+            let start = self.offset(visible.x, y);
+            self.pixels[start..start + len].copy_from_slice(&row(y - area.y)[skip..skip + len]);
+        }
+    }
+
+    /// Byte offset of a global point in the back buffer.
+    fn offset(&self, x: u32, y: u32) -> usize {
+        (((y - self.rect.y) * self.rect.width + (x - self.rect.x)) * 4) as usize
+    }
+}
+
+/// The outputs, by ID. There is always at least one.
+pub struct Outputs {
+    outputs: BTreeMap<u32, Output>,
+    next_id: u32,
+}
+
+impl Outputs {
+    pub fn new(primary: Output) -> Self {
+        let next_id = primary.id + 1;
+        let mut outputs = BTreeMap::new();
+        outputs.insert(primary.id, primary);
+        Self { outputs, next_id }
+    }
+
+    /// Adds an output of the given size to the right of the others.
+    pub fn add(&mut self, width: u32, height: u32, target: Target) -> &Output {
+        let id = self.next_id;
+        self.next_id += 1;
+        let rect = Rect { x: self.bounds().width, y: 0, width, height };
+        self.outputs.entry(id).or_insert(Output::new(id, rect, target))
+    }
+
+    /// Removes an output, unless it is the last one.
+    pub fn remove(&mut self, id: u32) -> Option<Output> {
+        if self.outputs.len() == 1 {
+            return None;
+        }
+        self.outputs.remove(&id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Output> {
+        self.outputs.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Output> {
+        self.outputs.get_mut(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Output> {
+        self.outputs.values_mut()
+    }
+
+    /// The output with the lowest ID, where windows go when theirs is removed.
+    pub fn primary(&self) -> &Output {
+        self.outputs.values().next().expect("there is always an output")
+    }
+
+    /// The output a global point is on, or else the one nearest to it, so
+    /// points in gaps between outputs still belong somewhere.
+    pub fn at(&self, x: u32, y: u32) -> &Output {
+        let distance = |rect: Rect| {
+            let dx = (rect.x as i64 - x as i64).max(x as i64 - (rect.x + rect.width - 1) as i64).max(0);
+            let dy = (rect.y as i64 - y as i64).max(y as i64 - (rect.y + rect.height - 1) as i64).max(0);
+            dx * dx + dy * dy
+        };
+        self.outputs.values().min_by_key(|output| distance(output.rect)).expect("there is always an output")
+    }
+
+    /// The box around every output, from the global origin. Dragged windows
+    /// and the cursor are kept inside it.
+    pub fn bounds(&self) -> Rect {
+        let origin = Rect { x: 0, y: 0, width: 0, height: 0 };
+        self.outputs.values().fold(origin, |bounds, output| bounds.union(&output.rect))
+    }
+
+    /// Marks `area` for recompositing on every output it touches, and only those.
+    pub fn damage(&mut self, area: Rect) {
+        for output in self.outputs.values_mut() {
+            output.damage(area);
+        }
+    }
+}
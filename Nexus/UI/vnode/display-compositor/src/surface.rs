@@ -0,0 +1,98 @@
+// vnode/display-compositor/src/surface.rs
+
+//! The compositor-side backing buffer of a window's client area.
+//!
+//! The buffer's dimensions are the window's client size; nothing else stores
+//! them. A resize replaces the buffer and its dimensions in one step between
+//! requests, so compositing, which only runs between requests too, always
+//! reads a buffer together with the size it was allocated for.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::decorations::{self, CLOSE_BUTTON_SIZE, TITLE_BAR_HEIGHT};
+
+/// Smallest client area: room for the close button and a few title glyphs,
+/// and a few text lines below the title bar.
+pub const MIN_WIDTH: u32 = CLOSE_BUTTON_SIZE * 4;
+pub const MIN_HEIGHT: u32 = TITLE_BAR_HEIGHT * 2;
+
+/// Newly exposed pixels, until the client draws the resized frame.
+const BACKGROUND: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+
+/// Clamps a requested client size to at least the minimum and at most what
+/// fits on screen below a title bar.
+pub fn clamp_size(width: u32, height: u32, screen_width: u32, screen_height: u32) -> (u32, u32) {
+    let max_width = screen_width.max(MIN_WIDTH);
+    let max_height = screen_height.saturating_sub(decorations::title_bar_height()).max(MIN_HEIGHT);
+    (width.clamp(MIN_WIDTH, max_width), height.clamp(MIN_HEIGHT, max_height))
+}
+
+/// RGBA pixels of a client area, row by row.
+pub struct Surface {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Surface {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Self { width, height, pixels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Replaces the buffer with one of the new size. The overlapping top-left
+    /// part is kept so the window doesn't flash empty until the client
+    /// redraws; the rest is background.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let mut resized = Surface::new(width, height);
+        let copy_width = (self.width.min(width) * 4) as usize;
+        for y in 0..self.height.min(height) {
+            let old = (y * self.width * 4) as usize;
+            let new = (y * width * 4) as usize;
+            resized.pixels[new..new + copy_width].copy_from_slice(&self.pixels[old..old + copy_width]);
+        }
+        *self = resized;
+    }
+
+    /// Copies a `width x height` block of RGBA pixels to (x, y). The block must
+    /// lie inside the surface and `pixels` must hold exactly that many pixels.
+    pub fn blit(&mut self, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) -> Result<(), &'static str> {
+        let fits = x.checked_add(width).map_or(false, |right| right <= self.width)
+            && y.checked_add(height).map_or(false, |bottom| bottom <= self.height);
+        if !fits {
+            return Err("region exceeds the client area");
+        }
+        if pixels.len() as u64 != width as u64 * height as u64 * 4 {
+            return Err("pixel data doesn't match the region size");
+        }
+        let row_len = (width * 4) as usize;
+        for row in 0..height {
+            let src = row as usize * row_len;
+            let dst = (((y + row) * self.width + x) * 4) as usize;
+            self.pixels[dst..dst + row_len].copy_from_slice(&pixels[src..src + row_len]);
+        }
+        Ok(())
+    }
+
+    /// One row of pixels, for compositing.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = (y * self.width * 4) as usize;
+        &self.pixels[start..start + (self.width * 4) as usize]
+    }
+}
@@ -0,0 +1,111 @@
+// vnode/display-compositor/src/switcher.rs
+
+//! The window switcher: the list the switch-window hotkey (Alt+Tab) shows
+//! in the middle of the primary output while its modifiers are held.
+//!
+//! Windows are listed top of the stack first, and the selection starts on
+//! the second, so a single press goes back to the window used before. Each
+//! further press moves the selection down, wrapping at the end. Letting go
+//! of the modifiers raises the selected window; Escape closes the list
+//! without changing anything.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ids::WindowId;
+use common::text;
+use common::ui::font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+
+use crate::cursor::Rect;
+use crate::notifications::Canvas;
+
+pub const SWITCHER_WIDTH: u32 = 360;
+/// Most windows listed at once; the list scrolls to keep the selection in view.
+pub const MAX_ROWS: usize = 10;
+const PADDING: u32 = 8;
+const ROW_HEIGHT: u32 = GLYPH_HEIGHT as u32 + 4;
+
+const BACKGROUND_COLOR: [u8; 4] = [0x28, 0x28, 0x3C, 0xFF];
+const BORDER_COLOR: [u8; 4] = [0x50, 0x60, 0x98, 0xFF];
+const SELECTED_COLOR: [u8; 4] = [0x38, 0x50, 0x88, 0xFF];
+const TITLE_COLOR: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+
+pub struct Switcher {
+    windows: Vec<(WindowId, String)>, // ID and title, top of the stack first
+    selected: usize,
+    height: u32,
+    pixels: Vec<u8>, // RGBA, SWITCHER_WIDTH x height
+}
+
+impl Switcher {
+    /// Opens the list on `windows`, top of the stack first. `None` with
+    /// fewer than two windows, where there is nothing to switch to.
+    pub fn new(windows: Vec<(WindowId, String)>) -> Option<Self> {
+        if windows.len() < 2 {
+            return None;
+        }
+        let rows = windows.len().min(MAX_ROWS) as u32;
+        let mut switcher = Self { windows, selected: 1, height: 2 * PADDING + rows * ROW_HEIGHT, pixels: Vec::new() };
+        switcher.render();
+        Some(switcher)
+    }
+
+    pub fn selected(&self) -> WindowId {
+        self.windows[self.selected].0
+    }
+
+    /// Moves the selection to the next window down, or back to the top.
+    pub fn advance(&mut self) {
+        self.selected = (self.selected + 1) % self.windows.len();
+        self.render();
+    }
+
+    /// Takes a closed window off the list. Returns false if fewer than two
+    /// are left, and the list should close.
+    pub fn window_closed(&mut self, window_id: WindowId) -> bool {
+        let Some(position) = self.windows.iter().position(|(id, _)| *id == window_id) else {
+            return true;
+        };
+        self.windows.remove(position);
+        if self.windows.len() < 2 {
+            return false;
+        }
+        if position < self.selected || self.selected == self.windows.len() {
+            self.selected -= 1;
+        }
+        self.render();
+        true
+    }
+
+    /// Where the list goes: the middle of the output `area`. Its height
+    /// doesn't change while it is open, so damaging this once covers it. On
+    /// an output smaller than the list, drawing clips what sticks out.
+    pub fn rect(&self, area: Rect) -> Rect {
+        let x = area.x + area.width.saturating_sub(SWITCHER_WIDTH) / 2;
+        let y = area.y + area.height.saturating_sub(self.height) / 2;
+        Rect { x, y, width: SWITCHER_WIDTH, height: self.height }
+    }
+
+    /// Row `n` of the rendered list.
+    pub fn row(&self, n: u32) -> &[u8] {
+        let len = (SWITCHER_WIDTH * 4) as usize;
+        &self.pixels[n as usize * len..(n as usize + 1) * len]
+    }
+
+    fn render(&mut self) {
+        let mut canvas = Canvas::new(SWITCHER_WIDTH, self.height, BACKGROUND_COLOR);
+        canvas.outline(Rect { x: 0, y: 0, width: SWITCHER_WIDTH, height: self.height }, BORDER_COLOR);
+        let cells = ((SWITCHER_WIDTH - 4 * PADDING) / GLYPH_WIDTH as u32) as usize;
+        let first = (self.selected + 1).saturating_sub(MAX_ROWS);
+        for (row, (i, (_, title))) in self.windows.iter().enumerate().skip(first).take(MAX_ROWS).enumerate() {
+            let y = PADDING + row as u32 * ROW_HEIGHT;
+            if i == self.selected {
+                canvas.fill(Rect { x: PADDING, y, width: SWITCHER_WIDTH - 2 * PADDING, height: ROW_HEIGHT }, SELECTED_COLOR);
+            }
+            canvas.text(2 * PADDING, y + 2, text::truncate_to_width(title, cells), TITLE_COLOR);
+        }
+        self.pixels = canvas.pixels;
+    }
+}
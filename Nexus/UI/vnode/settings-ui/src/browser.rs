@@ -0,0 +1,337 @@
+// vnode/settings-ui/src/browser.rs
+
+//! A file-browser screen over 10,000 made-up files, to try the virtualized
+//! list (`common::ui::list`) on a list far too long to lay out whole.
+//!
+//! Every seventh file shows its size on a second line, so rows differ in
+//! height and the list has estimates to correct as it scrolls. The list
+//! covers the window above the help line and only its damage is painted,
+//! so scrolling never repaints the help line.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::keys::{KEY_DOWN, KEY_END, KEY_ESCAPE, KEY_HOME, KEY_PAGE_DOWN, KEY_PAGE_UP, KEY_UP};
+use common::text;
+use common::ui::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use common::ui::list::{ListRect, ListSource, ScrollAlign, VirtualList};
+use common::ui::scale::UiScale;
+
+use crate::view::{Action, ROW_HEIGHT};
+
+/// Files the demo lists.
+pub const DEMO_FILES: usize = 10_000;
+/// Rows a wheel notch scrolls.
+const WHEEL_ROWS: u32 = 3;
+/// Left padding of every line.
+const TEXT_PADDING: u32 = 4;
+
+const BACKGROUND_COLOR: [u8; 4] = [0x20, 0x20, 0x30, 0xFF];
+const SELECTED_COLOR: [u8; 4] = [0x38, 0x50, 0x88, 0xFF];
+const FOOTER_COLOR: [u8; 4] = [0x30, 0x30, 0x48, 0xFF];
+const TEXT_COLOR: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+const DETAIL_COLOR: [u8; 4] = [0x90, 0x90, 0x90, 0xFF];
+
+const HELP: &str = "Up/Down, PgUp/PgDn, Home/End, wheel: move  Esc: back to settings";
+
+const EXTENSIONS: [&str; 4] = ["txt", "rs", "png", "md"];
+
+/// `len` files named after their index. Nothing is stored per file; a row
+/// is made up when it is bound.
+pub struct SyntheticFiles {
+    len: usize,
+    /// Height of one line of a row at the current scale.
+    line_height: u32,
+}
+
+/// The widgets of one row: the name, and the size line if the file has one.
+#[derive(Default)]
+pub struct FileRow {
+    pub name: String,
+    pub detail: Option<String>,
+}
+
+impl ListSource for SyntheticFiles {
+    type RowWidgets = FileRow;
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn bind_row(&self, index: usize, row: &mut FileRow) {
+        row.name.clear();
+        row.name.push_str(&format!("file-{:05}.{}", index, EXTENSIONS[index % EXTENSIONS.len()]));
+        row.detail = (index % 7 == 0).then(|| format!("{} bytes", (index * 7919) % 1_000_000));
+    }
+
+    fn measure_row(&self, row: &FileRow, _width: u32) -> u32 {
+        self.line_height * if row.detail.is_some() { 2 } else { 1 }
+    }
+}
+
+pub struct BrowserView {
+    files: SyntheticFiles,
+    list: VirtualList<SyntheticFiles>,
+    /// The next paint covers the whole window, not only the list's damage.
+    repaint_all: bool,
+    pub width: u32,
+    pub height: u32,
+    pub scale: UiScale,
+}
+
+impl BrowserView {
+    pub fn new(width: u32, height: u32, scale: UiScale) -> Self {
+        Self::with_files(DEMO_FILES, width, height, scale)
+    }
+
+    fn with_files(len: usize, width: u32, height: u32, scale: UiScale) -> Self {
+        let files = SyntheticFiles { len, line_height: scale.px(ROW_HEIGHT) };
+        let list_height = height.saturating_sub(files.line_height);
+        let list = VirtualList::new(&files, width, list_height, files.line_height);
+        Self { files, list, repaint_all: true, width, height, scale }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.files.line_height
+    }
+
+    /// Height of the list: the window above the help line.
+    fn list_height(&self) -> u32 {
+        self.height.saturating_sub(self.line_height())
+    }
+
+    /// Text cells that fit across the window.
+    fn cells(&self) -> usize {
+        (self.width.saturating_sub(2 * self.scale.px(TEXT_PADDING)) / self.scale.px(GLYPH_WIDTH as u32)) as usize
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.list.set_viewport(width, self.list_height());
+        if let Some(index) = self.list.selected() {
+            self.list.scroll_to_index(index, ScrollAlign::Nearest);
+        }
+        self.repaint_all = true;
+    }
+
+    /// Rows are measured at the scale, so the list starts over with the
+    /// selection in view.
+    pub fn set_scale(&mut self, scale: UiScale) {
+        let selected = self.list.selected();
+        *self = Self::with_files(self.files.len, self.width, self.height, scale);
+        self.list.select(selected);
+        if let Some(index) = selected {
+            self.list.scroll_to_index(index, ScrollAlign::Center);
+        }
+    }
+
+    pub fn key_down(&mut self, keycode: u16) -> Action {
+        let page = (self.list_height() / self.line_height()).max(1) as i64;
+        match keycode {
+            KEY_UP => self.list.move_selection(-1),
+            KEY_DOWN => self.list.move_selection(1),
+            KEY_PAGE_UP => self.list.move_selection(-page),
+            KEY_PAGE_DOWN => self.list.move_selection(page),
+            KEY_HOME => self.list.move_selection(i64::MIN),
+            KEY_END => self.list.move_selection(i64::MAX),
+            KEY_ESCAPE => return Action::Settings,
+            _ => return Action::None,
+        }
+        Action::Redraw
+    }
+
+    /// Selects the row under a click at client-relative (x, y).
+    pub fn click(&mut self, _x: u32, y: u32) -> Action {
+        match self.list.row_at(y) {
+            Some(index) => {
+                self.list.select(Some(index));
+                Action::Redraw
+            },
+            None => Action::None,
+        }
+    }
+
+    /// One wheel notch, towards the end of the list if `down`.
+    pub fn wheel(&mut self, down: bool) -> Action {
+        let delta = (WHEEL_ROWS * self.line_height()) as i64;
+        self.list.scroll_by(if down { delta } else { -delta });
+        Action::Redraw
+    }
+
+    /// Lays the list out and paints what changed since the last call: the
+    /// area, client-relative, and its RGBA rows. `None` if nothing did.
+    pub fn render(&mut self) -> Option<(ListRect, Vec<u8>)> {
+        self.list.update(&self.files);
+        let damage = self.list.take_damage();
+        let area = if core::mem::take(&mut self.repaint_all) {
+            ListRect { x: 0, y: 0, width: self.width, height: self.height }
+        } else {
+            damage?
+        };
+        Some((area, self.paint(area)))
+    }
+
+    /// Paints `area` of the window.
+    fn paint(&self, area: ListRect) -> Vec<u8> {
+        let mut canvas = Canvas { area, pixels: Vec::with_capacity((area.width * area.height * 4) as usize) };
+        for _ in 0..area.width * area.height {
+            canvas.pixels.extend_from_slice(&BACKGROUND_COLOR);
+        }
+        let (line_height, cells) = (self.line_height(), self.cells());
+        let list_height = self.list_height() as i64;
+        for row in self.list.rows() {
+            // Rows bound for the overscan are outside the list; clip them to it.
+            let top = row.y.max(0);
+            let bottom = (row.y + row.height as i64).min(list_height);
+            if bottom <= top {
+                continue;
+            }
+            if self.list.selected() == Some(row.index) {
+                canvas.fill(top, bottom, SELECTED_COLOR);
+            }
+            self.draw_text(&mut canvas, row.y, bottom, &row.widgets.name, cells, TEXT_COLOR);
+            if let Some(detail) = &row.widgets.detail {
+                self.draw_text(&mut canvas, row.y + line_height as i64, bottom, detail, cells, DETAIL_COLOR);
+            }
+        }
+        let footer_top = list_height;
+        canvas.fill(footer_top, footer_top + line_height as i64, FOOTER_COLOR);
+        self.draw_text(&mut canvas, footer_top, self.height as i64, HELP, cells, TEXT_COLOR);
+        canvas.pixels
+    }
+
+    /// Draws one line of text into the line starting at window row `y`,
+    /// clipped above `clip_bottom`.
+    fn draw_text(&self, canvas: &mut Canvas, y: i64, clip_bottom: i64, line: &str, cells: usize, color: [u8; 4]) {
+        let scale = self.scale;
+        let (glyph_width, glyph_height) = (scale.px(GLYPH_WIDTH as u32) as i64, scale.px(GLYPH_HEIGHT as u32) as usize);
+        let top = y + (self.line_height() as i64 - glyph_height as i64) / 2;
+        let mut cursor = scale.px(TEXT_PADDING) as i64;
+        for c in text::truncate_to_width(line, cells).chars() {
+            let width = text::char_width(c) as i64;
+            if width == 0 {
+                continue;
+            }
+            for gy in 0..glyph_height {
+                let py = top + gy as i64;
+                if py < 0 || py >= clip_bottom {
+                    continue;
+                }
+                let row = font::glyph_row_scaled(c, gy, scale);
+                for gx in 0..glyph_width {
+                    if row & (1 << gx) != 0 {
+                        canvas.put(cursor + gx, py, color);
+                    }
+                }
+            }
+            cursor += width * glyph_width;
+        }
+    }
+}
+
+/// The pixels of one area of the window, drawn to in window coordinates.
+struct Canvas {
+    area: ListRect,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn put(&mut self, x: i64, y: i64, color: [u8; 4]) {
+        let (left, top) = (self.area.x as i64, self.area.y as i64);
+        if x < left || y < top || x >= left + self.area.width as i64 || y >= top + self.area.height as i64 {
+            return;
+        }
+        let i = (((y - top) * self.area.width as i64 + (x - left)) * 4) as usize;
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+
+    /// Fills the full-width window rows from `top` to `bottom`.
+    fn fill(&mut self, top: i64, bottom: i64, color: [u8; 4]) {
+        let (left, right) = (self.area.x as i64, (self.area.x + self.area.width) as i64);
+        for y in top..bottom {
+            for x in left..right {
+                self.put(x, y, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A window with room for `rows` one-line rows and the help line, at 1x.
+    fn browser(files: usize, rows: u32) -> BrowserView {
+        BrowserView::with_files(files, 640, (rows + 1) * ROW_HEIGHT, UiScale::X1)
+    }
+
+    fn rows_show_their_files(browser: &BrowserView) -> bool {
+        browser.list.rows().iter().all(|row| row.widgets.name.starts_with(&format!("file-{:05}.", row.index)) && row.widgets.detail.is_some() == (row.index % 7 == 0))
+    }
+
+    #[test]
+    fn row_instances_stay_bounded_for_any_number_of_files() {
+        for files in [50, DEMO_FILES, 100 * DEMO_FILES] {
+            let mut browser = browser(files, 20);
+            browser.render();
+            for _ in 0..40 {
+                browser.key_down(KEY_PAGE_DOWN);
+                browser.render();
+            }
+            browser.key_down(KEY_END);
+            browser.render();
+            assert_eq!(browser.list.selected(), Some(files - 1));
+            // 20 rows, one more when scrolled between two, and 3 of overscan on each side.
+            assert!(browser.list.instance_count() <= 27, "{} files: {} instances", files, browser.list.instance_count());
+            assert!(rows_show_their_files(&browser));
+        }
+    }
+
+    #[test]
+    fn scrolling_far_and_back_rebinds_the_rows() {
+        let mut browser = browser(DEMO_FILES, 20);
+        browser.render();
+        for _ in 0..50 {
+            browser.wheel(true);
+        }
+        browser.key_down(KEY_END);
+        browser.render();
+        browser.key_down(KEY_HOME);
+        browser.render();
+        assert_eq!(browser.list.selected(), Some(0));
+        assert_eq!(browser.list.rows().first().map(|row| (row.index, row.y)), Some((0, 0)));
+        assert!(rows_show_their_files(&browser));
+        // File 0 has a size line, so file 1 starts two lines down.
+        assert_eq!(browser.click(10, 2 * ROW_HEIGHT), Action::Redraw);
+        assert_eq!(browser.list.selected(), Some(1));
+    }
+
+    #[test]
+    fn only_the_list_is_repainted() {
+        let mut browser = browser(DEMO_FILES, 20);
+        let (area, pixels) = browser.render().unwrap();
+        assert_eq!(area, ListRect { x: 0, y: 0, width: 640, height: 21 * ROW_HEIGHT });
+        assert_eq!(pixels.len(), (640 * 21 * ROW_HEIGHT * 4) as usize);
+        assert!(browser.render().is_none());
+
+        // Selecting repaints the rows that changed: file 0 with its size
+        // line, then file 0 and file 1 below it.
+        browser.key_down(KEY_DOWN);
+        let (area, pixels) = browser.render().unwrap();
+        assert_eq!(area, ListRect { x: 0, y: 0, width: 640, height: 2 * ROW_HEIGHT });
+        assert_eq!(pixels.len(), (area.width * area.height * 4) as usize);
+        browser.key_down(KEY_DOWN);
+        let (area, _) = browser.render().unwrap();
+        assert_eq!(area, ListRect { x: 0, y: 0, width: 640, height: 3 * ROW_HEIGHT });
+
+        // Scrolling repaints the list, never the help line below it.
+        browser.wheel(true);
+        let (area, _) = browser.render().unwrap();
+        assert_eq!(area, ListRect { x: 0, y: 0, width: 640, height: 20 * ROW_HEIGHT });
+        assert_eq!(browser.key_down(KEY_ESCAPE), Action::Settings);
+    }
+}
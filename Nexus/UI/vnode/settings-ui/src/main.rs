@@ -0,0 +1,302 @@
+// vnode/settings-ui/src/main.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::event_ipc::{Event, EventBusRequest, EventBusResponse};
+use common::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
+use common::ipc::vnode::VNodeChannel;
+use common::syscall::{syscall3, SYS_LOG, SUCCESS};
+use common::ui_protocol::{UiRequest, UiResponse, UiEvent, MouseEventType, KeyEventType, BUTTON_LEFT, SCROLL_DOWN};
+use common::ui::scale::{UiScale, SCALE_SETTING};
+use common::ui::latency::AppLatency;
+use common::ids::WindowId;
+use common::startup::{self, SELF_CHANNEL};
+use common::time;
+
+// Temporary log function for V-Nodes
+fn log(msg: &str) {
+    unsafe {
+        let res = syscall3(
+            SYS_LOG,
+            msg.as_ptr() as u64,
+            msg.len() as u64,
+            0 // arg3 is unused for SYS_LOG
+        );
+        if res != SUCCESS { /* Handle log error, maybe panic or fall back */ }
+    }
+}
+
+mod browser;
+mod view;
+use browser::BrowserView;
+use view::{Action, SettingsView};
+
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 480;
+
+struct SettingsUiVNode {
+    client_chan: VNodeChannel, // Channel for communication with UI Compositor
+    ui_events_chan: Option<VNodeChannel>, // Where the compositor sends our window's UiEvents, if init gave us one
+    settings_chan: VNodeChannel,
+    bus_events_chan: Option<VNodeChannel>, // settings.* changes, on our own channel
+    window_id: WindowId,
+    view: SettingsView,
+    browser: Option<BrowserView>, // The file list demo, shown in place of the settings while open
+    latency: AppLatency, // Timing of the latest input event, attached to the next frame
+}
+
+impl SettingsUiVNode {
+    fn new(client_chan_id: u32, ui_events_chan_id: Option<u32>, settings_chan_id: u32, event_bus_chan_id: u32, self_chan_id: Option<u32>) -> Self {
+        let mut client_chan = VNodeChannel::new(client_chan_id);
+        let ui_events_chan = ui_events_chan_id.map(VNodeChannel::new);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        log("Settings UI V-Node: Initializing...");
+
+        // Subscribed before listing, so no change falls between the list and the first event.
+        let bus_events_chan = self_chan_id.map(VNodeChannel::new).filter(|chan| {
+            let subscribe = EventBusRequest::Subscribe { topic_prefix: "settings.".to_string(), reply_chan: chan.id };
+            matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_)))
+        });
+        if bus_events_chan.is_none() {
+            log("Settings UI: Not following settings changes; values other clients set show after a restart.");
+        }
+
+        let entries = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::List) {
+            Ok(SettingsResponse::Settings(entries)) => entries,
+            Ok(SettingsResponse::Error(message)) => {
+                log(&format!("Settings UI: Settings service refused the list: {}.", message));
+                Vec::new()
+            },
+            _ => {
+                log("Settings UI: Unexpected response for List.");
+                Vec::new()
+            },
+        };
+        let scale = entries.iter()
+            .find(|entry| entry.key == SCALE_SETTING)
+            .and_then(|entry| match &entry.value {
+                SettingValue::Str(name) => UiScale::from_name(name),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        let title = "AetherOS Settings".to_string();
+        let create_window_req = match &ui_events_chan {
+            Some(events) => UiRequest::CreateWindowWithEvents { title, width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT, events_chan: events.id },
+            // Events then come back on the compositor's channel.
+            None => UiRequest::CreateWindow { title, width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT },
+        };
+        let window_id = match client_chan.send_and_recv(&create_window_req) {
+            Ok(UiResponse::Success { window_id: Some(id) }) => id,
+            Ok(UiResponse::Error { message }) => panic!("Failed to create window: {}", message),
+            _ => panic!("Unexpected response for CreateWindow"),
+        };
+        log(&format!("Settings UI: Listing {} settings in window {}.", entries.len(), window_id));
+
+        Self {
+            client_chan,
+            ui_events_chan,
+            settings_chan,
+            bus_events_chan,
+            window_id,
+            view: SettingsView::new(entries, DEFAULT_WIDTH, DEFAULT_HEIGHT, scale),
+            browser: None,
+            latency: AppLatency::default(),
+        }
+    }
+
+    /// Draws the screen shown. The file list demo sends only what changed.
+    fn render(&mut self) {
+        let (x, y, width, height, pixels) = match self.browser.as_mut() {
+            Some(browser) => match browser.render() {
+                Some((area, pixels)) => (area.x, area.y, area.width, area.height, pixels),
+                None => return,
+            },
+            None => (0, 0, self.view.width, self.view.height, self.view.render()),
+        };
+        let draw_req = UiRequest::DrawToSurface {
+            window_id: self.window_id,
+            x,
+            y,
+            width,
+            height,
+            pixels,
+            input: self.latency.on_commit(time::monotonic_nanos()),
+        };
+        match self.client_chan.send_and_recv(&draw_req) {
+            Ok(UiResponse::Success { .. }) => {},
+            Ok(UiResponse::Error { message }) => log(&format!("Settings UI: Failed to draw: {}.", message)),
+            _ => log("Settings UI: Unexpected response for DrawToSurface."),
+        }
+    }
+
+    /// Sends a change to the settings service. On success the row shows the
+    /// value the service stored, which for a typed string is the parsed one;
+    /// a refusal shows the service's reason on the status line.
+    fn change(&mut self, key: String, request: SettingsRequest) {
+        match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&request) {
+            Ok(SettingsResponse::Success) => {
+                match self.settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: key.clone() }) {
+                    Ok(SettingsResponse::Value { value, .. }) => self.apply(&key, value),
+                    // The change event brings the value instead.
+                    _ => log(&format!("Settings UI: Could not read {} back.", key)),
+                }
+                self.view.set_status(format!("{} saved.", key));
+            },
+            Ok(SettingsResponse::Error(message)) => self.view.set_status(message),
+            _ => self.view.set_status("The settings service did not answer.".to_string()),
+        }
+    }
+
+    /// Shows a setting's new value. The window follows `ui.scale` like every scaled UI.
+    fn apply(&mut self, key: &str, value: SettingValue) {
+        if let (SCALE_SETTING, SettingValue::Str(name)) = (key, &value) {
+            if let Some(scale) = UiScale::from_name(name) {
+                self.view.set_scale(scale);
+                if let Some(browser) = self.browser.as_mut() {
+                    browser.set_scale(scale);
+                }
+            }
+        }
+        self.view.changed(key, value);
+    }
+
+    /// Returns false once the window is closed.
+    fn handle_event(&mut self, event: UiEvent) -> bool {
+        if let UiEvent::Mouse { timing, .. } | UiEvent::Key { timing, .. } = &event {
+            self.latency.on_event(timing, time::monotonic_nanos());
+        }
+        let action = match (event, self.browser.as_mut()) {
+            (UiEvent::Key { window_id, keycode, event_type: KeyEventType::KeyDown, .. }, Some(browser)) if window_id == self.window_id => {
+                browser.key_down(keycode)
+            },
+            (UiEvent::Key { window_id, keycode, event_type: KeyEventType::KeyDown, text, .. }, None) if window_id == self.window_id => {
+                self.view.key_down(keycode, text.as_deref())
+            },
+            (UiEvent::Mouse { window_id, x, y, button: BUTTON_LEFT, event_type: MouseEventType::MouseDown, .. }, browser) if window_id == self.window_id => {
+                match browser {
+                    Some(browser) => browser.click(x, y),
+                    None => self.view.click(x, y),
+                }
+            },
+            (UiEvent::Mouse { window_id, button, event_type: MouseEventType::Scroll, .. }, Some(browser)) if window_id == self.window_id => {
+                browser.wheel(button == SCROLL_DOWN)
+            },
+            (UiEvent::Resized { window_id, width, height }, browser) if window_id == self.window_id => {
+                self.view.resize(width, height);
+                if let Some(browser) = browser {
+                    browser.resize(width, height);
+                }
+                Action::Redraw
+            },
+            // Nothing to save; every change was sent when it was made.
+            (UiEvent::CloseRequested { window_id }, _) if window_id == self.window_id => Action::Close,
+            _ => Action::None,
+        };
+        match action {
+            Action::None => {},
+            Action::Redraw => self.render(),
+            Action::Set { key, value } => {
+                self.change(key.clone(), SettingsRequest::Set { key, value });
+                self.render();
+            },
+            Action::Reset { key } => {
+                self.change(key.clone(), SettingsRequest::ResetToDefault { key });
+                self.render();
+            },
+            Action::Browse => {
+                self.browser = Some(BrowserView::new(self.view.width, self.view.height, self.view.scale));
+                self.render();
+            },
+            Action::Settings => {
+                self.browser = None;
+                self.render();
+            },
+            Action::Close => {
+                if !matches!(self.client_chan.send_and_recv(&UiRequest::CloseWindow { window_id: self.window_id }), Ok(UiResponse::Success { .. })) {
+                    log("Settings UI: Compositor did not confirm closing the window.");
+                }
+                return false;
+            },
+        }
+        true
+    }
+
+    /// Applies changes other clients made, e.g. the shell's `settings set`.
+    fn handle_bus_events(&mut self) {
+        let Some(bus_events_chan) = self.bus_events_chan.as_mut() else {
+            return;
+        };
+        let mut changed = Vec::new();
+        while let Ok(Some(event_data)) = bus_events_chan.recv_non_blocking() {
+            let Ok(event) = postcard::from_bytes::<Event>(&event_data) else {
+                continue;
+            };
+            if let Ok(SettingChanged { key, value }) = postcard::from_bytes(&event.payload) {
+                changed.push((key, value));
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+        for (key, value) in changed {
+            self.apply(&key, value);
+        }
+        self.render();
+    }
+
+    fn run_loop(&mut self) {
+        log("Settings UI V-Node: Entering main event loop.");
+        self.render();
+        loop {
+            let events = self.ui_events_chan.as_mut().unwrap_or(&mut self.client_chan);
+            if let Ok(Some(event_data)) = events.recv_non_blocking() {
+                match postcard::from_bytes::<UiEvent>(&event_data) {
+                    Ok(event) => {
+                        if !self.handle_event(event) {
+                            return;
+                        }
+                    },
+                    Err(_) => log("Settings UI: Failed to deserialize UI event."),
+                }
+            }
+            self.handle_bus_events();
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    // Channels come from init; the well-known IDs are the fallback:
+    // 12 for UI Compositor communication; our window's events come back on it
+    //    unless init hands out a "ui-events" channel
+    // 14 for Settings
+    // 13 for the Event Bus, which delivers settings changes to our own channel
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut settings_ui = SettingsUiVNode::new(
+        channel("compositor", 12),
+        channels.get("ui-events").copied(),
+        channel("settings", 14),
+        channel("event-bus", 13),
+        channels.get(SELF_CHANNEL).copied(),
+    );
+    settings_ui.run_loop();
+    log("Settings UI V-Node: Window closed, exiting.");
+    loop {}
+}
+
+#[panic_handler]
+pub extern "C" fn panic(info: &PanicInfo) -> ! {
+    log(&format!("Settings UI V-Node panicked! Info: {:?}.", info));
+    loop {}
+}
@@ -0,0 +1,474 @@
+// vnode/settings-ui/src/view.rs
+
+//! The settings window's contents: one row per setting, the selection, the
+//! value being typed and the two footer lines, and painting them.
+//!
+//! Nothing here talks to a service. Key presses come back as an `Action`
+//! for `main.rs` to send to svc://settings, and the value is only shown as
+//! changed once the service's change event (or its answer) comes back
+//! through `changed`, so the window never shows a value the service refused.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use common::ipc::settings_ipc::{SettingEntry, SettingValue};
+use common::keys::{KEY_BACKSPACE, KEY_DELETE, KEY_DOWN, KEY_ENTER, KEY_ESCAPE, KEY_F2, KEY_KP_ENTER, KEY_PAGE_DOWN, KEY_PAGE_UP, KEY_SPACE, KEY_UP};
+use common::text;
+use common::ui::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use common::ui::scale::UiScale;
+
+/// Height of a row: one text line plus 2px padding above and below.
+pub const ROW_HEIGHT: u32 = GLYPH_HEIGHT as u32 + 4;
+/// Lines below the list: the selected setting's type and description, and the status.
+pub const FOOTER_LINES: u32 = 2;
+/// Cells the key column takes before the value; longer keys are cut.
+const KEY_CELLS: usize = 34;
+/// Left padding of every line.
+const TEXT_PADDING: u32 = 4;
+
+const BACKGROUND_COLOR: [u8; 4] = [0x20, 0x20, 0x30, 0xFF];
+const SELECTED_COLOR: [u8; 4] = [0x38, 0x50, 0x88, 0xFF];
+const FOOTER_COLOR: [u8; 4] = [0x30, 0x30, 0x48, 0xFF];
+const TEXT_COLOR: [u8; 4] = [0xF0, 0xF0, 0xF0, 0xFF];
+/// Keys kept from the settings file that the schema doesn't know.
+const UNKNOWN_COLOR: [u8; 4] = [0x90, 0x90, 0x90, 0xFF];
+
+const HELP: &str = "Enter: change  Del: reset to default  F2: file list demo  Esc: close";
+
+/// What the window wants done after an input event.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    None,
+    /// Only the window's contents changed.
+    Redraw,
+    Set { key: String, value: SettingValue },
+    Reset { key: String },
+    /// Show the file list demo (`browser.rs`) in place of the settings.
+    Browse,
+    /// Back from the demo to the settings.
+    Settings,
+    Close,
+}
+
+pub struct SettingsView {
+    entries: Vec<SettingEntry>,
+    selected: usize,
+    /// Index of the first row shown.
+    top: usize,
+    /// Text typed for the selected setting, while it is being edited.
+    edit: Option<String>,
+    /// Last outcome, shown in place of the help line.
+    status: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub scale: UiScale,
+}
+
+/// A value the way `settings get` prints it.
+pub fn format_value(value: &SettingValue) -> String {
+    match value {
+        SettingValue::Bool(b) => b.to_string(),
+        SettingValue::Int(n) => n.to_string(),
+        SettingValue::Str(s) if s.is_empty() => "(empty)".to_string(),
+        SettingValue::Str(s) => s.clone(),
+    }
+}
+
+/// The value Enter sets without any typing: a bool flips and an enum moves
+/// to its next allowed value, wrapping around. `None` for settings that are
+/// typed in, and for unknown keys.
+pub fn next_value(entry: &SettingEntry) -> Option<SettingValue> {
+    if entry.unknown {
+        return None;
+    }
+    match &entry.value {
+        SettingValue::Bool(b) if entry.kind == "bool" => Some(SettingValue::Bool(!b)),
+        SettingValue::Str(current) => {
+            let values: Vec<&str> = entry.kind.strip_prefix("enum(")?.strip_suffix(')')?.split('|').collect();
+            let next = values.iter().position(|v| v == current).map_or(0, |i| (i + 1) % values.len());
+            Some(SettingValue::Str(values[next].to_string()))
+        },
+        _ => None,
+    }
+}
+
+/// The row of a setting, `cells` wide: a marker ('*' changed from the
+/// default, '?' unknown to the schema), the key and the value.
+pub fn row_text(entry: &SettingEntry, edit: Option<&str>, cells: usize) -> String {
+    let marker = if entry.unknown {
+        '?'
+    } else if entry.default.as_ref() != Some(&entry.value) {
+        '*'
+    } else {
+        ' '
+    };
+    let key = text::truncate_to_width(&entry.key, KEY_CELLS - 1);
+    let value = match edit {
+        Some(typed) => format!("{}_", typed),
+        None => format_value(&entry.value),
+    };
+    let line = format!("{} {}{:pad$}{}", marker, key, "", value, pad = KEY_CELLS - text::display_width(key));
+    text::truncate_to_width(&line, cells).to_string()
+}
+
+impl SettingsView {
+    pub fn new(entries: Vec<SettingEntry>, width: u32, height: u32, scale: UiScale) -> Self {
+        Self { entries, selected: 0, top: 0, edit: None, status: None, width, height, scale }
+    }
+
+    pub fn selected(&self) -> Option<&SettingEntry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.edit.is_some()
+    }
+
+    fn row_height(&self) -> u32 {
+        self.scale.px(ROW_HEIGHT)
+    }
+
+    /// Rows that fit above the footer.
+    pub fn visible_rows(&self) -> usize {
+        (self.height / self.row_height()).saturating_sub(FOOTER_LINES) as usize
+    }
+
+    /// Text cells that fit across the window.
+    fn cells(&self) -> usize {
+        (self.width.saturating_sub(2 * self.scale.px(TEXT_PADDING)) / self.scale.px(GLYPH_WIDTH as u32)) as usize
+    }
+
+    /// Moves the selection by `delta` rows, scrolling to keep it in view.
+    /// Ends any edit, which is dropped.
+    fn select(&mut self, delta: i64) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.edit = None;
+        self.selected = (self.selected as i64 + delta).clamp(0, self.entries.len() as i64 - 1) as usize;
+        self.scroll_into_view();
+    }
+
+    fn scroll_into_view(&mut self) {
+        let rows = self.visible_rows().max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+    }
+
+    /// The window changed size; the selection stays in view.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.scroll_into_view();
+    }
+
+    pub fn set_scale(&mut self, scale: UiScale) {
+        self.scale = scale;
+        self.scroll_into_view();
+    }
+
+    /// Shows `message` on the status line until the next key press.
+    pub fn set_status(&mut self, message: String) {
+        self.status = Some(message);
+    }
+
+    /// A key went down. `text` is what it types, if anything.
+    pub fn key_down(&mut self, keycode: u16, text: Option<&str>) -> Action {
+        self.status = None;
+        if let Some(typed) = self.edit.as_mut() {
+            return match keycode {
+                KEY_ENTER | KEY_KP_ENTER => {
+                    let value = SettingValue::Str(self.edit.take().unwrap_or_default());
+                    match self.selected() {
+                        // Typed text goes as a string; the service parses it for the setting's type.
+                        Some(entry) => Action::Set { key: entry.key.clone(), value },
+                        None => Action::Redraw,
+                    }
+                },
+                KEY_ESCAPE => {
+                    self.edit = None;
+                    Action::Redraw
+                },
+                KEY_BACKSPACE => {
+                    typed.pop();
+                    Action::Redraw
+                },
+                _ => match text {
+                    Some(text) if !text.chars().any(char::is_control) => {
+                        typed.push_str(text);
+                        Action::Redraw
+                    },
+                    _ => Action::None,
+                },
+            };
+        }
+
+        let page = self.visible_rows().max(1) as i64;
+        match keycode {
+            KEY_UP => self.select(-1),
+            KEY_DOWN => self.select(1),
+            KEY_PAGE_UP => self.select(-page),
+            KEY_PAGE_DOWN => self.select(page),
+            KEY_ESCAPE => return Action::Close,
+            KEY_F2 => return Action::Browse,
+            KEY_ENTER | KEY_KP_ENTER | KEY_SPACE => {
+                let Some(entry) = self.selected() else {
+                    return Action::None;
+                };
+                if entry.unknown {
+                    self.status = Some(format!("{} is not in the schema and can't be changed.", entry.key));
+                } else if let Some(value) = next_value(entry) {
+                    return Action::Set { key: entry.key.clone(), value };
+                } else {
+                    // Start from the current value; an empty one is typed from scratch.
+                    self.edit = Some(match &entry.value {
+                        SettingValue::Str(s) => s.clone(),
+                        value => format_value(value),
+                    });
+                }
+            },
+            KEY_DELETE => match self.selected() {
+                Some(entry) if entry.unknown => self.status = Some(format!("{} is not in the schema and can't be changed.", entry.key)),
+                Some(entry) => return Action::Reset { key: entry.key.clone() },
+                None => return Action::None,
+            },
+            _ => return Action::None,
+        }
+        Action::Redraw
+    }
+
+    /// Selects the row under a click at client-relative (x, y).
+    pub fn click(&mut self, _x: u32, y: u32) -> Action {
+        let row = (y / self.row_height()) as usize;
+        if row >= self.visible_rows() || self.top + row >= self.entries.len() {
+            return Action::None;
+        }
+        self.select(self.top as i64 + row as i64 - self.selected as i64);
+        Action::Redraw
+    }
+
+    /// Takes a setting's new value, from its change event or the service's
+    /// answer. Returns whether the window has to be redrawn.
+    pub fn changed(&mut self, key: &str, value: SettingValue) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.key == key) {
+            Some(entry) if entry.value != value => {
+                entry.value = value;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Paints the whole window as RGBA rows.
+    pub fn render(&self) -> Vec<u8> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(w * h * 4);
+        for _ in 0..w * h {
+            pixels.extend_from_slice(&BACKGROUND_COLOR);
+        }
+        let row_height = self.row_height();
+        let cells = self.cells();
+
+        for (i, entry) in self.entries.iter().enumerate().skip(self.top).take(self.visible_rows()) {
+            let y = (i - self.top) as u32 * row_height;
+            let selected = i == self.selected;
+            if selected {
+                self.fill(&mut pixels, y, row_height, SELECTED_COLOR);
+            }
+            let edit = self.edit.as_deref().filter(|_| selected);
+            let color = if entry.unknown { UNKNOWN_COLOR } else { TEXT_COLOR };
+            self.draw_text(&mut pixels, y, &row_text(entry, edit, cells), color);
+        }
+
+        let footer_top = self.height.saturating_sub(FOOTER_LINES * row_height);
+        self.fill(&mut pixels, footer_top, FOOTER_LINES * row_height, FOOTER_COLOR);
+        if let Some(entry) = self.selected() {
+            let about = match (&entry.default, entry.unknown) {
+                (_, true) => "Not in the schema; kept as it was found in the settings file.".to_string(),
+                (Some(default), false) => format!("{}, default {}: {}", entry.kind, format_value(default), entry.description),
+                (None, false) => format!("{}: {}", entry.kind, entry.description),
+            };
+            self.draw_text(&mut pixels, footer_top, &about, TEXT_COLOR);
+        }
+        self.draw_text(&mut pixels, footer_top + row_height, self.status.as_deref().unwrap_or(HELP), TEXT_COLOR);
+        pixels
+    }
+
+    /// Fills `height` full-width rows from `y` with `color`.
+    fn fill(&self, pixels: &mut [u8], y: u32, height: u32, color: [u8; 4]) {
+        let w = self.width as usize;
+        for py in y..(y + height).min(self.height) {
+            for px in 0..w {
+                let i = (py as usize * w + px) * 4;
+                pixels[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draws one line of text into the row starting at `y`, clipped to the window.
+    fn draw_text(&self, pixels: &mut [u8], y: u32, line: &str, color: [u8; 4]) {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let scale = self.scale;
+        let (glyph_width, glyph_height) = (scale.px(GLYPH_WIDTH as u32) as usize, scale.px(GLYPH_HEIGHT as u32) as usize);
+        let top = y as usize + (self.row_height() as usize - glyph_height) / 2;
+        let mut cursor = scale.px(TEXT_PADDING) as usize;
+        for c in text::truncate_to_width(line, self.cells()).chars() {
+            let width = text::char_width(c);
+            if width == 0 {
+                continue;
+            }
+            for gy in 0..glyph_height {
+                let row = font::glyph_row_scaled(c, gy, scale);
+                for gx in 0..glyph_width {
+                    let (px, py) = (cursor + gx, top + gy);
+                    if row & (1 << gx) != 0 && px < w && py < h {
+                        let i = (py * w + px) * 4;
+                        pixels[i..i + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+            cursor += width * glyph_width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn entry(key: &str, kind: &str, value: SettingValue, default: SettingValue) -> SettingEntry {
+        SettingEntry { key: key.to_string(), value, kind: kind.to_string(), default: Some(default), description: String::new(), unknown: false }
+    }
+
+    fn text(s: &str) -> SettingValue {
+        SettingValue::Str(s.to_string())
+    }
+
+    fn entries() -> Vec<SettingEntry> {
+        vec![
+            entry("compositor.background_color", "color(#RRGGBB)", text("#202030"), text("#202030")),
+            entry("compositor.show_decorations", "bool", SettingValue::Bool(true), SettingValue::Bool(true)),
+            entry("dns.preferred_family", "enum(ipv4|ipv6|any)", text("any"), text("any")),
+            entry("terminal.font_scale", "int(1..=4)", SettingValue::Int(2), SettingValue::Int(1)),
+            SettingEntry { key: "legacy.key".to_string(), value: text("1"), kind: String::new(), default: None, description: String::new(), unknown: true },
+        ]
+    }
+
+    /// A window with room for `rows` rows and the footer, at 1x.
+    fn view(rows: u32) -> SettingsView {
+        SettingsView::new(entries(), 640, (rows + FOOTER_LINES) * ROW_HEIGHT, UiScale::X1)
+    }
+
+    fn down(view: &mut SettingsView, times: usize) {
+        for _ in 0..times {
+            view.key_down(KEY_DOWN, None);
+        }
+    }
+
+    #[test]
+    fn bools_flip_and_enums_cycle() {
+        let list = entries();
+        assert_eq!(next_value(&list[1]), Some(SettingValue::Bool(false)));
+        assert_eq!(next_value(&list[2]), Some(text("ipv4")));
+        let mut last = list[2].clone();
+        last.value = text("ipv6");
+        assert_eq!(next_value(&last), Some(text("any")));
+        // Typed in, or not changeable.
+        assert_eq!(next_value(&list[0]), None);
+        assert_eq!(next_value(&list[3]), None);
+        assert_eq!(next_value(&list[4]), None);
+    }
+
+    #[test]
+    fn enter_sets_the_next_value_without_changing_the_row() {
+        let mut view = view(10);
+        down(&mut view, 1);
+        assert_eq!(view.key_down(KEY_ENTER, None), Action::Set { key: "compositor.show_decorations".to_string(), value: SettingValue::Bool(false) });
+        // The row shows the new value only once the service has taken it.
+        assert_eq!(view.selected().unwrap().value, SettingValue::Bool(true));
+        assert!(view.changed("compositor.show_decorations", SettingValue::Bool(false)));
+        assert!(!view.changed("compositor.show_decorations", SettingValue::Bool(false)));
+        assert!(!view.changed("no.such.key", SettingValue::Bool(false)));
+        assert_eq!(view.selected().unwrap().value, SettingValue::Bool(false));
+    }
+
+    #[test]
+    fn typed_values_are_sent_as_strings() {
+        let mut view = view(10);
+        assert_eq!(view.key_down(KEY_ENTER, None), Action::Redraw);
+        assert!(view.is_editing());
+        for _ in 0..6 {
+            view.key_down(KEY_BACKSPACE, None);
+        }
+        assert_eq!(view.key_down(0, Some("1")), Action::Redraw);
+        assert_eq!(view.key_down(0, Some("\u{1B}")), Action::None);
+        view.key_down(0, Some("02030"));
+        assert_eq!(view.key_down(KEY_ENTER, None), Action::Set { key: "compositor.background_color".to_string(), value: text("#102030") });
+        assert!(!view.is_editing());
+
+        // Escape drops the edit; a second one closes the window.
+        down(&mut view, 3);
+        view.key_down(KEY_ENTER, None);
+        view.key_down(0, Some("9"));
+        assert_eq!(view.key_down(KEY_ESCAPE, None), Action::Redraw);
+        assert!(!view.is_editing());
+        assert_eq!(view.key_down(KEY_ESCAPE, None), Action::Close);
+    }
+
+    #[test]
+    fn unknown_keys_can_not_be_changed() {
+        let mut view = view(10);
+        down(&mut view, 10);
+        assert_eq!(view.selected().unwrap().key, "legacy.key");
+        assert_eq!(view.key_down(KEY_ENTER, None), Action::Redraw);
+        assert_eq!(view.key_down(KEY_DELETE, None), Action::Redraw);
+        assert!(!view.is_editing());
+        view.key_down(KEY_UP, None);
+        assert_eq!(view.key_down(KEY_DELETE, None), Action::Reset { key: "terminal.font_scale".to_string() });
+    }
+
+    #[test]
+    fn the_selection_stays_in_view() {
+        let mut view = view(2);
+        assert_eq!(view.visible_rows(), 2);
+        down(&mut view, 3);
+        assert_eq!((view.selected, view.top), (3, 2));
+        view.key_down(KEY_PAGE_UP, None);
+        assert_eq!((view.selected, view.top), (1, 1));
+        view.key_down(KEY_PAGE_DOWN, None);
+        view.key_down(KEY_PAGE_DOWN, None);
+        assert_eq!((view.selected, view.top), (4, 3));
+        // Clicks pick a visible row; below the last one nothing happens.
+        assert_eq!(view.click(10, ROW_HEIGHT - 1), Action::Redraw);
+        assert_eq!(view.selected, 3);
+        assert_eq!(view.click(10, 2 * ROW_HEIGHT), Action::None);
+        view.resize(640, (5 + FOOTER_LINES) * ROW_HEIGHT);
+        assert_eq!(view.top, 3);
+    }
+
+    #[test]
+    fn rows_mark_changed_and_unknown_settings() {
+        let list = entries();
+        assert_eq!(row_text(&list[1], None, 80), format!("  {:34}true", "compositor.show_decorations"));
+        assert_eq!(row_text(&list[3], None, 80), format!("* {:34}2", "terminal.font_scale"));
+        assert_eq!(row_text(&list[4], None, 80), format!("? {:34}1", "legacy.key"));
+        assert_eq!(row_text(&list[3], Some("3"), 80), format!("* {:34}3_", "terminal.font_scale"));
+        assert_eq!(row_text(&list[3], None, 10), "* terminal");
+    }
+
+    #[test]
+    fn rendering_fills_the_window() {
+        let mut view = view(3);
+        assert_eq!(view.render().len(), (640 * 5 * ROW_HEIGHT * 4) as usize);
+        view.set_scale(UiScale::X2);
+        assert_eq!(view.visible_rows(), 0);
+        assert_eq!(view.render().len(), (640 * 5 * ROW_HEIGHT * 4) as usize);
+    }
+}
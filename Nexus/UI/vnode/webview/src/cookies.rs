@@ -0,0 +1,692 @@
+// vnode/webview/src/cookies.rs
+
+//! The cookie jar: `Set-Cookie` parsing and `Cookie` headers, after RFC 6265.
+//!
+//! Cookies are kept by the domain they belong to: the `Domain` attribute, or
+//! the host that set them. That domain is also what `aether://cookies` lists
+//! and clears. A `Domain` attribute must domain-match the host that sent it
+//! and, unless it is that host, contain a dot. There is no public suffix list,
+//! so `Domain=co.uk` from `shop.co.uk` is accepted. Nothing runs scripts, so
+//! every cookie is treated as `HttpOnly`.
+//!
+//! A domain holds at most `MAX_COOKIES_PER_DOMAIN` cookies and
+//! `MAX_BYTES_PER_DOMAIN` bytes of names and values. Past either, the cookie
+//! that expires soonest is dropped; session cookies go last, oldest first.
+//!
+//! With third-party cookies blocked, a cookie whose domain the document's host
+//! doesn't domain-match is neither stored nor sent.
+//!
+//! Expiry is wall-clock time and is checked whenever the jar is used, so
+//! cookies whose time passed during a jump of the clock are gone by the next
+//! request. Without a clock, `Expires` and `Max-Age` can't be judged and such
+//! cookies are kept for the session only.
+//!
+//! Cookies with an expiry are saved to `COOKIES_PATH`, at most every
+//! `SAVE_INTERVAL_TICKS` while they changed. `load` leaves out the ones that
+//! expired while the WebView wasn't running.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use common::ipc::vfs_ipc::{Fd, VfsRequest, VfsResponse};
+use common::ipc::vfs_stream::VfsStreams;
+use common::ipc::vfs_tx::VfsTx;
+use common::ipc::vnode::VNodeChannel;
+use common::time::DateTime;
+use common::url::{domain_matches, Url};
+
+pub const COOKIES_PATH: &str = "/data/cookies";
+pub const BLOCK_THIRD_PARTY_KEY: &str = "webview.block_third_party_cookies";
+
+pub const MAX_COOKIES_PER_DOMAIN: usize = 50;
+pub const MAX_BYTES_PER_DOMAIN: usize = 16 * 1024;
+/// Longer `Set-Cookie` headers are ignored.
+pub const MAX_COOKIE_BYTES: usize = 4096;
+pub const SAVE_INTERVAL_TICKS: u64 = 30 * 100; // 30 s
+const FORMAT_VERSION: u32 = 1;
+const MAX_STATE_FILE_SIZE: usize = 1024 * 1024;
+const ENOENT: i32 = 2;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Set without `Domain`: sent to `domain` itself, not its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// Epoch seconds; `None` for a session cookie.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    /// Creation order. A cookie that replaces another keeps its number.
+    pub created: u64,
+}
+
+impl Cookie {
+    fn bytes(&self) -> usize {
+        self.name.len() + self.value.len()
+    }
+
+    fn expired(&self, now_secs: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+    /// Not `name=value`, or an empty name.
+    Malformed,
+    TooLarge,
+    /// Only `http` and `https` responses set cookies.
+    NotHttp,
+    /// The `Domain` attribute isn't the host or a domain above it.
+    DomainMismatch(String),
+    /// The `Domain` attribute has no dot, like `com`.
+    TopLevelDomain(String),
+    /// `Secure` over plain `http`.
+    SecureOverHttp,
+    /// Blocked as a third-party cookie.
+    ThirdParty(String),
+}
+
+impl fmt::Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("malformed"),
+            Self::TooLarge => write!(f, "longer than {} bytes", MAX_COOKIE_BYTES),
+            Self::NotHttp => f.write_str("not an HTTP response"),
+            Self::DomainMismatch(domain) => write!(f, "domain {} doesn't match the host", domain),
+            Self::TopLevelDomain(domain) => write!(f, "domain {} is a top-level domain", domain),
+            Self::SecureOverHttp => f.write_str("Secure over http"),
+            Self::ThirdParty(domain) => write!(f, "third-party cookie for {}", domain),
+        }
+    }
+}
+
+/// One line of the `aether://cookies` listing.
+pub struct DomainSummary {
+    pub domain: String,
+    pub cookies: usize,
+    pub bytes: usize,
+    /// How many of them are saved across restarts.
+    pub persistent: usize,
+}
+
+/// What `load` found.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub cookies: usize,
+    pub expired: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCookies {
+    version: u32,
+    cookies: Vec<Cookie>,
+}
+
+/// The path a cookie without a `Path` attribute gets: the request path up to
+/// its last `/` (RFC 6265, 5.1.4).
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(end) => String::from(&request_path[..end]),
+    }
+}
+
+/// True if a cookie with `cookie_path` is sent for `request_path` (RFC 6265, 5.1.4).
+pub fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+/// The number at the start of `token` if it has `min` to `max` digits.
+fn leading_number(token: &str, min: usize, max: usize) -> Option<u32> {
+    let digits = token.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < min || digits > max {
+        return None;
+    }
+    token[..digits].parse().ok()
+}
+
+fn parse_time(token: &str) -> Option<(u8, u8, u8)> {
+    let mut fields = token.splitn(3, ':');
+    let (hour, minute, second) = (fields.next()?, fields.next()?, fields.next()?);
+    let whole = |field: &str| if (1..=2).contains(&field.len()) { field.parse::<u8>().ok() } else { None };
+    Some((whole(hour)?, whole(minute)?, leading_number(second, 1, 2)? as u8))
+}
+
+/// Parses an `Expires` value with the lenient algorithm of RFC 6265, 5.1.1,
+/// which takes every date format servers are known to send. Dates before
+/// 1970 come back as 0, i.e. long past.
+pub fn parse_cookie_date(text: &str) -> Option<u64> {
+    let is_delimiter = |c: char| {
+        c == '\t' || (' '..='/').contains(&c) || (';'..='@').contains(&c) || ('['..='`').contains(&c) || ('{'..='~').contains(&c)
+    };
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in text.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(found) = parse_time(token) {
+                time = Some(found);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(found) = leading_number(token, 1, 2) {
+                day = Some(found);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let name = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(found) = MONTHS.iter().position(|month| Some(*month) == name.as_deref()) {
+                month = Some(found as u8 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(found) = leading_number(token, 2, 4) {
+                year = Some(found);
+            }
+        }
+    }
+
+    let year = match year? {
+        year @ 70..=99 => year + 1900,
+        year @ 0..=69 => year + 2000,
+        year => year,
+    };
+    let (hour, minute, second) = time?;
+    let (day, month) = (day?, month?);
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    if year < 1970 {
+        return Some(0);
+    }
+    DateTime { year, month, day: day as u8, hour, minute, second }.to_epoch()
+}
+
+/// Parses one `Set-Cookie` header of a response to `url` (RFC 6265, 5.2 and 5.3).
+/// `now_secs` is 0 without a wall clock. A cookie already expired is returned
+/// as such; storing it deletes the cookie it replaces.
+pub fn parse_set_cookie(header: &str, url: &Url, now_secs: u64, created: u64) -> Result<Cookie, CookieError> {
+    if header.len() > MAX_COOKIE_BYTES {
+        return Err(CookieError::TooLarge);
+    }
+    if url.scheme != "http" && url.scheme != "https" {
+        return Err(CookieError::NotHttp);
+    }
+    let (pair, attributes) = header.split_once(';').unwrap_or((header, ""));
+    let (name, value) = pair.split_once('=').ok_or(CookieError::Malformed)?;
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() {
+        return Err(CookieError::Malformed);
+    }
+
+    // The last of each attribute counts.
+    let (mut expires, mut max_age, mut domain, mut path, mut secure) = (None, None, None, None, false);
+    for attribute in attributes.split(';') {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let (key, value) = (key.trim(), value.trim());
+        if key.eq_ignore_ascii_case("expires") {
+            if let Some(date) = parse_cookie_date(value) {
+                expires = Some(date);
+            }
+        } else if key.eq_ignore_ascii_case("max-age") {
+            let digits = value.strip_prefix('-').unwrap_or(value);
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                // Too many digits to parse is as good as forever.
+                max_age = Some(value.parse::<i64>().unwrap_or(if value.starts_with('-') { i64::MIN } else { i64::MAX }));
+            }
+        } else if key.eq_ignore_ascii_case("domain") {
+            if !value.is_empty() {
+                domain = Some(value.trim_start_matches('.').to_ascii_lowercase());
+            }
+        } else if key.eq_ignore_ascii_case("path") {
+            path = if value.starts_with('/') { Some(String::from(value)) } else { None };
+        } else if key.eq_ignore_ascii_case("secure") {
+            secure = true;
+        }
+        // HttpOnly is how every cookie is treated; other attributes are ignored.
+    }
+
+    // Max-Age wins over Expires. A zero or negative one deletes even without a clock.
+    let expires = match max_age {
+        Some(age) if age <= 0 => Some(0),
+        _ if now_secs == 0 => None,
+        Some(age) => Some(now_secs.saturating_add(age as u64)),
+        None => expires,
+    };
+    let (domain, host_only) = match domain {
+        Some(domain) if !domain_matches(&url.host, &domain) => return Err(CookieError::DomainMismatch(domain)),
+        Some(domain) if !domain.contains('.') && domain != url.host => return Err(CookieError::TopLevelDomain(domain)),
+        Some(domain) => (domain, false),
+        None => (url.host.clone(), true),
+    };
+    if secure && url.scheme != "https" {
+        return Err(CookieError::SecureOverHttp);
+    }
+    Ok(Cookie {
+        name: String::from(name),
+        value: String::from(value),
+        domain,
+        host_only,
+        path: path.unwrap_or_else(|| default_path(&url.path)),
+        expires,
+        secure,
+        created,
+    })
+}
+
+pub struct CookieJar {
+    domains: BTreeMap<String, Vec<Cookie>>,
+    /// `webview.block_third_party_cookies`.
+    pub block_third_party: bool,
+    next_created: u64,
+    dirty: bool, // The persistent cookies changed since the last save
+    last_save: u64,
+}
+
+impl CookieJar {
+    pub fn new(block_third_party: bool) -> Self {
+        Self { domains: BTreeMap::new(), block_third_party, next_created: 0, dirty: false, last_save: 0 }
+    }
+
+    /// Reads the saved cookies. A missing file is an empty jar; one that
+    /// can't be read or decoded is reported and treated the same.
+    pub fn load(vfs_chan: &mut VNodeChannel, block_third_party: bool, now_secs: u64, now: u64) -> (Self, Result<LoadReport, String>) {
+        let mut jar = Self::new(block_third_party);
+        jar.last_save = now;
+        let mut report = LoadReport::default();
+        let saved = match read_saved(vfs_chan) {
+            Ok(Some(saved)) if saved.version == FORMAT_VERSION => saved,
+            Ok(Some(saved)) => return (jar, Err(format!("{}: format version {} is not {}", COOKIES_PATH, saved.version, FORMAT_VERSION))),
+            Ok(None) => return (jar, Ok(report)),
+            Err(e) => return (jar, Err(e)),
+        };
+        for cookie in saved.cookies {
+            jar.next_created = jar.next_created.max(cookie.created + 1);
+            // Session cookies are never saved; one in the file didn't come from us.
+            if cookie.expires.is_none() || cookie.expired(now_secs) {
+                report.expired += 1;
+                continue;
+            }
+            jar.insert(cookie, now_secs);
+            report.cookies += 1;
+        }
+        // What expired shouldn't come back from the old file.
+        jar.dirty = report.expired > 0;
+        (jar, Ok(report))
+    }
+
+    /// Takes the `Set-Cookie` headers of a response to `url`, fetched for the
+    /// document at `document`. Returns the headers that were ignored, and why.
+    pub fn store(&mut self, url: &Url, document: &Url, headers: &[String], now_secs: u64) -> Vec<(String, CookieError)> {
+        let mut ignored = Vec::new();
+        for header in headers {
+            let cookie = parse_set_cookie(header, url, now_secs, self.next_created).and_then(|cookie| {
+                if self.block_third_party && !domain_matches(&document.host, &cookie.domain) {
+                    Err(CookieError::ThirdParty(cookie.domain))
+                } else {
+                    Ok(cookie)
+                }
+            });
+            match cookie {
+                Ok(cookie) => {
+                    self.next_created += 1;
+                    self.insert(cookie, now_secs);
+                },
+                Err(e) => ignored.push((header.clone(), e)),
+            }
+        }
+        ignored
+    }
+
+    /// Adds `cookie`, replacing the one with the same name, domain and path,
+    /// and evicts past the domain's limits. An expired cookie only deletes.
+    fn insert(&mut self, mut cookie: Cookie, now_secs: u64) {
+        let domain = cookie.domain.clone();
+        let cookies = self.domains.entry(domain.clone()).or_default();
+        if let Some(i) = cookies.iter().position(|old| old.name == cookie.name && old.path == cookie.path) {
+            let old = cookies.remove(i);
+            cookie.created = old.created;
+            self.dirty |= old.expires.is_some();
+        }
+        if cookie.expired(now_secs) {
+            if cookies.is_empty() {
+                self.domains.remove(&domain);
+            }
+            return;
+        }
+        self.dirty |= cookie.expires.is_some();
+        cookies.push(cookie);
+
+        while cookies.len() > MAX_COOKIES_PER_DOMAIN || cookies.iter().map(Cookie::bytes).sum::<usize>() > MAX_BYTES_PER_DOMAIN {
+            let soonest = cookies.iter().enumerate()
+                .min_by_key(|(_, cookie)| (cookie.expires.unwrap_or(u64::MAX), cookie.created))
+                .map(|(i, _)| i);
+            match soonest {
+                Some(i) => {
+                    self.dirty |= cookies.remove(i).expires.is_some();
+                },
+                None => break,
+            }
+        }
+    }
+
+    /// Drops every cookie whose expiry has passed.
+    fn purge_expired(&mut self, now_secs: u64) {
+        let mut dirty = false;
+        self.domains.retain(|_, cookies| {
+            cookies.retain(|cookie| {
+                let expired = cookie.expired(now_secs);
+                dirty |= expired;
+                !expired
+            });
+            !cookies.is_empty()
+        });
+        self.dirty |= dirty;
+    }
+
+    /// The `Cookie` header for a request to `url` made for the document at
+    /// `document`, or `None` if no cookie applies. Longer paths come first,
+    /// then older cookies.
+    pub fn header_for(&mut self, url: &Url, document: &Url, now_secs: u64) -> Option<String> {
+        self.purge_expired(now_secs);
+        let mut matching: Vec<&Cookie> = self.domains.iter()
+            .filter(|(domain, _)| domain_matches(&url.host, domain))
+            .filter(|(domain, _)| !self.block_third_party || domain_matches(&document.host, domain))
+            .flat_map(|(_, cookies)| cookies.iter())
+            .filter(|cookie| !cookie.host_only || cookie.domain == url.host)
+            .filter(|cookie| path_matches(&url.path, &cookie.path))
+            .filter(|cookie| !cookie.secure || url.scheme == "https")
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        let pairs: Vec<String> = matching.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect();
+        Some(pairs.join("; "))
+    }
+
+    /// The domains holding cookies, in name order.
+    pub fn domains(&mut self, now_secs: u64) -> Vec<DomainSummary> {
+        self.purge_expired(now_secs);
+        self.domains.iter().map(|(domain, cookies)| DomainSummary {
+            domain: domain.clone(),
+            cookies: cookies.len(),
+            bytes: cookies.iter().map(Cookie::bytes).sum(),
+            persistent: cookies.iter().filter(|cookie| cookie.expires.is_some()).count(),
+        }).collect()
+    }
+
+    /// Deletes every cookie of `domain`. Returns how many there were.
+    pub fn clear(&mut self, domain: &str) -> usize {
+        match self.domains.remove(domain) {
+            Some(cookies) => {
+                self.dirty |= cookies.iter().any(|cookie| cookie.expires.is_some());
+                cookies.len()
+            },
+            None => 0,
+        }
+    }
+
+    /// True if the persistent cookies changed and it is time to write them.
+    pub fn save_due(&self, now: u64) -> bool {
+        self.dirty && now.saturating_sub(self.last_save) >= SAVE_INTERVAL_TICKS
+    }
+
+    /// Replaces the saved cookies with the ones that have an expiry.
+    /// A failed save is tried again after the next interval.
+    pub fn save(&mut self, vfs_chan: &mut VNodeChannel, now: u64) -> Result<(), String> {
+        self.last_save = now;
+        let saved = SavedCookies {
+            version: FORMAT_VERSION,
+            cookies: self.domains.values().flatten().filter(|cookie| cookie.expires.is_some()).cloned().collect(),
+        };
+        let data = postcard::to_allocvec(&saved).map_err(|_| "Failed to encode the cookies".to_string())?;
+        VfsTx::begin(vfs_chan).and_then(|mut tx| {
+            tx.write_file(COOKIES_PATH, data)?;
+            tx.commit()
+        })?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Reads and decodes the saved cookies. `None` if there are none.
+fn read_saved(vfs_chan: &mut VNodeChannel) -> Result<Option<SavedCookies>, String> {
+    let fd: Fd = match vfs_chan.send_and_recv::<VfsRequest, VfsResponse>(&VfsRequest::Open { path: COOKIES_PATH.to_string(), flags: 0 /* O_RDONLY */ }) {
+        Ok(VfsResponse::Success(fd)) => fd as Fd,
+        Ok(VfsResponse::Error { code: ENOENT, .. }) => return Ok(None),
+        Ok(VfsResponse::Error { message, .. }) => return Err(format!("{}: {}", COOKIES_PATH, message)),
+        _ => return Err("Unexpected response from VFS".to_string()),
+    };
+    let mut streams = VfsStreams::new(vfs_chan);
+    let data = streams.open_read(fd, 0, MAX_STATE_FILE_SIZE as u64 + 1)
+        .and_then(|stream| streams.read_to_end(stream, MAX_STATE_FILE_SIZE));
+    let _ = streams.request(&VfsRequest::Close { fd });
+    let data = data.map_err(|e| format!("{}: {}", COOKIES_PATH, e))?;
+    postcard::from_bytes(&data).map(Some).map_err(|_| format!("{} is corrupt", COOKIES_PATH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).unwrap()
+    }
+
+    fn set(jar: &mut CookieJar, at: &str, headers: &[&str], now_secs: u64) -> Vec<CookieError> {
+        let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+        jar.store(&url(at), &url(at), &headers, now_secs).into_iter().map(|(_, e)| e).collect()
+    }
+
+    fn sent(jar: &mut CookieJar, to: &str, now_secs: u64) -> Option<String> {
+        jar.header_for(&url(to), &url(to), now_secs)
+    }
+
+    #[test]
+    fn domain_cookies_reach_subdomains_and_host_cookies_do_not() {
+        let mut jar = CookieJar::new(false);
+        assert!(set(&mut jar, "http://www.example.com/", &["a=1; Domain=.Example.COM", "b=2"], NOW).is_empty());
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW).as_deref(), Some("a=1"));
+        assert_eq!(sent(&mut jar, "http://a.b.example.com/", NOW).as_deref(), Some("a=1"));
+        assert_eq!(sent(&mut jar, "http://www.example.com/", NOW).as_deref(), Some("a=1; b=2"));
+        assert_eq!(sent(&mut jar, "http://notexample.com/", NOW), None);
+        assert_eq!(sent(&mut jar, "http://sub.www.example.com/", NOW).as_deref(), Some("a=1"));
+        let domains: Vec<String> = jar.domains(NOW).into_iter().map(|summary| summary.domain).collect();
+        assert_eq!(domains, vec!["example.com", "www.example.com"]);
+    }
+
+    #[test]
+    fn domains_that_do_not_cover_the_host_are_refused() {
+        let mut jar = CookieJar::new(false);
+        assert_eq!(
+            set(&mut jar, "http://example.org/", &["a=1; Domain=example.com", "b=1; Domain=www.example.org"], NOW),
+            vec![CookieError::DomainMismatch("example.com".into()), CookieError::DomainMismatch("www.example.org".into())]
+        );
+        assert_eq!(set(&mut jar, "http://example.com/", &["a=1; Domain=com"], NOW), vec![CookieError::TopLevelDomain("com".into())]);
+        assert!(set(&mut jar, "http://localhost/", &["a=1; Domain=localhost"], NOW).is_empty());
+        assert_eq!(sent(&mut jar, "http://localhost/", NOW).as_deref(), Some("a=1"));
+
+        // An IP address only ever matches itself.
+        assert_eq!(set(&mut jar, "http://10.0.0.1/", &["c=1; Domain=0.0.1"], NOW), vec![CookieError::DomainMismatch("0.0.1".into())]);
+        assert!(set(&mut jar, "http://10.0.0.1/", &["d=1; Domain=10.0.0.1"], NOW).is_empty());
+        assert_eq!(sent(&mut jar, "http://10.0.0.1/", NOW).as_deref(), Some("d=1"));
+        assert_eq!(sent(&mut jar, "http://110.0.0.1/", NOW), None);
+    }
+
+    #[test]
+    fn paths_match_themselves_and_what_is_below() {
+        assert!(path_matches("/docs", "/docs"));
+        assert!(path_matches("/docs/a", "/docs"));
+        assert!(path_matches("/docs/a", "/docs/"));
+        assert!(!path_matches("/docsx", "/docs"));
+        assert!(!path_matches("/", "/docs"));
+        assert!(path_matches("/anything", "/"));
+        assert_eq!(default_path("/a/b/c"), "/a/b");
+        assert_eq!(default_path("/a"), "/");
+        assert_eq!(default_path(""), "/");
+
+        let mut jar = CookieJar::new(false);
+        set(&mut jar, "http://example.com/a/b/c", &["deep=1", "top=1; Path=/", "docs=1; Path=/docs", "bad=1; Path=docs"], NOW);
+        // A `Path` that doesn't start with `/` is the default path.
+        assert_eq!(sent(&mut jar, "http://example.com/a/b/x", NOW).as_deref(), Some("deep=1; bad=1; top=1"));
+        assert_eq!(sent(&mut jar, "http://example.com/a/bx", NOW).as_deref(), Some("top=1"));
+        assert_eq!(sent(&mut jar, "http://example.com/docs/a", NOW).as_deref(), Some("docs=1; top=1"));
+        assert_eq!(sent(&mut jar, "http://example.com/docsx", NOW).as_deref(), Some("top=1"));
+    }
+
+    #[test]
+    fn cookie_dates_in_every_known_format_agree() {
+        let expected = Some(784_111_777);
+        assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_cookie_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_cookie_date("Thu, 01 Jan 1960 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_cookie_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_cookie_date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse_cookie_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn expired_cookies_are_dropped_when_the_clock_passes_them() {
+        let mut jar = CookieJar::new(false);
+        set(&mut jar, "http://example.com/", &["a=1; Max-Age=60", "b=2; Expires=Sun, 06 Nov 2050 08:49:37 GMT"], NOW);
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW + 59).as_deref(), Some("a=1; b=2"));
+        // A jump of the clock expires `a` before the next request.
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW + 3600).as_deref(), Some("b=2"));
+        assert_eq!(jar.domains(NOW + 3600)[0].cookies, 1);
+
+        // A cookie already expired deletes its namesake, whatever the attribute.
+        set(&mut jar, "http://example.com/", &["b=x; Expires=Sun, 06 Nov 1994 08:49:37 GMT"], NOW + 3600);
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW + 3600), None);
+        assert!(jar.domains(NOW + 3600).is_empty());
+
+        // Max-Age wins over Expires.
+        set(&mut jar, "http://example.com/", &["c=1; Max-Age=10; Expires=Sun, 06 Nov 2050 08:49:37 GMT"], NOW);
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW + 10), None);
+    }
+
+    #[test]
+    fn without_a_clock_expiring_cookies_last_the_session() {
+        let page = url("http://example.com/");
+        let cookie = parse_set_cookie("a=1; Max-Age=60", &page, 0, 0).unwrap();
+        assert_eq!(cookie.expires, None);
+        assert_eq!(parse_set_cookie("a=1; Expires=Sun, 06 Nov 2050 08:49:37 GMT", &page, 0, 0).unwrap().expires, None);
+        assert_eq!(parse_set_cookie("a=1; Max-Age=0", &page, 0, 0).unwrap().expires, Some(0));
+        assert_eq!(parse_set_cookie("a=1; Max-Age=-5", &page, NOW, 0).unwrap().expires, Some(0));
+
+        let mut jar = CookieJar::new(false);
+        set(&mut jar, "http://example.com/", &["a=1; Max-Age=60"], 0);
+        assert_eq!(sent(&mut jar, "http://example.com/", 0).as_deref(), Some("a=1"));
+        set(&mut jar, "http://example.com/", &["a=1; Max-Age=0"], 0);
+        assert_eq!(sent(&mut jar, "http://example.com/", 0), None);
+    }
+
+    #[test]
+    fn secure_cookies_need_https_both_ways() {
+        let mut jar = CookieJar::new(false);
+        assert_eq!(set(&mut jar, "http://example.com/", &["s=1; Secure"], NOW), vec![CookieError::SecureOverHttp]);
+        assert!(set(&mut jar, "https://example.com/", &["s=1; secure", "p=1"], NOW).is_empty());
+        assert_eq!(sent(&mut jar, "https://example.com/", NOW).as_deref(), Some("s=1; p=1"));
+        assert_eq!(sent(&mut jar, "http://example.com/", NOW).as_deref(), Some("p=1"));
+    }
+
+    #[test]
+    fn malformed_and_foreign_headers_are_ignored() {
+        let page = url("http://example.com/");
+        assert_eq!(parse_set_cookie("novalue", &page, NOW, 0), Err(CookieError::Malformed));
+        assert_eq!(parse_set_cookie(" =1", &page, NOW, 0), Err(CookieError::Malformed));
+        assert_eq!(parse_set_cookie(&"a=".repeat(MAX_COOKIE_BYTES), &page, NOW, 0), Err(CookieError::TooLarge));
+        assert_eq!(parse_set_cookie("a=1", &url("aether://cookies"), NOW, 0), Err(CookieError::NotHttp));
+        let cookie = parse_set_cookie(" a = b=c ; HttpOnly; SameSite=Lax", &page, NOW, 7).unwrap();
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str(), cookie.created), ("a", "b=c", 7));
+    }
+
+    #[test]
+    fn a_full_domain_evicts_the_cookie_expiring_soonest() {
+        let mut jar = CookieJar::new(false);
+        set(&mut jar, "http://example.com/", &["session=1"], NOW);
+        for i in 1..MAX_COOKIES_PER_DOMAIN {
+            let header = format!("c{}=1; Max-Age={}", i, 1000 + i);
+            set(&mut jar, "http://example.com/", &[&header], NOW);
+        }
+        assert_eq!(jar.domains(NOW)[0].cookies, MAX_COOKIES_PER_DOMAIN);
+
+        // The 51st goes in and `c1`, the soonest to expire, goes out.
+        set(&mut jar, "http://example.com/", &["late=1; Max-Age=99999"], NOW);
+        let header = sent(&mut jar, "http://example.com/", NOW).unwrap();
+        assert!(!header.split("; ").any(|pair| pair == "c1=1"));
+        assert!(header.contains("c2=1") && header.contains("late=1") && header.contains("session=1"));
+        assert_eq!(jar.domains(NOW)[0].cookies, MAX_COOKIES_PER_DOMAIN);
+
+        // Session cookies only go once no persistent one is left; then oldest first.
+        let mut jar = CookieJar::new(false);
+        for i in 0..=MAX_COOKIES_PER_DOMAIN {
+            let header = format!("s{}=1", i);
+            set(&mut jar, "http://example.com/", &[&header], NOW);
+        }
+        let header = sent(&mut jar, "http://example.com/", NOW).unwrap();
+        assert!(header.starts_with("s1=1; s2=1"));
+        assert!(header.ends_with(&format!("s{}=1", MAX_COOKIES_PER_DOMAIN)));
+
+        // The byte limit evicts too: five 4000-byte cookies don't fit in 16 KiB.
+        let mut jar = CookieJar::new(false);
+        let big = "v".repeat(3999);
+        for name in ["a", "b", "c", "d", "e"] {
+            let header = format!("{}={}; Max-Age=100", name, big);
+            set(&mut jar, "http://example.com/", &[&header], NOW);
+        }
+        let summary = &jar.domains(NOW)[0];
+        assert_eq!((summary.cookies, summary.bytes), (4, 16000));
+        assert!(!sent(&mut jar, "http://example.com/", NOW).unwrap().starts_with("a="));
+    }
+
+    #[test]
+    fn blocked_third_party_cookies_are_neither_stored_nor_sent() {
+        let mut jar = CookieJar::new(true);
+        let (page, cdn) = (url("http://example.com/"), url("http://cdn.example.net/lib.js"));
+        let ignored = jar.store(&cdn, &page, &["t=1".to_string()], NOW);
+        assert_eq!(ignored.into_iter().map(|(_, e)| e).collect::<Vec<_>>(), vec![CookieError::ThirdParty("cdn.example.net".into())]);
+        // Subdomains of the page's host are first-party.
+        assert!(jar.store(&url("http://www.example.com/"), &url("http://www.example.com/"), &["f=1; Domain=example.com".to_string()], NOW).is_empty());
+        assert_eq!(jar.store(&url("http://cdn.example.com/"), &url("http://www.example.com/"), &["n=1".to_string()], NOW).len(), 1);
+
+        jar.block_third_party = false;
+        assert!(jar.store(&cdn, &page, &["t=1".to_string()], NOW).is_empty());
+        assert_eq!(jar.header_for(&cdn, &page, NOW).as_deref(), Some("t=1"));
+        jar.block_third_party = true;
+        assert_eq!(jar.header_for(&cdn, &page, NOW), None);
+        assert_eq!(jar.header_for(&cdn, &cdn, NOW).as_deref(), Some("t=1"));
+    }
+
+    #[test]
+    fn clearing_a_domain_removes_only_its_cookies() {
+        let mut jar = CookieJar::new(false);
+        set(&mut jar, "http://a.com/", &["x=1; Max-Age=100", "y=1"], NOW);
+        set(&mut jar, "http://b.com/", &["z=1"], NOW);
+        let summary = &jar.domains(NOW)[0];
+        assert_eq!((summary.cookies, summary.persistent, summary.bytes), (2, 1, 4));
+        assert_eq!(jar.clear("a.com"), 2);
+        assert_eq!(jar.clear("a.com"), 0);
+        assert_eq!(sent(&mut jar, "http://b.com/", NOW).as_deref(), Some("z=1"));
+    }
+}
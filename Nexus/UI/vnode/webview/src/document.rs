@@ -0,0 +1,269 @@
+// vnode/webview/src/document.rs
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use common::ui::html_parser::DomNode;
+use common::ui::image::Image;
+use common::ui::layout::LayoutBox;
+use common::ui_protocol::CursorShape;
+use common::url::Url;
+
+use crate::subresources::SubresourceLoad;
+
+/// Pixels one wheel step scrolls.
+pub const SCROLL_STEP: u32 = 40;
+
+/// Everything the WebView keeps for one compositor window.
+pub struct DocumentState {
+    pub url: String,
+    pub dom: DomNode,
+    /// The document's own CSS; fetched stylesheets are applied after it.
+    pub inline_css: String,
+    pub computed_styles: BTreeMap<String, String>,
+    pub layout: LayoutBox,
+    pub width: u32,
+    pub height: u32,
+    /// Vertical scroll offset in pixels.
+    pub scroll_y: u32,
+    /// Previously visited URLs in this window, most recent last.
+    pub history: Vec<String>,
+    /// Decoded images by `src` as written.
+    pub images: BTreeMap<String, Image>,
+    /// Stylesheets and images still being fetched. The window isn't painted
+    /// until they are in, or the deadline passes.
+    pub loading: Option<SubresourceLoad>,
+    /// The cursor shape last asked of the compositor for this window.
+    pub cursor: CursorShape,
+    /// A link the left button went down on, followed on release or dragged
+    /// away as a URL.
+    pub press: Option<LinkPress>,
+    /// How the window answered the drag over it, if one is.
+    pub drag_accepted: Option<bool>,
+}
+
+impl DocumentState {
+    /// Intrinsic sizes of the loaded images, for the layout engine.
+    pub fn image_sizes(&self) -> BTreeMap<String, (u32, u32)> {
+        self.images.iter().map(|(src, image)| (src.clone(), (image.width, image.height))).collect()
+    }
+
+    /// The anchor under a point in window coordinates, taking the scroll offset into account.
+    pub fn anchor_at(&self, x: u32, y: u32) -> Option<AnchorHit> {
+        find_anchor_at(&self.dom, &self.layout, x, y + self.scroll_y)
+    }
+
+    /// Scrolls one step up or down, no further than the document goes.
+    /// Returns whether the offset changed, i.e. the window needs a repaint.
+    pub fn scroll(&mut self, up: bool) -> bool {
+        let max_scroll = self.layout.height.saturating_sub(self.height);
+        let scroll_y = if up { self.scroll_y.saturating_sub(SCROLL_STEP) } else { (self.scroll_y + SCROLL_STEP).min(max_scroll) };
+        let changed = scroll_y != self.scroll_y;
+        self.scroll_y = scroll_y;
+        changed
+    }
+}
+
+/// A press on a link: where it went down, in window coordinates.
+#[derive(Debug)]
+pub struct LinkPress {
+    pub anchor: AnchorHit,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// An anchor hit by a pointer event.
+#[derive(Debug, PartialEq)]
+pub struct AnchorHit {
+    pub href: String,
+    pub target: Option<String>,
+}
+
+impl AnchorHit {
+    /// True if following the link should open a new window.
+    pub fn opens_new_window(&self) -> bool {
+        self.target.as_deref() == Some("_blank")
+    }
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// Walks the DOM and its layout tree in parallel and returns the innermost
+/// `<a href>` whose box contains the point (document coordinates).
+pub fn find_anchor_at(dom: &DomNode, layout: &LayoutBox, x: u32, y: u32) -> Option<AnchorHit> {
+    let inside = x >= layout.x && x < layout.x + layout.width && y >= layout.y && y < layout.y + layout.height;
+    if !inside {
+        return None;
+    }
+    match dom {
+        DomNode::Element { tag_name, attributes, children } => {
+            // Layout children are produced in DOM order, so they can be zipped.
+            for (child_dom, child_layout) in children.iter().zip(layout.children.iter()) {
+                if let Some(hit) = find_anchor_at(child_dom, child_layout, x, y) {
+                    return Some(hit);
+                }
+            }
+            if tag_name == "a" {
+                attribute(attributes, "href").map(|href| AnchorHit {
+                    href: String::from(href),
+                    target: attribute(attributes, "target").map(String::from),
+                })
+            } else {
+                None
+            }
+        }
+        DomNode::Text(_) => None,
+    }
+}
+
+/// The cursor shape for the innermost element under the point (document
+/// coordinates) that has one: a hand over `<a href>`, a text beam over
+/// `<input>` and `<textarea>`. The arrow elsewhere.
+pub fn cursor_at(dom: &DomNode, layout: &LayoutBox, x: u32, y: u32) -> CursorShape {
+    element_cursor(dom, layout, x, y).unwrap_or(CursorShape::Arrow)
+}
+
+fn element_cursor(dom: &DomNode, layout: &LayoutBox, x: u32, y: u32) -> Option<CursorShape> {
+    let inside = x >= layout.x && x < layout.x + layout.width && y >= layout.y && y < layout.y + layout.height;
+    if !inside {
+        return None;
+    }
+    match dom {
+        DomNode::Element { tag_name, attributes, children } => {
+            for (child_dom, child_layout) in children.iter().zip(layout.children.iter()) {
+                if let Some(shape) = element_cursor(child_dom, child_layout, x, y) {
+                    return Some(shape);
+                }
+            }
+            match tag_name.as_str() {
+                "a" if attribute(attributes, "href").is_some() => Some(CursorShape::Hand),
+                "input" | "textarea" => Some(CursorShape::TextBeam),
+                _ => None,
+            }
+        }
+        DomNode::Text(_) => None,
+    }
+}
+
+/// Resolves `href` against the URL of the current document. An `href` that
+/// can't be resolved is used as it is.
+pub fn resolve_url(base: &str, href: &str) -> String {
+    Url::parse(base).and_then(|base| base.join(href)).map_or_else(|_| href.to_string(), |url| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use common::ids::WindowId;
+    use common::ui::layout::ImageSlot;
+
+    const RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+    const BLUE: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+    fn element(tag_name: &str, attributes: &[(&str, &str)], children: Vec<DomNode>) -> DomNode {
+        let attributes = attributes.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        DomNode::Element { tag_name: tag_name.to_string(), attributes, children }
+    }
+
+    fn layout_box(y: u32, width: u32, height: u32, image: Option<ImageSlot>, children: Vec<LayoutBox>) -> LayoutBox {
+        LayoutBox { x: 0, y, width, height, content_width: width, content_height: height, children, debug_name: String::new(), image }
+    }
+
+    /// A 100x100 window onto a 400px document: a link across the top, and a
+    /// 10x10 image of one color at `image_y`.
+    fn document(url: &str, href: &str, image_y: u32, color: [u8; 4]) -> DocumentState {
+        let dom = element("body", &[], vec![
+            element("a", &[("href", href)], vec![DomNode::Text("link".to_string())]),
+            element("img", &[("src", "dot.ppm")], vec![]),
+        ]);
+        let layout = layout_box(0, 100, 400, None, vec![
+            layout_box(0, 100, 20, None, vec![layout_box(0, 100, 20, None, vec![])]),
+            layout_box(image_y, 10, 10, Some(ImageSlot::Loaded { src: "dot.ppm".to_string() }), vec![]),
+        ]);
+        let mut images = BTreeMap::new();
+        images.insert("dot.ppm".to_string(), Image { width: 1, height: 1, pixels: color.to_vec() });
+        let mut computed_styles = BTreeMap::new();
+        computed_styles.insert("background-color".to_string(), "white".to_string());
+        DocumentState {
+            url: url.to_string(),
+            dom,
+            inline_css: String::new(),
+            computed_styles,
+            layout,
+            width: 100,
+            height: 100,
+            scroll_y: 0,
+            history: Vec::new(),
+            images,
+            loading: None,
+            cursor: CursorShape::Arrow,
+            press: None,
+            drag_accepted: None,
+        }
+    }
+
+    fn pixel(frame: &[u8], x: u32, y: u32) -> [u8; 4] {
+        let at = ((y * 100 + x) * 4) as usize;
+        [frame[at], frame[at + 1], frame[at + 2], frame[at + 3]]
+    }
+
+    fn rows_with(frame: &[u8], color: [u8; 4]) -> Vec<u32> {
+        (0..100).filter(|&y| pixel(frame, 0, y) == color).collect()
+    }
+
+    #[test]
+    fn two_windows_keep_their_input_and_repaints_apart() {
+        let (first, second) = (WindowId::from_raw(1), WindowId::from_raw(2));
+        let mut documents = BTreeMap::new();
+        documents.insert(first, document("http://one.test/", "/from-one", 60, RED));
+        documents.insert(second, document("http://two.test/", "/from-two", 30, BLUE));
+
+        // The same point hits each window's own link.
+        assert_eq!(documents[&first].anchor_at(5, 5).map(|hit| hit.href), Some("/from-one".to_string()));
+        assert_eq!(documents[&second].anchor_at(5, 5).map(|hit| hit.href), Some("/from-two".to_string()));
+        let before = crate::paint::render(&documents[&second]);
+        assert_eq!(rows_with(&crate::paint::render(&documents[&first]), RED), (60..70).collect::<Vec<_>>());
+        assert_eq!(rows_with(&before, BLUE), (30..40).collect::<Vec<_>>());
+
+        // A wheel step down in the first window scrolls only that one.
+        assert!(documents.get_mut(&first).unwrap().scroll(false));
+        assert_eq!(documents[&first].scroll_y, SCROLL_STEP);
+        assert_eq!(documents[&second].scroll_y, 0);
+
+        // Its frame shows the document from the offset; the other's is unchanged.
+        let scrolled = crate::paint::render(&documents[&first]);
+        assert_eq!(rows_with(&scrolled, RED), (60 - SCROLL_STEP..70 - SCROLL_STEP).collect::<Vec<_>>());
+        assert!(rows_with(&scrolled, BLUE).is_empty());
+        assert_eq!(crate::paint::render(&documents[&second]), before);
+
+        // The link scrolled out from under the point in the first window only.
+        assert_eq!(documents[&first].anchor_at(5, 5), None);
+        assert_eq!(documents[&second].anchor_at(5, 5).map(|hit| hit.href), Some("/from-two".to_string()));
+    }
+
+    #[test]
+    fn scrolling_stops_at_the_ends_of_the_document() {
+        let mut doc = document("http://one.test/", "/", 60, RED);
+        assert!(!doc.scroll(true));
+        while doc.scroll(false) {}
+        assert_eq!(doc.scroll_y, 300);
+        assert_eq!(rows_with(&crate::paint::render(&doc), RED), Vec::<u32>::new());
+        assert!(doc.scroll(true));
+        assert_eq!(doc.scroll_y, 300 - SCROLL_STEP);
+    }
+
+    #[test]
+    fn target_blank_opens_a_new_window() {
+        let blank = AnchorHit { href: "/".to_string(), target: Some("_blank".to_string()) };
+        let own = AnchorHit { href: "/".to_string(), target: None };
+        assert!(blank.opens_new_window());
+        assert!(!own.opens_new_window());
+    }
+}
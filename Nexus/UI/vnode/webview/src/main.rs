@@ -7,14 +7,23 @@ extern crate alloc;
 
 use core::panic::PanicInfo;
 use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::format;
 use alloc::string::{String, ToString};
 
+use common::ipc::event_ipc::{Event, EventBusRequest, EventBusResponse};
+use common::ipc::settings_ipc::{SettingChanged, SettingValue, SettingsRequest, SettingsResponse};
 use common::ipc::vnode::VNodeChannel;
 use common::syscall::{syscall3, SYS_LOG, SUCCESS, SYS_TIME};
-use common::ui_protocol::{UiRequest, UiResponse, WindowInfo, MouseEventType, KeyEventType};
+use common::ui_protocol::{UiRequest, UiResponse, UiEvent, WindowInfo, MouseEventType, KeyEventType, CursorShape, DragData, BUTTON_LEFT, SCROLL_UP};
 use common::ui::{HtmlParser, CssEngine, LayoutEngine};
 use common::ui::html_parser::DomNode;
+use common::ui::scale::{UiScale, SCALE_SETTING};
+use common::ui::latency::AppLatency;
+use common::ids::WindowId;
+use common::startup;
+use common::time;
+use common::url::Url;
 
 // Temporary log function for V-Nodes
 fn log(msg: &str) {
@@ -29,128 +38,644 @@ fn log(msg: &str) {
     }
 }
 
+mod cookies;
+mod document;
+mod paint;
+mod subresources;
+use cookies::{CookieJar, BLOCK_THIRD_PARTY_KEY};
+use document::{DocumentState, LinkPress};
+use subresources::{ContentCache, FetchError, Fetched, Fetcher, SubresourceLoad};
+
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+// Pixels the pointer moves with the button down on a link before it drags the URL.
+const DRAG_THRESHOLD: u32 = 4;
+/// Lists the cookie jar by domain; `aether://cookies/clear?<domain>` clears one.
+const COOKIES_PAGE: &str = "aether://cookies/";
+
+/// Conceptual: there is no HTTP client yet, so every subresource fetch fails
+/// with 404 on the next poll. Pages render with their inline styles and image
+/// placeholders.
+#[derive(Default)]
+struct ConceptualFetcher {
+    finished: VecDeque<Fetched>,
+}
+
+impl Fetcher for ConceptualFetcher {
+    fn start(&mut self, url: &str, _cookie: Option<String>) {
+        self.finished.push_back(Fetched { url: url.to_string(), set_cookies: Vec::new(), result: Err(FetchError::Status(404)) });
+    }
+
+    fn poll(&mut self) -> Option<Fetched> {
+        self.finished.pop_front()
+    }
+}
+
+/// The `Cookie` header for a fetch of `url` for the document at `document`,
+/// if both parse and any cookie applies.
+fn cookie_header(jar: &mut CookieJar, url: &str, document: &str, now_secs: u64) -> Option<String> {
+    let (url, document) = (Url::parse(url).ok()?, Url::parse(document).ok()?);
+    jar.header_for(&url, &document, now_secs)
+}
+
+/// Enough escaping for text and attribute values of generated pages.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
 struct WebViewVNode {
     client_chan: VNodeChannel, // Channel for communication with UI Compositor
+    ui_events_chan: Option<VNodeChannel>, // Where the compositor sends our windows' UiEvents, if init gave us one
+    vfs_chan: VNodeChannel, // Saved cookies
+    bus_events_chan: VNodeChannel, // settings.webview.* changes
     html_parser: HtmlParser,
     css_engine: CssEngine,
     layout_engine: LayoutEngine,
-    window_id: Option<u32>,
+    documents: BTreeMap<WindowId, DocumentState>, // Per-window document state, keyed by compositor window ID
+    fetcher: ConceptualFetcher, // Stylesheets and images for all windows
+    cache: ContentCache, // Shared by all windows
+    cookies: CookieJar, // Shared by all windows
+    latency: AppLatency, // Timing of the latest input event, attached to the next frame
+    now: u64, // Timer ticks as of the last SYS_TIME call
 }
 
 impl WebViewVNode {
-    fn new(client_chan_id: u32) -> Self {
+    fn new(client_chan_id: u32, ui_events_chan_id: Option<u32>, vfs_chan_id: u32, settings_chan_id: u32, event_bus_chan_id: u32, bus_events_chan_id: u32) -> Self {
         let client_chan = VNodeChannel::new(client_chan_id);
+        let mut vfs_chan = VNodeChannel::new(vfs_chan_id);
+        let mut settings_chan = VNodeChannel::new(settings_chan_id);
+        let mut event_bus_chan = VNodeChannel::new(event_bus_chan_id);
+        let bus_events_chan = VNodeChannel::new(bus_events_chan_id);
         log("WebView V-Node: Initializing...");
 
+        let block_third_party = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: BLOCK_THIRD_PARTY_KEY.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Bool(block), .. }) => block,
+            _ => false,
+        };
+        let scale = match settings_chan.send_and_recv::<SettingsRequest, SettingsResponse>(&SettingsRequest::Get { key: SCALE_SETTING.to_string() }) {
+            Ok(SettingsResponse::Value { value: SettingValue::Str(name), .. }) => UiScale::from_name(&name).unwrap_or_default(),
+            _ => UiScale::default(),
+        };
+        for topic_prefix in ["settings.webview.".to_string(), format!("settings.{}", SCALE_SETTING)] {
+            let subscribe = EventBusRequest::Subscribe { topic_prefix, reply_chan: bus_events_chan.id };
+            if !matches!(event_bus_chan.send_and_recv::<EventBusRequest, EventBusResponse>(&subscribe), Ok(EventBusResponse::Success(_))) {
+                log("WebView: Failed to subscribe to settings changes; they apply after a restart.");
+            }
+        }
+
+        let now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+        let (cookies, report) = CookieJar::load(&mut vfs_chan, block_third_party, time::now_secs(), now);
+        match report {
+            Ok(report) => log(&format!("WebView: Restored {} cookies, {} expired.", report.cookies, report.expired)),
+            Err(e) => log(&format!("WebView: Not restoring cookies: {}.", e)),
+        }
+
         Self {
             client_chan,
+            ui_events_chan: ui_events_chan_id.map(VNodeChannel::new),
+            vfs_chan,
+            bus_events_chan,
             html_parser: HtmlParser::new(),
             css_engine: CssEngine::new(),
-            layout_engine: LayoutEngine::new(),
-            window_id: None,
+            layout_engine: LayoutEngine::with_scale(scale),
+            documents: BTreeMap::new(),
+            fetcher: ConceptualFetcher::default(),
+            cache: ContentCache::new(),
+            cookies,
+            latency: AppLatency::default(),
+            now,
         }
     }
 
-    fn run_loop(&mut self) -> ! {
-        log("WebView V-Node: Entering main event loop.");
+    /// Conceptual: fetch a document over the network or from the VFS.
+    /// For now, returns a fixed page that mentions the requested URL.
+    fn fetch_document(&mut self, url: &str) -> (String, String) {
+        let css_content = String::from("body { background-color: white; color: black; }");
+        if url == COOKIES_PAGE {
+            return (self.cookies_page(), css_content);
+        }
+        // Conceptual: the request carries this header, and the response's
+        // Set-Cookie headers go to `take_cookies` with `url` as the document.
+        if let Some(cookie) = cookie_header(&mut self.cookies, url, url, time::now_secs()) {
+            log(&format!("WebView: Sending {} bytes of cookies to {}.", cookie.len(), url));
+        }
+        let html_content = format!("<html><body>Hello from WebView! ({})</body></html>", url);
+        (html_content, css_content)
+    }
+
+    /// Stores the cookies a response to `url` set, for the document at `document`.
+    fn take_cookies(&mut self, url: &str, document: &str, set_cookies: &[String]) {
+        if set_cookies.is_empty() {
+            return;
+        }
+        let (Ok(url), Ok(document)) = (Url::parse(url), Url::parse(document)) else {
+            return;
+        };
+        for (header, reason) in self.cookies.store(&url, &document, set_cookies, time::now_secs()) {
+            // Only the name: the value may be someone's session.
+            let name = header.split(|c| c == '=' || c == ';').next().unwrap_or("").trim();
+            log(&format!("WebView: Ignoring cookie '{}' from {}: {}.", name, url, reason));
+        }
+    }
+
+    /// The `aether://cookies` page: each domain holding cookies, with a link that clears it.
+    fn cookies_page(&mut self) -> String {
+        let domains = self.cookies.domains(time::now_secs());
+        let mut html = String::from("<html><body><h1>Cookies</h1>");
+        html.push_str(if self.cookies.block_third_party { "<p>Third-party cookies are blocked.</p>" } else { "<p>Third-party cookies are allowed.</p>" });
+        if domains.is_empty() {
+            html.push_str("<p>No cookies.</p>");
+        } else {
+            html.push_str("<ul>");
+            for summary in domains {
+                let domain = escape_html(&summary.domain);
+                html.push_str(&format!("<li>{}: {} cookies, {} bytes, {} saved <a href=\"{}clear?{}\">Clear</a></li>",
+                    domain, summary.cookies, summary.bytes, summary.persistent, COOKIES_PAGE, domain));
+            }
+            html.push_str("</ul>");
+        }
+        html.push_str("</body></html>");
+        html
+    }
 
-        // 1. Request to create a window
-        let create_window_req = UiRequest::CreateWindow {
-            title: String::from("AetherOS WebView"),
-            width: 800,
-            height: 600,
+    /// Asks the compositor for a new window and loads `url` into it.
+    fn open_window(&mut self, url: &str) -> Option<WindowId> {
+        let title = format!("AetherOS WebView - {}", url);
+        let create_window_req = match &self.ui_events_chan {
+            Some(events) => UiRequest::CreateWindowWithEvents { title, width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT, events_chan: events.id },
+            // Events then come back on the compositor's channel.
+            None => UiRequest::CreateWindow { title, width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT },
         };
 
         match self.client_chan.send_and_recv(&create_window_req) {
             Ok(UiResponse::Success { window_id: Some(id) }) => {
-                self.window_id = Some(id);
                 log(&alloc::format!("WebView: Created window with ID: {}.", id));
+                self.navigate(id, url, DEFAULT_WIDTH, DEFAULT_HEIGHT);
+                Some(id)
             },
             Ok(UiResponse::Error { message }) => {
-                log(&alloc::format!("WebView: Failed to create window: {}. Panicking.", message));
-                panic!("Failed to create window");
+                log(&alloc::format!("WebView: Failed to create window: {}.", message));
+                None
             },
             _ => {
-                log("WebView: Unexpected response for CreateWindow. Panicking.");
-                panic!("Unexpected CreateWindow response");
+                log("WebView: Unexpected response for CreateWindow.");
+                None
             }
         }
+    }
 
-        // 2. Simulate loading an HTML page
-        let html_content = "<html><body>Hello from WebView!</body></html>";
-        let css_content = "body { background-color: white; color: black; }";
+    /// Loads `url` into the given window, keeping the previous URL in its history.
+    /// The window is repainted once its stylesheets and images are in.
+    fn navigate(&mut self, window_id: WindowId, url: &str, width: u32, height: u32) {
+        // Clearing is an action rather than a page; the window shows the listing afterwards.
+        let url = match url.strip_prefix(COOKIES_PAGE).and_then(|rest| rest.strip_prefix("clear?")) {
+            Some(domain) => {
+                let cleared = self.cookies.clear(domain);
+                log(&alloc::format!("WebView: [{}] Cleared {} cookies of {}.", window_id, cleared, domain));
+                COOKIES_PAGE
+            },
+            None => url,
+        };
+        let (html_content, css_content) = self.fetch_document(url);
 
-        log(&alloc::format!("WebView: Parsing HTML: {}", html_content));
-        let dom_tree = self.html_parser.parse_html(html_content);
-        log(&alloc::format!("WebView: Parsed DOM: {:?}", dom_tree));
+        log(&alloc::format!("WebView: [{}] Parsing HTML: {}", window_id, html_content));
+        let dom = self.html_parser.parse_html(&html_content);
+        let css_rules = self.css_engine.parse_css(&css_content);
+        let computed_styles = self.css_engine.apply_styles(&dom, &css_rules);
+        // Laid out right away so links work; `finish_loading` lays it out again with what was fetched.
+        let layout = self.layout_engine.layout(&dom, &computed_styles, width, height);
 
-        log(&alloc::format!("WebView: Parsing CSS: {}", css_content));
-        let css_rules = self.css_engine.parse_css(css_content);
-        log(&alloc::format!("WebView: Parsed CSS rules: {:?}", css_rules));
+        let resources = match Url::parse(url) {
+            Ok(base) => subresources::collect(&dom, &base),
+            Err(e) => {
+                log(&alloc::format!("WebView: [{}] Not loading subresources of '{}': {}.", window_id, url, e));
+                Vec::new()
+            },
+        };
+        log(&alloc::format!("WebView: [{}] Loading {} subresources.", window_id, resources.len()));
+        let loading = SubresourceLoad::new(resources, &mut self.cache, self.now);
 
-        let computed_styles = self.css_engine.apply_styles(&dom_tree, &css_rules);
-        log(&alloc::format!("WebView: Computed styles: {:?}", computed_styles));
+        let (history, cursor) = match self.documents.remove(&window_id) {
+            Some(previous) => {
+                let mut history = previous.history;
+                history.push(previous.url);
+                (history, previous.cursor)
+            },
+            None => (Vec::new(), CursorShape::Arrow),
+        };
 
-        // 3. Perform layout
-        let layout_tree = self.layout_engine.layout(&dom_tree, &computed_styles, 800, 600);
-        log(&alloc::format!("WebView: Computed layout: {:?}", layout_tree));
+        self.documents.insert(window_id, DocumentState {
+            url: String::from(url),
+            dom,
+            inline_css: css_content,
+            computed_styles,
+            layout,
+            width,
+            height,
+            scroll_y: 0,
+            history,
+            images: BTreeMap::new(),
+            loading: Some(loading),
+            cursor,
+            press: None,
+            drag_accepted: None,
+        });
+        self.pump_loads();
+    }
 
-        // 4. Simulate rendering to a pixel buffer
-        let mut pixels: Vec<u8> = Vec::new();
-        pixels.resize(800 * 600 * 4, 0); // RGBA
-        // For simplicity, just fill with a color based on the body background
-        if let Some(bg_color) = computed_styles.get("background-color") {
-            let color_val = match bg_color.as_str() {
-                "white" => [0xFF, 0xFF, 0xFF, 0xFF],
-                "black" => [0x00, 0x00, 0x00, 0xFF],
-                _ => [0x80, 0x80, 0x80, 0xFF], // Gray default
-            };
-            for i in (0..pixels.len()).step_by(4) {
-                pixels[i] = color_val[0];
-                pixels[i+1] = color_val[1];
-                pixels[i+2] = color_val[2];
-                pixels[i+3] = color_val[3];
+    /// Hands finished fetches to the documents waiting for them, starts more
+    /// within each document's cap, and finishes documents that are done.
+    fn pump_loads(&mut self) {
+        while let Some(fetched) = self.fetcher.poll() {
+            if let Ok(data) = &fetched.result {
+                if data.len() <= subresources::MAX_SUBRESOURCE_BYTES {
+                    self.cache.insert(&fetched.url, data.clone(), self.now);
+                }
+            }
+            // A shared fetch sets its cookies for the first document that was waiting for it.
+            let mut document = None;
+            for doc in self.documents.values_mut() {
+                if let Some(loading) = doc.loading.as_mut() {
+                    if loading.complete(&fetched.url, &fetched.result) && document.is_none() {
+                        document = Some(doc.url.clone());
+                    }
+                }
+            }
+            if let Some(document) = document {
+                self.take_cookies(&fetched.url, &document, &fetched.set_cookies);
             }
         }
 
-        // 5. Send pixel buffer to the UI Compositor
-        if let Some(id) = self.window_id {
-            let draw_req = UiRequest::DrawToSurface {
-                window_id: id,
-                x: 0,
-                y: 0,
-                width: 800,
-                height: 600,
-                pixels,
+        let now_secs = time::now_secs();
+        let mut done = Vec::new();
+        for (window_id, doc) in self.documents.iter_mut() {
+            if let Some(loading) = doc.loading.as_mut() {
+                let (cookies, document) = (&mut self.cookies, doc.url.as_str());
+                loading.start_next(&mut self.fetcher, |url| cookie_header(cookies, url, document, now_secs));
+                if loading.is_done(self.now) {
+                    done.push(*window_id);
+                }
+            }
+        }
+        for window_id in done {
+            self.finish_loading(window_id);
+        }
+    }
+
+    /// Applies the fetched stylesheets after the document's own CSS, lays the
+    /// document out around its images and paints it.
+    fn finish_loading(&mut self, window_id: WindowId) {
+        let doc = match self.documents.get_mut(&window_id) {
+            Some(doc) => doc,
+            None => return,
+        };
+        let loaded = match doc.loading.take() {
+            Some(loading) => loading.finish(),
+            None => return,
+        };
+        for (url, reason) in &loaded.failed {
+            log(&alloc::format!("WebView: [{}] Failed to load {}: {}.", window_id, url, reason));
+        }
+
+        let mut css_rules = self.css_engine.parse_css(&doc.inline_css);
+        for stylesheet in &loaded.stylesheets {
+            css_rules.extend(self.css_engine.parse_css(stylesheet));
+        }
+        doc.computed_styles = self.css_engine.apply_styles(&doc.dom, &css_rules);
+        doc.images = loaded.images;
+        doc.layout = self.layout_engine.layout_with_images(&doc.dom, &doc.computed_styles, &doc.image_sizes(), doc.width, doc.height);
+        log(&alloc::format!("WebView: [{}] Computed layout: {:?}", window_id, doc.layout));
+        self.render_window(window_id);
+    }
+
+    /// Goes back one entry in the window's history, if any.
+    fn go_back(&mut self, window_id: WindowId) {
+        let previous = match self.documents.get_mut(&window_id) {
+            Some(doc) => doc.history.pop().map(|url| (url, doc.width, doc.height)),
+            None => None,
+        };
+        if let Some((url, width, height)) = previous {
+            // Navigate pushes the current URL; drop it again so "back" doesn't grow the history.
+            self.navigate(window_id, &url, width, height);
+            if let Some(doc) = self.documents.get_mut(&window_id) {
+                doc.history.pop();
+            }
+        }
+    }
+
+    /// Lays the window's document out again for a new viewport and repaints it.
+    /// The scroll offset is kept where the document is still long enough.
+    fn resize(&mut self, window_id: WindowId, width: u32, height: u32) {
+        let doc = match self.documents.get_mut(&window_id) {
+            Some(doc) => doc,
+            None => {
+                log(&alloc::format!("WebView: Resize for unknown window {}.", window_id));
+                return;
+            }
+        };
+        if (doc.width, doc.height) == (width, height) {
+            return;
+        }
+        doc.layout = self.layout_engine.layout_with_images(&doc.dom, &doc.computed_styles, &doc.image_sizes(), width, height);
+        doc.width = width;
+        doc.height = height;
+        doc.scroll_y = doc.scroll_y.min(doc.layout.height.saturating_sub(height));
+        log(&alloc::format!("WebView: [{}] Viewport is now {}x{}.", window_id, width, height));
+        self.render_window(window_id);
+    }
+
+    /// Lays every open document out again at `scale` and repaints it.
+    fn set_scale(&mut self, scale: UiScale) {
+        if self.layout_engine.scale() == scale {
+            return;
+        }
+        self.layout_engine.set_scale(scale);
+        let window_ids: Vec<WindowId> = self.documents.keys().copied().collect();
+        for window_id in window_ids {
+            let Some(doc) = self.documents.get_mut(&window_id) else {
+                continue;
             };
+            doc.layout = self.layout_engine.layout_with_images(&doc.dom, &doc.computed_styles, &doc.image_sizes(), doc.width, doc.height);
+            doc.scroll_y = doc.scroll_y.min(doc.layout.height.saturating_sub(doc.height));
+            self.render_window(window_id);
+        }
+        log(&format!("WebView: UI scale is now {}x.", scale.name()));
+    }
+
+    /// Renders only the given window and sends the frame to the compositor.
+    /// Does nothing while the window's subresources are loading.
+    fn render_window(&mut self, window_id: WindowId) {
+        let doc = match self.documents.get(&window_id) {
+            Some(doc) if doc.loading.is_none() => doc,
+            _ => return,
+        };
+
+        let pixels = paint::render(doc);
+
+        let draw_req = UiRequest::DrawToSurface {
+            window_id,
+            x: 0,
+            y: 0,
+            width: doc.width,
+            height: doc.height,
+            pixels,
+            input: self.latency.on_commit(time::monotonic_nanos()),
+        };
+
+        match self.client_chan.send_and_recv(&draw_req) {
+            Ok(UiResponse::Success { .. }) => {
+                log(&alloc::format!("WebView: Sent rendered frame to compositor for window {}.", window_id));
+            },
+            Ok(UiResponse::Error { message }) => {
+                log(&alloc::format!("WebView: Failed to draw to surface {}: {}.", window_id, message));
+            },
+            _ => {
+                log("WebView: Unexpected response for DrawToSurface.");
+            }
+        }
+    }
+
+    /// Closes a window from inside the WebView (window.close-style action).
+    /// Asks the compositor for a cursor shape, if it isn't the one the window
+    /// already has.
+    fn set_cursor(&mut self, window_id: WindowId, shape: CursorShape) {
+        match self.documents.get_mut(&window_id) {
+            Some(doc) if doc.cursor != shape => doc.cursor = shape,
+            _ => return,
+        }
+        if !matches!(self.client_chan.send_and_recv(&UiRequest::SetCursor { window_id, shape }), Ok(UiResponse::Success { .. })) {
+            log(&alloc::format!("WebView: [{}] Compositor did not take cursor {:?}.", window_id, shape));
+        }
+    }
+
+    /// Drags a link's URL out of the window. The compositor refuses if the
+    /// button was let go in the meantime.
+    fn start_link_drag(&mut self, window_id: WindowId, url: String) {
+        let request = UiRequest::StartDrag { window_id, mime: String::from("text/uri-list"), data: DragData::Inline(url.clone().into_bytes()) };
+        match self.client_chan.send_and_recv(&request) {
+            Ok(UiResponse::Success { .. }) => log(&alloc::format!("WebView: [{}] Dragging {}.", window_id, url)),
+            Ok(UiResponse::Error { message }) => log(&alloc::format!("WebView: [{}] Can't drag {}: {}.", window_id, url, message)),
+            _ => log("WebView: Unexpected response for StartDrag."),
+        }
+    }
+
+    fn close_window(&mut self, window_id: WindowId) {
+        match self.client_chan.send_and_recv(&UiRequest::CloseWindow { window_id }) {
+            Ok(UiResponse::Success { .. }) => {},
+            _ => log(&alloc::format!("WebView: Compositor did not confirm closing window {}.", window_id)),
+        }
+        self.drop_document(window_id);
+    }
+
+    fn drop_document(&mut self, window_id: WindowId) {
+        if self.documents.remove(&window_id).is_some() {
+            log(&alloc::format!("WebView: Tore down document state for window {}.", window_id));
+        }
+    }
 
-            match self.client_chan.send_and_recv(&draw_req) {
-                Ok(UiResponse::Success { .. }) => {
-                    log(&alloc::format!("WebView: Sent rendered frame to compositor for window {}.", id));
+    /// Routes a UI event from the compositor to the document of the window it targets.
+    fn handle_event(&mut self, event: UiEvent) {
+        if let UiEvent::Mouse { timing, .. } | UiEvent::Key { timing, .. } = &event {
+            self.latency.on_event(timing, time::monotonic_nanos());
+        }
+        match event {
+            UiEvent::Mouse { window_id, x, y, button, event_type, .. } => {
+                let doc = match self.documents.get_mut(&window_id) {
+                    Some(doc) => doc,
+                    None => {
+                        log(&alloc::format!("WebView: Mouse event for unknown window {}.", window_id));
+                        return;
+                    }
+                };
+                match event_type {
+                    // Links are followed on release over the same link, so a press can still turn into a drag.
+                    MouseEventType::MouseDown if button == BUTTON_LEFT => {
+                        doc.press = doc.anchor_at(x, y).map(|anchor| LinkPress { anchor, x, y });
+                    },
+                    MouseEventType::MouseDown => {},
+                    MouseEventType::MouseUp => {
+                        let press = match doc.press.take() {
+                            Some(press) if button == BUTTON_LEFT => press,
+                            _ => return,
+                        };
+                        if doc.anchor_at(x, y).as_ref() != Some(&press.anchor) {
+                            return;
+                        }
+                        let anchor = press.anchor;
+                        let url = document::resolve_url(&doc.url, &anchor.href);
+                        let (width, height) = (doc.width, doc.height);
+                        if anchor.opens_new_window() {
+                            log(&alloc::format!("WebView: [{}] Opening {} in a new window.", window_id, url));
+                            self.open_window(&url);
+                        } else {
+                            log(&alloc::format!("WebView: [{}] Following link to {}.", window_id, url));
+                            self.navigate(window_id, &url, width, height);
+                        }
+                    },
+                    MouseEventType::MouseMove => {
+                        let shape = document::cursor_at(&doc.dom, &doc.layout, x, y + doc.scroll_y);
+                        let dragged = doc.press.as_ref().map_or(false, |press| x.abs_diff(press.x).max(y.abs_diff(press.y)) > DRAG_THRESHOLD);
+                        if dragged {
+                            let press = doc.press.take();
+                            let url = press.map(|press| document::resolve_url(&doc.url, &press.anchor.href));
+                            if let Some(url) = url {
+                                self.start_link_drag(window_id, url);
+                            }
+                        }
+                        self.set_cursor(window_id, shape);
+                    },
+                    MouseEventType::Scroll => {
+                        if doc.scroll(button == SCROLL_UP) {
+                            self.render_window(window_id);
+                        }
+                    },
+                }
+            },
+            UiEvent::Key { window_id, keycode, event_type: KeyEventType::KeyDown, .. } => {
+                if !self.documents.contains_key(&window_id) {
+                    log(&alloc::format!("WebView: Key event for unknown window {}.", window_id));
+                    return;
+                }
+                match keycode {
+                    0x08 => self.go_back(window_id), // Backspace: history back
+                    0x1B => self.close_window(window_id), // Escape: close this window
+                    _ => log(&alloc::format!("WebView: [{}] Key {} pressed.", window_id, keycode)),
+                }
+            },
+            UiEvent::Key { .. } => {},
+            UiEvent::Resized { window_id, width, height } => self.resize(window_id, width, height),
+            UiEvent::CloseRequested { window_id } => {
+                // The user clicked the close button; nothing to save, so close right away.
+                log(&alloc::format!("WebView: [{}] Close requested by the user.", window_id));
+                self.close_window(window_id);
+            },
+            UiEvent::NotificationClicked { .. } => {}, // WebView doesn't post notifications
+            UiEvent::DragOver { window_id, mime, .. } => {
+                // Any point in the page takes a URL; the answer only needs sending when it changes.
+                let accept = mime == "text/uri-list" || mime == "text/plain";
+                let doc = match self.documents.get_mut(&window_id) {
+                    Some(doc) => doc,
+                    None => return,
+                };
+                if doc.drag_accepted.replace(accept) != Some(accept) {
+                    match self.client_chan.send_and_recv(&UiRequest::AcceptDrag { window_id, accept }) {
+                        Ok(UiResponse::Success { .. }) => {},
+                        // The drag may have moved on before the answer arrived.
+                        _ => log(&alloc::format!("WebView: [{}] AcceptDrag was not taken.", window_id)),
+                    }
+                }
+            },
+            UiEvent::DragLeave { window_id } => {
+                if let Some(doc) = self.documents.get_mut(&window_id) {
+                    doc.drag_accepted = None;
+                }
+            },
+            UiEvent::Drop { window_id, mime, data, .. } => {
+                let (width, height) = match self.documents.get_mut(&window_id) {
+                    Some(doc) => {
+                        doc.drag_accepted = None;
+                        (doc.width, doc.height)
+                    },
+                    None => return,
+                };
+                let bytes = match data {
+                    DragData::Inline(bytes) => bytes,
+                    DragData::Token(token) => {
+                        log(&alloc::format!("WebView: [{}] Can't redeem drag token {} for '{}' yet.", window_id, token, mime));
+                        return;
+                    },
+                };
+                // A uri-list may hold several URLs and comments; the first URL is opened.
+                let url = core::str::from_utf8(&bytes).ok()
+                    .and_then(|text| text.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')))
+                    .map(String::from);
+                match url {
+                    Some(url) => {
+                        log(&alloc::format!("WebView: [{}] Opening dropped {}.", window_id, url));
+                        self.navigate(window_id, &url, width, height);
+                    },
+                    None => log(&alloc::format!("WebView: [{}] Dropped '{}' holds no URL.", window_id, mime)),
+                }
+            },
+            UiEvent::DragEnded { window_id, dropped } => {
+                log(&alloc::format!("WebView: [{}] Link drag ended, {}.", window_id, if dropped { "dropped" } else { "cancelled" }));
+            },
+        }
+    }
+
+    fn handle_bus_events(&mut self) {
+        while let Ok(Some(event_data)) = self.bus_events_chan.recv_non_blocking() {
+            let Ok(event) = postcard::from_bytes::<Event>(&event_data) else {
+                continue;
+            };
+            let Ok(SettingChanged { key, value }) = postcard::from_bytes(&event.payload) else {
+                continue;
+            };
+            match (key.as_str(), value) {
+                (BLOCK_THIRD_PARTY_KEY, SettingValue::Bool(block)) => {
+                    self.cookies.block_third_party = block;
+                    log(&format!("WebView: Third-party cookies are now {}.", if block { "blocked" } else { "allowed" }));
                 },
-                Ok(UiResponse::Error { message }) => {
-                    log(&alloc::format!("WebView: Failed to draw to surface: {}. Panicking.", message));
-                    panic!("Failed to draw to surface");
+                (SCALE_SETTING, SettingValue::Str(name)) => {
+                    if let Some(scale) = UiScale::from_name(&name) {
+                        self.set_scale(scale);
+                    }
                 },
-                _ => {
-                    log("WebView: Unexpected response for DrawToSurface. Panicking.");
-                    panic!("Unexpected DrawToSurface response");
-                }
+                _ => {},
             }
         }
+    }
+
+    fn run_loop(&mut self) -> ! {
+        log("WebView V-Node: Entering main event loop.");
+
+        if self.open_window("aether://start/index.html").is_none() {
+            log("WebView: Failed to open the initial window. Panicking.");
+            panic!("Failed to create window");
+        }
 
         loop {
-            // WebView V-Node would typically idle here, waiting for UI events (mouse, keyboard) or navigation requests.
-            // For now, it just yields.
-            unsafe { syscall3(SYS_TIME, 0, 0, 0); }
+            // Wait for UI events (mouse, keyboard, close) from the compositor and route them per window.
+            let events = self.ui_events_chan.as_mut().unwrap_or(&mut self.client_chan);
+            if let Ok(Some(event_data)) = events.recv_non_blocking() {
+                if let Ok(event) = postcard::from_bytes::<UiEvent>(&event_data) {
+                    self.handle_event(event);
+                } else {
+                    log("WebView: Failed to deserialize UI event.");
+                }
+            }
+
+            self.handle_bus_events();
+            self.now = unsafe { syscall3(SYS_TIME, 0, 0, 0) };
+            self.pump_loads();
+
+            if self.cookies.save_due(self.now) {
+                if let Err(e) = self.cookies.save(&mut self.vfs_chan, self.now) {
+                    log(&format!("WebView: Failed to save cookies: {}.", e));
+                }
+            }
         }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    // Assuming channel ID 12 for UI Compositor communication
-    let mut webview_vnode = WebViewVNode::new(12);
+    // Channels come from init; the well-known IDs are the fallback:
+    // 12 for UI Compositor communication; our windows' events come back on it
+    //    unless init hands out a "ui-events" channel
+    // 7 for the VFS
+    // 14 for Settings
+    // 13 for the Event Bus, 28 for the events it delivers
+    let channels = startup::channels();
+    let channel = |name: &str, default: u32| channels.get(name).copied().unwrap_or(default);
+    let mut webview_vnode = WebViewVNode::new(
+        channel("compositor", 12),
+        channels.get("ui-events").copied(),
+        channel("vfs", 7),
+        channel("settings", 14),
+        channel("event-bus", 13),
+        28,
+    );
     webview_vnode.run_loop();
 }
 
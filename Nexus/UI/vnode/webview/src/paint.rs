@@ -0,0 +1,101 @@
+// vnode/webview/src/paint.rs
+
+//! Drawing a window's RGBA frame: the background, then its `<img>` boxes.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use common::ui::image::Image;
+use common::ui::layout::{ImageSlot, LayoutBox};
+
+use crate::document::DocumentState;
+
+const PLACEHOLDER_FILL: [u8; 4] = [0xE0, 0xE0, 0xE0, 0xFF];
+const PLACEHOLDER_BORDER: [u8; 4] = [0x90, 0x90, 0x90, 0xFF];
+
+/// A `width` x `height` RGBA frame showing the document from `scroll_y` down.
+pub struct Frame<'a> {
+    pub pixels: &'a mut [u8],
+    pub width: u32,
+    pub height: u32,
+    pub scroll_y: u32,
+}
+
+impl Frame<'_> {
+    /// Sets the pixel at document coordinates, if it is on screen.
+    fn put(&mut self, x: i64, y: i64, rgba: &[u8]) {
+        let y = y - self.scroll_y as i64;
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let at = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[at..at + 4].copy_from_slice(rgba);
+    }
+
+    /// Scales `image` to the box with nearest-neighbour sampling.
+    fn blit(&mut self, image: &Image, x: i64, y: i64, width: u32, height: u32) {
+        if image.width == 0 || image.height == 0 {
+            return;
+        }
+        for dy in 0..height {
+            let source_y = (dy as u64 * image.height as u64 / height as u64) as usize;
+            for dx in 0..width {
+                let source_x = (dx as u64 * image.width as u64 / width as u64) as usize;
+                let at = (source_y * image.width as usize + source_x) * 4;
+                self.put(x + dx as i64, y + dy as i64, &image.pixels[at..at + 4]);
+            }
+        }
+    }
+
+    /// A light grey box with a darker border, where an image failed to load.
+    fn placeholder(&mut self, x: i64, y: i64, width: u32, height: u32) {
+        for dy in 0..height {
+            for dx in 0..width {
+                let edge = dx == 0 || dy == 0 || dx + 1 == width || dy + 1 == height;
+                self.put(x + dx as i64, y + dy as i64, if edge { &PLACEHOLDER_BORDER } else { &PLACEHOLDER_FILL });
+            }
+        }
+    }
+}
+
+/// The frame of `doc`'s window as it is scrolled: filled with the body's
+/// background color, with the images on top.
+pub fn render(doc: &DocumentState) -> Vec<u8> {
+    let mut pixels = alloc::vec![0; (doc.width * doc.height * 4) as usize];
+    if let Some(background) = doc.computed_styles.get("background-color") {
+        let rgba = match background.as_str() {
+            "white" => [0xFF, 0xFF, 0xFF, 0xFF],
+            "black" => [0x00, 0x00, 0x00, 0xFF],
+            _ => [0x80, 0x80, 0x80, 0xFF], // Gray default
+        };
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+    let mut frame = Frame { pixels: &mut pixels, width: doc.width, height: doc.height, scroll_y: doc.scroll_y };
+    paint_images(&mut frame, &doc.layout, &doc.images);
+    pixels
+}
+
+/// Draws every image box of `layout`. Box positions are relative to their parent.
+pub fn paint_images(frame: &mut Frame, layout: &LayoutBox, images: &BTreeMap<String, Image>) {
+    fn walk(frame: &mut Frame, layout: &LayoutBox, images: &BTreeMap<String, Image>, origin_x: i64, origin_y: i64) {
+        let x = origin_x + layout.x as i64;
+        let y = origin_y + layout.y as i64;
+        match &layout.image {
+            Some(ImageSlot::Loaded { src }) => match images.get(src) {
+                Some(image) => frame.blit(image, x, y, layout.width, layout.height),
+                None => frame.placeholder(x, y, layout.width, layout.height),
+            },
+            Some(ImageSlot::Placeholder { .. }) => frame.placeholder(x, y, layout.width, layout.height),
+            None => {},
+        }
+        for child in &layout.children {
+            walk(frame, child, images, x, y);
+        }
+    }
+    walk(frame, layout, images, 0, 0);
+}
@@ -0,0 +1,256 @@
+// vnode/webview/src/subresources.rs
+
+//! Stylesheets and images a document references, fetched after it is parsed.
+//!
+//! Only same-origin references are loaded. A document fetches at most
+//! `MAX_CONCURRENT_FETCHES` of them at a time and waits for them at most
+//! `SUBRESOURCE_DEADLINE_TICKS`; whatever hasn't arrived by then counts as
+//! failed, so one slow server can't hold the page back.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use common::ui::html_parser::DomNode;
+use common::ui::image::{self, Image};
+use common::url::Url;
+
+pub const MAX_CONCURRENT_FETCHES: usize = 4;
+/// Larger responses are dropped, as failed.
+pub const MAX_SUBRESOURCE_BYTES: usize = 4 * 1024 * 1024;
+pub const SUBRESOURCE_DEADLINE_TICKS: u64 = 5 * 100; // 5 s
+/// Budget of the content cache shared by all windows.
+pub const CONTENT_CACHE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Stylesheet,
+    Image,
+}
+
+/// A reference found in the document.
+#[derive(Debug, Clone)]
+pub struct Subresource {
+    pub kind: Kind,
+    /// As written in `href` or `src`; the layout engine looks images up by it.
+    pub src: String,
+    /// Resolved against the document URL, without fragment.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The server answered with an error status, e.g. 404.
+    Status(u16),
+    TooLarge,
+    TimedOut,
+    Network(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "HTTP {}", status),
+            Self::TooLarge => write!(f, "larger than {} bytes", MAX_SUBRESOURCE_BYTES),
+            Self::TimedOut => f.write_str("timed out"),
+            Self::Network(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A finished fetch.
+pub struct Fetched {
+    pub url: String,
+    /// The response's `Set-Cookie` headers, for the cookie jar.
+    pub set_cookies: Vec<String>,
+    pub result: Result<Vec<u8>, FetchError>,
+}
+
+/// Fetches URLs in the background. Shared by all documents; results come
+/// back by URL.
+pub trait Fetcher {
+    /// `cookie` is the `Cookie` header to send, if any cookie applies.
+    fn start(&mut self, url: &str, cookie: Option<String>);
+    /// A finished fetch, if any.
+    fn poll(&mut self) -> Option<Fetched>;
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// The stylesheets (`<link rel="stylesheet" href>`) and images (`<img src>`)
+/// of a document, in document order. Cross-origin and unparsable references
+/// are left out; their images render as placeholders.
+pub fn collect(dom: &DomNode, base: &Url) -> Vec<Subresource> {
+    fn walk(node: &DomNode, base: &Url, out: &mut Vec<Subresource>) {
+        let (tag_name, attributes, children) = match node {
+            DomNode::Element { tag_name, attributes, children } => (tag_name, attributes, children),
+            DomNode::Text(_) => return,
+        };
+        let reference = match tag_name.as_str() {
+            "link" if attribute(attributes, "rel").map_or(false, |rel| rel.split_ascii_whitespace().any(|r| r.eq_ignore_ascii_case("stylesheet"))) => {
+                attribute(attributes, "href").map(|href| (Kind::Stylesheet, href))
+            },
+            "img" => attribute(attributes, "src").map(|src| (Kind::Image, src)),
+            _ => None,
+        };
+        if let Some((kind, src)) = reference {
+            if let Ok(url) = base.join(src) {
+                if url.same_origin(base) {
+                    out.push(Subresource { kind, src: src.to_string(), url: url.without_fragment() });
+                }
+            }
+        }
+        for child in children {
+            walk(child, base, out);
+        }
+    }
+    let mut out = Vec::new();
+    walk(dom, base, &mut out);
+    out
+}
+
+/// Recently fetched subresources by URL, least recently used evicted first.
+pub struct ContentCache {
+    entries: BTreeMap<String, (Vec<u8>, u64)>, // Data and when it was last used
+    bytes: usize,
+}
+
+impl ContentCache {
+    pub fn new() -> Self {
+        Self { entries: BTreeMap::new(), bytes: 0 }
+    }
+
+    pub fn get(&mut self, url: &str, now: u64) -> Option<Vec<u8>> {
+        let (data, last_used) = self.entries.get_mut(url)?;
+        *last_used = now;
+        Some(data.clone())
+    }
+
+    pub fn insert(&mut self, url: &str, data: Vec<u8>, now: u64) {
+        if data.len() > CONTENT_CACHE_BYTES {
+            return;
+        }
+        if let Some((old, _)) = self.entries.remove(url) {
+            self.bytes -= old.len();
+        }
+        while self.bytes + data.len() > CONTENT_CACHE_BYTES {
+            let oldest = match self.entries.iter().min_by_key(|(_, (_, last_used))| *last_used) {
+                Some((url, _)) => url.clone(),
+                None => break,
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+        self.bytes += data.len();
+        self.entries.insert(url.to_string(), (data, now));
+    }
+}
+
+/// What a document got once loading finished.
+pub struct Loaded {
+    /// Stylesheet texts in document order, without the ones that failed.
+    pub stylesheets: Vec<String>,
+    /// Decoded images by `src` as written.
+    pub images: BTreeMap<String, Image>,
+    /// URL and reason of everything that failed.
+    pub failed: Vec<(String, String)>,
+}
+
+/// The subresource fetches of one document.
+pub struct SubresourceLoad {
+    resources: Vec<Subresource>,
+    queued: VecDeque<String>,
+    in_flight: BTreeSet<String>,
+    results: BTreeMap<String, Result<Vec<u8>, FetchError>>,
+    deadline: u64,
+}
+
+impl SubresourceLoad {
+    /// Starts loading `resources`, taking what it can from `cache`.
+    pub fn new(resources: Vec<Subresource>, cache: &mut ContentCache, now: u64) -> Self {
+        let mut queued = VecDeque::new();
+        let mut results = BTreeMap::new();
+        for resource in &resources {
+            if results.contains_key(&resource.url) || queued.contains(&resource.url) {
+                continue;
+            }
+            match cache.get(&resource.url, now) {
+                Some(data) => {
+                    results.insert(resource.url.clone(), Ok(data));
+                },
+                None => queued.push_back(resource.url.clone()),
+            }
+        }
+        Self { resources, queued, in_flight: BTreeSet::new(), results, deadline: now + SUBRESOURCE_DEADLINE_TICKS }
+    }
+
+    /// Starts queued fetches while fewer than `MAX_CONCURRENT_FETCHES` are
+    /// running. `cookie_for` gives the `Cookie` header for a URL.
+    pub fn start_next(&mut self, fetcher: &mut dyn Fetcher, mut cookie_for: impl FnMut(&str) -> Option<String>) {
+        while self.in_flight.len() < MAX_CONCURRENT_FETCHES {
+            match self.queued.pop_front() {
+                Some(url) => {
+                    fetcher.start(&url, cookie_for(&url));
+                    self.in_flight.insert(url);
+                },
+                None => break,
+            }
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Takes the result of a fetch. Returns false if this document wasn't waiting for `url`.
+    pub fn complete(&mut self, url: &str, result: &Result<Vec<u8>, FetchError>) -> bool {
+        if !self.in_flight.remove(url) {
+            return false;
+        }
+        let result = match result {
+            Ok(data) if data.len() > MAX_SUBRESOURCE_BYTES => Err(FetchError::TooLarge),
+            other => other.clone(),
+        };
+        self.results.insert(url.to_string(), result);
+        true
+    }
+
+    /// True once every fetch has finished or the deadline has passed.
+    pub fn is_done(&self, now: u64) -> bool {
+        (self.queued.is_empty() && self.in_flight.is_empty()) || now >= self.deadline
+    }
+
+    /// Decodes what arrived. Fetches still outstanding count as timed out.
+    pub fn finish(self) -> Loaded {
+        let mut loaded = Loaded { stylesheets: Vec::new(), images: BTreeMap::new(), failed: Vec::new() };
+        for resource in self.resources {
+            let data = match self.results.get(&resource.url) {
+                Some(Ok(data)) => data,
+                Some(Err(e)) => {
+                    loaded.failed.push((resource.url, e.to_string()));
+                    continue;
+                },
+                None => {
+                    loaded.failed.push((resource.url, FetchError::TimedOut.to_string()));
+                    continue;
+                },
+            };
+            match resource.kind {
+                Kind::Stylesheet => loaded.stylesheets.push(String::from_utf8_lossy(data).into_owned()),
+                Kind::Image => match image::decode(data) {
+                    Ok(image) => {
+                        loaded.images.insert(resource.src, image);
+                    },
+                    Err(e) => loaded.failed.push((resource.url, e.to_string())),
+                },
+            }
+        }
+        loaded
+    }
+}